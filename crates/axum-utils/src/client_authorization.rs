@@ -177,7 +177,12 @@ impl Credentials {
     }
 }
 
-async fn fetch_jwks(
+/// Get the keys from a [`JwksOrJwksUri`], fetching them if needed
+///
+/// # Errors
+///
+/// Returns an error if the keys could not be fetched
+pub async fn fetch_jwks(
     http_client_factory: &HttpClientFactory,
     jwks: &JwksOrJwksUri,
 ) -> Result<PublicJsonWebKeySet, BoxError> {
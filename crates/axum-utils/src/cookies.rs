@@ -55,6 +55,17 @@ impl CookieManager {
         let key = Key::derive_from(key);
         Self::new(base_url, key)
     }
+
+    /// Send the cookies in cross-site requests, so that the pages can be
+    /// embedded by other sites.
+    ///
+    /// This only applies when the service is served over HTTPS, as browsers
+    /// reject `SameSite=None` cookies which are not `Secure`.
+    #[must_use]
+    pub fn with_cross_site(mut self, cross_site: bool) -> Self {
+        self.options.cross_site = cross_site;
+        self
+    }
 }
 
 #[async_trait]
@@ -77,11 +88,15 @@ where
 #[derive(Debug, Clone)]
 struct CookieOption {
     base_url: Url,
+    cross_site: bool,
 }
 
 impl CookieOption {
     const fn new(base_url: Url) -> Self {
-        Self { base_url }
+        Self {
+            base_url,
+            cross_site: false,
+        }
     }
 
     fn secure(&self) -> bool {
@@ -92,11 +107,19 @@ impl CookieOption {
         self.base_url.path()
     }
 
+    fn same_site(&self) -> SameSite {
+        if self.cross_site && self.secure() {
+            SameSite::None
+        } else {
+            SameSite::Lax
+        }
+    }
+
     fn apply<'a>(&self, mut cookie: Cookie<'a>) -> Cookie<'a> {
         cookie.set_http_only(true);
         cookie.set_secure(self.secure());
         cookie.set_path(self.path().to_owned());
-        cookie.set_same_site(SameSite::Lax);
+        cookie.set_same_site(self.same_site());
         cookie
     }
 }
@@ -150,3 +173,30 @@ impl IntoResponseParts for CookieJar {
         self.inner.into_response_parts(res)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_site() {
+        let https = CookieOption::new("https://example.com/".parse().unwrap());
+        let http = CookieOption::new("http://localhost:8080/".parse().unwrap());
+
+        let cookie = https.apply(Cookie::new("session", "value"));
+        assert_eq!(cookie.same_site(), Some(SameSite::Lax));
+        assert_eq!(cookie.secure(), Some(true));
+
+        // Cookies are only sent cross-site when they are secure
+        let cross_site = |options: CookieOption| CookieOption {
+            cross_site: true,
+            ..options
+        };
+
+        let cookie = cross_site(https).apply(Cookie::new("session", "value"));
+        assert_eq!(cookie.same_site(), Some(SameSite::None));
+
+        let cookie = cross_site(http).apply(Cookie::new("session", "value"));
+        assert_eq!(cookie.same_site(), Some(SameSite::Lax));
+    }
+}
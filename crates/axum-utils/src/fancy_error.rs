@@ -18,7 +18,7 @@ use axum::{
     Extension, TypedHeader,
 };
 use headers::ContentType;
-use mas_templates::ErrorContext;
+use mas_templates::{error_codes, ErrorContext};
 
 use crate::sentry::SentryEventID;
 
@@ -27,8 +27,15 @@ pub struct FancyError {
 }
 
 impl FancyError {
+    /// Wrap an error context, giving it the generic `internal_error` code if it
+    /// has none
     #[must_use]
     pub fn new(context: ErrorContext) -> Self {
+        let context = if context.code().is_some() {
+            context
+        } else {
+            context.with_code(error_codes::INTERNAL_ERROR)
+        };
         Self { context }
     }
 }
@@ -53,6 +60,7 @@ impl std::fmt::Display for FancyError {
 impl<E: std::fmt::Debug + std::fmt::Display> From<E> for FancyError {
     fn from(err: E) -> Self {
         let context = ErrorContext::new()
+            .with_code(error_codes::INTERNAL_ERROR)
             .with_description(format!("{err}"))
             .with_details(format!("{err:?}"));
         FancyError { context }
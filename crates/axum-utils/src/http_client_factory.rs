@@ -53,6 +53,24 @@ impl HttpClientFactory {
             .layer(client)
     }
 
+    /// Constructs a new HTTP client which doesn't follow redirects, for
+    /// requests to URLs which are checked before being fetched
+    pub fn client_without_redirects<B>(
+        &self,
+        category: &'static str,
+    ) -> ClientService<TracedClient<B>>
+    where
+        B: axum::body::HttpBody + Send,
+        B::Data: Send,
+    {
+        let client = Client::builder().build(self.traced_connector.clone());
+        self.client_layer
+            .clone()
+            .with_category(category)
+            .without_redirects()
+            .layer(client)
+    }
+
     /// Constructs a new [`HttpService`], suitable for `mas-oidc-client`
     ///
     /// # Errors
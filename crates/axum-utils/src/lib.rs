@@ -32,6 +32,7 @@ pub mod jwt;
 pub mod language_detection;
 pub mod sentry;
 pub mod session;
+pub mod upstream_oauth2;
 pub mod user_authorization;
 
 pub use axum;
@@ -13,7 +13,7 @@
 // limitations under the License.
 
 use mas_data_model::BrowserSession;
-use mas_storage::{user::BrowserSessionRepository, RepositoryAccess};
+use mas_storage::{user::BrowserSessionRepository, Clock, RepositoryAccess};
 use serde::{Deserialize, Serialize};
 use ulid::Ulid;
 
@@ -44,6 +44,7 @@ impl SessionInfo {
     /// Load the [`BrowserSession`] from database
     pub async fn load_session<E>(
         &self,
+        clock: &impl Clock,
         repo: &mut impl RepositoryAccess<Error = E>,
     ) -> Result<Option<BrowserSession>, E> {
         let Some(session_id) = self.current else {
@@ -55,7 +56,7 @@ impl SessionInfo {
             .lookup(session_id)
             .await?
             // Ensure that the session is still active
-            .filter(BrowserSession::active);
+            .filter(|session| session.active_at(clock.now()));
 
         Ok(maybe_session)
     }
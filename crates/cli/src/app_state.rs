@@ -18,10 +18,13 @@ use axum::{
     async_trait,
     extract::{FromRef, FromRequestParts},
 };
+use hyper::HeaderMap;
 use ipnetwork::IpNetwork;
+use mas_config::ClientIpHeader;
 use mas_handlers::{
-    passwords::PasswordManager, ActivityTracker, BoundActivityTracker, CookieManager, ErrorWrapper,
-    HttpClientFactory, MatrixHomeserver, MetadataCache, SiteConfig,
+    passwords::PasswordManager, ActivityTracker, AppserviceRegistry, BoundActivityTracker,
+    CookieManager, ErrorWrapper, HttpClientFactory, IntrospectionCache, IpFilter, Limiter,
+    MatrixHomeserver, SharedHomeserverConnection, SiteConfig,
 };
 use mas_i18n::Translator;
 use mas_keystore::{Encrypter, Keystore};
@@ -46,14 +49,19 @@ pub struct AppState {
     pub encrypter: Encrypter,
     pub url_builder: UrlBuilder,
     pub homeserver: MatrixHomeserver,
+    pub homeserver_connection: SharedHomeserverConnection,
+    pub appservices: AppserviceRegistry,
     pub policy_factory: Arc<PolicyFactory>,
     pub graphql_schema: mas_graphql::Schema,
     pub http_client_factory: HttpClientFactory,
     pub password_manager: PasswordManager,
-    pub metadata_cache: MetadataCache,
     pub site_config: SiteConfig,
+    pub limiter: Limiter,
+    pub ip_filter: IpFilter,
+    pub introspection_cache: IntrospectionCache,
     pub activity_tracker: ActivityTracker,
     pub trusted_proxies: Vec<IpNetwork>,
+    pub client_ip_headers: Vec<ClientIpHeader>,
     pub conn_acquisition_histogram: Option<Histogram<u64>>,
 }
 
@@ -104,35 +112,6 @@ impl AppState {
 
         Ok(())
     }
-
-    /// Init the metadata cache.
-    ///
-    /// # Panics
-    ///
-    /// Panics if the metadata cache could not be initialized.
-    pub async fn init_metadata_cache(&self) {
-        // XXX: this panics because the error is annoying to propagate
-        let conn = self
-            .pool
-            .acquire()
-            .await
-            .expect("Failed to acquire a database connection");
-
-        let mut repo = PgRepository::from_conn(conn);
-
-        let http_service = self
-            .http_client_factory
-            .http_service("upstream_oauth2.metadata");
-
-        self.metadata_cache
-            .warm_up_and_run(
-                http_service,
-                std::time::Duration::from_secs(60 * 15),
-                &mut repo,
-            )
-            .await
-            .expect("Failed to warm up the metadata cache");
-    }
 }
 
 impl FromRef<AppState> for PgPool {
@@ -183,6 +162,18 @@ impl FromRef<AppState> for MatrixHomeserver {
     }
 }
 
+impl FromRef<AppState> for SharedHomeserverConnection {
+    fn from_ref(input: &AppState) -> Self {
+        input.homeserver_connection.clone()
+    }
+}
+
+impl FromRef<AppState> for AppserviceRegistry {
+    fn from_ref(input: &AppState) -> Self {
+        input.appservices.clone()
+    }
+}
+
 impl FromRef<AppState> for HttpClientFactory {
     fn from_ref(input: &AppState) -> Self {
         input.http_client_factory.clone()
@@ -201,15 +192,27 @@ impl FromRef<AppState> for CookieManager {
     }
 }
 
-impl FromRef<AppState> for MetadataCache {
+impl FromRef<AppState> for SiteConfig {
     fn from_ref(input: &AppState) -> Self {
-        input.metadata_cache.clone()
+        input.site_config.clone()
     }
 }
 
-impl FromRef<AppState> for SiteConfig {
+impl FromRef<AppState> for IpFilter {
     fn from_ref(input: &AppState) -> Self {
-        input.site_config.clone()
+        input.ip_filter.clone()
+    }
+}
+
+impl FromRef<AppState> for Limiter {
+    fn from_ref(input: &AppState) -> Self {
+        input.limiter.clone()
+    }
+}
+
+impl FromRef<AppState> for IntrospectionCache {
+    fn from_ref(input: &AppState) -> Self {
+        input.introspection_cache.clone()
     }
 }
 
@@ -268,9 +271,56 @@ impl FromRequestParts<AppState> for ActivityTracker {
     }
 }
 
+/// Read the chain of addresses the request was forwarded through from the
+/// given header, from the farthest hop to the nearest one
+///
+/// Hops which can't be parsed, like obfuscated identifiers in the `Forwarded`
+/// header, are `None`. Returns `None` if the header is absent.
+fn forwarded_chain(headers: &HeaderMap, header: ClientIpHeader) -> Option<Vec<Option<IpAddr>>> {
+    let name = match header {
+        ClientIpHeader::XForwardedFor => "x-forwarded-for",
+        ClientIpHeader::Forwarded => "forwarded",
+    };
+
+    let mut values = headers.get_all(name).iter().peekable();
+    values.peek()?;
+
+    let chain = values
+        .flat_map(|value| value.to_str().unwrap_or_default().split(','))
+        .map(|element| match header {
+            ClientIpHeader::XForwardedFor => element.trim().parse().ok(),
+            ClientIpHeader::Forwarded => parse_forwarded_for(element),
+        })
+        .collect();
+
+    Some(chain)
+}
+
+/// Parse the `for` parameter of an element of the `Forwarded` header
+fn parse_forwarded_for(element: &str) -> Option<IpAddr> {
+    let value = element.split(';').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        key.trim().eq_ignore_ascii_case("for").then(|| value.trim())
+    })?;
+    let value = value.trim_matches('"');
+
+    // IPv6 addresses are enclosed in brackets, optionally followed by a port
+    if let Some(rest) = value.strip_prefix('[') {
+        let (ip, _port) = rest.split_once(']')?;
+        return ip.parse().ok();
+    }
+
+    // IPv4 addresses can be followed by a port
+    value
+        .parse()
+        .ok()
+        .or_else(|| value.split_once(':')?.0.parse().ok())
+}
+
 fn infer_client_ip(
     parts: &axum::http::request::Parts,
     trusted_proxies: &[IpNetwork],
+    client_ip_headers: &[ClientIpHeader],
 ) -> Option<IpAddr> {
     let connection_info = parts.extensions.get::<mas_listener::ConnectionInfo>();
 
@@ -287,32 +337,40 @@ fn infer_client_ip(
         None
     };
 
-    // Get the list of IPs from the X-Forwarded-For header
-    let peers_from_header = parts
-        .headers
-        .get("x-forwarded-for")
-        .and_then(|value| value.to_str().ok())
-        .map(|value| value.split(',').filter_map(|v| v.parse().ok()))
-        .into_iter()
-        .flatten();
-
-    // This constructs a list of IP addresses that might be the client's IP address.
-    // Each intermediate proxy is supposed to add the client's IP address to front
-    // of the list. We are effectively adding the IP we got from the socket to the
-    // front of the list.
-    let peer_list: Vec<IpAddr> = peer.into_iter().chain(peers_from_header).collect();
-
-    // We'll fallback to the first IP in the list if all the IPs we got are trusted
-    let fallback = peer_list.first().copied();
-
-    // Now we go through the list, and the IP of the client is the first IP that is
-    // not in the list of trusted proxies, starting from the back.
-    let client_ip = peer_list
+    let is_trusted = |ip: IpAddr| trusted_proxies.iter().any(|network| network.contains(ip));
+
+    // Only trusted proxies can tell us who the client is. Connections without a
+    // peer address, like the ones on UNIX sockets, come from a local proxy.
+    if let Some(peer) = peer {
+        if !is_trusted(peer) {
+            return Some(peer);
+        }
+    }
+
+    let Some(chain) = client_ip_headers
         .iter()
-        .rfind(|ip| !trusted_proxies.iter().any(|network| network.contains(**ip)))
-        .copied();
+        .find_map(|header| forwarded_chain(&parts.headers, *header))
+    else {
+        return peer;
+    };
 
-    client_ip.or(fallback)
+    // Each proxy appends the address it got the request from to the chain. Walk it
+    // from the nearest hop, skipping the trusted proxies: the client is the first
+    // hop which is not trusted. If all of them are, use the farthest one.
+    let mut client_ip = peer;
+    for hop in chain.into_iter().rev() {
+        // Hops before an unknown one can't be trusted
+        let Some(hop) = hop else {
+            break;
+        };
+
+        client_ip = Some(hop);
+        if !is_trusted(hop) {
+            break;
+        }
+    }
+
+    client_ip
 }
 
 #[async_trait]
@@ -323,7 +381,7 @@ impl FromRequestParts<AppState> for BoundActivityTracker {
         parts: &mut axum::http::request::Parts,
         state: &AppState,
     ) -> Result<Self, Self::Rejection> {
-        let ip = infer_client_ip(parts, &state.trusted_proxies);
+        let ip = infer_client_ip(parts, &state.trusted_proxies, &state.client_ip_headers);
         tracing::debug!(ip = ?ip, "Inferred client IP address");
         Ok(state.activity_tracker.clone().bind(ip))
     }
@@ -353,3 +411,73 @@ impl FromRequestParts<AppState> for BoxRepository {
             .boxed())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request_parts(headers: &[(&str, &str)]) -> axum::http::request::Parts {
+        let mut request = axum::http::Request::builder();
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        request.body(()).unwrap().into_parts().0
+    }
+
+    #[test]
+    fn test_parse_forwarded_for() {
+        assert_eq!(
+            parse_forwarded_for("for=192.0.2.60;proto=http;by=203.0.113.43"),
+            Some([192, 0, 2, 60].into())
+        );
+        assert_eq!(
+            parse_forwarded_for(r#" For="[2001:db8:cafe::17]:4711""#),
+            Some("2001:db8:cafe::17".parse().unwrap())
+        );
+        assert_eq!(
+            parse_forwarded_for(r#"for="192.0.2.43:47011""#),
+            Some([192, 0, 2, 43].into())
+        );
+        assert_eq!(parse_forwarded_for("for=_hidden"), None);
+        assert_eq!(parse_forwarded_for("proto=https"), None);
+    }
+
+    #[test]
+    fn test_infer_client_ip() {
+        let trusted_proxies = ["10.0.0.0/8".parse().unwrap()];
+        let x_forwarded_for = [ClientIpHeader::XForwardedFor];
+        let forwarded = [ClientIpHeader::Forwarded];
+
+        // The trusted proxies are skipped
+        let parts = request_parts(&[("x-forwarded-for", "192.0.2.1, 198.51.100.1, 10.0.0.1")]);
+        assert_eq!(
+            infer_client_ip(&parts, &trusted_proxies, &x_forwarded_for),
+            Some([198, 51, 100, 1].into())
+        );
+
+        // Only the selected headers are looked at
+        let parts = request_parts(&[("forwarded", "for=192.0.2.1, for=10.0.0.2")]);
+        assert_eq!(
+            infer_client_ip(&parts, &trusted_proxies, &x_forwarded_for),
+            None
+        );
+        assert_eq!(
+            infer_client_ip(&parts, &trusted_proxies, &forwarded),
+            Some([192, 0, 2, 1].into())
+        );
+
+        // Hops before an unknown one are not trusted
+        let parts = request_parts(&[("forwarded", "for=192.0.2.1, for=unknown, for=10.0.0.2")]);
+        assert_eq!(
+            infer_client_ip(&parts, &trusted_proxies, &forwarded),
+            Some([10, 0, 0, 2].into())
+        );
+
+        // The farthest hop is used if all of them are trusted
+        let parts = request_parts(&[("x-forwarded-for", "10.0.0.1, 10.0.0.2")]);
+        assert_eq!(
+            infer_client_ip(&parts, &trusted_proxies, &x_forwarded_for),
+            Some([10, 0, 0, 1].into())
+        );
+    }
+}
@@ -14,17 +14,23 @@
 
 use std::collections::HashSet;
 
+use anyhow::Context;
 use clap::Parser;
 use mas_config::{ConfigurationSection, RootConfig, SyncConfig};
+use mas_handlers::HttpClientFactory;
+use mas_iana::oauth::OAuthClientAuthenticationMethod;
 use mas_storage::{
     upstream_oauth2::UpstreamOAuthProviderRepository, RepositoryAccess, SystemClock,
 };
 use mas_storage_pg::PgRepository;
+use oauth2_types::{registration::is_hex_color, scope::Scope};
 use rand::SeedableRng;
 use sqlx::{postgres::PgAdvisoryLock, Acquire};
-use tracing::{info, info_span, warn};
+use tracing::{info, info_span, warn, Instrument};
 
-use crate::util::database_connection_from_config;
+use crate::util::{
+    claim_mappings_from_config, database_connection_from_config, external_secrets_from_config,
+};
 
 fn map_import_action(
     config: &mas_config::UpstreamOAuth2ImportAction,
@@ -50,6 +56,140 @@ fn map_import_preference(
 ) -> mas_data_model::UpstreamOAuthProviderImportPreference {
     mas_data_model::UpstreamOAuthProviderImportPreference {
         action: map_import_action(&config.action),
+        sync: mas_data_model::UpstreamOAuthProviderImportSync::default(),
+        template: config.template.clone(),
+    }
+}
+
+fn map_profile_import_preference(
+    config: &mas_config::UpstreamOAuth2ProfileImportPreference,
+) -> mas_data_model::UpstreamOAuthProviderImportPreference {
+    mas_data_model::UpstreamOAuthProviderImportPreference {
+        action: map_import_action(&config.action),
+        sync: match config.sync {
+            mas_config::UpstreamOAuth2ImportSync::Once => {
+                mas_data_model::UpstreamOAuthProviderImportSync::Once
+            }
+            mas_config::UpstreamOAuth2ImportSync::Always => {
+                mas_data_model::UpstreamOAuthProviderImportSync::Always
+            }
+            mas_config::UpstreamOAuth2ImportSync::UnlessModified => {
+                mas_data_model::UpstreamOAuthProviderImportSync::UnlessModified
+            }
+        },
+        template: config.template.clone(),
+    }
+}
+
+fn map_pkce_method(
+    config: mas_config::UpstreamOAuth2PkceMethod,
+) -> mas_data_model::UpstreamOAuthProviderPkceMethod {
+    match config {
+        mas_config::UpstreamOAuth2PkceMethod::Auto => {
+            mas_data_model::UpstreamOAuthProviderPkceMethod::Auto
+        }
+        mas_config::UpstreamOAuth2PkceMethod::Always => {
+            mas_data_model::UpstreamOAuthProviderPkceMethod::Always
+        }
+        mas_config::UpstreamOAuth2PkceMethod::Never => {
+            mas_data_model::UpstreamOAuthProviderPkceMethod::Never
+        }
+    }
+}
+
+fn map_authorization_params(
+    provider: &mas_config::UpstreamOAuth2Provider,
+) -> mas_data_model::UpstreamOAuthProviderAuthorizationParams {
+    mas_data_model::UpstreamOAuthProviderAuthorizationParams {
+        response_mode: provider.response_mode.map(|mode| match mode {
+            mas_config::UpstreamOAuth2ResponseMode::Query => {
+                mas_data_model::UpstreamOAuthProviderResponseMode::Query
+            }
+            mas_config::UpstreamOAuth2ResponseMode::FormPost => {
+                mas_data_model::UpstreamOAuthProviderResponseMode::FormPost
+            }
+        }),
+        prompt: provider.prompt.clone(),
+        forward_login_hint: provider.forward_login_hint,
+        max_age: provider.max_age,
+        revalidation_interval: provider.revalidation_interval,
+        additional_parameters: provider
+            .additional_authorization_parameters
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect(),
+    }
+}
+
+fn map_ui_options(
+    provider: &mas_config::UpstreamOAuth2Provider,
+) -> mas_data_model::UpstreamOAuthProviderUiOptions {
+    mas_data_model::UpstreamOAuthProviderUiOptions {
+        human_name: provider.human_name.clone(),
+        icon: provider.icon.as_ref().map(ToString::to_string),
+        brand_color: provider.brand_color.clone(),
+        sort_order: provider.sort_order,
+        show_on_login: provider.show_on_login,
+    }
+}
+
+fn map_protocol(
+    config: mas_config::UpstreamOAuth2Protocol,
+) -> mas_data_model::UpstreamOAuthProviderProtocol {
+    match config {
+        mas_config::UpstreamOAuth2Protocol::Oidc => {
+            mas_data_model::UpstreamOAuthProviderProtocol::Oidc
+        }
+        mas_config::UpstreamOAuth2Protocol::OAuth2 => {
+            mas_data_model::UpstreamOAuthProviderProtocol::OAuth2
+        }
+        mas_config::UpstreamOAuth2Protocol::Saml => {
+            mas_data_model::UpstreamOAuthProviderProtocol::Saml
+        }
+    }
+}
+
+fn map_saml(
+    provider: &mas_config::UpstreamOAuth2Provider,
+) -> mas_data_model::UpstreamOAuthProviderSamlSettings {
+    provider
+        .saml
+        .as_ref()
+        .map(|saml| mas_data_model::UpstreamOAuthProviderSamlSettings {
+            sso_url: Some(saml.sso_url.clone()),
+            idp_certificates: saml.idp_certificates.clone(),
+            name_id_format: saml.name_id_format.clone(),
+        })
+        .unwrap_or_default()
+}
+
+fn map_health_check(
+    provider: &mas_config::UpstreamOAuth2Provider,
+) -> mas_data_model::UpstreamOAuthProviderHealthCheckSettings {
+    provider
+        .health_check
+        .map(
+            |health_check| mas_data_model::UpstreamOAuthProviderHealthCheckSettings {
+                probe_token_endpoint: health_check.token_endpoint,
+                hide_when_unhealthy: health_check.hide_when_unhealthy,
+            },
+        )
+        .unwrap_or_default()
+}
+
+fn map_endpoints(
+    provider: &mas_config::UpstreamOAuth2Provider,
+) -> mas_data_model::UpstreamOAuthProviderEndpoints {
+    mas_data_model::UpstreamOAuthProviderEndpoints {
+        authorization_endpoint: provider.authorization_endpoint.clone(),
+        token_endpoint: provider.token_endpoint.clone(),
+        userinfo_endpoint: provider.userinfo_endpoint.clone(),
+        jwks_uri: provider.jwks_uri.clone(),
+        additional_userinfo_endpoints: provider
+            .additional_userinfo_endpoints
+            .iter()
+            .map(|(name, url)| (name.clone(), url.clone()))
+            .collect(),
     }
 }
 
@@ -65,13 +205,20 @@ fn map_claims_imports(
         displayname: config
             .displayname
             .as_ref()
-            .map(map_import_preference)
+            .map(map_profile_import_preference)
+            .unwrap_or_default(),
+        avatar_url: config
+            .avatar_url
+            .as_ref()
+            .map(map_profile_import_preference)
             .unwrap_or_default(),
         email: config
             .email
             .as_ref()
             .map(|c| mas_data_model::UpstreamOAuthProviderImportPreference {
                 action: map_import_action(&c.action),
+                sync: mas_data_model::UpstreamOAuthProviderImportSync::default(),
+                template: c.template.clone(),
             })
             .unwrap_or_default(),
         // XXX: this is a bit ugly
@@ -90,6 +237,63 @@ fn map_claims_imports(
                 }
             })
             .unwrap_or_default(),
+        localpart_conflict: config
+            .localpart
+            .as_ref()
+            .map(|c| match c.on_conflict {
+                mas_config::UpstreamOAuth2OnConflict::Fail => {
+                    mas_data_model::UpstreamOAuthProviderLocalpartConflict::Fail
+                }
+                mas_config::UpstreamOAuth2OnConflict::AddSuffix => {
+                    mas_data_model::UpstreamOAuthProviderLocalpartConflict::AddSuffix
+                }
+                mas_config::UpstreamOAuth2OnConflict::Interactive => {
+                    mas_data_model::UpstreamOAuthProviderLocalpartConflict::Interactive
+                }
+                mas_config::UpstreamOAuth2OnConflict::Link => {
+                    mas_data_model::UpstreamOAuthProviderLocalpartConflict::Link
+                }
+            })
+            .unwrap_or_default(),
+        link_by_email: config.email.as_ref().is_some_and(|c| c.link_existing),
+        groups: config
+            .groups
+            .as_ref()
+            .map(|c| mas_data_model::UpstreamOAuthProviderGroupsImport {
+                claim: Some(c.claim.clone()),
+                admin_groups: c.admin_groups.clone(),
+            })
+            .unwrap_or_default(),
+        subject_template: config.subject.as_ref().and_then(|c| c.template.clone()),
+        locked_attributes: config
+            .locked_attributes
+            .iter()
+            .map(|attribute| match attribute {
+                mas_config::UpstreamOAuth2ProfileAttribute::Displayname => {
+                    mas_data_model::ProfileAttribute::Displayname
+                }
+                mas_config::UpstreamOAuth2ProfileAttribute::AvatarUrl => {
+                    mas_data_model::ProfileAttribute::AvatarUrl
+                }
+                mas_config::UpstreamOAuth2ProfileAttribute::Email => {
+                    mas_data_model::ProfileAttribute::Email
+                }
+            })
+            .collect(),
+    }
+}
+
+fn map_redirect_uri_matching(
+    config: mas_config::RedirectUriMatchingConfig,
+) -> mas_data_model::RedirectUriMatching {
+    match config {
+        mas_config::RedirectUriMatchingConfig::Exact => mas_data_model::RedirectUriMatching::Exact,
+        mas_config::RedirectUriMatchingConfig::Loopback => {
+            mas_data_model::RedirectUriMatching::Loopback
+        }
+        mas_config::RedirectUriMatchingConfig::SubdomainWildcard => {
+            mas_data_model::RedirectUriMatching::SubdomainWildcard
+        }
     }
 }
 
@@ -161,13 +365,41 @@ impl Options {
     }
 }
 
+/// Slugs end up in URLs, so they are restricted to a safe set of characters
+fn is_valid_slug(slug: &str) -> bool {
+    !slug.is_empty()
+        && slug
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
 #[tracing::instrument(name = "cli.config.sync", skip(root), err(Debug))]
 async fn sync(root: &super::Options, prune: bool, dry_run: bool) -> anyhow::Result<()> {
+    let config: SyncConfig = root.load_config()?;
+    let http_client_factory = HttpClientFactory::new().await?;
+    sync_config(config, &http_client_factory, prune, dry_run).await
+}
+
+/// Sync the clients, upstream providers and JWT bearer issuers of the config
+/// to the database
+pub(crate) async fn sync_config(
+    mut config: SyncConfig,
+    http_client_factory: &HttpClientFactory,
+    prune: bool,
+    dry_run: bool,
+) -> anyhow::Result<()> {
     // XXX: we should disallow SeedableRng::from_entropy
+    let mut rng = rand_chacha::ChaChaRng::from_entropy();
     let clock = SystemClock::default();
 
-    let config: SyncConfig = root.load_config()?;
-    let encrypter = config.secrets.encrypter();
+    if let Some(secrets) =
+        external_secrets_from_config(&config.secrets, http_client_factory).await?
+    {
+        config.secrets.apply_external_secrets(&secrets);
+        config.upstream_oauth2.apply_external_secrets(&secrets)?;
+    }
+
+    let encrypter = config.secrets.encrypter()?;
     // Grab a connection to the database
     let mut conn = database_connection_from_config(&config.database).await?;
     // Start a transaction
@@ -184,11 +416,118 @@ async fn sync(root: &super::Options, prune: bool, dry_run: bool) -> anyhow::Resu
     tracing::info!(
         prune,
         dry_run,
-        "Syncing providers and clients defined in config to database"
+        "Syncing providers, clients and JWT bearer issuers defined in config to database"
     );
 
-    {
-        let _span = info_span!("cli.config.sync.providers").entered();
+    async {
+        let mut slugs = HashSet::new();
+        for provider in &config.upstream_oauth2.providers {
+            if let Some(slug) = provider.slug.as_deref() {
+                if !is_valid_slug(slug) {
+                    anyhow::bail!("Invalid slug {slug:?} for provider {}", provider.id);
+                }
+
+                if !slugs.insert(slug) {
+                    anyhow::bail!("Multiple providers have the slug {slug:?}");
+                }
+            }
+
+            // Brand colors end up in the style of the login buttons, so only
+            // hexadecimal colors are allowed
+            if let Some(brand_color) = provider.brand_color.as_deref() {
+                if !is_hex_color(brand_color) {
+                    anyhow::bail!(
+                        "Invalid brand color {brand_color:?} for provider {}",
+                        provider.id
+                    );
+                }
+            }
+
+            if provider.registration.is_none() {
+                if provider.client_id.is_none() {
+                    anyhow::bail!(
+                        "Provider {} has no client_id and doesn't use dynamic registration",
+                        provider.id
+                    );
+                }
+
+                if provider.client_secret().is_none()
+                    && matches!(
+                        provider.client_auth_method(),
+                        OAuthClientAuthenticationMethod::ClientSecretBasic
+                            | OAuthClientAuthenticationMethod::ClientSecretPost
+                            | OAuthClientAuthenticationMethod::ClientSecretJwt
+                    )
+                {
+                    anyhow::bail!(
+                        "Provider {} has no client_secret and doesn't use dynamic registration",
+                        provider.id
+                    );
+                }
+            }
+
+            // Plain OAuth 2.0 providers aren't discovered
+            if provider.protocol == mas_config::UpstreamOAuth2Protocol::OAuth2 {
+                let endpoints = [
+                    ("authorization_endpoint", &provider.authorization_endpoint),
+                    ("token_endpoint", &provider.token_endpoint),
+                    ("userinfo_endpoint", &provider.userinfo_endpoint),
+                ];
+                for (name, endpoint) in endpoints {
+                    if endpoint.is_none() {
+                        anyhow::bail!(
+                            "Provider {} uses the oauth2 protocol but has no {name}",
+                            provider.id
+                        );
+                    }
+                }
+            }
+
+            if provider.protocol == mas_config::UpstreamOAuth2Protocol::Saml {
+                let Some(saml) = &provider.saml else {
+                    anyhow::bail!(
+                        "Provider {} uses the saml protocol but has no saml section",
+                        provider.id
+                    );
+                };
+
+                if saml.idp_certificates.is_empty() {
+                    anyhow::bail!("Provider {} has no SAML certificate", provider.id);
+                }
+
+                for certificate in &saml.idp_certificates {
+                    certificate
+                        .parse::<mas_saml::Certificate>()
+                        .with_context(|| {
+                            format!("Invalid SAML certificate for provider {}", provider.id)
+                        })?;
+                }
+
+                if provider.store_tokens || !provider.token_clients.is_empty() {
+                    anyhow::bail!(
+                        "Provider {} uses the saml protocol, which doesn't issue tokens to store",
+                        provider.id
+                    );
+                }
+
+                if provider.registration.is_some() {
+                    anyhow::bail!(
+                        "Provider {} uses the saml protocol, which doesn't support dynamic \
+                         registration",
+                        provider.id
+                    );
+                }
+
+                if provider.max_age.is_some() || provider.revalidation_interval.is_some() {
+                    anyhow::bail!(
+                        "Provider {} uses the saml protocol, which doesn't support max_age and \
+                         revalidation_interval",
+                        provider.id
+                    );
+                }
+            }
+        }
+
         let config_ids = config
             .upstream_oauth2
             .providers
@@ -229,37 +568,113 @@ async fn sync(root: &super::Options, prune: bool, dry_run: bool) -> anyhow::Resu
                 continue;
             }
 
-            let encrypted_client_secret = provider
-                .client_secret()
-                .map(|client_secret| encrypter.encrypt_to_string(client_secret.as_bytes()))
-                .transpose()?;
+            // Providers registered with keep the credentials they issued, as long as
+            // the configuration asks for dynamic registration
+            let existing = repo.upstream_oauth_provider().lookup(provider.id).await?;
+            let registered = match existing {
+                Some(existing) => {
+                    let registration = repo
+                        .upstream_oauth_provider()
+                        .registration(&existing)
+                        .await?;
+                    registration.map(|_| existing)
+                }
+                None => None,
+            };
+
+            let (client_id, encrypted_client_secret) = match (&provider.registration, &registered) {
+                (Some(_), Some(registered)) => (
+                    registered.client_id.clone(),
+                    registered.encrypted_client_secret.clone(),
+                ),
+                _ => {
+                    if provider.registration.is_some() {
+                        warn!(
+                            %provider.id,
+                            "Provider is not registered with yet. Run `mas-cli upstream register` \
+                             to register with it."
+                        );
+                    }
+
+                    let encrypted_client_secret = provider
+                        .client_secret()
+                        .map(|client_secret| encrypter.encrypt_to_string(client_secret.as_bytes()))
+                        .transpose()?;
+                    (
+                        provider.client_id.clone().unwrap_or_default(),
+                        encrypted_client_secret,
+                    )
+                }
+            };
+            let forget_registration = provider.registration.is_none() && registered.is_some();
+
             let client_auth_method = provider.client_auth_method();
             let client_auth_signing_alg = provider.client_auth_signing_alg();
-
-            repo.upstream_oauth_provider()
+            let authorization_params = map_authorization_params(&provider);
+            let ui_options = map_ui_options(&provider);
+            let endpoints = map_endpoints(&provider);
+            let saml = map_saml(&provider);
+            let health_check = map_health_check(&provider);
+
+            let provider = repo
+                .upstream_oauth_provider()
                 .upsert(
                     &clock,
                     provider.id,
                     provider.issuer,
+                    provider.slug,
                     provider.scope.parse()?,
                     client_auth_method,
                     client_auth_signing_alg,
-                    provider.client_id,
+                    client_id,
                     encrypted_client_secret,
                     map_claims_imports(&provider.claims_imports),
+                    map_pkce_method(provider.pkce_method),
+                    authorization_params,
+                    provider.fetch_userinfo,
+                    ui_options,
+                    map_protocol(provider.protocol),
+                    endpoints,
+                    saml,
+                    provider.store_tokens,
+                    health_check,
                 )
                 .await?;
+
+            if forget_registration {
+                info!(%provider.id, "Forgetting the registration with the provider");
+                repo.upstream_oauth_provider()
+                    .remove_registration(&provider)
+                    .await?;
+            }
         }
+
+        Ok::<_, anyhow::Error>(())
     }
+    .instrument(info_span!("cli.config.sync.providers"))
+    .await?;
 
-    {
-        let _span = info_span!("cli.config.sync.clients").entered();
+    async {
         let config_ids = config
             .clients
             .iter()
             .map(|c| c.client_id)
             .collect::<HashSet<_>>();
 
+        // Clients can only get their userinfo responses signed with the algorithms
+        // our keys support
+        let key_store = config.secrets.key_store().await?;
+        for client in config.clients.iter() {
+            if let Some(alg) = &client.userinfo_signed_response_alg {
+                if key_store.signing_key_for_algorithm(alg).is_none() {
+                    anyhow::bail!(
+                        "No key supports the userinfo_signed_response_alg {alg} of client {}",
+                        client.client_id
+                    );
+                }
+            }
+        }
+
         let existing = repo.oauth2_client().all_static().await?;
         let existing_ids = existing.iter().map(|p| p.id).collect::<HashSet<_>>();
         let to_delete = existing.into_iter().filter(|p| !config_ids.contains(&p.id));
@@ -289,6 +704,20 @@ async fn sync(root: &super::Options, prune: bool, dry_run: bool) -> anyhow::Resu
                 info!(client.id = %client.client_id, "Adding client");
             }
 
+            let redirect_uri_matching = map_redirect_uri_matching(client.redirect_uri_matching);
+            if redirect_uri_matching != mas_data_model::RedirectUriMatching::SubdomainWildcard {
+                if let Some(uri) = client
+                    .redirect_uris
+                    .iter()
+                    .find(|uri| mas_data_model::RedirectUriMatching::is_wildcard(uri))
+                {
+                    anyhow::bail!(
+                        "Client {} has the wildcard redirect URI {uri}, which requires `redirect_uri_matching: subdomain_wildcard`",
+                        client.client_id
+                    );
+                }
+            }
+
             if dry_run {
                 continue;
             }
@@ -303,7 +732,30 @@ async fn sync(root: &super::Options, prune: bool, dry_run: bool) -> anyhow::Resu
                 .map(|client_secret| encrypter.encrypt_to_string(client_secret.as_bytes()))
                 .transpose()?;
 
-            repo.oauth2_client()
+            let client_credentials_scope: Option<Scope> = client
+                .client_credentials_scope
+                .as_deref()
+                .map(str::parse)
+                .transpose()?;
+
+            let token_settings = mas_data_model::ClientTokenSettings {
+                access_token_ttl: client.access_token_ttl,
+                refresh_token_ttl: client.refresh_token_ttl,
+                additional_audiences: client.additional_audiences.clone(),
+            };
+
+            let claim_mappings = claim_mappings_from_config(&client.claims)
+                .with_context(|| format!("Invalid claims of client {}", client.client_id))?;
+            let requires_approval = client.requires_approval;
+
+            let trusted_scope: Option<Scope> = client
+                .effective_trusted_scope()
+                .map(str::parse)
+                .transpose()
+                .with_context(|| format!("Invalid trusted scope of client {}", client.client_id))?;
+
+            let client = repo
+                .oauth2_client()
                 .upsert_static(
                     client.client_id,
                     client_auth_method,
@@ -311,10 +763,129 @@ async fn sync(root: &super::Options, prune: bool, dry_run: bool) -> anyhow::Resu
                     jwks.cloned(),
                     jwks_uri.cloned(),
                     client.redirect_uris.clone(),
+                    client.refresh_tokens,
+                    client.userinfo_signed_response_alg.clone(),
+                )
+                .await?;
+
+            repo.oauth2_client()
+                .set_client_credentials_scope(&client, client_credentials_scope.as_ref())
+                .await?;
+
+            repo.oauth2_client()
+                .set_token_settings(&client, &token_settings)
+                .await?;
+
+            repo.oauth2_client()
+                .set_redirect_uri_matching(&client, redirect_uri_matching)
+                .await?;
+
+            repo.oauth2_client()
+                .set_claim_mappings(&client, &claim_mappings)
+                .await?;
+
+            repo.oauth2_client()
+                .set_requires_approval(&client, requires_approval)
+                .await?;
+
+            repo.oauth2_client()
+                .set_trusted_scope(&client, trusted_scope.as_ref())
+                .await?;
+        }
+
+        Ok::<_, anyhow::Error>(())
+    }
+    .instrument(info_span!("cli.config.sync.clients"))
+    .await?;
+
+    async {
+        let config_issuers = config
+            .jwt_bearer
+            .issuers
+            .iter()
+            .map(|i| i.issuer.as_str())
+            .collect::<HashSet<_>>();
+
+        if config_issuers.len() != config.jwt_bearer.issuers.len() {
+            anyhow::bail!("Multiple JWT bearer issuers have the same issuer");
+        }
+
+        let existing = repo.oauth2_jwt_bearer_issuer().all().await?;
+        let existing_issuers = existing
+            .iter()
+            .map(|i| i.issuer.clone())
+            .collect::<HashSet<_>>();
+        let to_delete = existing
+            .into_iter()
+            .filter(|i| !config_issuers.contains(i.issuer.as_str()));
+        if prune {
+            for issuer in to_delete {
+                info!(jwt_bearer_issuer.issuer = %issuer.issuer, "Deleting JWT bearer issuer");
+
+                if dry_run {
+                    continue;
+                }
+
+                repo.oauth2_jwt_bearer_issuer().delete(issuer).await?;
+            }
+        } else {
+            let len = to_delete.count();
+            match len {
+                0 => {},
+                1 => warn!("A JWT bearer issuer in the database is not in the config. Run with `--prune` to delete it."),
+                n => warn!("{n} JWT bearer issuers in the database are not in the config. Run with `--prune` to delete them."),
+            }
+        }
+
+        for issuer in &config.jwt_bearer.issuers {
+            if existing_issuers.contains(&issuer.issuer) {
+                info!(jwt_bearer_issuer.issuer = %issuer.issuer, "Updating JWT bearer issuer");
+            } else {
+                info!(jwt_bearer_issuer.issuer = %issuer.issuer, "Adding JWT bearer issuer");
+            }
+
+            let allowed_scope: Option<Scope> = issuer
+                .allowed_scope
+                .as_deref()
+                .map(str::parse)
+                .transpose()
+                .with_context(|| {
+                    format!(
+                        "Invalid allowed_scope for JWT bearer issuer {}",
+                        issuer.issuer
+                    )
+                })?;
+
+            if dry_run {
+                continue;
+            }
+
+            let jwks = match &issuer.jwks {
+                mas_config::JwksOrJwksUri::Jwks(jwks) => {
+                    mas_data_model::JwksOrJwksUri::Jwks(jwks.clone())
+                }
+                mas_config::JwksOrJwksUri::JwksUri(uri) => {
+                    mas_data_model::JwksOrJwksUri::JwksUri(uri.clone())
+                }
+            };
+
+            repo.oauth2_jwt_bearer_issuer()
+                .upsert(
+                    &mut rng,
+                    &clock,
+                    issuer.issuer.clone(),
+                    jwks,
+                    issuer.allowed_subjects.clone(),
+                    issuer.allowed_clients.clone(),
+                    allowed_scope,
                 )
                 .await?;
         }
+
+        Ok::<_, anyhow::Error>(())
     }
+    .instrument(info_span!("cli.config.sync.jwt_bearer_issuers"))
+    .await?;
 
     // Get the lock and release it to commit the transaction
     let lock = repo.into_inner();
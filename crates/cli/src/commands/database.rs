@@ -14,11 +14,14 @@
 
 use anyhow::Context;
 use clap::Parser;
-use mas_config::DatabaseConfig;
+use mas_config::{DatabaseConfig, SecretsConfig};
+use mas_handlers::HttpClientFactory;
+use mas_keystore::DecryptError;
 use mas_storage_pg::MIGRATOR;
-use tracing::{info_span, Instrument};
+use sqlx::Acquire;
+use tracing::{info, info_span, Instrument};
 
-use crate::util::database_connection_from_config;
+use crate::util::{database_connection_from_config, external_secrets_from_config};
 
 #[derive(Parser, Debug)]
 pub(super) struct Options {
@@ -30,21 +33,80 @@ pub(super) struct Options {
 enum Subcommand {
     /// Run database migrations
     Migrate,
+
+    /// Re-encrypt the encrypted database fields with the newest encryption
+    /// key, so that the previous keys can be removed from the configuration
+    Reencrypt {
+        /// Re-encrypt the fields encrypted with the newest key as well
+        #[clap(long)]
+        all: bool,
+
+        /// Don't commit the changes, only print how many fields would be
+        /// re-encrypted
+        #[clap(long)]
+        dry_run: bool,
+    },
 }
 
 impl Options {
     pub async fn run(self, root: &super::Options) -> anyhow::Result<()> {
-        let _span = info_span!("cli.database.migrate").entered();
-        let config: DatabaseConfig = root.load_config()?;
-        let mut conn = database_connection_from_config(&config).await?;
-
-        // Run pending migrations
-        MIGRATOR
-            .run(&mut conn)
-            .instrument(info_span!("db.migrate"))
-            .await
-            .context("could not run migrations")?;
-
-        Ok(())
+        match self.subcommand {
+            Subcommand::Migrate => {
+                let _span = info_span!("cli.database.migrate").entered();
+                let config: DatabaseConfig = root.load_config()?;
+                let mut conn = database_connection_from_config(&config).await?;
+
+                // Run pending migrations
+                MIGRATOR
+                    .run(&mut conn)
+                    .instrument(info_span!("db.migrate"))
+                    .await
+                    .context("could not run migrations")?;
+
+                Ok(())
+            }
+
+            Subcommand::Reencrypt { all, dry_run } => {
+                let _span = info_span!("cli.database.reencrypt").entered();
+                let database_config: DatabaseConfig = root.load_config()?;
+                let mut secrets_config: SecretsConfig = root.load_config()?;
+
+                let http_client_factory = HttpClientFactory::new().await?;
+                if let Some(secrets) =
+                    external_secrets_from_config(&secrets_config, &http_client_factory).await?
+                {
+                    secrets_config.apply_external_secrets(&secrets);
+                }
+
+                let encrypter = secrets_config.encrypter()?;
+
+                let mut conn = database_connection_from_config(&database_config).await?;
+                let mut txn = conn.begin().await?;
+
+                let count = mas_storage_pg::encryption::reencrypt(&mut txn, |encrypted| {
+                    if !all && !encrypter.is_outdated(encrypted) {
+                        return Ok(None);
+                    }
+
+                    let decrypted = encrypter.decrypt_string(encrypted)?;
+                    let reencrypted = encrypter
+                        .encrypt_to_string(&decrypted)
+                        .map_err(DecryptError::from)?;
+                    Ok::<_, DecryptError>(Some(reencrypted))
+                })
+                .await
+                .context("could not re-encrypt the database fields")?;
+
+                if dry_run {
+                    info!(count, "Dry run, not committing");
+                    txn.rollback().await?;
+                } else {
+                    info!(count, "Re-encrypted the database fields");
+                    txn.commit().await?;
+                }
+
+                Ok(())
+            }
+        }
     }
 }
@@ -14,14 +14,19 @@
 
 use clap::Parser;
 use hyper::{Response, Uri};
-use mas_config::PolicyConfig;
+use mas_config::{
+    FeaturesConfig, PolicyConfig, ScopesConfig, UpstreamOAuth2Config, UsernamesConfig,
+};
 use mas_handlers::HttpClientFactory;
 use mas_http::HttpServiceExt;
 use tokio::io::AsyncWriteExt;
 use tower::{Service, ServiceExt};
 use tracing::{info, info_span};
 
-use crate::util::policy_factory_from_config;
+use crate::{
+    policy_watcher::PolicySource,
+    util::{oauth2_features_from_config, policy_factory_from_config},
+};
 
 #[derive(Parser, Debug)]
 pub(super) struct Options {
@@ -121,8 +126,22 @@ impl Options {
             SC::Policy => {
                 let _span = info_span!("cli.debug.policy").entered();
                 let config: PolicyConfig = root.load_config()?;
+                let usernames: UsernamesConfig = root.load_config()?;
+                let scopes: ScopesConfig = root.load_config()?;
+                let features: FeaturesConfig = root.load_config()?;
+                let features = oauth2_features_from_config(&features);
+                let upstream_oauth2: UpstreamOAuth2Config = root.load_config()?;
                 info!("Loading and compiling the policy module");
-                let policy_factory = policy_factory_from_config(&config).await?;
+                let mut source = PolicySource::from_config(&config, &http_client_factory);
+                let policy_factory = policy_factory_from_config(
+                    &config,
+                    &usernames,
+                    &scopes,
+                    &features,
+                    &upstream_oauth2,
+                    &mut source,
+                )
+                .await?;
 
                 let _instance = policy_factory.instantiate().await?;
             }
@@ -18,7 +18,10 @@ use mas_config::{DatabaseConfig, PasswordsConfig};
 use mas_data_model::{Device, TokenType};
 use mas_storage::{
     compat::{CompatAccessTokenRepository, CompatSessionRepository},
-    job::{DeactivateUserJob, DeleteDeviceJob, JobRepositoryExt, ProvisionUserJob},
+    job::{
+        DeactivateUserJob, DeleteDeviceJob, JobRepositoryExt, NotifyUserEventJob, ProvisionUserJob,
+        SendSecurityNotificationJob, UserLifecycleEvent,
+    },
     user::{UserEmailRepository, UserPasswordRepository, UserRepository},
     RepositoryAccess, SystemClock,
 };
@@ -85,6 +88,18 @@ enum Subcommand {
         /// User to unlock
         username: String,
     },
+
+    /// Quarantine a user, preventing them from starting new sessions
+    QuarantineUser {
+        /// User to quarantine
+        username: String,
+    },
+
+    /// Lift the quarantine of a user
+    UnquarantineUser {
+        /// User to lift the quarantine of
+        username: String,
+    },
 }
 
 impl Options {
@@ -122,6 +137,10 @@ impl Options {
                     .add(&mut rng, &clock, &user, version, hashed_password, None)
                     .await?;
 
+                repo.job()
+                    .schedule_job(SendSecurityNotificationJob::password_changed(&user))
+                    .await?;
+
                 info!(%user.id, %user.username, "Password changed");
                 repo.into_inner().commit().await?;
 
@@ -154,6 +173,10 @@ impl Options {
                     .context("Email not found")?;
                 let email = repo.user_email().mark_as_verified(&clock, email).await?;
 
+                repo.job()
+                    .schedule_job(NotifyUserEventJob::email_verified(&email))
+                    .await?;
+
                 repo.into_inner().commit().await?;
                 info!(?email, "Email marked as verified");
 
@@ -184,7 +207,7 @@ impl Options {
 
                 let compat_session = repo
                     .compat_session()
-                    .add(&mut rng, &clock, &user, device, admin)
+                    .add(&mut rng, &clock, &user, device, None, admin)
                     .await?;
 
                 let token = TokenType::CompatAccessToken.generate(&mut rng);
@@ -335,6 +358,12 @@ impl Options {
                         continue;
                     }
 
+                    if browser_session.is_impersonation() {
+                        repo.user_impersonation()
+                            .end(&clock, &browser_session)
+                            .await?;
+                    }
+
                     repo.browser_session()
                         .finish(&clock, browser_session)
                         .await?;
@@ -374,6 +403,14 @@ impl Options {
                 // synchronously yet.
                 let user = repo.user().lock(&clock, user).await?;
 
+                repo.job()
+                    .schedule_job(NotifyUserEventJob::new(&user, UserLifecycleEvent::Locked))
+                    .await?;
+
+                repo.job()
+                    .schedule_job(SendSecurityNotificationJob::account_locked(&user))
+                    .await?;
+
                 if deactivate {
                     warn!(%user.id, "Scheduling user deactivation");
                     repo.job()
@@ -406,6 +443,50 @@ impl Options {
 
                 Ok(())
             }
+
+            SC::QuarantineUser { username } => {
+                let _span =
+                    info_span!("cli.manage.quarantine_user", user.username = username).entered();
+                let config: DatabaseConfig = root.load_config()?;
+                let mut conn = database_connection_from_config(&config).await?;
+                let txn = conn.begin().await?;
+                let mut repo = PgRepository::from_conn(txn);
+
+                let user = repo
+                    .user()
+                    .find_by_username(&username)
+                    .await?
+                    .context("User not found")?;
+
+                info!(%user.id, "Quarantining user");
+
+                repo.user().quarantine(&clock, user).await?;
+                repo.into_inner().commit().await?;
+
+                Ok(())
+            }
+
+            SC::UnquarantineUser { username } => {
+                let _span =
+                    info_span!("cli.manage.unquarantine_user", user.username = username).entered();
+                let config: DatabaseConfig = root.load_config()?;
+                let mut conn = database_connection_from_config(&config).await?;
+                let txn = conn.begin().await?;
+                let mut repo = PgRepository::from_conn(txn);
+
+                let user = repo
+                    .user()
+                    .find_by_username(&username)
+                    .await?
+                    .context("User not found")?;
+
+                info!(%user.id, "Lifting user quarantine");
+
+                repo.user().unquarantine(user).await?;
+                repo.into_inner().commit().await?;
+
+                Ok(())
+            }
         }
     }
 }
@@ -23,8 +23,11 @@ mod debug;
 mod manage;
 mod server;
 mod templates;
+mod upstream;
 mod worker;
 
+pub(crate) use self::config::sync_config;
+
 #[derive(Parser, Debug)]
 enum Subcommand {
     /// Configuration-related commands
@@ -47,11 +50,15 @@ enum Subcommand {
 
     /// Debug utilities
     Debug(self::debug::Options),
+
+    /// Manage the upstream OAuth 2.0 providers
+    Upstream(self::upstream::Options),
 }
 
 #[derive(Parser, Debug)]
 pub struct Options {
-    /// Path to the configuration file
+    /// Path to the configuration file, or to a directory of configuration
+    /// files
     #[arg(short, long, global = true, action = clap::ArgAction::Append)]
     config: Vec<Utf8PathBuf>,
 
@@ -70,12 +77,14 @@ impl Options {
             Some(S::Manage(c)) => c.run(&self).await,
             Some(S::Templates(c)) => c.run(&self).await,
             Some(S::Debug(c)) => c.run(&self).await,
+            Some(S::Upstream(c)) => c.run(&self).await,
             None => self::server::Options::default().run(&self).await,
         }
     }
 
-    pub fn load_config<T: ConfigurationSection>(&self) -> anyhow::Result<T> {
-        let configs = if self.config.is_empty() {
+    /// The configuration files to load
+    pub fn config_paths(&self) -> Vec<Utf8PathBuf> {
+        if self.config.is_empty() {
             // Read the MAS_CONFIG environment variable
             std::env::var("MAS_CONFIG")
                 // Default to "config.yaml"
@@ -86,8 +95,10 @@ impl Options {
                 .collect()
         } else {
             self.config.clone()
-        };
+        }
+    }
 
-        T::load_from_files(&configs).context("could not load configuration")
+    pub fn load_config<T: ConfigurationSection>(&self) -> anyhow::Result<T> {
+        T::load_from_files(&self.config_paths()).context("could not load configuration")
     }
 }
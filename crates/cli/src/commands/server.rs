@@ -19,10 +19,10 @@ use clap::Parser;
 use itertools::Itertools;
 use mas_config::AppConfig;
 use mas_handlers::{
-    ActivityTracker, CookieManager, HttpClientFactory, MatrixHomeserver, MetadataCache, SiteConfig,
+    ActivityTracker, CookieManager, HttpClientFactory, IntrospectionCache, MatrixHomeserver,
+    SharedHomeserverConnection, SiteConfig,
 };
 use mas_listener::{server::Server, shutdown::ShutdownStream};
-use mas_matrix_synapse::SynapseConnection;
 use mas_router::UrlBuilder;
 use mas_storage_pg::MIGRATOR;
 use rand::{
@@ -34,9 +34,19 @@ use tracing::{info, info_span, warn, Instrument};
 
 use crate::{
     app_state::AppState,
+    config_watcher::ConfigWatcher,
+    policy_watcher::PolicySource,
+    secrets_watcher::SecretsWatcher,
+    tls::CertificateResolver,
     util::{
-        database_pool_from_config, mailer_from_config, password_manager_from_config,
-        policy_factory_from_config, register_sighup, templates_from_config,
+        account_requirements_from_config, appservices_from_config, claim_mappings_from_config,
+        client_well_known_from_config, database_pool_from_config, email_locales_from_config,
+        email_rate_limits_from_config, homeserver_connection_from_config, ip_filter_from_config,
+        jwt_login_from_config, limiter_from_config, mailer_from_config,
+        oauth2_features_from_config, password_manager_from_config, policy_factory_from_config,
+        register_sighup, scope_registry_from_config, security_notifications_from_config,
+        templates_from_config, upstream_token_clients_from_config, vault_secrets_from_config,
+        webhooks_from_config,
     },
 };
 
@@ -49,13 +59,19 @@ pub(super) struct Options {
     /// Do not start the task worker
     #[arg(long)]
     no_worker: bool,
+
+    /// Check the configuration files for changes every given number of
+    /// seconds, and sync the static clients and upstream providers to the
+    /// database when they change
+    #[arg(long, value_name = "SECONDS")]
+    watch_config: Option<u64>,
 }
 
 impl Options {
     #[allow(clippy::too_many_lines)]
     pub async fn run(self, root: &super::Options) -> anyhow::Result<()> {
         let span = info_span!("cli.run.init").entered();
-        let config: AppConfig = root.load_config()?;
+        let mut config: AppConfig = root.load_config()?;
 
         // Connect to the database
         info!("Connecting to the database");
@@ -70,6 +86,16 @@ impl Options {
                 .context("could not run migrations")?;
         }
 
+        let http_client_factory = HttpClientFactory::new().await?;
+
+        if let Some(vault) = config.secrets.vault.clone() {
+            let secrets = vault_secrets_from_config(&vault, &http_client_factory).await?;
+            config.secrets.apply_external_secrets(&secrets.secrets);
+
+            // Fetch the secrets again before their lease expires
+            SecretsWatcher::new(root.config_paths(), &vault, &http_client_factory, secrets).watch();
+        }
+
         // Initialize the key store
         let key_store = config
             .secrets
@@ -77,15 +103,40 @@ impl Options {
             .await
             .context("could not import keys from config")?;
 
-        let encrypter = config.secrets.encrypter();
-        let cookie_manager =
-            CookieManager::derive_from(config.http.public_base.clone(), &config.secrets.encryption);
+        let encrypter = config.secrets.encrypter()?;
+        let embedding_origins = config.embedding.origins();
+        let cookie_manager = CookieManager::derive_from(
+            config.http.public_base.clone(),
+            config.secrets.encryption()?,
+        )
+        .with_cross_site(!embedding_origins.is_empty());
 
         // Load and compile the WASM policies (and fallback to the default embedded one)
         info!("Loading and compiling the policy module");
-        let policy_factory = policy_factory_from_config(&config.policy).await?;
+        let mut policy_source = PolicySource::from_config(&config.policy, &http_client_factory);
+        let oauth2_features = oauth2_features_from_config(&config.features);
+        let policy_factory = policy_factory_from_config(
+            &config.policy,
+            &config.usernames,
+            &config.scopes,
+            &oauth2_features,
+            &config.upstream_oauth2,
+            &mut policy_source,
+        )
+        .await?;
         let policy_factory = Arc::new(policy_factory);
 
+        // Watch the policy module for changes. The built-in policy has no module
+        if let (Some(interval), None) = (config.policy.reload_interval, &config.policy.builtin) {
+            policy_source.watch(interval, &policy_factory);
+        }
+
+        if let Some(interval) = self.watch_config {
+            ConfigWatcher::new(root.config_paths(), &http_client_factory)
+                .watch(Duration::from_secs(interval))
+                .context("could not watch the configuration files")?;
+        }
+
         let url_builder = UrlBuilder::new(
             config.http.public_base.clone(),
             config.http.issuer.clone(),
@@ -95,10 +146,9 @@ impl Options {
         // Load and compile the templates
         let templates = templates_from_config(&config.templates, &url_builder).await?;
 
-        let http_client_factory = HttpClientFactory::new().await?;
-
         if !self.no_worker {
-            let mailer = mailer_from_config(&config.email, &templates)?;
+            let mailer =
+                mailer_from_config(&config.email, &templates, &http_client_factory).await?;
             mailer.test_connection().await?;
 
             #[allow(clippy::disallowed_methods)]
@@ -106,42 +156,87 @@ impl Options {
             let worker_name = Alphanumeric.sample_string(&mut rng, 10);
 
             info!(worker_name, "Starting task worker");
-            let conn = SynapseConnection::new(
-                config.matrix.homeserver.clone(),
-                config.matrix.endpoint.clone(),
-                config.matrix.secret.clone(),
-                http_client_factory.clone(),
-            );
-            let monitor = mas_tasks::init(&worker_name, &pool, &mailer, conn).await?;
+            let conn = homeserver_connection_from_config(
+                &config.matrix,
+                &config.ip_filter,
+                &http_client_factory,
+            )?;
+            let webhooks = webhooks_from_config(&config.webhooks);
+            let monitor = mas_tasks::init(
+                &worker_name,
+                &pool,
+                &mailer,
+                conn,
+                &http_client_factory,
+                &url_builder,
+                &encrypter,
+                &key_store,
+                webhooks,
+                config.guests.ttl,
+                security_notifications_from_config(&config.email.security_notifications),
+                email_locales_from_config(&config.email.locales)?,
+            )
+            .await?;
             // TODO: grab the handle
             tokio::spawn(monitor.run());
         }
 
         let homeserver = MatrixHomeserver::new(config.matrix.homeserver.clone());
+        let appservices = appservices_from_config(&config.matrix)?;
 
         let listeners_config = config.http.listeners.clone();
 
         let password_manager = password_manager_from_config(&config.passwords).await?;
 
-        // The upstream OIDC metadata cache
-        let metadata_cache = MetadataCache::new();
-
-        let conn = SynapseConnection::new(
-            config.matrix.homeserver.clone(),
-            config.matrix.endpoint.clone(),
-            config.matrix.secret.clone(),
-            http_client_factory.clone(),
-        );
+        let homeserver_connection: SharedHomeserverConnection =
+            Arc::from(homeserver_connection_from_config(
+                &config.matrix,
+                &config.ip_filter,
+                &http_client_factory,
+            )?);
 
         let site_config = SiteConfig {
             access_token_ttl: config.experimental.access_token_ttl,
+            offline_session_ttl: config.experimental.offline_session_ttl,
             compat_token_ttl: config.experimental.compat_token_ttl,
+            authorization_code_ttl: config.experimental.authorization_code_ttl,
+            clock_skew: config.experimental.clock_skew,
+            jwt_bearer_max_assertion_lifetime: config.jwt_bearer.max_assertion_lifetime,
+            impersonation_ttl: config.experimental.impersonation_ttl,
+            consent_ttl: config.experimental.consent_ttl,
+            case_fold_usernames: config.usernames.case_fold,
+            compat_jwt_login: jwt_login_from_config(&config.matrix),
+            client_well_known: client_well_known_from_config(&config.matrix),
+            guest_registration: config.guests.enabled,
+            email_rate_limits: email_rate_limits_from_config(&config.email.rate_limit),
+            account_requirements: account_requirements_from_config(&config.account),
+            scope_registry: scope_registry_from_config(&config.scopes)?,
+            trusted_resource_servers: config.matrix.trusted_resource_servers.clone(),
+            claim_mappings: claim_mappings_from_config(&config.scopes.claims)?,
+            oauth2_features,
+            upstream_token_clients: upstream_token_clients_from_config(&config.upstream_oauth2),
+            embedding_origins: embedding_origins.clone(),
         };
 
+        let limiter = limiter_from_config(&config.rate_limiting, &pool)?;
+        let ip_filter = ip_filter_from_config(&config.ip_filter, &http_client_factory)?;
+        let introspection_cache = config
+            .experimental
+            .introspection_cache_ttl
+            .map_or_else(IntrospectionCache::disabled, IntrospectionCache::new);
+        introspection_cache.listen(&pool).await?;
+
         // Initialize the activity tracker
         // Activity is flushed every minute
         let activity_tracker = ActivityTracker::new(pool.clone(), Duration::from_secs(60));
         let trusted_proxies = config.http.trusted_proxies.clone();
+        let client_ip_headers = config.http.client_ip_headers.clone();
+        let security_headers = crate::server::SecurityHeaders::from_config(
+            &config.http.security_headers,
+            &embedding_origins,
+        )?;
+        let hashed_assets =
+            crate::server::load_hashed_assets(&config.templates.assets_manifest).await?;
 
         // Explicitly the config to properly zeroize secret keys
         drop(config);
@@ -149,30 +244,39 @@ impl Options {
         // Listen for SIGHUP
         register_sighup(&templates, &activity_tracker)?;
 
-        let graphql_schema = mas_handlers::graphql_schema(&pool, &policy_factory, conn);
+        let graphql_schema = mas_handlers::graphql_schema(
+            &pool,
+            &policy_factory,
+            Arc::clone(&homeserver_connection),
+            site_config.email_rate_limits,
+            introspection_cache.clone(),
+        );
 
         let state = {
             let mut s = AppState {
                 pool,
                 templates,
                 key_store,
-                metadata_cache,
                 cookie_manager,
                 encrypter,
                 url_builder,
                 homeserver,
+                homeserver_connection,
+                appservices,
                 policy_factory,
                 graphql_schema,
                 http_client_factory,
                 password_manager,
                 site_config,
+                limiter,
+                ip_filter,
+                introspection_cache,
                 activity_tracker,
                 trusted_proxies,
+                client_ip_headers,
                 conn_acquisition_histogram: None,
             };
             s.init_metrics()?;
-            // XXX: this might panic
-            s.init_metadata_cache().await;
             s
         };
 
@@ -186,7 +290,11 @@ impl Options {
 
                 // Load the TLS config
                 let tls_config = if let Some(tls_config) = config.tls.as_ref() {
-                    let tls_config = crate::server::build_tls_server_config(tls_config)?;
+                    let resolver = Arc::new(CertificateResolver::from_config(tls_config)?);
+                    if let Some(interval) = tls_config.reload_interval {
+                        resolver.watch(interval);
+                    }
+                    let tls_config = crate::server::build_tls_server_config(resolver);
                     Some(Arc::new(tls_config))
                 } else {
                     None
@@ -198,7 +306,11 @@ impl Options {
                     &config.resources,
                     config.prefix.as_deref(),
                     config.name.as_deref(),
+                    &security_headers,
+                    &hashed_assets,
                 );
+                let router = crate::server::with_request_limits(router, &config.limits);
+                let connection_limits = crate::server::connection_limits_from_config(&config.limits);
 
 
                 // Display some informations about where we'll be serving connections
@@ -235,7 +347,7 @@ impl Options {
                     if config.proxy_protocol {
                         server = server.with_proxy();
                     }
-                    server
+                    server.with_limits(connection_limits)
                 }))
             })
             .flatten_ok()
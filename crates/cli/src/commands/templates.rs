@@ -12,10 +12,17 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use clap::Parser;
+use anyhow::Context;
+use clap::{Parser, ValueEnum};
 use mas_config::TemplatesConfig;
+use mas_data_model::SecurityNotification;
+use mas_i18n::DataLocale;
 use mas_storage::{Clock, SystemClock};
-use rand::SeedableRng;
+use mas_templates::{
+    EmailChangeNotificationContext, EmailVerificationContext, SecurityNotificationContext,
+    TemplateContext,
+};
+use rand::{Rng, SeedableRng};
 use tracing::info_span;
 
 use crate::util::templates_from_config;
@@ -30,6 +37,71 @@ pub(super) struct Options {
 enum Subcommand {
     /// Check that the templates specified in the config are valid
     Check,
+
+    /// Render an email with sample data, to preview the templates specified
+    /// in the config
+    PreviewEmail {
+        /// The email to render
+        #[arg(value_enum)]
+        email: Email,
+
+        /// Which part of the email to render
+        #[arg(long, value_enum, default_value_t = Part::Html)]
+        part: Part,
+
+        /// The language to render the email in
+        #[arg(long, default_value = "en")]
+        language: String,
+
+        /// Which of the samples to use
+        #[arg(long, default_value_t = 0)]
+        sample: usize,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Email {
+    /// The email sent to verify an email address
+    Verification,
+
+    /// The email sent to the old address after an email change
+    EmailChange,
+
+    /// The security notification sent after a password change
+    PasswordChange,
+
+    /// The security notification sent after an upstream account was linked
+    UpstreamLink,
+
+    /// The security notification sent after the account was locked
+    AccountLock,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Part {
+    /// The subject line
+    Subject,
+
+    /// The plain text variant
+    Text,
+
+    /// The HTML variant
+    Html,
+}
+
+/// Pick one of the samples of the security notifications of the given
+/// category
+fn security_notification_sample(
+    clock: &impl Clock,
+    rng: &mut impl Rng,
+    category: SecurityNotification,
+    sample: usize,
+) -> anyhow::Result<SecurityNotificationContext> {
+    SecurityNotificationContext::sample(clock.now(), rng)
+        .into_iter()
+        .filter(|context| context.category() == category)
+        .nth(sample)
+        .context("sample not found")
 }
 
 impl Options {
@@ -50,6 +122,104 @@ impl Options {
 
                 Ok(())
             }
+
+            SC::PreviewEmail {
+                email,
+                part,
+                language,
+                sample,
+            } => {
+                let _span = info_span!("cli.templates.preview_email").entered();
+
+                let config: TemplatesConfig = root.load_config()?;
+                let clock = SystemClock::default();
+                // XXX: we should disallow SeedableRng::from_entropy
+                let mut rng = rand_chacha::ChaChaRng::from_entropy();
+                let url_builder =
+                    mas_router::UrlBuilder::new("https://example.com/".parse()?, None, None);
+                let templates = templates_from_config(&config, &url_builder).await?;
+                let language: DataLocale = language.parse().context("invalid language")?;
+
+                let rendered = match email {
+                    Email::Verification => {
+                        let context = EmailVerificationContext::sample(clock.now(), &mut rng)
+                            .into_iter()
+                            .nth(sample)
+                            .context("sample not found")?
+                            .with_language(language);
+
+                        match part {
+                            Part::Subject => templates.render_email_verification_subject(&context),
+                            Part::Text => templates.render_email_verification_txt(&context),
+                            Part::Html => templates.render_email_verification_html(&context),
+                        }
+                    }
+                    Email::EmailChange => {
+                        let context = EmailChangeNotificationContext::sample(clock.now(), &mut rng)
+                            .into_iter()
+                            .nth(sample)
+                            .context("sample not found")?
+                            .with_language(language);
+
+                        match part {
+                            Part::Subject => templates.render_email_change_subject(&context),
+                            Part::Text => templates.render_email_change_txt(&context),
+                            Part::Html => templates.render_email_change_html(&context),
+                        }
+                    }
+                    Email::PasswordChange => {
+                        let context = security_notification_sample(
+                            &clock,
+                            &mut rng,
+                            SecurityNotification::PasswordChange,
+                            sample,
+                        )?
+                        .with_language(language);
+
+                        match part {
+                            Part::Subject => {
+                                templates.render_email_password_change_subject(&context)
+                            }
+                            Part::Text => templates.render_email_password_change_txt(&context),
+                            Part::Html => templates.render_email_password_change_html(&context),
+                        }
+                    }
+                    Email::UpstreamLink => {
+                        let context = security_notification_sample(
+                            &clock,
+                            &mut rng,
+                            SecurityNotification::UpstreamLink,
+                            sample,
+                        )?
+                        .with_language(language);
+
+                        match part {
+                            Part::Subject => templates.render_email_upstream_link_subject(&context),
+                            Part::Text => templates.render_email_upstream_link_txt(&context),
+                            Part::Html => templates.render_email_upstream_link_html(&context),
+                        }
+                    }
+                    Email::AccountLock => {
+                        let context = security_notification_sample(
+                            &clock,
+                            &mut rng,
+                            SecurityNotification::AccountLock,
+                            sample,
+                        )?
+                        .with_language(language);
+
+                        match part {
+                            Part::Subject => templates.render_email_account_lock_subject(&context),
+                            Part::Text => templates.render_email_account_lock_txt(&context),
+                            Part::Html => templates.render_email_account_lock_html(&context),
+                        }
+                    }
+                }?;
+
+                println!("{}", rendered.trim());
+
+                Ok(())
+            }
         }
     }
 }
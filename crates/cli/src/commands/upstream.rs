@@ -0,0 +1,355 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::Read;
+
+use anyhow::Context;
+use camino::Utf8PathBuf;
+use clap::Parser;
+use mas_axum_utils::upstream_oauth2::client_metadata_for_provider;
+use mas_config::{DatabaseConfig, HttpConfig, SecretsConfig, UpstreamOAuth2Config};
+use mas_data_model::UpstreamOAuthProviderProtocol;
+use mas_handlers::HttpClientFactory;
+use mas_router::UrlBuilder;
+use mas_storage::{
+    upstream_oauth2::{UpstreamOAuthLinkRepository, UpstreamOAuthProviderRepository},
+    user::UserRepository,
+    RepositoryAccess, SystemClock,
+};
+use mas_storage_pg::PgRepository;
+use rand::SeedableRng;
+use serde::Deserialize;
+use sqlx::Acquire;
+use tracing::{info, info_span, warn};
+
+use crate::util::{database_connection_from_config, external_secrets_from_config};
+
+#[derive(Parser, Debug)]
+pub(super) struct Options {
+    #[command(subcommand)]
+    subcommand: Subcommand,
+}
+
+#[derive(Parser, Debug)]
+enum Subcommand {
+    /// Register with an upstream provider through dynamic client registration
+    ///
+    /// The provider must have a `registration` section in the config file,
+    /// and be synced to the database with `mas-cli config sync` first.
+    Register {
+        /// The ID or the slug of the provider
+        provider: String,
+
+        /// Register again even if already registered, replacing the previous
+        /// credentials
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Link existing users to their account on upstream providers, from a CSV
+    /// file
+    ///
+    /// The file must have a header row, and the `localpart`, `provider` and
+    /// `subject` columns. The provider is given by its ID or its slug.
+    ImportLinks {
+        /// Path to the CSV file
+        path: Utf8PathBuf,
+
+        /// Do not actually write to the database
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+/// A row of the CSV file given to `upstream import-links`
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+struct LinkRecord {
+    localpart: String,
+    provider: String,
+    subject: String,
+}
+
+/// Read the rows of a CSV file of links, failing on the first invalid one
+fn read_link_records(reader: impl Read) -> anyhow::Result<Vec<LinkRecord>> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader)
+        .deserialize()
+        .collect::<Result<_, _>>()
+        .context("Invalid CSV file")
+}
+
+impl Options {
+    pub async fn run(self, root: &super::Options) -> anyhow::Result<()> {
+        use Subcommand as SC;
+        let clock = SystemClock::default();
+
+        match self.subcommand {
+            SC::Register { provider, force } => {
+                let _span =
+                    info_span!("cli.upstream.register", upstream_oauth_provider = %provider)
+                        .entered();
+
+                let upstream_config: UpstreamOAuth2Config = root.load_config()?;
+                let database_config: DatabaseConfig = root.load_config()?;
+                let mut secrets_config: SecretsConfig = root.load_config()?;
+                let http_config: HttpConfig = root.load_config()?;
+
+                let provider_config = upstream_config
+                    .providers
+                    .iter()
+                    .find(|p| p.id.to_string() == provider || p.slug.as_ref() == Some(&provider))
+                    .context("Provider not found in the config file")?;
+                let registration_config = provider_config
+                    .registration
+                    .as_ref()
+                    .context("Provider has no `registration` section in the config file")?;
+
+                let http_client_factory = HttpClientFactory::new().await?;
+                if let Some(secrets) =
+                    external_secrets_from_config(&secrets_config, &http_client_factory).await?
+                {
+                    secrets_config.apply_external_secrets(&secrets);
+                }
+
+                let encrypter = secrets_config.encrypter()?;
+                let url_builder =
+                    UrlBuilder::new(http_config.public_base, http_config.issuer, None);
+                let http_service = http_client_factory.http_service("upstream_oauth2.register");
+
+                let mut conn = database_connection_from_config(&database_config).await?;
+                let txn = conn.begin().await?;
+                let mut repo = PgRepository::from_conn(txn);
+
+                let provider = repo
+                    .upstream_oauth_provider()
+                    .lookup(provider_config.id)
+                    .await?
+                    .context(
+                        "Provider not found in the database, run `mas-cli config sync` first",
+                    )?;
+
+                if provider.protocol != UpstreamOAuthProviderProtocol::Oidc {
+                    anyhow::bail!("Only OpenID Connect providers support dynamic registration");
+                }
+
+                if !force
+                    && repo
+                        .upstream_oauth_provider()
+                        .registration(&provider)
+                        .await?
+                        .is_some()
+                {
+                    anyhow::bail!(
+                        "Already registered with the provider, use `--force` to register again"
+                    );
+                }
+
+                let discovery =
+                    mas_oidc_client::requests::discovery::discover(&http_service, &provider.issuer)
+                        .await?;
+                let registration_endpoint = discovery
+                    .registration_endpoint
+                    .as_ref()
+                    .context("The provider doesn't advertise a registration endpoint")?;
+
+                let client_metadata = client_metadata_for_provider(
+                    &provider,
+                    url_builder.upstream_oauth_callback(provider.id, provider.slug.as_deref()),
+                    url_builder.jwks_uri(),
+                )?;
+
+                let response = mas_oidc_client::requests::registration::register_client(
+                    &http_service,
+                    registration_endpoint,
+                    client_metadata,
+                    registration_config.software_statement.clone(),
+                    registration_config.initial_access_token.as_deref(),
+                )
+                .await?;
+
+                let encrypted_client_secret = response
+                    .client_secret
+                    .map(|client_secret| encrypter.encrypt_to_string(client_secret.as_bytes()))
+                    .transpose()?;
+                let encrypted_registration_access_token = response
+                    .registration_access_token
+                    .map(|token| encrypter.encrypt_to_string(token.as_bytes()))
+                    .transpose()?;
+
+                let (provider, registration) = repo
+                    .upstream_oauth_provider()
+                    .set_registration(
+                        &clock,
+                        provider,
+                        response.client_id,
+                        encrypted_client_secret,
+                        encrypted_registration_access_token,
+                        response.registration_client_uri,
+                        response.client_secret_expires_at,
+                    )
+                    .await?;
+
+                repo.into_inner().commit().await?;
+
+                info!(
+                    %provider.id,
+                    %provider.client_id,
+                    manageable = registration.registration_client_uri.is_some(),
+                    "Registered with the provider"
+                );
+
+                Ok(())
+            }
+
+            SC::ImportLinks { path, dry_run } => import_links(root, &path, dry_run).await,
+        }
+    }
+}
+
+#[tracing::instrument(name = "cli.upstream.import_links", skip(root), err(Debug))]
+async fn import_links(
+    root: &super::Options,
+    path: &Utf8PathBuf,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+    let clock = SystemClock::default();
+    // XXX: we should disallow SeedableRng::from_entropy
+    let mut rng = rand_chacha::ChaChaRng::from_entropy();
+
+    let database_config: DatabaseConfig = root.load_config()?;
+
+    let file = std::fs::File::open(path).with_context(|| format!("Could not open {path}"))?;
+    let records = read_link_records(file)?;
+
+    let mut conn = database_connection_from_config(&database_config).await?;
+    let txn = conn.begin().await?;
+    let mut repo = PgRepository::from_conn(txn);
+
+    let providers = repo.upstream_oauth_provider().all().await?;
+
+    let mut linked = 0;
+    let mut already_linked = 0;
+    let mut rejected = 0;
+    for (index, record) in records.iter().enumerate() {
+        // Account for the header row, and start counting from 1
+        let line = index + 2;
+
+        let Some(provider) = providers.iter().find(|p| {
+            p.id.to_string() == record.provider || p.slug.as_ref() == Some(&record.provider)
+        }) else {
+            warn!(line, provider = record.provider, "Provider not found");
+            rejected += 1;
+            continue;
+        };
+
+        let Some(user) = repo.user().find_by_username(&record.localpart).await? else {
+            warn!(line, user.username = record.localpart, "User not found");
+            rejected += 1;
+            continue;
+        };
+
+        let link = repo
+            .upstream_oauth_link()
+            .find_by_subject(provider, &record.subject)
+            .await?;
+
+        let link = match link {
+            Some(link) if link.user_id == Some(user.id) => {
+                already_linked += 1;
+                continue;
+            }
+
+            Some(link) if link.user_id.is_some() => {
+                warn!(
+                    line,
+                    %provider.id,
+                    subject = record.subject,
+                    "The subject is already linked to another user"
+                );
+                rejected += 1;
+                continue;
+            }
+
+            Some(link) => link,
+
+            None => {
+                repo.upstream_oauth_link()
+                    .add(&mut rng, &clock, provider, record.subject.clone())
+                    .await?
+            }
+        };
+
+        repo.upstream_oauth_link()
+            .associate_to_user(&link, &user)
+            .await?;
+        linked += 1;
+    }
+
+    // Importing only part of the file would make it hard to run it again once
+    // fixed, so it is all or nothing
+    if rejected > 0 {
+        repo.into_inner().rollback().await?;
+        anyhow::bail!("{rejected} rows of the file were rejected, no link was imported");
+    }
+
+    info!(linked, already_linked, "Imported the links");
+
+    if dry_run {
+        info!("Dry run, rolling back changes");
+        repo.into_inner().rollback().await?;
+    } else {
+        repo.into_inner().commit().await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_link_records() {
+        let csv = "localpart,provider,subject\n\
+                   alice, 01H8PKNWKKRPCBW4YGH1RWV279, 1234\n\
+                   bob,staff,\"some,subject\"\n";
+        let records = read_link_records(csv.as_bytes()).unwrap();
+        assert_eq!(
+            records,
+            vec![
+                LinkRecord {
+                    localpart: "alice".to_owned(),
+                    provider: "01H8PKNWKKRPCBW4YGH1RWV279".to_owned(),
+                    subject: "1234".to_owned(),
+                },
+                LinkRecord {
+                    localpart: "bob".to_owned(),
+                    provider: "staff".to_owned(),
+                    subject: "some,subject".to_owned(),
+                },
+            ]
+        );
+
+        // Columns are matched by name
+        let csv = "subject,localpart,provider\n1234,alice,staff\n";
+        let records = read_link_records(csv.as_bytes()).unwrap();
+        assert_eq!(records[0].localpart, "alice");
+        assert_eq!(records[0].subject, "1234");
+
+        // Missing columns are rejected
+        let csv = "localpart,subject\nalice,1234\n";
+        assert!(read_link_records(csv.as_bytes()).is_err());
+    }
+}
@@ -12,10 +12,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use anyhow::Context;
 use clap::Parser;
 use mas_config::AppConfig;
 use mas_handlers::HttpClientFactory;
-use mas_matrix_synapse::SynapseConnection;
 use mas_router::UrlBuilder;
 use rand::{
     distributions::{Alphanumeric, DistString},
@@ -23,7 +23,11 @@ use rand::{
 };
 use tracing::{info, info_span};
 
-use crate::util::{database_pool_from_config, mailer_from_config, templates_from_config};
+use crate::util::{
+    database_pool_from_config, email_locales_from_config, external_secrets_from_config,
+    homeserver_connection_from_config, mailer_from_config, security_notifications_from_config,
+    templates_from_config, webhooks_from_config,
+};
 
 #[derive(Parser, Debug, Default)]
 pub(super) struct Options {}
@@ -31,7 +35,7 @@ pub(super) struct Options {}
 impl Options {
     pub async fn run(self, root: &super::Options) -> anyhow::Result<()> {
         let span = info_span!("cli.worker.init").entered();
-        let config: AppConfig = root.load_config()?;
+        let mut config: AppConfig = root.load_config()?;
 
         // Connect to the database
         info!("Connecting to the database");
@@ -46,16 +50,33 @@ impl Options {
         // Load and compile the templates
         let templates = templates_from_config(&config.templates, &url_builder).await?;
 
-        let mailer = mailer_from_config(&config.email, &templates)?;
+        let http_client_factory = HttpClientFactory::new().await?;
+
+        if let Some(secrets) =
+            external_secrets_from_config(&config.secrets, &http_client_factory).await?
+        {
+            config.secrets.apply_external_secrets(&secrets);
+        }
+
+        let mailer = mailer_from_config(&config.email, &templates, &http_client_factory).await?;
         mailer.test_connection().await?;
 
-        let http_client_factory = HttpClientFactory::new().await?;
-        let conn = SynapseConnection::new(
-            config.matrix.homeserver.clone(),
-            config.matrix.endpoint.clone(),
-            config.matrix.secret.clone(),
-            http_client_factory,
-        );
+        let conn = homeserver_connection_from_config(
+            &config.matrix,
+            &config.ip_filter,
+            &http_client_factory,
+        )?;
+        let webhooks = webhooks_from_config(&config.webhooks);
+        let guests_ttl = config.guests.ttl;
+        let security_notifications =
+            security_notifications_from_config(&config.email.security_notifications);
+        let email_locales = email_locales_from_config(&config.email.locales)?;
+        let key_store = config
+            .secrets
+            .key_store()
+            .await
+            .context("could not import keys from config")?;
+        let encrypter = config.secrets.encrypter()?;
 
         drop(config);
 
@@ -64,7 +85,21 @@ impl Options {
         let worker_name = Alphanumeric.sample_string(&mut rng, 10);
 
         info!(worker_name, "Starting task scheduler");
-        let monitor = mas_tasks::init(&worker_name, &pool, &mailer, conn).await?;
+        let monitor = mas_tasks::init(
+            &worker_name,
+            &pool,
+            &mailer,
+            conn,
+            &http_client_factory,
+            &url_builder,
+            &encrypter,
+            &key_store,
+            webhooks,
+            guests_ttl,
+            security_notifications,
+            email_locales,
+        )
+        .await?;
 
         span.exit();
 
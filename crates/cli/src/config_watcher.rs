@@ -0,0 +1,311 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Watch the configuration files, and sync the static clients and upstream
+//! providers they define to the database when they change
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::{Duration, SystemTime},
+};
+
+use camino::Utf8PathBuf;
+use mas_config::{ConfigurationSection, SyncConfig};
+use mas_handlers::HttpClientFactory;
+use serde::Serialize;
+use tracing::{error, info, info_span, Instrument};
+
+/// The modification time and size of each configuration file
+type Version = Vec<(Utf8PathBuf, SystemTime, u64)>;
+
+/// The items of a list of the configuration, serialized and keyed by their ID
+type Items = BTreeMap<String, serde_json::Value>;
+
+fn version(paths: &[Utf8PathBuf]) -> std::io::Result<Version> {
+    let mut version = Vec::new();
+    for path in paths {
+        let metadata = std::fs::metadata(path)?;
+        if metadata.is_dir() {
+            // Files of a configuration directory are compared one by one, as editing a
+            // file doesn't change the modification time of its directory
+            let mut entries = path
+                .read_dir_utf8()?
+                .map(|entry| entry.map(camino::Utf8DirEntry::into_path))
+                .collect::<Result<Vec<_>, _>>()?;
+            entries.sort();
+
+            for entry in entries {
+                let metadata = std::fs::metadata(&entry)?;
+                version.push((entry, metadata.modified()?, metadata.len()));
+            }
+        } else {
+            version.push((path.clone(), metadata.modified()?, metadata.len()));
+        }
+    }
+
+    Ok(version)
+}
+
+fn items<T: Serialize>(items: &[T], id: impl Fn(&T) -> String) -> Result<Items, serde_json::Error> {
+    items
+        .iter()
+        .map(|item| serde_json::to_value(item).map(|value| (id(item), value)))
+        .collect()
+}
+
+/// A change to an item of a list of the configuration
+#[derive(Debug, PartialEq, Eq)]
+enum Change<'a> {
+    Added(&'a str),
+
+    /// The item changed, with the names of its changed fields
+    Updated(&'a str, BTreeSet<&'a str>),
+
+    Removed(&'a str),
+}
+
+/// The changes between two lists of items of the configuration
+fn diff<'a>(old: &'a Items, new: &'a Items) -> Vec<Change<'a>> {
+    let mut changes = Vec::new();
+
+    for (id, item) in new {
+        match old.get(id) {
+            None => changes.push(Change::Added(id)),
+            Some(old_item) if old_item != item => {
+                let fields = match (old_item, item) {
+                    (serde_json::Value::Object(old_item), serde_json::Value::Object(item)) => {
+                        old_item
+                            .keys()
+                            .chain(item.keys())
+                            .filter(|key| old_item.get(*key) != item.get(*key))
+                            .map(String::as_str)
+                            .collect()
+                    }
+                    _ => BTreeSet::new(),
+                };
+                changes.push(Change::Updated(id, fields));
+            }
+            Some(_) => {}
+        }
+    }
+
+    for id in old.keys().filter(|id| !new.contains_key(*id)) {
+        changes.push(Change::Removed(id));
+    }
+
+    changes
+}
+
+/// Log the difference between two lists of items of the configuration.
+/// Only the names of the changed fields are logged, as they may hold secrets
+fn log_diff(kind: &str, old: &Items, new: &Items) {
+    for change in diff(old, new) {
+        match change {
+            Change::Added(id) => info!(kind, id, "Adding from the configuration"),
+            Change::Updated(id, fields) => {
+                let fields = fields.into_iter().collect::<Vec<_>>().join(", ");
+                info!(kind, id, fields, "Updating from the configuration");
+            }
+            Change::Removed(id) => info!(
+                kind,
+                id,
+                "Removed from the configuration, run `mas-cli config sync --prune` to delete it"
+            ),
+        }
+    }
+}
+
+/// The clients and upstream providers last synced from the configuration
+struct Snapshot {
+    clients: Items,
+    providers: Items,
+}
+
+impl Snapshot {
+    fn new(config: &SyncConfig) -> Result<Self, serde_json::Error> {
+        Ok(Self {
+            clients: items(&config.clients, |client| client.client_id.to_string())?,
+            providers: items(&config.upstream_oauth2.providers, |provider| {
+                provider.id.to_string()
+            })?,
+        })
+    }
+
+    fn is_same(&self, other: &Self) -> bool {
+        self.clients == other.clients && self.providers == other.providers
+    }
+}
+
+pub struct ConfigWatcher {
+    paths: Vec<Utf8PathBuf>,
+    http_client_factory: HttpClientFactory,
+}
+
+impl ConfigWatcher {
+    pub fn new(paths: Vec<Utf8PathBuf>, http_client_factory: &HttpClientFactory) -> Self {
+        Self {
+            paths,
+            http_client_factory: http_client_factory.clone(),
+        }
+    }
+
+    /// Check the configuration files for changes every `interval`, and sync
+    /// the clients and upstream providers to the database when they change
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configuration can't be loaded initially
+    pub fn watch(self, interval: Duration) -> anyhow::Result<()> {
+        let mut current_version = version(&self.paths)?;
+        let config = SyncConfig::load_from_files(&self.paths)?;
+        let mut snapshot = Snapshot::new(&config)?;
+
+        tokio::spawn(
+            async move {
+                let mut ticker = tokio::time::interval(interval);
+                ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+                // The first tick completes immediately
+                ticker.tick().await;
+
+                loop {
+                    ticker.tick().await;
+
+                    match version(&self.paths) {
+                        Ok(new_version) if new_version == current_version => continue,
+                        Ok(new_version) => current_version = new_version,
+                        Err(err) => {
+                            error!(?err, "Failed to check the configuration files");
+                            continue;
+                        }
+                    }
+
+                    match self.reload(&snapshot).await {
+                        Ok(Some(new_snapshot)) => snapshot = new_snapshot,
+                        Ok(None) => {}
+                        Err(err) => error!(?err, "Failed to reload the configuration"),
+                    }
+                }
+            }
+            .instrument(info_span!("cli.config.watch")),
+        );
+
+        Ok(())
+    }
+
+    /// Load the configuration again, and sync it to the database if the
+    /// clients or upstream providers changed
+    async fn reload(&self, snapshot: &Snapshot) -> anyhow::Result<Option<Snapshot>> {
+        let config = SyncConfig::load_from_files(&self.paths)?;
+        let new_snapshot = Snapshot::new(&config)?;
+        if new_snapshot.is_same(snapshot) {
+            return Ok(None);
+        }
+
+        info!("Configuration changed, syncing clients and upstream providers");
+        log_diff("client", &snapshot.clients, &new_snapshot.clients);
+        log_diff(
+            "upstream_oauth2_provider",
+            &snapshot.providers,
+            &new_snapshot.providers,
+        );
+
+        sync(config, &self.http_client_factory).await?;
+
+        Ok(Some(new_snapshot))
+    }
+}
+
+/// Sync the clients and upstream providers of the configuration to the
+/// database, without pruning the ones which were removed from it
+pub(crate) async fn sync(
+    config: SyncConfig,
+    http_client_factory: &HttpClientFactory,
+) -> anyhow::Result<()> {
+    crate::commands::sync_config(config, http_client_factory, false, false).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sync_config(clients: serde_json::Value) -> SyncConfig {
+        serde_json::from_value(serde_json::json!({
+            "secrets": {},
+            "clients": clients,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_diff() {
+        let old: Items = serde_json::from_value(serde_json::json!({
+            "a": { "x": 1, "y": 2 },
+            "b": { "x": 1 },
+            "c": { "x": 1 },
+        }))
+        .unwrap();
+        let new: Items = serde_json::from_value(serde_json::json!({
+            "a": { "x": 1, "y": 3, "z": 4 },
+            "c": { "x": 1 },
+            "d": { "x": 1 },
+        }))
+        .unwrap();
+
+        assert_eq!(
+            diff(&old, &new),
+            [
+                Change::Updated("a", BTreeSet::from(["y", "z"])),
+                Change::Added("d"),
+                Change::Removed("b"),
+            ]
+        );
+        assert!(diff(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let config = sync_config(serde_json::json!([{
+            "client_id": "01GFWR28C4KNE04WG3HKXB7C9R",
+            "client_auth_method": "none",
+            "redirect_uris": ["https://example.com/callback"],
+        }]));
+        let snapshot = Snapshot::new(&config).unwrap();
+        assert!(snapshot.providers.is_empty());
+        assert!(snapshot.is_same(&Snapshot::new(&config).unwrap()));
+
+        let config = sync_config(serde_json::json!([{
+            "client_id": "01GFWR28C4KNE04WG3HKXB7C9R",
+            "client_auth_method": "none",
+            "redirect_uris": ["https://example.com/other-callback"],
+        }, {
+            "client_id": "01GFWR32NCQ12B8Z0J8CPXRRB6",
+            "client_auth_method": "client_secret_basic",
+            "client_secret": "hello",
+        }]));
+        let new_snapshot = Snapshot::new(&config).unwrap();
+        assert!(!snapshot.is_same(&new_snapshot));
+
+        // Clients are keyed by their ID, and only the changed fields are listed
+        assert_eq!(
+            diff(&snapshot.clients, &new_snapshot.clients),
+            [
+                Change::Updated(
+                    "01GFWR28C4KNE04WG3HKXB7C9R",
+                    BTreeSet::from(["redirect_uris"])
+                ),
+                Change::Added("01GFWR32NCQ12B8Z0J8CPXRRB6"),
+            ]
+        );
+    }
+}
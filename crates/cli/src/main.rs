@@ -31,9 +31,13 @@ use crate::sentry_transport::HyperTransportFactory;
 
 mod app_state;
 mod commands;
+mod config_watcher;
+mod policy_watcher;
+mod secrets_watcher;
 mod sentry_transport;
 mod server;
 mod telemetry;
+mod tls;
 mod util;
 
 #[tokio::main]
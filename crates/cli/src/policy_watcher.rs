@@ -0,0 +1,256 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Load the policy WASM module, and hot-reload it when it changes
+
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use camino::Utf8PathBuf;
+use hyper::{
+    body::Bytes,
+    header::{AUTHORIZATION, ETAG, IF_NONE_MATCH},
+    Request, StatusCode,
+};
+use mas_config::{PolicyBundleConfig, PolicyConfig};
+use mas_handlers::HttpClientFactory;
+use mas_http::HttpServiceExt;
+use mas_policy::PolicyFactory;
+use opentelemetry::{metrics::Counter, Key};
+use tower::{Service, ServiceExt};
+use tracing::{error, info};
+use url::Url;
+
+const RESULT: Key = Key::from_static_str("result");
+
+/// Fetch a remote file, or return `None` if it didn't change since the
+/// `etag` was recorded
+async fn fetch_url(
+    http_client_factory: &HttpClientFactory,
+    url: &Url,
+    token: Option<&str>,
+    etag: &mut Option<String>,
+) -> Result<Option<Bytes>, anyhow::Error> {
+    let mut request = Request::get(url.as_str());
+    if let Some(etag) = etag.as_deref() {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+    if let Some(token) = token {
+        request = request.header(AUTHORIZATION, format!("Bearer {token}"));
+    }
+    let request = request.body(hyper::Body::empty())?;
+
+    let mut client = http_client_factory
+        .client("policy.fetch")
+        .response_body_to_bytes();
+    let response = client.ready().await?.call(request).await?;
+
+    if response.status() == StatusCode::NOT_MODIFIED {
+        return Ok(None);
+    }
+
+    if !response.status().is_success() {
+        anyhow::bail!(
+            "failed to fetch the OPA policy: server replied with {}",
+            response.status()
+        );
+    }
+
+    *etag = response
+        .headers()
+        .get(ETAG)
+        .and_then(|value| value.to_str().ok())
+        .map(ToOwned::to_owned);
+
+    Ok(Some(response.into_body()))
+}
+
+/// Where the policy module is loaded from, and which version of it was last
+/// loaded
+enum Source {
+    /// A local file, compared using its modification time and size
+    File {
+        path: Utf8PathBuf,
+        version: Option<(SystemTime, u64)>,
+    },
+
+    /// A remote URL, compared using the `ETag` header
+    Url {
+        url: Url,
+        http_client_factory: HttpClientFactory,
+        etag: Option<String>,
+    },
+
+    /// An OPA bundle, compared using the `ETag` header
+    Bundle {
+        config: PolicyBundleConfig,
+        http_client_factory: HttpClientFactory,
+        etag: Option<String>,
+    },
+}
+
+/// The source of the policy WASM module, as set in the config
+pub struct PolicySource {
+    source: Source,
+}
+
+impl PolicySource {
+    pub fn from_config(config: &PolicyConfig, http_client_factory: &HttpClientFactory) -> Self {
+        let source = match (&config.bundle, &config.wasm_module_url) {
+            (Some(bundle), _) => Source::Bundle {
+                config: bundle.clone(),
+                http_client_factory: http_client_factory.clone(),
+                etag: None,
+            },
+            (None, Some(url)) => Source::Url {
+                url: url.clone(),
+                http_client_factory: http_client_factory.clone(),
+                etag: None,
+            },
+            (None, None) => Source::File {
+                path: config.wasm_module.clone(),
+                version: None,
+            },
+        };
+
+        Self { source }
+    }
+
+    /// Load the policy module
+    pub async fn load(&mut self) -> Result<Bytes, anyhow::Error> {
+        self.fetch()
+            .await?
+            .context("the OPA WASM policy module did not change")
+    }
+
+    /// Fetch the module, or return `None` if it didn't change since the last
+    /// fetch
+    async fn fetch(&mut self) -> Result<Option<Bytes>, anyhow::Error> {
+        match &mut self.source {
+            Source::File { path, version } => {
+                let metadata = tokio::fs::metadata(&*path)
+                    .await
+                    .context("failed to open OPA WASM policy file")?;
+                let new_version = (metadata.modified()?, metadata.len());
+                if version.as_ref() == Some(&new_version) {
+                    return Ok(None);
+                }
+
+                let module = tokio::fs::read(&*path)
+                    .await
+                    .context("failed to read OPA WASM policy file")?;
+                *version = Some(new_version);
+                Ok(Some(module.into()))
+            }
+
+            Source::Url {
+                url,
+                http_client_factory,
+                etag,
+            } => fetch_url(http_client_factory, url, None, etag).await,
+
+            Source::Bundle {
+                config,
+                http_client_factory,
+                etag,
+            } => {
+                let Some(bundle) = fetch_url(
+                    http_client_factory,
+                    &config.url,
+                    config.token.as_deref(),
+                    etag,
+                )
+                .await?
+                else {
+                    return Ok(None);
+                };
+
+                // Decompressing the bundle is CPU-bound, so do it in a blocking task
+                let keys = config.verification_keys.clone();
+                let module = tokio::task::spawn_blocking(move || {
+                    mas_policy::bundle::extract_module(&bundle, keys.as_ref())
+                })
+                .await?
+                .context("invalid OPA policy bundle")?;
+
+                Ok(Some(module.into()))
+            }
+        }
+    }
+
+    /// Check the policy module for changes every `interval`, and swap it in
+    /// the policy factory when it changes
+    pub fn watch(mut self, interval: Duration, policy_factory: &Arc<PolicyFactory>) {
+        let policy_factory = Arc::clone(policy_factory);
+
+        let meter = opentelemetry::global::meter_with_version(
+            env!("CARGO_PKG_NAME"),
+            Some(env!("CARGO_PKG_VERSION")),
+            Some(opentelemetry_semantic_conventions::SCHEMA_URL),
+            None,
+        );
+        let reload_counter: Counter<u64> = meter
+            .u64_counter("mas.policy.reloads")
+            .with_description("The number of attempts to reload the policy module")
+            .with_unit(opentelemetry::metrics::Unit::new("{reloads}"))
+            .init();
+
+        // Record stuff on the counter so that the metrics are initialized
+        reload_counter.add(0, &[RESULT.string("success")]);
+        reload_counter.add(0, &[RESULT.string("failure")]);
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            // The first tick completes immediately, and the module was just loaded
+            ticker.tick().await;
+
+            loop {
+                ticker.tick().await;
+
+                let module = match self.fetch().await {
+                    Ok(Some(module)) => module,
+                    Ok(None) => continue,
+                    Err(err) => {
+                        error!(?err, "Failed to fetch the policy module");
+                        reload_counter.add(1, &[RESULT.string("failure")]);
+                        continue;
+                    }
+                };
+
+                match policy_factory.reload(&module[..]).await {
+                    Ok(()) => {
+                        info!(
+                            audit = true,
+                            module.size = module.len(),
+                            "Policy module reloaded"
+                        );
+                        reload_counter.add(1, &[RESULT.string("success")]);
+                    }
+                    Err(err) => {
+                        error!(
+                            audit = true,
+                            ?err,
+                            "Failed to reload the policy module, keeping the previous one"
+                        );
+                        reload_counter.add(1, &[RESULT.string("failure")]);
+                    }
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,253 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fetch the secrets stored in Vault again before their lease expires, and
+//! sync the upstream providers to the database when their client secrets
+//! change
+
+use std::{collections::BTreeSet, time::Duration};
+
+use camino::Utf8PathBuf;
+use mas_config::{ConfigurationSection, SyncConfig, VaultConfig};
+use mas_handlers::HttpClientFactory;
+use tracing::{error, info, info_span, warn, Instrument};
+
+use crate::util::{vault_secrets_from_config, VaultSecrets};
+
+/// How long to wait before trying again when the secrets can't be fetched
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// The fields of the secret which differ between two versions of it
+fn changed_fields(
+    old: &serde_json::Map<String, serde_json::Value>,
+    new: &serde_json::Map<String, serde_json::Value>,
+) -> BTreeSet<String> {
+    old.keys()
+        .chain(new.keys())
+        .filter(|key| old.get(*key) != new.get(*key))
+        .cloned()
+        .collect()
+}
+
+/// When to fetch the secrets again: before their lease expires, like Vault
+/// Agent does, or every `refresh_interval` if they don't have one
+fn refresh_delay(vault: &VaultConfig, secrets: &VaultSecrets) -> Option<Duration> {
+    secrets
+        .lease_duration
+        .map(|lease| lease * 2 / 3)
+        .or(vault.refresh_interval)
+}
+
+pub struct SecretsWatcher {
+    paths: Vec<Utf8PathBuf>,
+    vault: VaultConfig,
+    http_client_factory: HttpClientFactory,
+    secrets: VaultSecrets,
+}
+
+impl SecretsWatcher {
+    pub fn new(
+        paths: Vec<Utf8PathBuf>,
+        vault: &VaultConfig,
+        http_client_factory: &HttpClientFactory,
+        secrets: VaultSecrets,
+    ) -> Self {
+        Self {
+            paths,
+            vault: vault.clone(),
+            http_client_factory: http_client_factory.clone(),
+            secrets,
+        }
+    }
+
+    /// Fetch the secrets again, and return the names of the fields which
+    /// changed
+    async fn refresh(&mut self) -> anyhow::Result<BTreeSet<String>> {
+        let secrets = vault_secrets_from_config(&self.vault, &self.http_client_factory).await?;
+        let fields = changed_fields(&self.secrets.raw, &secrets.raw);
+        self.secrets = secrets;
+        Ok(fields)
+    }
+
+    /// Sync the upstream providers of the configuration to the database, with
+    /// the client secrets just fetched
+    async fn sync(&self) -> anyhow::Result<()> {
+        let config = SyncConfig::load_from_files(&self.paths)?;
+        crate::config_watcher::sync(config, &self.http_client_factory).await
+    }
+
+    /// Fetch the secrets again before their lease expires, and sync the
+    /// upstream providers when their client secrets change.
+    ///
+    /// The other secrets are only used after a restart, which is logged when
+    /// they change.
+    pub fn watch(mut self) {
+        let Some(mut delay) = refresh_delay(&self.vault, &self.secrets) else {
+            return;
+        };
+
+        tokio::spawn(
+            async move {
+                loop {
+                    tokio::time::sleep(delay).await;
+
+                    let fields = match self.refresh().await {
+                        Ok(fields) => fields,
+                        Err(err) => {
+                            error!(?err, "Failed to refresh the secrets from Vault");
+                            delay = RETRY_DELAY;
+                            continue;
+                        }
+                    };
+
+                    if fields.contains("upstream_oauth2") {
+                        info!("Client secrets of the upstream providers changed, syncing them");
+                        if let Err(err) = self.sync().await {
+                            error!(?err, "Failed to sync the upstream providers");
+                        }
+                    }
+
+                    let restart: Vec<&str> = fields
+                        .iter()
+                        .map(String::as_str)
+                        .filter(|field| *field != "upstream_oauth2")
+                        .collect();
+                    if !restart.is_empty() {
+                        warn!(
+                            fields = restart.join(", "),
+                            "Secrets changed in Vault, restart the service to use them"
+                        );
+                    }
+
+                    let Some(next) = refresh_delay(&self.vault, &self.secrets) else {
+                        info!(
+                            "The secrets from Vault no longer have a lease, stop refreshing them"
+                        );
+                        break;
+                    };
+                    delay = next;
+                }
+            }
+            .instrument(info_span!("cli.secrets.watch")),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ulid::Ulid;
+    use wiremock::{
+        matchers::{header, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_changed_fields() {
+        let old = serde_json::json!({
+            "encryption": "aaaa",
+            "upstream_oauth2": { "01H8PKNWKKRPCBW4YGH1RWV279": "old" },
+        });
+        let new = serde_json::json!({
+            "encryption": "aaaa",
+            "upstream_oauth2": { "01H8PKNWKKRPCBW4YGH1RWV279": "new" },
+            "keys": [],
+        });
+        let (Some(old), Some(new)) = (old.as_object(), new.as_object()) else {
+            unreachable!();
+        };
+
+        let fields = changed_fields(old, new);
+        assert_eq!(
+            fields.iter().map(String::as_str).collect::<Vec<_>>(),
+            ["keys", "upstream_oauth2"]
+        );
+        assert!(changed_fields(old, old).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_refresh() {
+        let provider = Ulid::from_string("01H8PKNWKKRPCBW4YGH1RWV279").unwrap();
+        let mock_server = MockServer::start().await;
+
+        // The secret first has a lease, and loses it once it is updated
+        Mock::given(method("GET"))
+            .and(path("/v1/secret/data/mas"))
+            .and(header("X-Vault-Token", "s.token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "lease_duration": 3600,
+                "data": {
+                    "data": { "upstream_oauth2": { "01H8PKNWKKRPCBW4YGH1RWV279": "old" } },
+                    "metadata": { "version": 1 },
+                },
+            })))
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v1/secret/data/mas"))
+            .and(header("X-Vault-Token", "s.token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "lease_duration": 0,
+                "data": {
+                    "data": { "upstream_oauth2": { "01H8PKNWKKRPCBW4YGH1RWV279": "new" } },
+                    "metadata": { "version": 2 },
+                },
+            })))
+            .mount(&mock_server)
+            .await;
+
+        let vault: VaultConfig = serde_json::from_value(serde_json::json!({
+            "address": format!("{}/", mock_server.uri()),
+            "token": "s.token",
+            "path": "mas",
+        }))
+        .unwrap();
+        let http_client_factory = HttpClientFactory::new().await.unwrap();
+
+        let secrets = vault_secrets_from_config(&vault, &http_client_factory)
+            .await
+            .unwrap();
+        assert_eq!(secrets.lease_duration, Some(Duration::from_secs(3600)));
+        assert_eq!(secrets.secrets.upstream_oauth2[&provider], "old");
+        assert_eq!(
+            refresh_delay(&vault, &secrets),
+            Some(Duration::from_secs(2400))
+        );
+
+        let mut watcher = SecretsWatcher::new(Vec::new(), &vault, &http_client_factory, secrets);
+        let fields = watcher.refresh().await.unwrap();
+        assert_eq!(
+            fields.iter().map(String::as_str).collect::<Vec<_>>(),
+            ["upstream_oauth2"]
+        );
+        assert_eq!(watcher.secrets.secrets.upstream_oauth2[&provider], "new");
+
+        // Without a lease, the secret is only refreshed if an interval is set
+        assert_eq!(watcher.secrets.lease_duration, None);
+        assert_eq!(refresh_delay(&vault, &watcher.secrets), None);
+        let vault = VaultConfig {
+            refresh_interval: Some(Duration::from_secs(60)),
+            ..vault
+        };
+        assert_eq!(
+            refresh_delay(&vault, &watcher.secrets),
+            Some(Duration::from_secs(60))
+        );
+
+        // Nothing changed since the last fetch
+        assert!(watcher.refresh().await.unwrap().is_empty());
+    }
+}
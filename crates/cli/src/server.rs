@@ -13,26 +13,42 @@
 // limitations under the License.
 
 use std::{
+    collections::HashSet,
     future::ready,
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, ToSocketAddrs},
-    os::unix::net::UnixListener,
+    num::NonZeroUsize,
+    os::unix::{
+        fs::{FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    sync::Arc,
 };
 
 use anyhow::Context;
 use axum::{
     body::HttpBody,
     error_handling::HandleErrorLayer,
-    extract::{FromRef, MatchedPath},
+    extract::{DefaultBodyLimit, FromRef, MatchedPath},
+    middleware::Next,
     Extension, Router,
 };
+use camino::{Utf8Path, Utf8PathBuf};
 use hyper::{
-    header::{HeaderValue, CACHE_CONTROL, USER_AGENT},
-    Method, Request, Response, StatusCode, Version,
+    header::{
+        HeaderValue, CACHE_CONTROL, CONTENT_SECURITY_POLICY, CONTENT_TYPE, REFERRER_POLICY,
+        STRICT_TRANSPORT_SECURITY, USER_AGENT, X_FRAME_OPTIONS,
+    },
+    http::Extensions,
+    HeaderMap, Method, Request, Response, StatusCode, Version,
 };
 use listenfd::ListenFd;
-use mas_config::{HttpBindConfig, HttpResource, HttpTlsConfig, UnixOrTcp};
-use mas_listener::{unix_or_tcp::UnixOrTcpListener, ConnectionInfo};
+use mas_config::{
+    HttpBindConfig, HttpListenerLimitsConfig, HttpResource, RouteGroup, SecurityHeadersConfig,
+    UnixOrTcp,
+};
+use mas_listener::{server::ConnectionLimits, unix_or_tcp::UnixOrTcpListener, ConnectionInfo};
 use mas_router::Route;
+use mas_spa::ViteManifest;
 use mas_templates::Templates;
 use mas_tower::{
     make_span_fn, metrics_attributes_fn, DurationRecorderLayer, InFlightCounterLayer, TraceLayer,
@@ -47,11 +63,16 @@ use opentelemetry_semantic_conventions::trace::{
 use rustls::ServerConfig;
 use sentry_tower::{NewSentryLayer, SentryHttpLayer};
 use tower::Layer;
-use tower_http::{services::ServeDir, set_header::SetResponseHeaderLayer};
-use tracing::{warn, Span};
+use tower_http::{
+    compression::{predicate::Predicate, CompressionLayer, DefaultPredicate},
+    services::ServeDir,
+    set_header::SetResponseHeaderLayer,
+    timeout::TimeoutLayer,
+};
+use tracing::{info, warn, Span};
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 
-use crate::app_state::AppState;
+use crate::{app_state::AppState, tls::CertificateResolver};
 
 const MAS_LISTENER_NAME: Key = Key::from_static_str("mas.listener.name");
 
@@ -172,11 +193,138 @@ fn on_http_response_labels<B>(res: &Response<B>) -> Vec<KeyValue> {
     vec![HTTP_RESPONSE_STATUS_CODE.i64(res.status().as_u16().into())]
 }
 
+/// Layers setting the security headers on the responses
+type SecurityHeadersLayer = (
+    SetResponseHeaderLayer<Option<HeaderValue>>,
+    SetResponseHeaderLayer<Option<HeaderValue>>,
+    SetResponseHeaderLayer<Option<HeaderValue>>,
+    SetResponseHeaderLayer<Option<HeaderValue>>,
+);
+
+/// The values of the security headers set on a group of routes
+#[derive(Debug, Clone)]
+struct SecurityHeaderValues {
+    content_security_policy: Option<HeaderValue>,
+    strict_transport_security: Option<HeaderValue>,
+    frame_options: Option<HeaderValue>,
+    referrer_policy: Option<HeaderValue>,
+}
+
+impl SecurityHeaderValues {
+    fn from_config(
+        config: &SecurityHeadersConfig,
+        group: RouteGroup,
+        embedding_origins: &[String],
+    ) -> Result<Self, anyhow::Error> {
+        let headers = config.resolve(group, embedding_origins);
+        let parse = |name: &str, value: Option<String>| {
+            value
+                .map(HeaderValue::try_from)
+                .transpose()
+                .with_context(|| format!("invalid value for the {name} security header"))
+        };
+
+        Ok(Self {
+            content_security_policy: parse(
+                "content_security_policy",
+                headers.content_security_policy,
+            )?,
+            strict_transport_security: parse(
+                "strict_transport_security",
+                headers.strict_transport_security,
+            )?,
+            frame_options: parse("frame_options", headers.frame_options)?,
+            referrer_policy: parse("referrer_policy", headers.referrer_policy)?,
+        })
+    }
+
+    /// Headers already set by the handlers are left untouched
+    fn layer(self) -> SecurityHeadersLayer {
+        (
+            SetResponseHeaderLayer::if_not_present(
+                CONTENT_SECURITY_POLICY,
+                self.content_security_policy,
+            ),
+            SetResponseHeaderLayer::if_not_present(
+                STRICT_TRANSPORT_SECURITY,
+                self.strict_transport_security,
+            ),
+            SetResponseHeaderLayer::if_not_present(X_FRAME_OPTIONS, self.frame_options),
+            SetResponseHeaderLayer::if_not_present(REFERRER_POLICY, self.referrer_policy),
+        )
+    }
+}
+
+/// The security headers set on each group of routes
+#[derive(Debug, Clone)]
+pub struct SecurityHeaders {
+    human: SecurityHeaderValues,
+    api: SecurityHeaderValues,
+    assets: SecurityHeaderValues,
+}
+
+impl SecurityHeaders {
+    pub fn from_config(
+        config: &SecurityHeadersConfig,
+        embedding_origins: &[String],
+    ) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            human: SecurityHeaderValues::from_config(config, RouteGroup::Human, embedding_origins)?,
+            api: SecurityHeaderValues::from_config(config, RouteGroup::Api, embedding_origins)?,
+            assets: SecurityHeaderValues::from_config(
+                config,
+                RouteGroup::Assets,
+                embedding_origins,
+            )?,
+        })
+    }
+
+    fn human(&self) -> SecurityHeadersLayer {
+        self.human.clone().layer()
+    }
+
+    fn api(&self) -> SecurityHeadersLayer {
+        self.api.clone().layer()
+    }
+
+    fn assets(&self) -> SecurityHeadersLayer {
+        self.assets.clone().layer()
+    }
+}
+
+/// Load the list of the static assets with a content-hashed name from the Vite
+/// manifest
+pub async fn load_hashed_assets(
+    manifest_path: &Utf8Path,
+) -> Result<Arc<HashSet<Utf8PathBuf>>, anyhow::Error> {
+    let manifest = tokio::fs::read(manifest_path)
+        .await
+        .context("failed to read the assets manifest")?;
+    let manifest: ViteManifest =
+        serde_json::from_slice(&manifest).context("invalid assets manifest")?;
+    let files = manifest.files().map(ToOwned::to_owned).collect();
+    Ok(Arc::new(files))
+}
+
+/// Only the API responses are compressed: the static assets are
+/// pre-compressed, and the pages are not, as they hold CSRF tokens alongside
+/// reflected input, which compression would leak (BREACH)
+fn should_compress(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |content_type| {
+            content_type.starts_with("application/json")
+        })
+}
+
 pub fn build_router<B>(
     state: AppState,
     resources: &[HttpResource],
     prefix: Option<&str>,
     name: Option<&str>,
+    security_headers: &SecurityHeaders,
+    hashed_assets: &Arc<HashSet<Utf8PathBuf>>,
 ) -> Router<(), B>
 where
     B: HttpBody + Send + 'static,
@@ -188,20 +336,32 @@ where
 
     for resource in resources {
         router = match resource {
-            mas_config::HttpResource::Health => {
-                router.merge(mas_handlers::healthcheck_router::<AppState, B>())
-            }
-            mas_config::HttpResource::Prometheus => {
-                router.route_service("/metrics", crate::telemetry::prometheus_service())
-            }
-            mas_config::HttpResource::Discovery => {
-                router.merge(mas_handlers::discovery_router::<AppState, B>())
-            }
-            mas_config::HttpResource::Human => {
-                router.merge(mas_handlers::human_router::<AppState, B>(templates.clone()))
-            }
+            mas_config::HttpResource::Health => router.merge(
+                mas_handlers::healthcheck_router::<AppState, B>().layer(security_headers.api()),
+            ),
+            mas_config::HttpResource::Prometheus => router.route_service(
+                "/metrics",
+                security_headers
+                    .api()
+                    .layer(crate::telemetry::prometheus_service()),
+            ),
+            mas_config::HttpResource::Discovery => router.merge(
+                mas_handlers::discovery_router::<AppState, B>().layer(security_headers.api()),
+            ),
+            mas_config::HttpResource::Human => router.merge(
+                mas_handlers::human_router::<AppState, B>(templates.clone())
+                    .layer(security_headers.human()),
+            ),
             mas_config::HttpResource::GraphQL { playground } => {
-                router.merge(mas_handlers::graphql_router::<AppState, B>(*playground))
+                let mut headers = security_headers.api.clone();
+                if *playground {
+                    // The playground loads its scripts and styles from a CDN
+                    headers.content_security_policy = None;
+                }
+
+                router.merge(
+                    mas_handlers::graphql_router::<AppState, B>(*playground).layer(headers.layer()),
+                )
             }
             mas_config::HttpResource::Assets { path } => {
                 let static_service = ServeDir::new(path)
@@ -213,28 +373,43 @@ where
                 let error_layer =
                     HandleErrorLayer::new(|_e| ready(StatusCode::INTERNAL_SERVER_ERROR));
 
-                let cache_layer = SetResponseHeaderLayer::overriding(
-                    CACHE_CONTROL,
-                    HeaderValue::from_static("public, max-age=31536000, immutable"),
-                );
+                let hashed_assets = Arc::clone(hashed_assets);
+                let cache_layer =
+                    axum::middleware::from_fn(move |req: Request<B>, next: Next<B>| {
+                        let path = Utf8Path::new(req.uri().path().trim_start_matches('/'));
+                        let immutable = hashed_assets.contains(path);
+                        async move {
+                            let mut response = next.run(req).await;
+                            // Files with a content-hashed name never change, the others have to be
+                            // revalidated on each use
+                            let cache_control = if immutable {
+                                "public, max-age=31536000, immutable"
+                            } else {
+                                "no-cache"
+                            };
+                            response
+                                .headers_mut()
+                                .insert(CACHE_CONTROL, HeaderValue::from_static(cache_control));
+                            response
+                        }
+                    });
 
                 router.nest_service(
                     mas_router::StaticAsset::route(),
-                    (error_layer, cache_layer).layer(static_service),
+                    (cache_layer, error_layer, security_headers.assets()).layer(static_service),
                 )
             }
-            mas_config::HttpResource::OAuth => {
-                router.merge(mas_handlers::api_router::<AppState, B>())
-            }
-            mas_config::HttpResource::Compat => {
-                router.merge(mas_handlers::compat_router::<AppState, B>())
-            }
+            mas_config::HttpResource::OAuth => router
+                .merge(mas_handlers::api_router::<AppState, B>().layer(security_headers.api())),
+            mas_config::HttpResource::Compat => router
+                .merge(mas_handlers::compat_router::<AppState, B>().layer(security_headers.api())),
             // TODO: do a better handler here
             mas_config::HttpResource::ConnectionInfo => router.route(
                 "/connection-info",
                 axum::routing::get(|connection: Extension<ConnectionInfo>| async move {
                     format!("{connection:?}")
-                }),
+                })
+                .layer(security_headers.api()),
             ),
 
             #[allow(deprecated)]
@@ -253,6 +428,14 @@ where
     router = router.fallback(mas_handlers::fallback);
 
     router
+        .layer(
+            CompressionLayer::new().compress_when(DefaultPredicate::new().and(
+                |_status: StatusCode,
+                 _version: Version,
+                 headers: &HeaderMap,
+                 _extensions: &Extensions| should_compress(headers),
+            )),
+        )
         .layer(
             InFlightCounterLayer::new("http.server.active_requests").on_request((
                 name.map(|name| MAS_LISTENER_NAME.string(name.to_owned())),
@@ -283,19 +466,63 @@ where
         .with_state(state)
 }
 
-pub fn build_tls_server_config(config: &HttpTlsConfig) -> Result<ServerConfig, anyhow::Error> {
-    let (key, chain) = config.load()?;
-    let key = rustls::PrivateKey(key);
-    let chain = chain.into_iter().map(rustls::Certificate).collect();
+/// Limit the size of the request bodies and the time taken to respond
+pub fn with_request_limits<B>(
+    mut router: Router<(), B>,
+    limits: &HttpListenerLimitsConfig,
+) -> Router<(), B>
+where
+    B: HttpBody + Send + 'static,
+{
+    if let Some(max_body_size) = limits.max_body_size {
+        router = router.layer(DefaultBodyLimit::max(max_body_size));
+    }
+
+    if let Some(request_timeout) = limits.request_timeout {
+        router = router.layer(TimeoutLayer::new(request_timeout));
+    }
+
+    router
+}
+
+pub fn connection_limits_from_config(limits: &HttpListenerLimitsConfig) -> ConnectionLimits {
+    ConnectionLimits {
+        max_header_size: limits.max_header_size,
+        header_read_timeout: limits.request_timeout,
+        max_connections: limits.max_connections.map(NonZeroUsize::get),
+    }
+}
 
+pub fn build_tls_server_config(resolver: Arc<CertificateResolver>) -> ServerConfig {
     let mut config = rustls::ServerConfig::builder()
         .with_safe_defaults()
         .with_no_client_auth()
-        .with_single_cert(chain, key)
-        .context("failed to build TLS server config")?;
+        .with_cert_resolver(resolver);
     config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
 
-    Ok(config)
+    config
+}
+
+/// Remove a socket file left behind by a previous run, so that it can be bound
+/// again. Sockets on which something is still listening are left alone.
+fn remove_stale_socket(path: &Utf8Path) -> Result<(), anyhow::Error> {
+    let Ok(metadata) = std::fs::symlink_metadata(path) else {
+        return Ok(());
+    };
+
+    if !metadata.file_type().is_socket() {
+        return Ok(());
+    }
+
+    match UnixStream::connect(path) {
+        Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+            info!(path = %path, "Removing stale socket");
+            std::fs::remove_file(path).context("could not remove stale socket")?;
+        }
+        _ => {}
+    }
+
+    Ok(())
 }
 
 pub fn build_listeners(
@@ -333,8 +560,20 @@ pub fn build_listeners(
                 listener.try_into()?
             }
 
-            HttpBindConfig::Unix { socket } => {
+            HttpBindConfig::Unix { socket, mode } => {
+                let mode = mode
+                    .as_deref()
+                    .map(|mode| u32::from_str_radix(mode, 8))
+                    .transpose()
+                    .context("invalid socket mode, expected an octal value like \"660\"")?;
+
+                remove_stale_socket(socket)?;
                 let listener = UnixListener::bind(socket).context("could not bind socket")?;
+                if let Some(mode) = mode {
+                    std::fs::set_permissions(socket, std::fs::Permissions::from_mode(mode))
+                        .context("could not set the permissions of the socket")?;
+                }
+                listener.set_nonblocking(true)?;
                 listener.try_into()?
             }
 
@@ -366,3 +605,22 @@ pub fn build_listeners(
 
     Ok(listeners)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_should_compress() {
+        let headers = |content_type: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+            headers
+        };
+
+        assert!(should_compress(&headers("application/json")));
+        assert!(!should_compress(&headers("text/html; charset=utf-8")));
+        assert!(!should_compress(&headers("text/css")));
+        assert!(!should_compress(&HeaderMap::new()));
+    }
+}
@@ -0,0 +1,169 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Serve the TLS certificate of the listeners, and hot-reload it when the
+//! files it is loaded from change
+
+use std::{
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use camino::Utf8PathBuf;
+use mas_config::HttpTlsConfig;
+use opentelemetry::{metrics::Counter, Key};
+use rustls::{
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    Certificate,
+};
+use tracing::{error, info};
+
+const RESULT: Key = Key::from_static_str("result");
+
+/// Load the certificate chain and the key from the config
+fn load_certified_key(config: &HttpTlsConfig) -> Result<CertifiedKey, anyhow::Error> {
+    let (key, chain) = config.load()?;
+    let key = rustls::sign::any_supported_type(&rustls::PrivateKey(key))
+        .context("unsupported TLS private key")?;
+    let chain = chain.into_iter().map(Certificate).collect();
+    Ok(CertifiedKey::new(chain, key))
+}
+
+/// The modification time and size of each of the files, used to detect changes
+async fn files_version(files: &[Utf8PathBuf]) -> Result<Vec<(SystemTime, u64)>, anyhow::Error> {
+    let mut version = Vec::with_capacity(files.len());
+    for path in files {
+        let metadata = tokio::fs::metadata(path)
+            .await
+            .with_context(|| format!("failed to open {path}"))?;
+        version.push((metadata.modified()?, metadata.len()));
+    }
+    Ok(version)
+}
+
+/// Serves the certificate of a listener, which can be swapped while the
+/// listener is running
+pub struct CertificateResolver {
+    config: HttpTlsConfig,
+    certified_key: RwLock<Arc<CertifiedKey>>,
+}
+
+impl CertificateResolver {
+    /// Load the certificate chain and the key from the config
+    pub fn from_config(config: &HttpTlsConfig) -> Result<Self, anyhow::Error> {
+        let certified_key = load_certified_key(config)?;
+        Ok(Self {
+            config: config.clone(),
+            certified_key: RwLock::new(Arc::new(certified_key)),
+        })
+    }
+
+    /// Check the certificate, key and password files for changes every
+    /// `interval`, and reload the certificate when they change
+    pub fn watch(self: &Arc<Self>, interval: Duration) {
+        let files: Vec<Utf8PathBuf> = self
+            .config
+            .files()
+            .into_iter()
+            .map(ToOwned::to_owned)
+            .collect();
+        if files.is_empty() {
+            // Everything is embedded in the config file, nothing can change
+            return;
+        }
+
+        let resolver = Arc::clone(self);
+
+        let meter = opentelemetry::global::meter_with_version(
+            env!("CARGO_PKG_NAME"),
+            Some(env!("CARGO_PKG_VERSION")),
+            Some(opentelemetry_semantic_conventions::SCHEMA_URL),
+            None,
+        );
+        let reload_counter: Counter<u64> = meter
+            .u64_counter("mas.tls.reloads")
+            .with_description("The number of attempts to reload a TLS certificate")
+            .with_unit(opentelemetry::metrics::Unit::new("{reloads}"))
+            .init();
+
+        // Record stuff on the counter so that the metrics are initialized
+        reload_counter.add(0, &[RESULT.string("success")]);
+        reload_counter.add(0, &[RESULT.string("failure")]);
+
+        tokio::spawn(async move {
+            let mut version = files_version(&files).await.ok();
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            // The first tick completes immediately, and the certificate was just loaded
+            ticker.tick().await;
+
+            loop {
+                ticker.tick().await;
+
+                let new_version = match files_version(&files).await {
+                    Ok(new_version) => new_version,
+                    Err(err) => {
+                        error!(?err, "Failed to check the TLS certificate files");
+                        continue;
+                    }
+                };
+
+                if version.as_ref() == Some(&new_version) {
+                    continue;
+                }
+
+                // Decoding and decrypting the key might be slow
+                let config = resolver.config.clone();
+                let res = tokio::task::spawn_blocking(move || load_certified_key(&config)).await;
+
+                match res {
+                    Ok(Ok(certified_key)) => {
+                        *resolver
+                            .certified_key
+                            .write()
+                            .expect("TLS certificate lock poisoned") = Arc::new(certified_key);
+                        version = Some(new_version);
+                        info!("TLS certificate reloaded");
+                        reload_counter.add(1, &[RESULT.string("success")]);
+                    }
+                    Ok(Err(err)) => {
+                        // The files might be in the middle of being replaced. Leave the version
+                        // as is, so that it is retried on the next tick
+                        error!(
+                            ?err,
+                            "Failed to reload the TLS certificate, keeping the previous one"
+                        );
+                        reload_counter.add(1, &[RESULT.string("failure")]);
+                    }
+                    Err(err) => {
+                        error!(?err, "TLS certificate reload task panicked");
+                        reload_counter.add(1, &[RESULT.string("failure")]);
+                    }
+                }
+            }
+        });
+    }
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let certified_key = self
+            .certified_key
+            .read()
+            .expect("TLS certificate lock poisoned");
+        Some(Arc::clone(&certified_key))
+    }
+}
@@ -12,23 +12,149 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::time::Duration;
+use std::{collections::BTreeMap, time::Duration};
 
 use anyhow::Context;
+use hyper::Request;
 use mas_config::{
-    DatabaseConfig, DatabaseConnectConfig, EmailConfig, EmailSmtpMode, EmailTransportConfig,
-    PasswordsConfig, PolicyConfig, TemplatesConfig,
+    AccountConfig, BuiltinPolicyConfig, ClaimMappingConfig, DatabaseConfig, DatabaseConnectConfig,
+    DkimAlgorithm, EmailConfig, EmailLocalesConfig, EmailRateLimitConfig, EmailSmtpMode,
+    EmailTransportConfig, ExternalSecrets, FeaturesConfig, GrantTypeConfig, HomeserverKind,
+    IpFilterConfig, JwksOrJwksUri, MatrixConfig, PasswordsConfig, PolicyConfig,
+    RateLimiterConfiguration, RateLimitingBackend, RateLimitingConfig, ResponseModeConfig,
+    ScopeRiskConfig, ScopesConfig, SecretsConfig, SecurityNotificationsConfig, TemplatesConfig,
+    ThemeColorsConfig, ThemeConfig, UpstreamOAuth2Config, UserAttributeConfig, UsernamesConfig,
+    VaultConfig, WebhookEvent, WebhooksConfig,
+};
+use mas_data_model::{
+    ClaimMapping, EmailRateLimits, OAuth2Features, ScopeDefinition, ScopeRegistry, ScopeRisk,
+    SecurityNotification, UserAttribute,
+};
+use mas_email::{AwsCredentials, DkimSigningAlgorithm, DkimSigningKey, MailTransport, Mailer};
+use mas_handlers::{
+    passwords::PasswordManager, AccountRequirements, ActivityTracker, Appservice,
+    AppserviceRegistry, BucketConfig, ClientWellKnownConfig, HttpClientFactory, IpFilter,
+    IpFilterRules, JwtLoginConfig, Limiter, LoginFailureDelay, RateLimits,
+};
+use mas_http::HttpServiceExt;
+use mas_matrix::HomeserverConnection;
+use mas_matrix_dendrite::DendriteConnection;
+use mas_matrix_synapse::SynapseConnection;
+use mas_policy::{
+    builtin::{
+        BuiltinRules, ClientRules, EmailRules, PasswordRules, RestrictedClient, TokenRules,
+        UsernameRules,
+    },
+    GeoIp, PolicyFactory,
 };
-use mas_email::{MailTransport, Mailer};
-use mas_handlers::{passwords::PasswordManager, ActivityTracker};
-use mas_policy::PolicyFactory;
 use mas_router::UrlBuilder;
-use mas_templates::{TemplateLoadingError, Templates};
+use mas_storage::{Clock, SystemClock};
+use mas_tasks::{EmailLocales, WebhookEndpoint};
+use mas_templates::{Color, Templates, Theme, ThemeColors};
+use oauth2_types::{
+    requests::{GrantType, ResponseMode},
+    scope::ScopeToken,
+};
+use rand::SeedableRng;
+use serde::Deserialize;
 use sqlx::{
     postgres::{PgConnectOptions, PgPoolOptions},
     ConnectOptions, PgConnection, PgPool,
 };
+use tower::{Service, ServiceExt};
 use tracing::{error, info, log::LevelFilter};
+use ulid::Ulid;
+
+use crate::policy_watcher::PolicySource;
+
+/// The response of the Vault KV version 2 secrets engine when reading a secret
+#[derive(Deserialize)]
+struct VaultKvResponse {
+    /// How long the secret is valid for, in seconds. It is `0` when the secret
+    /// has no lease
+    #[serde(default)]
+    lease_duration: u64,
+
+    data: VaultKvData,
+}
+
+#[derive(Deserialize)]
+struct VaultKvData {
+    data: serde_json::Value,
+}
+
+/// Secrets fetched from Vault
+pub struct VaultSecrets {
+    /// The secrets, parsed
+    pub secrets: ExternalSecrets,
+
+    /// The secrets as they were fetched, to tell which fields changed when they
+    /// are fetched again
+    pub raw: serde_json::Map<String, serde_json::Value>,
+
+    /// How long the secrets are valid for, if Vault gave them a lease
+    pub lease_duration: Option<Duration>,
+}
+
+/// Fetch the secrets stored in Vault, if it is configured
+pub async fn external_secrets_from_config(
+    config: &SecretsConfig,
+    http_client_factory: &HttpClientFactory,
+) -> Result<Option<ExternalSecrets>, anyhow::Error> {
+    let Some(vault) = &config.vault else {
+        return Ok(None);
+    };
+
+    let secrets = vault_secrets_from_config(vault, http_client_factory).await?;
+    Ok(Some(secrets.secrets))
+}
+
+/// Fetch the secrets stored in Vault, along with their lease
+pub async fn vault_secrets_from_config(
+    vault: &VaultConfig,
+    http_client_factory: &HttpClientFactory,
+) -> Result<VaultSecrets, anyhow::Error> {
+    let url = vault
+        .secret_url()
+        .context("invalid path for the Vault secret")?;
+
+    let mut request = Request::get(url.as_str()).header("X-Vault-Token", &vault.token);
+    if let Some(namespace) = &vault.namespace {
+        request = request.header("X-Vault-Namespace", namespace);
+    }
+    let request = request.body(hyper::Body::empty())?;
+
+    info!(%url, "Fetching secrets from Vault");
+    let mut client = http_client_factory
+        .client("vault.read")
+        .response_body_to_bytes();
+    let response = client.ready().await?.call(request).await?;
+
+    if !response.status().is_success() {
+        anyhow::bail!(
+            "failed to fetch the secrets from Vault: server replied with {}",
+            response.status()
+        );
+    }
+
+    let response: VaultKvResponse =
+        serde_json::from_slice(response.body()).context("invalid secret in Vault")?;
+
+    let raw = match response.data.data {
+        serde_json::Value::Object(raw) => raw,
+        _ => anyhow::bail!("invalid secret in Vault: expected an object"),
+    };
+    let secrets = serde_json::from_value(serde_json::Value::Object(raw.clone()))
+        .context("invalid secret in Vault")?;
+    let lease_duration =
+        (response.lease_duration > 0).then(|| Duration::from_secs(response.lease_duration));
+
+    Ok(VaultSecrets {
+        secrets,
+        raw,
+        lease_duration,
+    })
+}
 
 pub async fn password_manager_from_config(
     config: &PasswordsConfig,
@@ -55,9 +181,10 @@ pub async fn password_manager_from_config(
     PasswordManager::new(schemes)
 }
 
-pub fn mailer_from_config(
+pub async fn mailer_from_config(
     config: &EmailConfig,
     templates: &Templates,
+    http_client_factory: &HttpClientFactory,
 ) -> Result<Mailer, anyhow::Error> {
     let from = config.from.parse()?;
     let reply_to = config.reply_to.parse()?;
@@ -68,6 +195,7 @@ pub fn mailer_from_config(
             hostname,
             credentials,
             port,
+            pool_size,
         } => {
             let credentials = credentials
                 .clone()
@@ -79,23 +207,477 @@ pub fn mailer_from_config(
                 EmailSmtpMode::Tls => mas_email::SmtpMode::Tls,
             };
 
-            MailTransport::smtp(mode, hostname, port.as_ref().copied(), credentials)
-                .context("failed to build SMTP transport")?
+            MailTransport::smtp(
+                mode,
+                hostname,
+                port.as_ref().copied(),
+                credentials,
+                pool_size.as_ref().copied(),
+                config.timeout,
+            )
+            .context("failed to build SMTP transport")?
         }
         EmailTransportConfig::Sendmail { command } => MailTransport::sendmail(command),
-        #[allow(deprecated)]
-        EmailTransportConfig::AwsSes => anyhow::bail!("AWS SESv2 backend has been removed"),
+        EmailTransportConfig::AwsSes {
+            region,
+            credentials,
+        } => {
+            let region = match region {
+                Some(region) => region.clone(),
+                None => std::env::var("AWS_REGION").context("AWS region is not configured")?,
+            };
+
+            let credentials = match credentials {
+                Some(c) => {
+                    AwsCredentials::new(c.access_key_id.clone(), c.secret_access_key.clone())
+                }
+                None => {
+                    let credentials = AwsCredentials::new(
+                        std::env::var("AWS_ACCESS_KEY_ID")
+                            .context("AWS credentials are not configured")?,
+                        std::env::var("AWS_SECRET_ACCESS_KEY")
+                            .context("AWS credentials are not configured")?,
+                    );
+
+                    match std::env::var("AWS_SESSION_TOKEN") {
+                        Ok(token) => credentials.with_session_token(token),
+                        Err(_) => credentials,
+                    }
+                }
+            };
+
+            MailTransport::aws_ses(http_client_factory.clone(), region, credentials)
+        }
+        EmailTransportConfig::SendGrid { api_key } => {
+            MailTransport::sendgrid(http_client_factory.clone(), api_key.clone())
+        }
+        EmailTransportConfig::Mailgun {
+            domain,
+            api_key,
+            endpoint,
+        } => MailTransport::mailgun(
+            http_client_factory.clone(),
+            endpoint.clone(),
+            domain.clone(),
+            api_key.clone(),
+        ),
+        EmailTransportConfig::Postmark {
+            server_token,
+            message_stream,
+        } => MailTransport::postmark(
+            http_client_factory.clone(),
+            server_token.clone(),
+            message_stream.clone(),
+        ),
+    };
+
+    let mut mailer =
+        Mailer::new(templates.clone(), transport, from, reply_to).with_timeout(config.timeout);
+
+    if let Some(dkim) = &config.dkim {
+        let algorithm = match dkim.algorithm {
+            DkimAlgorithm::Rsa => DkimSigningAlgorithm::Rsa,
+            DkimAlgorithm::Ed25519 => DkimSigningAlgorithm::Ed25519,
+        };
+        let key = DkimSigningKey::new(&dkim.private_key().await?, algorithm)
+            .context("invalid DKIM private key")?;
+        mailer = mailer.with_dkim(mas_email::DkimConfig::default_config(
+            dkim.selector.clone(),
+            dkim.domain.clone(),
+            key,
+        ));
+    }
+
+    Ok(mailer)
+}
+
+pub fn webhooks_from_config(config: &WebhooksConfig) -> Vec<WebhookEndpoint> {
+    config
+        .endpoints
+        .iter()
+        .map(|endpoint| {
+            WebhookEndpoint::new(endpoint.id, endpoint.url.clone(), endpoint.secret.clone())
+                .with_events(endpoint.events.iter().copied().map(WebhookEvent::as_str))
+        })
+        .collect()
+}
+
+pub fn email_rate_limits_from_config(config: &EmailRateLimitConfig) -> EmailRateLimits {
+    EmailRateLimits {
+        resend_cooldown: config.resend_cooldown,
+        window: config.window,
+        per_address: config.per_address.get().try_into().unwrap_or(usize::MAX),
+        per_user: config.per_user.get().try_into().unwrap_or(usize::MAX),
+    }
+}
+
+pub fn account_requirements_from_config(config: &AccountConfig) -> AccountRequirements {
+    AccountRequirements {
+        verified_email: config.require_verified_email,
+        displayname: config.require_displayname,
+        terms_uri: config.terms_uri.clone(),
+    }
+}
+
+fn bucket_config_from_config(
+    name: &str,
+    config: &RateLimiterConfiguration,
+) -> Result<BucketConfig, anyhow::Error> {
+    anyhow::ensure!(
+        config.per_second.is_finite() && config.per_second > 0.0,
+        "rate_limiting.{name}.per_second must be a positive number"
+    );
+
+    Ok(BucketConfig {
+        burst: config.burst,
+        per_second: config.per_second,
+    })
+}
+
+pub fn limiter_from_config(
+    config: &RateLimitingConfig,
+    pool: &PgPool,
+) -> Result<Limiter, anyhow::Error> {
+    let limits = RateLimits {
+        enabled: config.enabled,
+        login_per_ip: bucket_config_from_config("login.per_ip", &config.login.per_ip)?,
+        login_per_account: bucket_config_from_config(
+            "login.per_account",
+            &config.login.per_account,
+        )?,
+        registration_per_ip: bucket_config_from_config(
+            "registration.per_ip",
+            &config.registration.per_ip,
+        )?,
+        token_per_ip: bucket_config_from_config("token.per_ip", &config.token.per_ip)?,
+        token_per_client: bucket_config_from_config("token.per_client", &config.token.per_client)?,
+        login_failure_delay: config.login.failure_delay.map(|config| LoginFailureDelay {
+            free_attempts: config.free_attempts,
+            base_delay: config.base_delay,
+            max_delay: config.max_delay,
+            window: config.window,
+        }),
+    };
+
+    let limiter = match config.backend {
+        RateLimitingBackend::Memory => Limiter::new(&limits),
+        RateLimitingBackend::Postgres => Limiter::shared(&limits, pool.clone()),
+    };
+
+    Ok(limiter)
+}
+
+pub fn ip_filter_from_config(
+    config: &IpFilterConfig,
+    http_client_factory: &HttpClientFactory,
+) -> Result<IpFilter, anyhow::Error> {
+    anyhow::ensure!(
+        config
+            .dnsbl
+            .iter()
+            .all(|zone| !zone.trim_matches('.').is_empty()),
+        "ip_filter.dnsbl zones must not be empty"
+    );
+
+    let rules = IpFilterRules {
+        allow: config.allow.clone(),
+        deny: config.deny.clone(),
+        dnsbl_zones: config.dnsbl.clone(),
+        reputation_service: config.reputation_service.clone(),
     };
 
-    Ok(Mailer::new(templates.clone(), transport, from, reply_to))
+    Ok(IpFilter::new(
+        rules,
+        http_client_factory.http_service("ip_filter.reputation"),
+    ))
+}
+
+pub fn email_locales_from_config(
+    config: &EmailLocalesConfig,
+) -> Result<EmailLocales, anyhow::Error> {
+    let default = config
+        .default
+        .parse()
+        .with_context(|| format!("invalid default email locale {:?}", config.default))?;
+
+    config
+        .domains
+        .iter()
+        .try_fold(EmailLocales::new(default), |locales, (domain, locale)| {
+            let locale = locale
+                .parse()
+                .with_context(|| format!("invalid email locale {locale:?} for domain {domain}"))?;
+            Ok(locales.with_domain(domain, locale))
+        })
+}
+
+pub fn security_notifications_from_config(
+    config: &SecurityNotificationsConfig,
+) -> Vec<SecurityNotification> {
+    [
+        (SecurityNotification::PasswordChange, config.password_change),
+        (SecurityNotification::UpstreamLink, config.upstream_link),
+        (SecurityNotification::AccountLock, config.account_lock),
+    ]
+    .into_iter()
+    .filter_map(|(category, enabled)| enabled.then_some(category))
+    .collect()
+}
+
+pub fn homeserver_connection_from_config(
+    config: &MatrixConfig,
+    ip_filter: &IpFilterConfig,
+    http_client_factory: &HttpClientFactory,
+) -> Result<Box<dyn HomeserverConnection<Error = anyhow::Error>>, anyhow::Error> {
+    let conn: Box<dyn HomeserverConnection<Error = anyhow::Error>> = match config.kind {
+        HomeserverKind::Synapse => Box::new(
+            SynapseConnection::new(
+                config.homeserver.clone(),
+                config.endpoint.clone(),
+                config.secret.clone(),
+                http_client_factory.clone(),
+            )
+            // Avatars are not fetched from the networks denied by the IP filter
+            .with_avatar_denied_networks(ip_filter.deny.clone()),
+        ),
+        HomeserverKind::Dendrite => {
+            let registration_shared_secret = config
+                .registration_shared_secret
+                .clone()
+                .context("matrix.registration_shared_secret is required with Dendrite")?;
+
+            Box::new(DendriteConnection::new(
+                config.homeserver.clone(),
+                config.endpoint.clone(),
+                config.secret.clone(),
+                registration_shared_secret,
+                http_client_factory.clone(),
+            ))
+        }
+    };
+
+    Ok(conn)
+}
+
+pub fn appservices_from_config(config: &MatrixConfig) -> Result<AppserviceRegistry, anyhow::Error> {
+    config
+        .appservices
+        .iter()
+        .map(|appservice| {
+            Appservice::new(
+                appservice.id.clone(),
+                appservice.as_token.clone(),
+                appservice.sender_localpart.clone(),
+                &appservice.users,
+            )
+            .with_context(|| format!("Invalid user namespace for appservice {:?}", appservice.id))
+        })
+        .collect()
+}
+
+pub fn jwt_login_from_config(config: &MatrixConfig) -> Option<JwtLoginConfig> {
+    let config = config.jwt_login.as_ref()?;
+
+    let jwks = match &config.keys {
+        JwksOrJwksUri::Jwks(jwks) => mas_data_model::JwksOrJwksUri::Jwks(jwks.clone()),
+        JwksOrJwksUri::JwksUri(uri) => mas_data_model::JwksOrJwksUri::JwksUri(uri.clone()),
+    };
+
+    Some(JwtLoginConfig {
+        jwks,
+        subject_claim: config.subject_claim.clone(),
+        issuer: config.issuer.clone(),
+        audience: config.audience.clone(),
+    })
+}
+
+pub fn client_well_known_from_config(config: &MatrixConfig) -> Option<ClientWellKnownConfig> {
+    let config = config.client_well_known.as_ref()?;
+
+    Some(ClientWellKnownConfig {
+        homeserver_base_url: config.homeserver_base_url.clone(),
+        extra: config.extra.clone(),
+    })
+}
+
+pub fn scope_registry_from_config(config: &ScopesConfig) -> Result<ScopeRegistry, anyhow::Error> {
+    let mut registry = ScopeRegistry::default();
+
+    for scope in &config.custom {
+        scope
+            .name
+            .parse::<ScopeToken>()
+            .with_context(|| format!("invalid custom scope {:?}", scope.name))?;
+
+        let risk = match scope.risk {
+            ScopeRiskConfig::Low => ScopeRisk::Low,
+            ScopeRiskConfig::Medium => ScopeRisk::Medium,
+            ScopeRiskConfig::High => ScopeRisk::High,
+        };
+
+        let defined = registry.register(ScopeDefinition {
+            name: scope.name.clone(),
+            display_name: scope.display_name.clone(),
+            description: scope.description.clone(),
+            risk,
+        });
+
+        anyhow::ensure!(defined, "scope {:?} is already defined", scope.name);
+    }
+
+    Ok(registry)
+}
+
+/// The clients allowed to get the tokens stored for each upstream provider, by
+/// provider ID
+pub fn upstream_token_clients_from_config(
+    config: &UpstreamOAuth2Config,
+) -> BTreeMap<Ulid, Vec<Ulid>> {
+    config
+        .providers
+        .iter()
+        .filter(|provider| provider.store_tokens && !provider.token_clients.is_empty())
+        .map(|provider| (provider.id, provider.token_clients.clone()))
+        .collect()
+}
+
+/// The IDs of the clients allowed to get the tokens of at least one upstream
+/// provider, which the policy lets request the `urn:mas:upstream_tokens` scope
+fn all_upstream_token_clients(upstream_token_clients: &BTreeMap<Ulid, Vec<Ulid>>) -> Vec<String> {
+    let mut clients: Vec<String> = upstream_token_clients
+        .values()
+        .flatten()
+        .map(ToString::to_string)
+        .collect();
+    clients.sort_unstable();
+    clients.dedup();
+    clients
+}
+
+pub fn claim_mappings_from_config(
+    config: &[ClaimMappingConfig],
+) -> Result<Vec<ClaimMapping>, anyhow::Error> {
+    config
+        .iter()
+        .map(|mapping| {
+            anyhow::ensure!(!mapping.claim.is_empty(), "claim names can't be empty");
+
+            if let Some(scope) = &mapping.scope {
+                scope.parse::<ScopeToken>().with_context(|| {
+                    format!("invalid scope {scope:?} for claim {:?}", mapping.claim)
+                })?;
+            }
+
+            let attribute = match mapping.attribute {
+                UserAttributeConfig::DisplayName => UserAttribute::DisplayName,
+                UserAttributeConfig::Email => UserAttribute::Email,
+                UserAttributeConfig::IsAdmin => UserAttribute::IsAdmin,
+                UserAttributeConfig::Groups => UserAttribute::Groups,
+            };
+
+            Ok(ClaimMapping {
+                claim: mapping.claim.clone(),
+                attribute,
+                scope: mapping.scope.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Build the OAuth 2.0 features enabled on the server, by removing the
+/// disabled ones from the supported ones
+pub fn oauth2_features_from_config(config: &FeaturesConfig) -> OAuth2Features {
+    let disabled_grant_types: Vec<GrantType> = config
+        .disabled_grant_types
+        .iter()
+        .map(|grant_type| match grant_type {
+            GrantTypeConfig::AuthorizationCode => GrantType::AuthorizationCode,
+            GrantTypeConfig::RefreshToken => GrantType::RefreshToken,
+            GrantTypeConfig::ClientCredentials => GrantType::ClientCredentials,
+            GrantTypeConfig::JwtBearer => GrantType::JwtBearer,
+        })
+        .collect();
+
+    let disabled_response_modes: Vec<ResponseMode> = config
+        .disabled_response_modes
+        .iter()
+        .map(|response_mode| match response_mode {
+            ResponseModeConfig::Query => ResponseMode::Query,
+            ResponseModeConfig::Fragment => ResponseMode::Fragment,
+            ResponseModeConfig::FormPost => ResponseMode::FormPost,
+        })
+        .collect();
+
+    OAuth2Features {
+        dynamic_registration: config.dynamic_registration,
+        grant_types: OAuth2Features::SUPPORTED_GRANT_TYPES
+            .into_iter()
+            .filter(|grant_type| !disabled_grant_types.contains(grant_type))
+            .collect(),
+        response_modes: OAuth2Features::SUPPORTED_RESPONSE_MODES
+            .into_iter()
+            .filter(|response_mode| !disabled_response_modes.contains(response_mode))
+            .collect(),
+    }
 }
 
 pub async fn policy_factory_from_config(
     config: &PolicyConfig,
+    usernames: &UsernamesConfig,
+    scopes: &ScopesConfig,
+    features: &OAuth2Features,
+    upstream_oauth2: &UpstreamOAuth2Config,
+    source: &mut PolicySource,
 ) -> Result<PolicyFactory, anyhow::Error> {
-    let policy_file = tokio::fs::File::open(&config.wasm_module)
-        .await
-        .context("failed to open OPA WASM policy file")?;
+    let upstream_token_clients = upstream_token_clients_from_config(upstream_oauth2);
+    let mut policy_factory = if let Some(builtin) = &config.builtin {
+        PolicyFactory::builtin(builtin_policy_rules_from_config(
+            builtin,
+            usernames,
+            scopes,
+            features,
+            &upstream_token_clients,
+        )?)
+    } else {
+        opa_policy_factory_from_config(
+            config,
+            usernames,
+            scopes,
+            features,
+            &upstream_token_clients,
+            source,
+        )
+        .await?
+    };
+
+    if let Some(rate) = config.log_inputs_sample_rate {
+        anyhow::ensure!(
+            (0.0..=1.0).contains(&rate),
+            "policy.log_inputs_sample_rate must be between 0 and 1"
+        );
+        policy_factory = policy_factory.with_input_sample_rate(rate);
+    }
+
+    if let Some(path) = &config.geoip_database {
+        let geoip = GeoIp::open(path)
+            .await
+            .context("failed to load the GeoIP database")?;
+        policy_factory = policy_factory.with_geoip(geoip);
+    }
+
+    Ok(policy_factory)
+}
+
+/// Load the OPA policy module
+async fn opa_policy_factory_from_config(
+    config: &PolicyConfig,
+    usernames: &UsernamesConfig,
+    scopes: &ScopesConfig,
+    features: &OAuth2Features,
+    upstream_token_clients: &BTreeMap<Ulid, Vec<Ulid>>,
+    source: &mut PolicySource,
+) -> Result<PolicyFactory, anyhow::Error> {
+    let module = source.load().await?;
 
     let entrypoints = mas_policy::Entrypoints {
         register: config.register_entrypoint.clone(),
@@ -103,28 +685,203 @@ pub async fn policy_factory_from_config(
         authorization_grant: config.authorization_grant_entrypoint.clone(),
         email: config.email_entrypoint.clone(),
         password: config.password_entrypoint.clone(),
+        token: config.token_entrypoint.clone(),
+        upstream_login: config.upstream_login_entrypoint.clone(),
+        claims: config.claims_entrypoint.clone(),
     };
 
-    PolicyFactory::load(
-        policy_file,
-        config.data.clone().unwrap_or_default(),
-        entrypoints,
+    // Pass the username rules, the custom scopes, the disabled features and the
+    // clients allowed to get upstream tokens to the policy, alongside the
+    // arbitrary data
+    let mut data = config
+        .data
+        .clone()
+        .unwrap_or_else(|| serde_json::Value::Object(serde_json::Map::new()));
+    if let Some(data) = data.as_object_mut() {
+        data.insert(
+            "usernames".to_owned(),
+            serde_json::json!({
+                "pattern": usernames.pattern,
+                "min_length": usernames.min_length,
+                "max_length": usernames.max_length,
+                "reserved": usernames.reserved,
+            }),
+        );
+        data.insert(
+            "custom_scopes".to_owned(),
+            scopes
+                .custom
+                .iter()
+                .map(|scope| serde_json::Value::String(scope.name.clone()))
+                .collect(),
+        );
+        data.insert(
+            "features".to_owned(),
+            serde_json::json!({
+                "dynamic_registration": features.dynamic_registration,
+                "disabled_grant_types": features
+                    .disabled_grant_types()
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>(),
+            }),
+        );
+        data.insert(
+            "upstream_token_clients".to_owned(),
+            all_upstream_token_clients(upstream_token_clients)
+                .into_iter()
+                .map(serde_json::Value::String)
+                .collect(),
+        );
+    }
+
+    PolicyFactory::load(&module[..], data, entrypoints)
+        .await
+        .context("failed to load the policy")
+}
+
+/// Build the rules of the built-in policy
+fn builtin_policy_rules_from_config(
+    config: &BuiltinPolicyConfig,
+    usernames: &UsernamesConfig,
+    scopes: &ScopesConfig,
+    features: &OAuth2Features,
+    upstream_token_clients: &BTreeMap<Ulid, Vec<Ulid>>,
+) -> Result<BuiltinRules, anyhow::Error> {
+    let usernames = UsernameRules::new(
+        &usernames.pattern,
+        usernames.min_length,
+        usernames.max_length,
+        usernames.reserved.clone(),
     )
-    .await
-    .context("failed to load the policy")
+    .context("invalid username pattern")?;
+
+    let stripped_scopes = config
+        .token
+        .stripped_scopes
+        .iter()
+        .map(|(grant_type, scopes)| {
+            let grant_type = serde_json::from_value(serde_json::Value::String(grant_type.clone()))
+                .with_context(|| format!("invalid grant type {grant_type:?} in stripped_scopes"))?;
+            Ok((grant_type, scopes.clone()))
+        })
+        .collect::<Result<_, anyhow::Error>>()?;
+
+    Ok(BuiltinRules {
+        emails: EmailRules {
+            allowed_domains: config.emails.allowed_domains.clone(),
+            banned_domains: config.emails.banned_domains.clone(),
+        },
+        usernames,
+        passwords: PasswordRules {
+            min_length: config.passwords.min_length,
+            require_lowercase: config.passwords.require_lowercase,
+            require_uppercase: config.passwords.require_uppercase,
+            require_number: config.passwords.require_number,
+        },
+        clients: ClientRules {
+            allowed_hosts: config.clients.allowed_hosts.clone(),
+            allow_insecure_uris: config.clients.allow_insecure_uris,
+            disabled_grant_types: features.disabled_grant_types(),
+        },
+        admin_users: config.admin_users.clone(),
+        admin_clients: config.admin_clients.clone(),
+        upstream_token_clients: all_upstream_token_clients(upstream_token_clients),
+        custom_scopes: scopes
+            .custom
+            .iter()
+            .map(|scope| scope.name.clone())
+            .collect(),
+        restricted_clients: config
+            .restricted_clients
+            .iter()
+            .map(|(client_id, restricted)| {
+                let restricted = RestrictedClient {
+                    allowed_users: restricted.allowed_users.clone(),
+                    allow_admins: restricted.allow_admins,
+                };
+                (client_id.clone(), restricted)
+            })
+            .collect(),
+        tokens: TokenRules {
+            client_scopes: config
+                .token
+                .client_scopes
+                .iter()
+                .map(|(client_id, scopes)| (client_id.clone(), scopes.clone()))
+                .collect(),
+            stripped_scopes,
+        },
+    })
+}
+
+fn theme_colors_from_config(config: &ThemeColorsConfig) -> Result<ThemeColors, anyhow::Error> {
+    let parse = |color: Option<&str>| color.map(str::parse::<Color>).transpose();
+
+    Ok(ThemeColors {
+        accent: parse(config.accent.as_deref()).context("invalid accent color")?,
+        background: parse(config.background.as_deref()).context("invalid background color")?,
+        text: parse(config.text.as_deref()).context("invalid text color")?,
+    })
+}
+
+fn theme_from_config(config: &ThemeConfig) -> Result<Theme, anyhow::Error> {
+    Ok(Theme {
+        logo_uri: config.logo_uri.clone(),
+        favicon_uri: config.favicon_uri.clone(),
+        colors: theme_colors_from_config(&config.colors).context("invalid theme colors")?,
+        dark_colors: config
+            .dark_colors
+            .as_ref()
+            .map(theme_colors_from_config)
+            .transpose()
+            .context("invalid theme dark colors")?,
+        extra_css_path: config.extra_css_path.clone(),
+    })
 }
 
 pub async fn templates_from_config(
     config: &TemplatesConfig,
     url_builder: &UrlBuilder,
-) -> Result<Templates, TemplateLoadingError> {
-    Templates::load(
+) -> Result<Templates, anyhow::Error> {
+    let theme = config
+        .theme
+        .as_ref()
+        .map(theme_from_config)
+        .transpose()?
+        .unwrap_or_default();
+
+    let templates = Templates::load_with_email_overrides(
         config.path.clone(),
+        config.email_overrides_path.clone(),
         url_builder.clone(),
         config.assets_manifest.clone(),
         config.translations_path.clone(),
+        config.extra_translations_path.clone(),
+        theme,
+        config.custom_context.clone(),
     )
-    .await
+    .await?;
+
+    let clock = SystemClock::default();
+    // XXX: we should disallow SeedableRng::from_entropy
+    let mut rng = rand_chacha::ChaChaRng::from_entropy();
+
+    // Make sure the templates render with the custom context, so that typos in
+    // its keys are caught on startup
+    if !config.custom_context.is_empty() {
+        templates
+            .check_render(clock.now(), &mut rng)
+            .context("templates failed to render with the custom context")?;
+    } else if config.email_overrides_path.is_some() {
+        // Make sure the overridden email templates render, so that we don't find
+        // out when sending the first email
+        templates
+            .check_email_render(clock.now(), &mut rng)
+            .context("invalid email template overrides")?;
+    }
+
+    Ok(templates)
 }
 
 fn database_connect_options_from_config(
@@ -308,4 +1065,74 @@ mod tests {
         let manager = password_manager_from_config(&config).await;
         assert!(manager.is_err());
     }
+
+    #[test]
+    fn test_scope_registry_from_config() {
+        let config = serde_json::from_value(serde_json::json!({
+            "custom": [{
+                "name": "urn:example:admin",
+                "display_name": "Administer example.com",
+                "risk": "high"
+            }]
+        }))
+        .unwrap();
+
+        let registry = scope_registry_from_config(&config).unwrap();
+        let definition = registry.get("urn:example:admin").unwrap();
+        assert_eq!(definition.risk, ScopeRisk::High);
+        assert!(registry.get("openid").is_some());
+
+        // Built-in scopes can't be redefined
+        let config = serde_json::from_value(serde_json::json!({
+            "custom": [{
+                "name": "openid",
+                "display_name": "Something else"
+            }]
+        }))
+        .unwrap();
+        assert!(scope_registry_from_config(&config).is_err());
+
+        // Names must be valid scope tokens
+        let config = serde_json::from_value(serde_json::json!({
+            "custom": [{
+                "name": "not a scope",
+                "display_name": "Invalid"
+            }]
+        }))
+        .unwrap();
+        assert!(scope_registry_from_config(&config).is_err());
+    }
+
+    #[test]
+    fn test_claim_mappings_from_config() {
+        let config: ScopesConfig = serde_json::from_value(serde_json::json!({
+            "claims": [{
+                "claim": "groups",
+                "attribute": "groups",
+                "scope": "urn:example:groups"
+            }]
+        }))
+        .unwrap();
+
+        let mappings = claim_mappings_from_config(&config.claims).unwrap();
+        assert_eq!(
+            mappings,
+            vec![ClaimMapping {
+                claim: "groups".to_owned(),
+                attribute: UserAttribute::Groups,
+                scope: Some("urn:example:groups".to_owned()),
+            }]
+        );
+
+        // Scopes must be valid scope tokens
+        let config: ScopesConfig = serde_json::from_value(serde_json::json!({
+            "claims": [{
+                "claim": "groups",
+                "attribute": "groups",
+                "scope": "not a scope"
+            }]
+        }))
+        .unwrap();
+        assert!(claim_mappings_from_config(&config.claims).is_err());
+    }
 }
@@ -0,0 +1,254 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Resolution of environment variable references and secret files in the
+//! configuration files
+
+use figment::{
+    error::Error as FigmentError,
+    value::{Dict, Map, Value},
+    Metadata, Profile, Provider,
+};
+
+/// Secret fields which can be read from a file by setting `<field>_file`
+/// instead of `<field>`. A `*` matches any item of a list.
+///
+/// Fields which already have a dedicated file variant, like the keys and their
+/// passwords, are not listed here.
+const SECRET_FILE_FIELDS: &[&str] = &[
+    "clients.*.client_secret",
+    "database.password",
+    "email.password",
+    "email.secret_access_key",
+    "matrix.secret",
+    "matrix.registration_shared_secret",
+    "matrix.appservices.*.as_token",
+    "secrets.encryption",
+    "secrets.encryption_keys.*.key",
+    "secrets.vault.token",
+    "upstream_oauth2.providers.*.client_secret",
+    "webhooks.endpoints.*.secret",
+];
+
+/// A [`Provider`] which resolves the `${VAR}` environment variable references
+/// in the string values of another provider, and replaces the
+/// `<field>_file` secret fields with the content of the file they point to.
+///
+/// `$${` can be used to write a literal `${`.
+pub(crate) struct Interpolated<P> {
+    inner: P,
+}
+
+impl<P> Interpolated<P> {
+    pub(crate) const fn new(inner: P) -> Self {
+        Self { inner }
+    }
+}
+
+impl<P: Provider> Provider for Interpolated<P> {
+    fn metadata(&self) -> Metadata {
+        self.inner.metadata()
+    }
+
+    fn data(&self) -> Result<Map<Profile, Dict>, FigmentError> {
+        let mut data = self.inner.data()?;
+        for dict in data.values_mut() {
+            resolve_dict(dict, &mut Vec::new())?;
+        }
+        Ok(data)
+    }
+}
+
+fn resolve_value<'a>(value: &'a mut Value, path: &mut Vec<&'a str>) -> Result<(), FigmentError> {
+    match value {
+        Value::String(_, string) => {
+            *string = interpolate(string)?;
+        }
+        Value::Dict(_, dict) => resolve_dict(dict, path)?,
+        Value::Array(_, array) => {
+            for item in array {
+                path.push("*");
+                resolve_value(item, path)?;
+                path.pop();
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn resolve_dict<'a>(dict: &'a mut Dict, path: &mut Vec<&'a str>) -> Result<(), FigmentError> {
+    let secret_files: Vec<(String, String)> = dict
+        .keys()
+        .filter_map(|key| {
+            let field = key.strip_suffix("_file")?;
+            let full_path = path
+                .iter()
+                .copied()
+                .chain(std::iter::once(field))
+                .collect::<Vec<_>>()
+                .join(".");
+            SECRET_FILE_FIELDS
+                .contains(&full_path.as_str())
+                .then(|| (key.clone(), field.to_owned()))
+        })
+        .collect();
+
+    for (key, field) in secret_files {
+        if dict.contains_key(&field) {
+            return Err(FigmentError::from(format!(
+                "`{field}` and `{key}` can't be set at the same time"
+            )));
+        }
+
+        let Some(mut value) = dict.remove(&key) else {
+            continue;
+        };
+
+        // The path itself may reference environment variables
+        resolve_value(&mut value, &mut Vec::new())?;
+        let Some(file) = value.into_string() else {
+            return Err(FigmentError::from(format!("`{key}` must be a path")));
+        };
+
+        let content = std::fs::read_to_string(&file)
+            .map_err(|e| FigmentError::from(format!("could not read `{key}` ({file}): {e}")))?;
+
+        // Files often end with a newline which isn't part of the secret
+        let content = content
+            .strip_suffix('\n')
+            .map_or(content.as_str(), |content| {
+                content.strip_suffix('\r').unwrap_or(content)
+            });
+
+        dict.insert(field, Value::from(content.to_owned()));
+    }
+
+    for (key, value) in dict.iter_mut() {
+        path.push(key);
+        resolve_value(value, path)?;
+        path.pop();
+    }
+
+    Ok(())
+}
+
+/// Replace the `${VAR}` references in a string with the value of the
+/// corresponding environment variable
+fn interpolate(input: &str) -> Result<String, FigmentError> {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(index) = rest.find('$') {
+        output.push_str(&rest[..index]);
+        rest = &rest[index..];
+
+        if let Some(after) = rest.strip_prefix("$${") {
+            output.push_str("${");
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("${") {
+            let Some(end) = after.find('}') else {
+                return Err(FigmentError::from(format!(
+                    "unterminated environment variable reference in {input:?}"
+                )));
+            };
+
+            let name = &after[..end];
+            let value = std::env::var(name).map_err(|e| {
+                FigmentError::from(format!(
+                    "could not resolve environment variable `{name}`: {e}"
+                ))
+            })?;
+
+            output.push_str(&value);
+            rest = &after[end + 1..];
+        } else {
+            output.push('$');
+            rest = &rest[1..];
+        }
+    }
+
+    output.push_str(rest);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::{providers::Yaml, Figment, Jail};
+
+    use super::*;
+    use crate::{ConfigurationSection, DatabaseConfig, MatrixConfig};
+
+    #[test]
+    fn interpolate_env_vars() {
+        Jail::expect_with(|jail| {
+            jail.set_env("MAS_TEST_SECRET", "hunter2");
+
+            assert_eq!(interpolate("${MAS_TEST_SECRET}")?, "hunter2");
+            assert_eq!(
+                interpolate("a ${MAS_TEST_SECRET} b $ c $${NOT_A_VAR}")?,
+                "a hunter2 b $ c ${NOT_A_VAR}"
+            );
+            assert!(interpolate("${MAS_TEST_MISSING}").is_err());
+            assert!(interpolate("${MAS_TEST_SECRET").is_err());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn load_interpolated_config() {
+        Jail::expect_with(|jail| {
+            jail.set_env("MAS_TEST_HOMESERVER", "example.com");
+            jail.create_file("secret", "matrix-secret\n")?;
+            jail.create_file(
+                "config.yaml",
+                r"
+                    matrix:
+                      homeserver: ${MAS_TEST_HOMESERVER}
+                      secret_file: secret
+                ",
+            )?;
+
+            let config = MatrixConfig::load_from_file("config.yaml")?;
+            assert_eq!(config.homeserver, "example.com");
+            assert_eq!(config.secret, "matrix-secret");
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn reject_secret_and_secret_file() {
+        Jail::expect_with(|jail| {
+            jail.create_file("password", "hunter2")?;
+            jail.create_file(
+                "config.yaml",
+                r"
+                    database:
+                      password: hunter2
+                      password_file: password
+                ",
+            )?;
+
+            let res = Figment::new()
+                .merge(Interpolated::new(Yaml::file("config.yaml")))
+                .extract_inner::<DatabaseConfig>(DatabaseConfig::path());
+            assert!(res.is_err());
+
+            Ok(())
+        });
+    }
+}
@@ -27,6 +27,7 @@
 #[cfg(all(feature = "docker", feature = "dist"))]
 compile_error!("Only one of the `docker` and `dist` features can be enabled at once");
 
+mod interpolation;
 pub(crate) mod schema;
 mod sections;
 pub(crate) mod util;
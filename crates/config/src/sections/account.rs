@@ -0,0 +1,93 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use rand::Rng;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use super::ConfigurationSection;
+
+/// Attributes users must have on their account before signing in to a client
+///
+/// Users missing some of them are asked to complete their profile during the
+/// authorization flow, instead of being denied.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct AccountConfig {
+    /// Whether users must have a verified email address. Defaults to `false`.
+    #[serde(default)]
+    pub require_verified_email: bool,
+
+    /// Whether users must have a display name set on the homeserver. Defaults
+    /// to `false`.
+    #[serde(default)]
+    pub require_displayname: bool,
+
+    /// URL of the terms of service users must accept
+    ///
+    /// Changing this URL asks every user to accept the new terms.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub terms_uri: Option<Url>,
+}
+
+#[async_trait]
+impl ConfigurationSection for AccountConfig {
+    fn path() -> &'static str {
+        "account"
+    }
+
+    async fn generate<R>(_rng: R) -> anyhow::Result<Self>
+    where
+        R: Rng + Send,
+    {
+        Ok(Self::default())
+    }
+
+    fn test() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::Jail;
+
+    use super::*;
+
+    #[test]
+    fn load_config() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r#"
+                    account:
+                      require_verified_email: true
+                      terms_uri: https://example.com/terms
+                "#,
+            )?;
+
+            let config = AccountConfig::load_from_file("config.yaml")?;
+
+            assert!(config.require_verified_email);
+            assert!(!config.require_displayname);
+            assert_eq!(
+                config.terms_uri.as_ref().map(Url::as_str),
+                Some("https://example.com/terms")
+            );
+
+            Ok(())
+        });
+    }
+}
@@ -15,22 +15,27 @@
 use std::ops::{Deref, DerefMut};
 
 use async_trait::async_trait;
-use mas_iana::oauth::OAuthClientAuthenticationMethod;
+use chrono::Duration;
+use mas_iana::{jose::JsonWebSignatureAlg, oauth::OAuthClientAuthenticationMethod};
 use mas_jose::jwk::PublicJsonWebKeySet;
 use rand::Rng;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use serde_with::skip_serializing_none;
+use serde_with::{serde_as, skip_serializing_none};
 use thiserror::Error;
 use ulid::Ulid;
 use url::Url;
 
-use super::ConfigurationSection;
+use super::{ClaimMappingConfig, ConfigurationSection};
 
+/// The JSON Web Key Set of a client, either inline or by reference
 #[derive(JsonSchema, Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "snake_case")]
 pub enum JwksOrJwksUri {
+    /// Client's JSON Web Key Set, inlined in the configuration
     Jwks(PublicJsonWebKeySet),
+
+    /// URL of the client's JSON Web Key Set
     JwksUri(Url),
 }
 
@@ -73,7 +78,31 @@ pub enum ClientAuthMethodConfig {
     PrivateKeyJwt(JwksOrJwksUri),
 }
 
+/// How the redirect URIs of authorization requests are matched against the
+/// registered ones
+#[derive(JsonSchema, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RedirectUriMatchingConfig {
+    /// The redirect URI must be one of the registered ones
+    Exact,
+
+    /// Loopback redirect URIs, like `http://127.0.0.1/callback`, match on any
+    /// port, for native apps
+    #[default]
+    Loopback,
+
+    /// Like `loopback`, and registered redirect URIs can start their host with
+    /// a `*` label matching any single label, like in
+    /// `https://*.example.com/callback`
+    SubdomainWildcard,
+}
+
+const fn default_true() -> bool {
+    true
+}
+
 /// An OAuth 2.0 client configuration
+#[serde_as]
 #[skip_serializing_none]
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ClientConfig {
@@ -92,8 +121,76 @@ pub struct ClientConfig {
     /// List of allowed redirect URIs
     #[serde(default)]
     pub redirect_uris: Vec<Url>,
+
+    /// How the redirect URIs of authorization requests are matched against
+    /// `redirect_uris`. Defaults to `loopback`
+    #[serde(default)]
+    pub redirect_uri_matching: RedirectUriMatchingConfig,
+
+    /// Space-separated list of scopes the client is allowed to obtain through
+    /// the client credentials grant.
+    ///
+    /// If not set, the scopes are only restricted by the policy.
+    pub client_credentials_scope: Option<String>,
+
+    /// Whether refresh tokens are issued to this client, letting it keep
+    /// access to the account of users while they are away. Defaults to `true`.
+    #[serde(default = "default_true")]
+    pub refresh_tokens: bool,
+
+    /// Time-to-live of the access tokens issued to this client, in seconds.
+    /// Overrides `experimental.access_token_ttl`.
+    #[schemars(with = "Option<u64>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<i64>>")]
+    pub access_token_ttl: Option<Duration>,
+
+    /// How long the sessions of this client can go without being refreshed
+    /// before they end, in seconds. Overrides
+    /// `experimental.offline_session_ttl`.
+    #[schemars(with = "Option<u64>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<i64>>")]
+    pub refresh_token_ttl: Option<Duration>,
+
+    /// Audiences added to the tokens issued to this client, on top of the
+    /// client ID itself
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub additional_audiences: Vec<String>,
+
+    /// Algorithm used to sign the responses of the userinfo endpoint for this
+    /// client. If not set, the responses are plain JSON.
+    ///
+    /// The keys in the `secrets` section must support this algorithm.
+    pub userinfo_signed_response_alg: Option<JsonWebSignatureAlg>,
+
+    /// Attributes of the users mapped to the claims of the ID tokens and
+    /// userinfo responses of this client, on top of the ones of the `scopes`
+    /// section
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub claims: Vec<ClaimMappingConfig>,
+
+    /// Whether the authorization requests of this client are held until an
+    /// administrator approves them through the admin API. The user then gets
+    /// an email with a link to finish the request. Defaults to `false`
+    #[serde(default)]
+    pub requires_approval: bool,
+
+    /// Whether this is a first-party client, which users don't have to give
+    /// consent to for the scopes in `trusted_scope`. Scopes beyond those still
+    /// show the consent screen. Defaults to `false`
+    #[serde(default)]
+    pub trusted: bool,
+
+    /// Space-separated list of scopes a trusted client gets without asking the
+    /// user for consent.
+    ///
+    /// Defaults to `openid urn:matrix:org.matrix.msc2967.client:api:*`
+    pub trusted_scope: Option<String>,
 }
 
+const DEFAULT_TRUSTED_SCOPE: &str = "openid urn:matrix:org.matrix.msc2967.client:api:*";
+
 #[derive(Debug, Error)]
 #[error("Invalid redirect URI")]
 pub struct InvalidRedirectUriError;
@@ -130,6 +227,16 @@ impl ClientConfig {
         }
     }
 
+    #[doc(hidden)]
+    #[must_use]
+    pub fn effective_trusted_scope(&self) -> Option<&str> {
+        self.trusted.then(|| {
+            self.trusted_scope
+                .as_deref()
+                .unwrap_or(DEFAULT_TRUSTED_SCOPE)
+        })
+    }
+
     #[doc(hidden)]
     #[must_use]
     pub fn jwks(&self) -> Option<&PublicJsonWebKeySet> {
@@ -195,6 +302,7 @@ mod tests {
     use figment::Jail;
 
     use super::*;
+    use crate::UserAttributeConfig;
 
     #[test]
     fn load_config() {
@@ -207,18 +315,35 @@ mod tests {
                       client_auth_method: none
                       redirect_uris:
                         - https://exemple.fr/callback
+                      trusted: true
 
                     - client_id: 01GFWR32NCQ12B8Z0J8CPXRRB6
                       client_auth_method: client_secret_basic
                       client_secret: hello
+                      client_credentials_scope: "urn:mas:graphql:*"
+                      refresh_tokens: false
+                      access_token_ttl: 300
+                      additional_audiences:
+                        - https://ci.example.com/
+                      userinfo_signed_response_alg: RS256
+                      claims:
+                        - claim: is_admin
+                          attribute: is_admin
+                      requires_approval: true
+                      trusted: true
+                      trusted_scope: "openid urn:mas:graphql:*"
 
                     - client_id: 01GFWR3WHR93Y5HK389H28VHZ9
                       client_auth_method: client_secret_post
                       client_secret: hello
+                      redirect_uris:
+                        - https://*.example.com/callback
+                      redirect_uri_matching: subdomain_wildcard
 
                     - client_id: 01GFWR43R2ZZ8HX9CVBNW9TJWG
                       client_auth_method: client_secret_jwt
                       client_secret: hello
+                      redirect_uri_matching: exact
 
                     - client_id: 01GFWR4BNFDCC4QDG6AMSP1VRR
                       client_auth_method: private_key_jwt
@@ -252,12 +377,68 @@ mod tests {
                 config.0[0].redirect_uris,
                 vec!["https://exemple.fr/callback".parse().unwrap()]
             );
+            assert_eq!(
+                config.0[0].redirect_uri_matching,
+                RedirectUriMatchingConfig::Loopback
+            );
+            assert_eq!(config.0[0].client_credentials_scope, None);
+            assert!(config.0[0].refresh_tokens);
+            assert!(!config.0[0].requires_approval);
+            assert_eq!(
+                config.0[0].effective_trusted_scope(),
+                Some("openid urn:matrix:org.matrix.msc2967.client:api:*")
+            );
+            assert_eq!(config.0[0].access_token_ttl, None);
+            assert!(config.0[0].additional_audiences.is_empty());
+            assert_eq!(config.0[0].userinfo_signed_response_alg, None);
+            assert!(config.0[0].claims.is_empty());
 
             assert_eq!(
                 config.0[1].client_id,
                 Ulid::from_str("01GFWR32NCQ12B8Z0J8CPXRRB6").unwrap()
             );
             assert_eq!(config.0[1].redirect_uris, Vec::new());
+            assert_eq!(
+                config.0[1].client_credentials_scope.as_deref(),
+                Some("urn:mas:graphql:*")
+            );
+            assert!(!config.0[1].refresh_tokens);
+            assert!(config.0[1].requires_approval);
+            assert_eq!(
+                config.0[1].effective_trusted_scope(),
+                Some("openid urn:mas:graphql:*")
+            );
+            assert_eq!(config.0[1].access_token_ttl, Some(Duration::minutes(5)));
+            assert_eq!(config.0[1].refresh_token_ttl, None);
+            assert_eq!(
+                config.0[1].additional_audiences,
+                vec!["https://ci.example.com/".to_owned()]
+            );
+            assert_eq!(
+                config.0[1].userinfo_signed_response_alg,
+                Some(JsonWebSignatureAlg::Rs256)
+            );
+            assert_eq!(config.0[1].claims.len(), 1);
+            assert_eq!(config.0[1].claims[0].claim, "is_admin");
+            assert_eq!(
+                config.0[1].claims[0].attribute,
+                UserAttributeConfig::IsAdmin
+            );
+
+            assert_eq!(
+                config.0[2].redirect_uris,
+                vec!["https://*.example.com/callback".parse().unwrap()]
+            );
+            assert_eq!(
+                config.0[2].redirect_uri_matching,
+                RedirectUriMatchingConfig::SubdomainWildcard
+            );
+            assert_eq!(config.0[2].effective_trusted_scope(), None);
+
+            assert_eq!(
+                config.0[3].redirect_uri_matching,
+                RedirectUriMatchingConfig::Exact
+            );
 
             Ok(())
         });
@@ -12,16 +12,21 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-#![allow(deprecated)]
-
-use std::num::NonZeroU16;
+use std::{
+    collections::BTreeMap,
+    num::{NonZeroU16, NonZeroU32},
+    time::Duration,
+};
 
+use anyhow::Context;
 use async_trait::async_trait;
 use rand::Rng;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use url::Url;
 
-use super::ConfigurationSection;
+use super::{secrets::KeyOrFile, ConfigurationSection};
 
 #[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct Credentials {
@@ -70,6 +75,11 @@ pub enum EmailTransportConfig {
         /// Set of credentials to use
         #[serde(flatten, default)]
         credentials: Option<Credentials>,
+
+        /// Maximum number of connections to keep open to the relay. Default is
+        /// 10
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pool_size: Option<NonZeroU32>,
     },
 
     /// Send emails by calling sendmail
@@ -80,8 +90,61 @@ pub enum EmailTransportConfig {
     },
 
     /// Send emails via the AWS SESv2 API
-    #[deprecated(note = "The AWS SESv2 backend has be removed.")]
-    AwsSes,
+    AwsSes {
+        /// AWS region to use. Defaults to the value of the `AWS_REGION`
+        /// environment variable
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        region: Option<String>,
+
+        /// Access key to use. Defaults to the `AWS_ACCESS_KEY_ID`,
+        /// `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN` environment
+        /// variables
+        #[serde(flatten, default)]
+        credentials: Option<AwsCredentials>,
+    },
+
+    /// Send emails via the SendGrid API
+    #[serde(rename = "sendgrid")]
+    SendGrid {
+        /// API key to use
+        api_key: String,
+    },
+
+    /// Send emails via the Mailgun API
+    Mailgun {
+        /// Sending domain, as configured in Mailgun
+        #[schemars(schema_with = "crate::schema::hostname")]
+        domain: String,
+
+        /// API key to use
+        api_key: String,
+
+        /// Base URL of the API. Use `https://api.eu.mailgun.net/` for accounts
+        /// in the EU region
+        #[serde(default = "default_mailgun_endpoint")]
+        endpoint: Url,
+    },
+
+    /// Send emails via the Postmark API
+    Postmark {
+        /// Server API token to use
+        server_token: String,
+
+        /// Message stream to send the emails through. Defaults to the
+        /// transactional stream of the server
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        message_stream: Option<String>,
+    },
+}
+
+/// Static AWS credentials
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct AwsCredentials {
+    /// Access key ID
+    pub access_key_id: String,
+
+    /// Secret access key
+    pub secret_access_key: String,
 }
 
 impl Default for EmailTransportConfig {
@@ -98,7 +161,182 @@ fn default_sendmail_command() -> String {
     "sendmail".to_owned()
 }
 
+fn default_mailgun_endpoint() -> Url {
+    Url::parse("https://api.mailgun.net/").unwrap()
+}
+
+fn default_timeout() -> Duration {
+    Duration::from_secs(60)
+}
+
+const fn default_true() -> bool {
+    true
+}
+
+fn default_resend_cooldown() -> chrono::Duration {
+    chrono::Duration::seconds(60)
+}
+
+fn default_rate_limit_window() -> chrono::Duration {
+    chrono::Duration::hours(1)
+}
+
+fn default_per_address() -> NonZeroU32 {
+    NonZeroU32::new(5).unwrap()
+}
+
+fn default_per_user() -> NonZeroU32 {
+    NonZeroU32::new(10).unwrap()
+}
+
+/// Algorithm used to sign emails with DKIM
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DkimAlgorithm {
+    /// RSA-SHA256
+    #[default]
+    Rsa,
+
+    /// Ed25519-SHA256
+    Ed25519,
+}
+
+/// Configuration of the DKIM signature added to outgoing emails
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct DkimConfig {
+    /// Domain of the signing entity, usually the domain of the From address
+    #[schemars(schema_with = "crate::schema::hostname")]
+    pub domain: String,
+
+    /// Selector under which the public key is published in the DNS
+    pub selector: String,
+
+    /// Signing algorithm, which must match the type of the key
+    #[serde(default)]
+    pub algorithm: DkimAlgorithm,
+
+    /// The private key, either as a PKCS#1 PEM-encoded RSA key, or as a
+    /// base64-encoded Ed25519 key
+    #[serde(flatten)]
+    key: KeyOrFile,
+}
+
+impl DkimConfig {
+    /// Load the private key, reading it from disk if needed
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key file could not be read
+    pub async fn private_key(&self) -> anyhow::Result<String> {
+        match &self.key {
+            KeyOrFile::Key(key) => Ok(key.clone()),
+            KeyOrFile::KeyFile(path) => tokio::fs::read_to_string(path)
+                .await
+                .with_context(|| format!("could not read DKIM key from {path}")),
+        }
+    }
+}
+
+/// Configuration of the notification emails sent on security-relevant account
+/// events
+///
+/// Users can additionally opt out of each category individually
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SecurityNotificationsConfig {
+    /// Notify users when their password is changed. Default is true
+    #[serde(default = "default_true")]
+    pub password_change: bool,
+
+    /// Notify users when an upstream account is linked to their account.
+    /// Default is true
+    #[serde(default = "default_true")]
+    pub upstream_link: bool,
+
+    /// Notify users when their account is locked. Default is true
+    #[serde(default = "default_true")]
+    pub account_lock: bool,
+}
+
+impl Default for SecurityNotificationsConfig {
+    fn default() -> Self {
+        Self {
+            password_change: true,
+            upstream_link: true,
+            account_lock: true,
+        }
+    }
+}
+
+/// Limits on how often verification emails can be sent, to avoid being used
+/// to flood someone's inbox
+#[serde_as]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct EmailRateLimitConfig {
+    /// Minimum delay between two emails sent to the same address, in seconds.
+    /// Default is 60
+    #[schemars(with = "u64")]
+    #[serde(default = "default_resend_cooldown")]
+    #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+    pub resend_cooldown: chrono::Duration,
+
+    /// Period over which the emails are counted, in seconds. Default is 3600
+    #[schemars(with = "u64")]
+    #[serde(default = "default_rate_limit_window")]
+    #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+    pub window: chrono::Duration,
+
+    /// Maximum number of emails sent to a single address during the window,
+    /// across all users. Default is 5
+    #[serde(default = "default_per_address")]
+    pub per_address: NonZeroU32,
+
+    /// Maximum number of emails sent for a single user during the window,
+    /// across all their addresses. Default is 10
+    #[serde(default = "default_per_user")]
+    pub per_user: NonZeroU32,
+}
+
+impl Default for EmailRateLimitConfig {
+    fn default() -> Self {
+        Self {
+            resend_cooldown: default_resend_cooldown(),
+            window: default_rate_limit_window(),
+            per_address: default_per_address(),
+            per_user: default_per_user(),
+        }
+    }
+}
+
+fn default_locale() -> String {
+    "en".to_owned()
+}
+
+/// Which locale emails are rendered in, when the preferred locale of the
+/// recipient is unknown
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct EmailLocalesConfig {
+    /// Locale used when nothing else is known about the recipient, as a BCP 47
+    /// language tag. Default is `en`
+    #[serde(default = "default_locale")]
+    pub default: String,
+
+    /// Locales to use for recipients with an address on a given domain, keyed
+    /// by domain
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub domains: BTreeMap<String, String>,
+}
+
+impl Default for EmailLocalesConfig {
+    fn default() -> Self {
+        Self {
+            default: default_locale(),
+            domains: BTreeMap::new(),
+        }
+    }
+}
+
 /// Configuration related to sending emails
+#[serde_as]
 #[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct EmailConfig {
     /// Email address to use as From when sending emails
@@ -114,6 +352,30 @@ pub struct EmailConfig {
     /// What backend should be used when sending emails
     #[serde(flatten, default)]
     pub transport: EmailTransportConfig,
+
+    /// How long sending a single email can take before it is aborted, in
+    /// seconds. Default is 60
+    #[schemars(with = "u64")]
+    #[serde(default = "default_timeout")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub timeout: Duration,
+
+    /// Sign outgoing emails with DKIM
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dkim: Option<DkimConfig>,
+
+    /// Which security-relevant account events trigger a notification email
+    #[serde(default)]
+    pub security_notifications: SecurityNotificationsConfig,
+
+    /// Limits on how often verification emails can be sent
+    #[serde(default)]
+    pub rate_limit: EmailRateLimitConfig,
+
+    /// Which locale emails are rendered in, when the preferred locale of the
+    /// recipient is unknown
+    #[serde(default)]
+    pub locales: EmailLocalesConfig,
 }
 
 impl Default for EmailConfig {
@@ -122,6 +384,11 @@ impl Default for EmailConfig {
             from: default_email(),
             reply_to: default_email(),
             transport: EmailTransportConfig::Blackhole,
+            timeout: default_timeout(),
+            dkim: None,
+            security_notifications: SecurityNotificationsConfig::default(),
+            rate_limit: EmailRateLimitConfig::default(),
+            locales: EmailLocalesConfig::default(),
         }
     }
 }
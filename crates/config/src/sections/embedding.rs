@@ -0,0 +1,110 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use rand::Rng;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use super::ConfigurationSection;
+
+/// Configuration related to embedding the pages of the service in clients
+///
+/// Clients can open the account management in an iframe, and get notified
+/// when the user completes an action there.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct EmbeddingConfig {
+    /// Origins of the clients allowed to embed the pages of the service, like
+    /// `https://app.example.com`. Only the scheme, host and port of each URL
+    /// are used.
+    ///
+    /// The pages can then be framed by those origins, the cookies are sent in
+    /// cross-site requests, and the messages telling that an action was
+    /// completed are only posted to those origins.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_origins: Vec<Url>,
+}
+
+impl EmbeddingConfig {
+    /// The serialized origins allowed to embed the pages, like
+    /// `https://app.example.com`
+    #[must_use]
+    pub fn origins(&self) -> Vec<String> {
+        let mut origins: Vec<String> = self
+            .allowed_origins
+            .iter()
+            .map(Url::origin)
+            // Opaque origins, like the ones of `data:` URLs, can't be matched
+            .filter(url::Origin::is_tuple)
+            .map(|origin| origin.ascii_serialization())
+            .collect();
+        origins.dedup();
+        origins
+    }
+}
+
+#[async_trait]
+impl ConfigurationSection for EmbeddingConfig {
+    fn path() -> &'static str {
+        "embedding"
+    }
+
+    async fn generate<R>(_rng: R) -> anyhow::Result<Self>
+    where
+        R: Rng + Send,
+    {
+        Ok(Self::default())
+    }
+
+    fn test() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::Jail;
+
+    use super::*;
+
+    #[test]
+    fn load_config() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r#"
+                    embedding:
+                      allowed_origins:
+                        - https://app.example.com
+                        - https://app.example.com/some/path?query
+                        - http://localhost:8080/
+                        - data:text/plain,hello
+                "#,
+            )?;
+
+            let config = EmbeddingConfig::load_from_file("config.yaml")?;
+
+            assert_eq!(
+                config.origins(),
+                vec![
+                    "https://app.example.com".to_owned(),
+                    "http://localhost:8080".to_owned(),
+                ]
+            );
+
+            Ok(())
+        });
+    }
+}
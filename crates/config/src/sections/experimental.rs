@@ -25,6 +25,22 @@ fn default_token_ttl() -> Duration {
     Duration::minutes(5)
 }
 
+fn default_authorization_code_ttl() -> Duration {
+    Duration::minutes(10)
+}
+
+fn default_clock_skew() -> Duration {
+    Duration::minutes(5)
+}
+
+fn default_impersonation_ttl() -> Duration {
+    Duration::minutes(30)
+}
+
+fn default_consent_ttl() -> Duration {
+    Duration::days(90)
+}
+
 /// Configuration sections for experimental options
 ///
 /// Do not change these options unless you know what you are doing.
@@ -37,19 +53,70 @@ pub struct ExperimentalConfig {
     #[serde_as(as = "serde_with::DurationSeconds<i64>")]
     pub access_token_ttl: Duration,
 
+    /// Time-to-live of offline sessions in seconds, that is how long a session
+    /// with a refresh token can go without being refreshed before it ends.
+    /// Unlimited by default.
+    #[schemars(with = "Option<u64>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<i64>>")]
+    pub offline_session_ttl: Option<Duration>,
+
     /// Time-to-live of compatibility access tokens in seconds. Defaults to 5
     /// minutes.
     #[schemars(with = "u64", range(min = 60, max = 86400))]
     #[serde(default = "default_token_ttl")]
     #[serde_as(as = "serde_with::DurationSeconds<i64>")]
     pub compat_token_ttl: Duration,
+
+    /// How long an authorization code can be exchanged for tokens after it
+    /// was issued, in seconds. Defaults to 10 minutes.
+    #[schemars(with = "u64", range(min = 10, max = 3600))]
+    #[serde(default = "default_authorization_code_ttl")]
+    #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+    pub authorization_code_ttl: Duration,
+
+    /// How far the clock of the issuers of the JWTs the service accepts can be
+    /// ahead or behind its own clock, in seconds, when validating their
+    /// `exp` and `nbf` claims. Defaults to 5 minutes.
+    #[schemars(with = "u64", range(max = 3600))]
+    #[serde(default = "default_clock_skew")]
+    #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+    pub clock_skew: Duration,
+
+    /// Time-to-live of the browser sessions started by administrators to
+    /// impersonate users, in seconds. Defaults to 30 minutes.
+    #[schemars(with = "u64", range(min = 60, max = 86400))]
+    #[serde(default = "default_impersonation_ttl")]
+    #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+    pub impersonation_ttl: Duration,
+
+    /// How long the consent users give to clients is remembered, in seconds,
+    /// when they choose to. Defaults to 90 days.
+    #[schemars(with = "u64", range(min = 60))]
+    #[serde(default = "default_consent_ttl")]
+    #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+    pub consent_ttl: Duration,
+
+    /// How long the results of the introspection endpoint are cached, in
+    /// seconds. They are forgotten earlier if their session ends or their user
+    /// changes, on any server instance. Disabled by default.
+    #[schemars(with = "Option<u64>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<i64>>")]
+    pub introspection_cache_ttl: Option<Duration>,
 }
 
 impl Default for ExperimentalConfig {
     fn default() -> Self {
         Self {
             access_token_ttl: default_token_ttl(),
+            offline_session_ttl: None,
             compat_token_ttl: default_token_ttl(),
+            authorization_code_ttl: default_authorization_code_ttl(),
+            clock_skew: default_clock_skew(),
+            impersonation_ttl: default_impersonation_ttl(),
+            consent_ttl: default_consent_ttl(),
+            introspection_cache_ttl: None,
         }
     }
 }
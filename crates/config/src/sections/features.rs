@@ -0,0 +1,148 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use rand::Rng;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::ConfigurationSection;
+
+/// A grant type of the token endpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum GrantTypeConfig {
+    /// `authorization_code`
+    #[serde(rename = "authorization_code")]
+    AuthorizationCode,
+
+    /// `refresh_token`
+    #[serde(rename = "refresh_token")]
+    RefreshToken,
+
+    /// `client_credentials`
+    #[serde(rename = "client_credentials")]
+    ClientCredentials,
+
+    /// `urn:ietf:params:oauth:grant-type:jwt-bearer`
+    #[serde(rename = "urn:ietf:params:oauth:grant-type:jwt-bearer")]
+    JwtBearer,
+}
+
+/// A response mode of the authorization endpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseModeConfig {
+    /// `query`
+    Query,
+
+    /// `fragment`
+    Fragment,
+
+    /// `form_post`
+    FormPost,
+}
+
+const fn default_true() -> bool {
+    true
+}
+
+/// OAuth 2.0 and OpenID Connect capabilities which can be disabled.
+///
+/// Disabled capabilities are not advertised in the discovery document, are
+/// rejected by the endpoints, and clients can't register with them.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FeaturesConfig {
+    /// Whether clients can register through the dynamic client registration
+    /// endpoint. Defaults to `true`
+    #[serde(default = "default_true")]
+    pub dynamic_registration: bool,
+
+    /// Grant types the token endpoint rejects
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub disabled_grant_types: Vec<GrantTypeConfig>,
+
+    /// Response modes the authorization endpoint rejects
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub disabled_response_modes: Vec<ResponseModeConfig>,
+}
+
+impl Default for FeaturesConfig {
+    fn default() -> Self {
+        Self {
+            dynamic_registration: true,
+            disabled_grant_types: Vec::new(),
+            disabled_response_modes: Vec::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl ConfigurationSection for FeaturesConfig {
+    fn path() -> &'static str {
+        "features"
+    }
+
+    async fn generate<R>(_rng: R) -> anyhow::Result<Self>
+    where
+        R: Rng + Send,
+    {
+        Ok(Self::default())
+    }
+
+    fn test() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::Jail;
+
+    use super::*;
+
+    #[test]
+    fn load_config() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    features:
+                      dynamic_registration: false
+                      disabled_grant_types:
+                        - client_credentials
+                        - urn:ietf:params:oauth:grant-type:jwt-bearer
+                      disabled_response_modes:
+                        - form_post
+                ",
+            )?;
+
+            let config = FeaturesConfig::load_from_file("config.yaml")?;
+
+            assert!(!config.dynamic_registration);
+            assert_eq!(
+                config.disabled_grant_types,
+                vec![
+                    GrantTypeConfig::ClientCredentials,
+                    GrantTypeConfig::JwtBearer
+                ]
+            );
+            assert_eq!(
+                config.disabled_response_modes,
+                vec![ResponseModeConfig::FormPost]
+            );
+
+            Ok(())
+        });
+    }
+}
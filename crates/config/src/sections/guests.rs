@@ -0,0 +1,101 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use chrono::Duration;
+use rand::Rng;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+use super::ConfigurationSection;
+
+fn default_ttl() -> Duration {
+    Duration::days(7)
+}
+
+/// Configuration related to guest accounts
+///
+/// Guests are throwaway accounts registered through the Matrix guest access
+/// flow. They have restricted access, and are deactivated after a while unless
+/// they are upgraded to a full account.
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct GuestsConfig {
+    /// Whether guests can register. Defaults to `false`.
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// How long guest accounts are kept before being deactivated, in seconds.
+    /// Defaults to 7 days.
+    #[schemars(with = "u64", range(min = 60))]
+    #[serde(default = "default_ttl")]
+    #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+    pub ttl: Duration,
+}
+
+impl Default for GuestsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            ttl: default_ttl(),
+        }
+    }
+}
+
+#[async_trait]
+impl ConfigurationSection for GuestsConfig {
+    fn path() -> &'static str {
+        "guests"
+    }
+
+    async fn generate<R>(_rng: R) -> anyhow::Result<Self>
+    where
+        R: Rng + Send,
+    {
+        Ok(Self::default())
+    }
+
+    fn test() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::Jail;
+
+    use super::*;
+
+    #[test]
+    fn load_config() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r#"
+                    guests:
+                      enabled: true
+                      ttl: 86400
+                "#,
+            )?;
+
+            let config = GuestsConfig::load_from_file("config.yaml")?;
+
+            assert!(config.enabled);
+            assert_eq!(config.ttl, Duration::days(1));
+
+            Ok(())
+        });
+    }
+}
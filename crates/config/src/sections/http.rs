@@ -14,17 +14,17 @@
 
 #![allow(deprecated)]
 
-use std::{borrow::Cow, io::Cursor, ops::Deref};
+use std::{borrow::Cow, io::Cursor, num::NonZeroUsize, ops::Deref, time::Duration};
 
 use anyhow::bail;
 use async_trait::async_trait;
-use camino::Utf8PathBuf;
+use camino::{Utf8Path, Utf8PathBuf};
 use ipnetwork::IpNetwork;
 use mas_keystore::PrivateKey;
 use rand::Rng;
 use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
-use serde_with::skip_serializing_none;
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
+use serde_with::{serde_as, skip_serializing_none};
 use url::Url;
 
 use super::{secrets::PasswordOrFile, ConfigurationSection};
@@ -72,6 +72,177 @@ fn default_trusted_proxies() -> Vec<IpNetwork> {
     ]
 }
 
+fn default_client_ip_headers() -> Vec<ClientIpHeader> {
+    vec![ClientIpHeader::XForwardedFor]
+}
+
+/// Header from which the address of the client is read, when the request comes
+/// from a trusted proxy
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClientIpHeader {
+    /// The de-facto standard `X-Forwarded-For` header
+    XForwardedFor,
+
+    /// The `Forwarded` header, as defined by RFC 7239
+    Forwarded,
+}
+
+/// Groups of routes which can be given their own security headers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteGroup {
+    /// Pages destined to be viewed by humans
+    Human,
+
+    /// APIs, like the OAuth 2.0, Matrix compatibility and GraphQL ones
+    Api,
+
+    /// Static files
+    Assets,
+}
+
+/// Security-related headers set on the responses.
+///
+/// Setting a header to an empty string prevents it from being set
+#[skip_serializing_none]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Default, PartialEq, Eq)]
+pub struct SecurityHeaders {
+    /// Value of the `Content-Security-Policy` header
+    #[serde(default)]
+    pub content_security_policy: Option<String>,
+
+    /// Value of the `Strict-Transport-Security` header
+    #[serde(default)]
+    pub strict_transport_security: Option<String>,
+
+    /// Value of the `X-Frame-Options` header
+    #[serde(default)]
+    pub frame_options: Option<String>,
+
+    /// Value of the `Referrer-Policy` header
+    #[serde(default)]
+    pub referrer_policy: Option<String>,
+}
+
+impl SecurityHeaders {
+    /// Take the headers which are not set here from `other`
+    fn or(self, other: Self) -> Self {
+        Self {
+            content_security_policy: self
+                .content_security_policy
+                .or(other.content_security_policy),
+            strict_transport_security: self
+                .strict_transport_security
+                .or(other.strict_transport_security),
+            frame_options: self.frame_options.or(other.frame_options),
+            referrer_policy: self.referrer_policy.or(other.referrer_policy),
+        }
+    }
+
+    /// The built-in headers of a group of routes, given the origins allowed to
+    /// embed the pages
+    fn builtin(group: RouteGroup, embedding_origins: &[String]) -> Self {
+        let strict_transport_security = Some("max-age=31536000".to_owned());
+        match group {
+            RouteGroup::Human => {
+                let mut frame_ancestors = "'self'".to_owned();
+                for origin in embedding_origins {
+                    frame_ancestors.push(' ');
+                    frame_ancestors.push_str(origin);
+                }
+
+                Self {
+                    // The templates have inline scripts and styles, and the pages can be
+                    // embedded by the same origin and the allowed ones
+                    content_security_policy: Some(format!(
+                        "default-src 'self'; script-src 'self' 'unsafe-inline'; \
+                         style-src 'self' 'unsafe-inline'; img-src 'self' data: https:; \
+                         object-src 'none'; base-uri 'self'; frame-ancestors {frame_ancestors}"
+                    )),
+                    strict_transport_security,
+                    // `X-Frame-Options` can't list other origins, browsers rely on
+                    // `frame-ancestors` instead
+                    frame_options: embedding_origins
+                        .is_empty()
+                        .then(|| "SAMEORIGIN".to_owned()),
+                    referrer_policy: Some("same-origin".to_owned()),
+                }
+            }
+            RouteGroup::Api => Self {
+                content_security_policy: Some(
+                    "default-src 'none'; frame-ancestors 'none'".to_owned(),
+                ),
+                strict_transport_security,
+                frame_options: Some("DENY".to_owned()),
+                referrer_policy: Some("no-referrer".to_owned()),
+            },
+            RouteGroup::Assets => Self {
+                content_security_policy: None,
+                strict_transport_security,
+                frame_options: None,
+                referrer_policy: Some("no-referrer".to_owned()),
+            },
+        }
+    }
+}
+
+/// Configuration of the security headers set on the responses
+///
+/// The headers set at the top level apply to every group of routes, unless
+/// overridden in the section of a group. Headers which are not set anywhere
+/// get a built-in default suited to the group.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Default, PartialEq, Eq)]
+pub struct SecurityHeadersConfig {
+    /// Headers set on every group of routes
+    #[serde(flatten)]
+    pub defaults: SecurityHeaders,
+
+    /// Headers set on the pages destined to be viewed by humans
+    #[serde(default)]
+    pub human: SecurityHeaders,
+
+    /// Headers set on the APIs
+    #[serde(default)]
+    pub api: SecurityHeaders,
+
+    /// Headers set on the static files
+    #[serde(default)]
+    pub assets: SecurityHeaders,
+}
+
+impl SecurityHeadersConfig {
+    fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// The headers to set on the given group of routes. Headers which should
+    /// not be set are `None`
+    ///
+    /// The `embedding_origins` are allowed to frame the pages destined to
+    /// humans, unless their headers are overridden.
+    #[must_use]
+    pub fn resolve(&self, group: RouteGroup, embedding_origins: &[String]) -> SecurityHeaders {
+        let overrides = match group {
+            RouteGroup::Human => &self.human,
+            RouteGroup::Api => &self.api,
+            RouteGroup::Assets => &self.assets,
+        };
+
+        let headers = overrides
+            .clone()
+            .or(self.defaults.clone())
+            .or(SecurityHeaders::builtin(group, embedding_origins));
+
+        let non_empty = |value: Option<String>| value.filter(|value| !value.is_empty());
+        SecurityHeaders {
+            content_security_policy: non_empty(headers.content_security_policy),
+            strict_transport_security: non_empty(headers.strict_transport_security),
+            frame_options: non_empty(headers.frame_options),
+            referrer_policy: non_empty(headers.referrer_policy),
+        }
+    }
+}
+
 /// Kind of socket
 #[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy)]
 #[serde(rename_all = "lowercase")]
@@ -131,6 +302,11 @@ pub enum BindConfig {
         /// Path to the socket
         #[schemars(with = "String")]
         socket: Utf8PathBuf,
+
+        /// Permissions of the socket, as an octal string like `"660"`. Defaults
+        /// to the permissions given by the umask of the process
+        #[serde(default)]
+        mode: Option<String>,
     },
 
     /// Accept connections on file descriptors passed by the parent process.
@@ -169,6 +345,7 @@ pub enum CertificateOrFile {
 }
 
 /// Configuration related to TLS on a listener
+#[serde_as]
 #[skip_serializing_none]
 #[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct TlsConfig {
@@ -183,9 +360,34 @@ pub struct TlsConfig {
     /// Password used to decode the private key
     #[serde(flatten)]
     pub password: Option<PasswordOrFile>,
+
+    /// How often to check the certificate, key and password files for
+    /// changes, in seconds. The certificate is swapped without restarting the
+    /// listener when they change. Disabled by default
+    #[schemars(with = "Option<u64>")]
+    #[serde(default)]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<u64>>")]
+    pub reload_interval: Option<Duration>,
 }
 
 impl TlsConfig {
+    /// The files from which the certificate chain, the key and its password
+    /// are read, if any
+    #[must_use]
+    pub fn files(&self) -> Vec<&Utf8Path> {
+        let mut files = Vec::new();
+        if let CertificateOrFile::CertificateFile(path) = &self.certificate {
+            files.push(path.as_path());
+        }
+        if let KeyOrFile::KeyFile(path) = &self.key {
+            files.push(path.as_path());
+        }
+        if let Some(PasswordOrFile::PasswordFile(path)) = &self.password {
+            files.push(path.as_path());
+        }
+        files
+    }
+
     /// Load the TLS certificate chain and key file from disk
     ///
     /// # Errors
@@ -300,6 +502,58 @@ pub enum Resource {
     Spa,
 }
 
+/// hyper can't read the requests with a buffer smaller than this
+const MIN_MAX_HEADER_SIZE: usize = 8192;
+
+fn deserialize_max_header_size<'de, D>(deserializer: D) -> Result<Option<usize>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let max_header_size = Option::<usize>::deserialize(deserializer)?;
+    if let Some(size) = max_header_size.filter(|size| *size < MIN_MAX_HEADER_SIZE) {
+        return Err(D::Error::custom(format!(
+            "max_header_size must be at least {MIN_MAX_HEADER_SIZE} bytes, got {size}"
+        )));
+    }
+
+    Ok(max_header_size)
+}
+
+/// Limits applied to the connections and requests of a listener
+#[serde_as]
+#[skip_serializing_none]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Default, PartialEq, Eq)]
+pub struct ListenerLimitsConfig {
+    /// Maximum size of the request headers, in bytes. Can't be lower than
+    /// 8192. Defaults to about 400 KiB for HTTP/1.1, and 16 MiB for HTTP/2
+    #[schemars(range(min = 8192))]
+    #[serde(default, deserialize_with = "deserialize_max_header_size")]
+    pub max_header_size: Option<usize>,
+
+    /// Maximum size of the request bodies read by the service, in bytes.
+    /// Defaults to 2 MiB
+    #[serde(default)]
+    pub max_body_size: Option<usize>,
+
+    /// Time allowed to receive the headers of a request, and then to receive
+    /// its body and respond to it, in seconds. Unlimited by default
+    #[schemars(with = "Option<u64>")]
+    #[serde(default)]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<u64>>")]
+    pub request_timeout: Option<Duration>,
+
+    /// Maximum number of connections served at once. Connections above this
+    /// number are closed right away. Unlimited by default
+    #[serde(default)]
+    pub max_connections: Option<NonZeroUsize>,
+}
+
+impl ListenerLimitsConfig {
+    fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
 /// Configuration of a listener
 #[skip_serializing_none]
 #[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
@@ -319,13 +573,18 @@ pub struct ListenerConfig {
     /// List of sockets to bind
     pub binds: Vec<BindConfig>,
 
-    /// Accept HAProxy's Proxy Protocol V1
+    /// Accept HAProxy's Proxy Protocol, either V1 or V2. The version is
+    /// detected from the preamble of each connection
     #[serde(default)]
     pub proxy_protocol: bool,
 
     /// If set, makes the listener use TLS with the provided certificate and key
     #[serde(default)]
     pub tls: Option<TlsConfig>,
+
+    /// Limits applied to the connections and requests of this listener
+    #[serde(default, skip_serializing_if = "ListenerLimitsConfig::is_default")]
+    pub limits: ListenerLimitsConfig,
 }
 
 /// Configuration related to the web server
@@ -335,11 +594,21 @@ pub struct HttpConfig {
     #[serde(default)]
     pub listeners: Vec<ListenerConfig>,
 
-    /// List of trusted reverse proxies that can set the `X-Forwarded-For`
-    /// header
+    /// List of trusted reverse proxies that can set the address of the client
+    /// through one of the `client_ip_headers`
     #[serde(default = "default_trusted_proxies")]
     pub trusted_proxies: Vec<IpNetwork>,
 
+    /// Headers from which the address of the client is read when the request
+    /// comes from a trusted proxy. The first one present on the request is
+    /// used. Defaults to `x-forwarded-for`
+    #[serde(default = "default_client_ip_headers")]
+    pub client_ip_headers: Vec<ClientIpHeader>,
+
+    /// Security headers set on the responses
+    #[serde(default, skip_serializing_if = "SecurityHeadersConfig::is_default")]
+    pub security_headers: SecurityHeadersConfig,
+
     /// Public URL base from where the authentication service is reachable
     pub public_base: Url,
 
@@ -366,6 +635,7 @@ impl Default for HttpConfig {
                     prefix: None,
                     tls: None,
                     proxy_protocol: false,
+                    limits: ListenerLimitsConfig::default(),
                     binds: vec![BindConfig::Address {
                         address: "[::]:8080".into(),
                     }],
@@ -376,6 +646,7 @@ impl Default for HttpConfig {
                     prefix: None,
                     tls: None,
                     proxy_protocol: false,
+                    limits: ListenerLimitsConfig::default(),
                     binds: vec![BindConfig::Listen {
                         host: Some("localhost".to_owned()),
                         port: 8081,
@@ -383,6 +654,8 @@ impl Default for HttpConfig {
                 },
             ],
             trusted_proxies: default_trusted_proxies(),
+            client_ip_headers: default_client_ip_headers(),
+            security_headers: SecurityHeadersConfig::default(),
             issuer: Some(default_public_base()),
             public_base: default_public_base(),
         }
@@ -406,3 +679,135 @@ impl ConfigurationSection for HttpConfig {
         Self::default()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use figment::Jail;
+
+    use super::*;
+
+    #[test]
+    fn load_security_headers() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r#"
+                    http:
+                      public_base: https://auth.example.com/
+                      security_headers:
+                        referrer_policy: no-referrer
+                        human:
+                          frame_options: DENY
+                        assets:
+                          strict_transport_security: ""
+                "#,
+            )?;
+
+            let config = HttpConfig::load_from_file("config.yaml")?;
+            let headers = &config.security_headers;
+
+            let human = headers.resolve(RouteGroup::Human, &[]);
+            assert_eq!(human.frame_options.as_deref(), Some("DENY"));
+            assert_eq!(human.referrer_policy.as_deref(), Some("no-referrer"));
+            assert!(human.content_security_policy.is_some());
+
+            let api = headers.resolve(RouteGroup::Api, &[]);
+            assert_eq!(api.frame_options.as_deref(), Some("DENY"));
+            assert_eq!(api.referrer_policy.as_deref(), Some("no-referrer"));
+
+            // Empty values disable the header
+            let assets = headers.resolve(RouteGroup::Assets, &[]);
+            assert!(assets.strict_transport_security.is_none());
+            assert!(assets.content_security_policy.is_none());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn embedding_security_headers() {
+        let headers = SecurityHeadersConfig::default();
+        let origins = ["https://app.example.com".to_owned()];
+
+        let human = headers.resolve(RouteGroup::Human, &[]);
+        assert_eq!(human.frame_options.as_deref(), Some("SAMEORIGIN"));
+        assert!(human
+            .content_security_policy
+            .unwrap()
+            .ends_with("frame-ancestors 'self'"));
+
+        let human = headers.resolve(RouteGroup::Human, &origins);
+        assert!(human.frame_options.is_none());
+        assert!(human
+            .content_security_policy
+            .unwrap()
+            .ends_with("frame-ancestors 'self' https://app.example.com"));
+
+        // The APIs are never framed
+        let api = headers.resolve(RouteGroup::Api, &origins);
+        assert_eq!(api.frame_options.as_deref(), Some("DENY"));
+    }
+
+    #[test]
+    fn load_listener_limits() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r#"
+                    http:
+                      public_base: https://auth.example.com/
+                      listeners:
+                        - resources: []
+                          binds: []
+                          limits:
+                            max_header_size: 16384
+                            max_connections: 100
+                "#,
+            )?;
+
+            let config = HttpConfig::load_from_file("config.yaml")?;
+            assert_eq!(config.listeners[0].limits.max_header_size, Some(16384));
+            assert_eq!(
+                config.listeners[0].limits.max_connections,
+                NonZeroUsize::new(100)
+            );
+
+            // hyper can't handle buffers smaller than 8 KiB
+            jail.create_file(
+                "config.yaml",
+                r#"
+                    http:
+                      public_base: https://auth.example.com/
+                      listeners:
+                        - resources: []
+                          binds: []
+                          limits:
+                            max_header_size: 4096
+                "#,
+            )?;
+
+            let error = HttpConfig::load_from_file("config.yaml").unwrap_err();
+            assert!(error
+                .to_string()
+                .contains("max_header_size must be at least 8192 bytes, got 4096"));
+
+            // A listener which can't serve any connection is a mistake
+            jail.create_file(
+                "config.yaml",
+                r#"
+                    http:
+                      public_base: https://auth.example.com/
+                      listeners:
+                        - resources: []
+                          binds: []
+                          limits:
+                            max_connections: 0
+                "#,
+            )?;
+
+            assert!(HttpConfig::load_from_file("config.yaml").is_err());
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,105 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use async_trait::async_trait;
+use ipnetwork::IpNetwork;
+use rand::Rng;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use super::ConfigurationSection;
+
+/// Filtering of the login and registration attempts based on the IP address
+/// they come from
+///
+/// Denied attempts are rejected with a `403 Forbidden` response. The DNS
+/// blocklists and the reputation service are only queried for addresses which
+/// are in neither the `allow` nor the `deny` lists.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct IpFilterConfig {
+    /// Networks which are never denied, bypassing the other rules
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<IpNetwork>,
+
+    /// Networks which are always denied. Avatars of the upstream providers are
+    /// not fetched from these networks either
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<IpNetwork>,
+
+    /// DNS blocklist zones to look the IP addresses up in, e.g.
+    /// `zen.spamhaus.org`. Addresses listed in any of them are denied
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dnsbl: Vec<String>,
+
+    /// URL of an HTTP service telling whether an IP address should be denied
+    ///
+    /// The address is passed in the `ip` query parameter, and the service must
+    /// answer with a JSON object with a boolean `deny` field.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reputation_service: Option<Url>,
+}
+
+#[async_trait]
+impl ConfigurationSection for IpFilterConfig {
+    fn path() -> &'static str {
+        "ip_filter"
+    }
+
+    async fn generate<R>(_rng: R) -> anyhow::Result<Self>
+    where
+        R: Rng + Send,
+    {
+        Ok(Self::default())
+    }
+
+    fn test() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::Jail;
+
+    use super::*;
+
+    #[test]
+    fn load_config() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r#"
+                    ip_filter:
+                      allow:
+                        - 192.0.2.1/32
+                      deny:
+                        - 192.0.2.0/24
+                        - 2001:db8::/32
+                      dnsbl:
+                        - dnsbl.example.com
+                "#,
+            )?;
+
+            let config = IpFilterConfig::load_from_file("config.yaml")?;
+
+            let allowed: IpNetwork = "192.0.2.1/32".parse().unwrap();
+            assert_eq!(config.allow, vec![allowed]);
+            assert_eq!(config.deny.len(), 2);
+            assert_eq!(config.dnsbl, vec!["dnsbl.example.com".to_owned()]);
+            assert!(config.reputation_service.is_none());
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,156 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use chrono::Duration;
+use rand::Rng;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, skip_serializing_none};
+use ulid::Ulid;
+
+use super::{ConfigurationSection, JwksOrJwksUri};
+
+/// An external issuer trusted to sign assertions exchanged through the JWT
+/// bearer grant
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct JwtBearerIssuerConfig {
+    /// The `iss` claim of the assertions signed by this issuer
+    pub issuer: String,
+
+    /// The keys used to verify the signature of the assertions
+    #[serde(flatten)]
+    pub jwks: JwksOrJwksUri,
+
+    /// The usernames this issuer can sign assertions for, in the `sub` claim.
+    ///
+    /// Use `*` to let the issuer sign assertions for any user. If not set, no
+    /// assertion of this issuer is accepted.
+    #[serde(default)]
+    pub allowed_subjects: Vec<String>,
+
+    /// The IDs of the clients which can exchange the assertions of this
+    /// issuer. They also need to have the JWT bearer grant type.
+    #[serde(default)]
+    #[schemars(with = "Vec<String>")]
+    pub allowed_clients: Vec<Ulid>,
+
+    /// Space-separated list of scopes which can be obtained with the
+    /// assertions of this issuer.
+    ///
+    /// If not set, the scopes are only restricted by the policy.
+    pub allowed_scope: Option<String>,
+}
+
+fn default_max_assertion_lifetime() -> Duration {
+    Duration::minutes(5)
+}
+
+/// Configuration of the JWT bearer grant, through which assertions signed by
+/// trusted issuers can be exchanged for access tokens
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct JwtBearerConfig {
+    /// List of trusted issuers
+    #[serde(default)]
+    pub issuers: Vec<JwtBearerIssuerConfig>,
+
+    /// How long the assertions can be valid for, in seconds. Assertions with
+    /// an `exp` claim further in the future are rejected. Defaults to 5
+    /// minutes.
+    #[schemars(with = "u64", range(min = 10, max = 3600))]
+    #[serde(default = "default_max_assertion_lifetime")]
+    #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+    pub max_assertion_lifetime: Duration,
+}
+
+impl Default for JwtBearerConfig {
+    fn default() -> Self {
+        Self {
+            issuers: Vec::new(),
+            max_assertion_lifetime: default_max_assertion_lifetime(),
+        }
+    }
+}
+
+#[async_trait]
+impl ConfigurationSection for JwtBearerConfig {
+    fn path() -> &'static str {
+        "jwt_bearer"
+    }
+
+    async fn generate<R>(_rng: R) -> anyhow::Result<Self>
+    where
+        R: Rng + Send,
+    {
+        Ok(Self::default())
+    }
+
+    fn test() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use figment::Jail;
+
+    use super::*;
+
+    #[test]
+    fn load_config() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r#"
+                    jwt_bearer:
+                      issuers:
+                        - issuer: https://issuer.example.com/
+                          jwks_uri: https://issuer.example.com/jwks.json
+                          allowed_subjects:
+                            - alice
+                          allowed_clients:
+                            - 01GFWR28C4KNE04WG3HKXB7C9R
+                          allowed_scope: "urn:mas:graphql:*"
+                        - issuer: other-issuer
+                          jwks:
+                            keys: []
+                "#,
+            )?;
+
+            let config = JwtBearerConfig::load_from_file("config.yaml")?;
+
+            assert_eq!(config.issuers.len(), 2);
+            assert!(matches!(config.issuers[0].jwks, JwksOrJwksUri::JwksUri(_)));
+            assert_eq!(config.issuers[0].allowed_subjects, vec!["alice".to_owned()]);
+            assert_eq!(
+                config.issuers[0].allowed_clients,
+                vec![Ulid::from_str("01GFWR28C4KNE04WG3HKXB7C9R").unwrap()]
+            );
+            assert_eq!(
+                config.issuers[0].allowed_scope.as_deref(),
+                Some("urn:mas:graphql:*")
+            );
+            assert!(matches!(config.issuers[1].jwks, JwksOrJwksUri::Jwks(_)));
+            assert!(config.issuers[1].allowed_subjects.is_empty());
+            assert!(config.issuers[1].allowed_clients.is_empty());
+            assert_eq!(config.max_assertion_lifetime, Duration::minutes(5));
+
+            Ok(())
+        });
+    }
+}
@@ -12,6 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::BTreeMap;
+
 use async_trait::async_trait;
 use rand::{
     distributions::{Alphanumeric, DistString},
@@ -22,7 +24,7 @@ use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
 use url::Url;
 
-use super::ConfigurationSection;
+use super::{clients::JwksOrJwksUri, ConfigurationSection};
 
 fn default_homeserver() -> String {
     "localhost:8008".to_owned()
@@ -32,20 +34,122 @@ fn default_endpoint() -> Url {
     Url::parse("http://localhost:8008/").unwrap()
 }
 
+fn default_subject_claim() -> String {
+    "sub".to_owned()
+}
+
+/// The kind of homeserver the service is connected to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum HomeserverKind {
+    /// Synapse, using its admin API
+    #[default]
+    Synapse,
+
+    /// Dendrite, using its admin and shared-secret registration APIs
+    Dendrite,
+}
+
+/// An application service registered on the homeserver
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AppserviceConfig {
+    /// A unique identifier for the application service, as in its
+    /// registration file
+    pub id: String,
+
+    /// The token the application service uses to authenticate to the
+    /// homeserver (`as_token` in its registration file)
+    pub as_token: String,
+
+    /// The localpart of the application service sender
+    pub sender_localpart: String,
+
+    /// Regular expressions matching the Matrix IDs of the users the
+    /// application service is allowed to masquerade as
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub users: Vec<String>,
+}
+
+/// Configuration of the `org.matrix.login.jwt` login type, which lets
+/// clients of the compatibility layer log in with a JWT signed by a trusted
+/// party
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct JwtLoginConfig {
+    /// The keys used to verify the signature of the tokens, either as an
+    /// inline `jwks` or as a `jwks_uri`
+    #[serde(flatten)]
+    pub keys: JwksOrJwksUri,
+
+    /// The claim holding the localpart of the user logging in
+    #[serde(default = "default_subject_claim")]
+    pub subject_claim: String,
+
+    /// If set, the `iss` claim of the tokens must match this value
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+
+    /// If set, the `aud` claim of the tokens must contain this value
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audience: Option<String>,
+}
+
+/// Configuration of the `/.well-known/matrix/client` discovery document
+/// served by the service
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ClientWellKnownConfig {
+    /// The public base URL of the homeserver's client API, advertised as
+    /// `m.homeserver`
+    pub homeserver_base_url: Url,
+
+    /// Extra fields to add to the document, like `m.identity_server`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra: BTreeMap<String, serde_json::Value>,
+}
+
 /// Configuration related to the Matrix homeserver
 #[serde_as]
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct MatrixConfig {
+    /// The kind of homeserver. Defaults to Synapse.
+    #[serde(default)]
+    pub kind: HomeserverKind,
+
     /// The server name of the homeserver.
     #[serde(default = "default_homeserver")]
     pub homeserver: String,
 
-    /// Shared secret to use for calls to the admin API
+    /// Shared secret to use for calls to the admin API. With Dendrite, this is
+    /// the access token of an admin user.
     pub secret: String,
 
+    /// The `registration_shared_secret` of the homeserver, used to provision
+    /// users. Required with Dendrite.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registration_shared_secret: Option<String>,
+
     /// The base URL of the homeserver's client API
     #[serde(default = "default_endpoint")]
     pub endpoint: Url,
+
+    /// Application services whose tokens can be introspected
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub appservices: Vec<AppserviceConfig>,
+
+    /// Client IDs of the resource servers, like the homeserver or a media
+    /// proxy, which get the device ID, session ID and client name of the
+    /// tokens they introspect
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trusted_resource_servers: Vec<String>,
+
+    /// Allow logging in to the compatibility layer with JWTs. Disabled if not
+    /// set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jwt_login: Option<JwtLoginConfig>,
+
+    /// Serve the `/.well-known/matrix/client` discovery document, pointing
+    /// clients at this service. Disabled if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_well_known: Option<ClientWellKnownConfig>,
 }
 
 #[async_trait]
@@ -59,17 +163,29 @@ impl ConfigurationSection for MatrixConfig {
         R: Rng + Send,
     {
         Ok(Self {
+            kind: HomeserverKind::default(),
             homeserver: default_homeserver(),
             secret: Alphanumeric.sample_string(&mut rng, 32),
+            registration_shared_secret: None,
             endpoint: default_endpoint(),
+            appservices: Vec::new(),
+            trusted_resource_servers: Vec::new(),
+            jwt_login: None,
+            client_well_known: None,
         })
     }
 
     fn test() -> Self {
         Self {
+            kind: HomeserverKind::default(),
             homeserver: default_homeserver(),
             secret: "test".to_owned(),
+            registration_shared_secret: None,
             endpoint: default_endpoint(),
+            appservices: Vec::new(),
+            trusted_resource_servers: Vec::new(),
+            jwt_login: None,
+            client_well_known: None,
         }
     }
 }
@@ -94,8 +210,129 @@ mod tests {
 
             let config = MatrixConfig::load_from_file("config.yaml")?;
 
+            assert_eq!(config.kind, HomeserverKind::Synapse);
             assert_eq!(config.homeserver, "matrix.org".to_owned());
             assert_eq!(config.secret, "test".to_owned());
+            assert!(config.appservices.is_empty());
+            assert!(config.trusted_resource_servers.is_empty());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn load_appservices_config() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r#"
+                    matrix:
+                      homeserver: example.com
+                      secret: test
+                      appservices:
+                        - id: irc
+                          as_token: secret-token
+                          sender_localpart: irc-bridge
+                          users:
+                            - "@irc_.*:example.com"
+                "#,
+            )?;
+
+            let config = MatrixConfig::load_from_file("config.yaml")?;
+
+            assert_eq!(config.appservices.len(), 1);
+            assert_eq!(config.appservices[0].id, "irc");
+            assert_eq!(config.appservices[0].sender_localpart, "irc-bridge");
+            assert_eq!(config.appservices[0].users, vec!["@irc_.*:example.com"]);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn load_dendrite_config() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r#"
+                    matrix:
+                      kind: dendrite
+                      homeserver: example.com
+                      secret: admin-token
+                      registration_shared_secret: test
+                "#,
+            )?;
+
+            let config = MatrixConfig::load_from_file("config.yaml")?;
+
+            assert_eq!(config.kind, HomeserverKind::Dendrite);
+            assert_eq!(config.registration_shared_secret.as_deref(), Some("test"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn load_jwt_login_config() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r#"
+                    matrix:
+                      homeserver: example.com
+                      secret: test
+                      jwt_login:
+                        jwks_uri: https://auth.example.com/jwks.json
+                        issuer: https://auth.example.com/
+                "#,
+            )?;
+
+            let config = MatrixConfig::load_from_file("config.yaml")?;
+
+            let jwt_login = config.jwt_login.unwrap();
+            assert!(matches!(
+                jwt_login.keys,
+                JwksOrJwksUri::JwksUri(ref uri) if uri.as_str() == "https://auth.example.com/jwks.json"
+            ));
+            assert_eq!(jwt_login.subject_claim, "sub");
+            assert_eq!(
+                jwt_login.issuer.as_deref(),
+                Some("https://auth.example.com/")
+            );
+            assert_eq!(jwt_login.audience, None);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn load_client_well_known_config() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r#"
+                    matrix:
+                      homeserver: example.com
+                      secret: test
+                      client_well_known:
+                        homeserver_base_url: https://matrix.example.com/
+                        extra:
+                          m.identity_server:
+                            base_url: https://identity.example.com/
+                "#,
+            )?;
+
+            let config = MatrixConfig::load_from_file("config.yaml")?;
+
+            let well_known = config.client_well_known.unwrap();
+            assert_eq!(
+                well_known.homeserver_base_url.as_str(),
+                "https://matrix.example.com/"
+            );
+            assert_eq!(
+                well_known.extra["m.identity_server"]["base_url"],
+                "https://identity.example.com/"
+            );
 
             Ok(())
         });
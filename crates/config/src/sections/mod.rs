@@ -17,50 +17,107 @@ use rand::Rng;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+mod account;
 mod clients;
 mod database;
 mod email;
+mod embedding;
 mod experimental;
+mod features;
+mod guests;
 mod http;
+mod ip_filter;
+mod jwt_bearer;
 mod matrix;
 mod passwords;
 mod policy;
+mod profile;
+mod rate_limiting;
+mod scopes;
 mod secrets;
 mod telemetry;
 mod templates;
 mod upstream_oauth2;
+mod usernames;
+mod webhooks;
 
 pub use self::{
-    clients::{ClientAuthMethodConfig, ClientConfig, ClientsConfig},
+    account::AccountConfig,
+    clients::{
+        ClientAuthMethodConfig, ClientConfig, ClientsConfig, JwksOrJwksUri,
+        RedirectUriMatchingConfig,
+    },
     database::{ConnectConfig as DatabaseConnectConfig, DatabaseConfig},
-    email::{EmailConfig, EmailSmtpMode, EmailTransportConfig},
+    email::{
+        AwsCredentials, DkimAlgorithm, DkimConfig, EmailConfig, EmailLocalesConfig,
+        EmailRateLimitConfig, EmailSmtpMode, EmailTransportConfig, SecurityNotificationsConfig,
+    },
+    embedding::EmbeddingConfig,
     experimental::ExperimentalConfig,
+    features::{FeaturesConfig, GrantTypeConfig, ResponseModeConfig},
+    guests::GuestsConfig,
     http::{
-        BindConfig as HttpBindConfig, HttpConfig, ListenerConfig as HttpListenerConfig,
-        Resource as HttpResource, TlsConfig as HttpTlsConfig, UnixOrTcp,
+        BindConfig as HttpBindConfig, ClientIpHeader, HttpConfig,
+        ListenerConfig as HttpListenerConfig, ListenerLimitsConfig as HttpListenerLimitsConfig,
+        Resource as HttpResource, RouteGroup, SecurityHeaders, SecurityHeadersConfig,
+        TlsConfig as HttpTlsConfig, UnixOrTcp,
+    },
+    ip_filter::IpFilterConfig,
+    jwt_bearer::{JwtBearerConfig, JwtBearerIssuerConfig},
+    matrix::{
+        AppserviceConfig, ClientWellKnownConfig, HomeserverKind, JwtLoginConfig, MatrixConfig,
     },
-    matrix::MatrixConfig,
     passwords::{Algorithm as PasswordAlgorithm, PasswordsConfig},
-    policy::PolicyConfig,
-    secrets::SecretsConfig,
+    policy::{
+        BuiltinClientPolicyConfig, BuiltinEmailPolicyConfig, BuiltinPasswordPolicyConfig,
+        BuiltinPolicyConfig, BuiltinRestrictedClientConfig, BuiltinTokenPolicyConfig,
+        PolicyBundleConfig, PolicyConfig,
+    },
+    profile::DeploymentProfile,
+    rate_limiting::{
+        LoginFailureDelayConfig, LoginRateLimitingConfig, RateLimiterConfiguration,
+        RateLimitingBackend, RateLimitingConfig, RegistrationRateLimitingConfig,
+        TokenRateLimitingConfig,
+    },
+    scopes::{
+        ClaimMappingConfig, CustomScopeConfig, ScopeRisk as ScopeRiskConfig, ScopesConfig,
+        UserAttribute as UserAttributeConfig,
+    },
+    secrets::{EncryptionKeyConfig, ExternalSecrets, SecretsConfig, VaultConfig},
     telemetry::{
         JaegerExporterProtocolConfig, MetricsConfig, MetricsExporterConfig, Propagator,
         TelemetryConfig, TracingConfig, TracingExporterConfig,
     },
-    templates::TemplatesConfig,
+    templates::{TemplatesConfig, ThemeColorsConfig, ThemeConfig},
     upstream_oauth2::{
         ClaimsImports as UpstreamOAuth2ClaimsImports,
         EmailImportPreference as UpstreamOAuth2EmailImportPreference,
+        GroupsImportPreference as UpstreamOAuth2GroupsImportPreference,
+        HealthCheckConfig as UpstreamOAuth2HealthCheckConfig,
         ImportAction as UpstreamOAuth2ImportAction,
-        ImportPreference as UpstreamOAuth2ImportPreference,
-        SetEmailVerification as UpstreamOAuth2SetEmailVerification, UpstreamOAuth2Config,
+        ImportPreference as UpstreamOAuth2ImportPreference, ImportSync as UpstreamOAuth2ImportSync,
+        OnConflict as UpstreamOAuth2OnConflict, PkceMethod as UpstreamOAuth2PkceMethod,
+        ProfileAttribute as UpstreamOAuth2ProfileAttribute,
+        ProfileImportPreference as UpstreamOAuth2ProfileImportPreference,
+        Protocol as UpstreamOAuth2Protocol, Provider as UpstreamOAuth2Provider,
+        RegistrationConfig as UpstreamOAuth2RegistrationConfig,
+        ResponseMode as UpstreamOAuth2ResponseMode, SamlConfig as UpstreamOAuth2SamlConfig,
+        SetEmailVerification as UpstreamOAuth2SetEmailVerification,
+        SubjectImportPreference as UpstreamOAuth2SubjectImportPreference, UpstreamOAuth2Config,
     },
+    usernames::UsernamesConfig,
+    webhooks::{WebhookConfig, WebhookEvent, WebhooksConfig},
 };
 use crate::util::ConfigurationSection;
 
 /// Application configuration root
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct RootConfig {
+    /// Preset tuning the database pool, the rate limits and the caches for the
+    /// size of the deployment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<DeploymentProfile>,
+
     /// List of OAuth 2.0/OIDC clients config
     #[serde(default)]
     pub clients: ClientsConfig,
@@ -92,6 +149,28 @@ pub struct RootConfig {
     #[serde(default)]
     pub passwords: PasswordsConfig,
 
+    /// Rules applied to the localpart of new users
+    #[serde(default)]
+    pub usernames: UsernamesConfig,
+
+    /// Configuration related to guest accounts
+    #[serde(default)]
+    pub guests: GuestsConfig,
+
+    /// Attributes users must have on their account before signing in to a
+    /// client
+    #[serde(default)]
+    pub account: AccountConfig,
+
+    /// Rate limits applied to the sensitive endpoints
+    #[serde(default)]
+    pub rate_limiting: RateLimitingConfig,
+
+    /// Filtering of the login and registration attempts based on their IP
+    /// address
+    #[serde(default)]
+    pub ip_filter: IpFilterConfig,
+
     /// Configuration related to the homeserver
     pub matrix: MatrixConfig,
 
@@ -99,10 +178,31 @@ pub struct RootConfig {
     #[serde(default)]
     pub policy: PolicyConfig,
 
+    /// Custom scopes clients can request, on top of the built-in ones
+    #[serde(default)]
+    pub scopes: ScopesConfig,
+
     /// Configuration related to upstream OAuth providers
     #[serde(default)]
     pub upstream_oauth2: UpstreamOAuth2Config,
 
+    /// Issuers trusted to sign assertions for the JWT bearer grant
+    #[serde(default)]
+    pub jwt_bearer: JwtBearerConfig,
+
+    /// Configuration related to the webhooks notified about user lifecycle
+    /// changes
+    #[serde(default)]
+    pub webhooks: WebhooksConfig,
+
+    /// OAuth 2.0 and OpenID Connect capabilities which can be disabled
+    #[serde(default)]
+    pub features: FeaturesConfig,
+
+    /// Clients allowed to embed the pages of the service
+    #[serde(default)]
+    pub embedding: EmbeddingConfig,
+
     /// Experimental configuration options
     #[serde(default)]
     pub experimental: ExperimentalConfig,
@@ -119,6 +219,7 @@ impl ConfigurationSection for RootConfig {
         R: Rng + Send,
     {
         Ok(Self {
+            profile: None,
             clients: ClientsConfig::generate(&mut rng).await?,
             http: HttpConfig::generate(&mut rng).await?,
             database: DatabaseConfig::generate(&mut rng).await?,
@@ -126,27 +227,48 @@ impl ConfigurationSection for RootConfig {
             templates: TemplatesConfig::generate(&mut rng).await?,
             email: EmailConfig::generate(&mut rng).await?,
             passwords: PasswordsConfig::generate(&mut rng).await?,
+            usernames: UsernamesConfig::generate(&mut rng).await?,
+            guests: GuestsConfig::generate(&mut rng).await?,
+            account: AccountConfig::generate(&mut rng).await?,
+            rate_limiting: RateLimitingConfig::generate(&mut rng).await?,
+            ip_filter: IpFilterConfig::generate(&mut rng).await?,
             secrets: SecretsConfig::generate(&mut rng).await?,
             matrix: MatrixConfig::generate(&mut rng).await?,
             policy: PolicyConfig::generate(&mut rng).await?,
+            scopes: ScopesConfig::generate(&mut rng).await?,
             upstream_oauth2: UpstreamOAuth2Config::generate(&mut rng).await?,
+            jwt_bearer: JwtBearerConfig::generate(&mut rng).await?,
+            webhooks: WebhooksConfig::generate(&mut rng).await?,
+            features: FeaturesConfig::generate(&mut rng).await?,
+            embedding: EmbeddingConfig::generate(&mut rng).await?,
             experimental: ExperimentalConfig::generate(&mut rng).await?,
         })
     }
 
     fn test() -> Self {
         Self {
+            profile: None,
             clients: ClientsConfig::test(),
             http: HttpConfig::test(),
             database: DatabaseConfig::test(),
             telemetry: TelemetryConfig::test(),
             templates: TemplatesConfig::test(),
             passwords: PasswordsConfig::test(),
+            usernames: UsernamesConfig::test(),
+            guests: GuestsConfig::test(),
+            account: AccountConfig::test(),
+            rate_limiting: RateLimitingConfig::test(),
+            ip_filter: IpFilterConfig::test(),
             email: EmailConfig::test(),
             secrets: SecretsConfig::test(),
             matrix: MatrixConfig::test(),
             policy: PolicyConfig::test(),
+            scopes: ScopesConfig::test(),
             upstream_oauth2: UpstreamOAuth2Config::test(),
+            jwt_bearer: JwtBearerConfig::test(),
+            webhooks: WebhooksConfig::test(),
+            features: FeaturesConfig::test(),
+            embedding: EmbeddingConfig::test(),
             experimental: ExperimentalConfig::test(),
         }
     }
@@ -173,11 +295,44 @@ pub struct AppConfig {
     #[serde(default)]
     pub passwords: PasswordsConfig,
 
+    #[serde(default)]
+    pub usernames: UsernamesConfig,
+
+    #[serde(default)]
+    pub guests: GuestsConfig,
+
+    #[serde(default)]
+    pub account: AccountConfig,
+
+    #[serde(default)]
+    pub rate_limiting: RateLimitingConfig,
+
+    #[serde(default)]
+    pub ip_filter: IpFilterConfig,
+
     pub matrix: MatrixConfig,
 
     #[serde(default)]
     pub policy: PolicyConfig,
 
+    #[serde(default)]
+    pub scopes: ScopesConfig,
+
+    #[serde(default)]
+    pub upstream_oauth2: UpstreamOAuth2Config,
+
+    #[serde(default)]
+    pub jwt_bearer: JwtBearerConfig,
+
+    #[serde(default)]
+    pub webhooks: WebhooksConfig,
+
+    #[serde(default)]
+    pub features: FeaturesConfig,
+
+    #[serde(default)]
+    pub embedding: EmbeddingConfig,
+
     #[serde(default)]
     pub experimental: ExperimentalConfig,
 }
@@ -198,9 +353,20 @@ impl ConfigurationSection for AppConfig {
             templates: TemplatesConfig::generate(&mut rng).await?,
             email: EmailConfig::generate(&mut rng).await?,
             passwords: PasswordsConfig::generate(&mut rng).await?,
+            usernames: UsernamesConfig::generate(&mut rng).await?,
+            guests: GuestsConfig::generate(&mut rng).await?,
+            account: AccountConfig::generate(&mut rng).await?,
+            rate_limiting: RateLimitingConfig::generate(&mut rng).await?,
+            ip_filter: IpFilterConfig::generate(&mut rng).await?,
             secrets: SecretsConfig::generate(&mut rng).await?,
             matrix: MatrixConfig::generate(&mut rng).await?,
             policy: PolicyConfig::generate(&mut rng).await?,
+            scopes: ScopesConfig::generate(&mut rng).await?,
+            upstream_oauth2: UpstreamOAuth2Config::generate(&mut rng).await?,
+            jwt_bearer: JwtBearerConfig::generate(&mut rng).await?,
+            webhooks: WebhooksConfig::generate(&mut rng).await?,
+            features: FeaturesConfig::generate(&mut rng).await?,
+            embedding: EmbeddingConfig::generate(&mut rng).await?,
             experimental: ExperimentalConfig::generate(&mut rng).await?,
         })
     }
@@ -211,10 +377,21 @@ impl ConfigurationSection for AppConfig {
             database: DatabaseConfig::test(),
             templates: TemplatesConfig::test(),
             passwords: PasswordsConfig::test(),
+            usernames: UsernamesConfig::test(),
+            guests: GuestsConfig::test(),
+            account: AccountConfig::test(),
+            rate_limiting: RateLimitingConfig::test(),
+            ip_filter: IpFilterConfig::test(),
             email: EmailConfig::test(),
             secrets: SecretsConfig::test(),
             matrix: MatrixConfig::test(),
             policy: PolicyConfig::test(),
+            scopes: ScopesConfig::test(),
+            upstream_oauth2: UpstreamOAuth2Config::test(),
+            jwt_bearer: JwtBearerConfig::test(),
+            webhooks: WebhooksConfig::test(),
+            features: FeaturesConfig::test(),
+            embedding: EmbeddingConfig::test(),
             experimental: ExperimentalConfig::test(),
         }
     }
@@ -234,6 +411,9 @@ pub struct SyncConfig {
 
     #[serde(default)]
     pub upstream_oauth2: UpstreamOAuth2Config,
+
+    #[serde(default)]
+    pub jwt_bearer: JwtBearerConfig,
 }
 
 #[async_trait]
@@ -251,6 +431,7 @@ impl ConfigurationSection for SyncConfig {
             secrets: SecretsConfig::generate(&mut rng).await?,
             clients: ClientsConfig::generate(&mut rng).await?,
             upstream_oauth2: UpstreamOAuth2Config::generate(&mut rng).await?,
+            jwt_bearer: JwtBearerConfig::generate(&mut rng).await?,
         })
     }
 
@@ -260,6 +441,7 @@ impl ConfigurationSection for SyncConfig {
             secrets: SecretsConfig::test(),
             clients: ClientsConfig::test(),
             upstream_oauth2: UpstreamOAuth2Config::test(),
+            jwt_bearer: JwtBearerConfig::test(),
         }
     }
 }
@@ -12,12 +12,16 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::{collections::BTreeMap, time::Duration};
+
 use async_trait::async_trait;
 use camino::Utf8PathBuf;
+use mas_jose::jwk::PublicJsonWebKeySet;
 use rand::Rng;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
+use url::Url;
 
 use super::ConfigurationSection;
 
@@ -56,6 +60,142 @@ fn default_email_endpoint() -> String {
     "email/violation".to_owned()
 }
 
+fn default_token_endpoint() -> String {
+    "token/decision".to_owned()
+}
+
+fn default_upstream_login_endpoint() -> String {
+    "upstream_login/violation".to_owned()
+}
+
+fn default_claims_endpoint() -> String {
+    "claims/violation".to_owned()
+}
+
+/// An OPA bundle server to fetch the policy from
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PolicyBundleConfig {
+    /// URL of the bundle, which must be built with `opa build -t wasm`
+    pub url: Url,
+
+    /// Bearer token to authenticate to the bundle server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+
+    /// Keys used to verify the signature of the bundle. If set, bundles which
+    /// are not signed by one of these keys are rejected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verification_keys: Option<PublicJsonWebKeySet>,
+}
+
+/// Rules on the email addresses users can add, for the built-in policy
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct BuiltinEmailPolicyConfig {
+    /// If set, only emails on those domains are allowed. `*` matches a single
+    /// label, like in `*.example.com`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_domains: Vec<String>,
+
+    /// Emails on those domains are rejected
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub banned_domains: Vec<String>,
+}
+
+/// Rules on the passwords of users, for the built-in policy
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct BuiltinPasswordPolicyConfig {
+    /// Minimum length of passwords
+    #[serde(default)]
+    pub min_length: usize,
+
+    /// Require at least one lowercase letter
+    #[serde(default)]
+    pub require_lowercase: bool,
+
+    /// Require at least one uppercase letter
+    #[serde(default)]
+    pub require_uppercase: bool,
+
+    /// Require at least one number
+    #[serde(default)]
+    pub require_number: bool,
+}
+
+/// Rules on the clients registered through dynamic client registration, for
+/// the built-in policy
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct BuiltinClientPolicyConfig {
+    /// If set, the URIs of the clients must be on those hosts. `*` matches a
+    /// single label, like in `*.example.com`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_hosts: Vec<String>,
+
+    /// Allow non-HTTPS and localhost URIs, which is useful in development
+    #[serde(default)]
+    pub allow_insecure_uris: bool,
+}
+
+/// The users who can use a restricted client, for the built-in policy
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct BuiltinRestrictedClientConfig {
+    /// Usernames of the users allowed to use this client
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_users: Vec<String>,
+
+    /// Also allow users who can request admin access
+    #[serde(default)]
+    pub allow_admins: bool,
+}
+
+/// Scope restrictions applied every time a token is issued, for the built-in
+/// policy
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct BuiltinTokenPolicyConfig {
+    /// Limit some clients to a set of scopes, keyed by client ID
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub client_scopes: BTreeMap<String, Vec<String>>,
+
+    /// Strip some scopes for some grant types, keyed by grant type:
+    /// `authorization_code`, `client_credentials`, `refresh_token` or
+    /// `jwt_bearer`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub stripped_scopes: BTreeMap<String, Vec<String>>,
+}
+
+/// Declarative rules enforced without an OPA policy
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct BuiltinPolicyConfig {
+    /// Rules on email addresses
+    #[serde(default)]
+    pub emails: BuiltinEmailPolicyConfig,
+
+    /// Rules on passwords
+    #[serde(default)]
+    pub passwords: BuiltinPasswordPolicyConfig,
+
+    /// Rules on dynamically registered clients
+    #[serde(default)]
+    pub clients: BuiltinClientPolicyConfig,
+
+    /// Usernames of the users who can request admin scopes
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub admin_users: Vec<String>,
+
+    /// IDs of the clients which can get the `urn:mas:admin` scope with the
+    /// client credentials grant
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub admin_clients: Vec<String>,
+
+    /// Clients restricted to a subset of users, keyed by client ID
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub restricted_clients: BTreeMap<String, BuiltinRestrictedClientConfig>,
+
+    /// Scope restrictions applied every time a token is issued
+    #[serde(default)]
+    pub token: BuiltinTokenPolicyConfig,
+}
+
 /// Application secrets
 #[serde_as]
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
@@ -65,6 +205,30 @@ pub struct PolicyConfig {
     #[schemars(with = "String")]
     pub wasm_module: Utf8PathBuf,
 
+    /// URL to fetch the WASM module from, instead of reading it from
+    /// `wasm_module`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wasm_module_url: Option<Url>,
+
+    /// Fetch the policy from an OPA bundle server, instead of reading the WASM
+    /// module from `wasm_module` or `wasm_module_url`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bundle: Option<PolicyBundleConfig>,
+
+    /// Enforce a built-in set of rules instead of evaluating an OPA policy.
+    /// When set, the WASM module, its entrypoints and `data` are ignored.
+    /// Username rules are taken from the `usernames` section
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub builtin: Option<BuiltinPolicyConfig>,
+
+    /// How often to check the WASM module or bundle for changes, in seconds.
+    /// The module is reloaded without restarting the service when it changes.
+    /// Disabled by default
+    #[schemars(with = "Option<u64>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<u64>>")]
+    pub reload_interval: Option<Duration>,
+
     /// Entrypoint to use when evaluating client registrations
     #[serde(default = "default_client_registration_endpoint")]
     pub client_registration_entrypoint: String,
@@ -85,21 +249,55 @@ pub struct PolicyConfig {
     #[serde(default = "default_email_endpoint")]
     pub email_entrypoint: String,
 
+    /// Entrypoint to use when issuing tokens
+    #[serde(default = "default_token_endpoint")]
+    pub token_entrypoint: String,
+
+    /// Entrypoint to use when a user logs in through an upstream provider
+    #[serde(default = "default_upstream_login_endpoint")]
+    pub upstream_login_entrypoint: String,
+
+    /// Entrypoint to use when mapping user attributes to the claims of ID
+    /// tokens and userinfo responses
+    #[serde(default = "default_claims_endpoint")]
+    pub claims_entrypoint: String,
+
     /// Arbitrary data to pass to the policy
     #[serde(default)]
     pub data: Option<serde_json::Value>,
+
+    /// Path to a GeoIP database in the MaxMind DB format, used to look up the
+    /// country of the client passed to the policy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
+    pub geoip_database: Option<Utf8PathBuf>,
+
+    /// Fraction of the policy evaluations, between 0 and 1, for which the full
+    /// input is logged, to debug surprising decisions. Passwords are always
+    /// redacted. Disabled by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_inputs_sample_rate: Option<f64>,
 }
 
 impl Default for PolicyConfig {
     fn default() -> Self {
         Self {
             wasm_module: default_policy_path(),
+            wasm_module_url: None,
+            bundle: None,
+            builtin: None,
+            reload_interval: None,
             client_registration_entrypoint: default_client_registration_endpoint(),
             register_entrypoint: default_register_endpoint(),
             authorization_grant_entrypoint: default_authorization_grant_endpoint(),
             password_entrypoint: default_password_endpoint(),
             email_entrypoint: default_email_endpoint(),
+            token_entrypoint: default_token_endpoint(),
+            upstream_login_entrypoint: default_upstream_login_endpoint(),
+            claims_entrypoint: default_claims_endpoint(),
             data: None,
+            geoip_database: None,
+            log_inputs_sample_rate: None,
         }
     }
 }
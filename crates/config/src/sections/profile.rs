@@ -0,0 +1,83 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// Presets tuning the database pool, the rate limits and the caches together
+/// for the size of the deployment.
+///
+/// Each setting of the preset can still be overridden in the configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DeploymentProfile {
+    /// A single instance on a small machine, serving a handful of users
+    Small,
+
+    /// A single instance serving up to a few thousand users, which the
+    /// defaults are tuned for
+    Standard,
+
+    /// Several instances serving hundreds of thousands of users, many of them
+    /// sharing IP addresses
+    Large,
+}
+
+impl DeploymentProfile {
+    /// The configuration values set by the preset, used as defaults for the
+    /// configuration files
+    #[must_use]
+    pub fn preset(self) -> serde_json::Value {
+        match self {
+            Self::Small => json!({
+                "database": {
+                    "max_connections": 3,
+                    "min_connections": 0,
+                },
+                "rate_limiting": {
+                    "token": {
+                        "per_ip": { "burst": 30, "per_second": 0.5 },
+                        "per_client": { "burst": 60, "per_second": 1.0 },
+                    },
+                },
+            }),
+
+            // The defaults are tuned for this size of deployment
+            Self::Standard => json!({}),
+
+            Self::Large => json!({
+                "database": {
+                    "max_connections": 50,
+                    "min_connections": 10,
+                },
+                "rate_limiting": {
+                    "login": {
+                        "per_ip": { "burst": 30, "per_second": 30.0 / 60.0 },
+                    },
+                    "registration": {
+                        "per_ip": { "burst": 10, "per_second": 10.0 / 3600.0 },
+                    },
+                    "token": {
+                        "per_ip": { "burst": 300, "per_second": 5.0 },
+                        "per_client": { "burst": 3000, "per_second": 50.0 },
+                    },
+                },
+                "experimental": {
+                    "introspection_cache_ttl": 30,
+                },
+            }),
+        }
+    }
+}
@@ -0,0 +1,330 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::num::NonZeroU32;
+
+use async_trait::async_trait;
+use rand::Rng;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+use super::ConfigurationSection;
+
+/// Parameters of a token bucket
+///
+/// The bucket holds at most `burst` requests, and is refilled at a rate of
+/// `per_second` requests per second.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RateLimiterConfiguration {
+    /// Maximum number of requests allowed in a burst
+    pub burst: NonZeroU32,
+
+    /// Number of requests regained every second. Can be a fraction, e.g. `0.1`
+    /// for one request every ten seconds. Must be positive
+    #[schemars(range(min = 0.0))]
+    pub per_second: f64,
+}
+
+impl RateLimiterConfiguration {
+    fn new(burst: u32, per_second: f64) -> Self {
+        let burst = NonZeroU32::new(burst).expect("burst must not be zero");
+        Self { burst, per_second }
+    }
+}
+
+/// Progressive delay applied to login attempts from IP addresses which
+/// recently failed to log in, slowing down password guessing without having to
+/// rely on a CAPTCHA
+#[serde_as]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct LoginFailureDelayConfig {
+    /// Number of failed attempts allowed before the next ones are delayed.
+    /// Default is 3
+    #[serde(default = "default_free_attempts")]
+    pub free_attempts: u32,
+
+    /// Delay applied once the free attempts are exhausted, in milliseconds. It
+    /// doubles with every subsequent failure. Default is 1000
+    #[schemars(with = "u64")]
+    #[serde(default = "default_base_delay")]
+    #[serde_as(as = "serde_with::DurationMilliSeconds<u64>")]
+    pub base_delay: std::time::Duration,
+
+    /// Maximum delay applied to a login attempt, in milliseconds. Default is
+    /// 30000
+    #[schemars(with = "u64")]
+    #[serde(default = "default_max_delay")]
+    #[serde_as(as = "serde_with::DurationMilliSeconds<u64>")]
+    pub max_delay: std::time::Duration,
+
+    /// How long failed attempts are remembered after the last one, in seconds.
+    /// Default is 3600
+    #[schemars(with = "u64")]
+    #[serde(default = "default_failure_window")]
+    #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+    pub window: chrono::Duration,
+}
+
+impl Default for LoginFailureDelayConfig {
+    fn default() -> Self {
+        Self {
+            free_attempts: default_free_attempts(),
+            base_delay: default_base_delay(),
+            max_delay: default_max_delay(),
+            window: default_failure_window(),
+        }
+    }
+}
+
+/// Rate limits applied to the login forms and APIs
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct LoginRateLimitingConfig {
+    /// Limit of login attempts from a single IP address
+    #[serde(default = "default_login_per_ip")]
+    pub per_ip: RateLimiterConfiguration,
+
+    /// Limit of login attempts on a single account, regardless of where they
+    /// come from
+    #[serde(default = "default_login_per_account")]
+    pub per_account: RateLimiterConfiguration,
+
+    /// Delay login attempts from IP addresses which recently failed to log in
+    /// too many times. Disabled by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure_delay: Option<LoginFailureDelayConfig>,
+}
+
+impl Default for LoginRateLimitingConfig {
+    fn default() -> Self {
+        Self {
+            per_ip: default_login_per_ip(),
+            per_account: default_login_per_account(),
+            failure_delay: None,
+        }
+    }
+}
+
+/// Rate limits applied to the registration form
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RegistrationRateLimitingConfig {
+    /// Limit of registrations from a single IP address
+    #[serde(default = "default_registration_per_ip")]
+    pub per_ip: RateLimiterConfiguration,
+}
+
+impl Default for RegistrationRateLimitingConfig {
+    fn default() -> Self {
+        Self {
+            per_ip: default_registration_per_ip(),
+        }
+    }
+}
+
+/// Rate limits applied to the OAuth 2.0 token endpoint
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TokenRateLimitingConfig {
+    /// Limit of token requests from a single IP address
+    #[serde(default = "default_token_per_ip")]
+    pub per_ip: RateLimiterConfiguration,
+
+    /// Limit of token requests made by a single client from a single IP
+    /// address, checked once the client is authenticated
+    #[serde(default = "default_token_per_client")]
+    pub per_client: RateLimiterConfiguration,
+}
+
+impl Default for TokenRateLimitingConfig {
+    fn default() -> Self {
+        Self {
+            per_ip: default_token_per_ip(),
+            per_client: default_token_per_client(),
+        }
+    }
+}
+
+/// Where the state of the rate limiters is kept
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitingBackend {
+    /// In memory, each instance enforcing the limits separately
+    #[default]
+    Memory,
+
+    /// In an unlogged table of the database, shared between all the instances
+    Postgres,
+}
+
+/// Configuration of the rate limits applied to the sensitive endpoints
+///
+/// Requests over the limits are rejected with a `429 Too Many Requests`
+/// response.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RateLimitingConfig {
+    /// Whether the rate limits are enforced. Defaults to `true`
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Where the state of the rate limiters is kept. Defaults to `memory`,
+    /// which means each instance enforces the limits separately. Use
+    /// `postgres` when running multiple instances.
+    #[serde(default)]
+    pub backend: RateLimitingBackend,
+
+    /// Rate limits of the login forms and APIs
+    #[serde(default)]
+    pub login: LoginRateLimitingConfig,
+
+    /// Rate limits of the registration form
+    #[serde(default)]
+    pub registration: RegistrationRateLimitingConfig,
+
+    /// Rate limits of the OAuth 2.0 token endpoint
+    #[serde(default)]
+    pub token: TokenRateLimitingConfig,
+}
+
+impl Default for RateLimitingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_true(),
+            backend: RateLimitingBackend::default(),
+            login: LoginRateLimitingConfig::default(),
+            registration: RegistrationRateLimitingConfig::default(),
+            token: TokenRateLimitingConfig::default(),
+        }
+    }
+}
+
+const fn default_true() -> bool {
+    true
+}
+
+fn default_login_per_ip() -> RateLimiterConfiguration {
+    RateLimiterConfiguration::new(30, 30.0 / 60.0)
+}
+
+fn default_login_per_account() -> RateLimiterConfiguration {
+    RateLimiterConfiguration::new(1800, 1800.0 / 3600.0)
+}
+
+const fn default_free_attempts() -> u32 {
+    3
+}
+
+const fn default_base_delay() -> std::time::Duration {
+    std::time::Duration::from_secs(1)
+}
+
+const fn default_max_delay() -> std::time::Duration {
+    std::time::Duration::from_secs(30)
+}
+
+fn default_failure_window() -> chrono::Duration {
+    chrono::Duration::hours(1)
+}
+
+fn default_registration_per_ip() -> RateLimiterConfiguration {
+    RateLimiterConfiguration::new(10, 10.0 / 3600.0)
+}
+
+fn default_token_per_ip() -> RateLimiterConfiguration {
+    RateLimiterConfiguration::new(300, 5.0)
+}
+
+fn default_token_per_client() -> RateLimiterConfiguration {
+    RateLimiterConfiguration::new(3000, 50.0)
+}
+
+#[async_trait]
+impl ConfigurationSection for RateLimitingConfig {
+    fn path() -> &'static str {
+        "rate_limiting"
+    }
+
+    async fn generate<R>(_rng: R) -> anyhow::Result<Self>
+    where
+        R: Rng + Send,
+    {
+        Ok(Self::default())
+    }
+
+    fn test() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::Jail;
+
+    use super::*;
+
+    #[test]
+    fn load_config() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r#"
+                    rate_limiting:
+                      backend: postgres
+                      login:
+                        per_ip:
+                          burst: 10
+                          per_second: 0.5
+                        failure_delay:
+                          base_delay: 500
+                "#,
+            )?;
+
+            let config = RateLimitingConfig::load_from_file("config.yaml")?;
+
+            assert!(config.enabled);
+            assert_eq!(config.backend, RateLimitingBackend::Postgres);
+            assert_eq!(config.login.per_ip.burst.get(), 10);
+            assert!((config.login.per_ip.per_second - 0.5).abs() < f64::EPSILON);
+            assert_eq!(config.login.per_account, default_login_per_account());
+            let failure_delay = config.login.failure_delay.unwrap();
+            assert_eq!(failure_delay.free_attempts, 3);
+            assert_eq!(
+                failure_delay.base_delay,
+                std::time::Duration::from_millis(500)
+            );
+            assert_eq!(failure_delay.window, chrono::Duration::hours(1));
+            assert_eq!(config.token.per_client, default_token_per_client());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn load_disabled_config() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    rate_limiting:
+                      enabled: false
+                ",
+            )?;
+
+            let config = RateLimitingConfig::load_from_file("config.yaml")?;
+
+            assert!(!config.enabled);
+            assert_eq!(config.login.per_ip, default_login_per_ip());
+
+            Ok(())
+        });
+    }
+}
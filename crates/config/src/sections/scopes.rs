@@ -0,0 +1,177 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use rand::Rng;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+
+use super::ConfigurationSection;
+
+/// How much access to the account of a user a scope grants
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ScopeRisk {
+    /// Gives access to basic information about the user
+    #[default]
+    Low,
+
+    /// Gives access to the data of the user, or lets the client act on their
+    /// behalf
+    Medium,
+
+    /// Gives administrative access. Those scopes are highlighted on the
+    /// consent screen
+    High,
+}
+
+/// A custom scope clients can request
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CustomScopeConfig {
+    /// The scope token, as requested by clients
+    pub name: String,
+
+    /// Short human-readable name of the scope, shown to users on the consent
+    /// screen
+    pub display_name: String,
+
+    /// What the scope gives access to, shown to users on the consent screen
+    pub description: Option<String>,
+
+    /// How much access to the account the scope grants. Defaults to `low`
+    #[serde(default)]
+    pub risk: ScopeRisk,
+}
+
+/// An attribute of the users which can be mapped to a claim
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum UserAttribute {
+    /// The display name of the user, as set on the homeserver
+    DisplayName,
+
+    /// The primary email address of the user
+    Email,
+
+    /// Whether the user can request admin access, as a boolean
+    IsAdmin,
+
+    /// The groups imported from the upstream provider the user last logged in
+    /// with, as a list of strings
+    Groups,
+}
+
+/// Maps an attribute of the users to a claim of the ID tokens and userinfo
+/// responses
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ClaimMappingConfig {
+    /// The name of the claim
+    pub claim: String,
+
+    /// The attribute the claim is set to
+    pub attribute: UserAttribute,
+
+    /// Only set the claim when the session has this scope. If not set, the
+    /// claim is set for all the sessions with the `openid` scope
+    pub scope: Option<String>,
+}
+
+/// Configuration of the scopes clients can request
+///
+/// The built-in scopes are always defined. Clients can't register with, nor
+/// request, scopes which are not defined.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct ScopesConfig {
+    /// Scopes defined on top of the built-in ones
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub custom: Vec<CustomScopeConfig>,
+
+    /// Attributes of the users mapped to the claims of the ID tokens and
+    /// userinfo responses of all clients
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub claims: Vec<ClaimMappingConfig>,
+}
+
+#[async_trait]
+impl ConfigurationSection for ScopesConfig {
+    fn path() -> &'static str {
+        "scopes"
+    }
+
+    async fn generate<R>(_rng: R) -> anyhow::Result<Self>
+    where
+        R: Rng + Send,
+    {
+        Ok(Self::default())
+    }
+
+    fn test() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::Jail;
+
+    use super::*;
+
+    #[test]
+    fn load_config() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r#"
+                    scopes:
+                      custom:
+                        - name: "urn:example:read"
+                          display_name: Read your data on example.com
+                        - name: "urn:example:admin"
+                          display_name: Administer example.com
+                          description: Change the settings of example.com
+                          risk: high
+                      claims:
+                        - claim: name
+                          attribute: display_name
+                        - claim: groups
+                          attribute: groups
+                          scope: "urn:example:read"
+                "#,
+            )?;
+
+            let config = ScopesConfig::load_from_file("config.yaml")?;
+
+            assert_eq!(config.custom.len(), 2);
+            assert_eq!(config.custom[0].name, "urn:example:read");
+            assert_eq!(config.custom[0].description, None);
+            assert_eq!(config.custom[0].risk, ScopeRisk::Low);
+            assert_eq!(
+                config.custom[1].description.as_deref(),
+                Some("Change the settings of example.com")
+            );
+            assert_eq!(config.custom[1].risk, ScopeRisk::High);
+
+            assert_eq!(config.claims.len(), 2);
+            assert_eq!(config.claims[0].attribute, UserAttribute::DisplayName);
+            assert_eq!(config.claims[0].scope, None);
+            assert_eq!(config.claims[1].claim, "groups");
+            assert_eq!(config.claims[1].scope.as_deref(), Some("urn:example:read"));
+
+            Ok(())
+        });
+    }
+}
@@ -12,7 +12,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::borrow::Cow;
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    time::Duration,
+};
 
 use anyhow::Context;
 use async_trait::async_trait;
@@ -28,6 +33,8 @@ use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
 use tokio::task;
 use tracing::info;
+use ulid::Ulid;
+use url::Url;
 
 use super::ConfigurationSection;
 
@@ -51,10 +58,28 @@ pub enum PasswordOrFile {
     PasswordFile(Utf8PathBuf),
 }
 
+/// What a private key is used for
+#[derive(JsonSchema, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyUse {
+    /// Signing tokens
+    #[default]
+    Sig,
+
+    /// Decrypting the tokens encrypted for us, like the ID tokens of upstream
+    /// providers. Only RSA keys can be used for this
+    Enc,
+}
+
 #[derive(JsonSchema, Serialize, Deserialize, Clone, Debug)]
 pub struct KeyConfig {
     kid: String,
 
+    /// What the key is used for. Keys are either used for signing or for
+    /// decryption, never both. Defaults to `sig`
+    #[serde(default, rename = "use")]
+    use_: KeyUse,
+
     #[serde(flatten)]
     password: Option<PasswordOrFile>,
 
@@ -62,25 +87,190 @@ pub struct KeyConfig {
     key: KeyOrFile,
 }
 
+/// A versioned encryption key
+#[serde_as]
+#[derive(JsonSchema, Serialize, Deserialize, Clone, Debug)]
+pub struct EncryptionKeyConfig {
+    /// Identifier of the key, stored along the data encrypted with it. It
+    /// can't contain a `:`
+    pub kid: String,
+
+    /// The 32-byte long hex-encoded key
+    #[schemars(
+        with = "String",
+        regex(pattern = r"[0-9a-fA-F]{64}"),
+        example = "example_secret"
+    )]
+    #[serde_as(as = "serde_with::hex::Hex")]
+    pub key: [u8; 32],
+
+    /// Only use this key to decrypt data, never to encrypt it. This lets a new
+    /// key be rolled out to all the instances of the service before any of
+    /// them starts encrypting with it
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub decrypt_only: bool,
+}
+
+impl EncryptionKeyConfig {
+    fn validate_kid(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            !self.kid.is_empty() && !self.kid.contains(':'),
+            "invalid encryption key ID {:?}",
+            self.kid
+        );
+        Ok(())
+    }
+}
+
 /// Application secrets
 #[serde_as]
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct SecretsConfig {
-    /// Encryption key for secure cookies
+    /// Encryption key for secure cookies and database fields. It can be left
+    /// out if it is fetched from Vault, or if `encryption_keys` are set
     #[schemars(
         with = "String",
         regex(pattern = r"[0-9a-fA-F]{64}"),
         example = "example_secret"
     )]
-    #[serde_as(as = "serde_with::hex::Hex")]
-    pub encryption: [u8; 32],
+    #[serde_as(as = "Option<serde_with::hex::Hex>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<[u8; 32]>,
+
+    /// Versioned keys used to encrypt database fields, the newest first. The
+    /// first one which isn't `decrypt_only` is used to encrypt new data, the
+    /// others are only used to decrypt data encrypted with them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub encryption_keys: Vec<EncryptionKeyConfig>,
 
     /// List of private keys to use for signing and encrypting payloads
     #[serde(default)]
     keys: Vec<KeyConfig>,
+
+    /// Fetch additional secrets from HashiCorp Vault at startup
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vault: Option<VaultConfig>,
+}
+
+fn default_vault_mount() -> String {
+    "secret".to_owned()
+}
+
+/// Connection to the KV version 2 secrets engine of a HashiCorp Vault server,
+/// from which secrets are fetched at startup, and refreshed while the server
+/// runs
+#[serde_as]
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+pub struct VaultConfig {
+    /// Base URL of the Vault server
+    pub address: Url,
+
+    /// Token used to authenticate to Vault. It can be read from a file by
+    /// setting `token_file` instead
+    pub token: String,
+
+    /// Vault Enterprise namespace
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+
+    /// Path where the KV secrets engine is mounted
+    #[serde(default = "default_vault_mount")]
+    pub mount: String,
+
+    /// Path of the secret in the secrets engine
+    pub path: String,
+
+    /// How often to fetch the secret again, in seconds, if Vault doesn't give
+    /// it a lease. When it does, the secret is fetched again before its lease
+    /// expires. Disabled by default
+    #[schemars(with = "Option<u64>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<u64>>")]
+    pub refresh_interval: Option<Duration>,
+}
+
+impl fmt::Debug for VaultConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VaultConfig")
+            .field("address", &self.address)
+            .field("namespace", &self.namespace)
+            .field("mount", &self.mount)
+            .field("path", &self.path)
+            .field("refresh_interval", &self.refresh_interval)
+            .finish_non_exhaustive()
+    }
+}
+
+impl VaultConfig {
+    /// The URL to read the secret from
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the mount or the path make an invalid URL
+    pub fn secret_url(&self) -> Result<Url, url::ParseError> {
+        let mount = self.mount.trim_matches('/');
+        let path = self.path.trim_matches('/');
+        self.address.join(&format!("v1/{mount}/data/{path}"))
+    }
+}
+
+/// Secrets fetched from an external secret manager, which complete the ones
+/// set in the configuration
+#[serde_as]
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ExternalSecrets {
+    /// Encryption key, replacing the one set in the configuration
+    #[serde_as(as = "Option<serde_with::hex::Hex>")]
+    #[serde(default)]
+    pub encryption: Option<[u8; 32]>,
+
+    /// Versioned encryption keys, replacing the ones set in the configuration
+    #[serde(default)]
+    pub encryption_keys: Vec<EncryptionKeyConfig>,
+
+    /// Private keys, added to the ones set in the configuration
+    #[serde(default)]
+    keys: Vec<KeyConfig>,
+
+    /// Client secrets of the upstream OAuth 2.0 providers, by provider ID
+    #[serde(default)]
+    pub upstream_oauth2: BTreeMap<Ulid, String>,
 }
 
 impl SecretsConfig {
+    /// Complete the configuration with secrets fetched from an external
+    /// secret manager
+    pub fn apply_external_secrets(&mut self, secrets: &ExternalSecrets) {
+        if let Some(encryption) = secrets.encryption {
+            self.encryption = Some(encryption);
+        }
+
+        if !secrets.encryption_keys.is_empty() {
+            self.encryption_keys.clone_from(&secrets.encryption_keys);
+        }
+
+        self.keys.extend(secrets.keys.iter().cloned());
+    }
+
+    /// The key used for secure cookies: the `encryption` key, or the newest
+    /// of the `encryption_keys` which isn't `decrypt_only` if it is not set
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no key was set, neither in the configuration nor in
+    /// the external secret manager
+    pub fn encryption(&self) -> anyhow::Result<&[u8; 32]> {
+        self.encryption
+            .as_ref()
+            .or_else(|| self.current_encryption_key().map(|key| &key.key))
+            .context("the `secrets.encryption` key is not set")
+    }
+
+    /// The newest of the `encryption_keys` which isn't `decrypt_only`
+    fn current_encryption_key(&self) -> Option<&EncryptionKeyConfig> {
+        self.encryption_keys.iter().find(|key| !key.decrypt_only)
+    }
+
     /// Derive a signing and verifying keystore out of the config
     ///
     /// # Errors
@@ -120,9 +310,21 @@ impl SecretsConfig {
                 }
             };
 
+            let use_ = match item.use_ {
+                KeyUse::Sig => mas_iana::jose::JsonWebKeyUse::Sig,
+                KeyUse::Enc => {
+                    anyhow::ensure!(
+                        matches!(key, PrivateKey::Rsa(_)),
+                        "key {:?} is used for encryption, but is not an RSA key",
+                        item.kid
+                    );
+                    mas_iana::jose::JsonWebKeyUse::Enc
+                }
+            };
+
             let key = JsonWebKey::new(key)
                 .with_kid(item.kid.clone())
-                .with_use(mas_iana::jose::JsonWebKeyUse::Sig);
+                .with_use(use_);
             keys.push(key);
         }
 
@@ -131,9 +333,48 @@ impl SecretsConfig {
     }
 
     /// Derive an [`Encrypter`] out of the config
-    #[must_use]
-    pub fn encrypter(&self) -> Encrypter {
-        Encrypter::new(&self.encryption)
+    ///
+    /// New data is encrypted with the newest of the `encryption_keys` which
+    /// isn't `decrypt_only`, or with the `encryption` key if there are none.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no encryption key is set, or if a key ID is invalid
+    pub fn encrypter(&self) -> anyhow::Result<Encrypter> {
+        let mut kids = BTreeSet::new();
+        for key in &self.encryption_keys {
+            key.validate_kid()?;
+            anyhow::ensure!(
+                kids.insert(key.kid.as_str()),
+                "duplicate encryption key ID {:?}",
+                key.kid
+            );
+        }
+
+        let current = self.current_encryption_key();
+        let mut encrypter = match current {
+            Some(current) => Encrypter::with_kid(&current.kid, &current.key),
+            None => Encrypter::new(
+                self.encryption
+                    .as_ref()
+                    .context("the `secrets.encryption` key is not set")?,
+            ),
+        };
+
+        for key in &self.encryption_keys {
+            if current.is_some_and(|current| current.kid == key.kid) {
+                continue;
+            }
+            encrypter = encrypter.with_previous_key(Some(&key.kid), &key.key);
+        }
+
+        // Data encrypted before the keys were versioned can still be decrypted with
+        // the `encryption` key
+        if let (Some(_), Some(encryption)) = (current, &self.encryption) {
+            encrypter = encrypter.with_previous_key(None, encryption);
+        }
+
+        Ok(encrypter)
     }
 }
 
@@ -215,8 +456,10 @@ impl ConfigurationSection for SecretsConfig {
         };
 
         Ok(Self {
-            encryption: rng.gen(),
+            encryption: Some(rng.gen()),
+            encryption_keys: Vec::new(),
             keys: vec![rsa_key, ec_p256_key, ec_p384_key, ec_k256_key],
+            vault: None,
         })
     }
 
@@ -256,8 +499,139 @@ impl ConfigurationSection for SecretsConfig {
         };
 
         Self {
-            encryption: [0xEA; 32],
+            encryption: Some([0xEA; 32]),
+            encryption_keys: Vec::new(),
             keys: vec![rsa_key, ecdsa_key],
+            vault: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use figment::Jail;
+
+    use super::*;
+
+    #[test]
+    fn load_vault_config() {
+        Jail::expect_with(|jail| {
+            jail.create_file("vault-token", "s.token\n")?;
+            jail.create_file(
+                "config.yaml",
+                r"
+                    secrets:
+                      vault:
+                        address: https://vault.example.com:8200/
+                        token_file: vault-token
+                        path: /mas/production/
+                ",
+            )?;
+
+            let config = SecretsConfig::load_from_file("config.yaml")?;
+
+            assert!(config.encryption().is_err());
+            let vault = config.vault.unwrap();
+            assert_eq!(vault.token, "s.token");
+            assert_eq!(vault.mount, "secret");
+            assert_eq!(vault.refresh_interval, None);
+            assert!(!format!("{vault:?}").contains("s.token"));
+            assert_eq!(
+                vault.secret_url().unwrap().as_str(),
+                "https://vault.example.com:8200/v1/secret/data/mas/production"
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn apply_external_secrets() {
+        let mut config = SecretsConfig {
+            encryption: None,
+            ..SecretsConfig::test()
+        };
+        assert!(config.encryption().is_err());
+
+        let secrets: ExternalSecrets = serde_json::from_value(serde_json::json!({
+            "encryption": "0000111122223333444455556666777788889999aaaabbbbccccddddeeeeffff",
+        }))
+        .unwrap();
+        config.apply_external_secrets(&secrets);
+        assert_eq!(config.encryption().unwrap()[31], 0xFF);
+        assert_eq!(config.keys.len(), 2);
+
+        // Keys are added to the ones in the configuration, and the encryption
+        // key is replaced
+        let secrets = ExternalSecrets {
+            encryption: Some([0x42; 32]),
+            encryption_keys: Vec::new(),
+            keys: SecretsConfig::test().keys,
+            upstream_oauth2: BTreeMap::new(),
+        };
+        config.apply_external_secrets(&secrets);
+        assert_eq!(config.encryption().unwrap(), &[0x42; 32]);
+        assert_eq!(config.keys.len(), 4);
+        assert!(config.encryption_keys.is_empty());
+
+        // Versioned encryption keys replace the ones in the configuration
+        config.encryption_keys = vec![EncryptionKeyConfig {
+            kid: "old".to_owned(),
+            key: [0x01; 32],
+            decrypt_only: false,
+        }];
+        let secrets: ExternalSecrets = serde_json::from_value(serde_json::json!({
+            "encryption_keys": [{
+                "kid": "new",
+                "key": "0000111122223333444455556666777788889999aaaabbbbccccddddeeeeffff",
+            }],
+        }))
+        .unwrap();
+        config.apply_external_secrets(&secrets);
+        assert_eq!(config.encryption_keys.len(), 1);
+        assert_eq!(config.encryption_keys[0].kid, "new");
+        assert_eq!(config.encrypter().unwrap().current_kid(), Some("new"));
+    }
+
+    #[test]
+    fn decrypt_only_encryption_keys() {
+        let old = EncryptionKeyConfig {
+            kid: "old".to_owned(),
+            key: [0x01; 32],
+            decrypt_only: false,
+        };
+        let new = EncryptionKeyConfig {
+            kid: "new".to_owned(),
+            key: [0x02; 32],
+            decrypt_only: true,
+        };
+        let mut config = SecretsConfig {
+            encryption: None,
+            encryption_keys: vec![new.clone(), old],
+            ..SecretsConfig::test()
+        };
+
+        // While the new key is decrypt-only, data is still encrypted with the
+        // old one, but the new one can already decrypt
+        let encrypter = config.encrypter().unwrap();
+        assert_eq!(encrypter.current_kid(), Some("old"));
+        assert_eq!(config.encryption().unwrap(), &[0x01; 32]);
+        let encrypted = Encrypter::with_kid(&new.kid, &new.key)
+            .encrypt_to_string(b"hello")
+            .unwrap();
+        assert_eq!(encrypter.decrypt_string(&encrypted).unwrap(), b"hello");
+
+        // Once promoted, it encrypts new data
+        config.encryption_keys[0].decrypt_only = false;
+        assert_eq!(config.encrypter().unwrap().current_kid(), Some("new"));
+        assert_eq!(config.encryption().unwrap(), &[0x02; 32]);
+
+        // With only decrypt-only keys, the unversioned key encrypts
+        config.encryption_keys = vec![new];
+        assert!(config.encrypter().is_err());
+        config.encryption = Some([0x03; 32]);
+        let encrypter = config.encrypter().unwrap();
+        assert_eq!(encrypter.current_kid(), None);
+        assert_eq!(encrypter.decrypt_string(&encrypted).unwrap(), b"hello");
+    }
+}
@@ -12,11 +12,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::BTreeMap;
+
 use async_trait::async_trait;
 use camino::Utf8PathBuf;
 use rand::Rng;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use url::Url;
 
 use super::ConfigurationSection;
 
@@ -65,6 +68,49 @@ fn default_translations_path() -> Utf8PathBuf {
     "./share/translations/".into()
 }
 
+/// Colors of the theme, as hexadecimal `#rrggbb` or `#rgb` values
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Default)]
+pub struct ThemeColorsConfig {
+    /// Color of the primary buttons, links and focused fields
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accent: Option<String>,
+
+    /// Color of the page background
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub background: Option<String>,
+
+    /// Color of the text
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+}
+
+/// Cosmetic customisation of the pages rendered by the service
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Default)]
+pub struct ThemeConfig {
+    /// URL of a logo shown at the top of every page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logo_uri: Option<Url>,
+
+    /// URL of the favicon
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub favicon_uri: Option<Url>,
+
+    /// Colors used in light mode
+    #[serde(default)]
+    pub colors: ThemeColorsConfig,
+
+    /// Colors used in dark mode
+    ///
+    /// Defaults to variants derived from the light mode colors
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dark_colors: Option<ThemeColorsConfig>,
+
+    /// Path to a CSS file appended to the theme stylesheet
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
+    pub extra_css_path: Option<Utf8PathBuf>,
+}
+
 /// Configuration related to templates
 #[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct TemplatesConfig {
@@ -82,6 +128,37 @@ pub struct TemplatesConfig {
     #[serde(default = "default_translations_path")]
     #[schemars(with = "Option<String>")]
     pub translations_path: Utf8PathBuf,
+
+    /// Path to a folder holding extra translations, like community translations
+    ///
+    /// They are loaded on top of the ones in `translations_path`, one JSON
+    /// file per locale. They can add locales, or replace some messages of the
+    /// existing ones.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
+    pub extra_translations_path: Option<Utf8PathBuf>,
+
+    /// Path to a folder holding overrides for the email templates
+    ///
+    /// Files in this folder replace the built-in templates under `emails/`
+    /// with the same name, e.g. `verification.html`, `verification.txt` and
+    /// `verification.subject`. MJML templates have to be compiled to HTML
+    /// beforehand.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
+    pub email_overrides_path: Option<Utf8PathBuf>,
+
+    /// Logo, favicon, colors and extra CSS of the pages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub theme: Option<ThemeConfig>,
+
+    /// Arbitrary values exposed to all templates through the `custom`
+    /// function, like support URLs, announcement banners or footer links
+    ///
+    /// Templates referencing a key which is not set here fail to render,
+    /// unless they give a default value.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub custom_context: BTreeMap<String, serde_json::Value>,
 }
 
 impl Default for TemplatesConfig {
@@ -90,6 +167,10 @@ impl Default for TemplatesConfig {
             path: default_path(),
             assets_manifest: default_assets_path(),
             translations_path: default_translations_path(),
+            extra_translations_path: None,
+            email_overrides_path: None,
+            theme: None,
+            custom_context: BTreeMap::new(),
         }
     }
 }
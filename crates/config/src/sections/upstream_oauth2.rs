@@ -12,8 +12,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::ops::Deref;
+use std::{collections::BTreeMap, num::NonZeroU32, ops::Deref};
 
+use anyhow::Context;
 use async_trait::async_trait;
 use mas_iana::{jose::JsonWebSignatureAlg, oauth::OAuthClientAuthenticationMethod};
 use rand::Rng;
@@ -21,8 +22,9 @@ use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
 use ulid::Ulid;
+use url::Url;
 
-use crate::ConfigurationSection;
+use crate::{ConfigurationSection, ExternalSecrets};
 
 /// Upstream OAuth 2.0 providers configuration
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Default)]
@@ -31,6 +33,43 @@ pub struct UpstreamOAuth2Config {
     pub providers: Vec<Provider>,
 }
 
+impl UpstreamOAuth2Config {
+    /// Set the client secrets fetched from an external secret manager on the
+    /// providers
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a secret is set for a provider which doesn't exist
+    /// or doesn't authenticate with a client secret
+    pub fn apply_external_secrets(&mut self, secrets: &ExternalSecrets) -> anyhow::Result<()> {
+        for (id, client_secret) in &secrets.upstream_oauth2 {
+            let provider = self
+                .providers
+                .iter_mut()
+                .find(|provider| provider.id == *id)
+                .with_context(|| format!("no upstream provider with ID {id}"))?;
+
+            match &mut provider.token_auth_method {
+                TokenAuthMethod::ClientSecretBasic {
+                    client_secret: slot,
+                }
+                | TokenAuthMethod::ClientSecretPost {
+                    client_secret: slot,
+                }
+                | TokenAuthMethod::ClientSecretJwt {
+                    client_secret: slot,
+                    ..
+                } => *slot = Some(client_secret.clone()),
+                TokenAuthMethod::None | TokenAuthMethod::PrivateKeyJwt { .. } => {
+                    anyhow::bail!("upstream provider {id} doesn't use a client secret")
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
 #[async_trait]
 impl ConfigurationSection for UpstreamOAuth2Config {
     fn path() -> &'static str {
@@ -58,16 +97,23 @@ pub enum TokenAuthMethod {
 
     /// `client_secret_basic`: `client_id` and `client_secret` used as basic
     /// authorization credentials
-    ClientSecretBasic { client_secret: String },
+    ClientSecretBasic {
+        #[serde(default)]
+        client_secret: Option<String>,
+    },
 
     /// `client_secret_post`: `client_id` and `client_secret` sent in the
     /// request body
-    ClientSecretPost { client_secret: String },
+    ClientSecretPost {
+        #[serde(default)]
+        client_secret: Option<String>,
+    },
 
     /// `client_secret_basic`: a `client_assertion` sent in the request body and
     /// signed using the `client_secret`
     ClientSecretJwt {
-        client_secret: String,
+        #[serde(default)]
+        client_secret: Option<String>,
         token_endpoint_auth_signing_alg: Option<JsonWebSignatureAlg>,
     },
 
@@ -78,6 +124,116 @@ pub enum TokenAuthMethod {
     },
 }
 
+/// How to register with an upstream provider through dynamic client
+/// registration
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct RegistrationConfig {
+    /// The initial access token given by the provider to allow the
+    /// registration, if it doesn't allow anyone to register
+    #[serde(default)]
+    pub initial_access_token: Option<String>,
+
+    /// A software statement given by the provider, a signed JWT asserting
+    /// metadata about this service
+    #[serde(default)]
+    pub software_statement: Option<String>,
+}
+
+/// How the upstream provider is checked by the background health probe
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct HealthCheckConfig {
+    /// Whether to also check that the token endpoint of the provider is
+    /// reachable. Any HTTP response counts as reachable
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub token_endpoint: bool,
+
+    /// Whether to hide the provider from the login page while its last health
+    /// check failed. It can still be used through direct links
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub hide_when_unhealthy: bool,
+}
+
+/// Whether to use PKCE when talking to the upstream provider
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PkceMethod {
+    /// Use PKCE if the provider advertises support for the `S256` method in its
+    /// discovery document
+    #[default]
+    Auto,
+
+    /// Always use PKCE with the `S256` method, even if the provider doesn't
+    /// advertise support for it
+    Always,
+
+    /// Never use PKCE
+    Never,
+}
+
+impl PkceMethod {
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// The protocol spoken by the upstream provider
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    /// OpenID Connect, with discovery and ID tokens
+    #[default]
+    Oidc,
+
+    /// Plain OAuth 2.0, with manually configured endpoints and the claims
+    /// taken from the userinfo endpoint, e.g. for GitHub
+    #[serde(rename = "oauth2")]
+    OAuth2,
+
+    /// SAML 2.0, with the attributes of the assertion used as claims, e.g. for
+    /// academic identity federations
+    Saml,
+}
+
+impl Protocol {
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Settings of the providers speaking SAML 2.0
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct SamlConfig {
+    /// The URL of the single sign-on service of the identity provider, to
+    /// which authentication requests are sent with the HTTP-Redirect binding
+    pub sso_url: Url,
+
+    /// The certificates the identity provider signs its assertions with,
+    /// PEM-encoded.
+    ///
+    /// Several can be set while the identity provider rolls over its key.
+    pub idp_certificates: Vec<String>,
+
+    /// The format of the name ID to request, e.g.
+    /// `urn:oasis:names:tc:SAML:2.0:nameid-format:persistent`.
+    ///
+    /// If not set, the identity provider chooses it.
+    #[serde(default)]
+    pub name_id_format: Option<String>,
+}
+
+/// How the provider should send the authorization response back
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseMode {
+    /// In the query of the redirect URI
+    Query,
+
+    /// In the body of a `POST` request to the redirect URI
+    FormPost,
+}
+
 /// How to handle a claim
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default, JsonSchema)]
 #[serde(rename_all = "lowercase")]
@@ -102,6 +258,86 @@ pub struct ImportPreference {
     /// How to handle the claim
     #[serde(default)]
     pub action: ImportAction,
+
+    /// A template to derive the value from the claims, instead of using the
+    /// standard claim, e.g. `{{ preferred_username | split('@') | first }}`.
+    ///
+    /// The claims of the ID token and of the userinfo endpoint are available
+    /// as variables. A template rendering to an empty string is treated as a
+    /// missing claim.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+
+    /// What to do when the localpart is forced but already taken
+    #[serde(default)]
+    pub on_conflict: OnConflict,
+}
+
+/// What to do when the localpart is forced but already taken
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OnConflict {
+    /// Fail the registration
+    #[default]
+    Fail,
+
+    /// Add a number to the localpart until it is available, e.g. `john1`
+    AddSuffix,
+
+    /// Let the user choose another username
+    Interactive,
+
+    /// Link the upstream account to the existing account, once the user proved
+    /// they own it with its password or with a code sent to its email address
+    Link,
+}
+
+/// When the claim should be imported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportSync {
+    /// Only import the claim when the user registers
+    #[default]
+    Once,
+
+    /// Import the claim when the user registers, and update the homeserver
+    /// profile every time the user logs in through this provider
+    Always,
+
+    /// Like `always`, but stop updating the homeserver profile once the user
+    /// changed the value themselves
+    UnlessModified,
+}
+
+/// An attribute of the user profile
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ProfileAttribute {
+    /// The display name of the user
+    Displayname,
+
+    /// The avatar of the user
+    AvatarUrl,
+
+    /// The email addresses of the user
+    Email,
+}
+
+/// What should be done with a claim which ends up in the homeserver profile
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default, JsonSchema)]
+pub struct ProfileImportPreference {
+    /// How to handle the claim
+    #[serde(default)]
+    pub action: ImportAction,
+
+    /// When the claim should be imported
+    #[serde(default)]
+    pub sync: ImportSync,
+
+    /// A template to derive the value from the claims, instead of using the
+    /// standard claim, e.g. `{{ given_name }} {{ family_name }}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
 }
 
 /// Should the email address be marked as verified
@@ -130,25 +366,101 @@ pub struct EmailImportPreference {
     /// Should the email address be marked as verified
     #[serde(default)]
     pub set_email_verification: SetEmailVerification,
+
+    /// A template to derive the email address from the claims, instead of
+    /// using the `email` claim, e.g. `{{ upn }}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+
+    /// Automatically link the upstream account to the existing account with
+    /// the same verified email address, instead of asking the user to
+    /// register or link their account.
+    ///
+    /// This only happens if the provider asserts that the `email` claim is
+    /// verified. Only enable this for providers which are trusted to verify
+    /// the email addresses of their users.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub link_existing: bool,
+}
+
+fn default_groups_claim() -> String {
+    "groups".to_owned()
+}
+
+/// How the groups of the user should be imported
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct GroupsImportPreference {
+    /// The claim holding the list of groups or roles of the user
+    #[serde(default = "default_groups_claim")]
+    pub claim: String,
+
+    /// Users in any of those groups are allowed to request admin access.
+    ///
+    /// If set, the admin access of users logging in through this provider
+    /// is granted or revoked based on their groups every time they log in.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub admin_groups: Vec<String>,
+}
+
+impl Default for GroupsImportPreference {
+    fn default() -> Self {
+        Self {
+            claim: default_groups_claim(),
+            admin_groups: Vec::new(),
+        }
+    }
+}
+
+/// How the subject identifying the user should be imported
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default, JsonSchema)]
+pub struct SubjectImportPreference {
+    /// A template to derive the subject from the claims, instead of using the
+    /// `sub` claim, e.g. `{{ id }}` for GitHub.
+    ///
+    /// It must render to a value which is unique and stable for each user of
+    /// the provider.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
 }
 
 /// How claims should be imported
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default, JsonSchema)]
 pub struct ClaimsImports {
+    /// Import the subject identifying the user based on the `sub` claim
+    #[serde(default)]
+    pub subject: Option<SubjectImportPreference>,
+
     /// Import the localpart of the MXID based on the `preferred_username` claim
     #[serde(default)]
     pub localpart: Option<ImportPreference>,
 
     /// Import the displayname of the user based on the `name` claim
     #[serde(default)]
-    pub displayname: Option<ImportPreference>,
+    pub displayname: Option<ProfileImportPreference>,
+
+    /// Import the avatar of the user based on the `picture` claim
+    #[serde(default)]
+    pub avatar_url: Option<ProfileImportPreference>,
 
     /// Import the email address of the user based on the `email` and
     /// `email_verified` claims
     #[serde(default)]
     pub email: Option<EmailImportPreference>,
+
+    /// Import the groups of the user, replacing the previous ones every time
+    /// they log in through this provider.
+    ///
+    /// They are available to the policies as `user.upstream_groups`.
+    #[serde(default)]
+    pub groups: Option<GroupsImportPreference>,
+
+    /// Profile attributes which are managed by this provider, and which users
+    /// linked to it can't change themselves in their account.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub locked_attributes: Vec<ProfileAttribute>,
 }
 
+/// Configuration of a single upstream provider
 #[skip_serializing_none]
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Provider {
@@ -160,21 +472,214 @@ pub struct Provider {
     )]
     pub id: Ulid,
 
-    /// The OIDC issuer URL
+    /// The OIDC issuer URL.
+    ///
+    /// Plain OAuth 2.0 providers aren't discovered, and it only identifies
+    /// them. For SAML 2.0 providers, it is the entity ID of the identity
+    /// provider.
     pub issuer: String,
 
-    /// The client ID to use when authenticating with the provider
-    pub client_id: String,
+    /// The protocol spoken by the provider.
+    ///
+    /// Defaults to `oidc`. Plain `oauth2` providers don't issue ID tokens:
+    /// their `authorization_endpoint`, `token_endpoint` and
+    /// `userinfo_endpoint` must be set, and the claims are taken from the
+    /// userinfo endpoint.
+    ///
+    /// `saml` providers must have a `saml` section, and the claims are taken
+    /// from the attributes of the assertion, under both their name and their
+    /// friendly name. The name ID is available as the `sub` claim.
+    #[serde(default, skip_serializing_if = "Protocol::is_default")]
+    pub protocol: Protocol,
+
+    /// The URL of the authorization endpoint, overriding the one from the
+    /// discovery document
+    #[serde(default)]
+    pub authorization_endpoint: Option<Url>,
+
+    /// The URL of the token endpoint, overriding the one from the discovery
+    /// document
+    #[serde(default)]
+    pub token_endpoint: Option<Url>,
+
+    /// The URL of the userinfo endpoint, overriding the one from the
+    /// discovery document
+    #[serde(default)]
+    pub userinfo_endpoint: Option<Url>,
+
+    /// The URL of the JWKS used to verify the ID tokens, overriding the one
+    /// from the discovery document
+    #[serde(default)]
+    pub jwks_uri: Option<Url>,
+
+    /// Additional endpoints returning JSON documents about the user, queried
+    /// with the access token, e.g. `emails: https://api.github.com/user/emails`.
+    ///
+    /// Their responses are available to the claims templates under their
+    /// name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub additional_userinfo_endpoints: BTreeMap<String, Url>,
+
+    /// Settings of the provider if it speaks SAML 2.0.
+    ///
+    /// The metadata of this service is served under
+    /// `/upstream/saml/{id}/metadata`.
+    #[serde(default)]
+    pub saml: Option<SamlConfig>,
+
+    /// A stable identifier for this provider, used in its callback and logout
+    /// URLs instead of its ID, e.g. `/upstream/callback/staff`,
+    /// `/upstream/backchannel-logout/staff` and
+    /// `/upstream/frontchannel-logout/staff`.
+    ///
+    /// It must be unique, and only contain lowercase letters, digits, `-` and
+    /// `_`. It helps telling apart providers which share the same issuer.
+    #[schemars(regex(pattern = r"^[a-z0-9_-]+$"))]
+    #[serde(default)]
+    pub slug: Option<String>,
+
+    /// The client ID to use when authenticating with the provider.
+    ///
+    /// For SAML 2.0 providers, it is the entity ID of this service, which
+    /// must be the audience of the assertions.
+    ///
+    /// It can be left out, along with the `client_secret`, if `registration`
+    /// is set.
+    #[serde(default)]
+    pub client_id: Option<String>,
+
+    /// Register this service with the provider through dynamic client
+    /// registration, instead of using a client registered by hand.
+    ///
+    /// Run `mas-cli upstream register` to register once the provider is
+    /// synced to the database. The client ID and secret issued by the
+    /// provider are stored encrypted in the database, and are kept up to date
+    /// in the background if the provider lets clients manage their
+    /// registration.
+    #[serde(default)]
+    pub registration: Option<RegistrationConfig>,
 
     /// The scopes to request from the provider
     pub scope: String,
 
+    /// How to authenticate to the token endpoint of the provider
     #[serde(flatten)]
     pub token_auth_method: TokenAuthMethod,
 
     /// How claims should be imported from the `id_token` provided by the
     /// provider
     pub claims_imports: ClaimsImports,
+
+    /// Whether to use PKCE when talking to the provider.
+    ///
+    /// Defaults to `auto`, which uses it if the provider advertises support
+    /// for it. Set it to `always` for providers which require it without
+    /// advertising it.
+    #[serde(default, skip_serializing_if = "PkceMethod::is_default")]
+    pub pkce_method: PkceMethod,
+
+    /// How the provider should send the authorization response back.
+    ///
+    /// If not set, the `response_mode` parameter is not sent, and the provider
+    /// uses its default, which is usually `query`.
+    #[serde(default)]
+    pub response_mode: Option<ResponseMode>,
+
+    /// The `prompt` parameter to send to the provider, as a space-separated
+    /// list of values, e.g. `login` or `select_account`
+    #[serde(default)]
+    pub prompt: Option<String>,
+
+    /// Whether to forward to the provider the `login_hint` given when starting
+    /// the login
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub forward_login_hint: bool,
+
+    /// The `max_age` parameter to send to the provider, in seconds.
+    ///
+    /// The provider asks users to log in again if they authenticated with it
+    /// longer ago than this. Set `prompt` to `login` to ask them every time.
+    #[serde(default)]
+    pub max_age: Option<NonZeroU32>,
+
+    /// How long, in seconds, a login through the provider is trusted.
+    ///
+    /// Once it is older than this, users are sent through the provider again
+    /// with `prompt=none` when they authorize a client, and they are logged
+    /// out if they no longer have a session with the provider.
+    #[serde(default)]
+    pub revalidation_interval: Option<NonZeroU32>,
+
+    /// Additional parameters to add to the authorization requests, e.g.
+    /// `domain_hint` for Azure AD.
+    ///
+    /// They can't override the parameters set by the service itself.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub additional_authorization_parameters: BTreeMap<String, String>,
+
+    /// Whether to query the userinfo endpoint of the provider after the token
+    /// exchange, for providers which don't put all the claims in the ID token.
+    ///
+    /// The claims of the ID token take precedence over the ones returned by
+    /// the userinfo endpoint.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub fetch_userinfo: bool,
+
+    /// Whether to keep the access and refresh tokens issued by the provider,
+    /// so that trusted services can call its APIs on behalf of the users.
+    ///
+    /// They are stored encrypted, and refreshed in the background. Trusted
+    /// services get them through the `/api/upstream/tokens/{provider}/{user}`
+    /// endpoint, with the `urn:mas:upstream_tokens` scope.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub store_tokens: bool,
+
+    /// IDs of the clients allowed to get the tokens stored for this provider.
+    ///
+    /// Only those clients can get the `urn:mas:upstream_tokens` scope, with
+    /// the client credentials grant.
+    #[schemars(with = "Vec<String>")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub token_clients: Vec<Ulid>,
+
+    /// How the provider is checked by the background health probe.
+    ///
+    /// All providers have their discovery document and JWKS fetched every 15
+    /// minutes, and the outcome is shown to admins and exported as metrics.
+    /// This adds a check of the token endpoint, and can hide the provider
+    /// from the login page while it is unhealthy.
+    #[serde(default)]
+    pub health_check: Option<HealthCheckConfig>,
+
+    /// The name of the provider shown to users, e.g. on the login page.
+    /// Defaults to the issuer
+    #[serde(default)]
+    pub human_name: Option<String>,
+
+    /// URL of an icon shown next to the name of the provider on the login page
+    #[serde(default)]
+    pub icon: Option<Url>,
+
+    /// The color of the button of the provider on the login page, as a
+    /// hexadecimal color, e.g. `#1a73e8`
+    #[schemars(regex(pattern = r"^#([0-9a-fA-F]{3}|[0-9a-fA-F]{6})$"))]
+    #[serde(default)]
+    pub brand_color: Option<String>,
+
+    /// Providers are listed by ascending sort order on the login page
+    #[serde(default)]
+    pub sort_order: i32,
+
+    /// Whether to offer this provider as a login option on the login page.
+    ///
+    /// Disable it for providers which users should only link to their
+    /// existing account, or which are only used through direct links.
+    #[serde(default = "default_true")]
+    pub show_on_login: bool,
+}
+
+const fn default_true() -> bool {
+    true
 }
 
 impl Deref for Provider {
@@ -211,7 +716,7 @@ impl TokenAuthMethod {
             TokenAuthMethod::None | TokenAuthMethod::PrivateKeyJwt { .. } => None,
             TokenAuthMethod::ClientSecretBasic { client_secret }
             | TokenAuthMethod::ClientSecretPost { client_secret }
-            | TokenAuthMethod::ClientSecretJwt { client_secret, .. } => Some(client_secret),
+            | TokenAuthMethod::ClientSecretJwt { client_secret, .. } => client_secret.as_deref(),
         }
     }
 
@@ -231,3 +736,69 @@ impl TokenAuthMethod {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use figment::Jail;
+
+    use super::*;
+
+    #[test]
+    fn apply_external_secrets() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    upstream_oauth2:
+                      providers:
+                        - id: 01H8PKNWKKRPCBW4YGH1RWV279
+                          issuer: https://accounts.example.com/
+                          client_id: mas
+                          scope: openid
+                          token_endpoint_auth_method: client_secret_post
+                          claims_imports: {}
+                        - id: 01HFVBY12TMNTYTBV8W921M5FA
+                          issuer: https://id.example.org/
+                          client_id: mas
+                          scope: openid
+                          token_endpoint_auth_method: none
+                          claims_imports: {}
+                ",
+            )?;
+
+            let mut config = UpstreamOAuth2Config::load_from_file("config.yaml")?;
+
+            let secrets: ExternalSecrets = serde_json::from_value(serde_json::json!({
+                "upstream_oauth2": {
+                    "01H8PKNWKKRPCBW4YGH1RWV279": "hunter2",
+                },
+            }))
+            .unwrap();
+            config.apply_external_secrets(&secrets).unwrap();
+            assert!(matches!(
+                &config.providers[0].token_auth_method,
+                TokenAuthMethod::ClientSecretPost { client_secret: Some(secret) }
+                    if secret == "hunter2"
+            ));
+
+            // The provider must exist and use a client secret
+            let secrets: ExternalSecrets = serde_json::from_value(serde_json::json!({
+                "upstream_oauth2": {
+                    "01HFVBY12TMNTYTBV8W921M5FA": "hunter2",
+                },
+            }))
+            .unwrap();
+            assert!(config.apply_external_secrets(&secrets).is_err());
+
+            let secrets: ExternalSecrets = serde_json::from_value(serde_json::json!({
+                "upstream_oauth2": {
+                    "01HFVBY12TMNTYTBV8W921M5FB": "hunter2",
+                },
+            }))
+            .unwrap();
+            assert!(config.apply_external_secrets(&secrets).is_err());
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,127 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use rand::Rng;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::ConfigurationSection;
+
+fn default_pattern() -> String {
+    "^[a-z0-9.=_/-]+$".to_owned()
+}
+
+const fn default_min_length() -> usize {
+    3
+}
+
+const fn default_max_length() -> usize {
+    14
+}
+
+fn default_reserved() -> Vec<String> {
+    vec!["admin".to_owned(), "abuse".to_owned(), "matrix".to_owned()]
+}
+
+/// Rules applied to the localpart of new users, when they register or when
+/// they are provisioned from an upstream provider.
+///
+/// Those rules are passed to the registration policy as `data.usernames`.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct UsernamesConfig {
+    /// Regular expression the localpart must match
+    #[serde(default = "default_pattern")]
+    pub pattern: String,
+
+    /// Minimum length of the localpart
+    #[serde(default = "default_min_length")]
+    pub min_length: usize,
+
+    /// Maximum length of the localpart
+    #[serde(default = "default_max_length")]
+    pub max_length: usize,
+
+    /// Localparts which can't be registered, compared case-insensitively
+    #[serde(default = "default_reserved")]
+    pub reserved: Vec<String>,
+
+    /// Whether to lowercase the localpart before validating and registering it
+    #[serde(default)]
+    pub case_fold: bool,
+}
+
+impl Default for UsernamesConfig {
+    fn default() -> Self {
+        Self {
+            pattern: default_pattern(),
+            min_length: default_min_length(),
+            max_length: default_max_length(),
+            reserved: default_reserved(),
+            case_fold: false,
+        }
+    }
+}
+
+#[async_trait]
+impl ConfigurationSection for UsernamesConfig {
+    fn path() -> &'static str {
+        "usernames"
+    }
+
+    async fn generate<R>(_rng: R) -> anyhow::Result<Self>
+    where
+        R: Rng + Send,
+    {
+        Ok(Self::default())
+    }
+
+    fn test() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::Jail;
+
+    use super::*;
+
+    #[test]
+    fn load_config() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r#"
+                    usernames:
+                      max_length: 32
+                      reserved:
+                        - admin
+                        - support
+                      case_fold: true
+                "#,
+            )?;
+
+            let config = UsernamesConfig::load_from_file("config.yaml")?;
+
+            assert_eq!(config.pattern, default_pattern());
+            assert_eq!(config.min_length, 3);
+            assert_eq!(config.max_length, 32);
+            assert_eq!(config.reserved, vec!["admin", "support"]);
+            assert!(config.case_fold);
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,147 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use rand::Rng;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+use url::Url;
+
+use super::ConfigurationSection;
+
+/// A user lifecycle event which can be sent to webhooks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum WebhookEvent {
+    /// A user was created
+    #[serde(rename = "user.created")]
+    UserCreated,
+
+    /// A user was deactivated
+    #[serde(rename = "user.deactivated")]
+    UserDeactivated,
+
+    /// A user was locked
+    #[serde(rename = "user.locked")]
+    UserLocked,
+
+    /// A user verified one of their email addresses
+    #[serde(rename = "user.email_verified")]
+    UserEmailVerified,
+}
+
+impl WebhookEvent {
+    /// The name of the event, as sent in the webhook payloads
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::UserCreated => "user.created",
+            Self::UserDeactivated => "user.deactivated",
+            Self::UserLocked => "user.locked",
+            Self::UserEmailVerified => "user.email_verified",
+        }
+    }
+}
+
+/// Configuration of a single webhook endpoint
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct WebhookConfig {
+    /// An internal unique identifier for this endpoint
+    #[schemars(
+        with = "String",
+        regex(pattern = r"^[0123456789ABCDEFGHJKMNPQRSTVWXYZ]{26}$"),
+        description = "A ULID as per https://github.com/ulid/spec"
+    )]
+    pub id: Ulid,
+
+    /// The URL to which the events are sent, using a `POST` request
+    pub url: Url,
+
+    /// The secret used to sign the payloads, in the `X-MAS-Signature` header
+    pub secret: String,
+
+    /// The events to send to this endpoint. Defaults to all events.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<WebhookEvent>,
+}
+
+/// Configuration of the webhooks notified about user lifecycle changes
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct WebhooksConfig {
+    /// List of endpoints to notify
+    #[serde(default)]
+    pub endpoints: Vec<WebhookConfig>,
+}
+
+#[async_trait]
+impl ConfigurationSection for WebhooksConfig {
+    fn path() -> &'static str {
+        "webhooks"
+    }
+
+    async fn generate<R>(_rng: R) -> anyhow::Result<Self>
+    where
+        R: Rng + Send,
+    {
+        Ok(Self::default())
+    }
+
+    fn test() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::Jail;
+
+    use super::*;
+
+    #[test]
+    fn load_config() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r#"
+                    webhooks:
+                      endpoints:
+                        - id: 01H8PKNWKKRPCBW4YGH1RWV279
+                          url: https://crm.example.com/hooks/mas
+                          secret: test
+                          events:
+                            - user.created
+                            - user.email_verified
+                        - id: 01H8PKPD1Z2Q4Y4KZC8F0Y6B5T
+                          url: https://lists.example.com/hooks/mas
+                          secret: test
+                "#,
+            )?;
+
+            let config = WebhooksConfig::load_from_file("config.yaml")?;
+
+            assert_eq!(config.endpoints.len(), 2);
+            assert_eq!(
+                config.endpoints[0].id,
+                Ulid::from_string("01H8PKNWKKRPCBW4YGH1RWV279").unwrap()
+            );
+            assert_eq!(
+                config.endpoints[0].events,
+                vec![WebhookEvent::UserCreated, WebhookEvent::UserEmailVerified]
+            );
+            assert!(config.endpoints[1].events.is_empty());
+
+            Ok(())
+        });
+    }
+}
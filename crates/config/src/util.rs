@@ -14,7 +14,7 @@
 
 use anyhow::Context;
 use async_trait::async_trait;
-use camino::Utf8Path;
+use camino::{Utf8Path, Utf8PathBuf};
 use figment::{
     error::Error as FigmentError,
     providers::{Env, Format, Serialized, Yaml},
@@ -23,6 +23,8 @@ use figment::{
 use rand::Rng;
 use serde::{de::DeserializeOwned, Serialize};
 
+use crate::{interpolation::Interpolated, DeploymentProfile};
+
 #[async_trait]
 /// Trait implemented by all configuration section to help loading specific part
 /// of the config and generate the sample config.
@@ -62,16 +64,46 @@ pub trait ConfigurationSection: Sized + DeserializeOwned + Serialize {
     }
 
     /// Load configuration from a list of files and environment variables.
+    ///
+    /// The `${VAR}` references in the files are replaced with the value of the
+    /// environment variables, and secrets can be read from a file by setting
+    /// `<field>_file` instead of `<field>`.
+    ///
+    /// Paths pointing to a directory load all the YAML files it contains, in
+    /// lexical order. Unlike separate files, which replace the lists set by the
+    /// previous ones, the lists set by the files of a directory are appended to
+    /// each other, so that e.g. clients can be defined in separate files.
+    ///
+    /// When a deployment `profile` is set, the values of its preset are used
+    /// for the settings which are not set explicitly.
     fn load_from_files<P>(paths: &[P]) -> Result<Self, FigmentError>
     where
         P: AsRef<Utf8Path>,
     {
-        let base = Figment::new().merge(Env::prefixed("MAS_").split("_"));
+        let mut figment = Figment::new().merge(Env::prefixed("MAS_").split("_"));
 
-        paths
-            .iter()
-            .fold(base, |f, path| f.merge(Yaml::file(path.as_ref())))
-            .extract_inner(Self::path())
+        for path in paths {
+            let path = path.as_ref();
+            if path.is_dir() {
+                for file in config_directory_files(path)? {
+                    figment = figment.admerge(Interpolated::new(Yaml::file(file)));
+                }
+            } else {
+                figment = figment.merge(Interpolated::new(Yaml::file(path)));
+            }
+        }
+
+        let profile = match figment.find_value("profile") {
+            Ok(value) => Some(value.deserialize::<DeploymentProfile>()?),
+            Err(e) if e.missing() => None,
+            Err(e) => return Err(e),
+        };
+
+        if let Some(profile) = profile {
+            figment = Figment::from(Serialized::defaults(profile.preset())).merge(figment);
+        }
+
+        figment.extract_inner(Self::path())
     }
 
     /// Load configuration from a file and environment variables.
@@ -85,3 +117,118 @@ pub trait ConfigurationSection: Sized + DeserializeOwned + Serialize {
     /// Generate config used in unit tests
     fn test() -> Self;
 }
+
+/// List the YAML files of a configuration directory, in lexical order
+fn config_directory_files(path: &Utf8Path) -> Result<Vec<Utf8PathBuf>, FigmentError> {
+    let entries = path
+        .read_dir_utf8()
+        .map_err(|e| FigmentError::from(format!("could not read directory {path}: {e}")))?;
+
+    let mut files = Vec::new();
+    for entry in entries {
+        let entry = entry
+            .map_err(|e| FigmentError::from(format!("could not read directory {path}: {e}")))?;
+        let file = entry.into_path();
+        if file.is_file() && matches!(file.extension(), Some("yaml" | "yml")) {
+            files.push(file);
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::Jail;
+
+    use crate::{
+        ClientsConfig, ConfigurationSection, DatabaseConfig, MatrixConfig, RateLimitingConfig,
+    };
+
+    #[test]
+    fn load_config_directory() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    matrix:
+                      homeserver: example.com
+                      secret: test
+                    clients:
+                      - client_id: 01GFWR28C4KNE04WG3HKXB7C9R
+                        client_auth_method: none
+                ",
+            )?;
+
+            std::fs::create_dir(jail.directory().join("config.d")).map_err(|e| e.to_string())?;
+            jail.create_file(
+                "config.d/20-other.yml",
+                r"
+                    clients:
+                      - client_id: 01GFWR3WHR93Y5HK389H28VHZ9
+                        client_auth_method: none
+                ",
+            )?;
+            jail.create_file(
+                "config.d/10-matrix.yaml",
+                r"
+                    matrix:
+                      homeserver: example.org
+                    clients:
+                      - client_id: 01GFWR32NCQ12B8Z0J8CPXRRB6
+                        client_auth_method: none
+                ",
+            )?;
+            jail.create_file("config.d/README", "not a configuration file")?;
+
+            let matrix = MatrixConfig::load_from_files(&["config.yaml", "config.d"])?;
+            assert_eq!(matrix.homeserver, "example.org");
+            assert_eq!(matrix.secret, "test");
+
+            let clients = ClientsConfig::load_from_files(&["config.yaml", "config.d"])?;
+            let ids: Vec<String> = clients
+                .iter()
+                .map(|client| client.client_id.to_string())
+                .collect();
+            assert_eq!(
+                ids,
+                vec![
+                    "01GFWR28C4KNE04WG3HKXB7C9R",
+                    "01GFWR32NCQ12B8Z0J8CPXRRB6",
+                    "01GFWR3WHR93Y5HK389H28VHZ9",
+                ]
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn load_config_with_profile() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    profile: large
+                    database:
+                      max_connections: 42
+                ",
+            )?;
+
+            let database = DatabaseConfig::load_from_file("config.yaml")?;
+            assert_eq!(database.max_connections.get(), 42);
+            assert_eq!(database.min_connections, 10);
+
+            let rate_limiting = RateLimitingConfig::load_from_file("config.yaml")?;
+            assert_eq!(rate_limiting.token.per_client.burst.get(), 3000);
+            // Settings the preset doesn't set keep their default
+            assert_eq!(rate_limiting.login.per_account.burst.get(), 1800);
+
+            jail.create_file("config.yaml", "profile: huge")?;
+            assert!(DatabaseConfig::load_from_file("config.yaml").is_err());
+
+            Ok(())
+        });
+    }
+}
@@ -39,20 +39,31 @@ pub use self::{
         CompatSessionState, CompatSsoLogin, CompatSsoLoginState, Device,
     },
     oauth2::{
-        AuthorizationCode, AuthorizationGrant, AuthorizationGrantStage, Client,
-        InvalidRedirectUriError, JwksOrJwksUri, Pkce, Session, SessionState,
+        AuthorizationCode, AuthorizationGrant, AuthorizationGrantApproval, AuthorizationGrantStage,
+        ClaimMapping, Client, ClientTokenSettings, InvalidRedirectUriError, JwksOrJwksUri,
+        JwtBearerIssuer, OAuth2Features, Pkce, RedirectUriMatching, ScopeDefinition, ScopeRegistry,
+        ScopeRisk, Session, SessionState, UserAttribute,
     },
     tokens::{
         AccessToken, AccessTokenState, RefreshToken, RefreshTokenState, TokenFormatError, TokenType,
     },
     upstream_oauth2::{
         UpsreamOAuthProviderSetEmailVerification, UpstreamOAuthAuthorizationSession,
-        UpstreamOAuthAuthorizationSessionState, UpstreamOAuthLink, UpstreamOAuthProvider,
-        UpstreamOAuthProviderClaimsImports, UpstreamOAuthProviderImportAction,
-        UpstreamOAuthProviderImportPreference,
+        UpstreamOAuthAuthorizationSessionState, UpstreamOAuthLink, UpstreamOAuthLinkTokens,
+        UpstreamOAuthProvider, UpstreamOAuthProviderAuthorizationParams,
+        UpstreamOAuthProviderClaimsImports, UpstreamOAuthProviderEndpoints,
+        UpstreamOAuthProviderGroupsImport, UpstreamOAuthProviderHealth,
+        UpstreamOAuthProviderHealthCheckSettings, UpstreamOAuthProviderImportAction,
+        UpstreamOAuthProviderImportPreference, UpstreamOAuthProviderImportSync,
+        UpstreamOAuthProviderLocalpartConflict, UpstreamOAuthProviderMetadata,
+        UpstreamOAuthProviderPkceMethod, UpstreamOAuthProviderProtocol,
+        UpstreamOAuthProviderRegistration, UpstreamOAuthProviderResponseMode,
+        UpstreamOAuthProviderSamlSettings, UpstreamOAuthProviderUiOptions,
     },
     users::{
-        Authentication, AuthenticationMethod, BrowserSession, Password, User, UserEmail,
-        UserEmailVerification, UserEmailVerificationState,
+        Authentication, AuthenticationMethod, BrowserSession, EmailRateLimited, EmailRateLimits,
+        EmailSendCounts, Impersonation, Password, ProfileAttribute, SecurityNotification, User,
+        UserEmail, UserEmailChange, UserEmailVerification, UserEmailVerificationState,
+        UserImpersonation,
     },
 };
@@ -30,7 +30,7 @@ use ulid::Ulid;
 use url::Url;
 
 use super::session::Session;
-use crate::InvalidTransitionError;
+use crate::{InvalidTransitionError, User};
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct Pkce {
@@ -143,6 +143,33 @@ impl AuthorizationGrantStage {
     }
 }
 
+/// The approval of an authorization grant by an administrator, for clients
+/// which require one
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AuthorizationGrantApproval {
+    /// The user who asked for the approval, and who can complete the grant once
+    /// it is approved
+    pub user_id: Ulid,
+
+    /// When the approval was asked for
+    pub requested_at: DateTime<Utc>,
+
+    /// When an administrator approved the grant, if they did
+    pub approved_at: Option<DateTime<Utc>>,
+
+    /// The administrator who approved or denied the grant, if it was reviewed
+    /// by a user
+    pub reviewed_by: Option<Ulid>,
+}
+
+impl AuthorizationGrantApproval {
+    /// Whether the grant was approved by an administrator
+    #[must_use]
+    pub fn is_approved(&self) -> bool {
+        self.approved_at.is_some()
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct AuthorizationGrant {
     pub id: Ulid,
@@ -159,6 +186,9 @@ pub struct AuthorizationGrant {
     pub response_type_id_token: bool,
     pub created_at: DateTime<Utc>,
     pub requires_consent: bool,
+    pub consent_given: bool,
+    pub device_display_name: Option<String>,
+    pub approval: Option<AuthorizationGrantApproval>,
 }
 
 impl std::ops::Deref for AuthorizationGrant {
@@ -190,12 +220,87 @@ impl AuthorizationGrant {
         Ok(self)
     }
 
-    // TODO: this is not used?
     pub fn cancel(mut self, canceld_at: DateTime<Utc>) -> Result<Self, InvalidTransitionError> {
         self.stage = self.stage.cancel(canceld_at)?;
         Ok(self)
     }
 
+    /// Hold the grant until an administrator approves it
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the grant is not pending, or if an approval was
+    /// already asked for
+    pub fn request_approval(
+        mut self,
+        requested_at: DateTime<Utc>,
+        user: &User,
+    ) -> Result<Self, InvalidTransitionError> {
+        if !self.stage.is_pending() || self.approval.is_some() {
+            return Err(InvalidTransitionError);
+        }
+
+        self.approval = Some(AuthorizationGrantApproval {
+            user_id: user.id,
+            requested_at,
+            approved_at: None,
+            reviewed_by: None,
+        });
+        Ok(self)
+    }
+
+    /// Mark the grant as approved by an administrator
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the grant is not waiting for an approval
+    pub fn approve(
+        mut self,
+        approved_at: DateTime<Utc>,
+        reviewer: Option<&User>,
+    ) -> Result<Self, InvalidTransitionError> {
+        if !self.is_awaiting_approval() {
+            return Err(InvalidTransitionError);
+        }
+
+        if let Some(approval) = &mut self.approval {
+            approval.approved_at = Some(approved_at);
+            approval.reviewed_by = reviewer.map(|user| user.id);
+        }
+        Ok(self)
+    }
+
+    /// Cancel a grant which was waiting for an approval, because an
+    /// administrator denied it
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the grant is not waiting for an approval
+    pub fn deny(
+        mut self,
+        denied_at: DateTime<Utc>,
+        reviewer: Option<&User>,
+    ) -> Result<Self, InvalidTransitionError> {
+        if !self.is_awaiting_approval() {
+            return Err(InvalidTransitionError);
+        }
+
+        if let Some(approval) = &mut self.approval {
+            approval.reviewed_by = reviewer.map(|user| user.id);
+        }
+        self.cancel(denied_at)
+    }
+
+    /// Whether the grant is waiting for an administrator to approve it
+    #[must_use]
+    pub fn is_awaiting_approval(&self) -> bool {
+        self.stage.is_pending()
+            && self
+                .approval
+                .as_ref()
+                .is_some_and(|approval| !approval.is_approved())
+    }
+
     pub fn sample(now: DateTime<Utc>, rng: &mut impl RngCore) -> Self {
         Self {
             id: Ulid::from_datetime_with_source(now.into(), rng),
@@ -214,6 +319,9 @@ impl AuthorizationGrant {
             response_type_id_token: false,
             created_at: now,
             requires_consent: false,
+            consent_given: false,
+            device_display_name: None,
+            approval: None,
         }
     }
 }
@@ -0,0 +1,87 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use oauth2_types::scope::Scope;
+use serde::{Deserialize, Serialize};
+
+/// An attribute of a user which can be mapped to a claim
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UserAttribute {
+    /// The display name of the user, as set on the homeserver
+    DisplayName,
+
+    /// The primary email address of the user
+    Email,
+
+    /// Whether the user can request admin access
+    IsAdmin,
+
+    /// The groups imported from the upstream provider the user last logged in
+    /// with
+    Groups,
+}
+
+/// Maps a user attribute to a claim of the ID tokens and userinfo responses
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClaimMapping {
+    /// The name of the claim
+    pub claim: String,
+
+    /// The attribute the claim is set to
+    pub attribute: UserAttribute,
+
+    /// The scope the session must have for the claim to be set, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+}
+
+impl ClaimMapping {
+    /// Whether the mapping applies to a session with the given scope
+    #[must_use]
+    pub fn applies_to(&self, scope: &Scope) -> bool {
+        self.scope
+            .as_deref()
+            .map_or(true, |required| scope.contains(required))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_applies_to() {
+        let scope: Scope = "openid urn:example:groups".parse().unwrap();
+
+        let mapping = ClaimMapping {
+            claim: "groups".to_owned(),
+            attribute: UserAttribute::Groups,
+            scope: None,
+        };
+        assert!(mapping.applies_to(&scope));
+
+        let mapping = ClaimMapping {
+            scope: Some("urn:example:groups".to_owned()),
+            ..mapping
+        };
+        assert!(mapping.applies_to(&scope));
+
+        let mapping = ClaimMapping {
+            scope: Some("email".to_owned()),
+            ..mapping
+        };
+        assert!(!mapping.applies_to(&scope));
+    }
+}
@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use mas_iana::{
     jose::JsonWebSignatureAlg,
     oauth::{OAuthAuthorizationEndpointResponseType, OAuthClientAuthenticationMethod},
@@ -23,7 +23,7 @@ use rand::RngCore;
 use serde::Serialize;
 use thiserror::Error;
 use ulid::Ulid;
-use url::Url;
+use url::{Host, Position, Url};
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 #[serde(rename_all = "snake_case")]
@@ -77,6 +77,10 @@ pub struct Client {
     /// the Relying Party's terms of service
     pub tos_uri: Option<Url>, // TODO: translations
 
+    /// Color used to brand the pages shown on behalf of the Client, as a
+    /// hexadecimal RGB color like `#0dbd8b`
+    pub brand_color: Option<String>,
+
     pub jwks: Option<JwksOrJwksUri>,
 
     /// JWS alg algorithm REQUIRED for signing the ID Token issued to this
@@ -99,6 +103,98 @@ pub struct Client {
     pub initiate_login_uri: Option<Url>,
 }
 
+/// Per-client overrides of the lifetime and audience of the issued tokens
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientTokenSettings {
+    /// How long the access tokens are valid for, instead of the global
+    /// `access_token_ttl`
+    pub access_token_ttl: Option<Duration>,
+
+    /// How long the refresh tokens can be used after being issued, instead of
+    /// the global `offline_session_ttl`
+    pub refresh_token_ttl: Option<Duration>,
+
+    /// Audiences of the access tokens, on top of the client itself
+    pub additional_audiences: Vec<String>,
+}
+
+impl ClientTokenSettings {
+    /// The lifetime of the access tokens, given the global default
+    #[must_use]
+    pub fn access_token_ttl(&self, default: Duration) -> Duration {
+        self.access_token_ttl.unwrap_or(default)
+    }
+
+    /// The lifetime of the refresh tokens, given the global default, if any
+    #[must_use]
+    pub fn refresh_token_ttl(&self, default: Option<Duration>) -> Option<Duration> {
+        self.refresh_token_ttl.or(default)
+    }
+}
+
+/// How the redirect URIs of authorization requests are matched against the
+/// ones registered by the client
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RedirectUriMatching {
+    /// The redirect URI must be one of the registered ones
+    Exact,
+
+    /// Like [`Self::Exact`], but loopback redirect URIs on `http://localhost`,
+    /// `http://127.0.0.1` and `http://[::1]` match on any port, as native apps
+    /// listen on a random port (RFC 8252)
+    #[default]
+    Loopback,
+
+    /// Like [`Self::Loopback`], but registered redirect URIs can start their
+    /// host with a `*` label, matching any single label, like in
+    /// `https://*.example.com/callback`. Only meant for trusted first-party
+    /// clients
+    SubdomainWildcard,
+}
+
+impl RedirectUriMatching {
+    /// All the matching policies
+    pub const ALL: [Self; 3] = [Self::Exact, Self::Loopback, Self::SubdomainWildcard];
+
+    /// The name of the matching policy, as stored in the database
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Exact => "exact",
+            Self::Loopback => "loopback",
+            Self::SubdomainWildcard => "subdomain_wildcard",
+        }
+    }
+
+    /// Find a matching policy by its name
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|m| m.as_str() == name)
+    }
+
+    /// Whether the registered URI is a wildcard one, its host starting with a
+    /// `*` label
+    #[must_use]
+    pub fn is_wildcard(uri: &Url) -> bool {
+        uri.host_str().is_some_and(|host| host.starts_with("*."))
+    }
+
+    /// Whether the given URI matches the registered one
+    #[must_use]
+    pub fn matches(self, uri: &Url, registered_uri: &Url) -> bool {
+        // Wildcard URIs are patterns, they never match as-is
+        if Self::is_wildcard(registered_uri) {
+            return self == Self::SubdomainWildcard && wildcard_matches(uri, registered_uri);
+        }
+
+        if uri == registered_uri {
+            return true;
+        }
+
+        self != Self::Exact && loopback_matches(uri, registered_uri)
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum InvalidRedirectUriError {
     #[error("redirect_uri is not allowed for this client")]
@@ -109,18 +205,33 @@ pub enum InvalidRedirectUriError {
 
     #[error("client has no redirect_uri registered")]
     NoneRegistered,
+
+    #[error("redirect_uri is required, as the one registered is a wildcard")]
+    WildcardRegistered,
 }
 
 impl Client {
+    /// Find out where to redirect to after an authorization request, given the
+    /// redirect URI in the request, if any
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the redirect URI doesn't match the registered ones
+    /// with the given matching policy, or if it is missing and can't be
+    /// guessed
     pub fn resolve_redirect_uri<'a>(
         &'a self,
         redirect_uri: &'a Option<Url>,
+        matching: RedirectUriMatching,
     ) -> Result<&'a Url, InvalidRedirectUriError> {
         match (&self.redirect_uris[..], redirect_uri) {
             ([], _) => Err(InvalidRedirectUriError::NoneRegistered),
+            ([one], None) if RedirectUriMatching::is_wildcard(one) => {
+                Err(InvalidRedirectUriError::WildcardRegistered)
+            }
             ([one], None) => Ok(one),
             (_, None) => Err(InvalidRedirectUriError::MultipleRegistered),
-            (uris, Some(uri)) if uri_matches_one_of(uri, uris) => Ok(uri),
+            (uris, Some(uri)) if uri_matches_one_of(uri, uris, matching) => Ok(uri),
             _ => Err(InvalidRedirectUriError::NotAllowed),
         }
     }
@@ -145,6 +256,7 @@ impl Client {
                 logo_uri: Some(Url::parse("https://client1.example.com/logo.png").unwrap()),
                 tos_uri: Some(Url::parse("https://client1.example.com/tos").unwrap()),
                 policy_uri: Some(Url::parse("https://client1.example.com/policy").unwrap()),
+                brand_color: Some("#0dbd8b".to_owned()),
                 initiate_login_uri: Some(
                     Url::parse("https://client1.example.com/initiate-login").unwrap(),
                 ),
@@ -169,6 +281,7 @@ impl Client {
                 logo_uri: None,
                 tos_uri: None,
                 policy_uri: None,
+                brand_color: None,
                 initiate_login_uri: None,
                 token_endpoint_auth_method: None,
                 token_endpoint_auth_signing_alg: None,
@@ -184,19 +297,43 @@ impl Client {
 const LOCAL_HOSTS: &[&str] = &["localhost", "127.0.0.1", "[::1]"];
 
 /// Whether the given URI matches one of the registered URIs.
-///
-/// If the URI host is one if `localhost`, `127.0.0.1` or `[::1]`, any port is
-/// accepted.
-fn uri_matches_one_of(uri: &Url, registered_uris: &[Url]) -> bool {
-    if LOCAL_HOSTS.contains(&uri.host_str().unwrap_or_default()) {
-        let mut uri = uri.clone();
-        // Try matching without the port first
-        if uri.set_port(None).is_ok() && registered_uris.contains(&uri) {
-            return true;
-        }
+fn uri_matches_one_of(uri: &Url, registered_uris: &[Url], matching: RedirectUriMatching) -> bool {
+    registered_uris
+        .iter()
+        .any(|registered_uri| matching.matches(uri, registered_uri))
+}
+
+/// Whether both URIs are on the loopback interface and match regardless of
+/// their port.
+fn loopback_matches(uri: &Url, registered_uri: &Url) -> bool {
+    if uri.scheme() != "http" || !LOCAL_HOSTS.contains(&uri.host_str().unwrap_or_default()) {
+        return false;
+    }
+
+    let mut uri = uri.clone();
+    let mut registered_uri = registered_uri.clone();
+    uri.set_port(None).is_ok() && registered_uri.set_port(None).is_ok() && uri == registered_uri
+}
+
+/// Whether the URI matches the registered wildcard URI, the `*` label
+/// matching a single label of the host.
+fn wildcard_matches(uri: &Url, registered_uri: &Url) -> bool {
+    let (Some(Host::Domain(host)), Some(pattern)) = (uri.host(), registered_uri.host_str()) else {
+        return false;
+    };
+
+    // The `*` is the first label of the pattern, only match the same number of
+    // labels
+    let Some((label, rest)) = host.split_once('.') else {
+        return false;
+    };
+    if label.is_empty() || pattern.strip_prefix("*.") != Some(rest) {
+        return false;
     }
 
-    registered_uris.contains(uri)
+    // Everything else must be the same
+    uri[..Position::BeforeHost] == registered_uri[..Position::BeforeHost]
+        && uri[Position::AfterHost..] == registered_uri[Position::AfterHost..]
 }
 
 #[cfg(test)]
@@ -215,25 +352,117 @@ mod tests {
         // Non-loopback interface URIs.
         assert!(uri_matches_one_of(
             &Url::parse("https://example.org").unwrap(),
-            registered_uris
+            registered_uris,
+            RedirectUriMatching::Loopback
         ));
         assert!(!uri_matches_one_of(
             &Url::parse("https://example.org:8080").unwrap(),
-            registered_uris
+            registered_uris,
+            RedirectUriMatching::Loopback
         ));
 
         // Loopback interface URIS.
         assert!(uri_matches_one_of(
             &Url::parse("http://127.0.0.1").unwrap(),
-            registered_uris
+            registered_uris,
+            RedirectUriMatching::Loopback
         ));
         assert!(uri_matches_one_of(
             &Url::parse("http://127.0.0.1:8080").unwrap(),
-            registered_uris
+            registered_uris,
+            RedirectUriMatching::Loopback
         ));
         assert!(!uri_matches_one_of(
             &Url::parse("http://localhost").unwrap(),
-            registered_uris
+            registered_uris,
+            RedirectUriMatching::Loopback
+        ));
+
+        // The port of loopback URIs only matters with exact matching
+        assert!(!uri_matches_one_of(
+            &Url::parse("http://127.0.0.1:8080").unwrap(),
+            registered_uris,
+            RedirectUriMatching::Exact
+        ));
+        assert!(uri_matches_one_of(
+            &Url::parse("http://127.0.0.1").unwrap(),
+            registered_uris,
+            RedirectUriMatching::Exact
+        ));
+    }
+
+    #[test]
+    fn test_wildcard_redirect_uri() {
+        let registered_uris = &[Url::parse("https://*.example.org/callback").unwrap()];
+        let matches = |uri: &str, matching| {
+            uri_matches_one_of(&Url::parse(uri).unwrap(), registered_uris, matching)
+        };
+
+        assert!(matches(
+            "https://app.example.org/callback",
+            RedirectUriMatching::SubdomainWildcard
+        ));
+
+        // Only a single label matches
+        assert!(!matches(
+            "https://example.org/callback",
+            RedirectUriMatching::SubdomainWildcard
+        ));
+        assert!(!matches(
+            "https://a.b.example.org/callback",
+            RedirectUriMatching::SubdomainWildcard
+        ));
+        assert!(!matches(
+            "https://app.example.com/callback",
+            RedirectUriMatching::SubdomainWildcard
+        ));
+
+        // The rest of the URI must be the same
+        assert!(!matches(
+            "https://app.example.org/other",
+            RedirectUriMatching::SubdomainWildcard
+        ));
+        assert!(!matches(
+            "http://app.example.org/callback",
+            RedirectUriMatching::SubdomainWildcard
+        ));
+        assert!(!matches(
+            "https://app.example.org:8443/callback",
+            RedirectUriMatching::SubdomainWildcard
+        ));
+
+        // Wildcards are ignored with the other matching policies, even when
+        // given literally
+        assert!(!matches(
+            "https://app.example.org/callback",
+            RedirectUriMatching::Loopback
+        ));
+        assert!(!matches(
+            "https://*.example.org/callback",
+            RedirectUriMatching::Exact
+        ));
+    }
+
+    #[test]
+    fn test_resolve_loopback_redirect_uri() {
+        let now = chrono::Utc::now();
+        let mut rng = rand::thread_rng();
+        let mut client = Client::samples(now, &mut rng).remove(0);
+        client.redirect_uris = vec![Url::parse("http://127.0.0.1/callback").unwrap()];
+
+        // Native apps pick a random port, which must match by default, like
+        // it did before the matching policy was configurable
+        let redirect_uri = Some(Url::parse("http://127.0.0.1:51234/callback").unwrap());
+        assert_eq!(
+            client
+                .resolve_redirect_uri(&redirect_uri, RedirectUriMatching::default())
+                .unwrap(),
+            redirect_uri.as_ref().unwrap()
+        );
+
+        assert!(matches!(
+            client.resolve_redirect_uri(&redirect_uri, RedirectUriMatching::Exact),
+            Err(InvalidRedirectUriError::NotAllowed)
         ));
     }
 }
@@ -0,0 +1,81 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use oauth2_types::requests::{GrantType, ResponseMode};
+
+/// The OAuth 2.0 and OpenID Connect capabilities enabled on the server.
+///
+/// The discovery document, the endpoints and the client registration policy
+/// all rely on it, so that they agree on what is supported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OAuth2Features {
+    /// Whether clients can register through the dynamic client registration
+    /// endpoint
+    pub dynamic_registration: bool,
+
+    /// The grant types accepted by the token endpoint
+    pub grant_types: Vec<GrantType>,
+
+    /// The response modes accepted by the authorization endpoint
+    pub response_modes: Vec<ResponseMode>,
+}
+
+impl OAuth2Features {
+    /// The grant types the server implements
+    pub const SUPPORTED_GRANT_TYPES: [GrantType; 4] = [
+        GrantType::AuthorizationCode,
+        GrantType::RefreshToken,
+        GrantType::ClientCredentials,
+        GrantType::JwtBearer,
+    ];
+
+    /// The response modes the server implements
+    pub const SUPPORTED_RESPONSE_MODES: [ResponseMode; 3] = [
+        ResponseMode::FormPost,
+        ResponseMode::Query,
+        ResponseMode::Fragment,
+    ];
+
+    /// Whether the token endpoint accepts the given grant type
+    #[must_use]
+    pub fn grant_type_enabled(&self, grant_type: &GrantType) -> bool {
+        self.grant_types.contains(grant_type)
+    }
+
+    /// The grant types the server implements, but which are disabled. Clients
+    /// can't register with them
+    #[must_use]
+    pub fn disabled_grant_types(&self) -> Vec<GrantType> {
+        Self::SUPPORTED_GRANT_TYPES
+            .into_iter()
+            .filter(|grant_type| !self.grant_type_enabled(grant_type))
+            .collect()
+    }
+
+    /// Whether the authorization endpoint accepts the given response mode
+    #[must_use]
+    pub fn response_mode_enabled(&self, response_mode: &ResponseMode) -> bool {
+        self.response_modes.contains(response_mode)
+    }
+}
+
+impl Default for OAuth2Features {
+    fn default() -> Self {
+        Self {
+            dynamic_registration: true,
+            grant_types: Self::SUPPORTED_GRANT_TYPES.to_vec(),
+            response_modes: Self::SUPPORTED_RESPONSE_MODES.to_vec(),
+        }
+    }
+}
@@ -0,0 +1,99 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use chrono::{DateTime, Utc};
+use oauth2_types::scope::Scope;
+use serde::Serialize;
+use ulid::Ulid;
+
+use super::JwksOrJwksUri;
+
+/// An external issuer trusted to sign the assertions exchanged through the
+/// JWT bearer grant
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct JwtBearerIssuer {
+    pub id: Ulid,
+
+    /// The `iss` claim of the assertions signed by this issuer
+    pub issuer: String,
+
+    /// The keys used to verify the signature of the assertions
+    pub jwks: JwksOrJwksUri,
+
+    /// The usernames this issuer can sign assertions for. `*` means any user,
+    /// and an empty list means no user at all
+    pub allowed_subjects: Vec<String>,
+
+    /// The clients which can exchange the assertions of this issuer
+    pub allowed_clients: Vec<Ulid>,
+
+    /// The scopes which can be obtained with the assertions of this issuer.
+    /// `None` means the scopes are only restricted by the policy
+    pub allowed_scope: Option<Scope>,
+
+    pub created_at: DateTime<Utc>,
+}
+
+impl JwtBearerIssuer {
+    /// Whether this issuer can sign assertions for the given username
+    #[must_use]
+    pub fn is_subject_allowed(&self, username: &str) -> bool {
+        self.allowed_subjects
+            .iter()
+            .any(|subject| subject == "*" || subject == username)
+    }
+
+    /// Whether the given client can exchange the assertions of this issuer
+    #[must_use]
+    pub fn is_client_allowed(&self, client_id: Ulid) -> bool {
+        self.allowed_clients.contains(&client_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn issuer(allowed_subjects: &[&str]) -> JwtBearerIssuer {
+        JwtBearerIssuer {
+            id: Ulid::nil(),
+            issuer: "https://issuer.example.com/".to_owned(),
+            jwks: JwksOrJwksUri::JwksUri("https://issuer.example.com/jwks.json".parse().unwrap()),
+            allowed_subjects: allowed_subjects.iter().map(|&s| s.to_owned()).collect(),
+            allowed_clients: vec![Ulid::nil()],
+            allowed_scope: None,
+            created_at: DateTime::default(),
+        }
+    }
+
+    #[test]
+    fn subject_allowed() {
+        // No subjects means no user at all
+        assert!(!issuer(&[]).is_subject_allowed("alice"));
+
+        assert!(issuer(&["alice"]).is_subject_allowed("alice"));
+        assert!(!issuer(&["alice"]).is_subject_allowed("bob"));
+
+        // The wildcard has to be set explicitly to allow any user
+        assert!(issuer(&["*"]).is_subject_allowed("alice"));
+        assert!(issuer(&["*"]).is_subject_allowed("bob"));
+    }
+
+    #[test]
+    fn client_allowed() {
+        let issuer = issuer(&["*"]);
+        assert!(issuer.is_client_allowed(Ulid::nil()));
+        assert!(!issuer.is_client_allowed(Ulid::from_parts(0, 1)));
+    }
+}
@@ -13,11 +13,24 @@
 // limitations under the License.
 
 mod authorization_grant;
+mod claim_mapping;
 mod client;
+mod features;
+mod jwt_bearer_issuer;
+mod scope_registry;
 mod session;
 
 pub use self::{
-    authorization_grant::{AuthorizationCode, AuthorizationGrant, AuthorizationGrantStage, Pkce},
-    client::{Client, InvalidRedirectUriError, JwksOrJwksUri},
+    authorization_grant::{
+        AuthorizationCode, AuthorizationGrant, AuthorizationGrantApproval, AuthorizationGrantStage,
+        Pkce,
+    },
+    claim_mapping::{ClaimMapping, UserAttribute},
+    client::{
+        Client, ClientTokenSettings, InvalidRedirectUriError, JwksOrJwksUri, RedirectUriMatching,
+    },
+    features::OAuth2Features,
+    jwt_bearer_issuer::JwtBearerIssuer,
+    scope_registry::{ScopeDefinition, ScopeRegistry, ScopeRisk},
     session::{Session, SessionState},
 };
@@ -0,0 +1,206 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{btree_map::Entry, BTreeMap};
+
+use oauth2_types::scope::{Scope, ScopeToken};
+use serde::Serialize;
+
+use crate::Device;
+
+/// How much access to the account of a user a scope grants
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScopeRisk {
+    /// Gives access to basic information about the user
+    #[default]
+    Low,
+
+    /// Gives access to the data of the user, or lets the client act on their
+    /// behalf
+    Medium,
+
+    /// Gives administrative access
+    High,
+}
+
+/// A scope clients can request, with how it is presented to users
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ScopeDefinition {
+    /// The scope token, as requested by clients
+    pub name: String,
+
+    /// Short human-readable name of the scope
+    pub display_name: String,
+
+    /// What the scope gives access to
+    pub description: Option<String>,
+
+    /// How much access to the account the scope grants
+    pub risk: ScopeRisk,
+}
+
+/// The scopes defined by MAS itself, as `(name, display_name, description,
+/// risk)`
+const BUILTIN_SCOPES: &[(&str, &str, &str, ScopeRisk)] = &[
+    (
+        "openid",
+        "Profile",
+        "See your profile info and contact details",
+        ScopeRisk::Low,
+    ),
+    (
+        "email",
+        "Email address",
+        "See your email address",
+        ScopeRisk::Low,
+    ),
+    (
+        "urn:matrix:org.matrix.msc2967.client:api:*",
+        "Matrix client",
+        "View your existing messages and data, and send new messages on your behalf",
+        ScopeRisk::Medium,
+    ),
+    (
+        "urn:mas:graphql:*",
+        "Account management",
+        "Edit your profile and contact details, and manage your devices and sessions",
+        ScopeRisk::Medium,
+    ),
+    (
+        "urn:synapse:admin:*",
+        "Synapse administration",
+        "Administer the Synapse homeserver",
+        ScopeRisk::High,
+    ),
+    (
+        "urn:mas:admin",
+        "MAS administration",
+        "Administer any user on the matrix-authentication-service",
+        ScopeRisk::High,
+    ),
+    (
+        "urn:mas:upstream_tokens",
+        "Upstream tokens",
+        "Get the tokens issued to users by the upstream providers",
+        ScopeRisk::High,
+    ),
+];
+
+/// The scopes clients can request: the built-in ones, plus the custom ones
+/// defined by the operator
+///
+/// The device scopes are always defined, as they are generated by clients.
+#[derive(Debug, Clone)]
+pub struct ScopeRegistry {
+    definitions: BTreeMap<String, ScopeDefinition>,
+}
+
+impl Default for ScopeRegistry {
+    fn default() -> Self {
+        let definitions = BUILTIN_SCOPES
+            .iter()
+            .map(|&(name, display_name, description, risk)| {
+                let definition = ScopeDefinition {
+                    name: name.to_owned(),
+                    display_name: display_name.to_owned(),
+                    description: Some(description.to_owned()),
+                    risk,
+                };
+                (name.to_owned(), definition)
+            })
+            .collect();
+
+        Self { definitions }
+    }
+}
+
+impl ScopeRegistry {
+    /// Define a custom scope
+    ///
+    /// Returns `false` if a scope with the same name is already defined, in
+    /// which case the existing definition is kept
+    pub fn register(&mut self, definition: ScopeDefinition) -> bool {
+        match self.definitions.entry(definition.name.clone()) {
+            Entry::Vacant(entry) => {
+                entry.insert(definition);
+                true
+            }
+            Entry::Occupied(_) => false,
+        }
+    }
+
+    /// Get the definition of a scope
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&ScopeDefinition> {
+        self.definitions.get(name)
+    }
+
+    /// Whether the given scope token is defined
+    #[must_use]
+    pub fn is_defined(&self, token: &ScopeToken) -> bool {
+        self.definitions.contains_key(token.as_str()) || Device::from_scope_token(token).is_some()
+    }
+
+    /// Find the first token of the given scope which is not defined, if any
+    #[must_use]
+    pub fn find_undefined<'a>(&self, scope: &'a Scope) -> Option<&'a ScopeToken> {
+        scope.iter().find(|token| !self.is_defined(token))
+    }
+
+    /// Iterate over the definitions, ordered by name
+    pub fn iter(&self) -> impl Iterator<Item = &ScopeDefinition> {
+        self.definitions.values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scope_registry() {
+        let mut registry = ScopeRegistry::default();
+
+        let scope: Scope = "openid urn:matrix:org.matrix.msc2967.client:device:ABCDEFGHIJ"
+            .parse()
+            .unwrap();
+        assert_eq!(registry.find_undefined(&scope), None);
+
+        let scope: Scope = "openid urn:example:read".parse().unwrap();
+        assert_eq!(
+            registry.find_undefined(&scope).map(ScopeToken::as_str),
+            Some("urn:example:read")
+        );
+
+        let definition = ScopeDefinition {
+            name: "urn:example:read".to_owned(),
+            display_name: "Read your data on example.com".to_owned(),
+            description: None,
+            risk: ScopeRisk::Low,
+        };
+        assert!(registry.register(definition));
+        assert_eq!(registry.find_undefined(&scope), None);
+
+        // Built-in scopes can't be redefined
+        let definition = ScopeDefinition {
+            name: "openid".to_owned(),
+            display_name: "Something else".to_owned(),
+            description: None,
+            risk: ScopeRisk::High,
+        };
+        assert!(!registry.register(definition));
+        assert_eq!(registry.get("openid").unwrap().display_name, "Profile");
+    }
+}
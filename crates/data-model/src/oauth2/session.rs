@@ -77,6 +77,7 @@ pub struct Session {
     pub scope: Scope,
     pub last_active_at: Option<DateTime<Utc>>,
     pub last_active_ip: Option<IpAddr>,
+    pub human_name: Option<String>,
 }
 
 impl std::ops::Deref for Session {
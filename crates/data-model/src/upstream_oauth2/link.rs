@@ -24,3 +24,21 @@ pub struct UpstreamOAuthLink {
     pub subject: String,
     pub created_at: DateTime<Utc>,
 }
+
+/// The tokens issued by the provider through a link, kept to call its APIs on
+/// behalf of the user
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpstreamOAuthLinkTokens {
+    pub encrypted_access_token: String,
+    pub encrypted_refresh_token: Option<String>,
+    pub access_token_expires_at: Option<DateTime<Utc>>,
+}
+
+impl UpstreamOAuthLinkTokens {
+    /// Whether the access token is still valid at the given time
+    #[must_use]
+    pub fn is_valid(&self, now: DateTime<Utc>) -> bool {
+        self.access_token_expires_at
+            .map_or(true, |expires_at| now < expires_at)
+    }
+}
@@ -17,12 +17,24 @@ mod provider;
 mod session;
 
 pub use self::{
-    link::UpstreamOAuthLink,
+    link::{UpstreamOAuthLink, UpstreamOAuthLinkTokens},
     provider::{
+        AuthorizationParams as UpstreamOAuthProviderAuthorizationParams,
         ClaimsImports as UpstreamOAuthProviderClaimsImports,
+        Endpoints as UpstreamOAuthProviderEndpoints,
+        GroupsImport as UpstreamOAuthProviderGroupsImport, Health as UpstreamOAuthProviderHealth,
+        HealthCheckSettings as UpstreamOAuthProviderHealthCheckSettings,
         ImportAction as UpstreamOAuthProviderImportAction,
         ImportPreference as UpstreamOAuthProviderImportPreference,
-        SetEmailVerification as UpsreamOAuthProviderSetEmailVerification, UpstreamOAuthProvider,
+        ImportSync as UpstreamOAuthProviderImportSync,
+        LocalpartConflict as UpstreamOAuthProviderLocalpartConflict,
+        Metadata as UpstreamOAuthProviderMetadata, PkceMethod as UpstreamOAuthProviderPkceMethod,
+        Protocol as UpstreamOAuthProviderProtocol,
+        Registration as UpstreamOAuthProviderRegistration,
+        ResponseMode as UpstreamOAuthProviderResponseMode,
+        SamlSettings as UpstreamOAuthProviderSamlSettings,
+        SetEmailVerification as UpsreamOAuthProviderSetEmailVerification,
+        UiOptions as UpstreamOAuthProviderUiOptions, UpstreamOAuthProvider,
     },
     session::{UpstreamOAuthAuthorizationSession, UpstreamOAuthAuthorizationSessionState},
 };
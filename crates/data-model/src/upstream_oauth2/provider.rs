@@ -12,16 +12,23 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use chrono::{DateTime, Utc};
+use std::num::NonZeroU32;
+
+use chrono::{DateTime, Duration, Utc};
 use mas_iana::{jose::JsonWebSignatureAlg, oauth::OAuthClientAuthenticationMethod};
-use oauth2_types::scope::Scope;
+use mas_jose::jwk::PublicJsonWebKeySet;
+use oauth2_types::{oidc::ProviderMetadata, scope::Scope};
 use serde::{Deserialize, Serialize};
 use ulid::Ulid;
+use url::Url;
+
+use crate::users::ProfileAttribute;
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct UpstreamOAuthProvider {
     pub id: Ulid,
     pub issuer: String,
+    pub slug: Option<String>,
     pub scope: Scope,
     pub client_id: String,
     pub encrypted_client_secret: Option<String>,
@@ -29,6 +36,294 @@ pub struct UpstreamOAuthProvider {
     pub token_endpoint_auth_method: OAuthClientAuthenticationMethod,
     pub created_at: DateTime<Utc>,
     pub claims_imports: ClaimsImports,
+    pub pkce_method: PkceMethod,
+    pub authorization_params: AuthorizationParams,
+    pub fetch_userinfo: bool,
+    pub ui_options: UiOptions,
+    pub protocol: Protocol,
+    pub endpoints: Endpoints,
+    pub saml: SamlSettings,
+    pub store_tokens: bool,
+    pub health_check: HealthCheckSettings,
+}
+
+impl UpstreamOAuthProvider {
+    /// The name of the provider shown to users, falling back to its issuer
+    #[must_use]
+    pub fn human_name(&self) -> &str {
+        self.ui_options
+            .human_name
+            .as_deref()
+            .unwrap_or(&self.issuer)
+    }
+}
+
+/// The discovery document and the JWKS of a provider, as they were last
+/// fetched from it
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    pub discovery: ProviderMetadata,
+    pub jwks: PublicJsonWebKeySet,
+    pub fetched_at: DateTime<Utc>,
+}
+
+/// The registration of this service as a client of a provider, made through
+/// dynamic client registration.
+///
+/// The credentials it got are stored on the provider itself, this keeps what is
+/// needed to keep them up to date.
+#[derive(Debug, Clone)]
+pub struct Registration {
+    pub encrypted_registration_access_token: Option<String>,
+    pub registration_client_uri: Option<Url>,
+    pub client_secret_expires_at: Option<DateTime<Utc>>,
+    pub registered_at: DateTime<Utc>,
+}
+
+/// The outcome of the last health check of a provider
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Health {
+    /// Why the provider was found unhealthy, if it was
+    pub error: Option<String>,
+    pub checked_at: DateTime<Utc>,
+}
+
+impl Health {
+    /// Whether the provider passed its last health check
+    #[must_use]
+    pub fn is_healthy(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+const fn default_true() -> bool {
+    true
+}
+
+/// How the provider is presented to users
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UiOptions {
+    /// The name of the provider shown to users
+    #[serde(default)]
+    pub human_name: Option<String>,
+
+    /// The URL of an icon shown next to the name of the provider
+    #[serde(default)]
+    pub icon: Option<String>,
+
+    /// The color of the button of the provider on the login page, as a CSS
+    /// color
+    #[serde(default)]
+    pub brand_color: Option<String>,
+
+    /// Providers are listed by ascending sort order on the login page
+    #[serde(default)]
+    pub sort_order: i32,
+
+    /// Whether the provider is offered as a login option. Hidden providers can
+    /// still be used through a direct link, e.g. to link an account to them
+    #[serde(default = "default_true")]
+    pub show_on_login: bool,
+}
+
+impl Default for UiOptions {
+    fn default() -> Self {
+        Self {
+            human_name: None,
+            icon: None,
+            brand_color: None,
+            sort_order: 0,
+            show_on_login: true,
+        }
+    }
+}
+
+/// The protocol spoken by the provider
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    /// OpenID Connect: the endpoints are discovered, and the user is
+    /// identified by the ID token
+    #[default]
+    Oidc,
+
+    /// Plain OAuth 2.0: the endpoints are configured, and the user is
+    /// identified by the response of the userinfo endpoint
+    OAuth2,
+
+    /// SAML 2.0: the user is identified by the assertion the identity provider
+    /// posts back. The issuer is the entity ID of the identity provider, and
+    /// the client ID the entity ID of this service
+    Saml,
+}
+
+impl Protocol {
+    /// All the protocols
+    pub const ALL: [Self; 3] = [Self::Oidc, Self::OAuth2, Self::Saml];
+
+    /// The name of the protocol, as stored in the database
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Oidc => "oidc",
+            Self::OAuth2 => "oauth2",
+            Self::Saml => "saml",
+        }
+    }
+
+    /// Find a protocol by its name
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.as_str() == name)
+    }
+}
+
+/// Endpoints of the provider which are configured instead of being discovered
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct Endpoints {
+    #[serde(default)]
+    pub authorization_endpoint: Option<Url>,
+
+    #[serde(default)]
+    pub token_endpoint: Option<Url>,
+
+    #[serde(default)]
+    pub userinfo_endpoint: Option<Url>,
+
+    #[serde(default)]
+    pub jwks_uri: Option<Url>,
+
+    /// Other endpoints queried with the access token after the token exchange,
+    /// their responses being added to the claims under the given names
+    #[serde(default)]
+    pub additional_userinfo_endpoints: Vec<(String, Url)>,
+}
+
+/// Settings of the providers using the SAML 2.0 protocol
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct SamlSettings {
+    /// Where the authentication requests are sent, with the HTTP-Redirect
+    /// binding
+    #[serde(default)]
+    pub sso_url: Option<Url>,
+
+    /// The certificates the assertions may be signed with, PEM-encoded
+    #[serde(default)]
+    pub idp_certificates: Vec<String>,
+
+    /// The format of the name ID requested from the identity provider
+    #[serde(default)]
+    pub name_id_format: Option<String>,
+}
+
+/// How the provider is checked by the background health probe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct HealthCheckSettings {
+    /// Whether to also check that the token endpoint is reachable
+    #[serde(default)]
+    pub probe_token_endpoint: bool,
+
+    /// Whether to hide the provider from the login page while its last health
+    /// check failed
+    #[serde(default)]
+    pub hide_when_unhealthy: bool,
+}
+
+/// How the provider should send the authorization response back
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseMode {
+    /// In the query of the redirect URI
+    Query,
+
+    /// In the body of a `POST` request to the redirect URI
+    FormPost,
+}
+
+/// Parameters added to the authorization requests sent to the provider
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct AuthorizationParams {
+    /// The `response_mode` to ask for. The parameter is not sent if not set
+    #[serde(default)]
+    pub response_mode: Option<ResponseMode>,
+
+    /// The `prompt` parameter, as a space-separated list of values
+    #[serde(default)]
+    pub prompt: Option<String>,
+
+    /// Whether to forward the `login_hint` given when starting the login to
+    /// the provider
+    #[serde(default)]
+    pub forward_login_hint: bool,
+
+    /// The `max_age` parameter, in seconds. The parameter is not sent if not
+    /// set
+    #[serde(default)]
+    pub max_age: Option<NonZeroU32>,
+
+    /// How long, in seconds, a login through the provider is trusted before
+    /// checking again that the user still has a session with it, through an
+    /// authorization request with `prompt=none`
+    #[serde(default)]
+    pub revalidation_interval: Option<NonZeroU32>,
+
+    /// Other parameters to add to the authorization request
+    #[serde(default)]
+    pub additional_parameters: Vec<(String, String)>,
+}
+
+impl AuthorizationParams {
+    /// Whether a login through the provider which happened at the given time
+    /// should be re-validated
+    #[must_use]
+    pub fn requires_revalidation(
+        &self,
+        authenticated_at: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> bool {
+        self.revalidation_interval.is_some_and(|interval| {
+            now - authenticated_at > Duration::seconds(interval.get().into())
+        })
+    }
+}
+
+/// Whether to use PKCE when talking to the upstream provider
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PkceMethod {
+    /// Use PKCE if the provider advertises support for the `S256` method in
+    /// its discovery document
+    #[default]
+    Auto,
+
+    /// Always use PKCE with the `S256` method, even if the provider doesn't
+    /// advertise support for it
+    Always,
+
+    /// Never use PKCE
+    Never,
+}
+
+impl PkceMethod {
+    /// All the PKCE methods
+    pub const ALL: [Self; 3] = [Self::Auto, Self::Always, Self::Never];
+
+    /// The name of the method, as stored in the database and in the
+    /// configuration
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Auto => "auto",
+            Self::Always => "always",
+            Self::Never => "never",
+        }
+    }
+
+    /// Find a method by its name
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|m| m.as_str() == name)
+    }
 }
 
 /// Whether to set the email as verified when importing it from the upstream
@@ -65,17 +360,115 @@ pub struct ClaimsImports {
     #[serde(default)]
     pub displayname: ImportPreference,
 
+    #[serde(default)]
+    pub avatar_url: ImportPreference,
+
     #[serde(default)]
     pub email: ImportPreference,
 
     #[serde(default)]
     pub verify_email: SetEmailVerification,
+
+    #[serde(default)]
+    pub groups: GroupsImport,
+
+    #[serde(default)]
+    pub localpart_conflict: LocalpartConflict,
+
+    /// Whether to link the upstream account to the existing user with the same
+    /// verified email address, when the provider asserts it is verified
+    #[serde(default)]
+    pub link_by_email: bool,
+
+    /// A template to derive the subject identifying the user from the claims,
+    /// instead of using the `sub` claim
+    #[serde(default)]
+    pub subject_template: Option<String>,
+
+    /// The attributes managed by the provider, which its users can't change
+    /// themselves
+    #[serde(default)]
+    pub locked_attributes: Vec<ProfileAttribute>,
+}
+
+/// What to do when the localpart forced by the upstream provider is already
+/// taken
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LocalpartConflict {
+    /// Fail the registration
+    #[default]
+    Fail,
+
+    /// Add a number to the localpart until it is available
+    AddSuffix,
+
+    /// Let the user choose another localpart
+    Interactive,
+
+    /// Link the upstream account to the existing user, once the user proved
+    /// they own it
+    Link,
+}
+
+/// How to import the groups of the user from the upstream provider
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct GroupsImport {
+    /// The claim holding the groups, or `None` if they are not imported
+    #[serde(default)]
+    pub claim: Option<String>,
+
+    /// Users in any of those groups are allowed to request admin access, and
+    /// lose it when they leave them
+    #[serde(default)]
+    pub admin_groups: Vec<String>,
+}
+
+impl GroupsImport {
+    /// Whether the user is allowed to request admin access, based on their
+    /// groups.
+    ///
+    /// Returns `None` if no admin group is configured, in which case the admin
+    /// access of the user is left as is.
+    #[must_use]
+    pub fn grants_admin(&self, groups: &[String]) -> Option<bool> {
+        if self.admin_groups.is_empty() {
+            return None;
+        }
+
+        Some(groups.iter().any(|group| self.admin_groups.contains(group)))
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub struct ImportPreference {
     #[serde(default)]
     pub action: ImportAction,
+
+    #[serde(default)]
+    pub sync: ImportSync,
+
+    /// A template rendered over the claims to get the value, instead of using
+    /// the standard claim
+    #[serde(default)]
+    pub template: Option<String>,
+}
+
+impl ImportPreference {
+    /// Whether the claim should be imported again when the user logs in, given
+    /// whether the user changed the value themselves since it was imported
+    #[must_use]
+    pub fn sync_on_login(&self, modified_by_user: bool) -> bool {
+        if self.action.ignore() {
+            return false;
+        }
+
+        match self.sync {
+            ImportSync::Once => false,
+            ImportSync::Always => true,
+            ImportSync::UnlessModified => !modified_by_user,
+        }
+    }
 }
 
 impl std::ops::Deref for ImportPreference {
@@ -119,3 +512,21 @@ impl ImportAction {
         matches!(self, Self::Require)
     }
 }
+
+/// When to import the claim value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportSync {
+    /// Only import the claim when the user registers
+    #[default]
+    Once,
+
+    /// Import the claim when the user registers, and again every time they log
+    /// in through the upstream provider
+    Always,
+
+    /// Import the claim when the user registers, and again every time they log
+    /// in through the upstream provider, until they change the value
+    /// themselves
+    UnlessModified,
+}
@@ -14,6 +14,7 @@
 
 use chrono::{DateTime, Utc};
 use serde::Serialize;
+use serde_json::Value;
 use ulid::Ulid;
 
 use super::UpstreamOAuthLink;
@@ -27,12 +28,14 @@ pub enum UpstreamOAuthAuthorizationSessionState {
         completed_at: DateTime<Utc>,
         link_id: Ulid,
         id_token: Option<String>,
+        userinfo: Option<Value>,
     },
     Consumed {
         completed_at: DateTime<Utc>,
         consumed_at: DateTime<Utc>,
         link_id: Ulid,
         id_token: Option<String>,
+        userinfo: Option<Value>,
     },
 }
 
@@ -42,12 +45,14 @@ impl UpstreamOAuthAuthorizationSessionState {
         completed_at: DateTime<Utc>,
         link: &UpstreamOAuthLink,
         id_token: Option<String>,
+        userinfo: Option<Value>,
     ) -> Result<Self, InvalidTransitionError> {
         match self {
             Self::Pending => Ok(Self::Completed {
                 completed_at,
                 link_id: link.id,
                 id_token,
+                userinfo,
             }),
             Self::Completed { .. } | Self::Consumed { .. } => Err(InvalidTransitionError),
         }
@@ -59,11 +64,13 @@ impl UpstreamOAuthAuthorizationSessionState {
                 completed_at,
                 link_id,
                 id_token,
+                userinfo,
             } => Ok(Self::Consumed {
                 completed_at,
                 link_id,
                 consumed_at,
                 id_token,
+                userinfo,
             }),
             Self::Pending | Self::Consumed { .. } => Err(InvalidTransitionError),
         }
@@ -97,6 +104,16 @@ impl UpstreamOAuthAuthorizationSessionState {
         }
     }
 
+    /// The claims returned by the userinfo endpoint of the provider, if it
+    /// was queried
+    #[must_use]
+    pub fn userinfo(&self) -> Option<&Value> {
+        match self {
+            Self::Pending => None,
+            Self::Completed { userinfo, .. } | Self::Consumed { userinfo, .. } => userinfo.as_ref(),
+        }
+    }
+
     #[must_use]
     pub fn consumed_at(&self) -> Option<DateTime<Utc>> {
         match self {
@@ -158,8 +175,11 @@ impl UpstreamOAuthAuthorizationSession {
         completed_at: DateTime<Utc>,
         link: &UpstreamOAuthLink,
         id_token: Option<String>,
+        userinfo: Option<Value>,
     ) -> Result<Self, InvalidTransitionError> {
-        self.state = self.state.complete(completed_at, link, id_token)?;
+        self.state = self
+            .state
+            .complete(completed_at, link, id_token, userinfo)?;
         Ok(self)
     }
 
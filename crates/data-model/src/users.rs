@@ -16,7 +16,7 @@ use std::{net::IpAddr, ops::Deref};
 
 use chrono::{DateTime, Duration, Utc};
 use rand::{Rng, SeedableRng};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use ulid::Ulid;
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
@@ -27,7 +27,87 @@ pub struct User {
     pub primary_user_email_id: Option<Ulid>,
     pub created_at: DateTime<Utc>,
     pub locked_at: Option<DateTime<Utc>>,
+    pub quarantined_at: Option<DateTime<Utc>>,
     pub can_request_admin: bool,
+    pub is_guest: bool,
+    /// The preferred locale of the user, used to render the emails sent to
+    /// them
+    pub locale: Option<String>,
+    /// The groups of the user, as imported from the upstream provider they
+    /// last logged in with
+    pub upstream_groups: Vec<String>,
+}
+
+/// A category of security-relevant account events users get notified about
+/// by email
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SecurityNotification {
+    /// The password of the user was changed
+    PasswordChange,
+
+    /// An upstream account was linked to the user
+    UpstreamLink,
+
+    /// The user was locked
+    AccountLock,
+}
+
+impl SecurityNotification {
+    /// All the categories of security notifications
+    pub const ALL: [Self; 3] = [Self::PasswordChange, Self::UpstreamLink, Self::AccountLock];
+
+    /// The name of the category, as stored in the database
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::PasswordChange => "password_change",
+            Self::UpstreamLink => "upstream_link",
+            Self::AccountLock => "account_lock",
+        }
+    }
+
+    /// Find a category by its name
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.as_str() == name)
+    }
+}
+
+/// An attribute of the profile of a user which can be imported from an
+/// upstream provider
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProfileAttribute {
+    /// The display name of the user on the homeserver
+    Displayname,
+
+    /// The avatar of the user on the homeserver
+    AvatarUrl,
+
+    /// The email addresses of the user
+    Email,
+}
+
+impl ProfileAttribute {
+    /// All the profile attributes
+    pub const ALL: [Self; 3] = [Self::Displayname, Self::AvatarUrl, Self::Email];
+
+    /// The name of the attribute, as stored in the database
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Displayname => "displayname",
+            Self::AvatarUrl => "avatar_url",
+            Self::Email => "email",
+        }
+    }
+
+    /// Find an attribute by its name
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|a| a.as_str() == name)
+    }
 }
 
 impl User {
@@ -36,6 +116,15 @@ impl User {
     pub fn is_valid(&self) -> bool {
         self.locked_at.is_none()
     }
+
+    /// Returns `true` if the user is quarantined.
+    ///
+    /// Quarantined users can still use their existing sessions, but can't be
+    /// granted new ones.
+    #[must_use]
+    pub fn is_quarantined(&self) -> bool {
+        self.quarantined_at.is_some()
+    }
 }
 
 impl User {
@@ -48,7 +137,11 @@ impl User {
             primary_user_email_id: None,
             created_at: now,
             locked_at: None,
+            quarantined_at: None,
             can_request_admin: false,
+            is_guest: false,
+            locale: None,
+            upstream_groups: Vec::new(),
         }]
     }
 }
@@ -76,6 +169,52 @@ pub enum AuthenticationMethod {
     Unknown,
 }
 
+/// Details about a [`BrowserSession`] which was started by an administrator to
+/// act on behalf of the user
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Impersonation {
+    /// The ID of the administrator who started the session
+    pub impersonator_user_id: Ulid,
+
+    /// When the session stops being usable
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Audit record of an administrator impersonating a user
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UserImpersonation {
+    pub id: Ulid,
+
+    /// The ID of the administrator who impersonated the user
+    pub impersonator_user_id: Ulid,
+
+    /// The ID of the impersonated user
+    pub user_id: Ulid,
+
+    /// The ID of the [`BrowserSession`] used to impersonate the user
+    pub user_session_id: Ulid,
+
+    /// Why the administrator impersonated the user
+    pub reason: String,
+
+    pub started_at: DateTime<Utc>,
+
+    /// When the impersonation session expires
+    pub expires_at: DateTime<Utc>,
+
+    /// When the impersonation session was ended, if it was before it expired
+    pub ended_at: Option<DateTime<Utc>>,
+}
+
+impl UserImpersonation {
+    /// When the impersonation ended, or will end
+    #[must_use]
+    pub fn end(&self) -> DateTime<Utc> {
+        self.ended_at
+            .map_or(self.expires_at, |ended_at| ended_at.min(self.expires_at))
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct BrowserSession {
     pub id: Ulid,
@@ -85,6 +224,7 @@ pub struct BrowserSession {
     pub user_agent: Option<String>,
     pub last_active_at: Option<DateTime<Utc>>,
     pub last_active_ip: Option<IpAddr>,
+    pub impersonation: Option<Impersonation>,
 }
 
 impl BrowserSession {
@@ -92,6 +232,24 @@ impl BrowserSession {
     pub fn active(&self) -> bool {
         self.finished_at.is_none() && self.user.is_valid()
     }
+
+    /// Returns `true` if the session is active and, in case it is an
+    /// impersonation session, if it has not expired yet.
+    #[must_use]
+    pub fn active_at(&self, now: DateTime<Utc>) -> bool {
+        self.active()
+            && self
+                .impersonation
+                .as_ref()
+                .map_or(true, |impersonation| impersonation.expires_at > now)
+    }
+
+    /// Returns `true` if this session was started by an administrator
+    /// impersonating the user
+    #[must_use]
+    pub fn is_impersonation(&self) -> bool {
+        self.impersonation.is_some()
+    }
 }
 
 impl BrowserSession {
@@ -107,6 +265,7 @@ impl BrowserSession {
                 user_agent: Some("Mozilla/5.0".to_owned()),
                 last_active_at: Some(now),
                 last_active_ip: None,
+                impersonation: None,
             })
             .collect()
     }
@@ -143,6 +302,237 @@ impl UserEmail {
     }
 }
 
+/// A change of the primary email address of a user
+///
+/// Once the new address is verified, the old address has to confirm the change
+/// within [`UserEmailChange::grace_period`]. After the switch-over, the change
+/// can be reverted from the old address during the same period, after which
+/// the change is finished and the old address removed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UserEmailChange {
+    pub id: Ulid,
+    pub user_id: Ulid,
+    pub old_user_email_id: Ulid,
+    pub new_user_email_id: Ulid,
+    #[serde(skip)]
+    pub revert_token: String,
+    pub created_at: DateTime<Utc>,
+    pub verified_at: Option<DateTime<Utc>>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub reverted_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+impl UserEmailChange {
+    /// How long the old address has to confirm the change, and how long after
+    /// the switch-over the change can be reverted
+    #[must_use]
+    pub fn grace_period() -> Duration {
+        Duration::days(7)
+    }
+
+    /// Whether the switch-over did not happen yet
+    #[must_use]
+    pub fn is_pending(&self) -> bool {
+        self.completed_at.is_none() && self.reverted_at.is_none()
+    }
+
+    /// Until when the old address can confirm the change, if the new address
+    /// was verified and the switch-over did not happen yet
+    #[must_use]
+    pub fn confirmable_until(&self) -> Option<DateTime<Utc>> {
+        if !self.is_pending() {
+            return None;
+        }
+
+        self.verified_at
+            .map(|verified_at| verified_at + Self::grace_period())
+    }
+
+    /// Whether the change is waiting for the old address to confirm it at the
+    /// given time
+    #[must_use]
+    pub fn is_awaiting_confirmation(&self, now: DateTime<Utc>) -> bool {
+        self.confirmable_until().is_some_and(|until| now < until)
+    }
+
+    /// Until when the change can be reverted, if it was completed and not
+    /// reverted yet
+    #[must_use]
+    pub fn revertable_until(&self) -> Option<DateTime<Utc>> {
+        if self.reverted_at.is_some() || self.finished_at.is_some() {
+            return None;
+        }
+
+        self.completed_at
+            .map(|completed_at| completed_at + Self::grace_period())
+    }
+
+    /// Whether the change can be reverted at the given time
+    #[must_use]
+    pub fn can_revert(&self, now: DateTime<Utc>) -> bool {
+        self.revertable_until().is_some_and(|until| now < until)
+    }
+
+    /// Whether the change is in progress at the given time, in which case the
+    /// addresses involved can't be removed or made primary
+    #[must_use]
+    pub fn is_in_progress(&self, now: DateTime<Utc>) -> bool {
+        self.is_awaiting_confirmation(now) || self.can_revert(now)
+    }
+
+    #[must_use]
+    pub fn samples(now: chrono::DateTime<Utc>, rng: &mut impl Rng) -> Vec<Self> {
+        let user_id = Ulid::from_datetime_with_source(now.into(), rng);
+        let old_user_email_id = Ulid::from_datetime_with_source(now.into(), rng);
+        let new_user_email_id = Ulid::from_datetime_with_source(now.into(), rng);
+
+        vec![
+            Self {
+                id: Ulid::from_datetime_with_source(now.into(), rng),
+                user_id,
+                old_user_email_id,
+                new_user_email_id,
+                revert_token: "112233445566".to_owned(),
+                created_at: now - Duration::hours(1),
+                verified_at: Some(now),
+                completed_at: None,
+                reverted_at: None,
+                finished_at: None,
+            },
+            Self {
+                id: Ulid::from_datetime_with_source(now.into(), rng),
+                user_id,
+                old_user_email_id,
+                new_user_email_id,
+                revert_token: "aabbccddeeff".to_owned(),
+                created_at: now - Duration::hours(1),
+                verified_at: Some(now - Duration::minutes(30)),
+                completed_at: Some(now),
+                reverted_at: None,
+                finished_at: None,
+            },
+            Self {
+                id: Ulid::from_datetime_with_source(now.into(), rng),
+                user_id,
+                old_user_email_id,
+                new_user_email_id,
+                revert_token: "ffeeddccbbaa".to_owned(),
+                created_at: now - Duration::hours(1),
+                verified_at: Some(now - Duration::minutes(45)),
+                completed_at: Some(now - Duration::minutes(30)),
+                reverted_at: Some(now),
+                finished_at: None,
+            },
+        ]
+    }
+}
+
+/// Limits on how often verification emails can be sent, to avoid being used
+/// to flood someone's inbox
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EmailRateLimits {
+    /// Minimum delay between two emails sent to the same address
+    pub resend_cooldown: Duration,
+
+    /// Period over which the emails are counted
+    pub window: Duration,
+
+    /// Maximum number of emails sent to a single address during the window,
+    /// across all users
+    pub per_address: usize,
+
+    /// Maximum number of emails sent for a single user during the window,
+    /// across all their addresses
+    pub per_user: usize,
+}
+
+impl Default for EmailRateLimits {
+    fn default() -> Self {
+        Self {
+            resend_cooldown: Duration::minutes(1),
+            window: Duration::hours(1),
+            per_address: 5,
+            per_user: 10,
+        }
+    }
+}
+
+/// How many verification emails were recently sent, as recorded in the
+/// database
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EmailSendCounts {
+    /// When the last email was sent to the address
+    pub last_sent_to_address: Option<DateTime<Utc>>,
+
+    /// Number of emails sent to the address since the start of the window
+    pub sent_to_address: usize,
+
+    /// Number of emails sent for the user since the start of the window
+    pub sent_for_user: usize,
+}
+
+/// Why sending a verification email was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum EmailRateLimited {
+    #[error("an email was sent to this address recently, retry after {retry_after}")]
+    Cooldown { retry_after: DateTime<Utc> },
+
+    #[error("too many emails were sent to this address")]
+    TooManyForAddress,
+
+    #[error("too many emails were sent for this user")]
+    TooManyForUser,
+}
+
+impl EmailRateLimited {
+    /// When sending can be retried, if known
+    #[must_use]
+    pub fn retry_after(&self) -> Option<DateTime<Utc>> {
+        match self {
+            Self::Cooldown { retry_after } => Some(*retry_after),
+            Self::TooManyForAddress | Self::TooManyForUser => None,
+        }
+    }
+}
+
+impl EmailRateLimits {
+    /// When the window of the counters starts, relative to `now`
+    #[must_use]
+    pub fn window_start(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        now - self.window
+    }
+
+    /// Check whether another email can be sent given the recent ones
+    ///
+    /// # Errors
+    ///
+    /// Returns an error describing which limit was hit if sending is not
+    /// allowed
+    pub fn check(
+        &self,
+        now: DateTime<Utc>,
+        counts: &EmailSendCounts,
+    ) -> Result<(), EmailRateLimited> {
+        if let Some(last_sent) = counts.last_sent_to_address {
+            let retry_after = last_sent + self.resend_cooldown;
+            if now < retry_after {
+                return Err(EmailRateLimited::Cooldown { retry_after });
+            }
+        }
+
+        if counts.sent_to_address >= self.per_address {
+            return Err(EmailRateLimited::TooManyForAddress);
+        }
+
+        if counts.sent_for_user >= self.per_user {
+            return Err(EmailRateLimited::TooManyForUser);
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub enum UserEmailVerificationState {
     AlreadyUsed { when: DateTime<Utc> },
@@ -0,0 +1,800 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Send emails through the HTTP APIs of email providers
+
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use headers::{Authorization, HeaderMapExt};
+use hmac::{Hmac, Mac};
+use http::{
+    header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE},
+    Request, Response, StatusCode,
+};
+use lettre::message::Mailbox;
+use mas_axum_utils::http_client_factory::HttpClientFactory;
+use mas_http::HttpServiceExt;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tower::{Service, ServiceExt};
+use url::Url;
+
+use crate::transport::Email;
+
+/// Credentials used to sign requests to the AWS APIs
+#[derive(Debug, Clone)]
+pub struct AwsCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+impl AwsCredentials {
+    /// Constructs a new set of AWS credentials
+    #[must_use]
+    pub fn new(access_key_id: String, secret_access_key: String) -> Self {
+        Self {
+            access_key_id,
+            secret_access_key,
+            session_token: None,
+        }
+    }
+
+    /// Set the session token, when using temporary credentials
+    #[must_use]
+    pub fn with_session_token(mut self, session_token: String) -> Self {
+        self.session_token = Some(session_token);
+        self
+    }
+}
+
+pub(crate) enum Provider {
+    AwsSes {
+        region: String,
+        credentials: AwsCredentials,
+    },
+    SendGrid {
+        api_key: String,
+    },
+    Mailgun {
+        endpoint: Url,
+        domain: String,
+        api_key: String,
+    },
+    Postmark {
+        server_token: String,
+        message_stream: Option<String>,
+    },
+}
+
+pub(crate) struct ApiTransport {
+    http_client_factory: HttpClientFactory,
+    provider: Provider,
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The provider refused the email, for example because the recipient is on
+    /// its suppression list after a bounce. Trying again won't help.
+    #[error("the email provider rejected the email: {0}")]
+    Rejected(String),
+
+    /// The provider failed to accept the email, but might accept it later
+    #[error("the email provider failed to accept the email: {status}: {message}")]
+    Failed { status: StatusCode, message: String },
+
+    /// The request to the provider could not be made
+    #[error("failed to call the email provider")]
+    Request(#[source] Box<dyn std::error::Error + Send + Sync + 'static>),
+}
+
+impl Error {
+    /// Whether the email was refused by the provider, and should not be sent
+    /// again
+    #[must_use]
+    pub fn is_rejection(&self) -> bool {
+        matches!(self, Self::Rejected(_))
+    }
+
+    fn request(e: impl std::error::Error + Send + Sync + 'static) -> Self {
+        Self::Request(Box::new(e))
+    }
+}
+
+impl ApiTransport {
+    pub(crate) fn new(http_client_factory: HttpClientFactory, provider: Provider) -> Self {
+        Self {
+            http_client_factory,
+            provider,
+        }
+    }
+
+    pub(crate) async fn send(&self, email: &Email) -> Result<(), Error> {
+        let (category, request) = match &self.provider {
+            Provider::AwsSes {
+                region,
+                credentials,
+            } => ("email.aws_ses", aws_ses_request(region, credentials, email)),
+            Provider::SendGrid { api_key } => ("email.sendgrid", sendgrid_request(api_key, email)),
+            Provider::Mailgun {
+                endpoint,
+                domain,
+                api_key,
+            } => (
+                "email.mailgun",
+                mailgun_request(endpoint, domain, api_key, email),
+            ),
+            Provider::Postmark {
+                server_token,
+                message_stream,
+            } => (
+                "email.postmark",
+                postmark_request(server_token, message_stream.as_deref(), email),
+            ),
+        };
+
+        let request = request?;
+
+        let mut client = self
+            .http_client_factory
+            .client(category)
+            .request_bytes_to_body()
+            .response_body_to_bytes();
+
+        let response = client
+            .ready()
+            .await
+            .map_err(Error::request)?
+            .call(request)
+            .await
+            .map_err(Error::request)?;
+
+        match &self.provider {
+            Provider::AwsSes { .. } => aws_ses_response(&response),
+            Provider::SendGrid { .. } => sendgrid_response(&response),
+            Provider::Mailgun { .. } => mailgun_response(&response),
+            Provider::Postmark { .. } => postmark_response(&response),
+        }
+    }
+}
+
+/// Requests which failed because of a rate limit or a server error can be
+/// retried, the others are permanent failures
+fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// Build the error for an unsuccessful response, given the message extracted
+/// from the provider-specific error body
+fn error_for_status(status: StatusCode, message: String) -> Error {
+    if is_retryable(status) {
+        Error::Failed { status, message }
+    } else {
+        Error::Rejected(message)
+    }
+}
+
+/// Try to parse the body of a response, falling back to the body as text
+fn parse_body<T: DeserializeOwned>(response: &Response<Bytes>) -> Result<T, String> {
+    serde_json::from_slice(response.body())
+        .map_err(|_| String::from_utf8_lossy(response.body()).into_owned())
+}
+
+fn address(mailbox: &Mailbox) -> serde_json::Value {
+    let mut value = json!({ "email": mailbox.email.to_string() });
+    if let Some(name) = &mailbox.name {
+        value["name"] = name.clone().into();
+    }
+    value
+}
+
+fn hex_sha256(data: impl AsRef<[u8]>) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key size");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Build an AWS Signature Version 4 `Authorization` header for a POST request
+/// without query parameters
+///
+/// The `headers` are the ones to sign, with lowercase names. They must include
+/// the `host` and `x-amz-date` headers.
+fn aws_sigv4_authorization(
+    now: DateTime<Utc>,
+    region: &str,
+    service: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    credentials: &AwsCredentials,
+    body: &[u8],
+) -> String {
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+
+    let mut headers = headers.to_vec();
+    headers.sort_unstable_by_key(|(name, _)| *name);
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+
+    let canonical_request = format!(
+        "POST\n{path}\n\n{canonical_headers}\n{signed_headers}\n{payload}",
+        payload = hex_sha256(body),
+    );
+
+    let scope = format!("{date}/{region}/{service}/aws4_request");
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{request}",
+        request = hex_sha256(canonical_request),
+    );
+
+    let key = format!("AWS4{}", credentials.secret_access_key);
+    let key = hmac_sha256(key.as_bytes(), &date);
+    let key = hmac_sha256(&key, region);
+    let key = hmac_sha256(&key, service);
+    let key = hmac_sha256(&key, "aws4_request");
+    let signature: String = hmac_sha256(&key, &string_to_sign)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+
+    format!(
+        "AWS4-HMAC-SHA256 Credential={access_key_id}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+        access_key_id = credentials.access_key_id,
+    )
+}
+
+fn aws_ses_request(
+    region: &str,
+    credentials: &AwsCredentials,
+    email: &Email,
+) -> Result<Request<Bytes>, Error> {
+    let host = format!("email.{region}.amazonaws.com");
+    let path = "/v2/email/outbound-emails";
+
+    let body = json!({
+        "FromEmailAddress": email.from.to_string(),
+        "ReplyToAddresses": [email.reply_to.to_string()],
+        "Destination": {
+            "ToAddresses": [email.to.to_string()],
+        },
+        "Content": {
+            "Simple": {
+                "Subject": { "Data": email.subject, "Charset": "UTF-8" },
+                "Body": {
+                    "Text": { "Data": email.text, "Charset": "UTF-8" },
+                    "Html": { "Data": email.html, "Charset": "UTF-8" },
+                },
+            },
+        },
+    });
+    let body = Bytes::from(body.to_string());
+
+    // The signature has to be computed against the current time
+    #[allow(clippy::disallowed_methods)]
+    let now = Utc::now();
+
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+
+    let mut headers = vec![
+        ("content-type", "application/json"),
+        ("host", host.as_str()),
+        ("x-amz-date", amz_date.as_str()),
+    ];
+    if let Some(session_token) = &credentials.session_token {
+        headers.push(("x-amz-security-token", session_token.as_str()));
+    }
+
+    let authorization =
+        aws_sigv4_authorization(now, region, "ses", path, &headers, credentials, &body);
+
+    let mut builder = Request::post(format!("https://{host}{path}"));
+    for (name, value) in headers {
+        builder = builder.header(name, value);
+    }
+
+    builder
+        .header(AUTHORIZATION, authorization)
+        .body(body)
+        .map_err(Error::request)
+}
+
+#[derive(Deserialize)]
+struct AwsError {
+    #[serde(default, alias = "Message")]
+    message: String,
+}
+
+fn aws_ses_response(response: &Response<Bytes>) -> Result<(), Error> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(());
+    }
+
+    // The type of error is in a header, e.g. `MessageRejected` or
+    // `TooManyRequestsException`
+    let kind = response
+        .headers()
+        .get("x-amzn-errortype")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(':').next())
+        .unwrap_or("UnknownError")
+        .to_owned();
+
+    let message = match parse_body::<AwsError>(response) {
+        Ok(error) => format!("{kind}: {}", error.message),
+        Err(body) => format!("{kind}: {body}"),
+    };
+
+    match kind.as_str() {
+        // Those are only fixed by changing the configuration or by AWS support,
+        // but not by sending the same email again
+        "MessageRejected"
+        | "MailFromDomainNotVerifiedException"
+        | "AccountSuspendedException"
+        | "SendingPausedException"
+        | "NotFoundException"
+        | "BadRequestException" => Err(Error::Rejected(message)),
+        _ => Err(Error::Failed { status, message }),
+    }
+}
+
+fn sendgrid_request(api_key: &str, email: &Email) -> Result<Request<Bytes>, Error> {
+    let body = json!({
+        "personalizations": [{ "to": [address(&email.to)] }],
+        "from": address(&email.from),
+        "reply_to": address(&email.reply_to),
+        "subject": email.subject,
+        "content": [
+            { "type": "text/plain", "value": email.text },
+            { "type": "text/html", "value": email.html },
+        ],
+    });
+
+    let mut request = Request::post("https://api.sendgrid.com/v3/mail/send")
+        .header(CONTENT_TYPE, "application/json")
+        .body(Bytes::from(body.to_string()))
+        .map_err(Error::request)?;
+    request
+        .headers_mut()
+        .typed_insert(Authorization::bearer(api_key).map_err(Error::request)?);
+    Ok(request)
+}
+
+#[derive(Deserialize)]
+struct SendGridErrors {
+    errors: Vec<SendGridError>,
+}
+
+#[derive(Deserialize)]
+struct SendGridError {
+    message: String,
+}
+
+fn sendgrid_response(response: &Response<Bytes>) -> Result<(), Error> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(());
+    }
+
+    let message = match parse_body::<SendGridErrors>(response) {
+        Ok(body) => body
+            .errors
+            .into_iter()
+            .map(|e| e.message)
+            .collect::<Vec<_>>()
+            .join(", "),
+        Err(body) => body,
+    };
+
+    Err(error_for_status(status, message))
+}
+
+#[derive(Serialize)]
+struct MailgunMessage<'a> {
+    from: String,
+    to: String,
+    subject: &'a str,
+    text: &'a str,
+    html: &'a str,
+    #[serde(rename = "h:Reply-To")]
+    reply_to: String,
+}
+
+fn mailgun_request(
+    endpoint: &Url,
+    domain: &str,
+    api_key: &str,
+    email: &Email,
+) -> Result<Request<Bytes>, Error> {
+    // Append to the path of the endpoint, as `Url::join` would replace its last
+    // segment if it doesn't end with a slash
+    let mut url = endpoint.clone();
+    url.path_segments_mut()
+        .map_err(|()| Error::Request("the Mailgun endpoint is not a valid base URL".into()))?
+        .pop_if_empty()
+        .extend(["v3", domain, "messages"]);
+
+    let body = MailgunMessage {
+        from: email.from.to_string(),
+        to: email.to.to_string(),
+        subject: &email.subject,
+        text: &email.text,
+        html: &email.html,
+        reply_to: email.reply_to.to_string(),
+    };
+    let body = serde_urlencoded::to_string(&body).map_err(Error::request)?;
+
+    let mut request = Request::post(url.as_str())
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(Bytes::from(body))
+        .map_err(Error::request)?;
+    request
+        .headers_mut()
+        .typed_insert(Authorization::basic("api", api_key));
+    Ok(request)
+}
+
+#[derive(Deserialize)]
+struct MailgunError {
+    message: String,
+}
+
+fn mailgun_response(response: &Response<Bytes>) -> Result<(), Error> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(());
+    }
+
+    let message = match parse_body::<MailgunError>(response) {
+        Ok(body) => body.message,
+        Err(body) => body,
+    };
+
+    Err(error_for_status(status, message))
+}
+
+fn postmark_request(
+    server_token: &str,
+    message_stream: Option<&str>,
+    email: &Email,
+) -> Result<Request<Bytes>, Error> {
+    let mut body = json!({
+        "From": email.from.to_string(),
+        "To": email.to.to_string(),
+        "ReplyTo": email.reply_to.to_string(),
+        "Subject": email.subject,
+        "TextBody": email.text,
+        "HtmlBody": email.html,
+    });
+    if let Some(message_stream) = message_stream {
+        body["MessageStream"] = message_stream.into();
+    }
+
+    Request::post("https://api.postmarkapp.com/email")
+        .header(CONTENT_TYPE, "application/json")
+        .header(ACCEPT, "application/json")
+        .header("x-postmark-server-token", server_token)
+        .body(Bytes::from(body.to_string()))
+        .map_err(Error::request)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkResponse {
+    error_code: u32,
+    message: String,
+}
+
+fn postmark_response(response: &Response<Bytes>) -> Result<(), Error> {
+    let status = response.status();
+
+    let body = match parse_body::<PostmarkResponse>(response) {
+        Ok(body) => body,
+        Err(_) if status.is_success() => return Ok(()),
+        Err(body) => return Err(error_for_status(status, body)),
+    };
+
+    match body.error_code {
+        0 => Ok(()),
+        // Invalid or inactive recipient, e.g. after a hard bounce or a spam
+        // complaint
+        300 | 406 => Err(Error::Rejected(body.message)),
+        code => Err(Error::Failed {
+            status,
+            message: format!("{code}: {message}", message = body.message),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use lettre::Message;
+
+    use super::*;
+
+    fn email() -> Email {
+        let from: Mailbox = "Example <noreply@example.com>".parse().unwrap();
+        let reply_to: Mailbox = "support@example.com".parse().unwrap();
+        let to: Mailbox = "Alice <alice@example.org>".parse().unwrap();
+        let message = Message::builder()
+            .from(from.clone())
+            .reply_to(reply_to.clone())
+            .to(to.clone())
+            .subject("Hello")
+            .body("Hello, Alice".to_owned())
+            .unwrap();
+
+        Email {
+            message,
+            from,
+            reply_to,
+            to,
+            subject: "Hello".to_owned(),
+            text: "Hello, Alice".to_owned(),
+            html: "<p>Hello, Alice</p>".to_owned(),
+        }
+    }
+
+    fn json_body(request: &Request<Bytes>) -> serde_json::Value {
+        serde_json::from_slice(request.body()).unwrap()
+    }
+
+    fn header<'a>(request: &'a Request<Bytes>, name: &str) -> &'a str {
+        request.headers().get(name).unwrap().to_str().unwrap()
+    }
+
+    /// Test vectors from the AWS Signature Version 4 test suite
+    #[test]
+    fn test_aws_sigv4_vectors() {
+        let credentials = AwsCredentials::new(
+            "AKIDEXAMPLE".to_owned(),
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_owned(),
+        );
+        let now = Utc.with_ymd_and_hms(2015, 8, 30, 12, 36, 0).unwrap();
+
+        // post-vanilla
+        let authorization = aws_sigv4_authorization(
+            now,
+            "us-east-1",
+            "service",
+            "/",
+            &[
+                ("host", "example.amazonaws.com"),
+                ("x-amz-date", "20150830T123600Z"),
+            ],
+            &credentials,
+            b"",
+        );
+        assert_eq!(
+            authorization,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=host;x-amz-date, \
+             Signature=5da7c1a2acd57cee7505fc6676e4e544621c30862966e37dddb68e92efbe5d6b"
+        );
+
+        // post-x-www-form-urlencoded, with the headers in a different order
+        let authorization = aws_sigv4_authorization(
+            now,
+            "us-east-1",
+            "service",
+            "/",
+            &[
+                ("x-amz-date", "20150830T123600Z"),
+                ("host", "example.amazonaws.com"),
+                ("content-type", "application/x-www-form-urlencoded"),
+            ],
+            &credentials,
+            b"Param1=value1",
+        );
+        assert_eq!(
+            authorization,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=content-type;host;x-amz-date, \
+             Signature=ff11897932ad3f4e8b18135d722051e5ac45fc38421b1da7b9d196a0fe09473a"
+        );
+    }
+
+    #[test]
+    fn test_aws_ses_request() {
+        let credentials = AwsCredentials::new("AKID".to_owned(), "secret".to_owned())
+            .with_session_token("token".to_owned());
+        let request = aws_ses_request("eu-west-1", &credentials, &email()).unwrap();
+
+        assert_eq!(
+            request.uri(),
+            "https://email.eu-west-1.amazonaws.com/v2/email/outbound-emails"
+        );
+        assert_eq!(header(&request, "host"), "email.eu-west-1.amazonaws.com");
+        assert_eq!(header(&request, "content-type"), "application/json");
+        assert_eq!(header(&request, "x-amz-security-token"), "token");
+
+        // The signature covers the headers which were sent
+        let amz_date = header(&request, "x-amz-date");
+        let now = Utc.from_utc_datetime(
+            &chrono::NaiveDateTime::parse_from_str(amz_date, "%Y%m%dT%H%M%SZ").unwrap(),
+        );
+        let expected = aws_sigv4_authorization(
+            now,
+            "eu-west-1",
+            "ses",
+            "/v2/email/outbound-emails",
+            &[
+                ("content-type", "application/json"),
+                ("host", "email.eu-west-1.amazonaws.com"),
+                ("x-amz-date", amz_date),
+                ("x-amz-security-token", "token"),
+            ],
+            &credentials,
+            request.body(),
+        );
+        assert_eq!(header(&request, "authorization"), expected);
+        assert!(
+            expected.contains("SignedHeaders=content-type;host;x-amz-date;x-amz-security-token")
+        );
+
+        let body = json_body(&request);
+        assert_eq!(body["FromEmailAddress"], "Example <noreply@example.com>");
+        assert_eq!(body["ReplyToAddresses"][0], "support@example.com");
+        assert_eq!(
+            body["Destination"]["ToAddresses"][0],
+            "Alice <alice@example.org>"
+        );
+        assert_eq!(body["Content"]["Simple"]["Subject"]["Data"], "Hello");
+        assert_eq!(
+            body["Content"]["Simple"]["Body"]["Html"]["Data"],
+            "<p>Hello, Alice</p>"
+        );
+    }
+
+    #[test]
+    fn test_sendgrid_request() {
+        let request = sendgrid_request("api-key", &email()).unwrap();
+
+        assert_eq!(request.uri(), "https://api.sendgrid.com/v3/mail/send");
+        assert_eq!(header(&request, "authorization"), "Bearer api-key");
+        assert_eq!(header(&request, "content-type"), "application/json");
+
+        let body = json_body(&request);
+        assert_eq!(
+            body["personalizations"][0]["to"][0],
+            json!({ "email": "alice@example.org", "name": "Alice" })
+        );
+        assert_eq!(
+            body["from"],
+            json!({ "email": "noreply@example.com", "name": "Example" })
+        );
+        assert_eq!(body["reply_to"], json!({ "email": "support@example.com" }));
+        assert_eq!(body["subject"], "Hello");
+        assert_eq!(body["content"][0]["type"], "text/plain");
+        assert_eq!(body["content"][1]["value"], "<p>Hello, Alice</p>");
+    }
+
+    #[test]
+    fn test_mailgun_request() {
+        let endpoint = Url::parse("https://api.eu.mailgun.net/").unwrap();
+        let request = mailgun_request(&endpoint, "mg.example.com", "api-key", &email()).unwrap();
+
+        assert_eq!(
+            request.uri(),
+            "https://api.eu.mailgun.net/v3/mg.example.com/messages"
+        );
+        // Basic auth with the `api` user
+        assert_eq!(header(&request, "authorization"), "Basic YXBpOmFwaS1rZXk=");
+        assert_eq!(
+            header(&request, "content-type"),
+            "application/x-www-form-urlencoded"
+        );
+
+        let body: Vec<(String, String)> = serde_urlencoded::from_bytes(request.body()).unwrap();
+        assert!(body.contains(&("to".to_owned(), "Alice <alice@example.org>".to_owned())));
+        assert!(body.contains(&("h:Reply-To".to_owned(), "support@example.com".to_owned())));
+        assert!(body.contains(&("html".to_owned(), "<p>Hello, Alice</p>".to_owned())));
+
+        // The path of the endpoint is kept, with or without a trailing slash
+        for endpoint in [
+            "https://proxy.example.com/mailgun",
+            "https://proxy.example.com/mailgun/",
+        ] {
+            let endpoint = Url::parse(endpoint).unwrap();
+            let request =
+                mailgun_request(&endpoint, "mg.example.com", "api-key", &email()).unwrap();
+            assert_eq!(
+                request.uri(),
+                "https://proxy.example.com/mailgun/v3/mg.example.com/messages"
+            );
+        }
+    }
+
+    #[test]
+    fn test_postmark_request() {
+        let request = postmark_request("server-token", Some("outbound"), &email()).unwrap();
+
+        assert_eq!(request.uri(), "https://api.postmarkapp.com/email");
+        assert_eq!(header(&request, "x-postmark-server-token"), "server-token");
+        assert_eq!(header(&request, "accept"), "application/json");
+
+        let body = json_body(&request);
+        assert_eq!(body["From"], "Example <noreply@example.com>");
+        assert_eq!(body["To"], "Alice <alice@example.org>");
+        assert_eq!(body["ReplyTo"], "support@example.com");
+        assert_eq!(body["TextBody"], "Hello, Alice");
+        assert_eq!(body["MessageStream"], "outbound");
+
+        let request = postmark_request("server-token", None, &email()).unwrap();
+        assert!(json_body(&request).get("MessageStream").is_none());
+    }
+
+    #[test]
+    fn test_responses() {
+        let response = |status: u16, body: &str| {
+            Response::builder()
+                .status(status)
+                .body(Bytes::from(body.to_owned()))
+                .unwrap()
+        };
+
+        assert!(sendgrid_response(&response(202, "")).is_ok());
+        let error = sendgrid_response(&response(
+            400,
+            r#"{"errors": [{"message": "invalid address"}]}"#,
+        ))
+        .unwrap_err();
+        assert!(error.is_rejection());
+        assert!(!sendgrid_response(&response(503, "unavailable"))
+            .unwrap_err()
+            .is_rejection());
+
+        assert!(mailgun_response(&response(200, r#"{"message": "Queued"}"#)).is_ok());
+        assert!(
+            !mailgun_response(&response(429, r#"{"message": "Slow down"}"#))
+                .unwrap_err()
+                .is_rejection()
+        );
+
+        assert!(postmark_response(&response(200, r#"{"ErrorCode": 0, "Message": "OK"}"#)).is_ok());
+        assert!(postmark_response(&response(
+            422,
+            r#"{"ErrorCode": 406, "Message": "Inactive recipient"}"#
+        ))
+        .unwrap_err()
+        .is_rejection());
+
+        let mut throttled = response(429, r#"{"message": "Maximum sending rate exceeded."}"#);
+        throttled.headers_mut().insert(
+            "x-amzn-errortype",
+            "TooManyRequestsException:".parse().unwrap(),
+        );
+        assert!(!aws_ses_response(&throttled).unwrap_err().is_rejection());
+
+        let mut rejected = response(400, r#"{"message": "Email address is not verified."}"#);
+        rejected
+            .headers_mut()
+            .insert("x-amzn-errortype", "MessageRejected:".parse().unwrap());
+        assert!(aws_ses_response(&rejected).unwrap_err().is_rejection());
+    }
+}
@@ -23,15 +23,24 @@
 )]
 #![warn(clippy::pedantic)]
 
+mod api;
 mod mailer;
 mod transport;
 
 pub use lettre::{
-    message::Mailbox, transport::smtp::authentication::Credentials as SmtpCredentials, Address,
+    message::{
+        dkim::{DkimConfig, DkimSigningAlgorithm, DkimSigningKey},
+        Mailbox,
+    },
+    transport::smtp::authentication::Credentials as SmtpCredentials,
+    Address,
+};
+pub use mas_templates::{
+    EmailChangeNotificationContext, EmailVerificationContext, SecurityNotificationContext,
 };
-pub use mas_templates::EmailVerificationContext;
 
 pub use self::{
+    api::AwsCredentials,
     mailer::Mailer,
     transport::{SmtpMode, Transport as MailTransport},
 };
@@ -14,14 +14,20 @@
 
 //! Send emails to users
 
+use std::{sync::Arc, time::Duration};
+
 use lettre::{
-    message::{Mailbox, MessageBuilder, MultiPart},
-    AsyncTransport, Message,
+    message::{dkim::DkimConfig, Mailbox, MultiPart},
+    Message,
+};
+use mas_data_model::SecurityNotification;
+use mas_templates::{
+    AuthorizationApprovedContext, EmailChangeNotificationContext, EmailVerificationContext,
+    SecurityNotificationContext, Templates, WithLanguage,
 };
-use mas_templates::{EmailVerificationContext, Templates, WithLanguage};
 use thiserror::Error;
 
-use crate::MailTransport;
+use crate::{transport::Email, MailTransport};
 
 /// Helps sending mails to users
 #[derive(Clone)]
@@ -30,14 +36,71 @@ pub struct Mailer {
     transport: MailTransport,
     from: Mailbox,
     reply_to: Mailbox,
+    dkim: Option<Arc<DkimConfig>>,
+    timeout: Duration,
 }
 
 #[derive(Debug, Error)]
-#[error(transparent)]
 pub enum Error {
+    #[error(transparent)]
     Transport(#[from] crate::transport::Error),
+
+    #[error(transparent)]
     Templates(#[from] mas_templates::TemplateError),
+
+    #[error(transparent)]
     Content(#[from] lettre::error::Error),
+
+    #[error("timed out after {0:?} while sending the email")]
+    Timeout(Duration),
+}
+
+impl Error {
+    /// Whether the email was refused by the server or the provider, and
+    /// sending it again won't help
+    #[must_use]
+    pub fn is_rejection(&self) -> bool {
+        match self {
+            Self::Transport(e) => e.is_rejection(),
+            Self::Templates(_) | Self::Content(_) | Self::Timeout(_) => false,
+        }
+    }
+}
+
+/// Build the message of an email, and sign it if a DKIM configuration is given
+fn build_email(
+    from: &Mailbox,
+    reply_to: &Mailbox,
+    dkim: Option<&DkimConfig>,
+    to: Mailbox,
+    subject: &str,
+    text: String,
+    html: String,
+) -> Result<Email, Error> {
+    let multipart = MultiPart::alternative_plain_html(text.clone(), html.clone());
+
+    let subject = subject.trim().to_owned();
+
+    let mut message = Message::builder()
+        .from(from.clone())
+        .reply_to(reply_to.clone())
+        .subject(&subject)
+        .to(to.clone())
+        .multipart(multipart)?;
+
+    if let Some(dkim) = dkim {
+        message.sign(dkim);
+    }
+
+    Ok(Email {
+        message,
+        from: from.clone(),
+        reply_to: reply_to.clone(),
+        to,
+        subject,
+        text,
+        html,
+    })
 }
 
 impl Mailer {
@@ -54,35 +117,49 @@ impl Mailer {
             transport,
             from,
             reply_to,
+            dkim: None,
+            timeout: Duration::from_secs(60),
         }
     }
 
-    fn base_message(&self) -> MessageBuilder {
-        Message::builder()
-            .from(self.from.clone())
-            .reply_to(self.reply_to.clone())
+    /// Sign outgoing emails with the given DKIM configuration
+    #[must_use]
+    pub fn with_dkim(mut self, dkim: DkimConfig) -> Self {
+        self.dkim = Some(Arc::new(dkim));
+        self
+    }
+
+    /// Set how long sending a single email can take before giving up. Defaults
+    /// to 60 seconds.
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
     }
 
-    fn prepare_verification_email(
+    fn prepare_email(
         &self,
         to: Mailbox,
-        context: &WithLanguage<EmailVerificationContext>,
-    ) -> Result<Message, Error> {
-        let plain = self.templates.render_email_verification_txt(context)?;
-
-        let html = self.templates.render_email_verification_html(context)?;
-
-        let multipart = MultiPart::alternative_plain_html(plain, html);
-
-        let subject = self.templates.render_email_verification_subject(context)?;
-
-        let message = self
-            .base_message()
-            .subject(subject.trim())
-            .to(to)
-            .multipart(multipart)?;
+        subject: &str,
+        text: String,
+        html: String,
+    ) -> Result<Email, Error> {
+        build_email(
+            &self.from,
+            &self.reply_to,
+            self.dkim.as_deref(),
+            to,
+            subject,
+            text,
+            html,
+        )
+    }
 
-        Ok(message)
+    async fn send(&self, email: Email) -> Result<(), Error> {
+        tokio::time::timeout(self.timeout, self.transport.send(email))
+            .await
+            .map_err(|_| Error::Timeout(self.timeout))??;
+        Ok(())
     }
 
     /// Send the verification email to a user
@@ -107,9 +184,123 @@ impl Mailer {
         to: Mailbox,
         context: &WithLanguage<EmailVerificationContext>,
     ) -> Result<(), Error> {
-        let message = self.prepare_verification_email(to, context)?;
-        self.transport.send(message).await?;
-        Ok(())
+        let text = self.templates.render_email_verification_txt(context)?;
+        let html = self.templates.render_email_verification_html(context)?;
+        let subject = self.templates.render_email_verification_subject(context)?;
+
+        let email = self.prepare_email(to, &subject, text, html)?;
+        self.send(email).await
+    }
+
+    /// Notify the old address of a user that their primary email address
+    /// changed
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the email failed rendering or failed sending
+    #[tracing::instrument(
+        name = "email.email_change.send",
+        skip_all,
+        fields(
+            email.to = %to,
+            email.language = %context.language(),
+            user.id = %context.user().id,
+            old_user_email.id = %context.old_email().id,
+            new_user_email.id = %context.new_email().id,
+        ),
+        err,
+    )]
+    pub async fn send_email_change_notification(
+        &self,
+        to: Mailbox,
+        context: &WithLanguage<EmailChangeNotificationContext>,
+    ) -> Result<(), Error> {
+        let text = self.templates.render_email_change_txt(context)?;
+        let html = self.templates.render_email_change_html(context)?;
+        let subject = self.templates.render_email_change_subject(context)?;
+
+        let email = self.prepare_email(to, &subject, text, html)?;
+        self.send(email).await
+    }
+
+    /// Let a user know that an administrator approved their authorization
+    /// request, with a link to finish it
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the email failed rendering or failed sending
+    #[tracing::instrument(
+        name = "email.authorization_approved.send",
+        skip_all,
+        fields(
+            email.to = %to,
+            email.language = %context.language(),
+            user.id = %context.user().id,
+            client.id = %context.client().id,
+        ),
+        err,
+    )]
+    pub async fn send_authorization_approved(
+        &self,
+        to: Mailbox,
+        context: &WithLanguage<AuthorizationApprovedContext>,
+    ) -> Result<(), Error> {
+        let text = self
+            .templates
+            .render_email_authorization_approved_txt(context)?;
+        let html = self
+            .templates
+            .render_email_authorization_approved_html(context)?;
+        let subject = self
+            .templates
+            .render_email_authorization_approved_subject(context)?;
+
+        let email = self.prepare_email(to, &subject, text, html)?;
+        self.send(email).await
+    }
+
+    /// Notify a user about a security-relevant event on their account
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the email failed rendering or failed sending
+    #[tracing::instrument(
+        name = "email.security_notification.send",
+        skip_all,
+        fields(
+            email.to = %to,
+            email.language = %context.language(),
+            user.id = %context.user().id,
+            category = context.category().as_str(),
+        ),
+        err,
+    )]
+    pub async fn send_security_notification(
+        &self,
+        to: Mailbox,
+        context: &WithLanguage<SecurityNotificationContext>,
+    ) -> Result<(), Error> {
+        let (text, html, subject) = match context.category() {
+            SecurityNotification::PasswordChange => (
+                self.templates.render_email_password_change_txt(context)?,
+                self.templates.render_email_password_change_html(context)?,
+                self.templates
+                    .render_email_password_change_subject(context)?,
+            ),
+            SecurityNotification::UpstreamLink => (
+                self.templates.render_email_upstream_link_txt(context)?,
+                self.templates.render_email_upstream_link_html(context)?,
+                self.templates.render_email_upstream_link_subject(context)?,
+            ),
+            SecurityNotification::AccountLock => (
+                self.templates.render_email_account_lock_txt(context)?,
+                self.templates.render_email_account_lock_html(context)?,
+                self.templates.render_email_account_lock_subject(context)?,
+            ),
+        };
+
+        let email = self.prepare_email(to, &subject, text, html)?;
+        self.send(email).await
     }
 
     /// Test the connetion to the mail server
@@ -122,3 +313,70 @@ impl Mailer {
         self.transport.test_connection().await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use lettre::message::dkim::{DkimSigningAlgorithm, DkimSigningKey};
+
+    use super::*;
+
+    fn build(dkim: Option<&DkimConfig>) -> Email {
+        let from: Mailbox = "Example <noreply@example.com>".parse().unwrap();
+        let reply_to: Mailbox = "support@example.com".parse().unwrap();
+        let to: Mailbox = "Alice <alice@example.org>".parse().unwrap();
+
+        build_email(
+            &from,
+            &reply_to,
+            dkim,
+            to,
+            "  Hello\n",
+            "Hello, Alice".to_owned(),
+            "<p>Hello, Alice</p>".to_owned(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_build_email() {
+        let email = build(None);
+        assert_eq!(email.subject, "Hello");
+
+        let headers = email.message.headers();
+        assert_eq!(headers.get_raw("Subject"), Some("Hello"));
+        assert_eq!(headers.get_raw("Reply-To"), Some("support@example.com"));
+        assert!(headers.get_raw("DKIM-Signature").is_none());
+    }
+
+    #[test]
+    fn test_dkim_signature() {
+        let key = DkimSigningKey::new(
+            include_str!("../tests/keys/rsa.pkcs1.pem"),
+            DkimSigningAlgorithm::Rsa,
+        )
+        .unwrap();
+        let dkim = DkimConfig::default_config("mas".to_owned(), "example.com".to_owned(), key);
+
+        let email = build(Some(&dkim));
+        let signature = email.message.headers().get_raw("DKIM-Signature").unwrap();
+        assert!(signature.contains("a=rsa-sha256"));
+        assert!(signature.contains("d=example.com"));
+        assert!(signature.contains("s=mas"));
+        assert!(signature.contains("b="));
+    }
+
+    #[test]
+    fn test_is_rejection() {
+        let rejected = crate::api::Error::Rejected("bounced".to_owned());
+        assert!(Error::Transport(rejected.into()).is_rejection());
+
+        let failed = crate::api::Error::Failed {
+            status: http::StatusCode::SERVICE_UNAVAILABLE,
+            message: "unavailable".to_owned(),
+        };
+        assert!(!Error::Transport(failed.into()).is_rejection());
+
+        // Timeouts are retried, the email might go through the next time
+        assert!(!Error::Timeout(Duration::from_secs(60)).is_rejection());
+    }
+}
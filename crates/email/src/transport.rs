@@ -14,18 +14,26 @@
 
 //! Email transport backends
 
-use std::{ffi::OsString, num::NonZeroU16, sync::Arc};
+use std::{
+    ffi::OsString,
+    num::{NonZeroU16, NonZeroU32},
+    sync::Arc,
+    time::Duration,
+};
 
-use async_trait::async_trait;
 use lettre::{
-    address::Envelope,
+    message::Mailbox,
     transport::{
         sendmail::AsyncSendmailTransport,
-        smtp::{authentication::Credentials, AsyncSmtpTransport},
+        smtp::{authentication::Credentials, AsyncSmtpTransport, PoolConfig},
     },
-    AsyncTransport, Tokio1Executor,
+    AsyncTransport, Message, Tokio1Executor,
 };
+use mas_axum_utils::http_client_factory::HttpClientFactory;
 use thiserror::Error;
+use url::Url;
+
+use crate::api::{ApiTransport, AwsCredentials, Provider};
 
 /// Encryption mode to use
 #[derive(Debug, Clone, Copy)]
@@ -48,6 +56,21 @@ enum TransportInner {
     Blackhole,
     Smtp(AsyncSmtpTransport<Tokio1Executor>),
     Sendmail(AsyncSendmailTransport<Tokio1Executor>),
+    Api(ApiTransport),
+}
+
+/// An email ready to be sent
+///
+/// The HTTP APIs of most email providers don't accept a raw MIME message, so
+/// the parts of the message are kept alongside it.
+pub(crate) struct Email {
+    pub(crate) message: Message,
+    pub(crate) from: Mailbox,
+    pub(crate) reply_to: Mailbox,
+    pub(crate) to: Mailbox,
+    pub(crate) subject: String,
+    pub(crate) text: String,
+    pub(crate) html: String,
 }
 
 impl Transport {
@@ -64,6 +87,10 @@ impl Transport {
 
     /// Construct a SMTP transport
     ///
+    /// Connections to the relay are pooled, with at most `pool_size`
+    /// connections open at the same time, so that multiple emails can be sent
+    /// concurrently. `timeout` applies to each command sent to the relay.
+    ///
     /// # Errors
     ///
     /// Returns an error if the underlying SMTP transport could not be built
@@ -72,6 +99,8 @@ impl Transport {
         hostname: &str,
         port: Option<NonZeroU16>,
         credentials: Option<Credentials>,
+        pool_size: Option<NonZeroU32>,
+        timeout: Duration,
     ) -> Result<Self, lettre::transport::smtp::Error> {
         let mut t = match mode {
             SmtpMode::Plain => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(hostname),
@@ -87,6 +116,13 @@ impl Transport {
             t = t.port(port.into());
         }
 
+        let mut pool_config = PoolConfig::new();
+        if let Some(pool_size) = pool_size {
+            pool_config = pool_config.max_size(pool_size.get());
+        }
+
+        t = t.pool_config(pool_config).timeout(Some(timeout));
+
         Ok(Self::new(TransportInner::Smtp(t.build())))
     }
 
@@ -97,6 +133,72 @@ impl Transport {
             AsyncSendmailTransport::new_with_command(command),
         ))
     }
+
+    fn api(http_client_factory: HttpClientFactory, provider: Provider) -> Self {
+        Self::new(TransportInner::Api(ApiTransport::new(
+            http_client_factory,
+            provider,
+        )))
+    }
+
+    /// Construct a transport sending emails through the AWS SESv2 API
+    #[must_use]
+    pub fn aws_ses(
+        http_client_factory: HttpClientFactory,
+        region: String,
+        credentials: AwsCredentials,
+    ) -> Self {
+        Self::api(
+            http_client_factory,
+            Provider::AwsSes {
+                region,
+                credentials,
+            },
+        )
+    }
+
+    /// Construct a transport sending emails through the SendGrid API
+    #[must_use]
+    pub fn sendgrid(http_client_factory: HttpClientFactory, api_key: String) -> Self {
+        Self::api(http_client_factory, Provider::SendGrid { api_key })
+    }
+
+    /// Construct a transport sending emails through the Mailgun API
+    ///
+    /// The `endpoint` depends on the region of the Mailgun account, e.g.
+    /// `https://api.mailgun.net/` or `https://api.eu.mailgun.net/`
+    #[must_use]
+    pub fn mailgun(
+        http_client_factory: HttpClientFactory,
+        endpoint: Url,
+        domain: String,
+        api_key: String,
+    ) -> Self {
+        Self::api(
+            http_client_factory,
+            Provider::Mailgun {
+                endpoint,
+                domain,
+                api_key,
+            },
+        )
+    }
+
+    /// Construct a transport sending emails through the Postmark API
+    #[must_use]
+    pub fn postmark(
+        http_client_factory: HttpClientFactory,
+        server_token: String,
+        message_stream: Option<String>,
+    ) -> Self {
+        Self::api(
+            http_client_factory,
+            Provider::Postmark {
+                server_token,
+                message_stream,
+            },
+        )
+    }
 }
 
 impl Transport {
@@ -111,7 +213,7 @@ impl Transport {
             TransportInner::Smtp(t) => {
                 t.test_connection().await?;
             }
-            TransportInner::Blackhole | TransportInner::Sendmail(_) => {}
+            TransportInner::Blackhole | TransportInner::Sendmail(_) | TransportInner::Api(_) => {}
         }
 
         Ok(())
@@ -129,14 +231,25 @@ impl Default for TransportInner {
 pub enum Error {
     Smtp(#[from] lettre::transport::smtp::Error),
     Sendmail(#[from] lettre::transport::sendmail::Error),
+    Api(#[from] crate::api::Error),
 }
 
-#[async_trait]
-impl AsyncTransport for Transport {
-    type Ok = ();
-    type Error = Error;
+impl Error {
+    /// Whether the email was refused by the server or the provider, and
+    /// sending it again won't help
+    #[must_use]
+    pub fn is_rejection(&self) -> bool {
+        match self {
+            Self::Smtp(e) => e.is_permanent(),
+            Self::Sendmail(_) => false,
+            Self::Api(e) => e.is_rejection(),
+        }
+    }
+}
 
-    async fn send_raw(&self, envelope: &Envelope, email: &[u8]) -> Result<Self::Ok, Self::Error> {
+impl Transport {
+    /// Send an email through the underlying transport
+    pub(crate) async fn send(&self, email: Email) -> Result<(), Error> {
         match self.inner.as_ref() {
             TransportInner::Blackhole => {
                 tracing::warn!(
@@ -144,10 +257,13 @@ impl AsyncTransport for Transport {
                 );
             }
             TransportInner::Smtp(t) => {
-                t.send_raw(envelope, email).await?;
+                t.send(email.message).await?;
             }
             TransportInner::Sendmail(t) => {
-                t.send_raw(envelope, email).await?;
+                t.send(email.message).await?;
+            }
+            TransportInner::Api(t) => {
+                t.send(&email).await?;
             }
         };
 
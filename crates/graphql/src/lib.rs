@@ -66,6 +66,11 @@ pub enum Requester {
     OAuth2Session(Session, Option<User>),
 }
 
+/// The language preferred by the requester, used for the emails sent on their
+/// behalf
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestLanguage(pub String);
+
 trait OwnerId {
     fn owner_id(&self) -> Option<Ulid>;
 }
@@ -0,0 +1,79 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::Context as _;
+use async_graphql::{Context, Description, Object};
+use chrono::{DateTime, Utc};
+use mas_storage::{oauth2::OAuth2ClientRepository, user::UserRepository};
+
+use super::{OAuth2Client, User};
+use crate::state::ContextExt;
+
+/// An authorization request of a client which waits for an administrator to
+/// approve it.
+#[derive(Description)]
+pub struct AuthorizationApprovalRequest(pub mas_data_model::AuthorizationGrant);
+
+#[Object(use_type_description)]
+impl AuthorizationApprovalRequest {
+    /// ID of the authorization request.
+    pub async fn id(&self) -> String {
+        self.0.id.to_string()
+    }
+
+    /// The scope the client asked for.
+    pub async fn scope(&self) -> String {
+        self.0.scope.to_string()
+    }
+
+    /// When the user asked for the approval.
+    pub async fn requested_at(&self) -> DateTime<Utc> {
+        self.0
+            .approval
+            .as_ref()
+            .map_or(self.0.created_at, |approval| approval.requested_at)
+    }
+
+    /// The client which made the authorization request.
+    pub async fn client(&self, ctx: &Context<'_>) -> Result<OAuth2Client, async_graphql::Error> {
+        let state = ctx.state();
+        let mut repo = state.repository().await?;
+        let client = repo
+            .oauth2_client()
+            .lookup(self.0.client_id)
+            .await?
+            .context("Could not load client")?;
+        repo.cancel().await?;
+
+        Ok(OAuth2Client(client))
+    }
+
+    /// The user who asked for the approval.
+    pub async fn user(&self, ctx: &Context<'_>) -> Result<Option<User>, async_graphql::Error> {
+        let Some(approval) = &self.0.approval else {
+            return Ok(None);
+        };
+
+        let state = ctx.state();
+        let mut repo = state.repository().await?;
+        let user = repo
+            .user()
+            .lookup(approval.user_id)
+            .await?
+            .context("Could not load user")?;
+        repo.cancel().await?;
+
+        Ok(Some(User(user)))
+    }
+}
@@ -0,0 +1,47 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_graphql::Object;
+use chrono::{DateTime, Utc};
+
+/// A background job which failed and won't be retried anymore.
+pub struct FailedJob(pub mas_storage::job::FailedJob);
+
+#[Object]
+impl FailedJob {
+    /// ID of the job.
+    pub async fn id(&self) -> String {
+        self.0.id.to_string()
+    }
+
+    /// The kind of job, e.g. `provision-user`.
+    pub async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    /// The payload of the job, serialized as JSON.
+    pub async fn payload(&self) -> String {
+        self.0.payload.to_string()
+    }
+
+    /// The error the job last failed with, if any.
+    pub async fn last_error(&self) -> Option<&str> {
+        self.0.last_error.as_deref()
+    }
+
+    /// When the job failed.
+    pub async fn failed_at(&self) -> Option<DateTime<Utc>> {
+        self.0.failed_at
+    }
+}
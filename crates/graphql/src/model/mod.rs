@@ -15,9 +15,11 @@
 use async_graphql::{Enum, Interface, Object};
 use chrono::{DateTime, Utc};
 
+mod authorization_approvals;
 mod browser_sessions;
 mod compat_sessions;
 mod cursor;
+mod jobs;
 mod matrix;
 mod node;
 mod oauth;
@@ -26,15 +28,18 @@ mod users;
 mod viewer;
 
 pub use self::{
+    authorization_approvals::AuthorizationApprovalRequest,
     browser_sessions::{Authentication, BrowserSession},
     compat_sessions::{CompatSession, CompatSsoLogin},
     cursor::{Cursor, NodeCursor},
+    jobs::FailedJob,
     node::{Node, NodeType},
     oauth::{OAuth2Client, OAuth2Consent, OAuth2Session},
     upstream_oauth::{UpstreamOAuth2Link, UpstreamOAuth2Provider},
-    users::{User, UserEmail},
+    users::{SecurityNotification, User, UserEmail},
     viewer::{Anonymous, Viewer, ViewerSession},
 };
+pub(crate) use self::users::locked_profile_attributes;
 
 /// An object with a creation date.
 #[derive(Interface)]
@@ -54,6 +54,12 @@ impl OAuth2Session {
         self.0.scope.to_string()
     }
 
+    /// The human-readable name of the session, used as the display name of
+    /// its device.
+    pub async fn human_name(&self) -> Option<&str> {
+        self.0.human_name.as_deref()
+    }
+
     /// When the object was created.
     pub async fn created_at(&self) -> DateTime<Utc> {
         self.0.created_at
@@ -53,6 +53,52 @@ impl UpstreamOAuth2Provider {
     pub async fn client_id(&self) -> &str {
         &self.provider.client_id
     }
+
+    /// The outcome of the last health check of this provider, if it was ever
+    /// checked.
+    ///
+    /// This is only available to administrators.
+    pub async fn health(
+        &self,
+        ctx: &Context<'_>,
+    ) -> Result<Option<UpstreamOAuth2ProviderHealth>, async_graphql::Error> {
+        let state = ctx.state();
+        let requester = ctx.requester();
+
+        if !requester.is_admin() {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let mut repo = state.repository().await?;
+        let health = repo
+            .upstream_oauth_provider()
+            .health(&self.provider)
+            .await?;
+        repo.cancel().await?;
+
+        Ok(health.map(UpstreamOAuth2ProviderHealth))
+    }
+}
+
+/// The outcome of the health check of an upstream OAuth 2.0 provider.
+pub struct UpstreamOAuth2ProviderHealth(pub mas_data_model::UpstreamOAuthProviderHealth);
+
+#[Object]
+impl UpstreamOAuth2ProviderHealth {
+    /// Whether the provider passed the check.
+    pub async fn healthy(&self) -> bool {
+        self.0.is_healthy()
+    }
+
+    /// Why the provider failed the check, if it did.
+    pub async fn error(&self) -> Option<&str> {
+        self.0.error.as_deref()
+    }
+
+    /// When the provider was checked.
+    pub async fn checked_at(&self) -> DateTime<Utc> {
+        self.0.checked_at
+    }
 }
 
 impl UpstreamOAuth2Link {
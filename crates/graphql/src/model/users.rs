@@ -22,9 +22,14 @@ use mas_storage::{
     app_session::AppSessionFilter,
     compat::{CompatSessionFilter, CompatSsoLoginFilter, CompatSsoLoginRepository},
     oauth2::{OAuth2SessionFilter, OAuth2SessionRepository},
-    upstream_oauth2::{UpstreamOAuthLinkFilter, UpstreamOAuthLinkRepository},
-    user::{BrowserSessionFilter, BrowserSessionRepository, UserEmailFilter, UserEmailRepository},
-    Pagination, RepositoryAccess,
+    upstream_oauth2::{
+        UpstreamOAuthLinkFilter, UpstreamOAuthLinkRepository, UpstreamOAuthProviderRepository,
+    },
+    user::{
+        BrowserSessionFilter, BrowserSessionRepository, UserEmailFilter, UserEmailRepository,
+        UserRepository,
+    },
+    BoxRepository, Pagination, RepositoryAccess, RepositoryError,
 };
 
 use super::{
@@ -73,6 +78,11 @@ impl User {
         self.0.locked_at
     }
 
+    /// When the user was quarantined.
+    pub async fn quarantined_at(&self) -> Option<DateTime<Utc>> {
+        self.0.quarantined_at
+    }
+
     /// Whether the user can request admin privileges.
     pub async fn can_request_admin(&self) -> bool {
         self.0.can_request_admin
@@ -98,6 +108,43 @@ impl User {
         Ok(user_email)
     }
 
+    /// Categories of security notification emails the user opted out of.
+    async fn security_notification_opt_outs(
+        &self,
+        ctx: &Context<'_>,
+    ) -> Result<Vec<SecurityNotification>, async_graphql::Error> {
+        let state = ctx.state();
+        let mut repo = state.repository().await?;
+
+        let opt_outs = repo
+            .user()
+            .security_notification_opt_outs(&self.0)
+            .await?
+            .into_iter()
+            .map(SecurityNotification::from)
+            .collect();
+        repo.cancel().await?;
+        Ok(opt_outs)
+    }
+
+    /// Profile attributes which are managed by an upstream provider the user
+    /// is linked to, and which they can't change themselves.
+    async fn locked_profile_attributes(
+        &self,
+        ctx: &Context<'_>,
+    ) -> Result<Vec<ProfileAttribute>, async_graphql::Error> {
+        let state = ctx.state();
+        let mut repo = state.repository().await?;
+
+        let locked = locked_profile_attributes(&mut repo, &self.0)
+            .await?
+            .into_iter()
+            .map(ProfileAttribute::from)
+            .collect();
+        repo.cancel().await?;
+        Ok(locked)
+    }
+
     /// Get the list of compatibility SSO logins, chronologically sorted
     async fn compat_sso_logins(
         &self,
@@ -386,6 +433,12 @@ impl User {
 
         #[graphql(desc = "List only sessions for the given client.")] client: Option<ID>,
 
+        #[graphql(desc = "List only sessions created at or after the given time.")]
+        created_after: Option<DateTime<Utc>>,
+
+        #[graphql(desc = "List only sessions created before the given time.")]
+        created_before: Option<DateTime<Utc>>,
+
         #[graphql(desc = "Returns the elements in the list that come after the cursor.")]
         after: Option<String>,
         #[graphql(desc = "Returns the elements in the list that come before the cursor.")]
@@ -437,6 +490,16 @@ impl User {
                     None => filter,
                 };
 
+                let filter = match created_after {
+                    Some(created_after) => filter.with_created_after(created_after),
+                    None => filter,
+                };
+
+                let filter = match created_before {
+                    Some(created_before) => filter.with_created_before(created_before),
+                    None => filter,
+                };
+
                 let page = repo.oauth2_session().list(filter, pagination).await?;
 
                 let count = if ctx.look_ahead().field("totalCount").exists() {
@@ -658,6 +721,104 @@ impl UserEmail {
     }
 }
 
+/// A category of notification emails sent on security-relevant account events.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum SecurityNotification {
+    /// The password of the user was changed.
+    PasswordChange,
+
+    /// An upstream account was linked to the user.
+    UpstreamLink,
+
+    /// The user was locked.
+    AccountLock,
+}
+
+impl From<mas_data_model::SecurityNotification> for SecurityNotification {
+    fn from(value: mas_data_model::SecurityNotification) -> Self {
+        match value {
+            mas_data_model::SecurityNotification::PasswordChange => Self::PasswordChange,
+            mas_data_model::SecurityNotification::UpstreamLink => Self::UpstreamLink,
+            mas_data_model::SecurityNotification::AccountLock => Self::AccountLock,
+        }
+    }
+}
+
+impl From<SecurityNotification> for mas_data_model::SecurityNotification {
+    fn from(value: SecurityNotification) -> Self {
+        match value {
+            SecurityNotification::PasswordChange => Self::PasswordChange,
+            SecurityNotification::UpstreamLink => Self::UpstreamLink,
+            SecurityNotification::AccountLock => Self::AccountLock,
+        }
+    }
+}
+
+/// Get the profile attributes of the user which are locked by the upstream
+/// providers they are linked to
+pub(crate) async fn locked_profile_attributes(
+    repo: &mut BoxRepository,
+    user: &mas_data_model::User,
+) -> Result<Vec<mas_data_model::ProfileAttribute>, RepositoryError> {
+    let providers = repo.upstream_oauth_provider().all().await?;
+    let mut locked = Vec::new();
+    for provider in providers {
+        let attributes = &provider.claims_imports.locked_attributes;
+        if attributes
+            .iter()
+            .all(|attribute| locked.contains(attribute))
+        {
+            continue;
+        }
+
+        let filter = UpstreamOAuthLinkFilter::new()
+            .for_user(user)
+            .for_provider(&provider);
+        if repo.upstream_oauth_link().count(filter).await? > 0 {
+            for attribute in attributes {
+                if !locked.contains(attribute) {
+                    locked.push(*attribute);
+                }
+            }
+        }
+    }
+
+    Ok(locked)
+}
+
+/// An attribute of the user profile.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum ProfileAttribute {
+    /// The display name of the user.
+    Displayname,
+
+    /// The avatar of the user.
+    AvatarUrl,
+
+    /// The email addresses of the user.
+    Email,
+}
+
+impl From<mas_data_model::ProfileAttribute> for ProfileAttribute {
+    fn from(value: mas_data_model::ProfileAttribute) -> Self {
+        match value {
+            mas_data_model::ProfileAttribute::Displayname => Self::Displayname,
+            mas_data_model::ProfileAttribute::AvatarUrl => Self::AvatarUrl,
+            mas_data_model::ProfileAttribute::Email => Self::Email,
+        }
+    }
+}
+
+impl From<ProfileAttribute> for mas_data_model::ProfileAttribute {
+    fn from(value: ProfileAttribute) -> Self {
+        match value {
+            ProfileAttribute::Displayname => Self::Displayname,
+            ProfileAttribute::AvatarUrl => Self::AvatarUrl,
+            ProfileAttribute::Email => Self::Email,
+        }
+    }
+}
+
 /// The state of a compatibility session.
 #[derive(Enum, Copy, Clone, Eq, PartialEq)]
 pub enum UserEmailState {
@@ -0,0 +1,176 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_graphql::{Context, Enum, InputObject, Object};
+use mas_storage::{
+    job::{JobRepositoryExt, SendAuthorizationApprovedJob},
+    oauth2::OAuth2AuthorizationGrantRepository,
+    RepositoryAccess,
+};
+use tracing::info;
+use ulid::Ulid;
+
+use crate::state::ContextExt;
+
+#[derive(Default)]
+pub struct AuthorizationApprovalMutations {
+    _private: (),
+}
+
+/// The input for the `approveAuthorizationRequest` and
+/// `denyAuthorizationRequest` mutations.
+#[derive(InputObject)]
+struct AuthorizationApprovalInput {
+    /// The ID of the authorization request.
+    request_id: String,
+}
+
+/// The status of the `approveAuthorizationRequest` and
+/// `denyAuthorizationRequest` mutations.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+enum AuthorizationApprovalStatus {
+    /// The request was approved, the user was notified.
+    Approved,
+
+    /// The request was denied.
+    Denied,
+
+    /// No authorization request waiting for an approval was found.
+    NotFound,
+}
+
+/// The payload for the `approveAuthorizationRequest` and
+/// `denyAuthorizationRequest` mutations.
+struct AuthorizationApprovalPayload {
+    status: AuthorizationApprovalStatus,
+}
+
+#[Object]
+impl AuthorizationApprovalPayload {
+    /// Status of the operation
+    async fn status(&self) -> AuthorizationApprovalStatus {
+        self.status
+    }
+}
+
+#[Object]
+impl AuthorizationApprovalMutations {
+    /// Approve an authorization request which waits for an approval. The user
+    /// who made it gets an email with a link to finish it.
+    ///
+    /// This is only available to administrators.
+    async fn approve_authorization_request(
+        &self,
+        ctx: &Context<'_>,
+        input: AuthorizationApprovalInput,
+    ) -> Result<AuthorizationApprovalPayload, async_graphql::Error> {
+        let state = ctx.state();
+        let requester = ctx.requester();
+
+        if !requester.is_admin() {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let grant_id: Ulid = input.request_id.parse()?;
+
+        let mut repo = state.repository().await?;
+        let clock = state.clock();
+
+        let grant = repo.oauth2_authorization_grant().lookup(grant_id).await?;
+        let Some(grant) = grant.filter(mas_data_model::AuthorizationGrant::is_awaiting_approval)
+        else {
+            return Ok(AuthorizationApprovalPayload {
+                status: AuthorizationApprovalStatus::NotFound,
+            });
+        };
+
+        let reviewer = requester.user();
+        let grant = repo
+            .oauth2_authorization_grant()
+            .approve(&clock, reviewer, grant)
+            .await?;
+
+        repo.job()
+            .schedule_job(SendAuthorizationApprovedJob::new(&grant))
+            .await?;
+
+        repo.save().await?;
+
+        info!(
+            audit = true,
+            audit.action = "oauth2.authorization_grant.approve",
+            oauth2_authorization_grant.id = %grant.id,
+            oauth2_client.id = %grant.client_id,
+            reviewer.id = reviewer.map(|user| tracing::field::display(user.id)),
+            reviewer.username = reviewer.map(|user| user.username.as_str()),
+            "Administrator approved an authorization request"
+        );
+
+        Ok(AuthorizationApprovalPayload {
+            status: AuthorizationApprovalStatus::Approved,
+        })
+    }
+
+    /// Deny an authorization request which waits for an approval. It can't be
+    /// finished anymore.
+    ///
+    /// This is only available to administrators.
+    async fn deny_authorization_request(
+        &self,
+        ctx: &Context<'_>,
+        input: AuthorizationApprovalInput,
+    ) -> Result<AuthorizationApprovalPayload, async_graphql::Error> {
+        let state = ctx.state();
+        let requester = ctx.requester();
+
+        if !requester.is_admin() {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let grant_id: Ulid = input.request_id.parse()?;
+
+        let mut repo = state.repository().await?;
+        let clock = state.clock();
+
+        let grant = repo.oauth2_authorization_grant().lookup(grant_id).await?;
+        let Some(grant) = grant.filter(mas_data_model::AuthorizationGrant::is_awaiting_approval)
+        else {
+            return Ok(AuthorizationApprovalPayload {
+                status: AuthorizationApprovalStatus::NotFound,
+            });
+        };
+
+        let reviewer = requester.user();
+        let grant = repo
+            .oauth2_authorization_grant()
+            .deny(&clock, reviewer, grant)
+            .await?;
+
+        repo.save().await?;
+
+        info!(
+            audit = true,
+            audit.action = "oauth2.authorization_grant.deny",
+            oauth2_authorization_grant.id = %grant.id,
+            oauth2_client.id = %grant.client_id,
+            reviewer.id = reviewer.map(|user| tracing::field::display(user.id)),
+            reviewer.username = reviewer.map(|user| user.username.as_str()),
+            "Administrator denied an authorization request"
+        );
+
+        Ok(AuthorizationApprovalPayload {
+            status: AuthorizationApprovalStatus::Denied,
+        })
+    }
+}
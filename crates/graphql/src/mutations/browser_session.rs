@@ -92,6 +92,10 @@ impl BrowserSessionMutations {
             return Ok(EndBrowserSessionPayload::NotFound);
         }
 
+        if session.is_impersonation() {
+            repo.user_impersonation().end(&clock, &session).await?;
+        }
+
         let session = repo.browser_session().finish(&clock, session).await?;
 
         repo.save().await?;
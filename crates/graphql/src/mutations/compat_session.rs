@@ -110,6 +110,8 @@ impl CompatSessionMutations {
 
         repo.save().await?;
 
+        state.session_ended(session.id);
+
         Ok(EndCompatSessionPayload::Ended(session))
     }
 }
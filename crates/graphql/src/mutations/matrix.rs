@@ -14,9 +14,12 @@
 
 use anyhow::Context as _;
 use async_graphql::{Context, Description, Enum, InputObject, Object, ID};
+use mas_data_model::ProfileAttribute;
+use mas_matrix::Unsupported;
+use mas_storage::job::{JobRepositoryExt, SetDisplayNameJob};
 
 use crate::{
-    model::{NodeType, User},
+    model::{locked_profile_attributes, NodeType, User},
     state::ContextExt,
     UserId,
 };
@@ -43,6 +46,10 @@ pub enum SetDisplayNameStatus {
     Set,
     /// The display name is invalid
     Invalid,
+    /// The display name is managed by an upstream provider
+    Locked,
+    /// The homeserver does not allow changing the display name
+    Unsupported,
 }
 
 /// The payload of the `setDisplayName` mutation
@@ -50,6 +57,8 @@ pub enum SetDisplayNameStatus {
 enum SetDisplayNamePayload {
     Set(User),
     Invalid,
+    Locked,
+    Unsupported,
 }
 
 #[Object(use_type_description)]
@@ -59,6 +68,8 @@ impl SetDisplayNamePayload {
         match self {
             SetDisplayNamePayload::Set(_) => SetDisplayNameStatus::Set,
             SetDisplayNamePayload::Invalid => SetDisplayNameStatus::Invalid,
+            SetDisplayNamePayload::Locked => SetDisplayNameStatus::Locked,
+            SetDisplayNamePayload::Unsupported => SetDisplayNameStatus::Unsupported,
         }
     }
 
@@ -66,7 +77,9 @@ impl SetDisplayNamePayload {
     async fn user(&self) -> Option<&User> {
         match self {
             SetDisplayNamePayload::Set(user) => Some(user),
-            SetDisplayNamePayload::Invalid => None,
+            SetDisplayNamePayload::Invalid
+            | SetDisplayNamePayload::Locked
+            | SetDisplayNamePayload::Unsupported => None,
         }
     }
 }
@@ -93,12 +106,21 @@ impl MatrixMutations {
             .lookup(id)
             .await?
             .context("Failed to lookup user")?;
-        repo.cancel().await?;
+
+        // Admins can still change display names managed by upstream providers
+        if !requester.is_admin()
+            && locked_profile_attributes(&mut repo, &user)
+                .await?
+                .contains(&ProfileAttribute::Displayname)
+        {
+            repo.cancel().await?;
+            return Ok(SetDisplayNamePayload::Locked);
+        }
 
         let conn = state.homeserver_connection();
         let mxid = conn.mxid(&user.username);
 
-        if let Some(display_name) = &input.display_name {
+        let res = if let Some(display_name) = &input.display_name {
             // Let's do some basic validation on the display name
             if display_name.len() > 256 {
                 return Ok(SetDisplayNamePayload::Invalid);
@@ -108,15 +130,43 @@ impl MatrixMutations {
                 return Ok(SetDisplayNamePayload::Invalid);
             }
 
-            conn.set_displayname(&mxid, display_name)
-                .await
-                .context("Failed to set display name")?;
+            conn.set_displayname(&mxid, display_name).await
         } else {
-            conn.unset_displayname(&mxid)
-                .await
-                .context("Failed to unset display name")?;
+            conn.unset_displayname(&mxid).await
+        };
+
+        if let Err(e) = res {
+            // Retrying would never succeed if the homeserver has no API for it
+            if e.is::<Unsupported>() {
+                repo.cancel().await?;
+                return Ok(SetDisplayNamePayload::Unsupported);
+            }
+
+            // The homeserver might only be briefly unavailable, queue the change
+            // so that it gets retried in the background
+            tracing::warn!(
+                error = %e,
+                %mxid,
+                "Failed to update the display name, retrying in the background"
+            );
+            repo.job()
+                .schedule_job(SetDisplayNameJob::new(&user, input.display_name))
+                .await?;
         }
 
+        // Remember that the user changed it themselves, so that it doesn't get
+        // overwritten on their next login
+        if requester
+            .user()
+            .is_some_and(|requester| requester.id == user.id)
+        {
+            repo.user()
+                .mark_profile_attribute_modified(&user, ProfileAttribute::Displayname)
+                .await?;
+        }
+
+        repo.save().await?;
+
         Ok(SetDisplayNamePayload::Set(User(user.clone())))
     }
 }
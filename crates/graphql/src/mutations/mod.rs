@@ -12,9 +12,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod authorization_approval;
 mod browser_session;
 mod compat_session;
 mod matrix;
+mod oauth2_client;
 mod oauth2_session;
 mod user;
 mod user_email;
@@ -27,9 +29,11 @@ pub struct Mutation(
     user_email::UserEmailMutations,
     user::UserMutations,
     oauth2_session::OAuth2SessionMutations,
+    oauth2_client::OAuth2ClientMutations,
     compat_session::CompatSessionMutations,
     browser_session::BrowserSessionMutations,
     matrix::MatrixMutations,
+    authorization_approval::AuthorizationApprovalMutations,
 );
 
 impl Mutation {
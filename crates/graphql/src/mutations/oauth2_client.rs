@@ -0,0 +1,313 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::Context as _;
+use async_graphql::{Context, Description, Enum, InputObject, Object, ID};
+use chrono::Duration;
+use mas_data_model::{ClaimMapping, ClientTokenSettings};
+use mas_storage::{oauth2::OAuth2ClientRepository, RepositoryAccess};
+use oauth2_types::scope::{Scope, ScopeToken};
+
+use crate::{
+    model::{NodeType, OAuth2Client},
+    state::ContextExt,
+};
+
+#[derive(Default)]
+pub struct OAuth2ClientMutations {
+    _private: (),
+}
+
+/// The input for the `setOauth2ClientTokenSettings` mutation.
+#[derive(InputObject)]
+struct SetOAuth2ClientTokenSettingsInput {
+    /// The ID of the client to update.
+    client_id: ID,
+
+    /// Time-to-live of the access tokens issued to the client, in seconds.
+    /// Uses the server default if not set.
+    access_token_ttl: Option<u32>,
+
+    /// How long the sessions of the client can go without being refreshed
+    /// before they end, in seconds. Uses the server default if not set.
+    refresh_token_ttl: Option<u32>,
+
+    /// Audiences added to the tokens issued to the client.
+    #[graphql(default)]
+    additional_audiences: Vec<String>,
+}
+
+/// The payload for the `setOauth2ClientTokenSettings` mutation.
+#[derive(Description)]
+enum SetOAuth2ClientTokenSettingsPayload {
+    /// The client was updated.
+    Updated(mas_data_model::Client),
+
+    /// The client was not found.
+    NotFound,
+}
+
+#[Object(use_type_description)]
+impl SetOAuth2ClientTokenSettingsPayload {
+    /// The client that was updated.
+    async fn oauth2_client(&self) -> Option<OAuth2Client> {
+        match self {
+            Self::Updated(client) => Some(OAuth2Client(client.clone())),
+            Self::NotFound => None,
+        }
+    }
+}
+
+/// An attribute of the users which can be mapped to a claim.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+enum UserAttribute {
+    /// The display name of the user, as set on the homeserver.
+    DisplayName,
+
+    /// The primary email address of the user.
+    Email,
+
+    /// Whether the user can request admin access.
+    IsAdmin,
+
+    /// The groups imported from the upstream provider the user last logged in
+    /// with.
+    Groups,
+}
+
+impl From<UserAttribute> for mas_data_model::UserAttribute {
+    fn from(attribute: UserAttribute) -> Self {
+        match attribute {
+            UserAttribute::DisplayName => Self::DisplayName,
+            UserAttribute::Email => Self::Email,
+            UserAttribute::IsAdmin => Self::IsAdmin,
+            UserAttribute::Groups => Self::Groups,
+        }
+    }
+}
+
+/// Maps an attribute of the users to a claim.
+#[derive(InputObject)]
+struct ClaimMappingInput {
+    /// The name of the claim.
+    claim: String,
+
+    /// The attribute the claim is set to.
+    attribute: UserAttribute,
+
+    /// Only set the claim when the session has this scope.
+    scope: Option<String>,
+}
+
+/// The input for the `setOauth2ClientClaimMappings` mutation.
+#[derive(InputObject)]
+struct SetOAuth2ClientClaimMappingsInput {
+    /// The ID of the client to update.
+    client_id: ID,
+
+    /// The attributes of the users mapped to the claims of the ID tokens and
+    /// userinfo responses of the client. Replaces the existing ones.
+    mappings: Vec<ClaimMappingInput>,
+}
+
+/// The payload for the `setOauth2ClientClaimMappings` mutation.
+#[derive(Description)]
+enum SetOAuth2ClientClaimMappingsPayload {
+    /// The client was updated.
+    Updated(mas_data_model::Client),
+
+    /// The client was not found.
+    NotFound,
+}
+
+#[Object(use_type_description)]
+impl SetOAuth2ClientClaimMappingsPayload {
+    /// The client that was updated.
+    async fn oauth2_client(&self) -> Option<OAuth2Client> {
+        match self {
+            Self::Updated(client) => Some(OAuth2Client(client.clone())),
+            Self::NotFound => None,
+        }
+    }
+}
+
+/// The input for the `setOauth2ClientTrustedScope` mutation.
+#[derive(InputObject)]
+struct SetOAuth2ClientTrustedScopeInput {
+    /// The ID of the client to update.
+    client_id: ID,
+
+    /// Space-separated list of scopes users don't have to consent to for the
+    /// client. The client is no longer trusted if not set.
+    scope: Option<String>,
+}
+
+/// The payload for the `setOauth2ClientTrustedScope` mutation.
+#[derive(Description)]
+enum SetOAuth2ClientTrustedScopePayload {
+    /// The client was updated.
+    Updated(mas_data_model::Client),
+
+    /// The client was not found.
+    NotFound,
+}
+
+#[Object(use_type_description)]
+impl SetOAuth2ClientTrustedScopePayload {
+    /// The client that was updated.
+    async fn oauth2_client(&self) -> Option<OAuth2Client> {
+        match self {
+            Self::Updated(client) => Some(OAuth2Client(client.clone())),
+            Self::NotFound => None,
+        }
+    }
+}
+
+#[Object]
+impl OAuth2ClientMutations {
+    /// Override the lifetime and the audiences of the tokens issued to a
+    /// client. This is only available to administrators.
+    async fn set_oauth2_client_token_settings(
+        &self,
+        ctx: &Context<'_>,
+        input: SetOAuth2ClientTokenSettingsInput,
+    ) -> Result<SetOAuth2ClientTokenSettingsPayload, async_graphql::Error> {
+        let state = ctx.state();
+        let requester = ctx.requester();
+
+        if !requester.is_admin() {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let mut repo = state.repository().await?;
+
+        let client_id = NodeType::OAuth2Client.extract_ulid(&input.client_id)?;
+        let client = repo.oauth2_client().lookup(client_id).await?;
+
+        let Some(client) = client else {
+            return Ok(SetOAuth2ClientTokenSettingsPayload::NotFound);
+        };
+
+        let settings = ClientTokenSettings {
+            access_token_ttl: input
+                .access_token_ttl
+                .map(|ttl| Duration::seconds(ttl.into())),
+            refresh_token_ttl: input
+                .refresh_token_ttl
+                .map(|ttl| Duration::seconds(ttl.into())),
+            additional_audiences: input.additional_audiences,
+        };
+
+        repo.oauth2_client()
+            .set_token_settings(&client, &settings)
+            .await?;
+
+        repo.save().await?;
+
+        Ok(SetOAuth2ClientTokenSettingsPayload::Updated(client))
+    }
+
+    /// Set which attributes of the users are mapped to the claims of the ID
+    /// tokens and userinfo responses of a client. This is only available to
+    /// administrators.
+    async fn set_oauth2_client_claim_mappings(
+        &self,
+        ctx: &Context<'_>,
+        input: SetOAuth2ClientClaimMappingsInput,
+    ) -> Result<SetOAuth2ClientClaimMappingsPayload, async_graphql::Error> {
+        let state = ctx.state();
+        let requester = ctx.requester();
+
+        if !requester.is_admin() {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let mappings = input
+            .mappings
+            .into_iter()
+            .map(|mapping| {
+                if mapping.claim.is_empty() {
+                    return Err(anyhow::anyhow!("Claim names can't be empty"));
+                }
+
+                if let Some(scope) = &mapping.scope {
+                    scope.parse::<ScopeToken>().context("Invalid scope")?;
+                }
+
+                Ok(ClaimMapping {
+                    claim: mapping.claim,
+                    attribute: mapping.attribute.into(),
+                    scope: mapping.scope,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut repo = state.repository().await?;
+
+        let client_id = NodeType::OAuth2Client.extract_ulid(&input.client_id)?;
+        let client = repo.oauth2_client().lookup(client_id).await?;
+
+        let Some(client) = client else {
+            return Ok(SetOAuth2ClientClaimMappingsPayload::NotFound);
+        };
+
+        repo.oauth2_client()
+            .set_claim_mappings(&client, &mappings)
+            .await?;
+
+        repo.save().await?;
+
+        Ok(SetOAuth2ClientClaimMappingsPayload::Updated(client))
+    }
+
+    /// Set the scopes users don't have to consent to for a first-party
+    /// client. Scopes beyond those still need consent. This is only available
+    /// to administrators.
+    async fn set_oauth2_client_trusted_scope(
+        &self,
+        ctx: &Context<'_>,
+        input: SetOAuth2ClientTrustedScopeInput,
+    ) -> Result<SetOAuth2ClientTrustedScopePayload, async_graphql::Error> {
+        let state = ctx.state();
+        let requester = ctx.requester();
+
+        if !requester.is_admin() {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let scope: Option<Scope> = input
+            .scope
+            .as_deref()
+            .map(str::parse)
+            .transpose()
+            .context("Invalid scope")?;
+
+        let mut repo = state.repository().await?;
+
+        let client_id = NodeType::OAuth2Client.extract_ulid(&input.client_id)?;
+        let client = repo.oauth2_client().lookup(client_id).await?;
+
+        let Some(client) = client else {
+            return Ok(SetOAuth2ClientTrustedScopePayload::NotFound);
+        };
+
+        repo.oauth2_client()
+            .set_trusted_scope(&client, scope.as_ref())
+            .await?;
+
+        repo.save().await?;
+
+        Ok(SetOAuth2ClientTrustedScopePayload::Updated(client))
+    }
+}
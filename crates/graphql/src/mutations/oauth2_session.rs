@@ -16,6 +16,7 @@ use anyhow::Context as _;
 use async_graphql::{Context, Description, Enum, InputObject, Object, ID};
 use chrono::Duration;
 use mas_data_model::{Device, TokenType};
+use mas_matrix::Unsupported;
 use mas_storage::{
     job::{DeleteDeviceJob, JobRepositoryExt, ProvisionDeviceJob},
     oauth2::{
@@ -118,6 +119,56 @@ impl EndOAuth2SessionPayload {
     }
 }
 
+/// The input of the `setOauth2SessionName` mutation.
+#[derive(InputObject)]
+pub struct SetOAuth2SessionNameInput {
+    /// The ID of the session to rename.
+    oauth2_session_id: ID,
+
+    /// The new name of the session. An empty name unsets it.
+    human_name: String,
+}
+
+/// The payload of the `setOauth2SessionName` mutation.
+pub enum SetOAuth2SessionNamePayload {
+    NotFound,
+    Unsupported,
+    Updated(mas_data_model::Session),
+}
+
+/// The status of the `setOauth2SessionName` mutation.
+#[derive(Enum, Copy, Clone, PartialEq, Eq, Debug)]
+enum SetOAuth2SessionNameStatus {
+    /// The session was renamed.
+    Updated,
+
+    /// The session was not found.
+    NotFound,
+
+    /// The homeserver does not allow changing the display name of devices.
+    Unsupported,
+}
+
+#[Object]
+impl SetOAuth2SessionNamePayload {
+    /// The status of the mutation.
+    async fn status(&self) -> SetOAuth2SessionNameStatus {
+        match self {
+            Self::Updated(_) => SetOAuth2SessionNameStatus::Updated,
+            Self::NotFound => SetOAuth2SessionNameStatus::NotFound,
+            Self::Unsupported => SetOAuth2SessionNameStatus::Unsupported,
+        }
+    }
+
+    /// The updated session.
+    async fn oauth2_session(&self) -> Option<OAuth2Session> {
+        match self {
+            Self::Updated(session) => Some(OAuth2Session(session.clone())),
+            Self::NotFound | Self::Unsupported => None,
+        }
+    }
+}
+
 #[Object]
 impl OAuth2SessionMutations {
     /// Create a new arbitrary OAuth 2.0 Session.
@@ -256,6 +307,89 @@ impl OAuth2SessionMutations {
 
         repo.save().await?;
 
+        state.session_ended(session.id);
+
         Ok(EndOAuth2SessionPayload::Ended(session))
     }
+
+    /// Set the human-readable name of an OAuth 2.0 session.
+    ///
+    /// The name is also set as the display name of the device attached to the
+    /// session on the homeserver.
+    async fn set_oauth2_session_name(
+        &self,
+        ctx: &Context<'_>,
+        input: SetOAuth2SessionNameInput,
+    ) -> Result<SetOAuth2SessionNamePayload, async_graphql::Error> {
+        let state = ctx.state();
+        let oauth2_session_id = NodeType::OAuth2Session.extract_ulid(&input.oauth2_session_id)?;
+        let requester = ctx.requester();
+
+        let mut repo = state.repository().await?;
+
+        let session = repo.oauth2_session().lookup(oauth2_session_id).await?;
+        let Some(session) = session else {
+            return Ok(SetOAuth2SessionNamePayload::NotFound);
+        };
+
+        if !requester.is_owner_or_admin(&session) {
+            return Ok(SetOAuth2SessionNamePayload::NotFound);
+        }
+
+        let human_name = input.human_name.trim();
+        let human_name = (!human_name.is_empty()).then(|| human_name.to_owned());
+
+        let session = repo
+            .oauth2_session()
+            .set_human_name(session, human_name)
+            .await?;
+
+        // Sync the new name to the devices of the session, if it is still active
+        if let (true, Some(user_id), Some(human_name)) = (
+            session.is_valid(),
+            session.user_id,
+            session.human_name.as_deref(),
+        ) {
+            let user = repo
+                .user()
+                .lookup(user_id)
+                .await?
+                .context("Could not load user")?;
+
+            let conn = state.homeserver_connection();
+            let mxid = conn.mxid(&user.username);
+
+            for scope in &*session.scope {
+                if let Some(device) = Device::from_scope_token(scope) {
+                    let res = conn
+                        .update_device_display_name(&mxid, device.as_str(), human_name)
+                        .await;
+
+                    if let Err(e) = res {
+                        // Retrying would never succeed if the homeserver has no API for it
+                        if e.is::<Unsupported>() {
+                            repo.cancel().await?;
+                            return Ok(SetOAuth2SessionNamePayload::Unsupported);
+                        }
+
+                        // The device might not be provisioned yet, or the homeserver might
+                        // only be briefly unavailable, so retry in the background
+                        tracing::warn!(
+                            error = %e,
+                            %mxid,
+                            device.id = device.as_str(),
+                            "Failed to update the device display name, retrying in the background"
+                        );
+                        let job = ProvisionDeviceJob::new(&user, &device)
+                            .with_display_name(Some(human_name.to_owned()));
+                        repo.job().schedule_job(job).await?;
+                    }
+                }
+            }
+        }
+
+        repo.save().await?;
+
+        Ok(SetOAuth2SessionNamePayload::Updated(session))
+    }
 }
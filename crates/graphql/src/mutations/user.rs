@@ -13,15 +13,21 @@
 // limitations under the License.
 
 use async_graphql::{Context, Description, Enum, InputObject, Object, ID};
+use chrono::{DateTime, Utc};
 use mas_storage::{
-    job::{DeactivateUserJob, JobRepositoryExt, ProvisionUserJob},
+    job::{
+        DeactivateUserJob, JobRepositoryExt, NotifyUserEventJob, ProvisionUserJob,
+        SendSecurityNotificationJob, UserLifecycleEvent,
+    },
     user::UserRepository,
+    Clock,
 };
 use tracing::info;
 
 use crate::{
-    model::{NodeType, User},
+    model::{NodeType, SecurityNotification, User},
     state::ContextExt,
+    UserId,
 };
 
 #[derive(Default)]
@@ -126,6 +132,60 @@ impl LockUserPayload {
     }
 }
 
+/// The input for the `scheduleUserDeactivation` mutation.
+#[derive(InputObject)]
+struct ScheduleUserDeactivationInput {
+    /// The ID of the user to deactivate.
+    user_id: ID,
+
+    /// When to deactivate the user. Defaults to now.
+    deactivate_at: Option<DateTime<Utc>>,
+
+    /// Erase the user: this removes their personal information, like email
+    /// addresses and upstream OAuth links, and asks the homeserver to erase
+    /// their data.
+    erase: Option<bool>,
+}
+
+/// The status of the `scheduleUserDeactivation` mutation.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+enum ScheduleUserDeactivationStatus {
+    /// The deactivation was scheduled.
+    Scheduled,
+
+    /// The user was not found.
+    NotFound,
+}
+
+/// The payload for the `scheduleUserDeactivation` mutation.
+#[derive(Description)]
+enum ScheduleUserDeactivationPayload {
+    /// The deactivation was scheduled.
+    Scheduled(mas_data_model::User),
+
+    /// The user was not found.
+    NotFound,
+}
+
+#[Object(use_type_description)]
+impl ScheduleUserDeactivationPayload {
+    /// Status of the operation
+    async fn status(&self) -> ScheduleUserDeactivationStatus {
+        match self {
+            Self::Scheduled(_) => ScheduleUserDeactivationStatus::Scheduled,
+            Self::NotFound => ScheduleUserDeactivationStatus::NotFound,
+        }
+    }
+
+    /// The user that will be deactivated.
+    async fn user(&self) -> Option<User> {
+        match self {
+            Self::Scheduled(user) => Some(User(user.clone())),
+            Self::NotFound => None,
+        }
+    }
+}
+
 /// The input for the `setCanRequestAdmin` mutation.
 #[derive(InputObject)]
 struct SetCanRequestAdminInput {
@@ -157,6 +217,71 @@ impl SetCanRequestAdminPayload {
     }
 }
 
+/// The input for the `setQuarantined` mutation.
+#[derive(InputObject)]
+struct SetQuarantinedInput {
+    /// The ID of the user to update.
+    user_id: ID,
+
+    /// Whether the user should be quarantined.
+    quarantined: bool,
+}
+
+/// The payload for the `setQuarantined` mutation.
+#[derive(Description)]
+enum SetQuarantinedPayload {
+    /// The user was updated.
+    Updated(mas_data_model::User),
+
+    /// The user was not found.
+    NotFound,
+}
+
+#[Object(use_type_description)]
+impl SetQuarantinedPayload {
+    /// The user that was updated.
+    async fn user(&self) -> Option<User> {
+        match self {
+            Self::Updated(user) => Some(User(user.clone())),
+            Self::NotFound => None,
+        }
+    }
+}
+
+/// The input for the `setSecurityNotificationOptOut` mutation.
+#[derive(InputObject)]
+struct SetSecurityNotificationOptOutInput {
+    /// The ID of the user to update.
+    user_id: ID,
+
+    /// The category of security notifications to update.
+    category: SecurityNotification,
+
+    /// Whether the user should stop receiving this category of notifications.
+    opt_out: bool,
+}
+
+/// The payload for the `setSecurityNotificationOptOut` mutation.
+#[derive(Description)]
+enum SetSecurityNotificationOptOutPayload {
+    /// The user was updated.
+    Updated(mas_data_model::User),
+
+    /// The user was not found.
+    NotFound,
+}
+
+#[Object(use_type_description)]
+impl SetSecurityNotificationOptOutPayload {
+    /// The user that was updated.
+    async fn user(&self) -> Option<User> {
+        match self {
+            Self::Updated(user) => Some(User(user.clone())),
+            Self::NotFound => None,
+        }
+    }
+}
+
 fn valid_username_character(c: char) -> bool {
     c.is_ascii_lowercase()
         || c.is_ascii_digit()
@@ -221,6 +346,10 @@ impl UserMutations {
             .schedule_job(ProvisionUserJob::new(&user))
             .await?;
 
+        repo.job()
+            .schedule_job(NotifyUserEventJob::new(&user, UserLifecycleEvent::Created))
+            .await?;
+
         repo.save().await?;
 
         Ok(AddUserPayload::Added(user))
@@ -252,6 +381,14 @@ impl UserMutations {
 
         let user = repo.user().lock(&state.clock(), user).await?;
 
+        repo.job()
+            .schedule_job(NotifyUserEventJob::new(&user, UserLifecycleEvent::Locked))
+            .await?;
+
+        repo.job()
+            .schedule_job(SendSecurityNotificationJob::account_locked(&user))
+            .await?;
+
         if deactivate {
             info!("Scheduling deactivation of user {}", user.id);
             repo.job()
@@ -261,9 +398,62 @@ impl UserMutations {
 
         repo.save().await?;
 
+        state.user_changed(user.id);
+
         Ok(LockUserPayload::Locked(user))
     }
 
+    /// Schedule the deactivation of a user, optionally erasing their data.
+    /// When it runs, the user is locked and all their sessions are ended.
+    /// This is only available to administrators.
+    async fn schedule_user_deactivation(
+        &self,
+        ctx: &Context<'_>,
+        input: ScheduleUserDeactivationInput,
+    ) -> Result<ScheduleUserDeactivationPayload, async_graphql::Error> {
+        let state = ctx.state();
+        let requester = ctx.requester();
+
+        if !requester.is_admin() {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let mut repo = state.repository().await?;
+
+        let user_id = NodeType::User.extract_ulid(&input.user_id)?;
+        let user = repo.user().lookup(user_id).await?;
+
+        let Some(user) = user else {
+            return Ok(ScheduleUserDeactivationPayload::NotFound);
+        };
+
+        let erase = input.erase.unwrap_or(false);
+        let deactivate_at = input.deactivate_at.unwrap_or_else(|| state.clock().now());
+
+        let admin = requester.user();
+        info!(
+            audit = true,
+            audit.action = "user.deactivation.schedule",
+            user.id = %user.id,
+            user.username = %user.username,
+            admin.id = admin.map(|admin| tracing::field::display(admin.id)),
+            admin.username = admin.map(|admin| admin.username.as_str()),
+            %deactivate_at,
+            erase,
+            "Administrator scheduled the deactivation of a user"
+        );
+        repo.job()
+            .schedule_job_at(
+                DeactivateUserJob::new(&user, erase).with_erase_pii(erase),
+                deactivate_at,
+            )
+            .await?;
+
+        repo.save().await?;
+
+        Ok(ScheduleUserDeactivationPayload::Scheduled(user))
+    }
+
     /// Set whether a user can request admin. This is only available to
     /// administrators.
     async fn set_can_request_admin(
@@ -294,6 +484,76 @@ impl UserMutations {
 
         repo.save().await?;
 
+        state.user_changed(user.id);
+
         Ok(SetCanRequestAdminPayload::Updated(user))
     }
+
+    /// Set whether a user is quarantined. Quarantined users can still use
+    /// their existing sessions, but can't start new ones. This is only
+    /// available to administrators.
+    async fn set_quarantined(
+        &self,
+        ctx: &Context<'_>,
+        input: SetQuarantinedInput,
+    ) -> Result<SetQuarantinedPayload, async_graphql::Error> {
+        let state = ctx.state();
+        let requester = ctx.requester();
+
+        if !requester.is_admin() {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let mut repo = state.repository().await?;
+
+        let user_id = NodeType::User.extract_ulid(&input.user_id)?;
+        let user = repo.user().lookup(user_id).await?;
+
+        let Some(user) = user else {
+            return Ok(SetQuarantinedPayload::NotFound);
+        };
+
+        let user = if input.quarantined {
+            repo.user().quarantine(&state.clock(), user).await?
+        } else {
+            repo.user().unquarantine(user).await?
+        };
+
+        repo.save().await?;
+
+        state.user_changed(user.id);
+
+        Ok(SetQuarantinedPayload::Updated(user))
+    }
+
+    /// Opt a user in or out of a category of security notification emails.
+    async fn set_security_notification_opt_out(
+        &self,
+        ctx: &Context<'_>,
+        input: SetSecurityNotificationOptOutInput,
+    ) -> Result<SetSecurityNotificationOptOutPayload, async_graphql::Error> {
+        let state = ctx.state();
+        let requester = ctx.requester();
+
+        let user_id = NodeType::User.extract_ulid(&input.user_id)?;
+        if !requester.is_owner_or_admin(&UserId(user_id)) {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let mut repo = state.repository().await?;
+
+        let user = repo.user().lookup(user_id).await?;
+
+        let Some(user) = user else {
+            return Ok(SetSecurityNotificationOptOutPayload::NotFound);
+        };
+
+        repo.user()
+            .set_security_notification_opt_out(&user, input.category.into(), input.opt_out)
+            .await?;
+
+        repo.save().await?;
+
+        Ok(SetSecurityNotificationOptOutPayload::Updated(user))
+    }
 }
@@ -14,16 +14,20 @@
 
 use anyhow::Context as _;
 use async_graphql::{Context, Description, Enum, InputObject, Object, ID};
+use chrono::{DateTime, Utc};
+use mas_data_model::{EmailRateLimited, ProfileAttribute};
 use mas_storage::{
-    job::{JobRepositoryExt, ProvisionUserJob, VerifyEmailJob},
-    user::{UserEmailRepository, UserRepository},
-    RepositoryAccess,
+    job::{JobRepositoryExt, NotifyUserEventJob, ProvisionUserJob},
+    user::{continue_email_change, UserEmailRepository, UserRepository},
+    BoxRepository, Clock, RepositoryAccess,
 };
+use rand::distributions::{Alphanumeric, DistString};
+use tracing::warn;
 
 use crate::{
-    model::{NodeType, User, UserEmail},
+    model::{locked_profile_attributes, NodeType, User, UserEmail},
     state::ContextExt,
-    UserId,
+    BoxState, UserId,
 };
 
 #[derive(Default)]
@@ -137,6 +141,8 @@ enum SendVerificationEmailStatus {
     Sent,
     /// The email address is already verified
     AlreadyVerified,
+    /// Too many verification emails were sent recently
+    RateLimited,
 }
 
 /// The payload of the `sendVerificationEmail` mutation
@@ -144,6 +150,7 @@ enum SendVerificationEmailStatus {
 enum SendVerificationEmailPayload {
     Sent(mas_data_model::UserEmail),
     AlreadyVerified(mas_data_model::UserEmail),
+    RateLimited(mas_data_model::UserEmail, EmailRateLimited),
 }
 
 #[Object(use_type_description)]
@@ -155,6 +162,9 @@ impl SendVerificationEmailPayload {
             SendVerificationEmailPayload::AlreadyVerified(_) => {
                 SendVerificationEmailStatus::AlreadyVerified
             }
+            SendVerificationEmailPayload::RateLimited(_, _) => {
+                SendVerificationEmailStatus::RateLimited
+            }
         }
     }
 
@@ -162,7 +172,18 @@ impl SendVerificationEmailPayload {
     async fn email(&self) -> UserEmail {
         match self {
             SendVerificationEmailPayload::Sent(email)
-            | SendVerificationEmailPayload::AlreadyVerified(email) => UserEmail(email.clone()),
+            | SendVerificationEmailPayload::AlreadyVerified(email)
+            | SendVerificationEmailPayload::RateLimited(email, _) => UserEmail(email.clone()),
+        }
+    }
+
+    /// When a new verification email can be requested, if the rate limits
+    /// were hit and the delay is known
+    async fn retry_after(&self) -> Option<DateTime<Utc>> {
+        match self {
+            SendVerificationEmailPayload::RateLimited(_, e) => e.retry_after(),
+            SendVerificationEmailPayload::Sent(_)
+            | SendVerificationEmailPayload::AlreadyVerified(_) => None,
         }
     }
 
@@ -173,7 +194,8 @@ impl SendVerificationEmailPayload {
 
         let user_id = match self {
             SendVerificationEmailPayload::Sent(email)
-            | SendVerificationEmailPayload::AlreadyVerified(email) => email.user_id,
+            | SendVerificationEmailPayload::AlreadyVerified(email)
+            | SendVerificationEmailPayload::RateLimited(email, _) => email.user_id,
         };
 
         let user = repo
@@ -275,6 +297,10 @@ enum RemoveEmailStatus {
 
     /// The email address was not found
     NotFound,
+
+    /// Can't remove an email address involved in a change of the primary
+    /// email address which is in progress
+    ChangeInProgress,
 }
 
 /// The payload of the `removeEmail` mutation
@@ -283,6 +309,7 @@ enum RemoveEmailPayload {
     Removed(mas_data_model::UserEmail),
     Primary(mas_data_model::UserEmail),
     NotFound,
+    ChangeInProgress(mas_data_model::UserEmail),
 }
 
 #[Object(use_type_description)]
@@ -293,15 +320,16 @@ impl RemoveEmailPayload {
             RemoveEmailPayload::Removed(_) => RemoveEmailStatus::Removed,
             RemoveEmailPayload::Primary(_) => RemoveEmailStatus::Primary,
             RemoveEmailPayload::NotFound => RemoveEmailStatus::NotFound,
+            RemoveEmailPayload::ChangeInProgress(_) => RemoveEmailStatus::ChangeInProgress,
         }
     }
 
     /// The email address that was removed
     async fn email(&self) -> Option<UserEmail> {
         match self {
-            RemoveEmailPayload::Removed(email) | RemoveEmailPayload::Primary(email) => {
-                Some(UserEmail(email.clone()))
-            }
+            RemoveEmailPayload::Removed(email)
+            | RemoveEmailPayload::Primary(email)
+            | RemoveEmailPayload::ChangeInProgress(email) => Some(UserEmail(email.clone())),
             RemoveEmailPayload::NotFound => None,
         }
     }
@@ -312,9 +340,9 @@ impl RemoveEmailPayload {
         let mut repo = state.repository().await?;
 
         let user_id = match self {
-            RemoveEmailPayload::Removed(email) | RemoveEmailPayload::Primary(email) => {
-                email.user_id
-            }
+            RemoveEmailPayload::Removed(email)
+            | RemoveEmailPayload::Primary(email)
+            | RemoveEmailPayload::ChangeInProgress(email) => email.user_id,
             RemoveEmailPayload::NotFound => return Ok(None),
         };
 
@@ -344,6 +372,10 @@ enum SetPrimaryEmailStatus {
     NotFound,
     /// Can't make an unverified email address primary
     Unverified,
+    /// The current primary email address has to confirm the change first
+    AwaitingConfirmation,
+    /// Another change of the primary email address is in progress
+    ChangeInProgress,
 }
 
 /// The payload of the `setPrimaryEmail` mutation
@@ -352,6 +384,8 @@ enum SetPrimaryEmailPayload {
     Set(mas_data_model::User),
     NotFound,
     Unverified,
+    AwaitingConfirmation(mas_data_model::User),
+    ChangeInProgress,
 }
 
 #[Object(use_type_description)]
@@ -361,16 +395,151 @@ impl SetPrimaryEmailPayload {
             SetPrimaryEmailPayload::Set(_) => SetPrimaryEmailStatus::Set,
             SetPrimaryEmailPayload::NotFound => SetPrimaryEmailStatus::NotFound,
             SetPrimaryEmailPayload::Unverified => SetPrimaryEmailStatus::Unverified,
+            SetPrimaryEmailPayload::AwaitingConfirmation(_) => {
+                SetPrimaryEmailStatus::AwaitingConfirmation
+            }
+            SetPrimaryEmailPayload::ChangeInProgress => SetPrimaryEmailStatus::ChangeInProgress,
         }
     }
 
     /// The user to whom the email address belongs
     async fn user(&self) -> Option<User> {
         match self {
-            SetPrimaryEmailPayload::Set(user) => Some(User(user.clone())),
-            SetPrimaryEmailPayload::NotFound | SetPrimaryEmailPayload::Unverified => None,
+            SetPrimaryEmailPayload::Set(user)
+            | SetPrimaryEmailPayload::AwaitingConfirmation(user) => Some(User(user.clone())),
+            SetPrimaryEmailPayload::NotFound
+            | SetPrimaryEmailPayload::Unverified
+            | SetPrimaryEmailPayload::ChangeInProgress => None,
+        }
+    }
+}
+
+/// The input for the `startEmailChange` mutation
+#[derive(InputObject)]
+struct StartEmailChangeInput {
+    /// The ID of the user changing their email address
+    user_id: ID,
+
+    /// The email address which should replace the current primary one
+    email: String,
+}
+
+/// The status of the `startEmailChange` mutation
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+enum StartEmailChangeStatus {
+    /// The change was started, the new email address needs to be verified
+    Started,
+    /// The new email address was already verified, the current primary email
+    /// address has to confirm the change
+    AwaitingConfirmation,
+    /// The new email address was already verified, the change is done
+    Completed,
+    /// The email address is invalid
+    Invalid,
+    /// The email address is not allowed by the policy
+    Denied,
+    /// The user has no verified primary email address to change
+    NoPrimary,
+    /// The email address is already the primary one
+    Same,
+    /// Another change of the primary email address is in progress
+    ChangeInProgress,
+}
+
+/// The payload of the `startEmailChange` mutation
+#[derive(Description)]
+enum StartEmailChangePayload {
+    Started(mas_data_model::UserEmail),
+    AwaitingConfirmation(mas_data_model::UserEmail),
+    Completed(mas_data_model::UserEmail),
+    Invalid,
+    Denied {
+        violations: Vec<mas_policy::Violation>,
+    },
+    NoPrimary,
+    Same,
+    ChangeInProgress,
+}
+
+#[Object(use_type_description)]
+impl StartEmailChangePayload {
+    /// Status of the operation
+    async fn status(&self) -> StartEmailChangeStatus {
+        match self {
+            Self::Started(_) => StartEmailChangeStatus::Started,
+            Self::AwaitingConfirmation(_) => StartEmailChangeStatus::AwaitingConfirmation,
+            Self::Completed(_) => StartEmailChangeStatus::Completed,
+            Self::Invalid => StartEmailChangeStatus::Invalid,
+            Self::Denied { .. } => StartEmailChangeStatus::Denied,
+            Self::NoPrimary => StartEmailChangeStatus::NoPrimary,
+            Self::Same => StartEmailChangeStatus::Same,
+            Self::ChangeInProgress => StartEmailChangeStatus::ChangeInProgress,
+        }
+    }
+
+    /// The email address which will replace the primary one
+    async fn email(&self) -> Option<UserEmail> {
+        match self {
+            Self::Started(email) | Self::AwaitingConfirmation(email) | Self::Completed(email) => {
+                Some(UserEmail(email.clone()))
+            }
+            Self::Invalid
+            | Self::Denied { .. }
+            | Self::NoPrimary
+            | Self::Same
+            | Self::ChangeInProgress => None,
         }
     }
+
+    /// The list of policy violations if the email address was denied
+    async fn violations(&self) -> Option<Vec<String>> {
+        let Self::Denied { violations } = self else {
+            return None;
+        };
+
+        let messages = violations.iter().map(|v| v.msg.clone()).collect();
+        Some(messages)
+    }
+}
+
+/// Schedule a job to send a verification code to the given address, unless
+/// too many were sent to it or to its user recently.
+async fn send_verification_email(
+    state: &BoxState,
+    repo: &mut BoxRepository,
+    user_email: &mas_data_model::UserEmail,
+    language: Option<&str>,
+) -> Result<Result<(), EmailRateLimited>, async_graphql::Error> {
+    let clock = state.clock();
+    let mut rng = state.rng();
+    let limits = state.email_rate_limits();
+
+    let res = mas_storage::user::send_verification_email(
+        repo, &mut rng, &clock, &limits, user_email, language,
+    )
+    .await?;
+
+    Ok(res)
+}
+
+/// Fail unless the requester is an admin, if the email addresses of the user
+/// are managed by an upstream provider they are linked to.
+async fn ensure_emails_unlocked(
+    requester_is_admin: bool,
+    repo: &mut BoxRepository,
+    user: &mas_data_model::User,
+) -> Result<(), async_graphql::Error> {
+    if !requester_is_admin
+        && locked_profile_attributes(repo, user)
+            .await?
+            .contains(&ProfileAttribute::Email)
+    {
+        return Err(async_graphql::Error::new(
+            "Email addresses are managed by an upstream provider",
+        ));
+    }
+
+    Ok(())
 }
 
 #[Object]
@@ -407,6 +576,8 @@ impl UserEmailMutations {
             .await?
             .context("Failed to load user")?;
 
+        ensure_emails_unlocked(requester.is_admin(), &mut repo, &user).await?;
+
         // XXX: this logic should be extracted somewhere else, since most of it is
         // duplicated in mas_handlers
 
@@ -448,11 +619,15 @@ impl UserEmailMutations {
                     .user_email()
                     .mark_as_verified(&state.clock(), user_email)
                     .await?;
-            } else {
-                // TODO: figure out the locale
+
                 repo.job()
-                    .schedule_job(VerifyEmailJob::new(&user_email))
+                    .schedule_job(NotifyUserEventJob::email_verified(&user_email))
                     .await?;
+            } else if let Err(e) =
+                send_verification_email(state, &mut repo, &user_email, ctx.language()).await?
+            {
+                // The user can ask for a new code later on
+                warn!(user_email.id = %user_email.id, error = %e, "Not sending the verification email");
             }
         }
 
@@ -488,23 +663,21 @@ impl UserEmailMutations {
             return Err(async_graphql::Error::new("User email not found"));
         }
 
-        // Schedule a job to verify the email address if needed
-        let needs_verification = user_email.confirmed_at.is_none();
-        if needs_verification {
-            // TODO: figure out the locale
-            repo.job()
-                .schedule_job(VerifyEmailJob::new(&user_email))
-                .await?;
+        if user_email.confirmed_at.is_some() {
+            return Ok(SendVerificationEmailPayload::AlreadyVerified(user_email));
+        }
+
+        // Schedule a job to verify the email address, unless too many were sent
+        if let Err(e) =
+            send_verification_email(state, &mut repo, &user_email, ctx.language()).await?
+        {
+            repo.cancel().await?;
+            return Ok(SendVerificationEmailPayload::RateLimited(user_email, e));
         }
 
         repo.save().await?;
 
-        let payload = if needs_verification {
-            SendVerificationEmailPayload::Sent(user_email)
-        } else {
-            SendVerificationEmailPayload::AlreadyVerified(user_email)
-        };
-        Ok(payload)
+        Ok(SendVerificationEmailPayload::Sent(user_email))
     }
 
     /// Submit a verification code for an email address
@@ -570,10 +743,25 @@ impl UserEmailMutations {
             .mark_as_verified(&clock, user_email)
             .await?;
 
+        // If this address was meant to replace the primary one, ask the old
+        // address to confirm the change
+        continue_email_change(
+            &mut repo,
+            &clock,
+            &user_email,
+            requester.is_admin(),
+            ctx.language(),
+        )
+        .await?;
+
         repo.job()
             .schedule_job(ProvisionUserJob::new(&user))
             .await?;
 
+        repo.job()
+            .schedule_job(NotifyUserEventJob::email_verified(&user_email))
+            .await?;
+
         repo.save().await?;
 
         Ok(VerifyEmailPayload::Verified(user_email))
@@ -606,11 +794,25 @@ impl UserEmailMutations {
             .await?
             .context("Failed to load user")?;
 
+        ensure_emails_unlocked(requester.is_admin(), &mut repo, &user).await?;
+
         if user.primary_user_email_id == Some(user_email.id) {
             // Prevent removing the primary email address
             return Ok(RemoveEmailPayload::Primary(user_email));
         }
 
+        // Prevent removing an address from which a change can still be
+        // confirmed or reverted, or which is about to become primary
+        let change = repo
+            .user_email()
+            .find_change_in_progress(&state.clock(), &user)
+            .await?;
+        if change.is_some_and(|c| {
+            c.old_user_email_id == user_email.id || c.new_user_email_id == user_email.id
+        }) {
+            return Ok(RemoveEmailPayload::ChangeInProgress(user_email));
+        }
+
         repo.user_email().remove(user_email.clone()).await?;
 
         // Schedule a job to update the user
@@ -623,7 +825,10 @@ impl UserEmailMutations {
         Ok(RemoveEmailPayload::Removed(user_email))
     }
 
-    /// Set an email address as primary
+    /// Set an email address as primary.
+    ///
+    /// If the user already has a verified primary email address, it has to
+    /// confirm the change first, unless the requester is an admin.
     async fn set_primary_email(
         &self,
         ctx: &Context<'_>,
@@ -633,6 +838,7 @@ impl UserEmailMutations {
         let user_email_id = NodeType::UserEmail.extract_ulid(&input.user_email_id)?;
         let requester = ctx.requester();
 
+        let clock = state.clock();
         let mut repo = state.repository().await?;
 
         let user_email = repo.user_email().lookup(user_email_id).await?;
@@ -648,17 +854,191 @@ impl UserEmailMutations {
             return Ok(SetPrimaryEmailPayload::Unverified);
         }
 
-        repo.user_email().set_as_primary(&user_email).await?;
+        let user = repo
+            .user()
+            .lookup(user_email.user_id)
+            .await?
+            .context("Failed to load user")?;
+        ensure_emails_unlocked(requester.is_admin(), &mut repo, &user).await?;
+
+        if repo
+            .user_email()
+            .find_change_in_progress(&clock, &user)
+            .await?
+            .is_some()
+        {
+            return Ok(SetPrimaryEmailPayload::ChangeInProgress);
+        }
+
+        let old_email = repo
+            .user_email()
+            .get_primary(&user)
+            .await?
+            .filter(|e| e.confirmed_at.is_some() && e.id != user_email.id);
+
+        // Replacing a verified primary address goes through the email change
+        // flow, so that the old address can confirm and revert it
+        let Some(old_email) = old_email else {
+            repo.user_email().set_as_primary(&user_email).await?;
+
+            // The user primary email should already be up to date
+            let user = repo
+                .user()
+                .lookup(user_email.user_id)
+                .await?
+                .context("Failed to load user")?;
+
+            repo.save().await?;
+
+            return Ok(SetPrimaryEmailPayload::Set(user));
+        };
+
+        let mut rng = state.rng();
+        let revert_token = Alphanumeric.sample_string(&mut rng, 32);
+        repo.user_email()
+            .add_change(&mut rng, &clock, &old_email, &user_email, revert_token)
+            .await?;
+
+        let change = continue_email_change(
+            &mut repo,
+            &clock,
+            &user_email,
+            requester.is_admin(),
+            ctx.language(),
+        )
+        .await?
+        .context("Failed to start the email change")?;
 
-        // The user primary email should already be up to date
         let user = repo
             .user()
             .lookup(user_email.user_id)
             .await?
             .context("Failed to load user")?;
 
+        let payload = if change.completed_at.is_some() {
+            repo.job()
+                .schedule_job(ProvisionUserJob::new(&user))
+                .await?;
+            SetPrimaryEmailPayload::Set(user)
+        } else {
+            SetPrimaryEmailPayload::AwaitingConfirmation(user)
+        };
+
         repo.save().await?;
 
-        Ok(SetPrimaryEmailPayload::Set(user))
+        Ok(payload)
+    }
+
+    /// Start replacing the primary email address of the specified user.
+    ///
+    /// The new address has to be verified first. Once it is, the old address
+    /// has to confirm the change, unless the requester is an admin. After the
+    /// switch-over, the old address can revert the change for a while, after
+    /// which it is removed from the account.
+    async fn start_email_change(
+        &self,
+        ctx: &Context<'_>,
+        input: StartEmailChangeInput,
+    ) -> Result<StartEmailChangePayload, async_graphql::Error> {
+        let state = ctx.state();
+        let id = NodeType::User.extract_ulid(&input.user_id)?;
+        let requester = ctx.requester();
+
+        if !requester.is_owner_or_admin(&UserId(id)) {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let clock = state.clock();
+        let mut rng = state.rng();
+        let mut repo = state.repository().await?;
+
+        let user = repo
+            .user()
+            .lookup(id)
+            .await?
+            .context("Failed to load user")?;
+
+        ensure_emails_unlocked(requester.is_admin(), &mut repo, &user).await?;
+
+        if repo
+            .user_email()
+            .find_change_in_progress(&clock, &user)
+            .await?
+            .is_some()
+        {
+            return Ok(StartEmailChangePayload::ChangeInProgress);
+        }
+
+        let Some(old_email) = repo
+            .user_email()
+            .get_primary(&user)
+            .await?
+            .filter(|e| e.confirmed_at.is_some())
+        else {
+            return Ok(StartEmailChangePayload::NoPrimary);
+        };
+
+        if old_email.email == input.email {
+            return Ok(StartEmailChangePayload::Same);
+        }
+
+        if input.email.parse::<lettre::Address>().is_err() {
+            return Ok(StartEmailChangePayload::Invalid);
+        }
+
+        let mut policy = state.policy().await?;
+        let res = policy.evaluate_email(&input.email).await?;
+        if !res.valid() {
+            return Ok(StartEmailChangePayload::Denied {
+                violations: res.violations,
+            });
+        }
+
+        let existing = repo.user_email().find(&user, &input.email).await?;
+        let new_email = if let Some(new_email) = existing {
+            new_email
+        } else {
+            repo.user_email()
+                .add(&mut rng, &clock, &user, input.email)
+                .await?
+        };
+
+        let revert_token = Alphanumeric.sample_string(&mut rng, 32);
+        repo.user_email()
+            .add_change(&mut rng, &clock, &old_email, &new_email, revert_token)
+            .await?;
+
+        let payload = if new_email.confirmed_at.is_some() {
+            let change = continue_email_change(
+                &mut repo,
+                &clock,
+                &new_email,
+                requester.is_admin(),
+                ctx.language(),
+            )
+            .await?
+            .context("Failed to start the email change")?;
+
+            if change.completed_at.is_some() {
+                repo.job()
+                    .schedule_job(ProvisionUserJob::new(&user))
+                    .await?;
+                StartEmailChangePayload::Completed(new_email)
+            } else {
+                StartEmailChangePayload::AwaitingConfirmation(new_email)
+            }
+        } else {
+            if let Err(e) =
+                send_verification_email(state, &mut repo, &new_email, ctx.language()).await?
+            {
+                // The user can ask for a new code later on
+                warn!(user_email.id = %new_email.id, error = %e, "Not sending the verification email");
+            }
+            StartEmailChangePayload::Started(new_email)
+        };
+
+        repo.save().await?;
+
+        Ok(payload)
     }
 }
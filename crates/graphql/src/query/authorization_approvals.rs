@@ -0,0 +1,60 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_graphql::{Context, Object};
+use mas_storage::{oauth2::OAuth2AuthorizationGrantRepository, RepositoryAccess};
+
+use crate::{model::AuthorizationApprovalRequest, state::ContextExt};
+
+/// The maximum number of authorization requests returned at once
+const MAX_APPROVAL_REQUESTS: usize = 100;
+
+#[derive(Default)]
+pub struct AuthorizationApprovalsQuery;
+
+#[Object]
+impl AuthorizationApprovalsQuery {
+    /// Get the authorization requests waiting for an administrator to approve
+    /// them, oldest first.
+    ///
+    /// This is only available to administrators.
+    async fn authorization_approval_requests(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "Returns at most this many requests, up to 100.")] first: Option<usize>,
+    ) -> Result<Vec<AuthorizationApprovalRequest>, async_graphql::Error> {
+        let state = ctx.state();
+        let requester = ctx.requester();
+
+        if !requester.is_admin() {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let limit = first
+            .unwrap_or(MAX_APPROVAL_REQUESTS)
+            .min(MAX_APPROVAL_REQUESTS);
+
+        let mut repo = state.repository().await?;
+        let grants = repo
+            .oauth2_authorization_grant()
+            .list_awaiting_approval(limit)
+            .await?;
+        repo.cancel().await?;
+
+        Ok(grants
+            .into_iter()
+            .map(AuthorizationApprovalRequest)
+            .collect())
+    }
+}
@@ -0,0 +1,52 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_graphql::{Context, Object};
+use mas_storage::RepositoryAccess;
+
+use crate::{model::FailedJob, state::ContextExt};
+
+/// The maximum number of failed jobs returned at once
+const MAX_FAILED_JOBS: usize = 100;
+
+#[derive(Default)]
+pub struct JobsQuery;
+
+#[Object]
+impl JobsQuery {
+    /// Get the most recent background jobs which failed and won't be retried
+    /// anymore, e.g. because the homeserver was unreachable for too long.
+    ///
+    /// This is only available to administrators.
+    async fn failed_jobs(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "Returns at most this many jobs, up to 100.")] first: Option<usize>,
+    ) -> Result<Vec<FailedJob>, async_graphql::Error> {
+        let state = ctx.state();
+        let requester = ctx.requester();
+
+        if !requester.is_admin() {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let limit = first.unwrap_or(MAX_FAILED_JOBS).min(MAX_FAILED_JOBS);
+
+        let mut repo = state.repository().await?;
+        let jobs = repo.job().list_failed(limit).await?;
+        repo.cancel().await?;
+
+        Ok(jobs.into_iter().map(FailedJob).collect())
+    }
+}
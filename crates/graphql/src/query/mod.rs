@@ -21,15 +21,27 @@ use crate::{
     UserId,
 };
 
+mod authorization_approvals;
+mod jobs;
 mod session;
 mod upstream_oauth;
 mod viewer;
 
-use self::{session::SessionQuery, upstream_oauth::UpstreamOAuthQuery, viewer::ViewerQuery};
+use self::{
+    authorization_approvals::AuthorizationApprovalsQuery, jobs::JobsQuery, session::SessionQuery,
+    upstream_oauth::UpstreamOAuthQuery, viewer::ViewerQuery,
+};
 
 /// The query root of the GraphQL interface.
 #[derive(Default, MergedObject)]
-pub struct Query(BaseQuery, UpstreamOAuthQuery, SessionQuery, ViewerQuery);
+pub struct Query(
+    BaseQuery,
+    UpstreamOAuthQuery,
+    SessionQuery,
+    ViewerQuery,
+    JobsQuery,
+    AuthorizationApprovalsQuery,
+);
 
 impl Query {
     #[must_use]
@@ -12,7 +12,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use async_graphql::{Context, Object, Union, ID};
+use async_graphql::{
+    connection::{query, Connection, Edge, OpaqueCursor},
+    Context, Object, Union, ID,
+};
+use chrono::{DateTime, Utc};
 use mas_data_model::Device;
 use mas_storage::{
     compat::CompatSessionRepository, oauth2::OAuth2SessionFilter, Pagination, RepositoryAccess,
@@ -20,7 +24,10 @@ use mas_storage::{
 use oauth2_types::scope::Scope;
 
 use crate::{
-    model::{CompatSession, NodeType, OAuth2Session},
+    model::{
+        CompatSession, Cursor, NodeCursor, NodeType, OAuth2Session, PreloadedTotalCount,
+        SessionState,
+    },
     state::ContextExt,
     UserId,
 };
@@ -101,4 +108,139 @@ impl SessionQuery {
 
         Ok(None)
     }
+    /// Get the list of OAuth 2.0 sessions of all users, chronologically
+    /// sorted.
+    ///
+    /// This is only available to administrators.
+    #[allow(clippy::too_many_arguments)]
+    async fn oauth2_sessions(
+        &self,
+        ctx: &Context<'_>,
+
+        #[graphql(name = "state", desc = "List only sessions in the given state.")]
+        state_param: Option<SessionState>,
+
+        #[graphql(desc = "List only sessions of the given user.")] user: Option<ID>,
+
+        #[graphql(desc = "List only sessions for the given client.")] client: Option<ID>,
+
+        #[graphql(desc = "List only sessions created at or after the given time.")]
+        created_after: Option<DateTime<Utc>>,
+
+        #[graphql(desc = "List only sessions created before the given time.")]
+        created_before: Option<DateTime<Utc>>,
+
+        #[graphql(desc = "Returns the elements in the list that come after the cursor.")]
+        after: Option<String>,
+        #[graphql(desc = "Returns the elements in the list that come before the cursor.")]
+        before: Option<String>,
+        #[graphql(desc = "Returns the first *n* elements from the list.")] first: Option<i32>,
+        #[graphql(desc = "Returns the last *n* elements from the list.")] last: Option<i32>,
+    ) -> Result<Connection<Cursor, OAuth2Session, PreloadedTotalCount>, async_graphql::Error> {
+        let requester = ctx.requester();
+        if !requester.is_admin() {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let state = ctx.state();
+        let mut repo = state.repository().await?;
+
+        query(
+            after,
+            before,
+            first,
+            last,
+            |after, before, first, last| async move {
+                let after_id = after
+                    .map(|x: OpaqueCursor<NodeCursor>| x.extract_for_type(NodeType::OAuth2Session))
+                    .transpose()?;
+                let before_id = before
+                    .map(|x: OpaqueCursor<NodeCursor>| x.extract_for_type(NodeType::OAuth2Session))
+                    .transpose()?;
+                let pagination = Pagination::try_new(before_id, after_id, first, last)?;
+
+                let user = if let Some(id) = user {
+                    // Load the user if we're filtering by it
+                    let id = NodeType::User.extract_ulid(&id)?;
+                    let user = repo
+                        .user()
+                        .lookup(id)
+                        .await?
+                        .ok_or(async_graphql::Error::new("Unknown user ID"))?;
+
+                    Some(user)
+                } else {
+                    None
+                };
+
+                let client = if let Some(id) = client {
+                    // Load the client if we're filtering by it
+                    let id = NodeType::OAuth2Client.extract_ulid(&id)?;
+                    let client = repo
+                        .oauth2_client()
+                        .lookup(id)
+                        .await?
+                        .ok_or(async_graphql::Error::new("Unknown client ID"))?;
+
+                    Some(client)
+                } else {
+                    None
+                };
+
+                let filter = OAuth2SessionFilter::new();
+
+                let filter = match state_param {
+                    Some(SessionState::Active) => filter.active_only(),
+                    Some(SessionState::Finished) => filter.finished_only(),
+                    None => filter,
+                };
+
+                let filter = match user.as_ref() {
+                    Some(user) => filter.for_user(user),
+                    None => filter,
+                };
+
+                let filter = match client.as_ref() {
+                    Some(client) => filter.for_client(client),
+                    None => filter,
+                };
+
+                let filter = match created_after {
+                    Some(created_after) => filter.with_created_after(created_after),
+                    None => filter,
+                };
+
+                let filter = match created_before {
+                    Some(created_before) => filter.with_created_before(created_before),
+                    None => filter,
+                };
+
+                let page = repo.oauth2_session().list(filter, pagination).await?;
+
+                let count = if ctx.look_ahead().field("totalCount").exists() {
+                    Some(repo.oauth2_session().count(filter).await?)
+                } else {
+                    None
+                };
+
+                repo.cancel().await?;
+
+                let mut connection = Connection::with_additional_fields(
+                    page.has_previous_page,
+                    page.has_next_page,
+                    PreloadedTotalCount(count),
+                );
+
+                connection.edges.extend(page.edges.into_iter().map(|s| {
+                    Edge::new(
+                        OpaqueCursor(NodeCursor(NodeType::OAuth2Session, s.id)),
+                        OAuth2Session(s),
+                    )
+                }));
+
+                Ok::<_, async_graphql::Error>(connection)
+            },
+        )
+        .await
+    }
 }
@@ -12,11 +12,13 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use mas_data_model::EmailRateLimits;
 use mas_matrix::HomeserverConnection;
 use mas_policy::Policy;
 use mas_storage::{BoxClock, BoxRepository, BoxRng, RepositoryError};
+use ulid::Ulid;
 
-use crate::Requester;
+use crate::{RequestLanguage, Requester};
 
 #[async_trait::async_trait]
 pub trait State {
@@ -25,6 +27,15 @@ pub trait State {
     fn homeserver_connection(&self) -> &dyn HomeserverConnection<Error = anyhow::Error>;
     fn clock(&self) -> BoxClock;
     fn rng(&self) -> BoxRng;
+    fn email_rate_limits(&self) -> EmailRateLimits;
+
+    /// Called once a session ended, so that what is cached about its tokens is
+    /// forgotten
+    fn session_ended(&self, session_id: Ulid);
+
+    /// Called once a user got locked or otherwise changed, so that what is
+    /// cached about their tokens is forgotten
+    fn user_changed(&self, user_id: Ulid);
 }
 
 pub type BoxState = Box<dyn State + Send + Sync + 'static>;
@@ -33,6 +44,8 @@ pub trait ContextExt {
     fn state(&self) -> &BoxState;
 
     fn requester(&self) -> &Requester;
+
+    fn language(&self) -> Option<&str>;
 }
 
 impl ContextExt for async_graphql::Context<'_> {
@@ -43,4 +56,9 @@ impl ContextExt for async_graphql::Context<'_> {
     fn requester(&self) -> &Requester {
         self.data_unchecked()
     }
+
+    fn language(&self) -> Option<&str> {
+        self.data_opt::<RequestLanguage>()
+            .map(|RequestLanguage(language)| language.as_str())
+    }
 }
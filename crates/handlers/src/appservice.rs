@@ -0,0 +1,108 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use regex::RegexSet;
+
+/// A Matrix application service, whose tokens can be introspected
+#[derive(Debug)]
+pub struct Appservice {
+    id: String,
+    as_token: String,
+    sender_localpart: String,
+    users: RegexSet,
+}
+
+impl Appservice {
+    /// Create a new [`Appservice`]
+    ///
+    /// # Parameters
+    ///
+    /// * `id` - The ID of the application service
+    /// * `as_token` - The token the application service authenticates with
+    /// * `sender_localpart` - The localpart of the application service sender
+    /// * `users` - Regular expressions matching the Matrix IDs of the users the
+    ///   application service can masquerade as
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if one of the user regular expressions is invalid
+    pub fn new<I>(
+        id: String,
+        as_token: String,
+        sender_localpart: String,
+        users: I,
+    ) -> Result<Self, regex::Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        // The regexes must match the whole Matrix ID
+        let users = RegexSet::new(
+            users
+                .into_iter()
+                .map(|regex| format!("^(?:{})$", regex.as_ref())),
+        )?;
+
+        Ok(Self {
+            id,
+            as_token,
+            sender_localpart,
+            users,
+        })
+    }
+
+    /// The ID of the application service
+    #[must_use]
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The localpart of the application service sender
+    #[must_use]
+    pub fn sender_localpart(&self) -> &str {
+        &self.sender_localpart
+    }
+
+    /// Whether the application service is allowed to act as the given user
+    #[must_use]
+    pub fn is_interested_in_user(&self, mxid: &str) -> bool {
+        self.users.is_match(mxid)
+    }
+}
+
+/// The list of application services known to the server
+#[derive(Debug, Clone, Default)]
+pub struct AppserviceRegistry {
+    appservices: Arc<[Appservice]>,
+}
+
+impl AppserviceRegistry {
+    /// Find the application service using the given token
+    #[must_use]
+    pub fn find_by_token(&self, token: &str) -> Option<&Appservice> {
+        self.appservices
+            .iter()
+            .find(|appservice| appservice.as_token == token)
+    }
+}
+
+impl FromIterator<Appservice> for AppserviceRegistry {
+    fn from_iter<T: IntoIterator<Item = Appservice>>(iter: T) -> Self {
+        Self {
+            appservices: iter.into_iter().collect(),
+        }
+    }
+}
@@ -12,11 +12,19 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
+
 use axum::{extract::State, response::IntoResponse, Json};
 use chrono::Duration;
 use hyper::StatusCode;
-use mas_axum_utils::sentry::SentryEventID;
+use mas_axum_utils::{
+    client_authorization::fetch_jwks, http_client_factory::HttpClientFactory, sentry::SentryEventID,
+};
 use mas_data_model::{CompatSession, CompatSsoLoginState, Device, TokenType, User};
+use mas_jose::{
+    claims::{self, TimeOptions},
+    jwt::Jwt,
+};
 use mas_storage::{
     compat::{
         CompatAccessTokenRepository, CompatRefreshTokenRepository, CompatSessionRepository,
@@ -34,8 +42,11 @@ use zeroize::Zeroizing;
 
 use super::{MatrixError, MatrixHomeserver};
 use crate::{
-    impl_from_error_for_route, passwords::PasswordManager, site_config::SiteConfig,
-    BoundActivityTracker,
+    impl_from_error_for_route,
+    ip_filter::{Action, IpDenied},
+    passwords::PasswordManager,
+    site_config::{JwtLoginConfig, SiteConfig},
+    BoundActivityTracker, IpFilter, Limiter, RateLimited,
 };
 
 #[derive(Debug, Serialize)]
@@ -49,6 +60,9 @@ enum LoginType {
     #[serde(rename = "m.login.token")]
     Token,
 
+    #[serde(rename = "org.matrix.login.jwt")]
+    Jwt,
+
     #[serde(rename = "m.login.sso")]
     Sso {
         #[serde(skip_serializing_if = "Vec::is_empty")]
@@ -70,8 +84,11 @@ struct LoginTypes {
 }
 
 #[tracing::instrument(name = "handlers.compat.login.get", skip_all)]
-pub(crate) async fn get(State(password_manager): State<PasswordManager>) -> impl IntoResponse {
-    let flows = if password_manager.is_enabled() {
+pub(crate) async fn get(
+    State(password_manager): State<PasswordManager>,
+    State(site_config): State<SiteConfig>,
+) -> impl IntoResponse {
+    let mut flows = if password_manager.is_enabled() {
         vec![
             LoginType::Password,
             LoginType::Sso {
@@ -90,6 +107,10 @@ pub(crate) async fn get(State(password_manager): State<PasswordManager>) -> impl
         ]
     };
 
+    if site_config.compat_jwt_login.is_some() {
+        flows.push(LoginType::Jwt);
+    }
+
     let res = LoginTypes { flows };
 
     Json(res)
@@ -116,6 +137,9 @@ pub enum Credentials {
     #[serde(rename = "m.login.token")]
     Token { token: String },
 
+    #[serde(rename = "org.matrix.login.jwt")]
+    Jwt { token: String },
+
     #[serde(other)]
     Unsupported,
 }
@@ -167,6 +191,18 @@ pub enum RouteError {
 
     #[error("invalid login token")]
     InvalidLoginToken,
+
+    #[error("invalid JWT")]
+    InvalidJwt,
+
+    #[error("user is quarantined")]
+    UserQuarantined,
+
+    #[error(transparent)]
+    RateLimited(#[from] RateLimited),
+
+    #[error(transparent)]
+    IpDenied(#[from] IpDenied),
 }
 
 impl_from_error_for_route!(mas_storage::RepositoryError);
@@ -202,6 +238,29 @@ impl IntoResponse for RouteError {
                 error: "Invalid login token",
                 status: StatusCode::FORBIDDEN,
             },
+            Self::InvalidJwt => MatrixError {
+                errcode: "M_FORBIDDEN",
+                error: "Invalid JWT",
+                status: StatusCode::FORBIDDEN,
+            },
+            Self::UserQuarantined => MatrixError {
+                errcode: "M_FORBIDDEN",
+                error: "This account can't log in at the moment",
+                status: StatusCode::FORBIDDEN,
+            },
+            Self::IpDenied(_) => MatrixError {
+                errcode: "M_FORBIDDEN",
+                error: "Logins from your network are not allowed",
+                status: StatusCode::FORBIDDEN,
+            },
+            Self::RateLimited(rate_limited) => {
+                let response = MatrixError {
+                    errcode: "M_LIMIT_EXCEEDED",
+                    error: "Too many login attempts",
+                    status: StatusCode::TOO_MANY_REQUESTS,
+                };
+                return (SentryEventID::from(event_id), rate_limited, response).into_response();
+            }
         };
 
         (SentryEventID::from(event_id), response).into_response()
@@ -217,8 +276,15 @@ pub(crate) async fn post(
     activity_tracker: BoundActivityTracker,
     State(homeserver): State<MatrixHomeserver>,
     State(site_config): State<SiteConfig>,
+    State(http_client_factory): State<HttpClientFactory>,
+    State(limiter): State<Limiter>,
+    State(ip_filter): State<IpFilter>,
     Json(input): Json<RequestBody>,
 ) -> Result<impl IntoResponse, RouteError> {
+    ip_filter
+        .check(activity_tracker.ip(), Action::Login)
+        .await?;
+
     let (session, user) = match (password_manager.is_enabled(), input.credentials) {
         (
             true,
@@ -227,7 +293,14 @@ pub(crate) async fn post(
                 password,
             },
         ) => {
-            user_password_login(
+            limiter
+                .check_login(clock.now(), activity_tracker.ip(), &user)
+                .await?;
+            limiter
+                .delay_login(clock.now(), activity_tracker.ip())
+                .await;
+
+            let res = user_password_login(
                 &mut rng,
                 &clock,
                 &password_manager,
@@ -235,11 +308,42 @@ pub(crate) async fn post(
                 user,
                 password,
             )
-            .await?
+            .await;
+
+            if let Err(
+                RouteError::UserNotFound
+                | RouteError::NoPassword
+                | RouteError::PasswordVerificationFailed(_),
+            ) = res
+            {
+                limiter
+                    .record_login_failure(clock.now(), activity_tracker.ip())
+                    .await;
+            }
+
+            res?
         }
 
         (_, Credentials::Token { token }) => token_login(&mut repo, &clock, &token).await?,
 
+        (_, Credentials::Jwt { token }) => {
+            let config = site_config
+                .compat_jwt_login
+                .as_ref()
+                .ok_or(RouteError::Unsupported)?;
+
+            jwt_login(
+                &mut rng,
+                &clock,
+                &http_client_factory,
+                config,
+                site_config.clock_skew,
+                &mut repo,
+                &token,
+            )
+            .await?
+        }
+
         _ => {
             return Err(RouteError::Unsupported);
         }
@@ -353,6 +457,79 @@ async fn token_login(
     Ok((session, user))
 }
 
+async fn jwt_login(
+    mut rng: &mut (impl RngCore + CryptoRng + Send),
+    clock: &impl Clock,
+    http_client_factory: &HttpClientFactory,
+    config: &JwtLoginConfig,
+    clock_skew: Duration,
+    repo: &mut BoxRepository,
+    token: &str,
+) -> Result<(CompatSession, User), RouteError> {
+    let jwt: Jwt<'_, HashMap<String, serde_json::Value>> =
+        Jwt::try_from(token).map_err(|_| RouteError::InvalidJwt)?;
+
+    let jwks = fetch_jwks(http_client_factory, &config.jwks)
+        .await
+        .map_err(RouteError::Internal)?;
+
+    jwt.verify_with_jwks(&jwks)
+        .map_err(|_| RouteError::InvalidJwt)?;
+
+    let (_header, mut claims) = jwt.into_parts();
+
+    // Grab the username before validating the other claims, as validating them
+    // removes them from the map
+    let username = claims
+        .get(&config.subject_claim)
+        .and_then(serde_json::Value::as_str)
+        .ok_or(RouteError::InvalidJwt)?
+        .to_owned();
+
+    let time_options = TimeOptions::new(clock.now()).leeway(clock_skew);
+    claims::EXP
+        .extract_optional_with_options(&mut claims, &time_options)
+        .map_err(|_| RouteError::InvalidJwt)?;
+    claims::NBF
+        .extract_optional_with_options(&mut claims, &time_options)
+        .map_err(|_| RouteError::InvalidJwt)?;
+
+    if let Some(issuer) = &config.issuer {
+        claims::ISS
+            .extract_required_with_options(&mut claims, issuer.as_str())
+            .map_err(|_| RouteError::InvalidJwt)?;
+    }
+
+    if let Some(audience) = &config.audience {
+        claims::AUD
+            .extract_required_with_options(&mut claims, audience)
+            .map_err(|_| RouteError::InvalidJwt)?;
+    }
+
+    let user = repo
+        .user()
+        .find_by_username(&username)
+        .await?
+        .filter(mas_data_model::User::is_valid)
+        .ok_or(RouteError::UserNotFound)?;
+
+    if user.is_quarantined() {
+        return Err(RouteError::UserQuarantined);
+    }
+
+    let device = Device::generate(&mut rng);
+    repo.job()
+        .schedule_job(ProvisionDeviceJob::new(&user, &device))
+        .await?;
+
+    let session = repo
+        .compat_session()
+        .add(&mut rng, clock, &user, device, None, false)
+        .await?;
+
+    Ok((session, user))
+}
+
 async fn user_password_login(
     mut rng: &mut (impl RngCore + CryptoRng + Send),
     clock: &impl Clock,
@@ -403,6 +580,12 @@ async fn user_password_login(
             .await?;
     }
 
+    // Quarantined users can keep using their existing sessions, but not start new
+    // ones
+    if user.is_quarantined() {
+        return Err(RouteError::UserQuarantined);
+    }
+
     // Now that the user credentials have been verified, start a new compat session
     let device = Device::generate(&mut rng);
     repo.job()
@@ -411,7 +594,7 @@ async fn user_password_login(
 
     let session = repo
         .compat_session()
-        .add(&mut rng, clock, &user, device, false)
+        .add(&mut rng, clock, &user, device, None, false)
         .await?;
 
     Ok((session, user))
@@ -420,6 +603,8 @@ async fn user_password_login(
 #[cfg(test)]
 mod tests {
     use hyper::Request;
+    use mas_iana::jose::JsonWebSignatureAlg;
+    use mas_jose::{constraints::Constrainable, jwt::JsonWebSignatureHeader};
     use rand::distributions::{Alphanumeric, DistString};
     use sqlx::PgPool;
 
@@ -619,6 +804,42 @@ mod tests {
         // The response should be the same as the previous one, so that we don't leak if
         // it's the user that is invalid or the password.
         assert_eq!(body, old_body);
+
+        // Quarantined users can't start new sessions, even with the right password
+        let mut repo = state.repository().await.unwrap();
+        let user = repo.user().quarantine(&state.clock, user).await.unwrap();
+        repo.save().await.unwrap();
+
+        let request = Request::post("/_matrix/client/v3/login").json(serde_json::json!({
+            "type": "m.login.password",
+            "identifier": {
+                "type": "m.id.user",
+                "user": "alice",
+            },
+            "password": "password",
+        }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["errcode"], "M_FORBIDDEN");
+
+        // Lifting the quarantine lets them in again
+        let mut repo = state.repository().await.unwrap();
+        repo.user().unquarantine(user).await.unwrap();
+        repo.save().await.unwrap();
+
+        let request = Request::post("/_matrix/client/v3/login").json(serde_json::json!({
+            "type": "m.login.password",
+            "identifier": {
+                "type": "m.id.user",
+                "user": "alice",
+            },
+            "password": "password",
+        }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
     }
 
     /// Test the response of an unsupported login flow.
@@ -708,6 +929,107 @@ mod tests {
         assert_eq!(body["errcode"], "M_UNAUTHORIZED");
     }
 
+    /// Test the `org.matrix.login.jwt` login flow.
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_jwt_login(pool: PgPool) {
+        init_tracing();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+
+        // The flow is not advertised nor accepted until it is configured
+        let request = Request::post("/_matrix/client/v3/login").json(serde_json::json!({
+            "type": "org.matrix.login.jwt",
+            "token": "sometoken",
+        }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["errcode"], "M_UNRECOGNIZED");
+
+        state.site_config.compat_jwt_login = Some(JwtLoginConfig {
+            jwks: mas_data_model::JwksOrJwksUri::Jwks(state.key_store.public_jwks()),
+            subject_claim: "sub".to_owned(),
+            issuer: Some("https://auth.example.com/".to_owned()),
+            audience: None,
+        });
+
+        let request = Request::get("/_matrix/client/v3/login").empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["flows"][3]["type"], "org.matrix.login.jwt");
+
+        // Provision a user
+        let mut repo = state.repository().await.unwrap();
+        repo.user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let sign = |issuer: &str, exp: chrono::DateTime<chrono::Utc>| {
+            let mut claims = HashMap::new();
+            claims::SUB.insert(&mut claims, "alice").unwrap();
+            claims::ISS.insert(&mut claims, issuer).unwrap();
+            claims::EXP.insert(&mut claims, exp).unwrap();
+
+            let alg = JsonWebSignatureAlg::Rs256;
+            let key = state.key_store.signing_key_for_algorithm(&alg).unwrap();
+            let signer = key.params().signing_key_for_alg(&alg).unwrap();
+            let header = JsonWebSignatureHeader::new(alg).with_kid(key.kid().unwrap());
+            Jwt::sign_with_rng(&mut state.rng(), header, claims, &signer)
+                .unwrap()
+                .into_string()
+        };
+
+        // A valid token logs the user in
+        let token = sign(
+            "https://auth.example.com/",
+            state.clock.now() + Duration::minutes(5),
+        );
+        let request = Request::post("/_matrix/client/v3/login").json(serde_json::json!({
+            "type": "org.matrix.login.jwt",
+            "token": token,
+        }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: ResponseBody = response.json();
+        assert_eq!(body.user_id, "@alice:example.com");
+
+        // A token from another issuer is rejected
+        let token = sign(
+            "https://evil.example.com/",
+            state.clock.now() + Duration::minutes(5),
+        );
+        let request = Request::post("/_matrix/client/v3/login").json(serde_json::json!({
+            "type": "org.matrix.login.jwt",
+            "token": token,
+        }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["errcode"], "M_FORBIDDEN");
+
+        // So is an expired token
+        let token = sign(
+            "https://auth.example.com/",
+            state.clock.now() - Duration::hours(1),
+        );
+        let request = Request::post("/_matrix/client/v3/login").json(serde_json::json!({
+            "type": "org.matrix.login.jwt",
+            "token": token,
+        }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+
+        // And a token with a bad signature
+        let request = Request::post("/_matrix/client/v3/login").json(serde_json::json!({
+            "type": "org.matrix.login.jwt",
+            "token": format!("{token}x"),
+        }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+    }
+
     /// Get a login token for a user.
     /// Returns the device and the token.
     ///
@@ -738,7 +1060,14 @@ mod tests {
         // Complete the flow by fulfilling it with a session
         let compat_session = repo
             .compat_session()
-            .add(&mut state.rng(), &state.clock, user, device.clone(), false)
+            .add(
+                &mut state.rng(),
+                &state.clock,
+                user,
+                device.clone(),
+                None,
+                false,
+            )
             .await
             .unwrap();
 
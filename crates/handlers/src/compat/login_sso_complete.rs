@@ -33,7 +33,7 @@ use mas_storage::{
     job::{JobRepositoryExt, ProvisionDeviceJob},
     BoxClock, BoxRepository, BoxRng, Clock, RepositoryAccess,
 };
-use mas_templates::{CompatSsoContext, ErrorContext, TemplateContext, Templates};
+use mas_templates::{error_codes, CompatSsoContext, ErrorContext, TemplateContext, Templates};
 use serde::{Deserialize, Serialize};
 use ulid::Ulid;
 
@@ -73,7 +73,7 @@ pub async fn get(
     let (session_info, cookie_jar) = cookie_jar.session_info();
     let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info.load_session(&clock, &mut repo).await?;
 
     let Some(session) = maybe_session else {
         // If there is no session, redirect to the login or register screen
@@ -105,11 +105,34 @@ pub async fn get(
     // Bail out if that login session is more than 30min old
     if clock.now() > login.created_at + Duration::minutes(30) {
         let ctx = ErrorContext::new()
-            .with_code("compat_sso_login_expired")
+            .with_code(error_codes::COMPAT_SSO_LOGIN_EXPIRED)
             .with_description("This login session expired.".to_owned())
             .with_language(&locale);
 
-        let content = templates.render_error(&ctx)?;
+        let content = templates.render_error_page(&ctx)?;
+        return Ok((cookie_jar, Html(content)).into_response());
+    }
+
+    // Quarantined users can't start new sessions
+    if session.user.is_quarantined() {
+        let ctx = ErrorContext::new()
+            .with_code(error_codes::USER_QUARANTINED)
+            .with_description("This account can't sign in to new clients.".to_owned())
+            .with_language(&locale);
+
+        let content = templates.render_error_page(&ctx)?;
+        return Ok((cookie_jar, Html(content)).into_response());
+    }
+
+    // Impersonation sessions are only meant to look at the account, not to hand
+    // out sessions outliving them
+    if session.is_impersonation() {
+        let ctx = ErrorContext::new()
+            .with_code(error_codes::IMPERSONATION_NOT_ALLOWED)
+            .with_description("Can't sign in to clients while impersonating a user.".to_owned())
+            .with_language(&locale);
+
+        let content = templates.render_error_page(&ctx)?;
         return Ok((cookie_jar, Html(content)).into_response());
     }
 
@@ -144,7 +167,7 @@ pub async fn post(
     let (session_info, cookie_jar) = cookie_jar.session_info();
     cookie_jar.verify_form(&clock, form)?;
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info.load_session(&clock, &mut repo).await?;
 
     let Some(session) = maybe_session else {
         // If there is no session, redirect to the login or register screen
@@ -176,11 +199,34 @@ pub async fn post(
     // Bail out if that login session is more than 30min old
     if clock.now() > login.created_at + Duration::minutes(30) {
         let ctx = ErrorContext::new()
-            .with_code("compat_sso_login_expired")
+            .with_code(error_codes::COMPAT_SSO_LOGIN_EXPIRED)
             .with_description("This login session expired.".to_owned())
             .with_language(&locale);
 
-        let content = templates.render_error(&ctx)?;
+        let content = templates.render_error_page(&ctx)?;
+        return Ok((cookie_jar, Html(content)).into_response());
+    }
+
+    // Quarantined users can't start new sessions
+    if session.user.is_quarantined() {
+        let ctx = ErrorContext::new()
+            .with_code(error_codes::USER_QUARANTINED)
+            .with_description("This account can't sign in to new clients.".to_owned())
+            .with_language(&locale);
+
+        let content = templates.render_error_page(&ctx)?;
+        return Ok((cookie_jar, Html(content)).into_response());
+    }
+
+    // Impersonation sessions are only meant to look at the account, not to hand
+    // out sessions outliving them
+    if session.is_impersonation() {
+        let ctx = ErrorContext::new()
+            .with_code(error_codes::IMPERSONATION_NOT_ALLOWED)
+            .with_description("Can't sign in to clients while impersonating a user.".to_owned())
+            .with_language(&locale);
+
+        let content = templates.render_error_page(&ctx)?;
         return Ok((cookie_jar, Html(content)).into_response());
     }
 
@@ -208,7 +254,14 @@ pub async fn post(
 
     let compat_session = repo
         .compat_session()
-        .add(&mut rng, &clock, &session.user, device, false)
+        .add(
+            &mut rng,
+            &clock,
+            &session.user,
+            device,
+            Some(&session),
+            false,
+        )
         .await?;
 
     repo.compat_sso_login()
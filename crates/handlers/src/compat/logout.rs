@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use axum::{response::IntoResponse, Json, TypedHeader};
+use axum::{extract::State, response::IntoResponse, Json, TypedHeader};
 use headers::{authorization::Bearer, Authorization};
 use hyper::StatusCode;
 use mas_axum_utils::sentry::SentryEventID;
@@ -25,7 +25,7 @@ use mas_storage::{
 use thiserror::Error;
 
 use super::MatrixError;
-use crate::{impl_from_error_for_route, BoundActivityTracker};
+use crate::{impl_from_error_for_route, BoundActivityTracker, IntrospectionCache};
 
 #[derive(Error, Debug)]
 pub enum RouteError {
@@ -74,6 +74,7 @@ pub(crate) async fn post(
     clock: BoxClock,
     mut repo: BoxRepository,
     activity_tracker: BoundActivityTracker,
+    State(introspection_cache): State<IntrospectionCache>,
     maybe_authorization: Option<TypedHeader<Authorization<Bearer>>>,
 ) -> Result<impl IntoResponse, RouteError> {
     let TypedHeader(authorization) = maybe_authorization.ok_or(RouteError::MissingAuthorization)?;
@@ -114,9 +115,11 @@ pub(crate) async fn post(
         .schedule_job(DeleteDeviceJob::new(&user, &session.device))
         .await?;
 
-    repo.compat_session().finish(&clock, session).await?;
+    let session = repo.compat_session().finish(&clock, session).await?;
 
     repo.save().await?;
 
+    introspection_cache.invalidate_session(session.id);
+
     Ok(Json(serde_json::json!({})))
 }
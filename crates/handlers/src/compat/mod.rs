@@ -21,6 +21,8 @@ pub(crate) mod login_sso_complete;
 pub(crate) mod login_sso_redirect;
 pub(crate) mod logout;
 pub(crate) mod refresh;
+pub(crate) mod register;
+pub(crate) mod well_known;
 
 #[derive(Debug, Clone)]
 pub struct MatrixHomeserver(String);
@@ -26,7 +26,9 @@ use serde_with::{serde_as, DurationMilliSeconds};
 use thiserror::Error;
 
 use super::MatrixError;
-use crate::{impl_from_error_for_route, site_config::SiteConfig, BoundActivityTracker};
+use crate::{
+    impl_from_error_for_route, site_config::SiteConfig, BoundActivityTracker, IntrospectionCache,
+};
 
 #[derive(Debug, Deserialize)]
 pub struct RequestBody {
@@ -95,6 +97,7 @@ pub(crate) async fn post(
     mut repo: BoxRepository,
     activity_tracker: BoundActivityTracker,
     State(site_config): State<SiteConfig>,
+    State(introspection_cache): State<IntrospectionCache>,
     Json(input): Json<RequestBody>,
 ) -> Result<impl IntoResponse, RouteError> {
     let token_type = TokenType::check(&input.refresh_token)?;
@@ -170,6 +173,9 @@ pub(crate) async fn post(
 
     repo.save().await?;
 
+    // The previous tokens of the session are not valid anymore
+    introspection_cache.invalidate_session(session.id);
+
     Ok(Json(ResponseBody {
         access_token: new_access_token.token,
         refresh_token: new_refresh_token.token,
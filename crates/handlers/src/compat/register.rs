@@ -0,0 +1,506 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json, TypedHeader,
+};
+use headers::UserAgent;
+use hyper::StatusCode;
+use mas_axum_utils::sentry::SentryEventID;
+use mas_data_model::{Device, TokenFormatError, TokenType};
+use mas_policy::{Policy, Requester};
+use mas_storage::{
+    compat::{CompatAccessTokenRepository, CompatSessionRepository},
+    job::{
+        JobRepositoryExt, NotifyUserEventJob, ProvisionDeviceJob, ProvisionUserJob,
+        UserLifecycleEvent,
+    },
+    user::{UserPasswordRepository, UserRepository},
+    BoxClock, BoxRepository, BoxRng, Clock, RepositoryAccess,
+};
+use rand::distributions::{Alphanumeric, DistString};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+use super::{MatrixError, MatrixHomeserver};
+use crate::{
+    impl_from_error_for_route,
+    ip_filter::{Action, IpDenied},
+    passwords::PasswordManager,
+    site_config::SiteConfig,
+    BoundActivityTracker, IpFilter, Limiter, RateLimited,
+};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RegistrationKind {
+    Guest,
+
+    #[default]
+    User,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Params {
+    #[serde(default)]
+    kind: RegistrationKind,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RequestBody {
+    #[serde(default)]
+    password: Option<String>,
+
+    /// The access token of the guest account to upgrade
+    #[serde(default)]
+    guest_access_token: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResponseBody {
+    user_id: String,
+    access_token: String,
+    device_id: Device,
+}
+
+#[derive(Debug, Error)]
+pub enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    #[error("guest registration is disabled")]
+    GuestRegistrationDisabled,
+
+    #[error("registration is not supported through this endpoint")]
+    RegistrationUnsupported,
+
+    #[error("password authentication is disabled")]
+    PasswordDisabled,
+
+    #[error("missing password")]
+    MissingPassword,
+
+    #[error("invalid guest access token")]
+    InvalidGuestToken,
+
+    #[error("user is not a guest")]
+    NotAGuest,
+
+    #[error("password denied by the policy")]
+    WeakPassword,
+
+    #[error("registration denied by the policy")]
+    PolicyDenied,
+
+    #[error(transparent)]
+    RateLimited(#[from] RateLimited),
+
+    #[error(transparent)]
+    IpDenied(#[from] IpDenied),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+impl_from_error_for_route!(mas_policy::EvaluationError);
+
+impl From<TokenFormatError> for RouteError {
+    fn from(_e: TokenFormatError) -> Self {
+        Self::InvalidGuestToken
+    }
+}
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> axum::response::Response {
+        let event_id = sentry::capture_error(&self);
+        let response = match self {
+            Self::Internal(_) => MatrixError {
+                errcode: "M_UNKNOWN",
+                error: "Internal server error",
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+            },
+            Self::GuestRegistrationDisabled => MatrixError {
+                errcode: "M_FORBIDDEN",
+                error: "Guest access is disabled",
+                status: StatusCode::FORBIDDEN,
+            },
+            Self::RegistrationUnsupported | Self::PasswordDisabled => MatrixError {
+                errcode: "M_FORBIDDEN",
+                error: "Registration is not supported through this endpoint",
+                status: StatusCode::FORBIDDEN,
+            },
+            Self::MissingPassword => MatrixError {
+                errcode: "M_MISSING_PARAM",
+                error: "Missing password",
+                status: StatusCode::BAD_REQUEST,
+            },
+            Self::InvalidGuestToken => MatrixError {
+                errcode: "M_UNKNOWN_TOKEN",
+                error: "Invalid guest access token",
+                status: StatusCode::UNAUTHORIZED,
+            },
+            Self::NotAGuest => MatrixError {
+                errcode: "M_FORBIDDEN",
+                error: "This account is not a guest account",
+                status: StatusCode::FORBIDDEN,
+            },
+            Self::WeakPassword => MatrixError {
+                errcode: "M_WEAK_PASSWORD",
+                error: "The password doesn't meet the requirements",
+                status: StatusCode::BAD_REQUEST,
+            },
+            Self::PolicyDenied => MatrixError {
+                errcode: "M_FORBIDDEN",
+                error: "Registration is not allowed",
+                status: StatusCode::FORBIDDEN,
+            },
+            Self::IpDenied(_) => MatrixError {
+                errcode: "M_FORBIDDEN",
+                error: "Registrations from your network are not allowed",
+                status: StatusCode::FORBIDDEN,
+            },
+            Self::RateLimited(rate_limited) => {
+                let response = MatrixError {
+                    errcode: "M_LIMIT_EXCEEDED",
+                    error: "Too many registration attempts",
+                    status: StatusCode::TOO_MANY_REQUESTS,
+                };
+                return (SentryEventID::from(event_id), rate_limited, response).into_response();
+            }
+        };
+
+        (SentryEventID::from(event_id), response).into_response()
+    }
+}
+
+/// Handles the `/register` endpoint of the Client-Server API.
+///
+/// Only two flows are supported: registering a guest account with
+/// `kind=guest`, and upgrading an existing guest account to a full account by
+/// setting a password on it. Other registrations go through the web UI.
+///
+/// Upgrades are subject to the same password and registration policies, IP
+/// filter and rate limits as registrations through the web UI.
+#[tracing::instrument(name = "handlers.compat.register.post", skip_all, err)]
+#[allow(clippy::too_many_arguments, clippy::too_many_lines)]
+pub(crate) async fn post(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    State(password_manager): State<PasswordManager>,
+    mut repo: BoxRepository,
+    activity_tracker: BoundActivityTracker,
+    State(homeserver): State<MatrixHomeserver>,
+    State(site_config): State<SiteConfig>,
+    State(limiter): State<Limiter>,
+    State(ip_filter): State<IpFilter>,
+    mut policy: Policy,
+    user_agent: Option<TypedHeader<UserAgent>>,
+    Query(params): Query<Params>,
+    Json(input): Json<RequestBody>,
+) -> Result<impl IntoResponse, RouteError> {
+    let (session, user) = match (params.kind, input.guest_access_token) {
+        (RegistrationKind::Guest, _) => {
+            if !site_config.guest_registration {
+                return Err(RouteError::GuestRegistrationDisabled);
+            }
+
+            let username = format!(
+                "guest-{}",
+                Alphanumeric.sample_string(&mut rng, 10).to_lowercase()
+            );
+            let user = repo.user().add_guest(&mut rng, &clock, username).await?;
+
+            repo.job()
+                .schedule_job(ProvisionUserJob::new(&user))
+                .await?;
+
+            repo.job()
+                .schedule_job(NotifyUserEventJob::new(&user, UserLifecycleEvent::Created))
+                .await?;
+
+            let device = Device::generate(&mut rng);
+            repo.job()
+                .schedule_job(ProvisionDeviceJob::new(&user, &device))
+                .await?;
+
+            let session = repo
+                .compat_session()
+                .add(&mut rng, &clock, &user, device, None, false)
+                .await?;
+
+            (session, user)
+        }
+
+        (RegistrationKind::User, Some(guest_access_token)) => {
+            if !password_manager.is_enabled() {
+                return Err(RouteError::PasswordDisabled);
+            }
+
+            let password = input.password.ok_or(RouteError::MissingPassword)?;
+
+            if TokenType::check(&guest_access_token)? != TokenType::CompatAccessToken {
+                return Err(RouteError::InvalidGuestToken);
+            }
+
+            let access_token = repo
+                .compat_access_token()
+                .find_by_token(&guest_access_token)
+                .await?
+                .filter(|t| t.is_valid(clock.now()))
+                .ok_or(RouteError::InvalidGuestToken)?;
+
+            let session = repo
+                .compat_session()
+                .lookup(access_token.session_id)
+                .await?
+                .filter(|s| s.is_valid())
+                .ok_or(RouteError::InvalidGuestToken)?;
+
+            let user = repo
+                .user()
+                .lookup(session.user_id)
+                .await?
+                .filter(mas_data_model::User::is_valid)
+                .ok_or(RouteError::InvalidGuestToken)?;
+
+            if !user.is_guest {
+                return Err(RouteError::NotAGuest);
+            }
+
+            let res = policy.evaluate_password(&password).await?;
+            if !res.valid() {
+                return Err(RouteError::WeakPassword);
+            }
+
+            let requester = Requester::new(clock.now())
+                .with_ip_address(activity_tracker.ip())
+                .with_user_agent(user_agent.map(|ua| ua.as_str().to_owned()));
+            let res = policy
+                .evaluate_guest_upgrade(&user.username, &requester)
+                .await?;
+            if !res.valid() {
+                return Err(RouteError::PolicyDenied);
+            }
+
+            ip_filter
+                .check(activity_tracker.ip(), Action::Registration)
+                .await?;
+
+            // Only valid upgrade attempts count towards the limit
+            limiter
+                .check_registration(clock.now(), activity_tracker.ip())
+                .await?;
+
+            let password = Zeroizing::new(password.into_bytes());
+            let (version, hashed_password) = password_manager
+                .hash(&mut rng, password)
+                .await
+                .map_err(|e| RouteError::Internal(e.into()))?;
+
+            repo.user_password()
+                .add(&mut rng, &clock, &user, version, hashed_password, None)
+                .await?;
+
+            let user = repo.user().upgrade_guest(user).await?;
+
+            // The guest token had a restricted scope, revoke it so that the client
+            // switches to the new one
+            repo.compat_access_token()
+                .expire(&clock, access_token)
+                .await?;
+
+            (session, user)
+        }
+
+        (RegistrationKind::User, None) => return Err(RouteError::RegistrationUnsupported),
+    };
+
+    let user_id = format!("@{username}:{homeserver}", username = user.username);
+
+    let access_token = TokenType::CompatAccessToken.generate(&mut rng);
+    let access_token = repo
+        .compat_access_token()
+        .add(&mut rng, &clock, &session, access_token, None)
+        .await?;
+
+    repo.save().await?;
+
+    activity_tracker
+        .record_compat_session(&clock, &session)
+        .await;
+
+    Ok(Json(ResponseBody {
+        user_id,
+        access_token: access_token.token,
+        device_id: session.device,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Request;
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::test_utils::{init_tracing, RequestBuilderExt, ResponseExt, TestState};
+
+    /// Test that guest registration is rejected when it is disabled
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_guest_registration_disabled(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        let request =
+            Request::post("/_matrix/client/v3/register?kind=guest").json(serde_json::json!({}));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["errcode"], "M_FORBIDDEN");
+
+        // Regular registrations are never handled by this endpoint
+        let request = Request::post("/_matrix/client/v3/register").json(serde_json::json!({
+            "username": "alice",
+            "password": "password",
+        }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+    }
+
+    /// Test registering a guest and upgrading it to a full account
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_guest_registration(pool: PgPool) {
+        init_tracing();
+        let state = {
+            let mut state = TestState::from_pool(pool).await.unwrap();
+            state.site_config.guest_registration = true;
+            state
+        };
+
+        let request =
+            Request::post("/_matrix/client/v3/register?kind=guest").json(serde_json::json!({}));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        let guest_access_token = body["access_token"].as_str().unwrap().to_owned();
+        let user_id = body["user_id"].as_str().unwrap().to_owned();
+        assert!(user_id.starts_with("@guest-"));
+
+        let mut repo = state.repository().await.unwrap();
+        let username = user_id
+            .strip_prefix('@')
+            .and_then(|s| s.split_once(':'))
+            .unwrap()
+            .0;
+        let user = repo
+            .user()
+            .find_by_username(username)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(user.is_guest);
+        repo.save().await.unwrap();
+
+        // Upgrading without a password fails
+        let request = Request::post("/_matrix/client/v3/register").json(serde_json::json!({
+            "guest_access_token": guest_access_token,
+        }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+
+        // Upgrade the guest account
+        let request = Request::post("/_matrix/client/v3/register").json(serde_json::json!({
+            "guest_access_token": guest_access_token,
+            "password": "hunter2",
+        }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["user_id"], user_id);
+        assert_ne!(body["access_token"], guest_access_token);
+
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .find_by_username(username)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!user.is_guest);
+        repo.save().await.unwrap();
+
+        // The guest token can't be used anymore
+        let request = Request::post("/_matrix/client/v3/register").json(serde_json::json!({
+            "guest_access_token": guest_access_token,
+            "password": "hunter2",
+        }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+
+        // The user can now log in with their password
+        let request = Request::post("/_matrix/client/v3/login").json(serde_json::json!({
+            "type": "m.login.password",
+            "identifier": {
+                "type": "m.id.user",
+                "user": username,
+            },
+            "password": "hunter2",
+        }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+    }
+
+    /// Test that upgrading a guest account enforces the password policy
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_guest_upgrade_weak_password(pool: PgPool) {
+        init_tracing();
+        let state = {
+            let mut state = TestState::from_pool(pool).await.unwrap();
+            state.site_config.guest_registration = true;
+            state.policy_factory = crate::test_utils::policy_factory(serde_json::json!({
+                "passwords": {
+                    "min_length": 8,
+                },
+            }))
+            .await
+            .unwrap();
+            state
+        };
+
+        let request =
+            Request::post("/_matrix/client/v3/register?kind=guest").json(serde_json::json!({}));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        let guest_access_token = body["access_token"].as_str().unwrap().to_owned();
+
+        // The password is too short
+        let request = Request::post("/_matrix/client/v3/register").json(serde_json::json!({
+            "guest_access_token": guest_access_token,
+            "password": "hunter2",
+        }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["errcode"], "M_WEAK_PASSWORD");
+
+        // The account is still a guest, and the guest token still works
+        let request = Request::post("/_matrix/client/v3/register").json(serde_json::json!({
+            "guest_access_token": guest_access_token,
+            "password": "correct horse battery staple",
+        }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+    }
+}
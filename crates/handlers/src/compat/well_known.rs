@@ -0,0 +1,111 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use axum::{extract::State, response::IntoResponse, Json};
+use hyper::StatusCode;
+use mas_router::UrlBuilder;
+use serde_json::json;
+
+use crate::site_config::SiteConfig;
+
+/// Serves the `/.well-known/matrix/client` document, advertising the
+/// homeserver and this service as its MSC2965 authentication issuer.
+///
+/// Returns a 404 if the document is not configured.
+#[tracing::instrument(name = "handlers.compat.well_known.get", skip_all)]
+pub(crate) async fn get(
+    State(url_builder): State<UrlBuilder>,
+    State(site_config): State<SiteConfig>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let config = site_config.client_well_known.ok_or(StatusCode::NOT_FOUND)?;
+
+    // Extra fields are added first, so that they can't override the fields we
+    // set ourselves
+    let mut document: serde_json::Map<String, serde_json::Value> =
+        config.extra.into_iter().collect();
+
+    document.insert(
+        "m.homeserver".to_owned(),
+        json!({ "base_url": config.homeserver_base_url }),
+    );
+    document.insert(
+        "org.matrix.msc2965.authentication".to_owned(),
+        json!({
+            "issuer": url_builder.oidc_issuer(),
+            "account": url_builder.account_management_uri(),
+        }),
+    );
+
+    Ok(Json(document))
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Request;
+    use sqlx::PgPool;
+    use url::Url;
+
+    use super::*;
+    use crate::{
+        site_config::ClientWellKnownConfig,
+        test_utils::{init_tracing, RequestBuilderExt, ResponseExt, TestState},
+    };
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_well_known_not_configured(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        let request = Request::get("/.well-known/matrix/client").empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_well_known(pool: PgPool) {
+        init_tracing();
+        let state = {
+            let mut state = TestState::from_pool(pool).await.unwrap();
+            state.site_config.client_well_known = Some(ClientWellKnownConfig {
+                homeserver_base_url: Url::parse("https://matrix.example.com/").unwrap(),
+                extra: [
+                    ("io.element.e2ee".to_owned(), json!({ "default": false })),
+                    ("m.homeserver".to_owned(), json!({ "base_url": "nope" })),
+                ]
+                .into_iter()
+                .collect(),
+            });
+            state
+        };
+
+        let request = Request::get("/.well-known/matrix/client").empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+
+        assert_eq!(
+            body["m.homeserver"]["base_url"],
+            "https://matrix.example.com/"
+        );
+        assert_eq!(
+            body["org.matrix.msc2965.authentication"]["issuer"],
+            state.url_builder.oidc_issuer().as_str()
+        );
+        assert_eq!(
+            body["org.matrix.msc2965.authentication"]["account"],
+            state.url_builder.account_management_uri().as_str()
+        );
+        assert_eq!(body["io.element.e2ee"]["default"], false);
+    }
+}
@@ -0,0 +1,96 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Conditional requests on the JSON documents which only change when the
+//! service restarts, like the discovery document and the JWKS
+
+use std::{sync::OnceLock, time::SystemTime};
+
+use axum::{
+    response::{IntoResponse, Response},
+    TypedHeader,
+};
+use chrono::{DateTime, SubsecRound, Utc};
+use headers::{ETag, IfModifiedSince, IfNoneMatch, LastModified};
+use hyper::StatusCode;
+use mas_storage::Clock;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// When the documents were first served by this process
+static FIRST_SERVED_AT: OnceLock<DateTime<Utc>> = OnceLock::new();
+
+/// The value of the `Last-Modified` header of the documents
+///
+/// They are built from the configuration, which can't change without a
+/// restart, so the first time they were served is a safe upper bound.
+fn last_modified(clock: &impl Clock) -> SystemTime {
+    // HTTP dates have a precision of one second
+    let first_served_at = FIRST_SERVED_AT.get_or_init(|| clock.now().trunc_subsecs(0));
+    (*first_served_at).into()
+}
+
+/// Serve `document` as JSON with an `ETag` derived from its content and a
+/// `Last-Modified` date, replying with a `304 Not Modified` if the client
+/// already has it
+pub(crate) fn conditional_json<T: Serialize>(
+    clock: &impl Clock,
+    document: &T,
+    if_none_match: Option<TypedHeader<IfNoneMatch>>,
+    if_modified_since: Option<TypedHeader<IfModifiedSince>>,
+) -> Response {
+    let body = match serde_json::to_vec(document) {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::error!(
+                error = &e as &dyn std::error::Error,
+                "Failed to serialize document"
+            );
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    // The tag is weak, as the response might be compressed on its way out
+    let hash = Sha256::digest(&body);
+    let etag: ETag = format!("W/\"{hash:x}\"")
+        .parse()
+        .expect("hex-encoded hashes are valid entity tags");
+    let last_modified = last_modified(clock);
+
+    // `If-Modified-Since` is ignored when `If-None-Match` is present, as per RFC
+    // 9110 section 13.1.3
+    let not_modified = match (if_none_match, if_modified_since) {
+        (Some(TypedHeader(if_none_match)), _) => !if_none_match.precondition_passes(&etag),
+        (None, Some(TypedHeader(if_modified_since))) => {
+            !if_modified_since.is_modified(last_modified)
+        }
+        (None, None) => false,
+    };
+
+    let headers = (
+        TypedHeader(etag),
+        TypedHeader(LastModified::from(last_modified)),
+    );
+
+    if not_modified {
+        (StatusCode::NOT_MODIFIED, headers).into_response()
+    } else {
+        (
+            headers,
+            [(hyper::header::CONTENT_TYPE, "application/json")],
+            body,
+        )
+            .into_response()
+    }
+}
@@ -0,0 +1,128 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Render the errors of the human-facing routes, as an HTML page or as JSON
+//! depending on the `Accept` header of the request
+
+use axum::{
+    extract::State,
+    middleware::Next,
+    response::{Html, IntoResponse, Response},
+    Json,
+};
+use hyper::{
+    header::{ACCEPT, CONTENT_LENGTH, CONTENT_TYPE},
+    http::HeaderValue,
+    Request,
+};
+use mas_templates::{ErrorContext, Templates};
+use serde::Serialize;
+
+/// The JSON variant of an error page
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_description: Option<&'a str>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<&'a str>,
+}
+
+/// Whether the `Accept` header prefers JSON over HTML
+fn prefers_json(accept: Option<&HeaderValue>) -> bool {
+    let Some(accept) = accept.and_then(|value| value.to_str().ok()) else {
+        return false;
+    };
+
+    let mut json = 0.0_f32;
+    let mut html = 0.0_f32;
+    for item in accept.split(',') {
+        let mut params = item.split(';');
+        let media_type = params.next().unwrap_or_default().trim();
+        let quality = params
+            .find_map(|param| param.trim().strip_prefix("q="))
+            .and_then(|quality| quality.parse().ok())
+            .unwrap_or(1.0_f32);
+
+        match media_type {
+            "application/json" => json = json.max(quality),
+            "text/html" => html = html.max(quality),
+            _ => {}
+        }
+    }
+
+    json > html
+}
+
+/// Replace server errors which have an [`ErrorContext`] attached to them with
+/// the error page, or with its JSON variant
+pub(crate) async fn render<B>(
+    State(templates): State<Templates>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let json = prefers_json(request.headers().get(ACCEPT));
+    let response = next.run(request).await;
+
+    if !response.status().is_server_error() {
+        return response;
+    }
+
+    // Error responses should have an ErrorContext attached to them
+    let Some(ctx) = response.extensions().get::<ErrorContext>() else {
+        return response;
+    };
+
+    let body = if json {
+        Json(ErrorBody {
+            error: ctx
+                .code()
+                .unwrap_or(mas_templates::error_codes::INTERNAL_ERROR),
+            error_description: ctx.description(),
+            details: ctx.details(),
+        })
+        .into_response()
+    } else if let Ok(res) = templates.render_error_page(ctx) {
+        Html(res).into_response()
+    } else {
+        return response;
+    };
+
+    let (mut parts, _original_body) = response.into_parts();
+    parts.headers.remove(CONTENT_TYPE);
+    parts.headers.remove(CONTENT_LENGTH);
+    (parts, body).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefers_json() {
+        let prefers = |accept: &'static str| prefers_json(Some(&HeaderValue::from_static(accept)));
+
+        assert!(!prefers_json(None));
+        assert!(prefers("application/json"));
+        assert!(!prefers("text/html"));
+        assert!(!prefers("*/*"));
+        assert!(!prefers(
+            "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"
+        ));
+        assert!(prefers("text/html;q=0.5, application/json"));
+        assert!(!prefers("application/json;q=0.5, text/html"));
+    }
+}
@@ -31,8 +31,8 @@ use hyper::header::CACHE_CONTROL;
 use mas_axum_utils::{
     cookies::CookieJar, sentry::SentryEventID, FancyError, SessionInfo, SessionInfoExt,
 };
-use mas_data_model::User;
-use mas_graphql::{Requester, Schema};
+use mas_data_model::{EmailRateLimits, User};
+use mas_graphql::{RequestLanguage, Requester, Schema};
 use mas_matrix::HomeserverConnection;
 use mas_policy::{InstantiateError, Policy, PolicyFactory};
 use mas_storage::{
@@ -43,16 +43,22 @@ use rand::{thread_rng, SeedableRng};
 use rand_chacha::ChaChaRng;
 use sqlx::PgPool;
 use tracing::{info_span, Instrument};
+use ulid::Ulid;
 
-use crate::{impl_from_error_for_route, BoundActivityTracker};
+use crate::{
+    impl_from_error_for_route, BoundActivityTracker, IntrospectionCache, PreferredLanguage,
+    SharedHomeserverConnection,
+};
 
 #[cfg(test)]
 mod tests;
 
 struct GraphQLState {
     pool: PgPool,
-    homeserver_connection: Arc<dyn HomeserverConnection<Error = anyhow::Error>>,
+    homeserver_connection: SharedHomeserverConnection,
     policy_factory: Arc<PolicyFactory>,
+    email_rate_limits: EmailRateLimits,
+    introspection_cache: IntrospectionCache,
 }
 
 #[async_trait]
@@ -85,18 +91,34 @@ impl mas_graphql::State for GraphQLState {
         let rng = ChaChaRng::from_rng(rng).expect("Failed to seed rng");
         Box::new(rng)
     }
+
+    fn email_rate_limits(&self) -> EmailRateLimits {
+        self.email_rate_limits
+    }
+
+    fn session_ended(&self, session_id: Ulid) {
+        self.introspection_cache.invalidate_session(session_id);
+    }
+
+    fn user_changed(&self, user_id: Ulid) {
+        self.introspection_cache.invalidate_user(user_id);
+    }
 }
 
 #[must_use]
 pub fn schema(
     pool: &PgPool,
     policy_factory: &Arc<PolicyFactory>,
-    homeserver_connection: impl HomeserverConnection<Error = anyhow::Error> + 'static,
+    homeserver_connection: SharedHomeserverConnection,
+    email_rate_limits: EmailRateLimits,
+    introspection_cache: IntrospectionCache,
 ) -> Schema {
     let state = GraphQLState {
         pool: pool.clone(),
         policy_factory: Arc::clone(policy_factory),
-        homeserver_connection: Arc::new(homeserver_connection),
+        homeserver_connection,
+        email_rate_limits,
+        introspection_cache,
     };
     let state: mas_graphql::BoxState = Box::new(state);
 
@@ -239,7 +261,7 @@ async fn get_requester(
 
         Requester::OAuth2Session(session, user)
     } else {
-        let maybe_session = session_info.load_session(&mut repo).await?;
+        let maybe_session = session_info.load_session(clock, &mut repo).await?;
 
         if let Some(session) = maybe_session.as_ref() {
             activity_tracker
@@ -256,6 +278,7 @@ async fn get_requester(
 pub async fn post(
     State(schema): State<Schema>,
     clock: BoxClock,
+    PreferredLanguage(locale): PreferredLanguage,
     repo: BoxRepository,
     activity_tracker: BoundActivityTracker,
     cookie_jar: CookieJar,
@@ -278,7 +301,8 @@ pub async fn post(
         MultipartOptions::default(),
     )
     .await?
-    .data(requester); // XXX: this should probably return another error response?
+    .data(requester) // XXX: this should probably return another error response?
+    .data(RequestLanguage(locale.to_string()));
 
     let span = span_for_graphql_request(&request);
     let response = schema.execute(request).instrument(span).await;
@@ -297,6 +321,7 @@ pub async fn post(
 pub async fn get(
     State(schema): State<Schema>,
     clock: BoxClock,
+    PreferredLanguage(locale): PreferredLanguage,
     repo: BoxRepository,
     activity_tracker: BoundActivityTracker,
     cookie_jar: CookieJar,
@@ -309,8 +334,9 @@ pub async fn get(
     let (session_info, _cookie_jar) = cookie_jar.session_info();
     let requester = get_requester(&clock, &activity_tracker, repo, session_info, token).await?;
 
-    let request =
-        async_graphql::http::parse_query_string(&query.unwrap_or_default())?.data(requester);
+    let request = async_graphql::http::parse_query_string(&query.unwrap_or_default())?
+        .data(requester)
+        .data(RequestLanguage(locale.to_string()));
 
     let span = span_for_graphql_request(&request);
     let response = schema.execute(request).instrument(span).await;
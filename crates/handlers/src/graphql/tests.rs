@@ -13,16 +13,28 @@
 // limitations under the License.
 
 use axum::http::Request;
+use chrono::Duration;
 use hyper::StatusCode;
-use mas_data_model::{AccessToken, Client, TokenType, User};
+use mas_data_model::{
+    AccessToken, AuthorizationGrantStage, Client, ClientTokenSettings, ProfileAttribute, TokenType,
+    UpstreamOAuthProviderAuthorizationParams, UpstreamOAuthProviderClaimsImports,
+    UpstreamOAuthProviderEndpoints, UpstreamOAuthProviderHealthCheckSettings,
+    UpstreamOAuthProviderPkceMethod, UpstreamOAuthProviderProtocol,
+    UpstreamOAuthProviderSamlSettings, UpstreamOAuthProviderUiOptions, User,
+};
+use mas_iana::oauth::OAuthClientAuthenticationMethod;
 use mas_router::SimpleRoute;
 use mas_storage::{
-    oauth2::{OAuth2AccessTokenRepository, OAuth2ClientRepository},
-    RepositoryAccess,
+    job::{JobRepositoryExt, ProvisionUserJob},
+    oauth2::{
+        OAuth2AccessTokenRepository, OAuth2AuthorizationGrantRepository, OAuth2ClientRepository,
+    },
+    upstream_oauth2::{UpstreamOAuthLinkRepository, UpstreamOAuthProviderRepository},
+    Clock, RepositoryAccess,
 };
 use oauth2_types::{
     registration::ClientRegistrationResponse,
-    requests::AccessTokenResponse,
+    requests::{AccessTokenResponse, ResponseMode},
     scope::{Scope, ScopeToken, OPENID},
 };
 use sqlx::PgPool;
@@ -58,6 +70,7 @@ async fn create_test_client(state: &TestState) -> Client {
             None,
             None,
             None,
+            None,
         )
         .await
         .unwrap();
@@ -568,3 +581,1242 @@ async fn test_oauth2_client_credentials(pool: PgPool) {
         .unwrap();
     assert!(token.is_some());
 }
+
+/// Test that users can rename their OAuth 2.0 sessions.
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_oauth2_session_rename(pool: PgPool) {
+    init_tracing();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let client = create_test_client(&state).await;
+    let user = create_test_user(&state, "alice").await;
+    let device = ScopeToken::from_static("urn:matrix:org.matrix.msc2967.client:device:ABCDEF");
+    let access_token =
+        start_oauth_session(&state, &client, &user, Scope::from_iter([GRAPHQL, device])).await;
+
+    let req = Request::post("/graphql")
+        .bearer(&access_token.access_token)
+        .json(serde_json::json!({
+            "query": r#"
+                mutation RenameSession($id: ID!) {
+                    setOauth2SessionName(input: { oauth2SessionId: $id, humanName: " My phone " }) {
+                        status
+                        oauth2Session {
+                            humanName
+                        }
+                    }
+                }
+            "#,
+            "variables": {
+                "id": format!("oauth2_session:{id}", id = access_token.session_id),
+            },
+        }));
+
+    let response = state.request(req).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "setOauth2SessionName": {
+                "status": "UPDATED",
+                "oauth2Session": {
+                    "humanName": "My phone",
+                },
+            },
+        })
+    );
+
+    // Another user can't rename the session
+    let bob = create_test_user(&state, "bob").await;
+    let bob_token = start_oauth_session(&state, &client, &bob, Scope::from_iter([GRAPHQL])).await;
+
+    let req = Request::post("/graphql")
+        .bearer(&bob_token.access_token)
+        .json(serde_json::json!({
+            "query": r#"
+                mutation RenameSession($id: ID!) {
+                    setOauth2SessionName(input: { oauth2SessionId: $id, humanName: "Stolen" }) {
+                        status
+                    }
+                }
+            "#,
+            "variables": {
+                "id": format!("oauth2_session:{id}", id = access_token.session_id),
+            },
+        }));
+
+    let response = state.request(req).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "setOauth2SessionName": {
+                "status": "NOT_FOUND",
+            },
+        })
+    );
+
+    // The session keeps its name if the homeserver can't rename the device
+    state
+        .homeserver_connection
+        .set_unsupported("update_device_display_name")
+        .await;
+
+    let req = Request::post("/graphql")
+        .bearer(&access_token.access_token)
+        .json(serde_json::json!({
+            "query": r#"
+                mutation RenameSession($id: ID!) {
+                    setOauth2SessionName(input: { oauth2SessionId: $id, humanName: "My tablet" }) {
+                        status
+                    }
+                }
+            "#,
+            "variables": {
+                "id": format!("oauth2_session:{id}", id = access_token.session_id),
+            },
+        }));
+
+    let response = state.request(req).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "setOauth2SessionName": {
+                "status": "UNSUPPORTED",
+            },
+        })
+    );
+
+    let mut repo = state.repository().await.unwrap();
+    let session = repo
+        .oauth2_session()
+        .lookup(access_token.session_id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(session.human_name.as_deref(), Some("My phone"));
+}
+
+/// Test that administrators can schedule the deactivation of a user
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_schedule_user_deactivation(pool: PgPool) {
+    init_tracing();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let client = create_test_client(&state).await;
+    let admin = create_test_user(&state, "admin").await;
+    let user = create_test_user(&state, "alice").await;
+
+    let user_token = start_oauth_session(&state, &client, &user, Scope::from_iter([GRAPHQL])).await;
+    let admin_token =
+        start_oauth_session(&state, &client, &admin, Scope::from_iter([GRAPHQL, ADMIN])).await;
+
+    let deactivate_at = state.clock.now() + Duration::days(30);
+    let query = r#"
+        mutation ScheduleUserDeactivation($id: ID!, $deactivateAt: DateTime) {
+            scheduleUserDeactivation(input: { userId: $id, deactivateAt: $deactivateAt, erase: true }) {
+                status
+                user {
+                    username
+                }
+            }
+        }
+    "#;
+
+    // Users can't schedule their own deactivation
+    let request = Request::post("/graphql")
+        .bearer(&user_token.access_token)
+        .json(serde_json::json!({
+            "query": query,
+            "variables": {
+                "id": format!("user:{id}", id = user.id),
+                "deactivateAt": deactivate_at,
+            },
+        }));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert_eq!(response.errors.len(), 1);
+
+    // Unknown users are reported as such
+    let request = Request::post("/graphql")
+        .bearer(&admin_token.access_token)
+        .json(serde_json::json!({
+            "query": query,
+            "variables": {
+                "id": format!("user:{id}", id = ulid::Ulid::nil()),
+                "deactivateAt": deactivate_at,
+            },
+        }));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "scheduleUserDeactivation": {
+                "status": "NOT_FOUND",
+                "user": null,
+            },
+        })
+    );
+
+    let jobs: Vec<String> =
+        sqlx::query_scalar("SELECT job::text FROM apalis.jobs WHERE job_type = 'deactivate-user'")
+            .fetch_all(&state.pool)
+            .await
+            .unwrap();
+    assert!(jobs.is_empty());
+
+    // Administrators can schedule it
+    let request = Request::post("/graphql")
+        .bearer(&admin_token.access_token)
+        .json(serde_json::json!({
+            "query": query,
+            "variables": {
+                "id": format!("user:{id}", id = user.id),
+                "deactivateAt": deactivate_at,
+            },
+        }));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "scheduleUserDeactivation": {
+                "status": "SCHEDULED",
+                "user": {
+                    "username": "alice",
+                },
+            },
+        })
+    );
+
+    // The job only runs at the given time, and erases the data of the user
+    let jobs: Vec<(String, i64)> = sqlx::query_as(
+        "SELECT job::text, EXTRACT(EPOCH FROM run_at)::BIGINT FROM apalis.jobs WHERE job_type = 'deactivate-user'",
+    )
+    .fetch_all(&state.pool)
+    .await
+    .unwrap();
+    assert_eq!(jobs.len(), 1);
+    let (job, run_at) = &jobs[0];
+    assert_eq!(*run_at, deactivate_at.timestamp());
+    let job: serde_json::Value = serde_json::from_str(job).unwrap();
+    assert_eq!(job["user_id"], user.id.to_string());
+    assert_eq!(job["hs_erase"], true);
+    assert_eq!(job["erase_pii"], true);
+
+    // Nothing happened to the user yet
+    let mut repo = state.repository().await.unwrap();
+    let user = repo.user().lookup(user.id).await.unwrap().unwrap();
+    assert!(user.locked_at.is_none());
+}
+
+/// Test that failed background jobs are only visible to admins
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_failed_jobs(pool: PgPool) {
+    init_tracing();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let client = create_test_client(&state).await;
+    let user = create_test_user(&state, "alice").await;
+
+    let access_token =
+        start_oauth_session(&state, &client, &user, Scope::from_iter([GRAPHQL])).await;
+    let access_token = access_token.access_token;
+
+    let access_token_admin =
+        start_oauth_session(&state, &client, &user, Scope::from_iter([GRAPHQL, ADMIN])).await;
+    let access_token_admin = access_token_admin.access_token;
+
+    // Schedule a job and pretend it failed
+    let mut repo = state.repository().await.unwrap();
+    let job_id = repo
+        .job()
+        .schedule_job(ProvisionUserJob::new(&user))
+        .await
+        .unwrap();
+    repo.save().await.unwrap();
+
+    sqlx::query(
+        "UPDATE apalis.jobs SET status = 'Failed', last_error = 'homeserver unreachable', done_at = NOW() WHERE id = $1",
+    )
+    .bind(job_id.to_string())
+    .execute(&state.pool)
+    .await
+    .unwrap();
+
+    let query = serde_json::json!({
+        "query": r#"
+            query {
+                failedJobs {
+                    id
+                    name
+                    lastError
+                }
+            }
+        "#,
+    });
+
+    // Regular users can't see failed jobs
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(query.clone());
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(!response.errors.is_empty());
+
+    // Admins can
+    let request = Request::post("/graphql")
+        .bearer(&access_token_admin)
+        .json(query);
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty());
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "failedJobs": [{
+                "id": job_id.to_string(),
+                "name": "provision-user",
+                "lastError": "homeserver unreachable",
+            }],
+        })
+    );
+}
+
+/// Test that the health of upstream providers is only visible to admins
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_upstream_oauth2_provider_health(pool: PgPool) {
+    init_tracing();
+    let state = TestState::from_pool(pool).await.unwrap();
+    let mut rng = state.rng();
+
+    let client = create_test_client(&state).await;
+    let user = create_test_user(&state, "alice").await;
+
+    let access_token =
+        start_oauth_session(&state, &client, &user, Scope::from_iter([GRAPHQL])).await;
+    let access_token = access_token.access_token;
+
+    let access_token_admin =
+        start_oauth_session(&state, &client, &user, Scope::from_iter([GRAPHQL, ADMIN])).await;
+    let access_token_admin = access_token_admin.access_token;
+
+    // Add a provider which failed its health check
+    let mut repo = state.repository().await.unwrap();
+    let provider = repo
+        .upstream_oauth_provider()
+        .add(
+            &mut rng,
+            &state.clock,
+            "https://example.com/".to_owned(),
+            None,
+            Scope::from_iter([OPENID]),
+            OAuthClientAuthenticationMethod::None,
+            None,
+            "client".to_owned(),
+            None,
+            UpstreamOAuthProviderClaimsImports::default(),
+            UpstreamOAuthProviderPkceMethod::default(),
+            UpstreamOAuthProviderAuthorizationParams::default(),
+            false,
+            UpstreamOAuthProviderUiOptions::default(),
+            UpstreamOAuthProviderProtocol::Oidc,
+            UpstreamOAuthProviderEndpoints::default(),
+            UpstreamOAuthProviderSamlSettings::default(),
+            false,
+            UpstreamOAuthProviderHealthCheckSettings::default(),
+        )
+        .await
+        .unwrap();
+    repo.upstream_oauth_provider()
+        .set_health(&state.clock, &provider, Some("unreachable".to_owned()))
+        .await
+        .unwrap();
+    repo.save().await.unwrap();
+
+    let query = serde_json::json!({
+        "query": r#"
+            query UpstreamOAuth2Provider($id: ID!) {
+                upstreamOauth2Provider(id: $id) {
+                    health {
+                        healthy
+                        error
+                    }
+                }
+            }
+        "#,
+        "variables": {
+            "id": format!("upstream_oauth2_provider:{}", provider.id),
+        },
+    });
+
+    // Regular users can't see the health of the provider
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(query.clone());
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(!response.errors.is_empty());
+
+    // Admins can
+    let request = Request::post("/graphql")
+        .bearer(&access_token_admin)
+        .json(query);
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty());
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "upstreamOauth2Provider": {
+                "health": {
+                    "healthy": false,
+                    "error": "unreachable",
+                },
+            },
+        })
+    );
+}
+
+/// Test that users can't change their display name when it is locked by an
+/// upstream provider they are linked to
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_locked_display_name(pool: PgPool) {
+    init_tracing();
+    let state = TestState::from_pool(pool).await.unwrap();
+    let mut rng = state.rng();
+
+    let client = create_test_client(&state).await;
+    let user = create_test_user(&state, "alice").await;
+
+    let access_token =
+        start_oauth_session(&state, &client, &user, Scope::from_iter([GRAPHQL])).await;
+    let access_token = access_token.access_token;
+
+    let query = serde_json::json!({
+        "query": r#"
+            mutation SetDisplayName($userId: ID!) {
+                setDisplayName(input: { userId: $userId, displayName: "Alice" }) {
+                    status
+                }
+            }
+        "#,
+        "variables": {
+            "userId": format!("user:{}", user.id),
+        },
+    });
+
+    // The display name isn't locked yet
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(query.clone());
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "setDisplayName": { "status": "SET" } })
+    );
+
+    // Link the user to a provider which manages the display name
+    let mut repo = state.repository().await.unwrap();
+    let provider = repo
+        .upstream_oauth_provider()
+        .add(
+            &mut rng,
+            &state.clock,
+            "https://example.com/".to_owned(),
+            None,
+            Scope::from_iter([OPENID]),
+            OAuthClientAuthenticationMethod::None,
+            None,
+            "client".to_owned(),
+            None,
+            UpstreamOAuthProviderClaimsImports {
+                locked_attributes: vec![ProfileAttribute::Displayname],
+                ..UpstreamOAuthProviderClaimsImports::default()
+            },
+            UpstreamOAuthProviderPkceMethod::default(),
+            UpstreamOAuthProviderAuthorizationParams::default(),
+            false,
+            UpstreamOAuthProviderUiOptions::default(),
+            UpstreamOAuthProviderProtocol::Oidc,
+            UpstreamOAuthProviderEndpoints::default(),
+            UpstreamOAuthProviderSamlSettings::default(),
+            false,
+            UpstreamOAuthProviderHealthCheckSettings::default(),
+        )
+        .await
+        .unwrap();
+    let link = repo
+        .upstream_oauth_link()
+        .add(&mut rng, &state.clock, &provider, "subject".to_owned())
+        .await
+        .unwrap();
+    repo.upstream_oauth_link()
+        .associate_to_user(&link, &user)
+        .await
+        .unwrap();
+    repo.save().await.unwrap();
+
+    let request = Request::post("/graphql").bearer(&access_token).json(query);
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "setDisplayName": { "status": "LOCKED" } })
+    );
+
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(serde_json::json!({
+            "query": r#"
+                query {
+                    viewer {
+                        ... on User {
+                            lockedProfileAttributes
+                        }
+                    }
+                }
+            "#,
+        }));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "viewer": {
+                "lockedProfileAttributes": ["DISPLAYNAME"],
+            },
+        })
+    );
+}
+
+/// Test that users can opt out of security notifications, and that locking a
+/// user schedules one
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_security_notifications(pool: PgPool) {
+    init_tracing();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let client = create_test_client(&state).await;
+    let user = create_test_user(&state, "alice").await;
+    let bob = create_test_user(&state, "bob").await;
+
+    let access_token =
+        start_oauth_session(&state, &client, &user, Scope::from_iter([GRAPHQL])).await;
+    let access_token = access_token.access_token;
+
+    let access_token_admin =
+        start_oauth_session(&state, &client, &user, Scope::from_iter([GRAPHQL, ADMIN])).await;
+    let access_token_admin = access_token_admin.access_token;
+
+    let query = serde_json::json!({
+        "query": r#"
+            query {
+                viewer {
+                    ... on User {
+                        securityNotificationOptOuts
+                    }
+                }
+            }
+        "#,
+    });
+
+    // Nothing is opted out by default
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(query.clone());
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "viewer": { "securityNotificationOptOuts": [] } })
+    );
+
+    // Opt out of the password change notifications
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(serde_json::json!({
+            "query": r#"
+                mutation OptOut($userId: ID!) {
+                    setSecurityNotificationOptOut(input: {
+                        userId: $userId,
+                        category: PASSWORD_CHANGE,
+                        optOut: true,
+                    }) {
+                        user {
+                            id
+                        }
+                    }
+                }
+            "#,
+            "variables": {
+                "userId": format!("user:{}", user.id),
+            },
+        }));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+    let request = Request::post("/graphql").bearer(&access_token).json(query);
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "viewer": { "securityNotificationOptOuts": ["PASSWORD_CHANGE"] } })
+    );
+
+    // Users can't change the preferences of someone else
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(serde_json::json!({
+            "query": r#"
+                mutation OptOut($userId: ID!) {
+                    setSecurityNotificationOptOut(input: {
+                        userId: $userId,
+                        category: ACCOUNT_LOCK,
+                        optOut: true,
+                    }) {
+                        user {
+                            id
+                        }
+                    }
+                }
+            "#,
+            "variables": {
+                "userId": format!("user:{}", bob.id),
+            },
+        }));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(!response.errors.is_empty());
+
+    // Locking a user lets them know by email
+    let request = Request::post("/graphql")
+        .bearer(&access_token_admin)
+        .json(serde_json::json!({
+            "query": r#"
+                mutation LockUser($userId: ID!) {
+                    lockUser(input: { userId: $userId }) {
+                        status
+                    }
+                }
+            "#,
+            "variables": {
+                "userId": format!("user:{}", bob.id),
+            },
+        }));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "lockUser": { "status": "LOCKED" } })
+    );
+
+    let jobs: Vec<String> = sqlx::query_scalar(
+        "SELECT job::text FROM apalis.jobs WHERE job_type = 'send-security-notification'",
+    )
+    .fetch_all(&state.pool)
+    .await
+    .unwrap();
+    assert_eq!(jobs.len(), 1);
+    let job: serde_json::Value = serde_json::from_str(&jobs[0]).unwrap();
+    assert_eq!(job["user_id"], bob.id.to_string());
+    assert_eq!(job["category"], "account_lock");
+}
+
+/// Test that admins can override the token settings of a client
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_oauth2_client_token_settings(pool: PgPool) {
+    init_tracing();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let client = create_test_client(&state).await;
+    let user = create_test_user(&state, "alice").await;
+
+    let access_token =
+        start_oauth_session(&state, &client, &user, Scope::from_iter([GRAPHQL])).await;
+    let access_token = access_token.access_token;
+
+    let access_token_admin =
+        start_oauth_session(&state, &client, &user, Scope::from_iter([GRAPHQL, ADMIN])).await;
+    let access_token_admin = access_token_admin.access_token;
+
+    let query = serde_json::json!({
+        "query": r#"
+            mutation SetTokenSettings($clientId: ID!) {
+                setOauth2ClientTokenSettings(input: {
+                    clientId: $clientId,
+                    accessTokenTtl: 60,
+                    additionalAudiences: ["https://api.example.com/"],
+                }) {
+                    oauth2Client {
+                        id
+                    }
+                }
+            }
+        "#,
+        "variables": {
+            "clientId": format!("oauth2_client:{}", client.id),
+        },
+    });
+
+    // Regular users can't change them
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(query.clone());
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(!response.errors.is_empty());
+
+    let mut repo = state.repository().await.unwrap();
+    let settings = repo.oauth2_client().token_settings(&client).await.unwrap();
+    assert_eq!(settings, ClientTokenSettings::default());
+    repo.cancel().await.unwrap();
+
+    // Admins can
+    let request = Request::post("/graphql")
+        .bearer(&access_token_admin)
+        .json(query);
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "setOauth2ClientTokenSettings": {
+                "oauth2Client": {
+                    "id": format!("oauth2_client:{}", client.id),
+                },
+            },
+        })
+    );
+
+    let mut repo = state.repository().await.unwrap();
+    let settings = repo.oauth2_client().token_settings(&client).await.unwrap();
+    assert_eq!(
+        settings,
+        ClientTokenSettings {
+            access_token_ttl: Some(Duration::minutes(1)),
+            refresh_token_ttl: None,
+            additional_audiences: vec!["https://api.example.com/".to_owned()],
+        }
+    );
+}
+
+/// Test that admins can set the scopes users don't have to consent to for a
+/// client
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_oauth2_client_trusted_scope(pool: PgPool) {
+    init_tracing();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let client = create_test_client(&state).await;
+    let user = create_test_user(&state, "alice").await;
+
+    let access_token =
+        start_oauth_session(&state, &client, &user, Scope::from_iter([GRAPHQL])).await;
+    let access_token = access_token.access_token;
+
+    let access_token_admin =
+        start_oauth_session(&state, &client, &user, Scope::from_iter([GRAPHQL, ADMIN])).await;
+    let access_token_admin = access_token_admin.access_token;
+
+    let query = |scope: serde_json::Value| {
+        serde_json::json!({
+            "query": r#"
+                mutation SetTrustedScope($clientId: ID!, $scope: String) {
+                    setOauth2ClientTrustedScope(input: {
+                        clientId: $clientId,
+                        scope: $scope,
+                    }) {
+                        oauth2Client {
+                            id
+                        }
+                    }
+                }
+            "#,
+            "variables": {
+                "clientId": format!("oauth2_client:{}", client.id),
+                "scope": scope,
+            },
+        })
+    };
+
+    // Regular users can't change it
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(query(serde_json::json!("openid")));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(!response.errors.is_empty());
+
+    let mut repo = state.repository().await.unwrap();
+    let trusted_scope = repo.oauth2_client().trusted_scope(&client).await.unwrap();
+    assert_eq!(trusted_scope, None);
+    repo.cancel().await.unwrap();
+
+    // Invalid scopes are rejected
+    let request = Request::post("/graphql")
+        .bearer(&access_token_admin)
+        .json(query(serde_json::json!("openid \"email\"")));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(!response.errors.is_empty());
+
+    // Admins can
+    let request = Request::post("/graphql")
+        .bearer(&access_token_admin)
+        .json(query(serde_json::json!("openid email")));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "setOauth2ClientTrustedScope": {
+                "oauth2Client": {
+                    "id": format!("oauth2_client:{}", client.id),
+                },
+            },
+        })
+    );
+
+    let mut repo = state.repository().await.unwrap();
+    let trusted_scope = repo.oauth2_client().trusted_scope(&client).await.unwrap();
+    assert_eq!(trusted_scope, Some("openid email".parse().unwrap()));
+    repo.cancel().await.unwrap();
+
+    // The client is no longer trusted once the scope is unset
+    let request = Request::post("/graphql")
+        .bearer(&access_token_admin)
+        .json(query(serde_json::Value::Null));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+    let mut repo = state.repository().await.unwrap();
+    let trusted_scope = repo.oauth2_client().trusted_scope(&client).await.unwrap();
+    assert_eq!(trusted_scope, None);
+}
+
+/// Test that admins can list, approve and deny the authorization requests
+/// waiting for an approval
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_authorization_approvals(pool: PgPool) {
+    init_tracing();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let client = create_test_client(&state).await;
+    let user = create_test_user(&state, "alice").await;
+
+    let access_token =
+        start_oauth_session(&state, &client, &user, Scope::from_iter([GRAPHQL])).await;
+    let access_token = access_token.access_token;
+
+    let access_token_admin =
+        start_oauth_session(&state, &client, &user, Scope::from_iter([GRAPHQL, ADMIN])).await;
+    let access_token_admin = access_token_admin.access_token;
+
+    // Two requests wait for an approval, a third one doesn't need one
+    let mut repo = state.repository().await.unwrap();
+    let mut grants = Vec::new();
+    for _ in 0..3 {
+        let grant = repo
+            .oauth2_authorization_grant()
+            .add(
+                &mut state.rng(),
+                &state.clock,
+                &client,
+                "https://example.com/callback".parse().unwrap(),
+                Scope::from_iter([OPENID]),
+                None,
+                Some("state".to_owned()),
+                None,
+                None,
+                ResponseMode::Query,
+                false,
+                false,
+                None,
+            )
+            .await
+            .unwrap();
+        grants.push(grant);
+    }
+    let pending = grants.pop().unwrap();
+    let mut requests = Vec::new();
+    for grant in grants {
+        state.clock.advance(Duration::minutes(1));
+        let grant = repo
+            .oauth2_authorization_grant()
+            .request_approval(&state.clock, &user, grant)
+            .await
+            .unwrap();
+        requests.push(grant);
+    }
+    repo.save().await.unwrap();
+
+    let list_query = serde_json::json!({
+        "query": r"
+            query {
+                authorizationApprovalRequests {
+                    id
+                    scope
+                    user {
+                        id
+                    }
+                }
+            }
+        ",
+    });
+
+    // Regular users can't see them
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(list_query.clone());
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(!response.errors.is_empty());
+
+    // Admins can
+    let request = Request::post("/graphql")
+        .bearer(&access_token_admin)
+        .json(list_query.clone());
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "authorizationApprovalRequests": [{
+                "id": requests[0].id.to_string(),
+                "scope": "openid",
+                "user": {
+                    "id": format!("user:{id}", id = user.id),
+                },
+            }, {
+                "id": requests[1].id.to_string(),
+                "scope": "openid",
+                "user": {
+                    "id": format!("user:{id}", id = user.id),
+                },
+            }],
+        })
+    );
+
+    let approve_query = |request_id: String| {
+        serde_json::json!({
+            "query": r"
+                mutation ApproveAuthorizationRequest($requestId: String!) {
+                    approveAuthorizationRequest(input: { requestId: $requestId }) {
+                        status
+                    }
+                }
+            ",
+            "variables": {
+                "requestId": request_id,
+            },
+        })
+    };
+    let deny_query = |request_id: String| {
+        serde_json::json!({
+            "query": r"
+                mutation DenyAuthorizationRequest($requestId: String!) {
+                    denyAuthorizationRequest(input: { requestId: $requestId }) {
+                        status
+                    }
+                }
+            ",
+            "variables": {
+                "requestId": request_id,
+            },
+        })
+    };
+
+    // Regular users can't approve them
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(approve_query(requests[0].id.to_string()));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(!response.errors.is_empty());
+
+    // Approving a request notifies the user
+    let request = Request::post("/graphql")
+        .bearer(&access_token_admin)
+        .json(approve_query(requests[0].id.to_string()));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "approveAuthorizationRequest": {
+                "status": "APPROVED",
+            },
+        })
+    );
+
+    let jobs: Vec<String> = sqlx::query_scalar(
+        "SELECT job::text FROM apalis.jobs WHERE job_type = 'send-authorization-approved'",
+    )
+    .fetch_all(&state.pool)
+    .await
+    .unwrap();
+    assert_eq!(jobs.len(), 1);
+    let job: serde_json::Value = serde_json::from_str(&jobs[0]).unwrap();
+    assert_eq!(job["authorization_grant_id"], requests[0].id.to_string());
+
+    // Denying a request cancels it
+    let request = Request::post("/graphql")
+        .bearer(&access_token_admin)
+        .json(deny_query(requests[1].id.to_string()));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "denyAuthorizationRequest": {
+                "status": "DENIED",
+            },
+        })
+    );
+
+    let mut repo = state.repository().await.unwrap();
+    let approved = repo
+        .oauth2_authorization_grant()
+        .lookup(requests[0].id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(approved.stage.is_pending());
+    let approval = approved.approval.unwrap();
+    assert!(approval.is_approved());
+    assert_eq!(approval.reviewed_by, Some(user.id));
+    let denied = repo
+        .oauth2_authorization_grant()
+        .lookup(requests[1].id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(matches!(
+        denied.stage,
+        AuthorizationGrantStage::Cancelled { .. }
+    ));
+    assert_eq!(denied.approval.unwrap().reviewed_by, Some(user.id));
+    repo.cancel().await.unwrap();
+
+    // Requests which don't wait for an approval anymore, or never did, can't
+    // be approved or denied
+    for request_id in [requests[0].id, requests[1].id, pending.id] {
+        let request = Request::post("/graphql")
+            .bearer(&access_token_admin)
+            .json(approve_query(request_id.to_string()));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: GraphQLResponse = response.json();
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        assert_eq!(
+            response.data,
+            serde_json::json!({
+                "approveAuthorizationRequest": {
+                    "status": "NOT_FOUND",
+                },
+            })
+        );
+
+        let request = Request::post("/graphql")
+            .bearer(&access_token_admin)
+            .json(deny_query(request_id.to_string()));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: GraphQLResponse = response.json();
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        assert_eq!(
+            response.data,
+            serde_json::json!({
+                "denyAuthorizationRequest": {
+                    "status": "NOT_FOUND",
+                },
+            })
+        );
+    }
+
+    let request = Request::post("/graphql")
+        .bearer(&access_token_admin)
+        .json(list_query);
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "authorizationApprovalRequests": [],
+        })
+    );
+}
+
+/// Test that replacing a verified primary email address has to be confirmed by
+/// the old address, and that the addresses involved can't be removed meanwhile
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_email_change_in_progress(pool: PgPool) {
+    init_tracing();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let client = create_test_client(&state).await;
+    let user = create_test_user(&state, "alice").await;
+
+    let access_token =
+        start_oauth_session(&state, &client, &user, Scope::from_iter([GRAPHQL])).await;
+    let access_token = access_token.access_token;
+
+    let mut repo = state.repository().await.unwrap();
+    let mut rng = state.rng();
+    let old_email = repo
+        .user_email()
+        .add(&mut rng, &state.clock, &user, "old@example.com".to_owned())
+        .await
+        .unwrap();
+    let old_email = repo
+        .user_email()
+        .mark_as_verified(&state.clock, old_email)
+        .await
+        .unwrap();
+    repo.user_email().set_as_primary(&old_email).await.unwrap();
+    let new_email = repo
+        .user_email()
+        .add(&mut rng, &state.clock, &user, "new@example.com".to_owned())
+        .await
+        .unwrap();
+    let new_email = repo
+        .user_email()
+        .mark_as_verified(&state.clock, new_email)
+        .await
+        .unwrap();
+    repo.save().await.unwrap();
+
+    let set_primary = |id: String| {
+        Request::post("/graphql")
+            .bearer(&access_token)
+            .json(serde_json::json!({
+                "query": r#"
+                    mutation SetPrimary($id: ID!) {
+                        setPrimaryEmail(input: { userEmailId: $id }) {
+                            status
+                        }
+                    }
+                "#,
+                "variables": { "id": id },
+            }))
+    };
+    let remove = |id: String| {
+        Request::post("/graphql")
+            .bearer(&access_token)
+            .json(serde_json::json!({
+                "query": r#"
+                    mutation Remove($id: ID!) {
+                        removeEmail(input: { userEmailId: $id }) {
+                            status
+                        }
+                    }
+                "#,
+                "variables": { "id": id },
+            }))
+    };
+
+    // Making the new address primary needs a confirmation from the old one
+    let response = state
+        .request(set_primary(format!("user_email:{}", new_email.id)))
+        .await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "setPrimaryEmail": { "status": "AWAITING_CONFIRMATION" } })
+    );
+
+    let mut repo = state.repository().await.unwrap();
+    let primary = repo.user_email().get_primary(&user).await.unwrap().unwrap();
+    assert_eq!(primary.id, old_email.id);
+    repo.cancel().await.unwrap();
+
+    let jobs: Vec<String> = sqlx::query_scalar(
+        "SELECT job::text FROM apalis.jobs WHERE job_type = 'send-email-change-notification'",
+    )
+    .fetch_all(&state.pool)
+    .await
+    .unwrap();
+    assert_eq!(jobs.len(), 1);
+
+    // Neither address can be removed while the change is in progress
+    for id in [old_email.id, new_email.id] {
+        let response = state.request(remove(format!("user_email:{id}"))).await;
+        response.assert_status(StatusCode::OK);
+        let response: GraphQLResponse = response.json();
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        assert_eq!(
+            response.data,
+            serde_json::json!({ "removeEmail": { "status": "CHANGE_IN_PROGRESS" } })
+        );
+    }
+
+    // Another change can't be started meanwhile
+    let response = state
+        .request(set_primary(format!("user_email:{}", new_email.id)))
+        .await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "setPrimaryEmail": { "status": "CHANGE_IN_PROGRESS" } })
+    );
+}
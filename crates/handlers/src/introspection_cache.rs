@@ -0,0 +1,326 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Short-lived cache of the results of the introspection endpoint
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use chrono::{DateTime, Duration, Utc};
+use mas_data_model::{CompatSession, Session};
+use oauth2_types::requests::IntrospectionResponse;
+use sha2::{Digest, Sha256};
+use sqlx::{postgres::PgListener, PgPool};
+use ulid::Ulid;
+
+/// Above this number of cached results, the expired ones are forgotten
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// The channel on which the database notifies the changes to users and
+/// sessions
+const NOTIFICATION_CHANNEL: &str = "mas::introspection_cache";
+
+/// The session a cached result is about, kept to record its activity on every
+/// introspection
+#[derive(Debug, Clone)]
+pub(crate) enum CachedSession {
+    OAuth2(Session),
+    Compat(CompatSession),
+}
+
+impl CachedSession {
+    fn id(&self) -> Ulid {
+        match self {
+            Self::OAuth2(session) => session.id,
+            Self::Compat(session) => session.id,
+        }
+    }
+
+    fn user_id(&self) -> Option<Ulid> {
+        match self {
+            Self::OAuth2(session) => session.user_id,
+            Self::Compat(session) => Some(session.user_id),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    response: IntrospectionResponse,
+    session: CachedSession,
+    cached_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    ttl: Duration,
+    entries: Mutex<HashMap<[u8; 32], Entry>>,
+}
+
+impl Inner {
+    fn entries(&self) -> MutexGuard<'_, HashMap<[u8; 32], Entry>> {
+        self.entries
+            .lock()
+            .expect("introspection cache lock poisoned")
+    }
+}
+
+/// Caches the results of the introspection endpoint for a short time, so that
+/// resource servers introspecting the same token over and over don't hit the
+/// database every time
+///
+/// Only the results for active tokens are cached. They are forgotten as soon
+/// as their session ends or their user changes, and after the configured
+/// time-to-live otherwise. Each server instance has its own cache, kept up to
+/// date with the changes made elsewhere through [`IntrospectionCache::listen`].
+/// The cache is disabled by default.
+#[derive(Debug, Clone, Default)]
+pub struct IntrospectionCache {
+    inner: Option<Arc<Inner>>,
+}
+
+impl IntrospectionCache {
+    /// Create a new cache keeping the results for `ttl`
+    #[must_use]
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            inner: Some(Arc::new(Inner {
+                ttl,
+                entries: Mutex::new(HashMap::new()),
+            })),
+        }
+    }
+
+    /// Create a cache which never keeps anything
+    #[must_use]
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// The tokens are never kept in memory, only a hash of them along with the
+    /// client introspecting them, as the results depend on the client
+    fn key(client_id: &str, token: &str) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(client_id.as_bytes());
+        hasher.update([0]);
+        hasher.update(token.as_bytes());
+        hasher.finalize().into()
+    }
+
+    /// Get the cached result of the introspection of `token` by the given
+    /// client, if it is still fresh
+    pub(crate) fn get(
+        &self,
+        now: DateTime<Utc>,
+        client_id: &str,
+        token: &str,
+    ) -> Option<(IntrospectionResponse, CachedSession)> {
+        let inner = self.inner.as_ref()?;
+        let key = Self::key(client_id, token);
+        let mut entries = inner.entries();
+
+        let entry = entries.get(&key)?;
+        let token_expired = entry.response.exp.is_some_and(|exp| exp <= now);
+        if now - entry.cached_at >= inner.ttl || token_expired {
+            entries.remove(&key);
+            return None;
+        }
+
+        Some((entry.response.clone(), entry.session.clone()))
+    }
+
+    /// Cache the result of the introspection of `token` by the given client
+    pub(crate) fn insert(
+        &self,
+        now: DateTime<Utc>,
+        client_id: &str,
+        token: &str,
+        response: &IntrospectionResponse,
+        session: CachedSession,
+    ) {
+        let Some(inner) = &self.inner else {
+            return;
+        };
+
+        if !response.active {
+            return;
+        }
+
+        let mut entries = inner.entries();
+        if entries.len() >= PRUNE_THRESHOLD {
+            entries.retain(|_, entry| now - entry.cached_at < inner.ttl);
+        }
+
+        entries.insert(
+            Self::key(client_id, token),
+            Entry {
+                response: response.clone(),
+                session,
+                cached_at: now,
+            },
+        );
+    }
+
+    /// Forget the results about the tokens of the given session, be it an
+    /// OAuth 2.0 or a compatibility session
+    ///
+    /// This must be called whenever a session ends or some of its tokens get
+    /// revoked.
+    pub fn invalidate_session(&self, session_id: Ulid) {
+        if let Some(inner) = &self.inner {
+            inner
+                .entries()
+                .retain(|_, entry| entry.session.id() != session_id);
+        }
+    }
+
+    /// Forget the results about the tokens of the given user, for example
+    /// when they get locked
+    pub fn invalidate_user(&self, user_id: Ulid) {
+        if let Some(inner) = &self.inner {
+            inner
+                .entries()
+                .retain(|_, entry| entry.session.user_id() != Some(user_id));
+        }
+    }
+    /// Forget all the cached results
+    fn clear(&self) {
+        if let Some(inner) = &self.inner {
+            inner.entries().clear();
+        }
+    }
+
+    /// Apply a notification sent by the database, in the form `user:<uuid>`
+    /// or `session:<uuid>`
+    fn handle_notification(&self, payload: &str) {
+        let Some((kind, id)) = payload.split_once(':') else {
+            tracing::warn!(payload, "Invalid introspection cache notification");
+            return;
+        };
+
+        let Ok(id) = u128::from_str_radix(&id.replace('-', ""), 16) else {
+            tracing::warn!(payload, "Invalid introspection cache notification");
+            return;
+        };
+        let id = Ulid::from(id);
+
+        match kind {
+            "user" => self.invalidate_user(id),
+            "session" => self.invalidate_session(id),
+            _ => tracing::warn!(payload, "Invalid introspection cache notification"),
+        }
+    }
+
+    /// Listen to the changes made to users and sessions by any server
+    /// instance, worker or command line tool, and forget the results about
+    /// them
+    ///
+    /// This does nothing if the cache is disabled.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the listening connection could not be set up
+    pub async fn listen(&self, pool: &PgPool) -> Result<(), sqlx::Error> {
+        if self.inner.is_none() {
+            return Ok(());
+        }
+
+        let mut listener = PgListener::connect_with(pool).await?;
+        listener.listen(NOTIFICATION_CHANNEL).await?;
+
+        let cache = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match listener.try_recv().await {
+                    Ok(Some(notification)) => cache.handle_notification(notification.payload()),
+                    Ok(None) => {
+                        // Notifications may have been missed while the connection was down
+                        tracing::warn!("Lost the introspection cache listening connection");
+                        cache.clear();
+                    }
+                    Err(e) => {
+                        tracing::error!(
+                            error = &e as &dyn std::error::Error,
+                            "Failed to listen to introspection cache notifications"
+                        );
+                        cache.clear();
+                        tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                    }
+                }
+            }
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mas_data_model::{CompatSessionState, Device};
+    use mas_storage::{clock::MockClock, Clock};
+
+    use super::*;
+
+    fn compat_session(id: Ulid, user_id: Ulid, now: DateTime<Utc>) -> CachedSession {
+        CachedSession::Compat(CompatSession {
+            id,
+            state: CompatSessionState::Valid,
+            user_id,
+            device: Device::try_from("ABCDEFGHIJ".to_owned()).unwrap(),
+            user_session_id: None,
+            created_at: now,
+            is_synapse_admin: false,
+            last_active_at: None,
+            last_active_ip: None,
+        })
+    }
+
+    #[test]
+    fn test_notifications() {
+        let clock = MockClock::default();
+        let now = clock.now();
+        let cache = IntrospectionCache::new(Duration::minutes(1));
+        let response = IntrospectionResponse {
+            active: true,
+            ..IntrospectionResponse::default()
+        };
+
+        let alice = compat_session(
+            Ulid::from(1_u128),
+            Ulid::from(0x0123_4567_89ab_cdef_0123_4567_89ab_cdef_u128),
+            now,
+        );
+        let bob = compat_session(Ulid::from(2_u128), Ulid::from(3_u128), now);
+        cache.insert(now, "client", "alice", &response, alice.clone());
+        cache.insert(now, "client", "bob", &response, bob);
+
+        // Malformed payloads are ignored
+        cache.handle_notification("user");
+        cache.handle_notification("user:not-an-id");
+        cache.handle_notification("device:01234567-89ab-cdef-0123-456789abcdef");
+        assert!(cache.get(now, "client", "alice").is_some());
+
+        // The IDs are sent by the database as UUIDs
+        cache.handle_notification("user:01234567-89ab-cdef-0123-456789abcdef");
+        assert!(cache.get(now, "client", "alice").is_none());
+        assert!(cache.get(now, "client", "bob").is_some());
+
+        cache.insert(now, "client", "alice", &response, alice);
+        cache.handle_notification("session:00000000-0000-0000-0000-000000000001");
+        assert!(cache.get(now, "client", "alice").is_none());
+        assert!(cache.get(now, "client", "bob").is_some());
+    }
+}
@@ -0,0 +1,379 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Filtering of the login and registration attempts based on the IP address
+//! they come from, using static lists, DNS blocklists and a reputation service
+
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use hyper::body::Bytes;
+use ipnetwork::IpNetwork;
+use mas_http::HttpService;
+use opentelemetry::{metrics::Counter, Key};
+use serde::Deserialize;
+use thiserror::Error;
+use tower::ServiceExt;
+use url::Url;
+
+/// How long the result of the lookups of an IP address is remembered
+const CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Above this number of cached IP addresses, the expired entries are
+/// forgotten
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// How long a single lookup can take before it is given up
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(2);
+
+const ACTION: Key = Key::from_static_str("action");
+const REASON: Key = Key::from_static_str("reason");
+
+/// The attempts which are subject to the filter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Action {
+    Login,
+    Registration,
+}
+
+impl Action {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::Login => "login",
+            Self::Registration => "registration",
+        }
+    }
+}
+
+/// Why an IP address was denied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DenyReason {
+    Denylist,
+    Dnsbl,
+    Reputation,
+}
+
+impl DenyReason {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::Denylist => "denylist",
+            Self::Dnsbl => "dnsbl",
+            Self::Reputation => "reputation",
+        }
+    }
+}
+
+/// The attempt was denied because of the IP address it comes from
+#[derive(Debug, Error)]
+#[error("requests from this IP address are denied")]
+pub(crate) struct IpDenied;
+
+/// The rules applied by an [`IpFilter`]
+#[derive(Debug, Clone, Default)]
+pub struct IpFilterRules {
+    /// Networks which are never denied, bypassing the other rules
+    pub allow: Vec<IpNetwork>,
+
+    /// Networks which are always denied
+    pub deny: Vec<IpNetwork>,
+
+    /// DNS blocklist zones to look the IP addresses up in
+    pub dnsbl_zones: Vec<String>,
+
+    /// URL of an HTTP service telling whether an IP address should be denied
+    pub reputation_service: Option<Url>,
+}
+
+/// The answer of the reputation service
+#[derive(Deserialize)]
+struct ReputationResponse {
+    deny: bool,
+}
+
+#[derive(Debug)]
+struct IpFilterInner {
+    rules: IpFilterRules,
+    http_service: HttpService,
+    cache: Mutex<HashMap<IpAddr, (Instant, Option<DenyReason>)>>,
+    blocked_counter: Counter<u64>,
+}
+
+/// Denies login and registration attempts from unwanted IP addresses
+///
+/// Lookups on the DNS blocklists and on the reputation service are cached for
+/// a while. Should they fail, the attempt is let through rather than denied.
+/// Attempts of which the IP address is unknown are never denied.
+#[derive(Debug, Clone)]
+pub struct IpFilter {
+    inner: Arc<IpFilterInner>,
+}
+
+impl IpFilter {
+    /// Create a new filter enforcing the given rules, using `http_service` to
+    /// query the reputation service
+    #[must_use]
+    pub fn new(rules: IpFilterRules, http_service: HttpService) -> Self {
+        let meter = opentelemetry::global::meter_with_version(
+            env!("CARGO_PKG_NAME"),
+            Some(env!("CARGO_PKG_VERSION")),
+            Some(opentelemetry_semantic_conventions::SCHEMA_URL),
+            None,
+        );
+
+        let blocked_counter = meter
+            .u64_counter("mas.ip_filter.blocked")
+            .with_description("The number of attempts denied because of their IP address")
+            .with_unit(opentelemetry::metrics::Unit::new("{attempts}"))
+            .init();
+
+        // Record stuff on the counter so that the metrics are initialized
+        for action in [Action::Login, Action::Registration] {
+            for reason in [
+                DenyReason::Denylist,
+                DenyReason::Dnsbl,
+                DenyReason::Reputation,
+            ] {
+                blocked_counter.add(
+                    0,
+                    &[
+                        ACTION.string(action.as_str()),
+                        REASON.string(reason.as_str()),
+                    ],
+                );
+            }
+        }
+
+        Self {
+            inner: Arc::new(IpFilterInner {
+                rules,
+                http_service,
+                cache: Mutex::new(HashMap::new()),
+                blocked_counter,
+            }),
+        }
+    }
+
+    /// Check an attempt coming from the given IP address
+    pub(crate) async fn check(&self, ip: Option<IpAddr>, action: Action) -> Result<(), IpDenied> {
+        let Some(ip) = ip else {
+            return Ok(());
+        };
+
+        match self.deny_reason(ip).await {
+            Some(reason) => {
+                tracing::info!(
+                    %ip,
+                    ip_filter.action = action.as_str(),
+                    ip_filter.reason = reason.as_str(),
+                    "Denied attempt because of its IP address"
+                );
+                self.inner.blocked_counter.add(
+                    1,
+                    &[
+                        ACTION.string(action.as_str()),
+                        REASON.string(reason.as_str()),
+                    ],
+                );
+                Err(IpDenied)
+            }
+            None => Ok(()),
+        }
+    }
+
+    async fn deny_reason(&self, ip: IpAddr) -> Option<DenyReason> {
+        let rules = &self.inner.rules;
+        if rules.allow.iter().any(|network| network.contains(ip)) {
+            return None;
+        }
+
+        if rules.deny.iter().any(|network| network.contains(ip)) {
+            return Some(DenyReason::Denylist);
+        }
+
+        if rules.dnsbl_zones.is_empty() && rules.reputation_service.is_none() {
+            return None;
+        }
+
+        if let Some(reason) = self.cached(ip) {
+            return reason;
+        }
+
+        let reason = self.lookup(ip).await;
+
+        let mut cache = self.inner.cache.lock().expect("IP filter lock poisoned");
+        if cache.len() >= PRUNE_THRESHOLD {
+            cache.retain(|_, (looked_up_at, _)| looked_up_at.elapsed() < CACHE_TTL);
+        }
+        cache.insert(ip, (Instant::now(), reason));
+
+        reason
+    }
+
+    /// The cached result of the lookups of `ip`, if it is still fresh
+    fn cached(&self, ip: IpAddr) -> Option<Option<DenyReason>> {
+        let cache = self.inner.cache.lock().expect("IP filter lock poisoned");
+        cache
+            .get(&ip)
+            .filter(|(looked_up_at, _)| looked_up_at.elapsed() < CACHE_TTL)
+            .map(|(_, reason)| *reason)
+    }
+
+    async fn lookup(&self, ip: IpAddr) -> Option<DenyReason> {
+        for zone in &self.inner.rules.dnsbl_zones {
+            if is_listed(ip, zone).await {
+                return Some(DenyReason::Dnsbl);
+            }
+        }
+
+        if let Some(url) = &self.inner.rules.reputation_service {
+            match self.query_reputation_service(url, ip).await {
+                Ok(true) => return Some(DenyReason::Reputation),
+                Ok(false) => {}
+                Err(e) => {
+                    // Don't lock everyone out because the service is struggling
+                    tracing::warn!(
+                        %ip,
+                        error = &*e as &dyn std::error::Error,
+                        "Failed to query the IP reputation service, letting the attempt through"
+                    );
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Ask the reputation service whether `ip` should be denied
+    async fn query_reputation_service(
+        &self,
+        url: &Url,
+        ip: IpAddr,
+    ) -> Result<bool, tower::BoxError> {
+        let mut url = url.clone();
+        url.query_pairs_mut().append_pair("ip", &ip.to_string());
+
+        let request = hyper::Request::get(url.as_str()).body(Bytes::new())?;
+        let response = tokio::time::timeout(
+            LOOKUP_TIMEOUT,
+            self.inner.http_service.clone().oneshot(request),
+        )
+        .await??;
+
+        if !response.status().is_success() {
+            return Err(format!("unexpected status code {}", response.status()).into());
+        }
+
+        let response: ReputationResponse = serde_json::from_slice(response.body())?;
+        Ok(response.deny)
+    }
+}
+
+/// The name to query to look `ip` up in the given DNS blocklist zone
+fn dnsbl_query_name(ip: IpAddr, zone: &str) -> String {
+    let mut name = String::new();
+    match ip {
+        IpAddr::V4(ip) => {
+            for octet in ip.octets().iter().rev() {
+                // Writing to a String never fails
+                let _ = write!(name, "{octet}.");
+            }
+        }
+        IpAddr::V6(ip) => {
+            for byte in ip.octets().iter().rev() {
+                let _ = write!(name, "{:x}.{:x}.", byte & 0xf, byte >> 4);
+            }
+        }
+    }
+    name.push_str(zone.trim_matches('.'));
+    name
+}
+
+/// Whether `ip` is listed in the given DNS blocklist zone
+///
+/// Listed addresses resolve to an address in `127.0.0.0/8`. Addresses in
+/// `127.255.255.0/24` are used by some blocklists to report errors, and are
+/// not considered as listings.
+async fn is_listed(ip: IpAddr, zone: &str) -> bool {
+    let name = dnsbl_query_name(ip, zone);
+    let res =
+        tokio::time::timeout(LOOKUP_TIMEOUT, tokio::net::lookup_host((name.as_str(), 0))).await;
+
+    match res {
+        Ok(Ok(addrs)) => addrs.into_iter().any(|addr| match addr.ip() {
+            IpAddr::V4(addr) => {
+                let [a, b, c, _] = addr.octets();
+                a == 127 && (b, c) != (255, 255)
+            }
+            IpAddr::V6(_) => false,
+        }),
+        // Unlisted addresses don't resolve
+        Ok(Err(_)) => false,
+        Err(_) => {
+            tracing::warn!(%ip, zone, "DNS blocklist lookup timed out, letting the attempt through");
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mas_axum_utils::http_client_factory::HttpClientFactory;
+
+    use super::*;
+
+    #[test]
+    fn test_dnsbl_query_name() {
+        assert_eq!(
+            dnsbl_query_name(IpAddr::from([192, 0, 2, 1]), "dnsbl.example.com"),
+            "1.2.0.192.dnsbl.example.com"
+        );
+        assert_eq!(
+            dnsbl_query_name("2001:db8::1".parse().unwrap(), "dnsbl.example.com."),
+            "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.dnsbl.example.com"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_static_lists() {
+        let http_client_factory = HttpClientFactory::new().await.unwrap();
+        let filter = IpFilter::new(
+            IpFilterRules {
+                allow: vec!["192.0.2.1/32".parse().unwrap()],
+                deny: vec!["192.0.2.0/24".parse().unwrap()],
+                ..IpFilterRules::default()
+            },
+            http_client_factory.http_service("ip_filter"),
+        );
+
+        // Allowed addresses bypass the denylist
+        assert!(filter
+            .check(Some(IpAddr::from([192, 0, 2, 1])), Action::Login)
+            .await
+            .is_ok());
+        assert!(filter
+            .check(Some(IpAddr::from([192, 0, 2, 2])), Action::Login)
+            .await
+            .is_err());
+        assert!(filter
+            .check(Some(IpAddr::from([198, 51, 100, 1])), Action::Registration)
+            .await
+            .is_ok());
+        assert!(filter.check(None, Action::Registration).await.is_ok());
+    }
+}
@@ -30,7 +30,7 @@
     clippy::let_with_type_underscore,
 )]
 
-use std::{convert::Infallible, time::Duration};
+use std::{sync::Arc, time::Duration};
 
 use axum::{
     body::{Bytes, HttpBody},
@@ -42,24 +42,23 @@ use axum::{
 };
 use headers::HeaderName;
 use hyper::{
-    header::{
-        ACCEPT, ACCEPT_LANGUAGE, AUTHORIZATION, CONTENT_LANGUAGE, CONTENT_LENGTH, CONTENT_TYPE,
-    },
+    header::{ACCEPT, ACCEPT_LANGUAGE, AUTHORIZATION, CONTENT_LANGUAGE, CONTENT_TYPE},
     StatusCode, Version,
 };
 use mas_axum_utils::{cookies::CookieJar, FancyError};
 use mas_http::CorsLayerExt;
 use mas_keystore::{Encrypter, Keystore};
+use mas_matrix::HomeserverConnection;
 use mas_policy::Policy;
 use mas_router::{Route, UrlBuilder};
 use mas_storage::{BoxClock, BoxRepository, BoxRng};
-use mas_templates::{ErrorContext, NotFoundContext, TemplateContext, Templates};
+use mas_templates::{NotFoundContext, TemplateContext, Templates};
 use passwords::PasswordManager;
 use sqlx::PgPool;
-use tower::util::AndThenLayer;
 use tower_http::cors::{Any, CorsLayer};
 
 mod compat;
+mod error_page;
 mod graphql;
 mod health;
 mod oauth2;
@@ -68,7 +67,12 @@ pub mod upstream_oauth2;
 mod views;
 
 mod activity_tracker;
+mod appservice;
+mod conditional;
+mod introspection_cache;
+mod ip_filter;
 mod preferred_language;
+mod rate_limit;
 mod site_config;
 #[cfg(test)]
 mod test_utils;
@@ -95,13 +99,19 @@ pub use mas_axum_utils::{
 
 pub use self::{
     activity_tracker::{ActivityTracker, Bound as BoundActivityTracker},
+    appservice::{Appservice, AppserviceRegistry},
     compat::MatrixHomeserver,
     graphql::schema as graphql_schema,
+    introspection_cache::IntrospectionCache,
+    ip_filter::{IpFilter, IpFilterRules},
     preferred_language::PreferredLanguage,
-    site_config::SiteConfig,
-    upstream_oauth2::cache::MetadataCache,
+    rate_limit::{BucketConfig, Limiter, LoginFailureDelay, RateLimited, RateLimits},
+    site_config::{AccountRequirements, ClientWellKnownConfig, JwtLoginConfig, SiteConfig},
 };
 
+/// A connection to the homeserver, shared between the handlers
+pub type SharedHomeserverConnection = Arc<dyn HomeserverConnection<Error = anyhow::Error>>;
+
 pub fn healthcheck_router<S, B>() -> Router<S, B>
 where
     B: HttpBody + Send + 'static,
@@ -123,6 +133,7 @@ where
     BoxClock: FromRequestParts<S>,
     Encrypter: FromRef<S>,
     CookieJar: FromRequestParts<S>,
+    PreferredLanguage: FromRequestParts<S>,
 {
     let mut router = Router::new()
         .route(
@@ -158,6 +169,7 @@ where
     S: Clone + Send + Sync + 'static,
     Keystore: FromRef<S>,
     UrlBuilder: FromRef<S>,
+    SiteConfig: FromRef<S>,
     BoxClock: FromRequestParts<S>,
     BoxRng: FromRequestParts<S>,
 {
@@ -170,6 +182,10 @@ where
             mas_router::Webfinger::route(),
             get(self::oauth2::webfinger::get),
         )
+        .route(
+            mas_router::MatrixClientWellKnown::route(),
+            get(self::compat::well_known::get),
+        )
         .layer(
             CorsLayer::new()
                 .allow_origin(Any)
@@ -199,6 +215,11 @@ where
     Encrypter: FromRef<S>,
     HttpClientFactory: FromRef<S>,
     SiteConfig: FromRef<S>,
+    Limiter: FromRef<S>,
+    IntrospectionCache: FromRef<S>,
+    MatrixHomeserver: FromRef<S>,
+    AppserviceRegistry: FromRef<S>,
+    SharedHomeserverConnection: FromRef<S>,
     BoxClock: FromRequestParts<S>,
     BoxRng: FromRequestParts<S>,
     Policy: FromRequestParts<S>,
@@ -232,6 +253,22 @@ where
             mas_router::OAuth2RegistrationEndpoint::route(),
             post(self::oauth2::registration::post),
         )
+        .route(
+            mas_router::UpstreamOAuth2Token::route(),
+            get(self::upstream_oauth2::token::get),
+        )
+        .route(
+            mas_router::UpstreamOAuth2BackchannelLogout::route(),
+            post(self::upstream_oauth2::backchannel_logout::post),
+        )
+        .route(
+            mas_router::UpstreamOAuth2FrontchannelLogout::route(),
+            get(self::upstream_oauth2::frontchannel_logout::get),
+        )
+        .route(
+            mas_router::UpstreamOAuth2Keys::route(),
+            get(self::upstream_oauth2::keys::get),
+        )
         .layer(
             CorsLayer::new()
                 .allow_origin(Any)
@@ -256,12 +293,17 @@ where
     S: Clone + Send + Sync + 'static,
     UrlBuilder: FromRef<S>,
     SiteConfig: FromRef<S>,
+    IpFilter: FromRef<S>,
+    Limiter: FromRef<S>,
+    IntrospectionCache: FromRef<S>,
     MatrixHomeserver: FromRef<S>,
     PasswordManager: FromRef<S>,
+    HttpClientFactory: FromRef<S>,
     BoundActivityTracker: FromRequestParts<S>,
     BoxRepository: FromRequestParts<S>,
     BoxClock: FromRequestParts<S>,
     BoxRng: FromRequestParts<S>,
+    Policy: FromRequestParts<S>,
 {
     Router::new()
         .route(
@@ -276,6 +318,10 @@ where
             mas_router::CompatRefresh::route(),
             post(self::compat::refresh::post),
         )
+        .route(
+            mas_router::CompatRegister::route(),
+            post(self::compat::register::post),
+        )
         .route(
             mas_router::CompatLoginSsoRedirect::route(),
             get(self::compat::login_sso_redirect::get),
@@ -321,7 +367,10 @@ where
     Keystore: FromRef<S>,
     HttpClientFactory: FromRef<S>,
     PasswordManager: FromRef<S>,
-    MetadataCache: FromRef<S>,
+    SiteConfig: FromRef<S>,
+    IpFilter: FromRef<S>,
+    Limiter: FromRef<S>,
+    SharedHomeserverConnection: FromRef<S>,
     BoxClock: FromRequestParts<S>,
     BoxRng: FromRequestParts<S>,
     Policy: FromRequestParts<S>,
@@ -356,15 +405,27 @@ where
             }),
         )
         .route(mas_router::Index::route(), get(self::views::index::get))
+        .route(
+            mas_router::ThemeStylesheet::route(),
+            get(self::views::theme::get),
+        )
         .route(
             mas_router::Login::route(),
             get(self::views::login::get).post(self::views::login::post),
         )
         .route(mas_router::Logout::route(), post(self::views::logout::post))
+        .route(
+            mas_router::ChangeLanguage::route(),
+            post(self::views::language::post),
+        )
         .route(
             mas_router::Reauth::route(),
             get(self::views::reauth::get).post(self::views::reauth::post),
         )
+        .route(
+            mas_router::CompleteProfile::route(),
+            get(self::views::complete_profile::get).post(self::views::complete_profile::post),
+        )
         .route(
             mas_router::Register::route(),
             get(self::views::register::get).post(self::views::register::post),
@@ -373,6 +434,23 @@ where
             mas_router::AccountPassword::route(),
             get(self::views::account::password::get).post(self::views::account::password::post),
         )
+        .route(
+            mas_router::Impersonate::route(),
+            get(self::views::impersonate::get).post(self::views::impersonate::post),
+        )
+        .route(
+            mas_router::ResetCrossSigning::route(),
+            get(self::views::reset_cross_signing::get).post(self::views::reset_cross_signing::post),
+        )
+        .route(
+            mas_router::EmailChangeConfirm::route(),
+            get(self::views::email_change_confirm::get)
+                .post(self::views::email_change_confirm::post),
+        )
+        .route(
+            mas_router::EmailChangeRevert::route(),
+            get(self::views::email_change_revert::get).post(self::views::email_change_revert::post),
+        )
         .route(
             mas_router::AccountVerifyEmail::route(),
             get(self::views::account::emails::verify::get)
@@ -403,34 +481,86 @@ where
             mas_router::UpstreamOAuth2Authorize::route(),
             get(self::upstream_oauth2::authorize::get),
         )
+        .route(
+            mas_router::UpstreamOAuth2Revalidate::route(),
+            get(self::upstream_oauth2::authorize::revalidate),
+        )
+        .route(
+            mas_router::UpstreamOAuth2Upgrade::route(),
+            get(self::upstream_oauth2::authorize::upgrade),
+        )
         .route(
             mas_router::UpstreamOAuth2Callback::route(),
-            get(self::upstream_oauth2::callback::get),
+            get(self::upstream_oauth2::callback::handler)
+                .post(self::upstream_oauth2::callback::handler),
+        )
+        .route(
+            mas_router::UpstreamSamlMetadata::route(),
+            get(self::upstream_oauth2::saml::metadata),
+        )
+        .route(
+            mas_router::UpstreamSamlAcs::route(),
+            post(self::upstream_oauth2::saml::acs),
         )
         .route(
             mas_router::UpstreamOAuth2Link::route(),
             get(self::upstream_oauth2::link::get).post(self::upstream_oauth2::link::post),
         )
-        .layer(AndThenLayer::new(
-            move |response: axum::response::Response| async move {
-                if response.status().is_server_error() {
-                    // Error responses should have an ErrorContext attached to them
-                    let ext = response.extensions().get::<ErrorContext>();
-                    if let Some(ctx) = ext {
-                        if let Ok(res) = templates.render_error(ctx) {
-                            let (mut parts, _original_body) = response.into_parts();
-                            parts.headers.remove(CONTENT_TYPE);
-                            parts.headers.remove(CONTENT_LENGTH);
-                            return Ok((parts, Html(res)).into_response());
-                        }
-                    }
-                }
-
-                Ok::<_, Infallible>(response)
-            },
+        .layer(axum::middleware::from_fn_with_state(
+            templates,
+            self::error_page::render,
         ))
 }
 
+/// All the routes served by MAS, composed in a single router.
+///
+/// This is meant for embedding MAS in another application: the state can be
+/// any type from which the handlers can extract what they need. To serve it
+/// under a path prefix, nest the router under the path of the `public_base`
+/// used to build the [`UrlBuilder`], so that the generated URLs match the
+/// mounted routes.
+///
+/// Static assets and the [`fallback`] handler are not included, as they
+/// usually are shared with the application embedding MAS.
+#[allow(clippy::trait_duplication_in_bounds)]
+pub fn router<S, B>(templates: Templates, graphql_playground: bool) -> Router<S, B>
+where
+    B: HttpBody + Send + 'static,
+    <B as HttpBody>::Data: Into<Bytes> + Send,
+    <B as HttpBody>::Error: std::error::Error + Send + Sync,
+    S: Clone + Send + Sync + 'static,
+    PgPool: FromRef<S>,
+    mas_graphql::Schema: FromRef<S>,
+    Keystore: FromRef<S>,
+    UrlBuilder: FromRef<S>,
+    Encrypter: FromRef<S>,
+    Templates: FromRef<S>,
+    HttpClientFactory: FromRef<S>,
+    PasswordManager: FromRef<S>,
+    SiteConfig: FromRef<S>,
+    IpFilter: FromRef<S>,
+    Limiter: FromRef<S>,
+    IntrospectionCache: FromRef<S>,
+    MatrixHomeserver: FromRef<S>,
+    AppserviceRegistry: FromRef<S>,
+    SharedHomeserverConnection: FromRef<S>,
+    PreferredLanguage: FromRequestParts<S>,
+    BoxRepository: FromRequestParts<S>,
+    CookieJar: FromRequestParts<S>,
+    ActivityTracker: FromRequestParts<S>,
+    BoundActivityTracker: FromRequestParts<S>,
+    BoxClock: FromRequestParts<S>,
+    BoxRng: FromRequestParts<S>,
+    Policy: FromRequestParts<S>,
+{
+    healthcheck_router()
+        .merge(discovery_router())
+        .merge(api_router())
+        .merge(compat_router())
+        .merge(graphql_router(graphql_playground))
+        .merge(human_router(templates))
+}
+
 /// The fallback handler for all routes that don't match anything else.
 ///
 /// # Errors
@@ -450,3 +580,30 @@ pub async fn fallback(
 
     Ok((StatusCode::NOT_FOUND, Html(res)))
 }
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Request, StatusCode};
+    use sqlx::PgPool;
+    use tower::{Service, ServiceExt};
+
+    use crate::test_utils::{RequestBuilderExt, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_nested_router(pool: PgPool) {
+        let state = TestState::from_pool(pool).await.unwrap();
+        let app = axum::Router::new()
+            .nest("/auth", crate::router(state.templates.clone(), false))
+            .with_state(state);
+
+        let request = Request::get("/auth/health").empty();
+        let response = app.clone().ready_oneshot().await.unwrap().call(request);
+        let response = response.await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let request = Request::get("/health").empty();
+        let response = app.ready_oneshot().await.unwrap().call(request);
+        let response = response.await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
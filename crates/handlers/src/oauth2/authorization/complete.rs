@@ -15,19 +15,26 @@
 use axum::{
     extract::{Path, State},
     response::{Html, IntoResponse, Response},
+    TypedHeader,
 };
+use headers::UserAgent;
 use hyper::StatusCode;
 use mas_axum_utils::{cookies::CookieJar, csrf::CsrfExt, sentry::SentryEventID, SessionInfoExt};
-use mas_data_model::{AuthorizationGrant, BrowserSession, Client, Device};
+use mas_data_model::{AuthenticationMethod, AuthorizationGrant, BrowserSession, Client, Device};
 use mas_keystore::Keystore;
-use mas_policy::{EvaluationResult, Policy};
+use mas_matrix::HomeserverConnection;
+use mas_policy::{EvaluationResult, Policy, Requester};
 use mas_router::{PostAuthAction, UrlBuilder};
 use mas_storage::{
     oauth2::{OAuth2AuthorizationGrantRepository, OAuth2ClientRepository, OAuth2SessionRepository},
+    upstream_oauth2::{UpstreamOAuthProviderRepository, UpstreamOAuthSessionRepository},
     user::BrowserSessionRepository,
     BoxClock, BoxRepository, BoxRng, Clock, RepositoryAccess,
 };
-use mas_templates::{PolicyViolationContext, TemplateContext, Templates};
+use mas_templates::{
+    error_codes, ApprovalPendingContext, ErrorContext, PolicyViolationContext, TemplateContext,
+    Templates,
+};
 use oauth2_types::requests::AuthorizationResponse;
 use thiserror::Error;
 use tracing::warn;
@@ -35,7 +42,10 @@ use ulid::Ulid;
 
 use super::callback::CallbackDestination;
 use crate::{
-    impl_from_error_for_route, oauth2::generate_id_token, BoundActivityTracker, PreferredLanguage,
+    impl_from_error_for_route,
+    oauth2::{generate_id_token, mapped_claims},
+    views::complete_profile::MissingAttributes,
+    BoundActivityTracker, PreferredLanguage, SharedHomeserverConnection, SiteConfig,
 };
 
 #[derive(Debug, Error)]
@@ -95,15 +105,18 @@ pub(crate) async fn get(
     State(templates): State<Templates>,
     State(url_builder): State<UrlBuilder>,
     State(key_store): State<Keystore>,
+    State(site_config): State<SiteConfig>,
+    State(homeserver): State<SharedHomeserverConnection>,
     policy: Policy,
     activity_tracker: BoundActivityTracker,
+    user_agent: Option<TypedHeader<UserAgent>>,
     mut repo: BoxRepository,
     cookie_jar: CookieJar,
     Path(grant_id): Path<Ulid>,
 ) -> Result<Response, RouteError> {
     let (session_info, cookie_jar) = cookie_jar.session_info();
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info.load_session(&clock, &mut repo).await?;
 
     let grant = repo
         .oauth2_authorization_grant()
@@ -138,10 +151,13 @@ pub(crate) async fn get(
         &mut rng,
         &clock,
         &activity_tracker,
+        user_agent.map(|ua| ua.as_str().to_owned()),
         repo,
         key_store,
         policy,
         &url_builder,
+        &site_config,
+        homeserver.as_ref(),
         grant,
         &client,
         &session,
@@ -157,15 +173,28 @@ pub(crate) async fn get(
             url_builder.redirect(&mas_router::Reauth::and_then(continue_grant)),
         )
             .into_response()),
+        Err(GrantCompletionError::RequiresUpstreamRevalidation(provider_id)) => Ok((
+            cookie_jar,
+            url_builder.redirect(
+                &mas_router::UpstreamOAuth2Revalidate::new(provider_id).and_then(continue_grant),
+            ),
+        )
+            .into_response()),
         Err(GrantCompletionError::RequiresConsent) => {
             let next = mas_router::Consent(grant_id);
             Ok((cookie_jar, url_builder.redirect(&next)).into_response())
         }
+        Err(GrantCompletionError::RequiresProfile) => Ok((
+            cookie_jar,
+            url_builder.redirect(&mas_router::CompleteProfile::and_then(continue_grant)),
+        )
+            .into_response()),
         Err(GrantCompletionError::PolicyViolation(grant, res)) => {
             warn!(violation = ?res, "Authorization grant for client {} denied by policy", client.id);
 
             let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
             let ctx = PolicyViolationContext::new(grant, client)
+                .with_client_restricted(res.has_code("client-restricted"))
                 .with_session(session)
                 .with_csrf(csrf_token.form_value())
                 .with_language(locale);
@@ -174,6 +203,29 @@ pub(crate) async fn get(
 
             Ok((cookie_jar, Html(content)).into_response())
         }
+        Err(GrantCompletionError::RequiresApproval(grant)) => {
+            let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
+            let ctx = ApprovalPendingContext::new(grant, client)
+                .with_session(session)
+                .with_csrf(csrf_token.form_value())
+                .with_language(locale);
+
+            let content = templates.render_approval_pending(&ctx)?;
+
+            Ok((cookie_jar, Html(content)).into_response())
+        }
+        Err(GrantCompletionError::Impersonation) => {
+            let ctx = ErrorContext::new()
+                .with_code(error_codes::IMPERSONATION_NOT_ALLOWED)
+                .with_description(
+                    "Can't sign in to clients while impersonating a user.".to_owned(),
+                )
+                .with_language(&locale);
+
+            let content = templates.render_error_page(&ctx)?;
+
+            Ok((cookie_jar, Html(content)).into_response())
+        }
         Err(GrantCompletionError::NotPending) => Err(RouteError::NotPending),
         Err(GrantCompletionError::Internal(e)) => Err(RouteError::Internal(e)),
     }
@@ -190,11 +242,23 @@ pub enum GrantCompletionError {
     #[error("user needs to reauthenticate")]
     RequiresReauth,
 
+    #[error("user needs to be checked again with the upstream provider")]
+    RequiresUpstreamRevalidation(Ulid),
+
     #[error("client lacks consent")]
     RequiresConsent,
 
+    #[error("user lacks some attributes required on their account")]
+    RequiresProfile,
+
     #[error("denied by the policy")]
     PolicyViolation(AuthorizationGrant, EvaluationResult),
+
+    #[error("waiting for an administrator to approve the grant")]
+    RequiresApproval(AuthorizationGrant),
+
+    #[error("the browser session is impersonating the user")]
+    Impersonation,
 }
 
 impl_from_error_for_route!(GrantCompletionError: mas_storage::RepositoryError);
@@ -202,15 +266,19 @@ impl_from_error_for_route!(GrantCompletionError: super::callback::IntoCallbackDe
 impl_from_error_for_route!(GrantCompletionError: mas_policy::LoadError);
 impl_from_error_for_route!(GrantCompletionError: mas_policy::EvaluationError);
 impl_from_error_for_route!(GrantCompletionError: super::super::IdTokenSignatureError);
+impl_from_error_for_route!(GrantCompletionError: super::super::MappedClaimsError);
 
 pub(crate) async fn complete(
     rng: &mut (impl rand::RngCore + rand::CryptoRng + Send),
     clock: &impl Clock,
     activity_tracker: &BoundActivityTracker,
+    user_agent: Option<String>,
     mut repo: BoxRepository,
     key_store: Keystore,
     mut policy: Policy,
     url_builder: &UrlBuilder,
+    site_config: &SiteConfig,
+    homeserver: &dyn HomeserverConnection<Error = anyhow::Error>,
     grant: AuthorizationGrant,
     client: &Client,
     browser_session: &BrowserSession,
@@ -220,6 +288,12 @@ pub(crate) async fn complete(
         return Err(GrantCompletionError::NotPending);
     }
 
+    // Administrators impersonating a user can't log in to clients on their behalf,
+    // as the sessions would outlive the impersonation
+    if browser_session.is_impersonation() {
+        return Err(GrantCompletionError::Impersonation);
+    }
+
     // Check if the authentication is fresh enough
     let authentication = repo
         .browser_session()
@@ -232,9 +306,57 @@ pub(crate) async fn complete(
         return Err(GrantCompletionError::RequiresReauth);
     };
 
+    // Check again with the upstream provider the user logged in with, if it asks
+    // for their session with it to be re-validated
+    if let AuthenticationMethod::UpstreamOAuth2 {
+        upstream_oauth2_session_id,
+    } = valid_authentication.authentication_method
+    {
+        let upstream_session = repo
+            .upstream_oauth_session()
+            .lookup(upstream_oauth2_session_id)
+            .await?;
+        let provider = match upstream_session {
+            Some(upstream_session) => {
+                repo.upstream_oauth_provider()
+                    .lookup(upstream_session.provider_id)
+                    .await?
+            }
+            None => None,
+        };
+
+        if let Some(provider) = provider {
+            if provider
+                .authorization_params
+                .requires_revalidation(valid_authentication.created_at, clock.now())
+            {
+                repo.save().await?;
+                return Err(GrantCompletionError::RequiresUpstreamRevalidation(
+                    provider.id,
+                ));
+            }
+        }
+    }
+
+    // Ask the user for the attributes they lack before going any further
+    let missing = MissingAttributes::load(
+        &site_config.account_requirements,
+        &mut repo,
+        homeserver,
+        &browser_session.user,
+    )
+    .await?;
+    if !missing.is_empty() {
+        repo.save().await?;
+        return Err(GrantCompletionError::RequiresProfile);
+    }
+
     // Run through the policy
+    let requester = Requester::new(clock.now())
+        .with_ip_address(activity_tracker.ip())
+        .with_user_agent(user_agent);
     let res = policy
-        .evaluate_authorization_grant(&grant, client, &browser_session.user)
+        .evaluate_authorization_grant(&grant, client, &browser_session.user, &requester)
         .await?;
 
     if !res.valid() {
@@ -243,27 +365,68 @@ pub(crate) async fn complete(
 
     let current_consent = repo
         .oauth2_client()
-        .get_consent_for_user(client, &browser_session.user)
+        .get_consent_for_user(clock, client, &browser_session.user)
         .await?;
 
+    // Trusted clients don't need consent for the scopes they are trusted with
+    let trusted_scope = repo.oauth2_client().trusted_scope(client).await?;
+
     let lacks_consent = grant
         .scope
         .difference(&current_consent)
         .filter(|scope| Device::from_scope_token(scope).is_none())
-        .any(|_| true);
-
-    // Check if the client lacks consent *or* if consent was explicitly asked
-    if lacks_consent || grant.requires_consent {
+        .any(|scope| {
+            !trusted_scope
+                .as_ref()
+                .is_some_and(|trusted_scope| trusted_scope.contains(scope))
+        });
+
+    // Check if the client lacks consent *or* if consent was explicitly asked.
+    // Consent given to the grant itself is enough, even if it wasn't remembered
+    if (lacks_consent && !grant.consent_given) || grant.requires_consent {
         repo.save().await?;
         return Err(GrantCompletionError::RequiresConsent);
     }
 
+    // Clients which require an approval hold the grant until an administrator
+    // approves it. Only the user who asked for it can then complete it
+    if repo.oauth2_client().requires_approval(client).await? {
+        match &grant.approval {
+            None => {
+                let grant = repo
+                    .oauth2_authorization_grant()
+                    .request_approval(clock, &browser_session.user, grant)
+                    .await?;
+                repo.save().await?;
+                return Err(GrantCompletionError::RequiresApproval(grant));
+            }
+            Some(approval) if approval.user_id != browser_session.user.id => {
+                repo.save().await?;
+                return Err(GrantCompletionError::NotPending);
+            }
+            Some(approval) if !approval.is_approved() => {
+                repo.save().await?;
+                return Err(GrantCompletionError::RequiresApproval(grant));
+            }
+            Some(_) => {}
+        }
+    }
+
     // All good, let's start the session
     let session = repo
         .oauth2_session()
         .add_from_browser_session(rng, clock, client, browser_session, grant.scope.clone())
         .await?;
 
+    // Name the session after the device display name the client asked for
+    let session = if let Some(device_display_name) = grant.device_display_name.clone() {
+        repo.oauth2_session()
+            .set_human_name(session, Some(device_display_name))
+            .await?
+    } else {
+        session
+    };
+
     let grant = repo
         .oauth2_authorization_grant()
         .fulfill(clock, &session, grant)
@@ -274,6 +437,18 @@ pub(crate) async fn complete(
 
     // Did they request an ID token?
     if grant.response_type_id_token {
+        let mapped_claims = mapped_claims(
+            &mut repo,
+            homeserver,
+            &mut policy,
+            &requester,
+            &site_config.claim_mappings,
+            client,
+            &browser_session.user,
+            &session.scope,
+        )
+        .await?;
+
         params.id_token = Some(generate_id_token(
             rng,
             clock,
@@ -284,6 +459,7 @@ pub(crate) async fn complete(
             browser_session,
             None,
             Some(&valid_authentication),
+            mapped_claims,
         )?);
     }
 
@@ -300,3 +476,406 @@ pub(crate) async fn complete(
 
     Ok(params)
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use hyper::{header::LOCATION, Request, StatusCode};
+    use mas_router::{Route, SimpleRoute};
+    use mas_storage::{
+        oauth2::{OAuth2AuthorizationGrantRepository, OAuth2ClientRepository},
+        user::{BrowserSessionRepository, UserPasswordRepository, UserRepository},
+        RepositoryAccess,
+    };
+    use oauth2_types::{
+        registration::ClientRegistrationResponse,
+        requests::ResponseMode,
+        scope::{Scope, EMAIL, OPENID},
+    };
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::test_utils::{init_tracing, CookieHelper, RequestBuilderExt, ResponseExt, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_impersonation_cannot_complete(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let mut rng = state.rng();
+        let cookies = CookieHelper::new();
+
+        // Provision a client
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/callback"],
+                "contacts": ["contact@example.com"],
+                "token_endpoint_auth_method": "none",
+                "response_types": ["code"],
+                "grant_types": ["authorization_code"],
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+
+        let ClientRegistrationResponse { client_id, .. } = response.json();
+
+        // An administrator impersonates a user
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+        let admin = repo
+            .user()
+            .add(&mut rng, &state.clock, "admin".to_owned())
+            .await
+            .unwrap();
+        let browser_session = repo
+            .browser_session()
+            .add_impersonation(
+                &mut rng,
+                &state.clock,
+                &user,
+                &admin,
+                Duration::minutes(30),
+                None,
+            )
+            .await
+            .unwrap();
+
+        let client = repo
+            .oauth2_client()
+            .find_by_client_id(&client_id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let grant = repo
+            .oauth2_authorization_grant()
+            .add(
+                &mut rng,
+                &state.clock,
+                &client,
+                "https://example.com/callback".parse().unwrap(),
+                Scope::from_iter([OPENID]),
+                None,
+                Some("state".to_owned()),
+                None,
+                None,
+                ResponseMode::Query,
+                false,
+                false,
+                None,
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        cookies.set_session(&state, &browser_session).await;
+
+        // The grant can't be completed with the impersonation session
+        let continue_grant = mas_router::ContinueAuthorizationGrant(grant.id);
+        let request = Request::get(continue_grant.path()).empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        assert!(response.body().contains("impersonation_not_allowed"));
+
+        let mut repo = state.repository().await.unwrap();
+        let grant = repo
+            .oauth2_authorization_grant()
+            .lookup(grant.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(grant.stage.is_pending());
+    }
+
+    /// Register a public client redirecting to `https://example.com/callback`
+    async fn register_client(state: &TestState) -> Client {
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/callback"],
+                "contacts": ["contact@example.com"],
+                "token_endpoint_auth_method": "none",
+                "response_types": ["code"],
+                "grant_types": ["authorization_code"],
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+
+        let ClientRegistrationResponse { client_id, .. } = response.json();
+
+        let mut repo = state.repository().await.unwrap();
+        repo.oauth2_client()
+            .find_by_client_id(&client_id)
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    /// Start an authorization grant for the given scope
+    async fn add_grant(state: &TestState, client: &Client, scope: Scope) -> AuthorizationGrant {
+        let mut repo = state.repository().await.unwrap();
+        let grant = repo
+            .oauth2_authorization_grant()
+            .add(
+                &mut state.rng(),
+                &state.clock,
+                client,
+                "https://example.com/callback".parse().unwrap(),
+                scope,
+                None,
+                Some("state".to_owned()),
+                None,
+                None,
+                ResponseMode::Query,
+                false,
+                false,
+                None,
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+        grant
+    }
+
+    /// Create a user who just logged in with their password
+    async fn add_browser_session(state: &TestState, username: &str) -> BrowserSession {
+        let mut rng = state.rng();
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, username.to_owned())
+            .await
+            .unwrap();
+        let password = repo
+            .user_password()
+            .add(&mut rng, &state.clock, &user, 1, "hash".to_owned(), None)
+            .await
+            .unwrap();
+        let browser_session = repo
+            .browser_session()
+            .add(&mut rng, &state.clock, &user, None)
+            .await
+            .unwrap();
+        repo.browser_session()
+            .authenticate_with_password(&mut rng, &state.clock, &browser_session, &password)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+        browser_session
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_trusted_client_consent(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let cookies = CookieHelper::new();
+
+        let client = register_client(&state).await;
+
+        // The client is trusted with the openid scope only
+        let mut repo = state.repository().await.unwrap();
+        repo.oauth2_client()
+            .set_trusted_scope(&client, Some(&Scope::from_iter([OPENID])))
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let browser_session = add_browser_session(&state, "alice").await;
+        cookies.set_session(&state, &browser_session).await;
+
+        // Asking only for trusted scopes skips the consent screen
+        let grant = add_grant(&state, &client, Scope::from_iter([OPENID])).await;
+        let continue_grant = mas_router::ContinueAuthorizationGrant(grant.id);
+        let request = Request::get(continue_grant.path()).empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        response.assert_header_value(LOCATION, "https://example.com/callback?state=state");
+
+        let mut repo = state.repository().await.unwrap();
+        let grant = repo
+            .oauth2_authorization_grant()
+            .lookup(grant.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(grant.stage.is_fulfilled());
+        repo.save().await.unwrap();
+
+        // Asking for a scope the client isn't trusted with still needs consent
+        let grant = add_grant(&state, &client, Scope::from_iter([OPENID, EMAIL])).await;
+        let continue_grant = mas_router::ContinueAuthorizationGrant(grant.id);
+        let request = Request::get(continue_grant.path()).empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let consent = mas_router::Consent(grant.id);
+        response.assert_header_value(LOCATION, &consent.path_and_query());
+
+        let mut repo = state.repository().await.unwrap();
+        let grant = repo
+            .oauth2_authorization_grant()
+            .lookup(grant.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(grant.stage.is_pending());
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_quarantined_user_cannot_complete(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let mut rng = state.rng();
+        let cookies = CookieHelper::new();
+
+        let client = register_client(&state).await;
+
+        // A quarantined user who just logged in with their password
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+        let password = repo
+            .user_password()
+            .add(&mut rng, &state.clock, &user, 1, "hash".to_owned(), None)
+            .await
+            .unwrap();
+        let browser_session = repo
+            .browser_session()
+            .add(&mut rng, &state.clock, &user, None)
+            .await
+            .unwrap();
+        repo.browser_session()
+            .authenticate_with_password(&mut rng, &state.clock, &browser_session, &password)
+            .await
+            .unwrap();
+        repo.oauth2_client()
+            .set_trusted_scope(&client, Some(&Scope::from_iter([OPENID])))
+            .await
+            .unwrap();
+        let user = repo.user().quarantine(&state.clock, user).await.unwrap();
+        repo.save().await.unwrap();
+
+        cookies.set_session(&state, &browser_session).await;
+
+        // The policy denies the grant
+        let grant = add_grant(&state, &client, Scope::from_iter([OPENID])).await;
+        let continue_grant = mas_router::ContinueAuthorizationGrant(grant.id);
+        let request = Request::get(continue_grant.path()).empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let mut repo = state.repository().await.unwrap();
+        let grant = repo
+            .oauth2_authorization_grant()
+            .lookup(grant.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(grant.stage.is_pending());
+
+        // Once the quarantine is lifted, the same grant goes through
+        repo.user().unquarantine(user).await.unwrap();
+        repo.save().await.unwrap();
+
+        let request = Request::get(continue_grant.path()).empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        response.assert_header_value(LOCATION, "https://example.com/callback?state=state");
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_requires_approval(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let cookies = CookieHelper::new();
+        let other_cookies = CookieHelper::new();
+
+        // A trusted client which requires an approval
+        let client = register_client(&state).await;
+        let mut repo = state.repository().await.unwrap();
+        repo.oauth2_client()
+            .set_trusted_scope(&client, Some(&Scope::from_iter([OPENID])))
+            .await
+            .unwrap();
+        repo.oauth2_client()
+            .set_requires_approval(&client, true)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let browser_session = add_browser_session(&state, "alice").await;
+        cookies.set_session(&state, &browser_session).await;
+        let other_session = add_browser_session(&state, "bob").await;
+        other_cookies.set_session(&state, &other_session).await;
+
+        // The grant is parked until an administrator approves it
+        let grant = add_grant(&state, &client, Scope::from_iter([OPENID])).await;
+        let continue_grant = mas_router::ContinueAuthorizationGrant(grant.id);
+        let request = Request::get(continue_grant.path()).empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let mut repo = state.repository().await.unwrap();
+        let grant = repo
+            .oauth2_authorization_grant()
+            .lookup(grant.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(grant.is_awaiting_approval());
+        assert_eq!(
+            grant.approval.as_ref().map(|approval| approval.user_id),
+            Some(browser_session.user.id)
+        );
+        repo.save().await.unwrap();
+
+        // Coming back before the approval shows the same page
+        let request = Request::get(continue_grant.path()).empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let mut repo = state.repository().await.unwrap();
+        let grant = repo
+            .oauth2_authorization_grant()
+            .approve(&state.clock, None, grant)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        // Only the user who asked for the approval can finish the grant
+        let request = Request::get(continue_grant.path()).empty();
+        let request = other_cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+
+        let request = Request::get(continue_grant.path()).empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        response.assert_header_value(LOCATION, "https://example.com/callback?state=state");
+
+        let mut repo = state.repository().await.unwrap();
+        let grant = repo
+            .oauth2_authorization_grant()
+            .lookup(grant.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(grant.stage.is_fulfilled());
+    }
+}
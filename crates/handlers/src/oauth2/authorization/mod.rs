@@ -15,7 +15,9 @@
 use axum::{
     extract::{Form, State},
     response::{Html, IntoResponse, Response},
+    TypedHeader,
 };
+use headers::UserAgent;
 use hyper::StatusCode;
 use mas_axum_utils::{cookies::CookieJar, csrf::CsrfExt, sentry::SentryEventID, SessionInfoExt};
 use mas_data_model::{AuthorizationCode, Pkce};
@@ -26,7 +28,10 @@ use mas_storage::{
     oauth2::{OAuth2AuthorizationGrantRepository, OAuth2ClientRepository},
     BoxClock, BoxRepository, BoxRng,
 };
-use mas_templates::{PolicyViolationContext, TemplateContext, Templates};
+use mas_templates::{
+    error_codes, ApprovalPendingContext, ErrorContext, PolicyViolationContext, TemplateContext,
+    Templates,
+};
 use oauth2_types::{
     errors::{ClientError, ClientErrorCode},
     pkce,
@@ -39,7 +44,10 @@ use thiserror::Error;
 use tracing::warn;
 
 use self::{callback::CallbackDestination, complete::GrantCompletionError};
-use crate::{impl_from_error_for_route, BoundActivityTracker, PreferredLanguage};
+use crate::{
+    impl_from_error_for_route, BoundActivityTracker, PreferredLanguage, SharedHomeserverConnection,
+    SiteConfig,
+};
 
 mod callback;
 pub mod complete;
@@ -103,6 +111,11 @@ pub(crate) struct Params {
 
     #[serde(flatten)]
     pkce: Option<pkce::AuthorizationRequest>,
+
+    /// A human-readable name for the device the client asks for with the
+    /// device scope
+    #[serde(default)]
+    device_display_name: Option<String>,
 }
 
 /// Given a list of response types and an optional user-defined response mode,
@@ -143,12 +156,17 @@ pub(crate) async fn get(
     State(templates): State<Templates>,
     State(key_store): State<Keystore>,
     State(url_builder): State<UrlBuilder>,
+    State(site_config): State<SiteConfig>,
+    State(homeserver): State<SharedHomeserverConnection>,
     policy: Policy,
     activity_tracker: BoundActivityTracker,
+    user_agent: Option<TypedHeader<UserAgent>>,
     mut repo: BoxRepository,
     cookie_jar: CookieJar,
     Form(params): Form<Params>,
 ) -> Result<Response, RouteError> {
+    let user_agent = user_agent.map(|ua| ua.as_str().to_owned());
+
     // First, figure out what client it is
     let client = repo
         .oauth2_client()
@@ -157,11 +175,18 @@ pub(crate) async fn get(
         .ok_or(RouteError::ClientNotFound)?;
 
     // And resolve the redirect_uri and response_mode
+    let redirect_uri_matching = repo.oauth2_client().redirect_uri_matching(&client).await?;
     let redirect_uri = client
-        .resolve_redirect_uri(&params.auth.redirect_uri)?
+        .resolve_redirect_uri(&params.auth.redirect_uri, redirect_uri_matching)?
         .clone();
     let response_type = params.auth.response_type;
     let response_mode = resolve_response_mode(&response_type, params.auth.response_mode)?;
+    if !site_config
+        .oauth2_features
+        .response_mode_enabled(&response_mode)
+    {
+        return Err(RouteError::InvalidResponseMode);
+    }
 
     // Now we have a proper callback destination to go to on error
     let callback_destination = CallbackDestination::try_new(
@@ -179,7 +204,7 @@ pub(crate) async fn get(
         let templates = templates.clone();
         let callback_destination = callback_destination.clone();
         async move {
-            let maybe_session = session_info.load_session(&mut repo).await?;
+            let maybe_session = session_info.load_session(&clock, &mut repo).await?;
             let prompt = params.auth.prompt.as_deref().unwrap_or_default();
 
             // Check if the request/request_uri/registration params are used. If so, reply
@@ -233,6 +258,20 @@ pub(crate) async fn get(
                     .await?);
             }
 
+            // Clients can't request scopes which are not in the registry
+            if site_config
+                .scope_registry
+                .find_undefined(&params.auth.scope)
+                .is_some()
+            {
+                return Ok(callback_destination
+                    .go(
+                        &templates,
+                        ClientError::from(ClientErrorCode::InvalidScope),
+                    )
+                    .await?);
+            }
+
             // Fail early if prompt=none and there is no active session
             if prompt.contains(&Prompt::None) && maybe_session.is_none() {
                 return Ok(callback_destination
@@ -244,6 +283,19 @@ pub(crate) async fn get(
             }
 
             let code: Option<AuthorizationCode> = if response_type.has_code() {
+                // The authorization code grant may be disabled entirely
+                if !site_config
+                    .oauth2_features
+                    .grant_type_enabled(&GrantType::AuthorizationCode)
+                {
+                    return Ok(callback_destination
+                        .go(
+                            &templates,
+                            ClientError::from(ClientErrorCode::UnsupportedResponseType),
+                        )
+                        .await?);
+                }
+
                 // Check if it is allowed to use this grant type
                 if !client.grant_types.contains(&GrantType::AuthorizationCode) {
                     return Ok(callback_destination
@@ -283,6 +335,9 @@ pub(crate) async fn get(
             };
 
             let requires_consent = prompt.contains(&Prompt::Consent);
+            let device_display_name = params
+                .device_display_name
+                .filter(|name| !name.trim().is_empty());
 
             let grant = repo
                 .oauth2_authorization_grant()
@@ -299,6 +354,7 @@ pub(crate) async fn get(
                     response_mode,
                     response_type.has_id_token(),
                     requires_consent,
+                    device_display_name,
                 )
                 .await?;
             let continue_grant = PostAuthAction::continue_grant(grant.id);
@@ -347,10 +403,13 @@ pub(crate) async fn get(
                         &mut rng,
                         &clock,
                         &activity_tracker,
+                        user_agent,
                         repo,
                         key_store,
                         policy,
                         &url_builder,
+                        &site_config,
+                        homeserver.as_ref(),
                         grant,
                         &client,
                         &user_session,
@@ -366,7 +425,12 @@ pub(crate) async fn get(
                                 )
                                 .await?
                         }
-                        Err(GrantCompletionError::RequiresReauth) => {
+                        Err(
+                            GrantCompletionError::RequiresReauth
+                            | GrantCompletionError::RequiresUpstreamRevalidation(_)
+                            | GrantCompletionError::RequiresProfile
+                            | GrantCompletionError::RequiresApproval(_),
+                        ) => {
                             callback_destination
                                 .go(
                                     &templates,
@@ -374,7 +438,10 @@ pub(crate) async fn get(
                                 )
                                 .await?
                         }
-                        Err(GrantCompletionError::PolicyViolation(_grant, _res)) => {
+                        Err(
+                            GrantCompletionError::PolicyViolation(_, _)
+                            | GrantCompletionError::Impersonation,
+                        ) => {
                             callback_destination
                                 .go(&templates, ClientError::from(ClientErrorCode::AccessDenied))
                                 .await?
@@ -397,10 +464,13 @@ pub(crate) async fn get(
                         &mut rng,
                         &clock,
                         &activity_tracker,
+                        user_agent,
                         repo,
                         key_store,
                         policy,
                         &url_builder,
+                        &site_config,
+                        homeserver.as_ref(),
                         grant,
                         &client,
                         &user_session,
@@ -415,6 +485,7 @@ pub(crate) async fn get(
                             warn!(violation = ?res, "Authorization grant for client {} denied by policy", client.id);
 
                             let ctx = PolicyViolationContext::new(grant, client)
+                                .with_client_restricted(res.has_code("client-restricted"))
                                 .with_session(user_session)
                                 .with_csrf(csrf_token.form_value())
                                 .with_language(locale);
@@ -422,10 +493,42 @@ pub(crate) async fn get(
                             let content = templates.render_policy_violation(&ctx)?;
                             Html(content).into_response()
                         }
+                        Err(GrantCompletionError::RequiresApproval(grant)) => {
+                            let ctx = ApprovalPendingContext::new(grant, client)
+                                .with_session(user_session)
+                                .with_csrf(csrf_token.form_value())
+                                .with_language(locale);
+
+                            let content = templates.render_approval_pending(&ctx)?;
+                            Html(content).into_response()
+                        }
+                        Err(GrantCompletionError::Impersonation) => {
+                            let ctx = ErrorContext::new()
+                                .with_code(error_codes::IMPERSONATION_NOT_ALLOWED)
+                                .with_description(
+                                    "Can't sign in to clients while impersonating a user."
+                                        .to_owned(),
+                                )
+                                .with_language(&locale);
+
+                            let content = templates.render_error_page(&ctx)?;
+                            Html(content).into_response()
+                        }
                         Err(GrantCompletionError::RequiresReauth) => {
                             url_builder.redirect(&mas_router::Reauth::and_then(continue_grant))
                                 .into_response()
                         }
+                        Err(GrantCompletionError::RequiresUpstreamRevalidation(provider_id)) => {
+                            url_builder
+                                .redirect(
+                                    &mas_router::UpstreamOAuth2Revalidate::new(provider_id)
+                                        .and_then(continue_grant),
+                                )
+                                .into_response()
+                        }
+                        Err(GrantCompletionError::RequiresProfile) => url_builder
+                            .redirect(&mas_router::CompleteProfile::and_then(continue_grant))
+                            .into_response(),
                         Err(GrantCompletionError::Internal(e)) => {
                             return Err(RouteError::Internal(e))
                         }
@@ -454,3 +557,266 @@ pub(crate) async fn get(
 
     Ok((cookie_jar, response).into_response())
 }
+
+#[cfg(test)]
+mod tests {
+    use hyper::{header::LOCATION, Request};
+    use mas_data_model::RedirectUriMatching;
+    use mas_iana::oauth::OAuthClientAuthenticationMethod;
+    use mas_router::SimpleRoute;
+    use oauth2_types::registration::ClientRegistrationResponse;
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::test_utils::{init_tracing, RequestBuilderExt, ResponseExt, TestState};
+
+    /// Register a public client redirecting to `https://example.com/callback`,
+    /// and return its client ID
+    async fn register_client(state: &TestState) -> String {
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/callback"],
+                "contacts": ["contact@example.com"],
+                "token_endpoint_auth_method": "none",
+                "response_types": ["code"],
+                "grant_types": ["authorization_code"],
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+
+        let ClientRegistrationResponse { client_id, .. } = response.json();
+        client_id
+    }
+
+    /// Add a public client with the given redirect URIs and matching policy,
+    /// and return its client ID
+    async fn add_client(
+        state: &TestState,
+        redirect_uris: &[&str],
+        matching: RedirectUriMatching,
+    ) -> String {
+        let mut repo = state.repository().await.unwrap();
+        let client = repo
+            .oauth2_client()
+            .add(
+                &mut state.rng(),
+                &state.clock,
+                redirect_uris
+                    .iter()
+                    .map(|uri| uri.parse().unwrap())
+                    .collect(),
+                None,
+                None,
+                vec![GrantType::AuthorizationCode],
+                Vec::new(),
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                Some(OAuthClientAuthenticationMethod::None),
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        repo.oauth2_client()
+            .set_redirect_uri_matching(&client, matching)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        client.client_id
+    }
+
+    /// Build a request to the authorization endpoint, asking for a code
+    fn authorization_request(client_id: &str, response_mode: &str) -> Request<String> {
+        authorization_request_to(client_id, "https://example.com/callback", response_mode)
+    }
+
+    /// Build a request to the authorization endpoint, asking for a code to be
+    /// sent to the given redirect URI
+    fn authorization_request_to(
+        client_id: &str,
+        redirect_uri: &str,
+        response_mode: &str,
+    ) -> Request<String> {
+        let query = url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("client_id", client_id)
+            .append_pair("redirect_uri", redirect_uri)
+            .append_pair("response_type", "code")
+            .append_pair("response_mode", response_mode)
+            .append_pair("scope", "openid")
+            .append_pair("state", "state")
+            .finish();
+
+        Request::get(format!(
+            "{}?{query}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH
+        ))
+        .empty()
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_disabled_response_mode(pool: PgPool) {
+        init_tracing();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let client_id = register_client(&state).await;
+
+        // Enabled response modes go on with a login
+        let response = state
+            .request(authorization_request(&client_id, "form_post"))
+            .await;
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        state
+            .site_config
+            .oauth2_features
+            .response_modes
+            .retain(|mode| *mode != ResponseMode::FormPost);
+
+        // Disabled ones are rejected before anything is sent to the client
+        let response = state
+            .request(authorization_request(&client_id, "form_post"))
+            .await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        assert!(response.headers().get(LOCATION).is_none());
+
+        let response = state
+            .request(authorization_request(&client_id, "query"))
+            .await;
+        response.assert_status(StatusCode::SEE_OTHER);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_disabled_authorization_code_grant(pool: PgPool) {
+        init_tracing();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let client_id = register_client(&state).await;
+
+        state
+            .site_config
+            .oauth2_features
+            .grant_types
+            .retain(|grant_type| *grant_type != GrantType::AuthorizationCode);
+
+        // The client is sent back with an error
+        let response = state
+            .request(authorization_request(&client_id, "query"))
+            .await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let location = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+        assert!(location.starts_with("https://example.com/callback?"));
+        assert!(location.contains("error=unsupported_response_type"));
+    }
+
+    /// Send an authorization request to the given redirect URI, and check
+    /// whether it was accepted
+    async fn is_redirect_uri_allowed(
+        state: &TestState,
+        client_id: &str,
+        redirect_uri: &str,
+    ) -> bool {
+        let response = state
+            .request(authorization_request_to(client_id, redirect_uri, "query"))
+            .await;
+
+        // Accepted requests go on with a login, the others are rejected
+        // without sending anything to the client
+        match response.status() {
+            StatusCode::SEE_OTHER => true,
+            StatusCode::BAD_REQUEST => {
+                assert!(response.headers().get(LOCATION).is_none());
+                false
+            }
+            status => panic!("unexpected status {status}"),
+        }
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_exact_redirect_uri_matching(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let client_id = add_client(
+            &state,
+            &[
+                "https://example.com/callback",
+                "http://localhost:1234/callback",
+            ],
+            RedirectUriMatching::Exact,
+        )
+        .await;
+
+        assert!(is_redirect_uri_allowed(&state, &client_id, "https://example.com/callback").await);
+        assert!(
+            is_redirect_uri_allowed(&state, &client_id, "http://localhost:1234/callback").await
+        );
+        assert!(
+            !is_redirect_uri_allowed(&state, &client_id, "https://example.com/callback/other")
+                .await
+        );
+
+        // Loopback redirect URIs have to use the registered port
+        assert!(
+            !is_redirect_uri_allowed(&state, &client_id, "http://localhost:5678/callback").await
+        );
+
+        // ...unless loopback redirect URIs are allowed on any port
+        let client_id = add_client(
+            &state,
+            &["http://localhost:1234/callback"],
+            RedirectUriMatching::Loopback,
+        )
+        .await;
+        assert!(
+            is_redirect_uri_allowed(&state, &client_id, "http://localhost:5678/callback").await
+        );
+        assert!(!is_redirect_uri_allowed(&state, &client_id, "http://localhost:5678/other").await);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_wildcard_redirect_uri_matching(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let client_id = add_client(
+            &state,
+            &["https://*.example.com/callback"],
+            RedirectUriMatching::SubdomainWildcard,
+        )
+        .await;
+
+        // The wildcard matches a single label
+        assert!(
+            is_redirect_uri_allowed(&state, &client_id, "https://app.example.com/callback").await
+        );
+        assert!(
+            !is_redirect_uri_allowed(&state, &client_id, "https://a.b.example.com/callback").await
+        );
+        assert!(!is_redirect_uri_allowed(&state, &client_id, "https://example.com/callback").await);
+        assert!(
+            !is_redirect_uri_allowed(&state, &client_id, "https://app.example.com/other").await
+        );
+
+        // Without the wildcard policy, the registered URI is only a pattern
+        // which never matches
+        let client_id = add_client(
+            &state,
+            &["https://*.example.com/callback"],
+            RedirectUriMatching::Loopback,
+        )
+        .await;
+        assert!(
+            !is_redirect_uri_allowed(&state, &client_id, "https://app.example.com/callback").await
+        );
+        assert!(
+            !is_redirect_uri_allowed(&state, &client_id, "https://*.example.com/callback").await
+        );
+    }
+}
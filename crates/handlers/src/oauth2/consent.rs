@@ -15,26 +15,31 @@
 use axum::{
     extract::{Form, Path, State},
     response::{Html, IntoResponse, Response},
+    TypedHeader,
 };
+use headers::UserAgent;
 use hyper::StatusCode;
 use mas_axum_utils::{
     cookies::CookieJar,
     csrf::{CsrfExt, ProtectedForm},
     sentry::SentryEventID,
-    SessionInfoExt,
+    FancyError, SessionInfoExt,
 };
-use mas_data_model::{AuthorizationGrantStage, Device};
-use mas_policy::Policy;
+use mas_data_model::{AuthorizationGrantStage, Client, Device};
+use mas_policy::{Policy, Requester};
 use mas_router::{PostAuthAction, UrlBuilder};
 use mas_storage::{
     oauth2::{OAuth2AuthorizationGrantRepository, OAuth2ClientRepository},
-    BoxClock, BoxRepository, BoxRng,
+    BoxClock, BoxRepository, BoxRng, Clock,
 };
-use mas_templates::{ConsentContext, PolicyViolationContext, TemplateContext, Templates};
+use mas_templates::{
+    error_codes, ConsentContext, ErrorContext, PolicyViolationContext, TemplateContext, Templates,
+};
+use serde::Deserialize;
 use thiserror::Error;
 use ulid::Ulid;
 
-use crate::{impl_from_error_for_route, BoundActivityTracker, PreferredLanguage};
+use crate::{impl_from_error_for_route, BoundActivityTracker, PreferredLanguage, SiteConfig};
 
 #[derive(Debug, Error)]
 pub enum RouteError {
@@ -48,7 +53,7 @@ pub enum RouteError {
     GrantNotFound,
 
     #[error("Authorization grant already used")]
-    GrantNotPending,
+    GrantNotPending(Box<Client>),
 
     #[error("Policy violation")]
     PolicyViolation,
@@ -57,6 +62,12 @@ pub enum RouteError {
     NoSuchClient,
 }
 
+#[derive(Debug, Deserialize)]
+pub(crate) struct ConsentForm {
+    #[serde(default)]
+    remember: Option<String>,
+}
+
 impl_from_error_for_route!(mas_templates::TemplateError);
 impl_from_error_for_route!(mas_storage::RepositoryError);
 impl_from_error_for_route!(mas_policy::LoadError);
@@ -65,11 +76,18 @@ impl_from_error_for_route!(mas_policy::EvaluationError);
 impl IntoResponse for RouteError {
     fn into_response(self) -> axum::response::Response {
         let event_id = sentry::capture_error(&self);
-        (
-            SentryEventID::from(event_id),
-            StatusCode::INTERNAL_SERVER_ERROR,
-        )
-            .into_response()
+        let response = match self {
+            Self::GrantNotPending(client) => {
+                let ctx = ErrorContext::new()
+                    .with_code(error_codes::GRANT_NOT_PENDING)
+                    .with_description("The authorization request was already completed".to_owned())
+                    .with_client(*client);
+                FancyError::new(ctx).into_response()
+            }
+            _ => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        };
+
+        (SentryEventID::from(event_id), response).into_response()
     }
 }
 
@@ -85,15 +103,17 @@ pub(crate) async fn get(
     PreferredLanguage(locale): PreferredLanguage,
     State(templates): State<Templates>,
     State(url_builder): State<UrlBuilder>,
+    State(site_config): State<SiteConfig>,
     mut policy: Policy,
     mut repo: BoxRepository,
     activity_tracker: BoundActivityTracker,
+    user_agent: Option<TypedHeader<UserAgent>>,
     cookie_jar: CookieJar,
     Path(grant_id): Path<Ulid>,
 ) -> Result<Response, RouteError> {
     let (session_info, cookie_jar) = cookie_jar.session_info();
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info.load_session(&clock, &mut repo).await?;
 
     let grant = repo
         .oauth2_authorization_grant()
@@ -108,7 +128,7 @@ pub(crate) async fn get(
         .ok_or(RouteError::NoSuchClient)?;
 
     if !matches!(grant.stage, AuthorizationGrantStage::Pending) {
-        return Err(RouteError::GrantNotPending);
+        return Err(RouteError::GrantNotPending(Box::new(client)));
     }
 
     if let Some(session) = maybe_session {
@@ -118,12 +138,39 @@ pub(crate) async fn get(
 
         let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
 
+        let requester = Requester::new(clock.now())
+            .with_ip_address(activity_tracker.ip())
+            .with_user_agent(user_agent.map(|ua| ua.as_str().to_owned()));
         let res = policy
-            .evaluate_authorization_grant(&grant, &client, &session.user)
+            .evaluate_authorization_grant(&grant, &client, &session.user, &requester)
             .await?;
 
         if res.valid() {
-            let ctx = ConsentContext::new(grant, client)
+            let scope_definitions = grant
+                .scope
+                .iter()
+                .filter_map(|token| site_config.scope_registry.get(token.as_str()))
+                .cloned()
+                .collect::<Vec<_>>();
+
+            let granted_scope = repo
+                .oauth2_client()
+                .get_consent_for_user(&clock, &client, &session.user)
+                .await?;
+
+            // The scopes a trusted client is trusted with aren't shown as new
+            let granted_scope = match repo.oauth2_client().trusted_scope(&client).await? {
+                Some(trusted_scope) => granted_scope
+                    .iter()
+                    .chain(trusted_scope.iter())
+                    .cloned()
+                    .collect(),
+                None => granted_scope,
+            };
+
+            let ctx = ConsentContext::new(grant, client, site_config.consent_ttl)
+                .with_scope_definitions(scope_definitions)
+                .with_granted_scope(&granted_scope)
                 .with_session(session)
                 .with_csrf(csrf_token.form_value())
                 .with_language(locale);
@@ -133,6 +180,7 @@ pub(crate) async fn get(
             Ok((cookie_jar, Html(content)).into_response())
         } else {
             let ctx = PolicyViolationContext::new(grant, client)
+                .with_client_restricted(res.has_code("client-restricted"))
                 .with_session(session)
                 .with_csrf(csrf_token.form_value())
                 .with_language(locale);
@@ -159,16 +207,18 @@ pub(crate) async fn post(
     mut policy: Policy,
     mut repo: BoxRepository,
     activity_tracker: BoundActivityTracker,
+    user_agent: Option<TypedHeader<UserAgent>>,
     cookie_jar: CookieJar,
     State(url_builder): State<UrlBuilder>,
+    State(site_config): State<SiteConfig>,
     Path(grant_id): Path<Ulid>,
-    Form(form): Form<ProtectedForm<()>>,
+    Form(form): Form<ProtectedForm<ConsentForm>>,
 ) -> Result<Response, RouteError> {
-    cookie_jar.verify_form(&clock, form)?;
+    let form = cookie_jar.verify_form(&clock, form)?;
 
     let (session_info, cookie_jar) = cookie_jar.session_info();
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info.load_session(&clock, &mut repo).await?;
 
     let grant = repo
         .oauth2_authorization_grant()
@@ -182,6 +232,11 @@ pub(crate) async fn post(
         return Ok((cookie_jar, url_builder.redirect(&login)).into_response());
     };
 
+    // Administrators impersonating a user can't consent on their behalf
+    if session.is_impersonation() {
+        return Ok((cookie_jar, StatusCode::FORBIDDEN).into_response());
+    }
+
     activity_tracker
         .record_browser_session(&clock, &session)
         .await;
@@ -192,8 +247,11 @@ pub(crate) async fn post(
         .await?
         .ok_or(RouteError::NoSuchClient)?;
 
+    let requester = Requester::new(clock.now())
+        .with_ip_address(activity_tracker.ip())
+        .with_user_agent(user_agent.map(|ua| ua.as_str().to_owned()));
     let res = policy
-        .evaluate_authorization_grant(&grant, &client, &session.user)
+        .evaluate_authorization_grant(&grant, &client, &session.user, &requester)
         .await?;
 
     if !res.valid() {
@@ -208,15 +266,20 @@ pub(crate) async fn post(
         .cloned()
         .collect();
 
-    repo.oauth2_client()
-        .give_consent_for_user(
-            &mut rng,
-            &clock,
-            &client,
-            &session.user,
-            &scope_without_device,
-        )
-        .await?;
+    // Only remember the consent if the user asked for it, in which case the
+    // next grants of the client with the same scopes skip the consent screen
+    if form.remember.is_some() {
+        repo.oauth2_client()
+            .give_consent_for_user(
+                &mut rng,
+                &clock,
+                &client,
+                &session.user,
+                &scope_without_device,
+                Some(clock.now() + site_config.consent_ttl),
+            )
+            .await?;
+    }
 
     repo.oauth2_authorization_grant()
         .give_consent(grant)
@@ -226,3 +289,205 @@ pub(crate) async fn post(
 
     Ok((cookie_jar, next.go_next(&url_builder)).into_response())
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use hyper::{header::LOCATION, Request};
+    use mas_data_model::AuthorizationGrant;
+    use mas_router::{Route, SimpleRoute};
+    use mas_storage::{
+        user::{BrowserSessionRepository, UserPasswordRepository, UserRepository},
+        RepositoryAccess,
+    };
+    use oauth2_types::{
+        registration::ClientRegistrationResponse,
+        requests::ResponseMode,
+        scope::{Scope, EMAIL, OPENID},
+    };
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::test_utils::{init_tracing, CookieHelper, RequestBuilderExt, ResponseExt, TestState};
+
+    /// Register a public client redirecting to `https://example.com/callback`
+    async fn register_client(state: &TestState) -> Client {
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/callback"],
+                "contacts": ["contact@example.com"],
+                "token_endpoint_auth_method": "none",
+                "response_types": ["code"],
+                "grant_types": ["authorization_code"],
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+
+        let ClientRegistrationResponse { client_id, .. } = response.json();
+
+        let mut repo = state.repository().await.unwrap();
+        repo.oauth2_client()
+            .find_by_client_id(&client_id)
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    /// Start an authorization grant for the given scope
+    async fn add_grant(state: &TestState, client: &Client, scope: Scope) -> AuthorizationGrant {
+        let mut repo = state.repository().await.unwrap();
+        let grant = repo
+            .oauth2_authorization_grant()
+            .add(
+                &mut state.rng(),
+                &state.clock,
+                client,
+                "https://example.com/callback".parse().unwrap(),
+                scope,
+                None,
+                Some("state".to_owned()),
+                None,
+                None,
+                ResponseMode::Query,
+                false,
+                false,
+                None,
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+        grant
+    }
+
+    /// Continue a grant, returning where the user was sent
+    async fn continue_grant(
+        state: &TestState,
+        cookies: &CookieHelper,
+        grant: &AuthorizationGrant,
+    ) -> String {
+        let continue_grant = mas_router::ContinueAuthorizationGrant(grant.id);
+        let request = Request::get(continue_grant.path()).empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        response.headers()[LOCATION].to_str().unwrap().to_owned()
+    }
+
+    /// Render the consent page of a grant and submit it
+    async fn consent(
+        state: &TestState,
+        cookies: &CookieHelper,
+        grant: &AuthorizationGrant,
+        remember: bool,
+    ) -> String {
+        let consent = mas_router::Consent(grant.id);
+        let request = Request::get(consent.path()).empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        let body = response.body().to_owned();
+        let csrf_token = body
+            .split("name=\"csrf\" value=\"")
+            .nth(1)
+            .unwrap()
+            .split('\"')
+            .next()
+            .unwrap();
+
+        let form = if remember {
+            serde_json::json!({ "csrf": csrf_token, "remember": "true" })
+        } else {
+            serde_json::json!({ "csrf": csrf_token })
+        };
+        let request = Request::post(consent.path()).form(form);
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let continue_grant = mas_router::ContinueAuthorizationGrant(grant.id);
+        response.assert_header_value(LOCATION, &continue_grant.path_and_query());
+
+        body
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_remembered_consent(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let mut rng = state.rng();
+        let cookies = CookieHelper::new();
+        let callback = "https://example.com/callback?state=state";
+
+        let client = register_client(&state).await;
+
+        // A user who just logged in with their password
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+        let password = repo
+            .user_password()
+            .add(&mut rng, &state.clock, &user, 1, "hash".to_owned(), None)
+            .await
+            .unwrap();
+        let browser_session = repo
+            .browser_session()
+            .add(&mut rng, &state.clock, &user, None)
+            .await
+            .unwrap();
+        repo.browser_session()
+            .authenticate_with_password(&mut rng, &state.clock, &browser_session, &password)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        cookies.set_session(&state, &browser_session).await;
+
+        // Consent given without remembering it is only good for that grant
+        let grant = add_grant(&state, &client, Scope::from_iter([OPENID])).await;
+        let consent_path = mas_router::Consent(grant.id).path_and_query();
+        assert_eq!(continue_grant(&state, &cookies, &grant).await, consent_path);
+        consent(&state, &cookies, &grant, false).await;
+        assert_eq!(continue_grant(&state, &cookies, &grant).await, callback);
+
+        let grant = add_grant(&state, &client, Scope::from_iter([OPENID])).await;
+        let consent_path = mas_router::Consent(grant.id).path_and_query();
+        assert_eq!(continue_grant(&state, &cookies, &grant).await, consent_path);
+
+        // Remembering it skips the consent screen for the next grants
+        consent(&state, &cookies, &grant, true).await;
+        assert_eq!(continue_grant(&state, &cookies, &grant).await, callback);
+
+        let grant = add_grant(&state, &client, Scope::from_iter([OPENID])).await;
+        assert_eq!(continue_grant(&state, &cookies, &grant).await, callback);
+
+        // Asking for more scopes only asks consent for the new ones
+        let grant = add_grant(&state, &client, Scope::from_iter([OPENID, EMAIL])).await;
+        let consent_path = mas_router::Consent(grant.id).path_and_query();
+        assert_eq!(continue_grant(&state, &cookies, &grant).await, consent_path);
+        let body = consent(&state, &cookies, &grant, true).await;
+        assert!(body.contains("wants more access to your account"));
+        assert_eq!(continue_grant(&state, &cookies, &grant).await, callback);
+
+        let mut repo = state.repository().await.unwrap();
+        let granted_scope = repo
+            .oauth2_client()
+            .get_consent_for_user(&state.clock, &client, &user)
+            .await
+            .unwrap();
+        assert_eq!(granted_scope, Scope::from_iter([OPENID, EMAIL]));
+        repo.cancel().await.unwrap();
+
+        // Once the consent expired, it is asked again
+        state
+            .clock
+            .advance(state.site_config.consent_ttl + Duration::days(1));
+        let grant = add_grant(&state, &client, Scope::from_iter([OPENID])).await;
+        let consent_path = mas_router::Consent(grant.id).path_and_query();
+        assert_eq!(continue_grant(&state, &cookies, &grant).await, consent_path);
+    }
+}
@@ -12,7 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use axum::{extract::State, response::IntoResponse, Json};
+use axum::{extract::State, response::IntoResponse, TypedHeader};
+use headers::{IfModifiedSince, IfNoneMatch};
 use mas_iana::oauth::{
     OAuthAuthorizationEndpointResponseType, OAuthClientAuthenticationMethod,
     PkceCodeChallengeMethod,
@@ -20,13 +21,16 @@ use mas_iana::oauth::{
 use mas_jose::jwa::SUPPORTED_SIGNING_ALGORITHMS;
 use mas_keystore::Keystore;
 use mas_router::UrlBuilder;
+use mas_storage::BoxClock;
 use oauth2_types::{
     oidc::{ClaimType, ProviderMetadata, SubjectType},
-    requests::{Display, GrantType, Prompt, ResponseMode},
+    requests::{Display, Prompt},
     scope,
 };
 use serde::Serialize;
 
+use crate::{conditional::conditional_json, SiteConfig};
+
 #[derive(Debug, Serialize)]
 struct DiscoveryResponse {
     #[serde(flatten)]
@@ -34,13 +38,33 @@ struct DiscoveryResponse {
 
     #[serde(rename = "org.matrix.matrix-authentication-service.graphql_endpoint")]
     graphql_endpoint: url::Url,
+
+    /// Where clients can send users to manage their account, as defined by
+    /// MSC2965
+    account_management_uri: url::Url,
+
+    /// The `action` parameters understood by the account management URI
+    account_management_actions_supported: Vec<&'static str>,
 }
 
+/// The account management actions supported by the `/account/` route
+const ACCOUNT_MANAGEMENT_ACTIONS_SUPPORTED: [&str; 5] = [
+    "profile",
+    "sessions_list",
+    "session_view",
+    "session_end",
+    "org.matrix.cross_signing_reset",
+];
+
 #[tracing::instrument(name = "handlers.oauth2.discovery.get", skip_all)]
 #[allow(clippy::too_many_lines)]
 pub(crate) async fn get(
+    clock: BoxClock,
     State(key_store): State<Keystore>,
     State(url_builder): State<UrlBuilder>,
+    State(site_config): State<SiteConfig>,
+    if_none_match: Option<TypedHeader<IfNoneMatch>>,
+    if_modified_since: Option<TypedHeader<IfModifiedSince>>,
 ) -> impl IntoResponse {
     // This is how clients can authenticate
     let client_auth_methods_supported = Some(vec![
@@ -65,7 +89,10 @@ pub(crate) async fn get(
     let introspection_endpoint = Some(url_builder.oauth_introspection_endpoint());
     let revocation_endpoint = Some(url_builder.oauth_revocation_endpoint());
     let userinfo_endpoint = Some(url_builder.oidc_userinfo_endpoint());
-    let registration_endpoint = Some(url_builder.oauth_registration_endpoint());
+    let registration_endpoint = site_config
+        .oauth2_features
+        .dynamic_registration
+        .then(|| url_builder.oauth_registration_endpoint());
 
     let scopes_supported = Some(vec![scope::OPENID.to_string(), scope::EMAIL.to_string()]);
 
@@ -75,17 +102,9 @@ pub(crate) async fn get(
         OAuthAuthorizationEndpointResponseType::CodeIdToken.into(),
     ]);
 
-    let response_modes_supported = Some(vec![
-        ResponseMode::FormPost,
-        ResponseMode::Query,
-        ResponseMode::Fragment,
-    ]);
-
-    let grant_types_supported = Some(vec![
-        GrantType::AuthorizationCode,
-        GrantType::RefreshToken,
-        GrantType::ClientCredentials,
-    ]);
+    // Only advertise the features enabled on the server
+    let response_modes_supported = Some(site_config.oauth2_features.response_modes.clone());
+    let grant_types_supported = Some(site_config.oauth2_features.grant_types.clone());
 
     let token_endpoint_auth_methods_supported = client_auth_methods_supported.clone();
     let token_endpoint_auth_signing_alg_values_supported =
@@ -165,15 +184,22 @@ pub(crate) async fn get(
         ..ProviderMetadata::default()
     };
 
-    Json(DiscoveryResponse {
+    let document = DiscoveryResponse {
         standard,
         graphql_endpoint: url_builder.graphql_endpoint(),
-    })
+        account_management_uri: url_builder.account_management_uri(),
+        account_management_actions_supported: ACCOUNT_MANAGEMENT_ACTIONS_SUPPORTED.to_vec(),
+    };
+
+    conditional_json(&clock, &document, if_none_match, if_modified_since)
 }
 
 #[cfg(test)]
 mod tests {
-    use hyper::{Request, StatusCode};
+    use hyper::{
+        header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+        Request, StatusCode,
+    };
     use oauth2_types::oidc::ProviderMetadata;
     use sqlx::PgPool;
 
@@ -193,4 +219,58 @@ mod tests {
             .validate(state.url_builder.oidc_issuer().as_str())
             .expect("Invalid metadata");
     }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_account_management_metadata(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        let request = Request::get("/.well-known/openid-configuration").empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let metadata: serde_json::Value = response.json();
+        assert_eq!(
+            metadata["account_management_uri"],
+            state.url_builder.account_management_uri().as_str()
+        );
+        let actions = metadata["account_management_actions_supported"]
+            .as_array()
+            .unwrap();
+        assert!(actions.contains(&"session_end".into()));
+        assert!(actions.contains(&"org.matrix.cross_signing_reset".into()));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_conditional_discovery(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        let request = Request::get("/.well-known/openid-configuration").empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let etag = response.headers().get(ETAG).unwrap().clone();
+        let last_modified = response.headers().get(LAST_MODIFIED).unwrap().clone();
+
+        // The client already has the document
+        let request = Request::get("/.well-known/openid-configuration")
+            .header(IF_NONE_MATCH, etag.clone())
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers().get(ETAG), Some(&etag));
+
+        let request = Request::get("/.well-known/openid-configuration")
+            .header(IF_MODIFIED_SINCE, last_modified)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::NOT_MODIFIED);
+
+        // The client has an outdated copy
+        let request = Request::get("/.well-known/openid-configuration")
+            .header(IF_NONE_MATCH, "\"outdated\"")
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+    }
 }
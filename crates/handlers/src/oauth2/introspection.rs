@@ -19,23 +19,29 @@ use mas_axum_utils::{
     http_client_factory::HttpClientFactory,
     sentry::SentryEventID,
 };
-use mas_data_model::{TokenFormatError, TokenType};
+use mas_data_model::{Device, TokenFormatError, TokenType};
 use mas_iana::oauth::{OAuthClientAuthenticationMethod, OAuthTokenTypeHint};
 use mas_keystore::Encrypter;
 use mas_storage::{
     compat::{CompatAccessTokenRepository, CompatRefreshTokenRepository, CompatSessionRepository},
-    oauth2::{OAuth2AccessTokenRepository, OAuth2RefreshTokenRepository, OAuth2SessionRepository},
+    oauth2::{
+        OAuth2AccessTokenRepository, OAuth2ClientRepository, OAuth2RefreshTokenRepository,
+        OAuth2SessionRepository,
+    },
     user::UserRepository,
     BoxClock, BoxRepository, Clock,
 };
 use oauth2_types::{
     errors::{ClientError, ClientErrorCode},
     requests::{IntrospectionRequest, IntrospectionResponse},
-    scope::ScopeToken,
+    scope::{Scope, ScopeToken},
 };
 use thiserror::Error;
 
-use crate::{impl_from_error_for_route, ActivityTracker};
+use crate::{
+    impl_from_error_for_route, introspection_cache::CachedSession, ActivityTracker,
+    AppserviceRegistry, IntrospectionCache, MatrixHomeserver, SiteConfig,
+};
 
 #[derive(Debug, Error)]
 pub enum RouteError {
@@ -89,6 +95,10 @@ pub enum RouteError {
     #[error("unknown user")]
     CantLoadUser,
 
+    /// The application service is not allowed to act as the requested user.
+    #[error("application service is not allowed to act as this user")]
+    InvalidAppserviceUser,
+
     #[error("bad request")]
     BadRequest,
 
@@ -129,6 +139,7 @@ impl IntoResponse for RouteError {
             | Self::InvalidUser
             | Self::InvalidCompatSession
             | Self::InvalidOAuthSession
+            | Self::InvalidAppserviceUser
             | Self::InvalidTokenFormat(_) => Json(INACTIVE).into_response(),
             Self::NotAllowed => (
                 StatusCode::UNAUTHORIZED,
@@ -161,11 +172,25 @@ const INACTIVE: IntrospectionResponse = IntrospectionResponse {
     aud: None,
     iss: None,
     jti: None,
+    quarantined: None,
+    device_id: None,
+    session_id: None,
+    client_name: None,
 };
 
 const API_SCOPE: ScopeToken = ScopeToken::from_static("urn:matrix:org.matrix.msc2967.client:api:*");
+const API_GUEST_SCOPE: ScopeToken =
+    ScopeToken::from_static("urn:matrix:org.matrix.msc2967.client:api:guest");
 const SYNAPSE_ADMIN_SCOPE: ScopeToken = ScopeToken::from_static("urn:synapse:admin:*");
 
+/// Find the Matrix device ID in the scope of an OAuth 2.0 session
+fn oauth2_device_id(scope: &Scope) -> Option<String> {
+    scope
+        .iter()
+        .find_map(Device::from_scope_token)
+        .map(|device| device.as_str().to_owned())
+}
+
 #[tracing::instrument(
     name = "handlers.oauth2.introspection.post",
     fields(client.id = client_authorization.client_id()),
@@ -179,6 +204,10 @@ pub(crate) async fn post(
     mut repo: BoxRepository,
     activity_tracker: ActivityTracker,
     State(encrypter): State<Encrypter>,
+    State(homeserver): State<MatrixHomeserver>,
+    State(appservices): State<AppserviceRegistry>,
+    State(site_config): State<SiteConfig>,
+    State(introspection_cache): State<IntrospectionCache>,
     client_authorization: ClientAuthorization<IntrospectionRequest>,
 ) -> Result<impl IntoResponse, RouteError> {
     let client = client_authorization
@@ -205,6 +234,53 @@ pub(crate) async fn post(
     };
 
     let token = &form.token;
+
+    // Application service tokens don't follow our token format, so they have to be
+    // checked first
+    if let Some(appservice) = appservices.find_by_token(token) {
+        if form
+            .token_type_hint
+            .as_ref()
+            .is_some_and(|hint| *hint != OAuthTokenTypeHint::AccessToken)
+        {
+            return Err(RouteError::UnexpectedTokenType);
+        }
+
+        // The application service acts as its sender, unless it asked to masquerade
+        // as one of the users in its namespace
+        let sender = format!("@{}:{homeserver}", appservice.sender_localpart());
+        let user_id = form.user_id.as_deref().unwrap_or(&sender);
+        if user_id != sender && !appservice.is_interested_in_user(user_id) {
+            return Err(RouteError::InvalidAppserviceUser);
+        }
+
+        let username = user_id
+            .strip_prefix('@')
+            .and_then(|user_id| user_id.strip_suffix(&format!(":{homeserver}")))
+            .ok_or(RouteError::InvalidAppserviceUser)?;
+
+        let reply = IntrospectionResponse {
+            active: true,
+            scope: Some([API_SCOPE].into_iter().collect()),
+            client_id: Some(format!("appservice:{}", appservice.id())),
+            username: Some(username.to_owned()),
+            token_type: Some(OAuthTokenTypeHint::AccessToken),
+            exp: None,
+            iat: None,
+            nbf: None,
+            sub: Some(user_id.to_owned()),
+            aud: None,
+            iss: None,
+            jti: None,
+            quarantined: None,
+            device_id: None,
+            session_id: None,
+            client_name: None,
+        };
+
+        return Ok(Json(reply));
+    }
+
     let token_type = TokenType::check(token)?;
     if let Some(hint) = form.token_type_hint {
         if token_type != hint {
@@ -215,7 +291,29 @@ pub(crate) async fn post(
     // XXX: we should get the IP from the client introspecting the token
     let ip = None;
 
-    let reply = match token_type {
+    if let Some((reply, session)) = introspection_cache.get(clock.now(), &client.client_id, token) {
+        match &session {
+            CachedSession::OAuth2(session) => {
+                activity_tracker
+                    .record_oauth2_session(&clock, session, ip)
+                    .await;
+            }
+            CachedSession::Compat(session) => {
+                activity_tracker
+                    .record_compat_session(&clock, session, ip)
+                    .await;
+            }
+        }
+
+        return Ok(Json(reply));
+    }
+
+    // Only trusted resource servers get the non-standard details of the tokens
+    let extensions = site_config
+        .trusted_resource_servers
+        .contains(&client.client_id);
+
+    let (reply, session) = match token_type {
         TokenType::AccessToken => {
             let access_token = repo
                 .oauth2_access_token()
@@ -238,8 +336,9 @@ pub(crate) async fn post(
             }
 
             // The session might not have a user on it (for Client Credentials grants for
-            // example), so we're optionally fetching the user
-            let (sub, username) = if let Some(user_id) = session.user_id {
+            // example), so we're optionally fetching the user. Without a user, the client
+            // itself is the subject of the token
+            let (sub, username, quarantined) = if let Some(user_id) = session.user_id {
                 let user = repo
                     .user()
                     .lookup(user_id)
@@ -250,18 +349,48 @@ pub(crate) async fn post(
                     return Err(RouteError::InvalidUser);
                 }
 
-                (Some(user.sub), Some(user.username))
+                let quarantined = user.is_quarantined();
+                (Some(user.sub), Some(user.username), Some(quarantined))
             } else {
-                (None, None)
+                (Some(session.client_id.to_string()), None, None)
+            };
+
+            // The client the token was issued to can have it intended for other
+            // audiences too
+            let session_client = repo.oauth2_client().lookup(session.client_id).await?;
+            let additional_audiences = match &session_client {
+                Some(session_client) => {
+                    repo.oauth2_client()
+                        .token_settings(session_client)
+                        .await?
+                        .additional_audiences
+                }
+                None => Vec::new(),
+            };
+            let aud = (!additional_audiences.is_empty()).then(|| {
+                std::iter::once(session.client_id.to_string())
+                    .chain(additional_audiences)
+                    .collect()
+            });
+
+            let client_name = session_client.and_then(|c| c.client_name);
+            let (device_id, session_id, client_name) = if extensions {
+                (
+                    oauth2_device_id(&session.scope),
+                    Some(session.id.to_string()),
+                    client_name,
+                )
+            } else {
+                (None, None, None)
             };
 
             activity_tracker
                 .record_oauth2_session(&clock, &session, ip)
                 .await;
 
-            IntrospectionResponse {
+            let reply = IntrospectionResponse {
                 active: true,
-                scope: Some(session.scope),
+                scope: Some(session.scope.clone()),
                 client_id: Some(session.client_id.to_string()),
                 username,
                 token_type: Some(OAuthTokenTypeHint::AccessToken),
@@ -269,10 +398,16 @@ pub(crate) async fn post(
                 iat: Some(access_token.created_at),
                 nbf: Some(access_token.created_at),
                 sub,
-                aud: None,
+                aud,
                 iss: None,
                 jti: Some(access_token.jti()),
-            }
+                quarantined,
+                device_id,
+                session_id,
+                client_name,
+            };
+
+            (reply, CachedSession::OAuth2(session))
         }
 
         TokenType::RefreshToken => {
@@ -297,8 +432,9 @@ pub(crate) async fn post(
             }
 
             // The session might not have a user on it (for Client Credentials grants for
-            // example), so we're optionally fetching the user
-            let (sub, username) = if let Some(user_id) = session.user_id {
+            // example), so we're optionally fetching the user. Without a user, the client
+            // itself is the subject of the token
+            let (sub, username, quarantined) = if let Some(user_id) = session.user_id {
                 let user = repo
                     .user()
                     .lookup(user_id)
@@ -309,18 +445,34 @@ pub(crate) async fn post(
                     return Err(RouteError::InvalidUser);
                 }
 
-                (Some(user.sub), Some(user.username))
+                let quarantined = user.is_quarantined();
+                (Some(user.sub), Some(user.username), Some(quarantined))
             } else {
-                (None, None)
+                (Some(session.client_id.to_string()), None, None)
+            };
+
+            let (device_id, session_id, client_name) = if extensions {
+                let client_name = repo
+                    .oauth2_client()
+                    .lookup(session.client_id)
+                    .await?
+                    .and_then(|c| c.client_name);
+                (
+                    oauth2_device_id(&session.scope),
+                    Some(session.id.to_string()),
+                    client_name,
+                )
+            } else {
+                (None, None, None)
             };
 
             activity_tracker
                 .record_oauth2_session(&clock, &session, ip)
                 .await;
 
-            IntrospectionResponse {
+            let reply = IntrospectionResponse {
                 active: true,
-                scope: Some(session.scope),
+                scope: Some(session.scope.clone()),
                 client_id: Some(session.client_id.to_string()),
                 username,
                 token_type: Some(OAuthTokenTypeHint::RefreshToken),
@@ -331,7 +483,13 @@ pub(crate) async fn post(
                 aud: None,
                 iss: None,
                 jti: Some(refresh_token.jti()),
-            }
+                quarantined,
+                device_id,
+                session_id,
+                client_name,
+            };
+
+            (reply, CachedSession::OAuth2(session))
         }
 
         TokenType::CompatAccessToken => {
@@ -365,19 +523,36 @@ pub(crate) async fn post(
                 return Err(RouteError::InvalidUser)?;
             }
 
+            let quarantined = user.is_quarantined();
+
             // Grant the synapse admin scope if the session has the admin flag set.
             let synapse_admin = session.is_synapse_admin.then_some(SYNAPSE_ADMIN_SCOPE);
+            // Guests only get restricted access to the API
+            let api_scope = if user.is_guest {
+                API_GUEST_SCOPE
+            } else {
+                API_SCOPE
+            };
             let device_scope = session.device.to_scope_token();
-            let scope = [API_SCOPE, device_scope]
+            let scope = [api_scope, device_scope]
                 .into_iter()
                 .chain(synapse_admin)
                 .collect();
 
+            let (device_id, session_id) = if extensions {
+                (
+                    Some(session.device.as_str().to_owned()),
+                    Some(session.id.to_string()),
+                )
+            } else {
+                (None, None)
+            };
+
             activity_tracker
                 .record_compat_session(&clock, &session, ip)
                 .await;
 
-            IntrospectionResponse {
+            let reply = IntrospectionResponse {
                 active: true,
                 scope: Some(scope),
                 client_id: Some("legacy".into()),
@@ -390,7 +565,13 @@ pub(crate) async fn post(
                 aud: None,
                 iss: None,
                 jti: None,
-            }
+                quarantined: Some(quarantined),
+                device_id,
+                session_id,
+                client_name: None,
+            };
+
+            (reply, CachedSession::Compat(session))
         }
 
         TokenType::CompatRefreshToken => {
@@ -424,19 +605,36 @@ pub(crate) async fn post(
                 return Err(RouteError::InvalidUser)?;
             }
 
+            let quarantined = user.is_quarantined();
+
             // Grant the synapse admin scope if the session has the admin flag set.
             let synapse_admin = session.is_synapse_admin.then_some(SYNAPSE_ADMIN_SCOPE);
+            // Guests only get restricted access to the API
+            let api_scope = if user.is_guest {
+                API_GUEST_SCOPE
+            } else {
+                API_SCOPE
+            };
             let device_scope = session.device.to_scope_token();
-            let scope = [API_SCOPE, device_scope]
+            let scope = [api_scope, device_scope]
                 .into_iter()
                 .chain(synapse_admin)
                 .collect();
 
+            let (device_id, session_id) = if extensions {
+                (
+                    Some(session.device.as_str().to_owned()),
+                    Some(session.id.to_string()),
+                )
+            } else {
+                (None, None)
+            };
+
             activity_tracker
                 .record_compat_session(&clock, &session, ip)
                 .await;
 
-            IntrospectionResponse {
+            let reply = IntrospectionResponse {
                 active: true,
                 scope: Some(scope),
                 client_id: Some("legacy".into()),
@@ -449,10 +647,18 @@ pub(crate) async fn post(
                 aud: None,
                 iss: None,
                 jti: None,
-            }
+                quarantined: Some(quarantined),
+                device_id,
+                session_id,
+                client_name: None,
+            };
+
+            (reply, CachedSession::Compat(session))
         }
     };
 
+    introspection_cache.insert(clock.now(), &client.client_id, token, &reply, session);
+
     Ok(Json(reply))
 }
 
@@ -460,13 +666,15 @@ pub(crate) async fn post(
 mod tests {
     use chrono::Duration;
     use hyper::{Request, StatusCode};
-    use mas_data_model::{AccessToken, RefreshToken};
+    use mas_data_model::{AccessToken, ClientTokenSettings, Device, RefreshToken};
     use mas_iana::oauth::OAuthTokenTypeHint;
-    use mas_router::{OAuth2Introspection, OAuth2RegistrationEndpoint, SimpleRoute};
+    use mas_router::{
+        OAuth2Introspection, OAuth2RegistrationEndpoint, OAuth2TokenEndpoint, SimpleRoute,
+    };
     use mas_storage::Clock;
     use oauth2_types::{
         registration::ClientRegistrationResponse,
-        requests::IntrospectionResponse,
+        requests::{AccessTokenResponse, IntrospectionResponse},
         scope::{Scope, OPENID},
     };
     use serde_json::json;
@@ -476,6 +684,7 @@ mod tests {
     use crate::{
         oauth2::generate_token_pair,
         test_utils::{init_tracing, RequestBuilderExt, ResponseExt, TestState},
+        Appservice, IntrospectionCache,
     };
 
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
@@ -569,6 +778,25 @@ mod tests {
         assert_eq!(response.client_id, Some(client_id.clone()));
         assert_eq!(response.token_type, Some(OAuthTokenTypeHint::AccessToken));
         assert_eq!(response.scope, Some(Scope::from_iter([OPENID])));
+        assert_eq!(response.quarantined, Some(false));
+
+        // Quarantine the user: the token should still be active, but flagged
+        let mut repo = state.repository().await.unwrap();
+        let user = repo.user().quarantine(&state.clock, user).await.unwrap();
+        repo.save().await.unwrap();
+
+        let request = Request::post(OAuth2Introspection::PATH)
+            .basic_auth(&introspecting_client_id, &introspecting_client_secret)
+            .form(json!({ "token": access_token }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: IntrospectionResponse = response.json();
+        assert!(response.active);
+        assert_eq!(response.quarantined, Some(true));
+
+        let mut repo = state.repository().await.unwrap();
+        repo.user().unquarantine(user).await.unwrap();
+        repo.save().await.unwrap();
 
         // Do the same request, but with a token_type_hint
         let request = Request::post(OAuth2Introspection::PATH)
@@ -677,6 +905,308 @@ mod tests {
         repo.cancel().await.unwrap();
     }
 
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_introspect_extensions(pool: PgPool) {
+        init_tracing();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+
+        // Provision a client which will be used to do introspection requests
+        let request = Request::post(OAuth2RegistrationEndpoint::PATH).json(json!({
+            "contacts": ["hello@introspecting.com"],
+            "client_uri": "https://introspecting.com/",
+            "grant_types": [],
+            "token_endpoint_auth_method": "client_secret_basic",
+        }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+        let client: ClientRegistrationResponse = response.json();
+        let introspecting_client_id = client.client_id;
+        let introspecting_client_secret = client.client_secret.unwrap();
+
+        // Provision a client which will be used to generate tokens
+        let request = Request::post(OAuth2RegistrationEndpoint::PATH).json(json!({
+            "contacts": ["hello@client.com"],
+            "client_name": "My client",
+            "client_uri": "https://client.com/",
+            "redirect_uris": ["https://client.com/"],
+            "response_types": ["code"],
+            "grant_types": ["authorization_code", "refresh_token"],
+            "token_endpoint_auth_method": "none",
+        }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+        let ClientRegistrationResponse { client_id, .. } = response.json();
+
+        // Provision a user and an oauth session bound to a device
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let client = repo
+            .oauth2_client()
+            .find_by_client_id(&client_id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let browser_session = repo
+            .browser_session()
+            .add(&mut state.rng(), &state.clock, &user, None)
+            .await
+            .unwrap();
+
+        let device = Device::generate(&mut state.rng());
+        let session = repo
+            .oauth2_session()
+            .add_from_browser_session(
+                &mut state.rng(),
+                &state.clock,
+                &client,
+                &browser_session,
+                Scope::from_iter([OPENID, device.to_scope_token()]),
+            )
+            .await
+            .unwrap();
+
+        let (AccessToken { access_token, .. }, RefreshToken { refresh_token, .. }) =
+            generate_token_pair(
+                &mut state.rng(),
+                &state.clock,
+                &mut repo,
+                &session,
+                Duration::minutes(5),
+            )
+            .await
+            .unwrap();
+
+        repo.save().await.unwrap();
+
+        // The introspecting client is not trusted yet, so it doesn't get the details
+        let request = Request::post(OAuth2Introspection::PATH)
+            .basic_auth(&introspecting_client_id, &introspecting_client_secret)
+            .form(json!({ "token": access_token }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: IntrospectionResponse = response.json();
+        assert!(response.active);
+        assert_eq!(response.device_id, None);
+        assert_eq!(response.session_id, None);
+        assert_eq!(response.client_name, None);
+
+        // Once trusted, it gets them for both the access and the refresh token
+        state.site_config.trusted_resource_servers = vec![introspecting_client_id.clone()];
+
+        for token in [&access_token, &refresh_token] {
+            let request = Request::post(OAuth2Introspection::PATH)
+                .basic_auth(&introspecting_client_id, &introspecting_client_secret)
+                .form(json!({ "token": token }));
+            let response = state.request(request).await;
+            response.assert_status(StatusCode::OK);
+            let response: IntrospectionResponse = response.json();
+            assert!(response.active);
+            assert_eq!(response.device_id.as_deref(), Some(device.as_str()));
+            assert_eq!(response.session_id, Some(session.id.to_string()));
+            assert_eq!(response.client_name.as_deref(), Some("My client"));
+        }
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_introspect_cache(pool: PgPool) {
+        init_tracing();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        state.introspection_cache = IntrospectionCache::new(Duration::seconds(30));
+
+        // Provision a client which will be used to do introspection requests
+        let request = Request::post(OAuth2RegistrationEndpoint::PATH).json(json!({
+            "contacts": ["hello@introspecting.com"],
+            "client_uri": "https://introspecting.com/",
+            "grant_types": [],
+            "token_endpoint_auth_method": "client_secret_basic",
+        }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+        let client: ClientRegistrationResponse = response.json();
+        let introspecting_client_id = client.client_id;
+        let introspecting_client_secret = client.client_secret.unwrap();
+
+        // Provision a client which will be used to generate tokens
+        let request = Request::post(OAuth2RegistrationEndpoint::PATH).json(json!({
+            "contacts": ["hello@client.com"],
+            "client_uri": "https://client.com/",
+            "redirect_uris": ["https://client.com/"],
+            "response_types": ["code"],
+            "grant_types": ["authorization_code", "refresh_token"],
+            "token_endpoint_auth_method": "none",
+        }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+        let ClientRegistrationResponse { client_id, .. } = response.json();
+
+        // Provision a user with two oauth sessions
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let client = repo
+            .oauth2_client()
+            .find_by_client_id(&client_id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let browser_session = repo
+            .browser_session()
+            .add(&mut state.rng(), &state.clock, &user, None)
+            .await
+            .unwrap();
+
+        let mut access_tokens = Vec::new();
+        let mut sessions = Vec::new();
+        for _ in 0..2 {
+            let session = repo
+                .oauth2_session()
+                .add_from_browser_session(
+                    &mut state.rng(),
+                    &state.clock,
+                    &client,
+                    &browser_session,
+                    Scope::from_iter([OPENID]),
+                )
+                .await
+                .unwrap();
+
+            let (AccessToken { access_token, .. }, _) = generate_token_pair(
+                &mut state.rng(),
+                &state.clock,
+                &mut repo,
+                &session,
+                Duration::minutes(5),
+            )
+            .await
+            .unwrap();
+
+            access_tokens.push(access_token);
+            sessions.push(session);
+        }
+
+        repo.save().await.unwrap();
+
+        let introspect = |token: &str| {
+            let request = Request::post(OAuth2Introspection::PATH)
+                .basic_auth(&introspecting_client_id, &introspecting_client_secret)
+                .form(json!({ "token": token }));
+            let state = state.clone();
+            async move {
+                let response = state.request(request).await;
+                response.assert_status(StatusCode::OK);
+                let response: IntrospectionResponse = response.json();
+                response.active
+            }
+        };
+
+        assert!(introspect(&access_tokens[0]).await);
+        assert!(introspect(&access_tokens[1]).await);
+
+        // End the first session behind the back of the cache: the token is still
+        // considered active until the cached result expires
+        let mut repo = state.repository().await.unwrap();
+        repo.oauth2_session()
+            .finish(&state.clock, sessions.remove(0))
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        assert!(introspect(&access_tokens[0]).await);
+
+        state.clock.advance(Duration::seconds(31));
+        assert!(!introspect(&access_tokens[0]).await);
+
+        // Revoking the token of the second session invalidates the cached result
+        // right away
+        assert!(introspect(&access_tokens[1]).await);
+
+        let request = Request::post(mas_router::OAuth2Revocation::PATH).form(json!({
+            "token": &access_tokens[1],
+            "client_id": client_id,
+        }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        assert!(!introspect(&access_tokens[1]).await);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_introspect_client_credentials_tokens(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        // Provision a client which both gets a token and introspects it
+        let request = Request::post(OAuth2RegistrationEndpoint::PATH).json(json!({
+            "contacts": ["hello@client.com"],
+            "client_uri": "https://client.com/",
+            "grant_types": ["client_credentials"],
+            "token_endpoint_auth_method": "client_secret_basic",
+        }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+        let client: ClientRegistrationResponse = response.json();
+        let client_id = client.client_id;
+        let client_secret = client.client_secret.unwrap();
+
+        // Give the client an additional audience
+        let mut repo = state.repository().await.unwrap();
+        let client = repo
+            .oauth2_client()
+            .find_by_client_id(&client_id)
+            .await
+            .unwrap()
+            .unwrap();
+        let settings = ClientTokenSettings {
+            additional_audiences: vec!["https://api.example.com/".to_owned()],
+            ..ClientTokenSettings::default()
+        };
+        repo.oauth2_client()
+            .set_token_settings(&client, &settings)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let request = Request::post(OAuth2TokenEndpoint::PATH)
+            .basic_auth(&client_id, &client_secret)
+            .form(json!({ "grant_type": "client_credentials" }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let AccessTokenResponse { access_token, .. } = response.json();
+
+        // The client is the subject of the token, as there is no user
+        let request = Request::post(OAuth2Introspection::PATH)
+            .basic_auth(&client_id, &client_secret)
+            .form(json!({ "token": access_token }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: IntrospectionResponse = response.json();
+        assert!(response.active);
+        assert_eq!(response.sub, Some(client_id.clone()));
+        assert_eq!(response.client_id, Some(client_id.clone()));
+        assert_eq!(response.username, None);
+        assert_eq!(response.token_type, Some(OAuthTokenTypeHint::AccessToken));
+        assert_eq!(
+            response.aud,
+            Some(vec![client_id, "https://api.example.com/".to_owned()])
+        );
+    }
+
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
     async fn test_introspect_compat_tokens(pool: PgPool) {
         init_tracing();
@@ -827,4 +1357,93 @@ mod tests {
         let response: IntrospectionResponse = response.json();
         assert!(response.active);
     }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_introspect_appservice_tokens(pool: PgPool) {
+        init_tracing();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        state.appservices = [Appservice::new(
+            "irc".to_owned(),
+            "appservice-token".to_owned(),
+            "irc-bridge".to_owned(),
+            ["@irc_.*:example.com"],
+        )
+        .unwrap()]
+        .into_iter()
+        .collect();
+
+        // Provision a client which will be used to do introspection requests
+        let request = Request::post(OAuth2RegistrationEndpoint::PATH).json(json!({
+            "contacts": ["hello@introspecting.com"],
+            "client_uri": "https://introspecting.com/",
+            "grant_types": [],
+            "token_endpoint_auth_method": "client_secret_basic",
+        }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+        let client: ClientRegistrationResponse = response.json();
+        let introspecting_client_id = client.client_id;
+        let introspecting_client_secret = client.client_secret.unwrap();
+
+        // Without a user ID, the token is introspected as the sender
+        let request = Request::post(OAuth2Introspection::PATH)
+            .basic_auth(&introspecting_client_id, &introspecting_client_secret)
+            .form(json!({ "token": "appservice-token" }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: IntrospectionResponse = response.json();
+        assert!(response.active);
+        assert_eq!(response.username, Some("irc-bridge".to_owned()));
+        assert_eq!(response.sub, Some("@irc-bridge:example.com".to_owned()));
+        assert_eq!(response.client_id, Some("appservice:irc".to_owned()));
+        assert_eq!(response.token_type, Some(OAuthTokenTypeHint::AccessToken));
+
+        // It can masquerade as a user in its namespace
+        let request = Request::post(OAuth2Introspection::PATH)
+            .basic_auth(&introspecting_client_id, &introspecting_client_secret)
+            .form(json!({ "token": "appservice-token", "user_id": "@irc_alice:example.com" }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: IntrospectionResponse = response.json();
+        assert!(response.active);
+        assert_eq!(response.username, Some("irc_alice".to_owned()));
+        assert_eq!(response.sub, Some("@irc_alice:example.com".to_owned()));
+
+        // But not as a user outside of its namespace
+        let request = Request::post(OAuth2Introspection::PATH)
+            .basic_auth(&introspecting_client_id, &introspecting_client_secret)
+            .form(json!({ "token": "appservice-token", "user_id": "@alice:example.com" }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: IntrospectionResponse = response.json();
+        assert!(!response.active);
+
+        // Nor as a user on another server
+        let request = Request::post(OAuth2Introspection::PATH)
+            .basic_auth(&introspecting_client_id, &introspecting_client_secret)
+            .form(json!({ "token": "appservice-token", "user_id": "@irc_alice:example.org" }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: IntrospectionResponse = response.json();
+        assert!(!response.active);
+
+        // Appservice tokens are access tokens
+        let request = Request::post(OAuth2Introspection::PATH)
+            .basic_auth(&introspecting_client_id, &introspecting_client_secret)
+            .form(json!({ "token": "appservice-token", "token_type_hint": "refresh_token" }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: IntrospectionResponse = response.json();
+        assert!(!response.active);
+
+        // Unknown tokens are still inactive
+        let request = Request::post(OAuth2Introspection::PATH)
+            .basic_auth(&introspecting_client_id, &introspecting_client_secret)
+            .form(json!({ "token": "not-an-appservice-token" }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: IntrospectionResponse = response.json();
+        assert!(!response.active);
+    }
 }
@@ -12,11 +12,20 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use axum::{extract::State, response::IntoResponse, Json};
+use axum::{extract::State, response::IntoResponse, TypedHeader};
+use headers::{IfModifiedSince, IfNoneMatch};
 use mas_keystore::Keystore;
+use mas_storage::BoxClock;
+
+use crate::conditional::conditional_json;
 
 #[tracing::instrument(name = "handlers.oauth2.keys.get", skip_all)]
-pub(crate) async fn get(State(key_store): State<Keystore>) -> impl IntoResponse {
+pub(crate) async fn get(
+    clock: BoxClock,
+    State(key_store): State<Keystore>,
+    if_none_match: Option<TypedHeader<IfNoneMatch>>,
+    if_modified_since: Option<TypedHeader<IfModifiedSince>>,
+) -> impl IntoResponse {
     let jwks = key_store.public_jwks();
-    Json(jwks)
+    conditional_json(&clock, &jwks, if_none_match, if_modified_since)
 }
@@ -16,8 +16,8 @@ use std::collections::HashMap;
 
 use chrono::Duration;
 use mas_data_model::{
-    AccessToken, Authentication, AuthorizationGrant, BrowserSession, Client, RefreshToken, Session,
-    TokenType,
+    AccessToken, Authentication, AuthorizationGrant, BrowserSession, ClaimMapping, Client,
+    RefreshToken, Session, TokenType, User, UserAttribute,
 };
 use mas_iana::jose::JsonWebSignatureAlg;
 use mas_jose::{
@@ -26,9 +26,16 @@ use mas_jose::{
     jwt::{JsonWebSignatureHeader, Jwt},
 };
 use mas_keystore::Keystore;
+use mas_matrix::HomeserverConnection;
+use mas_policy::{Policy, Requester};
 use mas_router::UrlBuilder;
-use mas_storage::{Clock, RepositoryAccess};
+use mas_storage::{
+    oauth2::OAuth2ClientRepository, user::UserEmailRepository, BoxRepository, Clock,
+    RepositoryAccess,
+};
+use oauth2_types::scope::Scope;
 use thiserror::Error;
+use tracing::warn;
 
 pub mod authorization;
 pub mod consent;
@@ -52,6 +59,100 @@ pub(crate) enum IdTokenSignatureError {
     TokenHash(#[from] mas_jose::claims::TokenHashError),
 }
 
+#[derive(Debug, Error)]
+#[error(transparent)]
+pub(crate) enum MappedClaimsError {
+    Repository(#[from] mas_storage::RepositoryError),
+    Policy(#[from] mas_policy::EvaluationError),
+}
+
+/// Map the attributes of the user to claims, following the mappings of the
+/// site and of the client which apply to the given scope
+///
+/// The mappings of the client take precedence over the ones of the site, and
+/// the claims vetoed by the policy are left out.
+pub(crate) async fn mapped_claims(
+    repo: &mut BoxRepository,
+    homeserver: &dyn HomeserverConnection<Error = anyhow::Error>,
+    policy: &mut Policy,
+    requester: &Requester,
+    site_mappings: &[ClaimMapping],
+    client: &Client,
+    user: &User,
+    scope: &Scope,
+) -> Result<HashMap<String, serde_json::Value>, MappedClaimsError> {
+    let client_mappings = repo.oauth2_client().claim_mappings(client).await?;
+    let mappings: Vec<&ClaimMapping> = site_mappings
+        .iter()
+        .chain(&client_mappings)
+        .filter(|mapping| mapping.applies_to(scope))
+        .collect();
+
+    let mapped = |attribute| {
+        mappings
+            .iter()
+            .any(|mapping| mapping.attribute == attribute)
+    };
+
+    // Only the verified primary email is exposed
+    let email = if mapped(UserAttribute::Email) {
+        repo.user_email()
+            .get_primary(user)
+            .await?
+            .filter(|email| email.confirmed_at.is_some())
+            .map(|email| email.email)
+    } else {
+        None
+    };
+
+    let displayname = if mapped(UserAttribute::DisplayName) {
+        let mxid = homeserver.mxid(&user.username);
+        match homeserver.query_user(&mxid).await {
+            Ok(matrix_user) => matrix_user.displayname,
+            Err(err) => {
+                // Don't fail the whole request because the homeserver is unreachable
+                warn!(%mxid, error = %err, "Could not get the display name of the user");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let mut claims = HashMap::new();
+    for mapping in mappings {
+        let value = match mapping.attribute {
+            UserAttribute::DisplayName => displayname.clone().map(serde_json::Value::from),
+            UserAttribute::Email => email.clone().map(serde_json::Value::from),
+            UserAttribute::IsAdmin => Some(serde_json::Value::from(user.can_request_admin)),
+            UserAttribute::Groups => Some(serde_json::Value::from(user.upstream_groups.clone())),
+        };
+
+        if let Some(value) = value {
+            claims.insert(mapping.claim.clone(), value);
+        }
+    }
+
+    if claims.is_empty() {
+        return Ok(claims);
+    }
+
+    let res = policy
+        .evaluate_claims(user, client, scope, &claims, requester)
+        .await?;
+
+    for violation in res.violations {
+        if let Some(claim) = violation.field {
+            claims.remove(&claim);
+        } else {
+            // A violation which doesn't name a claim vetoes all of them
+            claims.clear();
+        }
+    }
+
+    Ok(claims)
+}
+
 pub(crate) fn generate_id_token(
     rng: &mut (impl rand::RngCore + rand::CryptoRng),
     clock: &impl Clock,
@@ -62,8 +163,11 @@ pub(crate) fn generate_id_token(
     browser_session: &BrowserSession,
     access_token: Option<&AccessToken>,
     last_authentication: Option<&Authentication>,
+    mapped_claims: HashMap<String, serde_json::Value>,
 ) -> Result<String, IdTokenSignatureError> {
-    let mut claims = HashMap::new();
+    // The standard claims are inserted afterwards, so that they can't be
+    // overridden by the mapped ones
+    let mut claims = mapped_claims;
     let now = clock.now();
     claims::ISS.insert(&mut claims, url_builder.oidc_issuer().to_string())?;
     claims::SUB.insert(&mut claims, &browser_session.user.sub)?;
@@ -12,18 +12,21 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use axum::{extract::State, response::IntoResponse, Json};
+use axum::{extract::State, response::IntoResponse, Json, TypedHeader};
+use headers::UserAgent;
 use hyper::StatusCode;
 use mas_axum_utils::sentry::SentryEventID;
+use mas_data_model::RedirectUriMatching;
 use mas_iana::oauth::OAuthClientAuthenticationMethod;
-use mas_keystore::Encrypter;
-use mas_policy::{Policy, Violation};
-use mas_storage::{oauth2::OAuth2ClientRepository, BoxClock, BoxRepository, BoxRng};
+use mas_keystore::{Encrypter, Keystore};
+use mas_policy::{Policy, Requester, Violation};
+use mas_storage::{oauth2::OAuth2ClientRepository, BoxClock, BoxRepository, BoxRng, Clock};
 use oauth2_types::{
     errors::{ClientError, ClientErrorCode},
     registration::{
         ClientMetadata, ClientMetadataVerificationError, ClientRegistrationResponse, Localized,
     },
+    requests::GrantType,
 };
 use psl::Psl;
 use rand::distributions::{Alphanumeric, DistString};
@@ -31,13 +34,16 @@ use thiserror::Error;
 use tracing::info;
 use url::Url;
 
-use crate::impl_from_error_for_route;
+use crate::{impl_from_error_for_route, BoundActivityTracker, SiteConfig};
 
 #[derive(Debug, Error)]
 pub(crate) enum RouteError {
     #[error(transparent)]
     Internal(Box<dyn std::error::Error + Send + Sync>),
 
+    #[error("dynamic client registration is disabled")]
+    Disabled,
+
     #[error(transparent)]
     JsonExtract(#[from] axum::extract::rejection::JsonRejection),
 
@@ -47,6 +53,18 @@ pub(crate) enum RouteError {
     #[error("{0} is a public suffix, not a valid domain")]
     UrlIsPublicSuffix(&'static str),
 
+    #[error("wildcard redirect_uri are reserved to static clients")]
+    WildcardRedirectUri,
+
+    #[error("the {0} grant type is reserved to static clients")]
+    ReservedGrantType(GrantType),
+
+    #[error("scope {0:?} is not defined")]
+    UndefinedScope(String),
+
+    #[error("{0} is not supported by the server keys")]
+    UnsupportedSigningAlg(&'static str),
+
     #[error("denied by the policy: {0:?}")]
     PolicyDenied(Vec<Violation>),
 }
@@ -66,6 +84,16 @@ impl IntoResponse for RouteError {
             )
                 .into_response(),
 
+            Self::Disabled => (
+                StatusCode::FORBIDDEN,
+                Json(
+                    ClientError::from(ClientErrorCode::AccessDenied).with_description(
+                        "dynamic client registration is disabled on this server".to_owned(),
+                    ),
+                ),
+            )
+                .into_response(),
+
             // This error happens if we managed to parse the incomiong JSON but it can't be
             // deserialized to the expected type. In this case we return an
             // `invalid_client_metadata` error with the details of the error.
@@ -128,6 +156,44 @@ impl IntoResponse for RouteError {
             )
                 .into_response(),
 
+            Self::WildcardRedirectUri => (
+                StatusCode::BAD_REQUEST,
+                Json(
+                    ClientError::from(ClientErrorCode::InvalidRedirectUri).with_description(
+                        "redirect_uri can't have a wildcard in its host".to_owned(),
+                    ),
+                ),
+            )
+                .into_response(),
+
+            Self::ReservedGrantType(grant_type) => (
+                StatusCode::BAD_REQUEST,
+                Json(
+                    ClientError::from(ClientErrorCode::InvalidClientMetadata).with_description(
+                        format!("the {grant_type} grant type is not allowed"),
+                    ),
+                ),
+            )
+                .into_response(),
+
+            Self::UndefinedScope(scope) => (
+                StatusCode::BAD_REQUEST,
+                Json(
+                    ClientError::from(ClientErrorCode::InvalidClientMetadata)
+                        .with_description(format!("scope {scope:?} is not defined")),
+                ),
+            )
+                .into_response(),
+
+            Self::UnsupportedSigningAlg(field) => (
+                StatusCode::BAD_REQUEST,
+                Json(
+                    ClientError::from(ClientErrorCode::InvalidClientMetadata)
+                        .with_description(format!("{field} is not supported")),
+                ),
+            )
+                .into_response(),
+
             // For policy violations, we return an `invalid_client_metadata` error with the details
             // of the violations in most cases. If a violation includes `redirect_uri` in the
             // message, we return an `invalid_redirect_uri` error instead.
@@ -194,8 +260,16 @@ pub(crate) async fn post(
     mut repo: BoxRepository,
     mut policy: Policy,
     State(encrypter): State<Encrypter>,
+    State(key_store): State<Keystore>,
+    State(site_config): State<SiteConfig>,
+    activity_tracker: BoundActivityTracker,
+    user_agent: Option<TypedHeader<UserAgent>>,
     body: Result<Json<ClientMetadata>, axum::extract::rejection::JsonRejection>,
 ) -> Result<impl IntoResponse, RouteError> {
+    if !site_config.oauth2_features.dynamic_registration {
+        return Err(RouteError::Disabled);
+    }
+
     // Propagate any JSON extraction error
     let Json(body) = body?;
 
@@ -240,9 +314,50 @@ pub(crate) async fn post(
         if host_is_public_suffix(redirect_uri) {
             return Err(RouteError::UrlIsPublicSuffix("redirect_uri"));
         }
+
+        // Wildcards can only be set up by the operator, for trusted clients
+        if RedirectUriMatching::is_wildcard(redirect_uri) {
+            return Err(RouteError::WildcardRedirectUri);
+        }
+    }
+
+    // Assertions from the trusted issuers can only be exchanged by clients set
+    // up by the operator
+    if metadata.grant_types().contains(&GrantType::JwtBearer) {
+        return Err(RouteError::ReservedGrantType(GrantType::JwtBearer));
+    }
+
+    // The ID tokens and userinfo responses can only be signed with the algorithms
+    // our keys support
+    if let Some(alg) = &metadata.id_token_signed_response_alg {
+        if key_store.signing_key_for_algorithm(alg).is_none() {
+            return Err(RouteError::UnsupportedSigningAlg(
+                "id_token_signed_response_alg",
+            ));
+        }
     }
 
-    let res = policy.evaluate_client_registration(&metadata).await?;
+    if let Some(alg) = &metadata.userinfo_signed_response_alg {
+        if key_store.signing_key_for_algorithm(alg).is_none() {
+            return Err(RouteError::UnsupportedSigningAlg(
+                "userinfo_signed_response_alg",
+            ));
+        }
+    }
+
+    // Clients can't register with scopes which are not in the registry
+    if let Some(scope) = &metadata.scope {
+        if let Some(token) = site_config.scope_registry.find_undefined(scope) {
+            return Err(RouteError::UndefinedScope(token.to_string()));
+        }
+    }
+
+    let requester = Requester::new(clock.now())
+        .with_ip_address(activity_tracker.ip())
+        .with_user_agent(user_agent.map(|ua| ua.as_str().to_owned()));
+    let res = policy
+        .evaluate_client_registration(&metadata, &requester)
+        .await?;
     if !res.valid() {
         return Err(RouteError::PolicyDenied(res.violations));
     }
@@ -280,6 +395,7 @@ pub(crate) async fn post(
             metadata.client_uri.clone().map(Localized::to_non_localized),
             metadata.policy_uri.clone().map(Localized::to_non_localized),
             metadata.tos_uri.clone().map(Localized::to_non_localized),
+            metadata.brand_color.clone(),
             metadata.jwks_uri.clone(),
             metadata.jwks.clone(),
             // XXX: those might not be right, should be function calls
@@ -299,6 +415,9 @@ pub(crate) async fn post(
         // XXX: we should have a `created_at` field on the clients
         client_id_issued_at: Some(client.id.datetime().into()),
         client_secret_expires_at: None,
+        // Clients can't manage their registration yet
+        registration_access_token: None,
+        registration_client_uri: None,
     };
 
     Ok((StatusCode::CREATED, Json(response)))
@@ -380,6 +499,20 @@ mod tests {
         let response: ClientError = response.json();
         assert_eq!(response.error, ClientErrorCode::InvalidRedirectUri);
 
+        // Wildcard redirect URI
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "application_type": "web",
+                "contacts": ["hello@example.com"],
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://*.example.com/"],
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let response: ClientError = response.json();
+        assert_eq!(response.error, ClientErrorCode::InvalidRedirectUri);
+
         // Incoherent response types
         let request =
             Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
@@ -435,6 +568,71 @@ mod tests {
             response.error_description.unwrap(),
             "client_uri is not using a valid domain"
         );
+
+        // Using a scope which is not defined
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "contacts": ["hello@example.com"],
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/"],
+                "response_types": ["code"],
+                "grant_types": ["authorization_code"],
+                "token_endpoint_auth_method": "none",
+                "scope": "openid urn:example:read",
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let response: ClientError = response.json();
+        assert_eq!(response.error, ClientErrorCode::InvalidClientMetadata);
+        assert_eq!(
+            response.error_description.unwrap(),
+            "scope \"urn:example:read\" is not defined"
+        );
+
+        // Asking for the JWT bearer grant type
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "contacts": ["hello@example.com"],
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/"],
+                "response_types": ["code"],
+                "grant_types": [
+                    "authorization_code",
+                    "urn:ietf:params:oauth:grant-type:jwt-bearer",
+                ],
+                "token_endpoint_auth_method": "client_secret_basic",
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let response: ClientError = response.json();
+        assert_eq!(response.error, ClientErrorCode::InvalidClientMetadata);
+        assert_eq!(
+            response.error_description.unwrap(),
+            "the urn:ietf:params:oauth:grant-type:jwt-bearer grant type is not allowed"
+        );
+
+        // Asking for userinfo responses signed with an algorithm no key supports
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "contacts": ["hello@example.com"],
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/"],
+                "response_types": ["code"],
+                "grant_types": ["authorization_code"],
+                "token_endpoint_auth_method": "none",
+                "userinfo_signed_response_alg": "ES256",
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let response: ClientError = response.json();
+        assert_eq!(response.error, ClientErrorCode::InvalidClientMetadata);
+        assert_eq!(
+            response.error_description.unwrap(),
+            "userinfo_signed_response_alg is not supported"
+        );
     }
 
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
@@ -475,5 +673,42 @@ mod tests {
         response.assert_status(StatusCode::CREATED);
         let response: ClientRegistrationResponse = response.json();
         assert!(response.client_secret.is_some());
+
+        // Clients can register with the scopes they will request
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "contacts": ["hello@example.com"],
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/"],
+                "response_types": ["code"],
+                "grant_types": ["authorization_code"],
+                "token_endpoint_auth_method": "none",
+                "scope": "openid urn:matrix:org.matrix.msc2967.client:api:*",
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_registration_disabled(pool: PgPool) {
+        init_tracing();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        state.site_config.oauth2_features.dynamic_registration = false;
+
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "contacts": ["hello@example.com"],
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/"],
+                "response_types": ["code"],
+                "grant_types": ["authorization_code"],
+                "token_endpoint_auth_method": "none",
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+        let response: ClientError = response.json();
+        assert_eq!(response.error, ClientErrorCode::AccessDenied);
     }
 }
@@ -32,7 +32,7 @@ use oauth2_types::{
 };
 use thiserror::Error;
 
-use crate::{impl_from_error_for_route, BoundActivityTracker};
+use crate::{impl_from_error_for_route, BoundActivityTracker, IntrospectionCache};
 
 #[derive(Debug, Error)]
 pub(crate) enum RouteError {
@@ -123,6 +123,7 @@ pub(crate) async fn post(
     mut repo: BoxRepository,
     activity_tracker: BoundActivityTracker,
     State(encrypter): State<Encrypter>,
+    State(introspection_cache): State<IntrospectionCache>,
     client_authorization: ClientAuthorization<RevocationRequest>,
 ) -> Result<impl IntoResponse, RouteError> {
     let client = client_authorization
@@ -234,10 +235,12 @@ pub(crate) async fn post(
     }
 
     // Now that we checked everything, we can end the session.
-    repo.oauth2_session().finish(&clock, session).await?;
+    let session = repo.oauth2_session().finish(&clock, session).await?;
 
     repo.save().await?;
 
+    introspection_cache.invalidate_session(session.id);
+
     Ok(())
 }
 
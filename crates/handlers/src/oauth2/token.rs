@@ -12,27 +12,37 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use axum::{extract::State, response::IntoResponse, Json};
+use std::collections::HashMap;
+
+use axum::{extract::State, response::IntoResponse, Json, TypedHeader};
 use chrono::{DateTime, Duration, Utc};
-use headers::{CacheControl, HeaderMap, HeaderMapExt, Pragma};
+use headers::{CacheControl, HeaderMap, HeaderMapExt, Pragma, UserAgent};
 use hyper::StatusCode;
 use mas_axum_utils::{
-    client_authorization::{ClientAuthorization, CredentialsVerificationError},
+    client_authorization::{fetch_jwks, ClientAuthorization, CredentialsVerificationError},
     http_client_factory::HttpClientFactory,
     sentry::SentryEventID,
 };
-use mas_data_model::{AuthorizationGrantStage, Client, Device, TokenType};
+use mas_data_model::{
+    AuthorizationGrantStage, Client, Device, RefreshTokenState, Session, TokenType, User,
+};
+use mas_iana::oauth::OAuthClientAuthenticationMethod;
+use mas_jose::{
+    claims::{self, TimeOptions},
+    jwt::Jwt,
+};
 use mas_keystore::{Encrypter, Keystore};
+use mas_matrix::HomeserverConnection;
 use mas_oidc_client::types::scope::ScopeToken;
-use mas_policy::Policy;
+use mas_policy::{Policy, Requester};
 use mas_router::UrlBuilder;
 use mas_storage::{
     job::{JobRepositoryExt, ProvisionDeviceJob},
     oauth2::{
-        OAuth2AccessTokenRepository, OAuth2AuthorizationGrantRepository,
-        OAuth2RefreshTokenRepository, OAuth2SessionRepository,
+        OAuth2AccessTokenRepository, OAuth2AuthorizationGrantRepository, OAuth2ClientRepository,
+        OAuth2JwtBearerIssuerRepository, OAuth2RefreshTokenRepository, OAuth2SessionRepository,
     },
-    user::BrowserSessionRepository,
+    user::{BrowserSessionRepository, UserRepository},
     BoxClock, BoxRepository, BoxRng, Clock, RepositoryAccess,
 };
 use oauth2_types::{
@@ -40,7 +50,7 @@ use oauth2_types::{
     pkce::CodeChallengeError,
     requests::{
         AccessTokenRequest, AccessTokenResponse, AuthorizationCodeGrant, ClientCredentialsGrant,
-        GrantType, RefreshTokenGrant,
+        GrantType, JwtBearerGrant, RefreshTokenGrant,
     },
     scope,
 };
@@ -51,8 +61,11 @@ use tracing::debug;
 use ulid::Ulid;
 use url::Url;
 
-use super::{generate_id_token, generate_token_pair};
-use crate::{impl_from_error_for_route, site_config::SiteConfig, BoundActivityTracker};
+use super::{generate_id_token, generate_token_pair, mapped_claims};
+use crate::{
+    impl_from_error_for_route, site_config::SiteConfig, BoundActivityTracker, IntrospectionCache,
+    Limiter, RateLimited, SharedHomeserverConnection,
+};
 
 #[serde_as]
 #[skip_serializing_none]
@@ -103,6 +116,9 @@ pub(crate) enum RouteError {
     #[error("refresh token {0} is invalid")]
     RefreshTokenInvalid(Ulid),
 
+    #[error("refresh token {0} has expired")]
+    RefreshTokenExpired(Ulid),
+
     #[error("session {0} is invalid")]
     SessionInvalid(Ulid),
 
@@ -118,11 +134,26 @@ pub(crate) enum RouteError {
     #[error("unauthorized client")]
     UnauthorizedClient,
 
+    #[error("client is not allowed to obtain the scope {0}")]
+    ScopeNotAllowed(scope::ScopeToken),
+
+    #[error("invalid assertion")]
+    InvalidAssertion,
+
+    #[error("assertion issuer {0:?} is not trusted")]
+    UntrustedIssuer(String),
+
     #[error("failed to load browser session")]
     NoSuchBrowserSession,
 
     #[error("failed to load oauth session")]
     NoSuchOAuthSession,
+
+    #[error("failed to load user")]
+    NoSuchUser,
+
+    #[error(transparent)]
+    RateLimited(#[from] RateLimited),
 }
 
 impl IntoResponse for RouteError {
@@ -130,7 +161,10 @@ impl IntoResponse for RouteError {
         let event_id = sentry::capture_error(&self);
 
         let response = match self {
-            Self::Internal(_) | Self::NoSuchBrowserSession | Self::NoSuchOAuthSession => (
+            Self::Internal(_)
+            | Self::NoSuchBrowserSession
+            | Self::NoSuchOAuthSession
+            | Self::NoSuchUser => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 Json(ClientError::from(ClientErrorCode::ServerError)),
             ),
@@ -168,16 +202,45 @@ impl IntoResponse for RouteError {
             Self::InvalidGrant
             | Self::RefreshTokenNotFound
             | Self::RefreshTokenInvalid(_)
+            | Self::RefreshTokenExpired(_)
             | Self::SessionInvalid(_)
             | Self::ClientIDMismatch { .. }
-            | Self::GrantNotFound => (
+            | Self::GrantNotFound
+            | Self::InvalidAssertion => (
                 StatusCode::BAD_REQUEST,
                 Json(ClientError::from(ClientErrorCode::InvalidGrant)),
             ),
+            Self::UntrustedIssuer(_) => (
+                StatusCode::BAD_REQUEST,
+                Json(
+                    ClientError::from(ClientErrorCode::InvalidGrant)
+                        .with_description("The assertion issuer is not trusted".to_owned()),
+                ),
+            ),
+            Self::ScopeNotAllowed(token) => (
+                StatusCode::BAD_REQUEST,
+                Json(
+                    ClientError::from(ClientErrorCode::InvalidScope).with_description(format!(
+                        "The client is not allowed to obtain the \"{token}\" scope"
+                    )),
+                ),
+            ),
             Self::UnsupportedGrantType => (
                 StatusCode::BAD_REQUEST,
                 Json(ClientError::from(ClientErrorCode::UnsupportedGrantType)),
             ),
+            Self::RateLimited(rate_limited) => {
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    SentryEventID::from(event_id),
+                    rate_limited,
+                    Json(
+                        ClientError::from(ClientErrorCode::TemporarilyUnavailable)
+                            .with_description("Too many requests, retry later".to_owned()),
+                    ),
+                )
+                    .into_response();
+            }
         };
 
         (SentryEventID::from(event_id), response).into_response()
@@ -187,6 +250,7 @@ impl IntoResponse for RouteError {
 impl_from_error_for_route!(mas_storage::RepositoryError);
 impl_from_error_for_route!(mas_policy::EvaluationError);
 impl_from_error_for_route!(super::IdTokenSignatureError);
+impl_from_error_for_route!(super::MappedClaimsError);
 
 #[tracing::instrument(
     name = "handlers.oauth2.token.post",
@@ -201,12 +265,18 @@ pub(crate) async fn post(
     State(key_store): State<Keystore>,
     State(url_builder): State<UrlBuilder>,
     activity_tracker: BoundActivityTracker,
+    user_agent: Option<TypedHeader<UserAgent>>,
     mut repo: BoxRepository,
     State(site_config): State<SiteConfig>,
     State(encrypter): State<Encrypter>,
+    State(limiter): State<Limiter>,
+    State(introspection_cache): State<IntrospectionCache>,
+    State(homeserver): State<SharedHomeserverConnection>,
     policy: Policy,
     client_authorization: ClientAuthorization<AccessTokenRequest>,
 ) -> Result<impl IntoResponse, RouteError> {
+    limiter.check_token(clock.now(), activity_tracker.ip()).await?;
+
     let client = client_authorization
         .credentials
         .fetch(&mut repo)
@@ -223,20 +293,40 @@ pub(crate) async fn post(
         .verify(&http_client_factory, &encrypter, method, &client)
         .await?;
 
+    limiter
+        .check_token_client(clock.now(), activity_tracker.ip(), &client)
+        .await?;
+
     let form = client_authorization.form.ok_or(RouteError::BadRequest)?;
 
+    // Grant types disabled in the configuration are handled like unsupported ones
+    let grant_type_enabled = form
+        .grant_type()
+        .is_some_and(|grant_type| site_config.oauth2_features.grant_type_enabled(&grant_type));
+    if !grant_type_enabled {
+        return Err(RouteError::UnsupportedGrantType);
+    }
+
+    let requester = Requester::new(clock.now())
+        .with_ip_address(activity_tracker.ip())
+        .with_user_agent(user_agent.map(|ua| ua.as_str().to_owned()));
+
     let (reply, repo) = match form {
         AccessTokenRequest::AuthorizationCode(grant) => {
             authorization_code_grant(
                 &mut rng,
                 &clock,
                 &activity_tracker,
+                &requester,
                 &grant,
                 &client,
                 &key_store,
                 &url_builder,
                 &site_config,
+                &introspection_cache,
+                &*homeserver,
                 repo,
+                policy,
             )
             .await?
         }
@@ -245,10 +335,13 @@ pub(crate) async fn post(
                 &mut rng,
                 &clock,
                 &activity_tracker,
+                &requester,
                 &grant,
                 &client,
                 &site_config,
+                &introspection_cache,
                 repo,
+                policy,
             )
             .await?
         }
@@ -257,8 +350,25 @@ pub(crate) async fn post(
                 &mut rng,
                 &clock,
                 &activity_tracker,
+                &requester,
+                &grant,
+                &client,
+                &site_config,
+                repo,
+                policy,
+            )
+            .await?
+        }
+        AccessTokenRequest::JwtBearer(grant) => {
+            jwt_bearer_grant(
+                &mut rng,
+                &clock,
+                &http_client_factory,
+                &activity_tracker,
+                &requester,
                 &grant,
                 &client,
+                &url_builder,
                 &site_config,
                 repo,
                 policy,
@@ -284,12 +394,16 @@ async fn authorization_code_grant(
     mut rng: &mut BoxRng,
     clock: &impl Clock,
     activity_tracker: &BoundActivityTracker,
+    requester: &Requester,
     grant: &AuthorizationCodeGrant,
     client: &Client,
     key_store: &Keystore,
     url_builder: &UrlBuilder,
     site_config: &SiteConfig,
+    introspection_cache: &IntrospectionCache,
+    homeserver: &dyn HomeserverConnection<Error = anyhow::Error>,
     mut repo: BoxRepository,
+    mut policy: Policy,
 ) -> Result<(AccessTokenResponse, BoxRepository), RouteError> {
     // Check that the client is allowed to use this grant type
     if !client.grant_types.contains(&GrantType::AuthorizationCode) {
@@ -326,6 +440,7 @@ async fn authorization_code_grant(
                     .ok_or(RouteError::NoSuchOAuthSession)?;
                 repo.oauth2_session().finish(clock, session).await?;
                 repo.save().await?;
+                introspection_cache.invalidate_session(session_id);
             }
 
             return Err(RouteError::InvalidGrant);
@@ -338,8 +453,8 @@ async fn authorization_code_grant(
             session_id,
             fulfilled_at,
         } => {
-            if now - fulfilled_at > Duration::minutes(10) {
-                debug!("Code exchange took more than 10 minutes");
+            if now - fulfilled_at > site_config.authorization_code_ttl {
+                debug!("Code exchange took too long");
                 return Err(RouteError::InvalidGrant);
             }
 
@@ -386,11 +501,52 @@ async fn authorization_code_grant(
         .get_last_authentication(&browser_session)
         .await?;
 
-    let ttl = site_config.access_token_ttl;
-    let (access_token, refresh_token) =
-        generate_token_pair(&mut rng, clock, &mut repo, &session, ttl).await?;
+    let session = apply_token_policy(
+        &mut policy,
+        &mut repo,
+        mas_policy::GrantType::AuthorizationCode,
+        session,
+        client,
+        Some(&browser_session.user),
+        requester,
+    )
+    .await?;
+
+    let token_settings = repo.oauth2_client().token_settings(client).await?;
+    let ttl = token_settings.access_token_ttl(site_config.access_token_ttl);
+    // Refresh tokens are only issued to clients allowed to use them, and only if
+    // the grant type is enabled. The others have to go through an authorization
+    // again once the access token expires
+    let issue_refresh_token = client.grant_types.contains(&GrantType::RefreshToken)
+        && site_config
+            .oauth2_features
+            .grant_type_enabled(&GrantType::RefreshToken);
+    let (access_token, refresh_token) = if issue_refresh_token {
+        let (access_token, refresh_token) =
+            generate_token_pair(&mut rng, clock, &mut repo, &session, ttl).await?;
+        (access_token, Some(refresh_token))
+    } else {
+        let access_token_str = TokenType::AccessToken.generate(&mut rng);
+        let access_token = repo
+            .oauth2_access_token()
+            .add(&mut rng, clock, &session, access_token_str, Some(ttl))
+            .await?;
+        (access_token, None)
+    };
 
     let id_token = if session.scope.contains(&scope::OPENID) {
+        let mapped_claims = mapped_claims(
+            &mut repo,
+            homeserver,
+            &mut policy,
+            requester,
+            &site_config.claim_mappings,
+            client,
+            &browser_session.user,
+            &session.scope,
+        )
+        .await?;
+
         Some(generate_id_token(
             &mut rng,
             clock,
@@ -401,6 +557,7 @@ async fn authorization_code_grant(
             &browser_session,
             Some(&access_token),
             last_authentication.as_ref(),
+            mapped_claims,
         )?)
     } else {
         None
@@ -408,9 +565,12 @@ async fn authorization_code_grant(
 
     let mut params = AccessTokenResponse::new(access_token.access_token)
         .with_expires_in(ttl)
-        .with_refresh_token(refresh_token.refresh_token)
         .with_scope(session.scope.clone());
 
+    if let Some(refresh_token) = refresh_token {
+        params = params.with_refresh_token(refresh_token.refresh_token);
+    }
+
     if let Some(id_token) = id_token {
         params = params.with_id_token(id_token);
     }
@@ -423,9 +583,9 @@ async fn authorization_code_grant(
             // client does its first request to the Homeserver. This is fine for now, since
             // Synapse still provision devices on-the-fly if it doesn't find them in the
             // database.
-            repo.job()
-                .schedule_job(ProvisionDeviceJob::new(&browser_session.user, &device))
-                .await?;
+            let job = ProvisionDeviceJob::new(&browser_session.user, &device)
+                .with_display_name(session.human_name.clone());
+            repo.job().schedule_job(job).await?;
         }
     }
 
@@ -447,10 +607,13 @@ async fn refresh_token_grant(
     rng: &mut BoxRng,
     clock: &impl Clock,
     activity_tracker: &BoundActivityTracker,
+    requester: &Requester,
     grant: &RefreshTokenGrant,
     client: &Client,
     site_config: &SiteConfig,
+    introspection_cache: &IntrospectionCache,
     mut repo: BoxRepository,
+    mut policy: Policy,
 ) -> Result<(AccessTokenResponse, BoxRepository), RouteError> {
     // Check that the client is allowed to use this grant type
     if !client.grant_types.contains(&GrantType::RefreshToken) {
@@ -469,7 +632,25 @@ async fn refresh_token_grant(
         .await?
         .ok_or(RouteError::NoSuchOAuthSession)?;
 
-    if !refresh_token.is_valid() {
+    let now = clock.now();
+
+    if let RefreshTokenState::Consumed { consumed_at } = refresh_token.state {
+        debug!(%consumed_at, "Refresh token was already consumed");
+
+        // Public clients can't prove the token was issued to them, so a token
+        // used again after its rotation is likely to have leaked. Ending the
+        // session if it was consumed more than 20s ago, to let clients retry
+        if client.token_endpoint_auth_method == Some(OAuthClientAuthenticationMethod::None)
+            && client.id == session.client_id
+            && session.is_valid()
+            && now - consumed_at > Duration::seconds(20)
+        {
+            debug!("Ending potentially compromised session");
+            let session = repo.oauth2_session().finish(clock, session).await?;
+            repo.save().await?;
+            introspection_cache.invalidate_session(session.id);
+        }
+
         return Err(RouteError::RefreshTokenInvalid(refresh_token.id));
     }
 
@@ -485,11 +666,48 @@ async fn refresh_token_grant(
         });
     }
 
+    let token_settings = repo.oauth2_client().token_settings(client).await?;
+
+    // Offline sessions end when they are not refreshed in time
+    if let Some(refresh_token_ttl) =
+        token_settings.refresh_token_ttl(site_config.offline_session_ttl)
+    {
+        if now - refresh_token.created_at > refresh_token_ttl {
+            debug!("Ending offline session which was not refreshed in time");
+            let session = repo.oauth2_session().finish(clock, session).await?;
+            repo.save().await?;
+            introspection_cache.invalidate_session(session.id);
+            return Err(RouteError::RefreshTokenExpired(refresh_token.id));
+        }
+    }
+
+    let user = if let Some(user_id) = session.user_id {
+        let user = repo
+            .user()
+            .lookup(user_id)
+            .await?
+            .ok_or(RouteError::NoSuchUser)?;
+        Some(user)
+    } else {
+        None
+    };
+
+    let session = apply_token_policy(
+        &mut policy,
+        &mut repo,
+        mas_policy::GrantType::RefreshToken,
+        session,
+        client,
+        user.as_ref(),
+        requester,
+    )
+    .await?;
+
     activity_tracker
         .record_oauth2_session(clock, &session)
         .await;
 
-    let ttl = site_config.access_token_ttl;
+    let ttl = token_settings.access_token_ttl(site_config.access_token_ttl);
     let (new_access_token, new_refresh_token) =
         generate_token_pair(rng, clock, &mut repo, &session, ttl).await?;
 
@@ -507,6 +725,9 @@ async fn refresh_token_grant(
         }
     }
 
+    // The previous tokens of the session are not valid anymore
+    introspection_cache.invalidate_session(session.id);
+
     let params = AccessTokenResponse::new(new_access_token.access_token)
         .with_expires_in(ttl)
         .with_refresh_token(new_refresh_token.refresh_token)
@@ -519,6 +740,7 @@ async fn client_credentials_grant(
     rng: &mut BoxRng,
     clock: &impl Clock,
     activity_tracker: &BoundActivityTracker,
+    requester: &Requester,
     grant: &ClientCredentialsGrant,
     client: &Client,
     site_config: &SiteConfig,
@@ -530,27 +752,60 @@ async fn client_credentials_grant(
         return Err(RouteError::UnauthorizedClient);
     }
 
+    // Only confidential clients can use this grant type, as the token is issued
+    // on the sole basis of the client credentials
+    if client.token_endpoint_auth_method == Some(OAuthClientAuthenticationMethod::None) {
+        return Err(RouteError::UnauthorizedClient);
+    }
+
     // Default to an empty scope if none is provided
     let scope = grant
         .scope
         .clone()
         .unwrap_or_else(|| std::iter::empty::<ScopeToken>().collect());
 
+    // Check the requested scope against the ones the client is allowed to obtain
+    if let Some(allowed_scope) = repo
+        .oauth2_client()
+        .client_credentials_scope(client)
+        .await?
+    {
+        if let Some(token) = scope.iter().find(|token| !allowed_scope.contains(token)) {
+            return Err(RouteError::ScopeNotAllowed(token.clone()));
+        }
+    }
+
     // Make the request go through the policy engine
     let res = policy
-        .evaluate_client_credentials_grant(&scope, client)
+        .evaluate_client_credentials_grant(&scope, client, requester)
         .await?;
     if !res.valid() {
         return Err(RouteError::DeniedByPolicy(res.violations));
     }
 
+    // Let the policy narrow down the scope of the token
+    let decision = policy
+        .evaluate_token(
+            mas_policy::GrantType::ClientCredentials,
+            &scope,
+            client,
+            None,
+            requester,
+        )
+        .await?;
+    if !decision.valid() {
+        return Err(RouteError::DeniedByPolicy(decision.violations));
+    }
+    let scope = decision.scope;
+
     // Start the session
     let session = repo
         .oauth2_session()
         .add_from_client_credentials(rng, clock, client, scope)
         .await?;
 
-    let ttl = site_config.access_token_ttl;
+    let token_settings = repo.oauth2_client().token_settings(client).await?;
+    let ttl = token_settings.access_token_ttl(site_config.access_token_ttl);
     let access_token_str = TokenType::AccessToken.generate(rng);
 
     let access_token = repo
@@ -575,10 +830,240 @@ async fn client_credentials_grant(
     Ok((params, repo))
 }
 
+#[allow(clippy::too_many_arguments)]
+async fn jwt_bearer_grant(
+    rng: &mut BoxRng,
+    clock: &impl Clock,
+    http_client_factory: &HttpClientFactory,
+    activity_tracker: &BoundActivityTracker,
+    requester: &Requester,
+    grant: &JwtBearerGrant,
+    client: &Client,
+    url_builder: &UrlBuilder,
+    site_config: &SiteConfig,
+    mut repo: BoxRepository,
+    mut policy: Policy,
+) -> Result<(AccessTokenResponse, BoxRepository), RouteError> {
+    // Check that the client is allowed to use this grant type
+    if !client.grant_types.contains(&GrantType::JwtBearer) {
+        return Err(RouteError::UnauthorizedClient);
+    }
+
+    // Only confidential clients can present assertions, so that a leaked
+    // assertion can't be used by anyone
+    if client.token_endpoint_auth_method == Some(OAuthClientAuthenticationMethod::None) {
+        return Err(RouteError::UnauthorizedClient);
+    }
+
+    let jwt: Jwt<'_, HashMap<String, serde_json::Value>> =
+        Jwt::try_from(grant.assertion.as_str()).map_err(|_| RouteError::InvalidAssertion)?;
+
+    // The issuer is read before verifying the signature, as it tells which keys
+    // to verify it with
+    let issuer = jwt
+        .payload()
+        .get("iss")
+        .and_then(serde_json::Value::as_str)
+        .ok_or(RouteError::InvalidAssertion)?;
+
+    let issuer = repo
+        .oauth2_jwt_bearer_issuer()
+        .find_by_issuer(issuer)
+        .await?
+        .ok_or_else(|| RouteError::UntrustedIssuer(issuer.to_owned()))?;
+
+    // Each issuer is bound to the clients which can exchange its assertions
+    if !issuer.is_client_allowed(client.id) {
+        debug!(
+            oauth2_jwt_bearer_issuer.id = %issuer.id,
+            oauth2_client.id = %client.id,
+            "Client is not allowed to exchange assertions of this issuer"
+        );
+        return Err(RouteError::UnauthorizedClient);
+    }
+
+    let jwks = fetch_jwks(http_client_factory, &issuer.jwks)
+        .await
+        .map_err(RouteError::Internal)?;
+
+    jwt.verify_with_jwks(&jwks)
+        .map_err(|_| RouteError::InvalidAssertion)?;
+
+    let (_header, mut claims) = jwt.into_parts();
+
+    let now = clock.now();
+    let time_options = TimeOptions::new(now).leeway(site_config.clock_skew);
+    let expires_at = *claims::EXP
+        .extract_required_with_options(&mut claims, &time_options)
+        .map_err(|_| RouteError::InvalidAssertion)?;
+
+    // Assertions must be short-lived, as they are only remembered until they
+    // expire to prevent them from being replayed
+    if expires_at > now + site_config.jwt_bearer_max_assertion_lifetime + site_config.clock_skew {
+        return Err(RouteError::InvalidAssertion);
+    }
+
+    claims::NBF
+        .extract_optional_with_options(&mut claims, &time_options)
+        .map_err(|_| RouteError::InvalidAssertion)?;
+
+    // The assertion must be intended for us
+    let audience = url_builder.oidc_issuer().to_string();
+    claims::AUD
+        .extract_required_with_options(&mut claims, &audience)
+        .map_err(|_| RouteError::InvalidAssertion)?;
+
+    let username = claims::SUB
+        .extract_required(&mut claims)
+        .map_err(|_| RouteError::InvalidAssertion)?;
+
+    let jti = claims::JTI
+        .extract_required(&mut claims)
+        .map_err(|_| RouteError::InvalidAssertion)?;
+
+    if !issuer.is_subject_allowed(&username) {
+        debug!(
+            oauth2_jwt_bearer_issuer.id = %issuer.id,
+            user.username = %username,
+            "Issuer is not allowed to sign assertions for this user"
+        );
+        return Err(RouteError::InvalidAssertion);
+    }
+
+    let user = repo
+        .user()
+        .find_by_username(&username)
+        .await?
+        .filter(User::is_valid)
+        .ok_or(RouteError::InvalidAssertion)?;
+
+    // Quarantined users keep their existing sessions, but can't be granted new
+    // ones
+    if user.is_quarantined() {
+        debug!(
+            user.id = %user.id,
+            "Refusing to exchange an assertion for a quarantined user"
+        );
+        return Err(RouteError::InvalidAssertion);
+    }
+
+    // Default to an empty scope if none is provided
+    let scope = grant
+        .scope
+        .clone()
+        .unwrap_or_else(|| std::iter::empty::<ScopeToken>().collect());
+
+    // Check the requested scope against the ones the issuer is trusted for
+    if let Some(allowed_scope) = &issuer.allowed_scope {
+        if let Some(token) = scope.iter().find(|token| !allowed_scope.contains(token)) {
+            return Err(RouteError::ScopeNotAllowed(token.clone()));
+        }
+    }
+
+    // Let the policy narrow down the scope of the token
+    let decision = policy
+        .evaluate_token(
+            mas_policy::GrantType::JwtBearer,
+            &scope,
+            client,
+            Some(&user),
+            requester,
+        )
+        .await?;
+    if !decision.valid() {
+        return Err(RouteError::DeniedByPolicy(decision.violations));
+    }
+    let scope = decision.scope;
+
+    // Remember the assertion until it expires so that it can't be replayed
+    let first_use = repo
+        .oauth2_jwt_bearer_issuer()
+        .record_assertion(clock, &issuer, &jti, expires_at)
+        .await?;
+    if !first_use {
+        debug!(
+            oauth2_jwt_bearer_issuer.id = %issuer.id,
+            oauth2_jwt_bearer_assertion.jti = %jti,
+            "Assertion was already used"
+        );
+        return Err(RouteError::InvalidAssertion);
+    }
+
+    // Start the session
+    let session = repo
+        .oauth2_session()
+        .add(rng, clock, client, Some(&user), None, scope)
+        .await?;
+
+    let token_settings = repo.oauth2_client().token_settings(client).await?;
+    let ttl = token_settings.access_token_ttl(site_config.access_token_ttl);
+    let access_token_str = TokenType::AccessToken.generate(rng);
+
+    let access_token = repo
+        .oauth2_access_token()
+        .add(rng, clock, &session, access_token_str, Some(ttl))
+        .await?;
+
+    let mut params = AccessTokenResponse::new(access_token.access_token).with_expires_in(ttl);
+
+    activity_tracker
+        .record_oauth2_session(clock, &session)
+        .await;
+
+    if !session.scope.is_empty() {
+        // We only return the scope if it's not empty
+        params = params.with_scope(session.scope);
+    }
+
+    Ok((params, repo))
+}
+
+/// Ask the policy whether a token can be issued for the session, and narrow
+/// down the scope of the session if the policy strips some of it
+async fn apply_token_policy(
+    policy: &mut Policy,
+    repo: &mut BoxRepository,
+    grant_type: mas_policy::GrantType,
+    session: Session,
+    client: &Client,
+    user: Option<&User>,
+    requester: &Requester,
+) -> Result<Session, RouteError> {
+    let decision = policy
+        .evaluate_token(grant_type, &session.scope, client, user, requester)
+        .await?;
+
+    if !decision.valid() {
+        return Err(RouteError::DeniedByPolicy(decision.violations));
+    }
+
+    if decision.scope == session.scope {
+        return Ok(session);
+    }
+
+    debug!(
+        session.scope = %session.scope,
+        token.scope = %decision.scope,
+        "Policy narrowed down the scope of the session"
+    );
+    let session = repo
+        .oauth2_session()
+        .set_scope(session, decision.scope)
+        .await?;
+
+    Ok(session)
+}
+
 #[cfg(test)]
 mod tests {
+    use std::num::NonZeroU32;
+
     use hyper::Request;
-    use mas_data_model::{AccessToken, AuthorizationCode, RefreshToken};
+    use mas_data_model::{
+        AccessToken, AuthorizationCode, ClientTokenSettings, JwksOrJwksUri, RefreshToken,
+    };
+    use mas_iana::jose::JsonWebSignatureAlg;
+    use mas_jose::{constraints::Constrainable, jwt::JsonWebSignatureHeader};
     use mas_router::SimpleRoute;
     use oauth2_types::{
         registration::ClientRegistrationResponse,
@@ -588,7 +1073,10 @@ mod tests {
     use sqlx::PgPool;
 
     use super::*;
-    use crate::test_utils::{init_tracing, RequestBuilderExt, ResponseExt, TestState};
+    use crate::{
+        test_utils::{init_tracing, RequestBuilderExt, ResponseExt, TestState},
+        BucketConfig, RateLimits,
+    };
 
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
     async fn test_auth_code_grant(pool: PgPool) {
@@ -655,6 +1143,7 @@ mod tests {
                 ResponseMode::Query,
                 false,
                 false,
+                None,
             )
             .await
             .unwrap();
@@ -692,11 +1181,19 @@ mod tests {
         let response = state.request(request).await;
         response.assert_status(StatusCode::OK);
 
-        let AccessTokenResponse { access_token, .. } = response.json();
+        let AccessTokenResponse {
+            access_token,
+            refresh_token,
+            ..
+        } = response.json();
 
         // Check that the token is valid
         assert!(state.is_access_token_valid(&access_token).await);
 
+        // The client doesn't have the refresh_token grant type, so it doesn't get
+        // a refresh token
+        assert!(refresh_token.is_none());
+
         // Exchange it again, this it should fail
         let request =
             Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
@@ -755,6 +1252,7 @@ mod tests {
                 ResponseMode::Query,
                 false,
                 false,
+                None,
             )
             .await
             .unwrap();
@@ -922,62 +1420,536 @@ mod tests {
     }
 
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
-    async fn test_client_credentials(pool: PgPool) {
+    async fn test_disabled_refresh_token_grant(pool: PgPool) {
         init_tracing();
-        let state = TestState::from_pool(pool).await.unwrap();
+        let mut state = TestState::from_pool(pool).await.unwrap();
 
-        // Provision a client
+        // Provision a client which can use refresh tokens
         let request =
             Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
                 "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/callback"],
                 "contacts": ["contact@example.com"],
-                "token_endpoint_auth_method": "client_secret_post",
-                "grant_types": ["client_credentials"],
+                "token_endpoint_auth_method": "none",
+                "response_types": ["code"],
+                "grant_types": ["authorization_code", "refresh_token"],
             }));
 
         let response = state.request(request).await;
         response.assert_status(StatusCode::CREATED);
 
-        let response: ClientRegistrationResponse = response.json();
-        let client_id = response.client_id;
-        let client_secret = response.client_secret.expect("to have a client secret");
+        let ClientRegistrationResponse { client_id, .. } = response.json();
 
-        // Call the token endpoint with an empty scope
-        let request =
-            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
-                "grant_type": "client_credentials",
-                "client_id": client_id,
-                "client_secret": client_secret,
-            }));
+        // Then disable the refresh token grant
+        state
+            .site_config
+            .oauth2_features
+            .grant_types
+            .retain(|grant_type| *grant_type != GrantType::RefreshToken);
 
-        let response = state.request(request).await;
-        response.assert_status(StatusCode::OK);
+        let mut repo = state.repository().await.unwrap();
 
-        let response: AccessTokenResponse = response.json();
-        assert!(response.refresh_token.is_none());
-        assert!(response.expires_in.is_some());
-        assert!(response.scope.is_none());
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
 
-        // Revoke the token
-        let request = Request::post(mas_router::OAuth2Revocation::PATH).form(serde_json::json!({
-            "token": response.access_token,
-            "client_id": client_id,
-            "client_secret": client_secret,
-        }));
+        let browser_session = repo
+            .browser_session()
+            .add(&mut state.rng(), &state.clock, &user, None)
+            .await
+            .unwrap();
 
-        let response = state.request(request).await;
-        response.assert_status(StatusCode::OK);
+        let client = repo
+            .oauth2_client()
+            .find_by_client_id(&client_id)
+            .await
+            .unwrap()
+            .unwrap();
 
-        // We should be allowed to ask for the GraphQL API scope
-        let request =
-            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
-                "grant_type": "client_credentials",
-                "client_id": client_id,
-                "client_secret": client_secret,
-                "scope": "urn:mas:graphql:*"
-            }));
+        let code = "thisisaverysecurecode";
+        let grant = repo
+            .oauth2_authorization_grant()
+            .add(
+                &mut state.rng(),
+                &state.clock,
+                &client,
+                "https://example.com/redirect".parse().unwrap(),
+                Scope::from_iter([OPENID]),
+                Some(AuthorizationCode {
+                    code: code.to_owned(),
+                    pkce: None,
+                }),
+                Some("state".to_owned()),
+                Some("nonce".to_owned()),
+                None,
+                ResponseMode::Query,
+                false,
+                false,
+                None,
+            )
+            .await
+            .unwrap();
 
-        let response = state.request(request).await;
+        let session = repo
+            .oauth2_session()
+            .add_from_browser_session(
+                &mut state.rng(),
+                &state.clock,
+                &client,
+                &browser_session,
+                grant.scope.clone(),
+            )
+            .await
+            .unwrap();
+
+        let grant = repo
+            .oauth2_authorization_grant()
+            .fulfill(&state.clock, &session, grant)
+            .await
+            .unwrap();
+
+        // A refresh token issued before the grant type was disabled
+        let (_, RefreshToken { refresh_token, .. }) = generate_token_pair(
+            &mut state.rng(),
+            &state.clock,
+            &mut repo,
+            &session,
+            Duration::minutes(5),
+        )
+        .await
+        .unwrap();
+
+        repo.save().await.unwrap();
+
+        // Exchanging the code only gives an access token
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "authorization_code",
+                "code": code,
+                "redirect_uri": grant.redirect_uri,
+                "client_id": client.client_id,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let AccessTokenResponse {
+            access_token,
+            refresh_token: new_refresh_token,
+            ..
+        } = response.json();
+        assert!(state.is_access_token_valid(&access_token).await);
+        assert!(new_refresh_token.is_none());
+
+        // And the refresh token grant is rejected as unsupported
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "refresh_token",
+                "refresh_token": refresh_token,
+                "client_id": client.client_id,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::UnsupportedGrantType);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_refresh_token_reuse_and_expiry(pool: PgPool) {
+        init_tracing();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        state.site_config.offline_session_ttl = Some(Duration::days(1));
+
+        // Provision a public client
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/callback"],
+                "contacts": ["contact@example.com"],
+                "token_endpoint_auth_method": "none",
+                "response_types": ["code"],
+                "grant_types": ["authorization_code", "refresh_token"],
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+
+        let ClientRegistrationResponse { client_id, .. } = response.json();
+
+        let mut repo = state.repository().await.unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let browser_session = repo
+            .browser_session()
+            .add(&mut state.rng(), &state.clock, &user, None)
+            .await
+            .unwrap();
+
+        let client = repo
+            .oauth2_client()
+            .find_by_client_id(&client_id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Start two sessions
+        let mut refresh_tokens = Vec::new();
+        for _ in 0..2 {
+            let session = repo
+                .oauth2_session()
+                .add_from_browser_session(
+                    &mut state.rng(),
+                    &state.clock,
+                    &client,
+                    &browser_session,
+                    Scope::from_iter([OPENID]),
+                )
+                .await
+                .unwrap();
+
+            let (_, RefreshToken { refresh_token, .. }) = generate_token_pair(
+                &mut state.rng(),
+                &state.clock,
+                &mut repo,
+                &session,
+                Duration::minutes(5),
+            )
+            .await
+            .unwrap();
+            refresh_tokens.push(refresh_token);
+        }
+
+        repo.save().await.unwrap();
+
+        let refresh = |refresh_token: &str| {
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "refresh_token",
+                "refresh_token": refresh_token,
+                "client_id": client.client_id,
+            }))
+        };
+
+        // Rotate the refresh token of the first session
+        let response = state.request(refresh(&refresh_tokens[0])).await;
+        response.assert_status(StatusCode::OK);
+        let response: AccessTokenResponse = response.json();
+        let access_token = response.access_token;
+        let refresh_token = response.refresh_token.expect("to have a refresh token");
+
+        // Using the old token again a while later ends the session
+        state.clock.advance(Duration::minutes(1));
+        let response = state.request(refresh(&refresh_tokens[0])).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidGrant);
+
+        assert!(!state.is_access_token_valid(&access_token).await);
+        let response = state.request(refresh(&refresh_token)).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+
+        // The second session was not refreshed in time
+        state.clock.advance(Duration::days(1));
+        let response = state.request(refresh(&refresh_tokens[1])).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidGrant);
+
+        // And it ended
+        let mut repo = state.repository().await.unwrap();
+        let refresh_token = repo
+            .oauth2_refresh_token()
+            .find_by_token(&refresh_tokens[1])
+            .await
+            .unwrap()
+            .unwrap();
+        let session = repo
+            .oauth2_session()
+            .lookup(refresh_token.session_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!session.is_valid());
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_refresh_token_client_settings(pool: PgPool) {
+        init_tracing();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        state.site_config.offline_session_ttl = Some(Duration::days(1));
+
+        // Provision a public client
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/callback"],
+                "contacts": ["contact@example.com"],
+                "token_endpoint_auth_method": "none",
+                "response_types": ["code"],
+                "grant_types": ["authorization_code", "refresh_token"],
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+
+        let ClientRegistrationResponse { client_id, .. } = response.json();
+
+        let mut repo = state.repository().await.unwrap();
+
+        let client = repo
+            .oauth2_client()
+            .find_by_client_id(&client_id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // The client gets short-lived tokens, and its sessions end sooner than the
+        // others
+        let settings = ClientTokenSettings {
+            access_token_ttl: Some(Duration::minutes(1)),
+            refresh_token_ttl: Some(Duration::hours(1)),
+            additional_audiences: Vec::new(),
+        };
+        repo.oauth2_client()
+            .set_token_settings(&client, &settings)
+            .await
+            .unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let browser_session = repo
+            .browser_session()
+            .add(&mut state.rng(), &state.clock, &user, None)
+            .await
+            .unwrap();
+
+        // Start two sessions
+        let mut refresh_tokens = Vec::new();
+        for _ in 0..2 {
+            let session = repo
+                .oauth2_session()
+                .add_from_browser_session(
+                    &mut state.rng(),
+                    &state.clock,
+                    &client,
+                    &browser_session,
+                    Scope::from_iter([OPENID]),
+                )
+                .await
+                .unwrap();
+
+            let (_, RefreshToken { refresh_token, .. }) = generate_token_pair(
+                &mut state.rng(),
+                &state.clock,
+                &mut repo,
+                &session,
+                Duration::minutes(5),
+            )
+            .await
+            .unwrap();
+            refresh_tokens.push(refresh_token);
+        }
+
+        repo.save().await.unwrap();
+
+        let refresh = |refresh_token: &str| {
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "refresh_token",
+                "refresh_token": refresh_token,
+                "client_id": client.client_id,
+            }))
+        };
+
+        // The new access token uses the lifetime of the client
+        let response = state.request(refresh(&refresh_tokens[0])).await;
+        response.assert_status(StatusCode::OK);
+        let response: AccessTokenResponse = response.json();
+        assert_eq!(response.expires_in, Some(Duration::minutes(1)));
+
+        // The second session was not refreshed within the lifetime of the client,
+        // even though it is shorter than the global one
+        state.clock.advance(Duration::hours(2));
+        let response = state.request(refresh(&refresh_tokens[1])).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidGrant);
+
+        let mut repo = state.repository().await.unwrap();
+        let refresh_token = repo
+            .oauth2_refresh_token()
+            .find_by_token(&refresh_tokens[1])
+            .await
+            .unwrap()
+            .unwrap();
+        let session = repo
+            .oauth2_session()
+            .lookup(refresh_token.session_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!session.is_valid());
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_refresh_token_grant_strips_admin_scope(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        // Provision a client
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/callback"],
+                "contacts": ["contact@example.com"],
+                "token_endpoint_auth_method": "none",
+                "response_types": ["code"],
+                "grant_types": ["authorization_code", "refresh_token"],
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+
+        let ClientRegistrationResponse { client_id, .. } = response.json();
+
+        // Provision a user which can't request admin, with a session which has the
+        // Synapse admin scope, as if it was removed from the admins since
+        let mut repo = state.repository().await.unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let browser_session = repo
+            .browser_session()
+            .add(&mut state.rng(), &state.clock, &user, None)
+            .await
+            .unwrap();
+
+        let client = repo
+            .oauth2_client()
+            .find_by_client_id(&client_id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let session = repo
+            .oauth2_session()
+            .add_from_browser_session(
+                &mut state.rng(),
+                &state.clock,
+                &client,
+                &browser_session,
+                "openid urn:synapse:admin:*".parse().unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let (_, RefreshToken { refresh_token, .. }) = generate_token_pair(
+            &mut state.rng(),
+            &state.clock,
+            &mut repo,
+            &session,
+            Duration::minutes(5),
+        )
+        .await
+        .unwrap();
+
+        repo.save().await.unwrap();
+
+        // Refreshing the token should strip the admin scope
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "refresh_token",
+                "refresh_token": refresh_token,
+                "client_id": client.client_id,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: AccessTokenResponse = response.json();
+        assert_eq!(response.scope, Some(Scope::from_iter([OPENID])));
+
+        // The session itself should have been narrowed down
+        let mut repo = state.repository().await.unwrap();
+        let session = repo
+            .oauth2_session()
+            .lookup(session.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(session.scope, Scope::from_iter([OPENID]));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_client_credentials(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        // Provision a client
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "contacts": ["contact@example.com"],
+                "token_endpoint_auth_method": "client_secret_post",
+                "grant_types": ["client_credentials"],
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+
+        let response: ClientRegistrationResponse = response.json();
+        let client_id = response.client_id;
+        let client_secret = response.client_secret.expect("to have a client secret");
+
+        // Call the token endpoint with an empty scope
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "client_credentials",
+                "client_id": client_id,
+                "client_secret": client_secret,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let response: AccessTokenResponse = response.json();
+        assert!(response.refresh_token.is_none());
+        assert!(response.expires_in.is_some());
+        assert!(response.scope.is_none());
+
+        // Revoke the token
+        let request = Request::post(mas_router::OAuth2Revocation::PATH).form(serde_json::json!({
+            "token": response.access_token,
+            "client_id": client_id,
+            "client_secret": client_secret,
+        }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        // We should be allowed to ask for the GraphQL API scope
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "client_credentials",
+                "client_id": client_id,
+                "client_secret": client_secret,
+                "scope": "urn:mas:graphql:*"
+            }));
+
+        let response = state.request(request).await;
         response.assert_status(StatusCode::OK);
 
         let response: AccessTokenResponse = response.json();
@@ -1046,6 +2018,376 @@ mod tests {
 
         let response = state.request(request).await;
         response.assert_status(StatusCode::OK);
+
+        // Restrict the client to the GraphQL API scope
+        let mut repo = state.repository().await.unwrap();
+        let client = repo
+            .oauth2_client()
+            .find_by_client_id(&client_id)
+            .await
+            .unwrap()
+            .unwrap();
+        repo.oauth2_client()
+            .set_client_credentials_scope(&client, Some(&"urn:mas:graphql:*".parse().unwrap()))
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        // Asking for the MAS admin scope should now fail, even with the policy
+        // allowing it
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "client_credentials",
+                "client_id": client_id,
+                "client_secret": client_secret,
+                "scope": "urn:mas:admin"
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidScope);
+
+        // But the GraphQL API scope is still allowed
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "client_credentials",
+                "client_id": client_id,
+                "client_secret": client_secret,
+                "scope": "urn:mas:graphql:*"
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let response: AccessTokenResponse = response.json();
+        assert_eq!(
+            response.expires_in,
+            Some(state.site_config.access_token_ttl)
+        );
+
+        // Override the lifetime of the access tokens issued to the client
+        let mut repo = state.repository().await.unwrap();
+        let settings = ClientTokenSettings {
+            access_token_ttl: Some(Duration::seconds(42)),
+            ..ClientTokenSettings::default()
+        };
+        repo.oauth2_client()
+            .set_token_settings(&client, &settings)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "client_credentials",
+                "client_id": client_id,
+                "client_secret": client_secret,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let response: AccessTokenResponse = response.json();
+        assert_eq!(response.expires_in, Some(Duration::seconds(42)));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_jwt_bearer(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        // Provision two clients. The JWT bearer grant type can't be obtained through
+        // dynamic client registration, so they are added directly
+        let client_secret = "secret";
+        let mut repo = state.repository().await.unwrap();
+        let mut clients = Vec::new();
+        for _ in 0..2 {
+            let encrypted_client_secret = state
+                .encrypter
+                .encrypt_to_string(client_secret.as_bytes())
+                .unwrap();
+            let client = repo
+                .oauth2_client()
+                .add(
+                    &mut state.rng(),
+                    &state.clock,
+                    Vec::new(),
+                    Some(encrypted_client_secret),
+                    None,
+                    vec![GrantType::JwtBearer],
+                    Vec::new(),
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    Some(OAuthClientAuthenticationMethod::ClientSecretPost),
+                    None,
+                    None,
+                )
+                .await
+                .unwrap();
+            clients.push(client);
+        }
+        let [client, other_client]: [Client; 2] = clients.try_into().unwrap();
+
+        // Provision users, and trust an issuer to sign assertions for some of them
+        repo.user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+        repo.user()
+            .add(&mut state.rng(), &state.clock, "bob".to_owned())
+            .await
+            .unwrap();
+        let carol = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "carol".to_owned())
+            .await
+            .unwrap();
+        repo.user().quarantine(&state.clock, carol).await.unwrap();
+        repo.oauth2_jwt_bearer_issuer()
+            .upsert(
+                &mut state.rng(),
+                &state.clock,
+                "https://sso.example.com/".to_owned(),
+                JwksOrJwksUri::Jwks(state.key_store.public_jwks()),
+                vec!["alice".to_owned(), "carol".to_owned()],
+                vec![client.id],
+                Some("urn:mas:graphql:*".parse().unwrap()),
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let sign_with = |issuer: &str, subject: &str, jti: Option<&str>, lifetime: Duration| {
+            let mut claims = HashMap::new();
+            claims::ISS.insert(&mut claims, issuer).unwrap();
+            claims::SUB.insert(&mut claims, subject).unwrap();
+            claims::AUD
+                .insert(&mut claims, state.url_builder.oidc_issuer().to_string())
+                .unwrap();
+            claims::EXP.insert(&mut claims, state.clock.now() + lifetime).unwrap();
+            if let Some(jti) = jti {
+                claims::JTI.insert(&mut claims, jti).unwrap();
+            }
+
+            let alg = JsonWebSignatureAlg::Rs256;
+            let key = state.key_store.signing_key_for_algorithm(&alg).unwrap();
+            let signer = key.params().signing_key_for_alg(&alg).unwrap();
+            let header = JsonWebSignatureHeader::new(alg).with_kid(key.kid().unwrap());
+            Jwt::sign_with_rng(&mut state.rng(), header, claims, &signer)
+                .unwrap()
+                .into_string()
+        };
+
+        // Each assertion gets a distinct `jti`
+        let assertions = std::cell::Cell::new(0);
+        let sign = |issuer: &str, subject: &str| {
+            assertions.set(assertions.get() + 1);
+            let jti = format!("assertion-{}", assertions.get());
+            sign_with(issuer, subject, Some(&jti), Duration::minutes(5))
+        };
+
+        let token_request_for = |client_id: &str, assertion: String, scope: &str| {
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "urn:ietf:params:oauth:grant-type:jwt-bearer",
+                "assertion": assertion,
+                "scope": scope,
+                "client_id": client_id,
+                "client_secret": client_secret,
+            }))
+        };
+        let token_request =
+            |assertion: String, scope: &str| token_request_for(&client.client_id, assertion, scope);
+
+        // A valid assertion gets a token for the user
+        let assertion = sign("https://sso.example.com/", "alice");
+        let request = token_request(assertion.clone(), "urn:mas:graphql:*");
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let response: AccessTokenResponse = response.json();
+        assert!(response.refresh_token.is_none());
+        assert_eq!(response.scope, Some("urn:mas:graphql:*".parse().unwrap()));
+
+        let mut repo = state.repository().await.unwrap();
+        let access_token = repo
+            .oauth2_access_token()
+            .find_by_token(&response.access_token)
+            .await
+            .unwrap()
+            .unwrap();
+        let session = repo
+            .oauth2_session()
+            .lookup(access_token.session_id)
+            .await
+            .unwrap()
+            .unwrap();
+        let alice = repo
+            .user()
+            .find_by_username("alice")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(session.user_id, Some(alice.id));
+        repo.cancel().await.unwrap();
+
+        // The same assertion can't be replayed
+        let request = token_request(assertion, "urn:mas:graphql:*");
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidGrant);
+
+        // Assertions without a `jti` are rejected
+        let assertion = sign_with(
+            "https://sso.example.com/",
+            "alice",
+            None,
+            Duration::minutes(5),
+        );
+        let request = token_request(assertion, "urn:mas:graphql:*");
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidGrant);
+
+        // And so are long-lived assertions
+        let assertion = sign_with(
+            "https://sso.example.com/",
+            "alice",
+            Some("long-lived"),
+            Duration::days(1),
+        );
+        let request = token_request(assertion, "urn:mas:graphql:*");
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidGrant);
+
+        // Other clients can't exchange the assertions of this issuer
+        let request = token_request_for(
+            &other_client.client_id,
+            sign("https://sso.example.com/", "alice"),
+            "urn:mas:graphql:*",
+        );
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::UnauthorizedClient);
+
+        // The issuer is not trusted for other scopes
+        let request = token_request(sign("https://sso.example.com/", "alice"), "openid");
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidScope);
+
+        // Nor for other users
+        let request = token_request(sign("https://sso.example.com/", "bob"), "urn:mas:graphql:*");
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidGrant);
+
+        // Quarantined users can't get new tokens
+        let request = token_request(
+            sign("https://sso.example.com/", "carol"),
+            "urn:mas:graphql:*",
+        );
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidGrant);
+
+        // Assertions from unknown issuers are rejected
+        let request = token_request(
+            sign("https://evil.example.com/", "alice"),
+            "urn:mas:graphql:*",
+        );
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidGrant);
+
+        // And so are assertions with a bad signature
+        let assertion = format!("{}x", sign("https://sso.example.com/", "alice"));
+        let request = token_request(assertion, "urn:mas:graphql:*");
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidGrant);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_client_rate_limit(pool: PgPool) {
+        init_tracing();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        state.limiter = Limiter::new(&RateLimits {
+            token_per_client: BucketConfig {
+                burst: NonZeroU32::new(1).unwrap(),
+                per_second: 0.1,
+            },
+            ..RateLimits::default()
+        });
+
+        // Provision a client
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "contacts": ["contact@example.com"],
+                "token_endpoint_auth_method": "client_secret_post",
+                "grant_types": ["client_credentials"],
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+
+        let response: ClientRegistrationResponse = response.json();
+        let client_id = response.client_id;
+        let client_secret = response.client_secret.expect("to have a client secret");
+
+        // Requests failing to authenticate don't count against the client limit
+        for _ in 0..2 {
+            let request =
+                Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                    "grant_type": "client_credentials",
+                    "client_id": client_id,
+                    "client_secret": "wrong",
+                }));
+
+            let response = state.request(request).await;
+            response.assert_status(StatusCode::UNAUTHORIZED);
+        }
+
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "client_credentials",
+                "client_id": client_id,
+                "client_secret": client_secret,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        // The authenticated client ran out of requests
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "client_credentials",
+                "client_id": client_id,
+                "client_secret": client_secret,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::TOO_MANY_REQUESTS);
     }
 
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
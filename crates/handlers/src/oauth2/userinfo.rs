@@ -12,11 +12,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
+
 use axum::{
     extract::State,
     response::{IntoResponse, Response},
-    Json,
+    Json, TypedHeader,
 };
+use headers::UserAgent;
 use hyper::StatusCode;
 use mas_axum_utils::{
     jwt::JwtResponse,
@@ -28,16 +31,21 @@ use mas_jose::{
     jwt::{JsonWebSignatureHeader, Jwt},
 };
 use mas_keystore::Keystore;
+use mas_policy::{Policy, Requester};
 use mas_router::UrlBuilder;
 use mas_storage::{
     oauth2::OAuth2ClientRepository, user::UserEmailRepository, BoxClock, BoxRepository, BoxRng,
+    Clock,
 };
 use oauth2_types::scope;
 use serde::Serialize;
 use serde_with::skip_serializing_none;
 use thiserror::Error;
 
-use crate::{impl_from_error_for_route, BoundActivityTracker};
+use super::mapped_claims;
+use crate::{
+    impl_from_error_for_route, BoundActivityTracker, SharedHomeserverConnection, SiteConfig,
+};
 
 #[skip_serializing_none]
 #[derive(Serialize)]
@@ -46,8 +54,16 @@ struct UserInfo {
     username: String,
     email: Option<String>,
     email_verified: Option<bool>,
+
+    /// The claims mapped from the attributes of the user
+    #[serde(flatten)]
+    mapped_claims: HashMap<String, serde_json::Value>,
 }
 
+/// Claims which can't be set through the claim mappings, as they are already
+/// part of the userinfo response
+const RESERVED_CLAIMS: [&str; 6] = ["sub", "username", "email", "email_verified", "iss", "aud"];
+
 #[derive(Serialize)]
 struct SignedUserInfo {
     iss: String,
@@ -82,6 +98,7 @@ pub enum RouteError {
 impl_from_error_for_route!(mas_storage::RepositoryError);
 impl_from_error_for_route!(mas_keystore::WrongAlgorithmError);
 impl_from_error_for_route!(mas_jose::jwt::JwtSignatureError);
+impl_from_error_for_route!(super::MappedClaimsError);
 
 impl IntoResponse for RouteError {
     fn into_response(self) -> axum::response::Response {
@@ -107,6 +124,10 @@ pub async fn get(
     activity_tracker: BoundActivityTracker,
     mut repo: BoxRepository,
     State(key_store): State<Keystore>,
+    State(site_config): State<SiteConfig>,
+    State(homeserver): State<SharedHomeserverConnection>,
+    mut policy: Policy,
+    user_agent: Option<TypedHeader<UserAgent>>,
     user_authorization: UserAuthorization,
 ) -> Result<Response, RouteError> {
     let session = user_authorization.protected(&mut repo, &clock).await?;
@@ -137,19 +158,37 @@ pub async fn get(
         None
     };
 
+    let client = repo
+        .oauth2_client()
+        .lookup(session.client_id)
+        .await?
+        .ok_or(RouteError::NoSuchClient)?;
+
+    let requester = Requester::new(clock.now())
+        .with_ip_address(activity_tracker.ip())
+        .with_user_agent(user_agent.map(|ua| ua.as_str().to_owned()));
+
+    let mut mapped_claims = mapped_claims(
+        &mut repo,
+        homeserver.as_ref(),
+        &mut policy,
+        &requester,
+        &site_config.claim_mappings,
+        &client,
+        &user,
+        &session.scope,
+    )
+    .await?;
+    mapped_claims.retain(|claim, _| !RESERVED_CLAIMS.contains(&claim.as_str()));
+
     let user_info = UserInfo {
         sub: user.sub.clone(),
         username: user.username.clone(),
         email_verified: user_email.as_ref().map(|u| u.confirmed_at.is_some()),
         email: user_email.map(|u| u.email),
+        mapped_claims,
     };
 
-    let client = repo
-        .oauth2_client()
-        .lookup(session.client_id)
-        .await?
-        .ok_or(RouteError::NoSuchClient)?;
-
     if let Some(alg) = client.userinfo_signed_response_alg {
         let key = key_store
             .signing_key_for_algorithm(&alg)
@@ -171,3 +210,149 @@ pub async fn get(
         Ok(Json(user_info).into_response())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::Duration;
+    use hyper::{header::CONTENT_TYPE, Request, StatusCode};
+    use mas_data_model::{AccessToken, ClaimMapping, UserAttribute};
+    use mas_jose::jwt::Jwt;
+    use mas_router::{OAuth2RegistrationEndpoint, OidcUserinfo, SimpleRoute};
+    use oauth2_types::{
+        registration::ClientRegistrationResponse,
+        scope::{Scope, OPENID},
+    };
+    use serde_json::{json, Value};
+    use sqlx::PgPool;
+
+    use crate::{
+        oauth2::generate_token_pair,
+        test_utils::{init_tracing, RequestBuilderExt, ResponseExt, TestState},
+    };
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_userinfo(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        // Provision a client getting plain userinfo responses, and one getting
+        // them signed
+        let mut client_ids = Vec::new();
+        for userinfo_signed_response_alg in [None, Some("RS256")] {
+            let request = Request::post(OAuth2RegistrationEndpoint::PATH).json(json!({
+                "contacts": ["hello@client.com"],
+                "client_uri": "https://client.com/",
+                "redirect_uris": ["https://client.com/"],
+                "response_types": ["code"],
+                "grant_types": ["authorization_code"],
+                "token_endpoint_auth_method": "none",
+                "userinfo_signed_response_alg": userinfo_signed_response_alg,
+            }));
+
+            let response = state.request(request).await;
+            response.assert_status(StatusCode::CREATED);
+            let ClientRegistrationResponse { client_id, .. } = response.json();
+            client_ids.push(client_id);
+        }
+
+        // Provision a user with a session on each client
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let browser_session = repo
+            .browser_session()
+            .add(&mut state.rng(), &state.clock, &user, None)
+            .await
+            .unwrap();
+
+        let mut access_tokens = Vec::new();
+        for client_id in &client_ids {
+            let client = repo
+                .oauth2_client()
+                .find_by_client_id(client_id)
+                .await
+                .unwrap()
+                .unwrap();
+
+            let session = repo
+                .oauth2_session()
+                .add_from_browser_session(
+                    &mut state.rng(),
+                    &state.clock,
+                    &client,
+                    &browser_session,
+                    Scope::from_iter([OPENID]),
+                )
+                .await
+                .unwrap();
+
+            let (AccessToken { access_token, .. }, _) = generate_token_pair(
+                &mut state.rng(),
+                &state.clock,
+                &mut repo,
+                &session,
+                Duration::minutes(5),
+            )
+            .await
+            .unwrap();
+
+            access_tokens.push(access_token);
+        }
+
+        // Map the admin flag of the user to a claim for the first client
+        let client = repo
+            .oauth2_client()
+            .find_by_client_id(&client_ids[0])
+            .await
+            .unwrap()
+            .unwrap();
+        repo.oauth2_client()
+            .set_claim_mappings(
+                &client,
+                &[ClaimMapping {
+                    claim: "is_admin".to_owned(),
+                    attribute: UserAttribute::IsAdmin,
+                    scope: None,
+                }],
+            )
+            .await
+            .unwrap();
+
+        repo.save().await.unwrap();
+
+        // The first client gets plain JSON
+        let request = Request::get(OidcUserinfo::PATH)
+            .bearer(&access_tokens[0])
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let claims: HashMap<String, Value> = response.json();
+        assert_eq!(claims["sub"], json!(user.sub));
+        assert_eq!(claims["username"], json!("alice"));
+        assert_eq!(claims["is_admin"], json!(false));
+
+        // The second one gets a JWT signed by the server keys
+        let request = Request::get(OidcUserinfo::PATH)
+            .bearer(&access_tokens[1])
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        response.assert_header_value(CONTENT_TYPE, "application/jwt");
+
+        let jwt: Jwt<'_, HashMap<String, Value>> = Jwt::try_from(response.body().as_str()).unwrap();
+        jwt.verify_with_jwks(&state.key_store.public_jwks())
+            .unwrap();
+
+        let claims = jwt.payload();
+        assert_eq!(claims["iss"], json!(state.url_builder.oidc_issuer()));
+        assert_eq!(claims["aud"], json!(client_ids[1]));
+        assert_eq!(claims["sub"], json!(user.sub));
+        assert_eq!(claims["username"], json!("alice"));
+    }
+}
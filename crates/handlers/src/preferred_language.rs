@@ -20,9 +20,19 @@ use axum::{
     http::request::Parts,
     TypedHeader,
 };
-use mas_axum_utils::language_detection::AcceptLanguage;
+use mas_axum_utils::{
+    cookies::{CookieJar, CookieManager},
+    language_detection::AcceptLanguage,
+};
 use mas_i18n::{DataLocale, Translator};
 
+/// The cookie holding the language picked by the user
+pub(crate) const LANGUAGE_COOKIE: &str = "mas-language";
+
+/// The language to render the pages in.
+///
+/// This is the language picked by the user if any, else the first language
+/// of the `Accept-Language` header the service has translations for.
 pub struct PreferredLanguage(pub DataLocale);
 
 #[async_trait]
@@ -30,20 +40,32 @@ impl<S> FromRequestParts<S> for PreferredLanguage
 where
     S: Send + Sync,
     Arc<Translator>: FromRef<S>,
+    CookieManager: FromRef<S>,
 {
     type Rejection = Infallible;
 
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
         let translator: Arc<Translator> = FromRef::from_ref(state);
+        let cookie_jar: CookieJar = FromRequestParts::from_request_parts(parts, state).await?;
         let accept_language: Option<TypedHeader<AcceptLanguage>> =
             FromRequestParts::from_request_parts(parts, state).await?;
         let supported_language = translator.available_locales();
 
-        let locale = accept_language
-            .and_then(|TypedHeader(accept_language)| {
-                accept_language.iter().find_map(|lang| {
-                    let locale: DataLocale = lang.into();
-                    supported_language.contains(&&locale).then_some(locale)
+        // An invalid or unsupported language in the cookie is ignored
+        let picked = cookie_jar
+            .load::<String>(LANGUAGE_COOKIE)
+            .ok()
+            .flatten()
+            .and_then(|lang| lang.parse::<DataLocale>().ok())
+            .filter(|locale| translator.has_locale(locale));
+
+        let locale = picked
+            .or_else(|| {
+                accept_language.and_then(|TypedHeader(accept_language)| {
+                    accept_language.iter().find_map(|lang| {
+                        let locale: DataLocale = lang.into();
+                        supported_language.contains(&&locale).then_some(locale)
+                    })
                 })
             })
             .unwrap_or("en".parse().unwrap());
@@ -0,0 +1,771 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rate limiting of the sensitive endpoints, using keyed token buckets
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    num::NonZeroU32,
+    sync::{Arc, Mutex},
+};
+
+use axum::response::{IntoResponseParts, ResponseParts};
+use chrono::{DateTime, Duration, Utc};
+use hyper::header::{HeaderName, HeaderValue, RETRY_AFTER};
+use mas_data_model::Client;
+use mas_storage::{rate_limit::RateLimitBucket, Clock, Repository, RepositoryAccess, SystemClock};
+use mas_storage_pg::{DatabaseError, PgRepository};
+use sqlx::PgPool;
+use thiserror::Error;
+
+/// Above this number of tracked keys, buckets which are full again are
+/// forgotten
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// How often the buckets which are full again are removed from the database
+const CLEANUP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+
+/// Parameters of a token bucket
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BucketConfig {
+    /// Maximum number of requests allowed in a burst
+    pub burst: NonZeroU32,
+
+    /// Number of requests regained every second
+    pub per_second: f64,
+}
+
+impl BucketConfig {
+    fn new(burst: u32, per_second: f64) -> Self {
+        let burst = NonZeroU32::new(burst).expect("burst must not be zero");
+        Self { burst, per_second }
+    }
+
+    fn capacity(&self) -> f64 {
+        f64::from(self.burst.get())
+    }
+
+    /// How long it takes for an empty bucket to be full again
+    fn time_to_full(&self) -> Duration {
+        #[allow(clippy::cast_possible_truncation)]
+        let millis = (self.capacity() / self.per_second * 1000.0).ceil() as i64;
+        Duration::milliseconds(millis)
+    }
+
+    /// The number of tokens in `bucket` at `now`
+    fn tokens_at(&self, bucket: &RateLimitBucket, now: DateTime<Utc>) -> f64 {
+        #[allow(clippy::cast_precision_loss)]
+        let elapsed = (now - bucket.updated_at).num_milliseconds().max(0) as f64 / 1000.0;
+        (bucket.tokens + elapsed * self.per_second).min(self.capacity())
+    }
+
+    /// Take a token from `bucket` if there is one left, returning the new state
+    /// of the bucket. Buckets which were never used are full.
+    fn take(
+        &self,
+        bucket: Option<RateLimitBucket>,
+        now: DateTime<Utc>,
+    ) -> (RateLimitBucket, Result<(), RateLimited>) {
+        let tokens = bucket.map_or(self.capacity(), |bucket| self.tokens_at(&bucket, now));
+
+        if tokens >= 1.0 {
+            let bucket = RateLimitBucket {
+                tokens: tokens - 1.0,
+                updated_at: now,
+            };
+            (bucket, Ok(()))
+        } else {
+            let bucket = RateLimitBucket {
+                tokens,
+                updated_at: now,
+            };
+            let err = RateLimited {
+                limit: self.burst.get(),
+                retry_after: self.seconds_to_regain(1.0 - tokens),
+                reset: self.seconds_to_regain(self.capacity() - tokens),
+            };
+            (bucket, Err(err))
+        }
+    }
+
+    /// How many seconds it takes to regain `tokens` requests
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn seconds_to_regain(&self, tokens: f64) -> u64 {
+        (tokens / self.per_second).ceil().max(0.0) as u64
+    }
+}
+
+/// Progressive delay applied to login attempts from IP addresses which
+/// recently failed to log in too many times
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoginFailureDelay {
+    /// Number of failed attempts allowed before delaying the next ones
+    pub free_attempts: u32,
+
+    /// Delay once the free attempts are exhausted, doubled with every
+    /// subsequent failure
+    pub base_delay: std::time::Duration,
+
+    /// Maximum delay applied to an attempt
+    pub max_delay: std::time::Duration,
+
+    /// How long failed attempts are remembered after the last one
+    pub window: Duration,
+}
+
+impl LoginFailureDelay {
+    /// The delay to apply after the given number of failed attempts
+    fn delay(&self, failures: u32) -> std::time::Duration {
+        let Some(excess) = failures.checked_sub(self.free_attempts) else {
+            return std::time::Duration::ZERO;
+        };
+
+        let factor = 2_u32.saturating_pow(excess);
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+}
+
+/// The rate limits applied to the sensitive endpoints
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimits {
+    /// Whether the limits are enforced at all
+    pub enabled: bool,
+
+    /// Login attempts from a single IP address
+    pub login_per_ip: BucketConfig,
+
+    /// Login attempts on a single account
+    pub login_per_account: BucketConfig,
+
+    /// Delay applied to login attempts after repeated failures, if enabled
+    pub login_failure_delay: Option<LoginFailureDelay>,
+
+    /// Registrations from a single IP address
+    pub registration_per_ip: BucketConfig,
+
+    /// Token requests from a single IP address
+    pub token_per_ip: BucketConfig,
+
+    /// Token requests made by a single client from a single IP address
+    pub token_per_client: BucketConfig,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            login_per_ip: BucketConfig::new(30, 30.0 / 60.0),
+            login_per_account: BucketConfig::new(1800, 1800.0 / 3600.0),
+            login_failure_delay: None,
+            registration_per_ip: BucketConfig::new(10, 10.0 / 3600.0),
+            token_per_ip: BucketConfig::new(300, 5.0),
+            token_per_client: BucketConfig::new(3000, 50.0),
+        }
+    }
+}
+
+/// A request was refused because it went over a rate limit
+///
+/// When added to a response, it sets the `Retry-After` header, along with the
+/// `RateLimit-*` headers from the IETF `RateLimit` header fields draft.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("rate limit exceeded, retry in {retry_after} seconds")]
+pub struct RateLimited {
+    limit: u32,
+    retry_after: u64,
+    reset: u64,
+}
+
+impl RateLimited {
+    /// How many seconds to wait before retrying
+    #[must_use]
+    pub fn retry_after(&self) -> u64 {
+        self.retry_after
+    }
+}
+
+impl IntoResponseParts for RateLimited {
+    type Error = std::convert::Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        let headers = res.headers_mut();
+        headers.insert(RETRY_AFTER, HeaderValue::from(self.retry_after));
+        headers.insert(
+            HeaderName::from_static("ratelimit-limit"),
+            HeaderValue::from(self.limit),
+        );
+        headers.insert(
+            HeaderName::from_static("ratelimit-remaining"),
+            HeaderValue::from(0),
+        );
+        headers.insert(
+            HeaderName::from_static("ratelimit-reset"),
+            HeaderValue::from(self.reset),
+        );
+        Ok(res)
+    }
+}
+
+/// The limiters enforced by a [`Limiter`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Kind {
+    LoginPerIp,
+    LoginPerAccount,
+    LoginFailuresPerIp,
+    RegistrationPerIp,
+    TokenPerIp,
+    TokenPerClient,
+}
+
+impl Kind {
+    const ALL: [Self; 6] = [
+        Self::LoginPerIp,
+        Self::LoginPerAccount,
+        Self::LoginFailuresPerIp,
+        Self::RegistrationPerIp,
+        Self::TokenPerIp,
+        Self::TokenPerClient,
+    ];
+
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::LoginPerIp => "login_per_ip",
+            Self::LoginPerAccount => "login_per_account",
+            Self::LoginFailuresPerIp => "login_failures_per_ip",
+            Self::RegistrationPerIp => "registration_per_ip",
+            Self::TokenPerIp => "token_per_ip",
+            Self::TokenPerClient => "token_per_client",
+        }
+    }
+
+    /// How long after its last update the state of a bucket can be forgotten
+    fn expiry(self, limits: &RateLimits) -> Duration {
+        match self {
+            Self::LoginPerIp => limits.login_per_ip.time_to_full(),
+            Self::LoginPerAccount => limits.login_per_account.time_to_full(),
+            Self::LoginFailuresPerIp => limits
+                .login_failure_delay
+                .map_or_else(Duration::zero, |delay| delay.window),
+            Self::RegistrationPerIp => limits.registration_per_ip.time_to_full(),
+            Self::TokenPerIp => limits.token_per_ip.time_to_full(),
+            Self::TokenPerClient => limits.token_per_client.time_to_full(),
+        }
+    }
+}
+
+/// Where the buckets are kept
+#[derive(Debug)]
+enum Store {
+    /// In memory, local to this instance
+    Memory(Mutex<HashMap<(Kind, String), RateLimitBucket>>),
+
+    /// In the database, shared between all the instances
+    Postgres(PgPool),
+}
+
+#[derive(Debug)]
+struct LimiterInner {
+    limits: RateLimits,
+    store: Store,
+}
+
+impl LimiterInner {
+    /// Atomically update the bucket of `key` in the given limiter
+    ///
+    /// `f` gets the current state of the bucket, `None` if it is unknown or
+    /// expired, and returns the new state to save, if any.
+    async fn update<T, F>(
+        &self,
+        now: DateTime<Utc>,
+        kind: Kind,
+        key: &str,
+        f: F,
+    ) -> Result<T, DatabaseError>
+    where
+        F: FnOnce(Option<RateLimitBucket>) -> (Option<RateLimitBucket>, T) + Send,
+        T: Send,
+    {
+        let expiry = kind.expiry(&self.limits);
+        let is_fresh = |bucket: &RateLimitBucket| now - bucket.updated_at < expiry;
+
+        match &self.store {
+            Store::Memory(buckets) => {
+                let mut buckets = buckets.lock().expect("rate limiter lock poisoned");
+
+                if buckets.len() >= PRUNE_THRESHOLD {
+                    buckets.retain(|(kind, _), bucket| {
+                        now - bucket.updated_at < kind.expiry(&self.limits)
+                    });
+                }
+
+                let key = (kind, key.to_owned());
+                let bucket = buckets.get(&key).copied().filter(is_fresh);
+                let (bucket, res) = f(bucket);
+                if let Some(bucket) = bucket {
+                    buckets.insert(key, bucket);
+                }
+                Ok(res)
+            }
+
+            Store::Postgres(pool) => {
+                let mut repo = PgRepository::from_pool(pool).await?.boxed();
+                let bucket = repo
+                    .rate_limit()
+                    .lock_bucket(kind.as_str(), key)
+                    .await?
+                    .filter(is_fresh);
+                let (bucket, res) = f(bucket);
+                if let Some(bucket) = bucket {
+                    repo.rate_limit()
+                        .save_bucket(kind.as_str(), key, bucket)
+                        .await?;
+                }
+                repo.save().await?;
+                Ok(res)
+            }
+        }
+    }
+}
+
+/// Tracks the rate limits of the sensitive endpoints
+///
+/// By default the state is kept in memory, so each server instance enforces
+/// the limits separately. It can instead be kept in the database, to share it
+/// between instances. Requests of which the IP address is unknown are only
+/// subject to the limits not keyed by IP address.
+#[derive(Debug, Clone)]
+pub struct Limiter {
+    inner: Arc<LimiterInner>,
+}
+
+impl Default for Limiter {
+    fn default() -> Self {
+        Self::new(&RateLimits::default())
+    }
+}
+
+impl Limiter {
+    /// Create a new limiter enforcing the given limits, keeping its state in
+    /// memory
+    #[must_use]
+    pub fn new(limits: &RateLimits) -> Self {
+        Self {
+            inner: Arc::new(LimiterInner {
+                limits: *limits,
+                store: Store::Memory(Mutex::new(HashMap::new())),
+            }),
+        }
+    }
+
+    /// Create a new limiter enforcing the given limits, keeping its state in
+    /// the database, spawning a task which regularly cleans it up
+    #[must_use]
+    pub fn shared(limits: &RateLimits, pool: PgPool) -> Self {
+        let limiter = Self {
+            inner: Arc::new(LimiterInner {
+                limits: *limits,
+                store: Store::Postgres(pool),
+            }),
+        };
+
+        tokio::spawn(limiter.clone().cleanup_loop());
+
+        limiter
+    }
+
+    /// Take a token from the bucket of `key` in the given limiter
+    async fn take(
+        &self,
+        now: DateTime<Utc>,
+        kind: Kind,
+        config: &BucketConfig,
+        key: &str,
+    ) -> Result<(), RateLimited> {
+        if !self.inner.limits.enabled {
+            return Ok(());
+        }
+
+        let res = self
+            .inner
+            .update(now, kind, key, |bucket| {
+                let (bucket, res) = config.take(bucket, now);
+                (Some(bucket), res)
+            })
+            .await;
+
+        match res {
+            Ok(res) => res,
+            Err(e) => {
+                // Don't lock everyone out because the database is struggling
+                tracing::warn!(
+                    error = &e as &dyn std::error::Error,
+                    rate_limit.limiter = kind.as_str(),
+                    "Failed to check the rate limit, letting the request through"
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Regularly remove the expired buckets from the database
+    async fn cleanup_loop(self) {
+        let Store::Postgres(pool) = &self.inner.store else {
+            return;
+        };
+
+        let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+        loop {
+            interval.tick().await;
+
+            let res: Result<(), DatabaseError> = async {
+                let now = SystemClock::default().now();
+                let mut repo = PgRepository::from_pool(pool).await?.boxed();
+                for kind in Kind::ALL {
+                    let count = repo
+                        .rate_limit()
+                        .cleanup(kind.as_str(), now - kind.expiry(&self.inner.limits))
+                        .await?;
+                    tracing::debug!(
+                        rate_limit.limiter = kind.as_str(),
+                        count,
+                        "Cleaned up expired rate limit buckets"
+                    );
+                }
+                repo.save().await?;
+                Ok(())
+            }
+            .await;
+
+            if let Err(e) = res {
+                tracing::warn!(
+                    error = &e as &dyn std::error::Error,
+                    "Failed to clean up the rate limit buckets"
+                );
+            }
+        }
+    }
+
+    /// Check a login attempt on the given username
+    pub(crate) async fn check_login(
+        &self,
+        now: DateTime<Utc>,
+        ip: Option<IpAddr>,
+        username: &str,
+    ) -> Result<(), RateLimited> {
+        let limits = &self.inner.limits;
+        if let Some(ip) = ip {
+            self.take(now, Kind::LoginPerIp, &limits.login_per_ip, &ip.to_string())
+                .await?;
+        }
+
+        self.take(
+            now,
+            Kind::LoginPerAccount,
+            &limits.login_per_account,
+            &username.to_lowercase(),
+        )
+        .await
+    }
+
+    /// The login failure delay configuration, if the limits are enforced and
+    /// the delay is enabled
+    fn login_failure_delay(&self) -> Option<LoginFailureDelay> {
+        let limits = &self.inner.limits;
+        limits.login_failure_delay.filter(|_| limits.enabled)
+    }
+
+    /// How long to wait before processing a login attempt from the given IP
+    /// address, given how many times it failed to log in recently
+    async fn login_delay(&self, now: DateTime<Utc>, ip: Option<IpAddr>) -> std::time::Duration {
+        let (Some(config), Some(ip)) = (self.login_failure_delay(), ip) else {
+            return std::time::Duration::ZERO;
+        };
+
+        let res = self
+            .inner
+            .update(now, Kind::LoginFailuresPerIp, &ip.to_string(), |bucket| {
+                (None, bucket.map_or(0.0, |bucket| bucket.tokens))
+            })
+            .await;
+
+        match res {
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            Ok(failures) => config.delay(failures as u32),
+            Err(e) => {
+                tracing::warn!(
+                    error = &e as &dyn std::error::Error,
+                    "Failed to count the recent login failures, not delaying the request"
+                );
+                std::time::Duration::ZERO
+            }
+        }
+    }
+
+    /// Wait before processing a login attempt, if the IP address it comes
+    /// from failed to log in too many times recently
+    pub(crate) async fn delay_login(&self, now: DateTime<Utc>, ip: Option<IpAddr>) {
+        let delay = self.login_delay(now, ip).await;
+        if !delay.is_zero() {
+            tracing::debug!(?ip, ?delay, "Delaying login attempt");
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Record a failed login attempt from the given IP address
+    pub(crate) async fn record_login_failure(&self, now: DateTime<Utc>, ip: Option<IpAddr>) {
+        let (Some(_), Some(ip)) = (self.login_failure_delay(), ip) else {
+            return;
+        };
+
+        let res = self
+            .inner
+            .update(now, Kind::LoginFailuresPerIp, &ip.to_string(), |bucket| {
+                let failures = bucket.map_or(0.0, |bucket| bucket.tokens);
+                let bucket = RateLimitBucket {
+                    tokens: failures + 1.0,
+                    updated_at: now,
+                };
+                (Some(bucket), ())
+            })
+            .await;
+
+        if let Err(e) = res {
+            tracing::warn!(
+                error = &e as &dyn std::error::Error,
+                "Failed to record the login failure"
+            );
+        }
+    }
+
+    /// Check a registration attempt
+    pub(crate) async fn check_registration(
+        &self,
+        now: DateTime<Utc>,
+        ip: Option<IpAddr>,
+    ) -> Result<(), RateLimited> {
+        if let Some(ip) = ip {
+            self.take(
+                now,
+                Kind::RegistrationPerIp,
+                &self.inner.limits.registration_per_ip,
+                &ip.to_string(),
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Check a request on the token endpoint from the given IP address
+    pub(crate) async fn check_token(
+        &self,
+        now: DateTime<Utc>,
+        ip: Option<IpAddr>,
+    ) -> Result<(), RateLimited> {
+        if let Some(ip) = ip {
+            self.take(
+                now,
+                Kind::TokenPerIp,
+                &self.inner.limits.token_per_ip,
+                &ip.to_string(),
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Check a request on the token endpoint made by the given client, once it
+    /// is authenticated
+    ///
+    /// The bucket is keyed on both the client and the IP address, so that
+    /// requests sent on behalf of a client cannot exhaust its limit for
+    /// everyone else.
+    pub(crate) async fn check_token_client(
+        &self,
+        now: DateTime<Utc>,
+        ip: Option<IpAddr>,
+        client: &Client,
+    ) -> Result<(), RateLimited> {
+        let key = match ip {
+            Some(ip) => format!("{}:{ip}", client.id),
+            None => client.id.to_string(),
+        };
+
+        self.take(
+            now,
+            Kind::TokenPerClient,
+            &self.inner.limits.token_per_client,
+            &key,
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket() {
+        let now = DateTime::parse_from_rfc3339("2023-11-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let config = BucketConfig::new(2, 0.5);
+
+        let (bucket, res) = config.take(None, now);
+        assert!(res.is_ok());
+        let (bucket, res) = config.take(Some(bucket), now);
+        assert!(res.is_ok());
+        let (bucket, res) = config.take(Some(bucket), now);
+        let err = res.unwrap_err();
+        assert_eq!(err.limit, 2);
+        assert_eq!(err.retry_after, 2);
+        assert_eq!(err.reset, 4);
+
+        // One token is regained after two seconds
+        let now = now + Duration::seconds(1);
+        let (bucket, res) = config.take(Some(bucket), now);
+        assert!(res.is_err());
+        let now = now + Duration::seconds(1);
+        let (bucket, res) = config.take(Some(bucket), now);
+        assert!(res.is_ok());
+        let (bucket, res) = config.take(Some(bucket), now);
+        assert!(res.is_err());
+
+        // The bucket never holds more than the burst
+        let now = now + Duration::hours(1);
+        assert!((config.tokens_at(&bucket, now) - 2.0).abs() < f64::EPSILON);
+        assert_eq!(config.time_to_full(), Duration::seconds(4));
+    }
+
+    #[tokio::test]
+    async fn test_login_limits() {
+        let now = DateTime::parse_from_rfc3339("2023-11-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let limiter = Limiter::new(&RateLimits {
+            login_per_ip: BucketConfig::new(2, 0.1),
+            login_per_account: BucketConfig::new(1, 0.1),
+            ..RateLimits::default()
+        });
+        let ip = Some(IpAddr::from([192, 0, 2, 1]));
+
+        assert!(limiter.check_login(now, ip, "alice").await.is_ok());
+        // Usernames are compared case-insensitively
+        assert!(limiter.check_login(now, None, "Alice").await.is_err());
+        assert!(limiter.check_login(now, ip, "bob").await.is_ok());
+        // The IP address ran out of attempts
+        assert!(limiter.check_login(now, ip, "charlie").await.is_err());
+        assert!(limiter.check_login(now, None, "charlie").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_login_failure_delay() {
+        let now = DateTime::parse_from_rfc3339("2023-11-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let delay = LoginFailureDelay {
+            free_attempts: 2,
+            base_delay: std::time::Duration::from_secs(1),
+            max_delay: std::time::Duration::from_secs(5),
+            window: Duration::hours(1),
+        };
+        assert_eq!(delay.delay(1), std::time::Duration::ZERO);
+        assert_eq!(delay.delay(2), std::time::Duration::from_secs(1));
+        assert_eq!(delay.delay(3), std::time::Duration::from_secs(2));
+        assert_eq!(delay.delay(4), std::time::Duration::from_secs(4));
+        assert_eq!(delay.delay(5), std::time::Duration::from_secs(5));
+        assert_eq!(delay.delay(u32::MAX), std::time::Duration::from_secs(5));
+
+        let limiter = Limiter::new(&RateLimits {
+            login_failure_delay: Some(delay),
+            ..RateLimits::default()
+        });
+        let ip = Some(IpAddr::from([192, 0, 2, 1]));
+
+        for _ in 0..3 {
+            limiter.record_login_failure(now, ip).await;
+        }
+        assert_eq!(
+            limiter.login_delay(now, ip).await,
+            std::time::Duration::from_secs(2)
+        );
+
+        // Other addresses are not affected
+        let other = Some(IpAddr::from([192, 0, 2, 2]));
+        assert_eq!(
+            limiter.login_delay(now, other).await,
+            std::time::Duration::ZERO
+        );
+
+        // Failures are forgotten once the window is over
+        let now = now + Duration::hours(1);
+        assert_eq!(
+            limiter.login_delay(now, ip).await,
+            std::time::Duration::ZERO
+        );
+    }
+
+    #[tokio::test]
+    async fn test_disabled_limits() {
+        let now = DateTime::parse_from_rfc3339("2023-11-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let limiter = Limiter::new(&RateLimits {
+            enabled: false,
+            login_per_account: BucketConfig::new(1, 0.1),
+            login_failure_delay: Some(LoginFailureDelay {
+                free_attempts: 0,
+                base_delay: std::time::Duration::from_secs(1),
+                max_delay: std::time::Duration::from_secs(5),
+                window: Duration::hours(1),
+            }),
+            ..RateLimits::default()
+        });
+        let ip = Some(IpAddr::from([192, 0, 2, 1]));
+
+        assert!(limiter.check_login(now, ip, "alice").await.is_ok());
+        assert!(limiter.check_login(now, ip, "alice").await.is_ok());
+
+        limiter.record_login_failure(now, ip).await;
+        assert_eq!(
+            limiter.login_delay(now, ip).await,
+            std::time::Duration::ZERO
+        );
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_shared_limits(pool: PgPool) {
+        let now = DateTime::parse_from_rfc3339("2023-11-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let limits = RateLimits {
+            login_per_account: BucketConfig::new(1, 0.1),
+            ..RateLimits::default()
+        };
+
+        // Two limiters sharing the same database share their buckets
+        let first = Limiter::shared(&limits, pool.clone());
+        let second = Limiter::shared(&limits, pool);
+
+        assert!(first.check_login(now, None, "alice").await.is_ok());
+        assert!(second.check_login(now, None, "alice").await.is_err());
+
+        let now = now + Duration::seconds(10);
+        assert!(second.check_login(now, None, "alice").await.is_ok());
+        assert!(first.check_login(now, None, "alice").await.is_err());
+    }
+}
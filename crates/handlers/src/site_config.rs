@@ -12,20 +12,117 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::BTreeMap;
+
 use chrono::Duration;
+use mas_data_model::{ClaimMapping, EmailRateLimits, JwksOrJwksUri, OAuth2Features, ScopeRegistry};
+use ulid::Ulid;
+use url::Url;
+
+/// Configuration of the `org.matrix.login.jwt` login type
+#[derive(Debug, Clone)]
+pub struct JwtLoginConfig {
+    /// The keys used to verify the tokens
+    pub jwks: JwksOrJwksUri,
+
+    /// The claim holding the localpart of the user
+    pub subject_claim: String,
+
+    /// The expected `iss` claim, if any
+    pub issuer: Option<String>,
+
+    /// The expected `aud` claim, if any
+    pub audience: Option<String>,
+}
+
+/// Configuration of the `/.well-known/matrix/client` document
+#[derive(Debug, Clone)]
+pub struct ClientWellKnownConfig {
+    /// The public base URL of the homeserver
+    pub homeserver_base_url: Url,
+
+    /// Extra fields added to the document
+    pub extra: BTreeMap<String, serde_json::Value>,
+}
+
+/// Attributes users must have on their account before signing in to a client
+#[derive(Debug, Clone, Default)]
+pub struct AccountRequirements {
+    /// Whether users must have a verified email address
+    pub verified_email: bool,
+
+    /// Whether users must have a display name set on the homeserver
+    pub displayname: bool,
+
+    /// The terms of service users must accept, if any
+    pub terms_uri: Option<Url>,
+}
 
 /// Random site configuration we don't now where to put yet.
 #[derive(Debug, Clone)]
 pub struct SiteConfig {
     pub access_token_ttl: Duration,
+    pub offline_session_ttl: Option<Duration>,
     pub compat_token_ttl: Duration,
+    pub authorization_code_ttl: Duration,
+
+    /// Leeway given when validating the time-based claims of the JWTs issued
+    /// by third parties
+    pub clock_skew: Duration,
+
+    /// How far in the future the `exp` claim of the assertions exchanged
+    /// through the JWT bearer grant can be
+    pub jwt_bearer_max_assertion_lifetime: Duration,
+
+    pub impersonation_ttl: Duration,
+    pub consent_ttl: Duration,
+    pub case_fold_usernames: bool,
+    pub compat_jwt_login: Option<JwtLoginConfig>,
+    pub guest_registration: bool,
+    pub client_well_known: Option<ClientWellKnownConfig>,
+    pub email_rate_limits: EmailRateLimits,
+    pub account_requirements: AccountRequirements,
+    pub scope_registry: ScopeRegistry,
+    pub trusted_resource_servers: Vec<String>,
+
+    /// Attributes of the users mapped to claims for all the clients
+    pub claim_mappings: Vec<ClaimMapping>,
+
+    /// The OAuth 2.0 and OpenID Connect capabilities which are enabled
+    pub oauth2_features: OAuth2Features,
+
+    /// The clients allowed to get the tokens stored for each upstream
+    /// provider, by provider ID
+    pub upstream_token_clients: BTreeMap<Ulid, Vec<Ulid>>,
+
+    /// The origins allowed to embed the pages, which get notified when an
+    /// action is completed
+    pub embedding_origins: Vec<String>,
 }
 
 impl Default for SiteConfig {
     fn default() -> Self {
         Self {
             access_token_ttl: Duration::minutes(5),
+            offline_session_ttl: None,
             compat_token_ttl: Duration::minutes(5),
+            authorization_code_ttl: Duration::minutes(10),
+            clock_skew: Duration::minutes(5),
+            jwt_bearer_max_assertion_lifetime: Duration::minutes(5),
+            impersonation_ttl: Duration::minutes(30),
+            consent_ttl: Duration::days(90),
+            case_fold_usernames: false,
+            compat_jwt_login: None,
+            guest_registration: false,
+            client_well_known: None,
+            email_rate_limits: EmailRateLimits::default(),
+            account_requirements: AccountRequirements::default(),
+            scope_registry: ScopeRegistry::default(),
+            trusted_resource_servers: Vec::new(),
+            claim_mappings: Vec::new(),
+            oauth2_features: OAuth2Features::default(),
+            upstream_token_clients: BTreeMap::new(),
+            embedding_origins: Vec::new(),
         }
     }
 }
@@ -22,6 +22,7 @@ use axum::{
     async_trait,
     body::{Bytes, HttpBody},
     extract::{FromRef, FromRequestParts},
+    response::IntoResponse,
 };
 use cookie_store::{CookieStore, RawCookie};
 use futures_util::future::BoxFuture;
@@ -31,8 +32,11 @@ use hyper::{
     Request, Response, StatusCode,
 };
 use mas_axum_utils::{
-    cookies::CookieManager, http_client_factory::HttpClientFactory, ErrorWrapper,
+    cookies::{CookieJar, CookieManager},
+    http_client_factory::HttpClientFactory,
+    ErrorWrapper, SessionInfoExt,
 };
+use mas_data_model::{BrowserSession, EmailRateLimits};
 use mas_i18n::Translator;
 use mas_keystore::{Encrypter, JsonWebKey, JsonWebKeySet, Keystore, PrivateKey};
 use mas_matrix::{HomeserverConnection, MockHomeserverConnection};
@@ -46,13 +50,14 @@ use rand_chacha::ChaChaRng;
 use serde::{de::DeserializeOwned, Serialize};
 use sqlx::PgPool;
 use tower::{Layer, Service, ServiceExt};
+use ulid::Ulid;
 use url::Url;
 
 use crate::{
     passwords::{Hasher, PasswordManager},
     site_config::SiteConfig,
-    upstream_oauth2::cache::MetadataCache,
-    ActivityTracker, BoundActivityTracker, MatrixHomeserver,
+    ActivityTracker, AppserviceRegistry, BoundActivityTracker, IntrospectionCache, IpFilter,
+    IpFilterRules, Limiter, MatrixHomeserver, SharedHomeserverConnection,
 };
 
 // This might fail if it's not the first time it's being called, which is fine,
@@ -80,6 +85,9 @@ pub(crate) async fn policy_factory(
         authorization_grant: "authorization_grant/violation".to_owned(),
         email: "email/violation".to_owned(),
         password: "password/violation".to_owned(),
+        token: "token/decision".to_owned(),
+        upstream_login: "upstream_login/violation".to_owned(),
+        claims: "claims/violation".to_owned(),
     };
 
     let policy_factory = PolicyFactory::load(file, data, entrypoints).await?;
@@ -93,15 +101,19 @@ pub(crate) struct TestState {
     pub templates: Templates,
     pub key_store: Keystore,
     pub cookie_manager: CookieManager,
-    pub metadata_cache: MetadataCache,
     pub encrypter: Encrypter,
     pub url_builder: UrlBuilder,
     pub homeserver: MatrixHomeserver,
+    pub homeserver_connection: Arc<MockHomeserverConnection>,
+    pub appservices: AppserviceRegistry,
     pub policy_factory: Arc<PolicyFactory>,
     pub graphql_schema: mas_graphql::Schema,
     pub http_client_factory: HttpClientFactory,
     pub password_manager: PasswordManager,
     pub site_config: SiteConfig,
+    pub limiter: Limiter,
+    pub ip_filter: IpFilter,
+    pub introspection_cache: IntrospectionCache,
     pub activity_tracker: ActivityTracker,
     pub clock: Arc<MockClock>,
     pub rng: Arc<Mutex<ChaChaRng>>,
@@ -136,29 +148,38 @@ impl TestState {
         let cookie_manager =
             CookieManager::derive_from("https://example.com".parse()?, &[0x42; 32]);
 
-        let metadata_cache = MetadataCache::new();
-
         let password_manager = PasswordManager::new([(1, Hasher::argon2id(None))])?;
 
         let homeserver = MatrixHomeserver::new("example.com".to_owned());
 
+        let appservices = AppserviceRegistry::default();
+
         let policy_factory = policy_factory(serde_json::json!({})).await?;
 
-        let homeserver_connection = MockHomeserverConnection::new("example.com");
+        let homeserver_connection = Arc::new(MockHomeserverConnection::new("example.com"));
 
         let http_client_factory = HttpClientFactory::new().await?;
 
         let site_config = SiteConfig::default();
 
+        let ip_filter = IpFilter::new(
+            IpFilterRules::default(),
+            http_client_factory.http_service("ip_filter"),
+        );
+
+        let introspection_cache = IntrospectionCache::disabled();
+
         let clock = Arc::new(MockClock::default());
         let rng = Arc::new(Mutex::new(ChaChaRng::seed_from_u64(42)));
 
         let graphql_state = TestGraphQLState {
             pool: pool.clone(),
             policy_factory: Arc::clone(&policy_factory),
-            homeserver_connection,
+            homeserver_connection: Arc::clone(&homeserver_connection),
             rng: Arc::clone(&rng),
             clock: Arc::clone(&clock),
+            email_rate_limits: site_config.email_rate_limits,
+            introspection_cache: introspection_cache.clone(),
         };
         let state: mas_graphql::BoxState = Box::new(graphql_state);
 
@@ -172,15 +193,19 @@ impl TestState {
             templates,
             key_store,
             cookie_manager,
-            metadata_cache,
             encrypter,
             url_builder,
             homeserver,
+            homeserver_connection,
+            appservices,
             policy_factory,
             graphql_schema,
             http_client_factory,
             password_manager,
             site_config,
+            limiter: Limiter::default(),
+            ip_filter,
+            introspection_cache,
             activity_tracker,
             clock,
             rng,
@@ -195,13 +220,7 @@ impl TestState {
         B::Error: std::error::Error + Send + Sync,
         B::Data: Send,
     {
-        let app = crate::healthcheck_router()
-            .merge(crate::discovery_router())
-            .merge(crate::api_router())
-            .merge(crate::compat_router())
-            .merge(crate::human_router(self.templates.clone()))
-            .merge(crate::graphql_router(false))
-            .with_state(self.clone());
+        let app = crate::router(self.templates.clone(), false).with_state(self.clone());
 
         // Both unwrap are on Infallible, so this is safe
         let response = app
@@ -265,10 +284,12 @@ impl TestState {
 
 struct TestGraphQLState {
     pool: PgPool,
-    homeserver_connection: MockHomeserverConnection,
+    homeserver_connection: Arc<MockHomeserverConnection>,
     policy_factory: Arc<PolicyFactory>,
     clock: Arc<MockClock>,
     rng: Arc<Mutex<ChaChaRng>>,
+    email_rate_limits: EmailRateLimits,
+    introspection_cache: IntrospectionCache,
 }
 
 #[async_trait]
@@ -288,7 +309,7 @@ impl mas_graphql::State for TestGraphQLState {
     }
 
     fn homeserver_connection(&self) -> &dyn HomeserverConnection<Error = anyhow::Error> {
-        &self.homeserver_connection
+        self.homeserver_connection.as_ref()
     }
 
     fn clock(&self) -> BoxClock {
@@ -300,6 +321,18 @@ impl mas_graphql::State for TestGraphQLState {
         let rng = ChaChaRng::from_rng(&mut *parent_rng).expect("Failed to seed RNG");
         Box::new(rng)
     }
+
+    fn email_rate_limits(&self) -> EmailRateLimits {
+        self.email_rate_limits
+    }
+
+    fn session_ended(&self, session_id: Ulid) {
+        self.introspection_cache.invalidate_session(session_id);
+    }
+
+    fn user_changed(&self, user_id: Ulid) {
+        self.introspection_cache.invalidate_user(user_id);
+    }
 }
 
 impl FromRef<TestState> for PgPool {
@@ -350,6 +383,12 @@ impl FromRef<TestState> for MatrixHomeserver {
     }
 }
 
+impl FromRef<TestState> for AppserviceRegistry {
+    fn from_ref(input: &TestState) -> Self {
+        input.appservices.clone()
+    }
+}
+
 impl FromRef<TestState> for HttpClientFactory {
     fn from_ref(input: &TestState) -> Self {
         input.http_client_factory.clone()
@@ -368,15 +407,33 @@ impl FromRef<TestState> for CookieManager {
     }
 }
 
-impl FromRef<TestState> for MetadataCache {
+impl FromRef<TestState> for SiteConfig {
+    fn from_ref(input: &TestState) -> Self {
+        input.site_config.clone()
+    }
+}
+
+impl FromRef<TestState> for Limiter {
     fn from_ref(input: &TestState) -> Self {
-        input.metadata_cache.clone()
+        input.limiter.clone()
     }
 }
 
-impl FromRef<TestState> for SiteConfig {
+impl FromRef<TestState> for IntrospectionCache {
     fn from_ref(input: &TestState) -> Self {
-        input.site_config.clone()
+        input.introspection_cache.clone()
+    }
+}
+
+impl FromRef<TestState> for IpFilter {
+    fn from_ref(input: &TestState) -> Self {
+        input.ip_filter.clone()
+    }
+}
+
+impl FromRef<TestState> for SharedHomeserverConnection {
+    fn from_ref(input: &TestState) -> Self {
+        input.homeserver_connection.clone()
     }
 }
 
@@ -607,6 +664,17 @@ impl CookieHelper {
         request
     }
 
+    /// Set the session cookie, as if the given browser session had just logged
+    /// in.
+    pub async fn set_session(&self, state: &TestState, session: &BrowserSession) {
+        let (mut parts, ()) = Request::new(()).into_parts();
+        let cookie_jar = CookieJar::from_request_parts(&mut parts, state)
+            .await
+            .unwrap_or_else(|e| match e {});
+        let response = (cookie_jar.set_session(session), ()).into_response();
+        self.save_cookies(&response);
+    }
+
     /// Save the cookies from the response into the store.
     pub fn save_cookies<B>(&self, response: &Response<B>) {
         let url = "https://example.com/".parse().unwrap();
@@ -12,48 +12,88 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashSet;
+
 use axum::{
     extract::{Path, Query, State},
-    response::{IntoResponse, Redirect},
+    response::{IntoResponse, Redirect, Response},
 };
 use hyper::StatusCode;
 use mas_axum_utils::{
     cookies::CookieJar, http_client_factory::HttpClientFactory, sentry::SentryEventID,
+    SessionInfoExt,
+};
+use mas_data_model::{
+    UpstreamOAuthProviderPkceMethod, UpstreamOAuthProviderProtocol,
+    UpstreamOAuthProviderResponseMode,
 };
+use mas_iana::oauth::PkceCodeChallengeMethod;
 use mas_oidc_client::requests::authorization_code::AuthorizationRequestData;
-use mas_router::UrlBuilder;
+use mas_router::{PostAuthAction, UpstreamOAuth2UpgradeQuery, UrlBuilder};
+use mas_saml::request::AuthnRequest;
 use mas_storage::{
-    upstream_oauth2::{UpstreamOAuthProviderRepository, UpstreamOAuthSessionRepository},
-    BoxClock, BoxRepository, BoxRng,
+    upstream_oauth2::{
+        UpstreamOAuthLinkFilter, UpstreamOAuthLinkRepository, UpstreamOAuthProviderRepository,
+        UpstreamOAuthSessionRepository,
+    },
+    BoxClock, BoxRepository, BoxRng, Clock, Pagination,
 };
+use oauth2_types::{
+    requests::{Prompt, ResponseMode},
+    scope::Scope,
+};
+use rand::distributions::{Alphanumeric, DistString};
+use serde::Deserialize;
 use thiserror::Error;
 use ulid::Ulid;
 
 use super::UpstreamSessionsCookie;
-use crate::{
-    impl_from_error_for_route, upstream_oauth2::cache::MetadataCache,
-    views::shared::OptionalPostAuthAction,
-};
+use crate::{impl_from_error_for_route, views::shared::OptionalPostAuthAction};
+
+#[derive(Deserialize)]
+pub(crate) struct Params {
+    /// A hint about the account the user wants to log in with, forwarded to
+    /// the provider if it is configured to
+    login_hint: Option<String>,
+
+    #[serde(flatten)]
+    post_auth_action: OptionalPostAuthAction,
+}
 
 #[derive(Debug, Error)]
 pub(crate) enum RouteError {
     #[error("Provider not found")]
     ProviderNotFound,
 
+    #[error("The user is not linked to the provider")]
+    LinkNotFound,
+
+    #[error("Invalid scope")]
+    InvalidScope,
+
+    #[error("The SAML single sign-on URL of the provider is not configured")]
+    MissingSsoUrl,
+
     #[error(transparent)]
     Internal(Box<dyn std::error::Error>),
 }
 
 impl_from_error_for_route!(mas_http::ClientInitError);
-impl_from_error_for_route!(mas_oidc_client::error::DiscoveryError);
 impl_from_error_for_route!(mas_oidc_client::error::AuthorizationError);
+impl_from_error_for_route!(super::cache::MetadataError);
 impl_from_error_for_route!(mas_storage::RepositoryError);
+impl_from_error_for_route!(std::io::Error);
 
 impl IntoResponse for RouteError {
     fn into_response(self) -> axum::response::Response {
         let event_id = sentry::capture_error(&self);
         let response = match self {
             Self::ProviderNotFound => (StatusCode::NOT_FOUND, "Provider not found").into_response(),
+            e @ Self::LinkNotFound => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+            e @ Self::InvalidScope => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+            e @ Self::MissingSsoUrl => {
+                (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+            }
             Self::Internal(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
         };
 
@@ -68,46 +108,298 @@ impl IntoResponse for RouteError {
     err,
 )]
 pub(crate) async fn get(
-    mut rng: BoxRng,
+    rng: BoxRng,
     clock: BoxClock,
     State(http_client_factory): State<HttpClientFactory>,
-    State(metadata_cache): State<MetadataCache>,
-    mut repo: BoxRepository,
+    repo: BoxRepository,
+    State(url_builder): State<UrlBuilder>,
+    cookie_jar: CookieJar,
+    Path(provider_id): Path<Ulid>,
+    Query(params): Query<Params>,
+) -> Result<impl IntoResponse, RouteError> {
+    start(
+        rng,
+        clock,
+        http_client_factory,
+        repo,
+        url_builder,
+        cookie_jar,
+        provider_id,
+        params.login_hint,
+        params.post_auth_action.post_auth_action,
+        false,
+        None,
+    )
+    .await
+}
+
+/// Send a user who logged in through the provider to it again, with
+/// `prompt=none`, to check that they still have a session with it
+#[tracing::instrument(
+    name = "handlers.upstream_oauth2.authorize.revalidate",
+    fields(upstream_oauth_provider.id = %provider_id),
+    skip_all,
+    err,
+)]
+pub(crate) async fn revalidate(
+    rng: BoxRng,
+    clock: BoxClock,
+    State(http_client_factory): State<HttpClientFactory>,
+    repo: BoxRepository,
     State(url_builder): State<UrlBuilder>,
     cookie_jar: CookieJar,
     Path(provider_id): Path<Ulid>,
-    Query(query): Query<OptionalPostAuthAction>,
+    Query(params): Query<OptionalPostAuthAction>,
 ) -> Result<impl IntoResponse, RouteError> {
+    start(
+        rng,
+        clock,
+        http_client_factory,
+        repo,
+        url_builder,
+        cookie_jar,
+        provider_id,
+        None,
+        params.post_auth_action,
+        true,
+        None,
+    )
+    .await
+}
+
+/// Send a user who is linked to the provider to it again, to grant the scopes
+/// they didn't grant yet out of the ones requested, e.g. by a trusted service
+/// which needs them to call the APIs of the provider
+#[tracing::instrument(
+    name = "handlers.upstream_oauth2.authorize.upgrade",
+    fields(upstream_oauth_provider.id = %provider_id),
+    skip_all,
+    err,
+)]
+pub(crate) async fn upgrade(
+    rng: BoxRng,
+    clock: BoxClock,
+    State(http_client_factory): State<HttpClientFactory>,
+    mut repo: BoxRepository,
+    State(url_builder): State<UrlBuilder>,
+    cookie_jar: CookieJar,
+    Path(provider_id): Path<Ulid>,
+    Query(params): Query<UpstreamOAuth2UpgradeQuery>,
+) -> Result<Response, RouteError> {
+    let scope: Scope = params.scope.parse().map_err(|_| RouteError::InvalidScope)?;
+
+    let (session_info, cookie_jar) = cookie_jar.session_info();
+    let Some(browser_session) = session_info.load_session(&clock, &mut repo).await? else {
+        let login = mas_router::Login::from(params.post_auth_action);
+        return Ok((cookie_jar, url_builder.redirect(&login)).into_response());
+    };
+
     let provider = repo
         .upstream_oauth_provider()
         .lookup(provider_id)
         .await?
         .ok_or(RouteError::ProviderNotFound)?;
 
+    let filter = UpstreamOAuthLinkFilter::new()
+        .for_user(&browser_session.user)
+        .for_provider(&provider);
+    let link = repo
+        .upstream_oauth_link()
+        .list(filter, Pagination::first(1))
+        .await?
+        .edges
+        .into_iter()
+        .next()
+        .ok_or(RouteError::LinkNotFound)?;
+
+    // The scopes granted on links created before they were recorded are not
+    // known, but they at least include the ones of the provider
+    let granted = repo
+        .upstream_oauth_link()
+        .granted_scope(&link)
+        .await?
+        .unwrap_or_else(|| provider.scope.clone());
+    let missing: Scope = scope
+        .iter()
+        .filter(|token| !granted.contains(token))
+        .cloned()
+        .collect();
+
+    if missing.is_empty() {
+        repo.cancel().await?;
+        let next = OptionalPostAuthAction {
+            post_auth_action: params.post_auth_action,
+        }
+        .go_next_or_default(&url_builder, &mas_router::Account::default());
+        return Ok((cookie_jar, next).into_response());
+    }
+
+    let response = start(
+        rng,
+        clock,
+        http_client_factory,
+        repo,
+        url_builder,
+        cookie_jar,
+        provider_id,
+        None,
+        params.post_auth_action,
+        false,
+        Some(missing),
+    )
+    .await?;
+
+    Ok(response.into_response())
+}
+
+/// Start an authorization session with the provider, and redirect the user to
+/// it.
+///
+/// Only the given scopes are requested if there are some, instead of the ones
+/// of the provider.
+#[allow(clippy::too_many_arguments, clippy::too_many_lines)]
+async fn start(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    http_client_factory: HttpClientFactory,
+    mut repo: BoxRepository,
+    url_builder: UrlBuilder,
+    cookie_jar: CookieJar,
+    provider_id: Ulid,
+    login_hint: Option<String>,
+    post_auth_action: Option<PostAuthAction>,
+    revalidation: bool,
+    scope: Option<Scope>,
+) -> Result<(CookieJar, Redirect), RouteError> {
+    let provider = repo
+        .upstream_oauth_provider()
+        .lookup(provider_id)
+        .await?
+        .ok_or(RouteError::ProviderNotFound)?;
+
+    // SAML providers are sent an authentication request instead, and post their
+    // response to the assertion consumer service
+    if provider.protocol == UpstreamOAuthProviderProtocol::Saml {
+        let sso_url = provider
+            .saml
+            .sso_url
+            .as_ref()
+            .ok_or(RouteError::MissingSsoUrl)?;
+
+        // The ID of the request is checked against the `InResponseTo` of the
+        // response, and is stored as the nonce of the session. The state is sent as
+        // the relay state.
+        let request_id = format!("_{}", Alphanumeric.sample_string(&mut rng, 32));
+        let state = Alphanumeric.sample_string(&mut rng, 16);
+
+        let assertion_consumer_service_url =
+            url_builder.upstream_saml_acs(provider.id, provider.slug.as_deref());
+        let request = AuthnRequest {
+            id: &request_id,
+            issue_instant: clock.now(),
+            issuer: &provider.client_id,
+            destination: sso_url,
+            assertion_consumer_service_url: &assertion_consumer_service_url,
+            name_id_format: provider.saml.name_id_format.as_deref(),
+        };
+        let url = request.redirect_url(&state)?;
+
+        let session = repo
+            .upstream_oauth_session()
+            .add(&mut rng, &clock, &provider, state.clone(), None, request_id)
+            .await?;
+
+        let cookie_jar = UpstreamSessionsCookie::load(&cookie_jar)
+            .add(session.id, provider.id, state, post_auth_action)
+            .save(cookie_jar, &clock);
+
+        repo.save().await?;
+
+        return Ok((cookie_jar, Redirect::temporary(url.as_str())));
+    }
+
     let http_service = http_client_factory.http_service("upstream_oauth2.authorize");
 
-    // First, discover the provider
-    let metadata = metadata_cache.get(&http_service, &provider.issuer).await?;
+    // First, load the metadata of the provider
+    let metadata = super::cache::load(&http_service, &clock, &mut repo, &provider).await?;
 
-    let redirect_uri = url_builder.upstream_oauth_callback(provider.id);
+    let redirect_uri = url_builder.upstream_oauth_callback(provider.id, provider.slug.as_deref());
 
-    let mut data = AuthorizationRequestData::new(
-        provider.client_id.clone(),
-        provider.scope.clone(),
-        redirect_uri,
-    );
+    let scope = scope.unwrap_or_else(|| provider.scope.clone());
+    let mut data =
+        AuthorizationRequestData::new(provider.client_id.clone(), scope.clone(), redirect_uri)
+            .with_openid(provider.protocol == UpstreamOAuthProviderProtocol::Oidc);
 
-    if let Some(methods) = metadata.code_challenge_methods_supported.clone() {
-        data = data.with_code_challenge_methods_supported(methods);
+    match provider.pkce_method {
+        UpstreamOAuthProviderPkceMethod::Auto => {
+            if let Some(methods) = metadata.code_challenge_methods_supported.clone() {
+                data = data.with_code_challenge_methods_supported(methods);
+            }
+        }
+        UpstreamOAuthProviderPkceMethod::Always => {
+            // Some providers require PKCE without advertising it
+            data = data.with_code_challenge_methods_supported(vec![PkceCodeChallengeMethod::S256]);
+        }
+        UpstreamOAuthProviderPkceMethod::Never => {}
+    }
+
+    let authorization_params = &provider.authorization_params;
+
+    if let Some(response_mode) = authorization_params.response_mode {
+        data = data.with_response_mode(match response_mode {
+            UpstreamOAuthProviderResponseMode::Query => ResponseMode::Query,
+            UpstreamOAuthProviderResponseMode::FormPost => ResponseMode::FormPost,
+        });
+    }
+
+    if revalidation {
+        // The user must not be asked anything when checking their session
+        data = data.with_prompt(vec![Prompt::None]);
+    } else if let Some(prompt) = &authorization_params.prompt {
+        let prompt: Vec<Prompt> = prompt
+            .split_whitespace()
+            .filter_map(|value| value.parse().ok())
+            .collect();
+        if !prompt.is_empty() {
+            data = data.with_prompt(prompt);
+        }
+    }
+
+    if let Some(max_age) = authorization_params.max_age {
+        data = data.with_max_age(max_age);
+    }
+
+    if authorization_params.forward_login_hint {
+        if let Some(login_hint) = login_hint {
+            data = data.with_login_hint(login_hint);
+        }
     }
 
     // Build an authorization request for it
-    let (url, data) = mas_oidc_client::requests::authorization_code::build_authorization_url(
-        metadata.authorization_endpoint().clone(),
+    let (mut url, data) = mas_oidc_client::requests::authorization_code::build_authorization_url(
+        metadata.authorization_endpoint,
         data,
         &mut rng,
     )?;
 
+    // Add the additional parameters, without overriding the ones we set
+    if !authorization_params.additional_parameters.is_empty() {
+        let existing: HashSet<String> =
+            url.query_pairs().map(|(key, _)| key.into_owned()).collect();
+        let mut query = url.query_pairs_mut();
+        for (key, value) in &authorization_params.additional_parameters {
+            if existing.contains(key) {
+                tracing::warn!(
+                    upstream_oauth_provider.id = %provider.id,
+                    parameter = key,
+                    "Ignoring additional authorization parameter which would override another one"
+                );
+                continue;
+            }
+            query.append_pair(key, value);
+        }
+    }
+
     let session = repo
         .upstream_oauth_session()
         .add(
@@ -120,11 +412,187 @@ pub(crate) async fn get(
         )
         .await?;
 
-    let cookie_jar = UpstreamSessionsCookie::load(&cookie_jar)
-        .add(session.id, provider.id, data.state, query.post_auth_action)
-        .save(cookie_jar, &clock);
+    // The scopes are remembered in case the provider doesn't say which ones
+    // were granted
+    let mut sessions_cookie = UpstreamSessionsCookie::load(&cookie_jar)
+        .add(session.id, provider.id, data.state, post_auth_action)
+        .with_scope(session.id, scope.to_string());
+    if revalidation {
+        sessions_cookie = sessions_cookie.mark_as_revalidation(session.id);
+    }
+    let cookie_jar = sessions_cookie.save(cookie_jar, &clock);
 
     repo.save().await?;
 
     Ok((cookie_jar, Redirect::temporary(url.as_str())))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, num::NonZeroU32};
+
+    use hyper::{header::LOCATION, Request};
+    use mas_data_model::{
+        UpstreamOAuthProvider, UpstreamOAuthProviderAuthorizationParams,
+        UpstreamOAuthProviderClaimsImports, UpstreamOAuthProviderEndpoints,
+        UpstreamOAuthProviderHealthCheckSettings, UpstreamOAuthProviderSamlSettings,
+        UpstreamOAuthProviderUiOptions,
+    };
+    use mas_iana::oauth::OAuthClientAuthenticationMethod;
+    use mas_router::Route;
+    use mas_storage::RepositoryAccess;
+    use oauth2_types::scope::OPENID;
+    use sqlx::PgPool;
+    use url::Url;
+
+    use super::*;
+    use crate::test_utils::{init_tracing, RequestBuilderExt, ResponseExt, TestState};
+
+    /// Add an OAuth 2.0 provider with static endpoints, which doesn't
+    /// advertise PKCE support
+    async fn add_provider(
+        state: &TestState,
+        pkce_method: UpstreamOAuthProviderPkceMethod,
+        authorization_params: UpstreamOAuthProviderAuthorizationParams,
+    ) -> UpstreamOAuthProvider {
+        let mut repo = state.repository().await.unwrap();
+        let provider = repo
+            .upstream_oauth_provider()
+            .add(
+                &mut state.rng(),
+                &state.clock,
+                "https://example.com/".to_owned(),
+                None,
+                Scope::from_iter([OPENID]),
+                OAuthClientAuthenticationMethod::None,
+                None,
+                "client".to_owned(),
+                None,
+                UpstreamOAuthProviderClaimsImports::default(),
+                pkce_method,
+                authorization_params,
+                false,
+                UpstreamOAuthProviderUiOptions::default(),
+                UpstreamOAuthProviderProtocol::OAuth2,
+                UpstreamOAuthProviderEndpoints {
+                    authorization_endpoint: Some("https://example.com/authorize".parse().unwrap()),
+                    token_endpoint: Some("https://example.com/token".parse().unwrap()),
+                    ..UpstreamOAuthProviderEndpoints::default()
+                },
+                UpstreamOAuthProviderSamlSettings::default(),
+                false,
+                UpstreamOAuthProviderHealthCheckSettings::default(),
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+        provider
+    }
+
+    /// Start a login with the provider, and return the parameters of the
+    /// authorization request it is sent
+    async fn authorize(
+        state: &TestState,
+        provider: &UpstreamOAuthProvider,
+        login_hint: Option<&str>,
+    ) -> HashMap<String, String> {
+        let mut path = mas_router::UpstreamOAuth2Authorize::new(provider.id)
+            .path()
+            .into_owned();
+        if let Some(login_hint) = login_hint {
+            let query = url::form_urlencoded::Serializer::new(String::new())
+                .append_pair("login_hint", login_hint)
+                .finish();
+            path = format!("{path}?{query}");
+        }
+
+        let response = state.request(Request::get(path).empty()).await;
+        response.assert_status(StatusCode::TEMPORARY_REDIRECT);
+        let location = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+        let url = Url::parse(location).unwrap();
+        assert_eq!(url.path(), "/authorize");
+        url.query_pairs().into_owned().collect()
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_authorization_params(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        let provider = add_provider(
+            &state,
+            UpstreamOAuthProviderPkceMethod::default(),
+            UpstreamOAuthProviderAuthorizationParams {
+                response_mode: Some(UpstreamOAuthProviderResponseMode::FormPost),
+                prompt: Some("login consent".to_owned()),
+                forward_login_hint: true,
+                max_age: NonZeroU32::new(300),
+                additional_parameters: vec![
+                    ("domain_hint".to_owned(), "example.com".to_owned()),
+                    ("client_id".to_owned(), "overridden".to_owned()),
+                ],
+                ..UpstreamOAuthProviderAuthorizationParams::default()
+            },
+        )
+        .await;
+
+        let params = authorize(&state, &provider, Some("alice@example.com")).await;
+        assert_eq!(params["response_mode"], "form_post");
+        assert_eq!(params["prompt"], "login consent");
+        assert_eq!(params["max_age"], "300");
+        assert_eq!(params["login_hint"], "alice@example.com");
+        assert_eq!(params["domain_hint"], "example.com");
+        // The additional parameters don't override the ones we set
+        assert_eq!(params["client_id"], "client");
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_default_authorization_params(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        let provider = add_provider(
+            &state,
+            UpstreamOAuthProviderPkceMethod::default(),
+            UpstreamOAuthProviderAuthorizationParams::default(),
+        )
+        .await;
+
+        // Nothing is added to the request, and the login hint isn't forwarded
+        let params = authorize(&state, &provider, Some("alice@example.com")).await;
+        assert_eq!(params["client_id"], "client");
+        assert_eq!(params["response_type"], "code");
+        for param in ["response_mode", "prompt", "max_age", "login_hint"] {
+            assert!(!params.contains_key(param), "unexpected {param}");
+        }
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_pkce_method(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        // The provider doesn't advertise PKCE support, so it is only used if it is
+        // always required
+        for (pkce_method, expected) in [
+            (UpstreamOAuthProviderPkceMethod::Auto, false),
+            (UpstreamOAuthProviderPkceMethod::Always, true),
+            (UpstreamOAuthProviderPkceMethod::Never, false),
+        ] {
+            let provider = add_provider(
+                &state,
+                pkce_method,
+                UpstreamOAuthProviderAuthorizationParams::default(),
+            )
+            .await;
+
+            let params = authorize(&state, &provider, None).await;
+            assert_eq!(params.contains_key("code_challenge"), expected);
+            if expected {
+                assert_eq!(params["code_challenge_method"], "S256");
+            } else {
+                assert!(!params.contains_key("code_challenge_method"));
+            }
+        }
+    }
+}
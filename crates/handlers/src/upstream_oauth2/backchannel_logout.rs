@@ -0,0 +1,606 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The OpenID Connect back-channel logout endpoint, called by the upstream
+//! providers to end the local sessions of users who logged out from them
+
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Response},
+    Form, TypedHeader,
+};
+use chrono::Duration;
+use headers::CacheControl;
+use hyper::StatusCode;
+use mas_axum_utils::{http_client_factory::HttpClientFactory, sentry::SentryEventID};
+use mas_data_model::{Device, UpstreamOAuthProviderProtocol};
+use mas_jose::claims::{self, TimeOptions};
+use mas_oidc_client::{error::LogoutTokenError, requests::jose::JwtVerificationData};
+use mas_storage::{
+    compat::{CompatSessionFilter, CompatSessionRepository},
+    job::{DeleteDeviceJob, JobRepositoryExt},
+    oauth2::{OAuth2SessionFilter, OAuth2SessionRepository},
+    upstream_oauth2::{UpstreamOAuthLinkRepository, UpstreamOAuthProviderRepository},
+    user::{BrowserSessionFilter, BrowserSessionRepository},
+    BoxClock, BoxRepository, Clock, Pagination, RepositoryError,
+};
+use serde::Deserialize;
+use thiserror::Error;
+use tracing::info;
+use ulid::Ulid;
+
+use crate::{impl_from_error_for_route, IntrospectionCache};
+
+/// How many sessions are ended at once
+const BATCH_SIZE: usize = 100;
+
+/// How many minutes logout tokens without an `exp` claim are accepted after
+/// they were issued
+const LOGOUT_TOKEN_MAX_AGE_MINUTES: i64 = 10;
+
+#[derive(Deserialize)]
+pub(crate) struct Params {
+    logout_token: String,
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum RouteError {
+    #[error("Provider not found")]
+    ProviderNotFound,
+
+    #[error("The provider doesn't support back-channel logout")]
+    ProtocolMismatch,
+
+    #[error("Invalid logout token")]
+    InvalidLogoutToken(#[from] LogoutTokenError),
+
+    #[error("The logout token has no jti claim")]
+    MissingJti,
+
+    #[error("The logout token is too old")]
+    ExpiredLogoutToken,
+
+    #[error("The logout token was already used")]
+    ReplayedLogoutToken,
+
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error>),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+impl_from_error_for_route!(super::cache::MetadataError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> axum::response::Response {
+        let event_id = sentry::capture_error(&self);
+        let response = match self {
+            Self::ProviderNotFound => (StatusCode::NOT_FOUND, "Provider not found").into_response(),
+            Self::Internal(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+            e => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        };
+
+        (
+            SentryEventID::from(event_id),
+            TypedHeader(CacheControl::new().with_no_store()),
+            response,
+        )
+            .into_response()
+    }
+}
+
+/// End the local sessions of a user who logged out from an upstream provider
+///
+/// The logout token identifies either the user, through the `sub` claim, or
+/// the upstream session, through the `sid` claim, in which case only the
+/// sessions authenticated through that upstream session are ended, along with
+/// the OAuth 2.0 and compatibility sessions started from them.
+///
+/// Logout tokens are remembered by their `jti` claim until they expire, so that
+/// they can't be replayed.
+#[tracing::instrument(
+    name = "handlers.upstream_oauth2.backchannel_logout.post",
+    fields(upstream_oauth_provider.id = %provider_ref),
+    skip_all,
+    err,
+)]
+#[allow(clippy::too_many_lines)]
+pub(crate) async fn post(
+    clock: BoxClock,
+    State(http_client_factory): State<HttpClientFactory>,
+    mut repo: BoxRepository,
+    State(introspection_cache): State<IntrospectionCache>,
+    Path(provider_ref): Path<String>,
+    Form(params): Form<Params>,
+) -> Result<Response, RouteError> {
+    let provider = super::lookup_provider(&mut repo, &provider_ref)
+        .await?
+        .ok_or(RouteError::ProviderNotFound)?;
+
+    // Only OpenID Connect providers issue logout tokens
+    if provider.protocol != UpstreamOAuthProviderProtocol::Oidc {
+        return Err(RouteError::ProtocolMismatch);
+    }
+
+    let http_service = http_client_factory.http_service("upstream_oauth2.backchannel_logout");
+
+    // Load the metadata and the JWKS of the provider
+    let metadata = super::cache::load(&http_service, &clock, &mut repo, &provider).await?;
+    let jwks = metadata.jwks.as_ref().ok_or(RouteError::ProtocolMismatch)?;
+
+    let res =
+        super::cache::verify_signed(&http_service, &clock, &mut repo, &provider, jwks, |jwks| {
+            let verification_data = JwtVerificationData {
+                issuer: &provider.issuer,
+                jwks,
+                // TODO: make that configurable
+                signing_algorithm: &mas_iana::jose::JsonWebSignatureAlg::Rs256,
+                client_id: &provider.client_id,
+            };
+
+            mas_oidc_client::requests::jose::verify_logout_token(
+                &params.logout_token,
+                verification_data,
+                clock.now(),
+            )
+        })
+        .await?;
+
+    let logout_token = match res {
+        Ok(logout_token) => logout_token,
+        Err(e) => {
+            // Keep the JWKS if it was fetched again, so that invalid tokens
+            // don't make us fetch it every time
+            repo.save().await?;
+            return Err(e.into());
+        }
+    };
+
+    let mut claims = logout_token.into_parts().1;
+    let jti = claims::JTI
+        .extract_optional(&mut claims)
+        .map_err(LogoutTokenError::from)?
+        .ok_or(RouteError::MissingJti)?;
+    let time_options = TimeOptions::new(clock.now());
+    let issued_at = claims::IAT
+        .extract_required_with_options(&mut claims, &time_options)
+        .map_err(LogoutTokenError::from)?;
+    let expires_at = claims::EXP
+        .extract_optional_with_options(&mut claims, &time_options)
+        .map_err(LogoutTokenError::from)?
+        .map_or_else(
+            || *issued_at + Duration::minutes(LOGOUT_TOKEN_MAX_AGE_MINUTES),
+            |exp| *exp,
+        );
+    if expires_at <= clock.now() {
+        return Err(RouteError::ExpiredLogoutToken);
+    }
+
+    let first_use = repo
+        .upstream_oauth_provider()
+        .record_logout_token(&clock, &provider, &jti, expires_at)
+        .await?;
+    if !first_use {
+        return Err(RouteError::ReplayedLogoutToken);
+    }
+
+    let sub = claims::SUB
+        .extract_optional(&mut claims)
+        .map_err(LogoutTokenError::from)?;
+    let sid = claims::SID
+        .extract_optional(&mut claims)
+        .map_err(LogoutTokenError::from)?;
+
+    let link = if let Some(sub) = &sub {
+        repo.upstream_oauth_link()
+            .find_by_subject(&provider, sub)
+            .await?
+    } else {
+        None
+    };
+
+    // The subject may not match the link if the provider has a subject
+    // template, in which case we can only rely on the session ID
+    if sub.is_some() && link.is_none() && sid.is_none() {
+        info!("No user linked to the subject of the logout token");
+        repo.save().await?;
+        return Ok((
+            TypedHeader(CacheControl::new().with_no_store()),
+            StatusCode::OK,
+        )
+            .into_response());
+    }
+
+    let mut filter = BrowserSessionFilter::new()
+        .authenticated_by_upstream_oauth_provider(&provider)
+        .active_only();
+    if let Some(link) = &link {
+        filter = filter.authenticated_by_upstream_oauth_link(link);
+    }
+    if let Some(sid) = &sid {
+        filter = filter.authenticated_by_upstream_oauth_sid(sid);
+    }
+
+    let ended_sessions = finish_sessions(&mut repo, &clock, filter).await?;
+
+    repo.save().await?;
+
+    for session_id in ended_sessions {
+        introspection_cache.invalidate_session(session_id);
+    }
+
+    Ok((
+        TypedHeader(CacheControl::new().with_no_store()),
+        StatusCode::OK,
+    )
+        .into_response())
+}
+
+/// Finish the browser sessions matching the filter, along with the OAuth 2.0
+/// and compatibility sessions started from them, and schedule the deletion of
+/// their devices on the homeserver
+///
+/// Returns the IDs of the OAuth 2.0 and compatibility sessions which were
+/// finished, so that they can be evicted from the introspection cache
+pub(super) async fn finish_sessions(
+    repo: &mut BoxRepository,
+    clock: &dyn Clock,
+    filter: BrowserSessionFilter<'_>,
+) -> Result<Vec<Ulid>, RepositoryError> {
+    let mut ended_sessions = Vec::new();
+
+    // Finished sessions are excluded from the filters, so we keep fetching the
+    // first page until there is nothing left
+    loop {
+        let page = repo
+            .browser_session()
+            .list(filter, Pagination::first(BATCH_SIZE))
+            .await?;
+
+        for browser_session in page.edges {
+            // End the OAuth 2.0 sessions started from this browser session
+            let oauth2_filter = OAuth2SessionFilter::new()
+                .for_browser_session(&browser_session)
+                .active_only();
+            loop {
+                let page = repo
+                    .oauth2_session()
+                    .list(oauth2_filter, Pagination::first(BATCH_SIZE))
+                    .await?;
+
+                for oauth2_session in page.edges {
+                    // Schedule the deletion of the devices of the session on the homeserver
+                    for scope in &*oauth2_session.scope {
+                        if let Some(device) = Device::from_scope_token(scope) {
+                            let job = DeleteDeviceJob::new(&browser_session.user, &device);
+                            repo.job().schedule_job(job).await?;
+                        }
+                    }
+
+                    info!(%oauth2_session.id, "Finishing OAuth 2.0 session");
+                    ended_sessions.push(oauth2_session.id);
+                    repo.oauth2_session().finish(clock, oauth2_session).await?;
+                }
+
+                if !page.has_next_page {
+                    break;
+                }
+            }
+
+            // End the compatibility sessions started from this browser session
+            let compat_filter = CompatSessionFilter::new()
+                .for_browser_session(&browser_session)
+                .active_only();
+            loop {
+                let page = repo
+                    .compat_session()
+                    .list(compat_filter, Pagination::first(BATCH_SIZE))
+                    .await?;
+
+                for (compat_session, _) in page.edges {
+                    // Schedule the deletion of the device on the homeserver
+                    let job = DeleteDeviceJob::new(&browser_session.user, &compat_session.device);
+                    repo.job().schedule_job(job).await?;
+
+                    info!(%compat_session.id, "Finishing compatibility session");
+                    ended_sessions.push(compat_session.id);
+                    repo.compat_session().finish(clock, compat_session).await?;
+                }
+
+                if !page.has_next_page {
+                    break;
+                }
+            }
+
+            info!(%browser_session.id, "Finishing browser session");
+            repo.browser_session()
+                .finish(clock, browser_session)
+                .await?;
+        }
+
+        if !page.has_next_page {
+            break;
+        }
+    }
+
+    Ok(ended_sessions)
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Request;
+    use mas_data_model::{
+        BrowserSession, UpstreamOAuthProvider, UpstreamOAuthProviderAuthorizationParams,
+        UpstreamOAuthProviderClaimsImports, UpstreamOAuthProviderEndpoints,
+        UpstreamOAuthProviderHealthCheckSettings, UpstreamOAuthProviderPkceMethod,
+        UpstreamOAuthProviderSamlSettings, UpstreamOAuthProviderUiOptions, User,
+    };
+    use mas_iana::{
+        jose::JsonWebSignatureAlg,
+        oauth::{OAuthAuthorizationEndpointResponseType, OAuthClientAuthenticationMethod},
+    };
+    use mas_jose::{
+        constraints::Constrainable,
+        jwt::{JsonWebSignatureHeader, Jwt},
+    };
+    use mas_router::Route;
+    use mas_storage::{upstream_oauth2::UpstreamOAuthSessionRepository, RepositoryAccess};
+    use oauth2_types::{
+        oidc::{ProviderMetadata, SubjectType},
+        scope::{Scope, OPENID},
+    };
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::test_utils::{init_tracing, RequestBuilderExt, ResponseExt, TestState};
+
+    /// Add an OpenID Connect provider which signs its logout tokens with the
+    /// keys of the test keystore, and a user linked to it, with one browser
+    /// session per upstream session ID
+    async fn setup(
+        state: &TestState,
+        sids: &[&str],
+    ) -> (UpstreamOAuthProvider, User, Vec<BrowserSession>) {
+        let mut rng = state.rng();
+        let mut repo = state.repository().await.unwrap();
+
+        let provider = repo
+            .upstream_oauth_provider()
+            .add(
+                &mut rng,
+                &state.clock,
+                "https://example.com/".to_owned(),
+                None,
+                Scope::from_iter([OPENID]),
+                OAuthClientAuthenticationMethod::None,
+                None,
+                "client".to_owned(),
+                None,
+                UpstreamOAuthProviderClaimsImports::default(),
+                UpstreamOAuthProviderPkceMethod::default(),
+                UpstreamOAuthProviderAuthorizationParams::default(),
+                false,
+                UpstreamOAuthProviderUiOptions::default(),
+                UpstreamOAuthProviderProtocol::Oidc,
+                UpstreamOAuthProviderEndpoints::default(),
+                UpstreamOAuthProviderSamlSettings::default(),
+                false,
+                UpstreamOAuthProviderHealthCheckSettings::default(),
+            )
+            .await
+            .unwrap();
+
+        let discovery = ProviderMetadata {
+            issuer: Some("https://example.com/".to_owned()),
+            authorization_endpoint: Some("https://example.com/authorize".parse().unwrap()),
+            token_endpoint: Some("https://example.com/token".parse().unwrap()),
+            jwks_uri: Some("https://example.com/jwks".parse().unwrap()),
+            response_types_supported: Some(vec![
+                OAuthAuthorizationEndpointResponseType::Code.into()
+            ]),
+            subject_types_supported: Some(vec![SubjectType::Public]),
+            id_token_signing_alg_values_supported: Some(vec![JsonWebSignatureAlg::Rs256]),
+            ..ProviderMetadata::default()
+        };
+        repo.upstream_oauth_provider()
+            .set_metadata(
+                &state.clock,
+                &provider,
+                discovery,
+                state.key_store.public_jwks(),
+            )
+            .await
+            .unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "john".to_owned())
+            .await
+            .unwrap();
+        let link = repo
+            .upstream_oauth_link()
+            .add(&mut rng, &state.clock, &provider, "subject".to_owned())
+            .await
+            .unwrap();
+        repo.upstream_oauth_link()
+            .associate_to_user(&link, &user)
+            .await
+            .unwrap();
+
+        let mut sessions = Vec::new();
+        for sid in sids {
+            let upstream_session = repo
+                .upstream_oauth_session()
+                .add(
+                    &mut rng,
+                    &state.clock,
+                    &provider,
+                    "state".to_owned(),
+                    None,
+                    "nonce".to_owned(),
+                )
+                .await
+                .unwrap();
+            let upstream_session = repo
+                .upstream_oauth_session()
+                .complete_with_link(
+                    &state.clock,
+                    upstream_session,
+                    &link,
+                    None,
+                    Some((*sid).to_owned()),
+                    None,
+                )
+                .await
+                .unwrap();
+
+            let session = repo
+                .browser_session()
+                .add(&mut rng, &state.clock, &user, None)
+                .await
+                .unwrap();
+            repo.browser_session()
+                .authenticate_with_upstream(&mut rng, &state.clock, &session, &upstream_session)
+                .await
+                .unwrap();
+            sessions.push(session);
+        }
+
+        repo.save().await.unwrap();
+
+        (provider, user, sessions)
+    }
+
+    /// Sign a logout token with the first key of the test keystore
+    fn logout_token(state: &TestState, jti: &str, sub: Option<&str>, sid: Option<&str>) -> String {
+        let mut claims = serde_json::json!({
+            "iss": "https://example.com/",
+            "aud": "client",
+            "iat": state.clock.now().timestamp(),
+            "jti": jti,
+            "events": { "http://schemas.openid.net/event/backchannel-logout": {} },
+        });
+        if let Some(sub) = sub {
+            claims["sub"] = sub.into();
+        }
+        if let Some(sid) = sid {
+            claims["sid"] = sid.into();
+        }
+
+        let alg = JsonWebSignatureAlg::Rs256;
+        let key = state.key_store.signing_key_for_algorithm(&alg).unwrap();
+        let signer = key.params().signing_key_for_alg(&alg).unwrap();
+        let header = JsonWebSignatureHeader::new(alg).with_kid(key.kid().unwrap());
+        Jwt::sign_with_rng(&mut state.rng(), header, claims, &signer)
+            .unwrap()
+            .into_string()
+    }
+
+    async fn logout(
+        state: &TestState,
+        provider: &UpstreamOAuthProvider,
+        logout_token: &str,
+    ) -> hyper::Response<String> {
+        let request = Request::post(
+            &*mas_router::UpstreamOAuth2BackchannelLogout::new(provider.id).path_and_query(),
+        )
+        .form(serde_json::json!({ "logout_token": logout_token }));
+        state.request(request).await
+    }
+
+    /// Whether each of the sessions is still active
+    async fn active(state: &TestState, sessions: &[BrowserSession]) -> Vec<bool> {
+        let mut repo = state.repository().await.unwrap();
+        let mut active = Vec::new();
+        for session in sessions {
+            let session = repo
+                .browser_session()
+                .lookup(session.id)
+                .await
+                .unwrap()
+                .unwrap();
+            active.push(session.finished_at.is_none());
+        }
+        repo.cancel().await.unwrap();
+        active
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_logout_by_subject(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let (provider, _user, sessions) = setup(&state, &["sid-1", "sid-2"]).await;
+
+        // All the sessions of the user are ended
+        let token = logout_token(&state, "jti-1", Some("subject"), None);
+        let response = logout(&state, &provider, &token).await;
+        response.assert_status(StatusCode::OK);
+        assert_eq!(active(&state, &sessions).await, [false, false]);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_logout_by_session_id(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let (provider, _user, sessions) = setup(&state, &["sid-1", "sid-2"]).await;
+
+        // Only the sessions authenticated through this upstream session are
+        // ended
+        let token = logout_token(&state, "jti-1", None, Some("sid-1"));
+        let response = logout(&state, &provider, &token).await;
+        response.assert_status(StatusCode::OK);
+        assert_eq!(active(&state, &sessions).await, [false, true]);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_unknown_subject(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let (provider, _user, sessions) = setup(&state, &["sid-1"]).await;
+
+        // The token is accepted, but there is nobody to log out
+        let token = logout_token(&state, "jti-1", Some("someone-else"), None);
+        let response = logout(&state, &provider, &token).await;
+        response.assert_status(StatusCode::OK);
+        assert_eq!(active(&state, &sessions).await, [true]);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_replayed_logout_token(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let (provider, _user, sessions) = setup(&state, &["sid-1", "sid-2"]).await;
+
+        let token = logout_token(&state, "jti-1", None, Some("sid-1"));
+        let response = logout(&state, &provider, &token).await;
+        response.assert_status(StatusCode::OK);
+        assert_eq!(active(&state, &sessions).await, [false, true]);
+
+        // The same token is rejected the second time
+        let response = logout(&state, &provider, &token).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+
+        // Another token with the same jti is rejected too, and doesn't end any
+        // session
+        let token = logout_token(&state, "jti-1", None, Some("sid-2"));
+        let response = logout(&state, &provider, &token).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        assert_eq!(active(&state, &sessions).await, [false, true]);
+
+        // A token with another jti is accepted
+        let token = logout_token(&state, "jti-2", None, Some("sid-2"));
+        let response = logout(&state, &provider, &token).await;
+        response.assert_status(StatusCode::OK);
+        assert_eq!(active(&state, &sessions).await, [false, false]);
+    }
+}
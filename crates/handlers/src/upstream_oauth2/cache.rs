@@ -12,106 +12,426 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{collections::HashMap, sync::Arc};
+//! The discovery document and the JWKS of the upstream providers, as stored in
+//! the database
 
+use chrono::Duration;
+use mas_data_model::{UpstreamOAuthProvider, UpstreamOAuthProviderProtocol};
 use mas_http::HttpService;
-use mas_oidc_client::error::DiscoveryError;
-use mas_storage::{upstream_oauth2::UpstreamOAuthProviderRepository, RepositoryAccess};
+use mas_iana::oauth::PkceCodeChallengeMethod;
+use mas_jose::jwk::PublicJsonWebKeySet;
+use mas_oidc_client::error::{DiscoveryError, IdTokenError, JwksError, LogoutTokenError};
+use mas_storage::{
+    upstream_oauth2::UpstreamOAuthProviderRepository, Clock, RepositoryAccess, RepositoryError,
+};
 use oauth2_types::oidc::VerifiedProviderMetadata;
-use tokio::sync::RwLock;
+use thiserror::Error;
+use url::Url;
 
-/// A simple OIDC metadata cache
-///
-/// It never evicts entries, does not cache failures and has no locking.
-/// It can also be refreshed in the background, and warmed up on startup.
-/// It is good enough for our use case.
-#[allow(clippy::module_name_repetitions)]
-#[derive(Debug, Clone, Default)]
-pub struct MetadataCache {
-    cache: Arc<RwLock<HashMap<String, VerifiedProviderMetadata>>>,
+#[derive(Debug, Error)]
+pub(crate) enum MetadataError {
+    #[error(transparent)]
+    Repository(#[from] RepositoryError),
+
+    #[error(transparent)]
+    Discovery(#[from] DiscoveryError),
+
+    #[error(transparent)]
+    Jwks(#[from] JwksError),
+
+    #[error("The {0} of the provider is not configured")]
+    MissingEndpoint(&'static str),
+
+    #[error("SAML providers don't have OAuth 2.0 endpoints")]
+    Saml,
 }
 
-impl MetadataCache {
-    #[must_use]
-    pub fn new() -> Self {
-        Self::default()
-    }
+/// The endpoints and the keys of a provider, either discovered or configured
+pub(crate) struct ProviderMetadata {
+    pub authorization_endpoint: Url,
+    pub token_endpoint: Url,
+    pub userinfo_endpoint: Option<Url>,
+    pub code_challenge_methods_supported: Option<Vec<PkceCodeChallengeMethod>>,
 
-    /// Warm up the cache by fetching all the known providers from the database
-    /// and inserting them into the cache.
-    ///
-    /// This spawns a background task that will refresh the cache at the given
-    /// interval.
-    #[tracing::instrument(name = "metadata_cache.warm_up_and_run", skip_all, err)]
-    pub async fn warm_up_and_run<R: RepositoryAccess>(
-        &self,
-        http_service: HttpService,
-        interval: std::time::Duration,
-        repository: &mut R,
-    ) -> Result<tokio::task::JoinHandle<()>, R::Error> {
-        let providers = repository.upstream_oauth_provider().all().await?;
-
-        for provider in providers {
-            if let Err(e) = self.fetch(&http_service, &provider.issuer).await {
-                tracing::error!(issuer = %provider.issuer, error = &e as &dyn std::error::Error, "Failed to fetch provider metadata");
-            }
+    /// The keys used to verify the ID tokens, `None` for plain OAuth 2.0
+    /// providers
+    pub jwks: Option<PublicJsonWebKeySet>,
+}
+
+/// Load the metadata of the provider
+///
+/// The endpoints configured on the provider take precedence over the ones from
+/// its discovery document. Plain OAuth 2.0 providers are not discovered at all.
+#[tracing::instrument(
+    name = "upstream_oauth2.metadata.load",
+    fields(upstream_oauth_provider.id = %provider.id),
+    skip_all,
+    err,
+)]
+pub(crate) async fn load(
+    http_service: &HttpService,
+    clock: &impl Clock,
+    repo: &mut impl RepositoryAccess<Error = RepositoryError>,
+    provider: &UpstreamOAuthProvider,
+) -> Result<ProviderMetadata, MetadataError> {
+    let endpoints = &provider.endpoints;
+
+    match provider.protocol {
+        UpstreamOAuthProviderProtocol::OAuth2 => Ok(ProviderMetadata {
+            authorization_endpoint: endpoints
+                .authorization_endpoint
+                .clone()
+                .ok_or(MetadataError::MissingEndpoint("authorization endpoint"))?,
+            token_endpoint: endpoints
+                .token_endpoint
+                .clone()
+                .ok_or(MetadataError::MissingEndpoint("token endpoint"))?,
+            userinfo_endpoint: endpoints.userinfo_endpoint.clone(),
+            code_challenge_methods_supported: None,
+            jwks: None,
+        }),
+
+        UpstreamOAuthProviderProtocol::Saml => Err(MetadataError::Saml),
+
+        UpstreamOAuthProviderProtocol::Oidc => {
+            let (discovery, jwks) = load_discovered(http_service, clock, repo, provider).await?;
+
+            Ok(ProviderMetadata {
+                authorization_endpoint: endpoints
+                    .authorization_endpoint
+                    .clone()
+                    .unwrap_or_else(|| discovery.authorization_endpoint().clone()),
+                token_endpoint: endpoints
+                    .token_endpoint
+                    .clone()
+                    .unwrap_or_else(|| discovery.token_endpoint().clone()),
+                userinfo_endpoint: endpoints
+                    .userinfo_endpoint
+                    .clone()
+                    .or_else(|| discovery.userinfo_endpoint.clone()),
+                code_challenge_methods_supported: discovery
+                    .code_challenge_methods_supported
+                    .clone(),
+                jwks: Some(jwks),
+            })
         }
+    }
+}
 
-        // Spawn a background task to refresh the cache regularly
-        let cache = self.clone();
-        Ok(tokio::spawn(async move {
-            loop {
-                // Re-fetch the known metadata at the given interval
-                tokio::time::sleep(interval).await;
-                cache.refresh_all(&http_service).await;
+/// Load the discovery document and the JWKS of an OpenID Connect provider
+///
+/// They are kept up to date in the database by a background job, so that
+/// logins keep working while the provider doesn't serve them. They are only
+/// fetched from the provider here if they were never stored, e.g. because the
+/// provider was just added, or if they were stored for another issuer.
+async fn load_discovered(
+    http_service: &HttpService,
+    clock: &impl Clock,
+    repo: &mut impl RepositoryAccess<Error = RepositoryError>,
+    provider: &UpstreamOAuthProvider,
+) -> Result<(VerifiedProviderMetadata, PublicJsonWebKeySet), MetadataError> {
+    if let Some(metadata) = repo.upstream_oauth_provider().metadata(provider).await? {
+        match metadata.discovery.validate(&provider.issuer) {
+            Ok(discovery) => return Ok((discovery, metadata.jwks)),
+            Err(e) => {
+                tracing::warn!(
+                    error = &e as &dyn std::error::Error,
+                    "Stored provider metadata is invalid, fetching it again"
+                );
             }
-        }))
+        }
     }
 
-    #[tracing::instrument(name = "metadata_cache.fetch", fields(%issuer), skip_all, err)]
-    async fn fetch(
-        &self,
-        http_service: &HttpService,
-        issuer: &str,
-    ) -> Result<VerifiedProviderMetadata, DiscoveryError> {
-        let metadata = mas_oidc_client::requests::discovery::discover(http_service, issuer).await?;
+    let discovery =
+        mas_oidc_client::requests::discovery::discover(http_service, &provider.issuer).await?;
+    let jwks = fetch_jwks(http_service, clock, repo, provider, &discovery).await?;
 
-        self.cache
-            .write()
-            .await
-            .insert(issuer.to_owned(), metadata.clone());
+    Ok((discovery, jwks))
+}
+
+/// Fetch the JWKS of a provider, and store it along with its discovery document
+async fn fetch_jwks(
+    http_service: &HttpService,
+    clock: &impl Clock,
+    repo: &mut impl RepositoryAccess<Error = RepositoryError>,
+    provider: &UpstreamOAuthProvider,
+    discovery: &VerifiedProviderMetadata,
+) -> Result<PublicJsonWebKeySet, MetadataError> {
+    let jwks_uri = provider
+        .endpoints
+        .jwks_uri
+        .as_ref()
+        .unwrap_or(discovery.jwks_uri());
+    let jwks = mas_oidc_client::requests::jose::fetch_jwks(http_service, jwks_uri).await?;
+
+    repo.upstream_oauth_provider()
+        .set_metadata(clock, provider, (**discovery).clone(), jwks.clone())
+        .await?;
+
+    Ok(jwks)
+}
+
+/// Errors from the verification of a token which may be caused by an outdated
+/// JWKS
+pub(crate) trait SignatureError {
+    fn is_signature_error(&self) -> bool;
+}
+
+impl SignatureError for IdTokenError {
+    fn is_signature_error(&self) -> bool {
+        IdTokenError::is_signature_error(self)
+    }
+}
 
-        Ok(metadata)
+impl SignatureError for LogoutTokenError {
+    fn is_signature_error(&self) -> bool {
+        LogoutTokenError::is_signature_error(self)
     }
+}
+
+/// Verify a token signed by an OpenID Connect provider with its JWKS
+///
+/// If none of the keys could verify the signature, the provider may have
+/// rotated them: its JWKS is fetched again, stored, and the verification is
+/// retried once with it. To avoid fetching it on every invalid token, this is
+/// only done if the stored JWKS is more than a minute old, and callers should
+/// save the repository even if the verification fails.
+#[tracing::instrument(
+    name = "upstream_oauth2.metadata.verify",
+    fields(upstream_oauth_provider.id = %provider.id),
+    skip_all,
+)]
+pub(crate) async fn verify_signed<T, E: SignatureError>(
+    http_service: &HttpService,
+    clock: &impl Clock,
+    repo: &mut impl RepositoryAccess<Error = RepositoryError>,
+    provider: &UpstreamOAuthProvider,
+    jwks: &PublicJsonWebKeySet,
+    verify: impl Fn(&PublicJsonWebKeySet) -> Result<T, E>,
+) -> Result<Result<T, E>, MetadataError> {
+    let res = verify(jwks);
+    if !res.as_ref().is_err_and(E::is_signature_error) {
+        return Ok(res);
+    }
+
+    let stored = repo.upstream_oauth_provider().metadata(provider).await?;
+    let discovery = match stored {
+        Some(metadata) if clock.now() - metadata.fetched_at < Duration::minutes(1) => {
+            return Ok(res);
+        }
+        Some(metadata) => metadata.discovery.validate(&provider.issuer).ok(),
+        None => None,
+    };
 
-    /// Get the metadata for the given issuer.
-    #[tracing::instrument(name = "metadata_cache.get", fields(%issuer), skip_all, err)]
-    pub async fn get(
-        &self,
-        http_service: &HttpService,
-        issuer: &str,
-    ) -> Result<VerifiedProviderMetadata, DiscoveryError> {
-        let cache = self.cache.read().await;
-        if let Some(metadata) = cache.get(issuer) {
-            return Ok(metadata.clone());
+    let discovery = match discovery {
+        Some(discovery) => discovery,
+        None => {
+            mas_oidc_client::requests::discovery::discover(http_service, &provider.issuer).await?
         }
+    };
+
+    tracing::info!("Token signed with an unknown key, fetching the provider JWKS again");
+    let jwks = fetch_jwks(http_service, clock, repo, provider, &discovery).await?;
+
+    Ok(verify(&jwks))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use mas_data_model::{
+        UpstreamOAuthProviderAuthorizationParams, UpstreamOAuthProviderClaimsImports,
+        UpstreamOAuthProviderEndpoints, UpstreamOAuthProviderHealthCheckSettings,
+        UpstreamOAuthProviderPkceMethod, UpstreamOAuthProviderSamlSettings,
+        UpstreamOAuthProviderUiOptions,
+    };
+    use mas_iana::{
+        jose::JsonWebSignatureAlg,
+        oauth::{OAuthAuthorizationEndpointResponseType, OAuthClientAuthenticationMethod},
+    };
+    use mas_jose::{
+        claims,
+        constraints::Constrainable,
+        jwt::{JsonWebSignatureHeader, Jwt},
+    };
+    use mas_keystore::{JsonWebKey, JsonWebKeySet, Keystore, PrivateKey};
+    use mas_oidc_client::requests::jose::{verify_id_token, JwtVerificationData};
+    use oauth2_types::{
+        oidc::{ProviderMetadata, SubjectType},
+        scope::{Scope, OPENID},
+    };
+    use sqlx::PgPool;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+    use crate::test_utils::{init_tracing, TestState};
+
+    /// Sign an ID token for the test provider with the first key of the
+    /// keystore
+    fn id_token(state: &TestState, keystore: &Keystore, alg: JsonWebSignatureAlg) -> String {
+        let mut claims = HashMap::new();
+        claims::ISS
+            .insert(&mut claims, "https://example.com/".to_owned())
+            .unwrap();
+        claims::AUD
+            .insert(&mut claims, "client".to_owned())
+            .unwrap();
+        claims::SUB
+            .insert(&mut claims, "subject".to_owned())
+            .unwrap();
+        claims::IAT.insert(&mut claims, state.clock.now()).unwrap();
+        claims::EXP
+            .insert(&mut claims, state.clock.now() + Duration::hours(1))
+            .unwrap();
 
-        let metadata = self.fetch(http_service, issuer).await?;
-        Ok(metadata)
+        let key = keystore.signing_key_for_algorithm(&alg).unwrap();
+        let signer = key.params().signing_key_for_alg(&alg).unwrap();
+        let header = JsonWebSignatureHeader::new(alg).with_kid(key.kid().unwrap());
+        Jwt::sign_with_rng(&mut state.rng(), header, claims, &signer)
+            .unwrap()
+            .into_string()
     }
 
-    #[tracing::instrument(name = "metadata_cache.refresh_all", skip_all)]
-    async fn refresh_all(&self, http_service: &HttpService) {
-        // Grab all the keys first to avoid locking the cache for too long
-        let keys: Vec<String> = {
-            let cache = self.cache.read().await;
-            cache.keys().cloned().collect()
+    /// Test that the JWKS is fetched again when a token is signed with a key
+    /// it doesn't have, at most once a minute
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_verify_signed_refreshes_jwks(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let http_service = state.http_client_factory.http_service("test");
+
+        // The provider rotated its keys, it now serves the ones of the test
+        // keystore
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/jwks"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(state.key_store.public_jwks()))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let mut repo = state.repository().await.unwrap();
+        let provider = repo
+            .upstream_oauth_provider()
+            .add(
+                &mut state.rng(),
+                &state.clock,
+                "https://example.com/".to_owned(),
+                None,
+                Scope::from_iter([OPENID]),
+                OAuthClientAuthenticationMethod::None,
+                None,
+                "client".to_owned(),
+                None,
+                UpstreamOAuthProviderClaimsImports::default(),
+                UpstreamOAuthProviderPkceMethod::default(),
+                UpstreamOAuthProviderAuthorizationParams::default(),
+                false,
+                UpstreamOAuthProviderUiOptions::default(),
+                UpstreamOAuthProviderProtocol::Oidc,
+                UpstreamOAuthProviderEndpoints {
+                    jwks_uri: Some(format!("{}/jwks", mock_server.uri()).parse().unwrap()),
+                    ..UpstreamOAuthProviderEndpoints::default()
+                },
+                UpstreamOAuthProviderSamlSettings::default(),
+                false,
+                UpstreamOAuthProviderHealthCheckSettings::default(),
+            )
+            .await
+            .unwrap();
+
+        // The stored JWKS doesn't have the new keys yet
+        let discovery = ProviderMetadata {
+            issuer: Some("https://example.com/".to_owned()),
+            authorization_endpoint: Some("https://example.com/authorize".parse().unwrap()),
+            token_endpoint: Some("https://example.com/token".parse().unwrap()),
+            jwks_uri: Some("https://example.com/jwks".parse().unwrap()),
+            response_types_supported: Some(vec![
+                OAuthAuthorizationEndpointResponseType::Code.into()
+            ]),
+            subject_types_supported: Some(vec![SubjectType::Public]),
+            id_token_signing_alg_values_supported: Some(vec![JsonWebSignatureAlg::Rs256]),
+            ..ProviderMetadata::default()
         };
+        repo.upstream_oauth_provider()
+            .set_metadata(
+                &state.clock,
+                &provider,
+                discovery,
+                PublicJsonWebKeySet::default(),
+            )
+            .await
+            .unwrap();
+        let stored = repo
+            .upstream_oauth_provider()
+            .metadata(&provider)
+            .await
+            .unwrap()
+            .unwrap();
+
+        state.clock.advance(Duration::minutes(5));
 
-        for issuer in keys {
-            if let Err(e) = self.fetch(http_service, &issuer).await {
-                tracing::error!(issuer = %issuer, error = &e as &dyn std::error::Error, "Failed to refresh provider metadata");
+        let verify = |id_token: &str| {
+            let id_token = id_token.to_owned();
+            let provider = provider.clone();
+            let now = state.clock.now();
+            move |jwks: &PublicJsonWebKeySet| {
+                let verification_data = JwtVerificationData {
+                    issuer: &provider.issuer,
+                    jwks,
+                    signing_algorithm: &JsonWebSignatureAlg::Rs256,
+                    client_id: &provider.client_id,
+                };
+                verify_id_token(&id_token, verification_data, None, now).map(|_| ())
             }
-        }
+        };
+
+        // A token signed with one of the new keys is accepted once the JWKS is
+        // fetched again
+        let token = id_token(&state, &state.key_store, JsonWebSignatureAlg::Rs256);
+        verify_signed(
+            &http_service,
+            &state.clock,
+            &mut repo,
+            &provider,
+            &stored.jwks,
+            verify(&token),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        // The new JWKS is stored
+        let stored = repo
+            .upstream_oauth_provider()
+            .metadata(&provider)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.jwks, state.key_store.public_jwks());
+
+        // A token signed with an unknown key is rejected, without fetching the
+        // JWKS again right away
+        let unknown_key =
+            JsonWebKey::new(PrivateKey::generate_ec_p256(&mut state.rng())).with_kid("unknown");
+        let unknown_keystore = Keystore::new(JsonWebKeySet::new(vec![unknown_key]));
+        let token = id_token(&state, &unknown_keystore, JsonWebSignatureAlg::Es256);
+        let res = verify_signed(
+            &http_service,
+            &state.clock,
+            &mut repo,
+            &provider,
+            &stored.jwks,
+            verify(&token),
+        )
+        .await
+        .unwrap();
+        assert!(res.unwrap_err().is_signature_error());
+
+        repo.save().await.unwrap();
     }
 }
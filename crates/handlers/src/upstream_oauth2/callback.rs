@@ -12,44 +12,59 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
+
 use axum::{
-    extract::{Path, Query, State},
-    response::IntoResponse,
+    extract::{Path, State},
+    response::{Html, IntoResponse, Response},
+    Form, TypedHeader,
 };
-use hyper::StatusCode;
+use hyper::{Method, StatusCode};
 use mas_axum_utils::{
     cookies::CookieJar, http_client_factory::HttpClientFactory, sentry::SentryEventID,
+    upstream_oauth2::client_credentials_for_provider, FancyError, SessionInfoExt,
+};
+use mas_data_model::{
+    UpstreamOAuthAuthorizationSession, UpstreamOAuthLinkTokens, UpstreamOAuthProvider,
+    UpstreamOAuthProviderProtocol,
 };
 use mas_jose::claims::ClaimError;
 use mas_keystore::{Encrypter, Keystore};
 use mas_oidc_client::requests::{
-    authorization_code::AuthorizationValidationData, jose::JwtVerificationData,
+    authorization_code::{verify_authorization_code_id_token, AuthorizationValidationData},
+    jose::{JweDecryptionFn, JwtVerificationData},
 };
+use mas_policy::{Policy, Requester};
 use mas_router::UrlBuilder;
 use mas_storage::{
-    upstream_oauth2::{
-        UpstreamOAuthLinkRepository, UpstreamOAuthProviderRepository,
-        UpstreamOAuthSessionRepository,
-    },
+    upstream_oauth2::{UpstreamOAuthLinkRepository, UpstreamOAuthSessionRepository},
+    user::BrowserSessionRepository,
     BoxClock, BoxRepository, BoxRng, Clock,
 };
-use oauth2_types::errors::ClientErrorCode;
-use serde::Deserialize;
+use mas_templates::{error_codes, ErrorContext, FormPostContext, Templates};
+use oauth2_types::{errors::ClientErrorCode, scope::Scope};
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
 use thiserror::Error;
-use ulid::Ulid;
 
-use super::{client_credentials_for_provider, UpstreamSessionsCookie};
-use crate::{impl_from_error_for_route, upstream_oauth2::cache::MetadataCache};
+use super::UpstreamSessionsCookie;
+use crate::{impl_from_error_for_route, BoundActivityTracker};
 
-#[derive(Deserialize)]
-pub struct QueryParams {
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize)]
+pub struct Params {
     state: String,
 
+    /// Set when the response was posted again from our own site, to get the
+    /// session cookie along
+    resubmitted: Option<String>,
+
     #[serde(flatten)]
     code_or_error: CodeOrError,
 }
 
-#[derive(Deserialize)]
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize)]
 #[serde(untagged)]
 enum CodeOrError {
     Code {
@@ -83,9 +98,21 @@ pub(crate) enum RouteError {
     #[error("Missing ID token")]
     MissingIDToken,
 
+    #[error("Missing subject identifying the user")]
+    MissingSubject,
+
+    #[error("The provider doesn't have a userinfo endpoint")]
+    MissingUserinfoEndpoint,
+
     #[error("Invalid ID token")]
     InvalidIdToken(#[from] ClaimError),
 
+    #[error("The provider doesn't speak this protocol")]
+    ProtocolMismatch,
+
+    #[error("Invalid SAML response")]
+    InvalidSamlResponse(#[from] mas_saml::response::ResponseError),
+
     #[error("Error from the provider: {error}")]
     ClientError {
         error: ClientErrorCode,
@@ -95,17 +122,28 @@ pub(crate) enum RouteError {
     #[error("Missing session cookie")]
     MissingCookie,
 
+    #[error("Policy violation: {violations:?}")]
+    PolicyViolation {
+        violations: Vec<mas_policy::Violation>,
+    },
+
     #[error(transparent)]
     Internal(Box<dyn std::error::Error>),
 }
 
 impl_from_error_for_route!(mas_storage::RepositoryError);
 impl_from_error_for_route!(mas_http::ClientInitError);
-impl_from_error_for_route!(mas_oidc_client::error::DiscoveryError);
-impl_from_error_for_route!(mas_oidc_client::error::JwksError);
+impl_from_error_for_route!(super::cache::MetadataError);
 impl_from_error_for_route!(mas_oidc_client::error::TokenAuthorizationCodeError);
-impl_from_error_for_route!(super::ProviderCredentialsError);
+impl_from_error_for_route!(mas_oidc_client::error::IdTokenError);
+impl_from_error_for_route!(mas_oidc_client::error::UserInfoError);
+impl_from_error_for_route!(mas_axum_utils::upstream_oauth2::ProviderCredentialsError);
 impl_from_error_for_route!(super::cookie::UpstreamSessionNotFound);
+impl_from_error_for_route!(mas_policy::EvaluationError);
+impl_from_error_for_route!(mas_templates::TemplateError);
+impl_from_error_for_route!(minijinja::Error);
+impl_from_error_for_route!(mas_saml::CertificateError);
+impl_from_error_for_route!(mas_keystore::aead::Error);
 
 impl IntoResponse for RouteError {
     fn into_response(self) -> axum::response::Response {
@@ -113,6 +151,15 @@ impl IntoResponse for RouteError {
         let response = match self {
             Self::ProviderNotFound => (StatusCode::NOT_FOUND, "Provider not found").into_response(),
             Self::SessionNotFound => (StatusCode::NOT_FOUND, "Session not found").into_response(),
+            Self::PolicyViolation { violations } => {
+                let details = violations.iter().map(|v| v.msg.clone()).collect::<Vec<_>>();
+                let details = details.join("\n");
+                let ctx = ErrorContext::new()
+                    .with_code(error_codes::UPSTREAM_LOGIN_DENIED)
+                    .with_description("Login denied because of policy violation".to_owned())
+                    .with_details(details);
+                FancyError::new(ctx).into_response()
+            }
             Self::Internal(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
             e => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
         };
@@ -122,35 +169,55 @@ impl IntoResponse for RouteError {
 }
 
 #[tracing::instrument(
-    name = "handlers.upstream_oauth2.callback.get",
-    fields(upstream_oauth_provider.id = %provider_id),
+    name = "handlers.upstream_oauth2.callback.handler",
+    fields(upstream_oauth_provider.id = %provider_ref),
     skip_all,
     err,
 )]
 #[allow(clippy::too_many_lines, clippy::too_many_arguments)]
-pub(crate) async fn get(
+pub(crate) async fn handler(
+    method: Method,
     mut rng: BoxRng,
     clock: BoxClock,
     State(http_client_factory): State<HttpClientFactory>,
-    State(metadata_cache): State<MetadataCache>,
     mut repo: BoxRepository,
     State(url_builder): State<UrlBuilder>,
     State(encrypter): State<Encrypter>,
     State(keystore): State<Keystore>,
+    State(templates): State<Templates>,
+    mut policy: Policy,
+    activity_tracker: BoundActivityTracker,
+    user_agent: Option<TypedHeader<headers::UserAgent>>,
     cookie_jar: CookieJar,
-    Path(provider_id): Path<Ulid>,
-    Query(params): Query<QueryParams>,
-) -> Result<impl IntoResponse, RouteError> {
-    let provider = repo
-        .upstream_oauth_provider()
-        .lookup(provider_id)
+    Path(provider_ref): Path<String>,
+    Form(params): Form<Params>,
+) -> Result<Response, RouteError> {
+    let provider = super::lookup_provider(&mut repo, &provider_ref)
         .await?
         .ok_or(RouteError::ProviderNotFound)?;
 
     let sessions_cookie = UpstreamSessionsCookie::load(&cookie_jar);
-    let (session_id, _post_auth_action) = sessions_cookie
-        .find_session(provider_id, &params.state)
-        .map_err(|_| RouteError::MissingCookie)?;
+    let Ok((session_id, post_auth_action)) =
+        sessions_cookie.find_session(provider.id, &params.state)
+    else {
+        // With the `form_post` response mode, the browser posts the response to us
+        // from the provider's site, so our cookies, which are `SameSite=Lax`, are
+        // not sent. Post it again from our own site once to get them.
+        if method == Method::POST && params.resubmitted.is_none() {
+            let params = Params {
+                resubmitted: Some("true".to_owned()),
+                ..params
+            };
+            let ctx = FormPostContext::new(
+                url_builder.upstream_oauth_callback(provider.id, provider.slug.as_deref()),
+                params,
+            );
+            let rendered = templates.render_form_post(&ctx)?;
+            return Ok(Html(rendered).into_response());
+        }
+
+        return Err(RouteError::MissingCookie);
+    };
 
     let session = repo
         .upstream_oauth_session()
@@ -180,32 +247,54 @@ pub(crate) async fn get(
             error_description,
             ..
         } => {
+            // When checking the upstream session of a user who is logged in, an error
+            // means that they no longer have a session with the provider, so they
+            // are logged out here as well
+            if sessions_cookie.is_revalidation(session.id) {
+                tracing::info!(%error, "Upstream session is no longer valid, logging out");
+
+                let (session_info, mut cookie_jar) = cookie_jar.session_info();
+                let maybe_session = session_info.load_session(&clock, &mut repo).await?;
+                if let Some(browser_session) = maybe_session {
+                    repo.browser_session()
+                        .finish(&clock, browser_session)
+                        .await?;
+                    cookie_jar = cookie_jar.update_session_info(&session_info.mark_session_ended());
+                }
+
+                repo.save().await?;
+
+                let login = mas_router::Login::from(post_auth_action.cloned());
+                return Ok((cookie_jar, url_builder.redirect(&login)).into_response());
+            }
+
             return Err(RouteError::ClientError {
                 error,
                 error_description,
-            })
+            });
         }
         CodeOrError::Code { code } => code,
     };
 
-    let http_service = http_client_factory.http_service("upstream_oauth2.callback");
+    // SAML providers post their assertions to the assertion consumer service
+    if provider.protocol == UpstreamOAuthProviderProtocol::Saml {
+        return Err(RouteError::ProtocolMismatch);
+    }
 
-    // Discover the provider
-    let metadata = metadata_cache.get(&http_service, &provider.issuer).await?;
+    let http_service = http_client_factory.http_service("upstream_oauth2.callback");
 
-    // Fetch the JWKS
-    let jwks =
-        mas_oidc_client::requests::jose::fetch_jwks(&http_service, metadata.jwks_uri()).await?;
+    // Load the metadata and the JWKS of the provider
+    let metadata = super::cache::load(&http_service, &clock, &mut repo, &provider).await?;
 
     // Figure out the client credentials
     let client_credentials = client_credentials_for_provider(
         &provider,
-        metadata.token_endpoint(),
+        &metadata.token_endpoint,
         &keystore,
         &encrypter,
     )?;
 
-    let redirect_uri = url_builder.upstream_oauth_callback(provider.id);
+    let redirect_uri = url_builder.upstream_oauth_callback(provider.id, provider.slug.as_deref());
 
     // TODO: all that should be borrowed
     let validation_data = AuthorizationValidationData {
@@ -215,59 +304,427 @@ pub(crate) async fn get(
         redirect_uri,
     };
 
-    let id_token_verification_data = JwtVerificationData {
-        issuer: &provider.issuer,
-        jwks: &jwks,
-        // TODO: make that configurable
-        signing_algorithm: &mas_iana::jose::JsonWebSignatureAlg::Rs256,
-        client_id: &provider.client_id,
-    };
-
-    let (response, id_token) =
+    // The ID token is verified after the code exchange, so that the verification
+    // can be retried if the provider rotated its keys
+    let (response, _) =
         mas_oidc_client::requests::authorization_code::access_token_with_authorization_code(
             &http_service,
             client_credentials,
-            metadata.token_endpoint(),
-            code,
+            &metadata.token_endpoint,
+            code.clone(),
             validation_data,
-            Some(id_token_verification_data),
+            None,
+            None,
             clock.now(),
             &mut rng,
         )
         .await?;
 
-    let (_header, mut id_token) = id_token.ok_or(RouteError::MissingIDToken)?.into_parts();
+    // The provider may encrypt the ID tokens with one of the keys we publish
+    let decrypt_id_token: &JweDecryptionFn = &move |jwe| keystore.decrypt(jwe);
+
+    // Plain OAuth 2.0 providers don't issue ID tokens
+    let id_token = if let Some(jwks) = &metadata.jwks {
+        let res = super::cache::verify_signed(
+            &http_service,
+            &clock,
+            &mut repo,
+            &provider,
+            jwks,
+            |jwks| {
+                let verification_data = JwtVerificationData {
+                    issuer: &provider.issuer,
+                    jwks,
+                    // TODO: make that configurable
+                    signing_algorithm: &mas_iana::jose::JsonWebSignatureAlg::Rs256,
+                    client_id: &provider.client_id,
+                };
+                verify_authorization_code_id_token(
+                    &response,
+                    &code,
+                    &session.nonce,
+                    verification_data,
+                    Some(decrypt_id_token),
+                    clock.now(),
+                )
+            },
+        )
+        .await?;
+
+        match res {
+            Ok(id_token) => Some(id_token),
+            Err(e) => {
+                // Keep the JWKS if it was fetched again, so that invalid tokens
+                // don't make us fetch it every time
+                repo.save().await?;
+                return Err(e.into());
+            }
+        }
+    } else {
+        None
+    };
+
+    let id_token = match provider.protocol {
+        UpstreamOAuthProviderProtocol::Oidc => Some(id_token.ok_or(RouteError::MissingIDToken)?),
+        UpstreamOAuthProviderProtocol::OAuth2 | UpstreamOAuthProviderProtocol::Saml => None,
+    };
+
+    // Without an ID token, the userinfo endpoint is the only source of claims
+    let mut userinfo = if provider.fetch_userinfo || id_token.is_none() {
+        let userinfo_endpoint = metadata
+            .userinfo_endpoint
+            .as_ref()
+            .ok_or(RouteError::MissingUserinfoEndpoint)?;
+        let userinfo = mas_oidc_client::requests::userinfo::fetch_userinfo(
+            &http_service,
+            userinfo_endpoint,
+            &response.access_token,
+            None,
+            id_token.as_ref(),
+        )
+        .await?;
+        Some(userinfo)
+    } else {
+        None
+    };
+
+    // The responses of the additional endpoints are exposed as claims named after
+    // them
+    if !provider.endpoints.additional_userinfo_endpoints.is_empty() {
+        let userinfo = userinfo.get_or_insert_with(HashMap::new);
+        for (name, url) in &provider.endpoints.additional_userinfo_endpoints {
+            let resource = mas_oidc_client::requests::userinfo::fetch_user_resource(
+                &http_service,
+                url,
+                &response.access_token,
+            )
+            .await?;
+            userinfo.insert(name.clone(), resource);
+        }
+    }
+
+    let id_token_verified = id_token.is_some();
+    let mut claims = id_token
+        .map(|id_token| id_token.into_parts().1)
+        .unwrap_or_default();
+
+    // Complete the claims of the ID token with the ones from the userinfo
+    // endpoint, the ID token taking precedence
+    if let Some(userinfo) = &userinfo {
+        for (name, value) in userinfo {
+            claims.entry(name.clone()).or_insert_with(|| value.clone());
+        }
+    }
+
+    // Keep the tokens, for trusted services to call the APIs of the provider on
+    // behalf of the user
+    let tokens = if provider.store_tokens {
+        let encrypted_access_token =
+            encrypter.encrypt_to_string(response.access_token.as_bytes())?;
+        let encrypted_refresh_token = response
+            .refresh_token
+            .as_deref()
+            .map(|refresh_token| encrypter.encrypt_to_string(refresh_token.as_bytes()))
+            .transpose()?;
+        Some(UpstreamOAuthLinkTokens {
+            encrypted_access_token,
+            encrypted_refresh_token,
+            access_token_expires_at: response
+                .expires_in
+                .map(|expires_in| clock.now() + expires_in),
+        })
+    } else {
+        None
+    };
+
+    // The provider only has to say which scopes were granted if they differ
+    // from the requested ones
+    let granted_scope = response.scope.clone().or_else(|| {
+        sessions_cookie
+            .scope(session.id)
+            .and_then(|scope| scope.parse().ok())
+    });
+
+    complete_login(
+        &mut rng,
+        &clock,
+        repo,
+        &url_builder,
+        &mut policy,
+        &activity_tracker,
+        user_agent,
+        cookie_jar,
+        sessions_cookie,
+        &provider,
+        session,
+        claims,
+        // The ID token was only verified for OpenID Connect providers
+        id_token_verified.then_some(response.id_token).flatten(),
+        userinfo,
+        tokens,
+        granted_scope,
+    )
+    .await
+}
+
+/// Identify the user from the claims of the provider, check that they are
+/// allowed to log in, and link the session to their upstream account.
+///
+/// This is shared with the SAML assertion consumer service, which gets the
+/// claims from the assertion instead.
+#[allow(clippy::too_many_arguments, clippy::too_many_lines)]
+pub(super) async fn complete_login(
+    rng: &mut BoxRng,
+    clock: &BoxClock,
+    mut repo: BoxRepository,
+    url_builder: &UrlBuilder,
+    policy: &mut Policy,
+    activity_tracker: &BoundActivityTracker,
+    user_agent: Option<TypedHeader<headers::UserAgent>>,
+    cookie_jar: CookieJar,
+    sessions_cookie: UpstreamSessionsCookie,
+    provider: &UpstreamOAuthProvider,
+    session: UpstreamOAuthAuthorizationSession,
+    mut claims: HashMap<String, serde_json::Value>,
+    id_token: Option<String>,
+    userinfo: Option<HashMap<String, serde_json::Value>>,
+    tokens: Option<UpstreamOAuthLinkTokens>,
+    granted_scope: Option<Scope>,
+) -> Result<Response, RouteError> {
+    // Extract the subject, either from the `sub` claim or through the template
+    // configured on the provider
+    let subject = if let Some(template) = &provider.claims_imports.subject_template {
+        let env = super::template::environment();
+        let claims: serde_json::Map<_, _> = claims.clone().into_iter().collect();
+        super::template::render(&env, template, &claims)?.ok_or(RouteError::MissingSubject)?
+    } else {
+        match claims.remove("sub") {
+            Some(serde_json::Value::String(subject)) => subject,
+            _ => return Err(RouteError::MissingSubject),
+        }
+    };
 
-    // Extract the subject from the id_token
-    let subject = mas_jose::claims::SUB.extract_required(&mut id_token)?;
+    // Check that the user is allowed to log in through this provider, before
+    // linking the login to any account
+    let claim = |name: &str| {
+        claims
+            .get(name)
+            .and_then(serde_json::Value::as_str)
+            .map(ToOwned::to_owned)
+    };
+    let email = if provider.claims_imports.email.ignore() {
+        None
+    } else {
+        claim("email")
+    };
+    let username = if provider.claims_imports.localpart.ignore() {
+        None
+    } else {
+        claim("preferred_username")
+    };
+    // The upstream session ID, used to handle back-channel logouts. It is only
+    // trusted if it comes from a verified ID token.
+    let sid = id_token.as_ref().and_then(|_| claim("sid"));
+
+    let requester = Requester::new(clock.now())
+        .with_ip_address(activity_tracker.ip())
+        .with_user_agent(user_agent.map(|ua| ua.as_str().to_owned()));
+    let res = policy
+        .evaluate_upstream_login(
+            provider,
+            &subject,
+            &claims,
+            email.as_deref(),
+            username.as_deref(),
+            &requester,
+        )
+        .await?;
+    if !res.valid() {
+        return Err(RouteError::PolicyViolation {
+            violations: res.violations,
+        });
+    }
 
     // Look for an existing link
     let maybe_link = repo
         .upstream_oauth_link()
-        .find_by_subject(&provider, &subject)
+        .find_by_subject(provider, &subject)
         .await?;
 
+    let existing_link = maybe_link.is_some();
     let link = if let Some(link) = maybe_link {
         link
     } else {
         repo.upstream_oauth_link()
-            .add(&mut rng, &clock, &provider, subject)
+            .add(rng, clock, provider, subject)
             .await?
     };
 
+    // Add the scopes granted through this login to the ones granted before
+    if let Some(mut scope) = granted_scope {
+        if existing_link {
+            // The scopes granted on links created before they were recorded are not
+            // known, but they at least include the ones of the provider
+            let previous_scope = repo
+                .upstream_oauth_link()
+                .granted_scope(&link)
+                .await?
+                .unwrap_or_else(|| provider.scope.clone());
+            for token in previous_scope.iter() {
+                scope.insert(token.clone());
+            }
+        }
+
+        repo.upstream_oauth_link()
+            .set_granted_scope(&link, &scope)
+            .await?;
+    }
+
+    // Replace the tokens stored on the link with the ones from this login
+    if let Some(tokens) = &tokens {
+        repo.upstream_oauth_link()
+            .set_tokens(&link, Some(tokens))
+            .await?;
+    }
+
     let session = repo
         .upstream_oauth_session()
-        .complete_with_link(&clock, session, &link, response.id_token)
+        .complete_with_link(
+            clock,
+            session,
+            &link,
+            id_token,
+            sid,
+            userinfo.map(|userinfo| serde_json::Value::Object(userinfo.into_iter().collect())),
+        )
         .await?;
 
     let cookie_jar = sessions_cookie
         .add_link_to_session(session.id, link.id)?
-        .save(cookie_jar, &clock);
+        .save(cookie_jar, clock);
 
     repo.save().await?;
 
     Ok((
         cookie_jar,
         url_builder.redirect(&mas_router::UpstreamOAuth2Link::new(link.id)),
-    ))
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{header::LOCATION, Request};
+    use mas_data_model::{
+        UpstreamOAuthProviderAuthorizationParams, UpstreamOAuthProviderClaimsImports,
+        UpstreamOAuthProviderEndpoints, UpstreamOAuthProviderHealthCheckSettings,
+        UpstreamOAuthProviderPkceMethod, UpstreamOAuthProviderResponseMode,
+        UpstreamOAuthProviderSamlSettings, UpstreamOAuthProviderUiOptions,
+    };
+    use mas_iana::oauth::OAuthClientAuthenticationMethod;
+    use mas_router::Route;
+    use mas_storage::RepositoryAccess;
+    use oauth2_types::scope::OPENID;
+    use sqlx::PgPool;
+    use url::Url;
+
+    use super::*;
+    use crate::test_utils::{
+        init_tracing, CookieHelper, RequestBuilderExt, ResponseExt, TestState,
+    };
+
+    /// Add an OAuth 2.0 provider which posts its responses back with the
+    /// `form_post` response mode
+    async fn add_provider(state: &TestState) -> UpstreamOAuthProvider {
+        let mut repo = state.repository().await.unwrap();
+        let provider = repo
+            .upstream_oauth_provider()
+            .add(
+                &mut state.rng(),
+                &state.clock,
+                "https://example.com/".to_owned(),
+                None,
+                Scope::from_iter([OPENID]),
+                OAuthClientAuthenticationMethod::None,
+                None,
+                "client".to_owned(),
+                None,
+                UpstreamOAuthProviderClaimsImports::default(),
+                UpstreamOAuthProviderPkceMethod::default(),
+                UpstreamOAuthProviderAuthorizationParams {
+                    response_mode: Some(UpstreamOAuthProviderResponseMode::FormPost),
+                    ..UpstreamOAuthProviderAuthorizationParams::default()
+                },
+                false,
+                UpstreamOAuthProviderUiOptions::default(),
+                UpstreamOAuthProviderProtocol::OAuth2,
+                UpstreamOAuthProviderEndpoints {
+                    authorization_endpoint: Some("https://example.com/authorize".parse().unwrap()),
+                    token_endpoint: Some("https://example.com/token".parse().unwrap()),
+                    ..UpstreamOAuthProviderEndpoints::default()
+                },
+                UpstreamOAuthProviderSamlSettings::default(),
+                false,
+                UpstreamOAuthProviderHealthCheckSettings::default(),
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+        provider
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_form_post_resubmitted(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let cookies = CookieHelper::new();
+        let provider = add_provider(&state).await;
+
+        // Start the login, which remembers the session in a cookie
+        let authorize = mas_router::UpstreamOAuth2Authorize::new(provider.id);
+        let request = Request::get(&*authorize.path_and_query()).empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::TEMPORARY_REDIRECT);
+        let location = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+        let location = Url::parse(location).unwrap();
+        let (_, upstream_state) = location
+            .query_pairs()
+            .find(|(key, _)| key == "state")
+            .unwrap();
+
+        let callback = mas_router::UpstreamOAuth2Callback::new(provider.id);
+        let form = serde_json::json!({
+            "state": upstream_state,
+            "error": "access_denied",
+        });
+
+        // The provider posts the response from its own site, without our cookies,
+        // so it is posted again from ours
+        let request = Request::post(callback.path()).form(form.clone());
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        assert!(response
+            .body()
+            .contains(r#"<input type="hidden" name="resubmitted" value="true" />"#));
+        assert!(response
+            .body()
+            .contains(&format!(r#"name="state" value="{upstream_state}""#)));
+
+        // It is only posted again once
+        let mut resubmitted = form;
+        resubmitted["resubmitted"] = "true".into();
+        let request = Request::post(callback.path()).form(resubmitted.clone());
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        assert_eq!(response.body(), "Missing session cookie");
+
+        // With the cookies, the response of the provider is handled
+        let request = Request::post(callback.path()).form(resubmitted);
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        assert_eq!(response.body(), "Error from the provider: access_denied");
+    }
 }
@@ -35,6 +35,10 @@ pub struct Payload {
     state: String,
     link: Option<Ulid>,
     post_auth_action: Option<PostAuthAction>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    revalidation: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    scope: Option<String>,
 }
 
 impl Payload {
@@ -99,10 +103,46 @@ impl UpstreamSessions {
             state,
             link: None,
             post_auth_action,
+            revalidation: false,
+            scope: None,
         });
         self
     }
 
+    /// Mark a session as re-validating the upstream session of a user who is
+    /// already logged in
+    pub fn mark_as_revalidation(mut self, session: Ulid) -> Self {
+        if let Some(payload) = self.0.iter_mut().find(|p| p.session == session) {
+            payload.revalidation = true;
+        }
+        self
+    }
+
+    /// Whether a session is re-validating the upstream session of a user who
+    /// is already logged in
+    pub fn is_revalidation(&self, session: Ulid) -> bool {
+        self.0
+            .iter()
+            .any(|p| p.session == session && p.revalidation)
+    }
+
+    /// Remember the scopes requested from the provider by a session
+    pub fn with_scope(mut self, session: Ulid, scope: String) -> Self {
+        if let Some(payload) = self.0.iter_mut().find(|p| p.session == session) {
+            payload.scope = Some(scope);
+        }
+        self
+    }
+
+    /// The scopes requested from the provider by a session, if they were
+    /// remembered
+    pub fn scope(&self, session: Ulid) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|p| p.session == session)
+            .and_then(|p| p.scope.as_deref())
+    }
+
     // Find a session ID from the provider and the state
     pub fn find_session(
         &self,
@@ -188,7 +228,18 @@ mod tests {
 
         let second_session = Ulid::from_datetime_with_source(now.into(), &mut rng);
         let second_state = "second-state";
-        let sessions = sessions.add(second_session, provider_b, second_state.into(), None);
+        let sessions = sessions
+            .add(second_session, provider_b, second_state.into(), None)
+            .mark_as_revalidation(second_session);
+
+        // Only the second session re-validates an upstream session
+        assert!(!sessions.is_revalidation(first_session));
+        assert!(sessions.is_revalidation(second_session));
+
+        // Remember the scopes requested by the first session
+        let sessions = sessions.with_scope(first_session, "openid calendar".to_owned());
+        assert_eq!(sessions.scope(first_session), Some("openid calendar"));
+        assert_eq!(sessions.scope(second_session), None);
 
         let sessions = sessions.expire(now);
         assert_eq!(
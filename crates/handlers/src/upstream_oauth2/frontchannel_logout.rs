@@ -0,0 +1,309 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The OpenID Connect front-channel logout endpoint, loaded by the upstream
+//! providers in an iframe to end the local sessions of users who logged out
+//! from them
+
+use axum::{
+    extract::{Path, Query, State},
+    response::{IntoResponse, Response},
+    TypedHeader,
+};
+use headers::CacheControl;
+use hyper::StatusCode;
+use mas_axum_utils::sentry::SentryEventID;
+use mas_data_model::UpstreamOAuthProviderProtocol;
+use mas_storage::{user::BrowserSessionFilter, BoxClock, BoxRepository};
+use serde::Deserialize;
+use thiserror::Error;
+
+use super::backchannel_logout::finish_sessions;
+use crate::{impl_from_error_for_route, IntrospectionCache};
+
+#[derive(Deserialize)]
+pub(crate) struct Params {
+    iss: Option<String>,
+    sid: Option<String>,
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum RouteError {
+    #[error("Provider not found")]
+    ProviderNotFound,
+
+    #[error("The provider doesn't support front-channel logout")]
+    ProtocolMismatch,
+
+    #[error("The issuer doesn't match the provider")]
+    IssuerMismatch,
+
+    #[error("Missing sid parameter")]
+    MissingSessionId,
+
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error>),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> axum::response::Response {
+        let event_id = sentry::capture_error(&self);
+        let response = match self {
+            Self::ProviderNotFound => (StatusCode::NOT_FOUND, "Provider not found").into_response(),
+            Self::Internal(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+            e => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        };
+
+        (
+            SentryEventID::from(event_id),
+            TypedHeader(CacheControl::new().with_no_store()),
+            response,
+        )
+            .into_response()
+    }
+}
+
+/// End the local sessions authenticated through an upstream session, when the
+/// user logs out from the provider
+///
+/// The provider must send the `sid` parameter, and should send the `iss`
+/// parameter. The user can't be recognised through their cookies, as browsers
+/// don't send them to third-party iframes.
+#[tracing::instrument(
+    name = "handlers.upstream_oauth2.frontchannel_logout.get",
+    fields(upstream_oauth_provider.id = %provider_ref),
+    skip_all,
+    err,
+)]
+pub(crate) async fn get(
+    clock: BoxClock,
+    mut repo: BoxRepository,
+    State(introspection_cache): State<IntrospectionCache>,
+    Path(provider_ref): Path<String>,
+    Query(params): Query<Params>,
+) -> Result<Response, RouteError> {
+    let provider = super::lookup_provider(&mut repo, &provider_ref)
+        .await?
+        .ok_or(RouteError::ProviderNotFound)?;
+
+    // Only OpenID Connect providers have sessions IDs
+    if provider.protocol != UpstreamOAuthProviderProtocol::Oidc {
+        return Err(RouteError::ProtocolMismatch);
+    }
+
+    if params.iss.is_some_and(|iss| iss != provider.issuer) {
+        return Err(RouteError::IssuerMismatch);
+    }
+
+    let sid = params.sid.ok_or(RouteError::MissingSessionId)?;
+
+    let filter = BrowserSessionFilter::new()
+        .authenticated_by_upstream_oauth_provider(&provider)
+        .authenticated_by_upstream_oauth_sid(&sid)
+        .active_only();
+    let ended_sessions = finish_sessions(&mut repo, &clock, filter).await?;
+
+    repo.save().await?;
+
+    for session_id in ended_sessions {
+        introspection_cache.invalidate_session(session_id);
+    }
+
+    Ok((
+        TypedHeader(CacheControl::new().with_no_store()),
+        StatusCode::OK,
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Request;
+    use mas_data_model::{
+        BrowserSession, UpstreamOAuthProvider, UpstreamOAuthProviderAuthorizationParams,
+        UpstreamOAuthProviderClaimsImports, UpstreamOAuthProviderEndpoints,
+        UpstreamOAuthProviderHealthCheckSettings, UpstreamOAuthProviderPkceMethod,
+        UpstreamOAuthProviderSamlSettings, UpstreamOAuthProviderUiOptions,
+    };
+    use mas_iana::oauth::OAuthClientAuthenticationMethod;
+    use mas_router::Route;
+    use mas_storage::{
+        upstream_oauth2::{
+            UpstreamOAuthLinkRepository, UpstreamOAuthProviderRepository,
+            UpstreamOAuthSessionRepository,
+        },
+        user::{BrowserSessionRepository, UserRepository},
+        RepositoryAccess,
+    };
+    use oauth2_types::scope::{Scope, OPENID};
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::test_utils::{init_tracing, RequestBuilderExt, ResponseExt, TestState};
+
+    /// Add an OpenID Connect provider, and a user linked to it with one
+    /// browser session per upstream session ID
+    async fn setup(
+        state: &TestState,
+        sids: &[&str],
+    ) -> (UpstreamOAuthProvider, Vec<BrowserSession>) {
+        let mut rng = state.rng();
+        let mut repo = state.repository().await.unwrap();
+
+        let provider = repo
+            .upstream_oauth_provider()
+            .add(
+                &mut rng,
+                &state.clock,
+                "https://example.com/".to_owned(),
+                None,
+                Scope::from_iter([OPENID]),
+                OAuthClientAuthenticationMethod::None,
+                None,
+                "client".to_owned(),
+                None,
+                UpstreamOAuthProviderClaimsImports::default(),
+                UpstreamOAuthProviderPkceMethod::default(),
+                UpstreamOAuthProviderAuthorizationParams::default(),
+                false,
+                UpstreamOAuthProviderUiOptions::default(),
+                UpstreamOAuthProviderProtocol::Oidc,
+                UpstreamOAuthProviderEndpoints::default(),
+                UpstreamOAuthProviderSamlSettings::default(),
+                false,
+                UpstreamOAuthProviderHealthCheckSettings::default(),
+            )
+            .await
+            .unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "john".to_owned())
+            .await
+            .unwrap();
+        let link = repo
+            .upstream_oauth_link()
+            .add(&mut rng, &state.clock, &provider, "subject".to_owned())
+            .await
+            .unwrap();
+        repo.upstream_oauth_link()
+            .associate_to_user(&link, &user)
+            .await
+            .unwrap();
+
+        let mut sessions = Vec::new();
+        for sid in sids {
+            let upstream_session = repo
+                .upstream_oauth_session()
+                .add(
+                    &mut rng,
+                    &state.clock,
+                    &provider,
+                    "state".to_owned(),
+                    None,
+                    "nonce".to_owned(),
+                )
+                .await
+                .unwrap();
+            let upstream_session = repo
+                .upstream_oauth_session()
+                .complete_with_link(
+                    &state.clock,
+                    upstream_session,
+                    &link,
+                    None,
+                    Some((*sid).to_owned()),
+                    None,
+                )
+                .await
+                .unwrap();
+
+            let session = repo
+                .browser_session()
+                .add(&mut rng, &state.clock, &user, None)
+                .await
+                .unwrap();
+            repo.browser_session()
+                .authenticate_with_upstream(&mut rng, &state.clock, &session, &upstream_session)
+                .await
+                .unwrap();
+            sessions.push(session);
+        }
+
+        repo.save().await.unwrap();
+
+        (provider, sessions)
+    }
+
+    async fn logout(
+        state: &TestState,
+        provider: &UpstreamOAuthProvider,
+        query: &str,
+    ) -> hyper::Response<String> {
+        let path = mas_router::UpstreamOAuth2FrontchannelLogout::new(provider.id).path_and_query();
+        let request = Request::get(format!("{path}?{query}")).empty();
+        state.request(request).await
+    }
+
+    /// Whether each of the sessions is still active
+    async fn active(state: &TestState, sessions: &[BrowserSession]) -> Vec<bool> {
+        let mut repo = state.repository().await.unwrap();
+        let mut active = Vec::new();
+        for session in sessions {
+            let session = repo
+                .browser_session()
+                .lookup(session.id)
+                .await
+                .unwrap()
+                .unwrap();
+            active.push(session.finished_at.is_none());
+        }
+        repo.cancel().await.unwrap();
+        active
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_frontchannel_logout(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let (provider, sessions) = setup(&state, &["sid-1", "sid-2"]).await;
+
+        // The session ID is required
+        let response = logout(&state, &provider, "iss=https%3A%2F%2Fexample.com%2F").await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+
+        // The issuer must match the provider
+        let response = logout(&state, &provider, "iss=https%3A%2F%2Fevil.com%2F&sid=sid-1").await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        assert_eq!(active(&state, &sessions).await, [true, true]);
+
+        // Only the sessions authenticated through this upstream session are
+        // ended
+        let response = logout(
+            &state,
+            &provider,
+            "iss=https%3A%2F%2Fexample.com%2F&sid=sid-1",
+        )
+        .await;
+        response.assert_status(StatusCode::OK);
+        assert_eq!(active(&state, &sessions).await, [false, true]);
+
+        // The issuer is optional
+        let response = logout(&state, &provider, "sid=sid-2").await;
+        response.assert_status(StatusCode::OK);
+        assert_eq!(active(&state, &sessions).await, [false, false]);
+    }
+}
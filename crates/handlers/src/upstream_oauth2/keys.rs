@@ -0,0 +1,34 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The public keys which upstream providers can use to encrypt the ID tokens
+//! they issue for us
+
+use axum::{extract::State, response::IntoResponse, TypedHeader};
+use headers::{IfModifiedSince, IfNoneMatch};
+use mas_keystore::Keystore;
+use mas_storage::BoxClock;
+
+use crate::conditional::conditional_json;
+
+#[tracing::instrument(name = "handlers.upstream_oauth2.keys.get", skip_all)]
+pub(crate) async fn get(
+    clock: BoxClock,
+    State(key_store): State<Keystore>,
+    if_none_match: Option<TypedHeader<IfNoneMatch>>,
+    if_modified_since: Option<TypedHeader<IfModifiedSince>>,
+) -> impl IntoResponse {
+    let jwks = key_store.public_encryption_jwks();
+    conditional_json(&clock, &jwks, if_none_match, if_modified_since)
+}
@@ -14,36 +14,50 @@
 
 use axum::{
     extract::{Path, State},
-    response::{Html, IntoResponse},
+    response::{Html, IntoResponse, Response},
     Form, TypedHeader,
 };
 use hyper::StatusCode;
 use mas_axum_utils::{
     cookies::CookieJar,
-    csrf::{CsrfExt, ProtectedForm},
+    csrf::{CsrfExt, CsrfToken, ProtectedForm},
     sentry::SentryEventID,
     FancyError, SessionInfoExt,
 };
-use mas_data_model::{UpstreamOAuthProviderImportPreference, User};
+use mas_data_model::{
+    ProfileAttribute, UpstreamOAuthAuthorizationSession, UpstreamOAuthLink, UpstreamOAuthProvider,
+    UpstreamOAuthProviderGroupsImport, UpstreamOAuthProviderImportPreference,
+    UpstreamOAuthProviderLocalpartConflict, User, UserEmail,
+};
+use mas_i18n::DataLocale;
 use mas_jose::jwt::Jwt;
-use mas_policy::Policy;
+use mas_policy::{Policy, Requester};
 use mas_router::UrlBuilder;
 use mas_storage::{
-    job::{JobRepositoryExt, ProvisionUserJob},
-    upstream_oauth2::{UpstreamOAuthLinkRepository, UpstreamOAuthSessionRepository},
-    user::{BrowserSessionRepository, UserEmailRepository, UserRepository},
-    BoxClock, BoxRepository, BoxRng, RepositoryAccess,
+    job::{
+        JobRepositoryExt, NotifyUserEventJob, ProvisionUserJob, SendSecurityNotificationJob,
+        UserLifecycleEvent,
+    },
+    upstream_oauth2::{
+        UpstreamOAuthLinkFilter, UpstreamOAuthLinkRepository, UpstreamOAuthSessionRepository,
+    },
+    user::{BrowserSessionRepository, UserEmailFilter, UserEmailRepository, UserRepository},
+    BoxClock, BoxRepository, BoxRng, Clock, Pagination, RepositoryAccess,
 };
 use mas_templates::{
-    ErrorContext, TemplateContext, Templates, UpstreamExistingLinkContext, UpstreamRegister,
-    UpstreamSuggestLink,
+    error_codes, ErrorContext, TemplateContext, Templates, UpstreamExistingLinkContext,
+    UpstreamLinkExistingContext, UpstreamRegister, UpstreamSuggestLink,
 };
 use serde::Deserialize;
 use thiserror::Error;
 use ulid::Ulid;
 
 use super::UpstreamSessionsCookie;
-use crate::{impl_from_error_for_route, views::shared::OptionalPostAuthAction, PreferredLanguage};
+use crate::{
+    impl_from_error_for_route,
+    views::{account::emails::send_verification_email, shared::OptionalPostAuthAction},
+    BoundActivityTracker, Limiter, PreferredLanguage, SiteConfig,
+};
 
 #[derive(Debug, Error)]
 pub(crate) enum RouteError {
@@ -80,6 +94,10 @@ pub(crate) enum RouteError {
     #[error("Missing username")]
     MissingUsername,
 
+    /// The localpart forced by the upstream provider is already taken
+    #[error("Username {0:?} is already taken")]
+    UsernameTaken(String),
+
     #[error("Policy violation: {violations:?}")]
     PolicyViolation {
         violations: Vec<mas_policy::Violation>,
@@ -95,6 +113,7 @@ impl_from_error_for_route!(super::cookie::UpstreamSessionNotFound);
 impl_from_error_for_route!(mas_storage::RepositoryError);
 impl_from_error_for_route!(mas_policy::EvaluationError);
 impl_from_error_for_route!(mas_jose::jwt::JwtDecodeError);
+impl_from_error_for_route!(minijinja::Error);
 
 impl IntoResponse for RouteError {
     fn into_response(self) -> axum::response::Response {
@@ -105,12 +124,22 @@ impl IntoResponse for RouteError {
                 let details = violations.iter().map(|v| v.msg.clone()).collect::<Vec<_>>();
                 let details = details.join("\n");
                 let ctx = ErrorContext::new()
+                    .with_code(error_codes::UPSTREAM_REGISTRATION_DENIED)
                     .with_description(
                         "Account registration denied because of policy violation".to_owned(),
                     )
                     .with_details(details);
                 FancyError::new(ctx).into_response()
             }
+            Self::UsernameTaken(username) => {
+                let ctx = ErrorContext::new()
+                    .with_code(error_codes::UPSTREAM_USERNAME_TAKEN)
+                    .with_description(format!(
+                        "The username {username:?} is already taken, and the upstream provider \
+                         does not allow choosing another one"
+                    ));
+                FancyError::new(ctx).into_response()
+            }
             Self::Internal(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
             e => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
         };
@@ -119,13 +148,115 @@ impl IntoResponse for RouteError {
     }
 }
 
-#[derive(Deserialize, Default)]
+/// The standard claims of the upstream account, read leniently: providers
+/// which are not fully compliant send booleans as strings, and SAML-backed ones
+/// send every attribute as a list of values
+#[derive(Default)]
 struct StandardClaims {
     name: Option<String>,
     email: Option<String>,
-    #[serde(default)]
     email_verified: bool,
     preferred_username: Option<String>,
+    picture: Option<String>,
+    locale: Option<String>,
+
+    /// All the claims, for the templates and the groups import
+    claims: serde_json::Map<String, serde_json::Value>,
+}
+
+/// The value of a string claim, or the first string of a multi-valued claim
+fn string_claim(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::String(value) => Some(value.clone()),
+        serde_json::Value::Array(values) => values.first().and_then(string_claim),
+        _ => None,
+    }
+}
+
+/// The value of a boolean claim, also accepting the `"true"` string, or `false`
+/// if it can't be understood
+fn bool_claim(value: &serde_json::Value) -> bool {
+    match value {
+        serde_json::Value::Bool(value) => *value,
+        serde_json::Value::String(value) => value.eq_ignore_ascii_case("true"),
+        serde_json::Value::Array(values) => values.first().is_some_and(bool_claim),
+        _ => false,
+    }
+}
+
+impl StandardClaims {
+    /// The claims of the ID token of the session, completed by the ones
+    /// returned by the userinfo endpoint if it was queried
+    fn from_session(session: &UpstreamOAuthAuthorizationSession) -> Result<Self, RouteError> {
+        let mut claims = session
+            .id_token()
+            .map(Jwt::<'_, serde_json::Map<String, serde_json::Value>>::try_from)
+            .transpose()?
+            .map(|id_token| id_token.into_parts().1)
+            .unwrap_or_default();
+
+        // The claims of the ID token take precedence
+        if let Some(serde_json::Value::Object(userinfo)) = session.userinfo() {
+            for (name, value) in userinfo {
+                claims.entry(name.clone()).or_insert_with(|| value.clone());
+            }
+        }
+
+        Ok(Self::from_claims(claims))
+    }
+
+    fn from_claims(claims: serde_json::Map<String, serde_json::Value>) -> Self {
+        let string = |name: &str| claims.get(name).and_then(string_claim);
+
+        Self {
+            name: string("name"),
+            email: string("email"),
+            email_verified: claims.get("email_verified").is_some_and(bool_claim),
+            preferred_username: string("preferred_username"),
+            picture: string("picture"),
+            locale: string("locale"),
+            claims,
+        }
+    }
+
+    /// The `locale` claim, normalized, if it is a valid BCP 47 language tag
+    fn locale(&self) -> Option<String> {
+        let locale: DataLocale = self.locale.as_deref()?.parse().ok()?;
+        Some(locale.to_string())
+    }
+
+    /// The groups of the user, if they are imported.
+    ///
+    /// A missing claim is treated as an empty list of groups, so that users
+    /// removed from all their groups upstream lose them here too.
+    fn groups(&self, import: &UpstreamOAuthProviderGroupsImport) -> Option<Vec<String>> {
+        let claim = import.claim.as_deref()?;
+        let groups = match self.claims.get(claim) {
+            Some(serde_json::Value::Array(values)) => values
+                .iter()
+                .filter_map(serde_json::Value::as_str)
+                .map(ToOwned::to_owned)
+                .collect(),
+            // Some providers send a single group as a string
+            Some(serde_json::Value::String(group)) => vec![group.clone()],
+            _ => Vec::new(),
+        };
+        Some(groups)
+    }
+}
+
+/// The value of a claim, as rendered by the template of the import preference
+/// if it has one, or else the value of the standard claim
+fn claim_value(
+    env: &minijinja::Environment<'_>,
+    claims: &serde_json::Map<String, serde_json::Value>,
+    preference: &UpstreamOAuthProviderImportPreference,
+    standard: Option<String>,
+) -> Result<Option<String>, RouteError> {
+    match &preference.template {
+        Some(template) => Ok(super::template::render(env, template, claims)?),
+        None => Ok(standard),
+    }
 }
 
 /// Utility function to import a claim from the upstream provider's response,
@@ -166,8 +297,418 @@ fn import_claim(
     Ok(())
 }
 
+/// Replace the groups of the user with the ones from the upstream provider, and
+/// grant or revoke their admin access accordingly.
+///
+/// Does nothing if the groups are not imported from this provider.
+async fn sync_groups(
+    repo: &mut BoxRepository,
+    import: &UpstreamOAuthProviderGroupsImport,
+    groups: Option<Vec<String>>,
+    mut user: User,
+) -> Result<User, RouteError> {
+    let Some(groups) = groups else {
+        return Ok(user);
+    };
+
+    if let Some(can_request_admin) = import.grants_admin(&groups) {
+        if user.can_request_admin != can_request_admin {
+            tracing::info!(
+                user.id = %user.id,
+                can_request_admin,
+                "Updating admin access of the user based on their upstream groups"
+            );
+            user = repo
+                .user()
+                .set_can_request_admin(user, can_request_admin)
+                .await?;
+        }
+    }
+
+    if user.upstream_groups != groups {
+        user = repo.user().set_upstream_groups(user, groups).await?;
+    }
+
+    Ok(user)
+}
+
+/// Schedule a job to update the profile of the user on the homeserver with
+/// the claims from the upstream provider, for the claims which are configured
+/// to be synced on every login.
+///
+/// Missing claims are skipped instead of failing the login. The preferred
+/// locale of the user is also updated from the `locale` claim, if present, and
+/// their groups are synced if they are imported.
+async fn sync_profile(
+    repo: &mut BoxRepository,
+    link: &UpstreamOAuthLink,
+    upstream_session: &UpstreamOAuthAuthorizationSession,
+    user: &User,
+) -> Result<(), RouteError> {
+    let provider = repo
+        .upstream_oauth_provider()
+        .lookup(link.provider_id)
+        .await?
+        .ok_or(RouteError::ProviderNotFound)?;
+
+    let payload = StandardClaims::from_session(upstream_session)?;
+
+    if let Some(locale) = payload.locale() {
+        if user.locale.as_ref() != Some(&locale) {
+            repo.user().set_locale(user.clone(), Some(locale)).await?;
+        }
+    }
+
+    let groups = payload.groups(&provider.claims_imports.groups);
+    sync_groups(repo, &provider.claims_imports.groups, groups, user.clone()).await?;
+
+    let imports = &provider.claims_imports;
+    let modified = repo.user().modified_profile_attributes(user).await?;
+    let sync_displayname = imports
+        .displayname
+        .sync_on_login(modified.contains(&ProfileAttribute::Displayname));
+    let sync_avatar_url = imports
+        .avatar_url
+        .sync_on_login(modified.contains(&ProfileAttribute::AvatarUrl));
+    if !sync_displayname && !sync_avatar_url {
+        return Ok(());
+    }
+
+    let env = super::template::environment();
+    let mut job = ProvisionUserJob::new(user);
+    let mut changed = false;
+
+    if sync_displayname {
+        let name = claim_value(&env, &payload.claims, &imports.displayname, payload.name)?;
+        if let Some(name) = name {
+            job = job.set_display_name(name);
+            changed = true;
+        }
+    }
+
+    if sync_avatar_url {
+        let picture = claim_value(&env, &payload.claims, &imports.avatar_url, payload.picture)?;
+        if let Some(picture) = picture {
+            job = job.set_avatar_url(picture);
+            changed = true;
+        }
+    }
+
+    if changed {
+        repo.job().schedule_job(job).await?;
+    }
+
+    Ok(())
+}
+
+/// How many numbers are tried as a suffix of a taken localpart
+const MAX_LOCALPART_SUFFIX: u32 = 100;
+
+/// What to do with the localpart forced by the upstream provider
+enum ForcedLocalpart {
+    /// It can be used, possibly with a suffix
+    Available(String),
+
+    /// It is taken, and the user has to choose another one
+    Taken(String),
+
+    /// It belongs to an existing user, which the upstream account can be linked
+    /// to once the user proved they own it
+    Existing(User),
+}
+
+/// The localpart forced by the upstream provider, if any
+fn forced_localpart(
+    env: &minijinja::Environment<'_>,
+    payload: &StandardClaims,
+    provider: &UpstreamOAuthProvider,
+    site_config: &SiteConfig,
+) -> Result<Option<String>, RouteError> {
+    let preference = &provider.claims_imports.localpart;
+    if !preference.is_forced() {
+        return Ok(None);
+    }
+
+    let localpart = claim_value(
+        env,
+        &payload.claims,
+        preference,
+        payload.preferred_username.clone(),
+    )?;
+
+    Ok(localpart.map(|localpart| {
+        if site_config.case_fold_usernames {
+            localpart.to_lowercase()
+        } else {
+            localpart
+        }
+    }))
+}
+
+/// Check whether the localpart forced by the upstream provider is available,
+/// and resolve the conflict as configured on the provider if it isn't
+async fn resolve_forced_localpart(
+    repo: &mut BoxRepository,
+    conflict: UpstreamOAuthProviderLocalpartConflict,
+    localpart: String,
+) -> Result<ForcedLocalpart, RouteError> {
+    let Some(existing) = repo.user().find_by_username(&localpart).await? else {
+        return Ok(ForcedLocalpart::Available(localpart));
+    };
+
+    match conflict {
+        UpstreamOAuthProviderLocalpartConflict::Fail => Err(RouteError::UsernameTaken(localpart)),
+        UpstreamOAuthProviderLocalpartConflict::AddSuffix => {
+            for suffix in 1..=MAX_LOCALPART_SUFFIX {
+                let candidate = format!("{localpart}{suffix}");
+                if !repo.user().exists(&candidate).await? {
+                    return Ok(ForcedLocalpart::Available(candidate));
+                }
+            }
+
+            Err(RouteError::UsernameTaken(localpart))
+        }
+        UpstreamOAuthProviderLocalpartConflict::Interactive => {
+            Ok(ForcedLocalpart::Taken(localpart))
+        }
+        // Locked users can't be logged in to, so there is no point in linking to them
+        UpstreamOAuthProviderLocalpartConflict::Link if existing.is_valid() => {
+            Ok(ForcedLocalpart::Existing(existing))
+        }
+        UpstreamOAuthProviderLocalpartConflict::Link => Err(RouteError::UsernameTaken(localpart)),
+    }
+}
+
+/// The existing user the upstream account can be linked to, when the localpart
+/// forced by the provider belongs to them and the provider is configured to
+/// link in that case
+async fn existing_user_for_link(
+    repo: &mut BoxRepository,
+    site_config: &SiteConfig,
+    link: &UpstreamOAuthLink,
+    upstream_session: &UpstreamOAuthAuthorizationSession,
+) -> Result<(UpstreamOAuthProvider, User), RouteError> {
+    let provider = repo
+        .upstream_oauth_provider()
+        .lookup(link.provider_id)
+        .await?
+        .ok_or(RouteError::ProviderNotFound)?;
+
+    let payload = StandardClaims::from_session(upstream_session)?;
+    let env = super::template::environment();
+    let localpart = forced_localpart(&env, &payload, &provider, site_config)?
+        .ok_or(RouteError::InvalidFormAction)?;
+
+    let conflict = provider.claims_imports.localpart_conflict;
+    match resolve_forced_localpart(repo, conflict, localpart).await? {
+        ForcedLocalpart::Existing(user) => Ok((provider, user)),
+        ForcedLocalpart::Available(_) | ForcedLocalpart::Taken(_) => {
+            Err(RouteError::InvalidFormAction)
+        }
+    }
+}
+
+/// The existing user to link the upstream account to, along with the matching
+/// email address, if the provider is configured to link accounts by email and
+/// asserts a verified email address which is verified on exactly one valid
+/// user
+async fn user_by_verified_email(
+    repo: &mut BoxRepository,
+    provider: &UpstreamOAuthProvider,
+    upstream_session: &UpstreamOAuthAuthorizationSession,
+) -> Result<Option<(User, UserEmail)>, RouteError> {
+    // Don't look at the claims at all unless the provider opted in
+    if !provider.claims_imports.link_by_email {
+        return Ok(None);
+    }
+
+    let payload = StandardClaims::from_session(upstream_session)?;
+    if !payload.email_verified {
+        return Ok(None);
+    }
+
+    let Some(email) = payload.email.as_deref() else {
+        return Ok(None);
+    };
+
+    let filter = UserEmailFilter::new().for_email(email).verified_only();
+    let page = repo.user_email().list(filter, Pagination::first(2)).await?;
+
+    // Don't guess which account it is if the address is verified on several ones
+    let [user_email] = &page.edges[..] else {
+        return Ok(None);
+    };
+
+    let Some(user) = repo
+        .user()
+        .lookup(user_email.user_id)
+        .await?
+        .filter(User::is_valid)
+    else {
+        return Ok(None);
+    };
+
+    // Don't add a second link to the same provider, the user might have a
+    // different account upstream
+    let filter = UpstreamOAuthLinkFilter::new()
+        .for_user(&user)
+        .for_provider(provider);
+    if repo.upstream_oauth_link().count(filter).await? > 0 {
+        return Ok(None);
+    }
+
+    Ok(Some((user, user_email.clone())))
+}
+
+/// The primary email address of the user, if it is verified
+async fn verified_primary_email(
+    repo: &mut BoxRepository,
+    user: &User,
+) -> Result<Option<UserEmail>, RouteError> {
+    let user_email = repo.user_email().get_primary(user).await?;
+    Ok(user_email.filter(|user_email| user_email.confirmed_at.is_some()))
+}
+
+/// Render the page to link the upstream account to the existing user with the
+/// same localpart
+async fn link_existing_page(
+    repo: &mut BoxRepository,
+    templates: &Templates,
+    link: &UpstreamOAuthLink,
+    user: &User,
+    csrf_token: &CsrfToken,
+    locale: DataLocale,
+    customize: impl FnOnce(UpstreamLinkExistingContext) -> UpstreamLinkExistingContext,
+) -> Result<Response, RouteError> {
+    let mut ctx = UpstreamLinkExistingContext::new(link, user);
+    if verified_primary_email(repo, user).await?.is_some() {
+        ctx = ctx.with_email_code();
+    }
+
+    let ctx = customize(ctx)
+        .with_csrf(csrf_token.form_value())
+        .with_language(locale);
+
+    Ok(Html(templates.render_upstream_oauth2_link_existing(&ctx)?).into_response())
+}
+
+/// Render the page to create an account from the upstream account, or to link
+/// it to the existing user with the same localpart
+///
+/// `username_taken` is set when the username the user chose is already taken.
+async fn register_page(
+    repo: &mut BoxRepository,
+    templates: &Templates,
+    site_config: &SiteConfig,
+    link: &UpstreamOAuthLink,
+    upstream_session: &UpstreamOAuthAuthorizationSession,
+    csrf_token: &CsrfToken,
+    locale: DataLocale,
+    username_taken: Option<String>,
+) -> Result<Response, RouteError> {
+    let payload = StandardClaims::from_session(upstream_session)?;
+
+    let provider = repo
+        .upstream_oauth_provider()
+        .lookup(link.provider_id)
+        .await?
+        .ok_or(RouteError::ProviderNotFound)?;
+
+    let env = super::template::environment();
+    let forced_localpart = forced_localpart(&env, &payload, &provider, site_config)?;
+    let mut ctx = UpstreamRegister::new(link);
+
+    import_claim(
+        "name",
+        claim_value(
+            &env,
+            &payload.claims,
+            &provider.claims_imports.displayname,
+            payload.name,
+        )?,
+        &provider.claims_imports.displayname,
+        |value, force| {
+            ctx.set_display_name(value, force);
+        },
+    )?;
+
+    import_claim(
+        "picture",
+        claim_value(
+            &env,
+            &payload.claims,
+            &provider.claims_imports.avatar_url,
+            payload.picture,
+        )?,
+        &provider.claims_imports.avatar_url,
+        |value, force| {
+            ctx.set_avatar_url(value, force);
+        },
+    )?;
+
+    import_claim(
+        "email",
+        claim_value(
+            &env,
+            &payload.claims,
+            &provider.claims_imports.email,
+            payload.email,
+        )?,
+        &provider.claims_imports.email,
+        |value, force| {
+            ctx.set_email(value, force);
+        },
+    )?;
+
+    import_claim(
+        "preferred_username",
+        claim_value(
+            &env,
+            &payload.claims,
+            &provider.claims_imports.localpart,
+            payload.preferred_username,
+        )?,
+        &provider.claims_imports.localpart,
+        |value, force| {
+            // The forced localpart is checked for conflicts below
+            if !force {
+                ctx.set_localpart(value, false);
+            }
+        },
+    )?;
+
+    if let Some(localpart) = forced_localpart {
+        let conflict = provider.claims_imports.localpart_conflict;
+        let resolved = resolve_forced_localpart(repo, conflict, localpart).await?;
+        match resolved {
+            ForcedLocalpart::Available(localpart) => ctx.set_localpart(localpart, true),
+            ForcedLocalpart::Taken(localpart) => ctx.set_localpart_taken(localpart),
+            ForcedLocalpart::Existing(user) => {
+                return link_existing_page(
+                    repo,
+                    templates,
+                    link,
+                    &user,
+                    csrf_token,
+                    locale,
+                    |ctx| ctx,
+                )
+                .await;
+            }
+        }
+    }
+
+    if let Some(username) = username_taken {
+        ctx.set_localpart_taken(username);
+    }
+
+    let ctx = ctx.with_csrf(csrf_token.form_value()).with_language(locale);
+
+    Ok(Html(templates.render_upstream_oauth2_do_register(&ctx)?).into_response())
+}
+
 #[derive(Deserialize)]
-#[serde(rename_all = "lowercase", tag = "action")]
+#[serde(rename_all = "snake_case", tag = "action")]
 pub(crate) enum FormData {
     Register {
         #[serde(default)]
@@ -176,8 +717,14 @@ pub(crate) enum FormData {
         import_email: Option<String>,
         #[serde(default)]
         import_display_name: Option<String>,
+        #[serde(default)]
+        import_avatar: Option<String>,
     },
     Link,
+    SendCode,
+    LinkWithCode {
+        code: String,
+    },
 }
 
 #[tracing::instrument(
@@ -193,6 +740,7 @@ pub(crate) async fn get(
     PreferredLanguage(locale): PreferredLanguage,
     State(templates): State<Templates>,
     State(url_builder): State<UrlBuilder>,
+    State(site_config): State<SiteConfig>,
     cookie_jar: CookieJar,
     user_agent: Option<TypedHeader<headers::UserAgent>>,
     Path(link_id): Path<Ulid>,
@@ -231,12 +779,14 @@ pub(crate) async fn get(
 
     let (user_session_info, cookie_jar) = cookie_jar.session_info();
     let (csrf_token, mut cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
-    let maybe_user_session = user_session_info.load_session(&mut repo).await?;
+    let maybe_user_session = user_session_info.load_session(&clock, &mut repo).await?;
 
     let response = match (maybe_user_session, link.user_id) {
         (Some(session), Some(user_id)) if session.user.id == user_id => {
             // Session already linked, and link matches the currently logged
             // user. Mark the session as consumed and renew the authentication.
+            sync_profile(&mut repo, &link, &upstream_session, &session.user).await?;
+
             let upstream_session = repo
                 .upstream_oauth_session()
                 .consume(&clock, upstream_session)
@@ -292,6 +842,8 @@ pub(crate) async fn get(
                 .filter(mas_data_model::User::is_valid)
                 .ok_or(RouteError::UserNotFound)?;
 
+            sync_profile(&mut repo, &link, &upstream_session, &user).await?;
+
             let session = repo
                 .browser_session()
                 .add(&mut rng, &clock, &user, user_agent)
@@ -317,55 +869,83 @@ pub(crate) async fn get(
         }
 
         (None, None) => {
-            // Session not linked and used not logged in: suggest creating an
-            // account or logging in an existing user
-            let id_token = upstream_session
-                .id_token()
-                .map(Jwt::<'_, StandardClaims>::try_from)
-                .transpose()?;
-
             let provider = repo
                 .upstream_oauth_provider()
                 .lookup(link.provider_id)
                 .await?
                 .ok_or(RouteError::ProviderNotFound)?;
 
-            let payload = id_token
-                .map(|id_token| id_token.into_parts().1)
-                .unwrap_or_default();
-
-            let mut ctx = UpstreamRegister::new(&link);
+            let existing_user =
+                user_by_verified_email(&mut repo, &provider, &upstream_session).await?;
+            if let Some((user, user_email)) = existing_user {
+                // Session not linked, but the provider asserts the user owns the
+                // email address of an existing user: link to it and do the login
+                repo.upstream_oauth_link()
+                    .associate_to_user(&link, &user)
+                    .await?;
 
-            import_claim(
-                "name",
-                payload.name,
-                &provider.claims_imports.displayname,
-                |value, force| {
-                    ctx.set_display_name(value, force);
-                },
-            )?;
+                tracing::info!(
+                    audit = true,
+                    audit.action = "upstream_oauth2.link_by_email",
+                    user.id = %user.id,
+                    user.username = %user.username,
+                    user_email.id = %user_email.id,
+                    user_email.email = %user_email.email,
+                    upstream_oauth_provider.id = %provider.id,
+                    upstream_oauth_provider.issuer = %provider.issuer,
+                    upstream_oauth_link.id = %link.id,
+                    upstream_oauth_link.subject = %link.subject,
+                    upstream_oauth_authorization_session.id = %upstream_session.id,
+                    "Linked the upstream account to the existing user with the same verified email address",
+                );
+
+                sync_profile(&mut repo, &link, &upstream_session, &user).await?;
+
+                // Let the user know that a new way to sign in to their account was added
+                repo.job()
+                    .schedule_job(
+                        SendSecurityNotificationJob::upstream_linked(&user, &provider)
+                            .with_language(locale.to_string()),
+                    )
+                    .await?;
 
-            import_claim(
-                "email",
-                payload.email,
-                &provider.claims_imports.email,
-                |value, force| {
-                    ctx.set_email(value, force);
-                },
-            )?;
+                let session = repo
+                    .browser_session()
+                    .add(&mut rng, &clock, &user, user_agent)
+                    .await?;
 
-            import_claim(
-                "preferred_username",
-                payload.preferred_username,
-                &provider.claims_imports.localpart,
-                |value, force| {
-                    ctx.set_localpart(value, force);
-                },
-            )?;
+                let upstream_session = repo
+                    .upstream_oauth_session()
+                    .consume(&clock, upstream_session)
+                    .await?;
 
-            let ctx = ctx.with_csrf(csrf_token.form_value()).with_language(locale);
+                repo.browser_session()
+                    .authenticate_with_upstream(&mut rng, &clock, &session, &upstream_session)
+                    .await?;
 
-            Html(templates.render_upstream_oauth2_do_register(&ctx)?).into_response()
+                cookie_jar = sessions_cookie
+                    .consume_link(link_id)?
+                    .save(cookie_jar, &clock);
+                cookie_jar = cookie_jar.set_session(&session);
+
+                repo.save().await?;
+
+                post_auth_action.go_next(&url_builder).into_response()
+            } else {
+                // Session not linked and used not logged in: suggest creating an
+                // account or logging in an existing user
+                register_page(
+                    &mut repo,
+                    &templates,
+                    &site_config,
+                    &link,
+                    &upstream_session,
+                    &csrf_token,
+                    locale,
+                    None,
+                )
+                .await?
+            }
         }
     };
 
@@ -384,11 +964,16 @@ pub(crate) async fn post(
     mut repo: BoxRepository,
     cookie_jar: CookieJar,
     user_agent: Option<TypedHeader<headers::UserAgent>>,
+    activity_tracker: BoundActivityTracker,
+    PreferredLanguage(locale): PreferredLanguage,
     mut policy: Policy,
+    State(templates): State<Templates>,
     State(url_builder): State<UrlBuilder>,
+    State(site_config): State<SiteConfig>,
+    State(limiter): State<Limiter>,
     Path(link_id): Path<Ulid>,
     Form(form): Form<ProtectedForm<FormData>>,
-) -> Result<impl IntoResponse, RouteError> {
+) -> Result<Response, RouteError> {
     let user_agent = user_agent.map(|ua| ua.as_str().to_owned());
     let form = cookie_jar.verify_form(&clock, form)?;
 
@@ -424,7 +1009,7 @@ pub(crate) async fn post(
     }
 
     let (user_session_info, cookie_jar) = cookie_jar.session_info();
-    let maybe_user_session = user_session_info.load_session(&mut repo).await?;
+    let maybe_user_session = user_session_info.load_session(&clock, &mut repo).await?;
 
     let session = match (maybe_user_session, link.user_id, form) {
         (Some(session), None, FormData::Link) => {
@@ -432,6 +1017,22 @@ pub(crate) async fn post(
                 .associate_to_user(&link, &session.user)
                 .await?;
 
+            sync_profile(&mut repo, &link, &upstream_session, &session.user).await?;
+
+            let provider = repo
+                .upstream_oauth_provider()
+                .lookup(link.provider_id)
+                .await?
+                .ok_or(RouteError::ProviderNotFound)?;
+
+            // Let the user know that a new way to sign in to their account was added
+            repo.job()
+                .schedule_job(
+                    SendSecurityNotificationJob::upstream_linked(&session.user, &provider)
+                        .with_language(locale.to_string()),
+                )
+                .await?;
+
             session
         }
 
@@ -442,16 +1043,15 @@ pub(crate) async fn post(
                 username,
                 import_email,
                 import_display_name,
+                import_avatar,
             },
         ) => {
             // Those fields are Some("on") if the checkbox is checked
             let import_email = import_email.is_some();
             let import_display_name = import_display_name.is_some();
+            let import_avatar = import_avatar.is_some();
 
-            let id_token = upstream_session
-                .id_token()
-                .map(Jwt::<'_, StandardClaims>::try_from)
-                .transpose()?;
+            let payload = StandardClaims::from_session(&upstream_session)?;
 
             let provider = repo
                 .upstream_oauth_provider()
@@ -459,16 +1059,22 @@ pub(crate) async fn post(
                 .await?
                 .ok_or(RouteError::ProviderNotFound)?;
 
-            let payload = id_token
-                .map(|id_token| id_token.into_parts().1)
-                .unwrap_or_default();
+            let user_locale = payload.locale().unwrap_or_else(|| locale.to_string());
+            let groups = payload.groups(&provider.claims_imports.groups);
+            let env = super::template::environment();
+            let forced_localpart = forced_localpart(&env, &payload, &provider, &site_config)?;
 
             // Let's try to import the claims from the ID token
 
             let mut name = None;
             import_claim(
                 "name",
-                payload.name,
+                claim_value(
+                    &env,
+                    &payload.claims,
+                    &provider.claims_imports.displayname,
+                    payload.name,
+                )?,
                 &provider.claims_imports.displayname,
                 |value, force| {
                     // Import the display name if it is either forced or the user has requested it
@@ -478,10 +1084,33 @@ pub(crate) async fn post(
                 },
             )?;
 
+            let mut avatar_url = None;
+            import_claim(
+                "picture",
+                claim_value(
+                    &env,
+                    &payload.claims,
+                    &provider.claims_imports.avatar_url,
+                    payload.picture,
+                )?,
+                &provider.claims_imports.avatar_url,
+                |value, force| {
+                    // Import the avatar if it is either forced or the user has requested it
+                    if force || import_avatar {
+                        avatar_url = Some(value);
+                    }
+                },
+            )?;
+
             let mut email = None;
             import_claim(
                 "email",
-                payload.email,
+                claim_value(
+                    &env,
+                    &payload.claims,
+                    &provider.claims_imports.email,
+                    payload.email,
+                )?,
                 &provider.claims_imports.email,
                 |value, force| {
                     // Import the email if it is either forced or the user has requested it
@@ -491,24 +1120,69 @@ pub(crate) async fn post(
                 },
             )?;
 
-            let mut username = username;
+            // This only checks that the claim is there if it is required, the forced
+            // localpart is resolved below
             import_claim(
                 "preferred_username",
-                payload.preferred_username,
+                claim_value(
+                    &env,
+                    &payload.claims,
+                    &provider.claims_imports.localpart,
+                    payload.preferred_username,
+                )?,
                 &provider.claims_imports.localpart,
-                |value, force| {
-                    // If the username is forced, override whatever was in the form
-                    if force {
-                        username = Some(value);
-                    }
-                },
+                |_, _| {},
             )?;
 
-            let username = username.ok_or(RouteError::MissingUsername)?;
+            let username = match forced_localpart {
+                Some(localpart) => {
+                    let conflict = provider.claims_imports.localpart_conflict;
+                    match resolve_forced_localpart(&mut repo, conflict, localpart).await? {
+                        // If the username is forced, override whatever was in the form
+                        ForcedLocalpart::Available(localpart) => Some(localpart),
+                        // The user was asked to choose another one
+                        ForcedLocalpart::Taken(_) => username,
+                        // The user was asked to prove they own the existing account
+                        ForcedLocalpart::Existing(_) => return Err(RouteError::InvalidFormAction),
+                    }
+                }
+                None => username,
+            };
+
+            let mut username = username.ok_or(RouteError::MissingUsername)?;
+            if site_config.case_fold_usernames {
+                username = username.to_lowercase();
+            }
+
+            // Let the user choose another username if this one is taken
+            if repo.user().exists(&username).await? {
+                let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
+                let response = register_page(
+                    &mut repo,
+                    &templates,
+                    &site_config,
+                    &link,
+                    &upstream_session,
+                    &csrf_token,
+                    locale,
+                    Some(username),
+                )
+                .await?;
+
+                return Ok((cookie_jar, response).into_response());
+            }
 
             // Policy check
+            let requester = Requester::new(clock.now())
+                .with_ip_address(activity_tracker.ip())
+                .with_user_agent(user_agent.clone());
             let res = policy
-                .evaluate_upstream_oauth_register(&username, email.as_deref())
+                .evaluate_upstream_oauth_register(
+                    &username,
+                    email.as_deref(),
+                    &provider,
+                    &requester,
+                )
                 .await?;
             if !res.valid() {
                 return Err(RouteError::PolicyViolation {
@@ -516,8 +1190,12 @@ pub(crate) async fn post(
                 });
             }
 
-            // Now we can create the user
+            // Now we can create the user, preferring the locale from the upstream provider
+            // over the one of the browser
             let user = repo.user().add(&mut rng, &clock, username).await?;
+            let user = repo.user().set_locale(user, Some(user_locale)).await?;
+            let user =
+                sync_groups(&mut repo, &provider.claims_imports.groups, groups, user).await?;
 
             // And schedule the job to provision it
             let mut job = ProvisionUserJob::new(&user);
@@ -527,8 +1205,17 @@ pub(crate) async fn post(
                 job = job.set_display_name(name);
             }
 
+            // Same for the avatar
+            if let Some(avatar_url) = avatar_url {
+                job = job.set_avatar_url(avatar_url);
+            }
+
             repo.job().schedule_job(job).await?;
 
+            repo.job()
+                .schedule_job(NotifyUserEventJob::new(&user, UserLifecycleEvent::Created))
+                .await?;
+
             // If we have an email, add it to the user
             if let Some(email) = email {
                 let user_email = repo
@@ -549,6 +1236,10 @@ pub(crate) async fn post(
                         .await?;
 
                     repo.user_email().set_as_primary(&user_email).await?;
+
+                    repo.job()
+                        .schedule_job(NotifyUserEventJob::email_verified(&user_email))
+                        .await?;
                 }
             }
 
@@ -561,6 +1252,129 @@ pub(crate) async fn post(
                 .await?
         }
 
+        (None, None, FormData::SendCode) => {
+            let (_provider, user) =
+                existing_user_for_link(&mut repo, &site_config, &link, &upstream_session).await?;
+
+            let user_email = verified_primary_email(&mut repo, &user)
+                .await?
+                .ok_or(RouteError::InvalidFormAction)?;
+
+            send_verification_email(
+                &mut rng,
+                &clock,
+                &mut repo,
+                &site_config.email_rate_limits,
+                &user_email,
+                &locale,
+            )
+            .await?;
+
+            let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
+            let response = link_existing_page(
+                &mut repo,
+                &templates,
+                &link,
+                &user,
+                &csrf_token,
+                locale,
+                UpstreamLinkExistingContext::with_code_sent,
+            )
+            .await?;
+
+            repo.save().await?;
+
+            return Ok((cookie_jar, response).into_response());
+        }
+
+        (None, None, FormData::LinkWithCode { code }) => {
+            let (provider, user) =
+                existing_user_for_link(&mut repo, &site_config, &link, &upstream_session).await?;
+
+            let user_email = verified_primary_email(&mut repo, &user)
+                .await?
+                .ok_or(RouteError::InvalidFormAction)?;
+
+            // Codes are as sensitive as passwords here, so guessing them is rate-limited
+            // the same way
+            if let Err(rate_limited) = limiter
+                .check_login(clock.now(), activity_tracker.ip(), &user.username)
+                .await
+            {
+                let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
+                let response = link_existing_page(
+                    &mut repo,
+                    &templates,
+                    &link,
+                    &user,
+                    &csrf_token,
+                    locale,
+                    |ctx| ctx.with_code_sent().with_invalid_code(),
+                )
+                .await?;
+
+                return Ok((
+                    StatusCode::TOO_MANY_REQUESTS,
+                    rate_limited,
+                    cookie_jar,
+                    response,
+                )
+                    .into_response());
+            }
+
+            limiter
+                .delay_login(clock.now(), activity_tracker.ip())
+                .await;
+
+            // Expired and already used codes are as good as wrong ones
+            let Some(verification) = repo
+                .user_email()
+                .find_verification_code(&clock, &user_email, code.trim())
+                .await?
+                .filter(|verification| verification.is_valid())
+            else {
+                limiter
+                    .record_login_failure(clock.now(), activity_tracker.ip())
+                    .await;
+
+                let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
+                let response = link_existing_page(
+                    &mut repo,
+                    &templates,
+                    &link,
+                    &user,
+                    &csrf_token,
+                    locale,
+                    |ctx| ctx.with_code_sent().with_invalid_code(),
+                )
+                .await?;
+
+                return Ok((cookie_jar, response).into_response());
+            };
+
+            repo.user_email()
+                .consume_verification_code(&clock, verification)
+                .await?;
+
+            repo.upstream_oauth_link()
+                .associate_to_user(&link, &user)
+                .await?;
+
+            sync_profile(&mut repo, &link, &upstream_session, &user).await?;
+
+            // Let the user know that a new way to sign in to their account was added
+            repo.job()
+                .schedule_job(
+                    SendSecurityNotificationJob::upstream_linked(&user, &provider)
+                        .with_language(locale.to_string()),
+                )
+                .await?;
+
+            repo.browser_session()
+                .add(&mut rng, &clock, &user, user_agent)
+                .await?
+        }
+
         _ => return Err(RouteError::InvalidFormAction),
     };
 
@@ -580,5 +1394,893 @@ pub(crate) async fn post(
 
     repo.save().await?;
 
-    Ok((cookie_jar, post_auth_action.go_next(&url_builder)))
+    Ok((cookie_jar, post_auth_action.go_next(&url_builder)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::extract::FromRequestParts;
+    use hyper::Request;
+    use mas_data_model::{
+        UpstreamOAuthProviderAuthorizationParams, UpstreamOAuthProviderClaimsImports,
+        UpstreamOAuthProviderEndpoints, UpstreamOAuthProviderHealthCheckSettings,
+        UpstreamOAuthProviderImportAction, UpstreamOAuthProviderPkceMethod,
+        UpstreamOAuthProviderProtocol, UpstreamOAuthProviderSamlSettings,
+        UpstreamOAuthProviderUiOptions,
+    };
+    use mas_iana::oauth::OAuthClientAuthenticationMethod;
+    use mas_router::Route;
+    use oauth2_types::scope::{Scope, OPENID};
+    use serde_json::json;
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::test_utils::{
+        init_tracing, CookieHelper, RequestBuilderExt, ResponseExt, TestState,
+    };
+
+    fn claims(claims: serde_json::Value) -> StandardClaims {
+        let serde_json::Value::Object(claims) = claims else {
+            panic!("claims must be an object");
+        };
+
+        StandardClaims::from_claims(claims)
+    }
+
+    /// Create a provider with the given claims imports, and an upstream session
+    /// completed with a new link and the given userinfo claims, as if the user
+    /// just came back from the provider in the browser of `cookies`
+    async fn start_link(
+        state: &TestState,
+        cookies: &CookieHelper,
+        claims_imports: UpstreamOAuthProviderClaimsImports,
+        userinfo: serde_json::Value,
+    ) -> (
+        UpstreamOAuthProvider,
+        UpstreamOAuthLink,
+        UpstreamOAuthAuthorizationSession,
+    ) {
+        let mut rng = state.rng();
+        let mut repo = state.repository().await.unwrap();
+        let provider = repo
+            .upstream_oauth_provider()
+            .add(
+                &mut rng,
+                &state.clock,
+                "https://example.com/".to_owned(),
+                None,
+                Scope::from_iter([OPENID]),
+                OAuthClientAuthenticationMethod::None,
+                None,
+                "client".to_owned(),
+                None,
+                claims_imports,
+                UpstreamOAuthProviderPkceMethod::default(),
+                UpstreamOAuthProviderAuthorizationParams::default(),
+                false,
+                UpstreamOAuthProviderUiOptions::default(),
+                UpstreamOAuthProviderProtocol::Oidc,
+                UpstreamOAuthProviderEndpoints::default(),
+                UpstreamOAuthProviderSamlSettings::default(),
+                false,
+                UpstreamOAuthProviderHealthCheckSettings::default(),
+            )
+            .await
+            .unwrap();
+
+        let session = repo
+            .upstream_oauth_session()
+            .add(
+                &mut rng,
+                &state.clock,
+                &provider,
+                "state".to_owned(),
+                None,
+                "nonce".to_owned(),
+            )
+            .await
+            .unwrap();
+
+        let link = repo
+            .upstream_oauth_link()
+            .add(&mut rng, &state.clock, &provider, "subject".to_owned())
+            .await
+            .unwrap();
+
+        let session = repo
+            .upstream_oauth_session()
+            .complete_with_link(&state.clock, session, &link, None, None, Some(userinfo))
+            .await
+            .unwrap();
+
+        repo.save().await.unwrap();
+
+        // Set the upstream sessions cookie, as the callback would
+        let (mut parts, ()) = Request::new(()).into_parts();
+        let cookie_jar = CookieJar::from_request_parts(&mut parts, state)
+            .await
+            .unwrap_or_else(|e| match e {});
+        let cookie_jar = UpstreamSessionsCookie::default()
+            .add(session.id, provider.id, "state".to_owned(), None)
+            .add_link_to_session(session.id, link.id)
+            .unwrap()
+            .save(cookie_jar, &state.clock);
+        cookies.save_cookies(&(cookie_jar, ()).into_response());
+
+        (provider, link, session)
+    }
+
+    /// Create a user with a verified primary email address
+    async fn add_user_with_email(state: &TestState, username: &str, email: &str) -> User {
+        let mut rng = state.rng();
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, username.to_owned())
+            .await
+            .unwrap();
+        let user_email = repo
+            .user_email()
+            .add(&mut rng, &state.clock, &user, email.to_owned())
+            .await
+            .unwrap();
+        let user_email = repo
+            .user_email()
+            .mark_as_verified(&state.clock, user_email)
+            .await
+            .unwrap();
+        repo.user_email().set_as_primary(&user_email).await.unwrap();
+        repo.save().await.unwrap();
+        user
+    }
+
+    /// The user the link is associated with, if any
+    async fn linked_user(state: &TestState, link: &UpstreamOAuthLink) -> Option<Ulid> {
+        let mut repo = state.repository().await.unwrap();
+        let link = repo
+            .upstream_oauth_link()
+            .lookup(link.id)
+            .await
+            .unwrap()
+            .unwrap();
+        link.user_id
+    }
+
+    /// Claims imports forcing the localpart from the `preferred_username`
+    /// claim, with the given strategy when it is taken
+    fn forced_localpart_imports(
+        conflict: UpstreamOAuthProviderLocalpartConflict,
+    ) -> UpstreamOAuthProviderClaimsImports {
+        UpstreamOAuthProviderClaimsImports {
+            localpart: UpstreamOAuthProviderImportPreference {
+                action: UpstreamOAuthProviderImportAction::Force,
+                ..UpstreamOAuthProviderImportPreference::default()
+            },
+            localpart_conflict: conflict,
+            ..UpstreamOAuthProviderClaimsImports::default()
+        }
+    }
+
+    /// Render the link page, checking it renders fine
+    async fn get_link_page(
+        state: &TestState,
+        cookies: &CookieHelper,
+        link: &UpstreamOAuthLink,
+    ) -> hyper::Response<String> {
+        let request =
+            Request::get(&*mas_router::UpstreamOAuth2Link::new(link.id).path_and_query()).empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        response
+    }
+
+    /// Submit a form on the link page, with the CSRF token from the given page
+    async fn post_link_page(
+        state: &TestState,
+        cookies: &CookieHelper,
+        link: &UpstreamOAuthLink,
+        page: &hyper::Response<String>,
+        mut form: serde_json::Value,
+    ) -> hyper::Response<String> {
+        let csrf_token = page
+            .body()
+            .split("name=\"csrf\" value=\"")
+            .nth(1)
+            .unwrap()
+            .split('\"')
+            .next()
+            .unwrap();
+        form["csrf"] = csrf_token.into();
+
+        let request =
+            Request::post(&*mas_router::UpstreamOAuth2Link::new(link.id).path_and_query())
+                .form(form);
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response
+    }
+
+    #[test]
+    fn test_lenient_claims() {
+        let payload = claims(json!({
+            "name": ["Alice", "Alice Liddell"],
+            "email": "alice@example.com",
+            "email_verified": "true",
+            "preferred_username": 42,
+        }));
+        assert_eq!(payload.name.as_deref(), Some("Alice"));
+        assert_eq!(payload.email.as_deref(), Some("alice@example.com"));
+        assert!(payload.email_verified);
+        assert_eq!(payload.preferred_username, None);
+
+        let payload = claims(json!({ "email_verified": ["True"] }));
+        assert!(payload.email_verified);
+
+        let payload = claims(json!({ "email_verified": "false" }));
+        assert!(!payload.email_verified);
+
+        let payload = claims(json!({ "email_verified": 1 }));
+        assert!(!payload.email_verified);
+    }
+
+    /// Test that the upstream account is linked to the user with the same
+    /// verified email address when the provider opted in
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_link_by_verified_email(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let cookies = CookieHelper::new();
+        let alice = add_user_with_email(&state, "alice", "alice@example.com").await;
+
+        let claims_imports = UpstreamOAuthProviderClaimsImports {
+            link_by_email: true,
+            ..UpstreamOAuthProviderClaimsImports::default()
+        };
+        // Some providers send booleans as strings and attributes as lists
+        let userinfo = json!({
+            "email": ["alice@example.com"],
+            "email_verified": "true",
+        });
+        let (_provider, link, _session) =
+            start_link(&state, &cookies, claims_imports, userinfo).await;
+
+        let request =
+            Request::get(&*mas_router::UpstreamOAuth2Link::new(link.id).path_and_query()).empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        assert_eq!(linked_user(&state, &link).await, Some(alice.id));
+
+        // The user is told that a new way to sign in was added to their account
+        let jobs: Vec<String> = sqlx::query_scalar(
+            "SELECT job::text FROM apalis.jobs WHERE job_type = 'send-security-notification'",
+        )
+        .fetch_all(&state.pool)
+        .await
+        .unwrap();
+        assert_eq!(jobs.len(), 1);
+        let job: serde_json::Value = serde_json::from_str(&jobs[0]).unwrap();
+        assert_eq!(job["user_id"], alice.id.to_string());
+    }
+
+    /// Test that accounts are not linked by email when the provider didn't opt
+    /// in, or when it doesn't assert the address is verified
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_no_link_by_email(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        add_user_with_email(&state, "alice", "alice@example.com").await;
+
+        let cases = [
+            (
+                false,
+                json!({ "email": "alice@example.com", "email_verified": true }),
+            ),
+            (
+                true,
+                json!({ "email": "alice@example.com", "email_verified": false }),
+            ),
+            (true, json!({ "email": "alice@example.com" })),
+            (
+                true,
+                json!({ "email": "bob@example.com", "email_verified": true }),
+            ),
+        ];
+
+        for (link_by_email, userinfo) in cases {
+            let cookies = CookieHelper::new();
+            let claims_imports = UpstreamOAuthProviderClaimsImports {
+                link_by_email,
+                ..UpstreamOAuthProviderClaimsImports::default()
+            };
+            let (_provider, link, _session) =
+                start_link(&state, &cookies, claims_imports, userinfo).await;
+
+            // The user is offered to create an account instead
+            let request =
+                Request::get(&*mas_router::UpstreamOAuth2Link::new(link.id).path_and_query())
+                    .empty();
+            let request = cookies.with_cookies(request);
+            let response = state.request(request).await;
+            response.assert_status(StatusCode::OK);
+
+            assert_eq!(linked_user(&state, &link).await, None);
+        }
+    }
+
+    /// Test that no user is picked when the address is verified on several
+    /// users, when the user is locked, or when the user already has a link to
+    /// the provider
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_user_by_verified_email(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let cookies = CookieHelper::new();
+        let alice = add_user_with_email(&state, "alice", "alice@example.com").await;
+
+        let claims_imports = UpstreamOAuthProviderClaimsImports {
+            link_by_email: true,
+            ..UpstreamOAuthProviderClaimsImports::default()
+        };
+        let userinfo = json!({ "email": "alice@example.com", "email_verified": true });
+        let (provider, _link, session) =
+            start_link(&state, &cookies, claims_imports, userinfo).await;
+
+        let mut repo = state.repository().await.unwrap();
+        let (user, user_email) = user_by_verified_email(&mut repo, &provider, &session)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.id, alice.id);
+        assert_eq!(user_email.email, "alice@example.com");
+
+        // Locked users can't be logged in to
+        let alice = repo.user().lock(&state.clock, alice).await.unwrap();
+        assert!(user_by_verified_email(&mut repo, &provider, &session)
+            .await
+            .unwrap()
+            .is_none());
+        let alice = repo.user().unlock(alice).await.unwrap();
+
+        // The user might have another account upstream
+        let other_link = repo
+            .upstream_oauth_link()
+            .add(
+                &mut state.rng(),
+                &state.clock,
+                &provider,
+                "other".to_owned(),
+            )
+            .await
+            .unwrap();
+        repo.upstream_oauth_link()
+            .associate_to_user(&other_link, &alice)
+            .await
+            .unwrap();
+        assert!(user_by_verified_email(&mut repo, &provider, &session)
+            .await
+            .unwrap()
+            .is_none());
+        let other_link = repo
+            .upstream_oauth_link()
+            .lookup(other_link.id)
+            .await
+            .unwrap()
+            .unwrap();
+        repo.upstream_oauth_link()
+            .dissociate_from_user(other_link)
+            .await
+            .unwrap();
+        assert!(user_by_verified_email(&mut repo, &provider, &session)
+            .await
+            .unwrap()
+            .is_some());
+        repo.save().await.unwrap();
+
+        // Don't guess which account it is if the address is verified on
+        // several ones
+        add_user_with_email(&state, "bob", "alice@example.com").await;
+        let mut repo = state.repository().await.unwrap();
+        assert!(user_by_verified_email(&mut repo, &provider, &session)
+            .await
+            .unwrap()
+            .is_none());
+        repo.save().await.unwrap();
+    }
+
+    #[test]
+    fn test_groups_claim() {
+        let import = UpstreamOAuthProviderGroupsImport {
+            claim: Some("groups".to_owned()),
+            admin_groups: Vec::new(),
+        };
+
+        let payload = claims(json!({ "groups": ["admins", "devs", 42] }));
+        assert_eq!(
+            payload.groups(&import),
+            Some(vec!["admins".to_owned(), "devs".to_owned()])
+        );
+
+        let payload = claims(json!({ "groups": "admins" }));
+        assert_eq!(payload.groups(&import), Some(vec!["admins".to_owned()]));
+
+        // A missing claim means the user is in no group anymore
+        let payload = claims(json!({}));
+        assert_eq!(payload.groups(&import), Some(Vec::new()));
+
+        // Groups are not imported if no claim is configured
+        let payload = claims(json!({ "groups": ["admins"] }));
+        assert_eq!(
+            payload.groups(&UpstreamOAuthProviderGroupsImport::default()),
+            None
+        );
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_sync_groups(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let mut repo = state.repository().await.unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+        assert!(!user.can_request_admin);
+
+        let import = UpstreamOAuthProviderGroupsImport {
+            claim: Some("groups".to_owned()),
+            admin_groups: vec!["admins".to_owned()],
+        };
+
+        // Joining an admin group grants admin access
+        let groups = vec!["admins".to_owned(), "devs".to_owned()];
+        let user = sync_groups(&mut repo, &import, Some(groups.clone()), user)
+            .await
+            .unwrap();
+        assert!(user.can_request_admin);
+        assert_eq!(user.upstream_groups, groups);
+
+        // Leaving it revokes it
+        let groups = vec!["devs".to_owned()];
+        let user = sync_groups(&mut repo, &import, Some(groups.clone()), user)
+            .await
+            .unwrap();
+        assert!(!user.can_request_admin);
+        assert_eq!(user.upstream_groups, groups);
+
+        // Admin access granted by other means is left alone if no admin group is
+        // configured
+        let user = repo.user().set_can_request_admin(user, true).await.unwrap();
+        let import = UpstreamOAuthProviderGroupsImport {
+            claim: Some("groups".to_owned()),
+            admin_groups: Vec::new(),
+        };
+        let user = sync_groups(&mut repo, &import, Some(Vec::new()), user)
+            .await
+            .unwrap();
+        assert!(user.can_request_admin);
+        assert!(user.upstream_groups.is_empty());
+
+        // Nothing changes if the groups are not imported
+        let user = sync_groups(&mut repo, &import, None, user).await.unwrap();
+        assert!(user.can_request_admin);
+
+        // The changes are persisted
+        let user = repo.user().lookup(user.id).await.unwrap().unwrap();
+        assert!(user.can_request_admin);
+        assert!(user.upstream_groups.is_empty());
+
+        repo.save().await.unwrap();
+
+        // Unlinking the upstream account erases the groups imported from it,
+        // along with the claims they came from
+        let groups = vec!["devs".to_owned()];
+        let (_provider, link, session) = start_link(
+            &state,
+            &CookieHelper::new(),
+            UpstreamOAuthProviderClaimsImports::default(),
+            json!({ "groups": groups }),
+        )
+        .await;
+        assert!(session.userinfo().is_some());
+
+        let mut repo = state.repository().await.unwrap();
+        repo.upstream_oauth_link()
+            .associate_to_user(&link, &user)
+            .await
+            .unwrap();
+        let user = sync_groups(&mut repo, &import, Some(groups), user)
+            .await
+            .unwrap();
+        assert!(!user.upstream_groups.is_empty());
+
+        let link = repo
+            .upstream_oauth_link()
+            .lookup(link.id)
+            .await
+            .unwrap()
+            .unwrap();
+        repo.upstream_oauth_link()
+            .dissociate_from_user(link)
+            .await
+            .unwrap();
+
+        let user = repo.user().lookup(user.id).await.unwrap().unwrap();
+        assert!(user.upstream_groups.is_empty());
+        let session = repo
+            .upstream_oauth_session()
+            .lookup(session.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(session.userinfo().is_none());
+        assert!(session.id_token().is_none());
+
+        repo.save().await.unwrap();
+    }
+
+    /// Test that the registration fails when the forced localpart is taken and
+    /// the provider is configured to fail in that case
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_localpart_conflict_fail(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let cookies = CookieHelper::new();
+        add_user_with_email(&state, "alice", "alice@example.com").await;
+
+        let claims_imports = forced_localpart_imports(UpstreamOAuthProviderLocalpartConflict::Fail);
+        let userinfo = json!({ "preferred_username": "alice" });
+        let (_provider, link, _session) =
+            start_link(&state, &cookies, claims_imports, userinfo).await;
+
+        let request =
+            Request::get(&*mas_router::UpstreamOAuth2Link::new(link.id).path_and_query()).empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(response.body().contains("is already taken"));
+
+        assert_eq!(linked_user(&state, &link).await, None);
+    }
+
+    /// Test that a number is added to the forced localpart when it is taken and
+    /// the provider is configured to do so
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_localpart_conflict_add_suffix(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let cookies = CookieHelper::new();
+        add_user_with_email(&state, "alice", "alice@example.com").await;
+
+        let claims_imports =
+            forced_localpart_imports(UpstreamOAuthProviderLocalpartConflict::AddSuffix);
+        let userinfo = json!({ "preferred_username": "alice" });
+        let (_provider, link, _session) =
+            start_link(&state, &cookies, claims_imports, userinfo).await;
+
+        let page = get_link_page(&state, &cookies, &link).await;
+        assert!(page.body().contains("alice1"));
+
+        // The forced localpart wins over whatever is in the form
+        let response = post_link_page(
+            &state,
+            &cookies,
+            &link,
+            &page,
+            json!({ "action": "register", "username": "mallory" }),
+        )
+        .await;
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .find_by_username("alice1")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!repo.user().exists("mallory").await.unwrap());
+        repo.cancel().await.unwrap();
+
+        assert_eq!(linked_user(&state, &link).await, Some(user.id));
+    }
+
+    /// Test that users can choose another username when the forced localpart is
+    /// taken and the provider is configured to let them
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_localpart_conflict_interactive(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let cookies = CookieHelper::new();
+        add_user_with_email(&state, "alice", "alice@example.com").await;
+
+        let claims_imports =
+            forced_localpart_imports(UpstreamOAuthProviderLocalpartConflict::Interactive);
+        let userinfo = json!({ "preferred_username": "alice" });
+        let (_provider, link, _session) =
+            start_link(&state, &cookies, claims_imports, userinfo).await;
+
+        let page = get_link_page(&state, &cookies, &link).await;
+        assert!(page.body().contains("is already taken"));
+        assert!(page.body().contains("name=\"username\""));
+
+        // Choosing a taken username shows the form again
+        let response = post_link_page(
+            &state,
+            &cookies,
+            &link,
+            &page,
+            json!({ "action": "register", "username": "alice" }),
+        )
+        .await;
+        response.assert_status(StatusCode::OK);
+        assert!(response.body().contains("is already taken"));
+        assert_eq!(linked_user(&state, &link).await, None);
+
+        let response = post_link_page(
+            &state,
+            &cookies,
+            &link,
+            &response,
+            json!({ "action": "register", "username": "alice2" }),
+        )
+        .await;
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .find_by_username("alice2")
+            .await
+            .unwrap()
+            .unwrap();
+        repo.cancel().await.unwrap();
+
+        assert_eq!(linked_user(&state, &link).await, Some(user.id));
+    }
+
+    /// Test that the upstream account can be linked to the existing user with
+    /// the forced localpart with a code sent to their email address, when the
+    /// provider is configured to do so
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_localpart_conflict_link(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let cookies = CookieHelper::new();
+        let alice = add_user_with_email(&state, "alice", "alice@example.com").await;
+
+        let claims_imports = forced_localpart_imports(UpstreamOAuthProviderLocalpartConflict::Link);
+        let userinfo = json!({ "preferred_username": "alice" });
+        let (_provider, link, _session) =
+            start_link(&state, &cookies, claims_imports, userinfo).await;
+
+        let page = get_link_page(&state, &cookies, &link).await;
+        assert!(page.body().contains("value=\"send_code\""));
+
+        // Registering instead is not allowed
+        let response = post_link_page(
+            &state,
+            &cookies,
+            &link,
+            &page,
+            json!({ "action": "register", "username": "alice" }),
+        )
+        .await;
+        response.assert_status(StatusCode::INTERNAL_SERVER_ERROR);
+
+        let response = post_link_page(
+            &state,
+            &cookies,
+            &link,
+            &page,
+            json!({ "action": "send_code" }),
+        )
+        .await;
+        response.assert_status(StatusCode::OK);
+        assert!(response.body().contains("value=\"link_with_code\""));
+
+        let jobs: Vec<String> =
+            sqlx::query_scalar("SELECT job::text FROM apalis.jobs WHERE job_type = 'verify-email'")
+                .fetch_all(&state.pool)
+                .await
+                .unwrap();
+        assert_eq!(jobs.len(), 1);
+
+        // The job would send this code
+        let mut repo = state.repository().await.unwrap();
+        let user_email = repo
+            .user_email()
+            .get_primary(&alice)
+            .await
+            .unwrap()
+            .unwrap();
+        repo.user_email()
+            .add_verification_code(
+                &mut state.rng(),
+                &state.clock,
+                &user_email,
+                chrono::Duration::hours(8),
+                "123456".to_owned(),
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        // A wrong code is rejected
+        let response = post_link_page(
+            &state,
+            &cookies,
+            &link,
+            &response,
+            json!({ "action": "link_with_code", "code": "654321" }),
+        )
+        .await;
+        response.assert_status(StatusCode::OK);
+        assert!(response
+            .body()
+            .contains("The code is invalid or has expired"));
+        assert_eq!(linked_user(&state, &link).await, None);
+
+        let response = post_link_page(
+            &state,
+            &cookies,
+            &link,
+            &response,
+            json!({ "action": "link_with_code", "code": " 123456 " }),
+        )
+        .await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        assert_eq!(linked_user(&state, &link).await, Some(alice.id));
+
+        // The user is told that a new way to sign in was added to their account
+        let jobs: Vec<String> = sqlx::query_scalar(
+            "SELECT job::text FROM apalis.jobs WHERE job_type = 'send-security-notification'",
+        )
+        .fetch_all(&state.pool)
+        .await
+        .unwrap();
+        assert_eq!(jobs.len(), 1);
+
+        // The code can't be used a second time
+        let mut repo = state.repository().await.unwrap();
+        assert!(repo
+            .user_email()
+            .find_verification_code(&state.clock, &user_email, "123456")
+            .await
+            .unwrap()
+            .is_none());
+        repo.save().await.unwrap();
+    }
+
+    /// Test that an expired code doesn't link the upstream account
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_localpart_conflict_link_expired_code(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let cookies = CookieHelper::new();
+        let alice = add_user_with_email(&state, "alice", "alice@example.com").await;
+
+        let claims_imports = forced_localpart_imports(UpstreamOAuthProviderLocalpartConflict::Link);
+        let userinfo = json!({ "preferred_username": "alice" });
+        let (_provider, link, _session) =
+            start_link(&state, &cookies, claims_imports, userinfo).await;
+
+        let mut repo = state.repository().await.unwrap();
+        let user_email = repo
+            .user_email()
+            .get_primary(&alice)
+            .await
+            .unwrap()
+            .unwrap();
+        repo.user_email()
+            .add_verification_code(
+                &mut state.rng(),
+                &state.clock,
+                &user_email,
+                chrono::Duration::minutes(5),
+                "123456".to_owned(),
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        state.clock.advance(chrono::Duration::minutes(10));
+
+        let page = get_link_page(&state, &cookies, &link).await;
+        let response = post_link_page(
+            &state,
+            &cookies,
+            &link,
+            &page,
+            json!({ "action": "link_with_code", "code": "123456" }),
+        )
+        .await;
+        response.assert_status(StatusCode::OK);
+        assert!(response
+            .body()
+            .contains("The code is invalid or has expired"));
+        assert_eq!(linked_user(&state, &link).await, None);
+    }
+
+    /// Test that a link which is already associated to a user logs them in,
+    /// and can't be associated to another user
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_already_linked(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let cookies = CookieHelper::new();
+        let alice = add_user_with_email(&state, "alice", "alice@example.com").await;
+        let bob = add_user_with_email(&state, "bob", "bob@example.com").await;
+
+        let claims_imports = forced_localpart_imports(UpstreamOAuthProviderLocalpartConflict::Link);
+        let userinfo = json!({ "preferred_username": "bob" });
+        let (_provider, link, _session) =
+            start_link(&state, &cookies, claims_imports, userinfo).await;
+
+        let mut repo = state.repository().await.unwrap();
+        repo.upstream_oauth_link()
+            .associate_to_user(&link, &alice)
+            .await
+            .unwrap();
+        let user_email = repo.user_email().get_primary(&bob).await.unwrap().unwrap();
+        repo.user_email()
+            .add_verification_code(
+                &mut state.rng(),
+                &state.clock,
+                &user_email,
+                chrono::Duration::hours(8),
+                "123456".to_owned(),
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        // Proving ownership of the account with the forced localpart doesn't
+        // move the link to it
+        let csrf_page = {
+            let request = Request::get(&*mas_router::Login::default().path_and_query()).empty();
+            let request = cookies.with_cookies(request);
+            let response = state.request(request).await;
+            cookies.save_cookies(&response);
+            response
+        };
+        let response = post_link_page(
+            &state,
+            &cookies,
+            &link,
+            &csrf_page,
+            json!({ "action": "link_with_code", "code": "123456" }),
+        )
+        .await;
+        response.assert_status(StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(linked_user(&state, &link).await, Some(alice.id));
+
+        // Instead, the user it is linked to is logged in
+        let request =
+            Request::get(&*mas_router::UpstreamOAuth2Link::new(link.id).path_and_query()).empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::SEE_OTHER);
+        assert_eq!(linked_user(&state, &link).await, Some(alice.id));
+
+        let mut repo = state.repository().await.unwrap();
+        let sessions = repo
+            .browser_session()
+            .list(
+                mas_storage::user::BrowserSessionFilter::new().for_user(&alice),
+                Pagination::first(10),
+            )
+            .await
+            .unwrap();
+        assert_eq!(sessions.edges.len(), 1);
+        repo.save().await.unwrap();
+    }
 }
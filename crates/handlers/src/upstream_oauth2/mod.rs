@@ -12,95 +12,41 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::string::FromUtf8Error;
-
 use mas_data_model::UpstreamOAuthProvider;
-use mas_iana::{jose::JsonWebSignatureAlg, oauth::OAuthClientAuthenticationMethod};
-use mas_keystore::{DecryptError, Encrypter, Keystore};
-use mas_oidc_client::types::client_credentials::{ClientCredentials, JwtSigningMethod};
-use thiserror::Error;
-use url::Url;
+use mas_storage::{
+    upstream_oauth2::UpstreamOAuthProviderRepository, BoxRepository, RepositoryError,
+};
+use ulid::Ulid;
 
 pub(crate) mod authorize;
+pub(crate) mod backchannel_logout;
 pub(crate) mod cache;
 pub(crate) mod callback;
 mod cookie;
+pub(crate) mod frontchannel_logout;
+pub(crate) mod keys;
 pub(crate) mod link;
+pub(crate) mod saml;
+mod template;
+pub(crate) mod token;
 
 use self::cookie::UpstreamSessions as UpstreamSessionsCookie;
 
-#[derive(Debug, Error)]
-#[allow(clippy::enum_variant_names)]
-enum ProviderCredentialsError {
-    #[error("Provider doesn't have a client secret")]
-    MissingClientSecret,
-
-    #[error("Could not decrypt client secret")]
-    DecryptClientSecret {
-        #[from]
-        inner: DecryptError,
-    },
-
-    #[error("Client secret is invalid")]
-    InvalidClientSecret {
-        #[from]
-        inner: FromUtf8Error,
-    },
-}
-
-fn client_credentials_for_provider(
-    provider: &UpstreamOAuthProvider,
-    token_endpoint: &Url,
-    keystore: &Keystore,
-    encrypter: &Encrypter,
-) -> Result<ClientCredentials, ProviderCredentialsError> {
-    let client_id = provider.client_id.clone();
-
-    // Decrypt the client secret
-    let client_secret = provider
-        .encrypted_client_secret
-        .as_deref()
-        .map(|encrypted_client_secret| {
-            let decrypted = encrypter.decrypt_string(encrypted_client_secret)?;
-            let decrypted = String::from_utf8(decrypted)?;
-            Ok::<_, ProviderCredentialsError>(decrypted)
-        })
-        .transpose()?;
-
-    let client_credentials = match provider.token_endpoint_auth_method {
-        OAuthClientAuthenticationMethod::None => ClientCredentials::None { client_id },
-        OAuthClientAuthenticationMethod::ClientSecretPost => ClientCredentials::ClientSecretPost {
-            client_id,
-            client_secret: client_secret.ok_or(ProviderCredentialsError::MissingClientSecret)?,
-        },
-        OAuthClientAuthenticationMethod::ClientSecretBasic => {
-            ClientCredentials::ClientSecretBasic {
-                client_id,
-                client_secret: client_secret
-                    .ok_or(ProviderCredentialsError::MissingClientSecret)?,
-            }
-        }
-        OAuthClientAuthenticationMethod::ClientSecretJwt => ClientCredentials::ClientSecretJwt {
-            client_id,
-            client_secret: client_secret.ok_or(ProviderCredentialsError::MissingClientSecret)?,
-            signing_algorithm: provider
-                .token_endpoint_signing_alg
-                .clone()
-                .unwrap_or(JsonWebSignatureAlg::Rs256),
-            token_endpoint: token_endpoint.clone(),
-        },
-        OAuthClientAuthenticationMethod::PrivateKeyJwt => ClientCredentials::PrivateKeyJwt {
-            client_id,
-            jwt_signing_method: JwtSigningMethod::Keystore(keystore.clone()),
-            signing_algorithm: provider
-                .token_endpoint_signing_alg
-                .clone()
-                .unwrap_or(JsonWebSignatureAlg::Rs256),
-            token_endpoint: token_endpoint.clone(),
-        },
-        // XXX: The database should never have an unsupported method in it
-        _ => unreachable!(),
-    };
-
-    Ok(client_credentials)
+/// Find the provider referenced in a URL, either by its slug or by its ID
+async fn lookup_provider(
+    repo: &mut BoxRepository,
+    provider_ref: &str,
+) -> Result<Option<UpstreamOAuthProvider>, RepositoryError> {
+    if let Some(provider) = repo
+        .upstream_oauth_provider()
+        .find_by_slug(provider_ref)
+        .await?
+    {
+        return Ok(Some(provider));
+    }
+
+    match provider_ref.parse::<Ulid>() {
+        Ok(id) => repo.upstream_oauth_provider().lookup(id).await,
+        Err(_) => Ok(None),
+    }
 }
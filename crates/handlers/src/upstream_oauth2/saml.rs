@@ -0,0 +1,289 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The endpoints of the SAML 2.0 providers: the metadata of this service, and
+//! the assertion consumer service to which the identity provider posts its
+//! responses
+
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Path, State},
+    response::{Html, IntoResponse, Response},
+    Form, TypedHeader,
+};
+use mas_axum_utils::cookies::CookieJar;
+use mas_data_model::UpstreamOAuthProviderProtocol;
+use mas_policy::Policy;
+use mas_router::UrlBuilder;
+use mas_saml::{
+    metadata::ServiceProviderMetadata,
+    response::{validate_response, Assertion, ValidationData},
+    Certificate,
+};
+use mas_storage::{
+    upstream_oauth2::UpstreamOAuthSessionRepository, BoxClock, BoxRepository, BoxRng, Clock,
+};
+use mas_templates::{FormPostContext, Templates};
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+
+use super::{callback::RouteError, UpstreamSessionsCookie};
+use crate::BoundActivityTracker;
+
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Params {
+    #[serde(rename = "SAMLResponse")]
+    saml_response: String,
+
+    /// The state of the session, sent as the relay state of the request
+    #[serde(rename = "RelayState")]
+    relay_state: String,
+
+    /// Set when the response was posted again from our own site, to get the
+    /// session cookie along
+    resubmitted: Option<String>,
+}
+
+#[tracing::instrument(
+    name = "handlers.upstream_oauth2.saml.metadata",
+    fields(upstream_oauth_provider.id = %provider_ref),
+    skip_all,
+    err,
+)]
+pub(crate) async fn metadata(
+    mut repo: BoxRepository,
+    State(url_builder): State<UrlBuilder>,
+    Path(provider_ref): Path<String>,
+) -> Result<Response, RouteError> {
+    let provider = super::lookup_provider(&mut repo, &provider_ref)
+        .await?
+        .ok_or(RouteError::ProviderNotFound)?;
+
+    if provider.protocol != UpstreamOAuthProviderProtocol::Saml {
+        return Err(RouteError::ProtocolMismatch);
+    }
+
+    let assertion_consumer_service_url =
+        url_builder.upstream_saml_acs(provider.id, provider.slug.as_deref());
+    let metadata = ServiceProviderMetadata {
+        entity_id: &provider.client_id,
+        assertion_consumer_service_url: &assertion_consumer_service_url,
+        name_id_format: provider.saml.name_id_format.as_deref(),
+    };
+
+    Ok((
+        [(hyper::header::CONTENT_TYPE, "application/samlmetadata+xml")],
+        metadata.to_xml(),
+    )
+        .into_response())
+}
+
+#[tracing::instrument(
+    name = "handlers.upstream_oauth2.saml.acs",
+    fields(upstream_oauth_provider.id = %provider_ref),
+    skip_all,
+    err,
+)]
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn acs(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    mut repo: BoxRepository,
+    State(url_builder): State<UrlBuilder>,
+    State(templates): State<Templates>,
+    mut policy: Policy,
+    activity_tracker: BoundActivityTracker,
+    user_agent: Option<TypedHeader<headers::UserAgent>>,
+    cookie_jar: CookieJar,
+    Path(provider_ref): Path<String>,
+    Form(params): Form<Params>,
+) -> Result<Response, RouteError> {
+    let provider = super::lookup_provider(&mut repo, &provider_ref)
+        .await?
+        .ok_or(RouteError::ProviderNotFound)?;
+
+    if provider.protocol != UpstreamOAuthProviderProtocol::Saml {
+        return Err(RouteError::ProtocolMismatch);
+    }
+
+    let assertion_consumer_service_url =
+        url_builder.upstream_saml_acs(provider.id, provider.slug.as_deref());
+
+    let sessions_cookie = UpstreamSessionsCookie::load(&cookie_jar);
+    let Ok((session_id, _post_auth_action)) =
+        sessions_cookie.find_session(provider.id, &params.relay_state)
+    else {
+        // The browser posts the response to us from the identity provider's site, so
+        // our cookies, which are `SameSite=Lax`, are not sent. Post it again from our
+        // own site once to get them.
+        if params.resubmitted.is_none() {
+            let params = Params {
+                resubmitted: Some("true".to_owned()),
+                ..params
+            };
+            let ctx = FormPostContext::new(assertion_consumer_service_url, params);
+            let rendered = templates.render_form_post(&ctx)?;
+            return Ok(Html(rendered).into_response());
+        }
+
+        return Err(RouteError::MissingCookie);
+    };
+
+    let session = repo
+        .upstream_oauth_session()
+        .lookup(session_id)
+        .await?
+        .ok_or(RouteError::SessionNotFound)?;
+
+    if provider.id != session.provider_id {
+        // The provider in the session cookie should match the one from the URL
+        return Err(RouteError::ProviderMismatch);
+    }
+
+    if params.relay_state != session.state_str {
+        // The state in the session cookie should match the relay state
+        return Err(RouteError::StateMismatch);
+    }
+
+    if !session.is_pending() {
+        // The session was already completed
+        return Err(RouteError::AlreadyCompleted);
+    }
+
+    let certificates = provider
+        .saml
+        .idp_certificates
+        .iter()
+        .map(|certificate| certificate.parse())
+        .collect::<Result<Vec<Certificate>, _>>()?;
+
+    // The ID of the request was stored as the nonce of the session
+    let validation_data = ValidationData {
+        idp_entity_id: &provider.issuer,
+        sp_entity_id: &provider.client_id,
+        assertion_consumer_service_url: &assertion_consumer_service_url,
+        request_id: &session.nonce,
+        certificates: &certificates,
+    };
+    let assertion = validate_response(&params.saml_response, &validation_data, clock.now())?;
+
+    // The claims are stored on the session as if they came from the userinfo
+    // endpoint, for the link page to import them
+    let claims = claims_from_assertion(assertion);
+
+    super::callback::complete_login(
+        &mut rng,
+        &clock,
+        repo,
+        &url_builder,
+        &mut policy,
+        &activity_tracker,
+        user_agent,
+        cookie_jar,
+        sessions_cookie,
+        &provider,
+        session,
+        claims.clone(),
+        None,
+        Some(claims),
+        None,
+        None,
+    )
+    .await
+}
+
+/// The claims of the user asserted by the identity provider: the name ID as
+/// the `sub` claim, and each attribute under both its name and its friendly
+/// name.
+///
+/// Attributes with a single value are strings, and attributes with several
+/// values are arrays of strings.
+fn claims_from_assertion(assertion: Assertion) -> HashMap<String, serde_json::Value> {
+    let mut claims = HashMap::new();
+    claims.insert(
+        "sub".to_owned(),
+        serde_json::Value::String(assertion.name_id),
+    );
+
+    for attribute in assertion.attributes {
+        let value = match <[String; 1]>::try_from(attribute.values) {
+            Ok([value]) => serde_json::Value::String(value),
+            Err(values) if values.is_empty() => continue,
+            Err(values) => values.into_iter().map(serde_json::Value::String).collect(),
+        };
+
+        // Attributes can't override the name ID, and the first of attributes
+        // sharing a name wins
+        if let Some(friendly_name) = attribute.friendly_name {
+            claims.entry(friendly_name).or_insert_with(|| value.clone());
+        }
+        claims.entry(attribute.name).or_insert(value);
+    }
+
+    claims
+}
+
+#[cfg(test)]
+mod tests {
+    use mas_saml::response::Attribute;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_claims_from_assertion() {
+        let assertion = Assertion {
+            name_id: "AAdzZWNyZXQxtyj8Mv".to_owned(),
+            name_id_format: None,
+            attributes: vec![
+                Attribute {
+                    name: "urn:oid:0.9.2342.19200300.100.1.3".to_owned(),
+                    friendly_name: Some("mail".to_owned()),
+                    values: vec!["jane.doe@example.edu".to_owned()],
+                },
+                Attribute {
+                    name: "urn:oid:1.3.6.1.4.1.5923.1.1.1.1".to_owned(),
+                    friendly_name: Some("eduPersonAffiliation".to_owned()),
+                    values: vec!["member".to_owned(), "student".to_owned()],
+                },
+                Attribute {
+                    name: "sub".to_owned(),
+                    friendly_name: None,
+                    values: vec!["someone-else".to_owned()],
+                },
+                Attribute {
+                    name: "empty".to_owned(),
+                    friendly_name: None,
+                    values: Vec::new(),
+                },
+            ],
+        };
+
+        let claims = claims_from_assertion(assertion);
+        assert_eq!(claims.len(), 5);
+        assert_eq!(claims["sub"], json!("AAdzZWNyZXQxtyj8Mv"));
+        assert_eq!(claims["mail"], json!("jane.doe@example.edu"));
+        assert_eq!(
+            claims["urn:oid:0.9.2342.19200300.100.1.3"],
+            json!("jane.doe@example.edu")
+        );
+        assert_eq!(claims["eduPersonAffiliation"], json!(["member", "student"]));
+        assert_eq!(
+            claims["urn:oid:1.3.6.1.4.1.5923.1.1.1.1"],
+            json!(["member", "student"])
+        );
+    }
+}
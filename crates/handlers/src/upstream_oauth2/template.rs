@@ -0,0 +1,80 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Templates used to derive the attributes of the user from the claims of the
+//! upstream provider
+
+use minijinja::Environment;
+
+/// The environment in which the claims templates are rendered
+pub(crate) fn environment() -> Environment<'static> {
+    let mut env = Environment::new();
+    env.add_filter("split", filter_split);
+    env
+}
+
+fn filter_split(value: &str, separator: &str) -> Vec<String> {
+    value.split(separator).map(ToOwned::to_owned).collect()
+}
+
+/// Render `template` over the claims
+///
+/// Returns `None` if it renders to an empty string, e.g. because the claims it
+/// uses are missing.
+pub(crate) fn render(
+    env: &Environment<'_>,
+    template: &str,
+    claims: &serde_json::Map<String, serde_json::Value>,
+) -> Result<Option<String>, minijinja::Error> {
+    let value = env.render_str(template, claims)?;
+    let value = value.trim();
+    if value.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(value.to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let env = environment();
+        let claims = json!({
+            "preferred_username": "john@example.com",
+            "given_name": "John",
+            "family_name": "Doe",
+        });
+        let claims = claims.as_object().unwrap();
+
+        let localpart = render(
+            &env,
+            "{{ preferred_username | split('@') | first }}",
+            claims,
+        )
+        .unwrap();
+        assert_eq!(localpart.as_deref(), Some("john"));
+
+        let name = render(&env, "{{ given_name }} {{ family_name }}", claims).unwrap();
+        assert_eq!(name.as_deref(), Some("John Doe"));
+
+        // Missing claims render to nothing
+        let email = render(&env, "{{ email }}", claims).unwrap();
+        assert_eq!(email, None);
+    }
+}
@@ -0,0 +1,502 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An API for trusted services to get the access tokens issued to users by the
+//! upstream providers, to call their APIs on behalf of the users
+
+use axum::{
+    extract::{Path, Query, State},
+    response::{IntoResponse, Response},
+    Json,
+};
+use hyper::StatusCode;
+use mas_axum_utils::{
+    sentry::SentryEventID,
+    user_authorization::{AuthorizationVerificationError, UserAuthorization},
+};
+use mas_keystore::Encrypter;
+use mas_router::UrlBuilder;
+use mas_storage::{
+    upstream_oauth2::{UpstreamOAuthLinkFilter, UpstreamOAuthLinkRepository},
+    user::UserRepository,
+    BoxClock, BoxRepository, Clock, Pagination,
+};
+use oauth2_types::scope::Scope;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use thiserror::Error;
+use tracing::{info, warn};
+use url::Url;
+
+use crate::{impl_from_error_for_route, site_config::SiteConfig, BoundActivityTracker};
+
+/// The scope needed to get the upstream tokens of the users
+const UPSTREAM_TOKENS_SCOPE: &str = "urn:mas:upstream_tokens";
+
+#[derive(Deserialize)]
+pub(crate) struct Params {
+    /// The scopes the token is needed for, which the user must have granted
+    /// to the provider
+    scope: Option<String>,
+}
+
+#[skip_serializing_none]
+#[derive(Serialize)]
+struct TokenResponse {
+    access_token: String,
+    token_type: &'static str,
+    expires_in: Option<i64>,
+    scope: Option<Scope>,
+}
+
+#[derive(Serialize)]
+struct InsufficientScopeResponse {
+    error: &'static str,
+    missing_scope: Scope,
+    upgrade_uri: Url,
+}
+
+#[derive(Debug, Error)]
+pub enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    #[error("failed to authenticate")]
+    AuthorizationVerificationError(
+        #[from] AuthorizationVerificationError<mas_storage::RepositoryError>,
+    ),
+
+    #[error("session is not allowed to access the upstream tokens")]
+    Unauthorized,
+
+    #[error("client is not allowed to get the tokens of this provider")]
+    ClientNotAllowed,
+
+    #[error("provider not found or not storing tokens")]
+    ProviderNotFound,
+
+    #[error("user not found")]
+    UserNotFound,
+
+    #[error("no valid token for this user")]
+    TokenNotFound,
+
+    #[error("invalid scope")]
+    InvalidScope,
+
+    #[error("the user didn't grant all the scopes")]
+    InsufficientScope {
+        missing_scope: Scope,
+        upgrade_uri: Url,
+    },
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+impl_from_error_for_route!(mas_keystore::DecryptError);
+impl_from_error_for_route!(std::string::FromUtf8Error);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> axum::response::Response {
+        let event_id = sentry::capture_error(&self);
+        let response = match self {
+            Self::Internal(_) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()).into_response()
+            }
+            Self::AuthorizationVerificationError(_) | Self::Unauthorized => {
+                StatusCode::UNAUTHORIZED.into_response()
+            }
+            Self::ClientNotAllowed => (StatusCode::FORBIDDEN, self.to_string()).into_response(),
+            Self::ProviderNotFound | Self::UserNotFound | Self::TokenNotFound => {
+                (StatusCode::NOT_FOUND, self.to_string()).into_response()
+            }
+            Self::InvalidScope => (StatusCode::BAD_REQUEST, self.to_string()).into_response(),
+            // The service can send the user to the upgrade URI for them to grant
+            // the missing scopes
+            Self::InsufficientScope {
+                missing_scope,
+                upgrade_uri,
+            } => (
+                StatusCode::FORBIDDEN,
+                Json(InsufficientScopeResponse {
+                    error: "insufficient_scope",
+                    missing_scope,
+                    upgrade_uri,
+                }),
+            )
+                .into_response(),
+        };
+
+        (SentryEventID::from(event_id), response).into_response()
+    }
+}
+
+/// Get a valid access token issued by a provider to a user
+///
+/// The tokens are only stored for providers with `store_tokens` set, and are
+/// refreshed in the background before they expire. Only the clients in the
+/// `token_clients` of the provider can get them, and each retrieval is
+/// audited.
+///
+/// If the token is needed for scopes the user didn't grant to the provider,
+/// the response has an URI to send them to for them to grant the missing ones.
+#[tracing::instrument(
+    name = "handlers.upstream_oauth2.token.get",
+    fields(upstream_oauth_provider.id = %provider_ref, user.username = %username),
+    skip_all,
+    err,
+)]
+pub(crate) async fn get(
+    clock: BoxClock,
+    activity_tracker: BoundActivityTracker,
+    mut repo: BoxRepository,
+    State(encrypter): State<Encrypter>,
+    State(url_builder): State<UrlBuilder>,
+    State(site_config): State<SiteConfig>,
+    Path((provider_ref, username)): Path<(String, String)>,
+    Query(params): Query<Params>,
+    user_authorization: UserAuthorization,
+) -> Result<Response, RouteError> {
+    let session = user_authorization.protected(&mut repo, &clock).await?;
+
+    if !session.scope.contains(UPSTREAM_TOKENS_SCOPE) {
+        return Err(RouteError::Unauthorized);
+    }
+
+    activity_tracker
+        .record_oauth2_session(&clock, &session)
+        .await;
+
+    let provider = super::lookup_provider(&mut repo, &provider_ref)
+        .await?
+        .filter(|provider| provider.store_tokens)
+        .ok_or(RouteError::ProviderNotFound)?;
+
+    // Each provider has its own list of clients allowed to get its tokens
+    let client_allowed = site_config
+        .upstream_token_clients
+        .get(&provider.id)
+        .is_some_and(|clients| clients.contains(&session.client_id));
+    if !client_allowed {
+        warn!(
+            audit = true,
+            audit.action = "upstream_oauth2.token.denied",
+            oauth2_session.id = %session.id,
+            oauth2_client.id = %session.client_id,
+            upstream_oauth_provider.id = %provider.id,
+            user.username = %username,
+            "Client tried to get upstream tokens of a provider it is not allowed to"
+        );
+        return Err(RouteError::ClientNotAllowed);
+    }
+
+    let user = repo
+        .user()
+        .find_by_username(&username)
+        .await?
+        .filter(mas_data_model::User::is_valid)
+        .ok_or(RouteError::UserNotFound)?;
+
+    let filter = UpstreamOAuthLinkFilter::new()
+        .for_user(&user)
+        .for_provider(&provider);
+    let link = repo
+        .upstream_oauth_link()
+        .list(filter, Pagination::first(1))
+        .await?
+        .edges
+        .into_iter()
+        .next()
+        .ok_or(RouteError::TokenNotFound)?;
+
+    // The scopes granted on links created before they were recorded are not
+    // known, but they at least include the ones of the provider
+    let granted_scope = repo
+        .upstream_oauth_link()
+        .granted_scope(&link)
+        .await?
+        .unwrap_or_else(|| provider.scope.clone());
+
+    if let Some(scope) = params.scope {
+        let scope: Scope = scope.parse().map_err(|_| RouteError::InvalidScope)?;
+        let missing_scope: Scope = scope
+            .iter()
+            .filter(|token| !granted_scope.contains(token))
+            .cloned()
+            .collect();
+        if !missing_scope.is_empty() {
+            let upgrade_uri =
+                url_builder.upstream_oauth_upgrade(provider.id, missing_scope.to_string());
+            return Err(RouteError::InsufficientScope {
+                missing_scope,
+                upgrade_uri,
+            });
+        }
+    }
+
+    let now = clock.now();
+    let tokens = repo
+        .upstream_oauth_link()
+        .tokens(&link)
+        .await?
+        .filter(|tokens| tokens.is_valid(now))
+        .ok_or(RouteError::TokenNotFound)?;
+
+    let access_token = encrypter.decrypt_string(&tokens.encrypted_access_token)?;
+    let access_token = String::from_utf8(access_token)?;
+
+    info!(
+        audit = true,
+        audit.action = "upstream_oauth2.token.retrieved",
+        oauth2_session.id = %session.id,
+        oauth2_client.id = %session.client_id,
+        upstream_oauth_provider.id = %provider.id,
+        upstream_oauth_link.id = %link.id,
+        user.id = %user.id,
+        user.username = %user.username,
+        scope = %granted_scope,
+        "Service retrieved the upstream access token of a user"
+    );
+
+    Ok(Json(TokenResponse {
+        access_token,
+        token_type: "Bearer",
+        expires_in: tokens
+            .access_token_expires_at
+            .map(|expires_at| (expires_at - now).num_seconds()),
+        scope: Some(granted_scope),
+    })
+    .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use hyper::Request;
+    use mas_data_model::{
+        TokenType, UpstreamOAuthLinkTokens, UpstreamOAuthProvider,
+        UpstreamOAuthProviderAuthorizationParams, UpstreamOAuthProviderClaimsImports,
+        UpstreamOAuthProviderEndpoints, UpstreamOAuthProviderHealthCheckSettings,
+        UpstreamOAuthProviderPkceMethod, UpstreamOAuthProviderProtocol,
+        UpstreamOAuthProviderSamlSettings, UpstreamOAuthProviderUiOptions,
+    };
+    use mas_iana::oauth::OAuthClientAuthenticationMethod;
+    use mas_router::Route;
+    use mas_storage::{
+        oauth2::{OAuth2AccessTokenRepository, OAuth2ClientRepository, OAuth2SessionRepository},
+        upstream_oauth2::UpstreamOAuthProviderRepository,
+        RepositoryAccess,
+    };
+    use oauth2_types::scope::{ScopeToken, OPENID};
+    use sqlx::PgPool;
+    use ulid::Ulid;
+
+    use super::*;
+    use crate::test_utils::{init_tracing, RequestBuilderExt, ResponseExt, TestState};
+
+    async fn add_provider(state: &TestState, store_tokens: bool) -> UpstreamOAuthProvider {
+        let mut repo = state.repository().await.unwrap();
+        let provider = repo
+            .upstream_oauth_provider()
+            .add(
+                &mut state.rng(),
+                &state.clock,
+                "https://example.com/".to_owned(),
+                None,
+                Scope::from_iter([OPENID]),
+                OAuthClientAuthenticationMethod::None,
+                None,
+                "client".to_owned(),
+                None,
+                UpstreamOAuthProviderClaimsImports::default(),
+                UpstreamOAuthProviderPkceMethod::default(),
+                UpstreamOAuthProviderAuthorizationParams::default(),
+                false,
+                UpstreamOAuthProviderUiOptions::default(),
+                UpstreamOAuthProviderProtocol::Oidc,
+                UpstreamOAuthProviderEndpoints::default(),
+                UpstreamOAuthProviderSamlSettings::default(),
+                store_tokens,
+                UpstreamOAuthProviderHealthCheckSettings::default(),
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+        provider
+    }
+
+    /// Start a client credentials session for a service with the given scope,
+    /// and return the ID of its client along with the access token
+    async fn service_token(state: &TestState, scope: Scope) -> (Ulid, String) {
+        let mut repo = state.repository().await.unwrap();
+        let mut rng = state.rng();
+
+        let client = repo
+            .oauth2_client()
+            .add(
+                &mut rng,
+                &state.clock,
+                vec![],
+                None,
+                None,
+                vec![],
+                vec![],
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+
+        let session = repo
+            .oauth2_session()
+            .add_from_client_credentials(&mut rng, &state.clock, &client, scope)
+            .await
+            .unwrap();
+
+        let access_token = TokenType::AccessToken.generate(&mut rng);
+        repo.oauth2_access_token()
+            .add(&mut rng, &state.clock, &session, access_token.clone(), None)
+            .await
+            .unwrap();
+
+        repo.save().await.unwrap();
+
+        (client.id, access_token)
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_get_upstream_token(pool: PgPool) {
+        init_tracing();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let mut rng = state.rng();
+
+        let provider = add_provider(&state, true).await;
+        let other_provider = add_provider(&state, false).await;
+
+        // A user linked to the provider, with an access token stored encrypted
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+        let link = repo
+            .upstream_oauth_link()
+            .add(&mut rng, &state.clock, &provider, "subject".to_owned())
+            .await
+            .unwrap();
+        repo.upstream_oauth_link()
+            .associate_to_user(&link, &user)
+            .await
+            .unwrap();
+        let encrypter = &state.encrypter;
+        let tokens = UpstreamOAuthLinkTokens {
+            encrypted_access_token: encrypter.encrypt_to_string(b"upstream-token").unwrap(),
+            encrypted_refresh_token: None,
+            access_token_expires_at: Some(state.clock.now() + Duration::minutes(5)),
+        };
+        repo.upstream_oauth_link()
+            .set_tokens(&link, Some(&tokens))
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        // The token is never stored in clear
+        assert!(!tokens.encrypted_access_token.contains("upstream-token"));
+
+        let route = mas_router::UpstreamOAuth2Token::new(&provider.id.to_string(), "alice");
+
+        // The API needs an access token...
+        let request = Request::get(route.path()).empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+
+        // ...with the right scope
+        let (_, access_token) = service_token(&state, Scope::from_iter([OPENID])).await;
+        let request = Request::get(route.path()).bearer(&access_token).empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+
+        // ...from a client the provider allows
+        let scope = Scope::from_iter([ScopeToken::from_static(UPSTREAM_TOKENS_SCOPE)]);
+        let (client_id, access_token) = service_token(&state, scope.clone()).await;
+        let request = Request::get(route.path()).bearer(&access_token).empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+
+        let (other_client_id, other_access_token) = service_token(&state, scope).await;
+        state
+            .site_config
+            .upstream_token_clients
+            .insert(provider.id, vec![client_id]);
+        state
+            .site_config
+            .upstream_token_clients
+            .insert(other_provider.id, vec![other_client_id]);
+
+        // Being allowed on another provider is not enough
+        let request = Request::get(route.path())
+            .bearer(&other_access_token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+
+        let request = Request::get(route.path()).bearer(&access_token).empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let body: serde_json::Value = response.json();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "access_token": "upstream-token",
+                "token_type": "Bearer",
+                "expires_in": 300,
+                "scope": "openid",
+            })
+        );
+
+        // Providers which don't store tokens are not exposed
+        let other_route =
+            mas_router::UpstreamOAuth2Token::new(&other_provider.id.to_string(), "alice");
+        let request = Request::get(other_route.path())
+            .bearer(&access_token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::NOT_FOUND);
+
+        // Neither are unknown users
+        let unknown_route = mas_router::UpstreamOAuth2Token::new(&provider.id.to_string(), "bob");
+        let request = Request::get(unknown_route.path())
+            .bearer(&access_token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::NOT_FOUND);
+
+        // Expired tokens are not handed out
+        state.clock.advance(Duration::minutes(10));
+        let request = Request::get(route.path()).bearer(&access_token).empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::NOT_FOUND);
+    }
+}
@@ -23,15 +23,14 @@ use mas_axum_utils::{
 };
 use mas_policy::Policy;
 use mas_router::UrlBuilder;
-use mas_storage::{
-    job::{JobRepositoryExt, VerifyEmailJob},
-    user::UserEmailRepository,
-    BoxClock, BoxRepository, BoxRng,
-};
-use mas_templates::{EmailAddContext, ErrorContext, TemplateContext, Templates};
+use mas_storage::{user::UserEmailRepository, BoxClock, BoxRepository, BoxRng};
+use mas_templates::{error_codes, EmailAddContext, ErrorContext, TemplateContext, Templates};
 use serde::Deserialize;
 
-use crate::{views::shared::OptionalPostAuthAction, BoundActivityTracker, PreferredLanguage};
+use super::send_verification_email;
+use crate::{
+    views::shared::OptionalPostAuthAction, BoundActivityTracker, PreferredLanguage, SiteConfig,
+};
 
 #[derive(Deserialize, Debug)]
 pub struct EmailForm {
@@ -52,7 +51,7 @@ pub(crate) async fn get(
     let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
     let (session_info, cookie_jar) = cookie_jar.session_info();
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info.load_session(&clock, &mut repo).await?;
 
     let Some(session) = maybe_session else {
         let login = mas_router::Login::default();
@@ -82,6 +81,7 @@ pub(crate) async fn post(
     mut policy: Policy,
     cookie_jar: CookieJar,
     State(url_builder): State<UrlBuilder>,
+    State(site_config): State<SiteConfig>,
     activity_tracker: BoundActivityTracker,
     Query(query): Query<OptionalPostAuthAction>,
     Form(form): Form<ProtectedForm<EmailForm>>,
@@ -89,7 +89,7 @@ pub(crate) async fn post(
     let form = cookie_jar.verify_form(&clock, form)?;
     let (session_info, cookie_jar) = cookie_jar.session_info();
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info.load_session(&clock, &mut repo).await?;
 
     let Some(session) = maybe_session else {
         let login = mas_router::Login::default();
@@ -108,6 +108,7 @@ pub(crate) async fn post(
     if !res.valid() {
         return Err(FancyError::new(
             ErrorContext::new()
+                .with_code(error_codes::EMAIL_DENIED)
                 .with_description(format!("Email address {:?} denied by policy", form.email))
                 .with_details(format!("{res}")),
         ));
@@ -126,9 +127,15 @@ pub(crate) async fn post(
     // If the email was not confirmed, send a confirmation email & redirect to the
     // verify page
     let next = if user_email.confirmed_at.is_none() {
-        repo.job()
-            .schedule_job(VerifyEmailJob::new(&user_email).with_language(locale.to_string()))
-            .await?;
+        send_verification_email(
+            &mut rng,
+            &clock,
+            &mut repo,
+            &site_config.email_rate_limits,
+            &user_email,
+            &locale,
+        )
+        .await?;
 
         let next = mas_router::AccountVerifyEmail::new(user_email.id);
         let next = if let Some(action) = query.post_auth_action {
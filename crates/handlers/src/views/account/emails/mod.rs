@@ -12,5 +12,42 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use mas_data_model::{EmailRateLimits, UserEmail};
+use mas_i18n::DataLocale;
+use mas_storage::{BoxRepository, Clock, RepositoryError};
+use rand::RngCore;
+use tracing::warn;
+
 pub mod add;
 pub mod verify;
+
+/// Schedule a job to send a verification code to the given address, unless
+/// too many were sent to it or to its user recently.
+///
+/// Hitting the rate limits is not an error: the user can ask for a new code
+/// later from the verification page.
+pub(crate) async fn send_verification_email(
+    rng: &mut (dyn RngCore + Send),
+    clock: &impl Clock,
+    repo: &mut BoxRepository,
+    limits: &EmailRateLimits,
+    user_email: &UserEmail,
+    locale: &DataLocale,
+) -> Result<(), RepositoryError> {
+    let language = locale.to_string();
+    let res = mas_storage::user::send_verification_email(
+        repo,
+        rng,
+        clock,
+        limits,
+        user_email,
+        Some(&language),
+    )
+    .await?;
+
+    if let Err(e) = res {
+        warn!(user_email.id = %user_email.id, error = %e, "Not sending the verification email");
+    }
+
+    Ok(())
+}
@@ -24,8 +24,8 @@ use mas_axum_utils::{
 };
 use mas_router::UrlBuilder;
 use mas_storage::{
-    job::{JobRepositoryExt, ProvisionUserJob},
-    user::UserEmailRepository,
+    job::{JobRepositoryExt, NotifyUserEventJob, ProvisionUserJob},
+    user::{continue_email_change, UserEmailRepository},
     BoxClock, BoxRepository, BoxRng, RepositoryAccess,
 };
 use mas_templates::{EmailVerificationPageContext, TemplateContext, Templates};
@@ -60,7 +60,7 @@ pub(crate) async fn get(
     let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
     let (session_info, cookie_jar) = cookie_jar.session_info();
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info.load_session(&clock, &mut repo).await?;
 
     let Some(session) = maybe_session else {
         let login = mas_router::Login::default();
@@ -102,6 +102,7 @@ pub(crate) async fn get(
 )]
 pub(crate) async fn post(
     clock: BoxClock,
+    PreferredLanguage(locale): PreferredLanguage,
     mut repo: BoxRepository,
     cookie_jar: CookieJar,
     State(url_builder): State<UrlBuilder>,
@@ -113,7 +114,7 @@ pub(crate) async fn post(
     let form = cookie_jar.verify_form(&clock, form)?;
     let (session_info, cookie_jar) = cookie_jar.session_info();
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info.load_session(&clock, &mut repo).await?;
 
     let Some(session) = maybe_session else {
         let login = mas_router::Login::default();
@@ -145,14 +146,24 @@ pub(crate) async fn post(
         repo.user_email().set_as_primary(&user_email).await?;
     }
 
-    repo.user_email()
+    let user_email = repo
+        .user_email()
         .mark_as_verified(&clock, user_email)
         .await?;
 
+    // If this address was meant to replace the primary one, ask the old address
+    // to confirm the change
+    let language = locale.to_string();
+    continue_email_change(&mut repo, &clock, &user_email, false, Some(&language)).await?;
+
     repo.job()
         .schedule_job(ProvisionUserJob::new(&session.user))
         .await?;
 
+    repo.job()
+        .schedule_job(NotifyUserEventJob::email_verified(&user_email))
+        .await?;
+
     repo.save().await?;
 
     activity_tracker
@@ -28,6 +28,7 @@ use mas_i18n::DataLocale;
 use mas_policy::Policy;
 use mas_router::UrlBuilder;
 use mas_storage::{
+    job::{JobRepositoryExt, SendSecurityNotificationJob},
     user::{BrowserSessionRepository, UserPasswordRepository},
     BoxClock, BoxRepository, BoxRng, Clock,
 };
@@ -66,9 +67,18 @@ pub(crate) async fn get(
 
     let (session_info, cookie_jar) = cookie_jar.session_info();
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info.load_session(&clock, &mut repo).await?;
 
     if let Some(session) = maybe_session {
+        // Administrators impersonating a user are not allowed to change their password
+        if session.is_impersonation() {
+            return Ok((
+                cookie_jar,
+                url_builder.redirect(&mas_router::Account::default()),
+            )
+                .into_response());
+        }
+
         activity_tracker
             .record_browser_session(&clock, &session)
             .await;
@@ -123,13 +133,17 @@ pub(crate) async fn post(
 
     let (session_info, cookie_jar) = cookie_jar.session_info();
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info.load_session(&clock, &mut repo).await?;
 
     let Some(session) = maybe_session else {
         let login = mas_router::Login::and_then(mas_router::PostAuthAction::ChangePassword);
         return Ok((cookie_jar, url_builder.redirect(&login)).into_response());
     };
 
+    if session.is_impersonation() {
+        return Ok((cookie_jar, StatusCode::FORBIDDEN).into_response());
+    }
+
     let user_password = repo
         .user_password()
         .active(&session.user)
@@ -177,6 +191,13 @@ pub(crate) async fn post(
         .authenticate_with_password(&mut rng, &clock, &session, &user_password)
         .await?;
 
+    repo.job()
+        .schedule_job(
+            SendSecurityNotificationJob::password_changed(&session.user)
+                .with_language(locale.to_string()),
+        )
+        .await?;
+
     activity_tracker
         .record_browser_session(&clock, &session)
         .await;
@@ -21,7 +21,7 @@ use mas_router::{PostAuthAction, UrlBuilder};
 use mas_storage::{BoxClock, BoxRepository};
 use mas_templates::{AppContext, TemplateContext, Templates};
 
-use crate::{BoundActivityTracker, PreferredLanguage};
+use crate::{BoundActivityTracker, PreferredLanguage, SiteConfig};
 
 #[tracing::instrument(name = "handlers.views.app.get", skip_all, err)]
 pub async fn get(
@@ -29,13 +29,14 @@ pub async fn get(
     State(templates): State<Templates>,
     activity_tracker: BoundActivityTracker,
     State(url_builder): State<UrlBuilder>,
+    State(site_config): State<SiteConfig>,
     action: Option<Query<mas_router::AccountAction>>,
     mut repo: BoxRepository,
     clock: BoxClock,
     cookie_jar: CookieJar,
 ) -> Result<impl IntoResponse, FancyError> {
     let (session_info, cookie_jar) = cookie_jar.session_info();
-    let session = session_info.load_session(&mut repo).await?;
+    let session = session_info.load_session(&clock, &mut repo).await?;
     let action = action.map(|Query(a)| a);
 
     // TODO: keep the full path, not just the action
@@ -49,11 +50,23 @@ pub async fn get(
             .into_response());
     };
 
+    // Cross-signing resets are approved on a dedicated page, not in the app
+    if matches!(action, Some(mas_router::AccountAction::CrossSigningReset)) {
+        return Ok((
+            cookie_jar,
+            url_builder.redirect(&mas_router::ResetCrossSigning),
+        )
+            .into_response());
+    }
+
     activity_tracker
         .record_browser_session(&clock, &session)
         .await;
 
-    let ctx = AppContext::from_url_builder(&url_builder).with_language(locale);
+    let ctx = AppContext::from_url_builder(&url_builder)
+        .with_embedding_origins(site_config.embedding_origins)
+        .with_session(session)
+        .with_language(locale);
     let content = templates.render_app(&ctx)?;
 
     Ok((cookie_jar, Html(content)).into_response())
@@ -0,0 +1,545 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::str::FromStr;
+
+use axum::{
+    extract::{Form, Query, State},
+    response::{Html, IntoResponse, Response},
+};
+use lettre::Address;
+use mas_axum_utils::{
+    cookies::CookieJar,
+    csrf::{CsrfExt, CsrfToken, ProtectedForm},
+    FancyError, SessionInfoExt,
+};
+use mas_data_model::{BrowserSession, ProfileAttribute, User};
+use mas_i18n::DataLocale;
+use mas_matrix::HomeserverConnection;
+use mas_policy::Policy;
+use mas_router::UrlBuilder;
+use mas_storage::{
+    job::{JobRepositoryExt, SetDisplayNameJob},
+    user::{UserEmailFilter, UserEmailRepository, UserRepository},
+    BoxClock, BoxRepository, BoxRng, RepositoryAccess, RepositoryError,
+};
+use mas_templates::{
+    CompleteProfileContext, CompleteProfileFormField, FieldError, TemplateContext, Templates,
+    ToFormState,
+};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use url::Url;
+
+use super::{account::emails::send_verification_email, shared::OptionalPostAuthAction};
+use crate::{
+    site_config::AccountRequirements, BoundActivityTracker, PreferredLanguage,
+    SharedHomeserverConnection, SiteConfig,
+};
+
+/// The attributes required by the [`AccountRequirements`] which are missing
+/// on the account of a user
+#[derive(Debug, Default)]
+pub(crate) struct MissingAttributes {
+    pub verified_email: bool,
+    pub displayname: bool,
+    pub terms_uri: Option<Url>,
+}
+
+impl MissingAttributes {
+    /// Find out which of the required attributes the user lacks
+    pub(crate) async fn load(
+        requirements: &AccountRequirements,
+        repo: &mut BoxRepository,
+        homeserver: &dyn HomeserverConnection<Error = anyhow::Error>,
+        user: &User,
+    ) -> Result<Self, RepositoryError> {
+        let verified_email = if requirements.verified_email {
+            let filter = UserEmailFilter::new().for_user(user).verified_only();
+            repo.user_email().count(filter).await? == 0
+        } else {
+            false
+        };
+
+        let displayname = if requirements.displayname {
+            let mxid = homeserver.mxid(&user.username);
+            match homeserver.query_user(&mxid).await {
+                Ok(matrix_user) => matrix_user
+                    .displayname
+                    .map_or(true, |displayname| displayname.trim().is_empty()),
+                Err(err) => {
+                    // Don't lock users out because the homeserver is unreachable, or
+                    // because the user was not provisioned yet
+                    warn!(%mxid, error = %err, "Could not check the display name of the user");
+                    false
+                }
+            }
+        } else {
+            false
+        };
+
+        let terms_uri = match &requirements.terms_uri {
+            Some(terms_uri) if !repo.user().has_accepted_terms(user, terms_uri).await? => {
+                Some(terms_uri.clone())
+            }
+            _ => None,
+        };
+
+        Ok(Self {
+            verified_email,
+            displayname,
+            terms_uri,
+        })
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        !self.verified_email && !self.displayname && self.terms_uri.is_none()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct CompleteProfileForm {
+    #[serde(default)]
+    email: String,
+    #[serde(default)]
+    displayname: String,
+    #[serde(default)]
+    accept_terms: Option<String>,
+}
+
+impl ToFormState for CompleteProfileForm {
+    type Field = CompleteProfileFormField;
+}
+
+#[tracing::instrument(name = "handlers.views.complete_profile.get", skip_all, err)]
+pub(crate) async fn get(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    PreferredLanguage(locale): PreferredLanguage,
+    State(templates): State<Templates>,
+    State(url_builder): State<UrlBuilder>,
+    State(site_config): State<SiteConfig>,
+    State(homeserver): State<SharedHomeserverConnection>,
+    activity_tracker: BoundActivityTracker,
+    mut repo: BoxRepository,
+    Query(query): Query<OptionalPostAuthAction>,
+    cookie_jar: CookieJar,
+) -> Result<Response, FancyError> {
+    let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
+    let (session_info, cookie_jar) = cookie_jar.session_info();
+
+    let maybe_session = session_info.load_session(&clock, &mut repo).await?;
+
+    let Some(session) = maybe_session else {
+        // If there is no session, redirect to the login screen, keeping the
+        // PostAuthAction
+        let login = mas_router::Login::from(query.post_auth_action);
+        return Ok((cookie_jar, url_builder.redirect(&login)).into_response());
+    };
+
+    activity_tracker
+        .record_browser_session(&clock, &session)
+        .await;
+
+    let missing = MissingAttributes::load(
+        &site_config.account_requirements,
+        &mut repo,
+        homeserver.as_ref(),
+        &session.user,
+    )
+    .await?;
+
+    if missing.is_empty() {
+        // Nothing to complete, carry on
+        let reply = query.go_next(&url_builder);
+        return Ok((cookie_jar, reply).into_response());
+    }
+
+    let ctx = CompleteProfileContext::new(
+        missing.verified_email,
+        missing.displayname,
+        missing.terms_uri,
+    );
+    let content = render(
+        locale, ctx, query, csrf_token, session, &mut repo, &templates,
+    )
+    .await?;
+
+    Ok((cookie_jar, Html(content)).into_response())
+}
+
+#[tracing::instrument(name = "handlers.views.complete_profile.post", skip_all, err)]
+pub(crate) async fn post(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    PreferredLanguage(locale): PreferredLanguage,
+    State(templates): State<Templates>,
+    State(url_builder): State<UrlBuilder>,
+    State(site_config): State<SiteConfig>,
+    State(homeserver): State<SharedHomeserverConnection>,
+    mut policy: Policy,
+    activity_tracker: BoundActivityTracker,
+    mut repo: BoxRepository,
+    Query(query): Query<OptionalPostAuthAction>,
+    cookie_jar: CookieJar,
+    Form(form): Form<ProtectedForm<CompleteProfileForm>>,
+) -> Result<Response, FancyError> {
+    let form = cookie_jar.verify_form(&clock, form)?;
+    let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
+    let (session_info, cookie_jar) = cookie_jar.session_info();
+
+    let maybe_session = session_info.load_session(&clock, &mut repo).await?;
+
+    let Some(session) = maybe_session else {
+        // If there is no session, redirect to the login screen, keeping the
+        // PostAuthAction
+        let login = mas_router::Login::from(query.post_auth_action);
+        return Ok((cookie_jar, url_builder.redirect(&login)).into_response());
+    };
+
+    let missing = MissingAttributes::load(
+        &site_config.account_requirements,
+        &mut repo,
+        homeserver.as_ref(),
+        &session.user,
+    )
+    .await?;
+
+    // Validate the form, only looking at the fields which were asked for
+    let email = form.email.trim();
+    let displayname = form.displayname.trim();
+    let state = {
+        let mut state = form.to_form_state();
+
+        if missing.verified_email {
+            if email.is_empty() {
+                state.add_error_on_field(CompleteProfileFormField::Email, FieldError::Required);
+            } else if Address::from_str(email).is_err() {
+                state.add_error_on_field(CompleteProfileFormField::Email, FieldError::Invalid);
+            } else {
+                let res = policy.evaluate_email(email).await?;
+                for violation in res.violations {
+                    state.add_error_on_field(
+                        CompleteProfileFormField::Email,
+                        FieldError::Policy {
+                            message: violation.msg,
+                        },
+                    );
+                }
+            }
+        }
+
+        if missing.displayname && displayname.is_empty() {
+            state.add_error_on_field(CompleteProfileFormField::Displayname, FieldError::Required);
+        }
+
+        if missing.terms_uri.is_some() && form.accept_terms.is_none() {
+            state.add_error_on_field(CompleteProfileFormField::AcceptTerms, FieldError::Required);
+        }
+
+        state
+    };
+
+    if !state.is_valid() {
+        let ctx = CompleteProfileContext::new(
+            missing.verified_email,
+            missing.displayname,
+            missing.terms_uri,
+        )
+        .with_form_state(state);
+        let content = render(
+            locale, ctx, query, csrf_token, session, &mut repo, &templates,
+        )
+        .await?;
+
+        return Ok((cookie_jar, Html(content)).into_response());
+    }
+
+    if let Some(terms_uri) = missing.terms_uri {
+        repo.user()
+            .accept_terms(&mut rng, &clock, &session.user, terms_uri)
+            .await?;
+    }
+
+    if missing.displayname {
+        // The display name is set in the background, so that users aren't
+        // stuck on this page if the homeserver is briefly unavailable
+        repo.job()
+            .schedule_job(SetDisplayNameJob::new(
+                &session.user,
+                Some(displayname.to_owned()),
+            ))
+            .await?;
+
+        // The user picked it themselves, so it shouldn't be overwritten on
+        // their next login
+        repo.user()
+            .mark_profile_attribute_modified(&session.user, ProfileAttribute::Displayname)
+            .await?;
+    }
+
+    // The email address has to be verified before carrying on, so the user is
+    // sent to the verification page, which then resumes the post auth action
+    let next = if missing.verified_email {
+        let existing_user_email = repo.user_email().find(&session.user, email).await?;
+        let user_email = if let Some(user_email) = existing_user_email {
+            user_email
+        } else {
+            repo.user_email()
+                .add(&mut rng, &clock, &session.user, email.to_owned())
+                .await?
+        };
+
+        send_verification_email(
+            &mut rng,
+            &clock,
+            &mut repo,
+            &site_config.email_rate_limits,
+            &user_email,
+            &locale,
+        )
+        .await?;
+
+        let next =
+            mas_router::AccountVerifyEmail::new(user_email.id).and_maybe(query.post_auth_action);
+        url_builder.redirect(&next)
+    } else {
+        query.go_next(&url_builder)
+    };
+
+    repo.save().await?;
+
+    activity_tracker
+        .record_browser_session(&clock, &session)
+        .await;
+
+    Ok((cookie_jar, next).into_response())
+}
+
+async fn render(
+    locale: DataLocale,
+    ctx: CompleteProfileContext,
+    action: OptionalPostAuthAction,
+    csrf_token: CsrfToken,
+    session: BrowserSession,
+    repo: &mut impl RepositoryAccess,
+    templates: &Templates,
+) -> Result<String, FancyError> {
+    let next = action.load_context(repo).await?;
+    let ctx = if let Some(next) = next {
+        ctx.with_post_action(next)
+    } else {
+        ctx
+    };
+    let ctx = ctx
+        .with_session(session)
+        .with_csrf(csrf_token.form_value())
+        .with_language(locale);
+
+    let content = templates.render_complete_profile(&ctx)?;
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{header::LOCATION, Request, StatusCode};
+    use mas_data_model::User;
+    use mas_matrix::{HomeserverConnection, ProvisionRequest};
+    use mas_router::Route;
+    use mas_storage::{user::UserRepository, RepositoryAccess};
+    use sqlx::PgPool;
+    use url::Url;
+
+    use crate::{
+        site_config::AccountRequirements,
+        test_utils::{init_tracing, CookieHelper, RequestBuilderExt, ResponseExt, TestState},
+    };
+
+    /// Create a user provisioned on the homeserver without a display name,
+    /// and log them in
+    async fn setup(state: &TestState, cookies: &CookieHelper) -> User {
+        let mut rng = state.rng();
+        let mut repo = state.repository().await.unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "john".to_owned())
+            .await
+            .unwrap();
+        let session = repo
+            .browser_session()
+            .add(&mut rng, &state.clock, &user, None)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let mxid = state.homeserver_connection.mxid(&user.username);
+        state
+            .homeserver_connection
+            .provision_user(&ProvisionRequest::new(mxid, user.sub.clone()))
+            .await
+            .unwrap();
+
+        cookies.set_session(state, &session).await;
+
+        user
+    }
+
+    fn terms_uri() -> Url {
+        Url::parse("https://example.com/tos").unwrap()
+    }
+
+    fn require_displayname_and_terms(state: &mut TestState) {
+        state.site_config.account_requirements = AccountRequirements {
+            verified_email: false,
+            displayname: true,
+            terms_uri: Some(terms_uri()),
+        };
+    }
+
+    /// Render the form, and return the CSRF token from it
+    async fn get_csrf_token(state: &TestState, cookies: &CookieHelper) -> String {
+        let request =
+            Request::get(&*mas_router::CompleteProfile::default().path_and_query()).empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        assert!(response.body().contains("name=\"displayname\""));
+        assert!(response.body().contains("name=\"accept_terms\""));
+        assert!(!response.body().contains("name=\"email\""));
+
+        response
+            .body()
+            .split("name=\"csrf\" value=\"")
+            .nth(1)
+            .unwrap()
+            .split('\"')
+            .next()
+            .unwrap()
+            .to_owned()
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_requires_session(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        let request =
+            Request::get(&*mas_router::CompleteProfile::default().path_and_query()).empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        response.assert_header_value(LOCATION, "/login");
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_nothing_to_complete(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let cookies = CookieHelper::new();
+        setup(&state, &cookies).await;
+
+        // Nothing is required by default, so users carry on right away
+        let request =
+            Request::get(&*mas_router::CompleteProfile::default().path_and_query()).empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_missing_fields(pool: PgPool) {
+        init_tracing();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        require_displayname_and_terms(&mut state);
+        let cookies = CookieHelper::new();
+        let user = setup(&state, &cookies).await;
+
+        let csrf_token = get_csrf_token(&state, &cookies).await;
+
+        // The form is shown again if a required field is missing
+        let request = Request::post(&*mas_router::CompleteProfile::default().path_and_query())
+            .form(serde_json::json!({
+                "csrf": csrf_token,
+                "displayname": "  ",
+            }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        assert!(response.body().contains("name=\"displayname\""));
+
+        let mut repo = state.repository().await.unwrap();
+        assert!(!repo
+            .user()
+            .has_accepted_terms(&user, &terms_uri())
+            .await
+            .unwrap());
+        repo.cancel().await.unwrap();
+
+        let jobs: Vec<String> = sqlx::query_scalar(
+            "SELECT job::text FROM apalis.jobs WHERE job_type = 'set-display-name'",
+        )
+        .fetch_all(&state.pool)
+        .await
+        .unwrap();
+        assert!(jobs.is_empty());
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_complete(pool: PgPool) {
+        init_tracing();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        require_displayname_and_terms(&mut state);
+        let cookies = CookieHelper::new();
+        let user = setup(&state, &cookies).await;
+
+        let csrf_token = get_csrf_token(&state, &cookies).await;
+
+        let request = Request::post(&*mas_router::CompleteProfile::default().path_and_query())
+            .form(serde_json::json!({
+                "csrf": csrf_token,
+                "displayname": " John ",
+                "accept_terms": "true",
+            }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        let mut repo = state.repository().await.unwrap();
+        assert!(repo
+            .user()
+            .has_accepted_terms(&user, &terms_uri())
+            .await
+            .unwrap());
+        repo.cancel().await.unwrap();
+
+        // The display name is set by a job, not while handling the request
+        let jobs: Vec<String> = sqlx::query_scalar(
+            "SELECT job::text FROM apalis.jobs WHERE job_type = 'set-display-name'",
+        )
+        .fetch_all(&state.pool)
+        .await
+        .unwrap();
+        assert_eq!(jobs.len(), 1);
+        let job: serde_json::Value = serde_json::from_str(&jobs[0]).unwrap();
+        assert_eq!(job["user_id"], user.id.to_string());
+        assert_eq!(job["display_name"], "John");
+
+        let mxid = state.homeserver_connection.mxid(&user.username);
+        let matrix_user = state.homeserver_connection.query_user(&mxid).await.unwrap();
+        assert_eq!(matrix_user.displayname, None);
+    }
+}
@@ -0,0 +1,388 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use axum::{
+    extract::{Form, Path, State},
+    response::{Html, IntoResponse, Response},
+};
+use mas_axum_utils::{
+    cookies::CookieJar,
+    csrf::{CsrfExt, ProtectedForm},
+    FancyError,
+};
+use mas_data_model::{UserEmail, UserEmailChange};
+use mas_storage::{
+    job::{JobRepositoryExt, ProvisionUserJob},
+    user::complete_email_change,
+    BoxClock, BoxRepository, BoxRng, Clock, RepositoryAccess,
+};
+use mas_templates::{EmailChangeConfirmContext, TemplateContext, Templates};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::PreferredLanguage;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", tag = "action")]
+pub(crate) enum FormData {
+    Confirm,
+    Cancel,
+}
+
+/// Lookup the change behind a confirmation token, along with the current and
+/// the new addresses, if the change is still waiting for a confirmation
+async fn load_confirmable_change(
+    clock: &dyn Clock,
+    repo: &mut BoxRepository,
+    token: &str,
+) -> Result<Option<(UserEmailChange, UserEmail, UserEmail)>, FancyError> {
+    let Some(change) = repo.user_email().find_change_by_revert_token(token).await? else {
+        return Ok(None);
+    };
+
+    if !change.is_awaiting_confirmation(clock.now()) {
+        return Ok(None);
+    }
+
+    let Some(old_email) = repo.user_email().lookup(change.old_user_email_id).await? else {
+        return Ok(None);
+    };
+
+    let Some(new_email) = repo.user_email().lookup(change.new_user_email_id).await? else {
+        return Ok(None);
+    };
+
+    Ok(Some((change, old_email, new_email)))
+}
+
+/// Shows the page where users can confirm or cancel a change of their primary
+/// email address, from the link sent to their current address. No session is
+/// needed, the token in the link is enough.
+#[tracing::instrument(name = "handlers.views.email_change_confirm.get", skip_all, err)]
+pub(crate) async fn get(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    PreferredLanguage(locale): PreferredLanguage,
+    State(templates): State<Templates>,
+    mut repo: BoxRepository,
+    cookie_jar: CookieJar,
+    Path(token): Path<String>,
+) -> Result<Response, FancyError> {
+    let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
+
+    let ctx = match load_confirmable_change(&clock, &mut repo, &token).await? {
+        Some((_change, old_email, new_email)) => EmailChangeConfirmContext::Form {
+            old_email: old_email.email,
+            new_email: new_email.email,
+        },
+        None => EmailChangeConfirmContext::Invalid,
+    };
+
+    let ctx = ctx.with_csrf(csrf_token.form_value()).with_language(locale);
+
+    let content = templates.render_email_change_confirm(&ctx)?;
+
+    Ok((cookie_jar, Html(content)).into_response())
+}
+
+/// Either confirms the change, making the new address primary, or cancels it,
+/// removing the new address from the account.
+#[tracing::instrument(name = "handlers.views.email_change_confirm.post", skip_all, err)]
+pub(crate) async fn post(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    PreferredLanguage(locale): PreferredLanguage,
+    State(templates): State<Templates>,
+    mut repo: BoxRepository,
+    cookie_jar: CookieJar,
+    Path(token): Path<String>,
+    Form(form): Form<ProtectedForm<FormData>>,
+) -> Result<Response, FancyError> {
+    let form = cookie_jar.verify_form(&clock, form)?;
+    let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
+
+    let Some((change, old_email, new_email)) =
+        load_confirmable_change(&clock, &mut repo, &token).await?
+    else {
+        let ctx = EmailChangeConfirmContext::Invalid
+            .with_csrf(csrf_token.form_value())
+            .with_language(locale);
+        let content = templates.render_email_change_confirm(&ctx)?;
+        return Ok((cookie_jar, Html(content)).into_response());
+    };
+
+    let user = repo
+        .user()
+        .lookup(change.user_id)
+        .await?
+        .ok_or(anyhow::anyhow!("user not found"))?;
+
+    let ctx = match form {
+        FormData::Confirm => {
+            let language = locale.to_string();
+            let change =
+                complete_email_change(&mut repo, &clock, change, &new_email, Some(&language))
+                    .await?;
+
+            repo.job()
+                .schedule_job(ProvisionUserJob::new(&user))
+                .await?;
+
+            repo.save().await?;
+
+            info!(
+                user.id = %user.id,
+                user.username = %user.username,
+                user_email_change.id = %change.id,
+                user_email.id = %new_email.id,
+                "User confirmed a change of their primary email address"
+            );
+
+            EmailChangeConfirmContext::Confirmed {
+                new_email: new_email.email,
+            }
+        }
+
+        FormData::Cancel => {
+            let change = repo.user_email().revert_change(&clock, change).await?;
+
+            // This also removes the change itself, which invalidates the link
+            repo.user_email().remove(new_email).await?;
+
+            repo.save().await?;
+
+            info!(
+                user.id = %user.id,
+                user.username = %user.username,
+                user_email_change.id = %change.id,
+                user_email.id = %old_email.id,
+                "User cancelled a change of their primary email address"
+            );
+
+            EmailChangeConfirmContext::Cancelled {
+                old_email: old_email.email,
+            }
+        }
+    };
+
+    let ctx = ctx.with_csrf(csrf_token.form_value()).with_language(locale);
+
+    let content = templates.render_email_change_confirm(&ctx)?;
+
+    Ok((cookie_jar, Html(content)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Request, StatusCode};
+    use mas_data_model::{User, UserEmail, UserEmailChange};
+    use mas_router::Route;
+    use mas_storage::{
+        user::{continue_email_change, UserEmailRepository, UserRepository},
+        Clock, RepositoryAccess,
+    };
+    use sqlx::PgPool;
+
+    use crate::test_utils::{
+        init_tracing, CookieHelper, RequestBuilderExt, ResponseExt, TestState,
+    };
+
+    /// Create a user with a primary address, and a change to a new, verified
+    /// address waiting for a confirmation
+    async fn setup(state: &TestState) -> (User, UserEmail, UserEmail) {
+        let mut rng = state.rng();
+        let mut repo = state.repository().await.unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "john".to_owned())
+            .await
+            .unwrap();
+
+        let old_email = repo
+            .user_email()
+            .add(&mut rng, &state.clock, &user, "old@example.com".to_owned())
+            .await
+            .unwrap();
+        let old_email = repo
+            .user_email()
+            .mark_as_verified(&state.clock, old_email)
+            .await
+            .unwrap();
+        repo.user_email().set_as_primary(&old_email).await.unwrap();
+
+        let new_email = repo
+            .user_email()
+            .add(&mut rng, &state.clock, &user, "new@example.com".to_owned())
+            .await
+            .unwrap();
+        let new_email = repo
+            .user_email()
+            .mark_as_verified(&state.clock, new_email)
+            .await
+            .unwrap();
+
+        repo.user_email()
+            .add_change(
+                &mut rng,
+                &state.clock,
+                &old_email,
+                &new_email,
+                "sometoken".to_owned(),
+            )
+            .await
+            .unwrap();
+
+        let change = continue_email_change(&mut repo, &state.clock, &new_email, false, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(change.is_awaiting_confirmation(state.clock.now()));
+
+        repo.save().await.unwrap();
+
+        (user, old_email, new_email)
+    }
+
+    /// Render the confirmation page, and return the CSRF token from the form
+    async fn get_csrf_token(state: &TestState, cookies: &CookieHelper) -> String {
+        let request = Request::get(&*path().path_and_query()).empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        assert!(response.body().contains("new@example.com"));
+
+        response
+            .body()
+            .split("name=\"csrf\" value=\"")
+            .nth(1)
+            .unwrap()
+            .split('\"')
+            .next()
+            .unwrap()
+            .to_owned()
+    }
+
+    fn path() -> mas_router::EmailChangeConfirm {
+        mas_router::EmailChangeConfirm::new("sometoken".to_owned())
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_confirm(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let cookies = CookieHelper::new();
+        let (user, old_email, new_email) = setup(&state).await;
+
+        let csrf_token = get_csrf_token(&state, &cookies).await;
+
+        let request = Request::post(&*path().path_and_query()).form(serde_json::json!({
+            "csrf": csrf_token,
+            "action": "confirm",
+        }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+
+        // The new address is now primary, and the old one can revert the change
+        let mut repo = state.repository().await.unwrap();
+        let primary = repo.user_email().get_primary(&user).await.unwrap().unwrap();
+        assert_eq!(primary.id, new_email.id);
+
+        let change = repo
+            .user_email()
+            .find_change_by_revert_token("sometoken")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(change.completed_at.is_some());
+        assert!(change.can_revert(state.clock.now()));
+        assert!(repo
+            .user_email()
+            .lookup(old_email.id)
+            .await
+            .unwrap()
+            .is_some());
+        repo.cancel().await.unwrap();
+
+        // The link can't be used a second time
+        let request = Request::get(&*path().path_and_query()).empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        assert!(!response.body().contains("new@example.com"));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_cancel(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let cookies = CookieHelper::new();
+        let (user, old_email, new_email) = setup(&state).await;
+
+        let csrf_token = get_csrf_token(&state, &cookies).await;
+
+        let request = Request::post(&*path().path_and_query()).form(serde_json::json!({
+            "csrf": csrf_token,
+            "action": "cancel",
+        }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+
+        // The old address stays primary, and the new one is gone, along with
+        // the change
+        let mut repo = state.repository().await.unwrap();
+        let primary = repo.user_email().get_primary(&user).await.unwrap().unwrap();
+        assert_eq!(primary.id, old_email.id);
+        assert!(repo
+            .user_email()
+            .lookup(new_email.id)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(repo
+            .user_email()
+            .find_change_by_revert_token("sometoken")
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_expired(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let cookies = CookieHelper::new();
+        let (user, old_email, _new_email) = setup(&state).await;
+
+        // Once the grace period is over, the change can't be confirmed anymore
+        state
+            .clock
+            .advance(UserEmailChange::grace_period() + chrono::Duration::minutes(1));
+
+        let request = Request::get(&*path().path_and_query()).empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        assert!(!response.body().contains("new@example.com"));
+
+        let mut repo = state.repository().await.unwrap();
+        let primary = repo.user_email().get_primary(&user).await.unwrap().unwrap();
+        assert_eq!(primary.id, old_email.id);
+    }
+}
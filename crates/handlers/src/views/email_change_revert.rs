@@ -0,0 +1,155 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use axum::{
+    extract::{Form, Path, State},
+    response::{Html, IntoResponse, Response},
+};
+use mas_axum_utils::{
+    cookies::CookieJar,
+    csrf::{CsrfExt, ProtectedForm},
+    FancyError,
+};
+use mas_data_model::{UserEmail, UserEmailChange};
+use mas_storage::{
+    job::{JobRepositoryExt, ProvisionUserJob},
+    BoxClock, BoxRepository, BoxRng, Clock, RepositoryAccess,
+};
+use mas_templates::{EmailChangeRevertContext, TemplateContext, Templates};
+use tracing::info;
+
+use crate::PreferredLanguage;
+
+/// Lookup the change behind a revert token, along with the address which was
+/// replaced, if the change can still be reverted
+async fn load_revertable_change(
+    clock: &dyn Clock,
+    repo: &mut BoxRepository,
+    token: &str,
+) -> Result<Option<(UserEmailChange, UserEmail)>, FancyError> {
+    let Some(change) = repo.user_email().find_change_by_revert_token(token).await? else {
+        return Ok(None);
+    };
+
+    if !change.can_revert(clock.now()) {
+        return Ok(None);
+    }
+
+    let Some(old_email) = repo.user_email().lookup(change.old_user_email_id).await? else {
+        return Ok(None);
+    };
+
+    Ok(Some((change, old_email)))
+}
+
+/// Shows the page where users can revert a change of their primary email
+/// address, from the link sent to their old address. No session is needed, the
+/// token in the link is enough.
+#[tracing::instrument(name = "handlers.views.email_change_revert.get", skip_all, err)]
+pub(crate) async fn get(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    PreferredLanguage(locale): PreferredLanguage,
+    State(templates): State<Templates>,
+    mut repo: BoxRepository,
+    cookie_jar: CookieJar,
+    Path(token): Path<String>,
+) -> Result<Response, FancyError> {
+    let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
+
+    let ctx = match load_revertable_change(&clock, &mut repo, &token).await? {
+        Some((change, old_email)) => {
+            let new_email = repo
+                .user_email()
+                .lookup(change.new_user_email_id)
+                .await?
+                .map(|e| e.email)
+                .unwrap_or_default();
+
+            EmailChangeRevertContext::Form {
+                old_email: old_email.email,
+                new_email,
+            }
+        }
+        None => EmailChangeRevertContext::Invalid,
+    };
+
+    let ctx = ctx.with_csrf(csrf_token.form_value()).with_language(locale);
+
+    let content = templates.render_email_change_revert(&ctx)?;
+
+    Ok((cookie_jar, Html(content)).into_response())
+}
+
+/// Reverts the change: the old address becomes primary again and the new one
+/// is removed from the account.
+#[tracing::instrument(name = "handlers.views.email_change_revert.post", skip_all, err)]
+pub(crate) async fn post(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    PreferredLanguage(locale): PreferredLanguage,
+    State(templates): State<Templates>,
+    mut repo: BoxRepository,
+    cookie_jar: CookieJar,
+    Path(token): Path<String>,
+    Form(form): Form<ProtectedForm<()>>,
+) -> Result<Response, FancyError> {
+    cookie_jar.verify_form(&clock, form)?;
+    let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
+
+    let ctx = if let Some((change, old_email)) =
+        load_revertable_change(&clock, &mut repo, &token).await?
+    {
+        let user = repo
+            .user()
+            .lookup(change.user_id)
+            .await?
+            .ok_or(anyhow::anyhow!("user not found"))?;
+
+        repo.user_email().set_as_primary(&old_email).await?;
+        let change = repo.user_email().revert_change(&clock, change).await?;
+
+        // This also removes the change itself, which invalidates the link
+        let new_email = repo.user_email().lookup(change.new_user_email_id).await?;
+        if let Some(new_email) = new_email {
+            repo.user_email().remove(new_email).await?;
+        }
+
+        repo.job()
+            .schedule_job(ProvisionUserJob::new(&user))
+            .await?;
+
+        repo.save().await?;
+
+        info!(
+            user.id = %user.id,
+            user.username = %user.username,
+            user_email_change.id = %change.id,
+            user_email.id = %old_email.id,
+            "User reverted a change of their primary email address"
+        );
+
+        EmailChangeRevertContext::Reverted {
+            old_email: old_email.email,
+        }
+    } else {
+        EmailChangeRevertContext::Invalid
+    };
+
+    let ctx = ctx.with_csrf(csrf_token.form_value()).with_language(locale);
+
+    let content = templates.render_email_change_revert(&ctx)?;
+
+    Ok((cookie_jar, Html(content)).into_response())
+}
@@ -0,0 +1,314 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use axum::{
+    extract::{Form, State},
+    response::{Html, IntoResponse, Response},
+    TypedHeader,
+};
+use headers::UserAgent;
+use hyper::StatusCode;
+use mas_axum_utils::{
+    cookies::CookieJar,
+    csrf::{CsrfExt, ProtectedForm},
+    FancyError, SessionInfoExt,
+};
+use mas_data_model::BrowserSession;
+use mas_router::UrlBuilder;
+use mas_storage::{
+    user::{BrowserSessionRepository, UserImpersonationRepository, UserRepository},
+    BoxClock, BoxRepository, BoxRng,
+};
+use mas_templates::{
+    FieldError, ImpersonateContext, ImpersonateFormField, TemplateContext, Templates, ToFormState,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{BoundActivityTracker, PreferredLanguage, SiteConfig};
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct ImpersonateForm {
+    username: String,
+    reason: String,
+}
+
+impl ToFormState for ImpersonateForm {
+    type Field = ImpersonateFormField;
+}
+
+/// Only designated administrators can impersonate users, and an impersonation
+/// session can't be used to start another one
+fn can_impersonate(session: &BrowserSession) -> bool {
+    session.user.can_request_admin && !session.is_impersonation()
+}
+
+#[tracing::instrument(name = "handlers.views.impersonate.get", skip_all, err)]
+pub(crate) async fn get(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    PreferredLanguage(locale): PreferredLanguage,
+    State(templates): State<Templates>,
+    State(url_builder): State<UrlBuilder>,
+    activity_tracker: BoundActivityTracker,
+    mut repo: BoxRepository,
+    cookie_jar: CookieJar,
+) -> Result<Response, FancyError> {
+    let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
+    let (session_info, cookie_jar) = cookie_jar.session_info();
+
+    let maybe_session = session_info.load_session(&clock, &mut repo).await?;
+
+    let Some(session) = maybe_session else {
+        let login = mas_router::Login::default();
+        return Ok((cookie_jar, url_builder.redirect(&login)).into_response());
+    };
+
+    if !can_impersonate(&session) {
+        return Ok((cookie_jar, StatusCode::FORBIDDEN).into_response());
+    }
+
+    activity_tracker
+        .record_browser_session(&clock, &session)
+        .await;
+
+    let ctx = ImpersonateContext::new()
+        .with_session(session)
+        .with_csrf(csrf_token.form_value())
+        .with_language(locale);
+
+    let content = templates.render_impersonate(&ctx)?;
+
+    Ok((cookie_jar, Html(content)).into_response())
+}
+
+#[tracing::instrument(name = "handlers.views.impersonate.post", skip_all, err)]
+pub(crate) async fn post(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    PreferredLanguage(locale): PreferredLanguage,
+    State(templates): State<Templates>,
+    State(url_builder): State<UrlBuilder>,
+    State(site_config): State<SiteConfig>,
+    activity_tracker: BoundActivityTracker,
+    mut repo: BoxRepository,
+    cookie_jar: CookieJar,
+    user_agent: Option<TypedHeader<UserAgent>>,
+    Form(form): Form<ProtectedForm<ImpersonateForm>>,
+) -> Result<Response, FancyError> {
+    let user_agent = user_agent.map(|ua| ua.as_str().to_owned());
+    let form = cookie_jar.verify_form(&clock, form)?;
+    let (session_info, cookie_jar) = cookie_jar.session_info();
+
+    let maybe_session = session_info.load_session(&clock, &mut repo).await?;
+
+    let Some(session) = maybe_session else {
+        let login = mas_router::Login::default();
+        return Ok((cookie_jar, url_builder.redirect(&login)).into_response());
+    };
+
+    if !can_impersonate(&session) {
+        return Ok((cookie_jar, StatusCode::FORBIDDEN).into_response());
+    }
+
+    activity_tracker
+        .record_browser_session(&clock, &session)
+        .await;
+
+    let mut state = form.to_form_state();
+
+    if form.reason.trim().is_empty() {
+        state.add_error_on_field(ImpersonateFormField::Reason, FieldError::Required);
+    }
+
+    let user = if form.username.is_empty() {
+        state.add_error_on_field(ImpersonateFormField::Username, FieldError::Required);
+        None
+    } else {
+        let user = repo
+            .user()
+            .find_by_username(&form.username)
+            .await?
+            // Locked users can't be impersonated, as their sessions would be invalid anyway
+            .filter(mas_data_model::User::is_valid);
+
+        if user.is_none() {
+            state.add_error_on_field(ImpersonateFormField::Username, FieldError::Invalid);
+        }
+
+        user
+    };
+
+    let (Some(user), true) = (user, state.is_valid()) else {
+        let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
+        let ctx = ImpersonateContext::with_form_state(state)
+            .with_session(session)
+            .with_csrf(csrf_token.form_value())
+            .with_language(locale);
+
+        let content = templates.render_impersonate(&ctx)?;
+
+        return Ok((cookie_jar, Html(content)).into_response());
+    };
+
+    let impersonation_session = repo
+        .browser_session()
+        .add_impersonation(
+            &mut rng,
+            &clock,
+            &user,
+            &session.user,
+            site_config.impersonation_ttl,
+            user_agent,
+        )
+        .await?;
+
+    let impersonation = repo
+        .user_impersonation()
+        .add(
+            &mut rng,
+            &clock,
+            &session.user,
+            &impersonation_session,
+            form.reason.trim().to_owned(),
+        )
+        .await?;
+
+    repo.save().await?;
+
+    info!(
+        impersonator.id = %session.user.id,
+        impersonator.username = %session.user.username,
+        user.id = %user.id,
+        user.username = %user.username,
+        user_session.id = %impersonation_session.id,
+        user_impersonation.id = %impersonation.id,
+        reason = %impersonation.reason,
+        "Administrator started impersonating a user"
+    );
+
+    let cookie_jar = cookie_jar.set_session(&impersonation_session);
+    let reply = url_builder.redirect(&mas_router::Account::default());
+
+    Ok((cookie_jar, reply).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Request, StatusCode};
+    use mas_router::SimpleRoute;
+    use mas_storage::{
+        user::{BrowserSessionRepository, UserImpersonationRepository, UserRepository},
+        Clock, RepositoryAccess,
+    };
+    use sqlx::PgPool;
+
+    use crate::test_utils::{init_tracing, CookieHelper, RequestBuilderExt, ResponseExt, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_impersonation_audit(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let mut rng = state.rng();
+        let cookies = CookieHelper::new();
+
+        let mut repo = state.repository().await.unwrap();
+        let admin = repo
+            .user()
+            .add(&mut rng, &state.clock, "admin".to_owned())
+            .await
+            .unwrap();
+        let admin = repo
+            .user()
+            .set_can_request_admin(admin, true)
+            .await
+            .unwrap();
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "john".to_owned())
+            .await
+            .unwrap();
+        let session = repo
+            .browser_session()
+            .add(&mut rng, &state.clock, &admin, None)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        cookies.set_session(&state, &session).await;
+
+        // Render the impersonation page to get a CSRF token
+        let request = Request::get(mas_router::Impersonate::PATH).empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        let csrf_token = response
+            .body()
+            .split("name=\"csrf\" value=\"")
+            .nth(1)
+            .unwrap()
+            .split('\"')
+            .next()
+            .unwrap()
+            .to_owned();
+
+        let request = Request::post(mas_router::Impersonate::PATH).form(serde_json::json!({
+            "csrf": csrf_token,
+            "username": "john",
+            "reason": "Support ticket",
+        }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        // The impersonation is recorded, along with who started it and why
+        let mut repo = state.repository().await.unwrap();
+        let impersonations = repo
+            .user_impersonation()
+            .list_for_user(&user)
+            .await
+            .unwrap();
+        assert_eq!(impersonations.len(), 1);
+        let impersonation = &impersonations[0];
+        assert_eq!(impersonation.impersonator_user_id, admin.id);
+        assert_eq!(impersonation.reason, "Support ticket");
+        assert_eq!(impersonation.started_at, state.clock.now());
+        assert_eq!(
+            impersonation.expires_at,
+            state.clock.now() + state.site_config.impersonation_ttl
+        );
+        assert!(impersonation.ended_at.is_none());
+        repo.cancel().await.unwrap();
+
+        // Logging out of the impersonation session records when it ended
+        state.clock.advance(chrono::Duration::minutes(5));
+        let request = Request::post(mas_router::Logout::PATH).form(serde_json::json!({
+            "csrf": csrf_token,
+        }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        let mut repo = state.repository().await.unwrap();
+        let impersonations = repo
+            .user_impersonation()
+            .list_for_user(&user)
+            .await
+            .unwrap();
+        assert_eq!(impersonations[0].ended_at, Some(state.clock.now()));
+    }
+}
@@ -0,0 +1,156 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use axum::{
+    extract::{Form, State},
+    response::{IntoResponse, Redirect},
+};
+use hyper::{header::REFERER, HeaderMap};
+use mas_axum_utils::{cookies::CookieJar, FancyError, SessionInfoExt};
+use mas_i18n::DataLocale;
+use mas_router::UrlBuilder;
+use mas_storage::{user::UserRepository, BoxClock, BoxRepository};
+use mas_templates::Templates;
+use serde::Deserialize;
+use url::Url;
+
+use crate::preferred_language::LANGUAGE_COOKIE;
+
+#[derive(Deserialize)]
+pub(crate) struct LanguageForm {
+    language: String,
+}
+
+/// The page to go back to after changing the language: the path of the
+/// page the form was submitted from, if any.
+///
+/// Only the path and query are kept, so that this can't be used to redirect
+/// to another site.
+fn back_to(headers: &HeaderMap) -> Option<String> {
+    let referer = headers.get(REFERER)?.to_str().ok()?;
+    let referer = Url::parse(referer).ok()?;
+    let path = referer.path();
+
+    // `//example.com` would be a protocol-relative URL
+    if path.starts_with("//") {
+        return None;
+    }
+
+    Some(match referer.query() {
+        Some(query) => format!("{path}?{query}"),
+        None => path.to_owned(),
+    })
+}
+
+/// Change the language of the interface.
+///
+/// The language is saved in a cookie, and on the account of the user if they
+/// are logged in, so that the emails sent to them use it too.
+///
+/// This is not CSRF-protected, as the footer is rendered on pages which don't
+/// have a CSRF token. The session cookie is not sent on cross-site POST
+/// requests, so another site can at most change the language of the browser.
+#[tracing::instrument(name = "handlers.views.language.post", skip_all, err)]
+pub(crate) async fn post(
+    clock: BoxClock,
+    mut repo: BoxRepository,
+    State(templates): State<Templates>,
+    State(url_builder): State<UrlBuilder>,
+    cookie_jar: CookieJar,
+    headers: HeaderMap,
+    Form(form): Form<LanguageForm>,
+) -> Result<impl IntoResponse, FancyError> {
+    let destination = back_to(&headers).map_or_else(
+        || url_builder.redirect(&mas_router::Index),
+        |path| Redirect::to(&path),
+    );
+
+    // Unknown languages are ignored, the page is rendered again as it was
+    let Some(locale) = form
+        .language
+        .parse::<DataLocale>()
+        .ok()
+        .filter(|locale| templates.translator().has_locale(locale))
+    else {
+        return Ok((cookie_jar, destination));
+    };
+
+    let cookie_jar = cookie_jar.save(LANGUAGE_COOKIE, &locale.to_string(), true);
+
+    let (session_info, cookie_jar) = cookie_jar.session_info();
+    if let Some(session) = session_info.load_session(&clock, &mut repo).await? {
+        repo.user()
+            .set_locale(session.user, Some(locale.to_string()))
+            .await?;
+    }
+
+    repo.save().await?;
+
+    Ok((cookie_jar, destination))
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{header::REFERER, Request, StatusCode};
+    use sqlx::PgPool;
+
+    use crate::test_utils::{
+        init_tracing, CookieHelper, RequestBuilderExt, ResponseExt, TestState,
+    };
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_change_language(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let cookies = CookieHelper::new();
+
+        let request = Request::get("/").empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        assert!(response.body().contains("<html lang=\"en\" dir=\"ltr\">"));
+
+        // Pick French, and go back to the page the form was submitted from
+        let request = Request::post("/language")
+            .header(REFERER, "http://localhost/account/?action=profile")
+            .form(serde_json::json!({ "language": "fr" }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::SEE_OTHER);
+        response.assert_header_value(hyper::header::LOCATION, "/account/?action=profile");
+
+        // The language is picked over the Accept-Language header
+        let request = Request::get("/")
+            .header(hyper::header::ACCEPT_LANGUAGE, "en")
+            .empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        assert!(response.body().contains("<html lang=\"fr\" dir=\"ltr\">"));
+
+        // Unknown languages are ignored
+        let request = Request::post("/language").form(serde_json::json!({ "language": "xx" }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::SEE_OTHER);
+        response.assert_header_value(hyper::header::LOCATION, "/");
+
+        let request = Request::get("/").empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        assert!(response.body().contains("<html lang=\"fr\" dir=\"ltr\">"));
+    }
+}
@@ -24,13 +24,13 @@ use mas_axum_utils::{
     csrf::{CsrfExt, CsrfToken, ProtectedForm},
     FancyError, SessionInfoExt,
 };
-use mas_data_model::BrowserSession;
+use mas_data_model::{BrowserSession, UpstreamOAuthProvider};
 use mas_i18n::DataLocale;
 use mas_router::{UpstreamOAuth2Authorize, UrlBuilder};
 use mas_storage::{
     upstream_oauth2::UpstreamOAuthProviderRepository,
     user::{BrowserSessionRepository, UserPasswordRepository, UserRepository},
-    BoxClock, BoxRepository, BoxRng, Clock, RepositoryAccess,
+    BoxClock, BoxRepository, BoxRng, Clock, RepositoryAccess, RepositoryError,
 };
 use mas_templates::{
     FieldError, FormError, LoginContext, LoginFormField, TemplateContext, Templates, ToFormState,
@@ -40,7 +40,10 @@ use serde::{Deserialize, Serialize};
 use zeroize::Zeroizing;
 
 use super::shared::OptionalPostAuthAction;
-use crate::{passwords::PasswordManager, BoundActivityTracker, PreferredLanguage};
+use crate::{
+    ip_filter::Action, passwords::PasswordManager, BoundActivityTracker, IpFilter, Limiter,
+    PreferredLanguage,
+};
 
 #[derive(Debug, Deserialize, Serialize)]
 pub(crate) struct LoginForm {
@@ -68,7 +71,7 @@ pub(crate) async fn get(
     let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
     let (session_info, cookie_jar) = cookie_jar.session_info();
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info.load_session(&clock, &mut repo).await?;
 
     if let Some(session) = maybe_session {
         activity_tracker
@@ -79,7 +82,7 @@ pub(crate) async fn get(
         return Ok((cookie_jar, reply).into_response());
     };
 
-    let providers = repo.upstream_oauth_provider().all().await?;
+    let providers = login_providers(&mut repo).await?;
 
     // If password-based login is disabled, and there is only one upstream provider,
     // we can directly start an authorization flow
@@ -119,6 +122,8 @@ pub(crate) async fn post(
     State(password_manager): State<PasswordManager>,
     State(templates): State<Templates>,
     State(url_builder): State<UrlBuilder>,
+    State(limiter): State<Limiter>,
+    State(ip_filter): State<IpFilter>,
     mut repo: BoxRepository,
     activity_tracker: BoundActivityTracker,
     Query(query): Query<OptionalPostAuthAction>,
@@ -152,7 +157,7 @@ pub(crate) async fn post(
     };
 
     if !state.is_valid() {
-        let providers = repo.upstream_oauth_provider().all().await?;
+        let providers = login_providers(&mut repo).await?;
         let content = render(
             locale,
             LoginContext::default()
@@ -168,6 +173,53 @@ pub(crate) async fn post(
         return Ok((cookie_jar, Html(content)).into_response());
     }
 
+    if ip_filter
+        .check(activity_tracker.ip(), Action::Login)
+        .await
+        .is_err()
+    {
+        let state = state.with_error_on_form(FormError::NetworkDenied);
+        let content = render(
+            locale,
+            LoginContext::default().with_form_state(state),
+            query,
+            csrf_token,
+            &mut repo,
+            &templates,
+        )
+        .await?;
+
+        return Ok((StatusCode::FORBIDDEN, cookie_jar, Html(content)).into_response());
+    }
+
+    if let Err(rate_limited) = limiter
+        .check_login(clock.now(), activity_tracker.ip(), &form.username)
+        .await
+    {
+        let state = state.with_error_on_form(FormError::RateLimitExceeded);
+        let content = render(
+            locale,
+            LoginContext::default().with_form_state(state),
+            query,
+            csrf_token,
+            &mut repo,
+            &templates,
+        )
+        .await?;
+
+        return Ok((
+            StatusCode::TOO_MANY_REQUESTS,
+            rate_limited,
+            cookie_jar,
+            Html(content),
+        )
+            .into_response());
+    }
+
+    limiter
+        .delay_login(clock.now(), activity_tracker.ip())
+        .await;
+
     match login(
         password_manager,
         &mut repo,
@@ -176,6 +228,7 @@ pub(crate) async fn post(
         &form.username,
         &form.password,
         user_agent,
+        &locale,
     )
     .await
     {
@@ -191,6 +244,12 @@ pub(crate) async fn post(
             Ok((cookie_jar, reply).into_response())
         }
         Err(e) => {
+            if matches!(e, FormError::InvalidCredentials) {
+                limiter
+                    .record_login_failure(clock.now(), activity_tracker.ip())
+                    .await;
+            }
+
             let state = state.with_error_on_form(e);
 
             let content = render(
@@ -217,6 +276,7 @@ async fn login(
     username: &str,
     password: &str,
     user_agent: Option<String>,
+    locale: &DataLocale,
 ) -> Result<BrowserSession, FormError> {
     // XXX: we're loosing the error context here
     // First, lookup the user
@@ -266,6 +326,17 @@ async fn login(
         user_password
     };
 
+    // Remember the language of the browser if the user has no preferred locale
+    // yet, so that emails are sent in that language
+    let user = if user.locale.is_none() {
+        repo.user()
+            .set_locale(user, Some(locale.to_string()))
+            .await
+            .map_err(|_| FormError::Internal)?
+    } else {
+        user
+    };
+
     // Start a new session
     let user_session = repo
         .browser_session()
@@ -282,6 +353,36 @@ async fn login(
     Ok(user_session)
 }
 
+/// The upstream providers offered as login options, in the order they are
+/// listed
+///
+/// Providers set to be hidden while unhealthy are left out if their last
+/// health check failed
+async fn login_providers(
+    repo: &mut BoxRepository,
+) -> Result<Vec<UpstreamOAuthProvider>, RepositoryError> {
+    let all_providers = repo.upstream_oauth_provider().all().await?;
+
+    let mut providers = Vec::with_capacity(all_providers.len());
+    for provider in all_providers {
+        if !provider.ui_options.show_on_login {
+            continue;
+        }
+
+        if provider.health_check.hide_when_unhealthy {
+            let health = repo.upstream_oauth_provider().health(&provider).await?;
+            if health.is_some_and(|health| !health.is_healthy()) {
+                continue;
+            }
+        }
+
+        providers.push(provider);
+    }
+
+    providers.sort_by_key(|provider| (provider.ui_options.sort_order, provider.id));
+    Ok(providers)
+}
+
 async fn render(
     locale: DataLocale,
     ctx: LoginContext,
@@ -304,11 +405,18 @@ async fn render(
 
 #[cfg(test)]
 mod test {
+    use std::num::NonZeroU32;
+
     use hyper::{
-        header::{CONTENT_TYPE, LOCATION},
+        header::{CONTENT_TYPE, LOCATION, RETRY_AFTER},
         Request, StatusCode,
     };
-    use mas_data_model::UpstreamOAuthProviderClaimsImports;
+    use mas_data_model::{
+        UpstreamOAuthProviderAuthorizationParams, UpstreamOAuthProviderClaimsImports,
+        UpstreamOAuthProviderEndpoints, UpstreamOAuthProviderHealthCheckSettings,
+        UpstreamOAuthProviderPkceMethod, UpstreamOAuthProviderProtocol,
+        UpstreamOAuthProviderSamlSettings, UpstreamOAuthProviderUiOptions,
+    };
     use mas_iana::oauth::OAuthClientAuthenticationMethod;
     use mas_router::Route;
     use mas_storage::{upstream_oauth2::UpstreamOAuthProviderRepository, RepositoryAccess};
@@ -320,6 +428,7 @@ mod test {
     use crate::{
         passwords::PasswordManager,
         test_utils::{init_tracing, CookieHelper, RequestBuilderExt, ResponseExt, TestState},
+        BucketConfig, Limiter, RateLimits,
     };
 
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
@@ -347,12 +456,22 @@ mod test {
                 &mut rng,
                 &state.clock,
                 "https://first.com/".into(),
+                None,
                 [OPENID].into_iter().collect(),
                 OAuthClientAuthenticationMethod::None,
                 None,
                 "first_client".into(),
                 None,
                 UpstreamOAuthProviderClaimsImports::default(),
+                UpstreamOAuthProviderPkceMethod::default(),
+                UpstreamOAuthProviderAuthorizationParams::default(),
+                false,
+                UpstreamOAuthProviderUiOptions::default(),
+                UpstreamOAuthProviderProtocol::Oidc,
+                UpstreamOAuthProviderEndpoints::default(),
+                UpstreamOAuthProviderSamlSettings::default(),
+                false,
+                UpstreamOAuthProviderHealthCheckSettings::default(),
             )
             .await
             .unwrap();
@@ -372,12 +491,26 @@ mod test {
                 &mut rng,
                 &state.clock,
                 "https://second.com/".into(),
+                None,
                 [OPENID].into_iter().collect(),
                 OAuthClientAuthenticationMethod::None,
                 None,
                 "second_client".into(),
                 None,
                 UpstreamOAuthProviderClaimsImports::default(),
+                UpstreamOAuthProviderPkceMethod::default(),
+                UpstreamOAuthProviderAuthorizationParams::default(),
+                false,
+                UpstreamOAuthProviderUiOptions {
+                    human_name: Some("Second Provider".to_owned()),
+                    sort_order: -1,
+                    ..UpstreamOAuthProviderUiOptions::default()
+                },
+                UpstreamOAuthProviderProtocol::Oidc,
+                UpstreamOAuthProviderEndpoints::default(),
+                UpstreamOAuthProviderSamlSettings::default(),
+                false,
+                UpstreamOAuthProviderHealthCheckSettings::default(),
             )
             .await
             .unwrap();
@@ -394,12 +527,132 @@ mod test {
         assert!(response
             .body()
             .contains(&escape_html(&first_provider_login.path_and_query())));
+        assert!(response.body().contains("Second Provider"));
+        assert!(response
+            .body()
+            .contains(&escape_html(&second_provider_login.path_and_query())));
+
+        // The second provider has a lower sort order, so it is listed first
+        let first_position = response
+            .body()
+            .find(&escape_html(&first_provider_login.path_and_query()))
+            .unwrap();
+        let second_position = response
+            .body()
+            .find(&escape_html(&second_provider_login.path_and_query()))
+            .unwrap();
+        assert!(second_position < first_position);
+
+        // Hidden providers are not offered as login options
+        let mut repo = state.repository().await.unwrap();
+        let hidden_provider = repo
+            .upstream_oauth_provider()
+            .add(
+                &mut rng,
+                &state.clock,
+                "https://hidden.com/".into(),
+                None,
+                [OPENID].into_iter().collect(),
+                OAuthClientAuthenticationMethod::None,
+                None,
+                "hidden_client".into(),
+                None,
+                UpstreamOAuthProviderClaimsImports::default(),
+                UpstreamOAuthProviderPkceMethod::default(),
+                UpstreamOAuthProviderAuthorizationParams::default(),
+                false,
+                UpstreamOAuthProviderUiOptions {
+                    show_on_login: false,
+                    ..UpstreamOAuthProviderUiOptions::default()
+                },
+                UpstreamOAuthProviderProtocol::Oidc,
+                UpstreamOAuthProviderEndpoints::default(),
+                UpstreamOAuthProviderSamlSettings::default(),
+                false,
+                UpstreamOAuthProviderHealthCheckSettings::default(),
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let hidden_provider_login = mas_router::UpstreamOAuth2Authorize::new(hidden_provider.id);
+
+        let response = state.request(Request::get("/login").empty()).await;
+        response.assert_status(StatusCode::OK);
+        assert!(!response
+            .body()
+            .contains(&escape_html(&hidden_provider_login.path_and_query())));
+
+        // Providers can be hidden while they are unhealthy
+        let mut repo = state.repository().await.unwrap();
+        let fragile_provider = repo
+            .upstream_oauth_provider()
+            .add(
+                &mut rng,
+                &state.clock,
+                "https://fragile.com/".into(),
+                None,
+                [OPENID].into_iter().collect(),
+                OAuthClientAuthenticationMethod::None,
+                None,
+                "fragile_client".into(),
+                None,
+                UpstreamOAuthProviderClaimsImports::default(),
+                UpstreamOAuthProviderPkceMethod::default(),
+                UpstreamOAuthProviderAuthorizationParams::default(),
+                false,
+                UpstreamOAuthProviderUiOptions::default(),
+                UpstreamOAuthProviderProtocol::Oidc,
+                UpstreamOAuthProviderEndpoints::default(),
+                UpstreamOAuthProviderSamlSettings::default(),
+                false,
+                UpstreamOAuthProviderHealthCheckSettings {
+                    hide_when_unhealthy: true,
+                    ..UpstreamOAuthProviderHealthCheckSettings::default()
+                },
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let fragile_provider_login = mas_router::UpstreamOAuth2Authorize::new(fragile_provider.id);
+
+        // It is offered as long as it wasn't found unhealthy
+        let response = state.request(Request::get("/login").empty()).await;
+        response.assert_status(StatusCode::OK);
         assert!(response
             .body()
-            .contains(&escape_html(&second_provider.issuer)));
+            .contains(&escape_html(&fragile_provider_login.path_and_query())));
+
+        let mut repo = state.repository().await.unwrap();
+        repo.upstream_oauth_provider()
+            .set_health(
+                &state.clock,
+                &fragile_provider,
+                Some("unreachable".to_owned()),
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let response = state.request(Request::get("/login").empty()).await;
+        response.assert_status(StatusCode::OK);
+        assert!(!response
+            .body()
+            .contains(&escape_html(&fragile_provider_login.path_and_query())));
+
+        let mut repo = state.repository().await.unwrap();
+        repo.upstream_oauth_provider()
+            .set_health(&state.clock, &fragile_provider, None)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let response = state.request(Request::get("/login").empty()).await;
+        response.assert_status(StatusCode::OK);
         assert!(response
             .body()
-            .contains(&escape_html(&second_provider_login.path_and_query())));
+            .contains(&escape_html(&fragile_provider_login.path_and_query())));
     }
 
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
@@ -464,4 +717,56 @@ mod test {
         response.assert_header_value(CONTENT_TYPE, "text/html; charset=utf-8");
         assert!(response.body().contains("john"));
     }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_login_rate_limited(pool: PgPool) {
+        init_tracing();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        state.limiter = Limiter::new(&RateLimits {
+            login_per_account: BucketConfig {
+                burst: NonZeroU32::new(1).unwrap(),
+                per_second: 0.1,
+            },
+            ..RateLimits::default()
+        });
+        let cookies = CookieHelper::new();
+
+        // Render the login page to get a CSRF token
+        let request = Request::get("/login").empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        let csrf_token = response
+            .body()
+            .split("name=\"csrf\" value=\"")
+            .nth(1)
+            .unwrap()
+            .split('\"')
+            .next()
+            .unwrap()
+            .to_owned();
+
+        // The first attempt goes through, and fails
+        let request = Request::post("/login").form(serde_json::json!({
+            "csrf": csrf_token,
+            "username": "john",
+            "password": "hunter2",
+        }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+
+        // The second one is refused until the bucket refills
+        let request = Request::post("/login").form(serde_json::json!({
+            "csrf": csrf_token,
+            "username": "john",
+            "password": "hunter2",
+        }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::TOO_MANY_REQUESTS);
+        response.assert_header_value(RETRY_AFTER, "10");
+    }
 }
@@ -22,7 +22,10 @@ use mas_axum_utils::{
     FancyError, SessionInfoExt,
 };
 use mas_router::{PostAuthAction, UrlBuilder};
-use mas_storage::{user::BrowserSessionRepository, BoxClock, BoxRepository};
+use mas_storage::{
+    user::{BrowserSessionRepository, UserImpersonationRepository},
+    BoxClock, BoxRepository,
+};
 
 use crate::BoundActivityTracker;
 
@@ -39,13 +42,17 @@ pub(crate) async fn post(
 
     let (session_info, mut cookie_jar) = cookie_jar.session_info();
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info.load_session(&clock, &mut repo).await?;
 
     if let Some(session) = maybe_session {
         activity_tracker
             .record_browser_session(&clock, &session)
             .await;
 
+        if session.is_impersonation() {
+            repo.user_impersonation().end(&clock, &session).await?;
+        }
+
         repo.browser_session().finish(&clock, session).await?;
         cookie_jar = cookie_jar.update_session_info(&session_info.mark_session_ended());
     }
@@ -14,9 +14,16 @@
 
 pub mod account;
 pub mod app;
+pub mod complete_profile;
+pub mod email_change_confirm;
+pub mod email_change_revert;
+pub mod impersonate;
 pub mod index;
+pub mod language;
 pub mod login;
 pub mod logout;
 pub mod reauth;
 pub mod register;
+pub mod reset_cross_signing;
 pub mod shared;
+pub mod theme;
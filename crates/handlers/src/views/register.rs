@@ -28,12 +28,12 @@ use mas_axum_utils::{
     FancyError, SessionInfoExt,
 };
 use mas_i18n::DataLocale;
-use mas_policy::Policy;
+use mas_policy::{Policy, Requester};
 use mas_router::UrlBuilder;
 use mas_storage::{
-    job::{JobRepositoryExt, ProvisionUserJob, VerifyEmailJob},
+    job::{JobRepositoryExt, NotifyUserEventJob, ProvisionUserJob, UserLifecycleEvent},
     user::{BrowserSessionRepository, UserEmailRepository, UserPasswordRepository, UserRepository},
-    BoxClock, BoxRepository, BoxRng, RepositoryAccess,
+    BoxClock, BoxRepository, BoxRng, Clock, RepositoryAccess,
 };
 use mas_templates::{
     FieldError, FormError, RegisterContext, RegisterFormField, TemplateContext, Templates,
@@ -42,8 +42,11 @@ use mas_templates::{
 use serde::{Deserialize, Serialize};
 use zeroize::Zeroizing;
 
-use super::shared::OptionalPostAuthAction;
-use crate::{passwords::PasswordManager, BoundActivityTracker, PreferredLanguage};
+use super::{account::emails::send_verification_email, shared::OptionalPostAuthAction};
+use crate::{
+    ip_filter::Action, passwords::PasswordManager, BoundActivityTracker, IpFilter, Limiter,
+    PreferredLanguage, SiteConfig,
+};
 
 #[derive(Debug, Deserialize, Serialize)]
 pub(crate) struct RegisterForm {
@@ -72,7 +75,7 @@ pub(crate) async fn get(
     let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
     let (session_info, cookie_jar) = cookie_jar.session_info();
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info.load_session(&clock, &mut repo).await?;
 
     if maybe_session.is_some() {
         let reply = query.go_next(&url_builder);
@@ -108,6 +111,9 @@ pub(crate) async fn post(
     State(password_manager): State<PasswordManager>,
     State(templates): State<Templates>,
     State(url_builder): State<UrlBuilder>,
+    State(site_config): State<SiteConfig>,
+    State(limiter): State<Limiter>,
+    State(ip_filter): State<IpFilter>,
     mut policy: Policy,
     mut repo: BoxRepository,
     activity_tracker: BoundActivityTracker,
@@ -121,10 +127,14 @@ pub(crate) async fn post(
         return Ok(StatusCode::METHOD_NOT_ALLOWED.into_response());
     }
 
-    let form = cookie_jar.verify_form(&clock, form)?;
+    let mut form = cookie_jar.verify_form(&clock, form)?;
 
     let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
 
+    if site_config.case_fold_usernames {
+        form.username = form.username.to_lowercase();
+    }
+
     // Validate the form
     let state = {
         let mut state = form.to_form_state();
@@ -155,8 +165,11 @@ pub(crate) async fn post(
             state.add_error_on_field(RegisterFormField::PasswordConfirm, FieldError::Unspecified);
         }
 
+        let requester = Requester::new(clock.now())
+            .with_ip_address(activity_tracker.ip())
+            .with_user_agent(user_agent.clone());
         let res = policy
-            .evaluate_register(&form.username, &form.password, &form.email)
+            .evaluate_register(&form.username, &form.password, &form.email, &requester)
             .await?;
 
         for violation in res.violations {
@@ -202,7 +215,55 @@ pub(crate) async fn post(
         return Ok((cookie_jar, Html(content)).into_response());
     }
 
+    if ip_filter
+        .check(activity_tracker.ip(), Action::Registration)
+        .await
+        .is_err()
+    {
+        let state = state.with_error_on_form(FormError::NetworkDenied);
+        let content = render(
+            locale,
+            RegisterContext::default().with_form_state(state),
+            query,
+            csrf_token,
+            &mut repo,
+            &templates,
+        )
+        .await?;
+
+        return Ok((StatusCode::FORBIDDEN, cookie_jar, Html(content)).into_response());
+    }
+
+    // Only valid registration attempts count towards the limit
+    if let Err(rate_limited) = limiter
+        .check_registration(clock.now(), activity_tracker.ip())
+        .await
+    {
+        let state = state.with_error_on_form(FormError::RateLimitExceeded);
+        let content = render(
+            locale,
+            RegisterContext::default().with_form_state(state),
+            query,
+            csrf_token,
+            &mut repo,
+            &templates,
+        )
+        .await?;
+
+        return Ok((
+            StatusCode::TOO_MANY_REQUESTS,
+            rate_limited,
+            cookie_jar,
+            Html(content),
+        )
+            .into_response());
+    }
+
     let user = repo.user().add(&mut rng, &clock, form.username).await?;
+    let user = repo
+        .user()
+        .set_locale(user, Some(locale.to_string()))
+        .await?;
     let password = Zeroizing::new(form.password.into_bytes());
     let (version, hashed_password) = password_manager.hash(&mut rng, password).await?;
     let user_password = repo
@@ -226,12 +287,22 @@ pub(crate) async fn post(
         .authenticate_with_password(&mut rng, &clock, &session, &user_password)
         .await?;
 
+    send_verification_email(
+        &mut rng,
+        &clock,
+        &mut repo,
+        &site_config.email_rate_limits,
+        &user_email,
+        &locale,
+    )
+    .await?;
+
     repo.job()
-        .schedule_job(VerifyEmailJob::new(&user_email).with_language(locale.to_string()))
+        .schedule_job(ProvisionUserJob::new(&user))
         .await?;
 
     repo.job()
-        .schedule_job(ProvisionUserJob::new(&user))
+        .schedule_job(NotifyUserEventJob::new(&user, UserLifecycleEvent::Created))
         .await?;
 
     repo.save().await?;
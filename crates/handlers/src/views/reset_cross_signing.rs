@@ -0,0 +1,318 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use axum::{
+    extract::{Form, State},
+    response::{Html, IntoResponse, Response},
+};
+use hyper::StatusCode;
+use mas_axum_utils::{
+    cookies::CookieJar,
+    csrf::{CsrfExt, ProtectedForm},
+    FancyError, SessionInfoExt,
+};
+use mas_router::UrlBuilder;
+use mas_storage::{
+    job::{AllowCrossSigningResetJob, JobRepositoryExt},
+    BoxClock, BoxRepository, BoxRng,
+};
+use mas_templates::{ResetCrossSigningContext, TemplateContext, Templates};
+use tracing::{info, warn};
+
+use crate::{BoundActivityTracker, PreferredLanguage, SiteConfig};
+
+/// Shows the page where users approve a reset of their cross-signing identity
+/// requested by one of their clients.
+#[tracing::instrument(name = "handlers.views.reset_cross_signing.get", skip_all, err)]
+pub(crate) async fn get(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    PreferredLanguage(locale): PreferredLanguage,
+    State(templates): State<Templates>,
+    State(url_builder): State<UrlBuilder>,
+    activity_tracker: BoundActivityTracker,
+    mut repo: BoxRepository,
+    cookie_jar: CookieJar,
+) -> Result<Response, FancyError> {
+    let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
+    let (session_info, cookie_jar) = cookie_jar.session_info();
+
+    let maybe_session = session_info.load_session(&clock, &mut repo).await?;
+
+    let Some(session) = maybe_session else {
+        let login = mas_router::Login::default();
+        return Ok((cookie_jar, url_builder.redirect(&login)).into_response());
+    };
+
+    // Administrators impersonating a user can't reset their identity
+    if session.is_impersonation() {
+        return Ok((cookie_jar, StatusCode::FORBIDDEN).into_response());
+    }
+
+    activity_tracker
+        .record_browser_session(&clock, &session)
+        .await;
+
+    let ctx = ResetCrossSigningContext::new()
+        .with_session(session)
+        .with_csrf(csrf_token.form_value())
+        .with_language(locale);
+
+    let content = templates.render_reset_cross_signing(&ctx)?;
+
+    Ok((cookie_jar, Html(content)).into_response())
+}
+
+/// Approves the reset, by asking the homeserver to let the user replace their
+/// cross-signing keys without going through user-interactive authentication.
+#[tracing::instrument(name = "handlers.views.reset_cross_signing.post", skip_all, err)]
+pub(crate) async fn post(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    PreferredLanguage(locale): PreferredLanguage,
+    State(templates): State<Templates>,
+    State(url_builder): State<UrlBuilder>,
+    State(site_config): State<SiteConfig>,
+    activity_tracker: BoundActivityTracker,
+    mut repo: BoxRepository,
+    cookie_jar: CookieJar,
+    Form(form): Form<ProtectedForm<()>>,
+) -> Result<Response, FancyError> {
+    cookie_jar.verify_form(&clock, form)?;
+    let (session_info, cookie_jar) = cookie_jar.session_info();
+
+    let maybe_session = session_info.load_session(&clock, &mut repo).await?;
+
+    let Some(session) = maybe_session else {
+        let login = mas_router::Login::default();
+        return Ok((cookie_jar, url_builder.redirect(&login)).into_response());
+    };
+
+    if let Some(impersonation) = &session.impersonation {
+        warn!(
+            audit = true,
+            audit.action = "user.cross_signing_reset.refuse",
+            user.id = %session.user.id,
+            user.username = %session.user.username,
+            user_session.id = %session.id,
+            impersonator.id = %impersonation.impersonator_user_id,
+            "Refused a reset of the cross-signing identity from an impersonation session"
+        );
+        return Ok((cookie_jar, StatusCode::FORBIDDEN).into_response());
+    }
+
+    activity_tracker
+        .record_browser_session(&clock, &session)
+        .await;
+
+    repo.job()
+        .schedule_job(AllowCrossSigningResetJob::new(&session.user))
+        .await?;
+
+    repo.save().await?;
+
+    info!(
+        audit = true,
+        audit.action = "user.cross_signing_reset.approve",
+        user.id = %session.user.id,
+        user.username = %session.user.username,
+        user_session.id = %session.id,
+        user_agent = session.user_agent.as_deref(),
+        "User approved a reset of their cross-signing identity"
+    );
+
+    let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
+    let ctx = ResetCrossSigningContext::approved()
+        .with_embedding_origins(site_config.embedding_origins)
+        .with_session(session)
+        .with_csrf(csrf_token.form_value())
+        .with_language(locale);
+
+    let content = templates.render_reset_cross_signing(&ctx)?;
+
+    Ok((cookie_jar, Html(content)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use hyper::{header::LOCATION, Request, StatusCode};
+    use mas_data_model::User;
+    use mas_router::Route;
+    use mas_storage::{user::UserRepository, RepositoryAccess};
+    use sqlx::PgPool;
+
+    use crate::test_utils::{
+        init_tracing, CookieHelper, RequestBuilderExt, ResponseExt, TestState,
+    };
+
+    /// Create a user and log them in
+    async fn setup(state: &TestState, cookies: &CookieHelper) -> User {
+        let mut rng = state.rng();
+        let mut repo = state.repository().await.unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "john".to_owned())
+            .await
+            .unwrap();
+        let session = repo
+            .browser_session()
+            .add(&mut rng, &state.clock, &user, None)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        cookies.set_session(state, &session).await;
+
+        user
+    }
+
+    /// Render the approval page, and return the CSRF token from the form
+    async fn get_csrf_token(state: &TestState, cookies: &CookieHelper) -> String {
+        let request =
+            Request::get(&*mas_router::ResetCrossSigning::default().path_and_query()).empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+
+        response
+            .body()
+            .split("name=\"csrf\" value=\"")
+            .nth(1)
+            .unwrap()
+            .split('\"')
+            .next()
+            .unwrap()
+            .to_owned()
+    }
+
+    /// The jobs scheduled to allow a reset of the cross-signing identity
+    async fn scheduled_jobs(state: &TestState) -> Vec<serde_json::Value> {
+        let jobs: Vec<String> = sqlx::query_scalar(
+            "SELECT job::text FROM apalis.jobs WHERE job_type = 'allow-cross-signing-reset'",
+        )
+        .fetch_all(&state.pool)
+        .await
+        .unwrap();
+
+        jobs.iter()
+            .map(|job| serde_json::from_str(job).unwrap())
+            .collect()
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_requires_session(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        let request =
+            Request::get(&*mas_router::ResetCrossSigning::default().path_and_query()).empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        response.assert_header_value(LOCATION, "/login");
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_approve(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let cookies = CookieHelper::new();
+        let user = setup(&state, &cookies).await;
+
+        let csrf_token = get_csrf_token(&state, &cookies).await;
+
+        // Nothing is scheduled until the user approves the reset
+        assert!(scheduled_jobs(&state).await.is_empty());
+
+        let request = Request::post(&*mas_router::ResetCrossSigning::default().path_and_query())
+            .form(serde_json::json!({ "csrf": csrf_token }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let jobs = scheduled_jobs(&state).await;
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0]["user_id"], user.id.to_string());
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_invalid_csrf(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let cookies = CookieHelper::new();
+        setup(&state, &cookies).await;
+
+        get_csrf_token(&state, &cookies).await;
+
+        // A form which didn't come from the approval page is rejected
+        let request = Request::post(&*mas_router::ResetCrossSigning::default().path_and_query())
+            .form(serde_json::json!({ "csrf": "not-the-token" }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::INTERNAL_SERVER_ERROR);
+
+        assert!(scheduled_jobs(&state).await.is_empty());
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_impersonation_forbidden(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let mut rng = state.rng();
+        let cookies = CookieHelper::new();
+        let user = setup(&state, &cookies).await;
+
+        let csrf_token = get_csrf_token(&state, &cookies).await;
+
+        // An administrator impersonates the user
+        let mut repo = state.repository().await.unwrap();
+        let admin = repo
+            .user()
+            .add(&mut rng, &state.clock, "admin".to_owned())
+            .await
+            .unwrap();
+        let session = repo
+            .browser_session()
+            .add_impersonation(
+                &mut rng,
+                &state.clock,
+                &user,
+                &admin,
+                Duration::minutes(30),
+                None,
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        cookies.set_session(&state, &session).await;
+
+        // They can neither see the approval page nor approve the reset
+        let request =
+            Request::get(&*mas_router::ResetCrossSigning::default().path_and_query()).empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+
+        let request = Request::post(&*mas_router::ResetCrossSigning::default().path_and_query())
+            .form(serde_json::json!({ "csrf": csrf_token }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+
+        assert!(scheduled_jobs(&state).await.is_empty());
+    }
+}
@@ -16,7 +16,7 @@ use anyhow::Context;
 use mas_router::{PostAuthAction, Route, UrlBuilder};
 use mas_storage::{
     compat::CompatSsoLoginRepository,
-    oauth2::OAuth2AuthorizationGrantRepository,
+    oauth2::{OAuth2AuthorizationGrantRepository, OAuth2ClientRepository},
     upstream_oauth2::{UpstreamOAuthLinkRepository, UpstreamOAuthProviderRepository},
     RepositoryAccess,
 };
@@ -59,8 +59,14 @@ impl OptionalPostAuthAction {
                     .lookup(id)
                     .await?
                     .context("Failed to load authorization grant")?;
+                let client = repo
+                    .oauth2_client()
+                    .lookup(grant.client_id)
+                    .await?
+                    .context("Failed to load client")?;
                 let grant = Box::new(grant);
-                PostAuthContextInner::ContinueAuthorizationGrant { grant }
+                let client = Box::new(client);
+                PostAuthContextInner::ContinueAuthorizationGrant { grant, client }
             }
 
             PostAuthAction::ContinueCompatSsoLogin { id } => {
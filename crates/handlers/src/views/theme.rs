@@ -0,0 +1,52 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use axum::{
+    extract::{Query, State},
+    response::{IntoResponse, Response},
+};
+use hyper::{
+    header::{CACHE_CONTROL, CONTENT_TYPE},
+    StatusCode,
+};
+use mas_router::ThemeStylesheetQuery;
+use mas_templates::Templates;
+
+/// Serve the stylesheet compiled from the theme set in the configuration
+#[tracing::instrument(name = "handlers.views.theme.get", skip_all)]
+pub async fn get(
+    State(templates): State<Templates>,
+    Query(query): Query<ThemeStylesheetQuery>,
+) -> Response {
+    let Some(stylesheet) = templates.theme_stylesheet() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    // The stylesheet is linked with its version in the URL, so it can be cached
+    // forever, unless an outdated version is requested
+    let cache_control = if query.version.as_deref() == Some(stylesheet.version.as_str()) {
+        "public, max-age=31536000, immutable"
+    } else {
+        "no-cache"
+    };
+
+    (
+        [
+            (CONTENT_TYPE, "text/css; charset=utf-8"),
+            (CACHE_CONTROL, cache_control),
+        ],
+        stylesheet.css,
+    )
+        .into_response()
+}
@@ -28,7 +28,10 @@ use tower::{
     Layer,
 };
 use tower_http::{
-    follow_redirect::{FollowRedirect, FollowRedirectLayer},
+    follow_redirect::{
+        policy::{FilterCredentials, Limited, PolicyExt},
+        FollowRedirect, FollowRedirectLayer,
+    },
     set_header::{SetRequestHeader, SetRequestHeaderLayer},
     timeout::{Timeout, TimeoutLayer},
 };
@@ -233,6 +236,16 @@ impl ClientLayer {
 
         self
     }
+
+    /// Don't follow redirects, for requests to URLs which are checked before
+    /// being fetched
+    #[must_use]
+    pub fn without_redirects(mut self) -> Self {
+        self.follow_redirect_layer = FollowRedirectLayer::with_policy(
+            Limited::new(0).and::<_, (), ()>(FilterCredentials::new()),
+        );
+        self
+    }
 }
 
 impl<S> Layer<S> for ClientLayer
@@ -0,0 +1,81 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use icu_calendar::{CalendarError, Gregorian};
+use icu_datetime::{options::length, DateTimeError, TypedDateTimeFormatter};
+use icu_provider::DataLocale;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum FormatDateTimeError {
+    #[error("Invalid date")]
+    Calendar(#[from] CalendarError),
+
+    #[error("Could not load the date format of the locale")]
+    DateTime(#[from] DateTimeError),
+}
+
+/// Format a timestamp as a medium-length date and a short time, like
+/// `Jul 21, 2023, 4:14 PM` in English or `21 juil. 2023, 16:14` in French.
+///
+/// The time is the UTC time: the caller is responsible for telling which
+/// time zone it is in.
+///
+/// # Errors
+///
+/// Returns an error if the date can't be represented in the Gregorian
+/// calendar, or if the date format of the locale couldn't be loaded
+pub fn format_datetime(
+    locale: &DataLocale,
+    datetime: DateTime<Utc>,
+) -> Result<String, FormatDateTimeError> {
+    let options =
+        length::Bag::from_date_time_style(length::Date::Medium, length::Time::Short).into();
+    let formatter = TypedDateTimeFormatter::<Gregorian>::try_new(locale, options)?;
+
+    // The values all fit, as they come from a valid chrono timestamp
+    #[allow(clippy::cast_possible_truncation)]
+    let datetime = icu_calendar::DateTime::try_new_gregorian_datetime(
+        datetime.year(),
+        datetime.month() as u8,
+        datetime.day() as u8,
+        datetime.hour() as u8,
+        datetime.minute() as u8,
+        datetime.second() as u8,
+    )?;
+
+    Ok(formatter.format_to_string(&datetime))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use icu_locid::locale;
+
+    use super::*;
+
+    #[test]
+    fn test_format_datetime() {
+        let datetime = Utc.with_ymd_and_hms(2023, 7, 21, 16, 14, 30).unwrap();
+
+        let en = format_datetime(&locale!("en").into(), datetime).unwrap();
+        assert!(en.starts_with("Jul 21, 2023"));
+        assert!(en.contains("4:14"));
+
+        let fr = format_datetime(&locale!("fr").into(), datetime).unwrap();
+        assert!(fr.starts_with("21 juil. 2023"));
+        assert!(fr.contains("16:14"));
+    }
+}
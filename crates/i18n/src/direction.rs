@@ -0,0 +1,83 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use icu_provider::DataLocale;
+
+/// Scripts written from right to left
+const RTL_SCRIPTS: [&str; 9] = [
+    "Adlm", "Arab", "Hebr", "Mand", "Nkoo", "Rohg", "Samr", "Syrc", "Thaa",
+];
+
+/// Languages written from right to left, when no script is given
+const RTL_LANGUAGES: [&str; 14] = [
+    "ar", "arc", "ckb", "dv", "fa", "he", "iw", "ks", "ps", "sd", "syr", "ug", "ur", "yi",
+];
+
+/// The direction in which a locale is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Left to right
+    Ltr,
+
+    /// Right to left
+    Rtl,
+}
+
+impl Direction {
+    /// Get the direction of a locale, from its script if it has one, else
+    /// from its language
+    #[must_use]
+    pub fn of(locale: &DataLocale) -> Self {
+        let langid = locale.get_langid();
+        let rtl = match langid.script {
+            Some(script) => RTL_SCRIPTS.contains(&script.as_str()),
+            None => RTL_LANGUAGES.contains(&langid.language.as_str()),
+        };
+
+        if rtl {
+            Self::Rtl
+        } else {
+            Self::Ltr
+        }
+    }
+
+    /// The value of the HTML `dir` attribute for this direction
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Ltr => "ltr",
+            Self::Rtl => "rtl",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use icu_locid::locale;
+
+    use super::*;
+
+    #[test]
+    fn test_direction() {
+        assert_eq!(Direction::of(&locale!("en").into()), Direction::Ltr);
+        assert_eq!(Direction::of(&locale!("fr-CA").into()), Direction::Ltr);
+        assert_eq!(Direction::of(&locale!("ar").into()), Direction::Rtl);
+        assert_eq!(Direction::of(&locale!("he-IL").into()), Direction::Rtl);
+        assert_eq!(Direction::of(&locale!("fa").into()), Direction::Rtl);
+
+        // The script wins over the language
+        assert_eq!(Direction::of(&locale!("az-Arab").into()), Direction::Rtl);
+        assert_eq!(Direction::of(&locale!("ks-Deva").into()), Direction::Ltr);
+    }
+}
@@ -15,6 +15,8 @@
 #![warn(clippy::pedantic)]
 #![deny(clippy::all)]
 
+mod datetime;
+mod direction;
 pub mod sprintf;
 pub mod translations;
 mod translator;
@@ -23,6 +25,8 @@ pub use icu_locid::locale;
 pub use icu_provider::DataLocale;
 
 pub use self::{
+    datetime::{format_datetime, FormatDateTimeError},
+    direction::Direction,
     sprintf::{Argument, ArgumentList, Message},
-    translator::{LoadError, Translator},
+    translator::{InvalidMessage, LoadError, Translator},
 };
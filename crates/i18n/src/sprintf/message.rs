@@ -238,6 +238,18 @@ impl Message {
         self.parts.iter()
     }
 
+    /// The names of the named arguments used by the message, like `name` in
+    /// `%(name)s`.
+    pub fn named_arguments(&self) -> impl Iterator<Item = &str> {
+        self.parts.iter().filter_map(|part| match part {
+            Part::Placeholder(Placeholder {
+                requested_argument: Some(ArgumentReference::Named(name)),
+                ..
+            }) => Some(name.as_str()),
+            _ => None,
+        })
+    }
+
     /// Create a message from a literal string, without any placeholders.
     #[must_use]
     pub fn from_literal(literal: String) -> Message {
@@ -37,6 +37,8 @@ fn plural_category_as_str(category: PluralCategory) -> &'static str {
     }
 }
 
+const PLURAL_CATEGORIES: [&str; 6] = ["zero", "one", "two", "few", "many", "other"];
+
 pub type TranslationTree = Tree;
 
 #[derive(Debug, Clone, Deserialize, Default)]
@@ -205,6 +207,90 @@ impl Tree {
         Some(message)
     }
 
+    /// List all the messages of the tree, with their dot-separated key.
+    ///
+    /// The variants of pluralized messages are listed separately, like
+    /// `active_sessions.one` and `active_sessions.other`.
+    #[must_use]
+    pub fn messages(&self) -> Vec<(String, &Message)> {
+        let mut messages = Vec::new();
+        self.collect_messages("", &mut messages);
+        messages
+    }
+
+    fn collect_messages<'a>(&'a self, prefix: &str, messages: &mut Vec<(String, &'a Message)>) {
+        for (key, node) in &self.inner {
+            let key = if prefix.is_empty() {
+                key.clone()
+            } else {
+                format!("{prefix}.{key}")
+            };
+
+            match &node.value {
+                Value::Leaf(message) => messages.push((key, message)),
+                Value::Tree(tree) => tree.collect_messages(&key, messages),
+            }
+        }
+    }
+
+    /// Get the key of the message a key belongs to: the key of the pluralized
+    /// message for its variants, like `active_sessions` for
+    /// `active_sessions.one`, else the key itself.
+    #[must_use]
+    pub fn message_key<'a>(&self, key: &'a str) -> &'a str {
+        let Some((parent, category)) = key.rsplit_once('.') else {
+            return key;
+        };
+
+        if !PLURAL_CATEGORIES.contains(&category) {
+            return key;
+        }
+
+        match self.walk_path(parent.split('.')) {
+            Some(Node {
+                value: Value::Tree(tree),
+                ..
+            }) if tree.is_plural() => parent,
+            _ => key,
+        }
+    }
+
+    /// Get the named arguments a message can use.
+    ///
+    /// `key` can point to a message, or to a variant of a pluralized message,
+    /// like `active_sessions.many`, even if that variant is not defined in
+    /// this tree. The arguments of a pluralized message are the ones of all
+    /// its variants, plus `count`.
+    ///
+    /// Returns `None` if the requested key is not found.
+    #[must_use]
+    pub fn arguments(&self, key: &str) -> Option<BTreeSet<String>> {
+        let node = self.walk_path(self.message_key(key).split('.'))?;
+
+        let arguments = match &node.value {
+            Value::Leaf(message) => message.named_arguments().map(ToOwned::to_owned).collect(),
+            // Groups of messages are not messages themselves
+            Value::Tree(tree) if !tree.is_plural() => return None,
+            Value::Tree(tree) => tree
+                .messages()
+                .into_iter()
+                .flat_map(|(_, message)| message.named_arguments().map(ToOwned::to_owned))
+                .chain(std::iter::once("count".to_owned()))
+                .collect(),
+        };
+
+        Some(arguments)
+    }
+
+    /// Whether this is the variants of a pluralized message
+    fn is_plural(&self) -> bool {
+        !self.inner.is_empty()
+            && self
+                .inner
+                .keys()
+                .all(|key| PLURAL_CATEGORIES.contains(&key.as_str()))
+    }
+
     #[doc(hidden)]
     pub fn set_if_not_defined<K: Deref<Target = str>, I: IntoIterator<Item = K>>(
         &mut self,
@@ -12,7 +12,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{collections::HashMap, fs::File, str::FromStr};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    str::FromStr,
+};
 
 use camino::{Utf8Path, Utf8PathBuf};
 use icu_list::{ListError, ListFormatter, ListLength};
@@ -42,6 +46,44 @@ pub enum LoadError {
     InvalidFileName(Utf8PathBuf),
 }
 
+/// A message of the extra translations which was skipped
+#[derive(Debug, Error)]
+pub enum InvalidMessage {
+    /// The message doesn't exist in the reference locale
+    #[error("{key:?} in {locale} is not a known message")]
+    UnknownKey { locale: DataLocale, key: String },
+
+    /// The message uses an argument the reference locale doesn't use
+    #[error("{key:?} in {locale} uses the unknown argument {argument:?}")]
+    UnknownArgument {
+        locale: DataLocale,
+        key: String,
+        argument: String,
+    },
+}
+
+/// Load the translation files of a directory, by locale
+fn load_directory(path: &Utf8Path) -> Result<HashMap<DataLocale, TranslationTree>, LoadError> {
+    let mut translations = HashMap::new();
+
+    let dir = path.read_dir_utf8()?;
+    for entry in dir {
+        let entry = entry?;
+        let path = entry.into_path();
+        let Some(name) = path.file_stem() else {
+            return Err(LoadError::InvalidFileName(path));
+        };
+
+        let locale: Locale = Locale::from_str(name)?;
+
+        let mut file = File::open(path)?;
+        let content = serde_json::from_reader(&mut file)?;
+        translations.insert(locale.into(), content);
+    }
+
+    Ok(translations)
+}
+
 /// A translator for a set of translations.
 #[derive(Debug)]
 pub struct Translator {
@@ -87,24 +129,95 @@ impl Translator {
     /// Returns an error if the directory cannot be read, or if any of the files
     /// cannot be parsed.
     pub fn load_from_path(path: &Utf8Path) -> Result<Self, LoadError> {
-        let mut translations = HashMap::new();
+        let translations = load_directory(path)?;
+        Ok(Self::new(translations))
+    }
 
-        let dir = path.read_dir_utf8()?;
-        for entry in dir {
-            let entry = entry?;
-            let path = entry.into_path();
-            let Some(name) = path.file_stem() else {
-                return Err(LoadError::InvalidFileName(path));
-            };
+    /// Load a set of translations from a directory, and the translations of
+    /// another directory on top of them.
+    ///
+    /// Both directories have the same layout as in [`Self::load_from_path`].
+    /// The extra translations can add locales, or replace some messages of
+    /// the existing ones.
+    ///
+    /// Extra messages must exist in the `reference` locale of the first
+    /// directory, and only use the arguments it uses. The others are skipped,
+    /// and returned alongside the translator.
+    ///
+    /// # Parameters
+    ///
+    /// * `path` - The path to load the translations from.
+    /// * `extra_path` - The path to load the extra translations from.
+    /// * `reference` - The locale the extra messages are checked against.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of the directories cannot be read, or if any of
+    /// the files cannot be parsed.
+    pub fn load_from_path_with_extra(
+        path: &Utf8Path,
+        extra_path: &Utf8Path,
+        reference: &DataLocale,
+    ) -> Result<(Self, Vec<InvalidMessage>), LoadError> {
+        let mut translations = load_directory(path)?;
+        let extra_translations = load_directory(extra_path)?;
+        let empty = TranslationTree::default();
+        let reference_tree = translations.get(reference).unwrap_or(&empty);
+
+        let mut invalid = Vec::new();
+        let mut merged_translations = HashMap::new();
+        for (locale, extra) in extra_translations {
+            let mut merged = TranslationTree::default();
+            let mut replaced = HashSet::new();
+
+            for (key, message) in extra.messages() {
+                let Some(arguments) = reference_tree.arguments(&key) else {
+                    invalid.push(InvalidMessage::UnknownKey {
+                        locale: locale.clone(),
+                        key,
+                    });
+                    continue;
+                };
+
+                if let Some(argument) = message
+                    .named_arguments()
+                    .find(|argument| !arguments.contains(*argument))
+                {
+                    invalid.push(InvalidMessage::UnknownArgument {
+                        locale: locale.clone(),
+                        argument: argument.to_owned(),
+                        key,
+                    });
+                    continue;
+                }
 
-            let locale: Locale = Locale::from_str(name)?;
+                replaced.insert(reference_tree.message_key(&key).to_owned());
+                merged.set_if_not_defined(key.split('.'), message.clone(), None);
+            }
 
-            let mut file = File::open(path)?;
-            let content = serde_json::from_reader(&mut file)?;
-            translations.insert(locale.into(), content);
+            // Keep the built-in messages which were not replaced. Pluralized
+            // messages are replaced as a whole, with all their variants
+            if let Some(builtin) = translations.get(&locale) {
+                for (key, message) in builtin.messages() {
+                    let message_key = reference_tree.message_key(&key);
+                    let is_replaced = replaced.iter().any(|replaced| {
+                        replaced == message_key
+                            || key.starts_with(&format!("{replaced}."))
+                            || replaced.starts_with(&format!("{key}."))
+                    });
+
+                    if !is_replaced {
+                        merged.set_if_not_defined(key.split('.'), message.clone(), None);
+                    }
+                }
+            }
+
+            merged_translations.insert(locale, merged);
         }
 
-        Ok(Self::new(translations))
+        translations.extend(merged_translations);
+
+        Ok((Self::new(translations), invalid))
     }
 
     /// Get a message from the tree by key, with locale fallback.
@@ -381,6 +494,51 @@ mod tests {
         assert_eq!(locale, locale!("en").into());
     }
 
+    #[test]
+    fn test_extra_translations() {
+        let root: Utf8PathBuf = env!("CARGO_MANIFEST_DIR").parse().unwrap();
+        let (translator, invalid) = Translator::load_from_path_with_extra(
+            &root.join("test_data"),
+            &root.join("test_data_extra"),
+            &locale!("en").into(),
+        )
+        .unwrap();
+
+        // The unknown message and the one with an unknown argument are skipped
+        let mut invalid: Vec<String> = invalid.iter().map(ToString::to_string).collect();
+        invalid.sort();
+        assert_eq!(
+            invalid,
+            vec![
+                "\"goodbye\" in fr uses the unknown argument \"name\"",
+                "\"unknown\" in fr is not a known message",
+            ]
+        );
+
+        // Extra messages replace the built-in ones
+        let message = translator.message(&locale!("fr").into(), "hello").unwrap();
+        let formatted = message.format(&arg_list!()).unwrap();
+        assert_eq!(formatted, "Salut !");
+
+        let message = translator
+            .plural(&locale!("fr").into(), "active_sessions", 1)
+            .unwrap();
+        let formatted = message.format(&arg_list!(count = 1)).unwrap();
+        assert_eq!(formatted, "Une session active.");
+
+        // The others are kept
+        let message = translator
+            .message(&locale!("fr").into(), "goodbye")
+            .unwrap();
+        let formatted = message.format(&arg_list!()).unwrap();
+        assert_eq!(formatted, "Au revoir !");
+
+        // New locales are added
+        let message = translator.message(&locale!("de").into(), "hello").unwrap();
+        let formatted = message.format(&arg_list!()).unwrap();
+        assert_eq!(formatted, "Hallo!");
+    }
+
     #[test]
     fn test_plurals() {
         let translator = translator();
@@ -496,7 +496,18 @@ mod oidc_core {
     pub const UPDATED_AT: Claim<Timestamp> = Claim::new("updated_at");
 }
 
-pub use self::{oidc_core::*, rfc7519::*};
+/// Claims defined in OIDC.BackChannel sec. 2.4
+/// <https://openid.net/specs/openid-connect-backchannel-1_0.html#LogoutToken>
+mod oidc_backchannel {
+    use std::collections::HashMap;
+
+    use super::Claim;
+
+    pub const SID: Claim<String> = Claim::new("sid");
+    pub const EVENTS: Claim<HashMap<String, serde_json::Value>> = Claim::new("events");
+}
+
+pub use self::{oidc_backchannel::*, oidc_core::*, rfc7519::*};
 
 #[cfg(test)]
 mod tests {
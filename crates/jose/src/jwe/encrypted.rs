@@ -0,0 +1,507 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use aes_gcm::aead::{
+    generic_array::{typenum::Unsigned, GenericArray},
+    AeadCore, AeadInPlace, KeyInit,
+};
+use base64ct::{Base64UrlUnpadded, Encoding};
+use cbc::cipher::{
+    block_padding::Pkcs7, BlockCipher, BlockDecryptMut, BlockEncryptMut, KeyIvInit, KeySizeUser,
+};
+use digest::{crypto_common::BlockSizeUser, Digest, Mac};
+use mas_iana::jose::{JsonWebEncryptionAlg, JsonWebEncryptionEnc};
+use rsa::{Oaep, RsaPrivateKey, RsaPublicKey};
+use sha2::{Sha256, Sha384, Sha512};
+use signature::rand_core::CryptoRngCore;
+use thiserror::Error;
+
+use super::header::JsonWebEncryptionHeader;
+
+type Aes192Gcm = aes_gcm::AesGcm<aes::Aes192, aes_gcm::aead::consts::U12>;
+
+/// A JSON Web Encryption token in its compact serialization
+#[derive(Clone, PartialEq, Eq)]
+pub struct Jwe {
+    protected_header: String,
+    header: JsonWebEncryptionHeader,
+    encrypted_key: Vec<u8>,
+    iv: Vec<u8>,
+    ciphertext: Vec<u8>,
+    tag: Vec<u8>,
+}
+
+impl std::fmt::Debug for Jwe {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Jwe")
+            .field("protected_header", &self.protected_header)
+            .field("header", &self.header)
+            .field("encrypted_key", &"...")
+            .field("iv", &"...")
+            .field("ciphertext", &"...")
+            .field("tag", &"...")
+            .finish()
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum JweDecodeError {
+    #[error("JWE must have exactly five parts")]
+    InvalidParts,
+
+    #[error("failed to decode JWE header")]
+    DecodeHeader {
+        #[source]
+        inner: base64ct::Error,
+    },
+
+    #[error("failed to deserialize JWE header")]
+    DeserializeHeader {
+        #[source]
+        inner: serde_json::Error,
+    },
+
+    #[error("failed to decode JWE encrypted key")]
+    DecodeEncryptedKey {
+        #[source]
+        inner: base64ct::Error,
+    },
+
+    #[error("failed to decode JWE initialization vector")]
+    DecodeIv {
+        #[source]
+        inner: base64ct::Error,
+    },
+
+    #[error("failed to decode JWE ciphertext")]
+    DecodeCiphertext {
+        #[source]
+        inner: base64ct::Error,
+    },
+
+    #[error("failed to decode JWE authentication tag")]
+    DecodeTag {
+        #[source]
+        inner: base64ct::Error,
+    },
+}
+
+impl JweDecodeError {
+    fn decode_header(inner: base64ct::Error) -> Self {
+        Self::DecodeHeader { inner }
+    }
+
+    fn deserialize_header(inner: serde_json::Error) -> Self {
+        Self::DeserializeHeader { inner }
+    }
+
+    fn decode_encrypted_key(inner: base64ct::Error) -> Self {
+        Self::DecodeEncryptedKey { inner }
+    }
+
+    fn decode_iv(inner: base64ct::Error) -> Self {
+        Self::DecodeIv { inner }
+    }
+
+    fn decode_ciphertext(inner: base64ct::Error) -> Self {
+        Self::DecodeCiphertext { inner }
+    }
+
+    fn decode_tag(inner: base64ct::Error) -> Self {
+        Self::DecodeTag { inner }
+    }
+}
+
+impl TryFrom<&str> for Jwe {
+    type Error = JweDecodeError;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let parts: Vec<&str> = value.split('.').collect();
+        let [protected_header, encrypted_key, iv, ciphertext, tag] = parts[..] else {
+            return Err(JweDecodeError::InvalidParts);
+        };
+
+        let header = Base64UrlUnpadded::decode_vec(protected_header)
+            .map_err(JweDecodeError::decode_header)?;
+        let header = serde_json::from_slice(&header).map_err(JweDecodeError::deserialize_header)?;
+
+        let encrypted_key = Base64UrlUnpadded::decode_vec(encrypted_key)
+            .map_err(JweDecodeError::decode_encrypted_key)?;
+        let iv = Base64UrlUnpadded::decode_vec(iv).map_err(JweDecodeError::decode_iv)?;
+        let ciphertext =
+            Base64UrlUnpadded::decode_vec(ciphertext).map_err(JweDecodeError::decode_ciphertext)?;
+        let tag = Base64UrlUnpadded::decode_vec(tag).map_err(JweDecodeError::decode_tag)?;
+
+        Ok(Self {
+            protected_header: protected_header.to_owned(),
+            header,
+            encrypted_key,
+            iv,
+            ciphertext,
+            tag,
+        })
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum JweDecryptionError {
+    #[error("unsupported key management algorithm {alg}")]
+    UnsupportedAlgorithm { alg: JsonWebEncryptionAlg },
+
+    #[error("unsupported content encryption algorithm {enc}")]
+    UnsupportedEncryption { enc: JsonWebEncryptionEnc },
+
+    #[error("compressed JWE payloads are not supported")]
+    UnsupportedCompression,
+
+    #[error("unsupported critical JWE header parameters")]
+    UnsupportedCriticalHeader,
+
+    #[error("invalid JWE initialization vector length")]
+    InvalidIvLength,
+
+    /// The content encryption key could not be decrypted, or the content
+    /// could not be decrypted or authenticated with it. Those cases are
+    /// deliberately not distinguished.
+    #[error("failed to decrypt JWE")]
+    Decryption,
+}
+
+impl Jwe {
+    #[must_use]
+    pub fn header(&self) -> &JsonWebEncryptionHeader {
+        &self.header
+    }
+
+    /// Decrypt the payload of this JWE with the given RSA private key
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the algorithms are not supported, or if the key
+    /// doesn't decrypt the token
+    pub fn decrypt(&self, key: &RsaPrivateKey) -> Result<Vec<u8>, JweDecryptionError> {
+        if self.header.zip().is_some() {
+            return Err(JweDecryptionError::UnsupportedCompression);
+        }
+
+        if self.header.crit().is_some() {
+            return Err(JweDecryptionError::UnsupportedCriticalHeader);
+        }
+
+        let alg = self.header.alg();
+        let padding = oaep_padding(alg)
+            .ok_or_else(|| JweDecryptionError::UnsupportedAlgorithm { alg: alg.clone() })?;
+
+        let cek = key
+            .decrypt(padding, &self.encrypted_key)
+            .map_err(|_| JweDecryptionError::Decryption)?;
+
+        // The additional authenticated data is the encoded protected header
+        let aad = self.protected_header.as_bytes();
+
+        match self.header.enc() {
+            JsonWebEncryptionEnc::A128CbcHs256 => decrypt_cbc_hmac::<aes::Aes128, Sha256>(
+                &cek,
+                &self.iv,
+                aad,
+                &self.ciphertext,
+                &self.tag,
+            ),
+            JsonWebEncryptionEnc::A192CbcHs384 => decrypt_cbc_hmac::<aes::Aes192, Sha384>(
+                &cek,
+                &self.iv,
+                aad,
+                &self.ciphertext,
+                &self.tag,
+            ),
+            JsonWebEncryptionEnc::A256CbcHs512 => decrypt_cbc_hmac::<aes::Aes256, Sha512>(
+                &cek,
+                &self.iv,
+                aad,
+                &self.ciphertext,
+                &self.tag,
+            ),
+            JsonWebEncryptionEnc::A128Gcm => {
+                decrypt_gcm::<aes_gcm::Aes128Gcm>(&cek, &self.iv, aad, &self.ciphertext, &self.tag)
+            }
+            JsonWebEncryptionEnc::A192Gcm => {
+                decrypt_gcm::<Aes192Gcm>(&cek, &self.iv, aad, &self.ciphertext, &self.tag)
+            }
+            JsonWebEncryptionEnc::A256Gcm => {
+                decrypt_gcm::<aes_gcm::Aes256Gcm>(&cek, &self.iv, aad, &self.ciphertext, &self.tag)
+            }
+            enc => Err(JweDecryptionError::UnsupportedEncryption { enc: enc.clone() }),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum JweEncryptionError {
+    #[error("unsupported key management algorithm {alg}")]
+    UnsupportedAlgorithm { alg: JsonWebEncryptionAlg },
+
+    #[error("unsupported content encryption algorithm {enc}")]
+    UnsupportedEncryption { enc: JsonWebEncryptionEnc },
+
+    #[error("failed to serialize header")]
+    EncodeHeader {
+        #[source]
+        inner: serde_json::Error,
+    },
+
+    #[error("failed to encrypt the content encryption key")]
+    EncryptKey {
+        #[source]
+        inner: rsa::Error,
+    },
+
+    #[error("failed to encrypt JWE")]
+    Encryption,
+}
+
+impl Jwe {
+    /// Encrypt a payload for the given RSA public key
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the algorithms in the header are not supported
+    pub fn encrypt<R>(
+        rng: &mut R,
+        header: JsonWebEncryptionHeader,
+        payload: &[u8],
+        key: &RsaPublicKey,
+    ) -> Result<Self, JweEncryptionError>
+    where
+        R: CryptoRngCore,
+    {
+        let alg = header.alg();
+        let padding = oaep_padding(alg)
+            .ok_or_else(|| JweEncryptionError::UnsupportedAlgorithm { alg: alg.clone() })?;
+
+        let protected_header = serde_json::to_vec(&header)
+            .map_err(|inner| JweEncryptionError::EncodeHeader { inner })?;
+        let protected_header = Base64UrlUnpadded::encode_string(&protected_header);
+        let aad = protected_header.as_bytes();
+
+        let (cek, iv, ciphertext, tag) = match header.enc() {
+            JsonWebEncryptionEnc::A128CbcHs256 => {
+                encrypt_cbc_hmac::<_, aes::Aes128, Sha256>(rng, aad, payload)
+            }
+            JsonWebEncryptionEnc::A192CbcHs384 => {
+                encrypt_cbc_hmac::<_, aes::Aes192, Sha384>(rng, aad, payload)
+            }
+            JsonWebEncryptionEnc::A256CbcHs512 => {
+                encrypt_cbc_hmac::<_, aes::Aes256, Sha512>(rng, aad, payload)
+            }
+            JsonWebEncryptionEnc::A128Gcm => {
+                encrypt_gcm::<_, aes_gcm::Aes128Gcm>(rng, aad, payload)
+            }
+            JsonWebEncryptionEnc::A192Gcm => encrypt_gcm::<_, Aes192Gcm>(rng, aad, payload),
+            JsonWebEncryptionEnc::A256Gcm => {
+                encrypt_gcm::<_, aes_gcm::Aes256Gcm>(rng, aad, payload)
+            }
+            enc => {
+                return Err(JweEncryptionError::UnsupportedEncryption { enc: enc.clone() });
+            }
+        }?;
+
+        let encrypted_key = key
+            .encrypt(rng, padding, &cek)
+            .map_err(|inner| JweEncryptionError::EncryptKey { inner })?;
+
+        Ok(Self {
+            protected_header,
+            header,
+            encrypted_key,
+            iv,
+            ciphertext,
+            tag,
+        })
+    }
+}
+
+impl std::fmt::Display for Jwe {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}.{}.{}.{}.{}",
+            self.protected_header,
+            Base64UrlUnpadded::encode_string(&self.encrypted_key),
+            Base64UrlUnpadded::encode_string(&self.iv),
+            Base64UrlUnpadded::encode_string(&self.ciphertext),
+            Base64UrlUnpadded::encode_string(&self.tag),
+        )
+    }
+}
+
+/// Get the RSA-OAEP padding scheme for the given key management algorithm
+fn oaep_padding(alg: &JsonWebEncryptionAlg) -> Option<Oaep> {
+    match alg {
+        JsonWebEncryptionAlg::RsaOaep => Some(Oaep::new::<sha1::Sha1>()),
+        JsonWebEncryptionAlg::RsaOaep256 => Some(Oaep::new::<Sha256>()),
+        JsonWebEncryptionAlg::RsaOaep384 => Some(Oaep::new::<Sha384>()),
+        JsonWebEncryptionAlg::RsaOaep512 => Some(Oaep::new::<Sha512>()),
+        _ => None,
+    }
+}
+
+/// The content encryption key, initialization vector, ciphertext and
+/// authentication tag of an encrypted payload
+type EncryptedContent = (Vec<u8>, Vec<u8>, Vec<u8>, Vec<u8>);
+
+/// Encrypt with AES-GCM, using a random key and initialization vector
+fn encrypt_gcm<R, C>(
+    rng: &mut R,
+    aad: &[u8],
+    payload: &[u8],
+) -> Result<EncryptedContent, JweEncryptionError>
+where
+    R: CryptoRngCore,
+    C: KeyInit + AeadInPlace,
+{
+    let mut cek = vec![0; <C as KeySizeUser>::KeySize::USIZE];
+    rng.fill_bytes(&mut cek);
+    let mut iv = vec![0; <C as AeadCore>::NonceSize::USIZE];
+    rng.fill_bytes(&mut iv);
+
+    let cipher = C::new_from_slice(&cek).map_err(|_| JweEncryptionError::Encryption)?;
+    let mut ciphertext = payload.to_vec();
+    let tag = cipher
+        .encrypt_in_place_detached(GenericArray::from_slice(&iv), aad, &mut ciphertext)
+        .map_err(|_| JweEncryptionError::Encryption)?;
+
+    Ok((cek, iv, ciphertext, tag.to_vec()))
+}
+
+/// Encrypt with AES-CBC, authenticated with HMAC-SHA2, using a random key and
+/// initialization vector
+fn encrypt_cbc_hmac<R, C, D>(
+    rng: &mut R,
+    aad: &[u8],
+    payload: &[u8],
+) -> Result<EncryptedContent, JweEncryptionError>
+where
+    R: CryptoRngCore,
+    C: BlockCipher + BlockEncryptMut + KeyInit,
+    D: Digest + BlockSizeUser,
+{
+    let key_size = <C as KeySizeUser>::KeySize::USIZE;
+    let mut cek = vec![0; key_size * 2];
+    rng.fill_bytes(&mut cek);
+    let mut iv = vec![0; <C as BlockSizeUser>::BlockSize::USIZE];
+    rng.fill_bytes(&mut iv);
+    let (mac_key, enc_key) = cek.split_at(key_size);
+
+    let ciphertext = cbc::Encryptor::<C>::new_from_slices(enc_key, &iv)
+        .map_err(|_| JweEncryptionError::Encryption)?
+        .encrypt_padded_vec_mut::<Pkcs7>(payload);
+
+    let mac = cbc_hmac_tag::<D>(mac_key, aad, &iv, &ciphertext)
+        .ok_or(JweEncryptionError::Encryption)?
+        .finalize()
+        .into_bytes();
+    let tag = mac[..key_size].to_vec();
+
+    Ok((cek, iv, ciphertext, tag))
+}
+
+/// Compute the MAC of an AES-CBC encrypted payload, over the additional
+/// authenticated data, the initialization vector, the ciphertext and the
+/// length of the additional authenticated data in bits
+fn cbc_hmac_tag<D>(
+    mac_key: &[u8],
+    aad: &[u8],
+    iv: &[u8],
+    ciphertext: &[u8],
+) -> Option<hmac::SimpleHmac<D>>
+where
+    D: Digest + BlockSizeUser,
+{
+    let aad_length = u64::try_from(aad.len()).ok()?.checked_mul(8)?;
+
+    let mut mac = <hmac::SimpleHmac<D> as Mac>::new_from_slice(mac_key).ok()?;
+    mac.update(aad);
+    mac.update(iv);
+    mac.update(ciphertext);
+    mac.update(&aad_length.to_be_bytes());
+    Some(mac)
+}
+
+/// Decrypt with AES-GCM
+///
+/// Ref: <https://www.rfc-editor.org/rfc/rfc7518#section-5.3>
+fn decrypt_gcm<C>(
+    cek: &[u8],
+    iv: &[u8],
+    aad: &[u8],
+    ciphertext: &[u8],
+    tag: &[u8],
+) -> Result<Vec<u8>, JweDecryptionError>
+where
+    C: KeyInit + AeadInPlace,
+{
+    if iv.len() != <C as AeadCore>::NonceSize::USIZE {
+        return Err(JweDecryptionError::InvalidIvLength);
+    }
+
+    if tag.len() != <C as AeadCore>::TagSize::USIZE {
+        return Err(JweDecryptionError::Decryption);
+    }
+
+    let cipher = C::new_from_slice(cek).map_err(|_| JweDecryptionError::Decryption)?;
+    let mut buffer = ciphertext.to_vec();
+    cipher
+        .decrypt_in_place_detached(
+            GenericArray::from_slice(iv),
+            aad,
+            &mut buffer,
+            GenericArray::from_slice(tag),
+        )
+        .map_err(|_| JweDecryptionError::Decryption)?;
+
+    Ok(buffer)
+}
+
+/// Decrypt with AES-CBC, authenticated with HMAC-SHA2
+///
+/// Ref: <https://www.rfc-editor.org/rfc/rfc7518#section-5.2>
+fn decrypt_cbc_hmac<C, D>(
+    cek: &[u8],
+    iv: &[u8],
+    aad: &[u8],
+    ciphertext: &[u8],
+    tag: &[u8],
+) -> Result<Vec<u8>, JweDecryptionError>
+where
+    C: BlockCipher + BlockDecryptMut + KeyInit,
+    D: Digest + BlockSizeUser,
+{
+    // The first half of the key is the MAC key, the second half the encryption
+    // key, and the tag is the MAC truncated to the same length
+    let key_size = <C as KeySizeUser>::KeySize::USIZE;
+    if cek.len() != key_size * 2 || tag.len() != key_size {
+        return Err(JweDecryptionError::Decryption);
+    }
+    let (mac_key, enc_key) = cek.split_at(key_size);
+
+    cbc_hmac_tag::<D>(mac_key, aad, iv, ciphertext)
+        .ok_or(JweDecryptionError::Decryption)?
+        .verify_truncated_left(tag)
+        .map_err(|_| JweDecryptionError::Decryption)?;
+
+    let decryptor = cbc::Decryptor::<C>::new_from_slices(enc_key, iv)
+        .map_err(|_| JweDecryptionError::InvalidIvLength)?;
+    decryptor
+        .decrypt_padded_vec_mut::<Pkcs7>(ciphertext)
+        .map_err(|_| JweDecryptionError::Decryption)
+}
@@ -0,0 +1,49 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Encryption and decryption of JSON Web Encryption tokens in the compact
+//! serialization
+//!
+//! Only the RSA-OAEP key management algorithms are supported, with either the
+//! AES-GCM or the AES-CBC with HMAC-SHA2 content encryption algorithms.
+//!
+//! Ref: <https://www.rfc-editor.org/rfc/rfc7516.html>
+
+use mas_iana::jose::{JsonWebEncryptionAlg, JsonWebEncryptionEnc};
+
+mod encrypted;
+mod header;
+
+pub use self::{
+    encrypted::{Jwe, JweDecodeError, JweDecryptionError, JweEncryptionError},
+    header::JsonWebEncryptionHeader,
+};
+
+/// All the key management algorithms supported by this crate.
+pub const SUPPORTED_KEY_MANAGEMENT_ALGORITHMS: [JsonWebEncryptionAlg; 4] = [
+    JsonWebEncryptionAlg::RsaOaep,
+    JsonWebEncryptionAlg::RsaOaep256,
+    JsonWebEncryptionAlg::RsaOaep384,
+    JsonWebEncryptionAlg::RsaOaep512,
+];
+
+/// All the content encryption algorithms supported by this crate.
+pub const SUPPORTED_CONTENT_ENCRYPTION_ALGORITHMS: [JsonWebEncryptionEnc; 6] = [
+    JsonWebEncryptionEnc::A128CbcHs256,
+    JsonWebEncryptionEnc::A192CbcHs384,
+    JsonWebEncryptionEnc::A256CbcHs512,
+    JsonWebEncryptionEnc::A128Gcm,
+    JsonWebEncryptionEnc::A192Gcm,
+    JsonWebEncryptionEnc::A256Gcm,
+];
@@ -332,6 +332,8 @@ impl<P> JsonWebKeySet<P> {
         let mut algs: Vec<_> = self
             .keys
             .iter()
+            // Keys dedicated to encryption can't sign anything
+            .filter(|key| key.use_() != Some(&mas_iana::jose::JsonWebKeyUse::Enc))
             .flat_map(|key| key.params().possible_algs())
             .cloned()
             .collect();
@@ -21,6 +21,7 @@ mod base64;
 pub mod claims;
 pub mod constraints;
 pub mod jwa;
+pub mod jwe;
 pub mod jwk;
 pub mod jwt;
 
@@ -1,4 +1,4 @@
-# Generates test keys, JWKS and JWTs
+# Generates test keys, JWKS, JWTs and JWEs
 # Required the `openssl` binary and the `authlib` python library
 
 import json
@@ -6,7 +6,7 @@ import subprocess
 from pathlib import Path
 from typing import List
 
-from authlib.jose import JsonWebKey, JsonWebSignature, KeySet
+from authlib.jose import JsonWebEncryption, JsonWebKey, JsonWebSignature, KeySet
 
 output_path = Path(__file__).parent
 
@@ -16,6 +16,9 @@ keys_path.mkdir(parents=True, exist_ok=True)
 jwts_path = output_path / "jwts"
 jwts_path.mkdir(parents=True, exist_ok=True)
 
+jwes_path = output_path / "jwes"
+jwes_path.mkdir(parents=True, exist_ok=True)
+
 
 def gen_key(
     name: str,
@@ -60,6 +63,17 @@ def sign_jwt(alg: str, filename: str, key: JsonWebKey):
         f.write(jwt)
 
 
+def encrypt_jwe(alg: str, enc: str, key: JsonWebKey):
+    """Encrypt a JWE for the given key"""
+    path = jwes_path / f"{alg.lower()}-{enc.lower()}.jwe"
+    protected = {"alg": alg, "enc": enc, "kid": key.thumbprint()}
+    payload = '{"hello":"world"}'
+    jwe = JsonWebEncryption(algorithms=[alg, enc])
+    token = jwe.serialize_compact(protected, payload, key)
+    with open(path, "wb") as f:
+        f.write(token)
+
+
 with open(keys_path / "oct.bin", "wb") as f:
     subprocess.run(
         ["openssl", "rand", "-hex", "64"], stdout=f, stderr=subprocess.DEVNULL
@@ -136,3 +150,14 @@ sign_jwt("ES512", "es512.jwt", p521_key)
 sign_jwt("ES256K", "es256k.jwt", k256_key)
 sign_jwt("EdDSA", "eddsa-ed25519.jwt", ed25519_key)
 sign_jwt("EdDSA", "eddsa-ed448.jwt", ed448_key)
+
+for alg in ["RSA-OAEP", "RSA-OAEP-256"]:
+    for enc in [
+        "A128CBC-HS256",
+        "A192CBC-HS384",
+        "A256CBC-HS512",
+        "A128GCM",
+        "A192GCM",
+        "A256GCM",
+    ]:
+        encrypt_jwe(alg, enc, rsa_key)
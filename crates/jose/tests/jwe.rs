@@ -0,0 +1,199 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use base64ct::Encoding;
+use mas_jose::{
+    constraints::Constrainable,
+    jwe::{Jwe, JweDecryptionError},
+};
+
+static RSA_OAEP_A128CBC_HS256_JWE: &str = include_str!("./jwes/rsa-oaep-a128cbc-hs256.jwe");
+static RSA_OAEP_A192CBC_HS384_JWE: &str = include_str!("./jwes/rsa-oaep-a192cbc-hs384.jwe");
+static RSA_OAEP_A256CBC_HS512_JWE: &str = include_str!("./jwes/rsa-oaep-a256cbc-hs512.jwe");
+static RSA_OAEP_A128GCM_JWE: &str = include_str!("./jwes/rsa-oaep-a128gcm.jwe");
+static RSA_OAEP_A192GCM_JWE: &str = include_str!("./jwes/rsa-oaep-a192gcm.jwe");
+static RSA_OAEP_A256GCM_JWE: &str = include_str!("./jwes/rsa-oaep-a256gcm.jwe");
+static RSA_OAEP_256_A128CBC_HS256_JWE: &str = include_str!("./jwes/rsa-oaep-256-a128cbc-hs256.jwe");
+static RSA_OAEP_256_A192CBC_HS384_JWE: &str = include_str!("./jwes/rsa-oaep-256-a192cbc-hs384.jwe");
+static RSA_OAEP_256_A256CBC_HS512_JWE: &str = include_str!("./jwes/rsa-oaep-256-a256cbc-hs512.jwe");
+static RSA_OAEP_256_A128GCM_JWE: &str = include_str!("./jwes/rsa-oaep-256-a128gcm.jwe");
+static RSA_OAEP_256_A192GCM_JWE: &str = include_str!("./jwes/rsa-oaep-256-a192gcm.jwe");
+static RSA_OAEP_256_A256GCM_JWE: &str = include_str!("./jwes/rsa-oaep-256-a256gcm.jwe");
+
+fn private_jwks() -> mas_jose::jwk::PrivateJsonWebKeySet {
+    serde_json::from_str(include_str!("./keys/jwks.priv.json")).unwrap()
+}
+
+fn rsa_key(kid: Option<&str>) -> rsa::RsaPrivateKey {
+    let jwks = private_jwks();
+    let key = jwks
+        .iter()
+        .filter(|key| kid.is_none() || key.kid() == kid)
+        .find_map(|key| key.params().rsa())
+        .unwrap();
+
+    key.try_into().unwrap()
+}
+
+fn decrypt(jwe: &Jwe) -> Result<Vec<u8>, JweDecryptionError> {
+    jwe.decrypt(&rsa_key(jwe.header().kid()))
+}
+
+#[derive(serde::Deserialize)]
+struct Payload {
+    hello: String,
+}
+
+macro_rules! jwe_test {
+    ($test_name:ident, $alg:ident, $enc:ident, $jwe:ident) => {
+        mod $test_name {
+            use mas_iana::jose::{JsonWebEncryptionAlg, JsonWebEncryptionEnc};
+            use mas_jose::jwe::JsonWebEncryptionHeader;
+            use rand::SeedableRng;
+            use rand_chacha::ChaCha8Rng;
+
+            use super::*;
+
+            #[test]
+            fn decode_jwe() {
+                let jwe = Jwe::try_from($jwe).unwrap();
+                assert_eq!(*jwe.header().alg(), JsonWebEncryptionAlg::$alg);
+                assert_eq!(*jwe.header().enc(), JsonWebEncryptionEnc::$enc);
+            }
+
+            #[test]
+            fn decrypt_jwe() {
+                let jwe = Jwe::try_from($jwe).unwrap();
+                let payload = decrypt(&jwe).unwrap();
+                let payload: Payload = serde_json::from_slice(&payload).unwrap();
+                assert_eq!(payload.hello, "world");
+            }
+
+            #[test]
+            fn decrypt_tampered_jwe() {
+                // Change the first character of the authentication tag
+                let (rest, tag) = $jwe.rsplit_once('.').unwrap();
+                let replacement = if tag.starts_with('A') { "B" } else { "A" };
+                let tampered = format!("{rest}.{replacement}{}", &tag[1..]);
+
+                let jwe = Jwe::try_from(tampered.as_str()).unwrap();
+                assert!(matches!(decrypt(&jwe), Err(JweDecryptionError::Decryption)));
+            }
+
+            #[test]
+            fn encrypt_and_decrypt_jwe() {
+                let mut rng = ChaCha8Rng::seed_from_u64(42);
+                let key = rsa_key(None);
+                let header = JsonWebEncryptionHeader::new(
+                    JsonWebEncryptionAlg::$alg,
+                    JsonWebEncryptionEnc::$enc,
+                );
+
+                let jwe = Jwe::encrypt(
+                    &mut rng,
+                    header,
+                    br#"{"hello":"world"}"#,
+                    &key.to_public_key(),
+                )
+                .unwrap();
+
+                // Go through the compact serialization
+                let jwe = Jwe::try_from(jwe.to_string().as_str()).unwrap();
+                let payload = jwe.decrypt(&key).unwrap();
+                let payload: Payload = serde_json::from_slice(&payload).unwrap();
+                assert_eq!(payload.hello, "world");
+            }
+        }
+    };
+}
+
+jwe_test!(
+    rsa_oaep_a128cbc_hs256,
+    RsaOaep,
+    A128CbcHs256,
+    RSA_OAEP_A128CBC_HS256_JWE
+);
+jwe_test!(
+    rsa_oaep_a192cbc_hs384,
+    RsaOaep,
+    A192CbcHs384,
+    RSA_OAEP_A192CBC_HS384_JWE
+);
+jwe_test!(
+    rsa_oaep_a256cbc_hs512,
+    RsaOaep,
+    A256CbcHs512,
+    RSA_OAEP_A256CBC_HS512_JWE
+);
+jwe_test!(rsa_oaep_a128gcm, RsaOaep, A128Gcm, RSA_OAEP_A128GCM_JWE);
+jwe_test!(rsa_oaep_a192gcm, RsaOaep, A192Gcm, RSA_OAEP_A192GCM_JWE);
+jwe_test!(rsa_oaep_a256gcm, RsaOaep, A256Gcm, RSA_OAEP_A256GCM_JWE);
+jwe_test!(
+    rsa_oaep_256_a128cbc_hs256,
+    RsaOaep256,
+    A128CbcHs256,
+    RSA_OAEP_256_A128CBC_HS256_JWE
+);
+jwe_test!(
+    rsa_oaep_256_a192cbc_hs384,
+    RsaOaep256,
+    A192CbcHs384,
+    RSA_OAEP_256_A192CBC_HS384_JWE
+);
+jwe_test!(
+    rsa_oaep_256_a256cbc_hs512,
+    RsaOaep256,
+    A256CbcHs512,
+    RSA_OAEP_256_A256CBC_HS512_JWE
+);
+jwe_test!(
+    rsa_oaep_256_a128gcm,
+    RsaOaep256,
+    A128Gcm,
+    RSA_OAEP_256_A128GCM_JWE
+);
+jwe_test!(
+    rsa_oaep_256_a192gcm,
+    RsaOaep256,
+    A192Gcm,
+    RSA_OAEP_256_A192GCM_JWE
+);
+jwe_test!(
+    rsa_oaep_256_a256gcm,
+    RsaOaep256,
+    A256Gcm,
+    RSA_OAEP_256_A256GCM_JWE
+);
+
+#[test]
+fn decode_jws_as_jwe() {
+    let jws = include_str!("./jwts/rs256.jwt");
+    assert!(Jwe::try_from(jws).is_err());
+}
+
+#[test]
+fn decrypt_compressed_jwe() {
+    // Replace the protected header with one asking for compression
+    let (_, rest) = RSA_OAEP_A128GCM_JWE.split_once('.').unwrap();
+    let header = base64ct::Base64UrlUnpadded::encode_string(
+        br#"{"alg":"RSA-OAEP","enc":"A128GCM","zip":"DEF"}"#,
+    );
+    let jwe = format!("{header}.{rest}");
+
+    let jwe = Jwe::try_from(jwe.as_str()).unwrap();
+    assert!(matches!(
+        jwe.decrypt(&rsa_key(None)),
+        Err(JweDecryptionError::UnsupportedCompression)
+    ));
+}
@@ -21,52 +21,109 @@ use generic_array::GenericArray;
 use thiserror::Error;
 
 /// Helps encrypting and decrypting data
+///
+/// It encrypts with its current key, and can decrypt with any of its keys.
+/// Keys can have a key ID, which is then prepended to the payloads encrypted
+/// with [`Encrypter::encrypt_to_string`], so that the right key is picked when
+/// decrypting them after the current key was rotated.
 #[derive(Clone)]
 pub struct Encrypter {
+    /// The keys, the first one being the current one
+    keys: Arc<[EncryptionKey]>,
+}
+
+#[derive(Clone)]
+struct EncryptionKey {
+    kid: Option<Arc<str>>,
     aead: Arc<ChaCha20Poly1305>,
 }
 
+impl EncryptionKey {
+    fn new(kid: Option<&str>, key: &[u8; 32]) -> Self {
+        let key = GenericArray::from_slice(key);
+        let aead = ChaCha20Poly1305::new(key);
+        Self {
+            kid: kid.map(Arc::from),
+            aead: Arc::new(aead),
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 #[error("Decryption error")]
 pub enum DecryptError {
     Aead(#[from] aead::Error),
     Base64(#[from] base64ct::Error),
     Shape,
+    UnknownKey,
 }
 
 impl Encrypter {
-    /// Creates an [`Encrypter`] out of an encryption key
+    /// Creates an [`Encrypter`] out of an encryption key without a key ID
     #[must_use]
     pub fn new(key: &[u8; 32]) -> Self {
-        let key = GenericArray::from_slice(key);
-        let aead = ChaCha20Poly1305::new(key);
-        let aead = Arc::new(aead);
-        Self { aead }
+        Self {
+            keys: Arc::new([EncryptionKey::new(None, key)]),
+        }
     }
 
-    /// Encrypt a payload
+    /// Creates an [`Encrypter`] out of an encryption key identified by `kid`
+    ///
+    /// The key ID must not contain a `:`
+    #[must_use]
+    pub fn with_kid(kid: &str, key: &[u8; 32]) -> Self {
+        Self {
+            keys: Arc::new([EncryptionKey::new(Some(kid), key)]),
+        }
+    }
+
+    /// Add a previous key, only used to decrypt payloads which were encrypted
+    /// with it
+    #[must_use]
+    pub fn with_previous_key(self, kid: Option<&str>, key: &[u8; 32]) -> Self {
+        let keys = self
+            .keys
+            .iter()
+            .cloned()
+            .chain(std::iter::once(EncryptionKey::new(kid, key)))
+            .collect();
+        Self { keys }
+    }
+
+    fn current(&self) -> &EncryptionKey {
+        &self.keys[0]
+    }
+
+    /// The ID of the key used to encrypt new payloads, if it has one
+    #[must_use]
+    pub fn current_kid(&self) -> Option<&str> {
+        self.current().kid.as_deref()
+    }
+
+    /// Encrypt a payload with the current key
     ///
     /// # Errors
     ///
     /// Will return `Err` when the payload failed to encrypt
     pub fn encrypt(&self, nonce: &[u8; 12], decrypted: &[u8]) -> Result<Vec<u8>, aead::Error> {
         let nonce = GenericArray::from_slice(&nonce[..]);
-        let encrypted = self.aead.encrypt(nonce, decrypted)?;
+        let encrypted = self.current().aead.encrypt(nonce, decrypted)?;
         Ok(encrypted)
     }
 
-    /// Decrypts a payload
+    /// Decrypts a payload with the current key
     ///
     /// # Errors
     ///
     /// Will return `Err` when the payload failed to decrypt
     pub fn decrypt(&self, nonce: &[u8; 12], encrypted: &[u8]) -> Result<Vec<u8>, aead::Error> {
         let nonce = GenericArray::from_slice(&nonce[..]);
-        let encrypted = self.aead.decrypt(nonce, encrypted)?;
+        let encrypted = self.current().aead.decrypt(nonce, encrypted)?;
         Ok(encrypted)
     }
 
-    /// Encrypt a payload to a self-contained base64-encoded string
+    /// Encrypt a payload to a self-contained base64-encoded string, prefixed
+    /// with the ID of the current key if it has one
     ///
     /// # Errors
     ///
@@ -76,27 +133,87 @@ impl Encrypter {
         let encrypted = self.encrypt(&nonce, decrypted)?;
         let encrypted = [&nonce[..], &encrypted].concat();
         let encrypted = Base64::encode_string(&encrypted);
-        Ok(encrypted)
+        match self.current_kid() {
+            Some(kid) => Ok(format!("{kid}:{encrypted}")),
+            None => Ok(encrypted),
+        }
     }
 
-    /// Decrypt a payload from a self-contained base64-encoded string
+    /// Decrypt a payload from a self-contained base64-encoded string, with the
+    /// key it was encrypted with
     ///
     /// # Errors
     ///
-    /// Will return `Err` when the payload failed to decrypt
+    /// Will return `Err` when the payload failed to decrypt, or when the key
+    /// it was encrypted with is not known
     pub fn decrypt_string(&self, encrypted: &str) -> Result<Vec<u8>, DecryptError> {
+        // The base64 alphabet doesn't have `:`, so payloads without a key ID are
+        // unambiguous
+        let (kid, encrypted) = match encrypted.split_once(':') {
+            Some((kid, encrypted)) => (Some(kid), encrypted),
+            None => (None, encrypted),
+        };
+
+        let key = self
+            .keys
+            .iter()
+            .find(|key| key.kid.as_deref() == kid)
+            .ok_or(DecryptError::UnknownKey)?;
+
         let encrypted = Base64::decode_vec(encrypted)?;
 
-        let nonce: &[u8; 12] = encrypted
-            .get(0..12)
-            .ok_or(DecryptError::Shape)?
-            .try_into()
-            .map_err(|_| DecryptError::Shape)?;
+        let nonce = encrypted.get(0..12).ok_or(DecryptError::Shape)?;
+        let nonce = GenericArray::from_slice(nonce);
 
         let payload = encrypted.get(12..).ok_or(DecryptError::Shape)?;
 
-        let decrypted_client_secret = self.decrypt(nonce, payload)?;
+        let decrypted_client_secret = key.aead.decrypt(nonce, payload)?;
 
         Ok(decrypted_client_secret)
     }
+
+    /// Whether a payload encrypted with [`Encrypter::encrypt_to_string`] was
+    /// encrypted with another key than the current one
+    #[must_use]
+    pub fn is_outdated(&self, encrypted: &str) -> bool {
+        let kid = encrypted.split_once(':').map(|(kid, _)| kid);
+        kid != self.current_kid()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_rotation() {
+        let old = Encrypter::new(&[0x42; 32]);
+        let legacy = old.encrypt_to_string(b"legacy").unwrap();
+
+        let previous = Encrypter::with_kid("1", &[0x43; 32]).with_previous_key(None, &[0x42; 32]);
+        assert_eq!(previous.decrypt_string(&legacy).unwrap(), b"legacy");
+        assert!(previous.is_outdated(&legacy));
+
+        let versioned = previous.encrypt_to_string(b"versioned").unwrap();
+        assert!(versioned.starts_with("1:"));
+        assert!(!previous.is_outdated(&versioned));
+
+        let current = Encrypter::with_kid("2", &[0x44; 32])
+            .with_previous_key(Some("1"), &[0x43; 32])
+            .with_previous_key(None, &[0x42; 32]);
+        assert_eq!(current.decrypt_string(&legacy).unwrap(), b"legacy");
+        assert_eq!(current.decrypt_string(&versioned).unwrap(), b"versioned");
+        assert!(current.is_outdated(&versioned));
+
+        // Once the previous keys are retired, their payloads can't be decrypted
+        let retired = Encrypter::with_kid("2", &[0x44; 32]);
+        assert!(matches!(
+            retired.decrypt_string(&versioned),
+            Err(DecryptError::UnknownKey)
+        ));
+        assert!(matches!(
+            retired.decrypt_string(&legacy),
+            Err(DecryptError::UnknownKey)
+        ));
+    }
 }
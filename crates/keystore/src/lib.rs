@@ -22,10 +22,12 @@ use std::{ops::Deref, sync::Arc};
 
 use der::{zeroize::Zeroizing, Decode, Encode, EncodePem};
 use elliptic_curve::{pkcs8::EncodePrivateKey, sec1::ToEncodedPoint};
-use mas_iana::jose::{JsonWebKeyType, JsonWebSignatureAlg};
+use mas_iana::jose::{JsonWebKeyType, JsonWebKeyUse, JsonWebSignatureAlg};
 pub use mas_jose::jwk::{JsonWebKey, JsonWebKeySet};
 use mas_jose::{
+    constraints::Constrainable,
     jwa::{AsymmetricSigningKey, AsymmetricVerifyingKey},
+    jwe::{Jwe, JweDecryptionError},
     jwk::{JsonWebKeyPublicParameters, ParametersInfo, PublicJsonWebKeySet},
 };
 use pem_rfc7468::PemLabel;
@@ -657,6 +659,64 @@ impl Keystore {
             })
             .collect()
     }
+
+    /// Get the public JSON Web Key Set of the keys which can be used to
+    /// encrypt tokens for us
+    ///
+    /// Only the RSA keys dedicated to encryption, with `use` set to `enc`, are
+    /// usable for encryption.
+    #[must_use]
+    pub fn public_encryption_jwks(&self) -> PublicJsonWebKeySet {
+        let keys = self
+            .encryption_keys()
+            .map(|(key, _)| {
+                let mut public = JsonWebKey::new(JsonWebKeyPublicParameters::from(key.params()))
+                    .with_use(JsonWebKeyUse::Enc);
+                if let Some(kid) = key.kid() {
+                    public = public.with_kid(kid);
+                }
+                public
+            })
+            .collect();
+
+        JsonWebKeySet::new(keys)
+    }
+
+    /// Decrypt a JSON Web Encryption token with the keys stored in this
+    /// [`Keystore`]
+    ///
+    /// If the token references a key ID, only the key with this ID is tried.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the token uses an unsupported algorithm, or if none
+    /// of the keys decrypt it
+    pub fn decrypt(&self, jwe: &Jwe) -> Result<Vec<u8>, JweDecryptionError> {
+        let kid = jwe.header().kid();
+        let candidates = self
+            .encryption_keys()
+            .filter(|(key, _)| kid.is_none() || key.kid() == kid);
+
+        for (_, key) in candidates {
+            match jwe.decrypt(key) {
+                Ok(payload) => return Ok(payload),
+                // Try the next key
+                Err(JweDecryptionError::Decryption) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(JweDecryptionError::Decryption)
+    }
+
+    fn encryption_keys(
+        &self,
+    ) -> impl Iterator<Item = (&JsonWebKey<PrivateKey>, &rsa::RsaPrivateKey)> {
+        self.keys.iter().filter_map(|key| match key.params() {
+            PrivateKey::Rsa(rsa) if key.use_() == Some(&JsonWebKeyUse::Enc) => Some((key, &**rsa)),
+            _ => None,
+        })
+    }
 }
 
 impl Deref for Keystore {
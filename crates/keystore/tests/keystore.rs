@@ -13,8 +13,12 @@
 // limitations under the License.
 
 use der::pem::LineEnding;
-use mas_iana::jose::JsonWebSignatureAlg;
+use mas_iana::jose::{
+    JsonWebEncryptionAlg, JsonWebEncryptionEnc, JsonWebKeyUse, JsonWebSignatureAlg,
+};
 use mas_jose::{
+    constraints::Constrainable,
+    jwe::{JsonWebEncryptionHeader, Jwe},
     jwk::ParametersInfo,
     jwt::{JsonWebSignatureHeader, Jwt},
 };
@@ -211,3 +215,73 @@ fn generate_sign_and_verify() {
         token.verify_with_jwks(&jwks).unwrap();
     }
 }
+
+#[test]
+fn encrypt_and_decrypt() {
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(42);
+
+    let rsa = PrivateKey::load_pem(include_str!("./keys/rsa.pkcs1.pem")).unwrap();
+    let PrivateKey::Rsa(public_key) = &rsa else {
+        panic!("wrong key type");
+    };
+    let public_key = public_key.to_public_key();
+
+    // The same key, but restricted to signing, and without a use
+    let rsa_sig = PrivateKey::load_pem(include_str!("./keys/rsa.pkcs8.pem")).unwrap();
+    let rsa_any = PrivateKey::load_pem(include_str!("./keys/rsa.pkcs8.pem")).unwrap();
+    let ec_p256 = PrivateKey::load_pem(include_str!("./keys/ec-p256.sec1.pem")).unwrap();
+
+    let keyset = Keystore::new(JsonWebKeySet::new(vec![
+        JsonWebKey::new(rsa)
+            .with_kid("enc")
+            .with_use(JsonWebKeyUse::Enc),
+        JsonWebKey::new(rsa_sig)
+            .with_kid("sig")
+            .with_use(JsonWebKeyUse::Sig),
+        JsonWebKey::new(rsa_any).with_kid("any"),
+        JsonWebKey::new(ec_p256)
+            .with_kid("ec")
+            .with_use(JsonWebKeyUse::Enc),
+    ]));
+
+    // Only the RSA key dedicated to encryption is published
+    let jwks = keyset.public_encryption_jwks();
+    assert_eq!(jwks.len(), 1);
+    assert_eq!(jwks[0].kid(), Some("enc"));
+    assert_eq!(jwks[0].use_(), Some(&JsonWebKeyUse::Enc));
+
+    let header = JsonWebEncryptionHeader::new(
+        JsonWebEncryptionAlg::RsaOaep256,
+        JsonWebEncryptionEnc::A128CbcHs256,
+    );
+    let jwe = Jwe::encrypt(&mut rng, header.clone(), b"hello", &public_key).unwrap();
+    assert_eq!(keyset.decrypt(&jwe).unwrap(), b"hello");
+
+    let jwe = Jwe::encrypt(
+        &mut rng,
+        header.clone().with_kid("enc"),
+        b"hello",
+        &public_key,
+    )
+    .unwrap();
+    assert_eq!(keyset.decrypt(&jwe).unwrap(), b"hello");
+
+    // Keys not dedicated to encryption are not used for decryption
+    let jwe = Jwe::encrypt(
+        &mut rng,
+        header.clone().with_kid("sig"),
+        b"hello",
+        &public_key,
+    )
+    .unwrap();
+    assert!(keyset.decrypt(&jwe).is_err());
+
+    let jwe = Jwe::encrypt(&mut rng, header.with_kid("any"), b"hello", &public_key).unwrap();
+    assert!(keyset.decrypt(&jwe).is_err());
+
+    // The encryption key can't be used for signing
+    let signing = keyset
+        .signing_key_for_algorithm(&JsonWebSignatureAlg::Rs256)
+        .unwrap();
+    assert_ne!(signing.kid(), Some("enc"));
+}
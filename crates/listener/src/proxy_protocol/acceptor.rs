@@ -16,7 +16,7 @@ use bytes::BytesMut;
 use thiserror::Error;
 use tokio::io::{AsyncRead, AsyncReadExt};
 
-use super::ProxyProtocolV1Info;
+use super::{v2::SIGNATURE as V2_SIGNATURE, ProxyProtocolInfo};
 use crate::rewind::Rewind;
 
 #[derive(Clone, Copy, Debug)]
@@ -28,6 +28,7 @@ pub struct ProxyAcceptor {
 #[error(transparent)]
 pub enum ProxyAcceptError {
     Parse(#[from] super::v1::ParseError),
+    ParseV2(#[from] super::v2::ParseError),
     Read(#[from] std::io::Error),
 }
 
@@ -39,6 +40,9 @@ impl ProxyAcceptor {
 
     /// Accept a proxy-protocol stream
     ///
+    /// Both versions of the protocol are accepted, the version being detected
+    /// from the preamble.
+    ///
     /// # Errors
     ///
     /// Returns an error on read error on the underlying stream, or when the
@@ -46,18 +50,29 @@ impl ProxyAcceptor {
     pub async fn accept<T>(
         &self,
         mut stream: T,
-    ) -> Result<(ProxyProtocolV1Info, Rewind<T>), ProxyAcceptError>
+    ) -> Result<(ProxyProtocolInfo, Rewind<T>), ProxyAcceptError>
     where
         T: AsyncRead + Unpin,
     {
         let mut buf = BytesMut::new();
         let info = loop {
-            stream.read_buf(&mut buf).await?;
+            if stream.read_buf(&mut buf).await? == 0 {
+                return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+            }
 
-            match ProxyProtocolV1Info::parse(&mut buf) {
-                Ok(info) => break info,
-                Err(e) if e.not_enough_bytes() => {}
-                Err(e) => return Err(e.into()),
+            // The v1 preamble starts with "PROXY", the v2 one with a binary signature
+            if buf.first() == V2_SIGNATURE.first() {
+                match ProxyProtocolInfo::parse_v2(&mut buf) {
+                    Ok(info) => break info,
+                    Err(e) if e.not_enough_bytes() => {}
+                    Err(e) => return Err(e.into()),
+                }
+            } else {
+                match ProxyProtocolInfo::parse_v1(&mut buf) {
+                    Ok(info) => break info,
+                    Err(e) if e.not_enough_bytes() => {}
+                    Err(e) => return Err(e.into()),
+                }
             }
         };
 
@@ -14,7 +14,7 @@
 
 use tokio::io::AsyncRead;
 
-use super::{acceptor::ProxyAcceptError, ProxyAcceptor, ProxyProtocolV1Info};
+use super::{acceptor::ProxyAcceptError, ProxyAcceptor, ProxyProtocolInfo};
 use crate::rewind::Rewind;
 
 #[derive(Clone, Copy)]
@@ -59,7 +59,7 @@ impl MaybeProxyAcceptor {
     pub async fn accept<T>(
         &self,
         stream: T,
-    ) -> Result<(Option<ProxyProtocolV1Info>, Rewind<T>), ProxyAcceptError>
+    ) -> Result<(Option<ProxyProtocolInfo>, Rewind<T>), ProxyAcceptError>
     where
         T: AsyncRead + Unpin,
     {
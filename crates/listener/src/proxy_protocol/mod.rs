@@ -15,9 +15,10 @@
 mod acceptor;
 mod maybe;
 mod v1;
+mod v2;
 
 pub use self::{
     acceptor::{ProxyAcceptError, ProxyAcceptor},
     maybe::MaybeProxyAcceptor,
-    v1::ProxyProtocolV1Info,
+    v1::ProxyProtocolInfo,
 };
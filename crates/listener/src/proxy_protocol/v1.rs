@@ -22,7 +22,7 @@ use bytes::Buf;
 use thiserror::Error;
 
 #[derive(Debug, Clone)]
-pub enum ProxyProtocolV1Info {
+pub enum ProxyProtocolInfo {
     Tcp {
         source: SocketAddr,
         destination: SocketAddr,
@@ -59,9 +59,9 @@ impl ParseError {
     }
 }
 
-impl ProxyProtocolV1Info {
+impl ProxyProtocolInfo {
     #[allow(clippy::too_many_lines)]
-    pub(super) fn parse<B>(buf: &mut B) -> Result<Self, ParseError>
+    pub(super) fn parse_v1<B>(buf: &mut B) -> Result<Self, ParseError>
     where
         B: Buf + AsRef<[u8]>,
     {
@@ -263,7 +263,7 @@ mod tests {
     fn test_parse() {
         let mut buf =
             b"PROXY TCP4 255.255.255.255 255.255.255.255 65535 65535\r\nhello world".as_slice();
-        let info = ProxyProtocolV1Info::parse(&mut buf).unwrap();
+        let info = ProxyProtocolInfo::parse_v1(&mut buf).unwrap();
         assert_eq!(buf, b"hello world");
         assert!(info.is_tcp());
         assert!(!info.is_udp());
@@ -274,7 +274,7 @@ mod tests {
         let mut buf =
             b"PROXY TCP6 ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff 65535 65535\r\nhello world"
             .as_slice();
-        let info = ProxyProtocolV1Info::parse(&mut buf).unwrap();
+        let info = ProxyProtocolInfo::parse_v1(&mut buf).unwrap();
         assert_eq!(buf, b"hello world");
         assert!(info.is_tcp());
         assert!(!info.is_udp());
@@ -283,7 +283,7 @@ mod tests {
         assert!(info.is_ipv6());
 
         let mut buf = b"PROXY UNKNOWN\r\nhello world".as_slice();
-        let info = ProxyProtocolV1Info::parse(&mut buf).unwrap();
+        let info = ProxyProtocolInfo::parse_v1(&mut buf).unwrap();
         assert_eq!(buf, b"hello world");
         assert!(!info.is_tcp());
         assert!(!info.is_udp());
@@ -294,7 +294,7 @@ mod tests {
         let mut buf =
             b"PROXY UNKNOWN ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff 65535 65535\r\nhello world"
             .as_slice();
-        let info = ProxyProtocolV1Info::parse(&mut buf).unwrap();
+        let info = ProxyProtocolInfo::parse_v1(&mut buf).unwrap();
         assert_eq!(buf, b"hello world");
         assert!(!info.is_tcp());
         assert!(!info.is_udp());
@@ -0,0 +1,206 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use bytes::Buf;
+use thiserror::Error;
+
+use super::ProxyProtocolInfo;
+
+/// The signature starting every proxy protocol v2 header
+pub(super) const SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Length of the fixed part of the header: the signature, the version and
+/// command, the address family and protocol, and the length of the rest
+const HEADER_LENGTH: usize = 16;
+
+#[derive(Error, Debug)]
+#[error("Invalid proxy protocol v2 header")]
+pub enum ParseError {
+    #[error("Not enough bytes provided")]
+    NotEnoughBytes,
+    NoSignature,
+    InvalidVersion,
+    InvalidCommand,
+    InvalidAddressFamily,
+    AddressesTooShort,
+}
+
+impl ParseError {
+    pub const fn not_enough_bytes(&self) -> bool {
+        matches!(self, &Self::NotEnoughBytes)
+    }
+}
+
+impl ProxyProtocolInfo {
+    pub(super) fn parse_v2<B>(buf: &mut B) -> Result<Self, ParseError>
+    where
+        B: Buf + AsRef<[u8]>,
+    {
+        use ParseError as E;
+
+        let bytes = buf.as_ref();
+        if bytes.len() < HEADER_LENGTH {
+            return Err(E::NotEnoughBytes);
+        }
+
+        if bytes[..SIGNATURE.len()] != SIGNATURE {
+            return Err(E::NoSignature);
+        }
+
+        let version = bytes[12] >> 4;
+        let command = bytes[12] & 0x0F;
+        let family = bytes[13] >> 4;
+        let protocol = bytes[13] & 0x0F;
+        let length = usize::from(u16::from_be_bytes([bytes[14], bytes[15]]));
+
+        if version != 2 {
+            return Err(E::InvalidVersion);
+        }
+
+        let Some(addresses) = bytes.get(HEADER_LENGTH..HEADER_LENGTH + length) else {
+            return Err(E::NotEnoughBytes);
+        };
+
+        let result = match command {
+            // The connection was made by the proxy itself, e.g. for health checks
+            0x0 => Self::Unknown,
+
+            0x1 => {
+                let (source, destination) = match family {
+                    // AF_INET
+                    0x1 => {
+                        let a = addresses.get(..12).ok_or(E::AddressesTooShort)?;
+                        let source_address = Ipv4Addr::new(a[0], a[1], a[2], a[3]);
+                        let destination_address = Ipv4Addr::new(a[4], a[5], a[6], a[7]);
+                        let source_port = u16::from_be_bytes([a[8], a[9]]);
+                        let destination_port = u16::from_be_bytes([a[10], a[11]]);
+                        (
+                            SocketAddr::from((source_address, source_port)),
+                            SocketAddr::from((destination_address, destination_port)),
+                        )
+                    }
+
+                    // AF_INET6
+                    0x2 => {
+                        let a = addresses.get(..36).ok_or(E::AddressesTooShort)?;
+                        let mut source_address = [0; 16];
+                        source_address.copy_from_slice(&a[..16]);
+                        let mut destination_address = [0; 16];
+                        destination_address.copy_from_slice(&a[16..32]);
+                        let source_port = u16::from_be_bytes([a[32], a[33]]);
+                        let destination_port = u16::from_be_bytes([a[34], a[35]]);
+                        (
+                            SocketAddr::from((Ipv6Addr::from(source_address), source_port)),
+                            SocketAddr::from((
+                                Ipv6Addr::from(destination_address),
+                                destination_port,
+                            )),
+                        )
+                    }
+
+                    // AF_UNSPEC and AF_UNIX don't carry IP addresses
+                    0x0 | 0x3 => {
+                        buf.advance(HEADER_LENGTH + length);
+                        return Ok(Self::Unknown);
+                    }
+
+                    _ => return Err(E::InvalidAddressFamily),
+                };
+
+                match protocol {
+                    // STREAM
+                    0x1 => Self::Tcp {
+                        source,
+                        destination,
+                    },
+                    // DGRAM
+                    0x2 => Self::Udp {
+                        source,
+                        destination,
+                    },
+                    _ => Self::Unknown,
+                }
+            }
+
+            _ => return Err(E::InvalidCommand),
+        };
+
+        // Skip the addresses and the TLVs following them
+        buf.advance(HEADER_LENGTH + length);
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let mut buf = SIGNATURE.to_vec();
+        // PROXY command, TCP over IPv4
+        buf.extend_from_slice(&[0x21, 0x11, 0x00, 0x0C]);
+        buf.extend_from_slice(&[192, 0, 2, 1, 198, 51, 100, 1, 0x30, 0x39, 0x01, 0xBB]);
+        buf.extend_from_slice(b"hello world");
+        let mut buf = buf.as_slice();
+        let info = ProxyProtocolInfo::parse_v2(&mut buf).unwrap();
+        assert_eq!(buf, b"hello world");
+        assert!(info.is_tcp());
+        assert!(info.is_ipv4());
+        assert_eq!(info.source(), Some(&"192.0.2.1:12345".parse().unwrap()));
+        assert_eq!(
+            info.destination(),
+            Some(&"198.51.100.1:443".parse().unwrap())
+        );
+
+        let mut buf = SIGNATURE.to_vec();
+        // PROXY command, UDP over IPv6, followed by a 4 bytes TLV
+        buf.extend_from_slice(&[0x21, 0x22, 0x00, 0x28]);
+        buf.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        buf.extend_from_slice(&Ipv6Addr::UNSPECIFIED.octets());
+        buf.extend_from_slice(&[0x00, 0x50, 0x00, 0x51]);
+        buf.extend_from_slice(&[0x04, 0x00, 0x01, 0x00]);
+        buf.extend_from_slice(b"hello world");
+        let mut buf = buf.as_slice();
+        let info = ProxyProtocolInfo::parse_v2(&mut buf).unwrap();
+        assert_eq!(buf, b"hello world");
+        assert!(info.is_udp());
+        assert!(info.is_ipv6());
+        assert_eq!(info.source(), Some(&"[::1]:80".parse().unwrap()));
+
+        let mut buf = SIGNATURE.to_vec();
+        // LOCAL command
+        buf.extend_from_slice(&[0x20, 0x00, 0x00, 0x00]);
+        buf.extend_from_slice(b"hello world");
+        let mut buf = buf.as_slice();
+        let info = ProxyProtocolInfo::parse_v2(&mut buf).unwrap();
+        assert_eq!(buf, b"hello world");
+        assert!(info.is_unknown());
+
+        // Truncated header
+        let mut buf = SIGNATURE.to_vec();
+        buf.extend_from_slice(&[0x21, 0x11, 0x00, 0x0C, 192, 0, 2]);
+        let err = ProxyProtocolInfo::parse_v2(&mut buf.as_slice()).unwrap_err();
+        assert!(err.not_enough_bytes());
+
+        // Wrong version
+        let mut buf = SIGNATURE.to_vec();
+        buf.extend_from_slice(&[0x11, 0x11, 0x00, 0x00]);
+        let err = ProxyProtocolInfo::parse_v2(&mut buf.as_slice()).unwrap_err();
+        assert!(matches!(err, ParseError::InvalidVersion));
+    }
+}
@@ -25,7 +25,10 @@ use http_body::Body;
 use hyper::{body::HttpBody, server::conn::Connection, Request, Response};
 use pin_project_lite::pin_project;
 use thiserror::Error;
-use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::{OwnedSemaphorePermit, Semaphore},
+};
 use tokio_rustls::rustls::ServerConfig;
 use tower_http::add_extension::AddExtension;
 use tower_service::Service;
@@ -41,9 +44,25 @@ use crate::{
 /// The timeout for the handshake to complete
 const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
 
+/// Limits applied to the connections accepted by a [`Server`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConnectionLimits {
+    /// Maximum size of the request headers, in bytes. Must be at least 8192, as
+    /// hyper panics on smaller buffers
+    pub max_header_size: Option<usize>,
+
+    /// Time allowed to the client to send the headers of a request
+    pub header_read_timeout: Option<Duration>,
+
+    /// Maximum number of connections served at once. Connections accepted
+    /// above this number are closed right away
+    pub max_connections: Option<usize>,
+}
+
 pub struct Server<S> {
     tls: Option<Arc<ServerConfig>>,
     proxy: bool,
+    limits: ConnectionLimits,
     listener: UnixOrTcpListener,
     service: S,
 }
@@ -59,6 +78,7 @@ impl<S> Server<S> {
         Ok(Self {
             tls: None,
             proxy: false,
+            limits: ConnectionLimits::default(),
             listener: listener.try_into()?,
             service,
         })
@@ -69,6 +89,7 @@ impl<S> Server<S> {
         Self {
             tls: None,
             proxy: false,
+            limits: ConnectionLimits::default(),
             listener: listener.into(),
             service,
         }
@@ -86,6 +107,12 @@ impl<S> Server<S> {
         self
     }
 
+    #[must_use]
+    pub const fn with_limits(mut self, limits: ConnectionLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
     /// Run a single server
     pub async fn run<B, SD>(self, shutdown: SD)
     where
@@ -128,6 +155,9 @@ enum AcceptError {
         #[source]
         source: tokio::time::error::Elapsed,
     },
+
+    #[error("too many connections on this listener")]
+    TooManyConnections,
 }
 
 impl AcceptError {
@@ -156,6 +186,7 @@ impl AcceptError {
 async fn accept<S, B>(
     maybe_proxy_acceptor: &MaybeProxyAcceptor,
     maybe_tls_acceptor: &MaybeTlsAcceptor,
+    limits: &ConnectionLimits,
     peer_addr: SocketAddr,
     stream: UnixOrTcpConnection,
     service: S,
@@ -196,16 +227,23 @@ where
 
         let service = AddExtension::new(service, info);
 
-        let conn = if is_h2 {
-            hyper::server::conn::Http::new()
-                .http2_only(true)
-                .serve_connection(stream, service)
+        let mut http = hyper::server::conn::Http::new();
+        if is_h2 {
+            http.http2_only(true);
+            if let Some(max_header_size) = limits.max_header_size {
+                http.http2_max_header_list_size(max_header_size.try_into().unwrap_or(u32::MAX));
+            }
         } else {
-            hyper::server::conn::Http::new()
-                .http1_only(true)
-                .http1_keep_alive(true)
-                .serve_connection(stream, service)
-        };
+            http.http1_only(true).http1_keep_alive(true);
+            if let Some(max_header_size) = limits.max_header_size {
+                http.max_buf_size(max_header_size);
+            }
+            if let Some(header_read_timeout) = limits.header_read_timeout {
+                http.http1_header_read_timeout(header_read_timeout);
+            }
+        }
+
+        let conn = http.serve_connection(stream, service);
 
         Ok(conn)
     })
@@ -225,15 +263,22 @@ pin_project! {
         connection: C,
         shutdown_in_progress: Arc<AtomicBool>,
         did_start_shutdown: bool,
+        // Released when the connection is dropped, making room for a new one
+        _permit: Option<OwnedSemaphorePermit>,
     }
 }
 
 impl<C> AbortableConnection<C> {
-    fn new(connection: C, shutdown_in_progress: &Arc<AtomicBool>) -> Self {
+    fn new(
+        connection: C,
+        shutdown_in_progress: &Arc<AtomicBool>,
+        permit: Option<OwnedSemaphorePermit>,
+    ) -> Self {
         Self {
             connection,
             shutdown_in_progress: Arc::clone(shutdown_in_progress),
             did_start_shutdown: false,
+            _permit: permit,
         }
     }
 }
@@ -289,12 +334,18 @@ where
         .map(|server| {
             let maybe_proxy_acceptor = MaybeProxyAcceptor::new(server.proxy);
             let maybe_tls_acceptor = MaybeTlsAcceptor::new(server.tls);
+            let limits = server.limits;
+            let connection_permits = limits
+                .max_connections
+                .map(|max_connections| Arc::new(Semaphore::new(max_connections)));
             futures_util::stream::poll_fn(move |cx| {
                 let res =
                     std::task::ready!(server.listener.poll_accept(cx)).map(|(addr, stream)| {
                         (
                             maybe_proxy_acceptor,
                             maybe_tls_acceptor.clone(),
+                            limits,
+                            connection_permits.clone(),
                             server.service.clone(),
                             addr,
                             stream,
@@ -328,11 +379,14 @@ where
             // Poll on the JoinSet to collect connections to serve
             res = accept_tasks.join_next(), if !accept_tasks.is_empty() => {
                 match res {
-                    Some(Ok(Ok(connection))) => {
+                    Some(Ok(Ok((connection, permit)))) => {
                         tracing::trace!("Accepted connection");
-                        let conn = AbortableConnection::new(connection, &shutdown_in_progress);
+                        let conn = AbortableConnection::new(connection, &shutdown_in_progress, permit);
                         connection_tasks.spawn(conn);
                     },
+                    Some(Ok(Err(AcceptError::TooManyConnections))) => {
+                        tracing::warn!("Closed a connection, too many connections on this listener");
+                    },
                     Some(Ok(Err(e))) => tracing::error!("Connection did not finish handshake: {e}"),
                     Some(Err(e)) => tracing::error!("Join error: {e}"),
                     None => tracing::error!("Join set was polled even though it was empty"),
@@ -358,9 +412,18 @@ where
                 // accept the next connection. This allows us to keep track of active connections
                 // and waiting on them for a graceful shutdown
                 accept_tasks.spawn(async move {
-                    let (maybe_proxy_acceptor, maybe_tls_acceptor, service, peer_addr, stream) = res
+                    let (maybe_proxy_acceptor, maybe_tls_acceptor, limits, connection_permits, service, peer_addr, stream) = res
                         .map_err(AcceptError::socket)?;
-                    accept(&maybe_proxy_acceptor, &maybe_tls_acceptor, peer_addr, stream, service).await
+
+                    // The permit is taken before the handshake, so that slow handshakes count
+                    // towards the limit. Dropping the stream closes the connection
+                    let permit = connection_permits
+                        .map(Semaphore::try_acquire_owned)
+                        .transpose()
+                        .map_err(|_| AcceptError::TooManyConnections)?;
+
+                    let connection = accept(&maybe_proxy_acceptor, &maybe_tls_acceptor, &limits, peer_addr, stream, service).await?;
+                    Ok::<_, AcceptError>((connection, permit))
                 });
             },
         };
@@ -384,11 +447,14 @@ where
                 // Poll on the JoinSet to collect connections to serve
                 res = accept_tasks.join_next(), if !accept_tasks.is_empty() => {
                     match res {
-                        Some(Ok(Ok(connection))) => {
+                        Some(Ok(Ok((connection, permit)))) => {
                             tracing::trace!("Accepted connection");
-                            let conn = AbortableConnection::new(connection, &shutdown_in_progress);
+                            let conn = AbortableConnection::new(connection, &shutdown_in_progress, permit);
                             connection_tasks.spawn(conn);
                         }
+                        Some(Ok(Err(AcceptError::TooManyConnections))) => {
+                            tracing::warn!("Closed a connection, too many connections on this listener");
+                        }
                         Some(Ok(Err(e))) => tracing::error!("Connection did not finish handshake: {e}"),
                         Some(Err(e)) => tracing::error!("Join error: {e}"),
                         None => tracing::error!("Join set was polled even though it was empty"),
@@ -423,3 +489,77 @@ where
     connection_tasks.shutdown().await;
     tracing::info!("Shutdown complete");
 }
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use hyper::service::service_fn;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::*;
+
+    /// Send a request on a new connection, and return what was read before
+    /// the connection was closed or the response was received
+    async fn request(addr: std::net::SocketAddr) -> (TcpStream, Vec<u8>) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+
+        let mut buf = vec![0; 1024];
+        // The connection may be reset if it is closed before reading the request
+        let read = stream.read(&mut buf).await.unwrap_or(0);
+        buf.truncate(read);
+        (stream, buf)
+    }
+
+    #[tokio::test]
+    async fn test_max_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let service = service_fn(|_req: Request<hyper::Body>| async {
+            Ok::<_, Infallible>(Response::new(hyper::Body::from("hello")))
+        });
+        let server = Server::new(listener, service).with_limits(ConnectionLimits {
+            max_connections: Some(1),
+            ..ConnectionLimits::default()
+        });
+
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+        let shutdown = Box::pin(futures_util::stream::once(async move {
+            let _ = shutdown_rx.await;
+            "test finished"
+        }));
+        let handle = tokio::spawn(server.run(shutdown));
+
+        // The first connection is served, and kept alive
+        let (first, response) = request(addr).await;
+        assert!(response.starts_with(b"HTTP/1.1 200 OK"));
+
+        // The second one is closed right away
+        let (_second, response) = request(addr).await;
+        assert!(response.is_empty());
+
+        // Once the first connection is closed, new ones are served again
+        drop(first);
+        let mut served = false;
+        for _ in 0..50 {
+            let (_stream, response) = request(addr).await;
+            if response.starts_with(b"HTTP/1.1 200 OK") {
+                served = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(served);
+
+        shutdown_tx.send(()).unwrap();
+        handle.await.unwrap();
+    }
+}
@@ -0,0 +1,471 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A [`HomeserverConnection`] implementation targeting Dendrite.
+//!
+//! Dendrite's admin API is a lot more limited than Synapse's:
+//!
+//!  - users are provisioned through the shared-secret registration API, which
+//!    means that the profile and emails of existing users can't be updated;
+//!  - there is no API to manage the devices or the display name of a user, nor
+//!    to allow cross-signing resets, so those operations return an
+//!    [`Unsupported`] error;
+//!  - deleting a user makes them leave all their rooms, but doesn't erase their
+//!    data.
+
+#![forbid(unsafe_code)]
+#![deny(clippy::all, clippy::str_to_string, rustdoc::broken_intra_doc_links)]
+#![warn(clippy::pedantic)]
+
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use http::{header::AUTHORIZATION, request::Builder, Method, Request, StatusCode};
+use mas_axum_utils::http_client_factory::HttpClientFactory;
+use mas_http::{EmptyBody, HttpServiceExt};
+use mas_matrix::{HomeserverConnection, MatrixUser, ProvisionRequest, Unsupported};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use tower::{Service, ServiceExt};
+use tracing::debug;
+use url::Url;
+
+pub struct DendriteConnection {
+    homeserver: String,
+    endpoint: Url,
+    access_token: String,
+    registration_shared_secret: String,
+    http_client_factory: HttpClientFactory,
+}
+
+impl DendriteConnection {
+    /// Create a new [`DendriteConnection`].
+    ///
+    /// # Parameters
+    ///
+    /// * `homeserver` - The server name of the homeserver.
+    /// * `endpoint` - The base URL of the homeserver's client API.
+    /// * `access_token` - The access token of an admin user, used for the admin
+    ///   API.
+    /// * `registration_shared_secret` - The `registration_shared_secret` from
+    ///   the Dendrite configuration, used to provision users.
+    /// * `http_client_factory` - The factory used to build HTTP clients.
+    #[must_use]
+    pub fn new(
+        homeserver: String,
+        endpoint: Url,
+        access_token: String,
+        registration_shared_secret: String,
+        http_client_factory: HttpClientFactory,
+    ) -> Self {
+        Self {
+            homeserver,
+            endpoint,
+            access_token,
+            registration_shared_secret,
+            http_client_factory,
+        }
+    }
+
+    fn builder(&self, url: &str) -> Builder {
+        Request::builder()
+            .uri(
+                self.endpoint
+                    .join(url)
+                    .map(Url::into)
+                    .unwrap_or(String::new()),
+            )
+            .header(AUTHORIZATION, format!("Bearer {}", self.access_token))
+    }
+
+    #[must_use]
+    pub fn post(&self, url: &str) -> Builder {
+        self.builder(url).method(Method::POST)
+    }
+
+    #[must_use]
+    pub fn get(&self, url: &str) -> Builder {
+        self.builder(url).method(Method::GET)
+    }
+
+    fn mac(&self) -> Hmac<Sha1> {
+        Hmac::<Sha1>::new_from_slice(self.registration_shared_secret.as_bytes())
+            .expect("HMAC accepts any key size")
+    }
+
+    /// Derive the password used to register a user.
+    ///
+    /// The shared-secret registration API requires a password, but users never
+    /// use it since they authenticate through the service. It is derived from
+    /// the shared secret so that it can't be guessed.
+    fn password_for(&self, sub: &str) -> String {
+        let mut mac = self.mac();
+        mac.update(b"password\x00");
+        mac.update(sub.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    /// Compute the MAC of a shared-secret registration request.
+    fn registration_mac(&self, nonce: &str, username: &str, password: &str) -> String {
+        let mut mac = self.mac();
+        mac.update(nonce.as_bytes());
+        mac.update(b"\x00");
+        mac.update(username.as_bytes());
+        mac.update(b"\x00");
+        mac.update(password.as_bytes());
+        mac.update(b"\x00notadmin");
+        hex::encode(mac.finalize().into_bytes())
+    }
+}
+
+/// Extract the localpart of a Matrix ID
+fn localpart(mxid: &str) -> Result<&str, anyhow::Error> {
+    mxid.strip_prefix('@')
+        .and_then(|rest| rest.split_once(':'))
+        .map(|(localpart, _)| localpart)
+        .with_context(|| format!("Invalid Matrix ID {mxid:?}"))
+}
+
+#[derive(Deserialize)]
+struct DendriteProfile {
+    #[serde(default)]
+    displayname: Option<String>,
+
+    #[serde(default)]
+    avatar_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct NonceResponse {
+    nonce: String,
+}
+
+#[derive(Serialize)]
+struct RegisterRequest<'a> {
+    nonce: &'a str,
+    username: &'a str,
+    password: &'a str,
+    admin: bool,
+    mac: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    displayname: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct RegisterResponse {
+    #[serde(default)]
+    errcode: Option<String>,
+}
+
+#[async_trait::async_trait]
+impl HomeserverConnection for DendriteConnection {
+    type Error = anyhow::Error;
+
+    fn homeserver(&self) -> &str {
+        &self.homeserver
+    }
+
+    #[tracing::instrument(
+        name = "homeserver.query_user",
+        skip_all,
+        fields(
+            matrix.homeserver = self.homeserver,
+            matrix.mxid = mxid,
+        ),
+        err(Display),
+    )]
+    async fn query_user(&self, mxid: &str) -> Result<MatrixUser, Self::Error> {
+        let mut client = self
+            .http_client_factory
+            .client("homeserver.query_user")
+            .response_body_to_bytes()
+            .json_response();
+
+        let request = self
+            .get(&format!("_matrix/client/v3/profile/{mxid}"))
+            .body(EmptyBody::new())?;
+
+        let response = client.ready().await?.call(request).await?;
+
+        if response.status() != StatusCode::OK {
+            return Err(anyhow::anyhow!("Failed to query user from Dendrite"));
+        }
+
+        let body: DendriteProfile = response.into_body();
+
+        Ok(MatrixUser {
+            displayname: body.displayname,
+            avatar_url: body.avatar_url,
+        })
+    }
+
+    #[tracing::instrument(
+        name = "homeserver.provision_user",
+        skip_all,
+        fields(
+            matrix.homeserver = self.homeserver,
+            matrix.mxid = request.mxid(),
+            user.id = request.sub(),
+        ),
+        err(Display),
+    )]
+    async fn provision_user(&self, request: &ProvisionRequest) -> Result<bool, Self::Error> {
+        let username = localpart(request.mxid())?;
+
+        let mut displayname = None;
+        request.on_displayname(|value| displayname = value.map(ToOwned::to_owned));
+
+        // First get a nonce for the registration request
+        let mut client = self
+            .http_client_factory
+            .client("homeserver.provision_user.nonce")
+            .response_body_to_bytes()
+            .json_response();
+
+        let nonce_request = self
+            .get("_synapse/admin/v1/register")
+            .body(EmptyBody::new())?;
+
+        let response = client.ready().await?.call(nonce_request).await?;
+
+        if response.status() != StatusCode::OK {
+            return Err(anyhow::anyhow!(
+                "Failed to get a registration nonce from Dendrite"
+            ));
+        }
+
+        let NonceResponse { nonce } = response.into_body();
+
+        let password = self.password_for(request.sub());
+        let mac = self.registration_mac(&nonce, username, &password);
+
+        let mut client = self
+            .http_client_factory
+            .client("homeserver.provision_user")
+            .request_bytes_to_body()
+            .response_body_to_bytes()
+            .json_response()
+            .json_request();
+
+        let register_request = self
+            .post("_synapse/admin/v1/register")
+            .body(RegisterRequest {
+                nonce: &nonce,
+                username,
+                password: &password,
+                admin: false,
+                mac: &mac,
+                displayname: displayname.as_deref(),
+            })?;
+
+        let response = client.ready().await?.call(register_request).await?;
+        let status = response.status();
+        let body: RegisterResponse = response.into_body();
+
+        match (status, body.errcode.as_deref()) {
+            (StatusCode::OK, _) => Ok(true),
+            // The user already exists. Dendrite has no API to update it, so
+            // there is nothing else to do.
+            (StatusCode::BAD_REQUEST, Some("M_USER_IN_USE")) => {
+                debug!("User already exists on Dendrite, not updating it");
+                Ok(false)
+            }
+            (code, errcode) => Err(anyhow::anyhow!(
+                "Failed to provision user in Dendrite: {code} ({errcode:?})"
+            )),
+        }
+    }
+
+    #[tracing::instrument(
+        name = "homeserver.create_device",
+        skip_all,
+        fields(
+            matrix.homeserver = self.homeserver,
+            matrix.mxid = mxid,
+            matrix.device_id = device_id,
+        ),
+        err(Display),
+    )]
+    async fn create_device(&self, mxid: &str, device_id: &str) -> Result<(), Self::Error> {
+        Err(Unsupported::new("create_device").into())
+    }
+
+    #[tracing::instrument(
+        name = "homeserver.update_device_display_name",
+        skip_all,
+        fields(
+            matrix.homeserver = self.homeserver,
+            matrix.mxid = mxid,
+            matrix.device_id = device_id,
+            matrix.device_display_name = display_name,
+        ),
+        err(Display),
+    )]
+    async fn update_device_display_name(
+        &self,
+        mxid: &str,
+        device_id: &str,
+        display_name: &str,
+    ) -> Result<(), Self::Error> {
+        Err(Unsupported::new("update_device_display_name").into())
+    }
+
+    #[tracing::instrument(
+        name = "homeserver.delete_device",
+        skip_all,
+        fields(
+            matrix.homeserver = self.homeserver,
+            matrix.mxid = mxid,
+            matrix.device_id = device_id,
+        ),
+        err(Display),
+    )]
+    async fn delete_device(&self, mxid: &str, device_id: &str) -> Result<(), Self::Error> {
+        Err(Unsupported::new("delete_device").into())
+    }
+
+    #[tracing::instrument(
+        name = "homeserver.delete_user",
+        skip_all,
+        fields(
+            matrix.homeserver = self.homeserver,
+            matrix.mxid = mxid,
+            erase = erase,
+        ),
+        err(Display),
+    )]
+    async fn delete_user(&self, mxid: &str, erase: bool) -> Result<(), Self::Error> {
+        let mut client = self.http_client_factory.client("homeserver.delete_user");
+
+        let request = self
+            .post(&format!("_dendrite/admin/evacuateUser/{mxid}"))
+            .body(EmptyBody::new())?;
+
+        let response = client.ready().await?.call(request).await?;
+
+        if response.status() != StatusCode::OK {
+            return Err(anyhow::anyhow!("Failed to evacuate user in Dendrite"));
+        }
+
+        if erase {
+            debug!("Dendrite has no API to erase user data, only evacuated the user");
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "homeserver.set_displayname",
+        skip_all,
+        fields(
+            matrix.homeserver = self.homeserver,
+            matrix.mxid = mxid,
+            matrix.displayname = displayname,
+        ),
+        err(Display),
+    )]
+    async fn set_displayname(&self, mxid: &str, displayname: &str) -> Result<(), Self::Error> {
+        // Dendrite only lets users set their own display name
+        Err(Unsupported::new("set_displayname").into())
+    }
+
+    #[tracing::instrument(
+        name = "homeserver.unset_displayname",
+        skip_all,
+        fields(
+            matrix.homeserver = self.homeserver,
+            matrix.mxid = mxid,
+        ),
+        err(Display),
+    )]
+    async fn unset_displayname(&self, mxid: &str) -> Result<(), Self::Error> {
+        Err(Unsupported::new("unset_displayname").into())
+    }
+
+    #[tracing::instrument(
+        name = "homeserver.allow_cross_signing_reset",
+        skip_all,
+        fields(
+            matrix.homeserver = self.homeserver,
+            matrix.mxid = mxid,
+        ),
+        err(Display),
+    )]
+    async fn allow_cross_signing_reset(&self, mxid: &str) -> Result<(), Self::Error> {
+        Err(Unsupported::new("allow_cross_signing_reset").into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn connection() -> DendriteConnection {
+        DendriteConnection::new(
+            "example.org".to_owned(),
+            Url::parse("http://localhost:8008/").unwrap(),
+            "access_token".to_owned(),
+            "secret".to_owned(),
+            HttpClientFactory::new().await.unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_localpart() {
+        assert_eq!(localpart("@alice:example.org").unwrap(), "alice");
+        assert_eq!(localpart("@alice:example.org:8448").unwrap(), "alice");
+        assert!(localpart("alice:example.org").is_err());
+        assert!(localpart("@alice").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_registration_mac() {
+        let conn = connection().await;
+
+        let password = conn.password_for("01FSHN9AG0MKGTBNZ16RDR3PVY");
+        assert_eq!(password, "6c67545b2411b7e966a3aeea8e44bade16f7e784");
+
+        let mac = conn.registration_mac("nonce", "alice", &password);
+        assert_eq!(mac, "eee850e275296b3417c66fd1ac9eea76d1428752");
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_operations() {
+        let conn = connection().await;
+        let mxid = "@alice:example.org";
+
+        let results = [
+            ("create_device", conn.create_device(mxid, "DEVICE").await),
+            (
+                "update_device_display_name",
+                conn.update_device_display_name(mxid, "DEVICE", "Phone")
+                    .await,
+            ),
+            ("delete_device", conn.delete_device(mxid, "DEVICE").await),
+            ("set_displayname", conn.set_displayname(mxid, "Alice").await),
+            ("unset_displayname", conn.unset_displayname(mxid).await),
+            (
+                "allow_cross_signing_reset",
+                conn.allow_cross_signing_reset(mxid).await,
+            ),
+        ];
+
+        for (operation, result) in results {
+            let error = result.unwrap_err();
+            let unsupported = error
+                .downcast_ref::<Unsupported>()
+                .unwrap_or_else(|| panic!("{operation} should be unsupported"));
+            assert_eq!(unsupported.operation(), operation);
+        }
+    }
+}
@@ -16,21 +16,39 @@
 #![deny(clippy::all, clippy::str_to_string, rustdoc::broken_intra_doc_links)]
 #![warn(clippy::pedantic)]
 
-use http::{header::AUTHORIZATION, request::Builder, Method, Request, StatusCode};
+use std::{net::IpAddr, time::Duration};
+
+use anyhow::Context;
+use bytes::Bytes;
+use http::{
+    header::{AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE},
+    request::Builder,
+    HeaderValue, Method, Request, StatusCode,
+};
+use http_body::Body;
+use ipnetwork::IpNetwork;
 use mas_axum_utils::http_client_factory::HttpClientFactory;
 use mas_http::{EmptyBody, HttpServiceExt};
 use mas_matrix::{HomeserverConnection, MatrixUser, ProvisionRequest};
 use serde::{Deserialize, Serialize};
 use tower::{Service, ServiceExt};
-use url::Url;
+use tracing::warn;
+use url::{Host, Url};
 
 static SYNAPSE_AUTH_PROVIDER: &str = "oauth-delegated";
 
+/// The maximum size of an avatar copied from an upstream provider
+const MAX_AVATAR_SIZE: usize = 1024 * 1024;
+
+/// How long fetching an avatar from an upstream provider can take
+const AVATAR_FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub struct SynapseConnection {
     homeserver: String,
     endpoint: Url,
     access_token: String,
     http_client_factory: HttpClientFactory,
+    avatar_denied_networks: Vec<IpNetwork>,
 }
 
 impl SynapseConnection {
@@ -46,9 +64,18 @@ impl SynapseConnection {
             endpoint,
             access_token,
             http_client_factory,
+            avatar_denied_networks: Vec::new(),
         }
     }
 
+    /// Don't fetch avatars from these networks, on top of the loopback,
+    /// private and link-local ones which are always denied
+    #[must_use]
+    pub fn with_avatar_denied_networks(mut self, networks: Vec<IpNetwork>) -> Self {
+        self.avatar_denied_networks = networks;
+        self
+    }
+
     fn builder(&self, url: &str) -> Builder {
         Request::builder()
             .uri(
@@ -79,6 +106,182 @@ impl SynapseConnection {
     pub fn delete(&self, url: &str) -> Builder {
         self.builder(url).method(Method::DELETE)
     }
+
+    /// Fetch a remote avatar, returning its content type and content
+    ///
+    /// Only images served over HTTPS by public addresses are fetched, up to
+    /// [`MAX_AVATAR_SIZE`] bytes.
+    async fn fetch_avatar(&self, url: &str) -> Result<(HeaderValue, Bytes), anyhow::Error> {
+        let url = check_avatar_url(url, &self.avatar_denied_networks).await?;
+
+        // Redirects are not followed, as their target wasn't checked
+        let mut client = self
+            .http_client_factory
+            .client_without_redirects("homeserver.upload_avatar.fetch");
+
+        let request = Request::get(url.as_str()).body(EmptyBody::new())?;
+
+        let fetch = async {
+            let response = client.ready().await?.call(request).await?;
+
+            if response.status() != StatusCode::OK {
+                anyhow::bail!("Failed to fetch avatar: {}", response.status());
+            }
+
+            let content_type = response
+                .headers()
+                .get(CONTENT_TYPE)
+                .cloned()
+                .context("Avatar has no content type")?;
+            if !is_image_content_type(&content_type) {
+                anyhow::bail!("Avatar is not an image");
+            }
+
+            let content_length = response
+                .headers()
+                .get(CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<usize>().ok());
+            if content_length.is_some_and(|length| length > MAX_AVATAR_SIZE) {
+                anyhow::bail!("Avatar is too large");
+            }
+
+            let mut body = response.into_body();
+            let mut avatar = Vec::new();
+            while let Some(chunk) = body.data().await {
+                let chunk = chunk?;
+                if avatar.len() + chunk.len() > MAX_AVATAR_SIZE {
+                    anyhow::bail!("Avatar is too large");
+                }
+                avatar.extend_from_slice(&chunk);
+            }
+
+            Ok::<_, anyhow::Error>((content_type, Bytes::from(avatar)))
+        };
+
+        tokio::time::timeout(AVATAR_FETCH_TIMEOUT, fetch)
+            .await
+            .context("Timed out fetching the avatar")?
+    }
+
+    /// Copy a remote avatar to the homeserver's media repository, returning
+    /// its MXC URI.
+    async fn upload_avatar(&self, url: &str) -> Result<String, anyhow::Error> {
+        let (content_type, avatar) = self.fetch_avatar(url).await?;
+
+        let mut client = self
+            .http_client_factory
+            .client("homeserver.upload_avatar")
+            .request_bytes_to_body()
+            .response_body_to_bytes()
+            .json_response();
+
+        let request = self
+            .post("_matrix/media/v3/upload")
+            .header(CONTENT_TYPE, content_type)
+            .body(avatar)?;
+
+        let response = client.ready().await?.call(request).await?;
+
+        if response.status() != StatusCode::OK {
+            return Err(anyhow::anyhow!(
+                "Failed to upload avatar to Synapse: {}",
+                response.status()
+            ));
+        }
+
+        let body: UploadResponse = response.into_body();
+        Ok(body.content_uri)
+    }
+}
+
+/// Whether avatars can't be fetched from this address. Loopback, private,
+/// link-local and other non-public addresses are always denied, on top of the
+/// given networks
+fn is_denied_avatar_address(ip: IpAddr, denied_networks: &[IpNetwork]) -> bool {
+    // Treat IPv4-mapped IPv6 addresses as the IPv4 address they map to
+    let ip = match ip {
+        IpAddr::V6(ip) => ip.to_ipv4_mapped().map_or(IpAddr::V6(ip), IpAddr::V4),
+        IpAddr::V4(ip) => IpAddr::V4(ip),
+    };
+
+    let non_public = match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                // 0.0.0.0/8, "this network"
+                || a == 0
+                // 100.64.0.0/10, shared address space
+                || (a == 100 && b & 0xC0 == 64)
+        }
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // fc00::/7, unique local addresses
+                || first & 0xFE00 == 0xFC00
+                // fe80::/10, link-local addresses
+                || first & 0xFFC0 == 0xFE80
+        }
+    };
+
+    non_public || denied_networks.iter().any(|network| network.contains(ip))
+}
+
+/// Check that an avatar can be fetched from this URL: it must use HTTPS, and
+/// its host must not resolve to a denied address
+async fn check_avatar_url(url: &str, denied_networks: &[IpNetwork]) -> Result<Url, anyhow::Error> {
+    let url = Url::parse(url).context("Invalid avatar URL")?;
+
+    if url.scheme() != "https" {
+        anyhow::bail!("Avatar URL does not use HTTPS");
+    }
+
+    let addresses: Vec<IpAddr> = match url.host() {
+        Some(Host::Ipv4(ip)) => vec![ip.into()],
+        Some(Host::Ipv6(ip)) => vec![ip.into()],
+        Some(Host::Domain(domain)) => {
+            let port = url.port_or_known_default().unwrap_or(443);
+            tokio::net::lookup_host((domain, port))
+                .await
+                .context("Failed to resolve the avatar host")?
+                .map(|address| address.ip())
+                .collect()
+        }
+        None => anyhow::bail!("Avatar URL has no host"),
+    };
+
+    if addresses.is_empty() {
+        anyhow::bail!("Avatar host does not resolve to any address");
+    }
+
+    if addresses
+        .iter()
+        .any(|ip| is_denied_avatar_address(*ip, denied_networks))
+    {
+        anyhow::bail!("Avatar host resolves to a denied address");
+    }
+
+    Ok(url)
+}
+
+/// Whether the content type is one of an image
+fn is_image_content_type(content_type: &HeaderValue) -> bool {
+    content_type
+        .to_str()
+        .is_ok_and(|content_type| content_type.starts_with("image/"))
+}
+
+#[derive(Deserialize)]
+struct UploadResponse {
+    content_uri: String,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -124,6 +327,11 @@ struct SynapseDevice<'a> {
     device_id: &'a str,
 }
 
+#[derive(Serialize)]
+struct UpdateDeviceRequest<'a> {
+    display_name: &'a str,
+}
+
 #[derive(Serialize)]
 struct SetDisplayNameRequest<'a> {
     displayname: &'a str,
@@ -195,12 +403,13 @@ impl HomeserverConnection for SynapseConnection {
             ..SynapseUser::default()
         };
 
+        let mut avatar_url = None;
         request
             .on_displayname(|displayname| {
                 body.display_name = Some(displayname.unwrap_or_default().to_owned());
             })
-            .on_avatar_url(|avatar_url| {
-                body.avatar_url = Some(avatar_url.unwrap_or_default().to_owned());
+            .on_avatar_url(|value| {
+                avatar_url = Some(value.unwrap_or_default().to_owned());
             })
             .on_emails(|emails| {
                 body.three_pids = Some(
@@ -215,6 +424,19 @@ impl HomeserverConnection for SynapseConnection {
                 );
             });
 
+        // Avatars coming from elsewhere need to be copied to the media repository
+        // first, as Synapse only accepts MXC URIs
+        if let Some(avatar_url) = avatar_url {
+            if avatar_url.is_empty() || avatar_url.starts_with("mxc://") {
+                body.avatar_url = Some(avatar_url);
+            } else {
+                match self.upload_avatar(&avatar_url).await {
+                    Ok(mxc) => body.avatar_url = Some(mxc),
+                    Err(e) => warn!(error = %e, "Failed to upload avatar, not updating it"),
+                }
+            }
+        }
+
         let mut client = self
             .http_client_factory
             .client("homeserver.provision_user")
@@ -270,6 +492,46 @@ impl HomeserverConnection for SynapseConnection {
         Ok(())
     }
 
+    #[tracing::instrument(
+        name = "homeserver.update_device_display_name",
+        skip_all,
+        fields(
+            matrix.homeserver = self.homeserver,
+            matrix.mxid = mxid,
+            matrix.device_id = device_id,
+            matrix.device_display_name = display_name,
+        ),
+        err(Display),
+    )]
+    async fn update_device_display_name(
+        &self,
+        mxid: &str,
+        device_id: &str,
+        display_name: &str,
+    ) -> Result<(), Self::Error> {
+        let mut client = self
+            .http_client_factory
+            .client("homeserver.update_device_display_name")
+            .request_bytes_to_body()
+            .json_request();
+
+        let request = self
+            .put(&format!(
+                "_synapse/admin/v2/users/{mxid}/devices/{device_id}"
+            ))
+            .body(UpdateDeviceRequest { display_name })?;
+
+        let response = client.ready().await?.call(request).await?;
+
+        if response.status() != StatusCode::OK {
+            return Err(anyhow::anyhow!(
+                "Failed to update device display name in Synapse"
+            ));
+        }
+
+        Ok(())
+    }
+
     #[tracing::instrument(
         name = "homeserver.delete_device",
         skip_all,
@@ -370,4 +632,112 @@ impl HomeserverConnection for SynapseConnection {
     async fn unset_displayname(&self, mxid: &str) -> Result<(), Self::Error> {
         self.set_displayname(mxid, "").await
     }
+
+    #[tracing::instrument(
+        name = "homeserver.allow_cross_signing_reset",
+        skip_all,
+        fields(
+            matrix.homeserver = self.homeserver,
+            matrix.mxid = mxid,
+        ),
+        err(Display),
+    )]
+    async fn allow_cross_signing_reset(&self, mxid: &str) -> Result<(), Self::Error> {
+        let mut client = self
+            .http_client_factory
+            .client("homeserver.allow_cross_signing_reset");
+
+        let request = self
+            .post(&format!(
+                "_synapse/admin/v1/users/{mxid}/_allow_cross_signing_replacement_without_uia"
+            ))
+            .body(EmptyBody::new())?;
+
+        let response = client.ready().await?.call(request).await?;
+
+        if response.status() != StatusCode::OK {
+            return Err(anyhow::anyhow!(
+                "Failed to allow cross-signing reset in Synapse"
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_denied_avatar_addresses() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fe80::1",
+            "fd00::1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+        ] {
+            let ip: IpAddr = ip.parse().unwrap();
+            assert!(is_denied_avatar_address(ip, &[]), "{ip} should be denied");
+        }
+
+        for ip in ["93.184.216.34", "2606:2800:220:1:248:1893:25c8:1946"] {
+            let ip: IpAddr = ip.parse().unwrap();
+            assert!(!is_denied_avatar_address(ip, &[]), "{ip} should be allowed");
+        }
+
+        // Networks denied by the IP filter are denied as well
+        let denied_networks = ["93.184.216.0/24".parse().unwrap()];
+        let ip: IpAddr = "93.184.216.34".parse().unwrap();
+        assert!(is_denied_avatar_address(ip, &denied_networks));
+    }
+
+    #[tokio::test]
+    async fn test_rejected_avatar_urls() {
+        for url in [
+            "not a url",
+            "http://93.184.216.34/avatar.png",
+            "ftp://93.184.216.34/avatar.png",
+            "file:///etc/passwd",
+            "https://127.0.0.1/avatar.png",
+            "https://10.0.0.1:8443/avatar.png",
+            "https://169.254.169.254/latest/meta-data/",
+            "https://[::1]/avatar.png",
+            "https://[::ffff:7f00:1]/avatar.png",
+            "https://localhost/avatar.png",
+        ] {
+            assert!(
+                check_avatar_url(url, &[]).await.is_err(),
+                "{url} should be rejected"
+            );
+        }
+
+        let url = "https://93.184.216.34/avatar.png";
+        assert!(check_avatar_url(url, &[]).await.is_ok());
+
+        let denied_networks = ["93.184.216.0/24".parse().unwrap()];
+        assert!(check_avatar_url(url, &denied_networks).await.is_err());
+    }
+
+    #[test]
+    fn test_image_content_type() {
+        assert!(is_image_content_type(&HeaderValue::from_static("image/png")));
+        assert!(is_image_content_type(&HeaderValue::from_static(
+            "image/jpeg; charset=binary"
+        )));
+        assert!(!is_image_content_type(&HeaderValue::from_static("text/html")));
+        assert!(!is_image_content_type(&HeaderValue::from_static(
+            "application/octet-stream"
+        )));
+    }
 }
@@ -12,14 +12,58 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+//! Abstraction over the homeserver the service is provisioning users on.
+//!
+//! The [`HomeserverConnection`] trait describes the operations the service
+//! needs to perform on the homeserver. Each homeserver implementation lives in
+//! its own crate, like `mas-matrix-synapse` or `mas-matrix-dendrite`.
+
 #![forbid(unsafe_code)]
 #![deny(clippy::all, clippy::str_to_string, rustdoc::broken_intra_doc_links)]
 #![warn(clippy::pedantic)]
 
 mod mock;
 
+use std::sync::Arc;
+
 pub use self::mock::HomeserverConnection as MockHomeserverConnection;
 
+/// Error returned by a [`HomeserverConnection`] for an operation the
+/// homeserver has no API for.
+///
+/// Callers can check for it with [`anyhow::Error::is`], and decide whether
+/// skipping the operation is acceptable, instead of retrying it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Unsupported {
+    operation: &'static str,
+}
+
+impl Unsupported {
+    /// Create a new [`Unsupported`] error for the given operation.
+    #[must_use]
+    pub const fn new(operation: &'static str) -> Self {
+        Self { operation }
+    }
+
+    /// The name of the operation which is not supported.
+    #[must_use]
+    pub const fn operation(&self) -> &'static str {
+        self.operation
+    }
+}
+
+impl std::fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The homeserver does not support the {} operation",
+            self.operation
+        )
+    }
+}
+
+impl std::error::Error for Unsupported {}
+
 #[derive(Debug)]
 pub struct MatrixUser {
     pub displayname: Option<String>,
@@ -181,6 +225,14 @@ impl ProvisionRequest {
     }
 }
 
+/// A connection to a Matrix homeserver, used to keep the homeserver in sync
+/// with the users, devices and profiles managed by the service.
+///
+/// Implementations are expected to be idempotent: the same operation may be
+/// retried by a job after a transient failure. Operations which are not
+/// supported by a given homeserver should return an [`Unsupported`] error
+/// rather than silently succeed, so that callers can tell them apart from
+/// failures worth retrying.
 #[async_trait::async_trait]
 pub trait HomeserverConnection: Send + Sync {
     /// The error type returned by all methods.
@@ -236,6 +288,25 @@ pub trait HomeserverConnection: Send + Sync {
     /// not be created.
     async fn create_device(&self, mxid: &str, device_id: &str) -> Result<(), Self::Error>;
 
+    /// Set the display name of a device on the homeserver.
+    ///
+    /// # Parameters
+    ///
+    /// * `mxid` - The Matrix ID of the user owning the device.
+    /// * `device_id` - The ID of the device to update.
+    /// * `display_name` - The display name to set.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the homeserver is unreachable or the device could
+    /// not be updated.
+    async fn update_device_display_name(
+        &self,
+        mxid: &str,
+        device_id: &str,
+        display_name: &str,
+    ) -> Result<(), Self::Error>;
+
     /// Delete a device for a user on the homeserver.
     ///
     /// # Parameters
@@ -286,6 +357,20 @@ pub trait HomeserverConnection: Send + Sync {
     /// Returns an error if the homeserver is unreachable or the displayname
     /// could not be unset.
     async fn unset_displayname(&self, mxid: &str) -> Result<(), Self::Error>;
+
+    /// Allow a user to replace their cross-signing keys without going through
+    /// user-interactive authentication, for a limited period of time.
+    ///
+    /// # Parameters
+    ///
+    /// * `mxid` - The Matrix ID of the user allowed to reset their
+    ///   cross-signing identity.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the homeserver is unreachable or the reset could
+    /// not be allowed.
+    async fn allow_cross_signing_reset(&self, mxid: &str) -> Result<(), Self::Error>;
 }
 
 #[async_trait::async_trait]
@@ -308,6 +393,69 @@ impl<T: HomeserverConnection + Send + Sync + ?Sized> HomeserverConnection for &T
         (**self).create_device(mxid, device_id).await
     }
 
+    async fn update_device_display_name(
+        &self,
+        mxid: &str,
+        device_id: &str,
+        display_name: &str,
+    ) -> Result<(), Self::Error> {
+        (**self)
+            .update_device_display_name(mxid, device_id, display_name)
+            .await
+    }
+
+    async fn delete_device(&self, mxid: &str, device_id: &str) -> Result<(), Self::Error> {
+        (**self).delete_device(mxid, device_id).await
+    }
+
+    async fn delete_user(&self, mxid: &str, erase: bool) -> Result<(), Self::Error> {
+        (**self).delete_user(mxid, erase).await
+    }
+
+    async fn set_displayname(&self, mxid: &str, displayname: &str) -> Result<(), Self::Error> {
+        (**self).set_displayname(mxid, displayname).await
+    }
+
+    async fn unset_displayname(&self, mxid: &str) -> Result<(), Self::Error> {
+        (**self).unset_displayname(mxid).await
+    }
+
+    async fn allow_cross_signing_reset(&self, mxid: &str) -> Result<(), Self::Error> {
+        (**self).allow_cross_signing_reset(mxid).await
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: HomeserverConnection + Send + Sync + ?Sized> HomeserverConnection for Box<T> {
+    type Error = T::Error;
+
+    fn homeserver(&self) -> &str {
+        (**self).homeserver()
+    }
+
+    async fn query_user(&self, mxid: &str) -> Result<MatrixUser, Self::Error> {
+        (**self).query_user(mxid).await
+    }
+
+    async fn provision_user(&self, request: &ProvisionRequest) -> Result<bool, Self::Error> {
+        (**self).provision_user(request).await
+    }
+
+    async fn create_device(&self, mxid: &str, device_id: &str) -> Result<(), Self::Error> {
+        (**self).create_device(mxid, device_id).await
+    }
+
+    async fn update_device_display_name(
+        &self,
+        mxid: &str,
+        device_id: &str,
+        display_name: &str,
+    ) -> Result<(), Self::Error> {
+        (**self)
+            .update_device_display_name(mxid, device_id, display_name)
+            .await
+    }
+
     async fn delete_device(&self, mxid: &str, device_id: &str) -> Result<(), Self::Error> {
         (**self).delete_device(mxid, device_id).await
     }
@@ -323,4 +471,60 @@ impl<T: HomeserverConnection + Send + Sync + ?Sized> HomeserverConnection for &T
     async fn unset_displayname(&self, mxid: &str) -> Result<(), Self::Error> {
         (**self).unset_displayname(mxid).await
     }
+
+    async fn allow_cross_signing_reset(&self, mxid: &str) -> Result<(), Self::Error> {
+        (**self).allow_cross_signing_reset(mxid).await
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: HomeserverConnection + Send + Sync + ?Sized> HomeserverConnection for Arc<T> {
+    type Error = T::Error;
+
+    fn homeserver(&self) -> &str {
+        (**self).homeserver()
+    }
+
+    async fn query_user(&self, mxid: &str) -> Result<MatrixUser, Self::Error> {
+        (**self).query_user(mxid).await
+    }
+
+    async fn provision_user(&self, request: &ProvisionRequest) -> Result<bool, Self::Error> {
+        (**self).provision_user(request).await
+    }
+
+    async fn create_device(&self, mxid: &str, device_id: &str) -> Result<(), Self::Error> {
+        (**self).create_device(mxid, device_id).await
+    }
+
+    async fn update_device_display_name(
+        &self,
+        mxid: &str,
+        device_id: &str,
+        display_name: &str,
+    ) -> Result<(), Self::Error> {
+        (**self)
+            .update_device_display_name(mxid, device_id, display_name)
+            .await
+    }
+
+    async fn delete_device(&self, mxid: &str, device_id: &str) -> Result<(), Self::Error> {
+        (**self).delete_device(mxid, device_id).await
+    }
+
+    async fn delete_user(&self, mxid: &str, erase: bool) -> Result<(), Self::Error> {
+        (**self).delete_user(mxid, erase).await
+    }
+
+    async fn set_displayname(&self, mxid: &str, displayname: &str) -> Result<(), Self::Error> {
+        (**self).set_displayname(mxid, displayname).await
+    }
+
+    async fn unset_displayname(&self, mxid: &str) -> Result<(), Self::Error> {
+        (**self).unset_displayname(mxid).await
+    }
+
+    async fn allow_cross_signing_reset(&self, mxid: &str) -> Result<(), Self::Error> {
+        (**self).allow_cross_signing_reset(mxid).await
+    }
 }
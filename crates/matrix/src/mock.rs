@@ -18,14 +18,15 @@ use anyhow::Context;
 use async_trait::async_trait;
 use tokio::sync::RwLock;
 
-use crate::{MatrixUser, ProvisionRequest};
+use crate::{MatrixUser, ProvisionRequest, Unsupported};
 
 struct MockUser {
     sub: String,
     avatar_url: Option<String>,
     displayname: Option<String>,
-    devices: HashSet<String>,
+    devices: HashMap<String, Option<String>>,
     emails: Option<Vec<String>>,
+    cross_signing_reset_allowed: bool,
 }
 
 /// A mock implementation of a [`HomeserverConnection`], which never fails and
@@ -33,6 +34,7 @@ struct MockUser {
 pub struct HomeserverConnection {
     homeserver: String,
     users: RwLock<HashMap<String, MockUser>>,
+    unsupported: RwLock<HashSet<&'static str>>,
 }
 
 impl HomeserverConnection {
@@ -44,8 +46,23 @@ impl HomeserverConnection {
         Self {
             homeserver: homeserver.into(),
             users: RwLock::new(HashMap::new()),
+            unsupported: RwLock::new(HashSet::new()),
         }
     }
+
+    /// Make an operation fail with an [`Unsupported`] error, like it would on
+    /// a homeserver which has no API for it.
+    pub async fn set_unsupported(&self, operation: &'static str) {
+        self.unsupported.write().await.insert(operation);
+    }
+
+    async fn ensure_supported(&self, operation: &'static str) -> Result<(), anyhow::Error> {
+        if self.unsupported.read().await.contains(operation) {
+            return Err(Unsupported::new(operation).into());
+        }
+
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -72,8 +89,9 @@ impl crate::HomeserverConnection for HomeserverConnection {
             sub: request.sub().to_owned(),
             avatar_url: None,
             displayname: None,
-            devices: HashSet::new(),
+            devices: HashMap::new(),
             emails: None,
+            cross_signing_reset_allowed: false,
         });
 
         anyhow::ensure!(
@@ -97,13 +115,32 @@ impl crate::HomeserverConnection for HomeserverConnection {
     }
 
     async fn create_device(&self, mxid: &str, device_id: &str) -> Result<(), Self::Error> {
+        self.ensure_supported("create_device").await?;
         let mut users = self.users.write().await;
         let user = users.get_mut(mxid).context("User not found")?;
-        user.devices.insert(device_id.to_owned());
+        user.devices.entry(device_id.to_owned()).or_default();
+        Ok(())
+    }
+
+    async fn update_device_display_name(
+        &self,
+        mxid: &str,
+        device_id: &str,
+        display_name: &str,
+    ) -> Result<(), Self::Error> {
+        self.ensure_supported("update_device_display_name").await?;
+        let mut users = self.users.write().await;
+        let user = users.get_mut(mxid).context("User not found")?;
+        let device = user
+            .devices
+            .get_mut(device_id)
+            .context("Device not found")?;
+        *device = Some(display_name.to_owned());
         Ok(())
     }
 
     async fn delete_device(&self, mxid: &str, device_id: &str) -> Result<(), Self::Error> {
+        self.ensure_supported("delete_device").await?;
         let mut users = self.users.write().await;
         let user = users.get_mut(mxid).context("User not found")?;
         user.devices.remove(device_id);
@@ -124,6 +161,7 @@ impl crate::HomeserverConnection for HomeserverConnection {
     }
 
     async fn set_displayname(&self, mxid: &str, displayname: &str) -> Result<(), Self::Error> {
+        self.ensure_supported("set_displayname").await?;
         let mut users = self.users.write().await;
         let user = users.get_mut(mxid).context("User not found")?;
         user.displayname = Some(displayname.to_owned());
@@ -131,11 +169,20 @@ impl crate::HomeserverConnection for HomeserverConnection {
     }
 
     async fn unset_displayname(&self, mxid: &str) -> Result<(), Self::Error> {
+        self.ensure_supported("unset_displayname").await?;
         let mut users = self.users.write().await;
         let user = users.get_mut(mxid).context("User not found")?;
         user.displayname = None;
         Ok(())
     }
+
+    async fn allow_cross_signing_reset(&self, mxid: &str) -> Result<(), Self::Error> {
+        self.ensure_supported("allow_cross_signing_reset").await?;
+        let mut users = self.users.write().await;
+        let user = users.get_mut(mxid).context("User not found")?;
+        user.cross_signing_reset_allowed = true;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -180,6 +227,13 @@ mod tests {
         let user = conn.query_user(mxid).await.unwrap();
         assert_eq!(user.displayname, None);
 
+        // Allow the user to reset their cross-signing identity
+        assert!(conn.allow_cross_signing_reset(mxid).await.is_ok());
+        assert!(conn
+            .allow_cross_signing_reset("@unknown:example.org")
+            .await
+            .is_err());
+
         // Deleting a non-existent device should not fail
         assert!(conn.delete_device(mxid, device).await.is_ok());
 
@@ -188,8 +242,32 @@ mod tests {
         // Create the same device again
         assert!(conn.create_device(mxid, device).await.is_ok());
 
+        // Rename the device
+        assert!(conn
+            .update_device_display_name(mxid, device, "Phone")
+            .await
+            .is_ok());
+        // Renaming a non-existent device fails
+        assert!(conn
+            .update_device_display_name(mxid, "unknown", "Phone")
+            .await
+            .is_err());
+
         // XXX: there is no API to query devices yet in the trait
         // Delete the device
         assert!(conn.delete_device(mxid, device).await.is_ok());
+
+        // Operations can be made unsupported, like on some homeservers
+        conn.set_unsupported("update_device_display_name").await;
+        let error = conn
+            .update_device_display_name(mxid, device, "Phone")
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error
+                .downcast_ref::<Unsupported>()
+                .map(Unsupported::operation),
+            Some("update_device_display_name")
+        );
     }
 }
@@ -35,6 +35,7 @@ use crate::{
     oidc::{ApplicationType, SubjectType},
     requests::GrantType,
     response_type::ResponseType,
+    scope::Scope,
 };
 
 impl<T> Localized<T> {
@@ -96,6 +97,7 @@ pub struct ClientMetadataSerdeHelper {
     redirect_uris: Option<Vec<Url>>,
     response_types: Option<Vec<ResponseType>>,
     grant_types: Option<Vec<GrantType>>,
+    scope: Option<Scope>,
     application_type: Option<ApplicationType>,
     contacts: Option<Vec<String>>,
     jwks_uri: Option<Url>,
@@ -127,6 +129,7 @@ pub struct ClientMetadataSerdeHelper {
     introspection_encrypted_response_alg: Option<JsonWebEncryptionAlg>,
     introspection_encrypted_response_enc: Option<JsonWebEncryptionEnc>,
     post_logout_redirect_uris: Option<Vec<Url>>,
+    brand_color: Option<String>,
     #[serde(flatten)]
     extra: ClientMetadataLocalizedFields,
 }
@@ -139,6 +142,7 @@ impl From<VerifiedClientMetadata> for ClientMetadataSerdeHelper {
                     redirect_uris,
                     response_types,
                     grant_types,
+                    scope,
                     application_type,
                     contacts,
                     client_name,
@@ -174,6 +178,7 @@ impl From<VerifiedClientMetadata> for ClientMetadataSerdeHelper {
                     introspection_encrypted_response_alg,
                     introspection_encrypted_response_enc,
                     post_logout_redirect_uris,
+                    brand_color,
                 },
         } = metadata;
 
@@ -181,6 +186,7 @@ impl From<VerifiedClientMetadata> for ClientMetadataSerdeHelper {
             redirect_uris,
             response_types,
             grant_types,
+            scope,
             application_type,
             contacts,
             jwks_uri,
@@ -211,6 +217,7 @@ impl From<VerifiedClientMetadata> for ClientMetadataSerdeHelper {
             introspection_encrypted_response_alg,
             introspection_encrypted_response_enc,
             post_logout_redirect_uris,
+            brand_color,
             extra: ClientMetadataLocalizedFields {
                 client_name,
                 logo_uri,
@@ -228,6 +235,7 @@ impl From<ClientMetadataSerdeHelper> for ClientMetadata {
             redirect_uris,
             response_types,
             grant_types,
+            scope,
             application_type,
             contacts,
             jwks_uri,
@@ -258,6 +266,7 @@ impl From<ClientMetadataSerdeHelper> for ClientMetadata {
             introspection_encrypted_response_alg,
             introspection_encrypted_response_enc,
             post_logout_redirect_uris,
+            brand_color,
             extra:
                 ClientMetadataLocalizedFields {
                     client_name,
@@ -272,6 +281,7 @@ impl From<ClientMetadataSerdeHelper> for ClientMetadata {
             redirect_uris,
             response_types,
             grant_types,
+            scope,
             application_type,
             contacts,
             client_name,
@@ -307,6 +317,7 @@ impl From<ClientMetadataSerdeHelper> for ClientMetadata {
             introspection_encrypted_response_alg,
             introspection_encrypted_response_enc,
             post_logout_redirect_uris,
+            brand_color,
         }
     }
 }
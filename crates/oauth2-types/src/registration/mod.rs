@@ -34,6 +34,7 @@ use crate::{
     oidc::{ApplicationType, SubjectType},
     requests::GrantType,
     response_type::ResponseType,
+    scope::Scope,
 };
 
 mod client_metadata_serde;
@@ -168,6 +169,9 @@ pub struct ClientMetadata {
     /// [token endpoint]: https://www.rfc-editor.org/rfc/rfc6749.html#section-3.2
     pub grant_types: Option<Vec<GrantType>>,
 
+    /// The scope values that the client can use when requesting access tokens.
+    pub scope: Option<Scope>,
+
     /// The kind of the application.
     ///
     /// Defaults to [`DEFAULT_APPLICATION_TYPE`].
@@ -436,6 +440,12 @@ pub struct ClientMetadata {
     ///
     /// [RP-Initiated Logout endpoint]: https://openid.net/specs/openid-connect-rpinitiated-1_0.html
     pub post_logout_redirect_uris: Option<Vec<Url>>,
+
+    /// Color used to brand the pages shown on behalf of the client, as a
+    /// hexadecimal RGB color like `#0dbd8b`.
+    ///
+    /// This is an extension to the client metadata.
+    pub brand_color: Option<String>,
 }
 
 impl ClientMetadata {
@@ -568,6 +578,16 @@ impl ClientMetadata {
             )?;
         }
 
+        if let Some(color) = self
+            .brand_color
+            .as_ref()
+            .filter(|color| !is_hex_color(color))
+        {
+            return Err(ClientMetadataVerificationError::InvalidBrandColor(
+                color.clone(),
+            ));
+        }
+
         Ok(VerifiedClientMetadata { inner: self })
     }
 
@@ -880,6 +900,18 @@ pub enum ClientMetadataVerificationError {
     /// The given encryption field has an `enc` value but not `alg` value.
     #[error("{0} missing encryption alg value")]
     MissingEncryptionAlg(&'static str),
+
+    /// The brand color is not a hexadecimal RGB color.
+    #[error("invalid brand color: {0}")]
+    InvalidBrandColor(String),
+}
+
+/// Whether the value is a hexadecimal RGB color, like `#0dbd8b` or `#fff`
+#[must_use]
+pub fn is_hex_color(value: &str) -> bool {
+    value.strip_prefix('#').is_some_and(|hex| {
+        (hex.len() == 3 || hex.len() == 6) && hex.chars().all(|c| c.is_ascii_hexdigit())
+    })
 }
 
 /// The issuer response to dynamic client registration.
@@ -905,6 +937,19 @@ pub struct ClientRegistrationResponse {
     #[serde(default)]
     #[serde_as(as = "Option<TimestampSeconds<i64>>")]
     pub client_secret_expires_at: Option<DateTime<Utc>>,
+
+    /// A token to authenticate with the client configuration endpoint, as
+    /// defined in [RFC 7592].
+    ///
+    /// [RFC 7592]: https://www.rfc-editor.org/rfc/rfc7592
+    #[serde(default)]
+    pub registration_access_token: Option<String>,
+
+    /// The URL of the client configuration endpoint, as defined in [RFC 7592].
+    ///
+    /// [RFC 7592]: https://www.rfc-editor.org/rfc/rfc7592
+    #[serde(default)]
+    pub registration_client_uri: Option<Url>,
 }
 
 #[cfg(test)]
@@ -1392,6 +1437,32 @@ mod tests {
         metadata.validate().unwrap();
     }
 
+    #[test]
+    fn validate_brand_color() {
+        let mut metadata = valid_client_metadata();
+
+        // Err - Not a hexadecimal color
+        for color in [
+            "red",
+            "#0dbd8",
+            "0dbd8b",
+            "#0dbd8g",
+            "url(https://example.com/)",
+        ] {
+            metadata.brand_color = Some(color.to_owned());
+            assert_matches!(
+                metadata.clone().validate(),
+                Err(ClientMetadataVerificationError::InvalidBrandColor(_))
+            );
+        }
+
+        // Ok - Hexadecimal colors
+        for color in ["#0dbd8b", "#0DBD8B", "#fff"] {
+            metadata.brand_color = Some(color.to_owned());
+            metadata.clone().validate().unwrap();
+        }
+    }
+
     #[test]
     fn validate_introspection_encrypted_response() {
         let mut metadata = valid_client_metadata();
@@ -24,8 +24,9 @@ use mas_iana::oauth::{OAuthAccessTokenType, OAuthTokenTypeHint};
 use parse_display::{Display, FromStr};
 use serde::{Deserialize, Serialize};
 use serde_with::{
-    formats::SpaceSeparator, serde_as, skip_serializing_none, DeserializeFromStr, DisplayFromStr,
-    DurationSeconds, SerializeDisplay, StringWithSeparator, TimestampSeconds,
+    formats::{PreferOne, SpaceSeparator},
+    serde_as, skip_serializing_none, DeserializeFromStr, DisplayFromStr, DurationSeconds,
+    OneOrMany, SerializeDisplay, StringWithSeparator, TimestampSeconds,
 };
 use url::Url;
 
@@ -405,6 +406,39 @@ pub struct DeviceAuthorizationResponse {
 }
 
 impl DeviceAuthorizationResponse {
+    /// The device verification code, to exchange for an access token at the
+    /// token endpoint once the user authorized the device.
+    #[must_use]
+    pub fn device_code(&self) -> &str {
+        &self.device_code
+    }
+
+    /// The end-user verification code, to show to the user.
+    #[must_use]
+    pub fn user_code(&self) -> &str {
+        &self.user_code
+    }
+
+    /// The end-user verification URI on the authorization server, to show to
+    /// the user.
+    #[must_use]
+    pub fn verification_uri(&self) -> &Url {
+        &self.verification_uri
+    }
+
+    /// The verification URI including the `user_code`, designed for
+    /// non-textual transmission, e.g. as a QR code.
+    #[must_use]
+    pub fn verification_uri_complete(&self) -> Option<&Url> {
+        self.verification_uri_complete.as_ref()
+    }
+
+    /// The lifetime of the `device_code` and `user_code`.
+    #[must_use]
+    pub fn expires_in(&self) -> Duration {
+        self.expires_in
+    }
+
     /// The minimum amount of time in seconds that the client should wait
     /// between polling requests to the token endpoint.
     ///
@@ -493,6 +527,28 @@ pub struct ClientCredentialsGrant {
     pub scope: Option<Scope>,
 }
 
+/// A request to the [Token Endpoint] for the [JWT Bearer] grant type.
+///
+/// [Token Endpoint]: https://www.rfc-editor.org/rfc/rfc6749#section-3.2
+/// [JWT Bearer]: https://www.rfc-editor.org/rfc/rfc7523#section-2.1
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct JwtBearerGrant {
+    /// The signed JWT used as an authorization grant.
+    pub assertion: String,
+
+    /// The scope of the access request.
+    pub scope: Option<Scope>,
+}
+
+impl fmt::Debug for JwtBearerGrant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JwtBearerGrant")
+            .field("scope", &self.scope)
+            .finish_non_exhaustive()
+    }
+}
+
 /// A request to the [Token Endpoint] for the [Device Authorization] grant type.
 ///
 /// [Token Endpoint]: https://www.rfc-editor.org/rfc/rfc6749#section-3.2
@@ -500,7 +556,7 @@ pub struct ClientCredentialsGrant {
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct DeviceCodeGrant {
     /// The device verification code, from the device authorization response.
-    pub device_code: Option<Scope>,
+    pub device_code: String,
 }
 
 impl fmt::Debug for DeviceCodeGrant {
@@ -573,11 +629,30 @@ pub enum AccessTokenRequest {
     #[serde(rename = "urn:ietf:params:oauth:grant-type:device_code")]
     DeviceCode(DeviceCodeGrant),
 
+    /// A request with a JWT used as an authorization grant.
+    #[serde(rename = "urn:ietf:params:oauth:grant-type:jwt-bearer")]
+    JwtBearer(JwtBearerGrant),
+
     /// An unsupported request.
     #[serde(skip_serializing, other)]
     Unsupported,
 }
 
+impl AccessTokenRequest {
+    /// The grant type of the request, or `None` if it is not supported.
+    #[must_use]
+    pub fn grant_type(&self) -> Option<GrantType> {
+        match self {
+            Self::AuthorizationCode(_) => Some(GrantType::AuthorizationCode),
+            Self::RefreshToken(_) => Some(GrantType::RefreshToken),
+            Self::ClientCredentials(_) => Some(GrantType::ClientCredentials),
+            Self::DeviceCode(_) => Some(GrantType::DeviceCode),
+            Self::JwtBearer(_) => Some(GrantType::JwtBearer),
+            Self::Unsupported => None,
+        }
+    }
+}
+
 /// A successful response from the [Token Endpoint].
 ///
 /// [Token Endpoint]: https://www.rfc-editor.org/rfc/rfc6749#section-3.2
@@ -670,12 +745,19 @@ pub struct IntrospectionRequest {
 
     /// A hint about the type of the token submitted for introspection.
     pub token_type_hint: Option<OAuthTokenTypeHint>,
+
+    /// The Matrix ID of the user a Matrix application service is acting as.
+    ///
+    /// This is a Matrix-specific extension, used when introspecting
+    /// application service tokens.
+    pub user_id: Option<String>,
 }
 
 impl fmt::Debug for IntrospectionRequest {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("IntrospectionRequest")
             .field("token_type_hint", &self.token_type_hint)
+            .field("user_id", &self.user_id)
             .finish_non_exhaustive()
     }
 }
@@ -718,14 +800,38 @@ pub struct IntrospectionResponse {
     /// Subject of the token.
     pub sub: Option<String>,
 
-    /// Intended audience of the token.
-    pub aud: Option<String>,
+    /// Intended audiences of the token.
+    ///
+    /// Serialized as a single string if there is only one audience.
+    #[serde_as(as = "Option<OneOrMany<_, PreferOne>>")]
+    pub aud: Option<Vec<String>>,
 
     /// Issuer of the token.
     pub iss: Option<String>,
 
     /// String identifier for the token.
     pub jti: Option<String>,
+
+    /// Whether the user owning the token is quarantined.
+    ///
+    /// This is a non-standard claim. The token is still valid, but the resource
+    /// server may want to restrict what the user can do with it.
+    pub quarantined: Option<bool>,
+
+    /// The Matrix device ID the token is bound to.
+    ///
+    /// This is a non-standard claim, only given to trusted resource servers.
+    pub device_id: Option<String>,
+
+    /// The ID of the session the token belongs to.
+    ///
+    /// This is a non-standard claim, only given to trusted resource servers.
+    pub session_id: Option<String>,
+
+    /// The human-readable name of the client the token was issued to.
+    ///
+    /// This is a non-standard claim, only given to trusted resource servers.
+    pub client_name: Option<String>,
 }
 
 /// A request to the [Revocation Endpoint].
@@ -812,6 +918,22 @@ mod tests {
         assert_serde_json(&req, expected);
     }
 
+    #[test]
+    fn serde_jwt_bearer_grant() {
+        let expected = json!({
+            "grant_type": "urn:ietf:params:oauth:grant-type:jwt-bearer",
+            "assertion": "abcd",
+            "scope": "openid",
+        });
+
+        let req = AccessTokenRequest::JwtBearer(JwtBearerGrant {
+            assertion: "abcd".into(),
+            scope: Some(vec![OPENID].into_iter().collect()),
+        });
+
+        assert_serde_json(&req, expected);
+    }
+
     #[test]
     fn serialize_grant_type() {
         assert_eq!(
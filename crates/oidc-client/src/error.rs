@@ -22,6 +22,7 @@ use mas_http::{catch_http_codes, form_urlencoded_request, json_request, json_res
 use mas_jose::{
     claims::ClaimError,
     jwa::InvalidAlgorithm,
+    jwe::{JweDecodeError, JweDecryptionError},
     jwt::{JwtDecodeError, JwtSignatureError, NoKeyWorked},
 };
 use mas_keystore::WrongAlgorithmError;
@@ -54,6 +55,12 @@ pub enum Error {
     /// An error occurred requesting an access token with client credentials.
     TokenClientCredentials(#[from] TokenRequestError),
 
+    /// An error occurred requesting a device authorization.
+    DeviceAuthorization(#[from] DeviceAuthorizationError),
+
+    /// An error occurred exchanging a device code for an access token.
+    TokenDeviceCode(#[from] TokenDeviceCodeError),
+
     /// An error occurred refreshing an access token.
     TokenRefresh(#[from] TokenRefreshError),
 
@@ -136,6 +143,10 @@ pub enum RegistrationError {
     #[error(transparent)]
     Http(#[from] HttpError),
 
+    /// The initial or registration access token is invalid.
+    #[error(transparent)]
+    Token(#[from] InvalidBearerToken),
+
     /// No client secret was received although one was expected because of the
     /// authentication method.
     #[error("missing client secret in response")]
@@ -356,6 +367,88 @@ pub enum TokenRefreshError {
     IdToken(#[from] IdTokenError),
 }
 
+/// All possible errors when requesting a device authorization.
+#[derive(Debug, Error)]
+pub enum DeviceAuthorizationError {
+    /// An error occurred building the request.
+    #[error(transparent)]
+    IntoHttp(#[from] http::Error),
+
+    /// An error occurred adding the client credentials to the request.
+    #[error(transparent)]
+    Credentials(#[from] CredentialsError),
+
+    /// An error occurred serializing the request.
+    #[error(transparent)]
+    UrlEncoded(#[from] serde_urlencoded::ser::Error),
+
+    /// The server returned an HTTP error status code.
+    #[error(transparent)]
+    Http(#[from] HttpError),
+
+    /// An error occurred deserializing the response.
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    /// An error occurred sending the request.
+    #[error(transparent)]
+    Service(BoxError),
+}
+
+impl<S> From<form_urlencoded_request::Error<S>> for DeviceAuthorizationError
+where
+    S: Into<DeviceAuthorizationError>,
+{
+    fn from(err: form_urlencoded_request::Error<S>) -> Self {
+        match err {
+            form_urlencoded_request::Error::Serialize { inner } => inner.into(),
+            form_urlencoded_request::Error::Service { inner } => inner.into(),
+        }
+    }
+}
+
+impl<S> From<json_response::Error<S>> for DeviceAuthorizationError
+where
+    S: Into<DeviceAuthorizationError>,
+{
+    fn from(err: json_response::Error<S>) -> Self {
+        match err {
+            json_response::Error::Deserialize { inner } => inner.into(),
+            json_response::Error::Service { inner } => inner.into(),
+        }
+    }
+}
+
+impl<S> From<catch_http_codes::Error<S, Option<ErrorBody>>> for DeviceAuthorizationError
+where
+    S: Into<BoxError>,
+{
+    fn from(err: catch_http_codes::Error<S, Option<ErrorBody>>) -> Self {
+        match err {
+            catch_http_codes::Error::HttpError { status_code, inner } => {
+                HttpError::new(status_code, inner).into()
+            }
+            catch_http_codes::Error::Service { inner } => Self::Service(inner.into()),
+        }
+    }
+}
+
+/// All possible errors when exchanging a device code for an access token.
+#[derive(Debug, Error)]
+pub enum TokenDeviceCodeError {
+    /// An error occurred requesting the access token.
+    #[error(transparent)]
+    Token(#[from] TokenRequestError),
+
+    /// The user denied the authorization request.
+    #[error("the authorization request was denied")]
+    AccessDenied,
+
+    /// The device code expired before the user authorized the device.
+    #[error("the device code expired")]
+    Expired,
+}
+
 /// All possible errors when revoking a token.
 #[derive(Debug, Error)]
 pub enum TokenRevokeError {
@@ -608,6 +701,15 @@ pub enum JwtVerificationError {
     WrongSignatureAlg,
 }
 
+impl JwtVerificationError {
+    /// Whether none of the keys could verify the signature of the JWT, e.g.
+    /// because the issuer rotated its keys.
+    #[must_use]
+    pub fn is_signature_error(&self) -> bool {
+        matches!(self, Self::JwtSignature(_))
+    }
+}
+
 /// All possible errors when verifying an ID token.
 #[derive(Debug, Error)]
 pub enum IdTokenError {
@@ -620,6 +722,18 @@ pub enum IdTokenError {
     #[error("Authorization ID token is missing")]
     MissingAuthIdToken,
 
+    /// An error occurred decoding the encrypted ID Token.
+    #[error(transparent)]
+    JweDecode(#[from] JweDecodeError),
+
+    /// An error occurred decrypting the ID Token.
+    #[error(transparent)]
+    JweDecryption(#[from] JweDecryptionError),
+
+    /// The payload of the encrypted ID Token is not a valid JWT.
+    #[error("invalid encrypted ID token payload")]
+    InvalidEncryptedPayload,
+
     /// An error occurred validating the ID Token's signature and basic claims.
     #[error(transparent)]
     Jwt(#[from] JwtVerificationError),
@@ -639,6 +753,48 @@ pub enum IdTokenError {
     WrongAuthTime,
 }
 
+impl IdTokenError {
+    /// Whether none of the keys could verify the signature of the ID Token.
+    #[must_use]
+    pub fn is_signature_error(&self) -> bool {
+        matches!(self, Self::Jwt(e) if e.is_signature_error())
+    }
+}
+
+/// All possible errors when verifying a logout token.
+#[derive(Debug, Error)]
+pub enum LogoutTokenError {
+    /// An error occurred validating the logout token's signature and basic
+    /// claims.
+    #[error(transparent)]
+    Jwt(#[from] JwtVerificationError),
+
+    /// An error occurred extracting a claim.
+    #[error(transparent)]
+    Claim(#[from] ClaimError),
+
+    /// The `events` claim doesn't contain the back-channel logout event.
+    #[error("missing back-channel logout event")]
+    MissingLogoutEvent,
+
+    /// Neither the `sub` nor the `sid` claim is present.
+    #[error("missing both subject identifier and session ID")]
+    MissingSubjectAndSessionId,
+
+    /// The `nonce` claim is present, which is prohibited in logout tokens.
+    #[error("logout token must not contain a nonce")]
+    UnexpectedNonce,
+}
+
+impl LogoutTokenError {
+    /// Whether none of the keys could verify the signature of the logout
+    /// token.
+    #[must_use]
+    pub fn is_signature_error(&self) -> bool {
+        matches!(self, Self::Jwt(e) if e.is_signature_error())
+    }
+}
+
 /// An error that can be returned by an OpenID Provider.
 #[derive(Debug, Clone, Error)]
 #[error("{status}: {body:?}")]
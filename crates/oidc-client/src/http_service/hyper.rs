@@ -32,19 +32,34 @@ static MAS_USER_AGENT: HeaderValue = HeaderValue::from_static("mas-oidc-client/0
 
 /// Constructs a [`HttpService`] using [hyper] as a backend.
 ///
+/// The server certificates are verified against the native root certificates
+/// of the system.
+///
 /// [hyper]: https://crates.io/crates/hyper
 #[must_use]
 pub fn hyper_service() -> HttpService {
-    let resolver = ServiceBuilder::new().service(GaiResolver::new());
-
-    let mut http = HttpConnector::new_with_resolver(resolver);
-    http.enforce_http(false);
-
     let tls_config = rustls::ClientConfig::builder()
         .with_safe_defaults()
         .with_native_roots()
         .with_no_client_auth();
 
+    hyper_service_with_tls_config(tls_config)
+}
+
+/// Constructs a [`HttpService`] using [hyper] as a backend, with the given TLS
+/// configuration.
+///
+/// This allows to trust custom root certificates, or to authenticate with a
+/// client certificate.
+///
+/// [hyper]: https://crates.io/crates/hyper
+#[must_use]
+pub fn hyper_service_with_tls_config(tls_config: rustls::ClientConfig) -> HttpService {
+    let resolver = ServiceBuilder::new().service(GaiResolver::new());
+
+    let mut http = HttpConnector::new_with_resolver(resolver);
+    http.enforce_http(false);
+
     let https = HttpsConnectorBuilder::new()
         .with_tls_config(tls_config)
         .https_or_http()
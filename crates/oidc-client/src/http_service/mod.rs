@@ -14,8 +14,58 @@
 
 //! Reexports of traits to implement to provide a custom HTTP service for
 //! `Client`.
+//!
+//! Any [`Service`](tower::Service) can be wrapped in a [`HttpService`] with
+//! [`HttpService::new()`]. For simpler HTTP stacks, implementing the
+//! [`HttpClient`] trait and wrapping it with [`from_http_client()`] is enough.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use tower::BoxError;
 
 #[cfg(feature = "hyper")]
 pub mod hyper;
 
 pub use mas_http::{BoxCloneSyncService, HttpService};
+
+/// A minimal HTTP client, to plug an existing HTTP stack into this crate.
+///
+/// The implementation is responsible for the transport concerns, like the
+/// TLS configuration, proxies, timeouts or the `User-Agent` header.
+#[async_trait]
+pub trait HttpClient: Send + Sync {
+    /// Send the request and return the response, whatever its status code.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request could not be sent, or the response
+    /// could not be received.
+    async fn send(&self, request: http::Request<Bytes>) -> Result<http::Response<Bytes>, BoxError>;
+}
+
+#[async_trait]
+impl<C> HttpClient for Arc<C>
+where
+    C: HttpClient + ?Sized,
+{
+    async fn send(&self, request: http::Request<Bytes>) -> Result<http::Response<Bytes>, BoxError> {
+        (**self).send(request).await
+    }
+}
+
+/// Constructs a [`HttpService`] sending the requests with the given
+/// [`HttpClient`].
+#[must_use]
+pub fn from_http_client<C>(client: C) -> HttpService
+where
+    C: HttpClient + 'static,
+{
+    let client = Arc::new(client);
+
+    HttpService::new(tower::service_fn(move |request| {
+        let client = Arc::clone(&client);
+        async move { client.send(request).await }
+    }))
+}
@@ -30,7 +30,7 @@
 //! - Grant Types:
 //!   - [Authorization Code](https://openid.net/specs/openid-connect-core-1_0.html#CodeFlowAuth)
 //!   - [Client Credentials](https://www.rfc-editor.org/rfc/rfc6749#section-4.4)
-//!   - [Device Code](https://www.rfc-editor.org/rfc/rfc8628) (TBD)
+//!   - [Device Code](https://www.rfc-editor.org/rfc/rfc8628)
 //! - [User Info](https://openid.net/specs/openid-connect-core-1_0.html#UserInfo)
 //! - Token:
 //!   - [Refresh Token](https://openid.net/specs/openid-connect-core-1_0.html#RefreshTokens)
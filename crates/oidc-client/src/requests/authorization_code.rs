@@ -30,7 +30,7 @@ use oauth2_types::{
     prelude::CodeChallengeMethodExt,
     requests::{
         AccessTokenRequest, AccessTokenResponse, AuthorizationCodeGrant, AuthorizationRequest,
-        Display, Prompt, PushedAuthorizationResponse,
+        Display, Prompt, PushedAuthorizationResponse, ResponseMode,
     },
     scope::Scope,
 };
@@ -49,7 +49,10 @@ use crate::{
         AuthorizationError, IdTokenError, PushedAuthorizationError, TokenAuthorizationCodeError,
     },
     http_service::HttpService,
-    requests::{jose::verify_id_token, token::request_access_token},
+    requests::{
+        jose::{decrypt_id_token, verify_id_token, JweDecryptionFn},
+        token::request_access_token,
+    },
     types::{
         client_credentials::ClientCredentials,
         scope::{ScopeExt, ScopeToken},
@@ -81,6 +84,13 @@ pub struct AuthorizationRequestData {
     /// set, this security measure will not be used.
     pub code_challenge_methods_supported: Option<Vec<PkceCodeChallengeMethod>>,
 
+    /// How the Authorization Server should return the result of the
+    /// authorization.
+    ///
+    /// If it is not set, the default of the authorization code flow is used,
+    /// which is to add the parameters to the query of the redirect URI.
+    pub response_mode: Option<ResponseMode>,
+
     /// How the Authorization Server should display the authentication and
     /// consent user interface pages to the End-User.
     pub display: Option<Display>,
@@ -109,6 +119,12 @@ pub struct AuthorizationRequestData {
 
     /// Requested Authentication Context Class Reference values.
     pub acr_values: Option<HashSet<String>>,
+
+    /// Whether this is an OpenID Connect request.
+    ///
+    /// If it is, the `openid` scope token is added to the scope and a nonce is
+    /// sent. Defaults to `true`.
+    pub openid: bool,
 }
 
 impl AuthorizationRequestData {
@@ -121,6 +137,7 @@ impl AuthorizationRequestData {
             scope,
             redirect_uri,
             code_challenge_methods_supported: None,
+            response_mode: None,
             display: None,
             prompt: None,
             max_age: None,
@@ -128,6 +145,7 @@ impl AuthorizationRequestData {
             id_token_hint: None,
             login_hint: None,
             acr_values: None,
+            openid: true,
         }
     }
 
@@ -142,6 +160,13 @@ impl AuthorizationRequestData {
         self
     }
 
+    /// Set the `response_mode` field of this `AuthorizationRequestData`.
+    #[must_use]
+    pub fn with_response_mode(mut self, response_mode: ResponseMode) -> Self {
+        self.response_mode = Some(response_mode);
+        self
+    }
+
     /// Set the `display` field of this `AuthorizationRequestData`.
     #[must_use]
     pub fn with_display(mut self, display: Display) -> Self {
@@ -190,6 +215,13 @@ impl AuthorizationRequestData {
         self.acr_values = Some(acr_values);
         self
     }
+
+    /// Set the `openid` field of this `AuthorizationRequestData`.
+    #[must_use]
+    pub fn with_openid(mut self, openid: bool) -> Self {
+        self.openid = openid;
+        self
+    }
 }
 
 /// The data necessary to validate a response from the Token endpoint in the
@@ -228,6 +260,7 @@ fn build_authorization_request(
         mut scope,
         redirect_uri,
         code_challenge_methods_supported,
+        response_mode,
         display,
         prompt,
         max_age,
@@ -235,6 +268,7 @@ fn build_authorization_request(
         id_token_hint,
         login_hint,
         acr_values,
+        openid,
     } = authorization_data;
 
     // Generate a random CSRF "state" token and a nonce.
@@ -263,7 +297,9 @@ fn build_authorization_request(
         (None, None)
     };
 
-    scope.insert_token(ScopeToken::Openid);
+    if openid {
+        scope.insert_token(ScopeToken::Openid);
+    }
 
     let auth_request = FullAuthorizationRequest {
         inner: AuthorizationRequest {
@@ -272,8 +308,8 @@ fn build_authorization_request(
             redirect_uri: Some(redirect_uri.clone()),
             scope,
             state: Some(state.clone()),
-            response_mode: None,
-            nonce: Some(nonce.clone()),
+            response_mode,
+            nonce: openid.then(|| nonce.clone()),
             display,
             prompt,
             max_age,
@@ -499,6 +535,12 @@ pub async fn build_par_authorization_url(
 ///   If it is not provided, the ID Token won't be verified. Note that in the
 ///   OpenID Connect specification, this verification is required.
 ///
+/// * `id_token_decryption` - The function to use to decrypt the ID Token, if
+///   the issuer encrypts it, as set with the `id_token_encrypted_response_alg`
+///   field in the client metadata.
+///
+///   If it is not provided, encrypted ID Tokens are rejected.
+///
 /// * `now` - The current time.
 ///
 /// * `rng` - A random number generator.
@@ -516,6 +558,7 @@ pub async fn access_token_with_authorization_code(
     code: String,
     validation_data: AuthorizationValidationData,
     id_token_verification_data: Option<JwtVerificationData<'_>>,
+    id_token_decryption: Option<&JweDecryptionFn>,
     now: DateTime<Utc>,
     rng: &mut impl Rng,
 ) -> Result<(AccessTokenResponse, Option<IdToken<'static>>), TokenAuthorizationCodeError> {
@@ -535,40 +578,91 @@ pub async fn access_token_with_authorization_code(
     )
     .await?;
 
-    let id_token = if let Some(verification_data) = id_token_verification_data {
-        let signing_alg = verification_data.signing_algorithm;
+    let id_token = id_token_verification_data
+        .map(|verification_data| {
+            verify_authorization_code_id_token(
+                &token_response,
+                &code,
+                &validation_data.nonce,
+                verification_data,
+                id_token_decryption,
+                now,
+            )
+        })
+        .transpose()?;
 
-        let id_token = token_response
-            .id_token
-            .as_deref()
-            .ok_or(IdTokenError::MissingIdToken)?;
+    Ok((token_response, id_token))
+}
 
-        let id_token = verify_id_token(id_token, verification_data, None, now)?;
+/// Verify the ID Token in the response to an authorization code grant request.
+///
+/// [`access_token_with_authorization_code()`] already does this when it gets
+/// the data to verify the ID Token. This allows to exchange the code without
+/// it, and to verify the ID Token afterwards, e.g. to retry the verification
+/// with an up to date JWKS.
+///
+/// # Arguments
+///
+/// * `token_response` - The response of the Token endpoint.
+///
+/// * `code` - The authorization code which was exchanged.
+///
+/// * `nonce` - The nonce from the validation data of the authorization.
+///
+/// * `verification_data` - The data required to verify the ID Token.
+///
+/// * `id_token_decryption` - The function to use to decrypt the ID Token, if
+///   the issuer encrypts it.
+///
+/// * `now` - The current time.
+///
+/// # Errors
+///
+/// Returns an error if the response has no ID Token, or if its verification
+/// fails.
+pub fn verify_authorization_code_id_token(
+    token_response: &AccessTokenResponse,
+    code: &str,
+    nonce: &str,
+    verification_data: JwtVerificationData<'_>,
+    id_token_decryption: Option<&JweDecryptionFn>,
+    now: DateTime<Utc>,
+) -> Result<IdToken<'static>, IdTokenError> {
+    let signing_alg = verification_data.signing_algorithm;
+
+    let id_token = token_response
+        .id_token
+        .as_deref()
+        .ok_or(IdTokenError::MissingIdToken)?;
+
+    // Encrypted ID Tokens must be decrypted before their signature can be
+    // verified
+    let id_token = match id_token_decryption {
+        Some(decrypt) => decrypt_id_token(id_token, decrypt)?,
+        None => id_token.into(),
+    };
 
-        let mut claims = id_token.payload().clone();
+    let id_token = verify_id_token(&id_token, verification_data, None, now)?;
 
-        // Access token hash must match.
-        claims::AT_HASH
-            .extract_optional_with_options(
-                &mut claims,
-                TokenHash::new(signing_alg, &token_response.access_token),
-            )
-            .map_err(IdTokenError::from)?;
+    let mut claims = id_token.payload().clone();
 
-        // Code hash must match.
-        claims::C_HASH
-            .extract_optional_with_options(&mut claims, TokenHash::new(signing_alg, &code))
-            .map_err(IdTokenError::from)?;
+    // Access token hash must match.
+    claims::AT_HASH
+        .extract_optional_with_options(
+            &mut claims,
+            TokenHash::new(signing_alg, &token_response.access_token),
+        )
+        .map_err(IdTokenError::from)?;
 
-        // Nonce must match.
-        claims::NONCE
-            .extract_required_with_options(&mut claims, validation_data.nonce.as_str())
-            .map_err(IdTokenError::from)?;
+    // Code hash must match.
+    claims::C_HASH
+        .extract_optional_with_options(&mut claims, TokenHash::new(signing_alg, code))
+        .map_err(IdTokenError::from)?;
 
-        Some(id_token.into_owned())
-    } else {
-        None
-    };
+    // Nonce must match.
+    claims::NONCE
+        .extract_required_with_options(&mut claims, nonce)
+        .map_err(IdTokenError::from)?;
 
-    Ok((token_response, id_token))
+    Ok(id_token.into_owned())
 }
@@ -29,10 +29,10 @@ use crate::{
     types::client_credentials::ClientCredentials,
 };
 
-/// Exchange an authorization code for an access token.
+/// Request an access token for the client itself, with its credentials.
 ///
-/// This should be used as the first step for logging in, and to request a
-/// token with a new scope.
+/// This is meant for confidential clients acting on their own behalf, e.g.
+/// to call an API as a service account.
 ///
 /// # Arguments
 ///
@@ -0,0 +1,246 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Requests for the [Device Authorization flow].
+//!
+//! [Device Authorization flow]: https://www.rfc-editor.org/rfc/rfc8628
+
+use chrono::{DateTime, Duration, Utc};
+use mas_http::{CatchHttpCodesLayer, FormUrlencodedRequestLayer, JsonResponseLayer};
+use oauth2_types::{
+    errors::ClientErrorCode,
+    requests::{
+        AccessTokenRequest, AccessTokenResponse, DeviceAuthorizationRequest,
+        DeviceAuthorizationResponse, DeviceCodeGrant,
+    },
+    scope::Scope,
+};
+use rand::Rng;
+use tower::{Layer, Service, ServiceExt};
+use url::Url;
+
+use crate::{
+    error::{
+        DeviceAuthorizationError, ErrorBody, HttpError, TokenDeviceCodeError, TokenRequestError,
+    },
+    http_service::HttpService,
+    requests::token::request_access_token,
+    types::client_credentials::ClientCredentials,
+    utils::{http_all_error_status_codes, http_error_mapper},
+};
+
+/// How much the polling interval is increased when the server asks to slow
+/// down, as per [RFC 8628 Section 3.5].
+///
+/// [RFC 8628 Section 3.5]: https://www.rfc-editor.org/rfc/rfc8628#section-3.5
+const SLOW_DOWN_INCREMENT_SECONDS: i64 = 5;
+
+/// Request a device authorization.
+///
+/// This should be used as the first step of the flow. The user must then be
+/// shown the `user_code` and the `verification_uri` of the response, while the
+/// `device_code` is exchanged for an access token with
+/// [`poll_access_token_with_device_code()`].
+///
+/// # Arguments
+///
+/// * `http_service` - The service to use for making HTTP requests.
+///
+/// * `client_credentials` - The credentials obtained when registering the
+///   client.
+///
+/// * `device_authorization_endpoint` - The URL of the issuer's Device
+///   Authorization endpoint.
+///
+/// * `scope` - The scope to authorize.
+///
+/// * `now` - The current time.
+///
+/// * `rng` - A random number generator.
+///
+/// # Errors
+///
+/// Returns an error if the request fails or the response is invalid.
+#[tracing::instrument(skip_all, fields(device_authorization_endpoint))]
+pub async fn request_device_authorization(
+    http_service: &HttpService,
+    client_credentials: ClientCredentials,
+    device_authorization_endpoint: &Url,
+    scope: Option<Scope>,
+    now: DateTime<Utc>,
+    rng: &mut impl Rng,
+) -> Result<DeviceAuthorizationResponse, DeviceAuthorizationError> {
+    tracing::debug!("Requesting device authorization...");
+
+    let request = DeviceAuthorizationRequest { scope };
+
+    let device_authorization_request =
+        http::Request::post(device_authorization_endpoint.as_str()).body(request)?;
+
+    let device_authorization_request =
+        client_credentials.apply_to_request(device_authorization_request, now, rng)?;
+
+    let service = (
+        FormUrlencodedRequestLayer::default(),
+        JsonResponseLayer::<DeviceAuthorizationResponse>::default(),
+        CatchHttpCodesLayer::new(http_all_error_status_codes(), http_error_mapper),
+    )
+        .layer(http_service.clone());
+
+    let res = service
+        .ready_oneshot()
+        .await?
+        .call(device_authorization_request)
+        .await?;
+
+    let response = res.into_body();
+
+    Ok(response)
+}
+
+/// Exchange a device code for an access token, once.
+///
+/// While the user hasn't authorized the device yet, this returns an
+/// [`HttpError`] with the [`ClientErrorCode::AuthorizationPending`] or
+/// [`ClientErrorCode::SlowDown`] error code. Use
+/// [`poll_access_token_with_device_code()`] to retry until the user made a
+/// decision.
+///
+/// # Arguments
+///
+/// * `http_service` - The service to use for making HTTP requests.
+///
+/// * `client_credentials` - The credentials obtained when registering the
+///   client.
+///
+/// * `token_endpoint` - The URL of the issuer's Token endpoint.
+///
+/// * `device_code` - The device code obtained from the device authorization
+///   response.
+///
+/// * `now` - The current time.
+///
+/// * `rng` - A random number generator.
+///
+/// # Errors
+///
+/// Returns an error if the request fails or the response is invalid.
+#[tracing::instrument(skip_all, fields(token_endpoint))]
+pub async fn access_token_with_device_code(
+    http_service: &HttpService,
+    client_credentials: ClientCredentials,
+    token_endpoint: &Url,
+    device_code: String,
+    now: DateTime<Utc>,
+    rng: &mut impl Rng,
+) -> Result<AccessTokenResponse, TokenRequestError> {
+    tracing::debug!("Exchanging device code for access token...");
+
+    request_access_token(
+        http_service,
+        client_credentials,
+        token_endpoint,
+        AccessTokenRequest::DeviceCode(DeviceCodeGrant { device_code }),
+        now,
+        rng,
+    )
+    .await
+}
+
+/// Poll the token endpoint until the user authorized or denied the device, or
+/// until the device code expires.
+///
+/// The interval between requests follows the one of the device authorization
+/// response, and is increased every time the server asks to slow down.
+///
+/// # Arguments
+///
+/// * `http_service` - The service to use for making HTTP requests.
+///
+/// * `client_credentials` - The credentials obtained when registering the
+///   client.
+///
+/// * `token_endpoint` - The URL of the issuer's Token endpoint.
+///
+/// * `device_authorization` - The response of the device authorization
+///   request.
+///
+/// * `now` - The current time, when the polling starts.
+///
+/// * `rng` - A random number generator.
+///
+/// # Errors
+///
+/// Returns [`TokenDeviceCodeError::AccessDenied`] if the user denied the
+/// authorization, [`TokenDeviceCodeError::Expired`] if the device code expired
+/// before the user made a decision, or another error if a request fails or
+/// the response is invalid.
+#[tracing::instrument(skip_all, fields(token_endpoint))]
+pub async fn poll_access_token_with_device_code(
+    http_service: &HttpService,
+    client_credentials: ClientCredentials,
+    token_endpoint: &Url,
+    device_authorization: &DeviceAuthorizationResponse,
+    now: DateTime<Utc>,
+    rng: &mut impl Rng,
+) -> Result<AccessTokenResponse, TokenDeviceCodeError> {
+    let start = tokio::time::Instant::now();
+    let expires_in = device_authorization.expires_in();
+    let mut interval = device_authorization.interval();
+
+    loop {
+        tokio::time::sleep(interval.to_std().unwrap_or_default()).await;
+
+        let elapsed = Duration::from_std(start.elapsed()).unwrap_or_else(|_| Duration::zero());
+        if elapsed >= expires_in {
+            return Err(TokenDeviceCodeError::Expired);
+        }
+
+        let res = access_token_with_device_code(
+            http_service,
+            client_credentials.clone(),
+            token_endpoint,
+            device_authorization.device_code().to_owned(),
+            now + elapsed,
+            rng,
+        )
+        .await;
+
+        let err = match res {
+            Ok(response) => return Ok(response),
+            Err(err) => err,
+        };
+
+        let error_code = match &err {
+            TokenRequestError::Http(HttpError {
+                body: Some(ErrorBody { error, .. }),
+                ..
+            }) => Some(error),
+            _ => None,
+        };
+
+        match error_code {
+            Some(ClientErrorCode::AuthorizationPending) => {
+                tracing::debug!("Authorization is still pending");
+            }
+            Some(ClientErrorCode::SlowDown) => {
+                tracing::debug!("Slowing down polling");
+                interval = interval + Duration::seconds(SLOW_DOWN_INCREMENT_SECONDS);
+            }
+            Some(ClientErrorCode::AccessDenied) => return Err(TokenDeviceCodeError::AccessDenied),
+            Some(ClientErrorCode::ExpiredToken) => return Err(TokenDeviceCodeError::Expired),
+            _ => return Err(err.into()),
+        }
+    }
+}
@@ -129,6 +129,7 @@ pub async fn introspect_token(
     let introspection_request = IntrospectionRequest {
         token,
         token_type_hint,
+        user_id: None,
     };
     let introspection_request =
         http::Request::post(introspection_endpoint.as_str()).body(introspection_request)?;
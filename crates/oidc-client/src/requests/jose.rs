@@ -14,7 +14,7 @@
 
 //! Requests and method related to JSON Object Signing and Encryption.
 
-use std::collections::HashMap;
+use std::{borrow::Cow, collections::HashMap};
 
 use bytes::Bytes;
 use chrono::{DateTime, Utc};
@@ -22,6 +22,7 @@ use mas_http::JsonResponseLayer;
 use mas_iana::jose::JsonWebSignatureAlg;
 use mas_jose::{
     claims::{self, TimeOptions},
+    jwe::{Jwe, JweDecryptionError},
     jwk::PublicJsonWebKeySet,
     jwt::Jwt,
 };
@@ -30,11 +31,14 @@ use tower::{Layer, Service, ServiceExt};
 use url::Url;
 
 use crate::{
-    error::{IdTokenError, JwksError, JwtVerificationError},
+    error::{IdTokenError, JwksError, JwtVerificationError, LogoutTokenError},
     http_service::HttpService,
-    types::IdToken,
+    types::{IdToken, LogoutToken},
 };
 
+/// The member of the `events` claim identifying a logout token.
+const BACKCHANNEL_LOGOUT_EVENT: &str = "http://schemas.openid.net/event/backchannel-logout";
+
 /// Fetch a JWKS at the given URL.
 ///
 /// # Arguments
@@ -79,6 +83,41 @@ pub struct JwtVerificationData<'a> {
     pub signing_algorithm: &'a JsonWebSignatureAlg,
 }
 
+/// A function to decrypt a JWE with the client's private keys.
+pub type JweDecryptionFn = dyn Fn(&Jwe) -> Result<Vec<u8>, JweDecryptionError> + Send + Sync;
+
+/// Decrypt an ID Token, if it is encrypted.
+///
+/// Encrypted ID Tokens are nested JWTs: the payload of the JWE is the signed
+/// ID Token, which must then be verified with [`verify_id_token()`].
+///
+/// # Arguments
+///
+/// * `id_token` - The serialized ID Token, either signed or encrypted.
+///
+/// * `decrypt` - The function to use to decrypt the ID Token.
+///
+/// # Errors
+///
+/// Returns an error if the ID Token is encrypted and can't be decrypted.
+pub fn decrypt_id_token<'a>(
+    id_token: &'a str,
+    decrypt: &JweDecryptionFn,
+) -> Result<Cow<'a, str>, IdTokenError> {
+    // A JWE has five parts in its compact serialization, a JWS only three
+    if id_token.split('.').count() != 5 {
+        return Ok(Cow::Borrowed(id_token));
+    }
+
+    tracing::debug!("Decrypting ID Token...");
+
+    let jwe = Jwe::try_from(id_token)?;
+    let payload = decrypt(&jwe)?;
+    let id_token = String::from_utf8(payload).map_err(|_| IdTokenError::InvalidEncryptedPayload)?;
+
+    Ok(Cow::Owned(id_token))
+}
+
 /// Decode and verify a signed JWT.
 ///
 /// The following checks are performed:
@@ -212,3 +251,70 @@ pub fn verify_id_token<'a>(
 
     Ok(id_token)
 }
+
+/// Decode and verify a back-channel logout token.
+///
+/// Besides the checks of [`verify_signed_jwt()`], the following checks are
+/// performed:
+///
+/// * The `iat` claim must be present and must be in the past.
+///
+/// * If the `exp` claim is present, the token must not have expired.
+///
+/// * The `events` claim must contain the back-channel logout event, with a
+///   JSON object as its value.
+///
+/// * At least one of the `sub` and `sid` claims must be present.
+///
+/// * The `nonce` claim must not be present.
+///
+/// # Arguments
+///
+/// * `logout_token` - The serialized logout token to decode and verify.
+///
+/// * `verification_data` - The data necessary to verify the logout token.
+///
+/// * `now` - The current time.
+///
+/// # Errors
+///
+/// Returns an error if the data is invalid or verification fails.
+pub fn verify_logout_token<'a>(
+    logout_token: &'a str,
+    verification_data: JwtVerificationData<'_>,
+    now: DateTime<Utc>,
+) -> Result<LogoutToken<'a>, LogoutTokenError> {
+    let logout_token = verify_signed_jwt(logout_token, verification_data)?;
+
+    let mut claims = logout_token.payload().clone();
+
+    let time_options = TimeOptions::new(now);
+    // `iat` claim must be present.
+    claims::IAT.extract_required_with_options(&mut claims, &time_options)?;
+
+    // Must not have expired, if it has an expiration.
+    claims::EXP.extract_optional_with_options(&mut claims, &time_options)?;
+
+    // Must be a logout token.
+    let events = claims::EVENTS.extract_required(&mut claims)?;
+    if !events
+        .get(BACKCHANNEL_LOGOUT_EVENT)
+        .is_some_and(Value::is_object)
+    {
+        return Err(LogoutTokenError::MissingLogoutEvent);
+    }
+
+    // Must identify the user or the session to log out.
+    let sub = claims::SUB.extract_optional(&mut claims)?;
+    let sid = claims::SID.extract_optional(&mut claims)?;
+    if sub.is_none() && sid.is_none() {
+        return Err(LogoutTokenError::MissingSubjectAndSessionId);
+    }
+
+    // Must not be mistaken for an ID token.
+    if claims.contains_key("nonce") {
+        return Err(LogoutTokenError::UnexpectedNonce);
+    }
+
+    Ok(logout_token)
+}
@@ -16,6 +16,7 @@
 
 pub mod authorization_code;
 pub mod client_credentials;
+pub mod device_authorization;
 pub mod discovery;
 pub mod introspection;
 pub mod jose;
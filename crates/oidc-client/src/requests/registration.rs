@@ -12,10 +12,13 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-//! Requests for [Dynamic Registration].
+//! Requests for [Dynamic Registration] and [Dynamic Client Registration
+//! Management].
 //!
 //! [Dynamic Registration]: https://openid.net/specs/openid-connect-registration-1_0.html
+//! [Dynamic Client Registration Management]: https://www.rfc-editor.org/rfc/rfc7592
 
+use headers::{Authorization, HeaderMapExt};
 use mas_http::{CatchHttpCodesLayer, JsonRequestLayer, JsonResponseLayer};
 use mas_iana::oauth::OAuthClientAuthenticationMethod;
 use oauth2_types::registration::{ClientRegistrationResponse, VerifiedClientMetadata};
@@ -51,6 +54,9 @@ struct RegistrationRequest {
 /// * `software_statement` - A JWT that asserts metadata values about the client
 ///   software that should be signed.
 ///
+/// * `initial_access_token` - The token given by the issuer to allow the
+///   registration, if it doesn't allow open registration.
+///
 /// # Errors
 ///
 /// Returns an error if the request fails or the response is invalid.
@@ -60,22 +66,24 @@ pub async fn register_client(
     registration_endpoint: &Url,
     client_metadata: VerifiedClientMetadata,
     software_statement: Option<String>,
+    initial_access_token: Option<&str>,
 ) -> Result<ClientRegistrationResponse, RegistrationError> {
     tracing::debug!("Registering client...");
 
-    let should_receive_secret = matches!(
-        client_metadata.token_endpoint_auth_method(),
-        OAuthClientAuthenticationMethod::ClientSecretPost
-            | OAuthClientAuthenticationMethod::ClientSecretBasic
-            | OAuthClientAuthenticationMethod::ClientSecretJwt
-    );
+    let should_receive_secret = should_receive_secret(&client_metadata);
 
     let body = RegistrationRequest {
         client_metadata,
         software_statement,
     };
 
-    let registration_req = http::Request::post(registration_endpoint.as_str()).body(body)?;
+    let mut registration_req = http::Request::post(registration_endpoint.as_str());
+    if let (Some(headers), Some(initial_access_token)) =
+        (registration_req.headers_mut(), initial_access_token)
+    {
+        headers.typed_insert(Authorization::bearer(initial_access_token)?);
+    }
+    let registration_req = registration_req.body(body)?;
 
     let service = (
         JsonRequestLayer::default(),
@@ -84,12 +92,13 @@ pub async fn register_client(
     )
         .layer(http_service.clone());
 
-    let response = service
+    let mut response = service
         .ready_oneshot()
         .await?
         .call(registration_req)
         .await?
         .into_body();
+    ignore_zero_expiration(&mut response);
 
     if should_receive_secret && response.client_secret.is_none() {
         return Err(RegistrationError::MissingClientSecret);
@@ -97,3 +106,105 @@ pub async fn register_client(
 
     Ok(response)
 }
+
+#[skip_serializing_none]
+#[derive(Serialize)]
+struct ClientUpdateRequest {
+    client_id: String,
+    client_secret: Option<String>,
+    #[serde(flatten)]
+    client_metadata: VerifiedClientMetadata,
+}
+
+/// Replace the metadata of a registered client.
+///
+/// The issuer may rotate the client secret and the registration access token
+/// in the process, so the ones in the response must replace the previous ones.
+///
+/// # Arguments
+///
+/// * `http_service` - The service to use for making HTTP requests.
+///
+/// * `registration_client_uri` - The URL of the client configuration endpoint,
+///   returned by the registration.
+///
+/// * `registration_access_token` - The token to authenticate with the client
+///   configuration endpoint, returned by the registration.
+///
+/// * `client_id` - The ID of the registered client.
+///
+/// * `client_secret` - The current secret of the registered client, if it has
+///   one.
+///
+/// * `client_metadata` - The new metadata of the client, which replaces all of
+///   the registered one.
+///
+/// # Errors
+///
+/// Returns an error if the request fails or the response is invalid.
+#[tracing::instrument(skip_all, fields(registration_client_uri))]
+pub async fn update_client(
+    http_service: &HttpService,
+    registration_client_uri: &Url,
+    registration_access_token: &str,
+    client_id: String,
+    client_secret: Option<String>,
+    client_metadata: VerifiedClientMetadata,
+) -> Result<ClientRegistrationResponse, RegistrationError> {
+    tracing::debug!("Updating client registration...");
+
+    let should_receive_secret = should_receive_secret(&client_metadata);
+
+    let body = ClientUpdateRequest {
+        client_id,
+        client_secret,
+        client_metadata,
+    };
+
+    let mut update_req = http::Request::put(registration_client_uri.as_str());
+    if let Some(headers) = update_req.headers_mut() {
+        headers.typed_insert(Authorization::bearer(registration_access_token)?);
+    }
+    let update_req = update_req.body(body)?;
+
+    let service = (
+        JsonRequestLayer::default(),
+        JsonResponseLayer::<ClientRegistrationResponse>::default(),
+        CatchHttpCodesLayer::new(http_all_error_status_codes(), http_error_mapper),
+    )
+        .layer(http_service.clone());
+
+    let mut response = service
+        .ready_oneshot()
+        .await?
+        .call(update_req)
+        .await?
+        .into_body();
+    ignore_zero_expiration(&mut response);
+
+    if should_receive_secret && response.client_secret.is_none() {
+        return Err(RegistrationError::MissingClientSecret);
+    }
+
+    Ok(response)
+}
+
+/// Whether the issuer must return a client secret for this metadata
+fn should_receive_secret(client_metadata: &VerifiedClientMetadata) -> bool {
+    matches!(
+        client_metadata.token_endpoint_auth_method(),
+        OAuthClientAuthenticationMethod::ClientSecretPost
+            | OAuthClientAuthenticationMethod::ClientSecretBasic
+            | OAuthClientAuthenticationMethod::ClientSecretJwt
+    )
+}
+
+/// A `client_secret_expires_at` of `0` means that the secret doesn't expire
+fn ignore_zero_expiration(response: &mut ClientRegistrationResponse) {
+    if response
+        .client_secret_expires_at
+        .is_some_and(|expires_at| expires_at.timestamp() == 0)
+    {
+        response.client_secret_expires_at = None;
+    }
+}
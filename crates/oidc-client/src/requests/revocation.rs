@@ -68,6 +68,7 @@ pub async fn revoke_token(
     let request = IntrospectionRequest {
         token,
         token_type_hint,
+        user_id: None,
     };
 
     let revocation_request = http::Request::post(revocation_endpoint.as_str()).body(request)?;
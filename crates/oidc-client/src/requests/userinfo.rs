@@ -36,6 +36,46 @@ use crate::{
     utils::{http_all_error_status_codes, http_error_mapper},
 };
 
+/// Send an authenticated `GET` request to `url` and return the body of the
+/// response, after checking that it has the expected content type.
+async fn fetch_with_access_token(
+    http_service: &HttpService,
+    url: &Url,
+    access_token: &str,
+    expected_content_type: &'static str,
+) -> Result<Bytes, UserInfoError> {
+    let mut request = http::Request::get(url.as_str());
+
+    if let Some(headers) = request.headers_mut() {
+        headers.typed_insert(Authorization::bearer(access_token)?);
+        headers.insert(ACCEPT, HeaderValue::from_static(expected_content_type));
+    }
+
+    let request = request.body(Bytes::new())?;
+
+    let service = CatchHttpCodesLayer::new(http_all_error_status_codes(), http_error_mapper)
+        .layer(http_service.clone());
+
+    let response = service.ready_oneshot().await?.call(request).await?;
+
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .ok_or(UserInfoError::MissingResponseContentType)?
+        .to_str()?;
+
+    // Ignore the parameters of the content-type, like the charset
+    let essence = content_type.split(';').next().unwrap_or_default().trim();
+    if !essence.eq_ignore_ascii_case(expected_content_type) {
+        return Err(UserInfoError::InvalidResponseContentType {
+            expected: expected_content_type.to_owned(),
+            got: content_type.to_owned(),
+        });
+    }
+
+    Ok(response.into_body())
+}
+
 /// Obtain information about an authenticated end-user.
 ///
 /// Returns a map of claims with their value, that should be extracted with
@@ -56,7 +96,9 @@ use crate::{
 ///   field in the client metadata.
 ///
 /// * `auth_id_token` - The ID token that was returned from the latest
-///   authorization request.
+///   authorization request, if any. If it is set, the subject of the response
+///   must match the one of the ID token. Plain OAuth 2.0 providers don't issue
+///   ID tokens, and their responses are returned as is.
 ///
 /// # Errors
 ///
@@ -70,48 +112,24 @@ pub async fn fetch_userinfo(
     userinfo_endpoint: &Url,
     access_token: &str,
     jwt_verification_data: Option<JwtVerificationData<'_>>,
-    auth_id_token: &IdToken<'_>,
+    auth_id_token: Option<&IdToken<'_>>,
 ) -> Result<HashMap<String, Value>, UserInfoError> {
     tracing::debug!("Obtaining user info…");
 
-    let mut userinfo_request = http::Request::get(userinfo_endpoint.as_str());
-
     let expected_content_type = if jwt_verification_data.is_some() {
         "application/jwt"
     } else {
         mime::APPLICATION_JSON.as_ref()
     };
 
-    if let Some(headers) = userinfo_request.headers_mut() {
-        headers.typed_insert(Authorization::bearer(access_token)?);
-        headers.insert(ACCEPT, HeaderValue::from_static(expected_content_type));
-    }
-
-    let userinfo_request = userinfo_request.body(Bytes::new())?;
-
-    let service = CatchHttpCodesLayer::new(http_all_error_status_codes(), http_error_mapper)
-        .layer(http_service.clone());
-
-    let userinfo_response = service
-        .ready_oneshot()
-        .await?
-        .call(userinfo_request)
-        .await?;
-
-    let content_type = userinfo_response
-        .headers()
-        .get(CONTENT_TYPE)
-        .ok_or(UserInfoError::MissingResponseContentType)?
-        .to_str()?;
-
-    if content_type != expected_content_type {
-        return Err(UserInfoError::InvalidResponseContentType {
-            expected: expected_content_type.to_owned(),
-            got: content_type.to_owned(),
-        });
-    }
-
-    let response_body = std::str::from_utf8(userinfo_response.body())?;
+    let response_body = fetch_with_access_token(
+        http_service,
+        userinfo_endpoint,
+        access_token,
+        expected_content_type,
+    )
+    .await?;
+    let response_body = std::str::from_utf8(&response_body)?;
 
     let mut claims = if let Some(verification_data) = jwt_verification_data {
         verify_signed_jwt(response_body, verification_data)
@@ -122,18 +140,51 @@ pub async fn fetch_userinfo(
         serde_json::from_str(response_body)?
     };
 
-    let mut auth_claims = auth_id_token.payload().clone();
-
-    // Subject identifier must always be the same.
-    let sub = claims::SUB
-        .extract_required(&mut claims)
-        .map_err(IdTokenError::from)?;
-    let auth_sub = claims::SUB
-        .extract_required(&mut auth_claims)
-        .map_err(IdTokenError::from)?;
-    if sub != auth_sub {
-        return Err(IdTokenError::WrongSubjectIdentifier.into());
+    if let Some(auth_id_token) = auth_id_token {
+        let mut auth_claims = auth_id_token.payload().clone();
+
+        // Subject identifier must always be the same.
+        let sub = claims::SUB
+            .extract_required(&mut claims)
+            .map_err(IdTokenError::from)?;
+        let auth_sub = claims::SUB
+            .extract_required(&mut auth_claims)
+            .map_err(IdTokenError::from)?;
+        if sub != auth_sub {
+            return Err(IdTokenError::WrongSubjectIdentifier.into());
+        }
     }
 
     Ok(claims)
 }
+
+/// Fetch a JSON resource about an authenticated end-user from a non-standard
+/// endpoint, e.g. the list of email addresses of a GitHub user.
+///
+/// # Arguments
+///
+/// * `http_service` - The service to use for making HTTP requests.
+///
+/// * `url` - The URL of the resource.
+///
+/// * `access_token` - The access token of the end-user.
+///
+/// # Errors
+///
+/// Returns an error if the request fails or if the response is not valid JSON.
+#[tracing::instrument(skip_all, fields(url))]
+pub async fn fetch_user_resource(
+    http_service: &HttpService,
+    url: &Url,
+    access_token: &str,
+) -> Result<Value, UserInfoError> {
+    let response_body = fetch_with_access_token(
+        http_service,
+        url,
+        access_token,
+        mime::APPLICATION_JSON.as_ref(),
+    )
+    .await?;
+
+    Ok(serde_json::from_slice(&response_body)?)
+}
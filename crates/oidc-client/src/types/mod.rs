@@ -29,3 +29,8 @@ use serde_json::Value;
 ///
 /// [ID Token]: https://openid.net/specs/openid-connect-core-1_0.html#IDToken
 pub type IdToken<'a> = Jwt<'a, HashMap<String, Value>>;
+
+/// An OpenID Connect Back-Channel [Logout Token].
+///
+/// [Logout Token]: https://openid.net/specs/openid-connect-backchannel-1_0.html#LogoutToken
+pub type LogoutToken<'a> = Jwt<'a, HashMap<String, Value>>;
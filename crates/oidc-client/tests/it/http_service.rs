@@ -0,0 +1,112 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::{Arc, Mutex};
+
+use assert_matches::assert_matches;
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::{Method, StatusCode};
+use mas_iana::oauth::{OAuthClientAuthenticationMethod, OAuthTokenTypeHint};
+use mas_oidc_client::{
+    error::TokenRevokeError,
+    http_service::{from_http_client, HttpClient},
+    requests::revocation::revoke_token,
+};
+use rand::SeedableRng;
+use tower::BoxError;
+use url::Url;
+
+use crate::{client_credentials, now, ACCESS_TOKEN};
+
+/// A client which records the requests it sends, and answers with a fixed
+/// status code.
+struct RecordingClient {
+    status: StatusCode,
+    requests: Mutex<Vec<(Method, String)>>,
+}
+
+#[async_trait]
+impl HttpClient for RecordingClient {
+    async fn send(&self, request: http::Request<Bytes>) -> Result<http::Response<Bytes>, BoxError> {
+        self.requests
+            .lock()
+            .unwrap()
+            .push((request.method().clone(), request.uri().to_string()));
+
+        Ok(http::Response::builder()
+            .status(self.status)
+            .body(Bytes::new())?)
+    }
+}
+
+#[tokio::test]
+async fn pass_custom_http_client() {
+    let issuer = Url::parse("https://auth.example.com/").unwrap();
+    let client = Arc::new(RecordingClient {
+        status: StatusCode::OK,
+        requests: Mutex::default(),
+    });
+    let http_service = from_http_client(client.clone());
+    let client_credentials =
+        client_credentials(OAuthClientAuthenticationMethod::None, &issuer, None);
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(42);
+
+    revoke_token(
+        &http_service,
+        client_credentials,
+        &issuer.join("revoke").unwrap(),
+        ACCESS_TOKEN.to_owned(),
+        Some(OAuthTokenTypeHint::AccessToken),
+        now(),
+        &mut rng,
+    )
+    .await
+    .unwrap();
+
+    let requests = client.requests.lock().unwrap();
+    assert_eq!(
+        *requests,
+        vec![(Method::POST, "https://auth.example.com/revoke".to_owned())]
+    );
+}
+
+#[tokio::test]
+async fn fail_custom_http_client_error_status() {
+    let issuer = Url::parse("https://auth.example.com/").unwrap();
+    let http_service = from_http_client(RecordingClient {
+        status: StatusCode::SERVICE_UNAVAILABLE,
+        requests: Mutex::default(),
+    });
+    let client_credentials =
+        client_credentials(OAuthClientAuthenticationMethod::None, &issuer, None);
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(42);
+
+    let error = revoke_token(
+        &http_service,
+        client_credentials,
+        &issuer.join("revoke").unwrap(),
+        ACCESS_TOKEN.to_owned(),
+        Some(OAuthTokenTypeHint::AccessToken),
+        now(),
+        &mut rng,
+    )
+    .await
+    .unwrap_err();
+
+    assert_matches!(
+        error,
+        TokenRevokeError::Http(err) if err.status == StatusCode::SERVICE_UNAVAILABLE
+    );
+}
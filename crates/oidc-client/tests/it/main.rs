@@ -37,6 +37,7 @@ use rand::{
 use url::Url;
 use wiremock::MockServer;
 
+mod http_service;
 mod requests;
 mod types;
 
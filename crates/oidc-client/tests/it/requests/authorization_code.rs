@@ -20,10 +20,16 @@ use std::{
 
 use assert_matches::assert_matches;
 use chrono::Duration;
-use mas_iana::oauth::{
-    OAuthAccessTokenType, OAuthClientAuthenticationMethod, PkceCodeChallengeMethod,
+use mas_iana::{
+    jose::{JsonWebEncryptionAlg, JsonWebEncryptionEnc, JsonWebSignatureAlg},
+    oauth::{OAuthAccessTokenType, OAuthClientAuthenticationMethod, PkceCodeChallengeMethod},
 };
-use mas_jose::{claims::ClaimError, jwk::PublicJsonWebKeySet};
+use mas_jose::{
+    claims::ClaimError,
+    jwe::{JsonWebEncryptionHeader, Jwe},
+    jwk::PublicJsonWebKeySet,
+};
+use mas_keystore::PrivateKey;
 use mas_oidc_client::{
     error::{
         AuthorizationError, IdTokenError, PushedAuthorizationError, TokenAuthorizationCodeError,
@@ -33,11 +39,13 @@ use mas_oidc_client::{
             access_token_with_authorization_code, build_authorization_url,
             build_par_authorization_url, AuthorizationRequestData, AuthorizationValidationData,
         },
-        jose::JwtVerificationData,
+        jose::{JweDecryptionFn, JwtVerificationData},
     },
     types::scope::{ScopeExt, ScopeToken},
 };
-use oauth2_types::requests::{AccessTokenResponse, Display, Prompt, PushedAuthorizationResponse};
+use oauth2_types::requests::{
+    AccessTokenResponse, Display, Prompt, PushedAuthorizationResponse, ResponseMode,
+};
 use rand::SeedableRng;
 use tokio::sync::oneshot;
 use url::Url;
@@ -47,8 +55,8 @@ use wiremock::{
 };
 
 use crate::{
-    client_credentials, id_token, init_test, now, ACCESS_TOKEN, AUTHORIZATION_CODE, CLIENT_ID,
-    CODE_VERIFIER, ID_TOKEN_SIGNING_ALG, NONCE, REDIRECT_URI, REQUEST_URI,
+    client_credentials, id_token, init_test, keystore, now, ACCESS_TOKEN, AUTHORIZATION_CODE,
+    CLIENT_ID, CODE_VERIFIER, ID_TOKEN_SIGNING_ALG, NONCE, REDIRECT_URI, REQUEST_URI,
 };
 
 #[test]
@@ -83,6 +91,7 @@ fn pass_authorization_url() {
     assert_eq!(query_pairs.get("response_type").unwrap(), "code");
     assert_eq!(query_pairs.get("client_id").unwrap(), CLIENT_ID);
     assert_eq!(query_pairs.get("redirect_uri").unwrap(), REDIRECT_URI);
+    assert_eq!(query_pairs.get("response_mode"), None);
     assert_eq!(query_pairs.get("display"), None);
     assert_eq!(query_pairs.get("prompt"), None);
     assert_eq!(query_pairs.get("max_age"), None);
@@ -97,6 +106,35 @@ fn pass_authorization_url() {
     assert_eq!(query_pairs.get("code_challenge_method").unwrap(), "S256");
 }
 
+#[test]
+fn pass_oauth2_authorization_url() {
+    let issuer = Url::parse("http://localhost/").unwrap();
+    let authorization_endpoint = issuer.join("authorize").unwrap();
+    let redirect_uri = Url::parse(REDIRECT_URI).unwrap();
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(42);
+
+    let (url, validation_data) = build_authorization_url(
+        authorization_endpoint,
+        AuthorizationRequestData::new(
+            CLIENT_ID.to_owned(),
+            "read:user".parse().unwrap(),
+            redirect_uri,
+        )
+        .with_openid(false),
+        &mut rng,
+    )
+    .unwrap();
+
+    assert_eq!(validation_data.state, "OrJ8xbWovSpJUTKz");
+
+    // Neither the `openid` scope nor a nonce are sent
+    let query_pairs = url.query_pairs().collect::<HashMap<_, _>>();
+    assert_eq!(query_pairs.get("scope").unwrap(), "read:user");
+    assert_eq!(query_pairs.get("response_type").unwrap(), "code");
+    assert_eq!(*query_pairs.get("state").unwrap(), validation_data.state);
+    assert_eq!(query_pairs.get("nonce"), None);
+}
+
 #[test]
 fn pass_full_authorization_url() {
     let issuer = Url::parse("http://localhost/").unwrap();
@@ -108,6 +146,7 @@ fn pass_full_authorization_url() {
         [ScopeToken::Openid].into_iter().collect(),
         Url::parse(REDIRECT_URI).unwrap(),
     )
+    .with_response_mode(ResponseMode::FormPost)
     .with_display(Display::Touch)
     .with_prompt(vec![Prompt::Create])
     .with_max_age(NonZeroU32::new(86400).unwrap())
@@ -129,6 +168,7 @@ fn pass_full_authorization_url() {
     assert_eq!(query_pairs.get("response_type").unwrap(), "code");
     assert_eq!(query_pairs.get("client_id").unwrap(), CLIENT_ID);
     assert_eq!(query_pairs.get("redirect_uri").unwrap(), REDIRECT_URI);
+    assert_eq!(query_pairs.get("response_mode").unwrap(), "form_post");
     assert_eq!(query_pairs.get("display").unwrap(), "touch");
     assert_eq!(query_pairs.get("prompt").unwrap(), "create");
     assert_eq!(query_pairs.get("max_age").unwrap(), "86400");
@@ -346,6 +386,7 @@ async fn pass_access_token_with_authorization_code() {
         AUTHORIZATION_CODE.to_owned(),
         validation_data,
         Some(id_token_verification_data),
+        None,
         now(),
         &mut rng,
     )
@@ -358,6 +399,81 @@ async fn pass_access_token_with_authorization_code() {
     assert_eq!(response_id_token.unwrap().as_str(), id_token.as_str());
 }
 
+#[tokio::test]
+async fn pass_access_token_with_authorization_code_encrypted_id_token() {
+    let (http_service, mock_server, issuer) = init_test().await;
+    let client_credentials =
+        client_credentials(OAuthClientAuthenticationMethod::None, &issuer, None);
+    let token_endpoint = issuer.join("token").unwrap();
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(42);
+
+    let redirect_uri = Url::parse(REDIRECT_URI).unwrap();
+    let validation_data = AuthorizationValidationData {
+        state: "some_state".to_owned(),
+        nonce: NONCE.to_owned(),
+        redirect_uri,
+        code_challenge_verifier: Some(CODE_VERIFIER.to_owned()),
+    };
+
+    let (id_token, jwks) = id_token(issuer.as_str());
+    let id_token_verification_data = JwtVerificationData {
+        issuer: issuer.as_str(),
+        jwks: &jwks,
+        client_id: &CLIENT_ID.to_owned(),
+        signing_algorithm: &ID_TOKEN_SIGNING_ALG,
+    };
+
+    // Encrypt the ID token with the key of the client
+    let client_keystore = keystore(&JsonWebSignatureAlg::Rs256);
+    let PrivateKey::Rsa(client_key) = client_keystore[0].params() else {
+        panic!("wrong key type");
+    };
+    let header =
+        JsonWebEncryptionHeader::new(JsonWebEncryptionAlg::RsaOaep, JsonWebEncryptionEnc::A128Gcm)
+            .with_cty("JWT".to_owned());
+    let encrypted_id_token = Jwe::encrypt(
+        &mut rng,
+        header,
+        id_token.as_str().as_bytes(),
+        &client_key.to_public_key(),
+    )
+    .unwrap();
+
+    Mock::given(method("POST"))
+        .and(path("/token"))
+        .and(is_valid_token_endpoint_request)
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(AccessTokenResponse {
+                access_token: ACCESS_TOKEN.to_owned(),
+                refresh_token: None,
+                id_token: Some(encrypted_id_token.to_string()),
+                token_type: OAuthAccessTokenType::Bearer,
+                expires_in: None,
+                scope: Some([ScopeToken::Openid].into_iter().collect()),
+            }),
+        )
+        .mount(&mock_server)
+        .await;
+
+    let decrypt: &JweDecryptionFn = &|jwe| client_keystore.decrypt(jwe);
+    let (response, response_id_token) = access_token_with_authorization_code(
+        &http_service,
+        client_credentials,
+        &token_endpoint,
+        AUTHORIZATION_CODE.to_owned(),
+        validation_data,
+        Some(id_token_verification_data),
+        Some(decrypt),
+        now(),
+        &mut rng,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(response.access_token, ACCESS_TOKEN);
+    assert_eq!(response_id_token.unwrap().as_str(), id_token.as_str());
+}
+
 #[tokio::test]
 async fn fail_access_token_with_authorization_code_wrong_nonce() {
     let (http_service, mock_server, issuer) = init_test().await;
@@ -405,6 +521,7 @@ async fn fail_access_token_with_authorization_code_wrong_nonce() {
         AUTHORIZATION_CODE.to_owned(),
         validation_data,
         Some(id_token_verification_data),
+        None,
         now(),
         &mut rng,
     )
@@ -467,6 +584,7 @@ async fn fail_access_token_with_authorization_code_no_id_token() {
         AUTHORIZATION_CODE.to_owned(),
         validation_data,
         Some(id_token_verification_data),
+        None,
         now(),
         &mut rng,
     )
@@ -0,0 +1,227 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+
+use assert_matches::assert_matches;
+use mas_iana::oauth::{OAuthAccessTokenType, OAuthClientAuthenticationMethod};
+use mas_oidc_client::{
+    error::TokenDeviceCodeError,
+    requests::device_authorization::{
+        poll_access_token_with_device_code, request_device_authorization,
+    },
+    types::scope::{ScopeExt, ScopeToken},
+};
+use oauth2_types::{
+    requests::{AccessTokenResponse, DeviceAuthorizationResponse},
+    scope::Scope,
+};
+use rand::SeedableRng;
+use serde_json::json;
+use wiremock::{
+    matchers::{method, path},
+    Mock, Request, ResponseTemplate,
+};
+
+use crate::{client_credentials, init_test, now, ACCESS_TOKEN, CLIENT_ID};
+
+const DEVICE_CODE: &str = "DeviceC0D3";
+const USER_CODE: &str = "ABCD-EFGH";
+
+/// A device authorization response which can be polled right away.
+fn device_authorization_response(issuer: &url::Url) -> DeviceAuthorizationResponse {
+    serde_json::from_value(json!({
+        "device_code": DEVICE_CODE,
+        "user_code": USER_CODE,
+        "verification_uri": issuer.join("device").unwrap(),
+        "expires_in": 300,
+        "interval": 0,
+    }))
+    .unwrap()
+}
+
+/// Matches token requests with the device code grant.
+fn is_device_code_grant(req: &Request) -> bool {
+    let query_pairs = form_urlencoded::parse(&req.body).collect::<HashMap<_, _>>();
+
+    if query_pairs
+        .get("grant_type")
+        .filter(|s| *s == "urn:ietf:params:oauth:grant-type:device_code")
+        .is_none()
+    {
+        println!("Wrong or missing grant type");
+        return false;
+    }
+    if query_pairs
+        .get("device_code")
+        .filter(|s| *s == DEVICE_CODE)
+        .is_none()
+    {
+        println!("Wrong or missing device code");
+        return false;
+    }
+    if query_pairs
+        .get("client_id")
+        .filter(|s| *s == CLIENT_ID)
+        .is_none()
+    {
+        println!("Wrong or missing client ID");
+        return false;
+    }
+
+    true
+}
+
+#[tokio::test]
+async fn pass_request_device_authorization() {
+    let (http_service, mock_server, issuer) = init_test().await;
+    let client_credentials =
+        client_credentials(OAuthClientAuthenticationMethod::None, &issuer, None);
+    let device_authorization_endpoint = issuer.join("device_authorization").unwrap();
+    let scope = [ScopeToken::Openid].into_iter().collect::<Scope>();
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(42);
+
+    Mock::given(method("POST"))
+        .and(path("/device_authorization"))
+        .and(|req: &Request| {
+            let query_pairs = form_urlencoded::parse(&req.body).collect::<HashMap<_, _>>();
+
+            if query_pairs
+                .get("scope")
+                .filter(|s| *s == "openid")
+                .is_none()
+            {
+                println!("Wrong or missing scope");
+                return false;
+            }
+            if query_pairs
+                .get("client_id")
+                .filter(|s| *s == CLIENT_ID)
+                .is_none()
+            {
+                println!("Wrong or missing client ID");
+                return false;
+            }
+
+            true
+        })
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "device_code": DEVICE_CODE,
+            "user_code": USER_CODE,
+            "verification_uri": issuer.join("device").unwrap(),
+            "expires_in": 300,
+        })))
+        .mount(&mock_server)
+        .await;
+
+    let response = request_device_authorization(
+        &http_service,
+        client_credentials,
+        &device_authorization_endpoint,
+        Some(scope),
+        now(),
+        &mut rng,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(response.device_code(), DEVICE_CODE);
+    assert_eq!(response.user_code(), USER_CODE);
+    assert_eq!(response.verification_uri_complete(), None);
+    assert_eq!(response.interval().num_seconds(), 5);
+}
+
+#[tokio::test]
+async fn pass_poll_access_token_with_device_code() {
+    let (http_service, mock_server, issuer) = init_test().await;
+    let client_credentials =
+        client_credentials(OAuthClientAuthenticationMethod::None, &issuer, None);
+    let token_endpoint = issuer.join("token").unwrap();
+    let scope = [ScopeToken::Openid].into_iter().collect::<Scope>();
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(42);
+
+    // The first attempt is still pending
+    Mock::given(method("POST"))
+        .and(path("/token"))
+        .and(is_device_code_grant)
+        .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+            "error": "authorization_pending",
+        })))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&mock_server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/token"))
+        .and(is_device_code_grant)
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(AccessTokenResponse {
+                access_token: ACCESS_TOKEN.to_owned(),
+                refresh_token: None,
+                id_token: None,
+                token_type: OAuthAccessTokenType::Bearer,
+                expires_in: None,
+                scope: Some(scope),
+            }),
+        )
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let response = poll_access_token_with_device_code(
+        &http_service,
+        client_credentials,
+        &token_endpoint,
+        &device_authorization_response(&issuer),
+        now(),
+        &mut rng,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(response.access_token, ACCESS_TOKEN);
+    assert!(response.scope.unwrap().contains_token(&ScopeToken::Openid));
+}
+
+#[tokio::test]
+async fn fail_poll_access_token_with_device_code_denied() {
+    let (http_service, mock_server, issuer) = init_test().await;
+    let client_credentials =
+        client_credentials(OAuthClientAuthenticationMethod::None, &issuer, None);
+    let token_endpoint = issuer.join("token").unwrap();
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(42);
+
+    Mock::given(method("POST"))
+        .and(path("/token"))
+        .and(is_device_code_grant)
+        .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+            "error": "access_denied",
+        })))
+        .mount(&mock_server)
+        .await;
+
+    let error = poll_access_token_with_device_code(
+        &http_service,
+        client_credentials,
+        &token_endpoint,
+        &device_authorization_response(&issuer),
+        now(),
+        &mut rng,
+    )
+    .await
+    .unwrap_err();
+
+    assert_matches!(error, TokenDeviceCodeError::AccessDenied);
+}
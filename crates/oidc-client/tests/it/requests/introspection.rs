@@ -79,9 +79,13 @@ async fn pass_introspect_token() {
                 iat: None,
                 nbf: None,
                 sub: Some(SUBJECT_IDENTIFIER.to_owned()),
-                aud: Some(CLIENT_ID.to_owned()),
+                aud: Some(vec![CLIENT_ID.to_owned()]),
                 iss: Some(issuer.to_string()),
                 jti: None,
+                quarantined: None,
+                device_id: None,
+                session_id: None,
+                client_name: None,
             }),
         )
         .mount(&mock_server)
@@ -100,7 +104,7 @@ async fn pass_introspect_token() {
     .unwrap();
 
     assert!(response.active);
-    assert_eq!(response.aud.unwrap(), CLIENT_ID);
+    assert_eq!(response.aud.unwrap(), [CLIENT_ID]);
     assert!(response.scope.unwrap().contains_token(&ScopeToken::Profile));
     assert_eq!(response.client_id.unwrap(), CLIENT_ID);
     assert_eq!(response.iss.unwrap(), issuer.as_str());
@@ -14,6 +14,7 @@
 
 mod authorization_code;
 mod client_credentials;
+mod device_authorization;
 mod discovery;
 mod introspection;
 mod jose;
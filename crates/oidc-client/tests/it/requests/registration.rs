@@ -15,7 +15,10 @@
 use assert_matches::assert_matches;
 use mas_iana::{jose::JsonWebSignatureAlg, oauth::OAuthClientAuthenticationMethod};
 use mas_jose::jwk::PublicJsonWebKeySet;
-use mas_oidc_client::{error::RegistrationError, requests::registration::register_client};
+use mas_oidc_client::{
+    error::RegistrationError,
+    requests::registration::{register_client, update_client},
+};
 use oauth2_types::{
     oidc::ApplicationType,
     registration::{ClientMetadata, ClientRegistrationResponse, VerifiedClientMetadata},
@@ -23,7 +26,7 @@ use oauth2_types::{
 use serde_json::json;
 use url::Url;
 use wiremock::{
-    matchers::{body_partial_json, method, path},
+    matchers::{body_partial_json, header, method, path},
     Mock, Request, ResponseTemplate,
 };
 
@@ -72,14 +75,22 @@ async fn pass_register_client_none() {
                 client_secret: None,
                 client_id_issued_at: None,
                 client_secret_expires_at: None,
+                registration_access_token: None,
+                registration_client_uri: None,
             }),
         )
         .mount(&mock_server)
         .await;
 
-    let response = register_client(&http_service, &registration_endpoint, client_metadata, None)
-        .await
-        .unwrap();
+    let response = register_client(
+        &http_service,
+        &registration_endpoint,
+        client_metadata,
+        None,
+        None,
+    )
+    .await
+    .unwrap();
 
     assert_eq!(response.client_id, CLIENT_ID);
     assert_eq!(response.client_secret, None);
@@ -103,14 +114,22 @@ async fn pass_register_client_client_secret_basic() {
                 client_secret: Some(CLIENT_SECRET.to_owned()),
                 client_id_issued_at: None,
                 client_secret_expires_at: None,
+                registration_access_token: None,
+                registration_client_uri: None,
             }),
         )
         .mount(&mock_server)
         .await;
 
-    let response = register_client(&http_service, &registration_endpoint, client_metadata, None)
-        .await
-        .unwrap();
+    let response = register_client(
+        &http_service,
+        &registration_endpoint,
+        client_metadata,
+        None,
+        None,
+    )
+    .await
+    .unwrap();
 
     assert_eq!(response.client_id, CLIENT_ID);
     assert_eq!(response.client_secret.unwrap(), CLIENT_SECRET);
@@ -134,14 +153,22 @@ async fn pass_register_client_client_secret_post() {
                 client_secret: Some(CLIENT_SECRET.to_owned()),
                 client_id_issued_at: None,
                 client_secret_expires_at: None,
+                registration_access_token: None,
+                registration_client_uri: None,
             }),
         )
         .mount(&mock_server)
         .await;
 
-    let response = register_client(&http_service, &registration_endpoint, client_metadata, None)
-        .await
-        .unwrap();
+    let response = register_client(
+        &http_service,
+        &registration_endpoint,
+        client_metadata,
+        None,
+        None,
+    )
+    .await
+    .unwrap();
 
     assert_eq!(response.client_id, CLIENT_ID);
     assert_eq!(response.client_secret.unwrap(), CLIENT_SECRET);
@@ -166,14 +193,22 @@ async fn pass_register_client_client_secret_jwt() {
                 client_secret: Some(CLIENT_SECRET.to_owned()),
                 client_id_issued_at: None,
                 client_secret_expires_at: None,
+                registration_access_token: None,
+                registration_client_uri: None,
             }),
         )
         .mount(&mock_server)
         .await;
 
-    let response = register_client(&http_service, &registration_endpoint, client_metadata, None)
-        .await
-        .unwrap();
+    let response = register_client(
+        &http_service,
+        &registration_endpoint,
+        client_metadata,
+        None,
+        None,
+    )
+    .await
+    .unwrap();
 
     assert_eq!(response.client_id, CLIENT_ID);
     assert_eq!(response.client_secret.unwrap(), CLIENT_SECRET);
@@ -203,14 +238,22 @@ async fn pass_register_client_private_key_jwt() {
                 client_secret: None,
                 client_id_issued_at: None,
                 client_secret_expires_at: None,
+                registration_access_token: None,
+                registration_client_uri: None,
             }),
         )
         .mount(&mock_server)
         .await;
 
-    let response = register_client(&http_service, &registration_endpoint, client_metadata, None)
-        .await
-        .unwrap();
+    let response = register_client(
+        &http_service,
+        &registration_endpoint,
+        client_metadata,
+        None,
+        None,
+    )
+    .await
+    .unwrap();
 
     assert_eq!(response.client_id, CLIENT_ID);
     assert_eq!(response.client_secret, None);
@@ -222,9 +265,15 @@ async fn fail_register_client_404() {
     let client_metadata = client_metadata(OAuthClientAuthenticationMethod::None);
     let registration_endpoint = issuer.join("register").unwrap();
 
-    let error = register_client(&http_service, &registration_endpoint, client_metadata, None)
-        .await
-        .unwrap_err();
+    let error = register_client(
+        &http_service,
+        &registration_endpoint,
+        client_metadata,
+        None,
+        None,
+    )
+    .await
+    .unwrap_err();
 
     assert_matches!(error, RegistrationError::Http(_));
 }
@@ -246,14 +295,144 @@ async fn fail_register_client_missing_secret() {
                 client_secret: None,
                 client_id_issued_at: None,
                 client_secret_expires_at: None,
+                registration_access_token: None,
+                registration_client_uri: None,
+            }),
+        )
+        .mount(&mock_server)
+        .await;
+
+    let error = register_client(
+        &http_service,
+        &registration_endpoint,
+        client_metadata,
+        None,
+        None,
+    )
+    .await
+    .unwrap_err();
+
+    assert_matches!(error, RegistrationError::MissingClientSecret);
+}
+
+#[tokio::test]
+async fn pass_register_client_initial_access_token() {
+    let (http_service, mock_server, issuer) = init_test().await;
+    let client_metadata = client_metadata(OAuthClientAuthenticationMethod::None);
+    let registration_endpoint = issuer.join("register").unwrap();
+
+    Mock::given(method("POST"))
+        .and(path("/register"))
+        .and(header("authorization", "Bearer initial"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(ClientRegistrationResponse {
+                client_id: CLIENT_ID.to_owned(),
+                client_secret: None,
+                client_id_issued_at: None,
+                client_secret_expires_at: None,
+                registration_access_token: Some("registration".to_owned()),
+                registration_client_uri: Some(issuer.join("register/client").unwrap()),
+            }),
+        )
+        .mount(&mock_server)
+        .await;
+
+    let response = register_client(
+        &http_service,
+        &registration_endpoint,
+        client_metadata,
+        None,
+        Some("initial"),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(response.client_id, CLIENT_ID);
+    assert_eq!(response.registration_access_token.unwrap(), "registration");
+    assert_eq!(
+        response.registration_client_uri.unwrap(),
+        issuer.join("register/client").unwrap()
+    );
+}
+
+#[tokio::test]
+async fn pass_update_client() {
+    let (http_service, mock_server, issuer) = init_test().await;
+    let client_metadata = client_metadata(OAuthClientAuthenticationMethod::ClientSecretBasic);
+    let registration_client_uri = issuer.join("register/client").unwrap();
+
+    Mock::given(method("PUT"))
+        .and(path("/register/client"))
+        .and(header("authorization", "Bearer registration"))
+        .and(body_partial_json(json!({
+            "client_id": CLIENT_ID,
+            "client_secret": "old_secret",
+            "redirect_uris": [REDIRECT_URI],
+            "token_endpoint_auth_method": "client_secret_basic",
+        })))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(ClientRegistrationResponse {
+                client_id: CLIENT_ID.to_owned(),
+                client_secret: Some(CLIENT_SECRET.to_owned()),
+                client_id_issued_at: None,
+                client_secret_expires_at: None,
+                registration_access_token: Some("new_registration".to_owned()),
+                registration_client_uri: None,
+            }),
+        )
+        .mount(&mock_server)
+        .await;
+
+    let response = update_client(
+        &http_service,
+        &registration_client_uri,
+        "registration",
+        CLIENT_ID.to_owned(),
+        Some("old_secret".to_owned()),
+        client_metadata,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(response.client_id, CLIENT_ID);
+    assert_eq!(response.client_secret.unwrap(), CLIENT_SECRET);
+    assert_eq!(
+        response.registration_access_token.unwrap(),
+        "new_registration"
+    );
+}
+
+#[tokio::test]
+async fn fail_update_client_missing_secret() {
+    let (http_service, mock_server, issuer) = init_test().await;
+    let client_metadata = client_metadata(OAuthClientAuthenticationMethod::ClientSecretPost);
+    let registration_client_uri = issuer.join("register/client").unwrap();
+
+    Mock::given(method("PUT"))
+        .and(path("/register/client"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(ClientRegistrationResponse {
+                client_id: CLIENT_ID.to_owned(),
+                client_secret: None,
+                client_id_issued_at: None,
+                client_secret_expires_at: None,
+                registration_access_token: None,
+                registration_client_uri: None,
             }),
         )
         .mount(&mock_server)
         .await;
 
-    let error = register_client(&http_service, &registration_endpoint, client_metadata, None)
-        .await
-        .unwrap_err();
+    let error = update_client(
+        &http_service,
+        &registration_client_uri,
+        "registration",
+        CLIENT_ID.to_owned(),
+        Some("old_secret".to_owned()),
+        client_metadata,
+    )
+    .await
+    .unwrap_err();
 
     assert_matches!(error, RegistrationError::MissingClientSecret);
 }
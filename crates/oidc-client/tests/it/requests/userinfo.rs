@@ -15,7 +15,7 @@
 use assert_matches::assert_matches;
 use mas_oidc_client::{
     error::{IdTokenError, UserInfoError},
-    requests::userinfo::fetch_userinfo,
+    requests::userinfo::{fetch_user_resource, fetch_userinfo},
 };
 use serde_json::json;
 use wiremock::{
@@ -49,7 +49,39 @@ async fn pass_fetch_userinfo() {
         &userinfo_endpoint,
         ACCESS_TOKEN,
         None,
-        &auth_id_token,
+        Some(&auth_id_token),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(claims.get("email").unwrap(), "janedoe@example.com");
+}
+
+#[tokio::test]
+async fn pass_fetch_userinfo_with_charset() {
+    let (http_service, mock_server, issuer) = init_test().await;
+    let userinfo_endpoint = issuer.join("userinfo").unwrap();
+    let (auth_id_token, _) = id_token(issuer.as_str());
+
+    Mock::given(method("GET"))
+        .and(path("/userinfo"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({
+                    "sub": SUBJECT_IDENTIFIER,
+                    "email": "janedoe@example.com",
+                }))
+                .insert_header("content-type", "application/json; charset=utf-8"),
+        )
+        .mount(&mock_server)
+        .await;
+
+    let claims = fetch_userinfo(
+        &http_service,
+        &userinfo_endpoint,
+        ACCESS_TOKEN,
+        None,
+        Some(&auth_id_token),
     )
     .await
     .unwrap();
@@ -81,7 +113,7 @@ async fn fail_wrong_subject_identifier() {
         &userinfo_endpoint,
         ACCESS_TOKEN,
         None,
-        &auth_id_token,
+        Some(&auth_id_token),
     )
     .await
     .unwrap_err();
@@ -91,3 +123,57 @@ async fn fail_wrong_subject_identifier() {
         UserInfoError::IdToken(IdTokenError::WrongSubjectIdentifier)
     );
 }
+
+#[tokio::test]
+async fn pass_fetch_userinfo_without_id_token() {
+    let (http_service, mock_server, issuer) = init_test().await;
+    let userinfo_endpoint = issuer.join("user").unwrap();
+
+    Mock::given(method("GET"))
+        .and(path("/user"))
+        .and(header(
+            "authorization",
+            format!("Bearer {ACCESS_TOKEN}").as_str(),
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "id": 42,
+            "login": "janedoe",
+        })))
+        .mount(&mock_server)
+        .await;
+
+    let claims = fetch_userinfo(&http_service, &userinfo_endpoint, ACCESS_TOKEN, None, None)
+        .await
+        .unwrap();
+
+    assert_eq!(claims.get("id").unwrap(), 42);
+    assert_eq!(claims.get("login").unwrap(), "janedoe");
+}
+
+#[tokio::test]
+async fn pass_fetch_user_resource() {
+    let (http_service, mock_server, issuer) = init_test().await;
+    let url = issuer.join("user/emails").unwrap();
+
+    Mock::given(method("GET"))
+        .and(path("/user/emails"))
+        .and(header(
+            "authorization",
+            format!("Bearer {ACCESS_TOKEN}").as_str(),
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+            {
+                "email": "janedoe@example.com",
+                "primary": true,
+                "verified": true,
+            },
+        ])))
+        .mount(&mock_server)
+        .await;
+
+    let resource = fetch_user_resource(&http_service, &url, ACCESS_TOKEN)
+        .await
+        .unwrap();
+
+    assert_eq!(resource[0]["email"], "janedoe@example.com");
+}
@@ -15,7 +15,8 @@
 use std::path::{Path, PathBuf};
 
 use mas_policy::model::{
-    AuthorizationGrantInput, ClientRegistrationInput, EmailInput, PasswordInput, RegisterInput,
+    AuthorizationGrantInput, ClaimsInput, ClientRegistrationInput, EmailInput, PasswordInput,
+    RegisterInput, TokenInput, UpstreamLoginInput,
 };
 use schemars::{gen::SchemaSettings, JsonSchema};
 
@@ -54,4 +55,7 @@ fn main() {
     write_schema::<AuthorizationGrantInput>(output_root, "authorization_grant_input.json");
     write_schema::<EmailInput>(output_root, "email_input.json");
     write_schema::<PasswordInput>(output_root, "password_input.json");
+    write_schema::<TokenInput>(output_root, "token_input.json");
+    write_schema::<UpstreamLoginInput>(output_root, "upstream_login_input.json");
+    write_schema::<ClaimsInput>(output_root, "claims_input.json");
 }
@@ -0,0 +1,1048 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A built-in policy evaluator, for deployments which don't want to write
+//! their own policies with Open Policy Agent.
+//!
+//! It enforces a fixed set of rules, configured declaratively, which mirror
+//! the default policies shipped with the service.
+
+use std::collections::HashMap;
+
+use mas_data_model::{Client, User};
+use mas_iana::oauth::OAuthClientAuthenticationMethod;
+use oauth2_types::{
+    oidc::ApplicationType, registration::VerifiedClientMetadata,
+    requests::GrantType as OAuthGrantType, scope::Scope,
+};
+use regex::Regex;
+use url::{Host, Url};
+
+use crate::model::{
+    AuthorizationGrantInput, ClaimsInput, ClientRegistrationInput, EmailInput, EvaluationResult,
+    GrantType, PasswordInput, RegisterInput, TokenDecision, TokenEvaluationResult, TokenInput,
+    UpstreamLoginInput, Violation,
+};
+
+/// Rules on the email addresses users can add
+#[derive(Debug, Clone, Default)]
+pub struct EmailRules {
+    /// If not empty, only emails on those domains are allowed. `*` matches a
+    /// single label, like in `*.example.com`
+    pub allowed_domains: Vec<String>,
+
+    /// Emails on those domains are not allowed
+    pub banned_domains: Vec<String>,
+}
+
+/// Rules on the usernames of new users
+#[derive(Debug, Clone)]
+pub struct UsernameRules {
+    /// Regular expression the username must match
+    pub pattern: Regex,
+
+    /// Minimum length of the username
+    pub min_length: usize,
+
+    /// Maximum length of the username
+    pub max_length: usize,
+
+    /// Usernames which can't be registered, compared case-insensitively
+    pub reserved: Vec<String>,
+}
+
+impl UsernameRules {
+    /// Create username rules, compiling the pattern
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the pattern is not a valid regular expression
+    pub fn new(
+        pattern: &str,
+        min_length: usize,
+        max_length: usize,
+        reserved: Vec<String>,
+    ) -> Result<Self, regex::Error> {
+        Ok(Self {
+            pattern: Regex::new(pattern)?,
+            min_length,
+            max_length,
+            reserved,
+        })
+    }
+}
+
+impl Default for UsernameRules {
+    fn default() -> Self {
+        Self {
+            pattern: Regex::new("^[a-z0-9.=_/-]+$").unwrap(),
+            min_length: 3,
+            max_length: 14,
+            reserved: vec!["admin".to_owned(), "abuse".to_owned(), "matrix".to_owned()],
+        }
+    }
+}
+
+/// Rules on the passwords of users
+#[derive(Debug, Clone, Default)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct PasswordRules {
+    /// Minimum length of passwords
+    pub min_length: usize,
+
+    /// Require at least one lowercase letter
+    pub require_lowercase: bool,
+
+    /// Require at least one uppercase letter
+    pub require_uppercase: bool,
+
+    /// Require at least one number
+    pub require_number: bool,
+}
+
+/// Rules on the clients registered through dynamic client registration
+#[derive(Debug, Clone, Default)]
+pub struct ClientRules {
+    /// If not empty, the URIs of the clients must be on those hosts. `*`
+    /// matches a single label, like in `*.example.com`
+    pub allowed_hosts: Vec<String>,
+
+    /// Allow non-HTTPS and localhost URIs
+    pub allow_insecure_uris: bool,
+
+    /// Grant types disabled on the server, which clients can't register with
+    pub disabled_grant_types: Vec<OAuthGrantType>,
+}
+
+/// The users who can use a restricted client
+#[derive(Debug, Clone, Default)]
+pub struct RestrictedClient {
+    /// Usernames of the users who can use the client
+    pub allowed_users: Vec<String>,
+
+    /// Let the users who can request admin use the client
+    pub allow_admins: bool,
+}
+
+/// Rules on the scopes of the tokens issued to clients
+#[derive(Debug, Clone, Default)]
+pub struct TokenRules {
+    /// Clients limited to a set of scopes, by client ID. Other scopes are
+    /// stripped from their tokens
+    pub client_scopes: HashMap<String, Vec<String>>,
+
+    /// Scopes stripped from the tokens issued with a grant type
+    pub stripped_scopes: HashMap<GrantType, Vec<String>>,
+}
+
+/// The rules enforced by the built-in policy evaluator
+#[derive(Debug, Clone, Default)]
+pub struct BuiltinRules {
+    pub emails: EmailRules,
+    pub usernames: UsernameRules,
+    pub passwords: PasswordRules,
+    pub clients: ClientRules,
+
+    /// Usernames of the users who can request admin scopes, on top of the
+    /// users with the `can_request_admin` flag
+    pub admin_users: Vec<String>,
+
+    /// IDs of the clients which can get the `urn:mas:admin` scope with the
+    /// client credentials grant
+    pub admin_clients: Vec<String>,
+
+    /// IDs of the clients which can get the `urn:mas:upstream_tokens` scope
+    /// with the client credentials grant
+    pub upstream_token_clients: Vec<String>,
+
+    /// Custom scopes defined by the operator, which any client can request
+    pub custom_scopes: Vec<String>,
+
+    /// Clients restricted to a subset of users, by client ID
+    pub restricted_clients: HashMap<String, RestrictedClient>,
+
+    /// Rules on the scopes of the tokens issued to clients
+    pub tokens: TokenRules,
+}
+
+/// An input which can be evaluated by the built-in evaluator
+pub(crate) trait BuiltinEvaluate {
+    type Output;
+
+    fn evaluate_builtin(&self, rules: &BuiltinRules) -> Self::Output;
+}
+
+fn violation(msg: impl Into<String>) -> Violation {
+    Violation {
+        msg: msg.into(),
+        field: None,
+        code: None,
+    }
+}
+
+fn with_field(mut violation: Violation, field: &str) -> Violation {
+    violation.field = Some(field.to_owned());
+    violation
+}
+
+/// Match a domain against a pattern, where `*` matches a single label
+fn domain_matches(pattern: &str, domain: &str) -> bool {
+    let pattern: Vec<&str> = pattern.split('.').collect();
+    let domain: Vec<&str> = domain.split('.').collect();
+    pattern.len() == domain.len()
+        && pattern
+            .iter()
+            .zip(domain)
+            .all(|(pattern, label)| *pattern == "*" || pattern.eq_ignore_ascii_case(label))
+}
+
+impl EmailRules {
+    fn violations(&self, email: &str) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        let domain = email.rsplit_once('@').map(|(_, domain)| domain);
+
+        let allowed = self.allowed_domains.is_empty()
+            || domain.is_some_and(|domain| {
+                self.allowed_domains
+                    .iter()
+                    .any(|pattern| domain_matches(pattern, domain))
+            });
+        if !allowed {
+            violations.push(violation("email domain is not allowed"));
+        }
+
+        let banned = domain.is_some_and(|domain| {
+            self.banned_domains
+                .iter()
+                .any(|pattern| domain_matches(pattern, domain))
+        });
+        if banned {
+            violations.push(violation("email domain is banned"));
+        }
+
+        violations
+    }
+}
+
+impl PasswordRules {
+    fn violations(&self, password: &str) -> Vec<Violation> {
+        let mut violations = Vec::new();
+
+        if password.chars().count() < self.min_length {
+            violations.push(violation(format!(
+                "needs to be at least {} characters",
+                self.min_length
+            )));
+        }
+
+        if self.require_number && !password.chars().any(|c| c.is_ascii_digit()) {
+            violations.push(violation("requires at least one number"));
+        }
+
+        if self.require_lowercase && !password.chars().any(|c| c.is_ascii_lowercase()) {
+            violations.push(violation("requires at least one lowercase letter"));
+        }
+
+        if self.require_uppercase && !password.chars().any(|c| c.is_ascii_uppercase()) {
+            violations.push(violation("requires at least one uppercase letter"));
+        }
+
+        violations
+    }
+}
+
+impl UsernameRules {
+    fn violations(&self, username: &str) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        let length = username.chars().count();
+
+        if length < self.min_length {
+            violations.push(violation("username too short"));
+        }
+
+        if length > self.max_length {
+            violations.push(violation("username too long"));
+        }
+
+        if !self.pattern.is_match(username) {
+            violations.push(violation("username contains invalid characters"));
+        }
+
+        if self
+            .reserved
+            .iter()
+            .any(|reserved| reserved.eq_ignore_ascii_case(username))
+        {
+            violations.push(violation("username is reserved"));
+        }
+
+        violations
+            .into_iter()
+            .map(|v| with_field(v, "username"))
+            .collect()
+    }
+}
+
+impl ClientRules {
+    fn host_allowed(&self, url: &Url) -> bool {
+        self.allowed_hosts.is_empty()
+            || url.host_str().is_some_and(|host| {
+                self.allowed_hosts
+                    .iter()
+                    .any(|pattern| domain_matches(pattern, host))
+            })
+    }
+
+    fn secure(&self, url: &Url) -> bool {
+        if self.allow_insecure_uris {
+            return true;
+        }
+
+        url.scheme() == "https" && url.port().is_none() && !is_localhost(url)
+    }
+
+    /// Check a URI of the client, like its `client_uri` or `tos_uri`
+    fn check_uri(&self, name: &str, url: &Url, violations: &mut Vec<Violation>) {
+        if !self.secure(url) {
+            violations.push(violation(format!("invalid {name}")));
+        } else if !self.host_allowed(url) {
+            violations.push(violation(format!("{name} is not on an allowed host")));
+        }
+    }
+
+    fn violations(&self, metadata: &VerifiedClientMetadata) -> Vec<Violation> {
+        let mut violations = Vec::new();
+
+        if let Some(client_uri) = &metadata.client_uri {
+            self.check_uri("client_uri", client_uri.non_localized(), &mut violations);
+        } else {
+            violations.push(violation("missing client_uri"));
+        }
+
+        for (name, uri) in [
+            ("tos_uri", &metadata.tos_uri),
+            ("policy_uri", &metadata.policy_uri),
+            ("logo_uri", &metadata.logo_uri),
+        ] {
+            if let Some(uri) = uri {
+                self.check_uri(name, uri.non_localized(), &mut violations);
+            }
+        }
+
+        if metadata.contacts.as_ref().map_or(true, Vec::is_empty) {
+            violations.push(violation("missing contacts"));
+        }
+
+        for grant_type in metadata.grant_types() {
+            if self.disabled_grant_types.contains(grant_type) {
+                violations.push(violation(format!("grant_type {grant_type} is disabled")));
+            }
+        }
+
+        if metadata
+            .grant_types()
+            .contains(&OAuthGrantType::ClientCredentials)
+            && *metadata.token_endpoint_auth_method() == OAuthClientAuthenticationMethod::None
+        {
+            violations.push(violation(
+                "client_credentials grant_type requires some form of client authentication",
+            ));
+        }
+
+        let native = metadata.application_type() == ApplicationType::Native;
+        for redirect_uri in metadata.redirect_uris() {
+            // Native clients can redirect to localhost or to custom schemes
+            let native_redirect = native
+                && ((redirect_uri.scheme() == "http" && is_localhost(redirect_uri))
+                    || (redirect_uri.scheme() != "http" && redirect_uri.scheme() != "https"));
+
+            if !native_redirect && !(self.secure(redirect_uri) && self.host_allowed(redirect_uri)) {
+                violations.push(violation("invalid redirect_uri"));
+            }
+        }
+
+        violations
+    }
+}
+
+fn is_localhost(url: &Url) -> bool {
+    match url.host() {
+        Some(Host::Domain(domain)) => domain == "localhost",
+        Some(Host::Ipv4(ip)) => ip.is_loopback() || ip.is_unspecified(),
+        Some(Host::Ipv6(ip)) => ip.is_loopback(),
+        None => false,
+    }
+}
+
+impl BuiltinRules {
+    fn can_request_admin(&self, user: &User) -> bool {
+        user.can_request_admin || self.admin_users.contains(&user.username)
+    }
+
+    /// Whether the user can use the client, if it is restricted to a subset of
+    /// users
+    fn user_allowed_on_client(&self, user: &User, client: &Client) -> bool {
+        let Some(restricted) = self.restricted_clients.get(&client.client_id) else {
+            return true;
+        };
+
+        restricted.allowed_users.contains(&user.username)
+            || (restricted.allow_admins && self.can_request_admin(user))
+    }
+
+    /// The violation of a restricted client, if the user can't use it
+    fn restricted_client_violation(
+        &self,
+        user: Option<&User>,
+        client: &Client,
+    ) -> Option<Violation> {
+        // Only applies to grants which have a user
+        let user = user?;
+        if self.user_allowed_on_client(user, client) {
+            return None;
+        }
+
+        let mut violation = violation("user is not allowed to use this client");
+        violation.code = Some("client-restricted".to_owned());
+        Some(violation)
+    }
+
+    /// Whether a scope token can be requested, as in the default
+    /// `authorization_grant` policy
+    fn scope_allowed(
+        &self,
+        scope: &str,
+        grant_type: &GrantType,
+        client: &Client,
+        user: Option<&User>,
+    ) -> bool {
+        let authorization_code = matches!(grant_type, GrantType::AuthorizationCode);
+        let admin_user = user.is_some_and(|user| self.can_request_admin(user));
+        match scope {
+            "openid" | "email" | "urn:mas:graphql:*" => true,
+            "urn:synapse:admin:*" => authorization_code && admin_user,
+            "urn:mas:admin" => match grant_type {
+                GrantType::AuthorizationCode => admin_user,
+                GrantType::ClientCredentials => self.admin_clients.contains(&client.id.to_string()),
+                GrantType::RefreshToken | GrantType::JwtBearer => false,
+            },
+            "urn:mas:upstream_tokens" => {
+                matches!(grant_type, GrantType::ClientCredentials)
+                    && self.upstream_token_clients.contains(&client.id.to_string())
+            }
+            "urn:matrix:org.matrix.msc2967.client:api:*" => authorization_code,
+            scope if self.custom_scopes.iter().any(|custom| custom == scope) => true,
+            scope => {
+                authorization_code
+                    && scope
+                        .strip_prefix("urn:matrix:org.matrix.msc2967.client:device:")
+                        .is_some_and(|device| {
+                            device.len() >= 10
+                                && device
+                                    .chars()
+                                    .all(|c| c.is_ascii_alphanumeric() || c == '-')
+                        })
+            }
+        }
+    }
+}
+
+impl BuiltinEvaluate for EmailInput<'_> {
+    type Output = EvaluationResult;
+
+    fn evaluate_builtin(&self, rules: &BuiltinRules) -> EvaluationResult {
+        EvaluationResult {
+            violations: rules.emails.violations(self.email),
+        }
+    }
+}
+
+impl BuiltinEvaluate for PasswordInput<'_> {
+    type Output = EvaluationResult;
+
+    fn evaluate_builtin(&self, rules: &BuiltinRules) -> EvaluationResult {
+        EvaluationResult {
+            violations: rules.passwords.violations(self.password),
+        }
+    }
+}
+
+impl BuiltinEvaluate for RegisterInput<'_> {
+    type Output = EvaluationResult;
+
+    fn evaluate_builtin(&self, rules: &BuiltinRules) -> EvaluationResult {
+        let (username, email, password) = match self {
+            RegisterInput::Password {
+                username,
+                password,
+                email,
+                ..
+            } => (*username, Some(*email), Some(*password)),
+            RegisterInput::UpstreamOAuth2 {
+                username, email, ..
+            } => (*username, *email, None),
+            // The server picked the username of the guest, nothing to check
+            RegisterInput::GuestUpgrade { .. } => {
+                return EvaluationResult {
+                    violations: Vec::new(),
+                }
+            }
+        };
+
+        let mut violations = rules.usernames.violations(username);
+
+        if let Some(password) = password {
+            violations.extend(
+                rules
+                    .passwords
+                    .violations(password)
+                    .into_iter()
+                    .map(|v| with_field(v, "password")),
+            );
+        }
+
+        if let Some(email) = email {
+            violations.extend(
+                rules
+                    .emails
+                    .violations(email)
+                    .into_iter()
+                    .map(|v| with_field(v, "email")),
+            );
+        }
+
+        EvaluationResult { violations }
+    }
+}
+
+impl BuiltinEvaluate for ClientRegistrationInput<'_> {
+    type Output = EvaluationResult;
+
+    fn evaluate_builtin(&self, rules: &BuiltinRules) -> EvaluationResult {
+        EvaluationResult {
+            violations: rules.clients.violations(self.client_metadata),
+        }
+    }
+}
+
+impl BuiltinEvaluate for AuthorizationGrantInput<'_> {
+    type Output = EvaluationResult;
+
+    fn evaluate_builtin(&self, rules: &BuiltinRules) -> EvaluationResult {
+        let mut violations: Vec<Violation> = self
+            .scope
+            .iter()
+            .filter(|scope| {
+                !rules.scope_allowed(scope.as_str(), &self.grant_type, self.client, self.user)
+            })
+            .map(|scope| violation(format!("scope '{scope}' not allowed")))
+            .collect();
+
+        let device_scopes = self
+            .scope
+            .iter()
+            .filter(|scope| {
+                scope
+                    .as_str()
+                    .starts_with("urn:matrix:org.matrix.msc2967.client:device:")
+            })
+            .count();
+        if device_scopes > 1 {
+            violations.push(violation("only one device scope is allowed at a time"));
+        }
+
+        violations.extend(rules.restricted_client_violation(self.user, self.client));
+
+        if self.user.is_some_and(|user| user.quarantined_at.is_some()) {
+            violations.push(violation("user is quarantined"));
+        }
+
+        EvaluationResult { violations }
+    }
+}
+
+impl BuiltinEvaluate for TokenInput<'_> {
+    type Output = TokenEvaluationResult;
+
+    fn evaluate_builtin(&self, rules: &BuiltinRules) -> TokenEvaluationResult {
+        // Admin scopes are stripped from tokens of users who can no longer request
+        // them, and from clients which are not admin clients
+        let scope: Scope = self
+            .scope
+            .iter()
+            .filter(|scope| match scope.as_str() {
+                "urn:synapse:admin:*" => {
+                    self.user.map_or(true, |user| rules.can_request_admin(user))
+                }
+                "urn:mas:admin" => match self.user {
+                    Some(user) => rules.can_request_admin(user),
+                    None => rules.admin_clients.contains(&self.client.id.to_string()),
+                },
+                "urn:mas:upstream_tokens" => rules
+                    .upstream_token_clients
+                    .contains(&self.client.id.to_string()),
+                _ => true,
+            })
+            // Clients can be limited to a set of scopes, regardless of what they
+            // were granted at authorization time
+            .filter(|scope| {
+                rules
+                    .tokens
+                    .client_scopes
+                    .get(&self.client.client_id)
+                    .map_or(true, |allowed| allowed.iter().any(|s| s == scope.as_str()))
+            })
+            // Scopes can be stripped for some grant types
+            .filter(|scope| {
+                rules
+                    .tokens
+                    .stripped_scopes
+                    .get(&self.grant_type)
+                    .map_or(true, |stripped| {
+                        !stripped.iter().any(|s| s == scope.as_str())
+                    })
+            })
+            .cloned()
+            .collect();
+
+        // Users removed from a restricted client can't refresh their tokens
+        // anymore
+        let mut violations: Vec<Violation> = rules
+            .restricted_client_violation(self.user, self.client)
+            .into_iter()
+            .collect();
+
+        // Quarantined users keep their existing sessions, but can't be granted
+        // new ones
+        if !matches!(self.grant_type, GrantType::RefreshToken)
+            && self.user.is_some_and(|user| user.quarantined_at.is_some())
+        {
+            violations.push(violation("user is quarantined"));
+        }
+
+        TokenEvaluationResult {
+            decision: TokenDecision { violations, scope },
+        }
+    }
+}
+
+impl BuiltinEvaluate for UpstreamLoginInput<'_> {
+    type Output = EvaluationResult;
+
+    fn evaluate_builtin(&self, _rules: &BuiltinRules) -> EvaluationResult {
+        // There are no built-in rules on upstream logins: the rules on the
+        // username and email are enforced when registering
+        EvaluationResult {
+            violations: Vec::new(),
+        }
+    }
+}
+
+impl BuiltinEvaluate for ClaimsInput<'_> {
+    type Output = EvaluationResult;
+
+    fn evaluate_builtin(&self, _rules: &BuiltinRules) -> EvaluationResult {
+        // The claims are only mapped from the attributes the operator chose, so
+        // there is nothing to veto by default
+        EvaluationResult {
+            violations: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::RequestInput;
+
+    #[test]
+    fn test_domain_matches() {
+        assert!(domain_matches("example.com", "example.com"));
+        assert!(domain_matches("example.com", "EXAMPLE.com"));
+        assert!(domain_matches("*.example.com", "foo.example.com"));
+        assert!(!domain_matches("*.example.com", "example.com"));
+        assert!(!domain_matches("*.example.com", "foo.bar.example.com"));
+        assert!(!domain_matches("example.com", "badexample.com"));
+    }
+
+    #[test]
+    fn test_email_rules() {
+        let rules = EmailRules {
+            allowed_domains: vec!["element.io".to_owned(), "*.element.io".to_owned()],
+            banned_domains: vec!["staging.element.io".to_owned()],
+        };
+
+        assert!(rules.violations("hello@element.io").is_empty());
+        assert!(rules.violations("hello@foo.element.io").is_empty());
+        assert_eq!(rules.violations("hello@example.com").len(), 1);
+        assert_eq!(rules.violations("hello@staging.element.io").len(), 1);
+        assert_eq!(rules.violations("not-an-email").len(), 1);
+    }
+
+    #[test]
+    fn test_username_rules() {
+        let rules = UsernameRules::default();
+
+        assert!(rules.violations("alice").is_empty());
+        assert_eq!(rules.violations("al").len(), 1);
+        assert_eq!(rules.violations("Alice").len(), 1);
+        assert_eq!(rules.violations("ADMIN").len(), 2);
+        assert_eq!(rules.violations("averyverylongusername").len(), 1);
+    }
+
+    #[test]
+    fn test_password_rules() {
+        let rules = PasswordRules {
+            min_length: 8,
+            require_lowercase: true,
+            require_uppercase: true,
+            require_number: true,
+        };
+
+        assert!(rules.violations("Hunter2hunter").is_empty());
+        assert_eq!(rules.violations("hunter2").len(), 2);
+        assert_eq!(rules.violations("HUNTERHUNTER").len(), 2);
+    }
+
+    #[test]
+    fn test_client_rules() {
+        let rules = ClientRules {
+            allowed_hosts: vec!["example.com".to_owned(), "*.example.com".to_owned()],
+            allow_insecure_uris: false,
+            disabled_grant_types: vec![OAuthGrantType::RefreshToken],
+        };
+
+        let metadata = |client_uri: &str, redirect_uri: &str| {
+            oauth2_types::registration::ClientMetadata {
+                client_uri: Some(oauth2_types::registration::Localized::new(
+                    client_uri.parse().unwrap(),
+                    None,
+                )),
+                redirect_uris: Some(vec![redirect_uri.parse().unwrap()]),
+                contacts: Some(vec!["contact@example.com".to_owned()]),
+                ..Default::default()
+            }
+            .validate()
+            .unwrap()
+        };
+
+        let valid = metadata("https://example.com/", "https://app.example.com/callback");
+        assert!(rules.violations(&valid).is_empty());
+
+        let other_host = metadata("https://example.org/", "https://example.org/callback");
+        assert_eq!(rules.violations(&other_host).len(), 2);
+
+        let insecure = metadata("http://example.com/", "https://example.com/callback");
+        assert_eq!(rules.violations(&insecure).len(), 1);
+
+        let refresh = oauth2_types::registration::ClientMetadata {
+            client_uri: Some(oauth2_types::registration::Localized::new(
+                "https://example.com/".parse().unwrap(),
+                None,
+            )),
+            redirect_uris: Some(vec!["https://example.com/callback".parse().unwrap()]),
+            grant_types: Some(vec![
+                OAuthGrantType::AuthorizationCode,
+                OAuthGrantType::RefreshToken,
+            ]),
+            contacts: Some(vec!["contact@example.com".to_owned()]),
+            ..Default::default()
+        }
+        .validate()
+        .unwrap();
+        assert_eq!(rules.violations(&refresh).len(), 1);
+    }
+
+    fn sample_user_and_client() -> (User, Client) {
+        let now = chrono::Utc::now();
+        let mut rng = rand::thread_rng();
+        let user = User::samples(now, &mut rng).remove(0);
+        let mut client = Client::samples(now, &mut rng).remove(0);
+        client.client_id = "client".to_owned();
+        (user, client)
+    }
+
+    fn restricted(allowed_users: &[&str], allow_admins: bool) -> HashMap<String, RestrictedClient> {
+        HashMap::from([(
+            "client".to_owned(),
+            RestrictedClient {
+                allowed_users: allowed_users.iter().map(|&u| u.to_owned()).collect(),
+                allow_admins,
+            },
+        )])
+    }
+
+    fn grant(
+        rules: &BuiltinRules,
+        user: Option<&User>,
+        client: &Client,
+        grant_type: GrantType,
+    ) -> EvaluationResult {
+        let scope: Scope = "openid".parse().unwrap();
+        AuthorizationGrantInput {
+            user,
+            client,
+            scope: &scope,
+            grant_type,
+            request: RequestInput {
+                ip_address: None,
+                user_agent: None,
+                country: None,
+                time: chrono::Utc::now().into(),
+            },
+        }
+        .evaluate_builtin(rules)
+    }
+
+    fn token(
+        rules: &BuiltinRules,
+        user: Option<&User>,
+        client: &Client,
+        grant_type: GrantType,
+        scope: &str,
+    ) -> TokenDecision {
+        let scope: Scope = scope.parse().unwrap();
+        TokenInput {
+            user,
+            client,
+            scope: &scope,
+            grant_type,
+            request: RequestInput {
+                ip_address: None,
+                user_agent: None,
+                country: None,
+                time: chrono::Utc::now().into(),
+            },
+        }
+        .evaluate_builtin(rules)
+        .decision
+    }
+
+    fn scope_set(scope: &Scope) -> Vec<&str> {
+        let mut scope: Vec<&str> = scope.iter().map(|s| s.as_str()).collect();
+        scope.sort_unstable();
+        scope
+    }
+
+    /// Mirrors `test_restricted_client` in `authorization_grant_test.rego`
+    #[test]
+    fn test_authorization_grant_restricted_client() {
+        let (user, client) = sample_user_and_client();
+        let code = GrantType::AuthorizationCode;
+
+        // Unrestricted clients are available to everyone
+        let mut rules = BuiltinRules {
+            restricted_clients: HashMap::from([(
+                "other-client".to_owned(),
+                RestrictedClient::default(),
+            )]),
+            ..Default::default()
+        };
+        assert!(grant(&rules, Some(&user), &client, code).valid());
+
+        rules.restricted_clients = restricted(&["john"], false);
+        assert!(grant(&rules, Some(&user), &client, code).valid());
+
+        rules.restricted_clients = restricted(&["jane"], false);
+        let result = grant(&rules, Some(&user), &client, code);
+        assert!(result.has_code("client-restricted"));
+
+        rules.restricted_clients = restricted(&[], true);
+        rules.admin_users = vec!["john".to_owned()];
+        assert!(grant(&rules, Some(&user), &client, code).valid());
+
+        rules.admin_users = Vec::new();
+        assert!(!grant(&rules, Some(&user), &client, code).valid());
+
+        // Only applies to grants which have a user
+        assert!(grant(&rules, None, &client, GrantType::ClientCredentials).valid());
+    }
+
+    /// Mirrors `test_client_scopes` in `token_test.rego`
+    #[test]
+    fn test_token_client_scopes() {
+        let (user, client) = sample_user_and_client();
+        let scope = "openid urn:matrix:org.matrix.msc2967.client:api:*";
+        let refresh = GrantType::RefreshToken;
+
+        let mut rules = BuiltinRules::default();
+        rules.tokens.client_scopes =
+            HashMap::from([("client".to_owned(), vec!["openid".to_owned()])]);
+        let decision = token(&rules, Some(&user), &client, refresh, scope);
+        assert_eq!(scope_set(&decision.scope), ["openid"]);
+
+        rules.tokens.client_scopes =
+            HashMap::from([("other".to_owned(), vec!["openid".to_owned()])]);
+        let decision = token(&rules, Some(&user), &client, refresh, scope);
+        assert_eq!(
+            scope_set(&decision.scope),
+            ["openid", "urn:matrix:org.matrix.msc2967.client:api:*"]
+        );
+    }
+
+    /// Mirrors `test_stripped_scopes` in `token_test.rego`
+    #[test]
+    fn test_token_stripped_scopes() {
+        let (mut user, client) = sample_user_and_client();
+        user.can_request_admin = true;
+        let scope = "openid urn:synapse:admin:*";
+
+        let mut rules = BuiltinRules::default();
+        rules.tokens.stripped_scopes = HashMap::from([(
+            GrantType::RefreshToken,
+            vec!["urn:synapse:admin:*".to_owned()],
+        )]);
+
+        let decision = token(&rules, Some(&user), &client, GrantType::RefreshToken, scope);
+        assert_eq!(scope_set(&decision.scope), ["openid"]);
+
+        let decision = token(
+            &rules,
+            Some(&user),
+            &client,
+            GrantType::AuthorizationCode,
+            scope,
+        );
+        assert_eq!(
+            scope_set(&decision.scope),
+            ["openid", "urn:synapse:admin:*"]
+        );
+    }
+
+    /// Mirrors `test_admin_scopes` and the client credentials tests in
+    /// `token_test.rego`
+    #[test]
+    fn test_token_admin_scopes() {
+        let (user, client) = sample_user_and_client();
+        let mut rules = BuiltinRules::default();
+
+        let decision = token(
+            &rules,
+            Some(&user),
+            &client,
+            GrantType::RefreshToken,
+            "openid urn:synapse:admin:*",
+        );
+        assert_eq!(scope_set(&decision.scope), ["openid"]);
+
+        let decision = token(
+            &rules,
+            None,
+            &client,
+            GrantType::ClientCredentials,
+            "urn:mas:admin urn:mas:upstream_tokens",
+        );
+        assert!(decision.scope.is_empty());
+
+        rules.admin_users = vec!["john".to_owned()];
+        rules.admin_clients = vec![client.id.to_string()];
+
+        let decision = token(
+            &rules,
+            Some(&user),
+            &client,
+            GrantType::AuthorizationCode,
+            "openid urn:mas:admin",
+        );
+        assert_eq!(scope_set(&decision.scope), ["openid", "urn:mas:admin"]);
+
+        let decision = token(
+            &rules,
+            None,
+            &client,
+            GrantType::ClientCredentials,
+            "urn:mas:admin urn:mas:upstream_tokens",
+        );
+        assert_eq!(scope_set(&decision.scope), ["urn:mas:admin"]);
+
+        // Upstream tokens have a dedicated list of clients
+        rules.upstream_token_clients = vec![client.id.to_string()];
+        let decision = token(
+            &rules,
+            None,
+            &client,
+            GrantType::ClientCredentials,
+            "urn:mas:admin urn:mas:upstream_tokens",
+        );
+        assert_eq!(
+            scope_set(&decision.scope),
+            ["urn:mas:admin", "urn:mas:upstream_tokens"]
+        );
+    }
+
+    /// Mirrors `test_restricted_clients` in `token_test.rego`
+    #[test]
+    fn test_token_restricted_clients() {
+        let (user, client) = sample_user_and_client();
+        let refresh = GrantType::RefreshToken;
+
+        let mut rules = BuiltinRules::default();
+        assert!(token(&rules, Some(&user), &client, refresh, "openid")
+            .violations
+            .is_empty());
+
+        rules.restricted_clients = restricted(&["alice"], false);
+        assert!(!token(&rules, Some(&user), &client, refresh, "openid")
+            .violations
+            .is_empty());
+
+        let mut admin = user.clone();
+        admin.username = "alice".to_owned();
+        assert!(token(&rules, Some(&admin), &client, refresh, "openid")
+            .violations
+            .is_empty());
+
+        // Client credentials grants have no user
+        assert!(token(
+            &rules,
+            None,
+            &client,
+            GrantType::ClientCredentials,
+            "openid"
+        )
+        .violations
+        .is_empty());
+    }
+
+    /// Mirrors `test_quarantined_user` in `token_test.rego`
+    #[test]
+    fn test_token_quarantined_user() {
+        let (mut user, client) = sample_user_and_client();
+        let rules = BuiltinRules::default();
+
+        assert!(
+            token(&rules, Some(&user), &client, GrantType::JwtBearer, "openid")
+                .violations
+                .is_empty()
+        );
+
+        user.quarantined_at = Some(chrono::Utc::now());
+        assert!(
+            !token(&rules, Some(&user), &client, GrantType::JwtBearer, "openid")
+                .violations
+                .is_empty()
+        );
+
+        // Existing sessions can still be refreshed
+        assert!(token(
+            &rules,
+            Some(&user),
+            &client,
+            GrantType::RefreshToken,
+            "openid"
+        )
+        .violations
+        .is_empty());
+    }
+}
@@ -0,0 +1,259 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Extract the policy WASM module from an OPA bundle, as served by OPA bundle
+//! servers.
+//!
+//! Bundles are gzipped tarballs built with `opa build -t wasm`. When they are
+//! signed with `opa build --signing-key`, the bundle has a `.signatures.json`
+//! file holding a JWT which lists the hashes of the files in the bundle.
+
+use std::io::Read;
+
+use flate2::read::GzDecoder;
+use mas_jose::{jwk::PublicJsonWebKeySet, jwt::Jwt};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+const MODULE_PATH: &str = "policy.wasm";
+const SIGNATURES_PATH: &str = ".signatures.json";
+
+#[derive(Debug, Error)]
+pub enum BundleError {
+    #[error("failed to read the bundle")]
+    Read(#[from] std::io::Error),
+
+    #[error("the bundle has no policy.wasm file")]
+    MissingModule,
+
+    #[error("the bundle is not signed")]
+    MissingSignature,
+
+    #[error("invalid signatures file in the bundle")]
+    InvalidSignaturesFile(#[source] serde_json::Error),
+
+    #[error("none of the bundle signatures could be verified")]
+    InvalidSignature,
+
+    #[error("the bundle signature doesn't cover the policy.wasm file")]
+    UnsignedModule,
+
+    #[error("unsupported hash algorithm {0:?} in the bundle signature")]
+    UnsupportedHashAlgorithm(String),
+
+    #[error("the hash of the policy.wasm file doesn't match the bundle signature")]
+    HashMismatch,
+}
+
+/// The `.signatures.json` file of a bundle
+#[derive(Deserialize)]
+struct SignaturesFile {
+    signatures: Vec<String>,
+}
+
+/// The claims of a bundle signature
+#[derive(Deserialize)]
+struct SignedFiles {
+    files: Vec<SignedFile>,
+}
+
+#[derive(Deserialize)]
+struct SignedFile {
+    name: String,
+    hash: String,
+    #[serde(default = "default_hash_algorithm")]
+    algorithm: String,
+}
+
+fn default_hash_algorithm() -> String {
+    "SHA-256".to_owned()
+}
+
+/// Extract the policy module from a bundle
+///
+/// If `keys` are given, the bundle must be signed with one of them, and the
+/// signature must match the policy module.
+///
+/// # Errors
+///
+/// Returns an error if the bundle could not be read, if it has no policy
+/// module, or if its signature could not be verified.
+pub fn extract_module(
+    bundle: &[u8],
+    keys: Option<&PublicJsonWebKeySet>,
+) -> Result<Vec<u8>, BundleError> {
+    let mut archive = tar::Archive::new(GzDecoder::new(bundle));
+
+    let mut module = None;
+    let mut signatures = None;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().into_owned();
+        match path.trim_start_matches('/') {
+            MODULE_PATH => {
+                let mut buf = Vec::new();
+                entry.read_to_end(&mut buf)?;
+                module = Some(buf);
+            }
+            SIGNATURES_PATH => {
+                let file: SignaturesFile =
+                    serde_json::from_reader(entry).map_err(BundleError::InvalidSignaturesFile)?;
+                signatures = Some(file.signatures);
+            }
+            _ => {}
+        }
+    }
+
+    let module = module.ok_or(BundleError::MissingModule)?;
+
+    if let Some(keys) = keys {
+        let signatures = signatures.ok_or(BundleError::MissingSignature)?;
+        verify_module(&signatures, keys, &module)?;
+    }
+
+    Ok(module)
+}
+
+/// Check that one of the signatures was made by one of the keys, and that it
+/// covers the policy module
+fn verify_module(
+    signatures: &[String],
+    keys: &PublicJsonWebKeySet,
+    module: &[u8],
+) -> Result<(), BundleError> {
+    let claims = signatures
+        .iter()
+        .filter_map(|signature| Jwt::<SignedFiles>::try_from(signature.as_str()).ok())
+        .find(|jwt| jwt.verify_with_jwks(keys).is_ok())
+        .ok_or(BundleError::InvalidSignature)?;
+
+    let file = claims
+        .payload()
+        .files
+        .iter()
+        .find(|file| file.name.trim_start_matches('/') == MODULE_PATH)
+        .ok_or(BundleError::UnsignedModule)?;
+
+    if !file.algorithm.eq_ignore_ascii_case("SHA-256") {
+        return Err(BundleError::UnsupportedHashAlgorithm(
+            file.algorithm.clone(),
+        ));
+    }
+
+    let hash = format!("{:x}", Sha256::digest(module));
+    if !hash.eq_ignore_ascii_case(&file.hash) {
+        return Err(BundleError::HashMismatch);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::{write::GzEncoder, Compression};
+    use mas_iana::jose::JsonWebSignatureAlg;
+    use mas_jose::jwt::JsonWebSignatureHeader;
+    use mas_keystore::{JsonWebKey, JsonWebKeySet, Keystore, PrivateKey};
+    use rand::SeedableRng;
+
+    use super::*;
+
+    const MODULE: &[u8] = b"\0asm not really a module";
+
+    fn build_bundle(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        for (path, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len().try_into().unwrap());
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, *content).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    fn sign(keystore: &Keystore, hash: &str) -> Vec<u8> {
+        let mut rng = rand_chacha::ChaChaRng::seed_from_u64(42);
+        let alg = JsonWebSignatureAlg::Es256;
+        let key = keystore.signing_key_for_algorithm(&alg).unwrap();
+        let signer = key.params().signing_key_for_alg(&alg).unwrap();
+        let header = JsonWebSignatureHeader::new(alg);
+        let claims = serde_json::json!({
+            "files": [{"name": "/policy.wasm", "hash": hash, "algorithm": "SHA-256"}],
+        });
+        let jwt = Jwt::sign_with_rng(&mut rng, header, claims, &signer).unwrap();
+        serde_json::to_vec(&serde_json::json!({ "signatures": [jwt.as_str()] })).unwrap()
+    }
+
+    fn keystore() -> Keystore {
+        let mut rng = rand_chacha::ChaChaRng::seed_from_u64(42);
+        let key = PrivateKey::generate_ec_p256(&mut rng);
+        Keystore::new(JsonWebKeySet::new(vec![JsonWebKey::new(key)]))
+    }
+
+    #[test]
+    fn test_unsigned_bundle() {
+        let bundle = build_bundle(&[("data.json", b"{}"), ("policy.wasm", MODULE)]);
+        let module = extract_module(&bundle, None).unwrap();
+        assert_eq!(module, MODULE);
+
+        // Signatures are required when keys are given
+        let keys = keystore().public_jwks();
+        assert!(matches!(
+            extract_module(&bundle, Some(&keys)),
+            Err(BundleError::MissingSignature)
+        ));
+
+        // The bundle must have a module
+        let bundle = build_bundle(&[("data.json", b"{}")]);
+        assert!(matches!(
+            extract_module(&bundle, None),
+            Err(BundleError::MissingModule)
+        ));
+    }
+
+    #[test]
+    fn test_signed_bundle() {
+        let keystore = keystore();
+        let keys = keystore.public_jwks();
+        let hash = format!("{:x}", Sha256::digest(MODULE));
+
+        let signatures = sign(&keystore, &hash);
+        let bundle = build_bundle(&[("policy.wasm", MODULE), (".signatures.json", &signatures)]);
+        let module = extract_module(&bundle, Some(&keys)).unwrap();
+        assert_eq!(module, MODULE);
+
+        // A tampered module is rejected
+        let bundle = build_bundle(&[
+            ("policy.wasm", b"\0asm tampered"),
+            (".signatures.json", &signatures),
+        ]);
+        assert!(matches!(
+            extract_module(&bundle, Some(&keys)),
+            Err(BundleError::HashMismatch)
+        ));
+
+        // A bundle signed with another key is rejected
+        let mut rng = rand_chacha::ChaChaRng::seed_from_u64(1337);
+        let other_key = PrivateKey::generate_ec_p256(&mut rng);
+        let other_keys =
+            Keystore::new(JsonWebKeySet::new(vec![JsonWebKey::new(other_key)])).public_jwks();
+        let bundle = build_bundle(&[("policy.wasm", MODULE), (".signatures.json", &signatures)]);
+        assert!(matches!(
+            extract_module(&bundle, Some(&other_keys)),
+            Err(BundleError::InvalidSignature)
+        ));
+    }
+}
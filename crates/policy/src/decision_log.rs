@@ -0,0 +1,208 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Log policy decisions, and record metrics about them.
+
+use std::time::Duration;
+
+use opentelemetry::{
+    metrics::{Counter, Histogram},
+    Key,
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::model::Violation;
+
+const ENTRYPOINT: Key = Key::from_static_str("entrypoint");
+const RESULT: Key = Key::from_static_str("result");
+
+/// Fields of the inputs which are never logged
+const REDACTED_FIELDS: &[&str] = &["password"];
+
+/// Logs every policy decision as an audit event, and records metrics about
+/// them
+#[derive(Clone)]
+pub(crate) struct DecisionLogger {
+    evaluations: Counter<u64>,
+    duration: Histogram<u64>,
+    input_sample_rate: f64,
+}
+
+impl DecisionLogger {
+    pub(crate) fn new() -> Self {
+        let meter = opentelemetry::global::meter_with_version(
+            env!("CARGO_PKG_NAME"),
+            Some(env!("CARGO_PKG_VERSION")),
+            Some(opentelemetry_semantic_conventions::SCHEMA_URL),
+            None,
+        );
+
+        let evaluations = meter
+            .u64_counter("mas.policy.evaluations")
+            .with_description("The number of policy evaluations")
+            .with_unit(opentelemetry::metrics::Unit::new("{evaluations}"))
+            .init();
+
+        let duration = meter
+            .u64_histogram("mas.policy.evaluation.duration")
+            .with_description("The time it took to evaluate a policy")
+            .with_unit(opentelemetry::metrics::Unit::new("ms"))
+            .init();
+
+        Self {
+            evaluations,
+            duration,
+            input_sample_rate: 0.0,
+        }
+    }
+
+    /// Log the full input of a fraction of the evaluations, between 0 and 1
+    pub(crate) fn set_input_sample_rate(&mut self, rate: f64) {
+        self.input_sample_rate = rate.clamp(0.0, 1.0);
+    }
+
+    /// Record a policy decision
+    pub(crate) fn record<I: Serialize>(
+        &self,
+        entrypoint: &'static str,
+        input: &I,
+        violations: &[Violation],
+        elapsed: Duration,
+    ) {
+        let result = if violations.is_empty() {
+            "allow"
+        } else {
+            "deny"
+        };
+        let attributes = [ENTRYPOINT.string(entrypoint), RESULT.string(result)];
+        let elapsed_ms = elapsed.as_millis().try_into().unwrap_or(u64::MAX);
+        self.evaluations.add(1, &attributes);
+        self.duration.record(elapsed_ms, &attributes);
+
+        let messages: Vec<&str> = violations.iter().map(|v| v.msg.as_str()).collect();
+
+        // The digest is computed on the redacted input, so that it can't be used to
+        // guess the redacted fields
+        let input = match serde_json::to_value(input) {
+            Ok(mut input) => {
+                redact(&mut input);
+                Some(input)
+            }
+            Err(err) => {
+                tracing::warn!(?err, "Could not serialize the policy input");
+                None
+            }
+        };
+        let digest = input
+            .as_ref()
+            .map(|input| Sha256::digest(input.to_string().as_bytes()));
+
+        let hex_digest = digest.as_ref().map(|digest| format!("{digest:x}"));
+        let sampled = digest.as_ref().is_some_and(|digest| self.sampled(digest));
+
+        if sampled {
+            tracing::info!(
+                audit = true,
+                policy.entrypoint = entrypoint,
+                policy.result = result,
+                policy.violations = ?messages,
+                policy.duration_ms = elapsed_ms,
+                policy.input.digest = hex_digest.as_deref(),
+                policy.input = %input.unwrap_or_default(),
+                "Policy evaluated",
+            );
+        } else {
+            tracing::info!(
+                audit = true,
+                policy.entrypoint = entrypoint,
+                policy.result = result,
+                policy.violations = ?messages,
+                policy.duration_ms = elapsed_ms,
+                policy.input.digest = hex_digest.as_deref(),
+                "Policy evaluated",
+            );
+        }
+    }
+
+    /// Decide whether to log the full input from its digest, so that the same
+    /// input is consistently sampled or not
+    fn sampled(&self, digest: &[u8]) -> bool {
+        if self.input_sample_rate <= 0.0 {
+            return false;
+        }
+
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&digest[..8]);
+
+        #[allow(clippy::cast_precision_loss)]
+        let position = u64::from_be_bytes(bytes) as f64 / u64::MAX as f64;
+        position < self.input_sample_rate
+    }
+}
+
+/// Replace the sensitive fields of an input with a placeholder
+fn redact(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if REDACTED_FIELDS.contains(&key.as_str()) {
+                    *value = serde_json::Value::String("[redacted]".to_owned());
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact() {
+        let mut input = serde_json::json!({
+            "registration_method": "password",
+            "username": "alice",
+            "password": "hunter2",
+            "nested": [{"password": "hunter3"}],
+        });
+        redact(&mut input);
+        assert_eq!(
+            input,
+            serde_json::json!({
+                "registration_method": "password",
+                "username": "alice",
+                "password": "[redacted]",
+                "nested": [{"password": "[redacted]"}],
+            })
+        );
+    }
+
+    #[test]
+    fn test_sampling() {
+        let mut logger = DecisionLogger::new();
+        let digest = Sha256::digest(b"input");
+        assert!(!logger.sampled(&digest));
+
+        logger.set_input_sample_rate(1.0);
+        assert!(logger.sampled(&digest));
+
+        logger.set_input_sample_rate(0.0);
+        assert!(!logger.sampled(&digest));
+    }
+}
@@ -15,22 +15,36 @@
 #![forbid(unsafe_code)]
 #![deny(clippy::all, clippy::str_to_string, rustdoc::broken_intra_doc_links)]
 #![warn(clippy::pedantic)]
-#![allow(clippy::missing_errors_doc)]
+#![allow(clippy::missing_errors_doc, clippy::module_name_repetitions)]
 
+pub mod builtin;
+pub mod bundle;
+mod decision_log;
 pub mod model;
 
-use mas_data_model::{AuthorizationGrant, Client, User};
+use std::{collections::HashMap, net::IpAddr, sync::Arc, time::Instant};
+
+use arc_swap::ArcSwap;
+use camino::Utf8Path;
+use mas_data_model::{AuthorizationGrant, Client, UpstreamOAuthProvider, User};
 use oauth2_types::{registration::VerifiedClientMetadata, scope::Scope};
 use opa_wasm::Runtime;
+use serde::{de::DeserializeOwned, Serialize};
 use thiserror::Error;
 use tokio::io::{AsyncRead, AsyncReadExt};
 use wasmtime::{Config, Engine, Module, Store};
 
-use self::model::{
-    AuthorizationGrantInput, ClientRegistrationInput, EmailInput, PasswordInput, RegisterInput,
+pub use self::model::{
+    EvaluationResult, GrantType, Requester, TokenDecision, TokenEvaluationResult, Violation,
+};
+use self::{
+    builtin::{BuiltinEvaluate, BuiltinRules},
+    decision_log::DecisionLogger,
+    model::{
+        AuthorizationGrantInput, ClaimsInput, ClientRegistrationInput, EmailInput, PasswordInput,
+        RegisterInput, RequestInput, TokenInput, UpstreamLoginInput,
+    },
 };
-pub use self::model::{EvaluationResult, Violation};
-use crate::model::GrantType;
 
 #[derive(Debug, Error)]
 pub enum LoadError {
@@ -52,6 +66,15 @@ pub enum LoadError {
     #[cfg(feature = "cache")]
     #[error("could not load wasmtime cache configuration")]
     CacheSetup(#[source] anyhow::Error),
+
+    #[error("failed to load the GeoIP database")]
+    GeoIp(#[source] maxminddb::MaxMindDBError),
+
+    #[error("GeoIP database loading task crashed")]
+    GeoIpTask(#[source] tokio::task::JoinError),
+
+    #[error("the built-in policy has no module to reload")]
+    Builtin,
 }
 
 #[derive(Debug, Error)]
@@ -74,27 +97,72 @@ pub struct Entrypoints {
     pub authorization_grant: String,
     pub email: String,
     pub password: String,
+    pub token: String,
+    pub upstream_login: String,
+    pub claims: String,
 }
 
 impl Entrypoints {
-    fn all(&self) -> [&str; 5] {
+    fn all(&self) -> [&str; 8] {
         [
             self.register.as_str(),
             self.client_registration.as_str(),
             self.authorization_grant.as_str(),
             self.email.as_str(),
             self.password.as_str(),
+            self.token.as_str(),
+            self.upstream_login.as_str(),
+            self.claims.as_str(),
         ]
     }
 }
 
-pub struct PolicyFactory {
+/// A GeoIP database, used to look up the country of the requester
+pub struct GeoIp {
+    reader: maxminddb::Reader<Vec<u8>>,
+}
+
+impl GeoIp {
+    /// Load a GeoIP database in the MaxMind DB format, like the `GeoLite2`
+    /// Country or City databases
+    pub async fn open(path: &Utf8Path) -> Result<Self, LoadError> {
+        let path = path.to_owned();
+        let reader = tokio::task::spawn_blocking(move || maxminddb::Reader::open_readfile(path))
+            .await
+            .map_err(LoadError::GeoIpTask)?
+            .map_err(LoadError::GeoIp)?;
+
+        Ok(Self { reader })
+    }
+
+    /// Get the ISO 3166-1 alpha-2 code of the country of an IP address
+    fn country(&self, ip: IpAddr) -> Option<String> {
+        let country: maxminddb::geoip2::Country = self.reader.lookup(ip).ok()?;
+        let code = country.country?.iso_code?;
+        Some(code.to_owned())
+    }
+}
+
+/// An OPA WASM module, with the data and entrypoints to evaluate it with
+struct OpaFactory {
     engine: Engine,
-    module: Module,
+    module: ArcSwap<Module>,
     data: serde_json::Value,
     entrypoints: Entrypoints,
 }
 
+/// How policies are evaluated
+enum FactoryBackend {
+    Opa(OpaFactory),
+    Builtin(Arc<BuiltinRules>),
+}
+
+pub struct PolicyFactory {
+    backend: FactoryBackend,
+    geoip: Option<Arc<GeoIp>>,
+    decision_logger: DecisionLogger,
+}
+
 impl PolicyFactory {
     #[tracing::instrument(name = "policy.load", skip(source), err)]
     pub async fn load(
@@ -113,22 +181,17 @@ impl PolicyFactory {
 
         let engine = Engine::new(&config).map_err(LoadError::Engine)?;
 
-        // Read and compile the module
-        let mut buf = Vec::new();
-        source.read_to_end(&mut buf).await?;
-        // Compilation is CPU-bound, so spawn that in a blocking task
-        let (engine, module) = tokio::task::spawn_blocking(move || {
-            let module = Module::new(&engine, buf)?;
-            anyhow::Ok((engine, module))
-        })
-        .await?
-        .map_err(LoadError::Compilation)?;
+        let module = Self::compile(&engine, &mut source).await?;
 
         let factory = Self {
-            engine,
-            module,
-            data,
-            entrypoints,
+            backend: FactoryBackend::Opa(OpaFactory {
+                engine,
+                module: ArcSwap::from_pointee(module),
+                data,
+                entrypoints,
+            }),
+            geoip: None,
+            decision_logger: DecisionLogger::new(),
         };
 
         // Try to instantiate
@@ -140,17 +203,105 @@ impl PolicyFactory {
         Ok(factory)
     }
 
+    /// Evaluate the policies with the built-in rules, instead of an OPA WASM
+    /// module
+    #[must_use]
+    pub fn builtin(rules: BuiltinRules) -> Self {
+        Self {
+            backend: FactoryBackend::Builtin(Arc::new(rules)),
+            geoip: None,
+            decision_logger: DecisionLogger::new(),
+        }
+    }
+
+    /// Read and compile a WASM module
+    async fn compile(
+        engine: &Engine,
+        source: &mut (impl AsyncRead + std::marker::Unpin),
+    ) -> Result<Module, LoadError> {
+        let mut buf = Vec::new();
+        source.read_to_end(&mut buf).await?;
+
+        // Compilation is CPU-bound, so spawn that in a blocking task
+        let engine = engine.clone();
+        let module = tokio::task::spawn_blocking(move || Module::new(&engine, buf))
+            .await?
+            .map_err(LoadError::Compilation)?;
+
+        Ok(module)
+    }
+
+    /// Replace the policy module with a new one.
+    ///
+    /// The new module is only swapped in if it compiles and can be
+    /// instantiated with the current data and entrypoints, so that a broken
+    /// module doesn't replace a working one. Policies which were already
+    /// instantiated keep using the previous module.
+    #[tracing::instrument(name = "policy.reload", skip_all, err)]
+    pub async fn reload(
+        &self,
+        mut source: impl AsyncRead + std::marker::Unpin,
+    ) -> Result<(), LoadError> {
+        let FactoryBackend::Opa(opa) = &self.backend else {
+            return Err(LoadError::Builtin);
+        };
+
+        let module = Self::compile(&opa.engine, &mut source).await?;
+
+        // Try to instantiate the new module before swapping it in
+        self.instantiate_module(opa, &module)
+            .await
+            .map_err(LoadError::Instantiate)?;
+
+        opa.module.store(Arc::new(module));
+
+        Ok(())
+    }
+
+    /// Look up the country of requesters in the given GeoIP database
+    #[must_use]
+    pub fn with_geoip(mut self, geoip: GeoIp) -> Self {
+        self.geoip = Some(Arc::new(geoip));
+        self
+    }
+
+    /// Log the full input of a fraction of the policy evaluations, between 0
+    /// and 1, to debug surprising decisions. Passwords are always redacted.
+    #[must_use]
+    pub fn with_input_sample_rate(mut self, rate: f64) -> Self {
+        self.decision_logger.set_input_sample_rate(rate);
+        self
+    }
+
     #[tracing::instrument(name = "policy.instantiate", skip_all, err)]
     pub async fn instantiate(&self) -> Result<Policy, InstantiateError> {
-        let mut store = Store::new(&self.engine, ());
-        let runtime = Runtime::new(&mut store, &self.module)
+        match &self.backend {
+            FactoryBackend::Opa(opa) => {
+                let module = opa.module.load_full();
+                self.instantiate_module(opa, &module).await
+            }
+            FactoryBackend::Builtin(rules) => Ok(Policy {
+                backend: PolicyBackend::Builtin(Arc::clone(rules)),
+                geoip: self.geoip.clone(),
+                decision_logger: self.decision_logger.clone(),
+            }),
+        }
+    }
+
+    async fn instantiate_module(
+        &self,
+        opa: &OpaFactory,
+        module: &Module,
+    ) -> Result<Policy, InstantiateError> {
+        let mut store = Store::new(&opa.engine, ());
+        let runtime = Runtime::new(&mut store, module)
             .await
             .map_err(InstantiateError::Runtime)?;
 
         // Check that we have the required entrypoints
         let policy_entrypoints = runtime.entrypoints();
 
-        for e in self.entrypoints.all() {
+        for e in opa.entrypoints.all() {
             if !policy_entrypoints.contains(e) {
                 return Err(InstantiateError::MissingEntrypoint {
                     entrypoint: e.to_owned(),
@@ -159,22 +310,95 @@ impl PolicyFactory {
         }
 
         let instance = runtime
-            .with_data(&mut store, &self.data)
+            .with_data(&mut store, &opa.data)
             .await
             .map_err(InstantiateError::LoadData)?;
 
         Ok(Policy {
-            store,
-            instance,
-            entrypoints: self.entrypoints.clone(),
+            backend: PolicyBackend::Opa {
+                store,
+                instance,
+                entrypoints: opa.entrypoints.clone(),
+            },
+            geoip: self.geoip.clone(),
+            decision_logger: self.decision_logger.clone(),
         })
     }
 }
 
+/// How a policy instance evaluates the policies
+enum PolicyBackend {
+    Opa {
+        store: Store<()>,
+        instance: opa_wasm::Policy<opa_wasm::DefaultContext>,
+        entrypoints: Entrypoints,
+    },
+    Builtin(Arc<BuiltinRules>),
+}
+
 pub struct Policy {
-    store: Store<()>,
-    instance: opa_wasm::Policy<opa_wasm::DefaultContext>,
-    entrypoints: Entrypoints,
+    backend: PolicyBackend,
+    geoip: Option<Arc<GeoIp>>,
+    decision_logger: DecisionLogger,
+}
+
+/// A policy entrypoint, used to resolve its path and to label the decision
+/// logs
+#[derive(Debug, Clone, Copy)]
+enum Entrypoint {
+    Register,
+    ClientRegistration,
+    AuthorizationGrant,
+    Email,
+    Password,
+    Token,
+    UpstreamLogin,
+    Claims,
+}
+
+impl Entrypoint {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Register => "register",
+            Self::ClientRegistration => "client_registration",
+            Self::AuthorizationGrant => "authorization_grant",
+            Self::Email => "email",
+            Self::Password => "password",
+            Self::Token => "token",
+            Self::UpstreamLogin => "upstream_login",
+            Self::Claims => "claims",
+        }
+    }
+
+    fn path(self, entrypoints: &Entrypoints) -> &str {
+        match self {
+            Self::Register => &entrypoints.register,
+            Self::ClientRegistration => &entrypoints.client_registration,
+            Self::AuthorizationGrant => &entrypoints.authorization_grant,
+            Self::Email => &entrypoints.email,
+            Self::Password => &entrypoints.password,
+            Self::Token => &entrypoints.token,
+            Self::UpstreamLogin => &entrypoints.upstream_login,
+            Self::Claims => &entrypoints.claims,
+        }
+    }
+}
+
+/// The result of a policy evaluation, which can be logged
+trait Decision {
+    fn violations(&self) -> &[Violation];
+}
+
+impl Decision for EvaluationResult {
+    fn violations(&self) -> &[Violation] {
+        &self.violations
+    }
+}
+
+impl Decision for TokenEvaluationResult {
+    fn violations(&self) -> &[Violation] {
+        &self.decision.violations
+    }
 }
 
 #[derive(Debug, Error)]
@@ -185,6 +409,52 @@ pub enum EvaluationError {
 }
 
 impl Policy {
+    /// Evaluate an entrypoint, and log the decision
+    async fn evaluate<I, O>(
+        &mut self,
+        entrypoint: Entrypoint,
+        input: &I,
+    ) -> Result<O, EvaluationError>
+    where
+        I: Serialize + Sync + BuiltinEvaluate<Output = O>,
+        O: DeserializeOwned + Decision + Send,
+    {
+        let start = Instant::now();
+
+        let res = match &mut self.backend {
+            PolicyBackend::Opa {
+                store,
+                instance,
+                entrypoints,
+            } => {
+                let [res]: [O; 1] = instance
+                    .evaluate(store, entrypoint.path(entrypoints), input)
+                    .await?;
+                res
+            }
+            PolicyBackend::Builtin(rules) => input.evaluate_builtin(rules),
+        };
+
+        self.decision_logger
+            .record(entrypoint.name(), input, res.violations(), start.elapsed());
+
+        Ok(res)
+    }
+
+    fn request_input<'a>(&self, requester: &'a Requester) -> RequestInput<'a> {
+        let country = requester
+            .ip_address
+            .zip(self.geoip.as_deref())
+            .and_then(|(ip, geoip)| geoip.country(ip));
+
+        RequestInput {
+            ip_address: requester.ip_address,
+            user_agent: requester.user_agent.as_deref(),
+            country,
+            time: requester.time.into(),
+        }
+    }
+
     #[tracing::instrument(
         name = "policy.evaluate_email",
         skip_all,
@@ -199,10 +469,7 @@ impl Policy {
     ) -> Result<EvaluationResult, EvaluationError> {
         let input = EmailInput { email };
 
-        let [res]: [EvaluationResult; 1] = self
-            .instance
-            .evaluate(&mut self.store, &self.entrypoints.email, &input)
-            .await?;
+        let res: EvaluationResult = self.evaluate(Entrypoint::Email, &input).await?;
 
         Ok(res)
     }
@@ -214,10 +481,7 @@ impl Policy {
     ) -> Result<EvaluationResult, EvaluationError> {
         let input = PasswordInput { password };
 
-        let [res]: [EvaluationResult; 1] = self
-            .instance
-            .evaluate(&mut self.store, &self.entrypoints.password, &input)
-            .await?;
+        let res: EvaluationResult = self.evaluate(Entrypoint::Password, &input).await?;
 
         Ok(res)
     }
@@ -229,6 +493,7 @@ impl Policy {
             input.registration_method = "password",
             input.user.username = username,
             input.user.email = email,
+            input.request.ip_address = ?requester.ip_address,
         ),
         err,
     )]
@@ -237,17 +502,16 @@ impl Policy {
         username: &str,
         password: &str,
         email: &str,
+        requester: &Requester,
     ) -> Result<EvaluationResult, EvaluationError> {
         let input = RegisterInput::Password {
             username,
             password,
             email,
+            request: self.request_input(requester),
         };
 
-        let [res]: [EvaluationResult; 1] = self
-            .instance
-            .evaluate(&mut self.store, &self.entrypoints.register, &input)
-            .await?;
+        let res: EvaluationResult = self.evaluate(Entrypoint::Register, &input).await?;
 
         Ok(res)
     }
@@ -256,9 +520,11 @@ impl Policy {
         name = "policy.evaluate.upstream_oauth_register",
         skip_all,
         fields(
-            input.registration_method = "password",
+            input.registration_method = "upstream-oauth2",
             input.user.username = username,
             input.user.email = email,
+            input.provider.id = %provider.id,
+            input.request.ip_address = ?requester.ip_address,
         ),
         err,
     )]
@@ -266,13 +532,42 @@ impl Policy {
         &mut self,
         username: &str,
         email: Option<&str>,
+        provider: &UpstreamOAuthProvider,
+        requester: &Requester,
     ) -> Result<EvaluationResult, EvaluationError> {
-        let input = RegisterInput::UpstreamOAuth2 { username, email };
+        let input = RegisterInput::UpstreamOAuth2 {
+            username,
+            email,
+            provider_id: provider.id,
+            request: self.request_input(requester),
+        };
 
-        let [res]: [EvaluationResult; 1] = self
-            .instance
-            .evaluate(&mut self.store, &self.entrypoints.register, &input)
-            .await?;
+        let res: EvaluationResult = self.evaluate(Entrypoint::Register, &input).await?;
+
+        Ok(res)
+    }
+
+    #[tracing::instrument(
+        name = "policy.evaluate.guest_upgrade",
+        skip_all,
+        fields(
+            input.registration_method = "guest-upgrade",
+            input.user.username = username,
+            input.request.ip_address = ?requester.ip_address,
+        ),
+        err,
+    )]
+    pub async fn evaluate_guest_upgrade(
+        &mut self,
+        username: &str,
+        requester: &Requester,
+    ) -> Result<EvaluationResult, EvaluationError> {
+        let input = RegisterInput::GuestUpgrade {
+            username,
+            request: self.request_input(requester),
+        };
+
+        let res: EvaluationResult = self.evaluate(Entrypoint::Register, &input).await?;
 
         Ok(res)
     }
@@ -281,16 +576,15 @@ impl Policy {
     pub async fn evaluate_client_registration(
         &mut self,
         client_metadata: &VerifiedClientMetadata,
+        requester: &Requester,
     ) -> Result<EvaluationResult, EvaluationError> {
-        let input = ClientRegistrationInput { client_metadata };
-
-        let [res]: [EvaluationResult; 1] = self
-            .instance
-            .evaluate(
-                &mut self.store,
-                &self.entrypoints.client_registration,
-                &input,
-            )
+        let input = ClientRegistrationInput {
+            client_metadata,
+            request: self.request_input(requester),
+        };
+
+        let res: EvaluationResult = self
+            .evaluate(Entrypoint::ClientRegistration, &input)
             .await?;
 
         Ok(res)
@@ -304,6 +598,7 @@ impl Policy {
             input.scope = %authorization_grant.scope,
             input.client.id = %client.id,
             input.user.id = %user.id,
+            input.request.ip_address = ?requester.ip_address,
         ),
         err,
     )]
@@ -312,21 +607,18 @@ impl Policy {
         authorization_grant: &AuthorizationGrant,
         client: &Client,
         user: &User,
+        requester: &Requester,
     ) -> Result<EvaluationResult, EvaluationError> {
         let input = AuthorizationGrantInput {
             user: Some(user),
             client,
             scope: &authorization_grant.scope,
             grant_type: GrantType::AuthorizationCode,
+            request: self.request_input(requester),
         };
 
-        let [res]: [EvaluationResult; 1] = self
-            .instance
-            .evaluate(
-                &mut self.store,
-                &self.entrypoints.authorization_grant,
-                &input,
-            )
+        let res: EvaluationResult = self
+            .evaluate(Entrypoint::AuthorizationGrant, &input)
             .await?;
 
         Ok(res)
@@ -338,6 +630,7 @@ impl Policy {
         fields(
             input.scope = %scope,
             input.client.id = %client.id,
+            input.request.ip_address = ?requester.ip_address,
         ),
         err,
     )]
@@ -345,29 +638,128 @@ impl Policy {
         &mut self,
         scope: &Scope,
         client: &Client,
+        requester: &Requester,
     ) -> Result<EvaluationResult, EvaluationError> {
         let input = AuthorizationGrantInput {
             user: None,
             client,
             scope,
             grant_type: GrantType::ClientCredentials,
+            request: self.request_input(requester),
         };
 
-        let [res]: [EvaluationResult; 1] = self
-            .instance
-            .evaluate(
-                &mut self.store,
-                &self.entrypoints.authorization_grant,
-                &input,
-            )
+        let res: EvaluationResult = self
+            .evaluate(Entrypoint::AuthorizationGrant, &input)
             .await?;
 
         Ok(res)
     }
+
+    #[tracing::instrument(
+        name = "policy.evaluate.token",
+        skip_all,
+        fields(
+            input.grant_type = ?grant_type,
+            input.scope = %scope,
+            input.client.id = %client.id,
+            input.user.id = user.map(|u| tracing::field::display(u.id)),
+            input.request.ip_address = ?requester.ip_address,
+        ),
+        err,
+    )]
+    pub async fn evaluate_token(
+        &mut self,
+        grant_type: GrantType,
+        scope: &Scope,
+        client: &Client,
+        user: Option<&User>,
+        requester: &Requester,
+    ) -> Result<TokenDecision, EvaluationError> {
+        let input = TokenInput {
+            user,
+            client,
+            scope,
+            grant_type,
+            request: self.request_input(requester),
+        };
+
+        let res: TokenEvaluationResult = self.evaluate(Entrypoint::Token, &input).await?;
+
+        Ok(res.decision)
+    }
+
+    #[tracing::instrument(
+        name = "policy.evaluate.upstream_login",
+        skip_all,
+        fields(
+            input.provider.id = %provider.id,
+            input.subject = subject,
+            input.email = email,
+            input.username = username,
+            input.request.ip_address = ?requester.ip_address,
+        ),
+        err,
+    )]
+    pub async fn evaluate_upstream_login(
+        &mut self,
+        provider: &UpstreamOAuthProvider,
+        subject: &str,
+        claims: &HashMap<String, serde_json::Value>,
+        email: Option<&str>,
+        username: Option<&str>,
+        requester: &Requester,
+    ) -> Result<EvaluationResult, EvaluationError> {
+        let input = UpstreamLoginInput {
+            provider_id: provider.id,
+            subject,
+            email,
+            username,
+            claims,
+            request: self.request_input(requester),
+        };
+
+        let res: EvaluationResult = self.evaluate(Entrypoint::UpstreamLogin, &input).await?;
+
+        Ok(res)
+    }
+
+    #[tracing::instrument(
+        name = "policy.evaluate.claims",
+        skip_all,
+        fields(
+            input.user.id = %user.id,
+            input.client.id = %client.id,
+            input.scope = %scope,
+            input.request.ip_address = ?requester.ip_address,
+        ),
+        err,
+    )]
+    pub async fn evaluate_claims(
+        &mut self,
+        user: &User,
+        client: &Client,
+        scope: &Scope,
+        claims: &HashMap<String, serde_json::Value>,
+        requester: &Requester,
+    ) -> Result<EvaluationResult, EvaluationError> {
+        let input = ClaimsInput {
+            user,
+            client,
+            scope,
+            claims,
+            request: self.request_input(requester),
+        };
+
+        let res: EvaluationResult = self.evaluate(Entrypoint::Claims, &input).await?;
+
+        Ok(res)
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use chrono::{TimeZone, Utc};
+
     use super::*;
 
     #[tokio::test]
@@ -392,26 +784,248 @@ mod tests {
             authorization_grant: "authorization_grant/violation".to_owned(),
             email: "email/violation".to_owned(),
             password: "password/violation".to_owned(),
+            token: "token/decision".to_owned(),
+            upstream_login: "upstream_login/violation".to_owned(),
+            claims: "claims/violation".to_owned(),
         };
 
         let factory = PolicyFactory::load(file, data, entrypoints).await.unwrap();
 
         let mut policy = factory.instantiate().await.unwrap();
 
+        let requester = Requester::new(Utc.with_ymd_and_hms(2023, 10, 30, 12, 0, 0).unwrap());
+
+        let res = policy
+            .evaluate_register("hello", "hunter2", "hello@example.com", &requester)
+            .await
+            .unwrap();
+        assert!(!res.valid());
+
+        let res = policy
+            .evaluate_register("hello", "hunter2", "hello@foo.element.io", &requester)
+            .await
+            .unwrap();
+        assert!(res.valid());
+
+        let res = policy
+            .evaluate_register("hello", "hunter2", "hello@staging.element.io", &requester)
+            .await
+            .unwrap();
+        assert!(!res.valid());
+    }
+
+    #[tokio::test]
+    async fn test_register_request_context() {
+        let data = serde_json::json!({
+            "registration": {
+                "banned_ip_ranges": ["10.0.0.0/8"],
+            },
+        });
+
+        #[allow(clippy::disallowed_types)]
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("..")
+            .join("..")
+            .join("policies")
+            .join("policy.wasm");
+
+        let file = tokio::fs::File::open(path).await.unwrap();
+
+        let entrypoints = Entrypoints {
+            register: "register/violation".to_owned(),
+            client_registration: "client_registration/violation".to_owned(),
+            authorization_grant: "authorization_grant/violation".to_owned(),
+            email: "email/violation".to_owned(),
+            password: "password/violation".to_owned(),
+            token: "token/decision".to_owned(),
+            upstream_login: "upstream_login/violation".to_owned(),
+            claims: "claims/violation".to_owned(),
+        };
+
+        let factory = PolicyFactory::load(file, data, entrypoints).await.unwrap();
+
+        let mut policy = factory.instantiate().await.unwrap();
+
+        let now = Utc.with_ymd_and_hms(2023, 10, 30, 12, 0, 0).unwrap();
+
+        // The IP address of the requester is passed to the policy
+        let requester = Requester::new(now).with_ip_address(Some([10, 1, 2, 3].into()));
+        let res = policy
+            .evaluate_register("hello", "hunter2", "hello@example.com", &requester)
+            .await
+            .unwrap();
+        assert!(!res.valid());
+        assert!(res.has_code("ip-banned"));
+
+        // This also applies to guests upgrading their account
+        let res = policy
+            .evaluate_guest_upgrade("guest-abcdefghij", &requester)
+            .await
+            .unwrap();
+        assert!(res.has_code("ip-banned"));
+
+        let requester = Requester::new(now).with_ip_address(Some([192, 168, 1, 1].into()));
+        let res = policy
+            .evaluate_register("hello", "hunter2", "hello@example.com", &requester)
+            .await
+            .unwrap();
+        assert!(res.valid());
+
+        // Without an IP address, the rule doesn't apply
+        let requester = Requester::new(now);
+        let res = policy
+            .evaluate_register("hello", "hunter2", "hello@example.com", &requester)
+            .await
+            .unwrap();
+        assert!(res.valid());
+    }
+
+    #[tokio::test]
+    async fn test_request_input() {
+        let factory = PolicyFactory::builtin(BuiltinRules::default());
+        let policy = factory.instantiate().await.unwrap();
+
+        // A Sunday evening
+        let now = Utc.with_ymd_and_hms(2023, 10, 29, 23, 15, 0).unwrap();
+
+        let requester = Requester::new(now)
+            .with_ip_address(Some([10, 1, 2, 3].into()))
+            .with_user_agent(Some("Mozilla/5.0".to_owned()));
+        let input = serde_json::to_value(policy.request_input(&requester)).unwrap();
+        assert_eq!(
+            input,
+            serde_json::json!({
+                "ip_address": "10.1.2.3",
+                "user_agent": "Mozilla/5.0",
+                "time": {
+                    "now": "2023-10-29T23:15:00Z",
+                    "hour": 23,
+                    "minute": 15,
+                    "weekday": 7,
+                },
+            })
+        );
+
+        // Unknown fields are left out, and there is no country without a GeoIP
+        // database
+        let requester = Requester::new(now);
+        let input = serde_json::to_value(policy.request_input(&requester)).unwrap();
+        assert_eq!(
+            input,
+            serde_json::json!({
+                "time": {
+                    "now": "2023-10-29T23:15:00Z",
+                    "hour": 23,
+                    "minute": 15,
+                    "weekday": 7,
+                },
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_builtin_register() {
+        let rules = BuiltinRules {
+            emails: builtin::EmailRules {
+                allowed_domains: vec!["element.io".to_owned(), "*.element.io".to_owned()],
+                banned_domains: vec!["staging.element.io".to_owned()],
+            },
+            ..BuiltinRules::default()
+        };
+
+        let factory = PolicyFactory::builtin(rules);
+        let mut policy = factory.instantiate().await.unwrap();
+
+        // The built-in policy can't be reloaded
+        assert!(matches!(
+            factory.reload(&b""[..]).await,
+            Err(LoadError::Builtin)
+        ));
+
+        let requester = Requester::new(Utc.with_ymd_and_hms(2023, 10, 30, 12, 0, 0).unwrap());
+
         let res = policy
-            .evaluate_register("hello", "hunter2", "hello@example.com")
+            .evaluate_register("hello", "hunter2", "hello@example.com", &requester)
             .await
             .unwrap();
         assert!(!res.valid());
 
         let res = policy
-            .evaluate_register("hello", "hunter2", "hello@foo.element.io")
+            .evaluate_register("hello", "hunter2", "hello@foo.element.io", &requester)
+            .await
+            .unwrap();
+        assert!(res.valid());
+
+        let res = policy
+            .evaluate_register("admin", "hunter2", "hello@foo.element.io", &requester)
+            .await
+            .unwrap();
+        assert!(!res.valid());
+
+        // The rules on usernames don't apply to the ones picked for guests
+        let res = policy
+            .evaluate_guest_upgrade("guest-abcdefghij", &requester)
+            .await
+            .unwrap();
+        assert!(res.valid());
+    }
+
+    #[tokio::test]
+    async fn test_reload() {
+        let data = serde_json::json!({
+            "allowed_domains": ["element.io"],
+        });
+
+        #[allow(clippy::disallowed_types)]
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("..")
+            .join("..")
+            .join("policies")
+            .join("policy.wasm");
+
+        let file = tokio::fs::File::open(&path).await.unwrap();
+
+        let entrypoints = Entrypoints {
+            register: "register/violation".to_owned(),
+            client_registration: "client_registration/violation".to_owned(),
+            authorization_grant: "authorization_grant/violation".to_owned(),
+            email: "email/violation".to_owned(),
+            password: "password/violation".to_owned(),
+            token: "token/decision".to_owned(),
+            upstream_login: "upstream_login/violation".to_owned(),
+            claims: "claims/violation".to_owned(),
+        };
+
+        let factory = PolicyFactory::load(file, data, entrypoints).await.unwrap();
+        let mut policy = factory.instantiate().await.unwrap();
+
+        let requester = Requester::new(Utc.with_ymd_and_hms(2023, 10, 30, 12, 0, 0).unwrap());
+
+        // A module which doesn't compile is refused, and the current one is kept
+        let res = factory.reload(&b"not a wasm module"[..]).await;
+        assert!(matches!(res, Err(LoadError::Compilation(_))));
+
+        let mut new_policy = factory.instantiate().await.unwrap();
+        let res = new_policy
+            .evaluate_register("hello", "hunter2", "hello@example.com", &requester)
+            .await
+            .unwrap();
+        assert!(!res.valid());
+
+        // A valid module is swapped in
+        let file = tokio::fs::File::open(&path).await.unwrap();
+        factory.reload(file).await.unwrap();
+
+        let mut new_policy = factory.instantiate().await.unwrap();
+        let res = new_policy
+            .evaluate_register("hello", "hunter2", "hello@element.io", &requester)
             .await
             .unwrap();
         assert!(res.valid());
 
+        // Policies instantiated before the reload still work
         let res = policy
-            .evaluate_register("hello", "hunter2", "hello@staging.element.io")
+            .evaluate_register("hello", "hunter2", "hello@example.com", &requester)
             .await
             .unwrap();
         assert!(!res.valid());
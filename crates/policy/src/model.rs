@@ -17,9 +17,13 @@
 //! This is useful to generate JSON schemas for each input type, which can then
 //! be type-checked by Open Policy Agent.
 
+use std::{collections::HashMap, net::IpAddr};
+
+use chrono::{DateTime, Datelike, Timelike, Utc};
 use mas_data_model::{Client, User};
 use oauth2_types::{registration::VerifiedClientMetadata, scope::Scope};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use ulid::Ulid;
 
 /// A single violation of a policy.
 #[derive(Deserialize, Debug)]
@@ -27,6 +31,7 @@ use serde::{Deserialize, Serialize};
 pub struct Violation {
     pub msg: String,
     pub field: Option<String>,
+    pub code: Option<String>,
 }
 
 /// The result of a policy evaluation.
@@ -57,6 +62,145 @@ impl EvaluationResult {
     pub fn valid(&self) -> bool {
         self.violations.is_empty()
     }
+
+    /// Returns true if one of the violations has the given code.
+    #[must_use]
+    pub fn has_code(&self, code: &str) -> bool {
+        self.violations
+            .iter()
+            .any(|violation| violation.code.as_deref() == Some(code))
+    }
+}
+
+/// The result of the token policy evaluation.
+#[derive(Deserialize, Debug)]
+pub struct TokenEvaluationResult {
+    #[serde(rename = "result")]
+    pub decision: TokenDecision,
+}
+
+/// The decision of the token policy: whether the token can be issued, and with
+/// which scope.
+#[derive(Deserialize, Debug)]
+pub struct TokenDecision {
+    /// The reasons why the token can't be issued, if any
+    pub violations: Vec<Violation>,
+
+    /// The scope the token should be issued with, which is a subset of the
+    /// requested scope
+    #[serde(deserialize_with = "deserialize_scope_tokens")]
+    pub scope: Scope,
+}
+
+/// Deserialize a [`Scope`] from a list of scope tokens, which can be empty
+fn deserialize_scope_tokens<'de, D>(deserializer: D) -> Result<Scope, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let tokens: Vec<String> = Deserialize::deserialize(deserializer)?;
+    tokens
+        .iter()
+        .map(|token| token.parse())
+        .collect::<Result<Scope, _>>()
+        .map_err(serde::de::Error::custom)
+}
+
+impl TokenDecision {
+    /// Returns true if the token can be issued.
+    #[must_use]
+    pub fn valid(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+/// Information about the client which made the request being evaluated.
+#[derive(Debug, Clone)]
+pub struct Requester {
+    /// IP address of the client, if known
+    pub ip_address: Option<IpAddr>,
+
+    /// User agent of the client, if known
+    pub user_agent: Option<String>,
+
+    /// When the request was made
+    pub time: DateTime<Utc>,
+}
+
+impl Requester {
+    /// Create a new requester, for a request made at the given time
+    #[must_use]
+    pub fn new(time: DateTime<Utc>) -> Self {
+        Self {
+            ip_address: None,
+            user_agent: None,
+            time,
+        }
+    }
+
+    /// Set the IP address of the client
+    #[must_use]
+    pub fn with_ip_address(mut self, ip_address: Option<IpAddr>) -> Self {
+        self.ip_address = ip_address;
+        self
+    }
+
+    /// Set the user agent of the client
+    #[must_use]
+    pub fn with_user_agent(mut self, user_agent: Option<String>) -> Self {
+        self.user_agent = user_agent;
+        self
+    }
+}
+
+/// The time at which the request is evaluated, in UTC.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "jsonschema", derive(schemars::JsonSchema))]
+pub struct TimeInput {
+    /// The current time, as an RFC 3339 timestamp
+    #[cfg_attr(feature = "jsonschema", schemars(with = "String"))]
+    pub now: DateTime<Utc>,
+
+    /// Hour of the day, from 0 to 23
+    pub hour: u32,
+
+    /// Minute of the hour, from 0 to 59
+    pub minute: u32,
+
+    /// Day of the week, from 1 (Monday) to 7 (Sunday)
+    pub weekday: u32,
+}
+
+impl From<DateTime<Utc>> for TimeInput {
+    fn from(now: DateTime<Utc>) -> Self {
+        Self {
+            now,
+            hour: now.hour(),
+            minute: now.minute(),
+            weekday: now.weekday().number_from_monday(),
+        }
+    }
+}
+
+/// Context of the request which triggered the policy evaluation.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "jsonschema", derive(schemars::JsonSchema))]
+pub struct RequestInput<'a> {
+    /// IP address of the client
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip_address: Option<IpAddr>,
+
+    /// User agent of the client
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<&'a str>,
+
+    /// ISO 3166-1 alpha-2 code of the country of the client, looked up from
+    /// its IP address in the GeoIP database
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+
+    pub time: TimeInput,
 }
 
 /// Input for the user registration policy.
@@ -69,6 +213,7 @@ pub enum RegisterInput<'a> {
         username: &'a str,
         password: &'a str,
         email: &'a str,
+        request: RequestInput<'a>,
     },
 
     #[serde(rename = "upstream-oauth2")]
@@ -77,6 +222,19 @@ pub enum RegisterInput<'a> {
 
         #[serde(skip_serializing_if = "Option::is_none")]
         email: Option<&'a str>,
+
+        #[cfg_attr(feature = "jsonschema", schemars(with = "String"))]
+        provider_id: Ulid,
+
+        request: RequestInput<'a>,
+    },
+
+    /// A guest account turned into a full account. The username was picked by
+    /// the server, so the rules on usernames don't apply.
+    #[serde(rename = "guest-upgrade")]
+    GuestUpgrade {
+        username: &'a str,
+        request: RequestInput<'a>,
     },
 }
 
@@ -90,14 +248,18 @@ pub struct ClientRegistrationInput<'a> {
         schemars(with = "std::collections::HashMap<String, serde_json::Value>")
     )]
     pub client_metadata: &'a VerifiedClientMetadata,
+
+    pub request: RequestInput<'a>,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[serde(rename_all = "snake_case")]
 #[cfg_attr(feature = "jsonschema", derive(schemars::JsonSchema))]
 pub enum GrantType {
     AuthorizationCode,
     ClientCredentials,
+    RefreshToken,
+    JwtBearer,
 }
 
 /// Input for the authorization grant policy.
@@ -121,6 +283,87 @@ pub struct AuthorizationGrantInput<'a> {
     pub scope: &'a Scope,
 
     pub grant_type: GrantType,
+
+    pub request: RequestInput<'a>,
+}
+
+/// Input for the token issuance policy.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "jsonschema", derive(schemars::JsonSchema))]
+pub struct TokenInput<'a> {
+    #[cfg_attr(
+        feature = "jsonschema",
+        schemars(with = "Option<std::collections::HashMap<String, serde_json::Value>>")
+    )]
+    pub user: Option<&'a User>,
+
+    #[cfg_attr(
+        feature = "jsonschema",
+        schemars(with = "std::collections::HashMap<String, serde_json::Value>")
+    )]
+    pub client: &'a Client,
+
+    #[cfg_attr(feature = "jsonschema", schemars(with = "String"))]
+    pub scope: &'a Scope,
+
+    pub grant_type: GrantType,
+
+    pub request: RequestInput<'a>,
+}
+
+/// Input for the upstream login policy, evaluated when a user comes back from
+/// an upstream provider, before the login is linked to an account.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "jsonschema", derive(schemars::JsonSchema))]
+pub struct UpstreamLoginInput<'a> {
+    #[cfg_attr(feature = "jsonschema", schemars(with = "String"))]
+    pub provider_id: Ulid,
+
+    /// The subject of the user on the upstream provider
+    pub subject: &'a str,
+
+    /// The email of the user, if it is imported from the upstream provider
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<&'a str>,
+
+    /// The username of the user, if it is imported from the upstream provider
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<&'a str>,
+
+    /// All the claims of the ID token
+    pub claims: &'a HashMap<String, serde_json::Value>,
+
+    pub request: RequestInput<'a>,
+}
+
+/// Input for the claims policy, evaluated when user attributes are mapped to
+/// the claims of an ID token or userinfo response.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "jsonschema", derive(schemars::JsonSchema))]
+pub struct ClaimsInput<'a> {
+    #[cfg_attr(
+        feature = "jsonschema",
+        schemars(with = "std::collections::HashMap<String, serde_json::Value>")
+    )]
+    pub user: &'a User,
+
+    #[cfg_attr(
+        feature = "jsonschema",
+        schemars(with = "std::collections::HashMap<String, serde_json::Value>")
+    )]
+    pub client: &'a Client,
+
+    #[cfg_attr(feature = "jsonschema", schemars(with = "String"))]
+    pub scope: &'a Scope,
+
+    /// The claims mapped from the attributes of the user. Each violation with
+    /// a `field` vetoes the claim it names
+    pub claims: &'a HashMap<String, serde_json::Value>,
+
+    pub request: RequestInput<'a>,
 }
 
 /// Input for the email add policy.
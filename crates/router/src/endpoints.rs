@@ -91,6 +91,14 @@ impl SimpleRoute for Webfinger {
     const PATH: &'static str = "/.well-known/webfinger";
 }
 
+/// `GET /.well-known/matrix/client`
+#[derive(Default, Debug, Clone)]
+pub struct MatrixClientWellKnown;
+
+impl SimpleRoute for MatrixClientWellKnown {
+    const PATH: &'static str = "/.well-known/matrix/client";
+}
+
 /// `GET /.well-known/change-password`
 pub struct ChangePasswordDiscovery;
 
@@ -245,6 +253,14 @@ impl SimpleRoute for Logout {
     const PATH: &'static str = "/logout";
 }
 
+/// `POST /language`
+#[derive(Default, Debug, Clone)]
+pub struct ChangeLanguage;
+
+impl SimpleRoute for ChangeLanguage {
+    const PATH: &'static str = "/language";
+}
+
 /// `GET|POST /reauth`
 #[derive(Default, Debug, Clone)]
 pub struct Reauth {
@@ -298,6 +314,45 @@ impl From<Option<PostAuthAction>> for Reauth {
     }
 }
 
+/// `GET|POST /complete-profile`
+#[derive(Default, Debug, Clone)]
+pub struct CompleteProfile {
+    post_auth_action: Option<PostAuthAction>,
+}
+
+impl CompleteProfile {
+    #[must_use]
+    pub fn and_then(action: PostAuthAction) -> Self {
+        Self {
+            post_auth_action: Some(action),
+        }
+    }
+
+    /// Get a reference to the post auth action.
+    #[must_use]
+    pub fn post_auth_action(&self) -> Option<&PostAuthAction> {
+        self.post_auth_action.as_ref()
+    }
+}
+
+impl Route for CompleteProfile {
+    type Query = PostAuthAction;
+
+    fn route() -> &'static str {
+        "/complete-profile"
+    }
+
+    fn query(&self) -> Option<&Self::Query> {
+        self.post_auth_action.as_ref()
+    }
+}
+
+impl From<Option<PostAuthAction>> for CompleteProfile {
+    fn from(post_auth_action: Option<PostAuthAction>) -> Self {
+        Self { post_auth_action }
+    }
+}
+
 /// `GET|POST /register`
 #[derive(Default, Debug, Clone)]
 pub struct Register {
@@ -433,8 +488,14 @@ impl AccountAddEmail {
 pub enum AccountAction {
     Profile,
     SessionsList,
-    SessionView { device_id: String },
-    SessionEnd { device_id: String },
+    SessionView {
+        device_id: String,
+    },
+    SessionEnd {
+        device_id: String,
+    },
+    #[serde(rename = "org.matrix.cross_signing_reset")]
+    CrossSigningReset,
 }
 
 /// `GET /account/`
@@ -471,6 +532,70 @@ impl SimpleRoute for AccountPassword {
     const PATH: &'static str = "/change-password";
 }
 
+/// `GET|POST /impersonate`
+#[derive(Default, Debug, Clone)]
+pub struct Impersonate;
+
+impl SimpleRoute for Impersonate {
+    const PATH: &'static str = "/impersonate";
+}
+
+/// `GET|POST /reset-cross-signing`
+#[derive(Default, Debug, Clone)]
+pub struct ResetCrossSigning;
+
+impl SimpleRoute for ResetCrossSigning {
+    const PATH: &'static str = "/reset-cross-signing";
+}
+
+/// `GET|POST /email-change/revert/:token`
+#[derive(Debug, Clone)]
+pub struct EmailChangeRevert {
+    token: String,
+}
+
+impl EmailChangeRevert {
+    #[must_use]
+    pub fn new(token: String) -> Self {
+        Self { token }
+    }
+}
+
+impl Route for EmailChangeRevert {
+    type Query = ();
+    fn route() -> &'static str {
+        "/email-change/revert/:token"
+    }
+
+    fn path(&self) -> std::borrow::Cow<'static, str> {
+        format!("/email-change/revert/{}", self.token).into()
+    }
+}
+
+/// `GET|POST /email-change/confirm/:token`
+#[derive(Debug, Clone)]
+pub struct EmailChangeConfirm {
+    token: String,
+}
+
+impl EmailChangeConfirm {
+    #[must_use]
+    pub fn new(token: String) -> Self {
+        Self { token }
+    }
+}
+
+impl Route for EmailChangeConfirm {
+    type Query = ();
+    fn route() -> &'static str {
+        "/email-change/confirm/:token"
+    }
+
+    fn path(&self) -> std::borrow::Cow<'static, str> {
+        format!("/email-change/confirm/{}", self.token).into()
+    }
+}
+
 /// `GET /authorize/:grant_id`
 #[derive(Debug, Clone)]
 pub struct ContinueAuthorizationGrant(pub Ulid);
@@ -508,6 +633,13 @@ impl SimpleRoute for CompatLogin {
     const PATH: &'static str = "/_matrix/client/:version/login";
 }
 
+/// `POST /_matrix/client/v3/register`
+pub struct CompatRegister;
+
+impl SimpleRoute for CompatRegister {
+    const PATH: &'static str = "/_matrix/client/:version/register";
+}
+
 /// `POST /_matrix/client/v3/logout`
 pub struct CompatLogout;
 
@@ -628,29 +760,214 @@ impl Route for UpstreamOAuth2Authorize {
     }
 }
 
+/// `GET /upstream/revalidate/:id`
+///
+/// Sends a user who is already logged in through the provider to it again,
+/// with `prompt=none`, to check that they still have a session with it.
+pub struct UpstreamOAuth2Revalidate {
+    id: Ulid,
+    post_auth_action: Option<PostAuthAction>,
+}
+
+impl UpstreamOAuth2Revalidate {
+    #[must_use]
+    pub const fn new(id: Ulid) -> Self {
+        Self {
+            id,
+            post_auth_action: None,
+        }
+    }
+
+    #[must_use]
+    pub fn and_then(mut self, action: PostAuthAction) -> Self {
+        self.post_auth_action = Some(action);
+        self
+    }
+}
+
+impl Route for UpstreamOAuth2Revalidate {
+    type Query = PostAuthAction;
+    fn route() -> &'static str {
+        "/upstream/revalidate/:provider_id"
+    }
+
+    fn path(&self) -> std::borrow::Cow<'static, str> {
+        format!("/upstream/revalidate/{}", self.id).into()
+    }
+
+    fn query(&self) -> Option<&Self::Query> {
+        self.post_auth_action.as_ref()
+    }
+}
+
+/// The query of [`UpstreamOAuth2Upgrade`]
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct UpstreamOAuth2UpgradeQuery {
+    /// The space-separated scopes the user should grant
+    pub scope: String,
+
+    #[serde(flatten)]
+    pub post_auth_action: Option<PostAuthAction>,
+}
+
+/// `GET /upstream/upgrade/:id`
+///
+/// Sends a user who is linked to the provider to it again, to grant the
+/// scopes from the query they didn't grant yet.
+pub struct UpstreamOAuth2Upgrade {
+    id: Ulid,
+    query: UpstreamOAuth2UpgradeQuery,
+}
+
+impl UpstreamOAuth2Upgrade {
+    #[must_use]
+    pub fn new(id: Ulid, scope: String) -> Self {
+        Self {
+            id,
+            query: UpstreamOAuth2UpgradeQuery {
+                scope,
+                post_auth_action: None,
+            },
+        }
+    }
+
+    #[must_use]
+    pub fn and_then(mut self, action: PostAuthAction) -> Self {
+        self.query.post_auth_action = Some(action);
+        self
+    }
+}
+
+impl Route for UpstreamOAuth2Upgrade {
+    type Query = UpstreamOAuth2UpgradeQuery;
+    fn route() -> &'static str {
+        "/upstream/upgrade/:provider_id"
+    }
+
+    fn path(&self) -> std::borrow::Cow<'static, str> {
+        format!("/upstream/upgrade/{}", self.id).into()
+    }
+
+    fn query(&self) -> Option<&Self::Query> {
+        Some(&self.query)
+    }
+}
+
 /// `GET /upstream/callback/:id`
+///
+/// The provider is identified either by its ID or by its slug.
 pub struct UpstreamOAuth2Callback {
-    id: Ulid,
+    provider: String,
 }
 
 impl UpstreamOAuth2Callback {
     #[must_use]
-    pub const fn new(id: Ulid) -> Self {
-        Self { id }
+    pub fn new(id: Ulid) -> Self {
+        Self {
+            provider: id.to_string(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_slug(slug: &str) -> Self {
+        Self {
+            provider: slug.to_owned(),
+        }
     }
 }
 
 impl Route for UpstreamOAuth2Callback {
     type Query = ();
     fn route() -> &'static str {
-        "/upstream/callback/:provider_id"
+        "/upstream/callback/:provider"
     }
 
     fn path(&self) -> std::borrow::Cow<'static, str> {
-        format!("/upstream/callback/{}", self.id).into()
+        format!("/upstream/callback/{}", self.provider).into()
     }
 }
 
+/// `POST /upstream/backchannel-logout/:id`
+///
+/// The OpenID Connect back-channel logout endpoint of a provider, identified
+/// either by its ID or by its slug.
+pub struct UpstreamOAuth2BackchannelLogout {
+    provider: String,
+}
+
+impl UpstreamOAuth2BackchannelLogout {
+    #[must_use]
+    pub fn new(id: Ulid) -> Self {
+        Self {
+            provider: id.to_string(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_slug(slug: &str) -> Self {
+        Self {
+            provider: slug.to_owned(),
+        }
+    }
+}
+
+impl Route for UpstreamOAuth2BackchannelLogout {
+    type Query = ();
+    fn route() -> &'static str {
+        "/upstream/backchannel-logout/:provider"
+    }
+
+    fn path(&self) -> std::borrow::Cow<'static, str> {
+        format!("/upstream/backchannel-logout/{}", self.provider).into()
+    }
+}
+
+/// `GET /upstream/frontchannel-logout/:id`
+///
+/// The OpenID Connect front-channel logout endpoint of a provider, identified
+/// either by its ID or by its slug.
+pub struct UpstreamOAuth2FrontchannelLogout {
+    provider: String,
+}
+
+impl UpstreamOAuth2FrontchannelLogout {
+    #[must_use]
+    pub fn new(id: Ulid) -> Self {
+        Self {
+            provider: id.to_string(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_slug(slug: &str) -> Self {
+        Self {
+            provider: slug.to_owned(),
+        }
+    }
+}
+
+impl Route for UpstreamOAuth2FrontchannelLogout {
+    type Query = ();
+    fn route() -> &'static str {
+        "/upstream/frontchannel-logout/:provider"
+    }
+
+    fn path(&self) -> std::borrow::Cow<'static, str> {
+        format!("/upstream/frontchannel-logout/{}", self.provider).into()
+    }
+}
+
+/// `GET /upstream/keys.json`
+///
+/// The public keys which upstream providers can use to encrypt the ID tokens
+/// they issue
+#[derive(Default, Debug, Clone)]
+pub struct UpstreamOAuth2Keys;
+
+impl SimpleRoute for UpstreamOAuth2Keys {
+    const PATH: &'static str = "/upstream/keys.json";
+}
+
 /// `GET /upstream/link/:id`
 pub struct UpstreamOAuth2Link {
     id: Ulid,
@@ -674,6 +991,106 @@ impl Route for UpstreamOAuth2Link {
     }
 }
 
+/// `GET /upstream/saml/:provider/metadata`
+///
+/// The SAML metadata of this service for a provider, identified either by its
+/// ID or by its slug.
+pub struct UpstreamSamlMetadata {
+    provider: String,
+}
+
+impl UpstreamSamlMetadata {
+    #[must_use]
+    pub fn new(id: Ulid) -> Self {
+        Self {
+            provider: id.to_string(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_slug(slug: &str) -> Self {
+        Self {
+            provider: slug.to_owned(),
+        }
+    }
+}
+
+impl Route for UpstreamSamlMetadata {
+    type Query = ();
+    fn route() -> &'static str {
+        "/upstream/saml/:provider/metadata"
+    }
+
+    fn path(&self) -> std::borrow::Cow<'static, str> {
+        format!("/upstream/saml/{}/metadata", self.provider).into()
+    }
+}
+
+/// `POST /upstream/saml/:provider/acs`
+///
+/// The assertion consumer service of a SAML provider, identified either by its
+/// ID or by its slug.
+pub struct UpstreamSamlAcs {
+    provider: String,
+}
+
+impl UpstreamSamlAcs {
+    #[must_use]
+    pub fn new(id: Ulid) -> Self {
+        Self {
+            provider: id.to_string(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_slug(slug: &str) -> Self {
+        Self {
+            provider: slug.to_owned(),
+        }
+    }
+}
+
+impl Route for UpstreamSamlAcs {
+    type Query = ();
+    fn route() -> &'static str {
+        "/upstream/saml/:provider/acs"
+    }
+
+    fn path(&self) -> std::borrow::Cow<'static, str> {
+        format!("/upstream/saml/{}/acs", self.provider).into()
+    }
+}
+
+/// `GET /api/upstream/tokens/:provider/:username`
+///
+/// A valid access token issued by a provider, identified either by its ID or by
+/// its slug, to a user, for trusted services to call its APIs on their behalf.
+pub struct UpstreamOAuth2Token {
+    provider: String,
+    username: String,
+}
+
+impl UpstreamOAuth2Token {
+    #[must_use]
+    pub fn new(provider: &str, username: &str) -> Self {
+        Self {
+            provider: provider.to_owned(),
+            username: username.to_owned(),
+        }
+    }
+}
+
+impl Route for UpstreamOAuth2Token {
+    type Query = ();
+    fn route() -> &'static str {
+        "/api/upstream/tokens/:provider/:username"
+    }
+
+    fn path(&self) -> std::borrow::Cow<'static, str> {
+        format!("/api/upstream/tokens/{}/{}", self.provider, self.username).into()
+    }
+}
+
 /// `GET /assets`
 pub struct StaticAsset {
     path: String,
@@ -697,6 +1114,42 @@ impl Route for StaticAsset {
     }
 }
 
+/// The version of the theme stylesheet, used to bust caches
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct ThemeStylesheetQuery {
+    #[serde(rename = "v", default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+/// `GET /theme.css`
+#[derive(Default, Debug, Clone)]
+pub struct ThemeStylesheet {
+    query: ThemeStylesheetQuery,
+}
+
+impl ThemeStylesheet {
+    #[must_use]
+    pub fn new(version: String) -> Self {
+        Self {
+            query: ThemeStylesheetQuery {
+                version: Some(version),
+            },
+        }
+    }
+}
+
+impl Route for ThemeStylesheet {
+    type Query = ThemeStylesheetQuery;
+
+    fn route() -> &'static str {
+        "/theme.css"
+    }
+
+    fn query(&self) -> Option<&Self::Query> {
+        Some(&self.query)
+    }
+}
+
 /// `GET|POST /graphql`
 pub struct GraphQL;
 
@@ -78,12 +78,17 @@ impl UrlBuilder {
 
     /// Create a new [`UrlBuilder`] from a base URL
     ///
+    /// The base URL may have a path, in which case all the URLs are built
+    /// relative to it, as if the service was mounted under that path. A
+    /// trailing slash is added to it if it is missing, but the issuer it
+    /// defaults to is kept as-is.
+    ///
     /// # Panics
     ///
     /// Panics if the base URL contains a fragment, a query, credentials or
     /// isn't HTTP/HTTPS;
     #[must_use]
-    pub fn new(base: Url, issuer: Option<Url>, assets_base: Option<String>) -> Self {
+    pub fn new(mut base: Url, issuer: Option<Url>, assets_base: Option<String>) -> Self {
         assert!(
             base.scheme() == "http" || base.scheme() == "https",
             "base URL must be HTTP/HTTPS"
@@ -102,6 +107,14 @@ impl UrlBuilder {
         );
 
         let issuer = issuer.unwrap_or_else(|| base.clone());
+
+        // Joining a relative path to a base URL replaces its last segment if it
+        // doesn't end with a slash
+        if !base.path().ends_with('/') {
+            let path = format!("{}/", base.path());
+            base.set_path(&path);
+        }
+
         let prefix = base.path().trim_end_matches('/').to_owned();
         let assets_base = assets_base.unwrap_or_else(|| format!("{prefix}/assets/"));
         Self {
@@ -121,7 +134,15 @@ impl UrlBuilder {
     /// OIDC discovery document URL
     #[must_use]
     pub fn oidc_discovery(&self) -> Url {
-        crate::endpoints::OidcConfiguration.absolute_url(&self.issuer)
+        // The discovery document lives under the issuer path, even if the issuer
+        // itself doesn't have a trailing slash
+        let mut base = self.issuer.clone();
+        if !base.path().ends_with('/') {
+            let path = format!("{}/", base.path());
+            base.set_path(&path);
+        }
+
+        crate::endpoints::OidcConfiguration.absolute_url(&base)
     }
 
     /// OAuth 2.0 authorization endpoint
@@ -178,16 +199,48 @@ impl UrlBuilder {
         &self.assets_base
     }
 
+    /// Account management URI, as defined by MSC2965
+    #[must_use]
+    pub fn account_management_uri(&self) -> Url {
+        self.absolute_url_for(&crate::endpoints::Account::default())
+    }
+
     /// GraphQL endpoint
     #[must_use]
     pub fn graphql_endpoint(&self) -> Url {
         self.absolute_url_for(&crate::endpoints::GraphQL)
     }
 
-    /// Upstream redirect URI
+    /// Upstream redirect URI, using the slug of the provider if it has one
+    #[must_use]
+    pub fn upstream_oauth_callback(&self, id: Ulid, slug: Option<&str>) -> Url {
+        let route = match slug {
+            Some(slug) => crate::endpoints::UpstreamOAuth2Callback::with_slug(slug),
+            None => crate::endpoints::UpstreamOAuth2Callback::new(id),
+        };
+        self.absolute_url_for(&route)
+    }
+
+    /// SAML metadata of this service for a provider, using the slug of the
+    /// provider if it has one
+    #[must_use]
+    pub fn upstream_saml_metadata(&self, id: Ulid, slug: Option<&str>) -> Url {
+        let route = match slug {
+            Some(slug) => crate::endpoints::UpstreamSamlMetadata::with_slug(slug),
+            None => crate::endpoints::UpstreamSamlMetadata::new(id),
+        };
+        self.absolute_url_for(&route)
+    }
+
+    /// SAML assertion consumer service URI, using the slug of the provider if
+    /// it has one
     #[must_use]
-    pub fn upstream_oauth_callback(&self, id: Ulid) -> Url {
-        self.absolute_url_for(&crate::endpoints::UpstreamOAuth2Callback::new(id))
+    pub fn upstream_saml_acs(&self, id: Ulid, slug: Option<&str>) -> Url {
+        let route = match slug {
+            Some(slug) => crate::endpoints::UpstreamSamlAcs::with_slug(slug),
+            None => crate::endpoints::UpstreamSamlAcs::new(id),
+        };
+        self.absolute_url_for(&route)
     }
 
     /// Upstream authorize URI
@@ -195,6 +248,32 @@ impl UrlBuilder {
     pub fn upstream_oauth_authorize(&self, id: Ulid) -> Url {
         self.absolute_url_for(&crate::endpoints::UpstreamOAuth2Authorize::new(id))
     }
+
+    /// URI to send a user to, for them to grant additional scopes to the
+    /// upstream provider
+    #[must_use]
+    pub fn upstream_oauth_upgrade(&self, id: Ulid, scope: String) -> Url {
+        self.absolute_url_for(&crate::endpoints::UpstreamOAuth2Upgrade::new(id, scope))
+    }
+
+    /// URI used to confirm a change of primary email address from the old
+    /// address
+    #[must_use]
+    pub fn email_change_confirm(&self, token: String) -> Url {
+        self.absolute_url_for(&crate::endpoints::EmailChangeConfirm::new(token))
+    }
+
+    /// URI used to revert a change of primary email address
+    #[must_use]
+    pub fn email_change_revert(&self, token: String) -> Url {
+        self.absolute_url_for(&crate::endpoints::EmailChangeRevert::new(token))
+    }
+
+    /// URI used to continue an authorization grant
+    #[must_use]
+    pub fn continue_authorization_grant(&self, id: Ulid) -> Url {
+        self.absolute_url_for(&crate::endpoints::ContinueAuthorizationGrant(id))
+    }
 }
 
 #[cfg(test)]
@@ -260,4 +339,43 @@ mod tests {
         let uri = builder.absolute_url_for(&crate::endpoints::OAuth2AuthorizationEndpoint);
         assert_eq!(uri.as_str(), "https://example.com/foo/authorize");
     }
+
+    #[test]
+    fn test_prefix_without_trailing_slash() {
+        let builder = super::UrlBuilder::new(
+            url::Url::parse("https://example.com/foo").unwrap(),
+            None,
+            None,
+        );
+        assert_eq!(builder.prefix, "/foo");
+        assert_eq!(builder.oidc_issuer().as_str(), "https://example.com/foo");
+        assert_eq!(
+            builder.oidc_discovery().as_str(),
+            "https://example.com/foo/.well-known/openid-configuration"
+        );
+
+        let uri = builder.absolute_url_for(&crate::endpoints::OAuth2AuthorizationEndpoint);
+        assert_eq!(uri.as_str(), "https://example.com/foo/authorize");
+        assert_eq!(
+            builder.relative_url_for(&crate::endpoints::Login::default()),
+            "/foo/login"
+        );
+        assert_eq!(builder.assets_base(), "/foo/assets/");
+    }
+
+    #[test]
+    fn test_discovery_with_issuer_path() {
+        let builder = super::UrlBuilder::new(
+            url::Url::parse("https://example.com/foo/").unwrap(),
+            Some(url::Url::parse("https://example.com/foo").unwrap()),
+            None,
+        );
+
+        // The issuer is advertised as configured
+        assert_eq!(builder.oidc_issuer().as_str(), "https://example.com/foo");
+        assert_eq!(
+            builder.oidc_discovery().as_str(),
+            "https://example.com/foo/.well-known/openid-configuration"
+        );
+    }
 }
@@ -0,0 +1,236 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Exclusive XML canonicalization, without comments
+//!
+//! See <https://www.w3.org/TR/xml-exc-c14n/>
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::xml::{Element, Node};
+
+/// Canonicalize `element` and its descendants, leaving out the `excluded`
+/// descendant, e.g. the signature of an enveloped signature.
+///
+/// The namespaces with a prefix in `inclusive_prefixes` are rendered like
+/// with the inclusive canonicalization. The default namespace is named
+/// `#default` in that list.
+pub(crate) fn canonicalize(
+    element: &Element,
+    excluded: Option<&Element>,
+    inclusive_prefixes: &[&str],
+) -> String {
+    let mut output = String::new();
+    write_element(
+        &mut output,
+        element,
+        excluded,
+        inclusive_prefixes,
+        &BTreeMap::new(),
+    );
+    output
+}
+
+/// Write an element, `rendered` being the namespaces declared by its output
+/// ancestors
+fn write_element<'a>(
+    output: &mut String,
+    element: &'a Element,
+    excluded: Option<&Element>,
+    inclusive_prefixes: &[&str],
+    rendered: &BTreeMap<&'a str, &'a str>,
+) {
+    // The namespaces which are visibly utilized by the element or its attributes
+    let mut prefixes: BTreeSet<&str> = BTreeSet::new();
+    prefixes.insert(element.prefix.as_deref().unwrap_or_default());
+    for attribute in &element.attributes {
+        match attribute.prefix.as_deref() {
+            Some("xml") | None => {}
+            Some(prefix) => {
+                prefixes.insert(prefix);
+            }
+        }
+    }
+
+    for prefix in inclusive_prefixes {
+        let prefix = if *prefix == "#default" { "" } else { prefix };
+        if let Some((prefix, _)) = element.namespaces.get_key_value(prefix) {
+            prefixes.insert(prefix);
+        }
+    }
+
+    let mut rendered = rendered.clone();
+    let mut declarations = Vec::new();
+    for prefix in prefixes {
+        let namespace = element
+            .namespaces
+            .get(prefix)
+            .map(String::as_str)
+            .unwrap_or_default();
+        // This also makes sure that an empty default namespace is only declared
+        // to undo one declared on an output ancestor
+        if rendered.get(prefix).copied().unwrap_or_default() == namespace {
+            continue;
+        }
+        rendered.insert(prefix, namespace);
+        declarations.push((prefix, namespace));
+    }
+
+    output.push('<');
+    write_qname(output, element.prefix.as_deref(), &element.name);
+
+    for (prefix, namespace) in declarations {
+        if prefix.is_empty() {
+            output.push_str(" xmlns=\"");
+        } else {
+            output.push_str(" xmlns:");
+            output.push_str(prefix);
+            output.push_str("=\"");
+        }
+        escape_attribute(output, namespace);
+        output.push('"');
+    }
+
+    // Attributes are sorted by namespace, then by local name, the ones without
+    // namespace coming first
+    let mut attributes: Vec<_> = element.attributes.iter().collect();
+    attributes.sort_by_key(|attribute| {
+        (
+            attribute.namespace.as_deref().unwrap_or_default(),
+            attribute.name.as_str(),
+        )
+    });
+    for attribute in attributes {
+        output.push(' ');
+        write_qname(output, attribute.prefix.as_deref(), &attribute.name);
+        output.push_str("=\"");
+        escape_attribute(output, &attribute.value);
+        output.push('"');
+    }
+
+    output.push('>');
+
+    for child in &element.children {
+        match child {
+            Node::Text(text) => escape_text(output, text),
+            Node::Element(child) => {
+                if excluded.is_some_and(|excluded| std::ptr::eq(excluded, child)) {
+                    continue;
+                }
+                write_element(output, child, excluded, inclusive_prefixes, &rendered);
+            }
+        }
+    }
+
+    output.push_str("</");
+    write_qname(output, element.prefix.as_deref(), &element.name);
+    output.push('>');
+}
+
+fn write_qname(output: &mut String, prefix: Option<&str>, name: &str) {
+    if let Some(prefix) = prefix {
+        output.push_str(prefix);
+        output.push(':');
+    }
+    output.push_str(name);
+}
+
+/// Escape a text node
+pub(crate) fn escape_text(output: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '\r' => output.push_str("&#xD;"),
+            c => output.push(c),
+        }
+    }
+}
+
+/// Escape the value of an attribute, to be written between double quotes
+pub(crate) fn escape_attribute(output: &mut String, value: &str) {
+    for c in value.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '"' => output.push_str("&quot;"),
+            '\t' => output.push_str("&#x9;"),
+            '\n' => output.push_str("&#xA;"),
+            '\r' => output.push_str("&#xD;"),
+            c => output.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xml::parse;
+
+    /// The example of the section 2.2 of the specification
+    const SPEC_EXAMPLE: &str = r#"<n0:local xmlns:n0="foo:bar" xmlns:n3="ftp://example.org">
+  <n1:elem2 xmlns:n1="http://example.net" xml:lang="en">
+    <n3:stuff xmlns:n3="ftp://example.org"/>
+  </n1:elem2>
+</n0:local>"#;
+
+    #[test]
+    fn test_spec_example() {
+        let root = parse(SPEC_EXAMPLE).unwrap();
+        let elem2 = root.child("http://example.net", "elem2").unwrap();
+
+        assert_eq!(
+            canonicalize(elem2, None, &[]),
+            r#"<n1:elem2 xmlns:n1="http://example.net" xml:lang="en">
+    <n3:stuff xmlns:n3="ftp://example.org"></n3:stuff>
+  </n1:elem2>"#
+        );
+
+        // With the `n3` prefix in the inclusive list, it is declared as soon
+        // as it is in scope
+        assert_eq!(
+            canonicalize(elem2, None, &["n3"]),
+            r#"<n1:elem2 xmlns:n1="http://example.net" xmlns:n3="ftp://example.org" xml:lang="en">
+    <n3:stuff></n3:stuff>
+  </n1:elem2>"#
+        );
+    }
+
+    #[test]
+    fn test_default_namespace_and_escaping() {
+        let root = parse(
+            "<root xmlns=\"urn:a\" xmlns:b=\"urn:b\">\
+                <child z=\"1\" b:y=\"2\" a='&lt;\"&#xA;\tx'>x &amp; y &gt; &#xD;</child>\
+                <inner xmlns=\"\"><leaf/></inner>\
+            </root>",
+        )
+        .unwrap();
+
+        assert_eq!(
+            canonicalize(&root, None, &[]),
+            "<root xmlns=\"urn:a\">\
+                <child xmlns:b=\"urn:b\" a=\"&lt;&quot;&#xA; x\" z=\"1\" b:y=\"2\">x &amp; y &gt; &#xD;</child>\
+                <inner xmlns=\"\"><leaf></leaf></inner>\
+            </root>"
+        );
+
+        // Leave out a child, like the enveloped signature transform does
+        let child = root.child("urn:a", "child").unwrap();
+        assert_eq!(
+            canonicalize(&root, Some(child), &[]),
+            "<root xmlns=\"urn:a\"><inner xmlns=\"\"><leaf></leaf></inner></root>"
+        );
+    }
+}
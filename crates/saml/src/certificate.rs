@@ -0,0 +1,95 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Certificates of the identity providers
+
+use std::str::FromStr;
+
+use base64ct::{Base64, Encoding};
+use der::{Decode, Encode};
+use rsa::{pkcs8::DecodePublicKey, RsaPublicKey};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum CertificateError {
+    #[error("Invalid PEM encoding")]
+    Pem(#[from] pem_rfc7468::Error),
+
+    #[error("Invalid base64 encoding")]
+    Base64(#[from] base64ct::Error),
+
+    #[error("Expected a certificate, got a {label}")]
+    UnexpectedLabel { label: String },
+
+    #[error("Invalid certificate")]
+    Der(#[from] der::Error),
+
+    #[error("Only RSA keys are supported")]
+    UnsupportedKey(#[from] rsa::pkcs8::spki::Error),
+}
+
+/// A certificate used by an identity provider to sign its messages.
+///
+/// Only its public key is used: certificates of identity providers are
+/// usually self-signed, and trusted because they are configured.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Certificate {
+    public_key: RsaPublicKey,
+}
+
+impl Certificate {
+    /// The RSA public key of the certificate
+    #[must_use]
+    pub fn public_key(&self) -> &RsaPublicKey {
+        &self.public_key
+    }
+
+    /// Parse a DER-encoded X.509 certificate
+    pub fn from_der(der: &[u8]) -> Result<Self, CertificateError> {
+        let certificate = x509_cert::Certificate::from_der(der)?;
+        let spki = certificate
+            .tbs_certificate
+            .subject_public_key_info
+            .to_der()?;
+        let public_key = RsaPublicKey::from_public_key_der(&spki)?;
+        Ok(Self { public_key })
+    }
+}
+
+impl FromStr for Certificate {
+    type Err = CertificateError;
+
+    /// Parse a PEM-encoded certificate, or the base64-encoded DER certificate
+    /// alone, as found in the metadata of identity providers
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.starts_with("-----BEGIN") {
+            let (label, der) = pem_rfc7468::decode_vec(s.as_bytes())?;
+            if label != "CERTIFICATE" {
+                return Err(CertificateError::UnexpectedLabel {
+                    label: label.to_owned(),
+                });
+            }
+            Self::from_der(&der)
+        } else {
+            let der = Base64::decode_vec(&remove_whitespace(s))?;
+            Self::from_der(&der)
+        }
+    }
+}
+
+/// Remove the whitespace of base64-encoded values, which are often wrapped
+pub(crate) fn remove_whitespace(value: &str) -> String {
+    value.split_ascii_whitespace().collect()
+}
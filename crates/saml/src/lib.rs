@@ -0,0 +1,43 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A minimal SAML 2.0 service provider, to log in through upstream identity
+//! providers with the web browser SSO profile.
+//!
+//! Authentication requests are sent with the HTTP-Redirect binding, and
+//! responses are received with the HTTP-POST binding.
+
+#![forbid(unsafe_code)]
+#![deny(clippy::all, clippy::str_to_string, rustdoc::broken_intra_doc_links)]
+#![warn(clippy::pedantic)]
+#![allow(clippy::missing_errors_doc, clippy::module_name_repetitions)]
+
+mod c14n;
+mod certificate;
+pub mod metadata;
+pub mod request;
+pub mod response;
+mod signature;
+mod xml;
+
+pub use self::{
+    certificate::{Certificate, CertificateError},
+    signature::SignatureError,
+    xml::XmlError,
+};
+
+pub(crate) const PROTOCOL_NAMESPACE: &str = "urn:oasis:names:tc:SAML:2.0:protocol";
+pub(crate) const ASSERTION_NAMESPACE: &str = "urn:oasis:names:tc:SAML:2.0:assertion";
+pub(crate) const METADATA_NAMESPACE: &str = "urn:oasis:names:tc:SAML:2.0:metadata";
+pub(crate) const HTTP_POST_BINDING: &str = "urn:oasis:names:tc:SAML:2.0:bindings:HTTP-POST";
@@ -0,0 +1,76 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Metadata of the service provider, to be imported by identity providers
+
+use url::Url;
+
+use crate::{
+    xml::{write_attribute, write_text_element},
+    HTTP_POST_BINDING, METADATA_NAMESPACE, PROTOCOL_NAMESPACE,
+};
+
+/// The metadata describing the service provider
+#[derive(Debug, Clone)]
+pub struct ServiceProviderMetadata<'a> {
+    /// The entity ID of the service provider
+    pub entity_id: &'a str,
+
+    /// Where the identity provider should post its responses
+    pub assertion_consumer_service_url: &'a Url,
+
+    /// The format of the name identifier requested, if any
+    pub name_id_format: Option<&'a str>,
+}
+
+impl ServiceProviderMetadata<'_> {
+    /// Serialize the metadata
+    #[must_use]
+    pub fn to_xml(&self) -> String {
+        let mut output = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+
+        output.push_str("<md:EntityDescriptor");
+        write_attribute(&mut output, "xmlns:md", METADATA_NAMESPACE);
+        write_attribute(&mut output, "entityID", self.entity_id);
+        output.push('>');
+
+        output.push_str("<md:SPSSODescriptor");
+        write_attribute(&mut output, "AuthnRequestsSigned", "false");
+        write_attribute(&mut output, "WantAssertionsSigned", "true");
+        write_attribute(
+            &mut output,
+            "protocolSupportEnumeration",
+            PROTOCOL_NAMESPACE,
+        );
+        output.push('>');
+
+        if let Some(name_id_format) = self.name_id_format {
+            write_text_element(&mut output, "md:NameIDFormat", name_id_format);
+        }
+
+        output.push_str("<md:AssertionConsumerService");
+        write_attribute(&mut output, "Binding", HTTP_POST_BINDING);
+        write_attribute(
+            &mut output,
+            "Location",
+            self.assertion_consumer_service_url.as_str(),
+        );
+        write_attribute(&mut output, "index", "0");
+        write_attribute(&mut output, "isDefault", "true");
+        output.push_str("/>");
+
+        output.push_str("</md:SPSSODescriptor></md:EntityDescriptor>\n");
+        output
+    }
+}
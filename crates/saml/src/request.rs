@@ -0,0 +1,161 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Authentication requests, sent with the HTTP-Redirect binding
+
+use std::io::Write;
+
+use base64ct::{Base64, Encoding};
+use chrono::{DateTime, SecondsFormat, Utc};
+use flate2::{write::DeflateEncoder, Compression};
+use url::Url;
+
+use crate::{
+    xml::{write_attribute, write_text_element},
+    ASSERTION_NAMESPACE, HTTP_POST_BINDING, PROTOCOL_NAMESPACE,
+};
+
+/// A request to authenticate the user, sent to the identity provider
+#[derive(Debug, Clone)]
+pub struct AuthnRequest<'a> {
+    /// The unique identifier of the request, which the response refers to.
+    ///
+    /// It must start with a letter or an underscore.
+    pub id: &'a str,
+
+    /// When the request was issued
+    pub issue_instant: DateTime<Utc>,
+
+    /// The entity ID of the service provider
+    pub issuer: &'a str,
+
+    /// The URL of the single sign-on service of the identity provider
+    pub destination: &'a Url,
+
+    /// Where the identity provider should post its response
+    pub assertion_consumer_service_url: &'a Url,
+
+    /// The format of the name identifier to request, if any
+    pub name_id_format: Option<&'a str>,
+}
+
+impl AuthnRequest<'_> {
+    /// Serialize the request
+    #[must_use]
+    pub fn to_xml(&self) -> String {
+        let mut output = String::from("<samlp:AuthnRequest");
+        write_attribute(&mut output, "xmlns:samlp", PROTOCOL_NAMESPACE);
+        write_attribute(&mut output, "xmlns:saml", ASSERTION_NAMESPACE);
+        write_attribute(&mut output, "ID", self.id);
+        write_attribute(&mut output, "Version", "2.0");
+        write_attribute(
+            &mut output,
+            "IssueInstant",
+            &self
+                .issue_instant
+                .to_rfc3339_opts(SecondsFormat::Secs, true),
+        );
+        write_attribute(&mut output, "Destination", self.destination.as_str());
+        write_attribute(
+            &mut output,
+            "AssertionConsumerServiceURL",
+            self.assertion_consumer_service_url.as_str(),
+        );
+        write_attribute(&mut output, "ProtocolBinding", HTTP_POST_BINDING);
+        output.push('>');
+
+        write_text_element(&mut output, "saml:Issuer", self.issuer);
+
+        if let Some(name_id_format) = self.name_id_format {
+            output.push_str("<samlp:NameIDPolicy");
+            write_attribute(&mut output, "Format", name_id_format);
+            write_attribute(&mut output, "AllowCreate", "true");
+            output.push_str("/>");
+        }
+
+        output.push_str("</samlp:AuthnRequest>");
+        output
+    }
+
+    /// The URL of the single sign-on service to redirect the user to, with the
+    /// deflated request and the `relay_state` in its query
+    pub fn redirect_url(&self, relay_state: &str) -> Result<Url, std::io::Error> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(self.to_xml().as_bytes())?;
+        let request = Base64::encode_string(&encoder.finish()?);
+
+        let mut url = self.destination.clone();
+        url.query_pairs_mut()
+            .append_pair("SAMLRequest", &request)
+            .append_pair("RelayState", relay_state);
+        Ok(url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use chrono::TimeZone;
+    use flate2::read::DeflateDecoder;
+
+    use super::*;
+
+    #[test]
+    fn test_redirect_url() {
+        let destination = Url::parse("https://idp.example.com/sso?tenant=1").unwrap();
+        let acs = Url::parse("https://auth.example.com/upstream/saml/idp/acs").unwrap();
+        let request = AuthnRequest {
+            id: "_abcdef",
+            issue_instant: Utc.with_ymd_and_hms(2023, 11, 10, 12, 0, 0).unwrap(),
+            issuer: "https://auth.example.com/upstream/saml/idp/metadata",
+            destination: &destination,
+            assertion_consumer_service_url: &acs,
+            name_id_format: Some("urn:oasis:names:tc:SAML:2.0:nameid-format:persistent"),
+        };
+
+        let url = request.redirect_url("state&more").unwrap();
+        assert_eq!(url.host_str(), Some("idp.example.com"));
+        assert_eq!(url.path(), "/sso");
+
+        let query: Vec<_> = url.query_pairs().collect();
+        assert_eq!(query[0].0, "tenant");
+        assert_eq!(query[2].0, "RelayState");
+        assert_eq!(query[2].1, "state&more");
+
+        assert_eq!(query[1].0, "SAMLRequest");
+        let deflated = Base64::decode_vec(&query[1].1).unwrap();
+        let mut xml = String::new();
+        DeflateDecoder::new(deflated.as_slice())
+            .read_to_string(&mut xml)
+            .unwrap();
+        assert_eq!(
+            xml,
+            "<samlp:AuthnRequest \
+                xmlns:samlp=\"urn:oasis:names:tc:SAML:2.0:protocol\" \
+                xmlns:saml=\"urn:oasis:names:tc:SAML:2.0:assertion\" \
+                ID=\"_abcdef\" \
+                Version=\"2.0\" \
+                IssueInstant=\"2023-11-10T12:00:00Z\" \
+                Destination=\"https://idp.example.com/sso?tenant=1\" \
+                AssertionConsumerServiceURL=\"https://auth.example.com/upstream/saml/idp/acs\" \
+                ProtocolBinding=\"urn:oasis:names:tc:SAML:2.0:bindings:HTTP-POST\">\
+                <saml:Issuer>https://auth.example.com/upstream/saml/idp/metadata</saml:Issuer>\
+                <samlp:NameIDPolicy \
+                    Format=\"urn:oasis:names:tc:SAML:2.0:nameid-format:persistent\" \
+                    AllowCreate=\"true\"/>\
+            </samlp:AuthnRequest>"
+        );
+    }
+}
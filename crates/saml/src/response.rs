@@ -0,0 +1,342 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Responses to authentication requests, received with the HTTP-POST binding
+
+use base64ct::{Base64, Encoding};
+use chrono::{DateTime, Duration, Utc};
+use thiserror::Error;
+use url::Url;
+
+use crate::{
+    certificate::remove_whitespace,
+    signature::{self, SignatureError},
+    xml::{self, Element, XmlError},
+    Certificate, ASSERTION_NAMESPACE, PROTOCOL_NAMESPACE,
+};
+
+/// How much the clocks of the identity provider and of the service may differ
+const CLOCK_SKEW_SECONDS: i64 = 120;
+
+const SUCCESS: &str = "urn:oasis:names:tc:SAML:2.0:status:Success";
+const BEARER: &str = "urn:oasis:names:tc:SAML:2.0:cm:bearer";
+
+#[derive(Debug, Error)]
+pub enum ResponseError {
+    #[error("Invalid base64 encoding")]
+    Base64(#[from] base64ct::Error),
+
+    #[error("Invalid UTF-8 encoding")]
+    Utf8(#[from] std::string::FromUtf8Error),
+
+    #[error(transparent)]
+    Xml(#[from] XmlError),
+
+    #[error("Not a SAML response")]
+    NotAResponse,
+
+    #[error("Missing {0}")]
+    Missing(&'static str),
+
+    #[error("Invalid {0}")]
+    Invalid(&'static str),
+
+    #[error("The identity provider returned the status {code}")]
+    Status {
+        code: String,
+        message: Option<String>,
+    },
+
+    #[error("Encrypted assertions and identifiers are not supported")]
+    Encrypted,
+
+    #[error("The response must contain exactly one assertion")]
+    AssertionCount,
+
+    #[error("Neither the response nor the assertion is signed")]
+    Unsigned,
+
+    #[error(transparent)]
+    Signature(#[from] SignatureError),
+
+    #[error("The assertion is not valid yet")]
+    NotYetValid,
+
+    #[error("The assertion expired")]
+    Expired,
+
+    #[error("The service is not in the audience of the assertion")]
+    AudienceMismatch,
+
+    #[error("The assertion has no valid bearer subject confirmation")]
+    Unconfirmed,
+}
+
+/// What a response is validated against
+#[derive(Debug, Clone)]
+pub struct ValidationData<'a> {
+    /// The entity ID of the identity provider
+    pub idp_entity_id: &'a str,
+
+    /// The entity ID of the service provider, which must be in the audience
+    /// of the assertion
+    pub sp_entity_id: &'a str,
+
+    /// Where the response was posted
+    pub assertion_consumer_service_url: &'a Url,
+
+    /// The identifier of the request the response must be for
+    pub request_id: &'a str,
+
+    /// The certificates the identity provider signs with
+    pub certificates: &'a [Certificate],
+}
+
+/// An attribute of the user asserted by the identity provider
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attribute {
+    /// The name of the attribute, often an URN, e.g.
+    /// `urn:oid:0.9.2342.19200300.100.1.3`
+    pub name: String,
+
+    /// The human-readable name of the attribute, e.g. `mail`
+    pub friendly_name: Option<String>,
+
+    pub values: Vec<String>,
+}
+
+/// A validated assertion about the user
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assertion {
+    /// The name identifier of the user
+    pub name_id: String,
+
+    /// The format of the name identifier
+    pub name_id_format: Option<String>,
+
+    pub attributes: Vec<Attribute>,
+}
+
+fn parse_instant(
+    element: &Element,
+    attribute: &'static str,
+) -> Result<Option<DateTime<Utc>>, ResponseError> {
+    element
+        .attribute(attribute)
+        .map(|value| {
+            DateTime::parse_from_rfc3339(value)
+                .map(|instant| instant.with_timezone(&Utc))
+                .map_err(|_| ResponseError::Invalid(attribute))
+        })
+        .transpose()
+}
+
+fn issuer(element: &Element) -> Option<String> {
+    element
+        .child(ASSERTION_NAMESPACE, "Issuer")
+        .map(|issuer| issuer.text().trim().to_owned())
+}
+
+/// Validate the base64-encoded response posted by the identity provider, and
+/// return the assertion it contains.
+///
+/// Either the response or the assertion must be signed by one of the
+/// certificates, and it must be a response to the request with the
+/// identifier in `data`: unsolicited responses are not supported.
+pub fn validate_response(
+    encoded: &str,
+    data: &ValidationData<'_>,
+    now: DateTime<Utc>,
+) -> Result<Assertion, ResponseError> {
+    let document = Base64::decode_vec(&remove_whitespace(encoded))?;
+    let document = String::from_utf8(document)?;
+    let response = xml::parse(&document)?;
+
+    if !response.is(PROTOCOL_NAMESPACE, "Response") {
+        return Err(ResponseError::NotAResponse);
+    }
+
+    if response.attribute("Version") != Some("2.0") {
+        return Err(ResponseError::Invalid("Version"));
+    }
+
+    if let Some(destination) = response.attribute("Destination") {
+        if destination != data.assertion_consumer_service_url.as_str() {
+            return Err(ResponseError::Invalid("Destination"));
+        }
+    }
+
+    if response.attribute("InResponseTo") != Some(data.request_id) {
+        return Err(ResponseError::Invalid("InResponseTo"));
+    }
+
+    if issuer(&response).is_some_and(|issuer| issuer != data.idp_entity_id) {
+        return Err(ResponseError::Invalid("Issuer"));
+    }
+
+    let status = response
+        .child(PROTOCOL_NAMESPACE, "Status")
+        .ok_or(ResponseError::Missing("Status"))?;
+    let status_code = status
+        .child(PROTOCOL_NAMESPACE, "StatusCode")
+        .ok_or(ResponseError::Missing("StatusCode"))?;
+    let code = status_code.attribute("Value").unwrap_or_default();
+    if code != SUCCESS {
+        // The second-level status code is usually more helpful
+        let code = status_code
+            .child(PROTOCOL_NAMESPACE, "StatusCode")
+            .and_then(|code| code.attribute("Value"))
+            .unwrap_or(code);
+        let message = status
+            .child(PROTOCOL_NAMESPACE, "StatusMessage")
+            .map(Element::text);
+        return Err(ResponseError::Status {
+            code: code.to_owned(),
+            message,
+        });
+    }
+
+    if response
+        .child(ASSERTION_NAMESPACE, "EncryptedAssertion")
+        .is_some()
+    {
+        return Err(ResponseError::Encrypted);
+    }
+
+    let mut assertions = response.children_named(ASSERTION_NAMESPACE, "Assertion");
+    let (Some(assertion), None) = (assertions.next(), assertions.next()) else {
+        return Err(ResponseError::AssertionCount);
+    };
+
+    // Signing the response also covers the assertion it contains. Every
+    // signature present must be valid.
+    let mut signed = false;
+    if let Some(signature) = signature::find(&response) {
+        signature::verify(&response, signature, data.certificates)?;
+        signed = true;
+    }
+    if let Some(signature) = signature::find(assertion) {
+        signature::verify(assertion, signature, data.certificates)?;
+        signed = true;
+    }
+    if !signed {
+        return Err(ResponseError::Unsigned);
+    }
+
+    validate_assertion(assertion, data, now)
+}
+
+fn validate_assertion(
+    assertion: &Element,
+    data: &ValidationData<'_>,
+    now: DateTime<Utc>,
+) -> Result<Assertion, ResponseError> {
+    let skew = Duration::seconds(CLOCK_SKEW_SECONDS);
+
+    if issuer(assertion).ok_or(ResponseError::Missing("Issuer"))? != data.idp_entity_id {
+        return Err(ResponseError::Invalid("Issuer"));
+    }
+
+    let conditions = assertion
+        .child(ASSERTION_NAMESPACE, "Conditions")
+        .ok_or(ResponseError::Missing("Conditions"))?;
+
+    if parse_instant(conditions, "NotBefore")?.is_some_and(|not_before| now + skew < not_before) {
+        return Err(ResponseError::NotYetValid);
+    }
+
+    if parse_instant(conditions, "NotOnOrAfter")?
+        .is_some_and(|not_on_or_after| now - skew >= not_on_or_after)
+    {
+        return Err(ResponseError::Expired);
+    }
+
+    // The service must be in every audience restriction, and there must be at
+    // least one
+    let mut restrictions = conditions
+        .children_named(ASSERTION_NAMESPACE, "AudienceRestriction")
+        .peekable();
+    if restrictions.peek().is_none() {
+        return Err(ResponseError::Missing("AudienceRestriction"));
+    }
+    for restriction in restrictions {
+        let in_audience = restriction
+            .children_named(ASSERTION_NAMESPACE, "Audience")
+            .any(|audience| audience.text().trim() == data.sp_entity_id);
+        if !in_audience {
+            return Err(ResponseError::AudienceMismatch);
+        }
+    }
+
+    let subject = assertion
+        .child(ASSERTION_NAMESPACE, "Subject")
+        .ok_or(ResponseError::Missing("Subject"))?;
+
+    if subject.child(ASSERTION_NAMESPACE, "EncryptedID").is_some() {
+        return Err(ResponseError::Encrypted);
+    }
+
+    let name_id = subject
+        .child(ASSERTION_NAMESPACE, "NameID")
+        .ok_or(ResponseError::Missing("NameID"))?;
+
+    let confirmed = subject
+        .children_named(ASSERTION_NAMESPACE, "SubjectConfirmation")
+        .filter(|confirmation| confirmation.attribute("Method") == Some(BEARER))
+        .filter_map(|confirmation| {
+            confirmation.child(ASSERTION_NAMESPACE, "SubjectConfirmationData")
+        })
+        .any(|confirmation| is_confirmed(confirmation, data, now, skew));
+    if !confirmed {
+        return Err(ResponseError::Unconfirmed);
+    }
+
+    let attributes = assertion
+        .children_named(ASSERTION_NAMESPACE, "AttributeStatement")
+        .flat_map(|statement| statement.children_named(ASSERTION_NAMESPACE, "Attribute"))
+        .filter_map(|attribute| {
+            Some(Attribute {
+                name: attribute.attribute("Name")?.to_owned(),
+                friendly_name: attribute.attribute("FriendlyName").map(ToOwned::to_owned),
+                values: attribute
+                    .children_named(ASSERTION_NAMESPACE, "AttributeValue")
+                    .map(Element::text)
+                    .collect(),
+            })
+        })
+        .collect();
+
+    Ok(Assertion {
+        name_id: name_id.text().trim().to_owned(),
+        name_id_format: name_id.attribute("Format").map(ToOwned::to_owned),
+        attributes,
+    })
+}
+
+/// Whether a bearer subject confirmation is valid, as per the web browser SSO
+/// profile: it must be for this service and this request, and not expired
+fn is_confirmed(
+    confirmation: &Element,
+    data: &ValidationData<'_>,
+    now: DateTime<Utc>,
+    skew: Duration,
+) -> bool {
+    let recipient = confirmation.attribute("Recipient");
+    let in_response_to = confirmation.attribute("InResponseTo");
+    let not_on_or_after = parse_instant(confirmation, "NotOnOrAfter").ok().flatten();
+
+    recipient == Some(data.assertion_consumer_service_url.as_str())
+        && in_response_to == Some(data.request_id)
+        && not_on_or_after.is_some_and(|not_on_or_after| now - skew < not_on_or_after)
+}
@@ -0,0 +1,226 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Verification of enveloped XML signatures
+//!
+//! Only the subset used by SAML identity providers is supported: a single
+//! reference to the signed element, with the enveloped signature and the
+//! exclusive canonicalization transforms, and RSA signatures.
+
+use base64ct::{Base64, Encoding};
+use rsa::{
+    pkcs1v15::{Signature, VerifyingKey},
+    signature::Verifier,
+};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use thiserror::Error;
+
+use crate::{c14n::canonicalize, certificate::remove_whitespace, xml::Element, Certificate};
+
+pub(crate) const DSIG_NAMESPACE: &str = "http://www.w3.org/2000/09/xmldsig#";
+/// Both the URI of the algorithm and the namespace of its parameters
+const EXC_C14N: &str = "http://www.w3.org/2001/10/xml-exc-c14n#";
+const ENVELOPED_SIGNATURE: &str = "http://www.w3.org/2000/09/xmldsig#enveloped-signature";
+
+#[derive(Debug, Error)]
+pub enum SignatureError {
+    #[error("Missing {0} element in the signature")]
+    MissingElement(&'static str),
+
+    #[error("Unsupported algorithm {0:?}")]
+    UnsupportedAlgorithm(String),
+
+    #[error("The signature must have exactly one reference")]
+    InvalidReferences,
+
+    #[error("The signature doesn't reference the element it is in")]
+    ReferenceMismatch,
+
+    #[error("The signed element was modified")]
+    DigestMismatch,
+
+    #[error("Invalid base64 encoding")]
+    Base64(#[from] base64ct::Error),
+
+    #[error("The signature doesn't match any of the certificates")]
+    Invalid,
+}
+
+#[derive(Clone, Copy)]
+enum DigestAlgorithm {
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl DigestAlgorithm {
+    fn from_uri(uri: &str) -> Result<Self, SignatureError> {
+        match uri {
+            "http://www.w3.org/2001/04/xmlenc#sha256" => Ok(Self::Sha256),
+            "http://www.w3.org/2001/04/xmldsig-more#sha384" => Ok(Self::Sha384),
+            "http://www.w3.org/2001/04/xmlenc#sha512" => Ok(Self::Sha512),
+            _ => Err(SignatureError::UnsupportedAlgorithm(uri.to_owned())),
+        }
+    }
+
+    fn digest(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Sha256 => Sha256::digest(data).to_vec(),
+            Self::Sha384 => Sha384::digest(data).to_vec(),
+            Self::Sha512 => Sha512::digest(data).to_vec(),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum SignatureAlgorithm {
+    RsaSha256,
+    RsaSha384,
+    RsaSha512,
+}
+
+impl SignatureAlgorithm {
+    fn from_uri(uri: &str) -> Result<Self, SignatureError> {
+        match uri {
+            "http://www.w3.org/2001/04/xmldsig-more#rsa-sha256" => Ok(Self::RsaSha256),
+            "http://www.w3.org/2001/04/xmldsig-more#rsa-sha384" => Ok(Self::RsaSha384),
+            "http://www.w3.org/2001/04/xmldsig-more#rsa-sha512" => Ok(Self::RsaSha512),
+            _ => Err(SignatureError::UnsupportedAlgorithm(uri.to_owned())),
+        }
+    }
+
+    fn verify(self, certificate: &Certificate, message: &[u8], signature: &Signature) -> bool {
+        let public_key = certificate.public_key().clone();
+        match self {
+            Self::RsaSha256 => VerifyingKey::<Sha256>::new(public_key)
+                .verify(message, signature)
+                .is_ok(),
+            Self::RsaSha384 => VerifyingKey::<Sha384>::new(public_key)
+                .verify(message, signature)
+                .is_ok(),
+            Self::RsaSha512 => VerifyingKey::<Sha512>::new(public_key)
+                .verify(message, signature)
+                .is_ok(),
+        }
+    }
+}
+
+/// The `Signature` child of an element, if it has one
+pub(crate) fn find(element: &Element) -> Option<&Element> {
+    element.child(DSIG_NAMESPACE, "Signature")
+}
+
+fn required<'a>(element: &'a Element, name: &'static str) -> Result<&'a Element, SignatureError> {
+    element
+        .child(DSIG_NAMESPACE, name)
+        .ok_or(SignatureError::MissingElement(name))
+}
+
+/// The algorithm of an element, which must be the exclusive canonicalization,
+/// and the prefixes of its inclusive namespaces list
+fn exclusive_c14n(element: &Element) -> Result<Vec<&str>, SignatureError> {
+    let algorithm = element.attribute("Algorithm").unwrap_or_default();
+    if algorithm != EXC_C14N {
+        return Err(SignatureError::UnsupportedAlgorithm(algorithm.to_owned()));
+    }
+
+    Ok(element
+        .child(EXC_C14N, "InclusiveNamespaces")
+        .and_then(|inclusive| inclusive.attribute("PrefixList"))
+        .map(|prefixes| prefixes.split_ascii_whitespace().collect())
+        .unwrap_or_default())
+}
+
+/// Verify the `signature` enveloped in `element` with any of the
+/// `certificates`.
+///
+/// The signature must reference `element` itself, so that what was verified
+/// is what the caller reads.
+pub(crate) fn verify(
+    element: &Element,
+    signature: &Element,
+    certificates: &[Certificate],
+) -> Result<(), SignatureError> {
+    let signed_info = required(signature, "SignedInfo")?;
+
+    let c14n_prefixes = exclusive_c14n(required(signed_info, "CanonicalizationMethod")?)?;
+    let signature_algorithm = SignatureAlgorithm::from_uri(
+        required(signed_info, "SignatureMethod")?
+            .attribute("Algorithm")
+            .unwrap_or_default(),
+    )?;
+
+    let mut references = signed_info.children_named(DSIG_NAMESPACE, "Reference");
+    let (Some(reference), None) = (references.next(), references.next()) else {
+        return Err(SignatureError::InvalidReferences);
+    };
+
+    let id = element
+        .attribute("ID")
+        .ok_or(SignatureError::ReferenceMismatch)?;
+    if reference
+        .attribute("URI")
+        .and_then(|uri| uri.strip_prefix('#'))
+        != Some(id)
+    {
+        return Err(SignatureError::ReferenceMismatch);
+    }
+
+    // The signature being in the signed element, the enveloped signature
+    // transform is required, and it must be canonicalized with the exclusive
+    // canonicalization
+    let mut enveloped = false;
+    let mut reference_prefixes = None;
+    if let Some(transforms) = reference.child(DSIG_NAMESPACE, "Transforms") {
+        for transform in transforms.children_named(DSIG_NAMESPACE, "Transform") {
+            if transform.attribute("Algorithm") == Some(ENVELOPED_SIGNATURE) {
+                enveloped = true;
+            } else {
+                reference_prefixes = Some(exclusive_c14n(transform)?);
+            }
+        }
+    }
+    let (true, Some(reference_prefixes)) = (enveloped, reference_prefixes) else {
+        return Err(SignatureError::MissingElement("Transform"));
+    };
+
+    let digest_algorithm = DigestAlgorithm::from_uri(
+        required(reference, "DigestMethod")?
+            .attribute("Algorithm")
+            .unwrap_or_default(),
+    )?;
+    let expected_digest = Base64::decode_vec(&remove_whitespace(
+        &required(reference, "DigestValue")?.text(),
+    ))?;
+
+    let canonical = canonicalize(element, Some(signature), &reference_prefixes);
+    if digest_algorithm.digest(canonical.as_bytes()) != expected_digest {
+        return Err(SignatureError::DigestMismatch);
+    }
+
+    let signature_value = Base64::decode_vec(&remove_whitespace(
+        &required(signature, "SignatureValue")?.text(),
+    ))?;
+    let signature_value =
+        Signature::try_from(signature_value.as_slice()).map_err(|_| SignatureError::Invalid)?;
+
+    let canonical = canonicalize(signed_info, None, &c14n_prefixes);
+    if certificates.iter().any(|certificate| {
+        signature_algorithm.verify(certificate, canonical.as_bytes(), &signature_value)
+    }) {
+        Ok(())
+    } else {
+        Err(SignatureError::Invalid)
+    }
+}
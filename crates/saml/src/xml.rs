@@ -0,0 +1,355 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A minimal XML tree, keeping what is needed to canonicalize it
+
+use std::{borrow::Cow, collections::BTreeMap, str::Utf8Error};
+
+use quick_xml::{
+    escape::{unescape, EscapeError},
+    events::{BytesStart, Event},
+    Reader,
+};
+use thiserror::Error;
+
+/// The namespace implicitly bound to the `xml` prefix
+const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+
+#[derive(Debug, Error)]
+pub enum XmlError {
+    #[error(transparent)]
+    Parse(#[from] quick_xml::Error),
+
+    #[error(transparent)]
+    Escape(#[from] EscapeError),
+
+    #[error(transparent)]
+    Utf8(#[from] Utf8Error),
+
+    #[error("Document type declarations are not allowed")]
+    DocType,
+
+    #[error("The prefix {0:?} is not bound to a namespace")]
+    UnboundPrefix(String),
+
+    #[error("The document doesn't have a single root element")]
+    InvalidRoot,
+}
+
+/// A node of the tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Node {
+    Element(Element),
+    Text(String),
+}
+
+/// An attribute, other than a namespace declaration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Attribute {
+    pub prefix: Option<String>,
+    pub name: String,
+    pub namespace: Option<String>,
+    pub value: String,
+}
+
+/// An element, with its namespace resolved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Element {
+    pub prefix: Option<String>,
+    pub name: String,
+    pub namespace: Option<String>,
+    pub attributes: Vec<Attribute>,
+
+    /// The namespaces in scope on this element, including the ones declared
+    /// on its ancestors, by prefix. The default namespace has an empty prefix.
+    pub namespaces: BTreeMap<String, String>,
+
+    pub children: Vec<Node>,
+}
+
+impl Element {
+    fn from_start(
+        start: &BytesStart<'_>,
+        parent_namespaces: Option<&BTreeMap<String, String>>,
+    ) -> Result<Self, XmlError> {
+        let mut namespaces = parent_namespaces.cloned().unwrap_or_default();
+        let mut raw_attributes = Vec::new();
+
+        for attribute in start.attributes() {
+            let attribute = attribute.map_err(quick_xml::Error::from)?;
+            let key = std::str::from_utf8(attribute.key.as_ref())?;
+            let value = normalize_attribute_value(std::str::from_utf8(&attribute.value)?);
+            let value = unescape(&value)?.into_owned();
+
+            if key == "xmlns" {
+                if value.is_empty() {
+                    namespaces.remove("");
+                } else {
+                    namespaces.insert(String::new(), value);
+                }
+            } else if let Some(prefix) = key.strip_prefix("xmlns:") {
+                // The `xml` prefix is always bound, and never rendered
+                if prefix != "xml" {
+                    namespaces.insert(prefix.to_owned(), value);
+                }
+            } else {
+                raw_attributes.push((key.to_owned(), value));
+            }
+        }
+
+        let qname = start.name();
+        let (prefix, name) = split_qname(std::str::from_utf8(qname.as_ref())?);
+        let namespace = resolve(&namespaces, prefix, true)?;
+
+        let attributes = raw_attributes
+            .into_iter()
+            .map(|(key, value)| {
+                let (prefix, name) = split_qname(&key);
+                Ok(Attribute {
+                    namespace: resolve(&namespaces, prefix, false)?,
+                    prefix: prefix.map(ToOwned::to_owned),
+                    name: name.to_owned(),
+                    value,
+                })
+            })
+            .collect::<Result<_, XmlError>>()?;
+
+        Ok(Self {
+            prefix: prefix.map(ToOwned::to_owned),
+            name: name.to_owned(),
+            namespace,
+            attributes,
+            namespaces,
+            children: Vec::new(),
+        })
+    }
+
+    /// Whether this element has the given namespace and local name
+    pub(crate) fn is(&self, namespace: &str, name: &str) -> bool {
+        self.namespace.as_deref() == Some(namespace) && self.name == name
+    }
+
+    /// The value of an attribute without namespace
+    pub(crate) fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|attribute| attribute.namespace.is_none() && attribute.name == name)
+            .map(|attribute| attribute.value.as_str())
+    }
+
+    /// The child elements
+    pub(crate) fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|child| match child {
+            Node::Element(element) => Some(element),
+            Node::Text(_) => None,
+        })
+    }
+
+    /// The child elements with the given namespace and local name
+    pub(crate) fn children_named<'a>(
+        &'a self,
+        namespace: &'a str,
+        name: &'a str,
+    ) -> impl Iterator<Item = &'a Element> + 'a {
+        self.elements()
+            .filter(move |element| element.is(namespace, name))
+    }
+
+    /// The first child element with the given namespace and local name
+    pub(crate) fn child(&self, namespace: &str, name: &str) -> Option<&Element> {
+        self.elements().find(|element| element.is(namespace, name))
+    }
+
+    /// The text content of this element, without the one of its descendants
+    pub(crate) fn text(&self) -> String {
+        self.children
+            .iter()
+            .filter_map(|child| match child {
+                Node::Text(text) => Some(text.as_str()),
+                Node::Element(_) => None,
+            })
+            .collect()
+    }
+}
+
+fn split_qname(qname: &str) -> (Option<&str>, &str) {
+    match qname.split_once(':') {
+        Some((prefix, name)) => (Some(prefix), name),
+        None => (None, qname),
+    }
+}
+
+/// Resolve the namespace bound to a prefix. Unprefixed attributes don't take
+/// the default namespace, unlike elements.
+fn resolve(
+    namespaces: &BTreeMap<String, String>,
+    prefix: Option<&str>,
+    is_element: bool,
+) -> Result<Option<String>, XmlError> {
+    match prefix {
+        Some("xml") => Ok(Some(XML_NAMESPACE.to_owned())),
+        Some(prefix) => namespaces
+            .get(prefix)
+            .cloned()
+            .map(Some)
+            .ok_or_else(|| XmlError::UnboundPrefix(prefix.to_owned())),
+        None if is_element => Ok(namespaces.get("").cloned()),
+        None => Ok(None),
+    }
+}
+
+/// Normalize the line endings like XML processors do
+fn normalize_line_endings(text: &str) -> Cow<'_, str> {
+    if text.contains('\r') {
+        Cow::Owned(text.replace("\r\n", "\n").replace('\r', "\n"))
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+/// Normalize the whitespace of a raw attribute value like XML processors do.
+/// Whitespace written as character references is kept as is.
+fn normalize_attribute_value(value: &str) -> Cow<'_, str> {
+    let value = normalize_line_endings(value);
+    if value.contains(['\n', '\t']) {
+        Cow::Owned(value.replace(['\n', '\t'], " "))
+    } else {
+        value
+    }
+}
+
+/// Parse a document into a tree.
+///
+/// Comments and processing instructions are dropped, as they are not
+/// expected in SAML messages. Documents with a DTD are rejected.
+pub(crate) fn parse(document: &str) -> Result<Element, XmlError> {
+    let mut reader = Reader::from_str(document);
+    let mut stack: Vec<Element> = Vec::new();
+    let mut root = None;
+
+    loop {
+        let event = reader.read_event()?;
+        let (element, closed) = match event {
+            Event::Start(start) => (Some(start), false),
+            Event::Empty(start) => (Some(start), true),
+            Event::End(_) => (None, true),
+            Event::Text(text) => {
+                let text = normalize_line_endings(std::str::from_utf8(&text)?);
+                let text = unescape(&text)?.into_owned();
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(Node::Text(text)),
+                    None if text.trim().is_empty() => {}
+                    None => return Err(XmlError::InvalidRoot),
+                }
+                continue;
+            }
+            Event::CData(cdata) => {
+                let cdata = cdata.into_inner();
+                let text = normalize_line_endings(std::str::from_utf8(&cdata)?).into_owned();
+                let parent = stack.last_mut().ok_or(XmlError::InvalidRoot)?;
+                parent.children.push(Node::Text(text));
+                continue;
+            }
+            Event::DocType(_) => return Err(XmlError::DocType),
+            Event::Decl(_) | Event::Comment(_) | Event::PI(_) => continue,
+            Event::Eof => break,
+        };
+
+        if let Some(start) = element {
+            if root.is_some() {
+                return Err(XmlError::InvalidRoot);
+            }
+            let parent_namespaces = stack.last().map(|parent| &parent.namespaces);
+            stack.push(Element::from_start(&start, parent_namespaces)?);
+        }
+
+        if closed {
+            let element = stack.pop().ok_or(XmlError::InvalidRoot)?;
+            match stack.last_mut() {
+                Some(parent) => parent.children.push(Node::Element(element)),
+                None => root = Some(element),
+            }
+        }
+    }
+
+    if !stack.is_empty() {
+        return Err(XmlError::InvalidRoot);
+    }
+
+    root.ok_or(XmlError::InvalidRoot)
+}
+
+/// Write an attribute of an element being generated
+pub(crate) fn write_attribute(output: &mut String, name: &str, value: &str) {
+    output.push(' ');
+    output.push_str(name);
+    output.push_str("=\"");
+    crate::c14n::escape_attribute(output, value);
+    output.push('"');
+}
+
+/// Write an element with only text content
+pub(crate) fn write_text_element(output: &mut String, name: &str, text: &str) {
+    output.push('<');
+    output.push_str(name);
+    output.push('>');
+    crate::c14n::escape_text(output, text);
+    output.push_str("</");
+    output.push_str(name);
+    output.push('>');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_namespaces() {
+        let root = parse(
+            r#"<?xml version="1.0"?>
+<a:root xmlns:a="urn:a" xmlns="urn:default" b:attr="1" plain="2" xmlns:b="urn:b">
+  <child>Hello &amp; <![CDATA[<world>]]></child>
+  <a:other xmlns=""/>
+</a:root>"#,
+        )
+        .unwrap();
+
+        assert!(root.is("urn:a", "root"));
+        assert_eq!(root.prefix.as_deref(), Some("a"));
+        assert_eq!(root.attribute("plain"), Some("2"));
+        // Namespaced attributes are not returned as plain ones
+        assert_eq!(root.attribute("attr"), None);
+        assert_eq!(root.attributes[0].namespace.as_deref(), Some("urn:b"));
+
+        let child = root.child("urn:default", "child").unwrap();
+        assert_eq!(child.text(), "Hello & <world>");
+
+        let other = root.child("urn:a", "other").unwrap();
+        assert!(!other.namespaces.contains_key(""));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(matches!(
+            parse(r#"<!DOCTYPE a [<!ENTITY b "c">]><a>&b;</a>"#),
+            Err(XmlError::DocType)
+        ));
+        assert!(matches!(
+            parse("<a:root/>"),
+            Err(XmlError::UnboundPrefix(prefix)) if prefix == "a"
+        ));
+        assert!(matches!(parse("<a/><b/>"), Err(XmlError::InvalidRoot)));
+        assert!(parse("<a>").is_err());
+    }
+}
@@ -0,0 +1,217 @@
+# Generates the test certificates and signed SAML responses
+# Requires the `cryptography` python library
+#
+# The canonical forms of the signed elements are written by hand, so that
+# they check the canonicalization of the crate against an independent one.
+
+import base64
+import datetime
+import hashlib
+import re
+from pathlib import Path
+
+from cryptography import x509
+from cryptography.hazmat.primitives import hashes, serialization
+from cryptography.hazmat.primitives.asymmetric import padding, rsa
+from cryptography.x509.oid import NameOID
+
+output_path = Path(__file__).parent
+
+keys_path = output_path / "keys"
+keys_path.mkdir(parents=True, exist_ok=True)
+
+responses_path = output_path / "responses"
+responses_path.mkdir(parents=True, exist_ok=True)
+
+PROTOCOL = "urn:oasis:names:tc:SAML:2.0:protocol"
+ASSERTION = "urn:oasis:names:tc:SAML:2.0:assertion"
+DSIG = "http://www.w3.org/2000/09/xmldsig#"
+EXC_C14N = "http://www.w3.org/2001/10/xml-exc-c14n#"
+XS = "http://www.w3.org/2001/XMLSchema"
+XSI = "http://www.w3.org/2001/XMLSchema-instance"
+
+IDP = "https://idp.example.edu/idp/shibboleth"
+SP = "https://auth.example.com/upstream/saml/metadata"
+ACS = "https://auth.example.com/upstream/saml/acs"
+REQUEST_ID = "_request"
+
+
+def gen_certificate(name: str, common_name: str) -> rsa.RSAPrivateKey:
+    """Generate a key and a self-signed certificate for it"""
+    key = rsa.generate_private_key(public_exponent=65537, key_size=2048)
+    subject = x509.Name([x509.NameAttribute(NameOID.COMMON_NAME, common_name)])
+    certificate = (
+        x509.CertificateBuilder()
+        .subject_name(subject)
+        .issuer_name(subject)
+        .public_key(key.public_key())
+        .serial_number(x509.random_serial_number())
+        .not_valid_before(datetime.datetime(2023, 1, 1))
+        .not_valid_after(datetime.datetime(2123, 1, 1))
+        .sign(key, hashes.SHA256())
+    )
+
+    with open(keys_path / f"{name}.crt.pem", "wb") as f:
+        f.write(certificate.public_bytes(serialization.Encoding.PEM))
+
+    return key
+
+
+def sign(key: rsa.RSAPrivateKey, reference: str, canonical: str) -> str:
+    """Sign the canonical form of the element with the `reference` ID.
+
+    Returns the `Signature` element, with the `ds` prefix declared on it
+    """
+    digest = base64.b64encode(hashlib.sha256(canonical.encode()).digest()).decode()
+
+    signed_info = (
+        f'<ds:CanonicalizationMethod Algorithm="{EXC_C14N}"></ds:CanonicalizationMethod>'
+        '<ds:SignatureMethod Algorithm="http://www.w3.org/2001/04/xmldsig-more#rsa-sha256"></ds:SignatureMethod>'
+        f'<ds:Reference URI="#{reference}">'
+        "<ds:Transforms>"
+        f'<ds:Transform Algorithm="{DSIG}enveloped-signature"></ds:Transform>'
+        f'<ds:Transform Algorithm="{EXC_C14N}">'
+        f'<ec:InclusiveNamespaces xmlns:ec="{EXC_C14N}" PrefixList="xs"></ec:InclusiveNamespaces>'
+        "</ds:Transform>"
+        "</ds:Transforms>"
+        '<ds:DigestMethod Algorithm="http://www.w3.org/2001/04/xmlenc#sha256"></ds:DigestMethod>'
+        f"<ds:DigestValue>{digest}</ds:DigestValue>"
+        "</ds:Reference>"
+    )
+
+    # The `ds` prefix is declared on the `Signature` element, so the canonical
+    # form of `SignedInfo` declares it too
+    canonical_signed_info = f'<ds:SignedInfo xmlns:ds="{DSIG}">{signed_info}</ds:SignedInfo>'
+    value = key.sign(canonical_signed_info.encode(), padding.PKCS1v15(), hashes.SHA256())
+    value = base64.encodebytes(value).decode().strip()
+
+    signature = (
+        f'<ds:Signature xmlns:ds="{DSIG}">'
+        f"<ds:SignedInfo>{signed_info}</ds:SignedInfo>"
+        f"<ds:SignatureValue>\n{value}\n</ds:SignatureValue>"
+        "</ds:Signature>"
+    )
+
+    # Use self-closing tags like most identity providers do
+    return re.sub(r"<(ds:\w+|ec:\w+)([^>]*)></\1>", r"<\1\2/>", signature)
+
+
+def assertion_content() -> str:
+    """The content of the assertion after its issuer, in canonical form.
+
+    It doesn't declare any namespace, they are all declared on the root of
+    the response."""
+    return f"""
+    <saml:Subject>
+      <saml:NameID Format="urn:oasis:names:tc:SAML:2.0:nameid-format:persistent">AAdzZWNyZXQxtyj8Mv</saml:NameID>
+      <saml:SubjectConfirmation Method="urn:oasis:names:tc:SAML:2.0:cm:bearer">
+        <saml:SubjectConfirmationData InResponseTo="{REQUEST_ID}" NotOnOrAfter="2023-11-10T12:05:00Z" Recipient="{ACS}"></saml:SubjectConfirmationData>
+      </saml:SubjectConfirmation>
+    </saml:Subject>
+    <saml:Conditions NotBefore="2023-11-10T11:59:30Z" NotOnOrAfter="2023-11-10T12:05:00Z">
+      <saml:AudienceRestriction>
+        <saml:Audience>{SP}</saml:Audience>
+      </saml:AudienceRestriction>
+    </saml:Conditions>
+    <saml:AuthnStatement AuthnInstant="2023-11-10T11:59:58Z" SessionIndex="_session">
+      <saml:AuthnContext>
+        <saml:AuthnContextClassRef>urn:oasis:names:tc:SAML:2.0:ac:classes:PasswordProtectedTransport</saml:AuthnContextClassRef>
+      </saml:AuthnContext>
+    </saml:AuthnStatement>
+    <saml:AttributeStatement>
+      <saml:Attribute FriendlyName="mail" Name="urn:oid:0.9.2342.19200300.100.1.3" NameFormat="urn:oasis:names:tc:SAML:2.0:attrname-format:uri">
+        <saml:AttributeValue{{XSI}} xsi:type="xs:string">jane.doe@example.edu</saml:AttributeValue>
+      </saml:Attribute>
+      <saml:Attribute FriendlyName="sn" Name="urn:oid:2.5.4.4" NameFormat="urn:oasis:names:tc:SAML:2.0:attrname-format:uri">
+        <saml:AttributeValue{{XSI}} xsi:type="xs:string">Smith &amp; Jones</saml:AttributeValue>
+      </saml:Attribute>
+      <saml:Attribute FriendlyName="eduPersonAffiliation" Name="urn:oid:1.3.6.1.4.1.5923.1.1.1.1" NameFormat="urn:oasis:names:tc:SAML:2.0:attrname-format:uri">
+        <saml:AttributeValue{{XSI}} xsi:type="xs:string">member</saml:AttributeValue>
+        <saml:AttributeValue{{XSI}} xsi:type="xs:string">student</saml:AttributeValue>
+      </saml:Attribute>
+    </saml:AttributeStatement>
+  """
+
+
+def response(assertion: str, signature: str = "", saml_declaration: str = "") -> str:
+    """Wrap an assertion in a response.
+
+    The namespaces are all declared on the response"""
+    return (
+        f'<samlp:Response xmlns:samlp="{PROTOCOL}" xmlns:saml="{ASSERTION}" '
+        f'xmlns:xs="{XS}" xmlns:xsi="{XSI}" '
+        f'Destination="{ACS}" ID="_response" InResponseTo="{REQUEST_ID}" '
+        'IssueInstant="2023-11-10T12:00:00Z" Version="2.0">\n'
+        f"  <saml:Issuer{saml_declaration}>{IDP}</saml:Issuer>{signature}\n"
+        "  <samlp:Status>\n"
+        '    <samlp:StatusCode Value="urn:oasis:names:tc:SAML:2.0:status:Success"/>\n'
+        "  </samlp:Status>\n"
+        f"  {assertion}\n"
+        "</samlp:Response>\n"
+    )
+
+
+def signed_assertion(key: rsa.RSAPrivateKey):
+    """A response with an unsigned response and a signed assertion"""
+    start = '<saml:Assertion ID="_assertion" IssueInstant="2023-11-10T12:00:00Z" Version="2.0">'
+    issuer = f"\n    <saml:Issuer>{IDP}</saml:Issuer>"
+    content = assertion_content()
+    end = "</saml:Assertion>"
+
+    # The namespaces used are declared on the assertion, along with `xs` which
+    # is in the inclusive namespaces list. `xsi` is only declared where it is
+    # used.
+    canonical = (
+        start.replace(
+            '<saml:Assertion',
+            f'<saml:Assertion xmlns:saml="{ASSERTION}" xmlns:xs="{XS}"',
+        )
+        + issuer
+        + content.replace("{XSI}", f' xmlns:xsi="{XSI}"')
+        + end
+    )
+    signature = sign(key, "_assertion", canonical)
+    assertion = start + issuer + signature + content.replace("{XSI}", "") + end
+
+    with open(responses_path / "signed-assertion.xml", "w") as f:
+        f.write(response(assertion))
+
+
+def signed_response(key: rsa.RSAPrivateKey):
+    """A response with a signed response and an unsigned assertion"""
+    assertion = (
+        '<saml:Assertion{SAML} ID="_assertion" IssueInstant="2023-11-10T12:00:00Z" Version="2.0">'
+        f"\n    <saml:Issuer>{IDP}</saml:Issuer>"
+        + assertion_content()
+        + "</saml:Assertion>"
+    )
+
+    # The `saml` prefix is declared on each of the top-level elements using it
+    saml = f' xmlns:saml="{ASSERTION}"'
+    xsi = f' xmlns:xsi="{XSI}"'
+    canonical_assertion = assertion.replace("{SAML}", saml).replace("{XSI}", xsi)
+    canonical = (
+        f'<samlp:Response xmlns:samlp="{PROTOCOL}" xmlns:xs="{XS}" '
+        f'Destination="{ACS}" ID="_response" InResponseTo="{REQUEST_ID}" '
+        'IssueInstant="2023-11-10T12:00:00Z" Version="2.0">\n'
+        f"  <saml:Issuer{saml}>{IDP}</saml:Issuer>\n"
+        "  <samlp:Status>\n"
+        '    <samlp:StatusCode Value="urn:oasis:names:tc:SAML:2.0:status:Success"></samlp:StatusCode>\n'
+        "  </samlp:Status>\n"
+        f"  {canonical_assertion}\n"
+        "</samlp:Response>"
+    )
+    signature = sign(key, "_response", canonical)
+    document = response(
+        assertion.replace("{SAML}", "").replace("{XSI}", ""), signature=signature
+    )
+
+    with open(responses_path / "signed-response.xml", "w") as f:
+        f.write(document)
+
+
+idp_key = gen_certificate("idp", "idp.example.edu")
+gen_certificate("other", "other.example.edu")
+
+signed_assertion(idp_key)
+signed_response(idp_key)
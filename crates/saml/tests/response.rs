@@ -0,0 +1,247 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use base64ct::{Base64, Encoding};
+use chrono::{DateTime, TimeZone, Utc};
+use mas_saml::{
+    response::{validate_response, Assertion, Attribute, ResponseError, ValidationData},
+    Certificate, SignatureError,
+};
+use url::Url;
+
+static SIGNED_ASSERTION: &str = include_str!("./responses/signed-assertion.xml");
+static SIGNED_RESPONSE: &str = include_str!("./responses/signed-response.xml");
+static IDP_CERTIFICATE: &str = include_str!("./keys/idp.crt.pem");
+static OTHER_CERTIFICATE: &str = include_str!("./keys/other.crt.pem");
+
+const IDP: &str = "https://idp.example.edu/idp/shibboleth";
+const SP: &str = "https://auth.example.com/upstream/saml/metadata";
+const ACS: &str = "https://auth.example.com/upstream/saml/acs";
+const REQUEST_ID: &str = "_request";
+
+fn now() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2023, 11, 10, 12, 0, 1).unwrap()
+}
+
+fn validate(
+    document: &str,
+    certificate: &str,
+    now: DateTime<Utc>,
+) -> Result<Assertion, ResponseError> {
+    let acs = Url::parse(ACS).unwrap();
+    let certificates = [certificate.parse::<Certificate>().unwrap()];
+    let data = ValidationData {
+        idp_entity_id: IDP,
+        sp_entity_id: SP,
+        assertion_consumer_service_url: &acs,
+        request_id: REQUEST_ID,
+        certificates: &certificates,
+    };
+
+    validate_response(&Base64::encode_string(document.as_bytes()), &data, now)
+}
+
+fn assert_expected(assertion: &Assertion) {
+    assert_eq!(assertion.name_id, "AAdzZWNyZXQxtyj8Mv");
+    assert_eq!(
+        assertion.name_id_format.as_deref(),
+        Some("urn:oasis:names:tc:SAML:2.0:nameid-format:persistent")
+    );
+    assert_eq!(
+        assertion.attributes,
+        vec![
+            Attribute {
+                name: "urn:oid:0.9.2342.19200300.100.1.3".to_owned(),
+                friendly_name: Some("mail".to_owned()),
+                values: vec!["jane.doe@example.edu".to_owned()],
+            },
+            Attribute {
+                name: "urn:oid:2.5.4.4".to_owned(),
+                friendly_name: Some("sn".to_owned()),
+                values: vec!["Smith & Jones".to_owned()],
+            },
+            Attribute {
+                name: "urn:oid:1.3.6.1.4.1.5923.1.1.1.1".to_owned(),
+                friendly_name: Some("eduPersonAffiliation".to_owned()),
+                values: vec!["member".to_owned(), "student".to_owned()],
+            },
+        ]
+    );
+}
+
+/// Remove the signature from a document
+fn strip_signature(document: &str) -> String {
+    let start = document.find("<ds:Signature").unwrap();
+    let end = document.find("</ds:Signature>").unwrap() + "</ds:Signature>".len();
+    format!("{}{}", &document[..start], &document[end..])
+}
+
+#[test]
+fn validate_signed_assertion() {
+    let assertion = validate(SIGNED_ASSERTION, IDP_CERTIFICATE, now()).unwrap();
+    assert_expected(&assertion);
+}
+
+#[test]
+fn validate_signed_response() {
+    let assertion = validate(SIGNED_RESPONSE, IDP_CERTIFICATE, now()).unwrap();
+    assert_expected(&assertion);
+}
+
+#[test]
+fn reject_other_certificate() {
+    for document in [SIGNED_ASSERTION, SIGNED_RESPONSE] {
+        let error = validate(document, OTHER_CERTIFICATE, now()).unwrap_err();
+        assert!(matches!(
+            error,
+            ResponseError::Signature(SignatureError::Invalid)
+        ));
+    }
+}
+
+#[test]
+fn reject_tampered() {
+    for document in [SIGNED_ASSERTION, SIGNED_RESPONSE] {
+        let document = document.replace("AAdzZWNyZXQxtyj8Mv", "someone-else");
+        let error = validate(&document, IDP_CERTIFICATE, now()).unwrap_err();
+        assert!(matches!(
+            error,
+            ResponseError::Signature(SignatureError::DigestMismatch)
+        ));
+    }
+
+    // Comments are not part of the canonical form, but they must not be used
+    // to truncate values either
+    let document = SIGNED_ASSERTION.replace(
+        "jane.doe@example.edu",
+        "jane.doe@example.edu<!-- comment -->",
+    );
+    let assertion = validate(&document, IDP_CERTIFICATE, now()).unwrap();
+    assert_expected(&assertion);
+}
+
+#[test]
+fn reject_unsigned() {
+    for document in [SIGNED_ASSERTION, SIGNED_RESPONSE] {
+        let document = strip_signature(document);
+        let error = validate(&document, IDP_CERTIFICATE, now()).unwrap_err();
+        assert!(matches!(error, ResponseError::Unsigned));
+    }
+}
+
+#[test]
+fn reject_additional_assertion() {
+    // Another assertion can't be smuggled next to the signed one
+    let start = SIGNED_ASSERTION.find("<saml:Assertion").unwrap();
+    let end = SIGNED_ASSERTION.find("</saml:Assertion>").unwrap() + "</saml:Assertion>".len();
+    let forged = strip_signature(&SIGNED_ASSERTION[start..end])
+        .replace("_assertion", "_forged")
+        .replace("AAdzZWNyZXQxtyj8Mv", "someone-else");
+    let document = SIGNED_ASSERTION.replace(
+        "  <saml:Assertion",
+        &format!("  {forged}\n  <saml:Assertion"),
+    );
+
+    let error = validate(&document, IDP_CERTIFICATE, now()).unwrap_err();
+    assert!(matches!(error, ResponseError::AssertionCount));
+}
+
+#[test]
+fn reject_invalid_time() {
+    let error = validate(
+        SIGNED_ASSERTION,
+        IDP_CERTIFICATE,
+        Utc.with_ymd_and_hms(2023, 11, 10, 12, 10, 0).unwrap(),
+    )
+    .unwrap_err();
+    assert!(matches!(error, ResponseError::Expired));
+
+    let error = validate(
+        SIGNED_ASSERTION,
+        IDP_CERTIFICATE,
+        Utc.with_ymd_and_hms(2023, 11, 10, 11, 50, 0).unwrap(),
+    )
+    .unwrap_err();
+    assert!(matches!(error, ResponseError::NotYetValid));
+
+    // Small clock differences are tolerated
+    validate(
+        SIGNED_ASSERTION,
+        IDP_CERTIFICATE,
+        Utc.with_ymd_and_hms(2023, 11, 10, 12, 6, 0).unwrap(),
+    )
+    .unwrap();
+}
+
+#[test]
+fn reject_other_request() {
+    let acs = Url::parse(ACS).unwrap();
+    let certificates = [IDP_CERTIFICATE.parse::<Certificate>().unwrap()];
+    let encoded = Base64::encode_string(SIGNED_ASSERTION.as_bytes());
+    let data = ValidationData {
+        idp_entity_id: IDP,
+        sp_entity_id: SP,
+        assertion_consumer_service_url: &acs,
+        request_id: REQUEST_ID,
+        certificates: &certificates,
+    };
+
+    let error = validate_response(
+        &encoded,
+        &ValidationData {
+            request_id: "_other",
+            ..data.clone()
+        },
+        now(),
+    )
+    .unwrap_err();
+    assert!(matches!(error, ResponseError::Invalid("InResponseTo")));
+
+    let error = validate_response(
+        &encoded,
+        &ValidationData {
+            sp_entity_id: "https://other.example.com/metadata",
+            ..data.clone()
+        },
+        now(),
+    )
+    .unwrap_err();
+    assert!(matches!(error, ResponseError::AudienceMismatch));
+
+    let error = validate_response(
+        &encoded,
+        &ValidationData {
+            idp_entity_id: "https://other.example.edu/idp",
+            ..data
+        },
+        now(),
+    )
+    .unwrap_err();
+    assert!(matches!(error, ResponseError::Invalid("Issuer")));
+}
+
+#[test]
+fn reject_error_status() {
+    let document = SIGNED_ASSERTION.replace(
+        r#"<samlp:StatusCode Value="urn:oasis:names:tc:SAML:2.0:status:Success"/>"#,
+        r#"<samlp:StatusCode Value="urn:oasis:names:tc:SAML:2.0:status:Responder"><samlp:StatusCode Value="urn:oasis:names:tc:SAML:2.0:status:AuthnFailed"/></samlp:StatusCode><samlp:StatusMessage>Nope</samlp:StatusMessage>"#,
+    );
+    let error = validate(&document, IDP_CERTIFICATE, now()).unwrap_err();
+    assert!(matches!(
+        error,
+        ResponseError::Status { code, message }
+            if code == "urn:oasis:names:tc:SAML:2.0:status:AuthnFailed"
+                && message.as_deref() == Some("Nope")
+    ));
+}
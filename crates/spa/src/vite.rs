@@ -175,6 +175,16 @@ impl<'a> Asset<'a> {
 }
 
 impl Manifest {
+    /// All the files produced by the build, which have a content-hashed name
+    pub fn files(&self) -> impl Iterator<Item = &Utf8Path> {
+        self.inner.values().flat_map(|entry| {
+            std::iter::once(&entry.file)
+                .chain(entry.css.iter().flatten())
+                .chain(entry.assets.iter().flatten())
+                .map(Utf8PathBuf::as_path)
+        })
+    }
+
     /// Find all assets which should be loaded for a given entrypoint
     ///
     /// # Errors
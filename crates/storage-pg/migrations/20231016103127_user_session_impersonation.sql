@@ -0,0 +1,22 @@
+-- Copyright 2023 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+-- Record on browser sessions which were started by an administrator to act on
+-- behalf of a user, and until when that session is valid
+ALTER TABLE "user_sessions"
+    ADD COLUMN "impersonator_user_id" UUID
+        REFERENCES "users" ("user_id")
+        DEFAULT NULL,
+    ADD COLUMN "impersonation_expires_at" TIMESTAMP WITH TIME ZONE
+        DEFAULT NULL;
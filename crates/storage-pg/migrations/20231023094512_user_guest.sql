@@ -0,0 +1,22 @@
+-- Copyright 2023 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+-- Guests are throwaway accounts, deactivated after a while unless upgraded
+ALTER TABLE "users"
+  ADD COLUMN "is_guest" BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Used to find the guests to clean up
+CREATE INDEX "users_guest_created_at_idx"
+  ON "users" ("created_at")
+  WHERE "is_guest";
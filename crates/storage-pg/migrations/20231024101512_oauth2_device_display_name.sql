@@ -0,0 +1,21 @@
+-- Copyright 2023 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+-- The device display name requested by the client during authorization
+ALTER TABLE "oauth2_authorization_grants"
+  ADD COLUMN "device_display_name" TEXT;
+
+-- A human-readable name for the session, used as the device display name
+ALTER TABLE "oauth2_sessions"
+  ADD COLUMN "human_name" TEXT;
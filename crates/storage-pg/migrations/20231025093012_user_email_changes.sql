@@ -0,0 +1,49 @@
+-- Copyright 2023 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+-- Changes of the primary email address of users. Once the new address is
+-- verified, the change can be reverted from the old address for a while
+CREATE TABLE "user_email_changes" (
+  "user_email_change_id" UUID NOT NULL
+    CONSTRAINT "user_email_changes_pkey"
+    PRIMARY KEY,
+
+  "user_id" UUID NOT NULL
+    CONSTRAINT "user_email_changes_user_id_fkey"
+    REFERENCES "users" ("user_id"),
+
+  "old_user_email_id" UUID NOT NULL
+    CONSTRAINT "user_email_changes_old_user_email_id_fkey"
+    REFERENCES "user_emails" ("user_email_id")
+    ON DELETE CASCADE,
+
+  "new_user_email_id" UUID NOT NULL
+    CONSTRAINT "user_email_changes_new_user_email_id_fkey"
+    REFERENCES "user_emails" ("user_email_id")
+    ON DELETE CASCADE,
+
+  "revert_token" TEXT NOT NULL
+    CONSTRAINT "user_email_changes_revert_token_unique"
+    UNIQUE,
+
+  "created_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+  "completed_at" TIMESTAMP WITH TIME ZONE,
+  "reverted_at" TIMESTAMP WITH TIME ZONE
+);
+
+-- Used to find the pending change when the new address gets verified
+CREATE INDEX "user_email_changes_new_user_email_id_idx"
+  ON "user_email_changes" ("new_user_email_id")
+  WHERE "completed_at" IS NULL
+    AND "reverted_at" IS NULL;
@@ -0,0 +1,38 @@
+-- Copyright 2023 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+-- Verification emails sent to users, used to rate limit them. Those don't
+-- reference the user email, so that removing and adding back an address
+-- doesn't reset the counters
+CREATE TABLE "user_email_sends" (
+  "user_email_send_id" UUID NOT NULL
+    CONSTRAINT "user_email_sends_pkey"
+    PRIMARY KEY,
+
+  "user_id" UUID NOT NULL
+    CONSTRAINT "user_email_sends_user_id_fkey"
+    REFERENCES "users" ("user_id"),
+
+  "email" TEXT NOT NULL,
+
+  "created_at" TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+-- Used to count the emails sent to an address, regardless of its case
+CREATE INDEX "user_email_sends_email_created_at_idx"
+  ON "user_email_sends" (LOWER("email"), "created_at");
+
+-- Used to count the emails sent for a user
+CREATE INDEX "user_email_sends_user_id_created_at_idx"
+  ON "user_email_sends" ("user_id", "created_at");
@@ -0,0 +1,29 @@
+-- Copyright 2023 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+-- The token buckets of the rate limiters, when they are shared between
+-- instances. The table is unlogged: losing its content on a crash only resets
+-- the rate limits, which is not worth the cost of writing to the WAL.
+CREATE UNLOGGED TABLE "rate_limit_buckets" (
+  "limiter" TEXT NOT NULL,
+  "key" TEXT NOT NULL,
+  "tokens" DOUBLE PRECISION NOT NULL,
+  "updated_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  CONSTRAINT "rate_limit_buckets_pkey"
+    PRIMARY KEY ("limiter", "key")
+);
+
+CREATE INDEX "rate_limit_buckets_limiter_updated_at_idx"
+  ON "rate_limit_buckets" ("limiter", "updated_at");
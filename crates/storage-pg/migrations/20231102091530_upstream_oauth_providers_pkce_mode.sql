@@ -0,0 +1,19 @@
+-- Copyright 2023 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+-- Whether to use PKCE when talking to the upstream provider: 'auto' to use it
+-- only if the provider advertises support for it, 's256' to always use it, or
+-- 'disabled' to never use it
+ALTER TABLE "upstream_oauth_providers"
+  ADD COLUMN "pkce_mode" TEXT NOT NULL DEFAULT 'auto';
@@ -0,0 +1,24 @@
+-- Copyright 2023 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+-- A stable identifier of the provider, used in its callback URL instead of its
+-- ID. It allows configuring several providers with the same issuer in a way
+-- which is recognizable on the provider side.
+--
+-- The constraint is only checked at the end of the transaction, so that slugs
+-- can be swapped between providers.
+ALTER TABLE "upstream_oauth_providers"
+  ADD COLUMN "slug" TEXT,
+  ADD CONSTRAINT "upstream_oauth_providers_slug_unique"
+    UNIQUE ("slug") DEFERRABLE INITIALLY DEFERRED;
@@ -0,0 +1,20 @@
+-- Copyright 2023 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+-- The discovery document and the JWKS last fetched from the provider, kept up to
+-- date by a background job, so that logins don't depend on fetching them live
+ALTER TABLE "upstream_oauth_providers"
+  ADD COLUMN "discovery_metadata" JSONB,
+  ADD COLUMN "jwks" JSONB,
+  ADD COLUMN "metadata_fetched_at" TIMESTAMP WITH TIME ZONE;
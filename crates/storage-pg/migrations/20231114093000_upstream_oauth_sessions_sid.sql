@@ -0,0 +1,22 @@
+-- Copyright 2023 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+
+-- The session ID of the upstream provider, from the `sid` claim of the ID
+-- token, used to handle back-channel logouts
+ALTER TABLE "upstream_oauth_authorization_sessions"
+  ADD COLUMN "sid" TEXT;
+
+CREATE INDEX "upstream_oauth_authorization_sessions_sid_idx"
+  ON "upstream_oauth_authorization_sessions" ("upstream_oauth_provider_id", "sid");
@@ -0,0 +1,23 @@
+-- Copyright 2023 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+
+-- What is needed to keep up to date the credentials obtained by registering
+-- with the provider through dynamic client registration. The credentials
+-- themselves replace the client ID and secret of the provider
+ALTER TABLE "upstream_oauth_providers"
+  ADD COLUMN "encrypted_registration_access_token" TEXT,
+  ADD COLUMN "registration_client_uri" TEXT,
+  ADD COLUMN "client_secret_expires_at" TIMESTAMP WITH TIME ZONE,
+  ADD COLUMN "registered_at" TIMESTAMP WITH TIME ZONE;
@@ -0,0 +1,20 @@
+-- Copyright 2023 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+-- How the provider is checked by the background health probe, and the outcome
+-- of the last check. A NULL error with a check date means it was healthy
+ALTER TABLE "upstream_oauth_providers"
+  ADD COLUMN "health_check" JSONB NOT NULL DEFAULT '{}',
+  ADD COLUMN "health_error" TEXT,
+  ADD COLUMN "health_checked_at" TIMESTAMP WITH TIME ZONE;
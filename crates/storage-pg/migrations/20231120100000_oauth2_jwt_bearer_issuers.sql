@@ -0,0 +1,45 @@
+-- Copyright 2023 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+-- Whether the client can use the JWT bearer grant type
+ALTER TABLE "oauth2_clients"
+  ADD COLUMN "grant_type_jwt_bearer" BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- The external issuers trusted to sign assertions for the JWT bearer grant
+CREATE TABLE "oauth2_jwt_bearer_issuers" (
+  "oauth2_jwt_bearer_issuer_id" UUID NOT NULL
+    CONSTRAINT "oauth2_jwt_bearer_issuers_pkey"
+    PRIMARY KEY,
+
+  -- The `iss` claim of the assertions
+  "issuer" TEXT NOT NULL
+    CONSTRAINT "oauth2_jwt_bearer_issuers_issuer_key"
+    UNIQUE,
+
+  -- The keys used to verify the assertions, either by value or by reference
+  "jwks" JSONB,
+  "jwks_uri" TEXT,
+
+  -- The usernames the issuer can sign assertions for, NULL meaning any user
+  "allowed_subjects" TEXT[],
+
+  -- The scopes which can be obtained with the assertions, NULL meaning they
+  -- are only restricted by the policy
+  "allowed_scope" TEXT[],
+
+  "created_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  CONSTRAINT "oauth2_jwt_bearer_issuers_jwks_check"
+    CHECK (("jwks" IS NULL) <> ("jwks_uri" IS NULL))
+);
@@ -0,0 +1,20 @@
+-- Copyright 2023 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+-- Per-client overrides of the lifetime of the tokens, in seconds, and of their
+-- audience. NULL lifetimes mean the global defaults apply
+ALTER TABLE "oauth2_clients"
+  ADD COLUMN "access_token_ttl" INTEGER,
+  ADD COLUMN "refresh_token_ttl" INTEGER,
+  ADD COLUMN "additional_audiences" TEXT[] NOT NULL DEFAULT '{}';
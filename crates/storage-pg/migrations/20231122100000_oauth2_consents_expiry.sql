@@ -0,0 +1,23 @@
+-- Copyright 2023 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+-- Consents can now be remembered for a limited time. Existing consents, which
+-- have no expiry, are kept forever
+ALTER TABLE "oauth2_consents"
+  ADD COLUMN "expires_at" TIMESTAMP WITH TIME ZONE;
+
+-- Whether the user consented to the grant itself, which lets it go through
+-- even if the consent was not remembered
+ALTER TABLE "oauth2_authorization_grants"
+  ADD COLUMN "consent_given" BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,22 @@
+-- Copyright 2023 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+-- How the redirect URIs of authorization requests are matched against the
+-- registered ones: 'exact', 'loopback' or 'subdomain_wildcard'.
+--
+-- Loopback redirect URIs used to match on any port for all clients, so keep
+-- it that way by default. Static clients can opt out of it in the
+-- configuration
+ALTER TABLE "oauth2_clients"
+  ADD COLUMN "redirect_uri_matching" TEXT NOT NULL DEFAULT 'loopback';
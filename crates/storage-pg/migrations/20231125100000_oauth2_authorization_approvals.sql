@@ -0,0 +1,32 @@
+-- Copyright 2023 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+-- Whether the authorization requests of the client are held until an
+-- administrator approves them
+ALTER TABLE "oauth2_clients"
+  ADD COLUMN "requires_approval" BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- The admin approval of authorization grants of those clients: who asked for
+-- it, when, and when it was approved
+ALTER TABLE "oauth2_authorization_grants"
+  ADD COLUMN "approval_user_id" UUID
+    REFERENCES "users" ("user_id") ON DELETE CASCADE,
+  ADD COLUMN "approval_requested_at" TIMESTAMP WITH TIME ZONE,
+  ADD COLUMN "approved_at" TIMESTAMP WITH TIME ZONE;
+
+CREATE INDEX "oauth2_authorization_grants_pending_approval_idx"
+  ON "oauth2_authorization_grants" ("approval_requested_at")
+  WHERE "approval_requested_at" IS NOT NULL
+    AND "approved_at" IS NULL
+    AND "cancelled_at" IS NULL;
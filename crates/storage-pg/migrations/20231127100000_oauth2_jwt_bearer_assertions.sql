@@ -0,0 +1,46 @@
+-- Copyright 2023 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+-- Issuers no longer sign assertions for any user by default: the '*' subject
+-- has to be set explicitly
+UPDATE "oauth2_jwt_bearer_issuers"
+  SET "allowed_subjects" = '{}'
+  WHERE "allowed_subjects" IS NULL;
+
+ALTER TABLE "oauth2_jwt_bearer_issuers"
+  ALTER COLUMN "allowed_subjects" SET DEFAULT '{}',
+  ALTER COLUMN "allowed_subjects" SET NOT NULL,
+
+  -- The clients which can exchange the assertions of the issuer
+  ADD COLUMN "allowed_clients" UUID[] NOT NULL DEFAULT '{}';
+
+-- The assertions which were exchanged, remembered until they expire so that
+-- they can't be replayed
+CREATE TABLE "oauth2_jwt_bearer_assertions" (
+  "oauth2_jwt_bearer_issuer_id" UUID NOT NULL
+    REFERENCES "oauth2_jwt_bearer_issuers" ("oauth2_jwt_bearer_issuer_id")
+    ON DELETE CASCADE,
+
+  -- The `jti` claim of the assertion
+  "jti" TEXT NOT NULL,
+
+  "created_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+  "expires_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  CONSTRAINT "oauth2_jwt_bearer_assertions_pkey"
+    PRIMARY KEY ("oauth2_jwt_bearer_issuer_id", "jti")
+);
+
+CREATE INDEX "oauth2_jwt_bearer_assertions_expires_at_idx"
+  ON "oauth2_jwt_bearer_assertions" ("expires_at");
@@ -0,0 +1,52 @@
+-- Copyright 2023 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+-- Audit log of the administrators impersonating users, kept even after the
+-- impersonation sessions are gone
+CREATE TABLE "user_impersonations" (
+  "user_impersonation_id" UUID NOT NULL
+    CONSTRAINT "user_impersonations_pkey"
+    PRIMARY KEY,
+
+  -- The administrator who impersonated the user
+  "impersonator_user_id" UUID NOT NULL
+    REFERENCES "users" ("user_id"),
+
+  -- The user who was impersonated
+  "user_id" UUID NOT NULL
+    REFERENCES "users" ("user_id"),
+
+  -- The browser session used to impersonate the user
+  "user_session_id" UUID NOT NULL
+    REFERENCES "user_sessions" ("user_session_id"),
+
+  "reason" TEXT NOT NULL,
+
+  "started_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  -- When the impersonation session expires, unless it ends earlier
+  "expires_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  -- Set if the impersonation session was ended before it expired
+  "ended_at" TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX "user_impersonations_impersonator_user_id_idx"
+  ON "user_impersonations" ("impersonator_user_id");
+
+CREATE INDEX "user_impersonations_user_id_idx"
+  ON "user_impersonations" ("user_id");
+
+CREATE INDEX "user_impersonations_user_session_id_idx"
+  ON "user_impersonations" ("user_session_id");
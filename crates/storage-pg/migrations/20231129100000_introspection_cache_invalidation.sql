@@ -0,0 +1,56 @@
+-- Copyright 2023 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+
+-- Tell every server instance when a user or a session changes, so that they
+-- can forget the introspection results they cached about it. The payload is
+-- the kind of object followed by its ID, e.g. `user:<uuid>`, and
+-- notifications are only delivered once the transaction commits.
+CREATE FUNCTION "notify_introspection_cache"()
+  RETURNS TRIGGER
+  AS $$
+    BEGIN
+      PERFORM pg_notify(
+        'mas::introspection_cache',
+        TG_ARGV[0] || ':' || (to_jsonb(NEW) ->> TG_ARGV[1])
+      );
+      RETURN NULL;
+    END;
+  $$ LANGUAGE plpgsql;
+
+CREATE TRIGGER "users_notify_introspection_cache"
+  AFTER UPDATE ON "users"
+  FOR EACH ROW
+  WHEN (OLD IS DISTINCT FROM NEW)
+  EXECUTE FUNCTION "notify_introspection_cache"('user', 'user_id');
+
+CREATE TRIGGER "oauth2_sessions_notify_introspection_cache"
+  AFTER UPDATE OF "finished_at", "scope_list" ON "oauth2_sessions"
+  FOR EACH ROW
+  EXECUTE FUNCTION "notify_introspection_cache"('session', 'oauth2_session_id');
+
+CREATE TRIGGER "oauth2_access_tokens_notify_introspection_cache"
+  AFTER UPDATE OF "revoked_at" ON "oauth2_access_tokens"
+  FOR EACH ROW
+  EXECUTE FUNCTION "notify_introspection_cache"('session', 'oauth2_session_id');
+
+CREATE TRIGGER "compat_sessions_notify_introspection_cache"
+  AFTER UPDATE OF "finished_at" ON "compat_sessions"
+  FOR EACH ROW
+  EXECUTE FUNCTION "notify_introspection_cache"('session', 'compat_session_id');
+
+CREATE TRIGGER "compat_access_tokens_notify_introspection_cache"
+  AFTER UPDATE OF "expires_at" ON "compat_access_tokens"
+  FOR EACH ROW
+  EXECUTE FUNCTION "notify_introspection_cache"('session', 'compat_session_id');
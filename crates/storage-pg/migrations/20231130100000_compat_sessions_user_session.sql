@@ -0,0 +1,25 @@
+-- Copyright 2023 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+
+-- The browser session from which a compatibility session was started through
+-- the SSO login, so that it can be ended along with it
+ALTER TABLE "compat_sessions"
+  ADD COLUMN "user_session_id" UUID
+    CONSTRAINT "compat_sessions_user_session_id_fkey"
+    REFERENCES "user_sessions" ("user_session_id")
+    ON DELETE SET NULL;
+
+CREATE INDEX "compat_sessions_user_session_id_idx"
+  ON "compat_sessions" ("user_session_id");
@@ -0,0 +1,34 @@
+-- Copyright 2023 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+
+-- The logout tokens received from the upstream providers, remembered until
+-- they expire so that they can't be replayed
+CREATE TABLE "upstream_oauth_logout_tokens" (
+  "upstream_oauth_provider_id" UUID NOT NULL
+    REFERENCES "upstream_oauth_providers" ("upstream_oauth_provider_id")
+    ON DELETE CASCADE,
+
+  -- The `jti` claim of the logout token
+  "jti" TEXT NOT NULL,
+
+  "created_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+  "expires_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  CONSTRAINT "upstream_oauth_logout_tokens_pkey"
+    PRIMARY KEY ("upstream_oauth_provider_id", "jti")
+);
+
+CREATE INDEX "upstream_oauth_logout_tokens_expires_at_idx"
+  ON "upstream_oauth_logout_tokens" ("expires_at");
@@ -0,0 +1,35 @@
+-- Copyright 2023 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+-- Once the new address is verified, the old address has to confirm the change
+-- before the switch-over. After the grace period, the change is finished and
+-- the old address is removed.
+ALTER TABLE "user_email_changes"
+  ADD COLUMN "verified_at" TIMESTAMP WITH TIME ZONE,
+  ADD COLUMN "finished_at" TIMESTAMP WITH TIME ZONE;
+
+-- The old address must not disappear while the change can still be confirmed
+-- or reverted from it, so it can't be removed without explicitly cleaning up
+-- the change first
+ALTER TABLE "user_email_changes"
+  DROP CONSTRAINT "user_email_changes_old_user_email_id_fkey",
+  ADD CONSTRAINT "user_email_changes_old_user_email_id_fkey"
+    FOREIGN KEY ("old_user_email_id")
+    REFERENCES "user_emails" ("user_email_id");
+
+-- Used to find the change in progress of a user
+CREATE INDEX "user_email_changes_user_id_idx"
+  ON "user_email_changes" ("user_id")
+  WHERE "reverted_at" IS NULL
+    AND "finished_at" IS NULL;
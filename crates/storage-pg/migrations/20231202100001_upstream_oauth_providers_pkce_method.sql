@@ -0,0 +1,26 @@
+-- Copyright 2023 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+-- The PKCE setting of the providers uses the same names as in the
+-- configuration: 'auto', 'always' or 'never'
+ALTER TABLE "upstream_oauth_providers"
+  RENAME COLUMN "pkce_mode" TO "pkce_method";
+
+UPDATE "upstream_oauth_providers"
+  SET "pkce_method" = 'always'
+  WHERE "pkce_method" = 's256';
+
+UPDATE "upstream_oauth_providers"
+  SET "pkce_method" = 'never'
+  WHERE "pkce_method" = 'disabled';
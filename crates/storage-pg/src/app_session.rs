@@ -75,6 +75,7 @@ mod priv_ {
         pub(super) is_synapse_admin: Option<bool>,
         pub(super) last_active_at: Option<DateTime<Utc>>,
         pub(super) last_active_ip: Option<IpAddr>,
+        pub(super) human_name: Option<String>,
     }
 }
 
@@ -100,6 +101,7 @@ impl TryFrom<AppSessionLookup> for AppSession {
             is_synapse_admin,
             last_active_at,
             last_active_ip,
+            human_name,
         } = value;
 
         match (
@@ -116,7 +118,7 @@ impl TryFrom<AppSessionLookup> for AppSession {
                 Some(compat_session_id),
                 None,
                 None,
-                None,
+                user_session_id,
                 Some(user_id),
                 None,
                 Some(device_id),
@@ -140,6 +142,7 @@ impl TryFrom<AppSessionLookup> for AppSession {
                     state,
                     user_id: user_id.into(),
                     device,
+                    user_session_id: user_session_id.map(Ulid::from),
                     created_at,
                     is_synapse_admin,
                     last_active_at,
@@ -184,6 +187,7 @@ impl TryFrom<AppSessionLookup> for AppSession {
                     scope,
                     last_active_at,
                     last_active_ip,
+                    human_name,
                 };
 
                 Ok(AppSession::OAuth2(Box::new(session)))
@@ -258,6 +262,10 @@ impl<'c> AppSessionRepository for PgAppSessionRepository<'c> {
                 Expr::col((OAuth2Sessions::Table, OAuth2Sessions::LastActiveIp)),
                 AppSessionLookupIden::LastActiveIp,
             )
+            .expr_as(
+                Expr::col((OAuth2Sessions::Table, OAuth2Sessions::HumanName)),
+                AppSessionLookupIden::HumanName,
+            )
             .from(OAuth2Sessions::Table)
             .and_where_option(filter.user().map(|user| {
                 Expr::col((OAuth2Sessions::Table, OAuth2Sessions::UserId)).eq(Uuid::from(user.id))
@@ -288,7 +296,10 @@ impl<'c> AppSessionRepository for PgAppSessionRepository<'c> {
             )
             .expr_as(Expr::cust("NULL"), AppSessionLookupIden::Oauth2SessionId)
             .expr_as(Expr::cust("NULL"), AppSessionLookupIden::Oauth2ClientId)
-            .expr_as(Expr::cust("NULL"), AppSessionLookupIden::UserSessionId)
+            .expr_as(
+                Expr::col((CompatSessions::Table, CompatSessions::UserSessionId)),
+                AppSessionLookupIden::UserSessionId,
+            )
             .expr_as(
                 Expr::col((CompatSessions::Table, CompatSessions::UserId)),
                 AppSessionLookupIden::UserId,
@@ -318,6 +329,7 @@ impl<'c> AppSessionRepository for PgAppSessionRepository<'c> {
                 Expr::col((CompatSessions::Table, CompatSessions::LastActiveIp)),
                 AppSessionLookupIden::LastActiveIp,
             )
+            .expr_as(Expr::cust("NULL"), AppSessionLookupIden::HumanName)
             .from(CompatSessions::Table)
             .and_where_option(filter.user().map(|user| {
                 Expr::col((CompatSessions::Table, CompatSessions::UserId)).eq(Uuid::from(user.id))
@@ -493,7 +505,7 @@ mod tests {
         let device = Device::generate(&mut rng);
         let compat_session = repo
             .compat_session()
-            .add(&mut rng, &clock, &user, device.clone(), false)
+            .add(&mut rng, &clock, &user, device.clone(), None, false)
             .await
             .unwrap();
 
@@ -559,6 +571,7 @@ mod tests {
                 Some("https://example.com/".parse().unwrap()),
                 Some("https://example.com/policy".parse().unwrap()),
                 Some("https://example.com/tos".parse().unwrap()),
+                None,
                 Some("https://example.com/jwks.json".parse().unwrap()),
                 None,
                 None,
@@ -35,7 +35,7 @@ mod tests {
             CompatAccessTokenRepository, CompatRefreshTokenRepository, CompatSessionFilter,
             CompatSessionRepository, CompatSsoLoginFilter,
         },
-        user::UserRepository,
+        user::{BrowserSessionRepository, UserRepository},
         Clock, Pagination, Repository, RepositoryAccess,
     };
     use rand::SeedableRng;
@@ -87,7 +87,7 @@ mod tests {
         let device_str = device.as_str().to_owned();
         let session = repo
             .compat_session()
-            .add(&mut rng, &clock, &user, device, false)
+            .add(&mut rng, &clock, &user, device, None, false)
             .await
             .unwrap();
         assert_eq!(session.user_id, user.id);
@@ -193,13 +193,52 @@ mod tests {
             .unwrap();
         assert!(login.is_pending());
 
-        // Start a compat session for that user
+        // Start a compat session for that user, from a browser session
+        let browser_session = repo
+            .browser_session()
+            .add(&mut rng, &clock, &user, None)
+            .await
+            .unwrap();
         let device = Device::generate(&mut rng);
         let sso_login_session = repo
             .compat_session()
-            .add(&mut rng, &clock, &user, device, false)
+            .add(
+                &mut rng,
+                &clock,
+                &user,
+                device,
+                Some(&browser_session),
+                false,
+            )
+            .await
+            .unwrap();
+        assert_eq!(sso_login_session.user_session_id, Some(browser_session.id));
+
+        let session_lookup = repo
+            .compat_session()
+            .lookup(sso_login_session.id)
+            .await
+            .unwrap()
+            .expect("compat session not found");
+        assert_eq!(session_lookup.user_session_id, Some(browser_session.id));
+
+        // Only that session was started from the browser session
+        let from_browser_session = CompatSessionFilter::new()
+            .for_browser_session(&browser_session);
+        assert_eq!(
+            repo.compat_session()
+                .count(from_browser_session)
+                .await
+                .unwrap(),
+            1
+        );
+        let list = repo
+            .compat_session()
+            .list(from_browser_session, pagination)
             .await
             .unwrap();
+        assert_eq!(list.edges.len(), 1);
+        assert_eq!(list.edges[0].0.id, sso_login_session.id);
 
         // Associate the login with the session
         let login = repo
@@ -285,7 +324,7 @@ mod tests {
         let device = Device::generate(&mut rng);
         let session = repo
             .compat_session()
-            .add(&mut rng, &clock, &user, device, false)
+            .add(&mut rng, &clock, &user, device, None, false)
             .await
             .unwrap();
 
@@ -405,7 +444,7 @@ mod tests {
         let device = Device::generate(&mut rng);
         let session = repo
             .compat_session()
-            .add(&mut rng, &clock, &user, device, false)
+            .add(&mut rng, &clock, &user, device, None, false)
             .await
             .unwrap();
 
@@ -578,7 +617,7 @@ mod tests {
         let device = Device::generate(&mut rng);
         let session = repo
             .compat_session()
-            .add(&mut rng, &clock, &user, device, false)
+            .add(&mut rng, &clock, &user, device, None, false)
             .await
             .unwrap();
 
@@ -0,0 +1,213 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Re-encryption of the columns holding encrypted data, used to retire an
+//! encryption key
+
+use sea_query::{Alias, Expr, Order, PostgresQueryBuilder, Query};
+use sea_query_binder::SqlxBinder;
+use sqlx::PgConnection;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::{DatabaseError, ExecuteExt};
+
+/// The columns holding encrypted data, as `(table, primary key, column)`
+const ENCRYPTED_COLUMNS: &[(&str, &str, &str)] = &[
+    (
+        "oauth2_clients",
+        "oauth2_client_id",
+        "encrypted_client_secret",
+    ),
+    (
+        "upstream_oauth_providers",
+        "upstream_oauth_provider_id",
+        "encrypted_client_secret",
+    ),
+    (
+        "upstream_oauth_providers",
+        "upstream_oauth_provider_id",
+        "encrypted_registration_access_token",
+    ),
+    (
+        "upstream_oauth_links",
+        "upstream_oauth_link_id",
+        "encrypted_access_token",
+    ),
+    (
+        "upstream_oauth_links",
+        "upstream_oauth_link_id",
+        "encrypted_refresh_token",
+    ),
+];
+
+/// How many rows are loaded at once
+const BATCH_SIZE: u64 = 1000;
+
+/// Re-encrypt the values of all the columns holding encrypted data.
+///
+/// `reencrypt` is called with each encrypted value, and returns the value
+/// encrypted with the new key, or `None` if it doesn't need to change.
+///
+/// Returns the number of updated values.
+///
+/// # Errors
+///
+/// Returns an error if the database fails, or if `reencrypt` fails
+#[tracing::instrument(name = "db.reencrypt", skip_all, fields(db.statement), err)]
+pub async fn reencrypt<F, E>(
+    conn: &mut PgConnection,
+    mut reencrypt: F,
+) -> Result<u64, DatabaseError>
+where
+    F: FnMut(&str) -> Result<Option<String>, E> + Send,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    let mut total = 0;
+
+    for (table, id, column) in ENCRYPTED_COLUMNS {
+        let mut updated = 0;
+        let mut after: Option<Uuid> = None;
+
+        loop {
+            let (sql, arguments) = Query::select()
+                .column(Alias::new(*id))
+                .column(Alias::new(*column))
+                .from(Alias::new(*table))
+                .and_where(Expr::col(Alias::new(*column)).is_not_null())
+                .and_where_option(after.map(|after| Expr::col(Alias::new(*id)).gt(after)))
+                .order_by(Alias::new(*id), Order::Asc)
+                .limit(BATCH_SIZE)
+                .build_sqlx(PostgresQueryBuilder);
+
+            let rows: Vec<(Uuid, String)> = sqlx::query_as_with(&sql, arguments)
+                .traced()
+                .fetch_all(&mut *conn)
+                .await?;
+
+            let Some((last, _)) = rows.last() else {
+                break;
+            };
+            after = Some(*last);
+
+            for (row_id, encrypted) in rows {
+                let Some(reencrypted) =
+                    reencrypt(&encrypted).map_err(DatabaseError::to_invalid_operation)?
+                else {
+                    continue;
+                };
+
+                // Only update the value if it didn't change in the meantime
+                let (sql, arguments) = Query::update()
+                    .table(Alias::new(*table))
+                    .value(Alias::new(*column), reencrypted)
+                    .and_where(Expr::col(Alias::new(*id)).eq(row_id))
+                    .and_where(Expr::col(Alias::new(*column)).eq(encrypted))
+                    .build_sqlx(PostgresQueryBuilder);
+
+                let res = sqlx::query_with(&sql, arguments)
+                    .traced()
+                    .execute(&mut *conn)
+                    .await?;
+
+                updated += res.rows_affected();
+            }
+        }
+
+        info!(table, column, updated, "Re-encrypted column");
+        total += updated;
+    }
+
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use mas_iana::oauth::OAuthClientAuthenticationMethod;
+    use mas_storage::oauth2::OAuth2ClientRepository;
+    use sqlx::PgPool;
+    use ulid::Ulid;
+
+    use super::*;
+    use crate::oauth2::PgOAuth2ClientRepository;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_reencrypt(pool: PgPool) {
+        let mut conn = pool.acquire().await.unwrap();
+        let mut repo = PgOAuth2ClientRepository::new(&mut conn);
+
+        let old = Ulid::from_parts(1, 1);
+        let new = Ulid::from_parts(1, 2);
+        let public = Ulid::from_parts(1, 3);
+        for (client_id, encrypted_client_secret) in [
+            (old, Some("old:secret".to_owned())),
+            (new, Some("new:secret".to_owned())),
+            (public, None),
+        ] {
+            repo.upsert_static(
+                client_id,
+                OAuthClientAuthenticationMethod::ClientSecretPost,
+                encrypted_client_secret,
+                None,
+                None,
+                Vec::new(),
+                true,
+                None,
+            )
+            .await
+            .unwrap();
+        }
+
+        // Only the values still encrypted with the old key are updated
+        let mut seen = Vec::new();
+        let updated = reencrypt(&mut conn, |encrypted| {
+            seen.push(encrypted.to_owned());
+            Ok::<_, Infallible>(
+                encrypted
+                    .strip_prefix("old:")
+                    .map(|secret| format!("new:{secret}")),
+            )
+        })
+        .await
+        .unwrap();
+        assert_eq!(updated, 1);
+        seen.sort();
+        assert_eq!(seen, ["new:secret", "old:secret"]);
+
+        let mut repo = PgOAuth2ClientRepository::new(&mut conn);
+        for client_id in [old, new] {
+            let client = repo.lookup(client_id).await.unwrap().unwrap();
+            assert_eq!(
+                client.encrypted_client_secret.as_deref(),
+                Some("new:secret")
+            );
+        }
+        let client = repo.lookup(public).await.unwrap().unwrap();
+        assert_eq!(client.encrypted_client_secret, None);
+
+        // Running it again doesn't change anything
+        let updated = reencrypt(&mut conn, |encrypted| {
+            Ok::<_, Infallible>(
+                encrypted
+                    .strip_prefix("old:")
+                    .map(|secret| format!("new:{secret}")),
+            )
+        })
+        .await
+        .unwrap();
+        assert_eq!(updated, 0);
+    }
+}
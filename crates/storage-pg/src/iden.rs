@@ -24,6 +24,16 @@ pub enum UserSessions {
     UserAgent,
     LastActiveAt,
     LastActiveIp,
+    ImpersonatorUserId,
+    ImpersonationExpiresAt,
+}
+
+#[derive(sea_query::Iden)]
+pub enum UserSessionAuthentications {
+    Table,
+    UserSessionId,
+    #[iden = "upstream_oauth_authorization_session_id"]
+    UpstreamOAuthAuthorizationSessionId,
 }
 
 #[derive(sea_query::Iden)]
@@ -34,7 +44,11 @@ pub enum Users {
     PrimaryUserEmailId,
     CreatedAt,
     LockedAt,
+    QuarantinedAt,
     CanRequestAdmin,
+    IsGuest,
+    Locale,
+    UpstreamGroups,
 }
 
 #[derive(sea_query::Iden)]
@@ -53,6 +67,7 @@ pub enum CompatSessions {
     CompatSessionId,
     UserId,
     DeviceId,
+    UserSessionId,
     CreatedAt,
     FinishedAt,
     IsSynapseAdmin,
@@ -87,6 +102,7 @@ pub enum OAuth2Sessions {
     FinishedAt,
     LastActiveAt,
     LastActiveIp,
+    HumanName,
 }
 
 #[derive(sea_query::Iden)]
@@ -103,6 +119,16 @@ pub enum UpstreamOAuthProviders {
     TokenEndpointAuthMethod,
     CreatedAt,
     ClaimsImports,
+    PkceMethod,
+    AuthorizationParams,
+    FetchUserinfo,
+    Slug,
+    UiOptions,
+    Protocol,
+    Endpoints,
+    Saml,
+    StoreTokens,
+    HealthCheck,
 }
 
 #[derive(sea_query::Iden)]
@@ -117,3 +143,16 @@ pub enum UpstreamOAuthLinks {
     Subject,
     CreatedAt,
 }
+
+#[derive(sea_query::Iden)]
+#[iden = "upstream_oauth_authorization_sessions"]
+pub enum UpstreamOAuthAuthorizationSessions {
+    Table,
+    #[iden = "upstream_oauth_authorization_session_id"]
+    UpstreamOAuthAuthorizationSessionId,
+    #[iden = "upstream_oauth_provider_id"]
+    UpstreamOAuthProviderId,
+    #[iden = "upstream_oauth_link_id"]
+    UpstreamOAuthLinkId,
+    Sid,
+}
@@ -14,11 +14,43 @@
 
 //! A module containing the PostgreSQL implementation of the [`JobRepository`].
 
+use std::str::FromStr;
+
 use async_trait::async_trait;
-use mas_storage::job::{JobId, JobRepository, JobSubmission};
+use chrono::{DateTime, Utc};
+use mas_storage::job::{FailedJob, JobId, JobRepository, JobSubmission};
+use serde_json::Value;
 use sqlx::PgConnection;
 
-use crate::{DatabaseError, ExecuteExt};
+use crate::{DatabaseError, DatabaseInconsistencyError, ExecuteExt};
+
+struct FailedJobLookup {
+    id: String,
+    job_type: String,
+    job: Value,
+    last_error: Option<String>,
+    done_at: Option<DateTime<Utc>>,
+}
+
+impl TryFrom<FailedJobLookup> for FailedJob {
+    type Error = DatabaseInconsistencyError;
+
+    fn try_from(value: FailedJobLookup) -> Result<Self, Self::Error> {
+        let id = JobId::from_str(&value.id).map_err(|e| {
+            DatabaseInconsistencyError::on("apalis.jobs")
+                .column("id")
+                .source(e)
+        })?;
+
+        Ok(FailedJob {
+            id,
+            name: value.job_type,
+            payload: value.job,
+            last_error: value.last_error,
+            failed_at: value.done_at,
+        })
+    }
+}
 
 /// An implementation of [`JobRepository`] for a PostgreSQL connection.
 pub struct PgJobRepository<'c> {
@@ -57,12 +89,13 @@ impl<'c> JobRepository for PgJobRepository<'c> {
 
         let res = sqlx::query!(
             r#"
-                INSERT INTO apalis.jobs (job, id, job_type)
-                VALUES ($1::json, $2::text, $3::text)
+                INSERT INTO apalis.jobs (job, id, job_type, run_at)
+                VALUES ($1::json, $2::text, $3::text, COALESCE($4, NOW()))
             "#,
             submission.payload(),
             id.to_string(),
             submission.name(),
+            submission.run_at(),
         )
         .traced()
         .execute(&mut *self.conn)
@@ -72,4 +105,85 @@ impl<'c> JobRepository for PgJobRepository<'c> {
 
         Ok(id)
     }
+
+    #[tracing::instrument(
+        name = "db.job.list_failed",
+        skip_all,
+        fields(
+            db.statement,
+        ),
+        err,
+    )]
+    async fn list_failed(&mut self, limit: usize) -> Result<Vec<FailedJob>, Self::Error> {
+        let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+
+        let res = sqlx::query_as!(
+            FailedJobLookup,
+            r#"
+                SELECT id
+                     , job_type
+                     , job
+                     , last_error
+                     , done_at
+                FROM apalis.jobs
+                WHERE status IN ('Failed', 'Killed')
+                ORDER BY done_at DESC NULLS LAST
+                LIMIT $1
+            "#,
+            limit,
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        let jobs = res
+            .into_iter()
+            .map(TryInto::try_into)
+            .collect::<Result<_, _>>()?;
+
+        Ok(jobs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mas_storage::job::{JobRepositoryExt, ProvisionUserJob};
+    use sqlx::PgPool;
+    use ulid::Ulid;
+
+    use super::*;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_list_failed(pool: PgPool) {
+        let mut conn = pool.acquire().await.unwrap();
+        let mut repo = PgJobRepository::new(&mut conn);
+
+        assert!(repo.list_failed(10).await.unwrap().is_empty());
+
+        let ok = repo
+            .schedule_job(ProvisionUserJob::new_for_id(Ulid::nil()))
+            .await
+            .unwrap();
+        let failed = repo
+            .schedule_job(ProvisionUserJob::new_for_id(Ulid::nil()))
+            .await
+            .unwrap();
+
+        sqlx::query(
+            "UPDATE apalis.jobs SET status = 'Failed', last_error = 'boom', done_at = NOW() WHERE id = $1",
+        )
+        .bind(failed.to_string())
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+
+        let mut repo = PgJobRepository::new(&mut conn);
+        let jobs = repo.list_failed(10).await.unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].id.to_string(), failed.to_string());
+        assert_ne!(jobs[0].id.to_string(), ok.to_string());
+        assert_eq!(jobs[0].name, "provision-user");
+        assert_eq!(jobs[0].last_error.as_deref(), Some("boom"));
+        assert!(jobs[0].failed_at.is_some());
+    }
 }
@@ -179,8 +179,10 @@ use sqlx::migrate::Migrator;
 
 pub mod app_session;
 pub mod compat;
+pub mod encryption;
 pub mod job;
 pub mod oauth2;
+pub mod rate_limit;
 pub mod upstream_oauth2;
 pub mod user;
 
@@ -17,7 +17,8 @@ use std::num::NonZeroU32;
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use mas_data_model::{
-    AuthorizationCode, AuthorizationGrant, AuthorizationGrantStage, Client, Pkce, Session,
+    AuthorizationCode, AuthorizationGrant, AuthorizationGrantApproval, AuthorizationGrantStage,
+    Client, Pkce, Session, User,
 };
 use mas_iana::oauth::PkceCodeChallengeMethod;
 use mas_storage::{oauth2::OAuth2AuthorizationGrantRepository, Clock};
@@ -63,6 +64,12 @@ struct GrantLookup {
     code_challenge: Option<String>,
     code_challenge_method: Option<String>,
     requires_consent: bool,
+    consent_given: bool,
+    device_display_name: Option<String>,
+    approval_user_id: Option<Uuid>,
+    approval_requested_at: Option<DateTime<Utc>>,
+    approved_at: Option<DateTime<Utc>>,
+    approval_reviewer_user_id: Option<Uuid>,
     oauth2_client_id: Uuid,
     oauth2_session_id: Option<Uuid>,
 }
@@ -146,6 +153,27 @@ impl TryFrom<GrantLookup> for AuthorizationGrant {
                 }
             };
 
+        let approval = match (
+            value.approval_user_id,
+            value.approval_requested_at,
+            value.approved_at,
+        ) {
+            (None, None, None) => None,
+            (Some(user_id), Some(requested_at), approved_at) => Some(AuthorizationGrantApproval {
+                user_id: user_id.into(),
+                requested_at,
+                approved_at,
+                reviewed_by: value.approval_reviewer_user_id.map(Ulid::from),
+            }),
+            _ => {
+                return Err(
+                    DatabaseInconsistencyError::on("oauth2_authorization_grants")
+                        .column("approval_requested_at")
+                        .row(id),
+                );
+            }
+        };
+
         let redirect_uri = value.redirect_uri.parse().map_err(|e| {
             DatabaseInconsistencyError::on("oauth2_authorization_grants")
                 .column("redirect_uri")
@@ -193,6 +221,9 @@ impl TryFrom<GrantLookup> for AuthorizationGrant {
             created_at: value.created_at,
             response_type_id_token: value.response_type_id_token,
             requires_consent: value.requires_consent,
+            consent_given: value.consent_given,
+            device_display_name: value.device_display_name,
+            approval,
         })
     }
 }
@@ -226,6 +257,7 @@ impl<'c> OAuth2AuthorizationGrantRepository for PgOAuth2AuthorizationGrantReposi
         response_mode: ResponseMode,
         response_type_id_token: bool,
         requires_consent: bool,
+        device_display_name: Option<String>,
     ) -> Result<AuthorizationGrant, Self::Error> {
         let code_challenge = code
             .as_ref()
@@ -260,10 +292,11 @@ impl<'c> OAuth2AuthorizationGrantRepository for PgOAuth2AuthorizationGrantReposi
                      response_type_id_token,
                      authorization_code,
                      requires_consent,
+                     device_display_name,
                      created_at
                 )
                 VALUES
-                    ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)
+                    ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)
             "#,
             Uuid::from(id),
             Uuid::from(client.id),
@@ -279,6 +312,7 @@ impl<'c> OAuth2AuthorizationGrantRepository for PgOAuth2AuthorizationGrantReposi
             response_type_id_token,
             code_str,
             requires_consent,
+            device_display_name.as_deref(),
             created_at,
         )
         .execute(&mut *self.conn)
@@ -298,6 +332,9 @@ impl<'c> OAuth2AuthorizationGrantRepository for PgOAuth2AuthorizationGrantReposi
             created_at,
             response_type_id_token,
             requires_consent,
+            consent_given: false,
+            device_display_name,
+            approval: None,
         })
     }
 
@@ -332,6 +369,12 @@ impl<'c> OAuth2AuthorizationGrantRepository for PgOAuth2AuthorizationGrantReposi
                      , code_challenge
                      , code_challenge_method
                      , requires_consent
+                     , consent_given
+                     , device_display_name
+                     , approval_user_id
+                     , approval_requested_at
+                     , approved_at
+                     , approval_reviewer_user_id
                      , oauth2_session_id
                 FROM
                     oauth2_authorization_grants
@@ -381,6 +424,12 @@ impl<'c> OAuth2AuthorizationGrantRepository for PgOAuth2AuthorizationGrantReposi
                      , code_challenge
                      , code_challenge_method
                      , requires_consent
+                     , consent_given
+                     , device_display_name
+                     , approval_user_id
+                     , approval_requested_at
+                     , approved_at
+                     , approval_reviewer_user_id
                      , oauth2_session_id
                 FROM
                     oauth2_authorization_grants
@@ -495,7 +544,8 @@ impl<'c> OAuth2AuthorizationGrantRepository for PgOAuth2AuthorizationGrantReposi
             r#"
                 UPDATE oauth2_authorization_grants AS og
                 SET
-                    requires_consent = 'f'
+                    requires_consent = 'f',
+                    consent_given = 't'
                 WHERE
                     og.oauth2_authorization_grant_id = $1
             "#,
@@ -505,7 +555,236 @@ impl<'c> OAuth2AuthorizationGrantRepository for PgOAuth2AuthorizationGrantReposi
         .await?;
 
         grant.requires_consent = false;
+        grant.consent_given = true;
+
+        Ok(grant)
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_authorization_grant.request_approval",
+        skip_all,
+        fields(
+            db.statement,
+            %grant.id,
+            client.id = %grant.client_id,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn request_approval(
+        &mut self,
+        clock: &dyn Clock,
+        user: &User,
+        grant: AuthorizationGrant,
+    ) -> Result<AuthorizationGrant, Self::Error> {
+        let requested_at = clock.now();
+        let grant = grant
+            .request_approval(requested_at, user)
+            .map_err(DatabaseError::to_invalid_operation)?;
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE oauth2_authorization_grants
+                SET approval_user_id = $2
+                  , approval_requested_at = $3
+                WHERE oauth2_authorization_grant_id = $1
+            "#,
+            Uuid::from(grant.id),
+            Uuid::from(user.id),
+            requested_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(grant)
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_authorization_grant.approve",
+        skip_all,
+        fields(
+            db.statement,
+            %grant.id,
+            client.id = %grant.client_id,
+        ),
+        err,
+    )]
+    async fn approve(
+        &mut self,
+        clock: &dyn Clock,
+        reviewer: Option<&User>,
+        grant: AuthorizationGrant,
+    ) -> Result<AuthorizationGrant, Self::Error> {
+        let approved_at = clock.now();
+        let grant = grant
+            .approve(approved_at, reviewer)
+            .map_err(DatabaseError::to_invalid_operation)?;
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE oauth2_authorization_grants
+                SET approved_at = $2
+                  , approval_reviewer_user_id = $3
+                WHERE oauth2_authorization_grant_id = $1
+            "#,
+            Uuid::from(grant.id),
+            approved_at,
+            reviewer.map(|user| Uuid::from(user.id)),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(grant)
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_authorization_grant.deny",
+        skip_all,
+        fields(
+            db.statement,
+            %grant.id,
+            client.id = %grant.client_id,
+        ),
+        err,
+    )]
+    async fn deny(
+        &mut self,
+        clock: &dyn Clock,
+        reviewer: Option<&User>,
+        grant: AuthorizationGrant,
+    ) -> Result<AuthorizationGrant, Self::Error> {
+        let cancelled_at = clock.now();
+        let grant = grant
+            .deny(cancelled_at, reviewer)
+            .map_err(DatabaseError::to_invalid_operation)?;
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE oauth2_authorization_grants
+                SET cancelled_at = $2
+                  , approval_reviewer_user_id = $3
+                WHERE oauth2_authorization_grant_id = $1
+            "#,
+            Uuid::from(grant.id),
+            cancelled_at,
+            reviewer.map(|user| Uuid::from(user.id)),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(grant)
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_authorization_grant.cancel",
+        skip_all,
+        fields(
+            db.statement,
+            %grant.id,
+            client.id = %grant.client_id,
+        ),
+        err,
+    )]
+    async fn cancel(
+        &mut self,
+        clock: &dyn Clock,
+        grant: AuthorizationGrant,
+    ) -> Result<AuthorizationGrant, Self::Error> {
+        let cancelled_at = clock.now();
+        let grant = grant
+            .cancel(cancelled_at)
+            .map_err(DatabaseError::to_invalid_operation)?;
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE oauth2_authorization_grants
+                SET cancelled_at = $2
+                WHERE oauth2_authorization_grant_id = $1
+            "#,
+            Uuid::from(grant.id),
+            cancelled_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
 
         Ok(grant)
     }
+
+    #[tracing::instrument(
+        name = "db.oauth2_authorization_grant.list_awaiting_approval",
+        skip_all,
+        fields(
+            db.statement,
+        ),
+        err,
+    )]
+    async fn list_awaiting_approval(
+        &mut self,
+        limit: usize,
+    ) -> Result<Vec<AuthorizationGrant>, Self::Error> {
+        let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+        let res = sqlx::query_as!(
+            GrantLookup,
+            r#"
+                SELECT oauth2_authorization_grant_id
+                     , created_at
+                     , cancelled_at
+                     , fulfilled_at
+                     , exchanged_at
+                     , scope
+                     , state
+                     , redirect_uri
+                     , response_mode
+                     , nonce
+                     , max_age
+                     , oauth2_client_id
+                     , authorization_code
+                     , response_type_code
+                     , response_type_id_token
+                     , code_challenge
+                     , code_challenge_method
+                     , requires_consent
+                     , consent_given
+                     , device_display_name
+                     , approval_user_id
+                     , approval_requested_at
+                     , approved_at
+                     , approval_reviewer_user_id
+                     , oauth2_session_id
+                FROM
+                    oauth2_authorization_grants
+
+                WHERE approval_requested_at IS NOT NULL
+                  AND approved_at IS NULL
+                  AND cancelled_at IS NULL
+                  AND fulfilled_at IS NULL
+
+                ORDER BY approval_requested_at ASC
+                LIMIT $1
+            "#,
+            limit,
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        let grants = res
+            .into_iter()
+            .map(TryInto::try_into)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(grants)
+    }
 }
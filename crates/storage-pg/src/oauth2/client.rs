@@ -19,7 +19,10 @@ use std::{
 };
 
 use async_trait::async_trait;
-use mas_data_model::{Client, JwksOrJwksUri, User};
+use chrono::{DateTime, Duration, Utc};
+use mas_data_model::{
+    ClaimMapping, Client, ClientTokenSettings, JwksOrJwksUri, RedirectUriMatching, User,
+};
 use mas_iana::{
     jose::JsonWebSignatureAlg,
     oauth::{OAuthAuthorizationEndpointResponseType, OAuthClientAuthenticationMethod},
@@ -32,7 +35,7 @@ use oauth2_types::{
     scope::{Scope, ScopeToken},
 };
 use rand::RngCore;
-use sqlx::PgConnection;
+use sqlx::{types::Json, PgConnection};
 use tracing::{info_span, Instrument};
 use ulid::Ulid;
 use url::Url;
@@ -64,12 +67,14 @@ struct OAuth2ClientLookup {
     grant_type_authorization_code: bool,
     grant_type_refresh_token: bool,
     grant_type_client_credentials: bool,
+    grant_type_jwt_bearer: bool,
     contacts: Vec<String>,
     client_name: Option<String>,
     logo_uri: Option<String>,
     client_uri: Option<String>,
     policy_uri: Option<String>,
     tos_uri: Option<String>,
+    brand_color: Option<String>,
     jwks_uri: Option<String>,
     jwks: Option<serde_json::Value>,
     id_token_signed_response_alg: Option<String>,
@@ -130,6 +135,9 @@ impl TryInto<Client> for OAuth2ClientLookup {
         if self.grant_type_client_credentials {
             grant_types.push(GrantType::ClientCredentials);
         }
+        if self.grant_type_jwt_bearer {
+            grant_types.push(GrantType::JwtBearer);
+        }
 
         let logo_uri = self.logo_uri.map(|s| s.parse()).transpose().map_err(|e| {
             DatabaseInconsistencyError::on("oauth2_clients")
@@ -264,6 +272,7 @@ impl TryInto<Client> for OAuth2ClientLookup {
             client_uri,
             policy_uri,
             tos_uri,
+            brand_color: self.brand_color,
             jwks,
             id_token_signed_response_alg,
             userinfo_signed_response_alg,
@@ -298,12 +307,14 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
                      , grant_type_authorization_code
                      , grant_type_refresh_token
                      , grant_type_client_credentials
+                     , grant_type_jwt_bearer
                      , contacts
                      , client_name
                      , logo_uri
                      , client_uri
                      , policy_uri
                      , tos_uri
+                     , brand_color
                      , jwks_uri
                      , jwks
                      , id_token_signed_response_alg
@@ -349,12 +360,14 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
                      , grant_type_authorization_code
                      , grant_type_refresh_token
                      , grant_type_client_credentials
+                     , grant_type_jwt_bearer
                      , contacts
                      , client_name
                      , logo_uri
                      , client_uri
                      , policy_uri
                      , tos_uri
+                     , brand_color
                      , jwks_uri
                      , jwks
                      , id_token_signed_response_alg
@@ -406,6 +419,7 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
         client_uri: Option<Url>,
         policy_uri: Option<Url>,
         tos_uri: Option<Url>,
+        brand_color: Option<String>,
         jwks_uri: Option<Url>,
         jwks: Option<PublicJsonWebKeySet>,
         id_token_signed_response_alg: Option<JsonWebSignatureAlg>,
@@ -436,11 +450,13 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
                     , grant_type_authorization_code
                     , grant_type_refresh_token
                     , grant_type_client_credentials
+                    , grant_type_jwt_bearer
                     , client_name
                     , logo_uri
                     , client_uri
                     , policy_uri
                     , tos_uri
+                    , brand_color
                     , jwks_uri
                     , jwks
                     , id_token_signed_response_alg
@@ -451,7 +467,7 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
                     , is_static
                     )
                 VALUES
-                    ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, FALSE)
+                    ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, FALSE)
             "#,
             Uuid::from(id),
             encrypted_client_secret,
@@ -460,11 +476,13 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
             grant_types.contains(&GrantType::AuthorizationCode),
             grant_types.contains(&GrantType::RefreshToken),
             grant_types.contains(&GrantType::ClientCredentials),
+            grant_types.contains(&GrantType::JwtBearer),
             client_name,
             logo_uri.as_ref().map(Url::as_str),
             client_uri.as_ref().map(Url::as_str),
             policy_uri.as_ref().map(Url::as_str),
             tos_uri.as_ref().map(Url::as_str),
+            brand_color.as_deref(),
             jwks_uri.as_ref().map(Url::as_str),
             jwks_json,
             id_token_signed_response_alg
@@ -508,6 +526,7 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
             client_uri,
             policy_uri,
             tos_uri,
+            brand_color,
             jwks,
             id_token_signed_response_alg,
             userinfo_signed_response_alg,
@@ -534,6 +553,8 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
         jwks: Option<PublicJsonWebKeySet>,
         jwks_uri: Option<Url>,
         redirect_uris: Vec<Url>,
+        issue_refresh_tokens: bool,
+        userinfo_signed_response_alg: Option<JsonWebSignatureAlg>,
     ) -> Result<Client, Self::Error> {
         let jwks_json = jwks
             .as_ref()
@@ -553,33 +574,41 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
                     , grant_type_authorization_code
                     , grant_type_refresh_token
                     , grant_type_client_credentials
+                    , grant_type_jwt_bearer
                     , token_endpoint_auth_method
                     , jwks
                     , jwks_uri
+                    , userinfo_signed_response_alg
                     , is_static
                     )
                 VALUES
-                    ($1, $2, $3, $4, $5, $6, $7, $8, $9, TRUE)
+                    ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, TRUE)
                 ON CONFLICT (oauth2_client_id)
                 DO
                     UPDATE SET encrypted_client_secret = EXCLUDED.encrypted_client_secret
                              , grant_type_authorization_code = EXCLUDED.grant_type_authorization_code
                              , grant_type_refresh_token = EXCLUDED.grant_type_refresh_token
                              , grant_type_client_credentials = EXCLUDED.grant_type_client_credentials
+                             , grant_type_jwt_bearer = EXCLUDED.grant_type_jwt_bearer
                              , token_endpoint_auth_method = EXCLUDED.token_endpoint_auth_method
                              , jwks = EXCLUDED.jwks
                              , jwks_uri = EXCLUDED.jwks_uri
+                             , userinfo_signed_response_alg = EXCLUDED.userinfo_signed_response_alg
                              , is_static = TRUE
             "#,
             Uuid::from(client_id),
             encrypted_client_secret,
             &redirect_uris_array,
             true,
+            issue_refresh_tokens,
             true,
             true,
             client_auth_method,
             jwks_json,
             jwks_uri.as_ref().map(Url::as_str),
+            userinfo_signed_response_alg
+                .as_ref()
+                .map(ToString::to_string),
         )
         .traced()
         .execute(&mut *self.conn)
@@ -592,6 +621,15 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
             _ => return Err(DatabaseError::invalid_operation()),
         };
 
+        let mut grant_types = vec![
+            GrantType::AuthorizationCode,
+            GrantType::ClientCredentials,
+            GrantType::JwtBearer,
+        ];
+        if issue_refresh_tokens {
+            grant_types.push(GrantType::RefreshToken);
+        }
+
         Ok(Client {
             id: client_id,
             client_id: client_id.to_string(),
@@ -603,20 +641,17 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
                 OAuthAuthorizationEndpointResponseType::IdToken,
                 OAuthAuthorizationEndpointResponseType::None,
             ],
-            grant_types: vec![
-                GrantType::AuthorizationCode,
-                GrantType::RefreshToken,
-                GrantType::ClientCredentials,
-            ],
+            grant_types,
             contacts: Vec::new(),
             client_name: None,
             logo_uri: None,
             client_uri: None,
             policy_uri: None,
             tos_uri: None,
+            brand_color: None,
             jwks,
             id_token_signed_response_alg: None,
-            userinfo_signed_response_alg: None,
+            userinfo_signed_response_alg,
             token_endpoint_auth_method: None,
             token_endpoint_auth_signing_alg: None,
             initiate_login_uri: None,
@@ -642,12 +677,14 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
                      , grant_type_authorization_code
                      , grant_type_refresh_token
                      , grant_type_client_credentials
+                     , grant_type_jwt_bearer
                      , contacts
                      , client_name
                      , logo_uri
                      , client_uri
                      , policy_uri
                      , tos_uri
+                     , brand_color
                      , jwks_uri
                      , jwks
                      , id_token_signed_response_alg
@@ -668,6 +705,424 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
             .collect()
     }
 
+    #[tracing::instrument(
+        name = "db.oauth2_client.client_credentials_scope",
+        skip_all,
+        fields(
+            db.statement,
+            %client.id,
+        ),
+        err,
+    )]
+    async fn client_credentials_scope(
+        &mut self,
+        client: &Client,
+    ) -> Result<Option<Scope>, Self::Error> {
+        let scope_tokens: Option<Vec<String>> = sqlx::query_scalar!(
+            r#"
+                SELECT client_credentials_scope
+                FROM oauth2_clients
+                WHERE oauth2_client_id = $1
+            "#,
+            Uuid::from(client.id),
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        let Some(scope_tokens) = scope_tokens else {
+            return Ok(None);
+        };
+
+        let scope: Result<Scope, _> = scope_tokens
+            .into_iter()
+            .map(|s| ScopeToken::from_str(&s))
+            .collect();
+
+        let scope = scope.map_err(|e| {
+            DatabaseInconsistencyError::on("oauth2_clients")
+                .column("client_credentials_scope")
+                .row(client.id)
+                .source(e)
+        })?;
+
+        Ok(Some(scope))
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_client.set_client_credentials_scope",
+        skip_all,
+        fields(
+            db.statement,
+            %client.id,
+        ),
+        err,
+    )]
+    async fn set_client_credentials_scope(
+        &mut self,
+        client: &Client,
+        scope: Option<&Scope>,
+    ) -> Result<(), Self::Error> {
+        let scope_tokens: Option<Vec<String>> =
+            scope.map(|scope| scope.iter().map(ToString::to_string).collect());
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE oauth2_clients
+                SET client_credentials_scope = $2
+                WHERE oauth2_client_id = $1
+            "#,
+            Uuid::from(client.id),
+            scope_tokens.as_deref(),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_client.token_settings",
+        skip_all,
+        fields(
+            db.statement,
+            %client.id,
+        ),
+        err,
+    )]
+    async fn token_settings(
+        &mut self,
+        client: &Client,
+    ) -> Result<ClientTokenSettings, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                SELECT access_token_ttl
+                     , refresh_token_ttl
+                     , additional_audiences
+                FROM oauth2_clients
+                WHERE oauth2_client_id = $1
+            "#,
+            Uuid::from(client.id),
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        Ok(ClientTokenSettings {
+            access_token_ttl: res
+                .access_token_ttl
+                .map(|ttl| Duration::seconds(ttl.into())),
+            refresh_token_ttl: res
+                .refresh_token_ttl
+                .map(|ttl| Duration::seconds(ttl.into())),
+            additional_audiences: res.additional_audiences,
+        })
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_client.set_token_settings",
+        skip_all,
+        fields(
+            db.statement,
+            %client.id,
+        ),
+        err,
+    )]
+    async fn set_token_settings(
+        &mut self,
+        client: &Client,
+        settings: &ClientTokenSettings,
+    ) -> Result<(), Self::Error> {
+        let access_token_ttl = settings
+            .access_token_ttl
+            .map(|ttl| i32::try_from(ttl.num_seconds()).unwrap_or(i32::MAX));
+        let refresh_token_ttl = settings
+            .refresh_token_ttl
+            .map(|ttl| i32::try_from(ttl.num_seconds()).unwrap_or(i32::MAX));
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE oauth2_clients
+                SET access_token_ttl = $2
+                  , refresh_token_ttl = $3
+                  , additional_audiences = $4
+                WHERE oauth2_client_id = $1
+            "#,
+            Uuid::from(client.id),
+            access_token_ttl,
+            refresh_token_ttl,
+            &settings.additional_audiences,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_client.redirect_uri_matching",
+        skip_all,
+        fields(
+            db.statement,
+            %client.id,
+        ),
+        err,
+    )]
+    async fn redirect_uri_matching(
+        &mut self,
+        client: &Client,
+    ) -> Result<RedirectUriMatching, Self::Error> {
+        let matching: String = sqlx::query_scalar!(
+            r#"
+                SELECT redirect_uri_matching
+                FROM oauth2_clients
+                WHERE oauth2_client_id = $1
+            "#,
+            Uuid::from(client.id),
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        let matching = RedirectUriMatching::from_name(&matching).ok_or_else(|| {
+            DatabaseInconsistencyError::on("oauth2_clients")
+                .column("redirect_uri_matching")
+                .row(client.id)
+        })?;
+
+        Ok(matching)
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_client.set_redirect_uri_matching",
+        skip_all,
+        fields(
+            db.statement,
+            %client.id,
+            matching = matching.as_str(),
+        ),
+        err,
+    )]
+    async fn set_redirect_uri_matching(
+        &mut self,
+        client: &Client,
+        matching: RedirectUriMatching,
+    ) -> Result<(), Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE oauth2_clients
+                SET redirect_uri_matching = $2
+                WHERE oauth2_client_id = $1
+            "#,
+            Uuid::from(client.id),
+            matching.as_str(),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_client.requires_approval",
+        skip_all,
+        fields(
+            db.statement,
+            %client.id,
+        ),
+        err,
+    )]
+    async fn requires_approval(&mut self, client: &Client) -> Result<bool, Self::Error> {
+        let requires_approval = sqlx::query_scalar!(
+            r#"
+                SELECT requires_approval
+                FROM oauth2_clients
+                WHERE oauth2_client_id = $1
+            "#,
+            Uuid::from(client.id),
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        Ok(requires_approval)
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_client.set_requires_approval",
+        skip_all,
+        fields(
+            db.statement,
+            %client.id,
+            requires_approval,
+        ),
+        err,
+    )]
+    async fn set_requires_approval(
+        &mut self,
+        client: &Client,
+        requires_approval: bool,
+    ) -> Result<(), Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE oauth2_clients
+                SET requires_approval = $2
+                WHERE oauth2_client_id = $1
+            "#,
+            Uuid::from(client.id),
+            requires_approval,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_client.trusted_scope",
+        skip_all,
+        fields(
+            db.statement,
+            %client.id,
+        ),
+        err,
+    )]
+    async fn trusted_scope(&mut self, client: &Client) -> Result<Option<Scope>, Self::Error> {
+        let scope_tokens: Option<Vec<String>> = sqlx::query_scalar!(
+            r#"
+                SELECT trusted_scope
+                FROM oauth2_clients
+                WHERE oauth2_client_id = $1
+            "#,
+            Uuid::from(client.id),
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        let Some(scope_tokens) = scope_tokens else {
+            return Ok(None);
+        };
+
+        let scope: Result<Scope, _> = scope_tokens
+            .into_iter()
+            .map(|s| ScopeToken::from_str(&s))
+            .collect();
+
+        let scope = scope.map_err(|e| {
+            DatabaseInconsistencyError::on("oauth2_clients")
+                .column("trusted_scope")
+                .row(client.id)
+                .source(e)
+        })?;
+
+        Ok(Some(scope))
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_client.set_trusted_scope",
+        skip_all,
+        fields(
+            db.statement,
+            %client.id,
+        ),
+        err,
+    )]
+    async fn set_trusted_scope(
+        &mut self,
+        client: &Client,
+        scope: Option<&Scope>,
+    ) -> Result<(), Self::Error> {
+        let scope_tokens: Option<Vec<String>> =
+            scope.map(|scope| scope.iter().map(ToString::to_string).collect());
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE oauth2_clients
+                SET trusted_scope = $2
+                WHERE oauth2_client_id = $1
+            "#,
+            Uuid::from(client.id),
+            scope_tokens.as_deref(),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_client.claim_mappings",
+        skip_all,
+        fields(
+            db.statement,
+            %client.id,
+        ),
+        err,
+    )]
+    async fn claim_mappings(&mut self, client: &Client) -> Result<Vec<ClaimMapping>, Self::Error> {
+        let res = sqlx::query_scalar!(
+            r#"
+                SELECT claim_mappings as "claim_mappings: Json<Vec<ClaimMapping>>"
+                FROM oauth2_clients
+                WHERE oauth2_client_id = $1
+            "#,
+            Uuid::from(client.id),
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        Ok(res.0)
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_client.set_claim_mappings",
+        skip_all,
+        fields(
+            db.statement,
+            %client.id,
+        ),
+        err,
+    )]
+    async fn set_claim_mappings(
+        &mut self,
+        client: &Client,
+        mappings: &[ClaimMapping],
+    ) -> Result<(), Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE oauth2_clients
+                SET claim_mappings = $2
+                WHERE oauth2_client_id = $1
+            "#,
+            Uuid::from(client.id),
+            Json(mappings) as _,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(())
+    }
+
     #[tracing::instrument(
         name = "db.oauth2_client.get_consent_for_user",
         skip_all,
@@ -680,6 +1135,7 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
     )]
     async fn get_consent_for_user(
         &mut self,
+        clock: &dyn Clock,
         client: &Client,
         user: &User,
     ) -> Result<Scope, Self::Error> {
@@ -688,9 +1144,11 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
                 SELECT scope_token
                 FROM oauth2_consents
                 WHERE user_id = $1 AND oauth2_client_id = $2
+                  AND (expires_at IS NULL OR expires_at > $3)
             "#,
             Uuid::from(user.id),
             Uuid::from(client.id),
+            clock.now(),
         )
         .fetch_all(&mut *self.conn)
         .await?;
@@ -727,6 +1185,7 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
         client: &Client,
         user: &User,
         scope: &Scope,
+        expires_at: Option<DateTime<Utc>>,
     ) -> Result<(), Self::Error> {
         let now = clock.now();
         let (tokens, ids): (Vec<String>, Vec<Uuid>) = scope
@@ -742,15 +1201,17 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
         sqlx::query!(
             r#"
                 INSERT INTO oauth2_consents
-                    (oauth2_consent_id, user_id, oauth2_client_id, scope_token, created_at)
-                SELECT id, $2, $3, scope_token, $5 FROM UNNEST($1::uuid[], $4::text[]) u(id, scope_token)
-                ON CONFLICT (user_id, oauth2_client_id, scope_token) DO UPDATE SET refreshed_at = $5
+                    (oauth2_consent_id, user_id, oauth2_client_id, scope_token, created_at, expires_at)
+                SELECT id, $2, $3, scope_token, $5, $6 FROM UNNEST($1::uuid[], $4::text[]) u(id, scope_token)
+                ON CONFLICT (user_id, oauth2_client_id, scope_token)
+                    DO UPDATE SET refreshed_at = $5, expires_at = $6
             "#,
             &ids,
             Uuid::from(user.id),
             Uuid::from(client.id),
             &tokens,
             now,
+            expires_at,
         )
         .traced()
         .execute(&mut *self.conn)
@@ -0,0 +1,402 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::{JwksOrJwksUri, JwtBearerIssuer};
+use mas_storage::{oauth2::OAuth2JwtBearerIssuerRepository, Clock};
+use oauth2_types::scope::{Scope, ScopeToken};
+use rand::RngCore;
+use sqlx::PgConnection;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{tracing::ExecuteExt, DatabaseError, DatabaseInconsistencyError};
+
+/// An implementation of [`OAuth2JwtBearerIssuerRepository`] for a PostgreSQL
+/// connection
+pub struct PgOAuth2JwtBearerIssuerRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgOAuth2JwtBearerIssuerRepository<'c> {
+    /// Create a new [`PgOAuth2JwtBearerIssuerRepository`] from an active
+    /// PostgreSQL connection
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+struct JwtBearerIssuerLookup {
+    oauth2_jwt_bearer_issuer_id: Uuid,
+    issuer: String,
+    jwks: Option<serde_json::Value>,
+    jwks_uri: Option<String>,
+    allowed_subjects: Vec<String>,
+    allowed_clients: Vec<Uuid>,
+    allowed_scope: Option<Vec<String>>,
+    created_at: DateTime<Utc>,
+}
+
+impl TryFrom<JwtBearerIssuerLookup> for JwtBearerIssuer {
+    type Error = DatabaseInconsistencyError;
+
+    fn try_from(value: JwtBearerIssuerLookup) -> Result<Self, Self::Error> {
+        let id = value.oauth2_jwt_bearer_issuer_id.into();
+
+        let jwks = match (value.jwks, value.jwks_uri) {
+            (Some(jwks), None) => {
+                let jwks = serde_json::from_value(jwks).map_err(|e| {
+                    DatabaseInconsistencyError::on("oauth2_jwt_bearer_issuers")
+                        .column("jwks")
+                        .row(id)
+                        .source(e)
+                })?;
+                JwksOrJwksUri::Jwks(jwks)
+            }
+            (None, Some(jwks_uri)) => {
+                let jwks_uri = jwks_uri.parse().map_err(|e| {
+                    DatabaseInconsistencyError::on("oauth2_jwt_bearer_issuers")
+                        .column("jwks_uri")
+                        .row(id)
+                        .source(e)
+                })?;
+                JwksOrJwksUri::JwksUri(jwks_uri)
+            }
+            _ => {
+                return Err(DatabaseInconsistencyError::on("oauth2_jwt_bearer_issuers")
+                    .column("jwks(_uri)")
+                    .row(id))
+            }
+        };
+
+        let allowed_scope = value
+            .allowed_scope
+            .map(|tokens| {
+                tokens
+                    .iter()
+                    .map(|token| token.parse::<ScopeToken>())
+                    .collect::<Result<Scope, _>>()
+            })
+            .transpose()
+            .map_err(|e| {
+                DatabaseInconsistencyError::on("oauth2_jwt_bearer_issuers")
+                    .column("allowed_scope")
+                    .row(id)
+                    .source(e)
+            })?;
+
+        Ok(JwtBearerIssuer {
+            id,
+            issuer: value.issuer,
+            jwks,
+            allowed_subjects: value.allowed_subjects,
+            allowed_clients: value
+                .allowed_clients
+                .into_iter()
+                .map(Ulid::from)
+                .collect(),
+            allowed_scope,
+            created_at: value.created_at,
+        })
+    }
+}
+
+#[async_trait]
+impl<'c> OAuth2JwtBearerIssuerRepository for PgOAuth2JwtBearerIssuerRepository<'c> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.oauth2_jwt_bearer_issuer.lookup",
+        skip_all,
+        fields(
+            db.statement,
+            oauth2_jwt_bearer_issuer.id = %id,
+        ),
+        err,
+    )]
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<JwtBearerIssuer>, Self::Error> {
+        let res = sqlx::query_as!(
+            JwtBearerIssuerLookup,
+            r#"
+                SELECT oauth2_jwt_bearer_issuer_id
+                     , issuer
+                     , jwks
+                     , jwks_uri
+                     , allowed_subjects
+                     , allowed_clients
+                     , allowed_scope
+                     , created_at
+                FROM oauth2_jwt_bearer_issuers
+                WHERE oauth2_jwt_bearer_issuer_id = $1
+            "#,
+            Uuid::from(id),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        let Some(res) = res else { return Ok(None) };
+
+        Ok(Some(res.try_into()?))
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_jwt_bearer_issuer.find_by_issuer",
+        skip_all,
+        fields(
+            db.statement,
+            oauth2_jwt_bearer_issuer.issuer = issuer,
+        ),
+        err,
+    )]
+    async fn find_by_issuer(
+        &mut self,
+        issuer: &str,
+    ) -> Result<Option<JwtBearerIssuer>, Self::Error> {
+        let res = sqlx::query_as!(
+            JwtBearerIssuerLookup,
+            r#"
+                SELECT oauth2_jwt_bearer_issuer_id
+                     , issuer
+                     , jwks
+                     , jwks_uri
+                     , allowed_subjects
+                     , allowed_clients
+                     , allowed_scope
+                     , created_at
+                FROM oauth2_jwt_bearer_issuers
+                WHERE issuer = $1
+            "#,
+            issuer,
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        let Some(res) = res else { return Ok(None) };
+
+        Ok(Some(res.try_into()?))
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_jwt_bearer_issuer.upsert",
+        skip_all,
+        fields(
+            db.statement,
+            oauth2_jwt_bearer_issuer.issuer = issuer,
+        ),
+        err,
+    )]
+    async fn upsert(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        issuer: String,
+        jwks: JwksOrJwksUri,
+        allowed_subjects: Vec<String>,
+        allowed_clients: Vec<Ulid>,
+        allowed_scope: Option<Scope>,
+    ) -> Result<JwtBearerIssuer, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+
+        let (jwks_json, jwks_uri) = match &jwks {
+            JwksOrJwksUri::Jwks(jwks) => (
+                Some(serde_json::to_value(jwks).map_err(DatabaseError::to_invalid_operation)?),
+                None,
+            ),
+            JwksOrJwksUri::JwksUri(jwks_uri) => (None, Some(jwks_uri.as_str())),
+        };
+
+        let allowed_scope_tokens: Option<Vec<String>> = allowed_scope
+            .as_ref()
+            .map(|scope| scope.iter().map(ToString::to_string).collect());
+        let allowed_clients_uuids: Vec<Uuid> =
+            allowed_clients.iter().copied().map(Uuid::from).collect();
+
+        // The ID and creation date are kept if the issuer already exists
+        let res = sqlx::query!(
+            r#"
+                INSERT INTO oauth2_jwt_bearer_issuers
+                    ( oauth2_jwt_bearer_issuer_id
+                    , issuer
+                    , jwks
+                    , jwks_uri
+                    , allowed_subjects
+                    , allowed_clients
+                    , allowed_scope
+                    , created_at
+                    )
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                ON CONFLICT (issuer)
+                DO
+                    UPDATE SET jwks = EXCLUDED.jwks
+                             , jwks_uri = EXCLUDED.jwks_uri
+                             , allowed_subjects = EXCLUDED.allowed_subjects
+                             , allowed_clients = EXCLUDED.allowed_clients
+                             , allowed_scope = EXCLUDED.allowed_scope
+                RETURNING oauth2_jwt_bearer_issuer_id, created_at
+            "#,
+            Uuid::from(id),
+            &issuer,
+            jwks_json,
+            jwks_uri,
+            &allowed_subjects,
+            &allowed_clients_uuids,
+            allowed_scope_tokens.as_deref(),
+            created_at,
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        Ok(JwtBearerIssuer {
+            id: res.oauth2_jwt_bearer_issuer_id.into(),
+            issuer,
+            jwks,
+            allowed_subjects,
+            allowed_clients,
+            allowed_scope,
+            created_at: res.created_at,
+        })
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_jwt_bearer_issuer.all",
+        skip_all,
+        fields(
+            db.statement,
+        ),
+        err,
+    )]
+    async fn all(&mut self) -> Result<Vec<JwtBearerIssuer>, Self::Error> {
+        let res = sqlx::query_as!(
+            JwtBearerIssuerLookup,
+            r#"
+                SELECT oauth2_jwt_bearer_issuer_id
+                     , issuer
+                     , jwks
+                     , jwks_uri
+                     , allowed_subjects
+                     , allowed_clients
+                     , allowed_scope
+                     , created_at
+                FROM oauth2_jwt_bearer_issuers
+                ORDER BY issuer
+            "#,
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        res.into_iter()
+            .map(|r| r.try_into().map_err(DatabaseError::from))
+            .collect()
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_jwt_bearer_issuer.delete",
+        skip_all,
+        fields(
+            db.statement,
+            %issuer.id,
+            %issuer.issuer,
+        ),
+        err,
+    )]
+    async fn delete(&mut self, issuer: JwtBearerIssuer) -> Result<(), Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                DELETE FROM oauth2_jwt_bearer_issuers
+                WHERE oauth2_jwt_bearer_issuer_id = $1
+            "#,
+            Uuid::from(issuer.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_jwt_bearer_issuer.record_assertion",
+        skip_all,
+        fields(
+            db.statement,
+            %issuer.id,
+            %issuer.issuer,
+            oauth2_jwt_bearer_assertion.jti = jti,
+        ),
+        err,
+    )]
+    async fn record_assertion(
+        &mut self,
+        clock: &dyn Clock,
+        issuer: &JwtBearerIssuer,
+        jti: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, Self::Error> {
+        let created_at = clock.now();
+
+        let res = sqlx::query!(
+            r#"
+                INSERT INTO oauth2_jwt_bearer_assertions
+                    ( oauth2_jwt_bearer_issuer_id
+                    , jti
+                    , created_at
+                    , expires_at
+                    )
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (oauth2_jwt_bearer_issuer_id, jti) DO NOTHING
+            "#,
+            Uuid::from(issuer.id),
+            jti,
+            created_at,
+            expires_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(res.rows_affected() == 1)
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_jwt_bearer_issuer.cleanup_expired_assertions",
+        skip_all,
+        fields(
+            db.statement,
+        ),
+        err,
+    )]
+    async fn cleanup_expired_assertions(
+        &mut self,
+        clock: &dyn Clock,
+    ) -> Result<usize, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                DELETE FROM oauth2_jwt_bearer_assertions
+                WHERE expires_at < $1
+            "#,
+            clock.now(),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(res.rows_affected().try_into().unwrap_or(usize::MAX))
+    }
+}
@@ -18,19 +18,24 @@
 mod access_token;
 mod authorization_grant;
 mod client;
+mod jwt_bearer_issuer;
 mod refresh_token;
 mod session;
 
 pub use self::{
     access_token::PgOAuth2AccessTokenRepository,
     authorization_grant::PgOAuth2AuthorizationGrantRepository, client::PgOAuth2ClientRepository,
+    jwt_bearer_issuer::PgOAuth2JwtBearerIssuerRepository,
     refresh_token::PgOAuth2RefreshTokenRepository, session::PgOAuth2SessionRepository,
 };
 
 #[cfg(test)]
 mod tests {
     use chrono::Duration;
-    use mas_data_model::AuthorizationCode;
+    use mas_data_model::{
+        AuthorizationCode, ClaimMapping, ClientTokenSettings, JwksOrJwksUri, RedirectUriMatching,
+        UserAttribute,
+    };
     use mas_storage::{
         clock::MockClock,
         oauth2::{OAuth2SessionFilter, OAuth2SessionRepository},
@@ -82,6 +87,7 @@ mod tests {
                 Some("https://example.com/".parse().unwrap()),
                 Some("https://example.com/policy".parse().unwrap()),
                 Some("https://example.com/tos".parse().unwrap()),
+                Some("#0dbd8b".to_owned()),
                 Some("https://example.com/jwks.json".parse().unwrap()),
                 None,
                 None,
@@ -111,6 +117,86 @@ mod tests {
             .expect("client not found");
         assert_eq!(client, client_lookup);
 
+        // The client uses the global token settings by default
+        let settings = repo.oauth2_client().token_settings(&client).await.unwrap();
+        assert_eq!(settings, ClientTokenSettings::default());
+
+        // Override them
+        let settings = ClientTokenSettings {
+            access_token_ttl: Some(Duration::minutes(5)),
+            refresh_token_ttl: None,
+            additional_audiences: vec!["https://api.example.com/".to_owned()],
+        };
+        repo.oauth2_client()
+            .set_token_settings(&client, &settings)
+            .await
+            .unwrap();
+        let settings_lookup = repo.oauth2_client().token_settings(&client).await.unwrap();
+        assert_eq!(settings, settings_lookup);
+
+        // Loopback redirect URIs match on any port by default
+        let matching = repo
+            .oauth2_client()
+            .redirect_uri_matching(&client)
+            .await
+            .unwrap();
+        assert_eq!(matching, RedirectUriMatching::Loopback);
+
+        repo.oauth2_client()
+            .set_redirect_uri_matching(&client, RedirectUriMatching::Exact)
+            .await
+            .unwrap();
+        let matching = repo
+            .oauth2_client()
+            .redirect_uri_matching(&client)
+            .await
+            .unwrap();
+        assert_eq!(matching, RedirectUriMatching::Exact);
+
+        // Authorization requests don't need an approval by default
+        assert!(!repo
+            .oauth2_client()
+            .requires_approval(&client)
+            .await
+            .unwrap());
+        repo.oauth2_client()
+            .set_requires_approval(&client, true)
+            .await
+            .unwrap();
+        assert!(repo
+            .oauth2_client()
+            .requires_approval(&client)
+            .await
+            .unwrap());
+
+        // Clients aren't trusted by default
+        let trusted_scope = repo.oauth2_client().trusted_scope(&client).await.unwrap();
+        assert_eq!(trusted_scope, None);
+
+        let scope = Scope::from_iter([OPENID]);
+        repo.oauth2_client()
+            .set_trusted_scope(&client, Some(&scope))
+            .await
+            .unwrap();
+        let trusted_scope = repo.oauth2_client().trusted_scope(&client).await.unwrap();
+        assert_eq!(trusted_scope, Some(scope));
+
+        // The client has no claim mappings of its own by default
+        let mappings = repo.oauth2_client().claim_mappings(&client).await.unwrap();
+        assert!(mappings.is_empty());
+
+        let mappings = vec![ClaimMapping {
+            claim: "groups".to_owned(),
+            attribute: UserAttribute::Groups,
+            scope: Some("urn:example:groups".to_owned()),
+        }];
+        repo.oauth2_client()
+            .set_claim_mappings(&client, &mappings)
+            .await
+            .unwrap();
+        let mappings_lookup = repo.oauth2_client().claim_mappings(&client).await.unwrap();
+        assert_eq!(mappings, mappings_lookup);
+
         // Lookup a non-existing grant
         let grant = repo
             .oauth2_authorization_grant()
@@ -146,10 +232,12 @@ mod tests {
                 ResponseMode::Query,
                 true,
                 false,
+                Some("My phone".to_owned()),
             )
             .await
             .unwrap();
         assert!(grant.is_pending());
+        assert_eq!(grant.device_display_name.as_deref(), Some("My phone"));
 
         // Lookup the same grant by id
         let grant_lookup = repo
@@ -169,6 +257,22 @@ mod tests {
             .expect("grant not found");
         assert_eq!(grant, grant_lookup);
 
+        // Give consent to the grant itself
+        assert!(!grant.consent_given);
+        let grant = repo
+            .oauth2_authorization_grant()
+            .give_consent(grant)
+            .await
+            .unwrap();
+        assert!(grant.consent_given);
+        let grant_lookup = repo
+            .oauth2_authorization_grant()
+            .lookup(grant.id)
+            .await
+            .unwrap()
+            .expect("grant not found");
+        assert_eq!(grant, grant_lookup);
+
         // Create a user and a start a user session
         let user = repo
             .user()
@@ -181,10 +285,53 @@ mod tests {
             .await
             .unwrap();
 
+        // Hold the grant until an administrator approves it
+        let grant = repo
+            .oauth2_authorization_grant()
+            .request_approval(&clock, &user, grant)
+            .await
+            .unwrap();
+        assert!(grant.is_awaiting_approval());
+        let awaiting = repo
+            .oauth2_authorization_grant()
+            .list_awaiting_approval(10)
+            .await
+            .unwrap();
+        assert_eq!(awaiting, vec![grant.clone()]);
+
+        let admin = repo
+            .user()
+            .add(&mut rng, &clock, "admin".to_owned())
+            .await
+            .unwrap();
+        let grant = repo
+            .oauth2_authorization_grant()
+            .approve(&clock, Some(&admin), grant)
+            .await
+            .unwrap();
+        assert!(!grant.is_awaiting_approval());
+        assert_eq!(
+            grant.approval.as_ref().and_then(|approval| approval.reviewed_by),
+            Some(admin.id)
+        );
+        let grant_lookup = repo
+            .oauth2_authorization_grant()
+            .lookup(grant.id)
+            .await
+            .unwrap()
+            .expect("grant not found");
+        assert_eq!(grant, grant_lookup);
+        let awaiting = repo
+            .oauth2_authorization_grant()
+            .list_awaiting_approval(10)
+            .await
+            .unwrap();
+        assert!(awaiting.is_empty());
+
         // Lookup the consent the user gave to the client
         let consent = repo
             .oauth2_client()
-            .get_consent_for_user(&client, &user)
+            .get_consent_for_user(&clock, &client, &user)
             .await
             .unwrap();
         assert!(consent.is_empty());
@@ -192,18 +339,47 @@ mod tests {
         // Give consent to the client
         let scope = Scope::from_iter([OPENID]);
         repo.oauth2_client()
-            .give_consent_for_user(&mut rng, &clock, &client, &user, &scope)
+            .give_consent_for_user(&mut rng, &clock, &client, &user, &scope, None)
             .await
             .unwrap();
 
         // Lookup the consent the user gave to the client
         let consent = repo
             .oauth2_client()
-            .get_consent_for_user(&client, &user)
+            .get_consent_for_user(&clock, &client, &user)
             .await
             .unwrap();
         assert_eq!(scope, consent);
 
+        // Give consent to another scope, for an hour only
+        let expires_at = clock.now() + Duration::hours(1);
+        repo.oauth2_client()
+            .give_consent_for_user(
+                &mut rng,
+                &clock,
+                &client,
+                &user,
+                &Scope::from_iter([EMAIL]),
+                Some(expires_at),
+            )
+            .await
+            .unwrap();
+        let consent = repo
+            .oauth2_client()
+            .get_consent_for_user(&clock, &client, &user)
+            .await
+            .unwrap();
+        assert_eq!(consent, Scope::from_iter([OPENID, EMAIL]));
+
+        // Once expired, the consent is not returned anymore
+        clock.advance(Duration::hours(2));
+        let consent = repo
+            .oauth2_client()
+            .get_consent_for_user(&clock, &client, &user)
+            .await
+            .unwrap();
+        assert_eq!(consent, scope);
+
         // Lookup a non-existing session
         let session = repo.oauth2_session().lookup(Ulid::nil()).await.unwrap();
         assert_eq!(session, None);
@@ -230,6 +406,40 @@ mod tests {
         assert!(grant.is_fulfilled());
 
         // Lookup the same session by id
+        let session_lookup = repo
+            .oauth2_session()
+            .lookup(session.id)
+            .await
+            .unwrap()
+            .expect("session not found");
+        assert_eq!(session, session_lookup);
+        assert_eq!(session.human_name, None);
+
+        // Set the name of the session
+        let session = repo
+            .oauth2_session()
+            .set_human_name(session, grant.device_display_name.clone())
+            .await
+            .unwrap();
+        assert_eq!(session.human_name.as_deref(), Some("My phone"));
+
+        let session_lookup = repo
+            .oauth2_session()
+            .lookup(session.id)
+            .await
+            .unwrap()
+            .expect("session not found");
+        assert_eq!(session, session_lookup);
+
+        // Narrow down the scope of the session
+        let scope = Scope::from_iter([OPENID]);
+        let session = repo
+            .oauth2_session()
+            .set_scope(session, scope.clone())
+            .await
+            .unwrap();
+        assert_eq!(session.scope, scope);
+
         let session_lookup = repo
             .oauth2_session()
             .lookup(session.id)
@@ -419,6 +629,7 @@ mod tests {
                 Some("https://first.example.com/".parse().unwrap()),
                 Some("https://first.example.com/policy".parse().unwrap()),
                 Some("https://first.example.com/tos".parse().unwrap()),
+                None,
                 Some("https://first.example.com/jwks.json".parse().unwrap()),
                 None,
                 None,
@@ -445,6 +656,7 @@ mod tests {
                 Some("https://second.example.com/".parse().unwrap()),
                 Some("https://second.example.com/policy".parse().unwrap()),
                 Some("https://second.example.com/tos".parse().unwrap()),
+                None,
                 Some("https://second.example.com/jwks.json".parse().unwrap()),
                 None,
                 None,
@@ -689,5 +901,152 @@ mod tests {
         assert_eq!(list.edges.len(), 1);
         assert_eq!(list.edges[0], session11);
         assert_eq!(repo.oauth2_session().count(filter).await.unwrap(), 1);
+
+        // Filter on the creation time. The lower bound is inclusive, the upper
+        // bound exclusive
+        let filter = OAuth2SessionFilter::new()
+            .with_created_after(session12.created_at)
+            .with_created_before(session22.created_at);
+        let list = repo
+            .oauth2_session()
+            .list(filter, pagination)
+            .await
+            .unwrap();
+        assert!(!list.has_next_page);
+        assert_eq!(list.edges.len(), 2);
+        assert_eq!(list.edges[0], session12);
+        assert_eq!(list.edges[1], session21);
+        assert_eq!(repo.oauth2_session().count(filter).await.unwrap(), 2);
+
+        // Combine the creation time filter with the client filter
+        let filter = OAuth2SessionFilter::new()
+            .for_client(&client1)
+            .with_created_after(session12.created_at);
+        let list = repo
+            .oauth2_session()
+            .list(filter, pagination)
+            .await
+            .unwrap();
+        assert_eq!(list.edges.len(), 1);
+        assert_eq!(list.edges[0], session12);
+        assert_eq!(repo.oauth2_session().count(filter).await.unwrap(), 1);
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_jwt_bearer_issuers(pool: PgPool) {
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let clock = MockClock::default();
+        let mut repo = PgRepository::from_pool(&pool).await.unwrap().boxed();
+
+        // Find a non-existing issuer
+        let issuer = repo
+            .oauth2_jwt_bearer_issuer()
+            .find_by_issuer("https://issuer.example.com/")
+            .await
+            .unwrap();
+        assert_eq!(issuer, None);
+
+        // Add an issuer
+        let issuer = repo
+            .oauth2_jwt_bearer_issuer()
+            .upsert(
+                &mut rng,
+                &clock,
+                "https://issuer.example.com/".to_owned(),
+                JwksOrJwksUri::JwksUri("https://issuer.example.com/jwks.json".parse().unwrap()),
+                vec!["alice".to_owned()],
+                vec![Ulid::nil()],
+                None,
+            )
+            .await
+            .unwrap();
+        assert!(issuer.is_subject_allowed("alice"));
+        assert!(!issuer.is_subject_allowed("bob"));
+        assert!(issuer.is_client_allowed(Ulid::nil()));
+
+        let lookup = repo
+            .oauth2_jwt_bearer_issuer()
+            .lookup(issuer.id)
+            .await
+            .unwrap();
+        assert_eq!(lookup.as_ref(), Some(&issuer));
+
+        // Replacing the issuer keeps its ID
+        clock.advance(Duration::minutes(1));
+        let replaced = repo
+            .oauth2_jwt_bearer_issuer()
+            .upsert(
+                &mut rng,
+                &clock,
+                "https://issuer.example.com/".to_owned(),
+                JwksOrJwksUri::JwksUri("https://issuer.example.com/jwks.json".parse().unwrap()),
+                vec!["*".to_owned()],
+                Vec::new(),
+                Some(Scope::from_iter([OPENID])),
+            )
+            .await
+            .unwrap();
+        assert_eq!(replaced.id, issuer.id);
+        assert_eq!(replaced.created_at, issuer.created_at);
+        assert!(replaced.is_subject_allowed("bob"));
+        assert!(!replaced.is_client_allowed(Ulid::nil()));
+
+        let found = repo
+            .oauth2_jwt_bearer_issuer()
+            .find_by_issuer("https://issuer.example.com/")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found, replaced);
+        assert_eq!(found.allowed_scope, Some(Scope::from_iter([OPENID])));
+
+        assert_eq!(
+            repo.oauth2_jwt_bearer_issuer().all().await.unwrap().len(),
+            1
+        );
+
+        // An assertion can only be recorded once
+        let expires_at = clock.now() + Duration::minutes(5);
+        assert!(repo
+            .oauth2_jwt_bearer_issuer()
+            .record_assertion(&clock, &found, "assertion-1", expires_at)
+            .await
+            .unwrap());
+        assert!(!repo
+            .oauth2_jwt_bearer_issuer()
+            .record_assertion(&clock, &found, "assertion-1", expires_at)
+            .await
+            .unwrap());
+        assert!(repo
+            .oauth2_jwt_bearer_issuer()
+            .record_assertion(&clock, &found, "assertion-2", expires_at)
+            .await
+            .unwrap());
+
+        // They are forgotten once they expire
+        assert_eq!(
+            repo.oauth2_jwt_bearer_issuer()
+                .cleanup_expired_assertions(&clock)
+                .await
+                .unwrap(),
+            0
+        );
+        clock.advance(Duration::minutes(6));
+        assert_eq!(
+            repo.oauth2_jwt_bearer_issuer()
+                .cleanup_expired_assertions(&clock)
+                .await
+                .unwrap(),
+            2
+        );
+
+        // Delete the issuer
+        repo.oauth2_jwt_bearer_issuer().delete(found).await.unwrap();
+        assert!(repo
+            .oauth2_jwt_bearer_issuer()
+            .all()
+            .await
+            .unwrap()
+            .is_empty());
     }
 }
@@ -61,6 +61,7 @@ struct OAuthSessionLookup {
     finished_at: Option<DateTime<Utc>>,
     last_active_at: Option<DateTime<Utc>>,
     last_active_ip: Option<IpAddr>,
+    human_name: Option<String>,
 }
 
 impl TryFrom<OAuthSessionLookup> for Session {
@@ -95,6 +96,7 @@ impl TryFrom<OAuthSessionLookup> for Session {
             scope,
             last_active_at: value.last_active_at,
             last_active_ip: value.last_active_ip,
+            human_name: value.human_name,
         })
     }
 }
@@ -125,6 +127,7 @@ impl<'c> OAuth2SessionRepository for PgOAuth2SessionRepository<'c> {
                      , finished_at
                      , last_active_at
                      , last_active_ip as "last_active_ip: IpAddr"
+                     , human_name
                 FROM oauth2_sessions
 
                 WHERE oauth2_session_id = $1
@@ -199,6 +202,7 @@ impl<'c> OAuth2SessionRepository for PgOAuth2SessionRepository<'c> {
             scope,
             last_active_at: None,
             last_active_ip: None,
+            human_name: None,
         })
     }
 
@@ -239,6 +243,78 @@ impl<'c> OAuth2SessionRepository for PgOAuth2SessionRepository<'c> {
             .map_err(DatabaseError::to_invalid_operation)
     }
 
+    #[tracing::instrument(
+        name = "db.oauth2_session.set_human_name",
+        skip_all,
+        fields(
+            db.statement,
+            %session.id,
+            client.id = %session.client_id,
+        ),
+        err,
+    )]
+    async fn set_human_name(
+        &mut self,
+        mut session: Session,
+        human_name: Option<String>,
+    ) -> Result<Session, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE oauth2_sessions
+                SET human_name = $2
+                WHERE oauth2_session_id = $1
+            "#,
+            Uuid::from(session.id),
+            human_name.as_deref(),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        session.human_name = human_name;
+
+        Ok(session)
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_session.set_scope",
+        skip_all,
+        fields(
+            db.statement,
+            %session.id,
+            client.id = %session.client_id,
+            session.scope = %scope,
+        ),
+        err,
+    )]
+    async fn set_scope(
+        &mut self,
+        mut session: Session,
+        scope: Scope,
+    ) -> Result<Session, Self::Error> {
+        let scope_list: Vec<String> = scope.iter().map(|s| s.as_str().to_owned()).collect();
+        let res = sqlx::query!(
+            r#"
+                UPDATE oauth2_sessions
+                SET scope_list = $2
+                WHERE oauth2_session_id = $1
+            "#,
+            Uuid::from(session.id),
+            &scope_list,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        session.scope = scope;
+
+        Ok(session)
+    }
+
     #[tracing::instrument(
         name = "db.oauth2_session.list",
         skip_all,
@@ -289,6 +365,10 @@ impl<'c> OAuth2SessionRepository for PgOAuth2SessionRepository<'c> {
                 Expr::col((OAuth2Sessions::Table, OAuth2Sessions::LastActiveIp)),
                 OAuthSessionLookupIden::LastActiveIp,
             )
+            .expr_as(
+                Expr::col((OAuth2Sessions::Table, OAuth2Sessions::HumanName)),
+                OAuthSessionLookupIden::HumanName,
+            )
             .from(OAuth2Sessions::Table)
             .and_where_option(filter.user().map(|user| {
                 Expr::col((OAuth2Sessions::Table, OAuth2Sessions::UserId)).eq(Uuid::from(user.id))
@@ -297,6 +377,10 @@ impl<'c> OAuth2SessionRepository for PgOAuth2SessionRepository<'c> {
                 Expr::col((OAuth2Sessions::Table, OAuth2Sessions::OAuth2ClientId))
                     .eq(Uuid::from(client.id))
             }))
+            .and_where_option(filter.browser_session().map(|browser_session| {
+                Expr::col((OAuth2Sessions::Table, OAuth2Sessions::UserSessionId))
+                    .eq(Uuid::from(browser_session.id))
+            }))
             .and_where_option(filter.state().map(|state| {
                 if state.is_active() {
                     Expr::col((OAuth2Sessions::Table, OAuth2Sessions::FinishedAt)).is_null()
@@ -308,6 +392,12 @@ impl<'c> OAuth2SessionRepository for PgOAuth2SessionRepository<'c> {
                 let scope: Vec<String> = scope.iter().map(|s| s.as_str().to_owned()).collect();
                 Expr::col((OAuth2Sessions::Table, OAuth2Sessions::ScopeList)).contains(scope)
             }))
+            .and_where_option(filter.created_after().map(|created_after| {
+                Expr::col((OAuth2Sessions::Table, OAuth2Sessions::CreatedAt)).gte(created_after)
+            }))
+            .and_where_option(filter.created_before().map(|created_before| {
+                Expr::col((OAuth2Sessions::Table, OAuth2Sessions::CreatedAt)).lt(created_before)
+            }))
             .generate_pagination(
                 (OAuth2Sessions::Table, OAuth2Sessions::OAuth2SessionId),
                 pagination,
@@ -358,6 +448,10 @@ impl<'c> OAuth2SessionRepository for PgOAuth2SessionRepository<'c> {
                 Expr::col((OAuth2Sessions::Table, OAuth2Sessions::OAuth2ClientId))
                     .eq(Uuid::from(client.id))
             }))
+            .and_where_option(filter.browser_session().map(|browser_session| {
+                Expr::col((OAuth2Sessions::Table, OAuth2Sessions::UserSessionId))
+                    .eq(Uuid::from(browser_session.id))
+            }))
             .and_where_option(filter.state().map(|state| {
                 if state.is_active() {
                     Expr::col((OAuth2Sessions::Table, OAuth2Sessions::FinishedAt)).is_null()
@@ -369,6 +463,12 @@ impl<'c> OAuth2SessionRepository for PgOAuth2SessionRepository<'c> {
                 let scope: Vec<String> = scope.iter().map(|s| s.as_str().to_owned()).collect();
                 Expr::col((OAuth2Sessions::Table, OAuth2Sessions::ScopeList)).contains(scope)
             }))
+            .and_where_option(filter.created_after().map(|created_after| {
+                Expr::col((OAuth2Sessions::Table, OAuth2Sessions::CreatedAt)).gte(created_after)
+            }))
+            .and_where_option(filter.created_before().map(|created_before| {
+                Expr::col((OAuth2Sessions::Table, OAuth2Sessions::CreatedAt)).lt(created_before)
+            }))
             .build_sqlx(PostgresQueryBuilder);
 
         let count: i64 = sqlx::query_scalar_with(&sql, arguments)
@@ -0,0 +1,203 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A module containing the PostgreSQL implementation of the
+//! [`RateLimitRepository`].
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_storage::rate_limit::{RateLimitBucket, RateLimitRepository};
+use sqlx::PgConnection;
+
+use crate::{DatabaseError, ExecuteExt};
+
+/// An implementation of [`RateLimitRepository`] for a PostgreSQL connection.
+pub struct PgRateLimitRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgRateLimitRepository<'c> {
+    /// Create a new [`PgRateLimitRepository`] from an active PostgreSQL
+    /// connection.
+    #[must_use]
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+struct RateLimitBucketLookup {
+    tokens: f64,
+    updated_at: DateTime<Utc>,
+}
+
+impl From<RateLimitBucketLookup> for RateLimitBucket {
+    fn from(value: RateLimitBucketLookup) -> Self {
+        Self {
+            tokens: value.tokens,
+            updated_at: value.updated_at,
+        }
+    }
+}
+
+#[async_trait]
+impl<'c> RateLimitRepository for PgRateLimitRepository<'c> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.rate_limit.lock_bucket",
+        skip_all,
+        fields(
+            db.statement,
+            rate_limit.limiter = limiter,
+        ),
+        err,
+    )]
+    async fn lock_bucket(
+        &mut self,
+        limiter: &str,
+        key: &str,
+    ) -> Result<Option<RateLimitBucket>, Self::Error> {
+        let res = sqlx::query_as!(
+            RateLimitBucketLookup,
+            r#"
+                SELECT tokens, updated_at
+                FROM rate_limit_buckets
+                WHERE limiter = $1 AND key = $2
+                FOR UPDATE
+            "#,
+            limiter,
+            key,
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        Ok(res.map(Into::into))
+    }
+
+    #[tracing::instrument(
+        name = "db.rate_limit.save_bucket",
+        skip_all,
+        fields(
+            db.statement,
+            rate_limit.limiter = limiter,
+        ),
+        err,
+    )]
+    async fn save_bucket(
+        &mut self,
+        limiter: &str,
+        key: &str,
+        bucket: RateLimitBucket,
+    ) -> Result<(), Self::Error> {
+        sqlx::query!(
+            r#"
+                INSERT INTO rate_limit_buckets (limiter, key, tokens, updated_at)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (limiter, key)
+                DO UPDATE SET tokens = EXCLUDED.tokens, updated_at = EXCLUDED.updated_at
+            "#,
+            limiter,
+            key,
+            bucket.tokens,
+            bucket.updated_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.rate_limit.cleanup",
+        skip_all,
+        fields(
+            db.statement,
+            rate_limit.limiter = limiter,
+        ),
+        err,
+    )]
+    async fn cleanup(
+        &mut self,
+        limiter: &str,
+        updated_before: DateTime<Utc>,
+    ) -> Result<usize, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                DELETE FROM rate_limit_buckets
+                WHERE limiter = $1 AND updated_at < $2
+            "#,
+            limiter,
+            updated_before,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(res.rows_affected().try_into().unwrap_or(usize::MAX))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use mas_storage::{clock::MockClock, Clock};
+    use sqlx::PgPool;
+
+    use super::*;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_buckets(pool: PgPool) {
+        let clock = MockClock::default();
+        let mut conn = pool.acquire().await.unwrap();
+        let mut repo = PgRateLimitRepository::new(&mut conn);
+
+        assert!(repo.lock_bucket("login", "alice").await.unwrap().is_none());
+
+        let bucket = RateLimitBucket {
+            tokens: 2.5,
+            updated_at: clock.now(),
+        };
+        repo.save_bucket("login", "alice", bucket).await.unwrap();
+        assert_eq!(
+            repo.lock_bucket("login", "alice").await.unwrap(),
+            Some(bucket)
+        );
+
+        // Buckets are scoped to their limiter
+        assert!(repo.lock_bucket("token", "alice").await.unwrap().is_none());
+
+        clock.advance(Duration::minutes(1));
+        let updated = RateLimitBucket {
+            tokens: 1.5,
+            updated_at: clock.now(),
+        };
+        repo.save_bucket("login", "alice", updated).await.unwrap();
+        repo.save_bucket("login", "bob", bucket).await.unwrap();
+        assert_eq!(
+            repo.lock_bucket("login", "alice").await.unwrap(),
+            Some(updated)
+        );
+
+        // Only bob's bucket was not updated in the last 30 seconds
+        let count = repo
+            .cleanup("login", clock.now() - Duration::seconds(30))
+            .await
+            .unwrap();
+        assert_eq!(count, 1);
+        assert!(repo.lock_bucket("login", "bob").await.unwrap().is_none());
+        assert!(repo.lock_bucket("login", "alice").await.unwrap().is_some());
+    }
+}
@@ -24,13 +24,17 @@ use mas_storage::{
     job::JobRepository,
     oauth2::{
         OAuth2AccessTokenRepository, OAuth2AuthorizationGrantRepository, OAuth2ClientRepository,
-        OAuth2RefreshTokenRepository, OAuth2SessionRepository,
+        OAuth2JwtBearerIssuerRepository, OAuth2RefreshTokenRepository, OAuth2SessionRepository,
     },
+    rate_limit::RateLimitRepository,
     upstream_oauth2::{
         UpstreamOAuthLinkRepository, UpstreamOAuthProviderRepository,
         UpstreamOAuthSessionRepository,
     },
-    user::{BrowserSessionRepository, UserEmailRepository, UserPasswordRepository, UserRepository},
+    user::{
+        BrowserSessionRepository, UserEmailRepository, UserImpersonationRepository,
+        UserPasswordRepository, UserRepository,
+    },
     Repository, RepositoryAccess, RepositoryTransaction,
 };
 use sqlx::{PgConnection, PgPool, Postgres, Transaction};
@@ -45,15 +49,17 @@ use crate::{
     job::PgJobRepository,
     oauth2::{
         PgOAuth2AccessTokenRepository, PgOAuth2AuthorizationGrantRepository,
-        PgOAuth2ClientRepository, PgOAuth2RefreshTokenRepository, PgOAuth2SessionRepository,
+        PgOAuth2ClientRepository, PgOAuth2JwtBearerIssuerRepository,
+        PgOAuth2RefreshTokenRepository, PgOAuth2SessionRepository,
     },
+    rate_limit::PgRateLimitRepository,
     upstream_oauth2::{
         PgUpstreamOAuthLinkRepository, PgUpstreamOAuthProviderRepository,
         PgUpstreamOAuthSessionRepository,
     },
     user::{
-        PgBrowserSessionRepository, PgUserEmailRepository, PgUserPasswordRepository,
-        PgUserRepository,
+        PgBrowserSessionRepository, PgUserEmailRepository, PgUserImpersonationRepository,
+        PgUserPasswordRepository, PgUserRepository,
     },
     DatabaseError,
 };
@@ -184,6 +190,12 @@ where
         Box::new(PgBrowserSessionRepository::new(self.conn.as_mut()))
     }
 
+    fn user_impersonation<'c>(
+        &'c mut self,
+    ) -> Box<dyn UserImpersonationRepository<Error = Self::Error> + 'c> {
+        Box::new(PgUserImpersonationRepository::new(self.conn.as_mut()))
+    }
+
     fn app_session<'c>(&'c mut self) -> Box<dyn AppSessionRepository<Error = Self::Error> + 'c> {
         Box::new(PgAppSessionRepository::new(self.conn.as_mut()))
     }
@@ -194,6 +206,12 @@ where
         Box::new(PgOAuth2ClientRepository::new(self.conn.as_mut()))
     }
 
+    fn oauth2_jwt_bearer_issuer<'c>(
+        &'c mut self,
+    ) -> Box<dyn OAuth2JwtBearerIssuerRepository<Error = Self::Error> + 'c> {
+        Box::new(PgOAuth2JwtBearerIssuerRepository::new(self.conn.as_mut()))
+    }
+
     fn oauth2_authorization_grant<'c>(
         &'c mut self,
     ) -> Box<dyn OAuth2AuthorizationGrantRepository<Error = Self::Error> + 'c> {
@@ -247,4 +265,8 @@ where
     fn job<'c>(&'c mut self) -> Box<dyn JobRepository<Error = Self::Error> + 'c> {
         Box::new(PgJobRepository::new(self.conn.as_mut()))
     }
+
+    fn rate_limit<'c>(&'c mut self) -> Box<dyn RateLimitRepository<Error = Self::Error> + 'c> {
+        Box::new(PgRateLimitRepository::new(self.conn.as_mut()))
+    }
 }
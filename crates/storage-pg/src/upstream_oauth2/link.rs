@@ -14,20 +14,23 @@
 
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use mas_data_model::{UpstreamOAuthLink, UpstreamOAuthProvider, User};
+use mas_data_model::{UpstreamOAuthLink, UpstreamOAuthLinkTokens, UpstreamOAuthProvider, User};
 use mas_storage::{
     upstream_oauth2::{UpstreamOAuthLinkFilter, UpstreamOAuthLinkRepository},
     Clock, Page, Pagination,
 };
+use oauth2_types::scope::Scope;
 use rand::RngCore;
 use sea_query::{enum_def, Expr, PostgresQueryBuilder, Query};
 use sea_query_binder::SqlxBinder;
 use sqlx::PgConnection;
+use tracing::{info_span, Instrument};
 use ulid::Ulid;
 use uuid::Uuid;
 
 use crate::{
     iden::UpstreamOAuthLinks, pagination::QueryBuilderExt, tracing::ExecuteExt, DatabaseError,
+    DatabaseInconsistencyError,
 };
 
 /// An implementation of [`UpstreamOAuthLinkRepository`] for a PostgreSQL
@@ -66,6 +69,22 @@ impl From<LinkLookup> for UpstreamOAuthLink {
     }
 }
 
+struct TokensLookup {
+    encrypted_access_token: Option<String>,
+    encrypted_refresh_token: Option<String>,
+    access_token_expires_at: Option<DateTime<Utc>>,
+}
+
+impl From<TokensLookup> for Option<UpstreamOAuthLinkTokens> {
+    fn from(value: TokensLookup) -> Self {
+        Some(UpstreamOAuthLinkTokens {
+            encrypted_access_token: value.encrypted_access_token?,
+            encrypted_refresh_token: value.encrypted_refresh_token,
+            access_token_expires_at: value.access_token_expires_at,
+        })
+    }
+}
+
 #[async_trait]
 impl<'c> UpstreamOAuthLinkRepository for PgUpstreamOAuthLinkRepository<'c> {
     type Error = DatabaseError;
@@ -228,6 +247,285 @@ impl<'c> UpstreamOAuthLinkRepository for PgUpstreamOAuthLinkRepository<'c> {
         Ok(())
     }
 
+    #[tracing::instrument(
+        name = "db.upstream_oauth_link.dissociate_from_user",
+        skip_all,
+        fields(
+            db.statement,
+            %upstream_oauth_link.id,
+            %upstream_oauth_link.subject,
+        ),
+        err,
+    )]
+    async fn dissociate_from_user(
+        &mut self,
+        mut upstream_oauth_link: UpstreamOAuthLink,
+    ) -> Result<UpstreamOAuthLink, Self::Error> {
+        let span = info_span!(
+            "db.upstream_oauth_link.dissociate_from_user.claims",
+            db.statement = tracing::field::Empty
+        );
+        sqlx::query!(
+            r#"
+                UPDATE upstream_oauth_authorization_sessions
+                SET id_token = NULL,
+                    userinfo = NULL
+                WHERE upstream_oauth_link_id = $1
+            "#,
+            Uuid::from(upstream_oauth_link.id),
+        )
+        .record(&span)
+        .execute(&mut *self.conn)
+        .instrument(span)
+        .await?;
+
+        // The groups of the user were imported from the upstream claims
+        if let Some(user_id) = upstream_oauth_link.user_id {
+            let span = info_span!(
+                "db.upstream_oauth_link.dissociate_from_user.upstream_groups",
+                db.statement = tracing::field::Empty
+            );
+            sqlx::query!(
+                r#"
+                    UPDATE users
+                    SET upstream_groups = '{}'
+                    WHERE user_id = $1
+                "#,
+                Uuid::from(user_id),
+            )
+            .record(&span)
+            .execute(&mut *self.conn)
+            .instrument(span)
+            .await?;
+        }
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE upstream_oauth_links
+                SET user_id = NULL,
+                    encrypted_access_token = NULL,
+                    encrypted_refresh_token = NULL,
+                    access_token_expires_at = NULL,
+                    granted_scope = NULL
+                WHERE upstream_oauth_link_id = $1
+            "#,
+            Uuid::from(upstream_oauth_link.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        upstream_oauth_link.user_id = None;
+        Ok(upstream_oauth_link)
+    }
+
+    #[tracing::instrument(
+        name = "db.upstream_oauth_link.tokens",
+        skip_all,
+        fields(
+            db.statement,
+            %upstream_oauth_link.id,
+        ),
+        err,
+    )]
+    async fn tokens(
+        &mut self,
+        upstream_oauth_link: &UpstreamOAuthLink,
+    ) -> Result<Option<UpstreamOAuthLinkTokens>, Self::Error> {
+        let res = sqlx::query_as!(
+            TokensLookup,
+            r#"
+                SELECT
+                    encrypted_access_token,
+                    encrypted_refresh_token,
+                    access_token_expires_at
+                FROM upstream_oauth_links
+                WHERE upstream_oauth_link_id = $1
+            "#,
+            Uuid::from(upstream_oauth_link.id),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?
+        .and_then(Into::into);
+
+        Ok(res)
+    }
+
+    #[tracing::instrument(
+        name = "db.upstream_oauth_link.set_tokens",
+        skip_all,
+        fields(
+            db.statement,
+            %upstream_oauth_link.id,
+        ),
+        err,
+    )]
+    async fn set_tokens(
+        &mut self,
+        upstream_oauth_link: &UpstreamOAuthLink,
+        tokens: Option<&UpstreamOAuthLinkTokens>,
+    ) -> Result<(), Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE upstream_oauth_links
+                SET encrypted_access_token = $2,
+                    encrypted_refresh_token = $3,
+                    access_token_expires_at = $4
+                WHERE upstream_oauth_link_id = $1
+            "#,
+            Uuid::from(upstream_oauth_link.id),
+            tokens.map(|t| &*t.encrypted_access_token),
+            tokens.and_then(|t| t.encrypted_refresh_token.as_deref()),
+            tokens.and_then(|t| t.access_token_expires_at),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.upstream_oauth_link.granted_scope",
+        skip_all,
+        fields(
+            db.statement,
+            %upstream_oauth_link.id,
+        ),
+        err,
+    )]
+    async fn granted_scope(
+        &mut self,
+        upstream_oauth_link: &UpstreamOAuthLink,
+    ) -> Result<Option<Scope>, Self::Error> {
+        let res = sqlx::query_scalar!(
+            r#"
+                SELECT granted_scope
+                FROM upstream_oauth_links
+                WHERE upstream_oauth_link_id = $1
+            "#,
+            Uuid::from(upstream_oauth_link.id),
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        let Some(scope) = res else {
+            return Ok(None);
+        };
+
+        let scope = scope.parse().map_err(|e| {
+            DatabaseInconsistencyError::on("upstream_oauth_links")
+                .column("granted_scope")
+                .row(upstream_oauth_link.id)
+                .source(e)
+        })?;
+
+        Ok(Some(scope))
+    }
+
+    #[tracing::instrument(
+        name = "db.upstream_oauth_link.set_granted_scope",
+        skip_all,
+        fields(
+            db.statement,
+            %upstream_oauth_link.id,
+            %scope,
+        ),
+        err,
+    )]
+    async fn set_granted_scope(
+        &mut self,
+        upstream_oauth_link: &UpstreamOAuthLink,
+        scope: &Scope,
+    ) -> Result<(), Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE upstream_oauth_links
+                SET granted_scope = $2
+                WHERE upstream_oauth_link_id = $1
+            "#,
+            Uuid::from(upstream_oauth_link.id),
+            scope.to_string(),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.upstream_oauth_link.list_refreshable",
+        skip_all,
+        fields(
+            db.statement,
+        ),
+        err,
+    )]
+    async fn list_refreshable(
+        &mut self,
+        expires_before: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<(UpstreamOAuthLink, UpstreamOAuthLinkTokens)>, Self::Error> {
+        let limit: i64 = limit
+            .try_into()
+            .map_err(DatabaseError::to_invalid_operation)?;
+
+        let res = sqlx::query!(
+            r#"
+                SELECT
+                    upstream_oauth_link_id,
+                    upstream_oauth_provider_id,
+                    user_id,
+                    subject,
+                    created_at,
+                    encrypted_access_token AS "encrypted_access_token!",
+                    encrypted_refresh_token AS "encrypted_refresh_token!",
+                    access_token_expires_at AS "access_token_expires_at!"
+                FROM upstream_oauth_links
+                WHERE encrypted_access_token IS NOT NULL
+                  AND encrypted_refresh_token IS NOT NULL
+                  AND access_token_expires_at < $1
+                ORDER BY access_token_expires_at ASC
+                LIMIT $2
+            "#,
+            expires_before,
+            limit,
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        let links = res
+            .into_iter()
+            .map(|row| {
+                let link = UpstreamOAuthLink {
+                    id: Ulid::from(row.upstream_oauth_link_id),
+                    provider_id: Ulid::from(row.upstream_oauth_provider_id),
+                    user_id: row.user_id.map(Ulid::from),
+                    subject: row.subject,
+                    created_at: row.created_at,
+                };
+                let tokens = UpstreamOAuthLinkTokens {
+                    encrypted_access_token: row.encrypted_access_token,
+                    encrypted_refresh_token: Some(row.encrypted_refresh_token),
+                    access_token_expires_at: Some(row.access_token_expires_at),
+                };
+                (link, tokens)
+            })
+            .collect();
+
+        Ok(links)
+    }
+
     #[tracing::instrument(
         name = "db.upstream_oauth_link.list",
         skip_all,
@@ -27,17 +27,27 @@ pub use self::{
 #[cfg(test)]
 mod tests {
     use chrono::Duration;
-    use mas_data_model::UpstreamOAuthProviderClaimsImports;
+    use mas_data_model::{
+        UpstreamOAuthLinkTokens, UpstreamOAuthProviderAuthorizationParams,
+        UpstreamOAuthProviderClaimsImports, UpstreamOAuthProviderEndpoints,
+        UpstreamOAuthProviderHealthCheckSettings, UpstreamOAuthProviderPkceMethod,
+        UpstreamOAuthProviderProtocol, UpstreamOAuthProviderSamlSettings,
+        UpstreamOAuthProviderUiOptions,
+    };
+    use mas_jose::jwk::PublicJsonWebKeySet;
     use mas_storage::{
         clock::MockClock,
         upstream_oauth2::{
             UpstreamOAuthLinkFilter, UpstreamOAuthLinkRepository, UpstreamOAuthProviderFilter,
             UpstreamOAuthProviderRepository, UpstreamOAuthSessionRepository,
         },
-        user::UserRepository,
-        Pagination, RepositoryAccess,
+        user::{BrowserSessionFilter, BrowserSessionRepository, UserRepository},
+        Clock, Pagination, RepositoryAccess,
+    };
+    use oauth2_types::{
+        oidc::ProviderMetadata,
+        scope::{Scope, OPENID},
     };
-    use oauth2_types::scope::{Scope, OPENID};
     use rand::SeedableRng;
     use sqlx::PgPool;
 
@@ -60,12 +70,22 @@ mod tests {
                 &mut rng,
                 &clock,
                 "https://example.com/".to_owned(),
+                Some("example".to_owned()),
                 Scope::from_iter([OPENID]),
                 mas_iana::oauth::OAuthClientAuthenticationMethod::None,
                 None,
                 "client-id".to_owned(),
                 None,
                 UpstreamOAuthProviderClaimsImports::default(),
+                UpstreamOAuthProviderPkceMethod::Always,
+                UpstreamOAuthProviderAuthorizationParams::default(),
+                false,
+                UpstreamOAuthProviderUiOptions::default(),
+                UpstreamOAuthProviderProtocol::Oidc,
+                UpstreamOAuthProviderEndpoints::default(),
+                UpstreamOAuthProviderSamlSettings::default(),
+                false,
+                UpstreamOAuthProviderHealthCheckSettings::default(),
             )
             .await
             .unwrap();
@@ -79,6 +99,26 @@ mod tests {
             .expect("provider to be found in the database");
         assert_eq!(provider.issuer, "https://example.com/");
         assert_eq!(provider.client_id, "client-id");
+        assert_eq!(
+            provider.pkce_method,
+            UpstreamOAuthProviderPkceMethod::Always
+        );
+        assert_eq!(provider.slug.as_deref(), Some("example"));
+
+        // Find it by its slug
+        let provider = repo
+            .upstream_oauth_provider()
+            .find_by_slug("example")
+            .await
+            .unwrap()
+            .expect("provider to be found by its slug");
+        assert_eq!(provider.issuer, "https://example.com/");
+        assert!(repo
+            .upstream_oauth_provider()
+            .find_by_slug("other")
+            .await
+            .unwrap()
+            .is_none());
 
         // It should be in the list of all providers
         let providers = repo.upstream_oauth_provider().all().await.unwrap();
@@ -86,6 +126,176 @@ mod tests {
         assert_eq!(providers[0].issuer, "https://example.com/");
         assert_eq!(providers[0].client_id, "client-id");
 
+        // Its metadata was never fetched
+        assert!(repo
+            .upstream_oauth_provider()
+            .metadata(&provider)
+            .await
+            .unwrap()
+            .is_none());
+
+        // Store some metadata
+        let discovery = ProviderMetadata {
+            issuer: Some("https://example.com/".to_owned()),
+            ..ProviderMetadata::default()
+        };
+        repo.upstream_oauth_provider()
+            .set_metadata(&clock, &provider, discovery, PublicJsonWebKeySet::default())
+            .await
+            .unwrap();
+
+        let metadata = repo
+            .upstream_oauth_provider()
+            .metadata(&provider)
+            .await
+            .unwrap()
+            .expect("metadata to be stored");
+        assert_eq!(
+            metadata.discovery.issuer.as_deref(),
+            Some("https://example.com/")
+        );
+        assert!(metadata.jwks.is_empty());
+        assert_eq!(metadata.fetched_at, clock.now());
+
+        // It wasn't registered with
+        assert!(repo
+            .upstream_oauth_provider()
+            .registration(&provider)
+            .await
+            .unwrap()
+            .is_none());
+
+        // Register with it
+        let (provider, _) = repo
+            .upstream_oauth_provider()
+            .set_registration(
+                &clock,
+                provider,
+                "registered-client-id".to_owned(),
+                Some("encrypted-secret".to_owned()),
+                Some("encrypted-token".to_owned()),
+                Some("https://example.com/register/client".parse().unwrap()),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(provider.client_id, "registered-client-id");
+
+        let provider = repo
+            .upstream_oauth_provider()
+            .lookup(provider.id)
+            .await
+            .unwrap()
+            .expect("provider to be found in the database");
+        assert_eq!(provider.client_id, "registered-client-id");
+        assert_eq!(
+            provider.encrypted_client_secret.as_deref(),
+            Some("encrypted-secret")
+        );
+
+        let registration = repo
+            .upstream_oauth_provider()
+            .registration(&provider)
+            .await
+            .unwrap()
+            .expect("registration to be stored");
+        assert_eq!(
+            registration.encrypted_registration_access_token.as_deref(),
+            Some("encrypted-token")
+        );
+        assert_eq!(
+            registration.registration_client_uri.unwrap().as_str(),
+            "https://example.com/register/client"
+        );
+        assert_eq!(registration.client_secret_expires_at, None);
+        assert_eq!(registration.registered_at, clock.now());
+
+        // Forget about the registration, but keep the credentials
+        repo.upstream_oauth_provider()
+            .remove_registration(&provider)
+            .await
+            .unwrap();
+        assert!(repo
+            .upstream_oauth_provider()
+            .registration(&provider)
+            .await
+            .unwrap()
+            .is_none());
+        let provider = repo
+            .upstream_oauth_provider()
+            .lookup(provider.id)
+            .await
+            .unwrap()
+            .expect("provider to be found in the database");
+        assert_eq!(provider.client_id, "registered-client-id");
+
+        // It was never checked
+        assert!(repo
+            .upstream_oauth_provider()
+            .health(&provider)
+            .await
+            .unwrap()
+            .is_none());
+
+        // Record a failed health check, then a successful one
+        repo.upstream_oauth_provider()
+            .set_health(&clock, &provider, Some("unreachable".to_owned()))
+            .await
+            .unwrap();
+        let health = repo
+            .upstream_oauth_provider()
+            .health(&provider)
+            .await
+            .unwrap()
+            .expect("health to be stored");
+        assert!(!health.is_healthy());
+        assert_eq!(health.error.as_deref(), Some("unreachable"));
+        assert_eq!(health.checked_at, clock.now());
+
+        clock.advance(Duration::minutes(15));
+        repo.upstream_oauth_provider()
+            .set_health(&clock, &provider, None)
+            .await
+            .unwrap();
+        let health = repo
+            .upstream_oauth_provider()
+            .health(&provider)
+            .await
+            .unwrap()
+            .expect("health to be stored");
+        assert!(health.is_healthy());
+        assert_eq!(health.checked_at, clock.now());
+
+        // A logout token can only be recorded once
+        let expires_at = clock.now() + Duration::minutes(5);
+        assert!(repo
+            .upstream_oauth_provider()
+            .record_logout_token(&clock, &provider, "logout-1", expires_at)
+            .await
+            .unwrap());
+        assert!(!repo
+            .upstream_oauth_provider()
+            .record_logout_token(&clock, &provider, "logout-1", expires_at)
+            .await
+            .unwrap());
+
+        // They are forgotten once they expire
+        assert_eq!(
+            repo.upstream_oauth_provider()
+                .cleanup_expired_logout_tokens(&clock)
+                .await
+                .unwrap(),
+            0
+        );
+        clock.advance(Duration::minutes(6));
+        assert_eq!(
+            repo.upstream_oauth_provider()
+                .cleanup_expired_logout_tokens(&clock)
+                .await
+                .unwrap(),
+            1
+        );
+
         // Start a session
         let session = repo
             .upstream_oauth_session()
@@ -139,7 +349,14 @@ mod tests {
 
         let session = repo
             .upstream_oauth_session()
-            .complete_with_link(&clock, session, &link, None)
+            .complete_with_link(
+                &clock,
+                session,
+                &link,
+                None,
+                Some("upstream-sid".to_owned()),
+                None,
+            )
             .await
             .unwrap();
         // Reload the session
@@ -195,6 +412,119 @@ mod tests {
 
         assert_eq!(repo.upstream_oauth_link().count(filter).await.unwrap(), 1);
 
+        // Log the user in through the upstream session
+        let browser_session = repo
+            .browser_session()
+            .add(&mut rng, &clock, &user, None)
+            .await
+            .unwrap();
+        repo.browser_session()
+            .authenticate_with_upstream(&mut rng, &clock, &browser_session, &session)
+            .await
+            .unwrap();
+
+        // The browser session can be found from the upstream session
+        let filter = BrowserSessionFilter::new()
+            .authenticated_by_upstream_oauth_provider(&provider)
+            .authenticated_by_upstream_oauth_link(&link)
+            .authenticated_by_upstream_oauth_sid("upstream-sid");
+        let browser_sessions = repo
+            .browser_session()
+            .list(filter, Pagination::first(10))
+            .await
+            .unwrap();
+        assert_eq!(browser_sessions.edges.len(), 1);
+        assert_eq!(browser_sessions.edges[0].id, browser_session.id);
+        assert_eq!(repo.browser_session().count(filter).await.unwrap(), 1);
+
+        let filter = BrowserSessionFilter::new().authenticated_by_upstream_oauth_sid("another-sid");
+        assert_eq!(repo.browser_session().count(filter).await.unwrap(), 0);
+
+        // Store some tokens on the link
+        assert_eq!(
+            repo.upstream_oauth_link().tokens(&link).await.unwrap(),
+            None
+        );
+        let tokens = UpstreamOAuthLinkTokens {
+            encrypted_access_token: "access-token".to_owned(),
+            encrypted_refresh_token: Some("refresh-token".to_owned()),
+            access_token_expires_at: Some(clock.now() + Duration::minutes(5)),
+        };
+        repo.upstream_oauth_link()
+            .set_tokens(&link, Some(&tokens))
+            .await
+            .unwrap();
+        assert_eq!(
+            repo.upstream_oauth_link().tokens(&link).await.unwrap(),
+            Some(tokens.clone())
+        );
+
+        // Record the scopes granted through the link
+        assert_eq!(
+            repo.upstream_oauth_link()
+                .granted_scope(&link)
+                .await
+                .unwrap(),
+            None
+        );
+        let scope: Scope = "openid calendar".parse().unwrap();
+        repo.upstream_oauth_link()
+            .set_granted_scope(&link, &scope)
+            .await
+            .unwrap();
+        assert_eq!(
+            repo.upstream_oauth_link()
+                .granted_scope(&link)
+                .await
+                .unwrap(),
+            Some(scope)
+        );
+
+        // They only need to be refreshed once they are about to expire
+        let refreshable = repo
+            .upstream_oauth_link()
+            .list_refreshable(clock.now(), 10)
+            .await
+            .unwrap();
+        assert!(refreshable.is_empty());
+        let refreshable = repo
+            .upstream_oauth_link()
+            .list_refreshable(clock.now() + Duration::minutes(10), 10)
+            .await
+            .unwrap();
+        assert_eq!(refreshable, vec![(link.clone(), tokens)]);
+
+        // Dissociate the link from the user
+        let link = repo
+            .upstream_oauth_link()
+            .dissociate_from_user(link)
+            .await
+            .unwrap();
+        assert_eq!(link.user_id, None);
+        assert_eq!(repo.upstream_oauth_link().count(filter).await.unwrap(), 0);
+
+        // The link itself should still be there
+        let link = repo
+            .upstream_oauth_link()
+            .lookup(link.id)
+            .await
+            .unwrap()
+            .expect("link to be found in database");
+        assert_eq!(link.user_id, None);
+
+        // The tokens and the granted scopes should have been erased
+        assert_eq!(
+            repo.upstream_oauth_link().tokens(&link).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            repo.upstream_oauth_link()
+                .granted_scope(&link)
+                .await
+                .unwrap(),
+            None
+        );
+
         // Try deleting the provider
         repo.upstream_oauth_provider()
             .delete(provider)
@@ -233,12 +563,22 @@ mod tests {
                     &mut rng,
                     &clock,
                     ISSUER.to_owned(),
+                    None,
                     scope.clone(),
                     mas_iana::oauth::OAuthClientAuthenticationMethod::None,
                     None,
                     client_id,
                     None,
                     UpstreamOAuthProviderClaimsImports::default(),
+                    UpstreamOAuthProviderPkceMethod::default(),
+                    UpstreamOAuthProviderAuthorizationParams::default(),
+                    false,
+                    UpstreamOAuthProviderUiOptions::default(),
+                    UpstreamOAuthProviderProtocol::Oidc,
+                    UpstreamOAuthProviderEndpoints::default(),
+                    UpstreamOAuthProviderSamlSettings::default(),
+                    false,
+                    UpstreamOAuthProviderHealthCheckSettings::default(),
                 )
                 .await
                 .unwrap();
@@ -14,19 +14,28 @@
 
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use mas_data_model::{UpstreamOAuthProvider, UpstreamOAuthProviderClaimsImports};
+use mas_data_model::{
+    UpstreamOAuthProvider, UpstreamOAuthProviderAuthorizationParams,
+    UpstreamOAuthProviderClaimsImports, UpstreamOAuthProviderEndpoints,
+    UpstreamOAuthProviderHealth, UpstreamOAuthProviderHealthCheckSettings,
+    UpstreamOAuthProviderMetadata, UpstreamOAuthProviderPkceMethod, UpstreamOAuthProviderProtocol,
+    UpstreamOAuthProviderRegistration, UpstreamOAuthProviderSamlSettings,
+    UpstreamOAuthProviderUiOptions,
+};
 use mas_iana::{jose::JsonWebSignatureAlg, oauth::OAuthClientAuthenticationMethod};
+use mas_jose::jwk::PublicJsonWebKeySet;
 use mas_storage::{
     upstream_oauth2::{UpstreamOAuthProviderFilter, UpstreamOAuthProviderRepository},
     Clock, Page, Pagination,
 };
-use oauth2_types::scope::Scope;
+use oauth2_types::{oidc::ProviderMetadata, scope::Scope};
 use rand::RngCore;
 use sea_query::{enum_def, Expr, PostgresQueryBuilder, Query};
 use sea_query_binder::SqlxBinder;
 use sqlx::{types::Json, PgConnection};
 use tracing::{info_span, Instrument};
 use ulid::Ulid;
+use url::Url;
 use uuid::Uuid;
 
 use crate::{
@@ -60,6 +69,16 @@ struct ProviderLookup {
     token_endpoint_auth_method: String,
     created_at: DateTime<Utc>,
     claims_imports: Json<UpstreamOAuthProviderClaimsImports>,
+    pkce_method: String,
+    authorization_params: Json<UpstreamOAuthProviderAuthorizationParams>,
+    fetch_userinfo: bool,
+    slug: Option<String>,
+    ui_options: Json<UpstreamOAuthProviderUiOptions>,
+    protocol: String,
+    endpoints: Json<UpstreamOAuthProviderEndpoints>,
+    saml: Json<UpstreamOAuthProviderSamlSettings>,
+    store_tokens: bool,
+    health_check: Json<UpstreamOAuthProviderHealthCheckSettings>,
 }
 
 impl TryFrom<ProviderLookup> for UpstreamOAuthProvider {
@@ -88,6 +107,18 @@ impl TryFrom<ProviderLookup> for UpstreamOAuthProvider {
                     .row(id)
                     .source(e)
             })?;
+        let pkce_method = UpstreamOAuthProviderPkceMethod::from_name(&value.pkce_method)
+            .ok_or_else(|| {
+                DatabaseInconsistencyError::on("upstream_oauth_providers")
+                    .column("pkce_method")
+                    .row(id)
+            })?;
+        let protocol =
+            UpstreamOAuthProviderProtocol::from_name(&value.protocol).ok_or_else(|| {
+                DatabaseInconsistencyError::on("upstream_oauth_providers")
+                    .column("protocol")
+                    .row(id)
+            })?;
 
         Ok(UpstreamOAuthProvider {
             id,
@@ -99,6 +130,16 @@ impl TryFrom<ProviderLookup> for UpstreamOAuthProvider {
             token_endpoint_signing_alg,
             created_at: value.created_at,
             claims_imports: value.claims_imports.0,
+            pkce_method,
+            authorization_params: value.authorization_params.0,
+            fetch_userinfo: value.fetch_userinfo,
+            slug: value.slug,
+            ui_options: value.ui_options.0,
+            protocol,
+            endpoints: value.endpoints.0,
+            saml: value.saml.0,
+            store_tokens: value.store_tokens,
+            health_check: value.health_check.0,
         })
     }
 }
@@ -129,7 +170,17 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
                     token_endpoint_signing_alg,
                     token_endpoint_auth_method,
                     created_at,
-                    claims_imports as "claims_imports: Json<UpstreamOAuthProviderClaimsImports>"
+                    claims_imports as "claims_imports: Json<UpstreamOAuthProviderClaimsImports>",
+                    pkce_method,
+                    authorization_params as "authorization_params: _",
+                    fetch_userinfo,
+                    slug,
+                    ui_options as "ui_options: Json<UpstreamOAuthProviderUiOptions>",
+                    protocol,
+                    endpoints as "endpoints: Json<UpstreamOAuthProviderEndpoints>",
+                    saml as "saml: Json<UpstreamOAuthProviderSamlSettings>",
+                    store_tokens,
+                    health_check as "health_check: Json<UpstreamOAuthProviderHealthCheckSettings>"
                 FROM upstream_oauth_providers
                 WHERE upstream_oauth_provider_id = $1
             "#,
@@ -147,6 +198,59 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
         Ok(res)
     }
 
+    #[tracing::instrument(
+        name = "db.upstream_oauth_provider.find_by_slug",
+        skip_all,
+        fields(
+            db.statement,
+            upstream_oauth_provider.slug = slug,
+        ),
+        err,
+    )]
+    async fn find_by_slug(
+        &mut self,
+        slug: &str,
+    ) -> Result<Option<UpstreamOAuthProvider>, Self::Error> {
+        let res = sqlx::query_as!(
+            ProviderLookup,
+            r#"
+                SELECT
+                    upstream_oauth_provider_id,
+                    issuer,
+                    scope,
+                    client_id,
+                    encrypted_client_secret,
+                    token_endpoint_signing_alg,
+                    token_endpoint_auth_method,
+                    created_at,
+                    claims_imports as "claims_imports: Json<UpstreamOAuthProviderClaimsImports>",
+                    pkce_method,
+                    authorization_params as "authorization_params: _",
+                    fetch_userinfo,
+                    slug,
+                    ui_options as "ui_options: Json<UpstreamOAuthProviderUiOptions>",
+                    protocol,
+                    endpoints as "endpoints: Json<UpstreamOAuthProviderEndpoints>",
+                    saml as "saml: Json<UpstreamOAuthProviderSamlSettings>",
+                    store_tokens,
+                    health_check as "health_check: Json<UpstreamOAuthProviderHealthCheckSettings>"
+                FROM upstream_oauth_providers
+                WHERE slug = $1
+            "#,
+            slug,
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        let res = res
+            .map(UpstreamOAuthProvider::try_from)
+            .transpose()
+            .map_err(DatabaseError::from)?;
+
+        Ok(res)
+    }
+
     #[tracing::instrument(
         name = "db.upstream_oauth_provider.add",
         skip_all,
@@ -164,12 +268,22 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
         rng: &mut (dyn RngCore + Send),
         clock: &dyn Clock,
         issuer: String,
+        slug: Option<String>,
         scope: Scope,
         token_endpoint_auth_method: OAuthClientAuthenticationMethod,
         token_endpoint_signing_alg: Option<JsonWebSignatureAlg>,
         client_id: String,
         encrypted_client_secret: Option<String>,
         claims_imports: UpstreamOAuthProviderClaimsImports,
+        pkce_method: UpstreamOAuthProviderPkceMethod,
+        authorization_params: UpstreamOAuthProviderAuthorizationParams,
+        fetch_userinfo: bool,
+        ui_options: UpstreamOAuthProviderUiOptions,
+        protocol: UpstreamOAuthProviderProtocol,
+        endpoints: UpstreamOAuthProviderEndpoints,
+        saml: UpstreamOAuthProviderSamlSettings,
+        store_tokens: bool,
+        health_check: UpstreamOAuthProviderHealthCheckSettings,
     ) -> Result<UpstreamOAuthProvider, Self::Error> {
         let created_at = clock.now();
         let id = Ulid::from_datetime_with_source(created_at.into(), rng);
@@ -186,8 +300,19 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
                 client_id,
                 encrypted_client_secret,
                 created_at,
-                claims_imports
-            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                claims_imports,
+                pkce_method,
+                authorization_params,
+                fetch_userinfo,
+                slug,
+                ui_options,
+                protocol,
+                endpoints,
+                saml,
+                store_tokens,
+                health_check
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17,
+                $18, $19)
         "#,
             Uuid::from(id),
             &issuer,
@@ -198,6 +323,16 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
             encrypted_client_secret.as_deref(),
             created_at,
             Json(&claims_imports) as _,
+            pkce_method.as_str(),
+            Json(&authorization_params) as _,
+            fetch_userinfo,
+            slug.as_deref(),
+            Json(&ui_options) as _,
+            protocol.as_str(),
+            Json(&endpoints) as _,
+            Json(&saml) as _,
+            store_tokens,
+            Json(&health_check) as _,
         )
         .traced()
         .execute(&mut *self.conn)
@@ -213,6 +348,16 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
             token_endpoint_auth_method,
             created_at,
             claims_imports,
+            pkce_method,
+            authorization_params,
+            fetch_userinfo,
+            slug,
+            ui_options,
+            protocol,
+            endpoints,
+            saml,
+            store_tokens,
+            health_check,
         })
     }
 
@@ -299,12 +444,22 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
         clock: &dyn Clock,
         id: Ulid,
         issuer: String,
+        slug: Option<String>,
         scope: Scope,
         token_endpoint_auth_method: OAuthClientAuthenticationMethod,
         token_endpoint_signing_alg: Option<JsonWebSignatureAlg>,
         client_id: String,
         encrypted_client_secret: Option<String>,
         claims_imports: UpstreamOAuthProviderClaimsImports,
+        pkce_method: UpstreamOAuthProviderPkceMethod,
+        authorization_params: UpstreamOAuthProviderAuthorizationParams,
+        fetch_userinfo: bool,
+        ui_options: UpstreamOAuthProviderUiOptions,
+        protocol: UpstreamOAuthProviderProtocol,
+        endpoints: UpstreamOAuthProviderEndpoints,
+        saml: UpstreamOAuthProviderSamlSettings,
+        store_tokens: bool,
+        health_check: UpstreamOAuthProviderHealthCheckSettings,
     ) -> Result<UpstreamOAuthProvider, Self::Error> {
         let created_at = clock.now();
 
@@ -319,8 +474,19 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
                     client_id,
                     encrypted_client_secret,
                     created_at,
-                    claims_imports
-                ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                    claims_imports,
+                    pkce_method,
+                    authorization_params,
+                    fetch_userinfo,
+                    slug,
+                    ui_options,
+                    protocol,
+                    endpoints,
+                    saml,
+                    store_tokens,
+                    health_check
+                ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16,
+                    $17, $18, $19)
                 ON CONFLICT (upstream_oauth_provider_id) 
                     DO UPDATE
                     SET
@@ -330,7 +496,17 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
                         token_endpoint_signing_alg = EXCLUDED.token_endpoint_signing_alg,
                         client_id = EXCLUDED.client_id,
                         encrypted_client_secret = EXCLUDED.encrypted_client_secret,
-                        claims_imports = EXCLUDED.claims_imports
+                        claims_imports = EXCLUDED.claims_imports,
+                        pkce_method = EXCLUDED.pkce_method,
+                        authorization_params = EXCLUDED.authorization_params,
+                        fetch_userinfo = EXCLUDED.fetch_userinfo,
+                        slug = EXCLUDED.slug,
+                        ui_options = EXCLUDED.ui_options,
+                        protocol = EXCLUDED.protocol,
+                        endpoints = EXCLUDED.endpoints,
+                        saml = EXCLUDED.saml,
+                        store_tokens = EXCLUDED.store_tokens,
+                        health_check = EXCLUDED.health_check
                 RETURNING created_at
             "#,
             Uuid::from(id),
@@ -342,6 +518,16 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
             encrypted_client_secret.as_deref(),
             created_at,
             Json(&claims_imports) as _,
+            pkce_method.as_str(),
+            Json(&authorization_params) as _,
+            fetch_userinfo,
+            slug.as_deref(),
+            Json(&ui_options) as _,
+            protocol.as_str(),
+            Json(&endpoints) as _,
+            Json(&saml) as _,
+            store_tokens,
+            Json(&health_check) as _,
         )
         .traced()
         .fetch_one(&mut *self.conn)
@@ -357,6 +543,16 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
             token_endpoint_auth_method,
             created_at,
             claims_imports,
+            pkce_method,
+            authorization_params,
+            fetch_userinfo,
+            slug,
+            ui_options,
+            protocol,
+            endpoints,
+            saml,
+            store_tokens,
+            health_check,
         })
     }
 
@@ -435,6 +631,70 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
                 )),
                 ProviderLookupIden::ClaimsImports,
             )
+            .expr_as(
+                Expr::col((
+                    UpstreamOAuthProviders::Table,
+                    UpstreamOAuthProviders::PkceMethod,
+                )),
+                ProviderLookupIden::PkceMethod,
+            )
+            .expr_as(
+                Expr::col((
+                    UpstreamOAuthProviders::Table,
+                    UpstreamOAuthProviders::AuthorizationParams,
+                )),
+                ProviderLookupIden::AuthorizationParams,
+            )
+            .expr_as(
+                Expr::col((
+                    UpstreamOAuthProviders::Table,
+                    UpstreamOAuthProviders::FetchUserinfo,
+                )),
+                ProviderLookupIden::FetchUserinfo,
+            )
+            .expr_as(
+                Expr::col((UpstreamOAuthProviders::Table, UpstreamOAuthProviders::Slug)),
+                ProviderLookupIden::Slug,
+            )
+            .expr_as(
+                Expr::col((
+                    UpstreamOAuthProviders::Table,
+                    UpstreamOAuthProviders::UiOptions,
+                )),
+                ProviderLookupIden::UiOptions,
+            )
+            .expr_as(
+                Expr::col((
+                    UpstreamOAuthProviders::Table,
+                    UpstreamOAuthProviders::Protocol,
+                )),
+                ProviderLookupIden::Protocol,
+            )
+            .expr_as(
+                Expr::col((
+                    UpstreamOAuthProviders::Table,
+                    UpstreamOAuthProviders::Endpoints,
+                )),
+                ProviderLookupIden::Endpoints,
+            )
+            .expr_as(
+                Expr::col((UpstreamOAuthProviders::Table, UpstreamOAuthProviders::Saml)),
+                ProviderLookupIden::Saml,
+            )
+            .expr_as(
+                Expr::col((
+                    UpstreamOAuthProviders::Table,
+                    UpstreamOAuthProviders::StoreTokens,
+                )),
+                ProviderLookupIden::StoreTokens,
+            )
+            .expr_as(
+                Expr::col((
+                    UpstreamOAuthProviders::Table,
+                    UpstreamOAuthProviders::HealthCheck,
+                )),
+                ProviderLookupIden::HealthCheck,
+            )
             .from(UpstreamOAuthProviders::Table)
             .generate_pagination(
                 (
@@ -512,7 +772,17 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
                     token_endpoint_signing_alg,
                     token_endpoint_auth_method,
                     created_at,
-                    claims_imports as "claims_imports: Json<UpstreamOAuthProviderClaimsImports>"
+                    claims_imports as "claims_imports: Json<UpstreamOAuthProviderClaimsImports>",
+                    pkce_method,
+                    authorization_params as "authorization_params: _",
+                    fetch_userinfo,
+                    slug,
+                    ui_options as "ui_options: Json<UpstreamOAuthProviderUiOptions>",
+                    protocol,
+                    endpoints as "endpoints: Json<UpstreamOAuthProviderEndpoints>",
+                    saml as "saml: Json<UpstreamOAuthProviderSamlSettings>",
+                    store_tokens,
+                    health_check as "health_check: Json<UpstreamOAuthProviderHealthCheckSettings>"
                 FROM upstream_oauth_providers
             "#,
         )
@@ -523,4 +793,383 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
         let res: Result<Vec<_>, _> = res.into_iter().map(TryInto::try_into).collect();
         Ok(res?)
     }
+
+    #[tracing::instrument(
+        name = "db.upstream_oauth_provider.metadata",
+        skip_all,
+        fields(
+            db.statement,
+            upstream_oauth_provider.id = %provider.id,
+        ),
+        err,
+    )]
+    async fn metadata(
+        &mut self,
+        provider: &UpstreamOAuthProvider,
+    ) -> Result<Option<UpstreamOAuthProviderMetadata>, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                SELECT
+                    discovery_metadata as "discovery_metadata: Json<ProviderMetadata>",
+                    jwks as "jwks: Json<PublicJsonWebKeySet>",
+                    metadata_fetched_at
+                FROM upstream_oauth_providers
+                WHERE upstream_oauth_provider_id = $1
+            "#,
+            Uuid::from(provider.id),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        let Some(res) = res else { return Ok(None) };
+
+        // The three columns are always set together
+        let (Some(discovery), Some(jwks), Some(fetched_at)) =
+            (res.discovery_metadata, res.jwks, res.metadata_fetched_at)
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(UpstreamOAuthProviderMetadata {
+            discovery: discovery.0,
+            jwks: jwks.0,
+            fetched_at,
+        }))
+    }
+
+    #[tracing::instrument(
+        name = "db.upstream_oauth_provider.set_metadata",
+        skip_all,
+        fields(
+            db.statement,
+            upstream_oauth_provider.id = %provider.id,
+        ),
+        err,
+    )]
+    async fn set_metadata(
+        &mut self,
+        clock: &dyn Clock,
+        provider: &UpstreamOAuthProvider,
+        discovery: ProviderMetadata,
+        jwks: PublicJsonWebKeySet,
+    ) -> Result<UpstreamOAuthProviderMetadata, Self::Error> {
+        let fetched_at = clock.now();
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE upstream_oauth_providers
+                SET discovery_metadata = $2,
+                    jwks = $3,
+                    metadata_fetched_at = $4
+                WHERE upstream_oauth_provider_id = $1
+            "#,
+            Uuid::from(provider.id),
+            Json(&discovery) as _,
+            Json(&jwks) as _,
+            fetched_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(UpstreamOAuthProviderMetadata {
+            discovery,
+            jwks,
+            fetched_at,
+        })
+    }
+
+    #[tracing::instrument(
+        name = "db.upstream_oauth_provider.registration",
+        skip_all,
+        fields(
+            db.statement,
+            upstream_oauth_provider.id = %provider.id,
+        ),
+        err,
+    )]
+    async fn registration(
+        &mut self,
+        provider: &UpstreamOAuthProvider,
+    ) -> Result<Option<UpstreamOAuthProviderRegistration>, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                SELECT
+                    encrypted_registration_access_token,
+                    registration_client_uri,
+                    client_secret_expires_at,
+                    registered_at
+                FROM upstream_oauth_providers
+                WHERE upstream_oauth_provider_id = $1
+            "#,
+            Uuid::from(provider.id),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        // Providers which were never registered with have no registration date
+        let Some(res) = res else { return Ok(None) };
+        let Some(registered_at) = res.registered_at else {
+            return Ok(None);
+        };
+
+        let registration_client_uri = res
+            .registration_client_uri
+            .map(|uri| uri.parse())
+            .transpose()
+            .map_err(|e| {
+                DatabaseInconsistencyError::on("upstream_oauth_providers")
+                    .column("registration_client_uri")
+                    .row(provider.id)
+                    .source(e)
+            })?;
+
+        Ok(Some(UpstreamOAuthProviderRegistration {
+            encrypted_registration_access_token: res.encrypted_registration_access_token,
+            registration_client_uri,
+            client_secret_expires_at: res.client_secret_expires_at,
+            registered_at,
+        }))
+    }
+
+    #[tracing::instrument(
+        name = "db.upstream_oauth_provider.set_registration",
+        skip_all,
+        fields(
+            db.statement,
+            upstream_oauth_provider.id = %provider.id,
+            upstream_oauth_provider.client_id = client_id,
+        ),
+        err,
+    )]
+    async fn set_registration(
+        &mut self,
+        clock: &dyn Clock,
+        mut provider: UpstreamOAuthProvider,
+        client_id: String,
+        encrypted_client_secret: Option<String>,
+        encrypted_registration_access_token: Option<String>,
+        registration_client_uri: Option<Url>,
+        client_secret_expires_at: Option<DateTime<Utc>>,
+    ) -> Result<(UpstreamOAuthProvider, UpstreamOAuthProviderRegistration), Self::Error> {
+        let registered_at = clock.now();
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE upstream_oauth_providers
+                SET client_id = $2,
+                    encrypted_client_secret = $3,
+                    encrypted_registration_access_token = $4,
+                    registration_client_uri = $5,
+                    client_secret_expires_at = $6,
+                    registered_at = $7
+                WHERE upstream_oauth_provider_id = $1
+            "#,
+            Uuid::from(provider.id),
+            &client_id,
+            encrypted_client_secret.as_deref(),
+            encrypted_registration_access_token.as_deref(),
+            registration_client_uri.as_ref().map(Url::as_str),
+            client_secret_expires_at,
+            registered_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        provider.client_id = client_id;
+        provider.encrypted_client_secret = encrypted_client_secret;
+
+        Ok((
+            provider,
+            UpstreamOAuthProviderRegistration {
+                encrypted_registration_access_token,
+                registration_client_uri,
+                client_secret_expires_at,
+                registered_at,
+            },
+        ))
+    }
+
+    #[tracing::instrument(
+        name = "db.upstream_oauth_provider.remove_registration",
+        skip_all,
+        fields(
+            db.statement,
+            upstream_oauth_provider.id = %provider.id,
+        ),
+        err,
+    )]
+    async fn remove_registration(
+        &mut self,
+        provider: &UpstreamOAuthProvider,
+    ) -> Result<(), Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE upstream_oauth_providers
+                SET encrypted_registration_access_token = NULL,
+                    registration_client_uri = NULL,
+                    client_secret_expires_at = NULL,
+                    registered_at = NULL
+                WHERE upstream_oauth_provider_id = $1
+            "#,
+            Uuid::from(provider.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.upstream_oauth_provider.health",
+        skip_all,
+        fields(
+            db.statement,
+            upstream_oauth_provider.id = %provider.id,
+        ),
+        err,
+    )]
+    async fn health(
+        &mut self,
+        provider: &UpstreamOAuthProvider,
+    ) -> Result<Option<UpstreamOAuthProviderHealth>, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                SELECT
+                    health_error,
+                    health_checked_at
+                FROM upstream_oauth_providers
+                WHERE upstream_oauth_provider_id = $1
+            "#,
+            Uuid::from(provider.id),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        // Providers which were never checked have no check date
+        let Some(res) = res else { return Ok(None) };
+        let Some(checked_at) = res.health_checked_at else {
+            return Ok(None);
+        };
+
+        Ok(Some(UpstreamOAuthProviderHealth {
+            error: res.health_error,
+            checked_at,
+        }))
+    }
+
+    #[tracing::instrument(
+        name = "db.upstream_oauth_provider.set_health",
+        skip_all,
+        fields(
+            db.statement,
+            upstream_oauth_provider.id = %provider.id,
+        ),
+        err,
+    )]
+    async fn set_health(
+        &mut self,
+        clock: &dyn Clock,
+        provider: &UpstreamOAuthProvider,
+        error: Option<String>,
+    ) -> Result<UpstreamOAuthProviderHealth, Self::Error> {
+        let checked_at = clock.now();
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE upstream_oauth_providers
+                SET health_error = $2,
+                    health_checked_at = $3
+                WHERE upstream_oauth_provider_id = $1
+            "#,
+            Uuid::from(provider.id),
+            error.as_deref(),
+            checked_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(UpstreamOAuthProviderHealth { error, checked_at })
+    }
+    #[tracing::instrument(
+        name = "db.upstream_oauth_provider.record_logout_token",
+        skip_all,
+        fields(
+            db.statement,
+            upstream_oauth_provider.id = %provider.id,
+            upstream_oauth_logout_token.jti = jti,
+        ),
+        err,
+    )]
+    async fn record_logout_token(
+        &mut self,
+        clock: &dyn Clock,
+        provider: &UpstreamOAuthProvider,
+        jti: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, Self::Error> {
+        let created_at = clock.now();
+
+        let res = sqlx::query!(
+            r#"
+                INSERT INTO upstream_oauth_logout_tokens
+                    ( upstream_oauth_provider_id
+                    , jti
+                    , created_at
+                    , expires_at
+                    )
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (upstream_oauth_provider_id, jti) DO NOTHING
+            "#,
+            Uuid::from(provider.id),
+            jti,
+            created_at,
+            expires_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(res.rows_affected() == 1)
+    }
+
+    #[tracing::instrument(
+        name = "db.upstream_oauth_provider.cleanup_expired_logout_tokens",
+        skip_all,
+        fields(
+            db.statement,
+        ),
+        err,
+    )]
+    async fn cleanup_expired_logout_tokens(
+        &mut self,
+        clock: &dyn Clock,
+    ) -> Result<usize, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                DELETE FROM upstream_oauth_logout_tokens
+                WHERE expires_at < $1
+            "#,
+            clock.now(),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(res.rows_affected().try_into().unwrap_or(usize::MAX))
+    }
 }
@@ -48,6 +48,7 @@ struct SessionLookup {
     code_challenge_verifier: Option<String>,
     nonce: String,
     id_token: Option<String>,
+    userinfo: Option<serde_json::Value>,
     created_at: DateTime<Utc>,
     completed_at: Option<DateTime<Utc>>,
     consumed_at: Option<DateTime<Utc>>,
@@ -70,6 +71,7 @@ impl TryFrom<SessionLookup> for UpstreamOAuthAuthorizationSession {
                     completed_at,
                     link_id: link_id.into(),
                     id_token,
+                    userinfo: value.userinfo,
                 }
             }
             (Some(link_id), id_token, Some(completed_at), Some(consumed_at)) => {
@@ -77,6 +79,7 @@ impl TryFrom<SessionLookup> for UpstreamOAuthAuthorizationSession {
                     completed_at,
                     link_id: link_id.into(),
                     id_token,
+                    userinfo: value.userinfo,
                     consumed_at,
                 }
             }
@@ -127,6 +130,7 @@ impl<'c> UpstreamOAuthSessionRepository for PgUpstreamOAuthSessionRepository<'c>
                     code_challenge_verifier,
                     nonce,
                     id_token,
+                    userinfo,
                     created_at,
                     completed_at,
                     consumed_at
@@ -224,6 +228,8 @@ impl<'c> UpstreamOAuthSessionRepository for PgUpstreamOAuthSessionRepository<'c>
         upstream_oauth_authorization_session: UpstreamOAuthAuthorizationSession,
         upstream_oauth_link: &UpstreamOAuthLink,
         id_token: Option<String>,
+        sid: Option<String>,
+        userinfo: Option<serde_json::Value>,
     ) -> Result<UpstreamOAuthAuthorizationSession, Self::Error> {
         let completed_at = clock.now();
 
@@ -232,12 +238,16 @@ impl<'c> UpstreamOAuthSessionRepository for PgUpstreamOAuthSessionRepository<'c>
                 UPDATE upstream_oauth_authorization_sessions
                 SET upstream_oauth_link_id = $1,
                     completed_at = $2,
-                    id_token = $3
-                WHERE upstream_oauth_authorization_session_id = $4
+                    id_token = $3,
+                    sid = $4,
+                    userinfo = $5
+                WHERE upstream_oauth_authorization_session_id = $6
             "#,
             Uuid::from(upstream_oauth_link.id),
             completed_at,
             id_token,
+            sid,
+            userinfo,
             Uuid::from(upstream_oauth_authorization_session.id),
         )
         .traced()
@@ -245,7 +255,7 @@ impl<'c> UpstreamOAuthSessionRepository for PgUpstreamOAuthSessionRepository<'c>
         .await?;
 
         let upstream_oauth_authorization_session = upstream_oauth_authorization_session
-            .complete(completed_at, upstream_oauth_link, id_token)
+            .complete(completed_at, upstream_oauth_link, id_token, userinfo)
             .map_err(DatabaseError::to_invalid_operation)?;
 
         Ok(upstream_oauth_authorization_session)
@@ -14,7 +14,10 @@
 
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use mas_data_model::{User, UserEmail, UserEmailVerification, UserEmailVerificationState};
+use mas_data_model::{
+    EmailSendCounts, User, UserEmail, UserEmailChange, UserEmailVerification,
+    UserEmailVerificationState,
+};
 use mas_storage::{
     user::{UserEmailFilter, UserEmailRepository},
     Clock, Page, Pagination,
@@ -99,6 +102,36 @@ impl UserEmailConfirmationCodeLookup {
     }
 }
 
+struct UserEmailChangeLookup {
+    user_email_change_id: Uuid,
+    user_id: Uuid,
+    old_user_email_id: Uuid,
+    new_user_email_id: Uuid,
+    revert_token: String,
+    created_at: DateTime<Utc>,
+    verified_at: Option<DateTime<Utc>>,
+    completed_at: Option<DateTime<Utc>>,
+    reverted_at: Option<DateTime<Utc>>,
+    finished_at: Option<DateTime<Utc>>,
+}
+
+impl From<UserEmailChangeLookup> for UserEmailChange {
+    fn from(e: UserEmailChangeLookup) -> UserEmailChange {
+        UserEmailChange {
+            id: e.user_email_change_id.into(),
+            user_id: e.user_id.into(),
+            old_user_email_id: e.old_user_email_id.into(),
+            new_user_email_id: e.new_user_email_id.into(),
+            revert_token: e.revert_token,
+            created_at: e.created_at,
+            verified_at: e.verified_at,
+            completed_at: e.completed_at,
+            reverted_at: e.reverted_at,
+            finished_at: e.finished_at,
+        }
+    }
+}
+
 #[async_trait]
 impl<'c> UserEmailRepository for PgUserEmailRepository<'c> {
     type Error = DatabaseError;
@@ -269,6 +302,11 @@ impl<'c> UserEmailRepository for PgUserEmailRepository<'c> {
             .and_where_option(filter.user().map(|user| {
                 Expr::col((UserEmails::Table, UserEmails::UserId)).eq(Uuid::from(user.id))
             }))
+            .and_where_option(
+                filter
+                    .email()
+                    .map(|email| Expr::col((UserEmails::Table, UserEmails::Email)).eq(email)),
+            )
             .and_where_option(filter.state().map(|state| {
                 if state.is_verified() {
                     Expr::col((UserEmails::Table, UserEmails::ConfirmedAt)).is_not_null()
@@ -304,6 +342,11 @@ impl<'c> UserEmailRepository for PgUserEmailRepository<'c> {
             .and_where_option(filter.user().map(|user| {
                 Expr::col((UserEmails::Table, UserEmails::UserId)).eq(Uuid::from(user.id))
             }))
+            .and_where_option(
+                filter
+                    .email()
+                    .map(|email| Expr::col((UserEmails::Table, UserEmails::Email)).eq(email)),
+            )
             .and_where_option(filter.state().map(|state| {
                 if state.is_verified() {
                     Expr::col((UserEmails::Table, UserEmails::ConfirmedAt)).is_not_null()
@@ -396,6 +439,28 @@ impl<'c> UserEmailRepository for PgUserEmailRepository<'c> {
         .instrument(span)
         .await?;
 
+        // Changes which are still in progress from this address are kept, so
+        // that removing the address fails
+        let span = info_span!(
+            "db.user_email.remove.changes",
+            db.statement = tracing::field::Empty
+        );
+        sqlx::query!(
+            r#"
+                DELETE FROM user_email_changes
+                WHERE new_user_email_id = $1
+                   OR (old_user_email_id = $1
+                       AND (verified_at IS NULL
+                            OR reverted_at IS NOT NULL
+                            OR finished_at IS NOT NULL))
+            "#,
+            Uuid::from(user_email.id),
+        )
+        .record(&span)
+        .execute(&mut *self.conn)
+        .instrument(span)
+        .await?;
+
         let res = sqlx::query!(
             r#"
                 DELETE FROM user_emails
@@ -586,4 +651,526 @@ impl<'c> UserEmailRepository for PgUserEmailRepository<'c> {
 
         Ok(user_email_verification)
     }
+
+    #[tracing::instrument(
+        name = "db.user_email.add_change",
+        skip_all,
+        fields(
+            db.statement,
+            user.id = %old_user_email.user_id,
+            old_user_email.id = %old_user_email.id,
+            new_user_email.id = %new_user_email.id,
+            user_email_change.id,
+        ),
+        err,
+    )]
+    async fn add_change(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        old_user_email: &UserEmail,
+        new_user_email: &UserEmail,
+        revert_token: String,
+    ) -> Result<UserEmailChange, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+        tracing::Span::current().record("user_email_change.id", tracing::field::display(id));
+
+        sqlx::query!(
+            r#"
+                INSERT INTO user_email_changes
+                  ( user_email_change_id
+                  , user_id
+                  , old_user_email_id
+                  , new_user_email_id
+                  , revert_token
+                  , created_at
+                  )
+                VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            Uuid::from(id),
+            Uuid::from(old_user_email.user_id),
+            Uuid::from(old_user_email.id),
+            Uuid::from(new_user_email.id),
+            &revert_token,
+            created_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(UserEmailChange {
+            id,
+            user_id: old_user_email.user_id,
+            old_user_email_id: old_user_email.id,
+            new_user_email_id: new_user_email.id,
+            revert_token,
+            created_at,
+            verified_at: None,
+            completed_at: None,
+            reverted_at: None,
+            finished_at: None,
+        })
+    }
+
+    #[tracing::instrument(
+        name = "db.user_email.lookup_change",
+        skip_all,
+        fields(
+            db.statement,
+            user_email_change.id = %id,
+        ),
+        err,
+    )]
+    async fn lookup_change(&mut self, id: Ulid) -> Result<Option<UserEmailChange>, Self::Error> {
+        let res = sqlx::query_as!(
+            UserEmailChangeLookup,
+            r#"
+                SELECT user_email_change_id
+                     , user_id
+                     , old_user_email_id
+                     , new_user_email_id
+                     , revert_token
+                     , created_at
+                     , verified_at
+                     , completed_at
+                     , reverted_at
+                     , finished_at
+                FROM user_email_changes
+                WHERE user_email_change_id = $1
+            "#,
+            Uuid::from(id),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        Ok(res.map(Into::into))
+    }
+
+    #[tracing::instrument(
+        name = "db.user_email.find_change_by_revert_token",
+        skip_all,
+        fields(
+            db.statement,
+        ),
+        err,
+    )]
+    async fn find_change_by_revert_token(
+        &mut self,
+        revert_token: &str,
+    ) -> Result<Option<UserEmailChange>, Self::Error> {
+        let res = sqlx::query_as!(
+            UserEmailChangeLookup,
+            r#"
+                SELECT user_email_change_id
+                     , user_id
+                     , old_user_email_id
+                     , new_user_email_id
+                     , revert_token
+                     , created_at
+                     , verified_at
+                     , completed_at
+                     , reverted_at
+                     , finished_at
+                FROM user_email_changes
+                WHERE revert_token = $1
+            "#,
+            revert_token,
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        Ok(res.map(Into::into))
+    }
+
+    #[tracing::instrument(
+        name = "db.user_email.find_pending_change",
+        skip_all,
+        fields(
+            db.statement,
+            %new_user_email.id,
+            user.id = %new_user_email.user_id,
+        ),
+        err,
+    )]
+    async fn find_pending_change(
+        &mut self,
+        new_user_email: &UserEmail,
+    ) -> Result<Option<UserEmailChange>, Self::Error> {
+        let res = sqlx::query_as!(
+            UserEmailChangeLookup,
+            r#"
+                SELECT user_email_change_id
+                     , user_id
+                     , old_user_email_id
+                     , new_user_email_id
+                     , revert_token
+                     , created_at
+                     , verified_at
+                     , completed_at
+                     , reverted_at
+                     , finished_at
+                FROM user_email_changes
+                WHERE new_user_email_id = $1
+                  AND completed_at IS NULL
+                  AND reverted_at IS NULL
+                ORDER BY user_email_change_id DESC
+                LIMIT 1
+            "#,
+            Uuid::from(new_user_email.id),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        Ok(res.map(Into::into))
+    }
+
+    #[tracing::instrument(
+        name = "db.user_email.find_change_in_progress",
+        skip_all,
+        fields(
+            db.statement,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn find_change_in_progress(
+        &mut self,
+        clock: &dyn Clock,
+        user: &User,
+    ) -> Result<Option<UserEmailChange>, Self::Error> {
+        let since = clock.now() - UserEmailChange::grace_period();
+
+        let res = sqlx::query_as!(
+            UserEmailChangeLookup,
+            r#"
+                SELECT user_email_change_id
+                     , user_id
+                     , old_user_email_id
+                     , new_user_email_id
+                     , revert_token
+                     , created_at
+                     , verified_at
+                     , completed_at
+                     , reverted_at
+                     , finished_at
+                FROM user_email_changes
+                WHERE user_id = $1
+                  AND reverted_at IS NULL
+                  AND finished_at IS NULL
+                  AND COALESCE(completed_at, verified_at) > $2
+                ORDER BY user_email_change_id DESC
+                LIMIT 1
+            "#,
+            Uuid::from(user.id),
+            since,
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        Ok(res.map(Into::into))
+    }
+
+    #[tracing::instrument(
+        name = "db.user_email.verify_change",
+        skip_all,
+        fields(
+            db.statement,
+            %user_email_change.id,
+            user.id = %user_email_change.user_id,
+        ),
+        err,
+    )]
+    async fn verify_change(
+        &mut self,
+        clock: &dyn Clock,
+        mut user_email_change: UserEmailChange,
+    ) -> Result<UserEmailChange, Self::Error> {
+        if !user_email_change.is_pending() || user_email_change.verified_at.is_some() {
+            return Err(DatabaseError::invalid_operation());
+        }
+
+        let verified_at = clock.now();
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE user_email_changes
+                SET verified_at = $2
+                WHERE user_email_change_id = $1
+            "#,
+            Uuid::from(user_email_change.id),
+            verified_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        user_email_change.verified_at = Some(verified_at);
+
+        Ok(user_email_change)
+    }
+
+    #[tracing::instrument(
+        name = "db.user_email.complete_change",
+        skip_all,
+        fields(
+            db.statement,
+            %user_email_change.id,
+            user.id = %user_email_change.user_id,
+        ),
+        err,
+    )]
+    async fn complete_change(
+        &mut self,
+        clock: &dyn Clock,
+        mut user_email_change: UserEmailChange,
+    ) -> Result<UserEmailChange, Self::Error> {
+        if !user_email_change.is_pending() || user_email_change.verified_at.is_none() {
+            return Err(DatabaseError::invalid_operation());
+        }
+
+        let completed_at = clock.now();
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE user_email_changes
+                SET completed_at = $2
+                WHERE user_email_change_id = $1
+            "#,
+            Uuid::from(user_email_change.id),
+            completed_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        user_email_change.completed_at = Some(completed_at);
+
+        Ok(user_email_change)
+    }
+
+    #[tracing::instrument(
+        name = "db.user_email.revert_change",
+        skip_all,
+        fields(
+            db.statement,
+            %user_email_change.id,
+            user.id = %user_email_change.user_id,
+        ),
+        err,
+    )]
+    async fn revert_change(
+        &mut self,
+        clock: &dyn Clock,
+        mut user_email_change: UserEmailChange,
+    ) -> Result<UserEmailChange, Self::Error> {
+        if user_email_change.reverted_at.is_some() {
+            return Err(DatabaseError::invalid_operation());
+        }
+
+        let reverted_at = clock.now();
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE user_email_changes
+                SET reverted_at = $2
+                WHERE user_email_change_id = $1
+            "#,
+            Uuid::from(user_email_change.id),
+            reverted_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        user_email_change.reverted_at = Some(reverted_at);
+
+        Ok(user_email_change)
+    }
+
+    #[tracing::instrument(
+        name = "db.user_email.finish_change",
+        skip_all,
+        fields(
+            db.statement,
+            %user_email_change.id,
+            user.id = %user_email_change.user_id,
+        ),
+        err,
+    )]
+    async fn finish_change(
+        &mut self,
+        clock: &dyn Clock,
+        mut user_email_change: UserEmailChange,
+    ) -> Result<UserEmailChange, Self::Error> {
+        if user_email_change.completed_at.is_none()
+            || user_email_change.reverted_at.is_some()
+            || user_email_change.finished_at.is_some()
+        {
+            return Err(DatabaseError::invalid_operation());
+        }
+
+        let finished_at = clock.now();
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE user_email_changes
+                SET finished_at = $2
+                WHERE user_email_change_id = $1
+            "#,
+            Uuid::from(user_email_change.id),
+            finished_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        user_email_change.finished_at = Some(finished_at);
+
+        Ok(user_email_change)
+    }
+
+    #[tracing::instrument(
+        name = "db.user_email.record_send",
+        skip_all,
+        fields(
+            db.statement,
+            %user_email.id,
+            %user_email.email,
+            %user_email.user_id,
+            user_email_send.id,
+        ),
+        err,
+    )]
+    async fn record_send(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user_email: &UserEmail,
+    ) -> Result<(), Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+        tracing::Span::current().record("user_email_send.id", tracing::field::display(id));
+
+        sqlx::query!(
+            r#"
+                INSERT INTO user_email_sends
+                  (user_email_send_id, user_id, email, created_at)
+                VALUES ($1, $2, $3, $4)
+            "#,
+            Uuid::from(id),
+            Uuid::from(user_email.user_id),
+            &user_email.email,
+            created_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.user_email.lock_sends",
+        skip_all,
+        fields(
+            db.statement,
+            %user_email.id,
+            %user_email.email,
+            %user_email.user_id,
+        ),
+        err,
+    )]
+    async fn lock_sends(&mut self, user_email: &UserEmail) -> Result<(), Self::Error> {
+        // The locks are taken in the same order everywhere, and released at
+        // the end of the transaction
+        sqlx::query!(
+            r#"
+                SELECT 1 AS "locked!"
+                FROM (
+                    SELECT
+                        pg_advisory_xact_lock(
+                            hashtextextended('user_email_sends:email:' || LOWER($1), 0)
+                        ),
+                        pg_advisory_xact_lock(
+                            hashtextextended('user_email_sends:user:' || $2::UUID::TEXT, 0)
+                        )
+                ) AS locks
+            "#,
+            &user_email.email,
+            Uuid::from(user_email.user_id),
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.user_email.count_sends",
+        skip_all,
+        fields(
+            db.statement,
+            %user_email.id,
+            %user_email.email,
+            %user_email.user_id,
+        ),
+        err,
+    )]
+    async fn count_sends(
+        &mut self,
+        user_email: &UserEmail,
+        since: DateTime<Utc>,
+    ) -> Result<EmailSendCounts, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                SELECT
+                    ( SELECT MAX(created_at)
+                      FROM user_email_sends
+                      WHERE LOWER(email) = LOWER($1)
+                    ) AS "last_sent_to_address",
+                    ( SELECT COUNT(*)
+                      FROM user_email_sends
+                      WHERE LOWER(email) = LOWER($1)
+                        AND created_at >= $3
+                    ) AS "sent_to_address!",
+                    ( SELECT COUNT(*)
+                      FROM user_email_sends
+                      WHERE user_id = $2
+                        AND created_at >= $3
+                    ) AS "sent_for_user!"
+            "#,
+            &user_email.email,
+            Uuid::from(user_email.user_id),
+            since,
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        Ok(EmailSendCounts {
+            last_sent_to_address: res.last_sent_to_address,
+            sent_to_address: res
+                .sent_to_address
+                .try_into()
+                .map_err(DatabaseError::to_invalid_operation)?,
+            sent_for_user: res
+                .sent_for_user
+                .try_into()
+                .map_err(DatabaseError::to_invalid_operation)?,
+        })
+    }
 }
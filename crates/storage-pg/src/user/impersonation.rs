@@ -0,0 +1,241 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::{BrowserSession, User, UserImpersonation};
+use mas_storage::{user::UserImpersonationRepository, Clock};
+use rand::RngCore;
+use sqlx::PgConnection;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{tracing::ExecuteExt, DatabaseError};
+
+/// An implementation of [`UserImpersonationRepository`] for a PostgreSQL
+/// connection
+pub struct PgUserImpersonationRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgUserImpersonationRepository<'c> {
+    /// Create a new [`PgUserImpersonationRepository`] from an active PostgreSQL
+    /// connection
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+struct UserImpersonationLookup {
+    user_impersonation_id: Uuid,
+    impersonator_user_id: Uuid,
+    user_id: Uuid,
+    user_session_id: Uuid,
+    reason: String,
+    started_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    ended_at: Option<DateTime<Utc>>,
+}
+
+impl From<UserImpersonationLookup> for UserImpersonation {
+    fn from(value: UserImpersonationLookup) -> Self {
+        Self {
+            id: value.user_impersonation_id.into(),
+            impersonator_user_id: value.impersonator_user_id.into(),
+            user_id: value.user_id.into(),
+            user_session_id: value.user_session_id.into(),
+            reason: value.reason,
+            started_at: value.started_at,
+            expires_at: value.expires_at,
+            ended_at: value.ended_at,
+        }
+    }
+}
+
+#[async_trait]
+impl<'c> UserImpersonationRepository for PgUserImpersonationRepository<'c> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.user_impersonation.find_by_session",
+        skip_all,
+        fields(
+            db.statement,
+            %user_session.id,
+        ),
+        err,
+    )]
+    async fn find_by_session(
+        &mut self,
+        user_session: &BrowserSession,
+    ) -> Result<Option<UserImpersonation>, Self::Error> {
+        let res = sqlx::query_as!(
+            UserImpersonationLookup,
+            r#"
+                SELECT user_impersonation_id
+                     , impersonator_user_id
+                     , user_id
+                     , user_session_id
+                     , reason
+                     , started_at
+                     , expires_at
+                     , ended_at
+                FROM user_impersonations
+
+                WHERE user_session_id = $1
+            "#,
+            Uuid::from(user_session.id),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        Ok(res.map(Into::into))
+    }
+
+    #[tracing::instrument(
+        name = "db.user_impersonation.list_for_user",
+        skip_all,
+        fields(
+            db.statement,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn list_for_user(&mut self, user: &User) -> Result<Vec<UserImpersonation>, Self::Error> {
+        let res = sqlx::query_as!(
+            UserImpersonationLookup,
+            r#"
+                SELECT user_impersonation_id
+                     , impersonator_user_id
+                     , user_id
+                     , user_session_id
+                     , reason
+                     , started_at
+                     , expires_at
+                     , ended_at
+                FROM user_impersonations
+
+                WHERE user_id = $1
+
+                ORDER BY started_at DESC
+            "#,
+            Uuid::from(user.id),
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        Ok(res.into_iter().map(Into::into).collect())
+    }
+
+    #[tracing::instrument(
+        name = "db.user_impersonation.add",
+        skip_all,
+        fields(
+            db.statement,
+            impersonator.id = %impersonator.id,
+            user.id = %user_session.user.id,
+            %user_session.id,
+            user_impersonation.id,
+        ),
+        err,
+    )]
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        impersonator: &User,
+        user_session: &BrowserSession,
+        reason: String,
+    ) -> Result<UserImpersonation, Self::Error> {
+        let started_at = clock.now();
+        let id = Ulid::from_datetime_with_source(started_at.into(), rng);
+        tracing::Span::current().record("user_impersonation.id", tracing::field::display(id));
+
+        let expires_at = user_session
+            .impersonation
+            .as_ref()
+            .map_or(started_at, |impersonation| impersonation.expires_at);
+
+        sqlx::query!(
+            r#"
+                INSERT INTO user_impersonations
+                    ( user_impersonation_id
+                    , impersonator_user_id
+                    , user_id
+                    , user_session_id
+                    , reason
+                    , started_at
+                    , expires_at
+                    )
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "#,
+            Uuid::from(id),
+            Uuid::from(impersonator.id),
+            Uuid::from(user_session.user.id),
+            Uuid::from(user_session.id),
+            &reason,
+            started_at,
+            expires_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(UserImpersonation {
+            id,
+            impersonator_user_id: impersonator.id,
+            user_id: user_session.user.id,
+            user_session_id: user_session.id,
+            reason,
+            started_at,
+            expires_at,
+            ended_at: None,
+        })
+    }
+
+    #[tracing::instrument(
+        name = "db.user_impersonation.end",
+        skip_all,
+        fields(
+            db.statement,
+            %user_session.id,
+        ),
+        err,
+    )]
+    async fn end(
+        &mut self,
+        clock: &dyn Clock,
+        user_session: &BrowserSession,
+    ) -> Result<(), Self::Error> {
+        let ended_at = clock.now();
+        sqlx::query!(
+            r#"
+                UPDATE user_impersonations
+                SET ended_at = $2
+                WHERE user_session_id = $1
+                  AND ended_at IS NULL
+                  AND expires_at > $2
+            "#,
+            Uuid::from(user_session.id),
+            ended_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -17,16 +17,18 @@
 
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use mas_data_model::User;
+use mas_data_model::{ProfileAttribute, SecurityNotification, User};
 use mas_storage::{user::UserRepository, Clock};
 use rand::RngCore;
 use sqlx::PgConnection;
 use ulid::Ulid;
+use url::Url;
 use uuid::Uuid;
 
 use crate::{tracing::ExecuteExt, DatabaseError};
 
 mod email;
+mod impersonation;
 mod password;
 mod session;
 
@@ -34,8 +36,8 @@ mod session;
 mod tests;
 
 pub use self::{
-    email::PgUserEmailRepository, password::PgUserPasswordRepository,
-    session::PgBrowserSessionRepository,
+    email::PgUserEmailRepository, impersonation::PgUserImpersonationRepository,
+    password::PgUserPasswordRepository, session::PgBrowserSessionRepository,
 };
 
 /// An implementation of [`UserRepository`] for a PostgreSQL connection
@@ -57,7 +59,11 @@ struct UserLookup {
     primary_user_email_id: Option<Uuid>,
     created_at: DateTime<Utc>,
     locked_at: Option<DateTime<Utc>>,
+    quarantined_at: Option<DateTime<Utc>>,
     can_request_admin: bool,
+    is_guest: bool,
+    locale: Option<String>,
+    upstream_groups: Vec<String>,
 }
 
 impl From<UserLookup> for User {
@@ -70,7 +76,11 @@ impl From<UserLookup> for User {
             primary_user_email_id: value.primary_user_email_id.map(Into::into),
             created_at: value.created_at,
             locked_at: value.locked_at,
+            quarantined_at: value.quarantined_at,
             can_request_admin: value.can_request_admin,
+            is_guest: value.is_guest,
+            locale: value.locale,
+            upstream_groups: value.upstream_groups,
         }
     }
 }
@@ -97,7 +107,11 @@ impl<'c> UserRepository for PgUserRepository<'c> {
                      , primary_user_email_id
                      , created_at
                      , locked_at
+                     , quarantined_at
                      , can_request_admin
+                     , is_guest
+                     , locale
+                     , upstream_groups
                 FROM users
                 WHERE user_id = $1
             "#,
@@ -130,7 +144,11 @@ impl<'c> UserRepository for PgUserRepository<'c> {
                      , primary_user_email_id
                      , created_at
                      , locked_at
+                     , quarantined_at
                      , can_request_admin
+                     , is_guest
+                     , locale
+                     , upstream_groups
                 FROM users
                 WHERE username = $1
             "#,
@@ -190,7 +208,62 @@ impl<'c> UserRepository for PgUserRepository<'c> {
             primary_user_email_id: None,
             created_at,
             locked_at: None,
+            quarantined_at: None,
             can_request_admin: false,
+            is_guest: false,
+            locale: None,
+            upstream_groups: Vec::new(),
+        })
+    }
+
+    #[tracing::instrument(
+        name = "db.user.add_guest",
+        skip_all,
+        fields(
+            db.statement,
+            user.username = username,
+            user.id,
+        ),
+        err,
+    )]
+    async fn add_guest(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        username: String,
+    ) -> Result<User, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+        tracing::Span::current().record("user.id", tracing::field::display(id));
+
+        let res = sqlx::query!(
+            r#"
+                INSERT INTO users (user_id, username, created_at, is_guest)
+                VALUES ($1, $2, $3, TRUE)
+                ON CONFLICT (username) DO NOTHING
+            "#,
+            Uuid::from(id),
+            username,
+            created_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(User {
+            id,
+            username,
+            sub: id.to_string(),
+            primary_user_email_id: None,
+            created_at,
+            locked_at: None,
+            quarantined_at: None,
+            can_request_admin: false,
+            is_guest: true,
+            locale: None,
+            upstream_groups: Vec::new(),
         })
     }
 
@@ -287,6 +360,74 @@ impl<'c> UserRepository for PgUserRepository<'c> {
         Ok(user)
     }
 
+    #[tracing::instrument(
+        name = "db.user.quarantine",
+        skip_all,
+        fields(
+            db.statement,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn quarantine(&mut self, clock: &dyn Clock, mut user: User) -> Result<User, Self::Error> {
+        if user.quarantined_at.is_some() {
+            return Ok(user);
+        }
+
+        let quarantined_at = clock.now();
+        let res = sqlx::query!(
+            r#"
+                UPDATE users
+                SET quarantined_at = $1
+                WHERE user_id = $2
+            "#,
+            quarantined_at,
+            Uuid::from(user.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        user.quarantined_at = Some(quarantined_at);
+
+        Ok(user)
+    }
+
+    #[tracing::instrument(
+        name = "db.user.unquarantine",
+        skip_all,
+        fields(
+            db.statement,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn unquarantine(&mut self, mut user: User) -> Result<User, Self::Error> {
+        if user.quarantined_at.is_none() {
+            return Ok(user);
+        }
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE users
+                SET quarantined_at = NULL
+                WHERE user_id = $1
+            "#,
+            Uuid::from(user.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        user.quarantined_at = None;
+
+        Ok(user)
+    }
+
     #[tracing::instrument(
         name = "db.user.set_can_request_admin",
         skip_all,
@@ -321,4 +462,360 @@ impl<'c> UserRepository for PgUserRepository<'c> {
 
         Ok(user)
     }
+
+    #[tracing::instrument(
+        name = "db.user.set_locale",
+        skip_all,
+        fields(
+            db.statement,
+            %user.id,
+            user.locale = locale,
+        ),
+        err,
+    )]
+    async fn set_locale(
+        &mut self,
+        mut user: User,
+        locale: Option<String>,
+    ) -> Result<User, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE users
+                SET locale = $2
+                WHERE user_id = $1
+            "#,
+            Uuid::from(user.id),
+            locale.as_deref(),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        user.locale = locale;
+
+        Ok(user)
+    }
+
+    #[tracing::instrument(
+        name = "db.user.set_upstream_groups",
+        skip_all,
+        fields(
+            db.statement,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn set_upstream_groups(
+        &mut self,
+        mut user: User,
+        groups: Vec<String>,
+    ) -> Result<User, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE users
+                SET upstream_groups = $2
+                WHERE user_id = $1
+            "#,
+            Uuid::from(user.id),
+            &groups,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        user.upstream_groups = groups;
+
+        Ok(user)
+    }
+
+    #[tracing::instrument(
+        name = "db.user.upgrade_guest",
+        skip_all,
+        fields(
+            db.statement,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn upgrade_guest(&mut self, mut user: User) -> Result<User, Self::Error> {
+        if !user.is_guest {
+            return Ok(user);
+        }
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE users
+                SET is_guest = FALSE
+                WHERE user_id = $1
+            "#,
+            Uuid::from(user.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        user.is_guest = false;
+
+        Ok(user)
+    }
+
+    #[tracing::instrument(
+        name = "db.user.list_expired_guests",
+        skip_all,
+        fields(
+            db.statement,
+            %created_before,
+            limit,
+        ),
+        err,
+    )]
+    async fn list_expired_guests(
+        &mut self,
+        created_before: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<User>, Self::Error> {
+        let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+        let res = sqlx::query_as!(
+            UserLookup,
+            r#"
+                SELECT user_id
+                     , username
+                     , primary_user_email_id
+                     , created_at
+                     , locked_at
+                     , quarantined_at
+                     , can_request_admin
+                     , is_guest
+                     , locale
+                     , upstream_groups
+                FROM users
+                WHERE is_guest
+                  AND locked_at IS NULL
+                  AND created_at < $1
+                ORDER BY created_at ASC
+                LIMIT $2
+            "#,
+            created_before,
+            limit,
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        Ok(res.into_iter().map(Into::into).collect())
+    }
+
+    #[tracing::instrument(
+        name = "db.user.security_notification_opt_outs",
+        skip_all,
+        fields(
+            db.statement,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn security_notification_opt_outs(
+        &mut self,
+        user: &User,
+    ) -> Result<Vec<SecurityNotification>, Self::Error> {
+        let res = sqlx::query_scalar!(
+            r#"
+                SELECT security_notification_opt_outs
+                FROM users
+                WHERE user_id = $1
+            "#,
+            Uuid::from(user.id),
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        // Ignore the categories we don't know about
+        Ok(res
+            .iter()
+            .filter_map(|name| SecurityNotification::from_name(name))
+            .collect())
+    }
+
+    #[tracing::instrument(
+        name = "db.user.set_security_notification_opt_out",
+        skip_all,
+        fields(
+            db.statement,
+            %user.id,
+            category = category.as_str(),
+            opt_out,
+        ),
+        err,
+    )]
+    async fn set_security_notification_opt_out(
+        &mut self,
+        user: &User,
+        category: SecurityNotification,
+        opt_out: bool,
+    ) -> Result<(), Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE users
+                SET security_notification_opt_outs = CASE
+                    WHEN $3 THEN array_append(array_remove(security_notification_opt_outs, $2), $2)
+                    ELSE array_remove(security_notification_opt_outs, $2)
+                END
+                WHERE user_id = $1
+            "#,
+            Uuid::from(user.id),
+            category.as_str(),
+            opt_out,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.user.modified_profile_attributes",
+        skip_all,
+        fields(
+            db.statement,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn modified_profile_attributes(
+        &mut self,
+        user: &User,
+    ) -> Result<Vec<ProfileAttribute>, Self::Error> {
+        let res = sqlx::query_scalar!(
+            r#"
+                SELECT modified_profile_attributes
+                FROM users
+                WHERE user_id = $1
+            "#,
+            Uuid::from(user.id),
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        // Ignore the attributes we don't know about
+        Ok(res
+            .iter()
+            .filter_map(|name| ProfileAttribute::from_name(name))
+            .collect())
+    }
+
+    #[tracing::instrument(
+        name = "db.user.mark_profile_attribute_modified",
+        skip_all,
+        fields(
+            db.statement,
+            %user.id,
+            attribute = attribute.as_str(),
+        ),
+        err,
+    )]
+    async fn mark_profile_attribute_modified(
+        &mut self,
+        user: &User,
+        attribute: ProfileAttribute,
+    ) -> Result<(), Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE users
+                SET modified_profile_attributes =
+                    array_append(array_remove(modified_profile_attributes, $2), $2)
+                WHERE user_id = $1
+            "#,
+            Uuid::from(user.id),
+            attribute.as_str(),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.user.accept_terms",
+        skip_all,
+        fields(
+            db.statement,
+            %user.id,
+            user_terms.id,
+            %terms_url,
+        ),
+        err,
+    )]
+    async fn accept_terms(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        terms_url: Url,
+    ) -> Result<(), Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+        tracing::Span::current().record("user_terms.id", tracing::field::display(id));
+
+        sqlx::query!(
+            r#"
+                INSERT INTO user_terms (user_terms_id, user_id, terms_url, accepted_at)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (user_id, terms_url) DO NOTHING
+            "#,
+            Uuid::from(id),
+            Uuid::from(user.id),
+            terms_url.as_str(),
+            created_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.user.has_accepted_terms",
+        skip_all,
+        fields(
+            db.statement,
+            %user.id,
+            %terms_url,
+        ),
+        err,
+    )]
+    async fn has_accepted_terms(
+        &mut self,
+        user: &User,
+        terms_url: &Url,
+    ) -> Result<bool, Self::Error> {
+        let accepted = sqlx::query_scalar!(
+            r#"
+                SELECT EXISTS(
+                    SELECT 1 FROM user_terms WHERE user_id = $1 AND terms_url = $2
+                ) AS "exists!"
+            "#,
+            Uuid::from(user.id),
+            terms_url.as_str(),
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        Ok(accepted)
+    }
 }
@@ -15,21 +15,24 @@
 use std::net::IpAddr;
 
 use async_trait::async_trait;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use mas_data_model::{
-    Authentication, AuthenticationMethod, BrowserSession, Password,
+    Authentication, AuthenticationMethod, BrowserSession, Impersonation, Password,
     UpstreamOAuthAuthorizationSession, User,
 };
-use mas_storage::{user::BrowserSessionRepository, Clock, Page, Pagination};
+use mas_storage::{
+    user::{BrowserSessionFilter, BrowserSessionRepository},
+    Clock, Page, Pagination,
+};
 use rand::RngCore;
-use sea_query::{Expr, PostgresQueryBuilder};
+use sea_query::{Expr, PostgresQueryBuilder, Query, SimpleExpr};
 use sea_query_binder::SqlxBinder;
 use sqlx::PgConnection;
 use ulid::Ulid;
 use uuid::Uuid;
 
 use crate::{
-    iden::{UserSessions, Users},
+    iden::{UpstreamOAuthAuthorizationSessions, UserSessionAuthentications, UserSessions, Users},
     pagination::QueryBuilderExt,
     tracing::ExecuteExt,
     DatabaseError, DatabaseInconsistencyError,
@@ -58,12 +61,18 @@ struct SessionLookup {
     user_session_user_agent: Option<String>,
     user_session_last_active_at: Option<DateTime<Utc>>,
     user_session_last_active_ip: Option<IpAddr>,
+    user_session_impersonator_user_id: Option<Uuid>,
+    user_session_impersonation_expires_at: Option<DateTime<Utc>>,
     user_id: Uuid,
     user_username: String,
     user_primary_user_email_id: Option<Uuid>,
     user_created_at: DateTime<Utc>,
     user_locked_at: Option<DateTime<Utc>>,
+    user_quarantined_at: Option<DateTime<Utc>>,
     user_can_request_admin: bool,
+    user_is_guest: bool,
+    user_locale: Option<String>,
+    user_upstream_groups: Vec<String>,
 }
 
 impl TryFrom<SessionLookup> for BrowserSession {
@@ -78,7 +87,26 @@ impl TryFrom<SessionLookup> for BrowserSession {
             primary_user_email_id: value.user_primary_user_email_id.map(Into::into),
             created_at: value.user_created_at,
             locked_at: value.user_locked_at,
+            quarantined_at: value.user_quarantined_at,
             can_request_admin: value.user_can_request_admin,
+            is_guest: value.user_is_guest,
+            locale: value.user_locale,
+            upstream_groups: value.user_upstream_groups,
+        };
+
+        let impersonation = match (
+            value.user_session_impersonator_user_id,
+            value.user_session_impersonation_expires_at,
+        ) {
+            (Some(impersonator_user_id), Some(expires_at)) => Some(Impersonation {
+                impersonator_user_id: impersonator_user_id.into(),
+                expires_at,
+            }),
+            (None, None) => None,
+            _ => {
+                return Err(DatabaseInconsistencyError::on("user_sessions")
+                    .row(value.user_session_id.into()));
+            }
         };
 
         Ok(BrowserSession {
@@ -89,6 +117,7 @@ impl TryFrom<SessionLookup> for BrowserSession {
             user_agent: value.user_session_user_agent,
             last_active_at: value.user_session_last_active_at,
             last_active_ip: value.user_session_last_active_ip,
+            impersonation,
         })
     }
 }
@@ -129,6 +158,67 @@ impl TryFrom<AuthenticationLookup> for Authentication {
     }
 }
 
+/// Build the condition matching the browser sessions authenticated through an
+/// upstream OAuth session, if the filter has any upstream OAuth criteria
+fn upstream_oauth_authentication_condition(
+    filter: &BrowserSessionFilter<'_>,
+) -> Option<SimpleExpr> {
+    if filter.upstream_oauth_provider().is_none()
+        && filter.upstream_oauth_link().is_none()
+        && filter.upstream_oauth_sid().is_none()
+    {
+        return None;
+    }
+
+    let condition = Expr::exists(
+        Query::select()
+            .expr(Expr::cust("1"))
+            .from(UserSessionAuthentications::Table)
+            .inner_join(
+                UpstreamOAuthAuthorizationSessions::Table,
+                Expr::col((
+                    UserSessionAuthentications::Table,
+                    UserSessionAuthentications::UpstreamOAuthAuthorizationSessionId,
+                ))
+                .equals((
+                    UpstreamOAuthAuthorizationSessions::Table,
+                    UpstreamOAuthAuthorizationSessions::UpstreamOAuthAuthorizationSessionId,
+                )),
+            )
+            .and_where(
+                Expr::col((
+                    UserSessionAuthentications::Table,
+                    UserSessionAuthentications::UserSessionId,
+                ))
+                .equals((UserSessions::Table, UserSessions::UserSessionId)),
+            )
+            .and_where_option(filter.upstream_oauth_provider().map(|provider| {
+                Expr::col((
+                    UpstreamOAuthAuthorizationSessions::Table,
+                    UpstreamOAuthAuthorizationSessions::UpstreamOAuthProviderId,
+                ))
+                .eq(Uuid::from(provider.id))
+            }))
+            .and_where_option(filter.upstream_oauth_link().map(|link| {
+                Expr::col((
+                    UpstreamOAuthAuthorizationSessions::Table,
+                    UpstreamOAuthAuthorizationSessions::UpstreamOAuthLinkId,
+                ))
+                .eq(Uuid::from(link.id))
+            }))
+            .and_where_option(filter.upstream_oauth_sid().map(|sid| {
+                Expr::col((
+                    UpstreamOAuthAuthorizationSessions::Table,
+                    UpstreamOAuthAuthorizationSessions::Sid,
+                ))
+                .eq(sid)
+            }))
+            .take(),
+    );
+
+    Some(condition)
+}
+
 #[async_trait]
 impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
     type Error = DatabaseError;
@@ -152,12 +242,18 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
                      , s.user_agent            AS "user_session_user_agent"
                      , s.last_active_at        AS "user_session_last_active_at"
                      , s.last_active_ip        AS "user_session_last_active_ip: IpAddr"
+                     , s.impersonator_user_id  AS "user_session_impersonator_user_id"
+                     , s.impersonation_expires_at AS "user_session_impersonation_expires_at"
                      , u.user_id
                      , u.username              AS "user_username"
                      , u.primary_user_email_id AS "user_primary_user_email_id"
                      , u.created_at            AS "user_created_at"
                      , u.locked_at             AS "user_locked_at"
+                     , u.quarantined_at        AS "user_quarantined_at"
                      , u.can_request_admin     AS "user_can_request_admin"
+                     , u.is_guest              AS "user_is_guest"
+                     , u.locale                AS "user_locale"
+                     , u.upstream_groups       AS "user_upstream_groups"
                 FROM user_sessions s
                 INNER JOIN users u
                     USING (user_id)
@@ -218,6 +314,72 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
             user_agent,
             last_active_at: None,
             last_active_ip: None,
+            impersonation: None,
+        };
+
+        Ok(session)
+    }
+
+    #[tracing::instrument(
+        name = "db.browser_session.add_impersonation",
+        skip_all,
+        fields(
+            db.statement,
+            %user.id,
+            impersonator.id = %impersonator.id,
+            user_session.id,
+        ),
+        err,
+    )]
+    async fn add_impersonation(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        impersonator: &User,
+        ttl: Duration,
+        user_agent: Option<String>,
+    ) -> Result<BrowserSession, Self::Error> {
+        let created_at = clock.now();
+        let expires_at = created_at + ttl;
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+        tracing::Span::current().record("user_session.id", tracing::field::display(id));
+
+        sqlx::query!(
+            r#"
+                INSERT INTO user_sessions
+                    ( user_session_id
+                    , user_id
+                    , created_at
+                    , user_agent
+                    , impersonator_user_id
+                    , impersonation_expires_at
+                    )
+                VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            Uuid::from(id),
+            Uuid::from(user.id),
+            created_at,
+            user_agent,
+            Uuid::from(impersonator.id),
+            expires_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        let session = BrowserSession {
+            id,
+            user: user.clone(),
+            created_at,
+            finished_at: None,
+            user_agent,
+            last_active_at: None,
+            last_active_ip: None,
+            impersonation: Some(Impersonation {
+                impersonator_user_id: impersonator.id,
+                expires_at,
+            }),
         };
 
         Ok(session)
@@ -268,7 +430,7 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
     )]
     async fn list(
         &mut self,
-        filter: mas_storage::user::BrowserSessionFilter<'_>,
+        filter: BrowserSessionFilter<'_>,
         pagination: Pagination,
     ) -> Result<Page<BrowserSession>, Self::Error> {
         let (sql, arguments) = sea_query::Query::select()
@@ -296,6 +458,14 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
                 Expr::col((UserSessions::Table, UserSessions::LastActiveIp)),
                 SessionLookupIden::UserSessionLastActiveIp,
             )
+            .expr_as(
+                Expr::col((UserSessions::Table, UserSessions::ImpersonatorUserId)),
+                SessionLookupIden::UserSessionImpersonatorUserId,
+            )
+            .expr_as(
+                Expr::col((UserSessions::Table, UserSessions::ImpersonationExpiresAt)),
+                SessionLookupIden::UserSessionImpersonationExpiresAt,
+            )
             .expr_as(
                 Expr::col((Users::Table, Users::UserId)),
                 SessionLookupIden::UserId,
@@ -316,10 +486,26 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
                 Expr::col((Users::Table, Users::LockedAt)),
                 SessionLookupIden::UserLockedAt,
             )
+            .expr_as(
+                Expr::col((Users::Table, Users::QuarantinedAt)),
+                SessionLookupIden::UserQuarantinedAt,
+            )
             .expr_as(
                 Expr::col((Users::Table, Users::CanRequestAdmin)),
                 SessionLookupIden::UserCanRequestAdmin,
             )
+            .expr_as(
+                Expr::col((Users::Table, Users::IsGuest)),
+                SessionLookupIden::UserIsGuest,
+            )
+            .expr_as(
+                Expr::col((Users::Table, Users::Locale)),
+                SessionLookupIden::UserLocale,
+            )
+            .expr_as(
+                Expr::col((Users::Table, Users::UpstreamGroups)),
+                SessionLookupIden::UserUpstreamGroups,
+            )
             .from(UserSessions::Table)
             .inner_join(
                 Users::Table,
@@ -338,6 +524,7 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
                     Expr::col((UserSessions::Table, UserSessions::FinishedAt)).is_not_null()
                 }
             }))
+            .and_where_option(upstream_oauth_authentication_condition(&filter))
             .generate_pagination(
                 (UserSessions::Table, UserSessions::UserSessionId),
                 pagination,
@@ -364,10 +551,7 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
         ),
         err,
     )]
-    async fn count(
-        &mut self,
-        filter: mas_storage::user::BrowserSessionFilter<'_>,
-    ) -> Result<usize, Self::Error> {
+    async fn count(&mut self, filter: BrowserSessionFilter<'_>) -> Result<usize, Self::Error> {
         let (sql, arguments) = sea_query::Query::select()
             .expr(Expr::col((UserSessions::Table, UserSessions::UserSessionId)).count())
             .from(UserSessions::Table)
@@ -381,6 +565,7 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
                     Expr::col((UserSessions::Table, UserSessions::FinishedAt)).is_not_null()
                 }
             }))
+            .and_where_option(upstream_oauth_authentication_condition(&filter))
             .build_sqlx(PostgresQueryBuilder);
 
         let count: i64 = sqlx::query_scalar_with(&sql, arguments)
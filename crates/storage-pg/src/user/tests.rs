@@ -13,17 +13,19 @@
 // limitations under the License.
 
 use chrono::Duration;
+use mas_data_model::{EmailRateLimited, EmailRateLimits, ProfileAttribute, SecurityNotification};
 use mas_storage::{
     clock::MockClock,
     user::{
-        BrowserSessionFilter, BrowserSessionRepository, UserEmailFilter, UserEmailRepository,
-        UserPasswordRepository, UserRepository,
+        send_verification_email, BrowserSessionFilter, BrowserSessionRepository, UserEmailFilter,
+        UserEmailRepository, UserImpersonationRepository, UserPasswordRepository, UserRepository,
     },
-    Pagination, Repository, RepositoryAccess,
+    Clock, Pagination, Repository, RepositoryAccess,
 };
 use rand::SeedableRng;
 use rand_chacha::ChaChaRng;
 use sqlx::PgPool;
+use url::Url;
 
 use crate::PgRepository;
 
@@ -95,6 +97,29 @@ async fn test_user_repo(pool: PgPool) {
     let user = repo.user().unlock(user).await.unwrap();
     assert!(user.is_valid());
 
+    // Try quarantining a user
+    assert!(!user.is_quarantined());
+    let user = repo.user().quarantine(&clock, user).await.unwrap();
+    assert!(user.is_quarantined());
+    // Quarantined users are still valid
+    assert!(user.is_valid());
+
+    // Check that the property is retrieved on lookup
+    let user = repo.user().lookup(user.id).await.unwrap().unwrap();
+    assert!(user.is_quarantined());
+
+    // Quarantining a second time should not fail
+    let user = repo.user().quarantine(&clock, user).await.unwrap();
+    assert!(user.is_quarantined());
+
+    // Try lifting the quarantine
+    let user = repo.user().unquarantine(user).await.unwrap();
+    assert!(!user.is_quarantined());
+
+    // Check that the property is retrieved on lookup
+    let user = repo.user().lookup(user.id).await.unwrap().unwrap();
+    assert!(!user.is_quarantined());
+
     // Set the can_request_admin flag
     let user = repo.user().set_can_request_admin(user, true).await.unwrap();
     assert!(user.can_request_admin);
@@ -115,6 +140,198 @@ async fn test_user_repo(pool: PgPool) {
     let user = repo.user().lookup(user.id).await.unwrap().unwrap();
     assert!(!user.can_request_admin);
 
+    // The user has no preferred locale by default
+    assert_eq!(user.locale, None);
+
+    // Set the preferred locale
+    let user = repo
+        .user()
+        .set_locale(user, Some("fr".to_owned()))
+        .await
+        .unwrap();
+    assert_eq!(user.locale.as_deref(), Some("fr"));
+
+    // Check that the property is retrieved on lookup
+    let user = repo.user().lookup(user.id).await.unwrap().unwrap();
+    assert_eq!(user.locale.as_deref(), Some("fr"));
+
+    // Unset it
+    let user = repo.user().set_locale(user, None).await.unwrap();
+    assert_eq!(user.locale, None);
+
+    // The user has no upstream groups by default
+    assert!(user.upstream_groups.is_empty());
+
+    // Set the upstream groups
+    let user = repo
+        .user()
+        .set_upstream_groups(user, vec!["admins".to_owned(), "staff".to_owned()])
+        .await
+        .unwrap();
+    assert_eq!(user.upstream_groups, ["admins", "staff"]);
+
+    // Check that the property is retrieved on lookup
+    let user = repo.user().lookup(user.id).await.unwrap().unwrap();
+    assert_eq!(user.upstream_groups, ["admins", "staff"]);
+
+    // Groups which disappeared upstream are removed
+    let user = repo
+        .user()
+        .set_upstream_groups(user, vec!["staff".to_owned()])
+        .await
+        .unwrap();
+    let user = repo.user().lookup(user.id).await.unwrap().unwrap();
+    assert_eq!(user.upstream_groups, ["staff"]);
+
+    // The user didn't opt out of any security notification yet
+    let opt_outs = repo
+        .user()
+        .security_notification_opt_outs(&user)
+        .await
+        .unwrap();
+    assert!(opt_outs.is_empty());
+
+    // Opting out twice only records the category once
+    for _ in 0..2 {
+        repo.user()
+            .set_security_notification_opt_out(&user, SecurityNotification::AccountLock, true)
+            .await
+            .unwrap();
+    }
+    let opt_outs = repo
+        .user()
+        .security_notification_opt_outs(&user)
+        .await
+        .unwrap();
+    assert_eq!(opt_outs, vec![SecurityNotification::AccountLock]);
+
+    // Opt back in
+    repo.user()
+        .set_security_notification_opt_out(&user, SecurityNotification::AccountLock, false)
+        .await
+        .unwrap();
+    let opt_outs = repo
+        .user()
+        .security_notification_opt_outs(&user)
+        .await
+        .unwrap();
+    assert!(opt_outs.is_empty());
+
+    // The user didn't change any profile attribute yet
+    let modified = repo
+        .user()
+        .modified_profile_attributes(&user)
+        .await
+        .unwrap();
+    assert!(modified.is_empty());
+
+    // Changing an attribute twice only records it once
+    for _ in 0..2 {
+        repo.user()
+            .mark_profile_attribute_modified(&user, ProfileAttribute::Displayname)
+            .await
+            .unwrap();
+    }
+    let modified = repo
+        .user()
+        .modified_profile_attributes(&user)
+        .await
+        .unwrap();
+    assert_eq!(modified, vec![ProfileAttribute::Displayname]);
+
+    // The user didn't accept any terms yet
+    let terms_url = Url::parse("https://example.com/terms/v1").unwrap();
+    assert!(!repo
+        .user()
+        .has_accepted_terms(&user, &terms_url)
+        .await
+        .unwrap());
+
+    // Accepting the same terms twice is fine
+    for _ in 0..2 {
+        repo.user()
+            .accept_terms(&mut rng, &clock, &user, terms_url.clone())
+            .await
+            .unwrap();
+    }
+    assert!(repo
+        .user()
+        .has_accepted_terms(&user, &terms_url)
+        .await
+        .unwrap());
+
+    // Other terms are still to be accepted
+    let new_terms_url = Url::parse("https://example.com/terms/v2").unwrap();
+    assert!(!repo
+        .user()
+        .has_accepted_terms(&user, &new_terms_url)
+        .await
+        .unwrap());
+
+    repo.save().await.unwrap();
+}
+
+/// Test guest users, from their creation to their cleanup
+#[sqlx::test(migrator = "crate::MIGRATOR")]
+async fn test_user_repo_guests(pool: PgPool) {
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap().boxed();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let guest = repo
+        .user()
+        .add_guest(&mut rng, &clock, "guest1".to_owned())
+        .await
+        .unwrap();
+    assert!(guest.is_guest);
+
+    // Check that the property is retrieved on lookup
+    let guest = repo.user().lookup(guest.id).await.unwrap().unwrap();
+    assert!(guest.is_guest);
+
+    // Regular users are not guests
+    let user = repo
+        .user()
+        .add(&mut rng, &clock, "john".to_owned())
+        .await
+        .unwrap();
+    assert!(!user.is_guest);
+
+    clock.advance(Duration::hours(1));
+
+    let other_guest = repo
+        .user()
+        .add_guest(&mut rng, &clock, "guest2".to_owned())
+        .await
+        .unwrap();
+
+    // Only the first guest is old enough to be cleaned up
+    let cutoff = clock.now() - Duration::minutes(30);
+    let expired = repo.user().list_expired_guests(cutoff, 10).await.unwrap();
+    assert_eq!(expired, vec![guest.clone()]);
+
+    // Both are after some time, oldest first
+    let cutoff = clock.now() + Duration::minutes(30);
+    let expired = repo.user().list_expired_guests(cutoff, 10).await.unwrap();
+    assert_eq!(expired, vec![guest.clone(), other_guest.clone()]);
+
+    // The limit is respected
+    let expired = repo.user().list_expired_guests(cutoff, 1).await.unwrap();
+    assert_eq!(expired, vec![guest.clone()]);
+
+    // Locked guests are not listed
+    repo.user().lock(&clock, guest).await.unwrap();
+    let expired = repo.user().list_expired_guests(cutoff, 10).await.unwrap();
+    assert_eq!(expired, vec![other_guest.clone()]);
+
+    // Upgraded guests are not listed either
+    let upgraded = repo.user().upgrade_guest(other_guest).await.unwrap();
+    assert!(!upgraded.is_guest);
+    let upgraded = repo.user().lookup(upgraded.id).await.unwrap().unwrap();
+    assert!(!upgraded.is_guest);
+    let expired = repo.user().list_expired_guests(cutoff, 10).await.unwrap();
+    assert!(expired.is_empty());
+
     repo.save().await.unwrap();
 }
 
@@ -242,6 +459,12 @@ async fn test_user_email_repo(pool: PgPool) {
     assert_eq!(repo.user_email().count(pending).await.unwrap(), 0);
     assert_eq!(repo.user_email().count(verified).await.unwrap(), 1);
 
+    // Look the verified email up by its address, across all users
+    let by_email = UserEmailFilter::new().for_email(EMAIL).verified_only();
+    assert_eq!(repo.user_email().count(by_email).await.unwrap(), 1);
+    let other_email = UserEmailFilter::new().for_email("other@example.com");
+    assert_eq!(repo.user_email().count(other_email).await.unwrap(), 0);
+
     // Reload the user_email
     let user_email = repo
         .user_email()
@@ -346,6 +569,419 @@ async fn test_user_email_repo(pool: PgPool) {
     repo.save().await.unwrap();
 }
 
+/// Test the primary email change flow of the user email repository
+#[sqlx::test(migrator = "crate::MIGRATOR")]
+async fn test_user_email_change(pool: PgPool) {
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap().boxed();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let user = repo
+        .user()
+        .add(&mut rng, &clock, "john".to_owned())
+        .await
+        .unwrap();
+
+    let old_email = repo
+        .user_email()
+        .add(&mut rng, &clock, &user, "john@example.com".to_owned())
+        .await
+        .unwrap();
+    let old_email = repo
+        .user_email()
+        .mark_as_verified(&clock, old_email)
+        .await
+        .unwrap();
+    repo.user_email().set_as_primary(&old_email).await.unwrap();
+
+    let new_email = repo
+        .user_email()
+        .add(&mut rng, &clock, &user, "john@example.org".to_owned())
+        .await
+        .unwrap();
+
+    // There is no pending change yet
+    assert!(repo
+        .user_email()
+        .find_pending_change(&new_email)
+        .await
+        .unwrap()
+        .is_none());
+
+    let change = repo
+        .user_email()
+        .add_change(&mut rng, &clock, &old_email, &new_email, "token".to_owned())
+        .await
+        .unwrap();
+    assert!(change.is_pending());
+    assert!(!change.is_in_progress(clock.now()));
+
+    // Lookup the change in various ways
+    let lookup = repo
+        .user_email()
+        .lookup_change(change.id)
+        .await
+        .unwrap()
+        .expect("change not found");
+    assert_eq!(lookup, change);
+
+    let lookup = repo
+        .user_email()
+        .find_pending_change(&new_email)
+        .await
+        .unwrap()
+        .expect("change not found");
+    assert_eq!(lookup, change);
+
+    let lookup = repo
+        .user_email()
+        .find_change_by_revert_token("token")
+        .await
+        .unwrap()
+        .expect("change not found");
+    assert_eq!(lookup, change);
+
+    assert!(repo
+        .user_email()
+        .find_change_by_revert_token("not-a-token")
+        .await
+        .unwrap()
+        .is_none());
+
+    // It can't be completed before the new address is verified
+    assert!(repo
+        .user_email()
+        .complete_change(&clock, change.clone())
+        .await
+        .is_err());
+
+    // Nothing is in progress until then
+    assert!(repo
+        .user_email()
+        .find_change_in_progress(&clock, &user)
+        .await
+        .unwrap()
+        .is_none());
+
+    let change = repo
+        .user_email()
+        .verify_change(&clock, change)
+        .await
+        .unwrap();
+    assert!(change.is_pending());
+    assert!(change.is_awaiting_confirmation(clock.now()));
+    assert!(!change.is_awaiting_confirmation(clock.now() + Duration::days(8)));
+
+    let lookup = repo
+        .user_email()
+        .find_change_in_progress(&clock, &user)
+        .await
+        .unwrap()
+        .expect("change not found");
+    assert_eq!(lookup, change);
+
+    // Complete the change, as if the old address confirmed it
+    clock.advance(Duration::days(1));
+    let change = repo
+        .user_email()
+        .complete_change(&clock, change)
+        .await
+        .unwrap();
+    assert!(!change.is_pending());
+    assert!(!change.is_awaiting_confirmation(clock.now()));
+    assert!(change.can_revert(clock.now()));
+
+    // It is still in progress while it can be reverted
+    let lookup = repo
+        .user_email()
+        .find_change_in_progress(&clock, &user)
+        .await
+        .unwrap()
+        .expect("change not found");
+    assert_eq!(lookup, change);
+
+    // It can't be completed twice
+    assert!(repo
+        .user_email()
+        .complete_change(&clock, change.clone())
+        .await
+        .is_err());
+
+    // And it's not pending anymore
+    assert!(repo
+        .user_email()
+        .find_pending_change(&new_email)
+        .await
+        .unwrap()
+        .is_none());
+
+    // It can't be reverted after the grace period
+    assert!(!change.can_revert(clock.now() + Duration::days(8)));
+
+    // Revert it
+    let change = repo
+        .user_email()
+        .revert_change(&clock, change)
+        .await
+        .unwrap();
+    assert!(!change.can_revert(clock.now()));
+
+    let lookup = repo
+        .user_email()
+        .lookup_change(change.id)
+        .await
+        .unwrap()
+        .expect("change not found");
+    assert_eq!(lookup, change);
+
+    assert!(repo
+        .user_email()
+        .find_change_in_progress(&clock, &user)
+        .await
+        .unwrap()
+        .is_none());
+
+    // Removing the new email removes the change
+    repo.user_email().remove(new_email).await.unwrap();
+    assert!(repo
+        .user_email()
+        .lookup_change(change.id)
+        .await
+        .unwrap()
+        .is_none());
+
+    repo.save().await.unwrap();
+}
+
+/// Test that the old address of an email change can't be removed while the
+/// change is in progress, but can once it is finished
+#[sqlx::test(migrator = "crate::MIGRATOR")]
+async fn test_user_email_change_old_address(pool: PgPool) {
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap().boxed();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let user = repo
+        .user()
+        .add(&mut rng, &clock, "john".to_owned())
+        .await
+        .unwrap();
+
+    let old_email = repo
+        .user_email()
+        .add(&mut rng, &clock, &user, "john@example.com".to_owned())
+        .await
+        .unwrap();
+    let new_email = repo
+        .user_email()
+        .add(&mut rng, &clock, &user, "john@example.org".to_owned())
+        .await
+        .unwrap();
+
+    let change = repo
+        .user_email()
+        .add_change(&mut rng, &clock, &old_email, &new_email, "token".to_owned())
+        .await
+        .unwrap();
+    let change = repo
+        .user_email()
+        .verify_change(&clock, change)
+        .await
+        .unwrap();
+    let change = repo
+        .user_email()
+        .complete_change(&clock, change)
+        .await
+        .unwrap();
+
+    // The change can only be finished once
+    let change = repo
+        .user_email()
+        .finish_change(&clock, change)
+        .await
+        .unwrap();
+    assert!(change.finished_at.is_some());
+    assert!(!change.can_revert(clock.now()));
+    assert!(repo
+        .user_email()
+        .finish_change(&clock, change.clone())
+        .await
+        .is_err());
+
+    // Once finished, the old address can be removed, along with the change
+    repo.user_email().remove(old_email).await.unwrap();
+    assert!(repo
+        .user_email()
+        .lookup_change(change.id)
+        .await
+        .unwrap()
+        .is_none());
+
+    // Start another change, back to the old address
+    let other_email = repo
+        .user_email()
+        .add(&mut rng, &clock, &user, "john@example.net".to_owned())
+        .await
+        .unwrap();
+    let change = repo
+        .user_email()
+        .add_change(
+            &mut rng,
+            &clock,
+            &new_email,
+            &other_email,
+            "other".to_owned(),
+        )
+        .await
+        .unwrap();
+    repo.user_email()
+        .verify_change(&clock, change)
+        .await
+        .unwrap();
+
+    // The old address can't be removed while the change is in progress
+    assert!(repo.user_email().remove(new_email).await.is_err());
+}
+
+/// Test the counters used to rate limit verification emails
+#[sqlx::test(migrator = "crate::MIGRATOR")]
+async fn test_user_email_sends(pool: PgPool) {
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap().boxed();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let alice = repo
+        .user()
+        .add(&mut rng, &clock, "alice".to_owned())
+        .await
+        .unwrap();
+    let bob = repo
+        .user()
+        .add(&mut rng, &clock, "bob".to_owned())
+        .await
+        .unwrap();
+
+    let alice_email = repo
+        .user_email()
+        .add(&mut rng, &clock, &alice, "victim@example.com".to_owned())
+        .await
+        .unwrap();
+    let bob_email = repo
+        .user_email()
+        .add(&mut rng, &clock, &bob, "Victim@Example.com".to_owned())
+        .await
+        .unwrap();
+
+    // Nothing was sent yet
+    let counts = repo
+        .user_email()
+        .count_sends(&alice_email, clock.now() - Duration::hours(1))
+        .await
+        .unwrap();
+    assert_eq!(counts.last_sent_to_address, None);
+    assert_eq!(counts.sent_to_address, 0);
+    assert_eq!(counts.sent_for_user, 0);
+
+    repo.user_email()
+        .record_send(&mut rng, &clock, &alice_email)
+        .await
+        .unwrap();
+    clock.advance(Duration::minutes(10));
+    repo.user_email()
+        .record_send(&mut rng, &clock, &bob_email)
+        .await
+        .unwrap();
+
+    // The address is counted regardless of the case and of the user
+    let counts = repo
+        .user_email()
+        .count_sends(&alice_email, clock.now() - Duration::hours(1))
+        .await
+        .unwrap();
+    assert_eq!(counts.last_sent_to_address, Some(clock.now()));
+    assert_eq!(counts.sent_to_address, 2);
+    assert_eq!(counts.sent_for_user, 1);
+
+    // Removing the address doesn't reset the counters
+    repo.user_email().remove(alice_email.clone()).await.unwrap();
+    let counts = repo
+        .user_email()
+        .count_sends(&alice_email, clock.now() - Duration::hours(1))
+        .await
+        .unwrap();
+    assert_eq!(counts.sent_to_address, 2);
+    assert_eq!(counts.sent_for_user, 1);
+
+    // Older sends are out of the window
+    clock.advance(Duration::minutes(55));
+    let counts = repo
+        .user_email()
+        .count_sends(&bob_email, clock.now() - Duration::hours(1))
+        .await
+        .unwrap();
+    assert_eq!(counts.sent_to_address, 1);
+    assert_eq!(counts.sent_for_user, 1);
+
+    repo.save().await.unwrap();
+}
+
+/// Test that concurrent verification emails are counted one after the other
+#[sqlx::test(migrator = "crate::MIGRATOR")]
+async fn test_send_verification_email_concurrently(pool: PgPool) {
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+    let limits = EmailRateLimits::default();
+
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap().boxed();
+    let user = repo
+        .user()
+        .add(&mut rng, &clock, "alice".to_owned())
+        .await
+        .unwrap();
+    let user_email = repo
+        .user_email()
+        .add(&mut rng, &clock, &user, "alice@example.com".to_owned())
+        .await
+        .unwrap();
+    repo.save().await.unwrap();
+
+    // The first transaction holds the locks until it is saved
+    let mut first = PgRepository::from_pool(&pool).await.unwrap().boxed();
+    send_verification_email(&mut first, &mut rng, &clock, &limits, &user_email, None)
+        .await
+        .unwrap()
+        .unwrap();
+
+    // The second one waits for it, and then sees its send
+    let mut second = PgRepository::from_pool(&pool).await.unwrap().boxed();
+    let mut second_rng = ChaChaRng::seed_from_u64(43);
+    let (res, ()) = futures_util::join!(
+        send_verification_email(
+            &mut second,
+            &mut second_rng,
+            &clock,
+            &limits,
+            &user_email,
+            None,
+        ),
+        async { first.save().await.unwrap() },
+    );
+    assert!(matches!(
+        res.unwrap(),
+        Err(EmailRateLimited::Cooldown { .. })
+    ));
+    second.save().await.unwrap();
+
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap().boxed();
+    let counts = repo
+        .user_email()
+        .count_sends(&user_email, clock.now() - Duration::hours(1))
+        .await
+        .unwrap();
+    assert_eq!(counts.sent_to_address, 1);
+    repo.save().await.unwrap();
+}
+
 /// Test the user password repository implementation.
 #[sqlx::test(migrator = "crate::MIGRATOR")]
 async fn test_user_password_repo(pool: PgPool) {
@@ -517,3 +1153,123 @@ async fn test_user_session(pool: PgPool) {
     // This time the session is finished
     assert!(session_lookup.finished_at.is_some());
 }
+
+#[sqlx::test(migrator = "crate::MIGRATOR")]
+async fn test_user_session_impersonation(pool: PgPool) {
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let user = repo
+        .user()
+        .add(&mut rng, &clock, "john".to_owned())
+        .await
+        .unwrap();
+
+    let admin = repo
+        .user()
+        .add(&mut rng, &clock, "admin".to_owned())
+        .await
+        .unwrap();
+
+    let session = repo
+        .browser_session()
+        .add_impersonation(&mut rng, &clock, &user, &admin, Duration::minutes(30), None)
+        .await
+        .unwrap();
+    assert_eq!(session.user.id, user.id);
+    assert!(session.is_impersonation());
+    assert!(session.active_at(clock.now()));
+
+    let session_lookup = repo
+        .browser_session()
+        .lookup(session.id)
+        .await
+        .unwrap()
+        .expect("user session not found");
+    assert_eq!(session_lookup, session);
+
+    let impersonation = session_lookup
+        .impersonation
+        .as_ref()
+        .expect("session should be an impersonation");
+    assert_eq!(impersonation.impersonator_user_id, admin.id);
+    assert_eq!(
+        impersonation.expires_at,
+        clock.now() + Duration::minutes(30)
+    );
+
+    // Record the impersonation in the audit log
+    let audit = repo
+        .user_impersonation()
+        .add(&mut rng, &clock, &admin, &session, "Support ticket".to_owned())
+        .await
+        .unwrap();
+    assert_eq!(audit.impersonator_user_id, admin.id);
+    assert_eq!(audit.user_id, user.id);
+    assert_eq!(audit.user_session_id, session.id);
+    assert_eq!(audit.started_at, clock.now());
+    assert_eq!(audit.end(), impersonation.expires_at);
+
+    let audit_lookup = repo
+        .user_impersonation()
+        .find_by_session(&session)
+        .await
+        .unwrap()
+        .expect("impersonation not found");
+    assert_eq!(audit_lookup, audit);
+
+    // The session stops being active once the impersonation expires
+    clock.advance(Duration::minutes(31));
+    assert!(session_lookup.active());
+    assert!(!session_lookup.active_at(clock.now()));
+
+    // Ending the session after it expired doesn't change when it ended
+    repo.user_impersonation()
+        .end(&clock, &session)
+        .await
+        .unwrap();
+    let audit_lookup = repo
+        .user_impersonation()
+        .find_by_session(&session)
+        .await
+        .unwrap()
+        .expect("impersonation not found");
+    assert_eq!(audit_lookup.ended_at, None);
+
+    // Sessions ended early record when they ended
+    let session = repo
+        .browser_session()
+        .add_impersonation(&mut rng, &clock, &user, &admin, Duration::minutes(30), None)
+        .await
+        .unwrap();
+    repo.user_impersonation()
+        .add(&mut rng, &clock, &admin, &session, "Follow-up".to_owned())
+        .await
+        .unwrap();
+    clock.advance(Duration::minutes(5));
+    repo.user_impersonation()
+        .end(&clock, &session)
+        .await
+        .unwrap();
+
+    let audits = repo
+        .user_impersonation()
+        .list_for_user(&user)
+        .await
+        .unwrap();
+    assert_eq!(audits.len(), 2);
+    assert_eq!(audits[0].reason, "Follow-up");
+    assert_eq!(audits[0].ended_at, Some(clock.now()));
+    assert_eq!(audits[0].end(), clock.now());
+    assert_eq!(audits[1].reason, "Support ticket");
+
+    // Regular sessions are not impersonations
+    let session = repo
+        .browser_session()
+        .add(&mut rng, &clock, &user, None)
+        .await
+        .unwrap();
+    assert!(!session.is_impersonation());
+    assert!(session.active_at(clock.now()));
+}
@@ -16,7 +16,7 @@ use std::net::IpAddr;
 
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use mas_data_model::{CompatSession, CompatSsoLogin, Device, User};
+use mas_data_model::{BrowserSession, CompatSession, CompatSsoLogin, Device, User};
 use rand_core::RngCore;
 use ulid::Ulid;
 
@@ -66,6 +66,7 @@ impl CompatSessionType {
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
 pub struct CompatSessionFilter<'a> {
     user: Option<&'a User>,
+    browser_session: Option<&'a BrowserSession>,
     state: Option<CompatSessionState>,
     auth_type: Option<CompatSessionType>,
 }
@@ -90,6 +91,20 @@ impl<'a> CompatSessionFilter<'a> {
         self.user
     }
 
+    /// Set the browser session from which the compatibility sessions were
+    /// started
+    #[must_use]
+    pub fn for_browser_session(mut self, browser_session: &'a BrowserSession) -> Self {
+        self.browser_session = Some(browser_session);
+        self
+    }
+
+    /// Get the browser session filter
+    #[must_use]
+    pub fn browser_session(&self) -> Option<&BrowserSession> {
+        self.browser_session
+    }
+
     /// Only return active compatibility sessions
     #[must_use]
     pub fn active_only(mut self) -> Self {
@@ -179,6 +194,8 @@ pub trait CompatSessionRepository: Send + Sync {
     /// * `clock`: The clock used to generate timestamps
     /// * `user`: The user to create the compat session for
     /// * `device`: The device ID of this session
+    /// * `browser_session`: The browser session from which the session was
+    ///   started through the SSO login, if any
     /// * `is_synapse_admin`: Whether the session is a synapse admin session
     ///
     /// # Errors
@@ -190,6 +207,7 @@ pub trait CompatSessionRepository: Send + Sync {
         clock: &dyn Clock,
         user: &User,
         device: Device,
+        browser_session: Option<&BrowserSession>,
         is_synapse_admin: bool,
     ) -> Result<CompatSession, Self::Error>;
 
@@ -271,6 +289,7 @@ repository_impl!(CompatSessionRepository:
         clock: &dyn Clock,
         user: &User,
         device: Device,
+        browser_session: Option<&BrowserSession>,
         is_synapse_admin: bool,
     ) -> Result<CompatSession, Self::Error>;
 
@@ -18,6 +18,7 @@ use std::{num::ParseIntError, ops::Deref};
 
 pub use apalis_core::job::{Job, JobId};
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -29,6 +30,7 @@ use crate::repository_impl;
 pub struct JobSubmission {
     name: &'static str,
     payload: Value,
+    run_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -122,6 +124,7 @@ impl JobSubmission {
         Self {
             name: J::NAME,
             payload,
+            run_at: None,
         }
     }
 
@@ -152,6 +155,38 @@ impl JobSubmission {
     pub fn payload(&self) -> &Value {
         &self.payload
     }
+
+    /// Delay the execution of the job until the given time.
+    #[must_use]
+    pub fn with_run_at(mut self, run_at: DateTime<Utc>) -> Self {
+        self.run_at = Some(run_at);
+        self
+    }
+
+    /// The time at which the job should run, if it was delayed.
+    #[must_use]
+    pub fn run_at(&self) -> Option<DateTime<Utc>> {
+        self.run_at
+    }
+}
+
+/// A job which failed and will not be retried anymore
+#[derive(Debug, Clone)]
+pub struct FailedJob {
+    /// The ID of the job
+    pub id: JobId,
+
+    /// The name of the job, as in [`Job::NAME`]
+    pub name: String,
+
+    /// The payload of the job
+    pub payload: Value,
+
+    /// The error the job failed with, if any
+    pub last_error: Option<String>,
+
+    /// When the job failed
+    pub failed_at: Option<DateTime<Utc>>,
 }
 
 /// A [`JobRepository`] is used to schedule jobs to be executed by a worker.
@@ -173,10 +208,22 @@ pub trait JobRepository: Send + Sync {
         &mut self,
         submission: JobSubmission,
     ) -> Result<JobId, Self::Error>;
+
+    /// List the most recent jobs which failed and won't be retried anymore
+    ///
+    /// # Parameters
+    ///
+    /// * `limit` - The maximum number of jobs to return
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn list_failed(&mut self, limit: usize) -> Result<Vec<FailedJob>, Self::Error>;
 }
 
 repository_impl!(JobRepository:
     async fn schedule_submission(&mut self, submission: JobSubmission) -> Result<JobId, Self::Error>;
+    async fn list_failed(&mut self, limit: usize) -> Result<Vec<FailedJob>, Self::Error>;
 );
 
 /// An extension trait for [`JobRepository`] to schedule jobs directly.
@@ -198,6 +245,22 @@ pub trait JobRepositoryExt {
         &mut self,
         job: J,
     ) -> Result<JobId, Self::Error>;
+
+    /// Schedule a job to be executed no earlier than the given time.
+    ///
+    /// # Parameters
+    ///
+    /// * `job` - The job to schedule.
+    /// * `run_at` - The time at which the job should run.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn schedule_job_at<J: Job + Serialize + Send>(
+        &mut self,
+        job: J,
+        run_at: DateTime<Utc>,
+    ) -> Result<JobId, Self::Error>;
 }
 
 #[async_trait]
@@ -226,12 +289,38 @@ where
         self.schedule_submission(JobSubmission::new_with_span_context(job, span_context))
             .await
     }
+
+    #[tracing::instrument(
+        name = "db.job.schedule_job_at",
+        skip_all,
+        fields(
+            job.name = J::NAME,
+            job.run_at = %run_at,
+        ),
+    )]
+    async fn schedule_job_at<J: Job + Serialize + Send>(
+        &mut self,
+        job: J,
+        run_at: DateTime<Utc>,
+    ) -> Result<JobId, Self::Error> {
+        let span = tracing::Span::current();
+        let ctx = span.context();
+        let span = ctx.span();
+        let span_context = span.span_context();
+
+        let submission =
+            JobSubmission::new_with_span_context(job, span_context).with_run_at(run_at);
+        self.schedule_submission(submission).await
+    }
 }
 
 mod jobs {
     // XXX: Move this somewhere else?
     use apalis_core::job::Job;
-    use mas_data_model::{Device, User, UserEmail};
+    use mas_data_model::{
+        AuthorizationGrant, Device, SecurityNotification, UpstreamOAuthProvider, User, UserEmail,
+        UserEmailChange,
+    };
     use serde::{Deserialize, Serialize};
     use ulid::Ulid;
 
@@ -240,6 +329,8 @@ mod jobs {
     pub struct VerifyEmailJob {
         user_email_id: Ulid,
         language: Option<String>,
+        #[serde(default)]
+        attempt: u32,
     }
 
     impl VerifyEmailJob {
@@ -249,6 +340,7 @@ mod jobs {
             Self {
                 user_email_id: user_email.id,
                 language: None,
+                attempt: 0,
             }
         }
 
@@ -270,17 +362,267 @@ mod jobs {
         pub fn user_email_id(&self) -> Ulid {
             self.user_email_id
         }
+
+        /// Create the job for the next sending attempt
+        #[must_use]
+        pub fn next_attempt(&self) -> Self {
+            Self {
+                attempt: self.attempt + 1,
+                ..self.clone()
+            }
+        }
+
+        /// How many sending attempts were already made
+        #[must_use]
+        pub fn attempt(&self) -> u32 {
+            self.attempt
+        }
     }
 
     impl Job for VerifyEmailJob {
         const NAME: &'static str = "verify-email";
     }
 
+    /// A job to ask the old address of a user to confirm a change of their
+    /// primary email address, or to notify it once the change happened
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct SendEmailChangeNotificationJob {
+        user_email_change_id: Ulid,
+        language: Option<String>,
+        #[serde(default)]
+        attempt: u32,
+    }
+
+    impl SendEmailChangeNotificationJob {
+        /// Create a new job to notify the old address of an email change
+        #[must_use]
+        pub fn new(user_email_change: &UserEmailChange) -> Self {
+            Self {
+                user_email_change_id: user_email_change.id,
+                language: None,
+                attempt: 0,
+            }
+        }
+
+        /// Set the language to use for the email.
+        #[must_use]
+        pub fn with_language(mut self, language: String) -> Self {
+            self.language = Some(language);
+            self
+        }
+
+        /// The language to use for the email.
+        #[must_use]
+        pub fn language(&self) -> Option<&str> {
+            self.language.as_deref()
+        }
+
+        /// The ID of the email change to notify about
+        #[must_use]
+        pub fn user_email_change_id(&self) -> Ulid {
+            self.user_email_change_id
+        }
+
+        /// Create the job for the next sending attempt
+        #[must_use]
+        pub fn next_attempt(&self) -> Self {
+            Self {
+                attempt: self.attempt + 1,
+                ..self.clone()
+            }
+        }
+
+        /// How many sending attempts were already made
+        #[must_use]
+        pub fn attempt(&self) -> u32 {
+            self.attempt
+        }
+    }
+
+    impl Job for SendEmailChangeNotificationJob {
+        const NAME: &'static str = "send-email-change-notification";
+    }
+
+    /// A job to let a user know by email about a security-relevant event on
+    /// their account
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct SendSecurityNotificationJob {
+        user_id: Ulid,
+        category: SecurityNotification,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        upstream_oauth_provider_id: Option<Ulid>,
+        language: Option<String>,
+        #[serde(default)]
+        attempt: u32,
+    }
+
+    impl SendSecurityNotificationJob {
+        fn new(user: &User, category: SecurityNotification) -> Self {
+            Self {
+                user_id: user.id,
+                category,
+                upstream_oauth_provider_id: None,
+                language: None,
+                attempt: 0,
+            }
+        }
+
+        /// Create a new job to notify a user that their password changed
+        #[must_use]
+        pub fn password_changed(user: &User) -> Self {
+            Self::new(user, SecurityNotification::PasswordChange)
+        }
+
+        /// Create a new job to notify a user that an upstream account was
+        /// linked to theirs
+        #[must_use]
+        pub fn upstream_linked(user: &User, provider: &UpstreamOAuthProvider) -> Self {
+            Self {
+                upstream_oauth_provider_id: Some(provider.id),
+                ..Self::new(user, SecurityNotification::UpstreamLink)
+            }
+        }
+
+        /// Create a new job to notify a user that their account was locked
+        #[must_use]
+        pub fn account_locked(user: &User) -> Self {
+            Self::new(user, SecurityNotification::AccountLock)
+        }
+
+        /// Set the language to use for the email.
+        #[must_use]
+        pub fn with_language(mut self, language: String) -> Self {
+            self.language = Some(language);
+            self
+        }
+
+        /// The language to use for the email.
+        #[must_use]
+        pub fn language(&self) -> Option<&str> {
+            self.language.as_deref()
+        }
+
+        /// The ID of the user to notify
+        #[must_use]
+        pub fn user_id(&self) -> Ulid {
+            self.user_id
+        }
+
+        /// The category of the notification
+        #[must_use]
+        pub fn category(&self) -> SecurityNotification {
+            self.category
+        }
+
+        /// The ID of the upstream provider which was linked, if any
+        #[must_use]
+        pub fn upstream_oauth_provider_id(&self) -> Option<Ulid> {
+            self.upstream_oauth_provider_id
+        }
+
+        /// Create the job for the next sending attempt
+        #[must_use]
+        pub fn next_attempt(&self) -> Self {
+            Self {
+                attempt: self.attempt + 1,
+                ..self.clone()
+            }
+        }
+
+        /// How many sending attempts were already made
+        #[must_use]
+        pub fn attempt(&self) -> u32 {
+            self.attempt
+        }
+    }
+
+    impl Job for SendSecurityNotificationJob {
+        const NAME: &'static str = "send-security-notification";
+    }
+
+    /// A job to let a user know by email that an administrator approved their
+    /// authorization request, with a link to finish it
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct SendAuthorizationApprovedJob {
+        authorization_grant_id: Ulid,
+        #[serde(default)]
+        attempt: u32,
+    }
+
+    impl SendAuthorizationApprovedJob {
+        /// Create a new job to notify the user who asked for the approval of
+        /// an authorization grant
+        #[must_use]
+        pub fn new(authorization_grant: &AuthorizationGrant) -> Self {
+            Self {
+                authorization_grant_id: authorization_grant.id,
+                attempt: 0,
+            }
+        }
+
+        /// The ID of the authorization grant which was approved
+        #[must_use]
+        pub fn authorization_grant_id(&self) -> Ulid {
+            self.authorization_grant_id
+        }
+
+        /// Create the job for the next sending attempt
+        #[must_use]
+        pub fn next_attempt(&self) -> Self {
+            Self {
+                attempt: self.attempt + 1,
+                ..self.clone()
+            }
+        }
+
+        /// How many sending attempts were already made
+        #[must_use]
+        pub fn attempt(&self) -> u32 {
+            self.attempt
+        }
+    }
+
+    impl Job for SendAuthorizationApprovedJob {
+        const NAME: &'static str = "send-authorization-approved";
+    }
+
+    /// A job to remove the old address of a user once the grace period of an
+    /// email change is over, or to cancel the change if the old address did
+    /// not confirm it in time
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct FinishEmailChangeJob {
+        user_email_change_id: Ulid,
+    }
+
+    impl FinishEmailChangeJob {
+        /// Create a new job to finish an email change
+        #[must_use]
+        pub fn new(user_email_change: &UserEmailChange) -> Self {
+            Self {
+                user_email_change_id: user_email_change.id,
+            }
+        }
+
+        /// The ID of the email change to finish
+        #[must_use]
+        pub fn user_email_change_id(&self) -> Ulid {
+            self.user_email_change_id
+        }
+    }
+
+    impl Job for FinishEmailChangeJob {
+        const NAME: &'static str = "finish-email-change";
+    }
+
     /// A job to provision the user on the homeserver.
     #[derive(Serialize, Deserialize, Debug, Clone)]
     pub struct ProvisionUserJob {
         user_id: Ulid,
         set_display_name: Option<String>,
+        #[serde(default)]
+        set_avatar_url: Option<String>,
+        #[serde(default)]
+        attempt: u32,
     }
 
     impl ProvisionUserJob {
@@ -290,6 +632,8 @@ mod jobs {
             Self {
                 user_id: user.id,
                 set_display_name: None,
+                set_avatar_url: None,
+                attempt: 0,
             }
         }
 
@@ -299,6 +643,8 @@ mod jobs {
             Self {
                 user_id,
                 set_display_name: None,
+                set_avatar_url: None,
+                attempt: 0,
             }
         }
 
@@ -315,11 +661,39 @@ mod jobs {
             self.set_display_name.as_deref()
         }
 
+        /// Set the avatar URL of the user.
+        #[must_use]
+        pub fn set_avatar_url(mut self, avatar_url: String) -> Self {
+            self.set_avatar_url = Some(avatar_url);
+            self
+        }
+
+        /// Get the avatar URL to be set.
+        #[must_use]
+        pub fn avatar_url_to_set(&self) -> Option<&str> {
+            self.set_avatar_url.as_deref()
+        }
+
         /// The ID of the user to provision.
         #[must_use]
         pub fn user_id(&self) -> Ulid {
             self.user_id
         }
+
+        /// Create the job for the next provisioning attempt
+        #[must_use]
+        pub fn next_attempt(&self) -> Self {
+            Self {
+                attempt: self.attempt + 1,
+                ..self.clone()
+            }
+        }
+
+        /// How many provisioning attempts were already made
+        #[must_use]
+        pub fn attempt(&self) -> u32 {
+            self.attempt
+        }
     }
 
     impl Job for ProvisionUserJob {
@@ -331,6 +705,10 @@ mod jobs {
     pub struct ProvisionDeviceJob {
         user_id: Ulid,
         device_id: String,
+        #[serde(default)]
+        display_name: Option<String>,
+        #[serde(default)]
+        attempt: u32,
     }
 
     impl ProvisionDeviceJob {
@@ -340,9 +718,18 @@ mod jobs {
             Self {
                 user_id: user.id,
                 device_id: device.as_str().to_owned(),
+                display_name: None,
+                attempt: 0,
             }
         }
 
+        /// Set the display name of the device on the homeserver, if any.
+        #[must_use]
+        pub fn with_display_name(mut self, display_name: Option<String>) -> Self {
+            self.display_name = display_name;
+            self
+        }
+
         /// The ID of the user to provision the device for.
         #[must_use]
         pub fn user_id(&self) -> Ulid {
@@ -354,6 +741,27 @@ mod jobs {
         pub fn device_id(&self) -> &str {
             &self.device_id
         }
+
+        /// The display name to set on the device, if any.
+        #[must_use]
+        pub fn display_name(&self) -> Option<&str> {
+            self.display_name.as_deref()
+        }
+
+        /// Create the job for the next provisioning attempt
+        #[must_use]
+        pub fn next_attempt(&self) -> Self {
+            Self {
+                attempt: self.attempt + 1,
+                ..self.clone()
+            }
+        }
+
+        /// How many provisioning attempts were already made
+        #[must_use]
+        pub fn attempt(&self) -> u32 {
+            self.attempt
+        }
     }
 
     impl Job for ProvisionDeviceJob {
@@ -365,6 +773,8 @@ mod jobs {
     pub struct DeleteDeviceJob {
         user_id: Ulid,
         device_id: String,
+        #[serde(default)]
+        attempt: u32,
     }
 
     impl DeleteDeviceJob {
@@ -374,6 +784,7 @@ mod jobs {
             Self {
                 user_id: user.id,
                 device_id: device.as_str().to_owned(),
+                attempt: 0,
             }
         }
 
@@ -388,17 +799,138 @@ mod jobs {
         pub fn device_id(&self) -> &str {
             &self.device_id
         }
+
+        /// Create the job for the next deletion attempt
+        #[must_use]
+        pub fn next_attempt(&self) -> Self {
+            Self {
+                attempt: self.attempt + 1,
+                ..self.clone()
+            }
+        }
+
+        /// How many deletion attempts were already made
+        #[must_use]
+        pub fn attempt(&self) -> u32 {
+            self.attempt
+        }
     }
 
     impl Job for DeleteDeviceJob {
         const NAME: &'static str = "delete-device";
     }
 
+    /// A job to set or remove the display name of a user on the homeserver
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct SetDisplayNameJob {
+        user_id: Ulid,
+        display_name: Option<String>,
+        #[serde(default)]
+        attempt: u32,
+    }
+
+    impl SetDisplayNameJob {
+        /// Create a new job to set the display name of a user on the
+        /// homeserver
+        ///
+        /// # Parameters
+        ///
+        /// * `user` - The user to update
+        /// * `display_name` - The display name to set, or `None` to remove it
+        #[must_use]
+        pub fn new(user: &User, display_name: Option<String>) -> Self {
+            Self {
+                user_id: user.id,
+                display_name,
+                attempt: 0,
+            }
+        }
+
+        /// The ID of the user to update
+        #[must_use]
+        pub fn user_id(&self) -> Ulid {
+            self.user_id
+        }
+
+        /// The display name to set, or `None` if it should be removed
+        #[must_use]
+        pub fn display_name(&self) -> Option<&str> {
+            self.display_name.as_deref()
+        }
+
+        /// Create the job for the next attempt
+        #[must_use]
+        pub fn next_attempt(&self) -> Self {
+            Self {
+                attempt: self.attempt + 1,
+                ..self.clone()
+            }
+        }
+
+        /// How many attempts were already made
+        #[must_use]
+        pub fn attempt(&self) -> u32 {
+            self.attempt
+        }
+    }
+
+    impl Job for SetDisplayNameJob {
+        const NAME: &'static str = "set-display-name";
+    }
+
+    /// A job to allow a user to reset their cross-signing identity on the
+    /// homeserver, without going through user-interactive authentication
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct AllowCrossSigningResetJob {
+        user_id: Ulid,
+        #[serde(default)]
+        attempt: u32,
+    }
+
+    impl AllowCrossSigningResetJob {
+        /// Create a new job to allow the user to reset their cross-signing
+        /// identity
+        #[must_use]
+        pub fn new(user: &User) -> Self {
+            Self {
+                user_id: user.id,
+                attempt: 0,
+            }
+        }
+
+        /// The ID of the user allowed to reset their cross-signing identity
+        #[must_use]
+        pub fn user_id(&self) -> Ulid {
+            self.user_id
+        }
+
+        /// Create the job for the next attempt
+        #[must_use]
+        pub fn next_attempt(&self) -> Self {
+            Self {
+                attempt: self.attempt + 1,
+                ..self.clone()
+            }
+        }
+
+        /// How many attempts were already made
+        #[must_use]
+        pub fn attempt(&self) -> u32 {
+            self.attempt
+        }
+    }
+
+    impl Job for AllowCrossSigningResetJob {
+        const NAME: &'static str = "allow-cross-signing-reset";
+    }
+
     /// A job to deactivate and lock a user
     #[derive(Serialize, Deserialize, Debug, Clone)]
     pub struct DeactivateUserJob {
         user_id: Ulid,
         hs_erase: bool,
+        #[serde(default)]
+        erase_pii: bool,
     }
 
     impl DeactivateUserJob {
@@ -413,9 +945,18 @@ mod jobs {
             Self {
                 user_id: user.id,
                 hs_erase,
+                erase_pii: false,
             }
         }
 
+        /// Also erase the personal information attached to the user, like
+        /// their email addresses and upstream OAuth links
+        #[must_use]
+        pub fn with_erase_pii(mut self, erase_pii: bool) -> Self {
+            self.erase_pii = erase_pii;
+            self
+        }
+
         /// The ID of the user to deactivate
         #[must_use]
         pub fn user_id(&self) -> Ulid {
@@ -427,13 +968,165 @@ mod jobs {
         pub fn hs_erase(&self) -> bool {
             self.hs_erase
         }
+
+        /// Whether to erase the personal information attached to the user
+        #[must_use]
+        pub fn erase_pii(&self) -> bool {
+            self.erase_pii
+        }
     }
 
     impl Job for DeactivateUserJob {
         const NAME: &'static str = "deactivate-user";
     }
+
+    /// A change in the lifecycle of a user, which is notified to the
+    /// configured webhooks
+    #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+    #[serde(rename_all = "snake_case", tag = "kind")]
+    pub enum UserLifecycleEvent {
+        /// The user was created
+        Created,
+
+        /// The user was deactivated
+        Deactivated,
+
+        /// The user was locked
+        Locked,
+
+        /// One of the user's email addresses was verified
+        EmailVerified {
+            /// The ID of the email address which was verified
+            user_email_id: Ulid,
+        },
+    }
+
+    impl UserLifecycleEvent {
+        /// The name of the event, as sent in the webhook payloads
+        #[must_use]
+        pub fn name(&self) -> &'static str {
+            match self {
+                Self::Created => "user.created",
+                Self::Deactivated => "user.deactivated",
+                Self::Locked => "user.locked",
+                Self::EmailVerified { .. } => "user.email_verified",
+            }
+        }
+    }
+
+    /// A job to notify the configured webhooks about a user lifecycle event
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct NotifyUserEventJob {
+        user_id: Ulid,
+        event: UserLifecycleEvent,
+    }
+
+    impl NotifyUserEventJob {
+        /// Create a new job to notify the webhooks about a user lifecycle
+        /// event
+        ///
+        /// # Parameters
+        ///
+        /// * `user` - The user the event is about
+        /// * `event` - The event to notify
+        #[must_use]
+        pub fn new(user: &User, event: UserLifecycleEvent) -> Self {
+            Self {
+                user_id: user.id,
+                event,
+            }
+        }
+
+        /// Create a new job to notify that a user email was verified
+        #[must_use]
+        pub fn email_verified(user_email: &UserEmail) -> Self {
+            Self {
+                user_id: user_email.user_id,
+                event: UserLifecycleEvent::EmailVerified {
+                    user_email_id: user_email.id,
+                },
+            }
+        }
+
+        /// The ID of the user the event is about
+        #[must_use]
+        pub fn user_id(&self) -> Ulid {
+            self.user_id
+        }
+
+        /// The event to notify
+        #[must_use]
+        pub fn event(&self) -> UserLifecycleEvent {
+            self.event
+        }
+    }
+
+    impl Job for NotifyUserEventJob {
+        const NAME: &'static str = "notify-user-event";
+    }
+
+    /// A job to deliver a single webhook payload to an endpoint
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct DeliverWebhookJob {
+        endpoint_id: Ulid,
+        body: String,
+        attempt: u32,
+    }
+
+    impl DeliverWebhookJob {
+        /// Create a new job to deliver a webhook payload
+        ///
+        /// # Parameters
+        ///
+        /// * `endpoint_id` - The ID of the configured endpoint to deliver the
+        ///   payload to
+        /// * `body` - The serialized payload
+        #[must_use]
+        pub fn new(endpoint_id: Ulid, body: String) -> Self {
+            Self {
+                endpoint_id,
+                body,
+                attempt: 0,
+            }
+        }
+
+        /// Create the job for the next delivery attempt
+        #[must_use]
+        pub fn next_attempt(&self) -> Self {
+            Self {
+                endpoint_id: self.endpoint_id,
+                body: self.body.clone(),
+                attempt: self.attempt + 1,
+            }
+        }
+
+        /// The ID of the endpoint to deliver the payload to
+        #[must_use]
+        pub fn endpoint_id(&self) -> Ulid {
+            self.endpoint_id
+        }
+
+        /// The serialized payload to deliver
+        #[must_use]
+        pub fn body(&self) -> &str {
+            &self.body
+        }
+
+        /// How many delivery attempts were already made
+        #[must_use]
+        pub fn attempt(&self) -> u32 {
+            self.attempt
+        }
+    }
+
+    impl Job for DeliverWebhookJob {
+        const NAME: &'static str = "deliver-webhook";
+    }
 }
 
 pub use self::jobs::{
-    DeactivateUserJob, DeleteDeviceJob, ProvisionDeviceJob, ProvisionUserJob, VerifyEmailJob,
+    AllowCrossSigningResetJob, DeactivateUserJob, DeleteDeviceJob, DeliverWebhookJob,
+    FinishEmailChangeJob, NotifyUserEventJob, ProvisionDeviceJob, ProvisionUserJob,
+    SendAuthorizationApprovedJob, SendEmailChangeNotificationJob, SendSecurityNotificationJob,
+    SetDisplayNameJob, UserLifecycleEvent, VerifyEmailJob,
 };
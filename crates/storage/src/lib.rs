@@ -153,6 +153,7 @@ pub mod app_session;
 pub mod compat;
 pub mod job;
 pub mod oauth2;
+pub mod rate_limit;
 pub mod upstream_oauth2;
 pub mod user;
 
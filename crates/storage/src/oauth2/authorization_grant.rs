@@ -15,7 +15,7 @@
 use std::num::NonZeroU32;
 
 use async_trait::async_trait;
-use mas_data_model::{AuthorizationCode, AuthorizationGrant, Client, Session};
+use mas_data_model::{AuthorizationCode, AuthorizationGrant, Client, Session, User};
 use oauth2_types::{requests::ResponseMode, scope::Scope};
 use rand_core::RngCore;
 use ulid::Ulid;
@@ -51,6 +51,8 @@ pub trait OAuth2AuthorizationGrantRepository: Send + Sync {
     /// * `response_type_id_token`: Whether the `id_token` `response_type` was
     ///   requested
     /// * `requires_consent`: Whether the client explicitly requested consent
+    /// * `device_display_name`: The display name the client asked to give to
+    ///   its device, if set
     ///
     /// # Errors
     ///
@@ -70,6 +72,7 @@ pub trait OAuth2AuthorizationGrantRepository: Send + Sync {
         response_mode: ResponseMode,
         response_type_id_token: bool,
         requires_consent: bool,
+        device_display_name: Option<String>,
     ) -> Result<AuthorizationGrant, Self::Error>;
 
     /// Lookup an authorization grant by its ID
@@ -138,7 +141,8 @@ pub trait OAuth2AuthorizationGrantRepository: Send + Sync {
         authorization_grant: AuthorizationGrant,
     ) -> Result<AuthorizationGrant, Self::Error>;
 
-    /// Unset the `requires_consent` flag on an authorization grant
+    /// Mark an authorization grant as consented to by the user, unsetting its
+    /// `requires_consent` flag
     ///
     /// Returns the updated authorization grant
     ///
@@ -153,6 +157,107 @@ pub trait OAuth2AuthorizationGrantRepository: Send + Sync {
         &mut self,
         authorization_grant: AuthorizationGrant,
     ) -> Result<AuthorizationGrant, Self::Error>;
+
+    /// Hold an authorization grant until an administrator approves it
+    ///
+    /// Returns the updated authorization grant
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `user`: The user who asks for the approval, and who will be able to
+    ///   complete the grant once it is approved
+    /// * `authorization_grant`: The authorization grant to hold
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn request_approval(
+        &mut self,
+        clock: &dyn Clock,
+        user: &User,
+        authorization_grant: AuthorizationGrant,
+    ) -> Result<AuthorizationGrant, Self::Error>;
+
+    /// Approve an authorization grant which was held for an approval
+    ///
+    /// Returns the updated authorization grant
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `reviewer`: The administrator who approves the grant, if it is
+    ///   approved by a user
+    /// * `authorization_grant`: The authorization grant to approve
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails, or if the
+    /// grant was not held for an approval
+    async fn approve(
+        &mut self,
+        clock: &dyn Clock,
+        reviewer: Option<&User>,
+        authorization_grant: AuthorizationGrant,
+    ) -> Result<AuthorizationGrant, Self::Error>;
+
+    /// Deny an authorization grant which was held for an approval, cancelling
+    /// it
+    ///
+    /// Returns the updated authorization grant
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `reviewer`: The administrator who denies the grant, if it is denied
+    ///   by a user
+    /// * `authorization_grant`: The authorization grant to deny
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails, or if the
+    /// grant was not held for an approval
+    async fn deny(
+        &mut self,
+        clock: &dyn Clock,
+        reviewer: Option<&User>,
+        authorization_grant: AuthorizationGrant,
+    ) -> Result<AuthorizationGrant, Self::Error>;
+
+    /// Cancel a pending authorization grant, so that it can't be completed
+    /// anymore
+    ///
+    /// Returns the updated authorization grant
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `authorization_grant`: The authorization grant to cancel
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails, or if the
+    /// grant is not pending
+    async fn cancel(
+        &mut self,
+        clock: &dyn Clock,
+        authorization_grant: AuthorizationGrant,
+    ) -> Result<AuthorizationGrant, Self::Error>;
+
+    /// List the pending authorization grants waiting for an administrator to
+    /// approve them, oldest requests first
+    ///
+    /// # Parameters
+    ///
+    /// * `limit`: The maximum number of grants to return
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn list_awaiting_approval(
+        &mut self,
+        limit: usize,
+    ) -> Result<Vec<AuthorizationGrant>, Self::Error>;
 }
 
 repository_impl!(OAuth2AuthorizationGrantRepository:
@@ -170,6 +275,7 @@ repository_impl!(OAuth2AuthorizationGrantRepository:
         response_mode: ResponseMode,
         response_type_id_token: bool,
         requires_consent: bool,
+        device_display_name: Option<String>,
     ) -> Result<AuthorizationGrant, Self::Error>;
 
     async fn lookup(&mut self, id: Ulid) -> Result<Option<AuthorizationGrant>, Self::Error>;
@@ -194,4 +300,36 @@ repository_impl!(OAuth2AuthorizationGrantRepository:
         &mut self,
         authorization_grant: AuthorizationGrant,
     ) -> Result<AuthorizationGrant, Self::Error>;
+
+    async fn request_approval(
+        &mut self,
+        clock: &dyn Clock,
+        user: &User,
+        authorization_grant: AuthorizationGrant,
+    ) -> Result<AuthorizationGrant, Self::Error>;
+
+    async fn approve(
+        &mut self,
+        clock: &dyn Clock,
+        reviewer: Option<&User>,
+        authorization_grant: AuthorizationGrant,
+    ) -> Result<AuthorizationGrant, Self::Error>;
+
+    async fn deny(
+        &mut self,
+        clock: &dyn Clock,
+        reviewer: Option<&User>,
+        authorization_grant: AuthorizationGrant,
+    ) -> Result<AuthorizationGrant, Self::Error>;
+
+    async fn cancel(
+        &mut self,
+        clock: &dyn Clock,
+        authorization_grant: AuthorizationGrant,
+    ) -> Result<AuthorizationGrant, Self::Error>;
+
+    async fn list_awaiting_approval(
+        &mut self,
+        limit: usize,
+    ) -> Result<Vec<AuthorizationGrant>, Self::Error>;
 );
@@ -15,7 +15,8 @@
 use std::collections::{BTreeMap, BTreeSet};
 
 use async_trait::async_trait;
-use mas_data_model::{Client, User};
+use chrono::{DateTime, Utc};
+use mas_data_model::{ClaimMapping, Client, ClientTokenSettings, RedirectUriMatching, User};
 use mas_iana::{jose::JsonWebSignatureAlg, oauth::OAuthClientAuthenticationMethod};
 use mas_jose::jwk::PublicJsonWebKeySet;
 use oauth2_types::{oidc::ApplicationType, requests::GrantType, scope::Scope};
@@ -88,6 +89,8 @@ pub trait OAuth2ClientRepository: Send + Sync {
     /// * `client_uri`: The URI of a website of this client, if given
     /// * `policy_uri`: The URI of the privacy policy of this client, if given
     /// * `tos_uri`: The URI of the terms of service of this client, if given
+    /// * `brand_color`: The color used to brand the pages shown on behalf of
+    ///   this client, if given
     /// * `jwks_uri`: The URI of the JWKS of this client, if given
     /// * `jwks`: The JWKS of this client, if given
     /// * `id_token_signed_response_alg`: The algorithm used to sign the ID
@@ -119,6 +122,7 @@ pub trait OAuth2ClientRepository: Send + Sync {
         client_uri: Option<Url>,
         policy_uri: Option<Url>,
         tos_uri: Option<Url>,
+        brand_color: Option<String>,
         jwks_uri: Option<Url>,
         jwks: Option<PublicJsonWebKeySet>,
         id_token_signed_response_alg: Option<JsonWebSignatureAlg>,
@@ -140,6 +144,10 @@ pub trait OAuth2ClientRepository: Send + Sync {
     /// * `jwks`: The client JWKS, if any
     /// * `jwks_uri`: The client JWKS URI, if any
     /// * `redirect_uris`: The list of redirect URIs used by this client
+    /// * `issue_refresh_tokens`: Whether refresh tokens are issued to this
+    ///   client
+    /// * `userinfo_signed_response_alg`: The algorithm used to sign the
+    ///   responses of the userinfo endpoint, if they should be signed
     ///
     /// # Errors
     ///
@@ -153,6 +161,8 @@ pub trait OAuth2ClientRepository: Send + Sync {
         jwks: Option<PublicJsonWebKeySet>,
         jwks_uri: Option<Url>,
         redirect_uris: Vec<Url>,
+        issue_refresh_tokens: bool,
+        userinfo_signed_response_alg: Option<JsonWebSignatureAlg>,
     ) -> Result<Client, Self::Error>;
 
     /// List all static clients
@@ -162,11 +172,194 @@ pub trait OAuth2ClientRepository: Send + Sync {
     /// Returns [`Self::Error`] if the underlying repository fails
     async fn all_static(&mut self) -> Result<Vec<Client>, Self::Error>;
 
-    /// Get the list of scopes that the user has given consent for the given
+    /// Get the scopes the client is allowed to obtain with the client
+    /// credentials grant
+    ///
+    /// Returns `None` if the client isn't restricted to a set of scopes
+    ///
+    /// # Parameters
+    ///
+    /// * `client`: The client to get the allowed scopes of
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn client_credentials_scope(
+        &mut self,
+        client: &Client,
+    ) -> Result<Option<Scope>, Self::Error>;
+
+    /// Set the scopes the client is allowed to obtain with the client
+    /// credentials grant
+    ///
+    /// # Parameters
+    ///
+    /// * `client`: The client to update
+    /// * `scope`: The allowed scopes, or `None` to lift the restriction
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_client_credentials_scope(
+        &mut self,
+        client: &Client,
+        scope: Option<&Scope>,
+    ) -> Result<(), Self::Error>;
+
+    /// Get the overrides of the lifetime and audience of the tokens issued to
+    /// the client
+    ///
+    /// # Parameters
+    ///
+    /// * `client`: The client to get the token settings of
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn token_settings(&mut self, client: &Client)
+        -> Result<ClientTokenSettings, Self::Error>;
+
+    /// Set the overrides of the lifetime and audience of the tokens issued to
+    /// the client
+    ///
+    /// # Parameters
+    ///
+    /// * `client`: The client to update
+    /// * `settings`: The new token settings
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_token_settings(
+        &mut self,
+        client: &Client,
+        settings: &ClientTokenSettings,
+    ) -> Result<(), Self::Error>;
+
+    /// Get how the redirect URIs of the authorization requests of the client
+    /// are matched against its registered ones
+    ///
+    /// # Parameters
+    ///
+    /// * `client`: The client to get the matching policy of
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn redirect_uri_matching(
+        &mut self,
+        client: &Client,
+    ) -> Result<RedirectUriMatching, Self::Error>;
+
+    /// Set how the redirect URIs of the authorization requests of the client
+    /// are matched against its registered ones
+    ///
+    /// # Parameters
+    ///
+    /// * `client`: The client to update
+    /// * `matching`: The new matching policy
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_redirect_uri_matching(
+        &mut self,
+        client: &Client,
+        matching: RedirectUriMatching,
+    ) -> Result<(), Self::Error>;
+
+    /// Get whether the authorization requests of the client are held until an
+    /// administrator approves them
+    ///
+    /// # Parameters
+    ///
+    /// * `client`: The client to check
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn requires_approval(&mut self, client: &Client) -> Result<bool, Self::Error>;
+
+    /// Set whether the authorization requests of the client are held until an
+    /// administrator approves them
+    ///
+    /// # Parameters
+    ///
+    /// * `client`: The client to update
+    /// * `requires_approval`: Whether an approval is required
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_requires_approval(
+        &mut self,
+        client: &Client,
+        requires_approval: bool,
+    ) -> Result<(), Self::Error>;
+
+    /// Get the scopes users don't have to consent to for the client
+    ///
+    /// Returns `None` if the client isn't trusted
+    ///
+    /// # Parameters
+    ///
+    /// * `client`: The client to get the trusted scopes of
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn trusted_scope(&mut self, client: &Client) -> Result<Option<Scope>, Self::Error>;
+
+    /// Set the scopes users don't have to consent to for the client
+    ///
+    /// # Parameters
+    ///
+    /// * `client`: The client to update
+    /// * `scope`: The trusted scopes, or `None` if the client isn't trusted
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_trusted_scope(
+        &mut self,
+        client: &Client,
+        scope: Option<&Scope>,
+    ) -> Result<(), Self::Error>;
+
+    /// Get the mappings of user attributes to claims specific to the client
+    ///
+    /// # Parameters
+    ///
+    /// * `client`: The client to get the claim mappings of
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn claim_mappings(&mut self, client: &Client) -> Result<Vec<ClaimMapping>, Self::Error>;
+
+    /// Replace the mappings of user attributes to claims specific to the
     /// client
     ///
     /// # Parameters
     ///
+    /// * `client`: The client to update
+    /// * `mappings`: The new claim mappings
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_claim_mappings(
+        &mut self,
+        client: &Client,
+        mappings: &[ClaimMapping],
+    ) -> Result<(), Self::Error>;
+
+    /// Get the list of scopes that the user has given consent for the given
+    /// client, leaving out the consents which expired
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to check the expiry of the consents
     /// * `client`: The client to get the consent for
     /// * `user`: The user to get the consent for
     ///
@@ -175,12 +368,16 @@ pub trait OAuth2ClientRepository: Send + Sync {
     /// Returns [`Self::Error`] if the underlying repository fails
     async fn get_consent_for_user(
         &mut self,
+        clock: &dyn Clock,
         client: &Client,
         user: &User,
     ) -> Result<Scope, Self::Error>;
 
     /// Give consent for a set of scopes for the given client and user
     ///
+    /// If consent was already given for some of the scopes, their expiry is
+    /// replaced
+    ///
     /// # Parameters
     ///
     /// * `rng`: The random number generator to use
@@ -188,6 +385,7 @@ pub trait OAuth2ClientRepository: Send + Sync {
     /// * `client`: The client to give the consent for
     /// * `user`: The user to give the consent for
     /// * `scope`: The scope to give consent for
+    /// * `expires_at`: When the consent expires, if ever
     ///
     /// # Errors
     ///
@@ -199,6 +397,7 @@ pub trait OAuth2ClientRepository: Send + Sync {
         client: &Client,
         user: &User,
         scope: &Scope,
+        expires_at: Option<DateTime<Utc>>,
     ) -> Result<(), Self::Error>;
 
     /// Delete a client
@@ -250,6 +449,7 @@ repository_impl!(OAuth2ClientRepository:
         client_uri: Option<Url>,
         policy_uri: Option<Url>,
         tos_uri: Option<Url>,
+        brand_color: Option<String>,
         jwks_uri: Option<Url>,
         jwks: Option<PublicJsonWebKeySet>,
         id_token_signed_response_alg: Option<JsonWebSignatureAlg>,
@@ -267,16 +467,74 @@ repository_impl!(OAuth2ClientRepository:
         jwks: Option<PublicJsonWebKeySet>,
         jwks_uri: Option<Url>,
         redirect_uris: Vec<Url>,
+        issue_refresh_tokens: bool,
+        userinfo_signed_response_alg: Option<JsonWebSignatureAlg>,
     ) -> Result<Client, Self::Error>;
 
     async fn all_static(&mut self) -> Result<Vec<Client>, Self::Error>;
 
+    async fn client_credentials_scope(
+        &mut self,
+        client: &Client,
+    ) -> Result<Option<Scope>, Self::Error>;
+
+    async fn set_client_credentials_scope(
+        &mut self,
+        client: &Client,
+        scope: Option<&Scope>,
+    ) -> Result<(), Self::Error>;
+
+    async fn token_settings(&mut self, client: &Client)
+        -> Result<ClientTokenSettings, Self::Error>;
+
+    async fn set_token_settings(
+        &mut self,
+        client: &Client,
+        settings: &ClientTokenSettings,
+    ) -> Result<(), Self::Error>;
+
+    async fn redirect_uri_matching(
+        &mut self,
+        client: &Client,
+    ) -> Result<RedirectUriMatching, Self::Error>;
+
+    async fn set_redirect_uri_matching(
+        &mut self,
+        client: &Client,
+        matching: RedirectUriMatching,
+    ) -> Result<(), Self::Error>;
+
+    async fn requires_approval(&mut self, client: &Client) -> Result<bool, Self::Error>;
+
+    async fn set_requires_approval(
+        &mut self,
+        client: &Client,
+        requires_approval: bool,
+    ) -> Result<(), Self::Error>;
+
+    async fn trusted_scope(&mut self, client: &Client) -> Result<Option<Scope>, Self::Error>;
+
+    async fn set_trusted_scope(
+        &mut self,
+        client: &Client,
+        scope: Option<&Scope>,
+    ) -> Result<(), Self::Error>;
+
+    async fn claim_mappings(&mut self, client: &Client) -> Result<Vec<ClaimMapping>, Self::Error>;
+
+    async fn set_claim_mappings(
+        &mut self,
+        client: &Client,
+        mappings: &[ClaimMapping],
+    ) -> Result<(), Self::Error>;
+
     async fn delete(&mut self, client: Client) -> Result<(), Self::Error>;
 
     async fn delete_by_id(&mut self, id: Ulid) -> Result<(), Self::Error>;
 
     async fn get_consent_for_user(
         &mut self,
+        clock: &dyn Clock,
         client: &Client,
         user: &User,
     ) -> Result<Scope, Self::Error>;
@@ -288,5 +546,6 @@ repository_impl!(OAuth2ClientRepository:
         client: &Client,
         user: &User,
         scope: &Scope,
+        expires_at: Option<DateTime<Utc>>,
     ) -> Result<(), Self::Error>;
 );
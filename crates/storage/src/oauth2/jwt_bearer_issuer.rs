@@ -0,0 +1,180 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::{JwksOrJwksUri, JwtBearerIssuer};
+use oauth2_types::scope::Scope;
+use rand_core::RngCore;
+use ulid::Ulid;
+
+use crate::{repository_impl, Clock};
+
+/// An [`OAuth2JwtBearerIssuerRepository`] helps interacting with
+/// [`JwtBearerIssuer`] saved in the storage backend
+#[async_trait]
+pub trait OAuth2JwtBearerIssuerRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// Lookup a JWT bearer issuer by its ID
+    ///
+    /// Returns `None` if the issuer does not exist
+    ///
+    /// # Parameters
+    ///
+    /// * `id`: The ID of the issuer to lookup
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<JwtBearerIssuer>, Self::Error>;
+
+    /// Find a JWT bearer issuer by its `iss` claim
+    ///
+    /// Returns `None` if no issuer matches
+    ///
+    /// # Parameters
+    ///
+    /// * `issuer`: The `iss` claim of the assertions signed by the issuer
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn find_by_issuer(
+        &mut self,
+        issuer: &str,
+    ) -> Result<Option<JwtBearerIssuer>, Self::Error>;
+
+    /// Add or replace a JWT bearer issuer, keyed by its `iss` claim
+    ///
+    /// Returns the issuer that was added or replaced
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: The random number generator to use
+    /// * `clock`: The clock used to generate timestamps
+    /// * `issuer`: The `iss` claim of the assertions signed by the issuer
+    /// * `jwks`: The keys used to verify the assertions
+    /// * `allowed_subjects`: The usernames the issuer can sign assertions for,
+    ///   `*` meaning any user
+    /// * `allowed_clients`: The clients which can exchange the assertions
+    /// * `allowed_scope`: The scopes which can be obtained with the assertions,
+    ///   or `None` to only restrict them through the policy
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn upsert(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        issuer: String,
+        jwks: JwksOrJwksUri,
+        allowed_subjects: Vec<String>,
+        allowed_clients: Vec<Ulid>,
+        allowed_scope: Option<Scope>,
+    ) -> Result<JwtBearerIssuer, Self::Error>;
+
+    /// List all JWT bearer issuers
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn all(&mut self) -> Result<Vec<JwtBearerIssuer>, Self::Error>;
+
+    /// Delete a JWT bearer issuer
+    ///
+    /// # Parameters
+    ///
+    /// * `issuer`: The issuer to delete
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails, or if the
+    /// issuer does not exist
+    async fn delete(&mut self, issuer: JwtBearerIssuer) -> Result<(), Self::Error>;
+
+    /// Record that an assertion of an issuer was exchanged, so that it can't
+    /// be replayed
+    ///
+    /// Returns `false` if an assertion with the same `jti` claim was already
+    /// recorded for this issuer
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `issuer`: The issuer which signed the assertion
+    /// * `jti`: The `jti` claim of the assertion
+    /// * `expires_at`: When the assertion expires, after which it doesn't need
+    ///   to be remembered
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn record_assertion(
+        &mut self,
+        clock: &dyn Clock,
+        issuer: &JwtBearerIssuer,
+        jti: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, Self::Error>;
+
+    /// Forget the recorded assertions which expired
+    ///
+    /// Returns the number of assertions forgotten
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to get the current time
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn cleanup_expired_assertions(&mut self, clock: &dyn Clock) -> Result<usize, Self::Error>;
+}
+
+repository_impl!(OAuth2JwtBearerIssuerRepository:
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<JwtBearerIssuer>, Self::Error>;
+
+    async fn find_by_issuer(
+        &mut self,
+        issuer: &str,
+    ) -> Result<Option<JwtBearerIssuer>, Self::Error>;
+
+    async fn upsert(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        issuer: String,
+        jwks: JwksOrJwksUri,
+        allowed_subjects: Vec<String>,
+        allowed_clients: Vec<Ulid>,
+        allowed_scope: Option<Scope>,
+    ) -> Result<JwtBearerIssuer, Self::Error>;
+
+    async fn all(&mut self) -> Result<Vec<JwtBearerIssuer>, Self::Error>;
+
+    async fn delete(&mut self, issuer: JwtBearerIssuer) -> Result<(), Self::Error>;
+
+    async fn record_assertion(
+        &mut self,
+        clock: &dyn Clock,
+        issuer: &JwtBearerIssuer,
+        jti: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, Self::Error>;
+
+    async fn cleanup_expired_assertions(&mut self, clock: &dyn Clock) -> Result<usize, Self::Error>;
+);
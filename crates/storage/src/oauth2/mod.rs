@@ -17,6 +17,7 @@
 mod access_token;
 mod authorization_grant;
 mod client;
+mod jwt_bearer_issuer;
 mod refresh_token;
 mod session;
 
@@ -24,6 +25,7 @@ pub use self::{
     access_token::OAuth2AccessTokenRepository,
     authorization_grant::OAuth2AuthorizationGrantRepository,
     client::OAuth2ClientRepository,
+    jwt_bearer_issuer::OAuth2JwtBearerIssuerRepository,
     refresh_token::OAuth2RefreshTokenRepository,
     session::{OAuth2SessionFilter, OAuth2SessionRepository},
 };
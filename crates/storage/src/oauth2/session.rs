@@ -44,8 +44,11 @@ impl OAuth2SessionState {
 pub struct OAuth2SessionFilter<'a> {
     user: Option<&'a User>,
     client: Option<&'a Client>,
+    browser_session: Option<&'a BrowserSession>,
     state: Option<OAuth2SessionState>,
     scope: Option<&'a Scope>,
+    created_after: Option<DateTime<Utc>>,
+    created_before: Option<DateTime<Utc>>,
 }
 
 impl<'a> OAuth2SessionFilter<'a> {
@@ -85,6 +88,21 @@ impl<'a> OAuth2SessionFilter<'a> {
         self.client
     }
 
+    /// List sessions started from a specific browser session
+    #[must_use]
+    pub fn for_browser_session(mut self, browser_session: &'a BrowserSession) -> Self {
+        self.browser_session = Some(browser_session);
+        self
+    }
+
+    /// Get the browser session filter
+    ///
+    /// Returns [`None`] if no browser session filter was set
+    #[must_use]
+    pub fn browser_session(&self) -> Option<&BrowserSession> {
+        self.browser_session
+    }
+
     /// Only return active sessions
     #[must_use]
     pub fn active_only(mut self) -> Self {
@@ -121,6 +139,36 @@ impl<'a> OAuth2SessionFilter<'a> {
     pub fn scope(&self) -> Option<&Scope> {
         self.scope
     }
+
+    /// Only return sessions created at or after the given time
+    #[must_use]
+    pub fn with_created_after(mut self, created_after: DateTime<Utc>) -> Self {
+        self.created_after = Some(created_after);
+        self
+    }
+
+    /// Get the lower bound of the creation time filter
+    ///
+    /// Returns [`None`] if no lower bound was set
+    #[must_use]
+    pub fn created_after(&self) -> Option<DateTime<Utc>> {
+        self.created_after
+    }
+
+    /// Only return sessions created strictly before the given time
+    #[must_use]
+    pub fn with_created_before(mut self, created_before: DateTime<Utc>) -> Self {
+        self.created_before = Some(created_before);
+        self
+    }
+
+    /// Get the upper bound of the creation time filter
+    ///
+    /// Returns [`None`] if no upper bound was set
+    #[must_use]
+    pub fn created_before(&self) -> Option<DateTime<Utc>> {
+        self.created_before
+    }
 }
 
 /// An [`OAuth2SessionRepository`] helps interacting with [`Session`]
@@ -245,6 +293,44 @@ pub trait OAuth2SessionRepository: Send + Sync {
     async fn finish(&mut self, clock: &dyn Clock, session: Session)
         -> Result<Session, Self::Error>;
 
+    /// Set the human-readable name of a [`Session`]
+    ///
+    /// This name is used as the display name of the device attached to the
+    /// session on the homeserver.
+    ///
+    /// Returns the updated [`Session`]
+    ///
+    /// # Parameters
+    ///
+    /// * `session`: The [`Session`] to update
+    /// * `human_name`: The new name of the session, or `None` to unset it
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_human_name(
+        &mut self,
+        session: Session,
+        human_name: Option<String>,
+    ) -> Result<Session, Self::Error>;
+
+    /// Set the scope of a [`Session`]
+    ///
+    /// This is used when the scope of a session is narrowed down when issuing
+    /// tokens.
+    ///
+    /// Returns the updated [`Session`]
+    ///
+    /// # Parameters
+    ///
+    /// * `session`: The [`Session`] to update
+    /// * `scope`: The new scope of the session
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_scope(&mut self, session: Session, scope: Scope) -> Result<Session, Self::Error>;
+
     /// List [`Session`]s matching the given filter and pagination parameters
     ///
     /// # Parameters
@@ -321,6 +407,14 @@ repository_impl!(OAuth2SessionRepository:
     async fn finish(&mut self, clock: &dyn Clock, session: Session)
         -> Result<Session, Self::Error>;
 
+    async fn set_human_name(
+        &mut self,
+        session: Session,
+        human_name: Option<String>,
+    ) -> Result<Session, Self::Error>;
+
+    async fn set_scope(&mut self, session: Session, scope: Scope) -> Result<Session, Self::Error>;
+
     async fn list(
         &mut self,
         filter: OAuth2SessionFilter<'_>,
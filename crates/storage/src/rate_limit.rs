@@ -0,0 +1,115 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Repository to share the state of the rate limiters between instances
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::repository_impl;
+
+/// The state of a token bucket
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitBucket {
+    /// The number of requests left in the bucket, as of `updated_at`
+    pub tokens: f64,
+
+    /// When the bucket was last updated
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A [`RateLimitRepository`] stores the token buckets of the rate limiters,
+/// identified by the name of the limiter and a key within that limiter
+#[async_trait]
+pub trait RateLimitRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// Lookup the state of a bucket, locking it until the end of the
+    /// transaction
+    ///
+    /// Returns `None` if the bucket was never used, or was cleaned up
+    ///
+    /// # Parameters
+    ///
+    /// * `limiter`: The name of the limiter
+    /// * `key`: The key of the bucket within the limiter
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn lock_bucket(
+        &mut self,
+        limiter: &str,
+        key: &str,
+    ) -> Result<Option<RateLimitBucket>, Self::Error>;
+
+    /// Save the state of a bucket
+    ///
+    /// # Parameters
+    ///
+    /// * `limiter`: The name of the limiter
+    /// * `key`: The key of the bucket within the limiter
+    /// * `bucket`: The new state of the bucket
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn save_bucket(
+        &mut self,
+        limiter: &str,
+        key: &str,
+        bucket: RateLimitBucket,
+    ) -> Result<(), Self::Error>;
+
+    /// Remove the buckets of a limiter which were not updated since the given
+    /// date
+    ///
+    /// Returns the number of buckets removed
+    ///
+    /// # Parameters
+    ///
+    /// * `limiter`: The name of the limiter
+    /// * `updated_before`: Remove the buckets last updated before this date
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn cleanup(
+        &mut self,
+        limiter: &str,
+        updated_before: DateTime<Utc>,
+    ) -> Result<usize, Self::Error>;
+}
+
+repository_impl!(RateLimitRepository:
+    async fn lock_bucket(
+        &mut self,
+        limiter: &str,
+        key: &str,
+    ) -> Result<Option<RateLimitBucket>, Self::Error>;
+
+    async fn save_bucket(
+        &mut self,
+        limiter: &str,
+        key: &str,
+        bucket: RateLimitBucket,
+    ) -> Result<(), Self::Error>;
+
+    async fn cleanup(
+        &mut self,
+        limiter: &str,
+        updated_before: DateTime<Utc>,
+    ) -> Result<usize, Self::Error>;
+);
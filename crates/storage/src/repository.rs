@@ -24,13 +24,17 @@ use crate::{
     job::JobRepository,
     oauth2::{
         OAuth2AccessTokenRepository, OAuth2AuthorizationGrantRepository, OAuth2ClientRepository,
-        OAuth2RefreshTokenRepository, OAuth2SessionRepository,
+        OAuth2JwtBearerIssuerRepository, OAuth2RefreshTokenRepository, OAuth2SessionRepository,
     },
+    rate_limit::RateLimitRepository,
     upstream_oauth2::{
         UpstreamOAuthLinkRepository, UpstreamOAuthProviderRepository,
         UpstreamOAuthSessionRepository,
     },
-    user::{BrowserSessionRepository, UserEmailRepository, UserPasswordRepository, UserRepository},
+    user::{
+        BrowserSessionRepository, UserEmailRepository, UserImpersonationRepository,
+        UserPasswordRepository, UserRepository,
+    },
     MapErr,
 };
 
@@ -151,6 +155,11 @@ pub trait RepositoryAccess: Send {
         &'c mut self,
     ) -> Box<dyn BrowserSessionRepository<Error = Self::Error> + 'c>;
 
+    /// Get an [`UserImpersonationRepository`]
+    fn user_impersonation<'c>(
+        &'c mut self,
+    ) -> Box<dyn UserImpersonationRepository<Error = Self::Error> + 'c>;
+
     /// Get a [`AppSessionRepository`]
     fn app_session<'c>(&'c mut self) -> Box<dyn AppSessionRepository<Error = Self::Error> + 'c>;
 
@@ -158,6 +167,11 @@ pub trait RepositoryAccess: Send {
     fn oauth2_client<'c>(&'c mut self)
         -> Box<dyn OAuth2ClientRepository<Error = Self::Error> + 'c>;
 
+    /// Get an [`OAuth2JwtBearerIssuerRepository`]
+    fn oauth2_jwt_bearer_issuer<'c>(
+        &'c mut self,
+    ) -> Box<dyn OAuth2JwtBearerIssuerRepository<Error = Self::Error> + 'c>;
+
     /// Get an [`OAuth2AuthorizationGrantRepository`]
     fn oauth2_authorization_grant<'c>(
         &'c mut self,
@@ -200,6 +214,9 @@ pub trait RepositoryAccess: Send {
 
     /// Get a [`JobRepository`]
     fn job<'c>(&'c mut self) -> Box<dyn JobRepository<Error = Self::Error> + 'c>;
+
+    /// Get a [`RateLimitRepository`]
+    fn rate_limit<'c>(&'c mut self) -> Box<dyn RateLimitRepository<Error = Self::Error> + 'c>;
 }
 
 /// Implementations of the [`RepositoryAccess`], [`RepositoryTransaction`] and
@@ -217,14 +234,17 @@ mod impls {
         job::JobRepository,
         oauth2::{
             OAuth2AccessTokenRepository, OAuth2AuthorizationGrantRepository,
-            OAuth2ClientRepository, OAuth2RefreshTokenRepository, OAuth2SessionRepository,
+            OAuth2ClientRepository, OAuth2JwtBearerIssuerRepository, OAuth2RefreshTokenRepository,
+            OAuth2SessionRepository,
         },
+        rate_limit::RateLimitRepository,
         upstream_oauth2::{
             UpstreamOAuthLinkRepository, UpstreamOAuthProviderRepository,
             UpstreamOAuthSessionRepository,
         },
         user::{
-            BrowserSessionRepository, UserEmailRepository, UserPasswordRepository, UserRepository,
+            BrowserSessionRepository, UserEmailRepository, UserImpersonationRepository,
+            UserPasswordRepository, UserRepository,
         },
         MapErr, Repository, RepositoryTransaction,
     };
@@ -315,6 +335,15 @@ mod impls {
             Box::new(MapErr::new(self.inner.browser_session(), &mut self.mapper))
         }
 
+        fn user_impersonation<'c>(
+            &'c mut self,
+        ) -> Box<dyn UserImpersonationRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(
+                self.inner.user_impersonation(),
+                &mut self.mapper,
+            ))
+        }
+
         fn app_session<'c>(
             &'c mut self,
         ) -> Box<dyn AppSessionRepository<Error = Self::Error> + 'c> {
@@ -327,6 +356,15 @@ mod impls {
             Box::new(MapErr::new(self.inner.oauth2_client(), &mut self.mapper))
         }
 
+        fn oauth2_jwt_bearer_issuer<'c>(
+            &'c mut self,
+        ) -> Box<dyn OAuth2JwtBearerIssuerRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(
+                self.inner.oauth2_jwt_bearer_issuer(),
+                &mut self.mapper,
+            ))
+        }
+
         fn oauth2_authorization_grant<'c>(
             &'c mut self,
         ) -> Box<dyn OAuth2AuthorizationGrantRepository<Error = Self::Error> + 'c> {
@@ -393,6 +431,10 @@ mod impls {
         fn job<'c>(&'c mut self) -> Box<dyn JobRepository<Error = Self::Error> + 'c> {
             Box::new(MapErr::new(self.inner.job(), &mut self.mapper))
         }
+
+        fn rate_limit<'c>(&'c mut self) -> Box<dyn RateLimitRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(self.inner.rate_limit(), &mut self.mapper))
+        }
     }
 
     impl<R: RepositoryAccess + ?Sized> RepositoryAccess for Box<R> {
@@ -436,6 +478,12 @@ mod impls {
             (**self).browser_session()
         }
 
+        fn user_impersonation<'c>(
+            &'c mut self,
+        ) -> Box<dyn UserImpersonationRepository<Error = Self::Error> + 'c> {
+            (**self).user_impersonation()
+        }
+
         fn app_session<'c>(
             &'c mut self,
         ) -> Box<dyn AppSessionRepository<Error = Self::Error> + 'c> {
@@ -448,6 +496,12 @@ mod impls {
             (**self).oauth2_client()
         }
 
+        fn oauth2_jwt_bearer_issuer<'c>(
+            &'c mut self,
+        ) -> Box<dyn OAuth2JwtBearerIssuerRepository<Error = Self::Error> + 'c> {
+            (**self).oauth2_jwt_bearer_issuer()
+        }
+
         fn oauth2_authorization_grant<'c>(
             &'c mut self,
         ) -> Box<dyn OAuth2AuthorizationGrantRepository<Error = Self::Error> + 'c> {
@@ -499,5 +553,9 @@ mod impls {
         fn job<'c>(&'c mut self) -> Box<dyn JobRepository<Error = Self::Error> + 'c> {
             (**self).job()
         }
+
+        fn rate_limit<'c>(&'c mut self) -> Box<dyn RateLimitRepository<Error = Self::Error> + 'c> {
+            (**self).rate_limit()
+        }
     }
 }
@@ -13,7 +13,9 @@
 // limitations under the License.
 
 use async_trait::async_trait;
-use mas_data_model::{UpstreamOAuthLink, UpstreamOAuthProvider, User};
+use chrono::{DateTime, Utc};
+use mas_data_model::{UpstreamOAuthLink, UpstreamOAuthLinkTokens, UpstreamOAuthProvider, User};
+use oauth2_types::scope::Scope;
 use rand_core::RngCore;
 use ulid::Ulid;
 
@@ -145,6 +147,108 @@ pub trait UpstreamOAuthLinkRepository: Send + Sync {
         user: &User,
     ) -> Result<(), Self::Error>;
 
+    /// Dissociate an upstream OAuth link from its user, erasing the ID tokens
+    /// and the userinfo claims received through it, the groups of the user
+    /// imported from them, and the tokens stored on it
+    ///
+    /// Returns the updated upstream OAuth link
+    ///
+    /// # Parameters
+    ///
+    /// * `upstream_oauth_link`: The upstream OAuth link to dissociate
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn dissociate_from_user(
+        &mut self,
+        upstream_oauth_link: UpstreamOAuthLink,
+    ) -> Result<UpstreamOAuthLink, Self::Error>;
+
+    /// Get the tokens stored on an upstream OAuth link
+    ///
+    /// Returns `None` if no tokens are stored on the link
+    ///
+    /// # Parameters
+    ///
+    /// * `upstream_oauth_link`: The upstream OAuth link to get the tokens of
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn tokens(
+        &mut self,
+        upstream_oauth_link: &UpstreamOAuthLink,
+    ) -> Result<Option<UpstreamOAuthLinkTokens>, Self::Error>;
+
+    /// Store the tokens issued by the provider on an upstream OAuth link,
+    /// replacing the previous ones
+    ///
+    /// # Parameters
+    ///
+    /// * `upstream_oauth_link`: The upstream OAuth link to update
+    /// * `tokens`: The tokens to store, or `None` to erase them
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_tokens(
+        &mut self,
+        upstream_oauth_link: &UpstreamOAuthLink,
+        tokens: Option<&UpstreamOAuthLinkTokens>,
+    ) -> Result<(), Self::Error>;
+
+    /// Get the scopes the user granted through an upstream OAuth link, across
+    /// all their authorizations with the provider
+    ///
+    /// Returns `None` if they were never recorded on the link
+    ///
+    /// # Parameters
+    ///
+    /// * `upstream_oauth_link`: The upstream OAuth link to get the scopes of
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn granted_scope(
+        &mut self,
+        upstream_oauth_link: &UpstreamOAuthLink,
+    ) -> Result<Option<Scope>, Self::Error>;
+
+    /// Record the scopes the user granted through an upstream OAuth link,
+    /// replacing the previous ones
+    ///
+    /// # Parameters
+    ///
+    /// * `upstream_oauth_link`: The upstream OAuth link to update
+    /// * `scope`: The granted scopes
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_granted_scope(
+        &mut self,
+        upstream_oauth_link: &UpstreamOAuthLink,
+        scope: &Scope,
+    ) -> Result<(), Self::Error>;
+
+    /// List the upstream OAuth links with a refresh token and an access token
+    /// expiring before the given time, the ones expiring first first
+    ///
+    /// # Parameters
+    ///
+    /// * `expires_before`: The time before which the access tokens expire
+    /// * `limit`: The maximum number of links to return
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn list_refreshable(
+        &mut self,
+        expires_before: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<(UpstreamOAuthLink, UpstreamOAuthLinkTokens)>, Self::Error>;
+
     /// List [`UpstreamOAuthLink`] with the given filter and pagination
     ///
     /// # Parameters
@@ -196,6 +300,39 @@ repository_impl!(UpstreamOAuthLinkRepository:
         user: &User,
     ) -> Result<(), Self::Error>;
 
+    async fn dissociate_from_user(
+        &mut self,
+        upstream_oauth_link: UpstreamOAuthLink,
+    ) -> Result<UpstreamOAuthLink, Self::Error>;
+
+    async fn tokens(
+        &mut self,
+        upstream_oauth_link: &UpstreamOAuthLink,
+    ) -> Result<Option<UpstreamOAuthLinkTokens>, Self::Error>;
+
+    async fn set_tokens(
+        &mut self,
+        upstream_oauth_link: &UpstreamOAuthLink,
+        tokens: Option<&UpstreamOAuthLinkTokens>,
+    ) -> Result<(), Self::Error>;
+
+    async fn granted_scope(
+        &mut self,
+        upstream_oauth_link: &UpstreamOAuthLink,
+    ) -> Result<Option<Scope>, Self::Error>;
+
+    async fn set_granted_scope(
+        &mut self,
+        upstream_oauth_link: &UpstreamOAuthLink,
+        scope: &Scope,
+    ) -> Result<(), Self::Error>;
+
+    async fn list_refreshable(
+        &mut self,
+        expires_before: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<(UpstreamOAuthLink, UpstreamOAuthLinkTokens)>, Self::Error>;
+
     async fn list(
         &mut self,
         filter: UpstreamOAuthLinkFilter<'_>,
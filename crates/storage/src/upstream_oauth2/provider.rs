@@ -15,11 +15,21 @@
 use std::marker::PhantomData;
 
 use async_trait::async_trait;
-use mas_data_model::{UpstreamOAuthProvider, UpstreamOAuthProviderClaimsImports};
+use chrono::{DateTime, Utc};
+use mas_data_model::{
+    UpstreamOAuthProvider, UpstreamOAuthProviderAuthorizationParams,
+    UpstreamOAuthProviderClaimsImports, UpstreamOAuthProviderEndpoints,
+    UpstreamOAuthProviderHealth, UpstreamOAuthProviderHealthCheckSettings,
+    UpstreamOAuthProviderMetadata, UpstreamOAuthProviderPkceMethod, UpstreamOAuthProviderProtocol,
+    UpstreamOAuthProviderRegistration, UpstreamOAuthProviderSamlSettings,
+    UpstreamOAuthProviderUiOptions,
+};
 use mas_iana::{jose::JsonWebSignatureAlg, oauth::OAuthClientAuthenticationMethod};
-use oauth2_types::scope::Scope;
+use mas_jose::jwk::PublicJsonWebKeySet;
+use oauth2_types::{oidc::ProviderMetadata, scope::Scope};
 use rand_core::RngCore;
 use ulid::Ulid;
+use url::Url;
 
 use crate::{pagination::Page, repository_impl, Clock, Pagination};
 
@@ -57,6 +67,22 @@ pub trait UpstreamOAuthProviderRepository: Send + Sync {
     /// Returns [`Self::Error`] if the underlying repository fails
     async fn lookup(&mut self, id: Ulid) -> Result<Option<UpstreamOAuthProvider>, Self::Error>;
 
+    /// Find an upstream OAuth provider by its slug
+    ///
+    /// Returns `None` if no provider has this slug
+    ///
+    /// # Parameters
+    ///
+    /// * `slug`: The slug of the provider to find
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn find_by_slug(
+        &mut self,
+        slug: &str,
+    ) -> Result<Option<UpstreamOAuthProvider>, Self::Error>;
+
     /// Add a new upstream OAuth provider
     ///
     /// Returns the newly created provider
@@ -66,6 +92,8 @@ pub trait UpstreamOAuthProviderRepository: Send + Sync {
     /// * `rng`: A random number generator
     /// * `clock`: The clock used to generate timestamps
     /// * `issuer`: The OIDC issuer of the provider
+    /// * `slug`: A stable, human-readable identifier of the provider, used in
+    ///   its callback URL
     /// * `scope`: The scope to request during the authorization flow
     /// * `token_endpoint_auth_method`: The token endpoint authentication method
     /// * `token_endpoint_auth_signing_alg`: The JWT signing algorithm to use
@@ -76,6 +104,22 @@ pub trait UpstreamOAuthProviderRepository: Send + Sync {
     ///   authenticating to the upstream
     /// * `claims_imports`: How claims should be imported from the upstream
     ///   provider
+    /// * `pkce_method`: Whether to use PKCE when talking to the upstream
+    ///   provider
+    /// * `authorization_params`: Parameters to add to the authorization
+    ///   requests sent to the upstream provider
+    /// * `fetch_userinfo`: Whether to query the userinfo endpoint of the
+    ///   upstream provider after the token exchange
+    /// * `ui_options`: How the provider is presented to users
+    /// * `protocol`: Whether the provider speaks OpenID Connect, plain OAuth
+    ///   2.0 or SAML 2.0
+    /// * `endpoints`: Endpoints of the provider which are configured instead of
+    ///   being discovered
+    /// * `saml`: Settings of the provider if it speaks SAML 2.0
+    /// * `store_tokens`: Whether to keep the tokens issued by the provider, to
+    ///   call its APIs on behalf of the users
+    /// * `health_check`: How the provider is checked by the background health
+    ///   probe
     ///
     /// # Errors
     ///
@@ -86,12 +130,22 @@ pub trait UpstreamOAuthProviderRepository: Send + Sync {
         rng: &mut (dyn RngCore + Send),
         clock: &dyn Clock,
         issuer: String,
+        slug: Option<String>,
         scope: Scope,
         token_endpoint_auth_method: OAuthClientAuthenticationMethod,
         token_endpoint_signing_alg: Option<JsonWebSignatureAlg>,
         client_id: String,
         encrypted_client_secret: Option<String>,
         claims_imports: UpstreamOAuthProviderClaimsImports,
+        pkce_method: UpstreamOAuthProviderPkceMethod,
+        authorization_params: UpstreamOAuthProviderAuthorizationParams,
+        fetch_userinfo: bool,
+        ui_options: UpstreamOAuthProviderUiOptions,
+        protocol: UpstreamOAuthProviderProtocol,
+        endpoints: UpstreamOAuthProviderEndpoints,
+        saml: UpstreamOAuthProviderSamlSettings,
+        store_tokens: bool,
+        health_check: UpstreamOAuthProviderHealthCheckSettings,
     ) -> Result<UpstreamOAuthProvider, Self::Error>;
 
     /// Delete an upstream OAuth provider
@@ -125,6 +179,8 @@ pub trait UpstreamOAuthProviderRepository: Send + Sync {
     /// * `clock`: The clock used to generate timestamps
     /// * `id`: The ID of the provider to update
     /// * `issuer`: The OIDC issuer of the provider
+    /// * `slug`: A stable, human-readable identifier of the provider, used in
+    ///   its callback URL
     /// * `scope`: The scope to request during the authorization flow
     /// * `token_endpoint_auth_method`: The token endpoint authentication method
     /// * `token_endpoint_auth_signing_alg`: The JWT signing algorithm to use
@@ -135,6 +191,22 @@ pub trait UpstreamOAuthProviderRepository: Send + Sync {
     ///   authenticating to the upstream
     /// * `claims_imports`: How claims should be imported from the upstream
     ///   provider
+    /// * `pkce_method`: Whether to use PKCE when talking to the upstream
+    ///   provider
+    /// * `authorization_params`: Parameters to add to the authorization
+    ///   requests sent to the upstream provider
+    /// * `fetch_userinfo`: Whether to query the userinfo endpoint of the
+    ///   upstream provider after the token exchange
+    /// * `ui_options`: How the provider is presented to users
+    /// * `protocol`: Whether the provider speaks OpenID Connect, plain OAuth
+    ///   2.0 or SAML 2.0
+    /// * `endpoints`: Endpoints of the provider which are configured instead of
+    ///   being discovered
+    /// * `saml`: Settings of the provider if it speaks SAML 2.0
+    /// * `store_tokens`: Whether to keep the tokens issued by the provider, to
+    ///   call its APIs on behalf of the users
+    /// * `health_check`: How the provider is checked by the background health
+    ///   probe
     ///
     /// # Errors
     ///
@@ -145,12 +217,22 @@ pub trait UpstreamOAuthProviderRepository: Send + Sync {
         clock: &dyn Clock,
         id: Ulid,
         issuer: String,
+        slug: Option<String>,
         scope: Scope,
         token_endpoint_auth_method: OAuthClientAuthenticationMethod,
         token_endpoint_signing_alg: Option<JsonWebSignatureAlg>,
         client_id: String,
         encrypted_client_secret: Option<String>,
         claims_imports: UpstreamOAuthProviderClaimsImports,
+        pkce_method: UpstreamOAuthProviderPkceMethod,
+        authorization_params: UpstreamOAuthProviderAuthorizationParams,
+        fetch_userinfo: bool,
+        ui_options: UpstreamOAuthProviderUiOptions,
+        protocol: UpstreamOAuthProviderProtocol,
+        endpoints: UpstreamOAuthProviderEndpoints,
+        saml: UpstreamOAuthProviderSamlSettings,
+        store_tokens: bool,
+        health_check: UpstreamOAuthProviderHealthCheckSettings,
     ) -> Result<UpstreamOAuthProvider, Self::Error>;
 
     /// List [`UpstreamOAuthProvider`] with the given filter and pagination
@@ -189,22 +271,224 @@ pub trait UpstreamOAuthProviderRepository: Send + Sync {
     ///
     /// Returns [`Self::Error`] if the underlying repository fails
     async fn all(&mut self) -> Result<Vec<UpstreamOAuthProvider>, Self::Error>;
+
+    /// Get the discovery document and the JWKS last fetched from an upstream
+    /// OAuth provider
+    ///
+    /// Returns `None` if they were never fetched
+    ///
+    /// # Parameters
+    ///
+    /// * `provider`: The provider to get the metadata of
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn metadata(
+        &mut self,
+        provider: &UpstreamOAuthProvider,
+    ) -> Result<Option<UpstreamOAuthProviderMetadata>, Self::Error>;
+
+    /// Save the discovery document and the JWKS fetched from an upstream OAuth
+    /// provider, replacing the previous ones
+    ///
+    /// Returns the saved metadata
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `provider`: The provider the metadata was fetched from
+    /// * `discovery`: The discovery document of the provider
+    /// * `jwks`: The JWKS of the provider
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_metadata(
+        &mut self,
+        clock: &dyn Clock,
+        provider: &UpstreamOAuthProvider,
+        discovery: ProviderMetadata,
+        jwks: PublicJsonWebKeySet,
+    ) -> Result<UpstreamOAuthProviderMetadata, Self::Error>;
+
+    /// Get the registration of this service with an upstream OAuth provider
+    ///
+    /// Returns `None` if the provider's credentials weren't obtained through
+    /// dynamic client registration
+    ///
+    /// # Parameters
+    ///
+    /// * `provider`: The provider to get the registration of
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn registration(
+        &mut self,
+        provider: &UpstreamOAuthProvider,
+    ) -> Result<Option<UpstreamOAuthProviderRegistration>, Self::Error>;
+
+    /// Save the credentials obtained by registering with an upstream OAuth
+    /// provider, or by updating that registration
+    ///
+    /// They replace the client ID and secret of the provider, which is
+    /// returned updated along with the registration
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `provider`: The provider this service registered with
+    /// * `client_id`: The client ID issued by the provider
+    /// * `encrypted_client_secret`: The encrypted client secret issued by the
+    ///   provider, if any
+    /// * `encrypted_registration_access_token`: The encrypted token to
+    ///   authenticate with the client configuration endpoint, if any
+    /// * `registration_client_uri`: The URL of the client configuration
+    ///   endpoint, if any
+    /// * `client_secret_expires_at`: When the client secret expires, if it does
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    #[allow(clippy::too_many_arguments)]
+    async fn set_registration(
+        &mut self,
+        clock: &dyn Clock,
+        provider: UpstreamOAuthProvider,
+        client_id: String,
+        encrypted_client_secret: Option<String>,
+        encrypted_registration_access_token: Option<String>,
+        registration_client_uri: Option<Url>,
+        client_secret_expires_at: Option<DateTime<Utc>>,
+    ) -> Result<(UpstreamOAuthProvider, UpstreamOAuthProviderRegistration), Self::Error>;
+
+    /// Forget the registration of this service with an upstream OAuth
+    /// provider, e.g. once its credentials are set in the configuration again
+    ///
+    /// The client ID and secret of the provider are left untouched
+    ///
+    /// # Parameters
+    ///
+    /// * `provider`: The provider to forget the registration with
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn remove_registration(
+        &mut self,
+        provider: &UpstreamOAuthProvider,
+    ) -> Result<(), Self::Error>;
+
+    /// Get the outcome of the last health check of an upstream OAuth provider
+    ///
+    /// Returns `None` if the provider was never checked
+    ///
+    /// # Parameters
+    ///
+    /// * `provider`: The provider to get the health of
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn health(
+        &mut self,
+        provider: &UpstreamOAuthProvider,
+    ) -> Result<Option<UpstreamOAuthProviderHealth>, Self::Error>;
+
+    /// Record the outcome of a health check of an upstream OAuth provider,
+    /// replacing the previous one
+    ///
+    /// Returns the saved health
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `provider`: The provider which was checked
+    /// * `error`: Why the provider was found unhealthy, or `None` if it is
+    ///   healthy
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_health(
+        &mut self,
+        clock: &dyn Clock,
+        provider: &UpstreamOAuthProvider,
+        error: Option<String>,
+    ) -> Result<UpstreamOAuthProviderHealth, Self::Error>;
+
+    /// Record that a logout token of a provider was received, so that it
+    /// can't be replayed
+    ///
+    /// Returns `false` if a logout token with the same `jti` claim was already
+    /// recorded for this provider
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `provider`: The provider which issued the logout token
+    /// * `jti`: The `jti` claim of the logout token
+    /// * `expires_at`: When the logout token expires, after which it doesn't
+    ///   need to be remembered
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn record_logout_token(
+        &mut self,
+        clock: &dyn Clock,
+        provider: &UpstreamOAuthProvider,
+        jti: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, Self::Error>;
+
+    /// Forget the recorded logout tokens which expired
+    ///
+    /// Returns the number of logout tokens forgotten
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to get the current time
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn cleanup_expired_logout_tokens(
+        &mut self,
+        clock: &dyn Clock,
+    ) -> Result<usize, Self::Error>;
 }
 
 repository_impl!(UpstreamOAuthProviderRepository:
     async fn lookup(&mut self, id: Ulid) -> Result<Option<UpstreamOAuthProvider>, Self::Error>;
 
+    async fn find_by_slug(
+        &mut self,
+        slug: &str,
+    ) -> Result<Option<UpstreamOAuthProvider>, Self::Error>;
+
     async fn add(
         &mut self,
         rng: &mut (dyn RngCore + Send),
         clock: &dyn Clock,
         issuer: String,
+        slug: Option<String>,
         scope: Scope,
         token_endpoint_auth_method: OAuthClientAuthenticationMethod,
         token_endpoint_signing_alg: Option<JsonWebSignatureAlg>,
         client_id: String,
         encrypted_client_secret: Option<String>,
-        claims_imports: UpstreamOAuthProviderClaimsImports
+        claims_imports: UpstreamOAuthProviderClaimsImports,
+        pkce_method: UpstreamOAuthProviderPkceMethod,
+        authorization_params: UpstreamOAuthProviderAuthorizationParams,
+        fetch_userinfo: bool,
+        ui_options: UpstreamOAuthProviderUiOptions,
+        protocol: UpstreamOAuthProviderProtocol,
+        endpoints: UpstreamOAuthProviderEndpoints,
+        saml: UpstreamOAuthProviderSamlSettings,
+        store_tokens: bool,
+        health_check: UpstreamOAuthProviderHealthCheckSettings,
     ) -> Result<UpstreamOAuthProvider, Self::Error>;
 
     async fn upsert(
@@ -212,12 +496,22 @@ repository_impl!(UpstreamOAuthProviderRepository:
         clock: &dyn Clock,
         id: Ulid,
         issuer: String,
+        slug: Option<String>,
         scope: Scope,
         token_endpoint_auth_method: OAuthClientAuthenticationMethod,
         token_endpoint_signing_alg: Option<JsonWebSignatureAlg>,
         client_id: String,
         encrypted_client_secret: Option<String>,
         claims_imports: UpstreamOAuthProviderClaimsImports,
+        pkce_method: UpstreamOAuthProviderPkceMethod,
+        authorization_params: UpstreamOAuthProviderAuthorizationParams,
+        fetch_userinfo: bool,
+        ui_options: UpstreamOAuthProviderUiOptions,
+        protocol: UpstreamOAuthProviderProtocol,
+        endpoints: UpstreamOAuthProviderEndpoints,
+        saml: UpstreamOAuthProviderSamlSettings,
+        store_tokens: bool,
+        health_check: UpstreamOAuthProviderHealthCheckSettings,
     ) -> Result<UpstreamOAuthProvider, Self::Error>;
 
     async fn delete(&mut self, provider: UpstreamOAuthProvider) -> Result<(), Self::Error>;
@@ -236,4 +530,63 @@ repository_impl!(UpstreamOAuthProviderRepository:
     ) -> Result<usize, Self::Error>;
 
     async fn all(&mut self) -> Result<Vec<UpstreamOAuthProvider>, Self::Error>;
+
+    async fn metadata(
+        &mut self,
+        provider: &UpstreamOAuthProvider,
+    ) -> Result<Option<UpstreamOAuthProviderMetadata>, Self::Error>;
+
+    async fn set_metadata(
+        &mut self,
+        clock: &dyn Clock,
+        provider: &UpstreamOAuthProvider,
+        discovery: ProviderMetadata,
+        jwks: PublicJsonWebKeySet,
+    ) -> Result<UpstreamOAuthProviderMetadata, Self::Error>;
+
+    async fn registration(
+        &mut self,
+        provider: &UpstreamOAuthProvider,
+    ) -> Result<Option<UpstreamOAuthProviderRegistration>, Self::Error>;
+
+    async fn set_registration(
+        &mut self,
+        clock: &dyn Clock,
+        provider: UpstreamOAuthProvider,
+        client_id: String,
+        encrypted_client_secret: Option<String>,
+        encrypted_registration_access_token: Option<String>,
+        registration_client_uri: Option<Url>,
+        client_secret_expires_at: Option<DateTime<Utc>>,
+    ) -> Result<(UpstreamOAuthProvider, UpstreamOAuthProviderRegistration), Self::Error>;
+
+    async fn remove_registration(
+        &mut self,
+        provider: &UpstreamOAuthProvider,
+    ) -> Result<(), Self::Error>;
+
+    async fn health(
+        &mut self,
+        provider: &UpstreamOAuthProvider,
+    ) -> Result<Option<UpstreamOAuthProviderHealth>, Self::Error>;
+
+    async fn set_health(
+        &mut self,
+        clock: &dyn Clock,
+        provider: &UpstreamOAuthProvider,
+        error: Option<String>,
+    ) -> Result<UpstreamOAuthProviderHealth, Self::Error>;
+
+    async fn record_logout_token(
+        &mut self,
+        clock: &dyn Clock,
+        provider: &UpstreamOAuthProvider,
+        jti: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, Self::Error>;
+
+    async fn cleanup_expired_logout_tokens(
+        &mut self,
+        clock: &dyn Clock,
+    ) -> Result<usize, Self::Error>;
 );
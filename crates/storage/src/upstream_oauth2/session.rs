@@ -82,6 +82,10 @@ pub trait UpstreamOAuthSessionRepository: Send + Sync {
     /// * `upstream_oauth_link`: the link to associate with the session
     /// * `id_token`: the ID token returned by the upstream OAuth provider, if
     ///   present
+    /// * `sid`: the session ID of the upstream OAuth provider, as found in the
+    ///   `sid` claim of the ID token, if present
+    /// * `userinfo`: the claims returned by the userinfo endpoint of the
+    ///   upstream OAuth provider, if it was queried
     ///
     /// # Errors
     ///
@@ -92,6 +96,8 @@ pub trait UpstreamOAuthSessionRepository: Send + Sync {
         upstream_oauth_authorization_session: UpstreamOAuthAuthorizationSession,
         upstream_oauth_link: &UpstreamOAuthLink,
         id_token: Option<String>,
+        sid: Option<String>,
+        userinfo: Option<serde_json::Value>,
     ) -> Result<UpstreamOAuthAuthorizationSession, Self::Error>;
 
     /// Mark a session as consumed
@@ -135,6 +141,8 @@ repository_impl!(UpstreamOAuthSessionRepository:
         upstream_oauth_authorization_session: UpstreamOAuthAuthorizationSession,
         upstream_oauth_link: &UpstreamOAuthLink,
         id_token: Option<String>,
+        sid: Option<String>,
+        userinfo: Option<serde_json::Value>,
     ) -> Result<UpstreamOAuthAuthorizationSession, Self::Error>;
 
     async fn consume(
@@ -13,11 +13,21 @@
 // limitations under the License.
 
 use async_trait::async_trait;
-use mas_data_model::{User, UserEmail, UserEmailVerification};
+use chrono::{DateTime, Utc};
+use mas_data_model::{
+    EmailRateLimited, EmailRateLimits, EmailSendCounts, User, UserEmail, UserEmailChange,
+    UserEmailVerification,
+};
 use rand_core::RngCore;
 use ulid::Ulid;
 
-use crate::{pagination::Page, repository_impl, Clock, Pagination};
+use crate::{
+    job::{FinishEmailChangeJob, JobRepositoryExt, SendEmailChangeNotificationJob, VerifyEmailJob},
+    pagination::Page,
+    repository_impl,
+    user::UserRepository,
+    Clock, Pagination, RepositoryAccess,
+};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum UserEmailState {
@@ -41,6 +51,7 @@ impl UserEmailState {
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
 pub struct UserEmailFilter<'a> {
     user: Option<&'a User>,
+    email: Option<&'a str>,
     state: Option<UserEmailState>,
 }
 
@@ -66,6 +77,21 @@ impl<'a> UserEmailFilter<'a> {
         self.user
     }
 
+    /// Filter for a specific email address
+    #[must_use]
+    pub fn for_email(mut self, email: &'a str) -> Self {
+        self.email = Some(email);
+        self
+    }
+
+    /// Get the email filter
+    ///
+    /// Returns [`None`] if no email filter is set
+    #[must_use]
+    pub fn email(&self) -> Option<&str> {
+        self.email
+    }
+
     /// Filter for emails that are verified
     #[must_use]
     pub fn verified_only(mut self) -> Self {
@@ -198,6 +224,10 @@ pub trait UserEmailRepository: Send + Sync {
 
     /// Delete a [`UserEmail`]
     ///
+    /// The pending changes to this address are removed with it. This fails if
+    /// the address is the old address of a [`UserEmailChange`] which was
+    /// verified and is neither reverted nor finished.
+    ///
     /// # Parameters
     ///
     /// * `user_email`: The [`UserEmail`] to delete
@@ -297,6 +327,220 @@ pub trait UserEmailRepository: Send + Sync {
         clock: &dyn Clock,
         verification: UserEmailVerification,
     ) -> Result<UserEmailVerification, Self::Error>;
+
+    /// Start changing the primary email address of a user
+    ///
+    /// Returns the newly created [`UserEmailChange`]
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: The random number generator to use
+    /// * `clock`: The clock to use
+    /// * `old_user_email`: The current primary [`UserEmail`]
+    /// * `new_user_email`: The [`UserEmail`] which should become primary
+    /// * `revert_token`: The token used to revert the change from the old
+    ///   address
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn add_change(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        old_user_email: &UserEmail,
+        new_user_email: &UserEmail,
+        revert_token: String,
+    ) -> Result<UserEmailChange, Self::Error>;
+
+    /// Lookup a [`UserEmailChange`] by its ID
+    ///
+    /// Returns `None` if no [`UserEmailChange`] was found
+    ///
+    /// # Parameters
+    ///
+    /// * `id`: The ID of the [`UserEmailChange`] to lookup
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn lookup_change(&mut self, id: Ulid) -> Result<Option<UserEmailChange>, Self::Error>;
+
+    /// Find a [`UserEmailChange`] by its revert token
+    ///
+    /// Returns `None` if no [`UserEmailChange`] was found
+    ///
+    /// # Parameters
+    ///
+    /// * `revert_token`: The token sent to the old address
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn find_change_by_revert_token(
+        &mut self,
+        revert_token: &str,
+    ) -> Result<Option<UserEmailChange>, Self::Error>;
+
+    /// Find the latest pending [`UserEmailChange`] to the given address
+    ///
+    /// Returns `None` if no pending [`UserEmailChange`] was found
+    ///
+    /// # Parameters
+    ///
+    /// * `new_user_email`: The [`UserEmail`] which should become primary
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn find_pending_change(
+        &mut self,
+        new_user_email: &UserEmail,
+    ) -> Result<Option<UserEmailChange>, Self::Error>;
+
+    /// Find the [`UserEmailChange`] of a [`User`] which is in progress, that
+    /// is waiting for the old address to confirm it or still revertable
+    ///
+    /// Returns `None` if no [`UserEmailChange`] is in progress
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock to use
+    /// * `user`: The [`User`] for whom to lookup the [`UserEmailChange`]
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn find_change_in_progress(
+        &mut self,
+        clock: &dyn Clock,
+        user: &User,
+    ) -> Result<Option<UserEmailChange>, Self::Error>;
+
+    /// Mark a [`UserEmailChange`] as verified, once the new address was
+    /// verified. The old address then has to confirm the change.
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock to use
+    /// * `change`: The [`UserEmailChange`] to mark as verified
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn verify_change(
+        &mut self,
+        clock: &dyn Clock,
+        change: UserEmailChange,
+    ) -> Result<UserEmailChange, Self::Error>;
+
+    /// Mark a [`UserEmailChange`] as completed, once the old address confirmed
+    /// it
+    ///
+    /// This does not change the primary email of the user, use
+    /// [`UserEmailRepository::set_as_primary`] for that.
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock to use
+    /// * `change`: The [`UserEmailChange`] to complete
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn complete_change(
+        &mut self,
+        clock: &dyn Clock,
+        change: UserEmailChange,
+    ) -> Result<UserEmailChange, Self::Error>;
+
+    /// Mark a [`UserEmailChange`] as reverted
+    ///
+    /// This does not change the primary email of the user, use
+    /// [`UserEmailRepository::set_as_primary`] for that.
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock to use
+    /// * `change`: The [`UserEmailChange`] to revert
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn revert_change(
+        &mut self,
+        clock: &dyn Clock,
+        change: UserEmailChange,
+    ) -> Result<UserEmailChange, Self::Error>;
+
+    /// Mark a [`UserEmailChange`] as finished, once its grace period is over
+    ///
+    /// The old address can be removed after that.
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock to use
+    /// * `change`: The [`UserEmailChange`] to finish
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn finish_change(
+        &mut self,
+        clock: &dyn Clock,
+        change: UserEmailChange,
+    ) -> Result<UserEmailChange, Self::Error>;
+
+    /// Record that a verification email is being sent to a [`UserEmail`], so
+    /// that it can be rate limited
+    ///
+    /// The record is kept even if the [`UserEmail`] is removed afterwards.
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: The random number generator to use
+    /// * `clock`: The clock to use
+    /// * `user_email`: The [`UserEmail`] the email is sent to
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn record_send(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user_email: &UserEmail,
+    ) -> Result<(), Self::Error>;
+
+    /// Lock the verification emails sent to the address of a [`UserEmail`]
+    /// and for its user until the end of the transaction, so that concurrent
+    /// sends are counted one after the other
+    ///
+    /// # Parameters
+    ///
+    /// * `user_email`: The [`UserEmail`] the email is about to be sent to
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn lock_sends(&mut self, user_email: &UserEmail) -> Result<(), Self::Error>;
+
+    /// Count the verification emails sent to the address of a [`UserEmail`]
+    /// and for its user since the given time
+    ///
+    /// # Parameters
+    ///
+    /// * `user_email`: The [`UserEmail`] to count the emails for
+    /// * `since`: The start of the counting window
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn count_sends(
+        &mut self,
+        user_email: &UserEmail,
+        since: DateTime<Utc>,
+    ) -> Result<EmailSendCounts, Self::Error>;
 }
 
 repository_impl!(UserEmailRepository:
@@ -350,4 +594,248 @@ repository_impl!(UserEmailRepository:
         clock: &dyn Clock,
         verification: UserEmailVerification,
     ) -> Result<UserEmailVerification, Self::Error>;
+
+    async fn add_change(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        old_user_email: &UserEmail,
+        new_user_email: &UserEmail,
+        revert_token: String,
+    ) -> Result<UserEmailChange, Self::Error>;
+
+    async fn lookup_change(&mut self, id: Ulid) -> Result<Option<UserEmailChange>, Self::Error>;
+
+    async fn find_change_by_revert_token(
+        &mut self,
+        revert_token: &str,
+    ) -> Result<Option<UserEmailChange>, Self::Error>;
+
+    async fn find_pending_change(
+        &mut self,
+        new_user_email: &UserEmail,
+    ) -> Result<Option<UserEmailChange>, Self::Error>;
+
+    async fn find_change_in_progress(
+        &mut self,
+        clock: &dyn Clock,
+        user: &User,
+    ) -> Result<Option<UserEmailChange>, Self::Error>;
+
+    async fn verify_change(
+        &mut self,
+        clock: &dyn Clock,
+        change: UserEmailChange,
+    ) -> Result<UserEmailChange, Self::Error>;
+
+    async fn complete_change(
+        &mut self,
+        clock: &dyn Clock,
+        change: UserEmailChange,
+    ) -> Result<UserEmailChange, Self::Error>;
+
+    async fn revert_change(
+        &mut self,
+        clock: &dyn Clock,
+        change: UserEmailChange,
+    ) -> Result<UserEmailChange, Self::Error>;
+
+    async fn finish_change(
+        &mut self,
+        clock: &dyn Clock,
+        change: UserEmailChange,
+    ) -> Result<UserEmailChange, Self::Error>;
+
+    async fn record_send(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user_email: &UserEmail,
+    ) -> Result<(), Self::Error>;
+
+    async fn lock_sends(&mut self, user_email: &UserEmail) -> Result<(), Self::Error>;
+
+    async fn count_sends(
+        &mut self,
+        user_email: &UserEmail,
+        since: DateTime<Utc>,
+    ) -> Result<EmailSendCounts, Self::Error>;
 );
+
+/// Schedule a job to send a verification code to a [`UserEmail`], unless too
+/// many were sent to its address or for its user recently
+///
+/// The sends are locked while they are counted, so that concurrent requests
+/// can't go over the limits.
+///
+/// Returns which limit was hit if the email was not sent
+///
+/// # Parameters
+///
+/// * `repo`: The repository to use
+/// * `rng`: The random number generator to use
+/// * `clock`: The clock to use
+/// * `limits`: The limits on the verification emails
+/// * `user_email`: The [`UserEmail`] to send the code to
+/// * `language`: The language to use for the email
+///
+/// # Errors
+///
+/// Returns an error if the underlying repository fails
+pub async fn send_verification_email<R>(
+    repo: &mut R,
+    rng: &mut (dyn RngCore + Send),
+    clock: &dyn Clock,
+    limits: &EmailRateLimits,
+    user_email: &UserEmail,
+    language: Option<&str>,
+) -> Result<Result<(), EmailRateLimited>, R::Error>
+where
+    R: RepositoryAccess + ?Sized,
+{
+    repo.user_email().lock_sends(user_email).await?;
+
+    let now = clock.now();
+    let counts = repo
+        .user_email()
+        .count_sends(user_email, limits.window_start(now))
+        .await?;
+    if let Err(e) = limits.check(now, &counts) {
+        return Ok(Err(e));
+    }
+
+    repo.user_email()
+        .record_send(rng, clock, user_email)
+        .await?;
+
+    let mut job = VerifyEmailJob::new(user_email);
+    if let Some(language) = language {
+        job = job.with_language(language.to_owned());
+    }
+    repo.job().schedule_job(job).await?;
+
+    Ok(Ok(()))
+}
+
+/// Move a pending change of the primary email address of a user forward, once
+/// its new address was verified
+///
+/// The old address is asked to confirm the change, unless `skip_confirmation`
+/// is set, in which case the switch-over happens right away. Nothing happens if
+/// the address was not meant to replace the primary one, or if another change
+/// is already in progress for the user.
+///
+/// Returns the [`UserEmailChange`] if it moved forward
+///
+/// # Parameters
+///
+/// * `repo`: The repository to use
+/// * `clock`: The clock to use
+/// * `new_user_email`: The [`UserEmail`] which was just verified
+/// * `skip_confirmation`: Whether to switch over without asking the old
+///   address
+/// * `language`: The language to use for the emails sent to the old address
+///
+/// # Errors
+///
+/// Returns an error if the underlying repository fails
+pub async fn continue_email_change<R>(
+    repo: &mut R,
+    clock: &dyn Clock,
+    new_user_email: &UserEmail,
+    skip_confirmation: bool,
+    language: Option<&str>,
+) -> Result<Option<UserEmailChange>, R::Error>
+where
+    R: RepositoryAccess + ?Sized,
+{
+    let Some(change) = repo.user_email().find_pending_change(new_user_email).await? else {
+        return Ok(None);
+    };
+
+    if change.verified_at.is_some() {
+        // The old address was already asked to confirm it
+        return Ok(None);
+    }
+
+    let Some(user) = repo.user().lookup(change.user_id).await? else {
+        return Ok(None);
+    };
+
+    if repo
+        .user_email()
+        .find_change_in_progress(clock, &user)
+        .await?
+        .is_some()
+    {
+        return Ok(None);
+    }
+
+    let change = repo.user_email().verify_change(clock, change).await?;
+
+    if skip_confirmation {
+        let change = complete_email_change(repo, clock, change, new_user_email, language).await?;
+        return Ok(Some(change));
+    }
+
+    let mut job = SendEmailChangeNotificationJob::new(&change);
+    if let Some(language) = language {
+        job = job.with_language(language.to_owned());
+    }
+    repo.job().schedule_job(job).await?;
+
+    // Cancel the change if the old address doesn't confirm it in time
+    if let Some(confirmable_until) = change.confirmable_until() {
+        repo.job()
+            .schedule_job_at(FinishEmailChangeJob::new(&change), confirmable_until)
+            .await?;
+    }
+
+    Ok(Some(change))
+}
+
+/// Switch the primary email address of a user over to the new address of a
+/// change, and let the old address know so that the change can be reverted
+/// until the end of the grace period
+///
+/// Returns the completed [`UserEmailChange`]
+///
+/// # Parameters
+///
+/// * `repo`: The repository to use
+/// * `clock`: The clock to use
+/// * `change`: The verified [`UserEmailChange`] to complete
+/// * `new_user_email`: The [`UserEmail`] which becomes primary
+/// * `language`: The language to use for the email sent to the old address
+///
+/// # Errors
+///
+/// Returns an error if the underlying repository fails
+pub async fn complete_email_change<R>(
+    repo: &mut R,
+    clock: &dyn Clock,
+    change: UserEmailChange,
+    new_user_email: &UserEmail,
+    language: Option<&str>,
+) -> Result<UserEmailChange, R::Error>
+where
+    R: RepositoryAccess + ?Sized,
+{
+    repo.user_email().set_as_primary(new_user_email).await?;
+    let change = repo.user_email().complete_change(clock, change).await?;
+
+    let mut job = SendEmailChangeNotificationJob::new(&change);
+    if let Some(language) = language {
+        job = job.with_language(language.to_owned());
+    }
+    repo.job().schedule_job(job).await?;
+
+    // Remove the old address once the change can't be reverted anymore
+    if let Some(revertable_until) = change.revertable_until() {
+        repo.job()
+            .schedule_job_at(FinishEmailChangeJob::new(&change), revertable_until)
+            .await?;
+    }
+
+    Ok(change)
+}
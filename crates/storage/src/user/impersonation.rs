@@ -0,0 +1,118 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use mas_data_model::{BrowserSession, User, UserImpersonation};
+use rand_core::RngCore;
+
+use crate::{repository_impl, Clock};
+
+/// A [`UserImpersonationRepository`] helps interacting with the
+/// [`UserImpersonation`] audit records saved in the storage backend
+#[async_trait]
+pub trait UserImpersonationRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// Find the [`UserImpersonation`] of an impersonation session
+    ///
+    /// Returns `None` if the session is not an impersonation session
+    ///
+    /// # Parameters
+    ///
+    /// * `user_session`: The impersonation session
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn find_by_session(
+        &mut self,
+        user_session: &BrowserSession,
+    ) -> Result<Option<UserImpersonation>, Self::Error>;
+
+    /// List the [`UserImpersonation`]s of a user, most recent first
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The impersonated user
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn list_for_user(&mut self, user: &User) -> Result<Vec<UserImpersonation>, Self::Error>;
+
+    /// Record that an administrator started impersonating a user
+    ///
+    /// Returns the newly created [`UserImpersonation`]
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: The random number generator to use
+    /// * `clock`: The clock used to generate timestamps
+    /// * `impersonator`: The administrator impersonating the user
+    /// * `user_session`: The impersonation session, which tells which user is
+    ///   impersonated and until when
+    /// * `reason`: Why the administrator impersonates the user
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        impersonator: &User,
+        user_session: &BrowserSession,
+        reason: String,
+    ) -> Result<UserImpersonation, Self::Error>;
+
+    /// Record that an impersonation session was ended before it expired
+    ///
+    /// Does nothing if the session is not an impersonation session
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `user_session`: The impersonation session which was ended
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn end(
+        &mut self,
+        clock: &dyn Clock,
+        user_session: &BrowserSession,
+    ) -> Result<(), Self::Error>;
+}
+
+repository_impl!(UserImpersonationRepository:
+    async fn find_by_session(
+        &mut self,
+        user_session: &BrowserSession,
+    ) -> Result<Option<UserImpersonation>, Self::Error>;
+    async fn list_for_user(&mut self, user: &User) -> Result<Vec<UserImpersonation>, Self::Error>;
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        impersonator: &User,
+        user_session: &BrowserSession,
+        reason: String,
+    ) -> Result<UserImpersonation, Self::Error>;
+    async fn end(
+        &mut self,
+        clock: &dyn Clock,
+        user_session: &BrowserSession,
+    ) -> Result<(), Self::Error>;
+);
@@ -15,18 +15,25 @@
 //! Repositories to interact with entities related to user accounts
 
 use async_trait::async_trait;
-use mas_data_model::User;
+use chrono::{DateTime, Utc};
+use mas_data_model::{ProfileAttribute, SecurityNotification, User};
 use rand_core::RngCore;
 use ulid::Ulid;
+use url::Url;
 
 use crate::{repository_impl, Clock};
 
 mod email;
+mod impersonation;
 mod password;
 mod session;
 
 pub use self::{
-    email::{UserEmailFilter, UserEmailRepository},
+    email::{
+        complete_email_change, continue_email_change, send_verification_email, UserEmailFilter,
+        UserEmailRepository,
+    },
+    impersonation::UserImpersonationRepository,
     password::UserPasswordRepository,
     session::{BrowserSessionFilter, BrowserSessionRepository},
 };
@@ -84,6 +91,26 @@ pub trait UserRepository: Send + Sync {
         username: String,
     ) -> Result<User, Self::Error>;
 
+    /// Create a new guest [`User`]
+    ///
+    /// Returns the newly created [`User`]
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: A random number generator to generate the [`User`] ID
+    /// * `clock`: The clock used to generate timestamps
+    /// * `username`: The username of the [`User`]
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn add_guest(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        username: String,
+    ) -> Result<User, Self::Error>;
+
     /// Check if a [`User`] exists
     ///
     /// Returns `true` if the [`User`] exists, `false` otherwise
@@ -124,6 +151,33 @@ pub trait UserRepository: Send + Sync {
     /// Returns [`Self::Error`] if the underlying repository fails
     async fn unlock(&mut self, user: User) -> Result<User, Self::Error>;
 
+    /// Quarantine a [`User`]
+    ///
+    /// Returns the quarantined [`User`]
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `user`: The [`User`] to quarantine
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn quarantine(&mut self, clock: &dyn Clock, user: User) -> Result<User, Self::Error>;
+
+    /// Lift the quarantine of a [`User`]
+    ///
+    /// Returns the updated [`User`]
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] to lift the quarantine of
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn unquarantine(&mut self, user: User) -> Result<User, Self::Error>;
+
     /// Set whether a [`User`] can request admin
     ///
     /// Returns the [`User`] with the new `can_request_admin` value
@@ -140,6 +194,172 @@ pub trait UserRepository: Send + Sync {
         user: User,
         can_request_admin: bool,
     ) -> Result<User, Self::Error>;
+
+    /// Set the preferred locale of a [`User`]
+    ///
+    /// Returns the [`User`] with the new `locale` value
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] to update
+    /// * `locale`: The BCP 47 language tag of the locale, or `None` to unset it
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_locale(&mut self, user: User, locale: Option<String>)
+        -> Result<User, Self::Error>;
+
+    /// Set the groups of a [`User`], as imported from an upstream provider
+    ///
+    /// Returns the [`User`] with the new `upstream_groups` value
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] to update
+    /// * `groups`: The groups of the user, replacing the previous ones
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_upstream_groups(
+        &mut self,
+        user: User,
+        groups: Vec<String>,
+    ) -> Result<User, Self::Error>;
+
+    /// Upgrade a guest [`User`] to a full account
+    ///
+    /// Returns the upgraded [`User`]
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The guest [`User`] to upgrade
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn upgrade_guest(&mut self, user: User) -> Result<User, Self::Error>;
+
+    /// List the guest [`User`]s which were created before the given date and
+    /// which are not locked yet, oldest first
+    ///
+    /// # Parameters
+    ///
+    /// * `created_before`: Only list guests created before this date
+    /// * `limit`: The maximum number of guests to return
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn list_expired_guests(
+        &mut self,
+        created_before: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<User>, Self::Error>;
+
+    /// Get the categories of security notifications a [`User`] opted out of
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] to get the opt-outs of
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn security_notification_opt_outs(
+        &mut self,
+        user: &User,
+    ) -> Result<Vec<SecurityNotification>, Self::Error>;
+
+    /// Set whether a [`User`] opted out of a category of security
+    /// notifications
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] to update
+    /// * `category`: The category of security notifications
+    /// * `opt_out`: Whether the user should stop receiving them
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_security_notification_opt_out(
+        &mut self,
+        user: &User,
+        category: SecurityNotification,
+        opt_out: bool,
+    ) -> Result<(), Self::Error>;
+
+    /// Get the profile attributes a [`User`] changed themselves
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] to get the modified attributes of
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn modified_profile_attributes(
+        &mut self,
+        user: &User,
+    ) -> Result<Vec<ProfileAttribute>, Self::Error>;
+
+    /// Record that a [`User`] changed one of their profile attributes
+    /// themselves, so that it stops being imported from upstream providers
+    /// which only import it until then
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] who changed the attribute
+    /// * `attribute`: The attribute they changed
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn mark_profile_attribute_modified(
+        &mut self,
+        user: &User,
+        attribute: ProfileAttribute,
+    ) -> Result<(), Self::Error>;
+
+    /// Record that a [`User`] accepted the terms of service at the given URL
+    ///
+    /// Accepting the same terms twice is a no-op.
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: The random number generator to use
+    /// * `clock`: The clock used to generate timestamps
+    /// * `user`: The [`User`] who accepted the terms
+    /// * `terms_url`: The URL of the terms of service
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn accept_terms(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        terms_url: Url,
+    ) -> Result<(), Self::Error>;
+
+    /// Check whether a [`User`] accepted the terms of service at the given URL
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] to check
+    /// * `terms_url`: The URL of the terms of service
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn has_accepted_terms(
+        &mut self,
+        user: &User,
+        terms_url: &Url,
+    ) -> Result<bool, Self::Error>;
 }
 
 repository_impl!(UserRepository:
@@ -151,12 +371,61 @@ repository_impl!(UserRepository:
         clock: &dyn Clock,
         username: String,
     ) -> Result<User, Self::Error>;
+    async fn add_guest(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        username: String,
+    ) -> Result<User, Self::Error>;
     async fn exists(&mut self, username: &str) -> Result<bool, Self::Error>;
     async fn lock(&mut self, clock: &dyn Clock, user: User) -> Result<User, Self::Error>;
     async fn unlock(&mut self, user: User) -> Result<User, Self::Error>;
+    async fn quarantine(&mut self, clock: &dyn Clock, user: User) -> Result<User, Self::Error>;
+    async fn unquarantine(&mut self, user: User) -> Result<User, Self::Error>;
     async fn set_can_request_admin(
         &mut self,
         user: User,
         can_request_admin: bool,
     ) -> Result<User, Self::Error>;
+    async fn set_locale(&mut self, user: User, locale: Option<String>)
+        -> Result<User, Self::Error>;
+    async fn set_upstream_groups(
+        &mut self,
+        user: User,
+        groups: Vec<String>,
+    ) -> Result<User, Self::Error>;
+    async fn upgrade_guest(&mut self, user: User) -> Result<User, Self::Error>;
+    async fn list_expired_guests(
+        &mut self,
+        created_before: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<User>, Self::Error>;
+    async fn security_notification_opt_outs(
+        &mut self,
+        user: &User,
+    ) -> Result<Vec<SecurityNotification>, Self::Error>;
+    async fn set_security_notification_opt_out(
+        &mut self,
+        user: &User,
+        category: SecurityNotification,
+        opt_out: bool,
+    ) -> Result<(), Self::Error>;
+    async fn modified_profile_attributes(
+        &mut self,
+        user: &User,
+    ) -> Result<Vec<ProfileAttribute>, Self::Error>;
+    async fn mark_profile_attribute_modified(
+        &mut self,
+        user: &User,
+        attribute: ProfileAttribute,
+    ) -> Result<(), Self::Error>;
+    async fn accept_terms(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        terms_url: Url,
+    ) -> Result<(), Self::Error>;
+    async fn has_accepted_terms(&mut self, user: &User, terms_url: &Url)
+        -> Result<bool, Self::Error>;
 );
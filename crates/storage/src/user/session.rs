@@ -15,9 +15,10 @@
 use std::net::IpAddr;
 
 use async_trait::async_trait;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use mas_data_model::{
-    Authentication, BrowserSession, Password, UpstreamOAuthAuthorizationSession, User,
+    Authentication, BrowserSession, Password, UpstreamOAuthAuthorizationSession, UpstreamOAuthLink,
+    UpstreamOAuthProvider, User,
 };
 use rand_core::RngCore;
 use ulid::Ulid;
@@ -45,6 +46,9 @@ impl BrowserSessionState {
 pub struct BrowserSessionFilter<'a> {
     user: Option<&'a User>,
     state: Option<BrowserSessionState>,
+    upstream_oauth_provider: Option<&'a UpstreamOAuthProvider>,
+    upstream_oauth_link: Option<&'a UpstreamOAuthLink>,
+    upstream_oauth_sid: Option<&'a str>,
 }
 
 impl<'a> BrowserSessionFilter<'a> {
@@ -86,6 +90,55 @@ impl<'a> BrowserSessionFilter<'a> {
     pub fn state(&self) -> Option<BrowserSessionState> {
         self.state
     }
+
+    /// Only return browser sessions authenticated through the given upstream
+    /// OAuth provider
+    #[must_use]
+    pub fn authenticated_by_upstream_oauth_provider(
+        mut self,
+        upstream_oauth_provider: &'a UpstreamOAuthProvider,
+    ) -> Self {
+        self.upstream_oauth_provider = Some(upstream_oauth_provider);
+        self
+    }
+
+    /// Get the upstream OAuth provider filter
+    #[must_use]
+    pub fn upstream_oauth_provider(&self) -> Option<&UpstreamOAuthProvider> {
+        self.upstream_oauth_provider
+    }
+
+    /// Only return browser sessions authenticated through the given upstream
+    /// OAuth link
+    #[must_use]
+    pub fn authenticated_by_upstream_oauth_link(
+        mut self,
+        upstream_oauth_link: &'a UpstreamOAuthLink,
+    ) -> Self {
+        self.upstream_oauth_link = Some(upstream_oauth_link);
+        self
+    }
+
+    /// Get the upstream OAuth link filter
+    #[must_use]
+    pub fn upstream_oauth_link(&self) -> Option<&UpstreamOAuthLink> {
+        self.upstream_oauth_link
+    }
+
+    /// Only return browser sessions authenticated through an upstream session
+    /// with the given session ID, as found in the `sid` claim of the upstream
+    /// ID token
+    #[must_use]
+    pub fn authenticated_by_upstream_oauth_sid(mut self, sid: &'a str) -> Self {
+        self.upstream_oauth_sid = Some(sid);
+        self
+    }
+
+    /// Get the upstream session ID filter
+    #[must_use]
+    pub fn upstream_oauth_sid(&self) -> Option<&str> {
+        self.upstream_oauth_sid
+    }
 }
 
 /// A [`BrowserSessionRepository`] helps interacting with [`BrowserSession`]
@@ -130,6 +183,33 @@ pub trait BrowserSessionRepository: Send + Sync {
         user_agent: Option<String>,
     ) -> Result<BrowserSession, Self::Error>;
 
+    /// Create a new [`BrowserSession`] for a [`User`], on behalf of an
+    /// administrator impersonating them
+    ///
+    /// Returns the newly created [`BrowserSession`]
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: The random number generator to use
+    /// * `clock`: The clock used to generate timestamps
+    /// * `user`: The user to create the session for
+    /// * `impersonator`: The administrator impersonating the user
+    /// * `ttl`: How long the session should be valid for
+    /// * `user_agent`: If available, the user agent of the browser
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn add_impersonation(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        impersonator: &User,
+        ttl: Duration,
+        user_agent: Option<String>,
+    ) -> Result<BrowserSession, Self::Error>;
+
     /// Finish a [`BrowserSession`]
     ///
     /// Returns the finished session
@@ -256,6 +336,15 @@ repository_impl!(BrowserSessionRepository:
         user: &User,
         user_agent: Option<String>,
     ) -> Result<BrowserSession, Self::Error>;
+    async fn add_impersonation(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        impersonator: &User,
+        ttl: Duration,
+        user_agent: Option<String>,
+    ) -> Result<BrowserSession, Self::Error>;
     async fn finish(
         &mut self,
         clock: &dyn Clock,
@@ -26,7 +26,13 @@ use apalis_core::{
 };
 use apalis_cron::CronStream;
 use chrono::{DateTime, Utc};
-use mas_storage::{oauth2::OAuth2AccessTokenRepository, RepositoryAccess};
+use mas_storage::{
+    job::{DeactivateUserJob, JobRepositoryExt},
+    oauth2::{OAuth2AccessTokenRepository, OAuth2JwtBearerIssuerRepository},
+    upstream_oauth2::UpstreamOAuthProviderRepository,
+    user::UserRepository,
+    Clock, RepositoryAccess,
+};
 use tracing::{debug, info};
 
 use crate::{
@@ -62,6 +68,14 @@ pub async fn cleanup_expired_tokens(
     let mut repo = state.repository().await?;
 
     let count = repo.oauth2_access_token().cleanup_expired(&clock).await?;
+    let assertions = repo
+        .oauth2_jwt_bearer_issuer()
+        .cleanup_expired_assertions(&clock)
+        .await?;
+    let logout_tokens = repo
+        .upstream_oauth_provider()
+        .cleanup_expired_logout_tokens(&clock)
+        .await?;
     repo.save().await?;
 
     if count == 0 {
@@ -70,6 +84,71 @@ pub async fn cleanup_expired_tokens(
         info!(count, "cleaned up expired tokens");
     }
 
+    if assertions > 0 {
+        info!(count = assertions, "forgot expired JWT bearer assertions");
+    }
+
+    if logout_tokens > 0 {
+        info!(count = logout_tokens, "forgot expired upstream logout tokens");
+    }
+
+    Ok(())
+}
+
+#[derive(Default, Clone)]
+pub struct CleanupExpiredGuestsJob {
+    scheduled: DateTime<Utc>,
+}
+
+impl From<DateTime<Utc>> for CleanupExpiredGuestsJob {
+    fn from(scheduled: DateTime<Utc>) -> Self {
+        Self { scheduled }
+    }
+}
+
+impl Job for CleanupExpiredGuestsJob {
+    const NAME: &'static str = "cleanup-expired-guests";
+}
+
+impl TracedJob for CleanupExpiredGuestsJob {}
+
+/// Maximum number of guests deactivated in a single run
+const GUESTS_BATCH_SIZE: usize = 100;
+
+pub async fn cleanup_expired_guests(
+    job: CleanupExpiredGuestsJob,
+    ctx: JobContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    debug!("cleanup expired guests job scheduled at {}", job.scheduled);
+
+    let state = ctx.state();
+    let clock = state.clock();
+    let mut repo = state.repository().await?;
+
+    let created_before = clock.now() - state.guests_ttl();
+    let guests = repo
+        .user()
+        .list_expired_guests(created_before, GUESTS_BATCH_SIZE)
+        .await?;
+
+    let count = guests.len();
+    for user in guests {
+        // Lock the guest right away so that it doesn't get picked up by the next
+        // run, the job will take care of ending its sessions
+        let user = repo.user().lock(&clock, user).await?;
+        repo.job()
+            .schedule_job(DeactivateUserJob::new(&user, true))
+            .await?;
+    }
+
+    repo.save().await?;
+
+    if count == 0 {
+        debug!("no guest to clean up");
+    } else {
+        info!(count, "deactivated expired guests");
+    }
+
     Ok(())
 }
 
@@ -87,5 +166,16 @@ pub(crate) fn register(
         .layer(trace_layer())
         .build_fn(cleanup_expired_tokens);
 
+    let monitor = monitor.register(worker);
+
+    let schedule = apalis_cron::Schedule::from_str("0 */10 * * * *").unwrap();
+    let worker_name = format!("{job}-{suffix}", job = CleanupExpiredGuestsJob::NAME);
+    let worker = WorkerBuilder::new(worker_name)
+        .stream(CronStream::new(schedule).timer(TokioTimer).to_stream())
+        .layer(state.inject())
+        .layer(metrics_layer())
+        .layer(trace_layer())
+        .build_fn(cleanup_expired_guests);
+
     monitor.register(worker)
 }
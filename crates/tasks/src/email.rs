@@ -12,21 +12,103 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
+
 use anyhow::Context;
 use apalis_core::{context::JobContext, executor::TokioExecutor, monitor::Monitor};
-use chrono::Duration;
+use chrono::{DateTime, Duration, Utc};
+use mas_data_model::User;
 use mas_email::{Address, Mailbox};
-use mas_i18n::locale;
-use mas_storage::job::{JobWithSpanContext, VerifyEmailJob};
-use mas_templates::{EmailVerificationContext, TemplateContext};
+use mas_i18n::{locale, DataLocale};
+use mas_storage::{
+    job::{
+        FinishEmailChangeJob, JobRepositoryExt, JobWithSpanContext, ProvisionUserJob,
+        SendAuthorizationApprovedJob, SendEmailChangeNotificationJob, SendSecurityNotificationJob,
+        VerifyEmailJob,
+    },
+    Clock, RepositoryAccess,
+};
+use mas_templates::{
+    AuthorizationApprovedContext, EmailChangeNotificationContext, EmailVerificationContext,
+    SecurityNotificationContext, TemplateContext,
+};
 use rand::{distributions::Uniform, Rng};
-use tracing::info;
+use tracing::{info, warn};
+
+use crate::{storage::PostgresStorageFactory, utils::backoff, JobContextExt, State};
+
+/// How many times sending an email is attempted before giving up
+const MAX_ATTEMPTS: u32 = 5;
+
+/// When to try sending an email again after the given attempt failed, or
+/// `None` if it already failed too many times
+fn retry_at(attempt: u32, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    if attempt + 1 >= MAX_ATTEMPTS {
+        return None;
+    }
 
-use crate::{storage::PostgresStorageFactory, JobContextExt, State};
+    Some(now + backoff(attempt))
+}
+
+/// Decides in which locale emails are rendered
+#[derive(Debug, Clone)]
+pub struct EmailLocales {
+    default: DataLocale,
+    by_domain: HashMap<String, DataLocale>,
+}
+
+impl Default for EmailLocales {
+    fn default() -> Self {
+        Self::new(locale!("en").into())
+    }
+}
+
+impl EmailLocales {
+    /// Create a new set of email locales, using the given locale when nothing
+    /// is known about the recipient
+    #[must_use]
+    pub fn new(default: DataLocale) -> Self {
+        Self {
+            default,
+            by_domain: HashMap::new(),
+        }
+    }
+
+    /// Use the given locale for recipients with an address on the given
+    /// domain, when their preferred locale is unknown
+    #[must_use]
+    pub fn with_domain(mut self, domain: &str, locale: DataLocale) -> Self {
+        self.by_domain.insert(domain.to_lowercase(), locale);
+        self
+    }
+
+    /// Pick the locale of an email sent to `address` for the given user.
+    ///
+    /// This is, in order, the preferred locale of the user, the locale in
+    /// which the job was requested, the default locale of the domain of the
+    /// address, and the default locale.
+    fn resolve(&self, user: &User, job_language: Option<&str>, address: &Address) -> DataLocale {
+        user.locale
+            .as_deref()
+            .into_iter()
+            .chain(job_language)
+            .find_map(|l| l.parse().ok())
+            .or_else(|| {
+                self.by_domain
+                    .get(&address.domain().to_lowercase())
+                    .cloned()
+            })
+            .unwrap_or_else(|| self.default.clone())
+    }
+}
 
+/// Job to send a verification code to an email address.
+///
+/// Failed sends are retried with an exponential backoff, with a fresh code,
+/// unless the email was rejected by the server or provider.
 #[tracing::instrument(
     name = "job.verify_email",
-    fields(user_email.id = %job.user_email_id()),
+    fields(user_email.id = %job.user_email_id(), attempt = job.attempt()),
     skip_all,
     err(Debug),
 )]
@@ -40,11 +122,6 @@ async fn verify_email(
     let mailer = state.mailer();
     let clock = state.clock();
 
-    let language = job
-        .language()
-        .and_then(|l| l.parse().ok())
-        .unwrap_or(locale!("en").into());
-
     // Lookup the user email
     let user_email = repo
         .user_email()
@@ -65,6 +142,9 @@ async fn verify_email(
     let code = format!("{code:06}");
 
     let address: Address = user_email.email.parse()?;
+    let language = state
+        .email_locales()
+        .resolve(&user, job.language(), &address);
 
     // Save the verification code in the database
     let verification = repo
@@ -78,7 +158,33 @@ async fn verify_email(
     let context =
         EmailVerificationContext::new(user.clone(), verification.clone()).with_language(language);
 
-    mailer.send_verification_email(mailbox, &context).await?;
+    if let Err(e) = mailer.send_verification_email(mailbox, &context).await {
+        // Don't save the verification code which was never sent
+        repo.cancel().await?;
+
+        // The email was bounced or refused, sending it again won't help
+        if e.is_rejection() {
+            return Err(anyhow::Error::new(e).context("The verification email was rejected"));
+        }
+
+        let Some(run_at) = retry_at(job.attempt(), clock.now()) else {
+            return Err(anyhow::Error::new(e).context("Giving up sending the verification email"));
+        };
+
+        warn!(
+            error = %e,
+            %run_at,
+            "Failed to send the verification email, retrying later"
+        );
+
+        let mut repo = state.repository().await?;
+        repo.job()
+            .schedule_job_at(job.next_attempt(), run_at)
+            .await?;
+        repo.save().await?;
+
+        return Ok(());
+    }
 
     info!(
         email.id = %user_email.id,
@@ -90,6 +196,420 @@ async fn verify_email(
     Ok(())
 }
 
+/// Job to ask the old address of a user to confirm a change of their primary
+/// email address, or to let it know that the change happened, with a link to
+/// revert it.
+///
+/// Failed sends are retried the same way as verification emails.
+#[tracing::instrument(
+    name = "job.send_email_change_notification",
+    fields(user_email_change.id = %job.user_email_change_id(), attempt = job.attempt()),
+    skip_all,
+    err(Debug),
+)]
+async fn send_email_change_notification(
+    job: JobWithSpanContext<SendEmailChangeNotificationJob>,
+    ctx: JobContext,
+) -> Result<(), anyhow::Error> {
+    let state = ctx.state();
+    let mut repo = state.repository().await?;
+    let mailer = state.mailer();
+    let clock = state.clock();
+
+    let Some(change) = repo
+        .user_email()
+        .lookup_change(job.user_email_change_id())
+        .await?
+    else {
+        // Either address was removed in the meantime, nothing to notify about
+        info!("Email change not found, skipping the notification");
+        return Ok(());
+    };
+
+    let awaiting_confirmation = change.is_awaiting_confirmation(clock.now());
+    if !awaiting_confirmation && !change.can_revert(clock.now()) {
+        info!("Email change can't be confirmed or reverted anymore, skipping the notification");
+        return Ok(());
+    }
+
+    let user = repo
+        .user()
+        .lookup(change.user_id)
+        .await?
+        .context("User not found")?;
+
+    let old_email = repo
+        .user_email()
+        .lookup(change.old_user_email_id)
+        .await?
+        .context("Old user email not found")?;
+
+    let new_email = repo
+        .user_email()
+        .lookup(change.new_user_email_id)
+        .await?
+        .context("New user email not found")?;
+
+    repo.cancel().await?;
+
+    let address: Address = old_email.email.parse()?;
+    let language = state
+        .email_locales()
+        .resolve(&user, job.language(), &address);
+    let mailbox = Mailbox::new(Some(user.username.clone()), address);
+
+    let url_builder = state.url_builder();
+    let revert_url = url_builder.email_change_revert(change.revert_token.clone());
+
+    let mut context = EmailChangeNotificationContext::new(user, old_email, new_email, revert_url);
+    if awaiting_confirmation {
+        let confirm_url = url_builder.email_change_confirm(change.revert_token.clone());
+        context = context.with_confirm_url(confirm_url);
+    }
+    let context = context.with_language(language);
+
+    if let Err(e) = mailer
+        .send_email_change_notification(mailbox, &context)
+        .await
+    {
+        if e.is_rejection() {
+            return Err(anyhow::Error::new(e).context("The email change notification was rejected"));
+        }
+
+        let Some(run_at) = retry_at(job.attempt(), clock.now()) else {
+            return Err(
+                anyhow::Error::new(e).context("Giving up sending the email change notification")
+            );
+        };
+
+        warn!(
+            error = %e,
+            %run_at,
+            "Failed to send the email change notification, retrying later"
+        );
+
+        let mut repo = state.repository().await?;
+        repo.job()
+            .schedule_job_at(job.next_attempt(), run_at)
+            .await?;
+        repo.save().await?;
+
+        return Ok(());
+    }
+
+    info!(
+        user_email.id = %change.old_user_email_id,
+        "Email change notification sent"
+    );
+
+    Ok(())
+}
+
+/// Job to remove the old address of a user once the grace period of an email
+/// change is over, unless the change was reverted.
+///
+/// If the old address did not confirm the change in time, the change is
+/// cancelled instead.
+#[tracing::instrument(
+    name = "job.finish_email_change",
+    fields(user_email_change.id = %job.user_email_change_id()),
+    skip_all,
+    err(Debug),
+)]
+async fn finish_email_change(
+    job: JobWithSpanContext<FinishEmailChangeJob>,
+    ctx: JobContext,
+) -> Result<(), anyhow::Error> {
+    let state = ctx.state();
+    let mut repo = state.repository().await?;
+    let clock = state.clock();
+
+    let Some(change) = repo
+        .user_email()
+        .lookup_change(job.user_email_change_id())
+        .await?
+    else {
+        info!("Email change not found, nothing to do");
+        return Ok(());
+    };
+
+    if change.reverted_at.is_some() || change.finished_at.is_some() {
+        info!("Email change was reverted or already finished, nothing to do");
+        return Ok(());
+    }
+
+    if change.is_pending() {
+        if change.is_awaiting_confirmation(clock.now()) {
+            // The job ran too early, try again once the change can't be
+            // confirmed anymore
+            let run_at = change
+                .confirmable_until()
+                .context("Email change is not confirmable")?;
+            repo.job()
+                .schedule_job_at(FinishEmailChangeJob::new(&change), run_at)
+                .await?;
+            repo.save().await?;
+            return Ok(());
+        }
+
+        // The old address never confirmed the change, cancel it
+        let change = repo.user_email().revert_change(&clock, change).await?;
+        repo.save().await?;
+
+        info!(user.id = %change.user_id, "Email change was not confirmed in time, cancelled it");
+
+        return Ok(());
+    }
+
+    if change.can_revert(clock.now()) {
+        // The job ran too early, try again at the end of the grace period
+        let run_at = change
+            .revertable_until()
+            .context("Email change is not revertable")?;
+        repo.job()
+            .schedule_job_at(FinishEmailChangeJob::new(&change), run_at)
+            .await?;
+        repo.save().await?;
+        return Ok(());
+    }
+
+    let user = repo
+        .user()
+        .lookup(change.user_id)
+        .await?
+        .context("User not found")?;
+
+    let change = repo.user_email().finish_change(&clock, change).await?;
+
+    // Don't remove the address if it somehow became primary again
+    let old_email = repo
+        .user_email()
+        .lookup(change.old_user_email_id)
+        .await?
+        .filter(|e| user.primary_user_email_id != Some(e.id));
+    if let Some(old_email) = old_email {
+        repo.user_email().remove(old_email).await?;
+        repo.job()
+            .schedule_job(ProvisionUserJob::new(&user))
+            .await?;
+    }
+
+    repo.save().await?;
+
+    info!(user.id = %user.id, "Email change finished");
+
+    Ok(())
+}
+
+/// Job to let a user know about a security-relevant event on their account.
+///
+/// Nothing is sent if the category is disabled in the configuration, if the
+/// user opted out of it, or if they have no confirmed primary email address.
+/// Failed sends are retried the same way as verification emails.
+#[tracing::instrument(
+    name = "job.send_security_notification",
+    fields(
+        user.id = %job.user_id(),
+        category = job.category().as_str(),
+        attempt = job.attempt(),
+    ),
+    skip_all,
+    err(Debug),
+)]
+async fn send_security_notification(
+    job: JobWithSpanContext<SendSecurityNotificationJob>,
+    ctx: JobContext,
+) -> Result<(), anyhow::Error> {
+    let state = ctx.state();
+    let mut repo = state.repository().await?;
+    let mailer = state.mailer();
+    let clock = state.clock();
+
+    if !state.security_notification_enabled(job.category()) {
+        info!("Security notification category is disabled, skipping");
+        return Ok(());
+    }
+
+    let user = repo
+        .user()
+        .lookup(job.user_id())
+        .await?
+        .context("User not found")?;
+
+    let opt_outs = repo.user().security_notification_opt_outs(&user).await?;
+    if opt_outs.contains(&job.category()) {
+        info!("User opted out of this security notification, skipping");
+        return Ok(());
+    }
+
+    let Some(user_email) = repo.user_email().get_primary(&user).await? else {
+        info!("User has no primary email address, skipping the security notification");
+        return Ok(());
+    };
+
+    if user_email.confirmed_at.is_none() {
+        info!("Primary email address is not confirmed, skipping the security notification");
+        return Ok(());
+    }
+
+    let mut context = SecurityNotificationContext::new(user.clone(), job.category());
+    if let Some(provider_id) = job.upstream_oauth_provider_id() {
+        let provider = repo
+            .upstream_oauth_provider()
+            .lookup(provider_id)
+            .await?
+            .context("Upstream OAuth 2.0 provider not found")?;
+        context = context.with_upstream_provider(&provider);
+    }
+
+    repo.cancel().await?;
+
+    let address: Address = user_email.email.parse()?;
+    let language = state
+        .email_locales()
+        .resolve(&user, job.language(), &address);
+    let mailbox = Mailbox::new(Some(user.username.clone()), address);
+
+    let context = context.with_language(language);
+
+    if let Err(e) = mailer.send_security_notification(mailbox, &context).await {
+        if e.is_rejection() {
+            return Err(anyhow::Error::new(e).context("The security notification was rejected"));
+        }
+
+        let Some(run_at) = retry_at(job.attempt(), clock.now()) else {
+            return Err(
+                anyhow::Error::new(e).context("Giving up sending the security notification")
+            );
+        };
+
+        warn!(
+            error = %e,
+            %run_at,
+            "Failed to send the security notification, retrying later"
+        );
+
+        let mut repo = state.repository().await?;
+        repo.job()
+            .schedule_job_at(job.next_attempt(), run_at)
+            .await?;
+        repo.save().await?;
+
+        return Ok(());
+    }
+
+    info!(
+        user_email.id = %user_email.id,
+        "Security notification sent"
+    );
+
+    Ok(())
+}
+
+/// Job to let a user know that an administrator approved their authorization
+/// request, with a link to finish it.
+///
+/// Nothing is sent if the grant can't be completed anymore, or if the user has
+/// no confirmed primary email address. Failed sends are retried the same way as
+/// verification emails.
+#[tracing::instrument(
+    name = "job.send_authorization_approved",
+    fields(grant.id = %job.authorization_grant_id(), attempt = job.attempt()),
+    skip_all,
+    err(Debug),
+)]
+async fn send_authorization_approved(
+    job: JobWithSpanContext<SendAuthorizationApprovedJob>,
+    ctx: JobContext,
+) -> Result<(), anyhow::Error> {
+    let state = ctx.state();
+    let mut repo = state.repository().await?;
+    let mailer = state.mailer();
+    let clock = state.clock();
+
+    let grant = repo
+        .oauth2_authorization_grant()
+        .lookup(job.authorization_grant_id())
+        .await?
+        .context("Authorization grant not found")?;
+
+    if !grant.stage.is_pending() {
+        info!("Authorization grant is not pending anymore, skipping the notification");
+        return Ok(());
+    }
+
+    let approval = grant
+        .approval
+        .as_ref()
+        .context("Authorization grant was not held for an approval")?;
+
+    let user = repo
+        .user()
+        .lookup(approval.user_id)
+        .await?
+        .context("User not found")?;
+
+    let client = repo
+        .oauth2_client()
+        .lookup(grant.client_id)
+        .await?
+        .context("Client not found")?;
+
+    let Some(user_email) = repo.user_email().get_primary(&user).await? else {
+        info!("User has no primary email address, skipping the notification");
+        return Ok(());
+    };
+
+    if user_email.confirmed_at.is_none() {
+        info!("Primary email address is not confirmed, skipping the notification");
+        return Ok(());
+    }
+
+    repo.cancel().await?;
+
+    let address: Address = user_email.email.parse()?;
+    let language = state.email_locales().resolve(&user, None, &address);
+    let mailbox = Mailbox::new(Some(user.username.clone()), address);
+
+    let continue_url = state.url_builder().continue_authorization_grant(grant.id);
+
+    let context =
+        AuthorizationApprovedContext::new(user, client, continue_url).with_language(language);
+
+    if let Err(e) = mailer.send_authorization_approved(mailbox, &context).await {
+        if e.is_rejection() {
+            return Err(anyhow::Error::new(e).context("The approval notification was rejected"));
+        }
+
+        let Some(run_at) = retry_at(job.attempt(), clock.now()) else {
+            return Err(
+                anyhow::Error::new(e).context("Giving up sending the approval notification")
+            );
+        };
+
+        warn!(
+            error = %e,
+            %run_at,
+            "Failed to send the approval notification, retrying later"
+        );
+
+        let mut repo = state.repository().await?;
+        repo.job()
+            .schedule_job_at(job.next_attempt(), run_at)
+            .await?;
+        repo.save().await?;
+
+        return Ok(());
+    }
+
+    info!(
+        user_email.id = %user_email.id,
+        "Approval notification sent"
+    );
+
+    Ok(())
+}
+
 pub(crate) fn register(
     suffix: &str,
     monitor: Monitor<TokioExecutor>,
@@ -99,5 +619,90 @@ pub(crate) fn register(
     let verify_email_worker =
         crate::build!(VerifyEmailJob => verify_email, suffix, state, storage_factory);
 
-    monitor.register(verify_email_worker)
+    let send_email_change_notification_worker = crate::build!(
+        SendEmailChangeNotificationJob => send_email_change_notification,
+        suffix,
+        state,
+        storage_factory
+    );
+    let finish_email_change_worker = crate::build!(
+        FinishEmailChangeJob => finish_email_change,
+        suffix,
+        state,
+        storage_factory
+    );
+    let send_security_notification_worker = crate::build!(
+        SendSecurityNotificationJob => send_security_notification,
+        suffix,
+        state,
+        storage_factory
+    );
+
+    let send_authorization_approved_worker = crate::build!(
+        SendAuthorizationApprovedJob => send_authorization_approved,
+        suffix,
+        state,
+        storage_factory
+    );
+
+    monitor
+        .register(verify_email_worker)
+        .register(send_email_change_notification_worker)
+        .register(finish_email_change_worker)
+        .register(send_security_notification_worker)
+        .register(send_authorization_approved_worker)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn test_retry_at() {
+        let now = Utc.with_ymd_and_hms(2022, 1, 16, 14, 40, 0).unwrap();
+
+        // The delay doubles with each failed attempt
+        assert_eq!(retry_at(0, now), Some(now + Duration::seconds(30)));
+        assert_eq!(retry_at(1, now), Some(now + Duration::seconds(60)));
+        assert_eq!(retry_at(3, now), Some(now + Duration::seconds(240)));
+
+        // The last attempt isn't retried
+        assert_eq!(retry_at(MAX_ATTEMPTS - 1, now), None);
+        assert_eq!(retry_at(MAX_ATTEMPTS, now), None);
+    }
+
+    #[test]
+    fn test_resolve_locale() {
+        let now = Utc.with_ymd_and_hms(2022, 1, 16, 14, 40, 0).unwrap();
+        let mut rng = rand_chacha::ChaChaRng::seed_from_u64(42);
+        let mut user = User::samples(now, &mut rng).remove(0);
+
+        let en = DataLocale::from(locale!("en"));
+        let fr = DataLocale::from(locale!("fr"));
+        let de = DataLocale::from(locale!("de"));
+        let es = DataLocale::from(locale!("es"));
+        let locales = EmailLocales::new(en.clone()).with_domain("Example.FR", fr.clone());
+
+        let other: Address = "alice@example.com".parse().unwrap();
+        let french: Address = "alice@EXAMPLE.fr".parse().unwrap();
+
+        // Nothing is known about the recipient
+        assert_eq!(locales.resolve(&user, None, &other), en);
+
+        // The domain of the address, regardless of its case
+        assert_eq!(locales.resolve(&user, None, &french), fr);
+
+        // The language of the request comes before the domain, unless it is
+        // invalid
+        assert_eq!(locales.resolve(&user, Some("de"), &french), de);
+        assert_eq!(locales.resolve(&user, Some("!!"), &french), fr);
+
+        // The preferred locale of the user comes first
+        user.locale = Some("es".to_owned());
+        assert_eq!(locales.resolve(&user, Some("de"), &french), es);
+        assert_eq!(locales.resolve(&user, None, &other), es);
+    }
 }
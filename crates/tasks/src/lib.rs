@@ -19,22 +19,29 @@
 use std::sync::Arc;
 
 use apalis_core::{executor::TokioExecutor, layers::extensions::Extension, monitor::Monitor};
+use mas_axum_utils::http_client_factory::HttpClientFactory;
+use mas_data_model::SecurityNotification;
 use mas_email::Mailer;
+use mas_keystore::{Encrypter, Keystore};
 use mas_matrix::HomeserverConnection;
+use mas_router::UrlBuilder;
 use mas_storage::{BoxClock, BoxRepository, Repository, SystemClock};
 use mas_storage_pg::{DatabaseError, PgRepository};
 use rand::SeedableRng;
 use sqlx::{Pool, Postgres};
 use tracing::debug;
 
+pub use self::{email::EmailLocales, webhook::WebhookEndpoint};
 use crate::storage::PostgresStorageFactory;
 
 mod database;
 mod email;
 mod matrix;
 mod storage;
+mod upstream_oauth2;
 mod user;
 mod utils;
+mod webhook;
 
 #[derive(Clone)]
 struct State {
@@ -42,20 +49,45 @@ struct State {
     mailer: Mailer,
     clock: SystemClock,
     homeserver: Arc<dyn HomeserverConnection<Error = anyhow::Error>>,
+    http_client_factory: HttpClientFactory,
+    url_builder: UrlBuilder,
+    encrypter: Encrypter,
+    keystore: Keystore,
+    webhooks: Arc<[WebhookEndpoint]>,
+    guests_ttl: chrono::Duration,
+    security_notifications: Arc<[SecurityNotification]>,
+    email_locales: Arc<EmailLocales>,
 }
 
 impl State {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         pool: Pool<Postgres>,
         clock: SystemClock,
         mailer: Mailer,
         homeserver: impl HomeserverConnection<Error = anyhow::Error> + 'static,
+        http_client_factory: HttpClientFactory,
+        url_builder: UrlBuilder,
+        encrypter: Encrypter,
+        keystore: Keystore,
+        webhooks: Vec<WebhookEndpoint>,
+        guests_ttl: chrono::Duration,
+        security_notifications: Vec<SecurityNotification>,
+        email_locales: EmailLocales,
     ) -> Self {
         Self {
             pool,
             mailer,
             clock,
             homeserver: Arc::new(homeserver),
+            http_client_factory,
+            url_builder,
+            encrypter,
+            keystore,
+            webhooks: webhooks.into(),
+            guests_ttl,
+            security_notifications: security_notifications.into(),
+            email_locales: Arc::new(email_locales),
         }
     }
 
@@ -93,6 +125,38 @@ impl State {
     pub fn matrix_connection(&self) -> &dyn HomeserverConnection<Error = anyhow::Error> {
         self.homeserver.as_ref()
     }
+
+    pub fn http_client_factory(&self) -> &HttpClientFactory {
+        &self.http_client_factory
+    }
+
+    pub fn url_builder(&self) -> &UrlBuilder {
+        &self.url_builder
+    }
+
+    pub fn encrypter(&self) -> &Encrypter {
+        &self.encrypter
+    }
+
+    pub fn keystore(&self) -> &Keystore {
+        &self.keystore
+    }
+
+    pub fn webhooks(&self) -> &[WebhookEndpoint] {
+        &self.webhooks
+    }
+
+    pub fn guests_ttl(&self) -> chrono::Duration {
+        self.guests_ttl
+    }
+
+    pub fn security_notification_enabled(&self, category: SecurityNotification) -> bool {
+        self.security_notifications.contains(&category)
+    }
+
+    pub fn email_locales(&self) -> &EmailLocales {
+        &self.email_locales
+    }
 }
 
 trait JobContextExt {
@@ -135,27 +199,50 @@ pub(crate) use build;
 
 /// Initialise the workers.
 ///
+/// Guest accounts older than `guests_ttl` are periodically deactivated.
+/// Security notifications are only sent for the categories listed in
+/// `security_notifications`.
+///
 /// # Errors
 ///
 /// This function can fail if the database connection fails.
+#[allow(clippy::too_many_arguments)]
 pub async fn init(
     name: &str,
     pool: &Pool<Postgres>,
     mailer: &Mailer,
     homeserver: impl HomeserverConnection<Error = anyhow::Error> + 'static,
+    http_client_factory: &HttpClientFactory,
+    url_builder: &UrlBuilder,
+    encrypter: &Encrypter,
+    keystore: &Keystore,
+    webhooks: Vec<WebhookEndpoint>,
+    guests_ttl: chrono::Duration,
+    security_notifications: Vec<SecurityNotification>,
+    email_locales: EmailLocales,
 ) -> Result<Monitor<TokioExecutor>, sqlx::Error> {
     let state = State::new(
         pool.clone(),
         SystemClock::default(),
         mailer.clone(),
         homeserver,
+        http_client_factory.clone(),
+        url_builder.clone(),
+        encrypter.clone(),
+        keystore.clone(),
+        webhooks,
+        guests_ttl,
+        security_notifications,
+        email_locales,
     );
     let factory = PostgresStorageFactory::new(pool.clone());
     let monitor = Monitor::new().executor(TokioExecutor::new());
     let monitor = self::database::register(name, monitor, &state);
     let monitor = self::email::register(name, monitor, &state, &factory);
     let monitor = self::matrix::register(name, monitor, &state, &factory);
+    let monitor = self::upstream_oauth2::register(name, monitor, &state);
     let monitor = self::user::register(name, monitor, &state, &factory);
+    let monitor = self::webhook::register(name, monitor, &state, &factory);
     // TODO: we might want to grab the join handle here
     factory.listen().await?;
     debug!(?monitor, "workers registered");
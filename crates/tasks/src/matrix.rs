@@ -14,15 +14,55 @@
 
 use anyhow::Context;
 use apalis_core::{context::JobContext, executor::TokioExecutor, monitor::Monitor};
-use mas_matrix::ProvisionRequest;
+use mas_matrix::{ProvisionRequest, Unsupported};
 use mas_storage::{
-    job::{DeleteDeviceJob, JobWithSpanContext, ProvisionDeviceJob, ProvisionUserJob},
+    job::{
+        AllowCrossSigningResetJob, DeleteDeviceJob, Job, JobRepositoryExt, JobWithSpanContext,
+        ProvisionDeviceJob, ProvisionUserJob, SetDisplayNameJob,
+    },
     user::{UserEmailRepository, UserRepository},
-    RepositoryAccess,
+    Clock, RepositoryAccess,
 };
-use tracing::info;
+use serde::Serialize;
+use tracing::{info, warn};
 
-use crate::{storage::PostgresStorageFactory, JobContextExt, State};
+use crate::{storage::PostgresStorageFactory, utils::backoff, JobContextExt, State};
+
+/// How many times a homeserver operation is attempted before giving up
+const MAX_ATTEMPTS: u32 = 10;
+
+/// Schedule the next attempt of a job which failed to reach the homeserver.
+///
+/// Once the job ran out of attempts, the error is returned, so that the job
+/// ends up marked as failed in the queue. Operations the homeserver doesn't
+/// support are skipped instead, as retrying them would never succeed.
+async fn retry_later<J>(
+    state: &State,
+    attempt: u32,
+    next: J,
+    error: anyhow::Error,
+) -> Result<(), anyhow::Error>
+where
+    J: Job + Serialize + Send,
+{
+    if error.is::<Unsupported>() {
+        info!(error = %error, "The homeserver does not support this operation, skipping");
+        return Ok(());
+    }
+
+    if attempt + 1 >= MAX_ATTEMPTS {
+        return Err(error.context("Giving up reaching the homeserver"));
+    }
+
+    let run_at = state.clock().now() + backoff(attempt);
+    warn!(error = %error, %run_at, "Failed to reach the homeserver, retrying later");
+
+    let mut repo = state.repository().await?;
+    repo.job().schedule_job_at(next, run_at).await?;
+    repo.save().await?;
+
+    Ok(())
+}
 
 /// Job to provision a user on the Matrix homeserver.
 /// This works by doing a PUT request to the /_synapse/admin/v2/users/{user_id}
@@ -65,7 +105,14 @@ async fn provision_user(
         request = request.set_displayname(display_name.to_owned());
     }
 
-    let created = matrix.provision_user(&request).await?;
+    if let Some(avatar_url) = job.avatar_url_to_set() {
+        request = request.set_avatar_url(avatar_url.to_owned());
+    }
+
+    let created = match matrix.provision_user(&request).await {
+        Ok(created) => created,
+        Err(e) => return retry_later(&state, job.attempt(), job.next_attempt(), e).await,
+    };
 
     if created {
         info!(%user.id, %mxid, "User created");
@@ -104,9 +151,21 @@ async fn provision_device(
 
     let mxid = matrix.mxid(&user.username);
 
-    matrix.create_device(&mxid, job.device_id()).await?;
+    if let Err(e) = matrix.create_device(&mxid, job.device_id()).await {
+        return retry_later(&state, job.attempt(), job.next_attempt(), e).await;
+    }
     info!(%user.id, %mxid, device.id = job.device_id(), "Device created");
 
+    if let Some(display_name) = job.display_name() {
+        if let Err(e) = matrix
+            .update_device_display_name(&mxid, job.device_id(), display_name)
+            .await
+        {
+            return retry_later(&state, job.attempt(), job.next_attempt(), e).await;
+        }
+        info!(%user.id, %mxid, device.id = job.device_id(), "Device display name updated");
+    }
+
     Ok(())
 }
 
@@ -138,12 +197,89 @@ async fn delete_device(
 
     let mxid = matrix.mxid(&user.username);
 
-    matrix.delete_device(&mxid, job.device_id()).await?;
+    if let Err(e) = matrix.delete_device(&mxid, job.device_id()).await {
+        return retry_later(&state, job.attempt(), job.next_attempt(), e).await;
+    }
     info!(%user.id, %mxid, device.id = job.device_id(), "Device deleted");
 
     Ok(())
 }
 
+/// Job to set or remove the display name of a user on the homeserver.
+#[tracing::instrument(
+    name = "job.set_display_name"
+    fields(user.id = %job.user_id()),
+    skip_all,
+    err(Debug),
+)]
+async fn set_display_name(
+    job: JobWithSpanContext<SetDisplayNameJob>,
+    ctx: JobContext,
+) -> Result<(), anyhow::Error> {
+    let state = ctx.state();
+    let matrix = state.matrix_connection();
+    let mut repo = state.repository().await?;
+
+    let user = repo
+        .user()
+        .lookup(job.user_id())
+        .await?
+        .context("User not found")?;
+
+    repo.cancel().await?;
+
+    let mxid = matrix.mxid(&user.username);
+
+    let res = if let Some(display_name) = job.display_name() {
+        matrix.set_displayname(&mxid, display_name).await
+    } else {
+        matrix.unset_displayname(&mxid).await
+    };
+
+    if let Err(e) = res {
+        return retry_later(&state, job.attempt(), job.next_attempt(), e).await;
+    }
+    info!(%user.id, %mxid, "Display name updated");
+
+    Ok(())
+}
+
+/// Job to allow a user to reset their cross-signing identity.
+/// This works by doing a POST request to the
+/// /_synapse/admin/v1/users/{user_id}/
+/// _allow_cross_signing_replacement_without_uia endpoint.
+#[tracing::instrument(
+    name = "job.allow_cross_signing_reset"
+    fields(user.id = %job.user_id()),
+    skip_all,
+    err(Debug),
+)]
+async fn allow_cross_signing_reset(
+    job: JobWithSpanContext<AllowCrossSigningResetJob>,
+    ctx: JobContext,
+) -> Result<(), anyhow::Error> {
+    let state = ctx.state();
+    let matrix = state.matrix_connection();
+    let mut repo = state.repository().await?;
+
+    let user = repo
+        .user()
+        .lookup(job.user_id())
+        .await?
+        .context("User not found")?;
+
+    repo.cancel().await?;
+
+    let mxid = matrix.mxid(&user.username);
+
+    if let Err(e) = matrix.allow_cross_signing_reset(&mxid).await {
+        return retry_later(&state, job.attempt(), job.next_attempt(), e).await;
+    }
+    info!(%user.id, %mxid, "Cross-signing reset allowed");
+
+    Ok(())
+}
+
 pub(crate) fn register(
     suffix: &str,
     monitor: Monitor<TokioExecutor>,
@@ -156,9 +292,19 @@ pub(crate) fn register(
         crate::build!(ProvisionDeviceJob => provision_device, suffix, state, storage_factory);
     let delete_device_worker =
         crate::build!(DeleteDeviceJob => delete_device, suffix, state, storage_factory);
+    let set_display_name_worker =
+        crate::build!(SetDisplayNameJob => set_display_name, suffix, state, storage_factory);
+    let allow_cross_signing_reset_worker = crate::build!(
+        AllowCrossSigningResetJob => allow_cross_signing_reset,
+        suffix,
+        state,
+        storage_factory
+    );
 
     monitor
         .register(provision_user_worker)
         .register(provision_device_worker)
         .register(delete_device_worker)
+        .register(set_display_name_worker)
+        .register(allow_cross_signing_reset_worker)
 }
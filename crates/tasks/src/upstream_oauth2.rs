@@ -0,0 +1,700 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Upstream OAuth 2.0 provider related tasks
+
+use std::{collections::HashMap, str::FromStr, sync::OnceLock};
+
+use apalis_core::{
+    builder::{WorkerBuilder, WorkerFactoryFn},
+    context::JobContext,
+    executor::TokioExecutor,
+    job::Job,
+    monitor::Monitor,
+    utils::timer::TokioTimer,
+};
+use apalis_cron::CronStream;
+use bytes::Bytes;
+use chrono::{DateTime, Duration, Utc};
+use http::header::CONTENT_TYPE;
+use mas_axum_utils::upstream_oauth2::{
+    client_credentials_for_provider, client_metadata_for_provider,
+};
+use mas_data_model::{
+    UpstreamOAuthLink, UpstreamOAuthLinkTokens, UpstreamOAuthProvider,
+    UpstreamOAuthProviderProtocol, UpstreamOAuthProviderRegistration,
+};
+use mas_jose::jwk::PublicJsonWebKeySet;
+use mas_oidc_client::error::{
+    DiscoveryError, HttpError, JwksError, RegistrationError, TokenRefreshError, TokenRequestError,
+};
+use mas_storage::{
+    upstream_oauth2::{UpstreamOAuthLinkRepository, UpstreamOAuthProviderRepository},
+    Clock, RepositoryAccess,
+};
+use oauth2_types::{
+    oidc::ProviderMetadata,
+    registration::{ClientMetadataVerificationError, ClientRegistrationResponse},
+};
+use opentelemetry::{
+    metrics::{Counter, Unit},
+    KeyValue,
+};
+use tower::{BoxError, ServiceExt};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    utils::{metrics_layer, trace_layer, TracedJob},
+    JobContextExt, State,
+};
+
+#[derive(Default, Clone)]
+pub struct RefreshUpstreamOAuthMetadataJob {
+    scheduled: DateTime<Utc>,
+}
+
+impl From<DateTime<Utc>> for RefreshUpstreamOAuthMetadataJob {
+    fn from(scheduled: DateTime<Utc>) -> Self {
+        Self { scheduled }
+    }
+}
+
+impl Job for RefreshUpstreamOAuthMetadataJob {
+    const NAME: &'static str = "refresh-upstream-oauth-metadata";
+}
+
+impl TracedJob for RefreshUpstreamOAuthMetadataJob {}
+
+/// Fetch the discovery document and the JWKS of every upstream OpenID Connect
+/// provider, and store them in the database.
+///
+/// If they can't be fetched from a provider, the ones stored previously are
+/// kept, so that logins through it keep working during short outages.
+///
+/// This doubles as the health check of the providers: the token endpoint of
+/// the ones which ask for it is probed as well, and the outcome is recorded in
+/// the database and in the `mas.upstream_oauth2.health_checks` metric.
+pub async fn refresh_upstream_oauth_metadata(
+    job: RefreshUpstreamOAuthMetadataJob,
+    ctx: JobContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    debug!(
+        "refresh upstream OAuth metadata job scheduled at {}",
+        job.scheduled
+    );
+
+    let state = ctx.state();
+    let clock = state.clock();
+    let http_service = state
+        .http_client_factory()
+        .http_service("upstream_oauth2.metadata");
+
+    // Don't keep a transaction open while talking to the providers
+    let mut repo = state.repository().await?;
+    let providers = repo.upstream_oauth_provider().all().await?;
+    repo.cancel().await?;
+
+    let mut checked = Vec::with_capacity(providers.len());
+    for provider in providers {
+        // There is nothing to check on SAML providers, nor on plain OAuth 2.0
+        // providers unless their token endpoint is probed
+        let has_checks = match provider.protocol {
+            UpstreamOAuthProviderProtocol::Oidc => true,
+            UpstreamOAuthProviderProtocol::OAuth2 => provider.health_check.probe_token_endpoint,
+            UpstreamOAuthProviderProtocol::Saml => false,
+        };
+        if !has_checks {
+            continue;
+        }
+
+        let mut metadata = None;
+        let error = match check_provider(&http_service, &provider, &mut metadata).await {
+            Ok(()) => None,
+            Err(e) => {
+                error!(
+                    upstream_oauth_provider.id = %provider.id,
+                    issuer = %provider.issuer,
+                    error = &e as &dyn std::error::Error,
+                    "Provider failed its health check, keeping its previous metadata"
+                );
+                Some(e.to_string())
+            }
+        };
+
+        health_checks_counter().add(
+            1,
+            &[
+                KeyValue::new("upstream_oauth_provider.id", provider.id.to_string()),
+                KeyValue::new("healthy", error.is_none()),
+            ],
+        );
+
+        checked.push((provider, metadata, error));
+    }
+
+    let mut repo = state.repository().await?;
+    let mut count = 0;
+    let mut unhealthy = 0;
+    for (provider, metadata, error) in checked {
+        if let Some((discovery, jwks)) = metadata {
+            repo.upstream_oauth_provider()
+                .set_metadata(&clock, &provider, discovery, jwks)
+                .await?;
+            count += 1;
+        }
+
+        if error.is_some() {
+            unhealthy += 1;
+        }
+
+        repo.upstream_oauth_provider()
+            .set_health(&clock, &provider, error)
+            .await?;
+    }
+    repo.save().await?;
+
+    info!(count, unhealthy, "refreshed upstream OAuth metadata");
+
+    Ok(())
+}
+
+/// The number of health checks of upstream providers, by provider and outcome
+fn health_checks_counter() -> &'static Counter<u64> {
+    static COUNTER: OnceLock<Counter<u64>> = OnceLock::new();
+    COUNTER.get_or_init(|| {
+        let meter = opentelemetry::global::meter_with_version(
+            env!("CARGO_PKG_NAME"),
+            Some(env!("CARGO_PKG_VERSION")),
+            Some(opentelemetry_semantic_conventions::SCHEMA_URL),
+            None,
+        );
+
+        meter
+            .u64_counter("mas.upstream_oauth2.health_checks")
+            .with_description("The number of health checks of upstream OAuth 2.0 providers")
+            .with_unit(Unit::new("{checks}"))
+            .init()
+    })
+}
+
+#[derive(Debug, thiserror::Error)]
+enum HealthCheckError {
+    #[error("Failed to fetch the discovery document: {0}")]
+    Discovery(#[from] DiscoveryError),
+
+    #[error("Failed to fetch the JWKS: {0}")]
+    Jwks(#[from] JwksError),
+
+    #[error("The provider has no token endpoint")]
+    MissingTokenEndpoint,
+
+    #[error("The token endpoint is unreachable: {0}")]
+    TokenEndpoint(#[source] BoxError),
+}
+
+/// Check that a provider is healthy, fetching its discovery document and JWKS
+/// if it speaks OpenID Connect, and probing its token endpoint if its health
+/// check settings ask for it
+///
+/// The discovery document and the JWKS are put in `metadata` as soon as they
+/// are fetched, so that they are saved even if the token endpoint isn't
+/// reachable
+async fn check_provider(
+    http_service: &mas_http::HttpService,
+    provider: &UpstreamOAuthProvider,
+    metadata: &mut Option<(ProviderMetadata, PublicJsonWebKeySet)>,
+) -> Result<(), HealthCheckError> {
+    let mut token_endpoint = provider.endpoints.token_endpoint.clone();
+
+    // Plain OAuth 2.0 providers have neither a discovery document nor a JWKS
+    if provider.protocol == UpstreamOAuthProviderProtocol::Oidc {
+        let discovery =
+            mas_oidc_client::requests::discovery::discover(http_service, &provider.issuer).await?;
+
+        let jwks_uri = provider
+            .endpoints
+            .jwks_uri
+            .as_ref()
+            .unwrap_or(discovery.jwks_uri());
+        let jwks = mas_oidc_client::requests::jose::fetch_jwks(http_service, jwks_uri).await?;
+
+        token_endpoint = token_endpoint.or_else(|| Some(discovery.token_endpoint().clone()));
+        *metadata = Some(((*discovery).clone(), jwks));
+    }
+
+    if provider.health_check.probe_token_endpoint {
+        let token_endpoint = token_endpoint.ok_or(HealthCheckError::MissingTokenEndpoint)?;
+
+        // Any response means the endpoint is reachable, even if it rejects the
+        // empty request
+        let request = http::Request::post(token_endpoint.as_str())
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Bytes::new())
+            .map_err(|e| HealthCheckError::TokenEndpoint(e.into()))?;
+        http_service
+            .clone()
+            .oneshot(request)
+            .await
+            .map_err(HealthCheckError::TokenEndpoint)?;
+    }
+
+    Ok(())
+}
+
+#[derive(Default, Clone)]
+pub struct RefreshUpstreamOAuthTokensJob {
+    scheduled: DateTime<Utc>,
+}
+
+impl From<DateTime<Utc>> for RefreshUpstreamOAuthTokensJob {
+    fn from(scheduled: DateTime<Utc>) -> Self {
+        Self { scheduled }
+    }
+}
+
+impl Job for RefreshUpstreamOAuthTokensJob {
+    const NAME: &'static str = "refresh-upstream-oauth-tokens";
+}
+
+impl TracedJob for RefreshUpstreamOAuthTokensJob {}
+
+/// How long before they expire the stored access tokens get refreshed
+const REFRESH_MARGIN_MINUTES: i64 = 5;
+
+/// How many links get their tokens refreshed in a single run
+const REFRESH_BATCH_SIZE: usize = 100;
+
+/// Refresh the access tokens stored on the upstream OAuth links which are
+/// about to expire.
+///
+/// Tokens rejected by the provider, or stored for a provider which doesn't
+/// store tokens anymore, are erased. If the provider can't be reached, they
+/// are kept and refreshing them is tried again on the next run.
+#[allow(clippy::too_many_lines)]
+pub async fn refresh_upstream_oauth_tokens(
+    job: RefreshUpstreamOAuthTokensJob,
+    ctx: JobContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    debug!(
+        "refresh upstream OAuth tokens job scheduled at {}",
+        job.scheduled
+    );
+
+    let state = ctx.state();
+    let clock = state.clock();
+    let mut rng = state.rng();
+    let http_service = state
+        .http_client_factory()
+        .http_service("upstream_oauth2.refresh_tokens");
+
+    // Don't keep a transaction open while talking to the providers
+    let mut repo = state.repository().await?;
+    let links = repo
+        .upstream_oauth_link()
+        .list_refreshable(
+            clock.now() + Duration::minutes(REFRESH_MARGIN_MINUTES),
+            REFRESH_BATCH_SIZE,
+        )
+        .await?;
+    if links.is_empty() {
+        repo.cancel().await?;
+        return Ok(());
+    }
+
+    let providers: HashMap<_, _> = repo
+        .upstream_oauth_provider()
+        .all()
+        .await?
+        .into_iter()
+        .map(|provider| (provider.id, provider))
+        .collect();
+
+    // The token endpoints of the providers, loaded once per provider
+    let mut token_endpoints = HashMap::new();
+    for provider in providers.values() {
+        let token_endpoint = match provider.endpoints.token_endpoint.clone() {
+            Some(token_endpoint) => Some(token_endpoint),
+            None => repo
+                .upstream_oauth_provider()
+                .metadata(provider)
+                .await?
+                .and_then(|metadata| metadata.discovery.token_endpoint),
+        };
+        token_endpoints.insert(provider.id, token_endpoint);
+    }
+    repo.cancel().await?;
+
+    let mut updates: Vec<(UpstreamOAuthLink, Option<UpstreamOAuthLinkTokens>)> = Vec::new();
+    for (link, tokens) in links {
+        let Some(provider) = providers.get(&link.provider_id).filter(|p| p.store_tokens) else {
+            updates.push((link, None));
+            continue;
+        };
+
+        let Some(token_endpoint) = token_endpoints.get(&provider.id).cloned().flatten() else {
+            error!(
+                upstream_oauth_provider.id = %provider.id,
+                "The token endpoint of the provider is unknown, can't refresh tokens"
+            );
+            continue;
+        };
+
+        match refresh_tokens(
+            &state,
+            &http_service,
+            &clock,
+            &mut rng,
+            provider,
+            &token_endpoint,
+            &tokens,
+        )
+        .await
+        {
+            Ok(tokens) => updates.push((link, Some(tokens))),
+
+            // The provider rejected the refresh token, it won't ever work again
+            Err(RefreshError::Refresh(TokenRefreshError::Token(TokenRequestError::Http(
+                HttpError {
+                    body: Some(body), ..
+                },
+            )))) => {
+                warn!(
+                    upstream_oauth_link.id = %link.id,
+                    upstream_oauth_provider.id = %provider.id,
+                    error = ?body.error,
+                    "The provider rejected the refresh token, erasing the stored tokens"
+                );
+                updates.push((link, None));
+            }
+
+            Err(e) => {
+                error!(
+                    upstream_oauth_link.id = %link.id,
+                    upstream_oauth_provider.id = %provider.id,
+                    error = &e as &dyn std::error::Error,
+                    "Failed to refresh the stored tokens, keeping them"
+                );
+            }
+        }
+    }
+
+    let mut repo = state.repository().await?;
+    let count = updates.len();
+    for (link, tokens) in updates {
+        repo.upstream_oauth_link()
+            .set_tokens(&link, tokens.as_ref())
+            .await?;
+    }
+    repo.save().await?;
+
+    info!(count, "refreshed upstream OAuth tokens");
+
+    Ok(())
+}
+
+#[derive(Debug, thiserror::Error)]
+enum RefreshError {
+    #[error(transparent)]
+    Decrypt(#[from] mas_keystore::DecryptError),
+
+    #[error(transparent)]
+    Encrypt(#[from] mas_keystore::aead::Error),
+
+    #[error("The refresh token is invalid")]
+    InvalidRefreshToken(#[from] std::string::FromUtf8Error),
+
+    #[error(transparent)]
+    Credentials(#[from] mas_axum_utils::upstream_oauth2::ProviderCredentialsError),
+
+    #[error(transparent)]
+    Refresh(#[from] TokenRefreshError),
+}
+
+/// Exchange the refresh token stored on a link for new tokens
+async fn refresh_tokens(
+    state: &State,
+    http_service: &mas_http::HttpService,
+    clock: &impl Clock,
+    rng: &mut rand_chacha::ChaChaRng,
+    provider: &UpstreamOAuthProvider,
+    token_endpoint: &url::Url,
+    tokens: &UpstreamOAuthLinkTokens,
+) -> Result<UpstreamOAuthLinkTokens, RefreshError> {
+    let encrypter = state.encrypter();
+
+    // Links are only listed if they have a refresh token
+    let Some(encrypted_refresh_token) = tokens.encrypted_refresh_token.clone() else {
+        return Ok(tokens.clone());
+    };
+    let refresh_token = String::from_utf8(encrypter.decrypt_string(&encrypted_refresh_token)?)?;
+
+    let client_credentials =
+        client_credentials_for_provider(provider, token_endpoint, state.keystore(), encrypter)?;
+
+    let (response, _id_token) = mas_oidc_client::requests::refresh_token::refresh_access_token(
+        http_service,
+        client_credentials,
+        token_endpoint,
+        refresh_token,
+        None,
+        None,
+        None,
+        clock.now(),
+        rng,
+    )
+    .await?;
+
+    // Providers may not rotate the refresh token, in which case the previous one
+    // stays valid
+    let encrypted_refresh_token = match response.refresh_token {
+        Some(refresh_token) => encrypter.encrypt_to_string(refresh_token.as_bytes())?,
+        None => encrypted_refresh_token,
+    };
+
+    Ok(UpstreamOAuthLinkTokens {
+        encrypted_access_token: encrypter.encrypt_to_string(response.access_token.as_bytes())?,
+        encrypted_refresh_token: Some(encrypted_refresh_token),
+        access_token_expires_at: response
+            .expires_in
+            .map(|expires_in| clock.now() + expires_in),
+    })
+}
+
+#[derive(Default, Clone)]
+pub struct RefreshUpstreamOAuthRegistrationsJob {
+    scheduled: DateTime<Utc>,
+}
+
+impl From<DateTime<Utc>> for RefreshUpstreamOAuthRegistrationsJob {
+    fn from(scheduled: DateTime<Utc>) -> Self {
+        Self { scheduled }
+    }
+}
+
+impl Job for RefreshUpstreamOAuthRegistrationsJob {
+    const NAME: &'static str = "refresh-upstream-oauth-registrations";
+}
+
+impl TracedJob for RefreshUpstreamOAuthRegistrationsJob {}
+
+/// How long before they expire the client secrets issued through dynamic
+/// client registration get renewed
+const REGISTRATION_REFRESH_MARGIN_HOURS: i64 = 24;
+
+/// Renew the client secrets issued by the upstream providers this service
+/// registered with, when they are about to expire.
+///
+/// The registrations are updated through the client configuration endpoint of
+/// the providers, which issue new secrets in the process. Registrations
+/// without one, or without a secret expiration, are left alone.
+pub async fn refresh_upstream_oauth_registrations(
+    job: RefreshUpstreamOAuthRegistrationsJob,
+    ctx: JobContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    debug!(
+        "refresh upstream OAuth registrations job scheduled at {}",
+        job.scheduled
+    );
+
+    let state = ctx.state();
+    let clock = state.clock();
+    let http_service = state
+        .http_client_factory()
+        .http_service("upstream_oauth2.registration");
+    let threshold = clock.now() + Duration::hours(REGISTRATION_REFRESH_MARGIN_HOURS);
+
+    // Don't keep a transaction open while talking to the providers
+    let mut repo = state.repository().await?;
+    let providers = repo.upstream_oauth_provider().all().await?;
+    let mut registrations = Vec::new();
+    for provider in providers {
+        let Some(registration) = repo
+            .upstream_oauth_provider()
+            .registration(&provider)
+            .await?
+        else {
+            continue;
+        };
+
+        if registration
+            .client_secret_expires_at
+            .is_some_and(|expires_at| expires_at < threshold)
+        {
+            registrations.push((provider, registration));
+        }
+    }
+    repo.cancel().await?;
+
+    let mut updates = Vec::with_capacity(registrations.len());
+    for (provider, registration) in registrations {
+        match update_registration(&state, &http_service, &provider, &registration).await {
+            Ok(response) => updates.push((provider, registration, response)),
+            Err(e) => {
+                error!(
+                    upstream_oauth_provider.id = %provider.id,
+                    error = &e as &dyn std::error::Error,
+                    "Failed to renew the client secret issued by the provider"
+                );
+            }
+        }
+    }
+
+    let encrypter = state.encrypter();
+    let mut repo = state.repository().await?;
+    let count = updates.len();
+    for (provider, registration, response) in updates {
+        let encrypted_client_secret = response
+            .client_secret
+            .map(|client_secret| encrypter.encrypt_to_string(client_secret.as_bytes()))
+            .transpose()?;
+
+        // Providers may not rotate the registration access token, in which case
+        // the previous one stays valid
+        let encrypted_registration_access_token = match response.registration_access_token {
+            Some(token) => Some(encrypter.encrypt_to_string(token.as_bytes())?),
+            None => registration.encrypted_registration_access_token,
+        };
+        let registration_client_uri = response
+            .registration_client_uri
+            .or(registration.registration_client_uri);
+
+        repo.upstream_oauth_provider()
+            .set_registration(
+                &clock,
+                provider,
+                response.client_id,
+                encrypted_client_secret,
+                encrypted_registration_access_token,
+                registration_client_uri,
+                response.client_secret_expires_at,
+            )
+            .await?;
+    }
+    repo.save().await?;
+
+    info!(count, "renewed upstream OAuth client secrets");
+
+    Ok(())
+}
+
+#[derive(Debug, thiserror::Error)]
+enum UpdateRegistrationError {
+    #[error("The registration can't be managed")]
+    NotManageable,
+
+    #[error(transparent)]
+    Decrypt(#[from] mas_keystore::DecryptError),
+
+    #[error("The stored credentials are invalid")]
+    InvalidCredentials(#[from] std::string::FromUtf8Error),
+
+    #[error(transparent)]
+    Metadata(#[from] ClientMetadataVerificationError),
+
+    #[error(transparent)]
+    Registration(#[from] RegistrationError),
+}
+
+/// Send the metadata of this service to the client configuration endpoint of
+/// a provider, so that it issues a new client secret
+async fn update_registration(
+    state: &State,
+    http_service: &mas_http::HttpService,
+    provider: &UpstreamOAuthProvider,
+    registration: &UpstreamOAuthProviderRegistration,
+) -> Result<ClientRegistrationResponse, UpdateRegistrationError> {
+    let encrypter = state.encrypter();
+    let url_builder = state.url_builder();
+
+    let (Some(registration_client_uri), Some(encrypted_registration_access_token)) = (
+        &registration.registration_client_uri,
+        &registration.encrypted_registration_access_token,
+    ) else {
+        return Err(UpdateRegistrationError::NotManageable);
+    };
+
+    let registration_access_token =
+        String::from_utf8(encrypter.decrypt_string(encrypted_registration_access_token)?)?;
+    let client_secret = provider
+        .encrypted_client_secret
+        .as_deref()
+        .map(|encrypted_client_secret| {
+            let decrypted = encrypter.decrypt_string(encrypted_client_secret)?;
+            Ok::<_, UpdateRegistrationError>(String::from_utf8(decrypted)?)
+        })
+        .transpose()?;
+
+    let client_metadata = client_metadata_for_provider(
+        provider,
+        url_builder.upstream_oauth_callback(provider.id, provider.slug.as_deref()),
+        url_builder.jwks_uri(),
+    )?;
+
+    let response = mas_oidc_client::requests::registration::update_client(
+        http_service,
+        registration_client_uri,
+        &registration_access_token,
+        provider.client_id.clone(),
+        client_secret,
+        client_metadata,
+    )
+    .await?;
+
+    Ok(response)
+}
+
+pub(crate) fn register(
+    suffix: &str,
+    monitor: Monitor<TokioExecutor>,
+    state: &State,
+) -> Monitor<TokioExecutor> {
+    let schedule = apalis_cron::Schedule::from_str("0 */15 * * * *").unwrap();
+    let worker_name = format!(
+        "{job}-{suffix}",
+        job = RefreshUpstreamOAuthMetadataJob::NAME
+    );
+    let worker = WorkerBuilder::new(worker_name)
+        .stream(CronStream::new(schedule).timer(TokioTimer).to_stream())
+        .layer(state.inject())
+        .layer(metrics_layer())
+        .layer(trace_layer())
+        .build_fn(refresh_upstream_oauth_metadata);
+
+    let monitor = monitor.register(worker);
+
+    let schedule = apalis_cron::Schedule::from_str("0 * * * * *").unwrap();
+    let worker_name = format!("{job}-{suffix}", job = RefreshUpstreamOAuthTokensJob::NAME);
+    let worker = WorkerBuilder::new(worker_name)
+        .stream(CronStream::new(schedule).timer(TokioTimer).to_stream())
+        .layer(state.inject())
+        .layer(metrics_layer())
+        .layer(trace_layer())
+        .build_fn(refresh_upstream_oauth_tokens);
+
+    let monitor = monitor.register(worker);
+
+    let schedule = apalis_cron::Schedule::from_str("0 0 * * * *").unwrap();
+    let worker_name = format!(
+        "{job}-{suffix}",
+        job = RefreshUpstreamOAuthRegistrationsJob::NAME
+    );
+    let worker = WorkerBuilder::new(worker_name)
+        .stream(CronStream::new(schedule).timer(TokioTimer).to_stream())
+        .layer(state.inject())
+        .layer(metrics_layer())
+        .layer(trace_layer())
+        .build_fn(refresh_upstream_oauth_registrations);
+
+    monitor.register(worker)
+}
@@ -15,21 +15,37 @@
 use anyhow::Context;
 use apalis_core::{context::JobContext, executor::TokioExecutor, monitor::Monitor};
 use mas_storage::{
-    job::{DeactivateUserJob, JobWithSpanContext},
-    user::UserRepository,
-    RepositoryAccess,
+    compat::{CompatSessionFilter, CompatSessionRepository},
+    job::{
+        DeactivateUserJob, JobRepositoryExt, JobWithSpanContext, NotifyUserEventJob,
+        UserLifecycleEvent,
+    },
+    oauth2::{OAuth2SessionFilter, OAuth2SessionRepository},
+    upstream_oauth2::{UpstreamOAuthLinkFilter, UpstreamOAuthLinkRepository},
+    user::{
+        BrowserSessionFilter, BrowserSessionRepository, UserEmailRepository,
+        UserImpersonationRepository, UserRepository,
+    },
+    Pagination, RepositoryAccess,
 };
 use tracing::info;
 
 use crate::{storage::PostgresStorageFactory, JobContextExt, State};
 
+/// How many sessions or links are processed at once when revoking them
+const BATCH_SIZE: usize = 100;
+
 /// Job to deactivate a user, both locally and on the Matrix homeserver.
+///
+/// This locks the user, ends all their sessions, optionally erases their
+/// personal information, and finally deactivates them on the homeserver.
 #[tracing::instrument(
     name = "job.deactivate_user"
-    fields(user.id = %job.user_id(), erase = %job.hs_erase()),
+    fields(user.id = %job.user_id(), erase = %job.hs_erase(), erase_pii = %job.erase_pii()),
     skip_all,
     err(Debug),
 )]
+#[allow(clippy::too_many_lines)]
 async fn deactivate_user(
     job: JobWithSpanContext<DeactivateUserJob>,
     ctx: JobContext,
@@ -52,11 +68,119 @@ async fn deactivate_user(
         .await
         .context("Failed to lock user")?;
 
-    // TODO: delete the sessions & access tokens
+    // Then end all the sessions of the user. Finished sessions are excluded from
+    // the filters, so we keep fetching the first page until there is nothing
+    // left.
+    let filter = CompatSessionFilter::new().for_user(&user).active_only();
+    loop {
+        let page = repo
+            .compat_session()
+            .list(filter, Pagination::first(BATCH_SIZE))
+            .await?;
+
+        for (compat_session, _) in page.edges {
+            info!(%compat_session.id, "Finishing compat session");
+            repo.compat_session().finish(&clock, compat_session).await?;
+        }
+
+        if !page.has_next_page {
+            break;
+        }
+    }
+
+    let filter = OAuth2SessionFilter::new().for_user(&user).active_only();
+    loop {
+        let page = repo
+            .oauth2_session()
+            .list(filter, Pagination::first(BATCH_SIZE))
+            .await?;
+
+        for oauth2_session in page.edges {
+            info!(%oauth2_session.id, "Finishing OAuth 2.0 session");
+            repo.oauth2_session().finish(&clock, oauth2_session).await?;
+        }
+
+        if !page.has_next_page {
+            break;
+        }
+    }
+
+    let filter = BrowserSessionFilter::new().for_user(&user).active_only();
+    loop {
+        let page = repo
+            .browser_session()
+            .list(filter, Pagination::first(BATCH_SIZE))
+            .await?;
+
+        for browser_session in page.edges {
+            info!(%browser_session.id, "Finishing browser session");
+            if browser_session.is_impersonation() {
+                repo.user_impersonation()
+                    .end(&clock, &browser_session)
+                    .await?;
+            }
+
+            repo.browser_session()
+                .finish(&clock, browser_session)
+                .await?;
+        }
+
+        if !page.has_next_page {
+            break;
+        }
+    }
+
+    if job.erase_pii() {
+        let user_emails = repo.user_email().all(&user).await?;
+        for user_email in user_emails {
+            info!(%user_email.id, "Erasing email address");
+            repo.user_email().remove(user_email).await?;
+        }
+
+        let filter = UpstreamOAuthLinkFilter::new().for_user(&user);
+        loop {
+            let page = repo
+                .upstream_oauth_link()
+                .list(filter, Pagination::first(BATCH_SIZE))
+                .await?;
+
+            for link in page.edges {
+                info!(upstream_oauth_link.id = %link.id, "Erasing upstream OAuth link");
+                repo.upstream_oauth_link()
+                    .dissociate_from_user(link)
+                    .await?;
+            }
+
+            if !page.has_next_page {
+                break;
+            }
+        }
+
+        // Those were imported from the upstream claims
+        repo.user()
+            .set_upstream_groups(user.clone(), Vec::new())
+            .await?;
+    }
+
+    repo.job()
+        .schedule_job(NotifyUserEventJob::new(
+            &user,
+            UserLifecycleEvent::Deactivated,
+        ))
+        .await?;
 
     // Before calling back to the homeserver, commit the changes to the database
     repo.save().await?;
 
+    info!(
+        audit = true,
+        audit.action = "user.deactivate",
+        user.id = %user.id,
+        user.username = %user.username,
+        erase_pii = job.erase_pii(),
+        "User deactivated"
+    );
+
     let mxid = matrix.mxid(&user.username);
     info!("Deactivating user {} on homeserver", mxid);
     matrix.delete_user(&mxid, job.hs_erase()).await?;
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use apalis_core::{job::Job, request::JobRequest};
+use chrono::Duration;
 use mas_storage::job::JobWithSpanContext;
 use mas_tower::{
     make_span_fn, DurationRecorderLayer, FnWrapper, IdentityLayer, InFlightCounterLayer,
@@ -25,6 +26,12 @@ use tracing_opentelemetry::OpenTelemetrySpanExt;
 const JOB_NAME: Key = Key::from_static_str("job.name");
 const JOB_STATUS: Key = Key::from_static_str("job.status");
 
+/// Delay before the next attempt of a job, doubling every time, starting at
+/// 30 seconds
+pub(crate) fn backoff(attempt: u32) -> Duration {
+    Duration::seconds(30 << attempt.min(10))
+}
+
 /// Represents a job that can may have a span context attached to it.
 pub trait TracedJob: Job {
     /// Returns the span context for this job, if any.
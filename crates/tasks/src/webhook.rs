@@ -0,0 +1,271 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::Context;
+use apalis_core::{context::JobContext, executor::TokioExecutor, monitor::Monitor};
+use base64ct::{Base64, Encoding};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use http::{header::CONTENT_TYPE, Method, Request, StatusCode};
+use mas_http::HttpServiceExt;
+use mas_storage::{
+    job::{
+        DeliverWebhookJob, JobRepositoryExt, JobWithSpanContext, NotifyUserEventJob,
+        UserLifecycleEvent,
+    },
+    user::{UserEmailRepository, UserRepository},
+    Clock, RepositoryAccess,
+};
+use serde::Serialize;
+use sha2::Sha256;
+use tower::{Service, ServiceExt};
+use tracing::{info, warn};
+use ulid::Ulid;
+use url::Url;
+
+use crate::{storage::PostgresStorageFactory, utils::backoff, JobContextExt, State};
+
+/// Name of the header carrying the signature of the payload
+const SIGNATURE_HEADER: &str = "X-MAS-Signature";
+
+/// How many times a delivery is attempted before giving up
+const MAX_ATTEMPTS: u32 = 8;
+
+/// An HTTP endpoint notified about user lifecycle events
+#[derive(Debug, Clone)]
+pub struct WebhookEndpoint {
+    id: Ulid,
+    url: Url,
+    secret: String,
+    events: Vec<&'static str>,
+}
+
+impl WebhookEndpoint {
+    /// Create a new endpoint, notified about all events
+    #[must_use]
+    pub fn new(id: Ulid, url: Url, secret: String) -> Self {
+        Self {
+            id,
+            url,
+            secret,
+            events: Vec::new(),
+        }
+    }
+
+    /// Only notify this endpoint about the given events
+    #[must_use]
+    pub fn with_events(mut self, events: impl IntoIterator<Item = &'static str>) -> Self {
+        self.events = events.into_iter().collect();
+        self
+    }
+
+    fn wants(&self, event: &UserLifecycleEvent) -> bool {
+        self.events.is_empty() || self.events.contains(&event.name())
+    }
+}
+
+#[derive(Serialize)]
+struct WebhookPayload<'a> {
+    event: &'static str,
+    timestamp: DateTime<Utc>,
+    user: WebhookUser<'a>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    email: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct WebhookUser<'a> {
+    id: Ulid,
+    username: &'a str,
+    mxid: &'a str,
+    created_at: DateTime<Utc>,
+    locked_at: Option<DateTime<Utc>>,
+}
+
+/// Sign a payload, so that the endpoint can check that it was sent by us and
+/// was not replayed.
+///
+/// The signature is a HMAC-SHA256 of the timestamp and the body, separated by
+/// a dot.
+fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    let signature = Base64::encode_string(&mac.finalize().into_bytes());
+    format!("t={timestamp},v1={signature}")
+}
+
+/// Job to notify the configured webhooks about a user lifecycle event.
+///
+/// This schedules one delivery job per interested endpoint.
+#[tracing::instrument(
+    name = "job.notify_user_event"
+    fields(user.id = %job.user_id(), event = job.event().name()),
+    skip_all,
+    err(Debug),
+)]
+async fn notify_user_event(
+    job: JobWithSpanContext<NotifyUserEventJob>,
+    ctx: JobContext,
+) -> Result<(), anyhow::Error> {
+    let state = ctx.state();
+    let event = job.event();
+
+    let endpoints: Vec<_> = state
+        .webhooks()
+        .iter()
+        .filter(|endpoint| endpoint.wants(&event))
+        .collect();
+
+    if endpoints.is_empty() {
+        return Ok(());
+    }
+
+    let clock = state.clock();
+    let matrix = state.matrix_connection();
+    let mut repo = state.repository().await?;
+
+    let user = repo
+        .user()
+        .lookup(job.user_id())
+        .await?
+        .context("User not found")?;
+
+    let email = if let UserLifecycleEvent::EmailVerified { user_email_id } = event {
+        let user_email = repo
+            .user_email()
+            .lookup(user_email_id)
+            .await?
+            .context("User email not found")?;
+        Some(user_email.email)
+    } else {
+        None
+    };
+
+    let mxid = matrix.mxid(&user.username);
+    let payload = WebhookPayload {
+        event: event.name(),
+        timestamp: clock.now(),
+        user: WebhookUser {
+            id: user.id,
+            username: &user.username,
+            mxid: &mxid,
+            created_at: user.created_at,
+            locked_at: user.locked_at,
+        },
+        email: email.as_deref(),
+    };
+    let body = serde_json::to_string(&payload)?;
+
+    for endpoint in endpoints {
+        repo.job()
+            .schedule_job(DeliverWebhookJob::new(endpoint.id, body.clone()))
+            .await?;
+    }
+
+    repo.save().await?;
+
+    Ok(())
+}
+
+async fn send(state: &State, request: Request<Bytes>) -> Result<StatusCode, anyhow::Error> {
+    let mut client = state
+        .http_client_factory()
+        .client("webhook.deliver")
+        .request_bytes_to_body();
+
+    let response = client.ready().await?.call(request).await?;
+
+    Ok(response.status())
+}
+
+/// Job to deliver a payload to a webhook endpoint.
+///
+/// Failed deliveries are retried with an exponential backoff.
+#[tracing::instrument(
+    name = "job.deliver_webhook"
+    fields(webhook.id = %job.endpoint_id(), attempt = job.attempt()),
+    skip_all,
+    err(Debug),
+)]
+async fn deliver_webhook(
+    job: JobWithSpanContext<DeliverWebhookJob>,
+    ctx: JobContext,
+) -> Result<(), anyhow::Error> {
+    let state = ctx.state();
+    let clock = state.clock();
+
+    // Look up the endpoint from the configuration, so that the secret doesn't
+    // get stored in the job queue
+    let Some(endpoint) = state
+        .webhooks()
+        .iter()
+        .find(|endpoint| endpoint.id == job.endpoint_id())
+    else {
+        warn!("Webhook endpoint is not configured anymore, dropping the payload");
+        return Ok(());
+    };
+
+    let now = clock.now();
+    let signature = sign(&endpoint.secret, now.timestamp(), job.body());
+
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(endpoint.url.as_str())
+        .header(CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, signature)
+        .body(Bytes::from(job.body().to_owned()))?;
+
+    let error = match send(&state, request).await {
+        Ok(status) if status.is_success() => {
+            info!(webhook.url = %endpoint.url, "Webhook delivered");
+            return Ok(());
+        }
+        Ok(status) => anyhow::anyhow!("Endpoint replied with {status}"),
+        Err(e) => e,
+    };
+
+    let next = job.next_attempt();
+    if next.attempt() >= MAX_ATTEMPTS {
+        return Err(error.context("Giving up delivering the webhook"));
+    }
+
+    let run_at = now + backoff(job.attempt());
+    warn!(error = %error, %run_at, "Failed to deliver webhook, retrying later");
+
+    let mut repo = state.repository().await?;
+    repo.job().schedule_job_at(next, run_at).await?;
+    repo.save().await?;
+
+    Ok(())
+}
+
+pub(crate) fn register(
+    suffix: &str,
+    monitor: Monitor<TokioExecutor>,
+    state: &State,
+    storage_factory: &PostgresStorageFactory,
+) -> Monitor<TokioExecutor> {
+    let notify_user_event_worker =
+        crate::build!(NotifyUserEventJob => notify_user_event, suffix, state, storage_factory);
+    let deliver_webhook_worker =
+        crate::build!(DeliverWebhookJob => deliver_webhook, suffix, state, storage_factory);
+
+    monitor
+        .register(notify_user_event_worker)
+        .register(deliver_webhook_worker)
+}
@@ -14,16 +14,18 @@
 
 //! Contexts used in templates
 
-use std::fmt::Formatter;
+use std::{collections::BTreeMap, fmt::Formatter};
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use http::{Method, Uri, Version};
 use mas_data_model::{
     AuthorizationGrant, BrowserSession, Client, CompatSsoLogin, CompatSsoLoginState,
-    UpstreamOAuthLink, UpstreamOAuthProvider, User, UserEmail, UserEmailVerification,
+    ScopeDefinition, SecurityNotification, UpstreamOAuthLink, UpstreamOAuthProvider, User,
+    UserEmail, UserEmailChange, UserEmailVerification,
 };
 use mas_i18n::DataLocale;
 use mas_router::{Account, GraphQL, PostAuthAction, Route, UrlBuilder};
+use oauth2_types::scope::Scope;
 use rand::Rng;
 use serde::{ser::SerializeStruct, Deserialize, Serialize};
 use ulid::Ulid;
@@ -280,6 +282,7 @@ impl TemplateContext for IndexContext {
 pub struct AppConfig {
     root: String,
     graphql_endpoint: String,
+    embedding_origins: Vec<String>,
 }
 
 /// Context used by the `app.html` template
@@ -298,9 +301,18 @@ impl AppContext {
             app_config: AppConfig {
                 root,
                 graphql_endpoint,
+                embedding_origins: Vec::new(),
             },
         }
     }
+
+    /// Set the origins allowed to embed the app, which get notified when an
+    /// action is completed
+    #[must_use]
+    pub fn with_embedding_origins(mut self, embedding_origins: Vec<String>) -> Self {
+        self.app_config.embedding_origins = embedding_origins;
+        self
+    }
 }
 
 impl TemplateContext for AppContext {
@@ -341,6 +353,9 @@ pub enum PostAuthContextInner {
     ContinueAuthorizationGrant {
         /// The authorization grant that will be continued after authentication
         grant: Box<AuthorizationGrant>,
+
+        /// The client which requested the authorization grant
+        client: Box<Client>,
     },
 
     /// Continue legacy login
@@ -510,6 +525,9 @@ pub struct ConsentContext {
     grant: AuthorizationGrant,
     client: Client,
     action: PostAuthAction,
+    scope_definitions: BTreeMap<String, ScopeDefinition>,
+    new_scope: Option<Scope>,
+    remember_days: i64,
 }
 
 impl TemplateContext for ConsentContext {
@@ -528,6 +546,9 @@ impl TemplateContext for ConsentContext {
                     grant,
                     client,
                     action,
+                    scope_definitions: BTreeMap::new(),
+                    new_scope: None,
+                    remember_days: 90,
                 }
             })
             .collect()
@@ -536,13 +557,57 @@ impl TemplateContext for ConsentContext {
 
 impl ConsentContext {
     /// Constructs a context for the client consent page
+    ///
+    /// # Parameters
+    ///
+    /// * `grant`: The authorization grant to consent to
+    /// * `client`: The client which started the grant
+    /// * `remember_for`: How long to remember the consent, if the user asks to
     #[must_use]
-    pub fn new(grant: AuthorizationGrant, client: Client) -> Self {
+    pub fn new(grant: AuthorizationGrant, client: Client, remember_for: Duration) -> Self {
         let action = PostAuthAction::continue_grant(grant.id);
         Self {
             grant,
             client,
             action,
+            scope_definitions: BTreeMap::new(),
+            new_scope: None,
+            remember_days: remember_for.num_days(),
+        }
+    }
+
+    /// Set the scope the user already consented to, so that only the
+    /// requested scopes on top of it are shown
+    #[must_use]
+    pub fn with_granted_scope(self, granted: &Scope) -> Self {
+        let new_scope: Scope = self.grant.scope.difference(granted).cloned().collect();
+
+        // If the user already consented to everything, e.g. because the client
+        // explicitly asked for consent, show the full scope again
+        let new_scope = if granted.is_empty() || new_scope.is_empty() {
+            None
+        } else {
+            Some(new_scope)
+        };
+
+        Self { new_scope, ..self }
+    }
+
+    /// Set the definitions of the requested scopes, used to describe the
+    /// custom scopes
+    #[must_use]
+    pub fn with_scope_definitions(
+        self,
+        definitions: impl IntoIterator<Item = ScopeDefinition>,
+    ) -> Self {
+        let scope_definitions = definitions
+            .into_iter()
+            .map(|definition| (definition.name.clone(), definition))
+            .collect();
+
+        Self {
+            scope_definitions,
+            ..self
         }
     }
 }
@@ -553,6 +618,7 @@ pub struct PolicyViolationContext {
     grant: AuthorizationGrant,
     client: Client,
     action: PostAuthAction,
+    client_restricted: bool,
 }
 
 impl TemplateContext for PolicyViolationContext {
@@ -562,16 +628,17 @@ impl TemplateContext for PolicyViolationContext {
     {
         Client::samples(now, rng)
             .into_iter()
-            .map(|client| {
+            .flat_map(|client| {
                 let mut grant = AuthorizationGrant::sample(now, rng);
                 let action = PostAuthAction::continue_grant(grant.id);
                 // XXX
                 grant.client_id = client.id;
-                Self {
-                    grant,
-                    client,
-                    action,
-                }
+                [false, true].map(|client_restricted| Self {
+                    grant: grant.clone(),
+                    client: client.clone(),
+                    action: action.clone(),
+                    client_restricted,
+                })
             })
             .collect()
     }
@@ -580,6 +647,53 @@ impl TemplateContext for PolicyViolationContext {
 impl PolicyViolationContext {
     /// Constructs a context for the policy violation page
     #[must_use]
+    pub const fn new(grant: AuthorizationGrant, client: Client) -> Self {
+        let action = PostAuthAction::continue_grant(grant.id);
+        Self {
+            grant,
+            client,
+            action,
+            client_restricted: false,
+        }
+    }
+
+    /// Set whether the violation is caused by the client being restricted to
+    /// other users
+    #[must_use]
+    pub fn with_client_restricted(mut self, client_restricted: bool) -> Self {
+        self.client_restricted = client_restricted;
+        self
+    }
+}
+
+/// Context used by the `approval_pending.html` template
+#[derive(Serialize)]
+pub struct ApprovalPendingContext {
+    grant: AuthorizationGrant,
+    client: Client,
+    action: PostAuthAction,
+}
+
+impl TemplateContext for ApprovalPendingContext {
+    fn sample(now: chrono::DateTime<Utc>, rng: &mut impl Rng) -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        Client::samples(now, rng)
+            .into_iter()
+            .map(|client| {
+                let mut grant = AuthorizationGrant::sample(now, rng);
+                grant.client_id = client.id;
+                Self::new(grant, client)
+            })
+            .collect()
+    }
+}
+
+impl ApprovalPendingContext {
+    /// Constructs a context for the page shown while an authorization grant
+    /// waits for an administrator to approve it
+    #[must_use]
     pub const fn new(grant: AuthorizationGrant, client: Client) -> Self {
         let action = PostAuthAction::continue_grant(grant.id);
         Self {
@@ -643,6 +757,82 @@ impl ReauthContext {
     }
 }
 
+/// Fields of the profile completion form
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CompleteProfileFormField {
+    /// The email address field
+    Email,
+
+    /// The display name field
+    Displayname,
+
+    /// The terms of service checkbox
+    AcceptTerms,
+}
+
+impl FormField for CompleteProfileFormField {
+    fn keep(&self) -> bool {
+        match self {
+            Self::Email | Self::Displayname => true,
+            Self::AcceptTerms => false,
+        }
+    }
+}
+
+/// Context used by the `pages/complete_profile.html` template
+#[derive(Serialize)]
+pub struct CompleteProfileContext {
+    missing_email: bool,
+    missing_displayname: bool,
+    terms_uri: Option<Url>,
+    form: FormState<CompleteProfileFormField>,
+    next: Option<PostAuthContext>,
+}
+
+impl CompleteProfileContext {
+    /// Constructs a context asking for the given missing attributes
+    #[must_use]
+    pub fn new(missing_email: bool, missing_displayname: bool, terms_uri: Option<Url>) -> Self {
+        Self {
+            missing_email,
+            missing_displayname,
+            terms_uri,
+            form: FormState::default(),
+            next: None,
+        }
+    }
+
+    /// Set the form state
+    #[must_use]
+    pub fn with_form_state(self, form: FormState<CompleteProfileFormField>) -> Self {
+        Self { form, ..self }
+    }
+
+    /// Add a post authentication action to the context
+    #[must_use]
+    pub fn with_post_action(self, next: PostAuthContext) -> Self {
+        Self {
+            next: Some(next),
+            ..self
+        }
+    }
+}
+
+impl TemplateContext for CompleteProfileContext {
+    fn sample(_now: chrono::DateTime<Utc>, _rng: &mut impl Rng) -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        let terms_uri = Url::parse("https://example.com/terms").unwrap();
+        vec![
+            Self::new(true, true, Some(terms_uri.clone())),
+            Self::new(false, false, Some(terms_uri)),
+            Self::new(true, false, None),
+        ]
+    }
+}
+
 /// Context used by the `sso.html` template
 #[derive(Serialize)]
 pub struct CompatSsoContext {
@@ -733,6 +923,309 @@ impl TemplateContext for EmailVerificationContext {
     }
 }
 
+/// Context used by the `emails/email_change.{txt,html,subject}` templates
+#[derive(Serialize, Clone)]
+pub struct EmailChangeNotificationContext {
+    user: User,
+    old_email: UserEmail,
+    new_email: UserEmail,
+    revert_url: Url,
+    confirm_url: Option<Url>,
+    grace_period_days: i64,
+}
+
+impl EmailChangeNotificationContext {
+    /// Constructs a context for the email change notification
+    #[must_use]
+    pub fn new(user: User, old_email: UserEmail, new_email: UserEmail, revert_url: Url) -> Self {
+        Self {
+            user,
+            old_email,
+            new_email,
+            revert_url,
+            confirm_url: None,
+            grace_period_days: UserEmailChange::grace_period().num_days(),
+        }
+    }
+
+    /// Ask the old address to confirm the change before it happens, with the
+    /// given link
+    #[must_use]
+    pub fn with_confirm_url(mut self, confirm_url: Url) -> Self {
+        self.confirm_url = Some(confirm_url);
+        self
+    }
+
+    /// Get the user to which this email is being sent
+    #[must_use]
+    pub fn user(&self) -> &User {
+        &self.user
+    }
+
+    /// Get the address which is not primary anymore
+    #[must_use]
+    pub fn old_email(&self) -> &UserEmail {
+        &self.old_email
+    }
+
+    /// Get the address which became primary
+    #[must_use]
+    pub fn new_email(&self) -> &UserEmail {
+        &self.new_email
+    }
+}
+
+impl TemplateContext for EmailChangeNotificationContext {
+    fn sample(now: chrono::DateTime<Utc>, rng: &mut impl Rng) -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        User::samples(now, rng)
+            .into_iter()
+            .flat_map(|user| {
+                let old_email = UserEmail {
+                    id: Ulid::from_datetime_with_source(now.into(), rng),
+                    user_id: user.id,
+                    email: "foobar@example.com".to_owned(),
+                    created_at: now,
+                    confirmed_at: Some(now),
+                };
+
+                let new_email = UserEmail {
+                    id: Ulid::from_datetime_with_source(now.into(), rng),
+                    user_id: user.id,
+                    email: "foobar@example.org".to_owned(),
+                    created_at: now,
+                    confirmed_at: Some(now),
+                };
+
+                let revert_url = "https://example.com/email-change/revert/aabbccddeeff"
+                    .parse()
+                    .unwrap();
+                let confirm_url = "https://example.com/email-change/confirm/aabbccddeeff"
+                    .parse()
+                    .unwrap();
+
+                let notification = Self::new(
+                    user.clone(),
+                    old_email.clone(),
+                    new_email.clone(),
+                    revert_url,
+                );
+                let confirmation = notification.clone().with_confirm_url(confirm_url);
+
+                [notification, confirmation]
+            })
+            .collect()
+    }
+}
+
+/// Context used by the `emails/security/*.{txt,html,subject}` templates
+#[derive(Serialize)]
+pub struct SecurityNotificationContext {
+    user: User,
+    category: SecurityNotification,
+    upstream_provider_name: Option<String>,
+}
+
+impl SecurityNotificationContext {
+    /// Constructs a context for a security notification
+    #[must_use]
+    pub fn new(user: User, category: SecurityNotification) -> Self {
+        Self {
+            user,
+            category,
+            upstream_provider_name: None,
+        }
+    }
+
+    /// Set the upstream provider which was linked
+    #[must_use]
+    pub fn with_upstream_provider(mut self, provider: &UpstreamOAuthProvider) -> Self {
+        self.upstream_provider_name = Some(provider.human_name().to_owned());
+        self
+    }
+
+    /// Get the user to which this email is being sent
+    #[must_use]
+    pub fn user(&self) -> &User {
+        &self.user
+    }
+
+    /// Get the category of the notification
+    #[must_use]
+    pub fn category(&self) -> SecurityNotification {
+        self.category
+    }
+}
+
+impl TemplateContext for SecurityNotificationContext {
+    fn sample(now: chrono::DateTime<Utc>, rng: &mut impl Rng) -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        User::samples(now, rng)
+            .into_iter()
+            .flat_map(|user| {
+                SecurityNotification::ALL
+                    .into_iter()
+                    .map(move |category| Self {
+                        user: user.clone(),
+                        category,
+                        upstream_provider_name: (category == SecurityNotification::UpstreamLink)
+                            .then(|| "Example Accounts".to_owned()),
+                    })
+            })
+            .collect()
+    }
+}
+
+/// Context used by the `emails/authorization_approved.{txt,html,subject}`
+/// templates
+#[derive(Serialize)]
+pub struct AuthorizationApprovedContext {
+    user: User,
+    client: Client,
+    continue_url: Url,
+}
+
+impl AuthorizationApprovedContext {
+    /// Constructs a context for the email letting a user know that an
+    /// administrator approved their authorization request
+    #[must_use]
+    pub fn new(user: User, client: Client, continue_url: Url) -> Self {
+        Self {
+            user,
+            client,
+            continue_url,
+        }
+    }
+
+    /// Get the user to which this email is being sent
+    #[must_use]
+    pub fn user(&self) -> &User {
+        &self.user
+    }
+
+    /// Get the client the authorization request was made by
+    #[must_use]
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+}
+
+impl TemplateContext for AuthorizationApprovedContext {
+    fn sample(now: chrono::DateTime<Utc>, rng: &mut impl Rng) -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        let clients = Client::samples(now, rng);
+        User::samples(now, rng)
+            .into_iter()
+            .zip(clients)
+            .map(|(user, client)| {
+                let continue_url = format!(
+                    "https://example.com/authorize/{}",
+                    Ulid::from_datetime_with_source(now.into(), rng)
+                )
+                .parse()
+                .unwrap();
+
+                Self::new(user, client, continue_url)
+            })
+            .collect()
+    }
+}
+
+/// The state of the email change confirmation page
+#[derive(Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum EmailChangeConfirmContext {
+    /// The change can be confirmed or cancelled
+    Form {
+        /// The address which is about to be replaced
+        old_email: String,
+        /// The address which is about to replace it
+        new_email: String,
+    },
+
+    /// The link is invalid, or the change can't be confirmed anymore
+    Invalid,
+
+    /// The change was confirmed
+    Confirmed {
+        /// The address which is now primary
+        new_email: String,
+    },
+
+    /// The change was cancelled
+    Cancelled {
+        /// The address which stays primary
+        old_email: String,
+    },
+}
+
+impl TemplateContext for EmailChangeConfirmContext {
+    fn sample(_now: chrono::DateTime<Utc>, _rng: &mut impl Rng) -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        vec![
+            Self::Form {
+                old_email: "foobar@example.com".to_owned(),
+                new_email: "foobar@example.org".to_owned(),
+            },
+            Self::Invalid,
+            Self::Confirmed {
+                new_email: "foobar@example.org".to_owned(),
+            },
+            Self::Cancelled {
+                old_email: "foobar@example.com".to_owned(),
+            },
+        ]
+    }
+}
+
+/// The state of the email change revert page
+#[derive(Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum EmailChangeRevertContext {
+    /// The change can be reverted, ask for confirmation
+    Form {
+        /// The address which was replaced
+        old_email: String,
+        /// The address which replaced it
+        new_email: String,
+    },
+
+    /// The link is invalid, or the grace period is over
+    Invalid,
+
+    /// The change was reverted
+    Reverted {
+        /// The address which is primary again
+        old_email: String,
+    },
+}
+
+impl TemplateContext for EmailChangeRevertContext {
+    fn sample(_now: chrono::DateTime<Utc>, _rng: &mut impl Rng) -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        vec![
+            Self::Form {
+                old_email: "foobar@example.com".to_owned(),
+                new_email: "foobar@example.org".to_owned(),
+            },
+            Self::Invalid,
+            Self::Reverted {
+                old_email: "foobar@example.com".to_owned(),
+            },
+        ]
+    }
+}
+
 /// Fields of the email verification form
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, Hash, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
@@ -838,6 +1331,98 @@ impl TemplateContext for EmailAddContext {
     }
 }
 
+/// Fields of the impersonation form
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImpersonateFormField {
+    /// The username of the user to impersonate
+    Username,
+
+    /// The reason given for the impersonation
+    Reason,
+}
+
+impl FormField for ImpersonateFormField {
+    fn keep(&self) -> bool {
+        match self {
+            Self::Username | Self::Reason => true,
+        }
+    }
+}
+
+/// Context used by the `pages/impersonate.html` template
+#[derive(Serialize, Default)]
+pub struct ImpersonateContext {
+    form: FormState<ImpersonateFormField>,
+}
+
+impl ImpersonateContext {
+    /// Constructs a context for the impersonation page
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the form state
+    #[must_use]
+    pub fn with_form_state(form: FormState<ImpersonateFormField>) -> Self {
+        Self { form }
+    }
+}
+
+impl TemplateContext for ImpersonateContext {
+    fn sample(_now: chrono::DateTime<Utc>, _rng: &mut impl Rng) -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        vec![Self::default()]
+    }
+}
+
+/// Context used by the `pages/reset_cross_signing.html` template
+#[derive(Serialize, Default)]
+pub struct ResetCrossSigningContext {
+    approved: bool,
+    embedding_origins: Vec<String>,
+}
+
+impl ResetCrossSigningContext {
+    /// Constructs a context asking the user to approve the reset
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Constructs a context telling the user the reset was approved
+    #[must_use]
+    pub fn approved() -> Self {
+        Self {
+            approved: true,
+            ..Self::default()
+        }
+    }
+
+    /// Set the origins allowed to embed the page, which get notified when the
+    /// reset is approved
+    #[must_use]
+    pub fn with_embedding_origins(mut self, embedding_origins: Vec<String>) -> Self {
+        self.embedding_origins = embedding_origins;
+        self
+    }
+}
+
+impl TemplateContext for ResetCrossSigningContext {
+    fn sample(_now: chrono::DateTime<Utc>, _rng: &mut impl Rng) -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        vec![
+            Self::new(),
+            Self::approved().with_embedding_origins(vec!["https://app.example.com".to_owned()]),
+        ]
+    }
+}
+
 /// Context used by the `pages/upstream_oauth2/{link_mismatch,do_login}.html`
 /// templates
 #[derive(Serialize)]
@@ -904,8 +1489,11 @@ pub struct UpstreamRegister {
     force_localpart: bool,
     suggested_display_name: Option<String>,
     force_display_name: bool,
+    suggested_avatar_url: Option<String>,
+    force_avatar_url: bool,
     suggested_email: Option<String>,
     force_email: bool,
+    localpart_taken: Option<String>,
 }
 
 impl UpstreamRegister {
@@ -927,12 +1515,24 @@ impl UpstreamRegister {
         self.force_display_name = force;
     }
 
+    /// Set the suggested avatar URL
+    pub fn set_avatar_url(&mut self, avatar_url: String, force: bool) {
+        self.suggested_avatar_url = Some(avatar_url);
+        self.force_avatar_url = force;
+    }
+
     /// Set the suggested email
     pub fn set_email(&mut self, email: String, force: bool) {
         self.suggested_email = Some(email);
         self.force_email = force;
     }
 
+    /// Let the user know that the given localpart is already taken, and that
+    /// they should choose another one
+    pub fn set_localpart_taken(&mut self, localpart: String) {
+        self.localpart_taken = Some(localpart);
+    }
+
     fn for_link_id(id: Ulid) -> Self {
         let login_link = mas_router::Login::and_link_upstream(id)
             .path_and_query()
@@ -944,8 +1544,11 @@ impl UpstreamRegister {
             force_localpart: false,
             suggested_display_name: None,
             force_display_name: false,
+            suggested_avatar_url: None,
+            force_avatar_url: false,
             suggested_email: None,
             force_email: false,
+            localpart_taken: None,
         }
     }
 }
@@ -960,6 +1563,85 @@ impl TemplateContext for UpstreamRegister {
     }
 }
 
+/// Context used by the `pages/upstream_oauth2/link_existing.html` template,
+/// shown when the localpart imported from the upstream provider belongs to an
+/// existing account, which the user has to prove they own to link it
+#[derive(Serialize)]
+pub struct UpstreamLinkExistingContext {
+    login_link: String,
+    username: String,
+    can_send_code: bool,
+    code_sent: bool,
+    invalid_code: bool,
+}
+
+impl UpstreamLinkExistingContext {
+    /// Constructs a new context for linking to the given existing user
+    #[must_use]
+    pub fn new(link: &UpstreamOAuthLink, user: &User) -> Self {
+        Self::for_link_id(link.id, user.username.clone())
+    }
+
+    /// Let the user prove they own the account with a code sent to its email
+    /// address
+    #[must_use]
+    pub fn with_email_code(self) -> Self {
+        Self {
+            can_send_code: true,
+            ..self
+        }
+    }
+
+    /// Let the user know that a code was sent to the email address of the
+    /// account
+    #[must_use]
+    pub fn with_code_sent(self) -> Self {
+        Self {
+            code_sent: true,
+            ..self
+        }
+    }
+
+    /// Let the user know that the code they entered is invalid
+    #[must_use]
+    pub fn with_invalid_code(self) -> Self {
+        Self {
+            invalid_code: true,
+            ..self
+        }
+    }
+
+    fn for_link_id(id: Ulid, username: String) -> Self {
+        let login_link = mas_router::Login::and_link_upstream(id)
+            .path_and_query()
+            .into();
+
+        Self {
+            login_link,
+            username,
+            can_send_code: false,
+            code_sent: false,
+            invalid_code: false,
+        }
+    }
+}
+
+impl TemplateContext for UpstreamLinkExistingContext {
+    fn sample(now: chrono::DateTime<Utc>, rng: &mut impl Rng) -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        let id = Ulid::from_datetime_with_source(now.into(), rng);
+        vec![
+            Self::for_link_id(id, "john".to_owned()),
+            Self::for_link_id(id, "john".to_owned())
+                .with_email_code()
+                .with_code_sent()
+                .with_invalid_code(),
+        ]
+    }
+}
+
 /// Context used by the `form_post.html` template
 #[derive(Serialize)]
 pub struct FormPostContext<T> {
@@ -1000,6 +1682,7 @@ pub struct ErrorContext {
     description: Option<String>,
     details: Option<String>,
     lang: Option<String>,
+    client: Option<Box<Client>>,
 }
 
 impl std::fmt::Display for ErrorContext {
@@ -1020,16 +1703,22 @@ impl std::fmt::Display for ErrorContext {
 }
 
 impl TemplateContext for ErrorContext {
-    fn sample(_now: chrono::DateTime<Utc>, _rng: &mut impl Rng) -> Vec<Self>
+    fn sample(now: chrono::DateTime<Utc>, rng: &mut impl Rng) -> Vec<Self>
     where
         Self: Sized,
     {
+        let client = Client::samples(now, rng).into_iter().next().unwrap();
+
         vec![
             Self::new()
                 .with_code("sample_error")
                 .with_description("A fancy description".into())
                 .with_details("Something happened".into()),
             Self::new().with_code("another_error"),
+            Self::new()
+                .with_code("grant_not_pending")
+                .with_description("The authorization request was already completed".into())
+                .with_client(client),
             Self::new(),
         ]
     }
@@ -1070,6 +1759,14 @@ impl ErrorContext {
         self
     }
 
+    /// Add the client on behalf of which the error is shown, to brand the
+    /// error page
+    #[must_use]
+    pub fn with_client(mut self, client: Client) -> Self {
+        self.client = Some(Box::new(client));
+        self
+    }
+
     /// Get the error code, if any
     #[must_use]
     pub fn code(&self) -> Option<&'static str> {
@@ -0,0 +1,116 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Catalog of the stable codes shown on error pages
+//!
+//! Each code can have its own template, `pages/errors/<code>.html`, which
+//! replaces `pages/error.html` for errors with that code.
+
+/// An entry of the error code catalog
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorCode {
+    /// The stable code of the error
+    pub code: &'static str,
+
+    /// The template used instead of `pages/error.html`, if it exists
+    pub template: &'static str,
+
+    doc: &'static str,
+}
+
+impl ErrorCode {
+    /// What the error means
+    #[must_use]
+    pub fn description(&self) -> &'static str {
+        self.doc.trim()
+    }
+}
+
+macro_rules! error_codes {
+    ($( $(#[doc = $doc:literal])+ $name:ident = $code:literal; )*) => {
+        $(
+            $(#[doc = $doc])+
+            pub const $name: &str = $code;
+        )*
+
+        /// All the error codes, with their description
+        pub const CATALOG: &[ErrorCode] = &[
+            $(
+                ErrorCode {
+                    code: $code,
+                    template: concat!("pages/errors/", $code, ".html"),
+                    doc: concat!($($doc),+),
+                },
+            )*
+        ];
+    };
+}
+
+error_codes! {
+    /// An unexpected error happened while handling the request
+    INTERNAL_ERROR = "internal_error";
+
+    /// The authorization request was already completed
+    GRANT_NOT_PENDING = "grant_not_pending";
+
+    /// The legacy SSO login request expired
+    COMPAT_SSO_LOGIN_EXPIRED = "compat_sso_login_expired";
+
+    /// The user is quarantined and can't log in to new clients
+    USER_QUARANTINED = "user_quarantined";
+
+    /// Administrators impersonating a user can't log in to clients on their
+    /// behalf
+    IMPERSONATION_NOT_ALLOWED = "impersonation_not_allowed";
+
+    /// The policy denied logging in with the upstream provider
+    UPSTREAM_LOGIN_DENIED = "upstream_login_denied";
+
+    /// The policy denied registering an account from the upstream provider
+    UPSTREAM_REGISTRATION_DENIED = "upstream_registration_denied";
+
+    /// The username imported from the upstream provider is already taken
+    UPSTREAM_USERNAME_TAKEN = "upstream_username_taken";
+
+    /// The policy denied adding the email address
+    EMAIL_DENIED = "email_denied";
+}
+
+/// Look up an error code in the catalog
+#[must_use]
+pub fn lookup(code: &str) -> Option<&'static ErrorCode> {
+    CATALOG.iter().find(|entry| entry.code == code)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn catalog() {
+        let entry = lookup(GRANT_NOT_PENDING).unwrap();
+        assert_eq!(entry.template, "pages/errors/grant_not_pending.html");
+        assert_eq!(
+            entry.description(),
+            "The authorization request was already completed"
+        );
+        assert!(lookup("not_a_code").is_none());
+
+        // Codes must be unique
+        let codes: HashSet<_> = CATALOG.iter().map(|entry| entry.code).collect();
+        assert_eq!(codes.len(), CATALOG.len());
+    }
+}
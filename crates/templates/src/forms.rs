@@ -56,6 +56,12 @@ pub enum FormError {
     /// Password fields don't match
     PasswordMismatch,
 
+    /// Too many attempts were made recently
+    RateLimitExceeded,
+
+    /// Requests from the network of the user are denied
+    NetworkDenied,
+
     /// There was an internal error
     Internal,
 
@@ -18,14 +18,18 @@
 //! Additional functions, tests and filters used in templates
 
 use std::{
-    collections::{BTreeSet, HashMap},
+    collections::{BTreeMap, BTreeSet, HashMap},
     fmt::Formatter,
     str::FromStr,
     sync::Arc,
 };
 
 use camino::Utf8Path;
-use mas_i18n::{sprintf::FormattedMessagePart, Argument, ArgumentList, DataLocale, Translator};
+use chrono::{DateTime, Utc};
+use mas_i18n::{
+    format_datetime, sprintf::FormattedMessagePart, Argument, ArgumentList, DataLocale, Direction,
+    Translator,
+};
 use mas_router::UrlBuilder;
 use mas_spa::ViteManifest;
 use minijinja::{
@@ -36,11 +40,15 @@ use minijinja::{
 };
 use url::Url;
 
+use crate::theme::{CompiledTheme, ThemeGlobal};
+
 pub fn register(
     env: &mut minijinja::Environment,
     url_builder: UrlBuilder,
     vite_manifest: ViteManifest,
     translator: Arc<Translator>,
+    theme: &CompiledTheme,
+    custom_context: &BTreeMap<String, serde_json::Value>,
 ) {
     env.add_test("empty", self::tester_empty);
     env.add_test("starting_with", tester_starting_with);
@@ -48,6 +56,8 @@ pub fn register(
     env.add_filter("simplify_url", filter_simplify_url);
     env.add_filter("add_slashes", filter_add_slashes);
     env.add_filter("split", filter_split);
+    env.add_filter("text_direction", filter_text_direction);
+    env.add_filter("format_datetime", filter_format_datetime);
     env.add_function("add_params_to_url", function_add_params_to_url);
     env.add_global(
         "include_asset",
@@ -56,10 +66,41 @@ pub fn register(
             vite_manifest,
         }),
     );
+    let mut available_languages: Vec<String> = translator
+        .available_locales()
+        .into_iter()
+        .map(ToString::to_string)
+        .collect();
+    available_languages.sort();
+    env.add_global(
+        "available_languages",
+        Value::from_serializable(&available_languages),
+    );
     env.add_global(
         "translator",
         Value::from_object(TranslatorFunc { translator }),
     );
+    env.add_global(
+        "custom",
+        Value::from_object(CustomContextFunc {
+            values: custom_context
+                .iter()
+                .map(|(key, value)| (key.clone(), Value::from_serializable(value)))
+                .collect(),
+        }),
+    );
+    env.add_global(
+        "theme",
+        Value::from_serializable(&ThemeGlobal {
+            logo_uri: theme.logo_uri.clone(),
+            favicon_uri: theme.favicon_uri.clone(),
+            stylesheet_url: theme.stylesheet.as_ref().map(|stylesheet| {
+                url_builder.relative_url_for(&mas_router::ThemeStylesheet::new(
+                    stylesheet.version.clone(),
+                ))
+            }),
+        }),
+    );
     env.add_filter("prefix_url", move |url: &str| -> String {
         if !url.starts_with('/') {
             // Let's assume it's not an internal URL and return it as-is
@@ -90,6 +131,35 @@ fn filter_split(value: &str, separator: &str) -> Vec<String> {
         .collect()
 }
 
+/// The direction of a language, as the value of the HTML `dir` attribute
+fn filter_text_direction(lang: &str) -> Result<&'static str, Error> {
+    let lang: DataLocale = lang
+        .parse()
+        .map_err(|e| Error::new(ErrorKind::InvalidOperation, "Invalid language").with_source(e))?;
+
+    Ok(Direction::of(&lang).as_str())
+}
+
+/// Format a timestamp for humans in the given language, in UTC.
+///
+/// Pages localise it to the time zone of the browser when it is in a `<time>`
+/// element, see `base.html`
+fn filter_format_datetime(value: &str, lang: &str) -> Result<String, Error> {
+    let lang: DataLocale = lang
+        .parse()
+        .map_err(|e| Error::new(ErrorKind::InvalidOperation, "Invalid language").with_source(e))?;
+
+    let datetime: DateTime<Utc> = DateTime::parse_from_rfc3339(value)
+        .map_err(|e| Error::new(ErrorKind::InvalidOperation, "Invalid timestamp").with_source(e))?
+        .with_timezone(&Utc);
+
+    let formatted = format_datetime(&lang, datetime).map_err(|e| {
+        Error::new(ErrorKind::InvalidOperation, "Could not format timestamp").with_source(e)
+    })?;
+
+    Ok(format!("{formatted} UTC"))
+}
+
 fn filter_add_slashes(value: &str) -> String {
     value
         .replace('\\', "\\\\")
@@ -315,6 +385,51 @@ impl Object for TranslateFunc {
     }
 }
 
+/// Look up a value from the custom context set in the configuration
+///
+/// Unknown keys are an error unless a `default` is given, so that typos are
+/// caught when checking the templates.
+struct CustomContextFunc {
+    values: BTreeMap<String, Value>,
+}
+
+impl std::fmt::Debug for CustomContextFunc {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CustomContextFunc")
+            .field("keys", &self.values.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl std::fmt::Display for CustomContextFunc {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("custom")
+    }
+}
+
+impl Object for CustomContextFunc {
+    fn call(&self, _state: &State, args: &[Value]) -> Result<Value, Error> {
+        let (key, kwargs): (&str, Kwargs) = from_args(args)?;
+        // `default=none` is a valid default, so check for the presence of the
+        // argument rather than its value
+        let default = if kwargs.has("default") {
+            Some(kwargs.get::<Value>("default")?)
+        } else {
+            None
+        };
+        kwargs.assert_all_used()?;
+
+        match (self.values.get(key), default) {
+            (Some(value), _) => Ok(value.clone()),
+            (None, Some(default)) => Ok(default),
+            (None, None) => Err(Error::new(
+                ErrorKind::UndefinedError,
+                format!("Unknown custom context key {key:?}"),
+            )),
+        }
+    }
+}
+
 struct IncludeAsset {
     url_builder: UrlBuilder,
     vite_manifest: ViteManifest,
@@ -377,3 +492,41 @@ impl Object for IncludeAsset {
         Ok(Value::from_safe_string(tags.join("\n")))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_context() {
+        let mut env = minijinja::Environment::new();
+        env.add_global(
+            "custom",
+            Value::from_object(CustomContextFunc {
+                values: [(
+                    "support_url".to_owned(),
+                    Value::from("https://example.com/"),
+                )]
+                .into_iter()
+                .collect(),
+            }),
+        );
+
+        let render = |template: &str| env.render_str(template, ());
+        assert_eq!(
+            render(r#"{{ custom("support_url") }}"#).unwrap(),
+            "https://example.com/"
+        );
+        assert_eq!(
+            render(r#"{{ custom("banner", default="none") }}"#).unwrap(),
+            "none"
+        );
+        assert_eq!(
+            render(r#"{% if custom("banner", default=none) %}banner{% endif %}"#).unwrap(),
+            ""
+        );
+
+        // Typos are caught
+        assert!(render(r#"{{ custom("suport_url") }}"#).is_err());
+    }
+}
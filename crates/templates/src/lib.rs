@@ -24,12 +24,15 @@
 
 //! Templates rendering
 
-use std::{collections::HashSet, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::Arc,
+};
 
 use anyhow::Context as _;
 use arc_swap::ArcSwap;
 use camino::{Utf8Path, Utf8PathBuf};
-use mas_i18n::Translator;
+use mas_i18n::{locale, Translator};
 use mas_router::UrlBuilder;
 use mas_spa::ViteManifest;
 use rand::Rng;
@@ -40,22 +43,30 @@ use tracing::{debug, info, warn};
 use walkdir::DirEntry;
 
 mod context;
+pub mod error_codes;
 mod forms;
 mod functions;
+mod theme;
 
 #[macro_use]
 mod macros;
 
+use self::theme::CompiledTheme;
 pub use self::{
     context::{
-        AppContext, CompatSsoContext, ConsentContext, EmailAddContext, EmailVerificationContext,
-        EmailVerificationPageContext, EmptyContext, ErrorContext, FormPostContext, IndexContext,
-        LoginContext, LoginFormField, NotFoundContext, PolicyViolationContext, PostAuthContext,
+        AppContext, ApprovalPendingContext, AuthorizationApprovedContext, CompatSsoContext,
+        CompleteProfileContext, CompleteProfileFormField, ConsentContext, EmailAddContext,
+        EmailChangeConfirmContext, EmailChangeNotificationContext, EmailChangeRevertContext,
+        EmailVerificationContext, EmailVerificationPageContext, EmptyContext, ErrorContext,
+        FormPostContext, ImpersonateContext, ImpersonateFormField, IndexContext, LoginContext,
+        LoginFormField, NotFoundContext, PolicyViolationContext, PostAuthContext,
         PostAuthContextInner, ReauthContext, ReauthFormField, RegisterContext, RegisterFormField,
-        TemplateContext, UpstreamExistingLinkContext, UpstreamRegister, UpstreamSuggestLink,
-        WithCsrf, WithLanguage, WithOptionalSession, WithSession,
+        ResetCrossSigningContext, SecurityNotificationContext, TemplateContext,
+        UpstreamExistingLinkContext, UpstreamLinkExistingContext, UpstreamRegister,
+        UpstreamSuggestLink, WithCsrf, WithLanguage, WithOptionalSession, WithSession,
     },
     forms::{FieldError, FormError, FormField, FormState, ToFormState},
+    theme::{Color, CompiledStylesheet, InvalidColor, Theme, ThemeColors},
 };
 
 /// Escape the given string for use in HTML
@@ -72,10 +83,15 @@ pub fn escape_html(input: &str) -> String {
 pub struct Templates {
     environment: Arc<ArcSwap<minijinja::Environment<'static>>>,
     translator: Arc<ArcSwap<Translator>>,
+    theme: Arc<ArcSwap<CompiledTheme>>,
     url_builder: UrlBuilder,
     vite_manifest_path: Utf8PathBuf,
     translations_path: Utf8PathBuf,
+    extra_translations_path: Option<Utf8PathBuf>,
     path: Utf8PathBuf,
+    email_overrides_path: Option<Utf8PathBuf>,
+    theme_config: Theme,
+    custom_context: BTreeMap<String, serde_json::Value>,
 }
 
 /// There was an issue while loading the templates
@@ -93,6 +109,10 @@ pub enum TemplateLoadingError {
     #[error("invalid assets manifest")]
     ViteManifest(#[from] serde_json::Error),
 
+    /// Failed to read the extra CSS of the theme
+    #[error("failed to read the extra CSS of the theme")]
+    ThemeCss(#[source] std::io::Error),
+
     /// Failed to load the translations
     #[error("failed to load the translations")]
     Translations(#[from] mas_i18n::LoadError),
@@ -138,44 +158,137 @@ fn is_hidden(entry: &DirEntry) -> bool {
         .is_some_and(|s| s.starts_with('.'))
 }
 
+/// Register all the templates found under `root` in the environment, prefixing
+/// their name with `prefix`
+fn register_from_directory(
+    env: &mut minijinja::Environment<'static>,
+    loaded: &mut HashSet<String>,
+    root: &Utf8Path,
+    prefix: &str,
+) -> Result<(), TemplateLoadingError> {
+    for entry in walkdir::WalkDir::new(root)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|e| !is_hidden(e))
+    {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            let path = Utf8PathBuf::try_from(entry.into_path())?;
+            let Some(ext) = path.extension() else {
+                continue;
+            };
+
+            if ext == "html" || ext == "txt" || ext == "subject" {
+                let relative = path.strip_prefix(root)?;
+                let name = format!("{prefix}{relative}");
+                debug!(%name, "Registering template");
+                let template = std::fs::read_to_string(&path)?;
+                env.add_template_owned(name.clone(), template)?;
+                loaded.insert(name);
+            } else if ext == "mjml" {
+                warn!(%path, "MJML templates must be compiled to HTML first, ignoring");
+            }
+        }
+    }
+
+    Ok(())
+}
+
 impl Templates {
     /// Load the templates from the given config
+    pub async fn load(
+        path: Utf8PathBuf,
+        url_builder: UrlBuilder,
+        vite_manifest_path: Utf8PathBuf,
+        translations_path: Utf8PathBuf,
+    ) -> Result<Self, TemplateLoadingError> {
+        Self::load_with_email_overrides(
+            path,
+            None,
+            url_builder,
+            vite_manifest_path,
+            translations_path,
+            None,
+            Theme::default(),
+            BTreeMap::new(),
+        )
+        .await
+    }
+
+    /// Load the templates from the given config, replacing the email templates
+    /// with the ones found in `email_overrides_path` and applying the `theme`
+    ///
+    /// Files in that directory override the templates under `emails/` with
+    /// the same name, like `verification.html` or `verification.txt`.
+    ///
+    /// The translations found in `extra_translations_path` are added on top of
+    /// the ones in `translations_path`. Their messages which are unknown to the
+    /// built-in English translations, or which use arguments it doesn't, are
+    /// skipped with a warning.
+    ///
+    /// The `custom_context` values are exposed to all templates through the
+    /// `custom` function.
     #[tracing::instrument(
         name = "templates.load",
         skip_all,
-        fields(%path),
+        fields(%path, ?email_overrides_path, ?extra_translations_path),
         err,
     )]
-    pub async fn load(
+    pub async fn load_with_email_overrides(
         path: Utf8PathBuf,
+        email_overrides_path: Option<Utf8PathBuf>,
         url_builder: UrlBuilder,
         vite_manifest_path: Utf8PathBuf,
         translations_path: Utf8PathBuf,
+        extra_translations_path: Option<Utf8PathBuf>,
+        theme: Theme,
+        custom_context: BTreeMap<String, serde_json::Value>,
     ) -> Result<Self, TemplateLoadingError> {
-        let (translator, environment) = Self::load_(
+        let (translator, environment, compiled_theme) = Self::load_(
             &path,
+            email_overrides_path.as_deref(),
             url_builder.clone(),
             &vite_manifest_path,
             &translations_path,
+            extra_translations_path.as_deref(),
+            &theme,
+            &custom_context,
         )
         .await?;
         Ok(Self {
             environment: Arc::new(ArcSwap::new(environment)),
             translator: Arc::new(ArcSwap::new(translator)),
+            theme: Arc::new(ArcSwap::new(compiled_theme)),
             path,
+            email_overrides_path,
+            theme_config: theme,
+            custom_context,
             url_builder,
             vite_manifest_path,
             translations_path,
+            extra_translations_path,
         })
     }
 
     async fn load_(
         path: &Utf8Path,
+        email_overrides_path: Option<&Utf8Path>,
         url_builder: UrlBuilder,
         vite_manifest_path: &Utf8Path,
         translations_path: &Utf8Path,
-    ) -> Result<(Arc<Translator>, Arc<minijinja::Environment<'static>>), TemplateLoadingError> {
+        extra_translations_path: Option<&Utf8Path>,
+        theme: &Theme,
+        custom_context: &BTreeMap<String, serde_json::Value>,
+    ) -> Result<
+        (
+            Arc<Translator>,
+            Arc<minijinja::Environment<'static>>,
+            Arc<CompiledTheme>,
+        ),
+        TemplateLoadingError,
+    > {
         let path = path.to_owned();
+        let email_overrides_path = email_overrides_path.map(ToOwned::to_owned);
         let span = tracing::Span::current();
 
         // Read the assets manifest from disk
@@ -188,37 +301,43 @@ impl Templates {
             serde_json::from_slice(&vite_manifest).map_err(TemplateLoadingError::ViteManifest)?;
 
         let translations_path = translations_path.to_owned();
-        let translator =
-            tokio::task::spawn_blocking(move || Translator::load_from_path(&translations_path))
-                .await??;
+        let extra_translations_path = extra_translations_path.map(ToOwned::to_owned);
+        if let Some(extra_translations_path) = &extra_translations_path {
+            info!(%extra_translations_path, "Loading extra translations from filesystem");
+        }
+        let (translator, invalid) =
+            tokio::task::spawn_blocking(move || match extra_translations_path {
+                Some(extra_translations_path) => Translator::load_from_path_with_extra(
+                    &translations_path,
+                    &extra_translations_path,
+                    &locale!("en").into(),
+                ),
+                None => Translator::load_from_path(&translations_path)
+                    .map(|translator| (translator, Vec::new())),
+            })
+            .await??;
+        for message in invalid {
+            warn!(%message, "Skipping invalid extra translation");
+        }
         let translator = Arc::new(translator);
 
+        let compiled_theme = theme
+            .compile()
+            .await
+            .map_err(TemplateLoadingError::ThemeCss)?;
+
         let (loaded, mut env) = tokio::task::spawn_blocking(move || {
             span.in_scope(move || {
                 let mut loaded: HashSet<_> = HashSet::new();
                 let mut env = minijinja::Environment::new();
                 let root = path.canonicalize_utf8()?;
                 info!(%root, "Loading templates from filesystem");
-                for entry in walkdir::WalkDir::new(&root)
-                    .min_depth(1)
-                    .into_iter()
-                    .filter_entry(|e| !is_hidden(e))
-                {
-                    let entry = entry?;
-                    if entry.file_type().is_file() {
-                        let path = Utf8PathBuf::try_from(entry.into_path())?;
-                        let Some(ext) = path.extension() else {
-                            continue;
-                        };
-
-                        if ext == "html" || ext == "txt" || ext == "subject" {
-                            let relative = path.strip_prefix(&root)?;
-                            debug!(%relative, "Registering template");
-                            let template = std::fs::read_to_string(&path)?;
-                            env.add_template_owned(relative.as_str().to_owned(), template)?;
-                            loaded.insert(relative.as_str().to_owned());
-                        }
-                    }
+                register_from_directory(&mut env, &mut loaded, &root, "")?;
+
+                if let Some(email_overrides_path) = email_overrides_path {
+                    let root = email_overrides_path.canonicalize_utf8()?;
+                    info!(%root, "Loading email template overrides from filesystem");
+                    register_from_directory(&mut env, &mut loaded, &root, "emails/")?;
                 }
 
                 Ok::<_, TemplateLoadingError>((loaded, env))
@@ -231,6 +350,8 @@ impl Templates {
             url_builder,
             vite_manifest,
             Arc::clone(&translator),
+            &compiled_theme,
+            custom_context,
         );
 
         let env = Arc::new(env);
@@ -240,7 +361,7 @@ impl Templates {
         let missing: HashSet<_> = needed.difference(&loaded).cloned().collect();
 
         if missing.is_empty() {
-            Ok((translator, env))
+            Ok((translator, env, Arc::new(compiled_theme)))
         } else {
             Err(TemplateLoadingError::MissingTemplates { missing, loaded })
         }
@@ -254,17 +375,22 @@ impl Templates {
         err,
     )]
     pub async fn reload(&self) -> Result<(), TemplateLoadingError> {
-        let (translator, environment) = Self::load_(
+        let (translator, environment, theme) = Self::load_(
             &self.path,
+            self.email_overrides_path.as_deref(),
             self.url_builder.clone(),
             &self.vite_manifest_path,
             &self.translations_path,
+            self.extra_translations_path.as_deref(),
+            &self.theme_config,
+            &self.custom_context,
         )
         .await?;
 
         // Swap them
         self.environment.store(environment);
         self.translator.store(translator);
+        self.theme.store(theme);
 
         Ok(())
     }
@@ -274,6 +400,13 @@ impl Templates {
     pub fn translator(&self) -> Arc<Translator> {
         self.translator.load_full()
     }
+
+    /// Get the stylesheet compiled from the theme, if the theme has any
+    /// colors or extra CSS
+    #[must_use]
+    pub fn theme_stylesheet(&self) -> Option<CompiledStylesheet> {
+        self.theme.load().stylesheet.clone()
+    }
 }
 
 /// Failed to render a template
@@ -307,7 +440,7 @@ register_templates! {
     pub fn render_not_found(WithLanguage<NotFoundContext>) { "pages/404.html" }
 
     /// Render the frontend app
-    pub fn render_app(WithLanguage<AppContext>) { "app.html" }
+    pub fn render_app(WithLanguage<WithSession<AppContext>>) { "app.html" }
 
     /// Render the login page
     pub fn render_login(WithLanguage<WithCsrf<LoginContext>>) { "pages/login.html" }
@@ -321,6 +454,9 @@ register_templates! {
     /// Render the policy violation page
     pub fn render_policy_violation(WithLanguage<WithCsrf<WithSession<PolicyViolationContext>>>) { "pages/policy_violation.html" }
 
+    /// Render the page shown while an authorization request waits for an approval
+    pub fn render_approval_pending(WithLanguage<WithCsrf<WithSession<ApprovalPendingContext>>>) { "pages/approval_pending.html" }
+
     /// Render the legacy SSO login consent page
     pub fn render_sso_login(WithLanguage<WithCsrf<WithSession<CompatSsoContext>>>) { "pages/sso.html" }
 
@@ -339,6 +475,21 @@ register_templates! {
     /// Render the re-authentication form
     pub fn render_reauth(WithLanguage<WithCsrf<WithSession<ReauthContext>>>) { "pages/reauth.html" }
 
+    /// Render the page asking users for the attributes missing on their account
+    pub fn render_complete_profile(WithLanguage<WithCsrf<WithSession<CompleteProfileContext>>>) { "pages/complete_profile.html" }
+
+    /// Render the impersonation form
+    pub fn render_impersonate(WithLanguage<WithCsrf<WithSession<ImpersonateContext>>>) { "pages/impersonate.html" }
+
+    /// Render the cross-signing reset approval page
+    pub fn render_reset_cross_signing(WithLanguage<WithCsrf<WithSession<ResetCrossSigningContext>>>) { "pages/reset_cross_signing.html" }
+
+    /// Render the page used to confirm an email change
+    pub fn render_email_change_confirm(WithLanguage<WithCsrf<EmailChangeConfirmContext>>) { "pages/email_change_confirm.html" }
+
+    /// Render the page used to revert an email change
+    pub fn render_email_change_revert(WithLanguage<WithCsrf<EmailChangeRevertContext>>) { "pages/email_change_revert.html" }
+
     /// Render the form used by the form_post response mode
     pub fn render_form_post<T: Serialize>(FormPostContext<T>) { "form_post.html" }
 
@@ -354,6 +505,51 @@ register_templates! {
     /// Render the email verification subject
     pub fn render_email_verification_subject(WithLanguage<EmailVerificationContext>) { "emails/verification.subject" }
 
+    /// Render the email change notification email (plain text variant)
+    pub fn render_email_change_txt(WithLanguage<EmailChangeNotificationContext>) { "emails/email_change.txt" }
+
+    /// Render the email change notification email (HTML text variant)
+    pub fn render_email_change_html(WithLanguage<EmailChangeNotificationContext>) { "emails/email_change.html" }
+
+    /// Render the email change notification subject
+    pub fn render_email_change_subject(WithLanguage<EmailChangeNotificationContext>) { "emails/email_change.subject" }
+
+    /// Render the authorization approval email (plain text variant)
+    pub fn render_email_authorization_approved_txt(WithLanguage<AuthorizationApprovedContext>) { "emails/authorization_approved.txt" }
+
+    /// Render the authorization approval email (HTML text variant)
+    pub fn render_email_authorization_approved_html(WithLanguage<AuthorizationApprovedContext>) { "emails/authorization_approved.html" }
+
+    /// Render the authorization approval subject
+    pub fn render_email_authorization_approved_subject(WithLanguage<AuthorizationApprovedContext>) { "emails/authorization_approved.subject" }
+
+    /// Render the password change notification email (plain text variant)
+    pub fn render_email_password_change_txt(WithLanguage<SecurityNotificationContext>) { "emails/security/password_change.txt" }
+
+    /// Render the password change notification email (HTML text variant)
+    pub fn render_email_password_change_html(WithLanguage<SecurityNotificationContext>) { "emails/security/password_change.html" }
+
+    /// Render the password change notification subject
+    pub fn render_email_password_change_subject(WithLanguage<SecurityNotificationContext>) { "emails/security/password_change.subject" }
+
+    /// Render the upstream link notification email (plain text variant)
+    pub fn render_email_upstream_link_txt(WithLanguage<SecurityNotificationContext>) { "emails/security/upstream_link.txt" }
+
+    /// Render the upstream link notification email (HTML text variant)
+    pub fn render_email_upstream_link_html(WithLanguage<SecurityNotificationContext>) { "emails/security/upstream_link.html" }
+
+    /// Render the upstream link notification subject
+    pub fn render_email_upstream_link_subject(WithLanguage<SecurityNotificationContext>) { "emails/security/upstream_link.subject" }
+
+    /// Render the account lock notification email (plain text variant)
+    pub fn render_email_account_lock_txt(WithLanguage<SecurityNotificationContext>) { "emails/security/account_lock.txt" }
+
+    /// Render the account lock notification email (HTML text variant)
+    pub fn render_email_account_lock_html(WithLanguage<SecurityNotificationContext>) { "emails/security/account_lock.html" }
+
+    /// Render the account lock notification subject
+    pub fn render_email_account_lock_subject(WithLanguage<SecurityNotificationContext>) { "emails/security/account_lock.subject" }
+
     /// Render the upstream link mismatch message
     pub fn render_upstream_oauth2_link_mismatch(WithLanguage<WithCsrf<WithSession<UpstreamExistingLinkContext>>>) { "pages/upstream_oauth2/link_mismatch.html" }
 
@@ -362,6 +558,9 @@ register_templates! {
 
     /// Render the upstream register screen
     pub fn render_upstream_oauth2_do_register(WithLanguage<WithCsrf<UpstreamRegister>>) { "pages/upstream_oauth2/do_register.html" }
+
+    /// Render the screen to link the upstream account to an existing account with the same username
+    pub fn render_upstream_oauth2_link_existing(WithLanguage<WithCsrf<UpstreamLinkExistingContext>>) { "pages/upstream_oauth2/link_existing.html" }
 }
 
 impl Templates {
@@ -378,20 +577,98 @@ impl Templates {
         check::render_register(self, now, rng)?;
         check::render_consent(self, now, rng)?;
         check::render_policy_violation(self, now, rng)?;
+        check::render_approval_pending(self, now, rng)?;
         check::render_sso_login(self, now, rng)?;
         check::render_index(self, now, rng)?;
         check::render_account_password(self, now, rng)?;
         check::render_account_add_email(self, now, rng)?;
         check::render_account_verify_email(self, now, rng)?;
         check::render_reauth(self, now, rng)?;
+        check::render_complete_profile(self, now, rng)?;
+        check::render_impersonate(self, now, rng)?;
+        check::render_reset_cross_signing(self, now, rng)?;
+        check::render_email_change_confirm(self, now, rng)?;
+        check::render_email_change_revert(self, now, rng)?;
         check::render_form_post::<EmptyContext>(self, now, rng)?;
         check::render_error(self, now, rng)?;
-        check::render_email_verification_txt(self, now, rng)?;
-        check::render_email_verification_html(self, now, rng)?;
-        check::render_email_verification_subject(self, now, rng)?;
+        self.check_error_overrides(now, rng)?;
         check::render_upstream_oauth2_link_mismatch(self, now, rng)?;
         check::render_upstream_oauth2_suggest_link(self, now, rng)?;
         check::render_upstream_oauth2_do_register(self, now, rng)?;
+        check::render_upstream_oauth2_link_existing(self, now, rng)?;
+        self.check_email_render(now, rng)?;
+        Ok(())
+    }
+
+    /// Render the error page for the given context
+    ///
+    /// If the error has a code from the [`error_codes`] catalog and a
+    /// `pages/errors/<code>.html` template exists, it is used instead of
+    /// `pages/error.html`.
+    pub fn render_error_page(&self, context: &ErrorContext) -> Result<String, TemplateError> {
+        let entry = context.code().and_then(error_codes::lookup);
+        if let Some(entry) = entry {
+            let env = self.environment.load();
+            if let Ok(tmpl) = env.get_template(entry.template) {
+                let ctx = minijinja::value::Value::from_serializable(context);
+                return tmpl.render(ctx).map_err(|source| TemplateError::Render {
+                    template: entry.template,
+                    source,
+                });
+            }
+        }
+
+        self.render_error(context)
+    }
+
+    /// Render the error code overrides with the generated samples to check if
+    /// they render properly
+    fn check_error_overrides(
+        &self,
+        now: chrono::DateTime<chrono::Utc>,
+        rng: &mut impl Rng,
+    ) -> anyhow::Result<()> {
+        let env = self.environment.load();
+        for entry in error_codes::CATALOG {
+            if env.get_template(entry.template).is_err() {
+                continue;
+            }
+
+            for sample in ErrorContext::sample(now, rng) {
+                let sample = sample.with_code(entry.code);
+                self.render_error_page(&sample)
+                    .with_context(|| format!("Failed to render template {:?}", entry.template))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Render the email templates with the generated samples to check if they
+    /// render properly
+    pub fn check_email_render(
+        &self,
+        now: chrono::DateTime<chrono::Utc>,
+        rng: &mut impl Rng,
+    ) -> anyhow::Result<()> {
+        check::render_email_verification_txt(self, now, rng)?;
+        check::render_email_verification_html(self, now, rng)?;
+        check::render_email_verification_subject(self, now, rng)?;
+        check::render_email_change_txt(self, now, rng)?;
+        check::render_email_change_html(self, now, rng)?;
+        check::render_email_change_subject(self, now, rng)?;
+        check::render_email_authorization_approved_txt(self, now, rng)?;
+        check::render_email_authorization_approved_html(self, now, rng)?;
+        check::render_email_authorization_approved_subject(self, now, rng)?;
+        check::render_email_password_change_txt(self, now, rng)?;
+        check::render_email_password_change_html(self, now, rng)?;
+        check::render_email_password_change_subject(self, now, rng)?;
+        check::render_email_upstream_link_txt(self, now, rng)?;
+        check::render_email_upstream_link_html(self, now, rng)?;
+        check::render_email_upstream_link_subject(self, now, rng)?;
+        check::render_email_account_lock_txt(self, now, rng)?;
+        check::render_email_account_lock_html(self, now, rng)?;
+        check::render_email_account_lock_subject(self, now, rng)?;
         Ok(())
     }
 }
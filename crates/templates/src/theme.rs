@@ -0,0 +1,346 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Operator-supplied theme: logo, favicon, colors and extra CSS, compiled to a
+//! stylesheet served alongside the other assets
+
+use std::{fmt::Write, str::FromStr};
+
+use camino::Utf8PathBuf;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use url::Url;
+
+/// The given string is not a valid hexadecimal color
+#[derive(Debug, Error)]
+#[error("invalid color {0:?}, expected a #rrggbb or #rgb value")]
+pub struct InvalidColor(String);
+
+/// An opaque RGB color
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    r: u8,
+    g: u8,
+    b: u8,
+}
+
+impl FromStr for Color {
+    type Err = InvalidColor;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || InvalidColor(s.to_owned());
+        let hex = s.strip_prefix('#').ok_or_else(err)?;
+        if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(err());
+        }
+
+        let channel = |range: std::ops::Range<usize>| u8::from_str_radix(&hex[range], 16);
+        let (r, g, b) = match hex.len() {
+            3 => (channel(0..1), channel(1..2), channel(2..3)),
+            6 => (channel(0..2), channel(2..4), channel(4..6)),
+            _ => return Err(err()),
+        };
+        let (r, g, b) = (
+            r.map_err(|_| err())?,
+            g.map_err(|_| err())?,
+            b.map_err(|_| err())?,
+        );
+
+        if hex.len() == 3 {
+            // #abc is a shorthand for #aabbcc
+            Ok(Self {
+                r: r * 17,
+                g: g * 17,
+                b: b * 17,
+            })
+        } else {
+            Ok(Self { r, g, b })
+        }
+    }
+}
+
+impl std::fmt::Display for Color {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
+impl Color {
+    /// Convert the color to hue (in degrees), saturation and lightness
+    #[allow(clippy::float_cmp)]
+    fn to_hsl(self) -> (f64, f64, f64) {
+        let r = f64::from(self.r) / 255.0;
+        let g = f64::from(self.g) / 255.0;
+        let b = f64::from(self.b) / 255.0;
+
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let l = (max + min) / 2.0;
+        let delta = max - min;
+        if delta == 0.0 {
+            return (0.0, 0.0, l);
+        }
+
+        let s = delta / (1.0 - (2.0 * l - 1.0).abs());
+        let h = if max == r {
+            60.0 * ((g - b) / delta).rem_euclid(6.0)
+        } else if max == g {
+            60.0 * ((b - r) / delta + 2.0)
+        } else {
+            60.0 * ((r - g) / delta + 4.0)
+        };
+
+        (h, s, l)
+    }
+
+    /// Build a color from hue (in degrees), saturation and lightness
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn from_hsl(h: f64, s: f64, l: f64) -> Self {
+        let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+        let x = c * (1.0 - ((h / 60.0).rem_euclid(2.0) - 1.0).abs());
+        let m = l - c / 2.0;
+        let (r, g, b) = match h {
+            h if h < 60.0 => (c, x, 0.0),
+            h if h < 120.0 => (x, c, 0.0),
+            h if h < 180.0 => (0.0, c, x),
+            h if h < 240.0 => (0.0, x, c),
+            h if h < 300.0 => (x, 0.0, c),
+            _ => (c, 0.0, x),
+        };
+
+        let channel = |v: f64| ((v + m) * 255.0).round().clamp(0.0, 255.0) as u8;
+        Self {
+            r: channel(r),
+            g: channel(g),
+            b: channel(b),
+        }
+    }
+
+    /// Shift the lightness of the color by `amount`, between -1 and 1
+    fn lighten(self, amount: f64) -> Self {
+        let (h, s, l) = self.to_hsl();
+        Self::from_hsl(h, s, (l + amount).clamp(0.0, 1.0))
+    }
+
+    /// Mirror the lightness of the color, turning light colors into dark ones
+    /// and the other way around
+    fn invert_lightness(self) -> Self {
+        let (h, s, l) = self.to_hsl();
+        Self::from_hsl(h, s, 1.0 - l)
+    }
+}
+
+/// The colors of a theme
+#[derive(Debug, Clone, Default)]
+pub struct ThemeColors {
+    /// Color of the primary buttons, links and focused fields
+    pub accent: Option<Color>,
+
+    /// Color of the page background
+    pub background: Option<Color>,
+
+    /// Color of the text
+    pub text: Option<Color>,
+}
+
+impl ThemeColors {
+    fn is_empty(&self) -> bool {
+        self.accent.is_none() && self.background.is_none() && self.text.is_none()
+    }
+
+    /// Derive colors suitable for dark mode from light mode colors
+    fn dark_variant(&self) -> Self {
+        Self {
+            // Accent colors have to stand out on a dark background
+            accent: self.accent.map(|c| c.lighten(0.15)),
+            background: self.background.map(Color::invert_lightness),
+            text: self.text.map(Color::invert_lightness),
+        }
+    }
+
+    /// Write the CSS variables overridden by those colors, under `selector`
+    ///
+    /// `hover_shift` is the lightness shift applied to the accent color for
+    /// hovered and pressed buttons.
+    fn write_css(&self, out: &mut String, selector: &str, hover_shift: f64) -> std::fmt::Result {
+        writeln!(out, "{selector} {{")?;
+        if let Some(accent) = self.accent {
+            writeln!(out, "  --cpd-color-bg-action-primary-rest: {accent};")?;
+            writeln!(
+                out,
+                "  --cpd-color-bg-action-primary-hovered: {};",
+                accent.lighten(hover_shift)
+            )?;
+            writeln!(
+                out,
+                "  --cpd-color-bg-action-primary-pressed: {};",
+                accent.lighten(hover_shift * 2.0)
+            )?;
+            writeln!(out, "  --cpd-color-text-action-accent: {accent};")?;
+            writeln!(out, "  --cpd-color-border-focused: {accent};")?;
+        }
+        if let Some(background) = self.background {
+            writeln!(out, "  --cpd-color-bg-canvas-default: {background};")?;
+        }
+        if let Some(text) = self.text {
+            writeln!(out, "  --cpd-color-text-primary: {text};")?;
+        }
+        writeln!(out, "}}")
+    }
+}
+
+/// The theme of the pages, as configured by the operator
+#[derive(Debug, Clone, Default)]
+pub struct Theme {
+    /// URL of a logo shown at the top of every page
+    pub logo_uri: Option<Url>,
+
+    /// URL of the favicon
+    pub favicon_uri: Option<Url>,
+
+    /// Colors used in light mode
+    pub colors: ThemeColors,
+
+    /// Colors used in dark mode, derived from the light mode ones if not set
+    pub dark_colors: Option<ThemeColors>,
+
+    /// Path to a CSS file appended to the theme stylesheet
+    pub extra_css_path: Option<Utf8PathBuf>,
+}
+
+impl Theme {
+    /// Compile the theme to a stylesheet, reading the extra CSS from disk
+    pub(crate) async fn compile(&self) -> Result<CompiledTheme, std::io::Error> {
+        let extra_css = match &self.extra_css_path {
+            Some(path) => Some(tokio::fs::read_to_string(path).await?),
+            None => None,
+        };
+
+        Ok(self.compile_with(extra_css.as_deref()))
+    }
+
+    fn compile_with(&self, extra_css: Option<&str>) -> CompiledTheme {
+        let dark_colors = self
+            .dark_colors
+            .clone()
+            .unwrap_or_else(|| self.colors.dark_variant());
+
+        let mut css = String::new();
+        if !self.colors.is_empty() {
+            // Writing to a String never fails
+            let _ = self.colors.write_css(&mut css, ":root", -0.08);
+        }
+        if !dark_colors.is_empty() {
+            // The compound design tokens use a doubled class for the dark theme, so the
+            // selector needs to be more specific to override them
+            let _ = dark_colors.write_css(&mut css, ":root.cpd-theme-dark.cpd-theme-dark", 0.08);
+        }
+        if let Some(extra_css) = extra_css {
+            css.push_str(extra_css);
+        }
+
+        let stylesheet = (!css.is_empty()).then(|| {
+            let digest = Sha256::digest(css.as_bytes());
+            let version = format!("{digest:x}")[..16].to_owned();
+            CompiledStylesheet { css, version }
+        });
+
+        CompiledTheme {
+            logo_uri: self.logo_uri.clone(),
+            favicon_uri: self.favicon_uri.clone(),
+            stylesheet,
+        }
+    }
+}
+
+/// A theme ready to be used by the templates
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CompiledTheme {
+    pub logo_uri: Option<Url>,
+    pub favicon_uri: Option<Url>,
+    pub stylesheet: Option<CompiledStylesheet>,
+}
+
+/// The stylesheet of a theme, with a version derived from its content
+#[derive(Debug, Clone)]
+pub struct CompiledStylesheet {
+    /// The CSS content of the stylesheet
+    pub css: String,
+
+    /// A short hash of the content, used to bust caches
+    pub version: String,
+}
+
+/// The `theme` global exposed to the templates
+#[derive(Serialize)]
+pub(crate) struct ThemeGlobal {
+    pub logo_uri: Option<Url>,
+    pub favicon_uri: Option<Url>,
+    pub stylesheet_url: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_color() {
+        let color: Color = "#0dbd8b".parse().unwrap();
+        assert_eq!(color.to_string(), "#0dbd8b");
+        let color: Color = "#FFF".parse().unwrap();
+        assert_eq!(color.to_string(), "#ffffff");
+
+        assert!("0dbd8b".parse::<Color>().is_err());
+        assert!("#0dbd8".parse::<Color>().is_err());
+        assert!("#gggggg".parse::<Color>().is_err());
+        assert!("#ü00".parse::<Color>().is_err());
+    }
+
+    #[test]
+    fn dark_variant() {
+        let colors = ThemeColors {
+            accent: None,
+            background: Some("#ffffff".parse().unwrap()),
+            text: Some("#101317".parse().unwrap()),
+        };
+        let dark = colors.dark_variant();
+        assert_eq!(dark.background.unwrap().to_string(), "#000000");
+        let (_, _, l) = dark.text.unwrap().to_hsl();
+        assert!(l > 0.9);
+    }
+
+    #[test]
+    fn compile() {
+        let theme = Theme::default();
+        assert!(theme.compile_with(None).stylesheet.is_none());
+
+        let theme = Theme {
+            colors: ThemeColors {
+                accent: Some("#0dbd8b".parse().unwrap()),
+                ..ThemeColors::default()
+            },
+            ..Theme::default()
+        };
+        let stylesheet = theme.compile_with(Some(".extra {}")).stylesheet.unwrap();
+        assert!(stylesheet
+            .css
+            .contains("--cpd-color-bg-action-primary-rest: #0dbd8b;"));
+        assert!(stylesheet
+            .css
+            .contains(":root.cpd-theme-dark.cpd-theme-dark {"));
+        assert!(stylesheet.css.ends_with(".extra {}"));
+        assert_eq!(stylesheet.version.len(), 16);
+    }
+}
@@ -19,6 +19,7 @@ import {
   differenceInHours,
   parseISO,
 } from "date-fns";
+import { useTranslation } from "react-i18next";
 
 type Props = {
   className?: string;
@@ -26,35 +27,48 @@ type Props = {
   now?: Date;
 };
 
-export const formatDate = (datetime: Date): string =>
-  intlFormat(datetime, {
-    year: "numeric",
-    month: "short",
-    day: "numeric",
-    weekday: "short",
-    hour: "numeric",
-    minute: "numeric",
-  });
+/**
+ * Formats a datetime in the time zone of the browser
+ * The locale defaults to the one of the browser
+ */
+export const formatDate = (datetime: Date, locale?: string): string =>
+  intlFormat(
+    datetime,
+    {
+      year: "numeric",
+      month: "short",
+      day: "numeric",
+      weekday: "short",
+      hour: "numeric",
+      minute: "numeric",
+    },
+    { locale },
+  );
 
 /**
  * Formats a datetime
  * Uses distance when less than an hour ago
  * Else internationalised `Fri, 21 Jul 2023, 16:14`
  */
-export const formatReadableDate = (datetime: Date, now: Date): string =>
+export const formatReadableDate = (
+  datetime: Date,
+  now: Date,
+  locale?: string,
+): string =>
   Math.abs(differenceInHours(now, datetime, { roundingMethod: "round" })) > 1
-    ? formatDate(datetime)
-    : intlFormatDistance(datetime, now);
+    ? formatDate(datetime, locale)
+    : intlFormatDistance(datetime, now, { locale });
 
 const DateTime: React.FC<Props> = ({
   datetime: datetimeProps,
   now: nowProps,
   className,
 }) => {
+  const { i18n } = useTranslation();
   const datetime =
     typeof datetimeProps === "string" ? parseISO(datetimeProps) : datetimeProps;
   const now = nowProps || new Date();
-  const text = formatReadableDate(datetime, now);
+  const text = formatReadableDate(datetime, now, i18n.language);
 
   return (
     <time className={className} dateTime={formatISO(datetime)}>
@@ -38,7 +38,10 @@ const HydrateLocation: React.FC<React.PropsWithChildren<{ path: string }>> = ({
   path,
 }) => {
   useHydrateAtoms([
-    [appConfigAtom, { root: "/", graphqlEndpoint: "/graphql" }],
+    [
+      appConfigAtom,
+      { root: "/", graphqlEndpoint: "/graphql", embeddingOrigins: [] },
+    ],
     [locationAtom, { pathname: path }],
   ]);
   return <>{children}</>;
@@ -35,7 +35,10 @@ const meta = {
 
 const WithHomePage: React.FC<React.PropsWithChildren<{}>> = ({ children }) => {
   useHydrateAtoms([
-    [appConfigAtom, { root: "/", graphqlEndpoint: "/graphql" }],
+    [
+      appConfigAtom,
+      { root: "/", graphqlEndpoint: "/graphql", embeddingOrigins: [] },
+    ],
     [locationAtom, { pathname: "/" }],
   ]);
   return <>{children}</>;
@@ -28,7 +28,7 @@ const LastActive: React.FC<{
   lastActive: Date | string;
   now?: Date | string;
 }> = ({ lastActive: lastActiveProps, now: nowProps }) => {
-  const { t } = useTranslation();
+  const { t, i18n } = useTranslation();
 
   const lastActive =
     typeof lastActiveProps === "string"
@@ -41,7 +41,7 @@ const LastActive: React.FC<{
       : nowProps
     : new Date();
 
-  const formattedDate = formatDate(lastActive);
+  const formattedDate = formatDate(lastActive, i18n.language);
   if (differenceInSeconds(now, lastActive) <= ACTIVE_NOW_MAX_AGE) {
     return (
       <span title={formattedDate} className={styles.active}>
@@ -56,7 +56,7 @@ const LastActive: React.FC<{
       </span>
     );
   }
-  const relativeDate = formatReadableDate(lastActive, now);
+  const relativeDate = formatReadableDate(lastActive, now, i18n.language);
   return (
     <span title={formattedDate}>
       {t("frontend.last_active.active_date", { relativeDate })}
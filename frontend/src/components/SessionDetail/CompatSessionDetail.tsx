@@ -17,6 +17,7 @@ import { useSetAtom } from "jotai";
 import { useTranslation } from "react-i18next";
 
 import { FragmentType, graphql, useFragment } from "../../gql";
+import { notifyActionCompleted } from "../../utils/embedding";
 import BlockList from "../BlockList/BlockList";
 import { endCompatSessionFamily, simplifyUrl } from "../CompatSession";
 import DateTime from "../DateTime";
@@ -53,6 +54,7 @@ const CompatSessionDetail: React.FC<Props> = ({ session }) => {
 
   const onSessionEnd = async (): Promise<void> => {
     await endSession();
+    notifyActionCompleted("session_end", data.deviceId);
   };
 
   const finishedAt = data.finishedAt
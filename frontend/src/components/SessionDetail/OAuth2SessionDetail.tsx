@@ -19,6 +19,7 @@ import { useTranslation } from "react-i18next";
 import { FragmentType, graphql, useFragment } from "../../gql";
 import { Link } from "../../routing";
 import { getDeviceIdFromScope } from "../../utils/deviceIdFromScope";
+import { notifyActionCompleted } from "../../utils/embedding";
 import BlockList from "../BlockList/BlockList";
 import DateTime from "../DateTime";
 import { endSessionFamily } from "../OAuth2Session";
@@ -33,6 +34,7 @@ export const FRAGMENT = graphql(/* GraphQL */ `
   fragment OAuth2Session_detail on Oauth2Session {
     id
     scope
+    humanName
     createdAt
     finishedAt
     lastActiveIp
@@ -56,12 +58,13 @@ const OAuth2SessionDetail: React.FC<Props> = ({ session }) => {
   const endSession = useSetAtom(endSessionFamily(data.id));
   const { t } = useTranslation();
 
+  const deviceId = getDeviceIdFromScope(data.scope);
+
   const onSessionEnd = async (): Promise<void> => {
     await endSession();
+    notifyActionCompleted("session_end", deviceId);
   };
 
-  const deviceId = getDeviceIdFromScope(data.scope);
-
   const scopes = data.scope.split(" ");
 
   const finishedAt = data.finishedAt
@@ -142,7 +145,7 @@ const OAuth2SessionDetail: React.FC<Props> = ({ session }) => {
           type: "sessions-overview",
         }}
       >
-        {deviceId || data.id}
+        {data.humanName || deviceId || data.id}
       </SessionHeader>
       <SessionDetails
         title={t("frontend.oauth2_session_detail.session_details_title")}
@@ -0,0 +1,152 @@
+// Copyright 2023 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+import {
+  Alert,
+  Button,
+  Control,
+  Field,
+  H6,
+  Label,
+  Root,
+} from "@vector-im/compound-web";
+import { atom, useSetAtom } from "jotai";
+import { atomFamily } from "jotai/utils";
+import { atomWithMutation } from "jotai-urql";
+import { useState, useEffect, ChangeEventHandler } from "react";
+import { useTranslation } from "react-i18next";
+
+import { FragmentType, graphql, useFragment } from "../../gql";
+import { SetOAuth2SessionNameStatus } from "../../gql/graphql";
+import Block from "../Block/Block";
+import LoadingSpinner from "../LoadingSpinner/LoadingSpinner";
+
+import { FRAGMENT } from "./OAuth2SessionDetail";
+import styles from "./OAuth2SessionName.module.css";
+
+const SET_SESSION_NAME_MUTATION = graphql(/* GraphQL */ `
+  mutation SetOAuth2SessionName($id: ID!, $humanName: String!) {
+    setOauth2SessionName(
+      input: { oauth2SessionId: $id, humanName: $humanName }
+    ) {
+      status
+      oauth2Session {
+        id
+        humanName
+      }
+    }
+  }
+`);
+
+const setSessionNameFamily = atomFamily((id: string) => {
+  const setSessionName = atomWithMutation(SET_SESSION_NAME_MUTATION);
+
+  // A proxy atom which pre-sets the id variable in the mutation
+  const setSessionNameAtom = atom(
+    (get) => get(setSessionName),
+    (get, set, humanName: string) => set(setSessionName, { id, humanName }),
+  );
+
+  return setSessionNameAtom;
+});
+
+type Props = {
+  session: FragmentType<typeof FRAGMENT>;
+};
+
+/**
+ * Lets the user rename an active session. The name is also set as the display
+ * name of the device on the homeserver.
+ */
+const OAuth2SessionName: React.FC<Props> = ({ session }) => {
+  const data = useFragment(FRAGMENT, session);
+  const setSessionName = useSetAtom(setSessionNameFamily(data.id));
+  const [inProgress, setInProgress] = useState(false);
+  const [errorMessage, setErrorMessage] = useState<string | null>(null);
+  const { t } = useTranslation();
+
+  const humanName = data.humanName || "";
+  const [fieldValue, setFieldValue] = useState(humanName);
+
+  useEffect(() => {
+    setFieldValue(humanName);
+  }, [humanName]);
+
+  if (data.finishedAt) {
+    return null;
+  }
+
+  const hasChanges = fieldValue.trim() !== humanName;
+
+  const onChange: ChangeEventHandler<HTMLInputElement> = (event): void => {
+    setFieldValue(event.target.value);
+  };
+
+  const onSubmit = (event: React.FormEvent<HTMLFormElement>): void => {
+    event.preventDefault();
+
+    setInProgress(true);
+    setSessionName(fieldValue).then((result) => {
+      const status = result.data?.setOauth2SessionName.status;
+      if (status === SetOAuth2SessionNameStatus.Updated) {
+        setErrorMessage(null);
+      } else {
+        console.error("Failed to rename the session", result.error);
+        setErrorMessage(
+          status === SetOAuth2SessionNameStatus.Unsupported
+            ? t("frontend.oauth2_session_name.unsupported_alert")
+            : t("frontend.oauth2_session_name.failed_alert"),
+        );
+        // reset to the current saved name
+        setFieldValue(humanName);
+      }
+      setInProgress(false);
+    });
+  };
+
+  return (
+    <Block>
+      <H6>{t("frontend.oauth2_session_name.title")}</H6>
+      <Root onSubmit={onSubmit} className={styles.form}>
+        <Field name="human_name">
+          <Label>{t("frontend.oauth2_session_name.name_field_label")}</Label>
+          <Control
+            value={fieldValue}
+            onChange={onChange}
+            inputMode="text"
+            max={250}
+          />
+        </Field>
+        {!inProgress && errorMessage && (
+          <Alert type="critical" title={t("common.error")}>
+            {errorMessage}
+          </Alert>
+        )}
+
+        <Button
+          className={styles.saveButton}
+          disabled={inProgress || !hasChanges}
+          kind="primary"
+          size="sm"
+          type="submit"
+        >
+          {!!inProgress && <LoadingSpinner inline />}
+          {t("action.save")}
+        </Button>
+      </Root>
+    </Block>
+  );
+};
+
+export default OAuth2SessionName;
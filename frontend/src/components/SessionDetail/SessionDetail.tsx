@@ -21,9 +21,11 @@ import { useTranslation } from "react-i18next";
 
 import { graphql } from "../../gql";
 import { Link } from "../../routing";
+import BlockList from "../BlockList/BlockList";
 
 import CompatSessionDetail from "./CompatSessionDetail";
 import OAuth2SessionDetail from "./OAuth2SessionDetail";
+import OAuth2SessionName from "./OAuth2SessionName";
 
 const QUERY = graphql(/* GraphQL */ `
   query SessionQuery($userId: ID!, $deviceId: String!) {
@@ -84,7 +86,12 @@ const SessionDetail: React.FC<{
     case "CompatSession":
       return <CompatSessionDetail session={session} />;
     case "Oauth2Session":
-      return <OAuth2SessionDetail session={session} />;
+      return (
+        <BlockList>
+          <OAuth2SessionDetail session={session} />
+          <OAuth2SessionName session={session} />
+        </BlockList>
+      );
     default:
       unknownSessionType(sessionType);
   }
@@ -69,6 +69,12 @@ const getErrorMessage = (result: {
   if (result.data?.setDisplayName.status === "INVALID") {
     return "Failed to save invalid display name.";
   }
+  if (result.data?.setDisplayName.status === "LOCKED") {
+    return "Your display name is managed by your identity provider.";
+  }
+  if (result.data?.setDisplayName.status === "UNSUPPORTED") {
+    return "Your homeserver does not allow changing your display name.";
+  }
 };
 
 const UserName: React.FC<{ userId: string }> = ({ userId }) => {
@@ -120,7 +126,7 @@ const UserName: React.FC<{ userId: string }> = ({ userId }) => {
         userGreeting({
           requestPolicy: "network-only",
         });
-      } else if (result.data.setDisplayName.status === "INVALID") {
+      } else {
         // reset to current saved display name
         setFieldValue(displayName);
       }
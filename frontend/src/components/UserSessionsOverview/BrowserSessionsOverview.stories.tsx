@@ -27,7 +27,10 @@ type Props = {
 
 const WithHomePage: React.FC<React.PropsWithChildren<{}>> = ({ children }) => {
   useHydrateAtoms([
-    [appConfigAtom, { root: "/", graphqlEndpoint: "/graphql" }],
+    [
+      appConfigAtom,
+      { root: "/", graphqlEndpoint: "/graphql", embeddingOrigins: [] },
+    ],
     [locationAtom, { pathname: "/" }],
   ]);
   return <>{children}</>;
@@ -25,6 +25,7 @@ import {
   H1,
   Text,
 } from "@vector-im/compound-web";
+import { parseISO } from "date-fns";
 import { useSetAtom, atom, useAtom } from "jotai";
 import { atomFamily } from "jotai/utils";
 import { atomWithMutation } from "jotai-urql";
@@ -33,6 +34,7 @@ import { Trans, useTranslation } from "react-i18next";
 
 import { FragmentType, graphql, useFragment } from "../../gql";
 import { routeAtom, useNavigationLink } from "../../routing";
+import { formatReadableDate } from "../DateTime";
 
 import styles from "./VerifyEmail.module.css";
 
@@ -67,6 +69,7 @@ const RESEND_VERIFICATION_EMAIL_MUTATION = graphql(/* GraphQL */ `
   mutation ResendVerificationEmail($id: ID!) {
     sendVerificationEmail(input: { userEmailId: $id }) {
       status
+      retryAfter
 
       user {
         id
@@ -139,7 +142,7 @@ const VerifyEmail: React.FC<{
   );
   const setRoute = useSetAtom(routeAtom);
   const fieldRef = useRef<HTMLInputElement>(null);
-  const { t } = useTranslation();
+  const { t, i18n } = useTranslation();
 
   const onFormSubmit = (e: React.FormEvent<HTMLFormElement>): void => {
     e.preventDefault();
@@ -175,6 +178,11 @@ const VerifyEmail: React.FC<{
 
   const emailSent =
     resendVerificationEmailResult.data?.sendVerificationEmail.status === "SENT";
+  const rateLimited =
+    resendVerificationEmailResult.data?.sendVerificationEmail.status ===
+    "RATE_LIMITED";
+  const retryAfter =
+    resendVerificationEmailResult.data?.sendVerificationEmail.retryAfter;
   const invalidCode =
     verifyEmailResult.data?.verifyEmail.status === "INVALID_CODE";
   const { email: codeEmail } = data;
@@ -201,6 +209,22 @@ const VerifyEmail: React.FC<{
             title={t("frontend.verify_email.invalid_code_alert")}
           />
         )}
+        {rateLimited && (
+          <Alert
+            type="critical"
+            title={t("frontend.verify_email.rate_limited_alert.title")}
+          >
+            {retryAfter
+              ? t("frontend.verify_email.rate_limited_alert.retry_after", {
+                  when: formatReadableDate(
+                    parseISO(retryAfter),
+                    new Date(),
+                    i18n.language,
+                  ),
+                })
+              : t("frontend.verify_email.rate_limited_alert.text")}
+          </Alert>
+        )}
         <Field name="code" serverInvalid={invalidCode}>
           <Label>{t("frontend.verify_email.code_field_label")}</Label>
           <Control
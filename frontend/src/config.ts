@@ -15,6 +15,7 @@
 export type AppConfig = {
   root: string;
   graphqlEndpoint: string;
+  embeddingOrigins: string[];
 };
 
 interface IWindow {
@@ -22,6 +23,10 @@ interface IWindow {
 }
 
 const config: AppConfig = (typeof window !== "undefined" &&
-  (window as IWindow).APP_CONFIG) || { root: "/", graphqlEndpoint: "/graphql" };
+  (window as IWindow).APP_CONFIG) || {
+  root: "/",
+  graphqlEndpoint: "/graphql",
+  embeddingOrigins: [],
+};
 
 export default config;
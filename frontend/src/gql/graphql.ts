@@ -141,6 +141,52 @@ export type Authentication = CreationEvent &
     id: Scalars["ID"]["output"];
   };
 
+/**
+ * The input for the `approveAuthorizationRequest` and
+ * `denyAuthorizationRequest` mutations.
+ */
+export type AuthorizationApprovalInput = {
+  /** The ID of the authorization request. */
+  requestId: Scalars["String"]["input"];
+};
+
+export type AuthorizationApprovalPayload = {
+  __typename?: "AuthorizationApprovalPayload";
+  /** Status of the operation */
+  status: AuthorizationApprovalStatus;
+};
+
+/**
+ * An authorization request of a client which waits for an administrator to
+ * approve it.
+ */
+export type AuthorizationApprovalRequest = {
+  __typename?: "AuthorizationApprovalRequest";
+  /** The client which made the authorization request. */
+  client: Oauth2Client;
+  /** ID of the authorization request. */
+  id: Scalars["String"]["output"];
+  /** When the user asked for the approval. */
+  requestedAt: Scalars["DateTime"]["output"];
+  /** The scope the client asked for. */
+  scope: Scalars["String"]["output"];
+  /** The user who asked for the approval. */
+  user?: Maybe<User>;
+};
+
+/**
+ * The status of the `approveAuthorizationRequest` and
+ * `denyAuthorizationRequest` mutations.
+ */
+export enum AuthorizationApprovalStatus {
+  /** The request was approved, the user was notified. */
+  Approved = "APPROVED",
+  /** The request was denied. */
+  Denied = "DENIED",
+  /** No authorization request waiting for an approval was found. */
+  NotFound = "NOT_FOUND",
+}
+
 /** A browser session represents a logged in user in a browser. */
 export type BrowserSession = CreationEvent &
   Node & {
@@ -186,6 +232,16 @@ export type BrowserSessionEdge = {
   node: BrowserSession;
 };
 
+/** Maps an attribute of the users to a claim. */
+export type ClaimMappingInput = {
+  /** The attribute the claim is set to. */
+  attribute: UserAttribute;
+  /** The name of the claim. */
+  claim: Scalars["String"]["input"];
+  /** Only set the claim when the session has this scope. */
+  scope?: InputMaybe<Scalars["String"]["input"]>;
+};
+
 /**
  * A compat session represents a client session which used the legacy Matrix
  * login API.
@@ -379,6 +435,21 @@ export enum EndOAuth2SessionStatus {
   NotFound = "NOT_FOUND",
 }
 
+/** A background job which failed and won't be retried anymore. */
+export type FailedJob = {
+  __typename?: "FailedJob";
+  /** When the job failed. */
+  failedAt?: Maybe<Scalars["DateTime"]["output"]>;
+  /** ID of the job. */
+  id: Scalars["String"]["output"];
+  /** The error the job last failed with, if any. */
+  lastError?: Maybe<Scalars["String"]["output"]>;
+  /** The kind of job, e.g. `provision-user`. */
+  name: Scalars["String"]["output"];
+  /** The payload of the job, serialized as JSON. */
+  payload: Scalars["String"]["output"];
+};
+
 /** The input for the `lockUser` mutation. */
 export type LockUserInput = {
   /** Permanently lock the user. */
@@ -421,12 +492,26 @@ export type Mutation = {
   addEmail: AddEmailPayload;
   /** Add a user. This is only available to administrators. */
   addUser: AddUserPayload;
+  /**
+   * Approve an authorization request which waits for an approval. The user
+   * who made it gets an email with a link to finish it.
+   *
+   * This is only available to administrators.
+   */
+  approveAuthorizationRequest: AuthorizationApprovalPayload;
   /**
    * Create a new arbitrary OAuth 2.0 Session.
    *
    * Only available for administrators.
    */
   createOauth2Session: CreateOAuth2SessionPayload;
+  /**
+   * Deny an authorization request which waits for an approval. It can't be
+   * finished anymore.
+   *
+   * This is only available to administrators.
+   */
+  denyAuthorizationRequest: AuthorizationApprovalPayload;
   endBrowserSession: EndBrowserSessionPayload;
   endCompatSession: EndCompatSessionPayload;
   endOauth2Session: EndOAuth2SessionPayload;
@@ -434,6 +519,12 @@ export type Mutation = {
   lockUser: LockUserPayload;
   /** Remove an email address */
   removeEmail: RemoveEmailPayload;
+  /**
+   * Schedule the deactivation of a user, optionally erasing their data.
+   * When it runs, the user is locked and all their sessions are ended.
+   * This is only available to administrators.
+   */
+  scheduleUserDeactivation: ScheduleUserDeactivationPayload;
   /** Send a verification code for an email address */
   sendVerificationEmail: SendVerificationEmailPayload;
   /**
@@ -443,8 +534,54 @@ export type Mutation = {
   setCanRequestAdmin: SetCanRequestAdminPayload;
   /** Set the display name of a user */
   setDisplayName: SetDisplayNamePayload;
-  /** Set an email address as primary */
+  /**
+   * Set which attributes of the users are mapped to the claims of the ID
+   * tokens and userinfo responses of a client. This is only available to
+   * administrators.
+   */
+  setOauth2ClientClaimMappings: SetOAuth2ClientClaimMappingsPayload;
+  /**
+   * Override the lifetime and the audiences of the tokens issued to a
+   * client. This is only available to administrators.
+   */
+  setOauth2ClientTokenSettings: SetOAuth2ClientTokenSettingsPayload;
+  /**
+   * Set the scopes users don't have to consent to for a first-party
+   * client. Scopes beyond those still need consent. This is only available
+   * to administrators.
+   */
+  setOauth2ClientTrustedScope: SetOAuth2ClientTrustedScopePayload;
+  /**
+   * Set the human-readable name of an OAuth 2.0 session.
+   *
+   * The name is also set as the display name of the device attached to the
+   * session on the homeserver.
+   */
+  setOauth2SessionName: SetOAuth2SessionNamePayload;
+  /**
+   * Set an email address as primary.
+   *
+   * If the user already has a verified primary email address, it has to
+   * confirm the change first, unless the requester is an admin.
+   */
   setPrimaryEmail: SetPrimaryEmailPayload;
+  /**
+   * Set whether a user is quarantined. Quarantined users can still use
+   * their existing sessions, but can't start new ones. This is only
+   * available to administrators.
+   */
+  setQuarantined: SetQuarantinedPayload;
+  /** Opt a user in or out of a category of security notification emails. */
+  setSecurityNotificationOptOut: SetSecurityNotificationOptOutPayload;
+  /**
+   * Start replacing the primary email address of the specified user.
+   *
+   * The new address has to be verified first. Once it is, the old address
+   * has to confirm the change, unless the requester is an admin. After the
+   * switch-over, the old address can revert the change for a while, after
+   * which it is removed from the account.
+   */
+  startEmailChange: StartEmailChangePayload;
   /** Submit a verification code for an email address */
   verifyEmail: VerifyEmailPayload;
 };
@@ -459,11 +596,21 @@ export type MutationAddUserArgs = {
   input: AddUserInput;
 };
 
+/** The mutations root of the GraphQL interface. */
+export type MutationApproveAuthorizationRequestArgs = {
+  input: AuthorizationApprovalInput;
+};
+
 /** The mutations root of the GraphQL interface. */
 export type MutationCreateOauth2SessionArgs = {
   input: CreateOAuth2SessionInput;
 };
 
+/** The mutations root of the GraphQL interface. */
+export type MutationDenyAuthorizationRequestArgs = {
+  input: AuthorizationApprovalInput;
+};
+
 /** The mutations root of the GraphQL interface. */
 export type MutationEndBrowserSessionArgs = {
   input: EndBrowserSessionInput;
@@ -489,6 +636,11 @@ export type MutationRemoveEmailArgs = {
   input: RemoveEmailInput;
 };
 
+/** The mutations root of the GraphQL interface. */
+export type MutationScheduleUserDeactivationArgs = {
+  input: ScheduleUserDeactivationInput;
+};
+
 /** The mutations root of the GraphQL interface. */
 export type MutationSendVerificationEmailArgs = {
   input: SendVerificationEmailInput;
@@ -504,11 +656,46 @@ export type MutationSetDisplayNameArgs = {
   input: SetDisplayNameInput;
 };
 
+/** The mutations root of the GraphQL interface. */
+export type MutationSetOauth2ClientClaimMappingsArgs = {
+  input: SetOAuth2ClientClaimMappingsInput;
+};
+
+/** The mutations root of the GraphQL interface. */
+export type MutationSetOauth2ClientTokenSettingsArgs = {
+  input: SetOAuth2ClientTokenSettingsInput;
+};
+
+/** The mutations root of the GraphQL interface. */
+export type MutationSetOauth2ClientTrustedScopeArgs = {
+  input: SetOAuth2ClientTrustedScopeInput;
+};
+
+/** The mutations root of the GraphQL interface. */
+export type MutationSetOauth2SessionNameArgs = {
+  input: SetOAuth2SessionNameInput;
+};
+
 /** The mutations root of the GraphQL interface. */
 export type MutationSetPrimaryEmailArgs = {
   input: SetPrimaryEmailInput;
 };
 
+/** The mutations root of the GraphQL interface. */
+export type MutationSetQuarantinedArgs = {
+  input: SetQuarantinedInput;
+};
+
+/** The mutations root of the GraphQL interface. */
+export type MutationSetSecurityNotificationOptOutArgs = {
+  input: SetSecurityNotificationOptOutInput;
+};
+
+/** The mutations root of the GraphQL interface. */
+export type MutationStartEmailChangeArgs = {
+  input: StartEmailChangeInput;
+};
+
 /** The mutations root of the GraphQL interface. */
 export type MutationVerifyEmailArgs = {
   input: VerifyEmailInput;
@@ -568,6 +755,11 @@ export type Oauth2Session = CreationEvent &
     createdAt: Scalars["DateTime"]["output"];
     /** When the session ended. */
     finishedAt?: Maybe<Scalars["DateTime"]["output"]>;
+    /**
+     * The human-readable name of the session, used as the display name of
+     * its device.
+     */
+    humanName?: Maybe<Scalars["String"]["output"]>;
     /** ID of the object. */
     id: Scalars["ID"]["output"];
     /** The last time the session was active. */
@@ -616,9 +808,26 @@ export type PageInfo = {
   startCursor?: Maybe<Scalars["String"]["output"]>;
 };
 
+/** An attribute of the user profile. */
+export enum ProfileAttribute {
+  /** The avatar of the user. */
+  AvatarUrl = "AVATAR_URL",
+  /** The display name of the user. */
+  Displayname = "DISPLAYNAME",
+  /** The email addresses of the user. */
+  Email = "EMAIL",
+}
+
 /** The query root of the GraphQL interface. */
 export type Query = {
   __typename?: "Query";
+  /**
+   * Get the authorization requests waiting for an administrator to approve
+   * them, oldest first.
+   *
+   * This is only available to administrators.
+   */
+  authorizationApprovalRequests: Array<AuthorizationApprovalRequest>;
   /** Fetch a browser session by its ID. */
   browserSession?: Maybe<BrowserSession>;
   /**
@@ -631,10 +840,24 @@ export type Query = {
    * @deprecated Use `viewer` instead.
    */
   currentUser?: Maybe<User>;
+  /**
+   * Get the most recent background jobs which failed and won't be retried
+   * anymore, e.g. because the homeserver was unreachable for too long.
+   *
+   * This is only available to administrators.
+   */
+  failedJobs: Array<FailedJob>;
   /** Fetches an object given its ID. */
   node?: Maybe<Node>;
   /** Fetch an OAuth 2.0 client by its ID. */
   oauth2Client?: Maybe<Oauth2Client>;
+  /**
+   * Get the list of OAuth 2.0 sessions of all users, chronologically
+   * sorted.
+   *
+   * This is only available to administrators.
+   */
+  oauth2Sessions: Oauth2SessionConnection;
   /** Lookup a compat or OAuth 2.0 session */
   session?: Maybe<Session>;
   /** Fetch an upstream OAuth 2.0 link by its ID. */
@@ -655,11 +878,21 @@ export type Query = {
   viewerSession: ViewerSession;
 };
 
+/** The query root of the GraphQL interface. */
+export type QueryAuthorizationApprovalRequestsArgs = {
+  first?: InputMaybe<Scalars["Int"]["input"]>;
+};
+
 /** The query root of the GraphQL interface. */
 export type QueryBrowserSessionArgs = {
   id: Scalars["ID"]["input"];
 };
 
+/** The query root of the GraphQL interface. */
+export type QueryFailedJobsArgs = {
+  first?: InputMaybe<Scalars["Int"]["input"]>;
+};
+
 /** The query root of the GraphQL interface. */
 export type QueryNodeArgs = {
   id: Scalars["ID"]["input"];
@@ -670,6 +903,19 @@ export type QueryOauth2ClientArgs = {
   id: Scalars["ID"]["input"];
 };
 
+/** The query root of the GraphQL interface. */
+export type QueryOauth2SessionsArgs = {
+  after?: InputMaybe<Scalars["String"]["input"]>;
+  before?: InputMaybe<Scalars["String"]["input"]>;
+  client?: InputMaybe<Scalars["ID"]["input"]>;
+  createdAfter?: InputMaybe<Scalars["DateTime"]["input"]>;
+  createdBefore?: InputMaybe<Scalars["DateTime"]["input"]>;
+  first?: InputMaybe<Scalars["Int"]["input"]>;
+  last?: InputMaybe<Scalars["Int"]["input"]>;
+  state?: InputMaybe<SessionState>;
+  user?: InputMaybe<Scalars["ID"]["input"]>;
+};
+
 /** The query root of the GraphQL interface. */
 export type QuerySessionArgs = {
   deviceId: Scalars["String"]["input"];
@@ -728,6 +974,11 @@ export type RemoveEmailPayload = {
 
 /** The status of the `removeEmail` mutation */
 export enum RemoveEmailStatus {
+  /**
+   * Can't remove an email address involved in a change of the primary
+   * email address which is in progress
+   */
+  ChangeInProgress = "CHANGE_IN_PROGRESS",
   /** The email address was not found */
   NotFound = "NOT_FOUND",
   /** Can't remove the primary email address */
@@ -736,6 +987,47 @@ export enum RemoveEmailStatus {
   Removed = "REMOVED",
 }
 
+/** The input for the `scheduleUserDeactivation` mutation. */
+export type ScheduleUserDeactivationInput = {
+  /** When to deactivate the user. Defaults to now. */
+  deactivateAt?: InputMaybe<Scalars["DateTime"]["input"]>;
+  /**
+   * Erase the user: this removes their personal information, like email
+   * addresses and upstream OAuth links, and asks the homeserver to erase
+   * their data.
+   */
+  erase?: InputMaybe<Scalars["Boolean"]["input"]>;
+  /** The ID of the user to deactivate. */
+  userId: Scalars["ID"]["input"];
+};
+
+/** The payload for the `scheduleUserDeactivation` mutation. */
+export type ScheduleUserDeactivationPayload = {
+  __typename?: "ScheduleUserDeactivationPayload";
+  /** Status of the operation */
+  status: ScheduleUserDeactivationStatus;
+  /** The user that will be deactivated. */
+  user?: Maybe<User>;
+};
+
+/** The status of the `scheduleUserDeactivation` mutation. */
+export enum ScheduleUserDeactivationStatus {
+  /** The user was not found. */
+  NotFound = "NOT_FOUND",
+  /** The deactivation was scheduled. */
+  Scheduled = "SCHEDULED",
+}
+
+/** A category of notification emails sent on security-relevant account events. */
+export enum SecurityNotification {
+  /** The user was locked. */
+  AccountLock = "ACCOUNT_LOCK",
+  /** The password of the user was changed. */
+  PasswordChange = "PASSWORD_CHANGE",
+  /** An upstream account was linked to the user. */
+  UpstreamLink = "UPSTREAM_LINK",
+}
+
 /** The input for the `sendVerificationEmail` mutation */
 export type SendVerificationEmailInput = {
   /** The ID of the email address to verify */
@@ -747,6 +1039,11 @@ export type SendVerificationEmailPayload = {
   __typename?: "SendVerificationEmailPayload";
   /** The email address to which the verification email was sent */
   email: UserEmail;
+  /**
+   * When a new verification email can be requested, if the rate limits
+   * were hit and the delay is known
+   */
+  retryAfter?: Maybe<Scalars["DateTime"]["output"]>;
   /** Status of the operation */
   status: SendVerificationEmailStatus;
   /** The user to whom the email address belongs */
@@ -757,6 +1054,8 @@ export type SendVerificationEmailPayload = {
 export enum SendVerificationEmailStatus {
   /** The email address is already verified */
   AlreadyVerified = "ALREADY_VERIFIED",
+  /** Too many verification emails were sent recently */
+  RateLimited = "RATE_LIMITED",
   /** The verification email was sent */
   Sent = "SENT",
 }
@@ -808,8 +1107,100 @@ export type SetDisplayNamePayload = {
 export enum SetDisplayNameStatus {
   /** The display name is invalid */
   Invalid = "INVALID",
+  /** The display name is managed by an upstream provider */
+  Locked = "LOCKED",
   /** The display name was set */
   Set = "SET",
+  /** The homeserver does not allow changing the display name */
+  Unsupported = "UNSUPPORTED",
+}
+
+/** The input for the `setOauth2ClientClaimMappings` mutation. */
+export type SetOAuth2ClientClaimMappingsInput = {
+  /** The ID of the client to update. */
+  clientId: Scalars["ID"]["input"];
+  /**
+   * The attributes of the users mapped to the claims of the ID tokens and
+   * userinfo responses of the client. Replaces the existing ones.
+   */
+  mappings: Array<ClaimMappingInput>;
+};
+
+/** The payload for the `setOauth2ClientClaimMappings` mutation. */
+export type SetOAuth2ClientClaimMappingsPayload = {
+  __typename?: "SetOAuth2ClientClaimMappingsPayload";
+  /** The client that was updated. */
+  oauth2Client?: Maybe<Oauth2Client>;
+};
+
+/** The input for the `setOauth2ClientTokenSettings` mutation. */
+export type SetOAuth2ClientTokenSettingsInput = {
+  /**
+   * Time-to-live of the access tokens issued to the client, in seconds.
+   * Uses the server default if not set.
+   */
+  accessTokenTtl?: InputMaybe<Scalars["Int"]["input"]>;
+  /** Audiences added to the tokens issued to the client. */
+  additionalAudiences?: Array<Scalars["String"]["input"]>;
+  /** The ID of the client to update. */
+  clientId: Scalars["ID"]["input"];
+  /**
+   * How long the sessions of the client can go without being refreshed
+   * before they end, in seconds. Uses the server default if not set.
+   */
+  refreshTokenTtl?: InputMaybe<Scalars["Int"]["input"]>;
+};
+
+/** The payload for the `setOauth2ClientTokenSettings` mutation. */
+export type SetOAuth2ClientTokenSettingsPayload = {
+  __typename?: "SetOAuth2ClientTokenSettingsPayload";
+  /** The client that was updated. */
+  oauth2Client?: Maybe<Oauth2Client>;
+};
+
+/** The input for the `setOauth2ClientTrustedScope` mutation. */
+export type SetOAuth2ClientTrustedScopeInput = {
+  /** The ID of the client to update. */
+  clientId: Scalars["ID"]["input"];
+  /**
+   * Space-separated list of scopes users don't have to consent to for the
+   * client. The client is no longer trusted if not set.
+   */
+  scope?: InputMaybe<Scalars["String"]["input"]>;
+};
+
+/** The payload for the `setOauth2ClientTrustedScope` mutation. */
+export type SetOAuth2ClientTrustedScopePayload = {
+  __typename?: "SetOAuth2ClientTrustedScopePayload";
+  /** The client that was updated. */
+  oauth2Client?: Maybe<Oauth2Client>;
+};
+
+/** The input of the `setOauth2SessionName` mutation. */
+export type SetOAuth2SessionNameInput = {
+  /** The new name of the session. An empty name unsets it. */
+  humanName: Scalars["String"]["input"];
+  /** The ID of the session to rename. */
+  oauth2SessionId: Scalars["ID"]["input"];
+};
+
+/** The payload of the `setOauth2SessionName` mutation. */
+export type SetOAuth2SessionNamePayload = {
+  __typename?: "SetOAuth2SessionNamePayload";
+  /** The updated session. */
+  oauth2Session?: Maybe<Oauth2Session>;
+  /** The status of the mutation. */
+  status: SetOAuth2SessionNameStatus;
+};
+
+/** The status of the `setOauth2SessionName` mutation. */
+export enum SetOAuth2SessionNameStatus {
+  /** The session was not found. */
+  NotFound = "NOT_FOUND",
+  /** The homeserver does not allow changing the display name of devices. */
+  Unsupported = "UNSUPPORTED",
+  /** The session was renamed. */
+  Updated = "UPDATED",
 }
 
 /** The input for the `setPrimaryEmail` mutation */
@@ -828,6 +1219,10 @@ export type SetPrimaryEmailPayload = {
 
 /** The status of the `setPrimaryEmail` mutation */
 export enum SetPrimaryEmailStatus {
+  /** The current primary email address has to confirm the change first */
+  AwaitingConfirmation = "AWAITING_CONFIRMATION",
+  /** Another change of the primary email address is in progress */
+  ChangeInProgress = "CHANGE_IN_PROGRESS",
   /** The email address was not found */
   NotFound = "NOT_FOUND",
   /** The email address was set as primary */
@@ -836,6 +1231,80 @@ export enum SetPrimaryEmailStatus {
   Unverified = "UNVERIFIED",
 }
 
+/** The input for the `setQuarantined` mutation. */
+export type SetQuarantinedInput = {
+  /** Whether the user should be quarantined. */
+  quarantined: Scalars["Boolean"]["input"];
+  /** The ID of the user to update. */
+  userId: Scalars["ID"]["input"];
+};
+
+/** The payload for the `setQuarantined` mutation. */
+export type SetQuarantinedPayload = {
+  __typename?: "SetQuarantinedPayload";
+  /** The user that was updated. */
+  user?: Maybe<User>;
+};
+
+/** The input for the `setSecurityNotificationOptOut` mutation. */
+export type SetSecurityNotificationOptOutInput = {
+  /** The category of security notifications to update. */
+  category: SecurityNotification;
+  /** Whether the user should stop receiving this category of notifications. */
+  optOut: Scalars["Boolean"]["input"];
+  /** The ID of the user to update. */
+  userId: Scalars["ID"]["input"];
+};
+
+/** The payload for the `setSecurityNotificationOptOut` mutation. */
+export type SetSecurityNotificationOptOutPayload = {
+  __typename?: "SetSecurityNotificationOptOutPayload";
+  /** The user that was updated. */
+  user?: Maybe<User>;
+};
+
+/** The input for the `startEmailChange` mutation */
+export type StartEmailChangeInput = {
+  /** The email address which should replace the current primary one */
+  email: Scalars["String"]["input"];
+  /** The ID of the user changing their email address */
+  userId: Scalars["ID"]["input"];
+};
+
+/** The payload of the `startEmailChange` mutation */
+export type StartEmailChangePayload = {
+  __typename?: "StartEmailChangePayload";
+  /** The email address which will replace the primary one */
+  email?: Maybe<UserEmail>;
+  /** Status of the operation */
+  status: StartEmailChangeStatus;
+  /** The list of policy violations if the email address was denied */
+  violations?: Maybe<Array<Scalars["String"]["output"]>>;
+};
+
+/** The status of the `startEmailChange` mutation */
+export enum StartEmailChangeStatus {
+  /**
+   * The new email address was already verified, the current primary email
+   * address has to confirm the change
+   */
+  AwaitingConfirmation = "AWAITING_CONFIRMATION",
+  /** Another change of the primary email address is in progress */
+  ChangeInProgress = "CHANGE_IN_PROGRESS",
+  /** The new email address was already verified, the change is done */
+  Completed = "COMPLETED",
+  /** The email address is not allowed by the policy */
+  Denied = "DENIED",
+  /** The email address is invalid */
+  Invalid = "INVALID",
+  /** The user has no verified primary email address to change */
+  NoPrimary = "NO_PRIMARY",
+  /** The email address is already the primary one */
+  Same = "SAME",
+  /** The change was started, the new email address needs to be verified */
+  Started = "STARTED",
+}
+
 export type UpstreamOAuth2Link = CreationEvent &
   Node & {
     __typename?: "UpstreamOAuth2Link";
@@ -879,6 +1348,13 @@ export type UpstreamOAuth2Provider = CreationEvent &
     clientId: Scalars["String"]["output"];
     /** When the object was created. */
     createdAt: Scalars["DateTime"]["output"];
+    /**
+     * The outcome of the last health check of this provider, if it was ever
+     * checked.
+     *
+     * This is only available to administrators.
+     */
+    health?: Maybe<UpstreamOAuth2ProviderHealth>;
     /** ID of the object. */
     id: Scalars["ID"]["output"];
     /** OpenID Connect issuer URL. */
@@ -906,6 +1382,17 @@ export type UpstreamOAuth2ProviderEdge = {
   node: UpstreamOAuth2Provider;
 };
 
+/** The outcome of the health check of an upstream OAuth 2.0 provider. */
+export type UpstreamOAuth2ProviderHealth = {
+  __typename?: "UpstreamOAuth2ProviderHealth";
+  /** When the provider was checked. */
+  checkedAt: Scalars["DateTime"]["output"];
+  /** Why the provider failed the check, if it did. */
+  error?: Maybe<Scalars["String"]["output"]>;
+  /** Whether the provider passed the check. */
+  healthy: Scalars["Boolean"]["output"];
+};
+
 /** A user is an individual's account. */
 export type User = Node & {
   __typename?: "User";
@@ -930,12 +1417,21 @@ export type User = Node & {
   id: Scalars["ID"]["output"];
   /** When the user was locked out. */
   lockedAt?: Maybe<Scalars["DateTime"]["output"]>;
+  /**
+   * Profile attributes which are managed by an upstream provider the user
+   * is linked to, and which they can't change themselves.
+   */
+  lockedProfileAttributes: Array<ProfileAttribute>;
   /** Access to the user's Matrix account information. */
   matrix: MatrixUser;
   /** Get the list of OAuth 2.0 sessions, chronologically sorted */
   oauth2Sessions: Oauth2SessionConnection;
   /** Primary email address of the user. */
   primaryEmail?: Maybe<UserEmail>;
+  /** When the user was quarantined. */
+  quarantinedAt?: Maybe<Scalars["DateTime"]["output"]>;
+  /** Categories of security notification emails the user opted out of. */
+  securityNotificationOptOuts: Array<SecurityNotification>;
   /** Get the list of upstream OAuth 2.0 links */
   upstreamOauth2Links: UpstreamOAuth2LinkConnection;
   /** Username chosen by the user. */
@@ -993,6 +1489,8 @@ export type UserOauth2SessionsArgs = {
   after?: InputMaybe<Scalars["String"]["input"]>;
   before?: InputMaybe<Scalars["String"]["input"]>;
   client?: InputMaybe<Scalars["ID"]["input"]>;
+  createdAfter?: InputMaybe<Scalars["DateTime"]["input"]>;
+  createdBefore?: InputMaybe<Scalars["DateTime"]["input"]>;
   first?: InputMaybe<Scalars["Int"]["input"]>;
   last?: InputMaybe<Scalars["Int"]["input"]>;
   state?: InputMaybe<SessionState>;
@@ -1006,6 +1504,21 @@ export type UserUpstreamOauth2LinksArgs = {
   last?: InputMaybe<Scalars["Int"]["input"]>;
 };
 
+/** An attribute of the users which can be mapped to a claim. */
+export enum UserAttribute {
+  /** The display name of the user, as set on the homeserver. */
+  DisplayName = "DISPLAY_NAME",
+  /** The primary email address of the user. */
+  Email = "EMAIL",
+  /**
+   * The groups imported from the upstream provider the user last logged in
+   * with.
+   */
+  Groups = "GROUPS",
+  /** Whether the user can request admin access. */
+  IsAdmin = "IS_ADMIN",
+}
+
 /** A user email address */
 export type UserEmail = CreationEvent &
   Node & {
@@ -1295,6 +1808,7 @@ export type OAuth2Session_DetailFragment = {
   __typename?: "Oauth2Session";
   id: string;
   scope: string;
+  humanName?: string | null;
   createdAt: string;
   finishedAt?: string | null;
   lastActiveIp?: string | null;
@@ -1309,6 +1823,24 @@ export type OAuth2Session_DetailFragment = {
   };
 } & { " $fragmentName"?: "OAuth2Session_DetailFragment" };
 
+export type SetOAuth2SessionNameMutationVariables = Exact<{
+  id: Scalars["ID"]["input"];
+  humanName: Scalars["String"]["input"];
+}>;
+
+export type SetOAuth2SessionNameMutation = {
+  __typename?: "Mutation";
+  setOauth2SessionName: {
+    __typename?: "SetOAuth2SessionNamePayload";
+    status: SetOAuth2SessionNameStatus;
+    oauth2Session?: {
+      __typename?: "Oauth2Session";
+      id: string;
+      humanName?: string | null;
+    } | null;
+  };
+};
+
 export type SessionQueryQueryVariables = Exact<{
   userId: Scalars["ID"]["input"];
   deviceId: Scalars["String"]["input"];
@@ -1577,6 +2109,7 @@ export type ResendVerificationEmailMutation = {
   sendVerificationEmail: {
     __typename?: "SendVerificationEmailPayload";
     status: SendVerificationEmailStatus;
+    retryAfter?: string | null;
     user: {
       __typename?: "User";
       id: string;
@@ -1881,6 +2414,7 @@ export const OAuth2Session_DetailFragmentDoc = {
         selections: [
           { kind: "Field", name: { kind: "Name", value: "id" } },
           { kind: "Field", name: { kind: "Name", value: "scope" } },
+          { kind: "Field", name: { kind: "Name", value: "humanName" } },
           { kind: "Field", name: { kind: "Name", value: "createdAt" } },
           { kind: "Field", name: { kind: "Name", value: "finishedAt" } },
           { kind: "Field", name: { kind: "Name", value: "lastActiveIp" } },
@@ -2649,6 +3183,99 @@ export const EndOAuth2SessionDocument = {
   EndOAuth2SessionMutation,
   EndOAuth2SessionMutationVariables
 >;
+export const SetOAuth2SessionNameDocument = {
+  kind: "Document",
+  definitions: [
+    {
+      kind: "OperationDefinition",
+      operation: "mutation",
+      name: { kind: "Name", value: "SetOAuth2SessionName" },
+      variableDefinitions: [
+        {
+          kind: "VariableDefinition",
+          variable: { kind: "Variable", name: { kind: "Name", value: "id" } },
+          type: {
+            kind: "NonNullType",
+            type: { kind: "NamedType", name: { kind: "Name", value: "ID" } },
+          },
+        },
+        {
+          kind: "VariableDefinition",
+          variable: {
+            kind: "Variable",
+            name: { kind: "Name", value: "humanName" },
+          },
+          type: {
+            kind: "NonNullType",
+            type: {
+              kind: "NamedType",
+              name: { kind: "Name", value: "String" },
+            },
+          },
+        },
+      ],
+      selectionSet: {
+        kind: "SelectionSet",
+        selections: [
+          {
+            kind: "Field",
+            name: { kind: "Name", value: "setOauth2SessionName" },
+            arguments: [
+              {
+                kind: "Argument",
+                name: { kind: "Name", value: "input" },
+                value: {
+                  kind: "ObjectValue",
+                  fields: [
+                    {
+                      kind: "ObjectField",
+                      name: { kind: "Name", value: "oauth2SessionId" },
+                      value: {
+                        kind: "Variable",
+                        name: { kind: "Name", value: "id" },
+                      },
+                    },
+                    {
+                      kind: "ObjectField",
+                      name: { kind: "Name", value: "humanName" },
+                      value: {
+                        kind: "Variable",
+                        name: { kind: "Name", value: "humanName" },
+                      },
+                    },
+                  ],
+                },
+              },
+            ],
+            selectionSet: {
+              kind: "SelectionSet",
+              selections: [
+                { kind: "Field", name: { kind: "Name", value: "status" } },
+                {
+                  kind: "Field",
+                  name: { kind: "Name", value: "oauth2Session" },
+                  selectionSet: {
+                    kind: "SelectionSet",
+                    selections: [
+                      { kind: "Field", name: { kind: "Name", value: "id" } },
+                      {
+                        kind: "Field",
+                        name: { kind: "Name", value: "humanName" },
+                      },
+                    ],
+                  },
+                },
+              ],
+            },
+          },
+        ],
+      },
+    },
+  ],
+} as unknown as DocumentNode<
+  SetOAuth2SessionNameMutation,
+  SetOAuth2SessionNameMutationVariables
+>;
 export const SessionQueryDocument = {
   kind: "Document",
   definitions: [
@@ -2767,6 +3394,7 @@ export const SessionQueryDocument = {
         selections: [
           { kind: "Field", name: { kind: "Name", value: "id" } },
           { kind: "Field", name: { kind: "Name", value: "scope" } },
+          { kind: "Field", name: { kind: "Name", value: "humanName" } },
           { kind: "Field", name: { kind: "Name", value: "createdAt" } },
           { kind: "Field", name: { kind: "Name", value: "finishedAt" } },
           { kind: "Field", name: { kind: "Name", value: "lastActiveIp" } },
@@ -3955,6 +4583,7 @@ export const ResendVerificationEmailDocument = {
               kind: "SelectionSet",
               selections: [
                 { kind: "Field", name: { kind: "Name", value: "status" } },
+                { kind: "Field", name: { kind: "Name", value: "retryAfter" } },
                 {
                   kind: "Field",
                   name: { kind: "Name", value: "user" },
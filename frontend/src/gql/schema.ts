@@ -257,6 +257,85 @@ export default {
           },
         ],
       },
+      {
+        kind: "OBJECT",
+        name: "AuthorizationApprovalPayload",
+        fields: [
+          {
+            name: "status",
+            type: {
+              kind: "NON_NULL",
+              ofType: {
+                kind: "SCALAR",
+                name: "Any",
+              },
+            },
+            args: [],
+          },
+        ],
+        interfaces: [],
+      },
+      {
+        kind: "OBJECT",
+        name: "AuthorizationApprovalRequest",
+        fields: [
+          {
+            name: "client",
+            type: {
+              kind: "NON_NULL",
+              ofType: {
+                kind: "OBJECT",
+                name: "Oauth2Client",
+                ofType: null,
+              },
+            },
+            args: [],
+          },
+          {
+            name: "id",
+            type: {
+              kind: "NON_NULL",
+              ofType: {
+                kind: "SCALAR",
+                name: "Any",
+              },
+            },
+            args: [],
+          },
+          {
+            name: "requestedAt",
+            type: {
+              kind: "NON_NULL",
+              ofType: {
+                kind: "SCALAR",
+                name: "Any",
+              },
+            },
+            args: [],
+          },
+          {
+            name: "scope",
+            type: {
+              kind: "NON_NULL",
+              ofType: {
+                kind: "SCALAR",
+                name: "Any",
+              },
+            },
+            args: [],
+          },
+          {
+            name: "user",
+            type: {
+              kind: "OBJECT",
+              name: "User",
+              ofType: null,
+            },
+            args: [],
+          },
+        ],
+        interfaces: [],
+      },
       {
         kind: "OBJECT",
         name: "BrowserSession",
@@ -989,6 +1068,62 @@ export default {
         ],
         interfaces: [],
       },
+      {
+        kind: "OBJECT",
+        name: "FailedJob",
+        fields: [
+          {
+            name: "failedAt",
+            type: {
+              kind: "SCALAR",
+              name: "Any",
+            },
+            args: [],
+          },
+          {
+            name: "id",
+            type: {
+              kind: "NON_NULL",
+              ofType: {
+                kind: "SCALAR",
+                name: "Any",
+              },
+            },
+            args: [],
+          },
+          {
+            name: "lastError",
+            type: {
+              kind: "SCALAR",
+              name: "Any",
+            },
+            args: [],
+          },
+          {
+            name: "name",
+            type: {
+              kind: "NON_NULL",
+              ofType: {
+                kind: "SCALAR",
+                name: "Any",
+              },
+            },
+            args: [],
+          },
+          {
+            name: "payload",
+            type: {
+              kind: "NON_NULL",
+              ofType: {
+                kind: "SCALAR",
+                name: "Any",
+              },
+            },
+            args: [],
+          },
+        ],
+        interfaces: [],
+      },
       {
         kind: "OBJECT",
         name: "LockUserPayload",
@@ -1100,6 +1235,29 @@ export default {
               },
             ],
           },
+          {
+            name: "approveAuthorizationRequest",
+            type: {
+              kind: "NON_NULL",
+              ofType: {
+                kind: "OBJECT",
+                name: "AuthorizationApprovalPayload",
+                ofType: null,
+              },
+            },
+            args: [
+              {
+                name: "input",
+                type: {
+                  kind: "NON_NULL",
+                  ofType: {
+                    kind: "SCALAR",
+                    name: "Any",
+                  },
+                },
+              },
+            ],
+          },
           {
             name: "createOauth2Session",
             type: {
@@ -1123,6 +1281,29 @@ export default {
               },
             ],
           },
+          {
+            name: "denyAuthorizationRequest",
+            type: {
+              kind: "NON_NULL",
+              ofType: {
+                kind: "OBJECT",
+                name: "AuthorizationApprovalPayload",
+                ofType: null,
+              },
+            },
+            args: [
+              {
+                name: "input",
+                type: {
+                  kind: "NON_NULL",
+                  ofType: {
+                    kind: "SCALAR",
+                    name: "Any",
+                  },
+                },
+              },
+            ],
+          },
           {
             name: "endBrowserSession",
             type: {
@@ -1238,6 +1419,29 @@ export default {
               },
             ],
           },
+          {
+            name: "scheduleUserDeactivation",
+            type: {
+              kind: "NON_NULL",
+              ofType: {
+                kind: "OBJECT",
+                name: "ScheduleUserDeactivationPayload",
+                ofType: null,
+              },
+            },
+            args: [
+              {
+                name: "input",
+                type: {
+                  kind: "NON_NULL",
+                  ofType: {
+                    kind: "SCALAR",
+                    name: "Any",
+                  },
+                },
+              },
+            ],
+          },
           {
             name: "sendVerificationEmail",
             type: {
@@ -1308,12 +1512,12 @@ export default {
             ],
           },
           {
-            name: "setPrimaryEmail",
+            name: "setOauth2ClientClaimMappings",
             type: {
               kind: "NON_NULL",
               ofType: {
                 kind: "OBJECT",
-                name: "SetPrimaryEmailPayload",
+                name: "SetOAuth2ClientClaimMappingsPayload",
                 ofType: null,
               },
             },
@@ -1331,12 +1535,12 @@ export default {
             ],
           },
           {
-            name: "verifyEmail",
+            name: "setOauth2ClientTokenSettings",
             type: {
               kind: "NON_NULL",
               ofType: {
                 kind: "OBJECT",
-                name: "VerifyEmailPayload",
+                name: "SetOAuth2ClientTokenSettingsPayload",
                 ofType: null,
               },
             },
@@ -1353,45 +1557,206 @@ export default {
               },
             ],
           },
-        ],
-        interfaces: [],
-      },
-      {
-        kind: "INTERFACE",
-        name: "Node",
-        fields: [
           {
-            name: "id",
+            name: "setOauth2ClientTrustedScope",
             type: {
               kind: "NON_NULL",
               ofType: {
-                kind: "SCALAR",
-                name: "Any",
+                kind: "OBJECT",
+                name: "SetOAuth2ClientTrustedScopePayload",
+                ofType: null,
               },
             },
-            args: [],
-          },
-        ],
-        interfaces: [],
-        possibleTypes: [
-          {
-            kind: "OBJECT",
-            name: "Anonymous",
-          },
-          {
-            kind: "OBJECT",
-            name: "Authentication",
-          },
-          {
-            kind: "OBJECT",
-            name: "BrowserSession",
+            args: [
+              {
+                name: "input",
+                type: {
+                  kind: "NON_NULL",
+                  ofType: {
+                    kind: "SCALAR",
+                    name: "Any",
+                  },
+                },
+              },
+            ],
           },
           {
-            kind: "OBJECT",
-            name: "CompatSession",
+            name: "setOauth2SessionName",
+            type: {
+              kind: "NON_NULL",
+              ofType: {
+                kind: "OBJECT",
+                name: "SetOAuth2SessionNamePayload",
+                ofType: null,
+              },
+            },
+            args: [
+              {
+                name: "input",
+                type: {
+                  kind: "NON_NULL",
+                  ofType: {
+                    kind: "SCALAR",
+                    name: "Any",
+                  },
+                },
+              },
+            ],
           },
           {
-            kind: "OBJECT",
+            name: "setPrimaryEmail",
+            type: {
+              kind: "NON_NULL",
+              ofType: {
+                kind: "OBJECT",
+                name: "SetPrimaryEmailPayload",
+                ofType: null,
+              },
+            },
+            args: [
+              {
+                name: "input",
+                type: {
+                  kind: "NON_NULL",
+                  ofType: {
+                    kind: "SCALAR",
+                    name: "Any",
+                  },
+                },
+              },
+            ],
+          },
+          {
+            name: "setQuarantined",
+            type: {
+              kind: "NON_NULL",
+              ofType: {
+                kind: "OBJECT",
+                name: "SetQuarantinedPayload",
+                ofType: null,
+              },
+            },
+            args: [
+              {
+                name: "input",
+                type: {
+                  kind: "NON_NULL",
+                  ofType: {
+                    kind: "SCALAR",
+                    name: "Any",
+                  },
+                },
+              },
+            ],
+          },
+          {
+            name: "setSecurityNotificationOptOut",
+            type: {
+              kind: "NON_NULL",
+              ofType: {
+                kind: "OBJECT",
+                name: "SetSecurityNotificationOptOutPayload",
+                ofType: null,
+              },
+            },
+            args: [
+              {
+                name: "input",
+                type: {
+                  kind: "NON_NULL",
+                  ofType: {
+                    kind: "SCALAR",
+                    name: "Any",
+                  },
+                },
+              },
+            ],
+          },
+          {
+            name: "startEmailChange",
+            type: {
+              kind: "NON_NULL",
+              ofType: {
+                kind: "OBJECT",
+                name: "StartEmailChangePayload",
+                ofType: null,
+              },
+            },
+            args: [
+              {
+                name: "input",
+                type: {
+                  kind: "NON_NULL",
+                  ofType: {
+                    kind: "SCALAR",
+                    name: "Any",
+                  },
+                },
+              },
+            ],
+          },
+          {
+            name: "verifyEmail",
+            type: {
+              kind: "NON_NULL",
+              ofType: {
+                kind: "OBJECT",
+                name: "VerifyEmailPayload",
+                ofType: null,
+              },
+            },
+            args: [
+              {
+                name: "input",
+                type: {
+                  kind: "NON_NULL",
+                  ofType: {
+                    kind: "SCALAR",
+                    name: "Any",
+                  },
+                },
+              },
+            ],
+          },
+        ],
+        interfaces: [],
+      },
+      {
+        kind: "INTERFACE",
+        name: "Node",
+        fields: [
+          {
+            name: "id",
+            type: {
+              kind: "NON_NULL",
+              ofType: {
+                kind: "SCALAR",
+                name: "Any",
+              },
+            },
+            args: [],
+          },
+        ],
+        interfaces: [],
+        possibleTypes: [
+          {
+            kind: "OBJECT",
+            name: "Anonymous",
+          },
+          {
+            kind: "OBJECT",
+            name: "Authentication",
+          },
+          {
+            kind: "OBJECT",
+            name: "BrowserSession",
+          },
+          {
+            kind: "OBJECT",
+            name: "CompatSession",
+          },
+          {
+            kind: "OBJECT",
             name: "CompatSsoLogin",
           },
           {
@@ -1580,6 +1945,14 @@ export default {
             },
             args: [],
           },
+          {
+            name: "humanName",
+            type: {
+              kind: "SCALAR",
+              name: "Any",
+            },
+            args: [],
+          },
           {
             name: "id",
             type: {
@@ -1795,6 +2168,32 @@ export default {
         kind: "OBJECT",
         name: "Query",
         fields: [
+          {
+            name: "authorizationApprovalRequests",
+            type: {
+              kind: "NON_NULL",
+              ofType: {
+                kind: "LIST",
+                ofType: {
+                  kind: "NON_NULL",
+                  ofType: {
+                    kind: "OBJECT",
+                    name: "AuthorizationApprovalRequest",
+                    ofType: null,
+                  },
+                },
+              },
+            },
+            args: [
+              {
+                name: "first",
+                type: {
+                  kind: "SCALAR",
+                  name: "Any",
+                },
+              },
+            ],
+          },
           {
             name: "browserSession",
             type: {
@@ -1833,6 +2232,32 @@ export default {
             },
             args: [],
           },
+          {
+            name: "failedJobs",
+            type: {
+              kind: "NON_NULL",
+              ofType: {
+                kind: "LIST",
+                ofType: {
+                  kind: "NON_NULL",
+                  ofType: {
+                    kind: "OBJECT",
+                    name: "FailedJob",
+                    ofType: null,
+                  },
+                },
+              },
+            },
+            args: [
+              {
+                name: "first",
+                type: {
+                  kind: "SCALAR",
+                  name: "Any",
+                },
+              },
+            ],
+          },
           {
             name: "node",
             type: {
@@ -1873,6 +2298,82 @@ export default {
               },
             ],
           },
+          {
+            name: "oauth2Sessions",
+            type: {
+              kind: "NON_NULL",
+              ofType: {
+                kind: "OBJECT",
+                name: "Oauth2SessionConnection",
+                ofType: null,
+              },
+            },
+            args: [
+              {
+                name: "after",
+                type: {
+                  kind: "SCALAR",
+                  name: "Any",
+                },
+              },
+              {
+                name: "before",
+                type: {
+                  kind: "SCALAR",
+                  name: "Any",
+                },
+              },
+              {
+                name: "client",
+                type: {
+                  kind: "SCALAR",
+                  name: "Any",
+                },
+              },
+              {
+                name: "createdAfter",
+                type: {
+                  kind: "SCALAR",
+                  name: "Any",
+                },
+              },
+              {
+                name: "createdBefore",
+                type: {
+                  kind: "SCALAR",
+                  name: "Any",
+                },
+              },
+              {
+                name: "first",
+                type: {
+                  kind: "SCALAR",
+                  name: "Any",
+                },
+              },
+              {
+                name: "last",
+                type: {
+                  kind: "SCALAR",
+                  name: "Any",
+                },
+              },
+              {
+                name: "state",
+                type: {
+                  kind: "SCALAR",
+                  name: "Any",
+                },
+              },
+              {
+                name: "user",
+                type: {
+                  kind: "SCALAR",
+                  name: "Any",
+                },
+              },
+            ],
+          },
           {
             name: "session",
             type: {
@@ -2068,22 +2569,49 @@ export default {
             },
             args: [],
           },
-        ],
-        interfaces: [],
-      },
-      {
-        kind: "OBJECT",
-        name: "RemoveEmailPayload",
-        fields: [
+        ],
+        interfaces: [],
+      },
+      {
+        kind: "OBJECT",
+        name: "RemoveEmailPayload",
+        fields: [
+          {
+            name: "email",
+            type: {
+              kind: "OBJECT",
+              name: "UserEmail",
+              ofType: null,
+            },
+            args: [],
+          },
+          {
+            name: "status",
+            type: {
+              kind: "NON_NULL",
+              ofType: {
+                kind: "SCALAR",
+                name: "Any",
+              },
+            },
+            args: [],
+          },
           {
-            name: "email",
+            name: "user",
             type: {
               kind: "OBJECT",
-              name: "UserEmail",
+              name: "User",
               ofType: null,
             },
             args: [],
           },
+        ],
+        interfaces: [],
+      },
+      {
+        kind: "OBJECT",
+        name: "ScheduleUserDeactivationPayload",
+        fields: [
           {
             name: "status",
             type: {
@@ -2123,6 +2651,14 @@ export default {
             },
             args: [],
           },
+          {
+            name: "retryAfter",
+            type: {
+              kind: "SCALAR",
+              name: "Any",
+            },
+            args: [],
+          },
           {
             name: "status",
             type: {
@@ -2206,6 +2742,81 @@ export default {
         ],
         interfaces: [],
       },
+      {
+        kind: "OBJECT",
+        name: "SetOAuth2ClientClaimMappingsPayload",
+        fields: [
+          {
+            name: "oauth2Client",
+            type: {
+              kind: "OBJECT",
+              name: "Oauth2Client",
+              ofType: null,
+            },
+            args: [],
+          },
+        ],
+        interfaces: [],
+      },
+      {
+        kind: "OBJECT",
+        name: "SetOAuth2ClientTokenSettingsPayload",
+        fields: [
+          {
+            name: "oauth2Client",
+            type: {
+              kind: "OBJECT",
+              name: "Oauth2Client",
+              ofType: null,
+            },
+            args: [],
+          },
+        ],
+        interfaces: [],
+      },
+      {
+        kind: "OBJECT",
+        name: "SetOAuth2ClientTrustedScopePayload",
+        fields: [
+          {
+            name: "oauth2Client",
+            type: {
+              kind: "OBJECT",
+              name: "Oauth2Client",
+              ofType: null,
+            },
+            args: [],
+          },
+        ],
+        interfaces: [],
+      },
+      {
+        kind: "OBJECT",
+        name: "SetOAuth2SessionNamePayload",
+        fields: [
+          {
+            name: "oauth2Session",
+            type: {
+              kind: "OBJECT",
+              name: "Oauth2Session",
+              ofType: null,
+            },
+            args: [],
+          },
+          {
+            name: "status",
+            type: {
+              kind: "NON_NULL",
+              ofType: {
+                kind: "SCALAR",
+                name: "Any",
+              },
+            },
+            args: [],
+          },
+        ],
+        interfaces: [],
+      },
       {
         kind: "OBJECT",
         name: "SetPrimaryEmailPayload",
@@ -2233,6 +2844,79 @@ export default {
         ],
         interfaces: [],
       },
+      {
+        kind: "OBJECT",
+        name: "SetQuarantinedPayload",
+        fields: [
+          {
+            name: "user",
+            type: {
+              kind: "OBJECT",
+              name: "User",
+              ofType: null,
+            },
+            args: [],
+          },
+        ],
+        interfaces: [],
+      },
+      {
+        kind: "OBJECT",
+        name: "SetSecurityNotificationOptOutPayload",
+        fields: [
+          {
+            name: "user",
+            type: {
+              kind: "OBJECT",
+              name: "User",
+              ofType: null,
+            },
+            args: [],
+          },
+        ],
+        interfaces: [],
+      },
+      {
+        kind: "OBJECT",
+        name: "StartEmailChangePayload",
+        fields: [
+          {
+            name: "email",
+            type: {
+              kind: "OBJECT",
+              name: "UserEmail",
+              ofType: null,
+            },
+            args: [],
+          },
+          {
+            name: "status",
+            type: {
+              kind: "NON_NULL",
+              ofType: {
+                kind: "SCALAR",
+                name: "Any",
+              },
+            },
+            args: [],
+          },
+          {
+            name: "violations",
+            type: {
+              kind: "LIST",
+              ofType: {
+                kind: "NON_NULL",
+                ofType: {
+                  kind: "SCALAR",
+                  name: "Any",
+                },
+              },
+            },
+            args: [],
+          },
+        ],
+        interfaces: [],
+      },
       {
         kind: "OBJECT",
         name: "UpstreamOAuth2Link",
@@ -2425,6 +3109,15 @@ export default {
             },
             args: [],
           },
+          {
+            name: "health",
+            type: {
+              kind: "OBJECT",
+              name: "UpstreamOAuth2ProviderHealth",
+              ofType: null,
+            },
+            args: [],
+          },
           {
             name: "id",
             type: {
@@ -2555,6 +3248,43 @@ export default {
         ],
         interfaces: [],
       },
+      {
+        kind: "OBJECT",
+        name: "UpstreamOAuth2ProviderHealth",
+        fields: [
+          {
+            name: "checkedAt",
+            type: {
+              kind: "NON_NULL",
+              ofType: {
+                kind: "SCALAR",
+                name: "Any",
+              },
+            },
+            args: [],
+          },
+          {
+            name: "error",
+            type: {
+              kind: "SCALAR",
+              name: "Any",
+            },
+            args: [],
+          },
+          {
+            name: "healthy",
+            type: {
+              kind: "NON_NULL",
+              ofType: {
+                kind: "SCALAR",
+                name: "Any",
+              },
+            },
+            args: [],
+          },
+        ],
+        interfaces: [],
+      },
       {
         kind: "OBJECT",
         name: "User",
@@ -2847,6 +3577,23 @@ export default {
             },
             args: [],
           },
+          {
+            name: "lockedProfileAttributes",
+            type: {
+              kind: "NON_NULL",
+              ofType: {
+                kind: "LIST",
+                ofType: {
+                  kind: "NON_NULL",
+                  ofType: {
+                    kind: "SCALAR",
+                    name: "Any",
+                  },
+                },
+              },
+            },
+            args: [],
+          },
           {
             name: "matrix",
             type: {
@@ -2891,6 +3638,20 @@ export default {
                   name: "Any",
                 },
               },
+              {
+                name: "createdAfter",
+                type: {
+                  kind: "SCALAR",
+                  name: "Any",
+                },
+              },
+              {
+                name: "createdBefore",
+                type: {
+                  kind: "SCALAR",
+                  name: "Any",
+                },
+              },
               {
                 name: "first",
                 type: {
@@ -2923,6 +3684,31 @@ export default {
             },
             args: [],
           },
+          {
+            name: "quarantinedAt",
+            type: {
+              kind: "SCALAR",
+              name: "Any",
+            },
+            args: [],
+          },
+          {
+            name: "securityNotificationOptOuts",
+            type: {
+              kind: "NON_NULL",
+              ofType: {
+                kind: "LIST",
+                ofType: {
+                  kind: "NON_NULL",
+                  ofType: {
+                    kind: "SCALAR",
+                    name: "Any",
+                  },
+                },
+              },
+            },
+            args: [],
+          },
           {
             name: "upstreamOauth2Links",
             type: {
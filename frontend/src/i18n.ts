@@ -46,7 +46,9 @@ i18n
     pluralSeparator: ":",
     supportedLngs,
     detection: {
-      order: ["navigator", "htmlTag"],
+      // The server sets the language picked by the user on the page, so that
+      // dates and messages follow it rather than the browser settings
+      order: ["htmlTag", "navigator"],
     } satisfies DetectorOptions,
     interpolation: {
       escapeValue: false, // React has built-in XSS protections
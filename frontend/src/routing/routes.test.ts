@@ -59,6 +59,19 @@ describe("routes", () => {
       });
     });
 
+    it("returns sessions overview for the sessions deep link", () => {
+      const segments: string[] = ["sessions"];
+      expect(segmentsToRoute(segments)).toEqual({ type: "sessions-overview" });
+    });
+
+    it("returns session detail for the session deep link", () => {
+      const segments: string[] = ["sessions", "device-id"];
+      expect(segmentsToRoute(segments)).toEqual({
+        type: "session",
+        id: "device-id",
+      });
+    });
+
     it("returns unknown for other segments", () => {
       const segments: string[] = ["just", "testing"];
       expect(segmentsToRoute(segments)).toEqual({ type: "unknown", segments });
@@ -108,7 +108,9 @@ export const segmentsToRoute = (segments: Segments): Route => {
     return { type: "profile" };
   }
 
-  if (matches("sessions-overview")) {
+  // `sessions` and `sessions/:id` are stable paths which clients can deep link
+  // to, and must keep working
+  if (matches("sessions-overview") || matches("sessions")) {
     return { type: "sessions-overview" };
   }
 
@@ -128,7 +130,7 @@ export const segmentsToRoute = (segments: Segments): Route => {
     return { type: "browser-session", id: segments[1] };
   }
 
-  if (matches("session", P)) {
+  if (matches("session", P) || matches("sessions", P)) {
     return { type: "session", id: segments[1] };
   }
 
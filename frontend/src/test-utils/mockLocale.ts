@@ -17,14 +17,15 @@ import { vi } from "vitest";
 /**
  * Mock the locale on Intl.DateTimeFormat
  * To achieve stable formatted dates across environments
+ * Overrides the locale picked by the app, which is `en` in tests
  * Defaults to `en-GB`
  */
 export const mockLocale = (defaultLocale = "en-GB"): void => {
   const { DateTimeFormat } = Intl;
   vi.spyOn(Intl, "DateTimeFormat").mockImplementation(
     (
-      locales?: string | string[] | undefined,
+      _locales?: string | string[] | undefined,
       options?: Intl.DateTimeFormatOptions | undefined,
-    ) => new DateTimeFormat(locales || defaultLocale, options),
+    ) => new DateTimeFormat(defaultLocale, options),
   );
 };
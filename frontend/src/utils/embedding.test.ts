@@ -0,0 +1,86 @@
+/* Copyright 2023 The Matrix.org Foundation C.I.C.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+import { describe, it, expect, vi } from "vitest";
+
+import { ACTION_COMPLETED, notifyActionCompleted } from "./embedding";
+
+const fakeWindow = (opener: unknown, parent?: unknown): Window => {
+  const current = { opener } as { opener: unknown; parent: unknown };
+  current.parent = parent ?? current;
+  return current as unknown as Window;
+};
+
+const ORIGINS = ["https://app.example.com", "https://other.example.com"];
+
+describe("notifyActionCompleted()", () => {
+  it("does nothing when the page is not embedded", () => {
+    expect(() =>
+      notifyActionCompleted(
+        "session_end",
+        "device-id",
+        fakeWindow(null),
+        ORIGINS,
+      ),
+    ).not.toThrow();
+  });
+
+  it("notifies the window which opened the page", () => {
+    const opener = { postMessage: vi.fn() };
+    notifyActionCompleted(
+      "session_end",
+      "device-id",
+      fakeWindow(opener),
+      ORIGINS,
+    );
+    expect(opener.postMessage).toHaveBeenCalledWith(
+      { type: ACTION_COMPLETED, action: "session_end", device_id: "device-id" },
+      "https://app.example.com",
+    );
+  });
+
+  it("notifies the window which embedded the page", () => {
+    const parent = { postMessage: vi.fn() };
+    notifyActionCompleted(
+      "profile",
+      undefined,
+      fakeWindow(null, parent),
+      ORIGINS,
+    );
+    expect(parent.postMessage).toHaveBeenCalledWith(
+      { type: ACTION_COMPLETED, action: "profile" },
+      "https://app.example.com",
+    );
+  });
+
+  it("only posts to the allowed origins", () => {
+    const opener = { postMessage: vi.fn() };
+    notifyActionCompleted("profile", undefined, fakeWindow(opener), ORIGINS);
+    expect(opener.postMessage).toHaveBeenCalledTimes(2);
+    expect(opener.postMessage).toHaveBeenCalledWith(
+      { type: ACTION_COMPLETED, action: "profile" },
+      "https://other.example.com",
+    );
+    expect(opener.postMessage).not.toHaveBeenCalledWith(
+      expect.anything(),
+      "*",
+    );
+
+    // Nothing is posted when no origin is allowed
+    const opener2 = { postMessage: vi.fn() };
+    notifyActionCompleted("profile", undefined, fakeWindow(opener2), []);
+    expect(opener2.postMessage).not.toHaveBeenCalled();
+  });
+});
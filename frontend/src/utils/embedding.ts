@@ -0,0 +1,59 @@
+/* Copyright 2023 The Matrix.org Foundation C.I.C.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+import config from "../config";
+
+// Must be kept in sync with templates/components/embedding.html
+export const ACTION_COMPLETED = "org.matrix.account_management.action_completed";
+
+export type ActionCompletedMessage = {
+  type: typeof ACTION_COMPLETED;
+  action: string;
+  device_id?: string;
+};
+
+/**
+ * Get the window which opened this page in a popup or embedded it in an
+ * iframe, if any
+ */
+const getEmbedder = (current: Window): Window | null => {
+  if (current.opener) return current.opener;
+  if (current.parent !== current) return current.parent;
+  return null;
+};
+
+/**
+ * Tell the client which deep linked to the account management that an action
+ * was completed
+ *
+ * The message is only posted to the origins allowed to embed the account
+ * management, the browser drops it if the client has another origin.
+ */
+export const notifyActionCompleted = (
+  action: string,
+  deviceId?: string | null,
+  current: Window = window,
+  origins: string[] = config.embeddingOrigins,
+): void => {
+  const embedder = getEmbedder(current);
+  if (!embedder) return;
+
+  const message: ActionCompletedMessage = { type: ACTION_COMPLETED, action };
+  if (deviceId) message.device_id = deviceId;
+
+  for (const origin of origins) {
+    embedder.postMessage(message, origin);
+  }
+};
@@ -61,6 +61,14 @@ export default defineConfig((env) => ({
         resolve(__dirname, "src/main.tsx"),
         resolve(__dirname, "src/templates.css"),
       ],
+
+      // The server caches the files listed in the manifest forever, so their
+      // names must change whenever their content does
+      output: {
+        entryFileNames: "[name]-[hash].js",
+        chunkFileNames: "[name]-[hash].js",
+        assetFileNames: "[name]-[hash][extname]",
+      },
     },
   },
 
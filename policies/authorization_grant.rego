@@ -3,6 +3,8 @@
 #   - input: schema["authorization_grant_input"]
 package authorization_grant
 
+import data.request as request_policy
+
 import future.keywords.in
 
 default allow := false
@@ -53,6 +55,13 @@ allowed_scope("urn:mas:admin") {
 	input.client.id == client
 }
 
+# Trusted services can get the tokens issued to users by the upstream providers
+allowed_scope("urn:mas:upstream_tokens") {
+	input.grant_type == "client_credentials"
+	some client in data.upstream_token_clients
+	input.client.id == client
+}
+
 allowed_scope(scope) {
 	# Grant access to the C-S API only if there is a user
 	input.grant_type == "authorization_code"
@@ -64,6 +73,12 @@ allowed_scope("urn:matrix:org.matrix.msc2967.client:api:*") {
 	input.grant_type == "authorization_code"
 }
 
+# Custom scopes defined by the operator in the `scopes` section of the config
+allowed_scope(scope) {
+	some custom_scope in data.custom_scopes
+	scope == custom_scope
+}
+
 violation[{"msg": msg}] {
 	some scope in split(input.scope, " ")
 	not allowed_scope(scope)
@@ -74,3 +89,33 @@ violation[{"msg": "only one device scope is allowed at a time"}] {
 	scope_list := split(input.scope, " ")
 	count({key | scope_list[key]; startswith(scope_list[key], "urn:matrix:org.matrix.msc2967.client:device:")}) > 1
 }
+
+# Clients can be restricted to a subset of users, either by listing their
+# usernames, or by letting in users who can request admin
+user_allowed_on_client(user, client) {
+	some username in data.restricted_clients[client.client_id].allowed_users
+	user.username == username
+}
+
+user_allowed_on_client(user, client) {
+	data.restricted_clients[client.client_id].allow_admins
+	can_request_admin(user)
+}
+
+violation[{"msg": "user is not allowed to use this client", "code": "client-restricted"}] {
+	# Only applies to grants which have a user
+	input.user
+	data.restricted_clients[input.client.client_id]
+	not user_allowed_on_client(input.user, input.client)
+}
+
+# Quarantined users keep their existing sessions, but can't be granted new ones
+violation[{"msg": "user is quarantined"}] {
+	input.user.quarantined_at != null
+}
+
+# Clients can be restricted to some hours of the day, in UTC
+violation[{"msg": "client is not available at this time", "code": "client-outside-hours"}] {
+	hours := data.client_hours[input.client.client_id]
+	not request_policy.between_hours(input.request, hours.start, hours.end)
+}
@@ -39,6 +39,20 @@ test_matrix_scopes {
 		with input.scope as "urn:matrix:org.matrix.msc2967.client:api:*"
 }
 
+test_custom_scopes {
+	allow with input.user as user
+		with input.client as client
+		with input.grant_type as "client_credentials"
+		with input.scope as "urn:example:read"
+		with data.custom_scopes as ["urn:example:read"]
+
+	not allow with input.user as user
+		with input.client as client
+		with input.grant_type as "client_credentials"
+		with input.scope as "urn:example:write"
+		with data.custom_scopes as ["urn:example:read"]
+}
+
 test_device_scopes {
 	allow with input.user as user
 		with input.client as client
@@ -129,3 +143,106 @@ test_mas_scopes {
 		with input.grant_type as "authorization_code"
 		with input.scope as "urn:mas:admin"
 }
+
+test_upstream_tokens_scope {
+	allow with input.client as client
+		with input.client.id as "01H8PKNWKKRPCBW4YGH1RWV279"
+		with data.upstream_token_clients as ["01H8PKNWKKRPCBW4YGH1RWV279"]
+		with input.grant_type as "client_credentials"
+		with input.scope as "urn:mas:upstream_tokens"
+
+	not allow with input.client as client
+		with input.client.id as "01H8PKNWKKRPCBW4YGH1RWV279"
+		with data.upstream_token_clients as []
+		with input.grant_type as "client_credentials"
+		with input.scope as "urn:mas:upstream_tokens"
+
+	# Admin clients don't get them
+	not allow with input.client as client
+		with input.client.id as "01H8PKNWKKRPCBW4YGH1RWV279"
+		with data.admin_clients as ["01H8PKNWKKRPCBW4YGH1RWV279"]
+		with data.upstream_token_clients as []
+		with input.grant_type as "client_credentials"
+		with input.scope as "urn:mas:upstream_tokens"
+
+	# Users can't get them
+	not allow with input.user as user
+		with input.client as client
+		with input.client.id as "01H8PKNWKKRPCBW4YGH1RWV279"
+		with data.upstream_token_clients as ["01H8PKNWKKRPCBW4YGH1RWV279"]
+		with input.grant_type as "authorization_code"
+		with input.scope as "urn:mas:upstream_tokens"
+}
+
+test_quarantined_user {
+	allow with input.user as user
+		with input.user.quarantined_at as null
+		with input.client as client
+		with input.grant_type as "authorization_code"
+		with input.scope as "openid"
+
+	not allow with input.user as user
+		with input.user.quarantined_at as "2023-10-17T09:15:12Z"
+		with input.client as client
+		with input.grant_type as "authorization_code"
+		with input.scope as "openid"
+}
+
+test_restricted_client {
+	# Unrestricted clients are available to everyone
+	allow with input.user as user
+		with input.client as client
+		with data.restricted_clients as {"other-client": {"allowed_users": []}}
+		with input.grant_type as "authorization_code"
+		with input.scope as "openid"
+
+	allow with input.user as user
+		with input.client as client
+		with data.restricted_clients as {"client": {"allowed_users": ["john"]}}
+		with input.grant_type as "authorization_code"
+		with input.scope as "openid"
+
+	not allow with input.user as user
+		with input.client as client
+		with data.restricted_clients as {"client": {"allowed_users": ["jane"]}}
+		with input.grant_type as "authorization_code"
+		with input.scope as "openid"
+
+	allow with input.user as user
+		with input.client as client
+		with data.restricted_clients as {"client": {"allow_admins": true}}
+		with data.admin_users as ["john"]
+		with input.grant_type as "authorization_code"
+		with input.scope as "openid"
+
+	not allow with input.user as user
+		with input.client as client
+		with data.restricted_clients as {"client": {"allow_admins": true}}
+		with data.admin_users as []
+		with input.grant_type as "authorization_code"
+		with input.scope as "openid"
+}
+
+test_client_hours {
+	allow with input.user as user
+		with input.client as client
+		with data.client_hours as {"client": {"start": 9, "end": 18}}
+		with input.request.time.hour as 10
+		with input.grant_type as "authorization_code"
+		with input.scope as "openid"
+
+	not allow with input.user as user
+		with input.client as client
+		with data.client_hours as {"client": {"start": 9, "end": 18}}
+		with input.request.time.hour as 20
+		with input.grant_type as "authorization_code"
+		with input.scope as "openid"
+
+	# Other clients are not restricted
+	allow with input.user as user
+		with input.client as client
+		with data.client_hours as {"other-client": {"start": 9, "end": 18}}
+		with input.request.time.hour as 20
+		with input.grant_type as "authorization_code"
+		with input.scope as "openid"
+}
@@ -0,0 +1,25 @@
+# METADATA
+# schemas:
+#   - input: schema["claims_input"]
+package claims
+
+import future.keywords.in
+
+default allow := false
+
+allow {
+	count(violation) == 0
+}
+
+# Some claims can be denied to some clients, keyed by client ID
+violation[{"msg": "claim is denied to this client", "field": claim, "code": "claim-denied"}] {
+	some claim, _ in input.claims
+	claim in data.claims.denied_claims[input.client.client_id]
+}
+
+# Some claims can be restricted to a set of clients, keyed by claim
+violation[{"msg": "claim is restricted to other clients", "field": claim, "code": "claim-restricted"}] {
+	some claim, _ in input.claims
+	allowed_clients := data.claims.restricted_claims[claim]
+	not input.client.client_id in allowed_clients
+}
@@ -0,0 +1,34 @@
+package claims
+
+client := {"id": "01H8PKNWKKRPCBW4YGH1RWV279", "client_id": "client"}
+
+claims := {"groups": ["staff"], "is_admin": true}
+
+test_no_rules {
+	allow with input.client as client
+		with input.claims as claims
+}
+
+test_denied_claims {
+	not allow with input.client as client
+		with input.claims as claims
+		with data.claims.denied_claims as {"client": ["groups"]}
+
+	violation == {{"msg": "claim is denied to this client", "field": "groups", "code": "claim-denied"}} with input.client as client
+		with input.claims as claims
+		with data.claims.denied_claims as {"client": ["groups"]}
+
+	allow with input.client as client
+		with input.claims as claims
+		with data.claims.denied_claims as {"other-client": ["groups"]}
+}
+
+test_restricted_claims {
+	allow with input.client as client
+		with input.claims as claims
+		with data.claims.restricted_claims as {"is_admin": ["client"]}
+
+	violation == {{"msg": "claim is restricted to other clients", "field": "is_admin", "code": "claim-restricted"}} with input.client as client
+		with input.claims as claims
+		with data.claims.restricted_claims as {"is_admin": ["other-client"]}
+}
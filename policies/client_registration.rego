@@ -133,6 +133,11 @@ requires_redirect_uris {
 	uses_grant_type("implicit")
 }
 
+violation[{"msg": sprintf("grant_type %s is disabled", [grant_type])}] {
+	some grant_type in data.features.disabled_grant_types
+	uses_grant_type(grant_type)
+}
+
 violation[{"msg": "client_credentials grant_type requires some form of client authentication"}] {
 	uses_grant_type("client_credentials")
 	is_public_client
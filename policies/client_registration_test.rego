@@ -434,6 +434,32 @@ test_client_credentials_grant {
 	}
 }
 
+test_disabled_grant_types {
+	allow with input.client_metadata as {
+		"grant_types": ["authorization_code", "refresh_token"],
+		"client_uri": "https://example.com/",
+		"redirect_uris": ["https://example.com/callback"],
+		"contacts": ["contact@example.com"],
+	}
+		with data.features.disabled_grant_types as ["client_credentials"]
+
+	not allow with input.client_metadata as {
+		"grant_types": ["authorization_code", "refresh_token"],
+		"client_uri": "https://example.com/",
+		"redirect_uris": ["https://example.com/callback"],
+		"contacts": ["contact@example.com"],
+	}
+		with data.features.disabled_grant_types as ["refresh_token"]
+
+	# A missing grant_types means authorization_code
+	not allow with input.client_metadata as {
+		"client_uri": "https://example.com/",
+		"redirect_uris": ["https://example.com/callback"],
+		"contacts": ["contact@example.com"],
+	}
+		with data.features.disabled_grant_types as ["authorization_code"]
+}
+
 test_is_subdomain {
 	is_subdomain("example.com", "example.com")
 	is_subdomain("example.com", "app.example.com")
@@ -5,6 +5,7 @@ package register
 
 import data.email as email_policy
 import data.password as password_policy
+import data.request as request_policy
 
 import future.keywords.in
 
@@ -14,16 +15,43 @@ allow {
 	count(violation) == 0
 }
 
+# The rules on usernames can be overridden through data.usernames. They don't
+# apply to guest upgrades, as the server picked the username of the guest
+default min_username_length := 3
+
+min_username_length := data.usernames.min_length
+
+default max_username_length := 14
+
+max_username_length := data.usernames.max_length
+
+default username_pattern := "^[a-z0-9.=_/-]+$"
+
+username_pattern := data.usernames.pattern
+
+default reserved_usernames := ["admin", "abuse", "matrix"]
+
+reserved_usernames := data.usernames.reserved
+
 violation[{"field": "username", "msg": "username too short"}] {
-	count(input.username) <= 2
+	not input.registration_method == "guest-upgrade"
+	count(input.username) < min_username_length
 }
 
 violation[{"field": "username", "msg": "username too long"}] {
-	count(input.username) >= 15
+	not input.registration_method == "guest-upgrade"
+	count(input.username) > max_username_length
 }
 
 violation[{"field": "username", "msg": "username contains invalid characters"}] {
-	not regex.match("^[a-z0-9.=_/-]+$", input.username)
+	not input.registration_method == "guest-upgrade"
+	not regex.match(username_pattern, input.username)
+}
+
+violation[{"field": "username", "msg": "username is reserved"}] {
+	not input.registration_method == "guest-upgrade"
+	some reserved in reserved_usernames
+	lower(input.username) == lower(reserved)
 }
 
 violation[{"msg": "unspecified registration method"}] {
@@ -31,7 +59,7 @@ violation[{"msg": "unspecified registration method"}] {
 }
 
 violation[{"msg": "unknown registration method"}] {
-	not input.registration_method in ["password", "upstream-oauth2"]
+	not input.registration_method in ["password", "upstream-oauth2", "guest-upgrade"]
 }
 
 violation[object.union({"field": "password"}, v)] {
@@ -58,3 +86,11 @@ violation[object.union({"field": "email"}, v)] {
 	# Get the violation object from the email policy
 	some v in email_policy.violation
 }
+
+violation[{"msg": "registration is not allowed from this country", "code": "country-banned"}] {
+	request_policy.country_in(input.request, data.registration.banned_countries)
+}
+
+violation[{"msg": "registration is not allowed from this IP address", "code": "ip-banned"}] {
+	request_policy.ip_in_ranges(input.request, data.registration.banned_ip_ranges)
+}
@@ -52,6 +52,33 @@ test_invalid_username {
 	not allow with input as {"username": "hello world", "registration_method": "upstream-oauth2"}
 }
 
+test_reserved_username {
+	not allow with input as {"username": "admin", "registration_method": "upstream-oauth2"}
+	not allow with input as {"username": "Abuse", "registration_method": "upstream-oauth2"}
+
+	allow with input as {"username": "admin", "registration_method": "upstream-oauth2"}
+		with data.usernames.reserved as ["root"]
+
+	not allow with input as {"username": "root", "registration_method": "upstream-oauth2"}
+		with data.usernames.reserved as ["root"]
+}
+
+test_username_length_limits {
+	allow with input as {"username": "ab", "registration_method": "upstream-oauth2"}
+		with data.usernames.min_length as 2
+
+	not allow with input as {"username": "hello", "registration_method": "upstream-oauth2"}
+		with data.usernames.max_length as 4
+}
+
+test_username_pattern {
+	allow with input as {"username": "Hello", "registration_method": "upstream-oauth2"}
+		with data.usernames.pattern as "^[a-zA-Z]+$"
+
+	not allow with input as {"username": "hello_world", "registration_method": "upstream-oauth2"}
+		with data.usernames.pattern as "^[a-zA-Z]+$"
+}
+
 test_password_require_number {
 	allow with input as mock_registration
 		with data.passwords.require_number as true
@@ -87,3 +114,36 @@ test_password_min_length {
 		with input.password as "short"
 		with data.passwords.min_length as 6
 }
+
+test_banned_countries {
+	allow with input as mock_registration
+		with input.request.country as "FR"
+		with data.registration.banned_countries as ["DE"]
+
+	not allow with input as mock_registration
+		with input.request.country as "DE"
+		with data.registration.banned_countries as ["DE"]
+
+	# The country is unknown without a GeoIP database
+	allow with input as mock_registration
+		with data.registration.banned_countries as ["DE"]
+}
+
+test_banned_ip_ranges {
+	allow with input as mock_registration
+		with input.request.ip_address as "192.168.1.1"
+		with data.registration.banned_ip_ranges as ["10.0.0.0/8"]
+
+	not allow with input as mock_registration
+		with input.request.ip_address as "10.1.2.3"
+		with data.registration.banned_ip_ranges as ["10.0.0.0/8"]
+}
+
+test_guest_upgrade {
+	# The username of guests doesn't have to follow the rules on usernames
+	allow with input as {"username": "guest-abcdefghij", "registration_method": "guest-upgrade"}
+
+	not allow with input as {"username": "guest-abcdefghij", "registration_method": "guest-upgrade"}
+		with input.request.ip_address as "10.1.2.3"
+		with data.registration.banned_ip_ranges as ["10.0.0.0/8"]
+}
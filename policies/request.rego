@@ -0,0 +1,44 @@
+package request
+
+import future.keywords.in
+
+# Helpers to write rules on the context of the request, passed as
+# `input.request` to the policies evaluated on behalf of a client
+
+# Check if the IP address of the client is in one of the given CIDR ranges
+ip_in_ranges(request, ranges) {
+	some range in ranges
+	net.cidr_contains(range, request.ip_address)
+}
+
+# Check if the request was made from one of the given countries, as looked up
+# in the GeoIP database
+country_in(request, countries) {
+	some country in countries
+	upper(request.country) == upper(country)
+}
+
+# Check if the request was made between the `start` hour (included) and the
+# `end` hour (excluded), in UTC. Ranges can wrap around midnight, e.g. from 22
+# to 6
+between_hours(request, start, end) {
+	start <= end
+	request.time.hour >= start
+	request.time.hour < end
+}
+
+between_hours(request, start, end) {
+	start > end
+	request.time.hour >= start
+}
+
+between_hours(request, start, end) {
+	start > end
+	request.time.hour < end
+}
+
+# Check if the request was made on one of the given days of the week, from 1
+# (Monday) to 7 (Sunday)
+on_weekdays(request, weekdays) {
+	request.time.weekday in weekdays
+}
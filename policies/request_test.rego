@@ -0,0 +1,41 @@
+package request
+
+office := {
+	"ip_address": "192.168.1.42",
+	"user_agent": "Mozilla/5.0",
+	"country": "FR",
+	"time": {"now": "2023-10-30T10:30:00Z", "hour": 10, "minute": 30, "weekday": 1},
+}
+
+night := {
+	"ip_address": "2001:db8::1",
+	"time": {"now": "2023-11-04T23:15:00Z", "hour": 23, "minute": 15, "weekday": 6},
+}
+
+test_ip_in_ranges {
+	ip_in_ranges(office, ["10.0.0.0/8", "192.168.0.0/16"])
+	not ip_in_ranges(office, ["10.0.0.0/8"])
+	ip_in_ranges(night, ["2001:db8::/32"])
+	not ip_in_ranges(night, ["192.168.0.0/16"])
+	not ip_in_ranges({}, ["0.0.0.0/0"])
+}
+
+test_country_in {
+	country_in(office, ["fr", "de"])
+	not country_in(office, ["DE"])
+
+	# Without a GeoIP database, the country is unknown
+	not country_in(night, ["FR"])
+}
+
+test_between_hours {
+	between_hours(office, 9, 18)
+	not between_hours(night, 9, 18)
+	between_hours(night, 22, 6)
+	not between_hours(office, 22, 6)
+}
+
+test_on_weekdays {
+	on_weekdays(office, [1, 2, 3, 4, 5])
+	not on_weekdays(night, [1, 2, 3, 4, 5])
+}
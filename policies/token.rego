@@ -0,0 +1,83 @@
+# METADATA
+# schemas:
+#   - input: schema["token_input"]
+package token
+
+import data.authorization_grant as authorization_grant_policy
+
+import future.keywords.in
+
+default allow := false
+
+allow {
+	count(violation) == 0
+}
+
+# The decision returned to the token endpoint: the token is only issued if
+# there are no violations, with the scope stripped of the denied scopes
+decision := {
+	"violations": violation,
+	"scope": granted_scope,
+}
+
+requested_scope := {scope |
+	some scope in split(input.scope, " ")
+	scope != ""
+}
+
+granted_scope := {scope |
+	some scope in requested_scope
+	not stripped_scope(scope)
+}
+
+# Scopes giving access to admin APIs
+admin_scope("urn:synapse:admin:*")
+
+admin_scope("urn:mas:admin")
+
+# Admin scopes are stripped from tokens of users who can no longer request
+# them, for example when refreshing a token after being removed from the
+# admin users
+stripped_scope(scope) {
+	admin_scope(scope)
+	input.user
+	not authorization_grant_policy.can_request_admin(input.user)
+}
+
+# Clients not in the admin clients list can't get the MAS admin scope
+stripped_scope("urn:mas:admin") {
+	input.grant_type == "client_credentials"
+	not input.client.id in data.admin_clients
+}
+
+# Only the clients allowed by an upstream provider can get the upstream tokens
+# of the users
+stripped_scope("urn:mas:upstream_tokens") {
+	not input.client.id in data.upstream_token_clients
+}
+
+# Clients can be limited to a set of scopes, regardless of what they were
+# granted at authorization time
+stripped_scope(scope) {
+	allowed_scopes := data.token.client_scopes[input.client.client_id]
+	not scope in allowed_scopes
+}
+
+# Scopes can be stripped for some grant types
+stripped_scope(scope) {
+	some stripped in data.token.stripped_scopes[input.grant_type]
+	scope == stripped
+}
+
+# Users removed from a restricted client can't refresh their tokens anymore
+violation[{"msg": "user is not allowed to use this client", "code": "client-restricted"}] {
+	input.user
+	data.restricted_clients[input.client.client_id]
+	not authorization_grant_policy.user_allowed_on_client(input.user, input.client)
+}
+
+# Quarantined users keep their existing sessions, but can't be granted new ones
+violation[{"msg": "user is quarantined"}] {
+	input.grant_type != "refresh_token"
+	input.user.quarantined_at != null
+}
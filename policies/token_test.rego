@@ -0,0 +1,143 @@
+package token
+
+user := {"username": "john"}
+
+admin_user := {"username": "alice", "can_request_admin": true}
+
+client := {"id": "01H8PKNWKKRPCBW4YGH1RWV279", "client_id": "client"}
+
+test_keeps_scope {
+	decision.scope == {"openid", "urn:matrix:org.matrix.msc2967.client:api:*"} with input.user as user
+		with input.client as client
+		with input.grant_type as "refresh_token"
+		with input.scope as "openid urn:matrix:org.matrix.msc2967.client:api:*"
+
+	count(decision.scope) == 0 with input.user as user
+		with input.client as client
+		with input.grant_type as "authorization_code"
+		with input.scope as ""
+}
+
+test_admin_scopes {
+	decision.scope == {"openid", "urn:synapse:admin:*"} with input.user as admin_user
+		with input.client as client
+		with input.grant_type as "refresh_token"
+		with input.scope as "openid urn:synapse:admin:*"
+
+	decision.scope == {"openid"} with input.user as user
+		with input.client as client
+		with input.grant_type as "refresh_token"
+		with input.scope as "openid urn:synapse:admin:*"
+
+	decision.scope == {"openid"} with input.user as user
+		with input.client as client
+		with input.grant_type as "authorization_code"
+		with input.scope as "openid urn:mas:admin"
+
+	decision.scope == {"openid", "urn:mas:admin"} with input.user as user
+		with input.client as client
+		with input.grant_type as "authorization_code"
+		with input.scope as "openid urn:mas:admin"
+		with data.admin_users as ["john"]
+}
+
+test_client_credentials_admin {
+	count(decision.scope) == 0 with input.client as client
+		with input.grant_type as "client_credentials"
+		with input.scope as "urn:mas:admin"
+
+	decision.scope == {"urn:mas:admin"} with input.client as client
+		with input.grant_type as "client_credentials"
+		with input.scope as "urn:mas:admin"
+		with data.admin_clients as ["01H8PKNWKKRPCBW4YGH1RWV279"]
+}
+
+test_client_credentials_upstream_tokens {
+	count(decision.scope) == 0 with input.client as client
+		with input.grant_type as "client_credentials"
+		with input.scope as "urn:mas:upstream_tokens"
+
+	count(decision.scope) == 0 with input.client as client
+		with input.grant_type as "client_credentials"
+		with input.scope as "urn:mas:upstream_tokens"
+		with data.admin_clients as ["01H8PKNWKKRPCBW4YGH1RWV279"]
+
+	decision.scope == {"urn:mas:upstream_tokens"} with input.client as client
+		with input.grant_type as "client_credentials"
+		with input.scope as "urn:mas:upstream_tokens"
+		with data.upstream_token_clients as ["01H8PKNWKKRPCBW4YGH1RWV279"]
+}
+
+test_client_scopes {
+	decision.scope == {"openid"} with input.user as user
+		with input.client as client
+		with input.grant_type as "refresh_token"
+		with input.scope as "openid urn:matrix:org.matrix.msc2967.client:api:*"
+		with data.token.client_scopes as {"client": ["openid"]}
+
+	decision.scope == {"openid", "urn:matrix:org.matrix.msc2967.client:api:*"} with input.user as user
+		with input.client as client
+		with input.grant_type as "refresh_token"
+		with input.scope as "openid urn:matrix:org.matrix.msc2967.client:api:*"
+		with data.token.client_scopes as {"other": ["openid"]}
+}
+
+test_stripped_scopes {
+	decision.scope == {"openid"} with input.user as admin_user
+		with input.client as client
+		with input.grant_type as "refresh_token"
+		with input.scope as "openid urn:synapse:admin:*"
+		with data.token.stripped_scopes as {"refresh_token": ["urn:synapse:admin:*"]}
+
+	decision.scope == {"openid", "urn:synapse:admin:*"} with input.user as admin_user
+		with input.client as client
+		with input.grant_type as "authorization_code"
+		with input.scope as "openid urn:synapse:admin:*"
+		with data.token.stripped_scopes as {"refresh_token": ["urn:synapse:admin:*"]}
+}
+
+test_restricted_clients {
+	allow with input.user as user
+		with input.client as client
+		with input.grant_type as "refresh_token"
+		with input.scope as "openid"
+
+	not allow with input.user as user
+		with input.client as client
+		with input.grant_type as "refresh_token"
+		with input.scope as "openid"
+		with data.restricted_clients as {"client": {"allowed_users": ["alice"]}}
+
+	allow with input.user as admin_user
+		with input.client as client
+		with input.grant_type as "refresh_token"
+		with input.scope as "openid"
+		with data.restricted_clients as {"client": {"allowed_users": ["alice"]}}
+
+	# Client credentials grants have no user
+	allow with input.client as client
+		with input.grant_type as "client_credentials"
+		with input.scope as ""
+		with data.restricted_clients as {"client": {"allowed_users": ["alice"]}}
+}
+
+test_quarantined_user {
+	allow with input.user as user
+		with input.user.quarantined_at as null
+		with input.client as client
+		with input.grant_type as "jwt_bearer"
+		with input.scope as "openid"
+
+	not allow with input.user as user
+		with input.user.quarantined_at as "2023-10-17T09:15:12Z"
+		with input.client as client
+		with input.grant_type as "jwt_bearer"
+		with input.scope as "openid"
+
+	# Existing sessions can still be refreshed
+	allow with input.user as user
+		with input.user.quarantined_at as "2023-10-17T09:15:12Z"
+		with input.client as client
+		with input.grant_type as "refresh_token"
+		with input.scope as "openid"
+}
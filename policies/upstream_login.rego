@@ -0,0 +1,52 @@
+# METADATA
+# schemas:
+#   - input: schema["upstream_login_input"]
+package upstream_login
+
+import future.keywords.in
+
+default allow := false
+
+allow {
+	count(violation) == 0
+}
+
+# The rules for each upstream provider, keyed by provider ID
+provider_rules := data.upstream_login.providers[input.provider_id]
+
+# The claim holding the groups of the user, `groups` by default
+default groups_claim := "groups"
+
+groups_claim := provider_rules.groups_claim
+
+# The claim can either be a list of groups or a single group
+user_groups := input.claims[groups_claim] {
+	is_array(input.claims[groups_claim])
+}
+
+user_groups := [input.claims[groups_claim]] {
+	is_string(input.claims[groups_claim])
+}
+
+in_required_group {
+	some group in user_groups
+	group in provider_rules.required_groups
+}
+
+# Users must be in at least one of the required groups
+violation[{"msg": "user is not in a required group", "code": "missing-group"}] {
+	provider_rules.required_groups
+	not in_required_group
+}
+
+email_domain_allowed {
+	[_, domain] := split(input.email, "@")
+	some allowed_domain in provider_rules.email_domains
+	glob.match(allowed_domain, ["."], domain)
+}
+
+# The email of the user must match one of the domains of the provider
+violation[{"msg": "email domain does not match the provider", "code": "email-domain-mismatch"}] {
+	provider_rules.email_domains
+	not email_domain_allowed
+}
@@ -0,0 +1,68 @@
+package upstream_login
+
+provider_id := "01HFRQFT5QFMJFGF01P7JAV2ME"
+
+rules := {"providers": {provider_id: {
+	"required_groups": ["mas-users"],
+	"email_domains": ["example.com", "*.example.com"],
+}}}
+
+test_no_rules {
+	allow with input.provider_id as provider_id
+		with input.claims as {}
+}
+
+test_required_groups {
+	allow with input.provider_id as provider_id
+		with input.claims as {"groups": ["staff", "mas-users"]}
+		with input.email as "alice@example.com"
+		with data.upstream_login as rules
+
+	allow with input.provider_id as provider_id
+		with input.claims as {"groups": "mas-users"}
+		with input.email as "alice@example.com"
+		with data.upstream_login as rules
+
+	not allow with input.provider_id as provider_id
+		with input.claims as {"groups": ["staff"]}
+		with input.email as "alice@example.com"
+		with data.upstream_login as rules
+
+	not allow with input.provider_id as provider_id
+		with input.claims as {}
+		with input.email as "alice@example.com"
+		with data.upstream_login as rules
+}
+
+test_custom_groups_claim {
+	allow with input.provider_id as provider_id
+		with input.claims as {"roles": ["mas-users"]}
+		with input.email as "alice@example.com"
+		with data.upstream_login as {"providers": {provider_id: {
+			"groups_claim": "roles",
+			"required_groups": ["mas-users"],
+		}}}
+}
+
+test_email_domains {
+	allow with input.provider_id as provider_id
+		with input.claims as {"groups": ["mas-users"]}
+		with input.email as "alice@staff.example.com"
+		with data.upstream_login as rules
+
+	not allow with input.provider_id as provider_id
+		with input.claims as {"groups": ["mas-users"]}
+		with input.email as "alice@example.org"
+		with data.upstream_login as rules
+
+	# The email is required when the domains are restricted
+	not allow with input.provider_id as provider_id
+		with input.claims as {"groups": ["mas-users"]}
+		with data.upstream_login as rules
+}
+
+test_other_provider {
+	allow with input.provider_id as "01HFRQFT5QFMJFGF01P7JAV2MF"
+		with input.claims as {}
+		with data.upstream_login as rules
+}
@@ -12,9 +12,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-import { readFile } from "node:fs/promises";
+import { readFile, writeFile } from "node:fs/promises";
 
 import id128 from "id128";
+import { Knex } from "knex";
 import log4js from "log4js";
 import { parse } from "ts-command-line-args";
 import yaml from "yaml";
@@ -30,6 +31,7 @@ import type { MUpstreamOauthProvider } from "./types/MUpstreamOauthProvider.d.ts
 import type { MUserEmail } from "./types/MUserEmail.d.ts";
 import type { MUserPassword } from "./types/MUserPassword.d.ts";
 import type { SAccessToken } from "./types/SAccessToken.d.ts";
+import type { SDevice } from "./types/SDevice.d.ts";
 import type { SRefreshToken } from "./types/SRefreshToken.d.ts";
 import type { SUser } from "./types/SUser.d.ts";
 import type { SUserExternalId } from "./types/SUserExternalId.d.ts";
@@ -43,6 +45,8 @@ interface MigrationOptions {
   synapseConfigFile: string;
   masConfigFile: string;
   upstreamProviderMapping: string[];
+  checkpointFile?: string;
+  batchSize: number;
   dryRun?: boolean;
   help?: boolean;
 }
@@ -68,6 +72,17 @@ export async function migrate(): Promise<void> {
         description:
           "Mapping of upstream provider IDs to MAS provider IDs. Format: <upstream_provider_id>:<mas_provider_id>",
       },
+      checkpointFile: {
+        type: String,
+        optional: true,
+        description:
+          "Path to a file recording the last migrated user, used to resume an interrupted migration",
+      },
+      batchSize: {
+        type: Number,
+        defaultValue: 1000,
+        description: "Number of Synapse users to load at a time",
+      },
       dryRun: {
         type: Boolean,
         optional: true,
@@ -165,22 +180,66 @@ export async function migrate(): Promise<void> {
     );
   }
 
-  type Execution = () => Promise<void>;
+  type Execution = (tx: Knex.Transaction) => Promise<unknown>;
+
+  // When resuming, the checkpoint file holds the name of the last Synapse user
+  // which was fully migrated
+  let checkpoint: string | undefined;
+  if (args.checkpointFile) {
+    try {
+      checkpoint =
+        (await readFile(args.checkpointFile, "utf8")).trim() || undefined;
+    } catch (e) {
+      if ((e as NodeJS.ErrnoException).code !== "ENOENT") {
+        throw e;
+      }
+    }
+  }
+
+  if (checkpoint) {
+    log.info(`Resuming migration after user ${checkpoint}`);
+  } else {
+    const existingMasUsers = await mas
+      .count({ count: "*" })
+      .from("users")
+      .first();
+
+    if (parseInt(`${existingMasUsers?.count ?? 0}`) > 0) {
+      fatal(
+        `Found ${existingMasUsers?.count} existing users in MAS. Refusing to continue. Please clean MAS and try again.`,
+      );
+    }
+  }
 
-  const existingMasUsers = await mas
+  const synapseUserCount = await synapse
     .count({ count: "*" })
     .from("users")
     .first();
+  log.info(`Found ${synapseUserCount?.count ?? 0} users in Synapse`);
 
-  if (parseInt(`${existingMasUsers?.count ?? 0}`) > 0) {
-    fatal(
-      `Found ${existingMasUsers?.count} existing users in MAS. Refusing to continue. Please clean MAS and try again.`,
-    );
+  let processedUsers = 0;
+  for (;;) {
+    const query = synapse
+      .select("*")
+      .from<SUser>("users")
+      .orderBy("name")
+      .limit(args.batchSize);
+    if (checkpoint) {
+      query.where("name", ">", checkpoint);
+    }
+    const synapseUsers: SUser[] = await query;
+    if (synapseUsers.length === 0) {
+      break;
+    }
+
+    for (const user of synapseUsers) {
+      await migrateUser(user);
+      processedUsers += 1;
+      checkpoint = user.name;
+    }
   }
 
-  const synapseUsers = await synapse.select("*").from<SUser>("users");
-  log.info(`Found ${synapseUsers.length} users in Synapse`);
-  for (const user of synapseUsers) {
+  async function migrateUser(user: SUser): Promise<void> {
     const localpart = user.name.split(":")[0].substring(1);
     log.info(`Processing user ${user.name} as ${localpart}`);
 
@@ -195,13 +254,27 @@ export async function migrate(): Promise<void> {
       fatal(`Migration of guest users is not supported: ${user.name}`);
     }
 
+    // The previous run might have been interrupted between committing this
+    // user and writing the checkpoint
+    if (args.checkpointFile && processedUsers === 0) {
+      const existingUser = await mas("users")
+        .select("user_id")
+        .where({ username: localpart })
+        .first();
+      if (existingUser) {
+        log.info(`User ${user.name} already exists in MAS, skipping`);
+        await saveCheckpoint(user.name);
+        return;
+      }
+    }
+
     // users => users
     const masUser = {
       user_id: makeUuid(),
       username: localpart,
       created_at: new Date(parseInt(`${user.creation_ts}`) * 1000),
     };
-    executions.push(() => mas.insert(masUser!).into("users"));
+    executions.push((tx) => tx.insert(masUser!).into("users"));
     log.debug(`${stringifyAndRedact(user)} => ${stringifyAndRedact(masUser)}`);
     // users.password_hash => user_passwords
     if (user.password_hash) {
@@ -218,15 +291,19 @@ export async function migrate(): Promise<void> {
           masUserPassword,
         )}`,
       );
-      executions.push(() => mas.insert(masUserPassword).into("user_passwords"));
+      executions.push((tx) =>
+        tx.insert(masUserPassword).into("user_passwords"),
+      );
     }
 
     // user_threepids => user_emails
     let primaryEmail: MUserEmail | undefined;
+    const seenEmails = new Set<string>();
     const synapseThreePids = await synapse
       .select("*")
       .from<SUserThreePid>("user_threepids")
-      .where({ user_id: user.name });
+      .where({ user_id: user.name })
+      .orderBy("added_at");
     for (const threePid of synapseThreePids) {
       if (threePid.medium !== "email") {
         warningsForUser += 1;
@@ -235,35 +312,41 @@ export async function migrate(): Promise<void> {
         );
         continue;
       }
+
+      const email = threePid.address.toLowerCase();
+      if (seenEmails.has(email)) {
+        log.debug(`Skipping duplicate email ${email} for user ${user.name}`);
+        continue;
+      }
+      seenEmails.add(email);
+
+      // Synapse only stores threepids once they have been validated
       const masUserEmail: MUserEmail = {
         user_email_id: makeUuid(),
         user_id: masUser.user_id,
-        email: threePid.address.toLowerCase(),
+        email,
         created_at: new Date(parseInt(`${threePid.added_at}`) * 1000),
+        confirmed_at: new Date(
+          parseInt(`${threePid.validated_at ?? threePid.added_at}`) * 1000,
+        ),
       };
 
-      if (threePid.validated_at) {
-        masUserEmail.confirmed_at = new Date(
-          parseInt(`${threePid.validated_at}`) * 1000,
-        );
-      }
-
       log.debug(
         `${stringifyAndRedact(threePid)} => ${stringifyAndRedact(
           masUserEmail,
         )}`,
       );
-      if (!primaryEmail && threePid.validated_at) {
+      if (!primaryEmail) {
         primaryEmail = masUserEmail;
       }
-      executions.push(() => mas.insert(masUserEmail).into("user_emails"));
+      executions.push((tx) => tx.insert(masUserEmail).into("user_emails"));
     }
     if (primaryEmail) {
       log.debug(
         `Setting primary email for existing user ${masUser.username} to ${primaryEmail.email} as update`,
       );
-      executions.push(() =>
-        mas("users")
+      executions.push((tx) =>
+        tx("users")
           .where({ user_id: masUser!.user_id })
           .update({ primary_user_email_id: primaryEmail!.user_email_id }),
       );
@@ -275,112 +358,165 @@ export async function migrate(): Promise<void> {
       .from<SUserExternalId>("user_external_ids")
       .where({ user_id: user.name });
     for (const externalId of synapseExternalIds) {
-      try {
-        if (!upstreamProviders.has(externalId.auth_provider)) {
-          throw new Error(
-            `Unknown upstream provider ${externalId.auth_provider}`,
-          );
-        }
-        const provider = upstreamProviders.get(externalId.auth_provider)!;
-        const masUpstreamOauthLink: MUpstreamOauthLink = {
-          upstream_oauth_link_id: makeUuid(),
-          user_id: masUser.user_id,
-          upstream_oauth_provider_id: provider.upstream_oauth_provider_id,
-          subject: externalId.external_id,
-          created_at: masUser.created_at,
-        };
-
-        log.debug(
-          `${stringifyAndRedact(synapseExternalIds)} => ${stringifyAndRedact(
-            masUpstreamOauthLink,
-          )}`,
-        );
-
-        executions.push(() =>
-          mas.insert(masUpstreamOauthLink).into("upstream_oauth_links"),
-        );
-      } catch (e) {
-        fatal(
-          `Failed to import external id ${externalId.external_id} with ${externalId.auth_provider} for user ${user.name}: ${e}`,
+      const provider = upstreamProviders.get(externalId.auth_provider);
+      if (!provider) {
+        // Not being able to link an account is not fatal: the user can still
+        // link it again once the provider is configured in MAS
+        log.warn(
+          `Skipping external id ${externalId.external_id} for user ${user.name}: no upstream provider mapped for ${externalId.auth_provider}`,
         );
+        continue;
       }
+
+      const masUpstreamOauthLink: MUpstreamOauthLink = {
+        upstream_oauth_link_id: makeUuid(),
+        user_id: masUser.user_id,
+        upstream_oauth_provider_id: provider.upstream_oauth_provider_id,
+        subject: externalId.external_id,
+        created_at: masUser.created_at,
+      };
+
+      log.debug(
+        `${stringifyAndRedact(externalId)} => ${stringifyAndRedact(
+          masUpstreamOauthLink,
+        )}`,
+      );
+
+      executions.push((tx) =>
+        tx.insert(masUpstreamOauthLink).into("upstream_oauth_links"),
+      );
+    }
+
+    // devices,access_tokens,refresh_tokens => compat_sessions,compat_access_tokens,compat_refresh_tokens
+    const synapseDevices = new Map<string, SDevice>();
+    for (const device of await synapse
+      .select("*")
+      .from<SDevice>("devices")
+      .where({ user_id: user.name })) {
+      synapseDevices.set(device.device_id, device);
     }
 
-    // access_tokens,refresh_tokens => compat_sessions,compat_access_tokens
     const synapseAccessTokens = await synapse
       .select("*")
       .from<SAccessToken>("access_tokens")
-      .where({ user_id: user.name });
+      .where({ user_id: user.name })
+      .orderBy("id");
+
+    // A device can have multiple access tokens, but MAS only allows one
+    // session per device
+    const tokensByDevice = new Map<string, SAccessToken[]>();
     for (const accessToken of synapseAccessTokens) {
       if (!accessToken.device_id) {
         warningsForUser += 1;
         warn(
-          `Skipping access token ${accessToken.token} for user ${user.name} with no device_id`,
+          `Skipping access token ${accessToken.id} for user ${user.name} with no device_id`,
+        );
+        continue;
+      }
+
+      if (accessToken.puppets_user_id) {
+        log.debug(
+          `Skipping access token ${accessToken.id} for user ${user.name} puppeting ${accessToken.puppets_user_id}`,
+        );
+        continue;
+      }
+
+      if (
+        accessToken.valid_until_ms &&
+        parseInt(`${accessToken.valid_until_ms}`) < Date.now()
+      ) {
+        log.debug(
+          `Skipping expired access token ${accessToken.id} for user ${user.name}`,
         );
         continue;
       }
 
+      const tokens = tokensByDevice.get(accessToken.device_id) ?? [];
+      tokens.push(accessToken);
+      tokensByDevice.set(accessToken.device_id, tokens);
+    }
+
+    for (const [deviceId, accessTokens] of tokensByDevice) {
+      const device = synapseDevices.get(deviceId);
+      const firstToken = accessTokens[0]!;
+
       const masCompatSession: MCompatSession = {
         compat_session_id: makeUuid(),
         user_id: masUser.user_id,
-        device_id: accessToken.device_id,
-        created_at: accessToken.last_validated
-          ? new Date(parseInt(`${accessToken.last_validated}`))
+        device_id: deviceId,
+        created_at: firstToken.last_validated
+          ? new Date(parseInt(`${firstToken.last_validated}`))
           : masUser.created_at,
         is_synapse_admin: user.admin === 1,
       };
+      if (device?.last_seen) {
+        masCompatSession.last_active_at = new Date(
+          parseInt(`${device.last_seen}`),
+        );
+      }
+      if (device?.ip) {
+        masCompatSession.last_active_ip = device.ip;
+      }
       log.debug(
-        `${stringifyAndRedact(accessToken)} => ${stringifyAndRedact(
-          masCompatSession,
-        )}`,
-      );
-      executions.push(() =>
-        mas.insert(masCompatSession).into("compat_sessions"),
-      );
-
-      const masCompatAccessToken: MCompatAccessToken = {
-        compat_access_token_id: makeUuid(),
-        compat_session_id: masCompatSession.compat_session_id,
-        access_token: accessToken.token,
-        created_at: masCompatSession.created_at,
-      };
-      log.debug(
-        `Access token ${accessToken.id} => ${stringifyAndRedact(
-          masCompatAccessToken,
-        )}`,
+        `Device ${deviceId} => ${stringifyAndRedact(masCompatSession)}`,
       );
-      executions.push(() =>
-        mas.insert(masCompatAccessToken).into("compat_access_tokens"),
+      executions.push((tx) =>
+        tx.insert(masCompatSession).into("compat_sessions"),
       );
 
-      if (accessToken.refresh_token_id) {
-        const synapseRefreshToken = await synapse
-          .select("*")
-          .from<SRefreshToken>("refresh_tokens")
-          .where({ id: accessToken.refresh_token_id })
-          .first();
-        if (synapseRefreshToken) {
-          const masCompatRefreshToken: MCompatRefreshToken = {
-            compat_refresh_token_id: makeUuid(),
-            compat_session_id: masCompatSession.compat_session_id,
-            compat_access_token_id: masCompatAccessToken.compat_access_token_id,
-            refresh_token: synapseRefreshToken.token,
-            created_at: masCompatSession.created_at,
-          };
-          log.debug(
-            `Refresh token ${synapseRefreshToken.id} => ${stringifyAndRedact(
-              masCompatRefreshToken,
-            )}`,
-          );
-          executions.push(() =>
-            mas.insert(masCompatRefreshToken).into("compat_refresh_tokens"),
-          );
-        } else {
-          warningsForUser += 1;
-          warn(
-            `Unable to locate refresh token ${accessToken.refresh_token_id} for user ${user.name}`,
+      for (const accessToken of accessTokens) {
+        const masCompatAccessToken: MCompatAccessToken = {
+          compat_access_token_id: makeUuid(),
+          compat_session_id: masCompatSession.compat_session_id,
+          access_token: accessToken.token,
+          created_at: accessToken.last_validated
+            ? new Date(parseInt(`${accessToken.last_validated}`))
+            : masCompatSession.created_at,
+        };
+        if (accessToken.valid_until_ms) {
+          masCompatAccessToken.expires_at = new Date(
+            parseInt(`${accessToken.valid_until_ms}`),
           );
         }
+        log.debug(
+          `Access token ${accessToken.id} => ${stringifyAndRedact(
+            masCompatAccessToken,
+          )}`,
+        );
+        executions.push((tx) =>
+          tx.insert(masCompatAccessToken).into("compat_access_tokens"),
+        );
+
+        if (accessToken.refresh_token_id) {
+          const synapseRefreshToken = await synapse
+            .select("*")
+            .from<SRefreshToken>("refresh_tokens")
+            .where({ id: accessToken.refresh_token_id })
+            .first();
+          if (synapseRefreshToken) {
+            const masCompatRefreshToken: MCompatRefreshToken = {
+              compat_refresh_token_id: makeUuid(),
+              compat_session_id: masCompatSession.compat_session_id,
+              compat_access_token_id:
+                masCompatAccessToken.compat_access_token_id,
+              refresh_token: synapseRefreshToken.token,
+              created_at: masCompatAccessToken.created_at,
+            };
+            log.debug(
+              `Refresh token ${synapseRefreshToken.id} => ${stringifyAndRedact(
+                masCompatRefreshToken,
+              )}`,
+            );
+            executions.push((tx) =>
+              tx.insert(masCompatRefreshToken).into("compat_refresh_tokens"),
+            );
+          } else {
+            warningsForUser += 1;
+            warn(
+              `Unable to locate refresh token ${accessToken.refresh_token_id} for user ${user.name}`,
+            );
+          }
+        }
       }
     }
 
@@ -395,10 +531,11 @@ export async function migrate(): Promise<void> {
       const tx = await mas.transaction();
       try {
         for (const execution of executions) {
-          await execution();
+          await execution(tx);
         }
         await tx.commit();
         log.info(`Migrated user ${user.name}`);
+        await saveCheckpoint(user.name);
       } catch (e) {
         try {
           await tx.rollback();
@@ -409,10 +546,17 @@ export async function migrate(): Promise<void> {
       }
     }
   }
+
+  async function saveCheckpoint(name: string): Promise<void> {
+    if (args.checkpointFile && !args.dryRun) {
+      await writeFile(args.checkpointFile, name, "utf8");
+    }
+  }
+
   log.info(
-    `Completed migration ${args.dryRun ? "dry-run " : ""}of ${
-      synapseUsers.length
-    } users with ${fatals} fatals and ${warnings.length} warnings:`,
+    `Completed migration ${
+      args.dryRun ? "dry-run " : ""
+    }of ${processedUsers} users with ${fatals} fatals and ${warnings.length} warnings:`,
   );
   warnings.forEach((w) => log.warn(w));
   if (fatals > 0) {
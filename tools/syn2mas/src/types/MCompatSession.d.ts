@@ -26,6 +26,8 @@ import { UUID } from "./index";
 | created_at        | timestamp with time zone |  not null |
 | finished_at       | timestamp with time zone |           |
 | is_synapse_admin  | boolean                  | not null  |
+| last_active_at    | timestamp with time zone |           |
+| last_active_ip    | inet                     |           |
 +-------------------+--------------------------+-----------+
 Indexes:
     "compat_sessions_pkey" PRIMARY KEY, btree (compat_session_id)
@@ -45,4 +47,6 @@ export interface MCompatSession {
   created_at: Date;
   finished_at?: Date;
   is_synapse_admin: boolean;
+  last_active_at?: Date;
+  last_active_ip?: string;
 }